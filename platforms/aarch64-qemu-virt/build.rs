@@ -0,0 +1,10 @@
+//! Places `qemu-virt.x` somewhere the linker can find it.
+
+use std::{env, fs, path::PathBuf};
+
+fn main() {
+    let out = PathBuf::from(env::var_os("OUT_DIR").unwrap());
+    fs::copy("qemu-virt.x", out.join("qemu-virt.x")).expect("failed to copy qemu-virt.x");
+    println!("cargo:rustc-link-search={}", out.display());
+    println!("cargo:rerun-if-changed=qemu-virt.x");
+}
@@ -0,0 +1,47 @@
+/* Linker script for the QEMU `virt` machine.
+ *
+ * QEMU loads the ELF image at the addresses given here, and jumps to its
+ * entry point. RAM starts at 0x4000_0000; the first 512KB is left free for
+ * the device tree, which QEMU places at the start of RAM.
+ */
+ENTRY(_start)
+
+STACK_SIZE = 64K;
+
+SECTIONS
+{
+    . = 0x40080000;
+
+    .text : {
+        KEEP(*(.text.boot))
+        *(.text .text.*)
+    }
+
+    .rodata : ALIGN(8) {
+        *(.rodata .rodata.*)
+    }
+
+    .data : ALIGN(8) {
+        *(.data .data.*)
+    }
+
+    .bss (NOLOAD) : ALIGN(16) {
+        __bss_start = .;
+        *(.bss .bss.*)
+        *(COMMON)
+        . = ALIGN(16);
+        __bss_end = .;
+    }
+
+    .stack (NOLOAD) : ALIGN(16) {
+        . += STACK_SIZE;
+        __stack_top = .;
+    }
+
+    . = ALIGN(4096);
+    __heap_start = .;
+
+    /DISCARD/ : {
+        *(.comment)
+    }
+}
@@ -0,0 +1,11 @@
+#![no_std]
+#![no_main]
+
+/// Called by the boot code in `mnemos_aarch64_qemu_virt::boot`.
+#[no_mangle]
+extern "C" fn mnemos_main() -> ! {
+    let k = unsafe { mnemos_aarch64_qemu_virt::init() };
+    mnemos_aarch64_qemu_virt::spawn_serial(k);
+    mnemos_aarch64_qemu_virt::spawn_daemons(k);
+    mnemos_aarch64_qemu_virt::run(k)
+}
@@ -0,0 +1,289 @@
+//! Early boot: the entry point, exception vectors, and MMU setup.
+//!
+//! QEMU jumps to [`_start`] on the boot core, at EL1 (or at EL2, if the
+//! machine was started with `virtualization=on`), with the MMU and caches
+//! disabled. `_start` drops to EL1 if necessary, sets up the stack, zeroes
+//! `.bss`, installs the exception vectors, and calls the binary's
+//! `mnemos_main` function.
+use core::{arch::global_asm, ptr::addr_of};
+
+global_asm!(
+    r#"
+    .section .text.boot, "ax"
+    .global _start
+_start:
+    // Park all cores but the boot core. QEMU only starts secondary cores
+    // when asked to over PSCI, but be careful anyway.
+    mrs     x0, mpidr_el1
+    and     x0, x0, #0xff
+    cbnz    x0, .Lpark
+
+    // If we were started at EL2, drop to EL1.
+    mrs     x0, CurrentEL
+    lsr     x0, x0, #2
+    cmp     x0, #2
+    b.ne    .Lel1
+    // EL1 is AArch64.
+    mov     x0, #(1 << 31)
+    msr     hcr_el2, x0
+    // Let EL1 access the physical and virtual timers.
+    mov     x0, #3
+    msr     cnthctl_el2, x0
+    msr     cntvoff_el2, xzr
+    // Return to EL1h, with all exceptions masked.
+    mov     x0, #0x3c5
+    msr     spsr_el2, x0
+    adr     x0, .Lel1
+    msr     elr_el2, x0
+    eret
+
+.Lel1:
+    ldr     x0, =__stack_top
+    mov     sp, x0
+
+    // Don't trap FP/SIMD instructions, which the compiler is free to emit.
+    mov     x0, #(3 << 20)
+    msr     cpacr_el1, x0
+    isb
+
+    // Zero .bss
+    ldr     x0, =__bss_start
+    ldr     x1, =__bss_end
+.Lzero_bss:
+    cmp     x0, x1
+    b.hs    .Lbss_done
+    str     xzr, [x0], #8
+    b       .Lzero_bss
+.Lbss_done:
+
+    ldr     x0, =__vectors
+    msr     vbar_el1, x0
+    isb
+
+    bl      mnemos_main
+
+.Lpark:
+    wfe
+    b       .Lpark
+
+    // Saves the registers which aren't preserved across a function call,
+    // and the exception return state.
+    .macro save_context
+    sub     sp, sp, #(16 * 12)
+    stp     x0, x1, [sp, #(16 * 0)]
+    stp     x2, x3, [sp, #(16 * 1)]
+    stp     x4, x5, [sp, #(16 * 2)]
+    stp     x6, x7, [sp, #(16 * 3)]
+    stp     x8, x9, [sp, #(16 * 4)]
+    stp     x10, x11, [sp, #(16 * 5)]
+    stp     x12, x13, [sp, #(16 * 6)]
+    stp     x14, x15, [sp, #(16 * 7)]
+    stp     x16, x17, [sp, #(16 * 8)]
+    stp     x18, x30, [sp, #(16 * 9)]
+    mrs     x0, elr_el1
+    mrs     x1, spsr_el1
+    stp     x0, x1, [sp, #(16 * 10)]
+    mrs     x0, fpsr
+    mrs     x1, fpcr
+    stp     x0, x1, [sp, #(16 * 11)]
+    sub     sp, sp, #(32 * 16)
+    stp     q0, q1, [sp, #(32 * 0)]
+    stp     q2, q3, [sp, #(32 * 1)]
+    stp     q4, q5, [sp, #(32 * 2)]
+    stp     q6, q7, [sp, #(32 * 3)]
+    stp     q8, q9, [sp, #(32 * 4)]
+    stp     q10, q11, [sp, #(32 * 5)]
+    stp     q12, q13, [sp, #(32 * 6)]
+    stp     q14, q15, [sp, #(32 * 7)]
+    stp     q16, q17, [sp, #(32 * 8)]
+    stp     q18, q19, [sp, #(32 * 9)]
+    stp     q20, q21, [sp, #(32 * 10)]
+    stp     q22, q23, [sp, #(32 * 11)]
+    stp     q24, q25, [sp, #(32 * 12)]
+    stp     q26, q27, [sp, #(32 * 13)]
+    stp     q28, q29, [sp, #(32 * 14)]
+    stp     q30, q31, [sp, #(32 * 15)]
+    .endm
+
+    .macro restore_context
+    ldp     q0, q1, [sp, #(32 * 0)]
+    ldp     q2, q3, [sp, #(32 * 1)]
+    ldp     q4, q5, [sp, #(32 * 2)]
+    ldp     q6, q7, [sp, #(32 * 3)]
+    ldp     q8, q9, [sp, #(32 * 4)]
+    ldp     q10, q11, [sp, #(32 * 5)]
+    ldp     q12, q13, [sp, #(32 * 6)]
+    ldp     q14, q15, [sp, #(32 * 7)]
+    ldp     q16, q17, [sp, #(32 * 8)]
+    ldp     q18, q19, [sp, #(32 * 9)]
+    ldp     q20, q21, [sp, #(32 * 10)]
+    ldp     q22, q23, [sp, #(32 * 11)]
+    ldp     q24, q25, [sp, #(32 * 12)]
+    ldp     q26, q27, [sp, #(32 * 13)]
+    ldp     q28, q29, [sp, #(32 * 14)]
+    ldp     q30, q31, [sp, #(32 * 15)]
+    add     sp, sp, #(32 * 16)
+    ldp     x0, x1, [sp, #(16 * 11)]
+    msr     fpsr, x0
+    msr     fpcr, x1
+    ldp     x0, x1, [sp, #(16 * 10)]
+    msr     elr_el1, x0
+    msr     spsr_el1, x1
+    ldp     x0, x1, [sp, #(16 * 0)]
+    ldp     x2, x3, [sp, #(16 * 1)]
+    ldp     x4, x5, [sp, #(16 * 2)]
+    ldp     x6, x7, [sp, #(16 * 3)]
+    ldp     x8, x9, [sp, #(16 * 4)]
+    ldp     x10, x11, [sp, #(16 * 5)]
+    ldp     x12, x13, [sp, #(16 * 6)]
+    ldp     x14, x15, [sp, #(16 * 7)]
+    ldp     x16, x17, [sp, #(16 * 8)]
+    ldp     x18, x30, [sp, #(16 * 9)]
+    add     sp, sp, #(16 * 12)
+    .endm
+
+    .section .text.vectors, "ax"
+
+    // Each vector table entry is 0x80 bytes, which isn't enough room to save
+    // the context, so entries just branch to the real handlers.
+    .balign 0x800
+    .global __vectors
+__vectors:
+    // Current EL with SP_EL0. We always use SP_ELx, so these are unexpected.
+    .balign 0x80
+    b       .Lunexpected
+    .balign 0x80
+    b       .Lunexpected
+    .balign 0x80
+    b       .Lunexpected
+    .balign 0x80
+    b       .Lunexpected
+
+    // Current EL with SP_ELx
+    .balign 0x80
+    b       .Lsync
+    .balign 0x80
+    b       .Lirq
+    .balign 0x80
+    b       .Lunexpected
+    .balign 0x80
+    b       .Lunexpected
+
+    // Lower EL, AArch64. Nothing runs at EL0.
+    .balign 0x80
+    b       .Lunexpected
+    .balign 0x80
+    b       .Lunexpected
+    .balign 0x80
+    b       .Lunexpected
+    .balign 0x80
+    b       .Lunexpected
+
+    // Lower EL, AArch32
+    .balign 0x80
+    b       .Lunexpected
+    .balign 0x80
+    b       .Lunexpected
+    .balign 0x80
+    b       .Lunexpected
+    .balign 0x80
+    b       .Lunexpected
+
+.Lirq:
+    save_context
+    bl      mnemos_handle_irq
+    restore_context
+    eret
+
+.Lsync:
+    save_context
+    bl      mnemos_handle_sync
+    // `mnemos_handle_sync` never returns.
+    b       .Lpark
+
+.Lunexpected:
+    save_context
+    bl      mnemos_handle_unexpected
+    b       .Lpark
+    "#
+);
+
+// === MMU ===
+
+/// MAIR_EL1 attribute index for device memory (Device-nGnRnE).
+const ATTR_DEVICE: u64 = 0;
+/// MAIR_EL1 attribute index for normal, write-back cacheable memory.
+const ATTR_NORMAL: u64 = 1;
+
+/// A level 1 translation table, where each entry maps a 1GB block.
+#[repr(C, align(4096))]
+struct Table([u64; 512]);
+
+static mut TABLE: Table = Table([0; 512]);
+
+/// Enables the MMU and caches, with an identity map of the first 4GB of the
+/// physical address space.
+///
+/// Running with the MMU disabled treats all memory as device memory, where
+/// unaligned accesses and exclusive loads and stores (used for atomics) don't
+/// behave as the compiler expects. The first 1GB, which contains the flash
+/// and peripherals on the `virt` machine, is mapped as device memory, and the
+/// next 3GB (RAM) as normal memory.
+///
+/// # Safety
+///
+/// This must be called once, early in boot, with the MMU disabled.
+pub(crate) unsafe fn enable_mmu() {
+    // Block descriptor bits.
+    const VALID_BLOCK: u64 = 0b01;
+    const INNER_SHAREABLE: u64 = 0b11 << 8;
+    const ACCESS_FLAG: u64 = 1 << 10;
+    const EXECUTE_NEVER: u64 = (1 << 53) | (1 << 54);
+
+    let table = &mut *core::ptr::addr_of_mut!(TABLE);
+    table.0[0] = VALID_BLOCK | (ATTR_DEVICE << 2) | ACCESS_FLAG | EXECUTE_NEVER;
+    for (i, entry) in table.0.iter_mut().enumerate().take(4).skip(1) {
+        *entry =
+            ((i as u64) << 30) | VALID_BLOCK | (ATTR_NORMAL << 2) | INNER_SHAREABLE | ACCESS_FLAG;
+    }
+
+    // attr0: Device-nGnRnE, attr1: Normal, inner/outer write-back.
+    let mair: u64 = 0xff << (ATTR_NORMAL * 8);
+    let tcr: u64 = 25 // T0SZ: 39-bit VA, so translation starts at level 1.
+        | (0b01 << 8) // IRGN0: write-back cacheable
+        | (0b01 << 10) // ORGN0: write-back cacheable
+        | (0b11 << 12) // SH0: inner shareable
+        // TG0 (bits 14-15) is 0: 4KB granule
+        | (1 << 23) // EPD1: no TTBR1 walks
+        | (0b001 << 32); // IPS: 36-bit PA
+
+    let mut sctlr: u64;
+    core::arch::asm!(
+        "msr mair_el1, {mair}",
+        "msr tcr_el1, {tcr}",
+        "msr ttbr0_el1, {ttbr0}",
+        "isb",
+        "tlbi vmalle1",
+        "dsb ish",
+        "isb",
+        "mrs {sctlr}, sctlr_el1",
+        mair = in(reg) mair,
+        tcr = in(reg) tcr,
+        ttbr0 = in(reg) addr_of!(TABLE) as u64,
+        sctlr = out(reg) sctlr,
+        options(nostack),
+    );
+
+    // M: MMU, C: data cache, I: instruction cache
+    sctlr |= (1 << 0) | (1 << 2) | (1 << 12);
+    if cfg!(feature = "alignment-check") {
+        // A: alignment fault checking
+        sctlr |= 1 << 1;
+    }
+    core::arch::asm!(
+        "msr sctlr_el1, {sctlr}",
+        "isb",
+        sctlr = in(reg) sctlr,
+        options(nostack),
+    );
+}
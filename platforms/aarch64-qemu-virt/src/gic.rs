@@ -0,0 +1,192 @@
+//! GICv2 interrupt controller driver.
+//!
+//! The `virt` machine's GIC distributor and CPU interface are at fixed
+//! addresses. Interrupt handlers are registered by interrupt ID (INTID), and
+//! called from the IRQ exception handler by [`Gic::dispatch_interrupt`].
+use core::{
+    ptr::{self, null_mut},
+    sync::atomic::{AtomicPtr, Ordering},
+};
+
+use kernel::isr::{self, IsrConfig};
+
+/// The base address of the GIC distributor.
+const GICD_BASE: usize = 0x0800_0000;
+/// The base address of the GIC CPU interface.
+const GICC_BASE: usize = 0x0801_0000;
+
+// Distributor registers
+const GICD_CTLR: usize = 0x000;
+const GICD_ISENABLER: usize = 0x100;
+const GICD_ICENABLER: usize = 0x180;
+const GICD_IPRIORITYR: usize = 0x400;
+const GICD_ITARGETSR: usize = 0x800;
+
+// CPU interface registers
+const GICC_CTLR: usize = 0x000;
+const GICC_PMR: usize = 0x004;
+const GICC_IAR: usize = 0x00c;
+const GICC_EOIR: usize = 0x010;
+
+/// The INTID returned by `GICC_IAR` when there is no pending interrupt.
+const SPURIOUS: u32 = 1023;
+
+/// The number of interrupts we can register handlers for. This covers the
+/// SGIs and PPIs (0-31), and the first 32 SPIs, which include all of the
+/// `virt` machine's fixed peripherals.
+pub const MAX_INTERRUPTS: usize = 64;
+
+/// Interrupt IDs of the interrupts used by this platform.
+pub mod intid {
+    /// The EL1 virtual timer (a PPI).
+    pub const VIRTUAL_TIMER: u32 = 27;
+    /// The PL011 UART (SPI 1).
+    pub const UART0: u32 = 33;
+}
+
+/// Errors returned by [`Gic::activate`].
+#[derive(Debug, Copy, Clone)]
+pub enum MaskError {
+    /// The interrupt ID is larger than [`MAX_INTERRUPTS`].
+    NotFound(u32),
+    /// The interrupt did not have a handler.
+    NoHandler(u32),
+}
+
+/// GICv2 interface
+pub struct Gic {
+    _x: (),
+}
+
+static HANDLERS: [AtomicPtr<()>; MAX_INTERRUPTS] =
+    [const { AtomicPtr::new(null_mut()) }; MAX_INTERRUPTS];
+
+impl Gic {
+    /// Enable the distributor and the boot core's CPU interface, with all
+    /// interrupts masked.
+    ///
+    /// # Safety
+    ///
+    /// This should only be called once, early in boot.
+    pub unsafe fn init() -> Self {
+        for n in 0..MAX_INTERRUPTS / 32 {
+            write(GICD_BASE + GICD_ICENABLER + n * 4, u32::MAX);
+        }
+        write(GICD_BASE + GICD_CTLR, 1);
+        // Don't filter interrupts by priority.
+        write(GICC_BASE + GICC_PMR, 0xff);
+        write(GICC_BASE + GICC_CTLR, 1);
+        Self { _x: () }
+    }
+
+    /// Obtain a `Gic` instance for use in e.g. interrupt handlers
+    ///
+    /// # Safety
+    ///
+    /// The GIC must have been initialized with [`Gic::init`].
+    pub unsafe fn summon() -> Self {
+        Self { _x: () }
+    }
+
+    /// Register a handler for an interrupt. The interrupt must be activated
+    /// with [`Gic::activate`] before the handler will be called.
+    ///
+    /// # Safety
+    ///
+    /// May effect normal interrupt processing
+    pub unsafe fn register(&self, intid: u32, handler: fn()) {
+        let slot = HANDLERS
+            .get(intid as usize)
+            .unwrap_or_else(|| panic!("interrupt {intid} exceeds MAX_INTERRUPTS"));
+        slot.store(handler as *mut (), Ordering::Release);
+    }
+
+    /// Returns the GIC priority used for interrupts with the given
+    /// [`isr::Priority`]. Lower values are higher priorities.
+    pub const fn priority(priority: isr::Priority) -> u8 {
+        match priority {
+            isr::Priority::Low => 0xc0,
+            isr::Priority::Normal => 0x80,
+            isr::Priority::High => 0x40,
+            isr::Priority::Critical => 0x00,
+        }
+    }
+
+    /// Route an interrupt to the boot core with the priority requested by
+    /// `config`, and unmask it.
+    ///
+    /// Handlers always run with IRQs masked on this platform, so nesting is
+    /// never enabled, even if `config` allows it.
+    ///
+    /// # Safety
+    ///
+    /// May effect normal interrupt processing
+    pub unsafe fn activate(&self, intid: u32, config: IsrConfig) -> Result<(), MaskError> {
+        let handler = HANDLERS
+            .get(intid as usize)
+            .ok_or(MaskError::NotFound(intid))?;
+        if handler.load(Ordering::Acquire).is_null() {
+            return Err(MaskError::NoHandler(intid));
+        }
+
+        let n = intid as usize;
+        write8(
+            GICD_BASE + GICD_IPRIORITYR + n,
+            Self::priority(config.priority),
+        );
+        // SGI and PPI targets are read-only.
+        if n >= 32 {
+            write8(GICD_BASE + GICD_ITARGETSR + n, 0b1);
+        }
+        write(GICD_BASE + GICD_ISENABLER + (n / 32) * 4, 1 << (n % 32));
+        Ok(())
+    }
+
+    /// Mask an interrupt.
+    pub fn deactivate(&self, intid: u32) -> Result<(), MaskError> {
+        if intid as usize >= MAX_INTERRUPTS {
+            return Err(MaskError::NotFound(intid));
+        }
+        let n = intid as usize;
+        unsafe { write(GICD_BASE + GICD_ICENABLER + (n / 32) * 4, 1 << (n % 32)) };
+        Ok(())
+    }
+
+    /// Acknowledge the highest priority pending interrupt, call its handler,
+    /// and signal the end of the interrupt.
+    ///
+    /// # Safety
+    ///
+    /// This must only be called from the IRQ exception handler.
+    pub unsafe fn dispatch_interrupt(&self) {
+        let iar = read(GICC_BASE + GICC_IAR);
+        let intid = iar & 0x3ff;
+        if intid == SPURIOUS {
+            return;
+        }
+
+        let handler = HANDLERS
+            .get(intid as usize)
+            .map(|h| h.load(Ordering::Acquire))
+            .unwrap_or(null_mut());
+        if handler.is_null() {
+            panic!("no handler for interrupt {intid}");
+        }
+        let handler: fn() = core::mem::transmute(handler);
+        handler();
+
+        write(GICC_BASE + GICC_EOIR, iar);
+    }
+}
+
+unsafe fn read(addr: usize) -> u32 {
+    ptr::read_volatile(addr as *const u32)
+}
+
+unsafe fn write(addr: usize, value: u32) {
+    ptr::write_volatile(addr as *mut u32, value)
+}
+
+unsafe fn write8(addr: usize, value: u8) {
+    ptr::write_volatile(addr as *mut u8, value)
+}
@@ -0,0 +1,189 @@
+//! MnemOS for the QEMU `virt` machine on AArch64.
+//!
+//! This platform exists so that MnemOS can be run on a second major
+//! architecture, locally in QEMU, without any hardware.
+#![no_std]
+extern crate alloc;
+
+pub mod boot;
+pub mod gic;
+pub mod pl011;
+pub mod timer;
+
+use core::{
+    arch::asm,
+    fmt::Write,
+    panic::PanicInfo,
+    ptr::{addr_of, NonNull},
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+use gic::Gic;
+use kernel::{
+    maitake,
+    mnemos_alloc::{
+        containers::Box,
+        heap::{MnemosAlloc, SingleThreadedLinkedListAllocator},
+    },
+    Kernel, KernelSettings,
+};
+use pl011::Pl011;
+
+#[global_allocator]
+static AHEAP: MnemosAlloc<SingleThreadedLinkedListAllocator> = MnemosAlloc::new();
+
+/// The size of the heap, which starts after the end of the kernel image.
+///
+/// QEMU gives the `virt` machine 128MB of RAM by default.
+pub const HEAP_SIZE: usize = 64 * 1024 * 1024;
+
+extern "C" {
+    static __heap_start: u8;
+}
+
+/// Initializes the hardware and the kernel.
+///
+/// # Safety
+///
+/// This must be called once, from `mnemos_main`.
+pub unsafe fn init() -> &'static Kernel {
+    boot::enable_mmu();
+
+    AHEAP
+        .init(
+            NonNull::new(addr_of!(__heap_start) as *mut u8).unwrap(),
+            HEAP_SIZE,
+        )
+        .expect("heap should only be initialized once!");
+
+    let gic = Gic::init();
+    timer::enable_interrupt(&gic);
+
+    let k_settings = KernelSettings { max_drivers: 16 };
+    let clock =
+        maitake::time::Clock::new(timer::tick_duration(), timer::now).named("CLOCK_CNTVCT_EL0");
+    let k = Box::into_raw(Kernel::new(k_settings, clock).expect("cannot initialize kernel"))
+        .as_ref()
+        .unwrap();
+
+    // Unmask IRQs.
+    asm!("msr daifclr, #2", options(nomem, nostack));
+
+    k
+}
+
+/// Spawns the PL011 UART's [`SimpleSerialService`](kernel::services::simple_serial)
+/// server, used by the serial mux.
+pub fn spawn_serial(k: &'static Kernel) {
+    let uart = unsafe { Pl011::init() };
+    k.initialize(uart.register(k, 4096, 4096))
+        .expect("failed to spawn PL011 UART server!");
+}
+
+/// Spawns the default kernel services, including serial tracing.
+pub fn spawn_daemons(k: &'static Kernel) {
+    k.initialize_default_services(Default::default());
+}
+
+/// Runs the kernel.
+pub fn run(k: &'static Kernel) -> ! {
+    loop {
+        let tick = k.tick();
+        let turn = k.turn_timer();
+
+        // If there is nothing else scheduled, and we didn't just wake something up,
+        // sleep for some amount of time
+        if turn.expired == 0 && !tick.has_remaining {
+            // If there's no timer deadline, still wake up occasionally. The
+            // kernel's clock is the virtual counter, so its ticks are counter
+            // ticks.
+            let amount = k.ticks_to_next_deadline(&turn, Duration::from_millis(100));
+            timer::set_alarm(amount.ticks());
+
+            unsafe { asm!("wfi", options(nomem, nostack)) };
+
+            // Disable the timer in case that wasn't what woke us up
+            timer::clear_alarm();
+
+            // Account for time slept
+            let _turn = k.turn_timer();
+        }
+    }
+}
+
+/// Runs `f` with IRQs masked.
+pub fn without_interrupts<R>(f: impl FnOnce() -> R) -> R {
+    let daif: u64;
+    unsafe {
+        asm!("mrs {}, daif", "msr daifset, #2", out(reg) daif, options(nomem, nostack));
+    }
+    let ret = f();
+    unsafe { asm!("msr daif, {}", in(reg) daif, options(nomem, nostack)) };
+    ret
+}
+
+#[no_mangle]
+extern "C" fn mnemos_handle_irq() {
+    // tell the kernel that we are inside an ISR. currently, this just results
+    // in switching tracing buffers to use a special ISR tracebuf, in case the
+    // interrupt fired while someone was holding a tracebuf WGR.
+    let _in_isr = kernel::isr::Isr::enter();
+    unsafe { Gic::summon().dispatch_interrupt() };
+}
+
+#[no_mangle]
+extern "C" fn mnemos_handle_sync() -> ! {
+    let (esr, elr, far): (u64, u64, u64);
+    unsafe {
+        asm!(
+            "mrs {esr}, esr_el1",
+            "mrs {elr}, elr_el1",
+            "mrs {far}, far_el1",
+            esr = out(reg) esr,
+            elr = out(reg) elr,
+            far = out(reg) far,
+            options(nomem, nostack),
+        )
+    };
+    let class = esr >> 26;
+    let kind = match class {
+        0x00 => "unknown reason",
+        0x15 => "SVC",
+        0x20 | 0x21 => "instruction abort",
+        0x22 => "PC alignment fault",
+        0x24 | 0x25 => "data abort",
+        0x26 => "SP alignment fault",
+        0x3c => "BRK",
+        _ => "exception",
+    };
+    panic!("CPU exception: {kind} (ESR {esr:#x}) at {elr:#x}, FAR {far:#x}");
+}
+
+#[no_mangle]
+extern "C" fn mnemos_handle_unexpected() -> ! {
+    let esr: u64;
+    unsafe { asm!("mrs {}, esr_el1", out(reg) esr, options(nomem, nostack)) };
+    panic!("unexpected exception vector taken (ESR {esr:#x})");
+}
+
+#[panic_handler]
+fn handler(info: &PanicInfo) -> ! {
+    // Disable interrupts.
+    unsafe { asm!("msr daifset, #0xf", options(nomem, nostack)) };
+
+    // Avoid double panics.
+    static PANICKING: AtomicBool = AtomicBool::new(false);
+    if !PANICKING.swap(true, Ordering::SeqCst) {
+        let mut uart = unsafe { Pl011::summon() };
+        // end any existing SerMux frame on the UART
+        uart.write_blocking(&[0]);
+        // write out the panic message in plaintext
+        write!(&mut uart, "\r\n{info}\r\n").ok();
+        // end the SerMux frame so crowtty can decode the panic message as utf8
+        uart.write_blocking(&[0]);
+    }
+
+    loop {
+        unsafe { asm!("wfi", options(nomem, nostack)) };
+    }
+}
@@ -0,0 +1,231 @@
+//! A [`SimpleSerialService`] implementation for the `virt` machine's PL011
+//! UART.
+use core::{
+    ptr::{self, null_mut},
+    sync::atomic::{AtomicPtr, Ordering},
+};
+
+use kernel::{
+    comms::bbq::{new_bidi_channel, BidiHandle, Consumer, SpscProducer},
+    isr::{IsrConfig, Priority},
+    maitake::sync::WaitCell,
+    mnemos_alloc::containers::Box,
+    registry,
+    services::simple_serial::{Request, Response, SimpleSerialError, SimpleSerialService},
+    Kernel,
+};
+
+use crate::gic::{intid, Gic};
+
+/// The base address of the PL011 UART.
+const BASE: usize = 0x0900_0000;
+
+// Registers
+const DR: usize = 0x000;
+const FR: usize = 0x018;
+const LCR_H: usize = 0x02c;
+const CR: usize = 0x030;
+const IFLS: usize = 0x034;
+const IMSC: usize = 0x038;
+const MIS: usize = 0x040;
+const ICR: usize = 0x044;
+
+// FR bits
+const FR_RXFE: u32 = 1 << 4;
+const FR_TXFF: u32 = 1 << 5;
+
+// LCR_H bits
+const LCR_H_FEN: u32 = 1 << 4;
+const LCR_H_WLEN_8: u32 = 0b11 << 5;
+
+// CR bits
+const CR_UARTEN: u32 = 1 << 0;
+const CR_TXE: u32 = 1 << 8;
+const CR_RXE: u32 = 1 << 9;
+
+// Interrupt bits, used by IMSC, MIS, and ICR
+const INT_RX: u32 = 1 << 4;
+const INT_TX: u32 = 1 << 5;
+const INT_RT: u32 = 1 << 6;
+
+static TX_READY: WaitCell = WaitCell::new();
+static UART_RX: AtomicPtr<SpscProducer> = AtomicPtr::new(null_mut());
+
+pub struct Pl011 {
+    _x: (),
+}
+
+impl Pl011 {
+    /// Configure the UART for 8N1 with FIFOs enabled.
+    ///
+    /// QEMU ignores the baud rate, so it isn't configured.
+    ///
+    /// # Safety
+    ///
+    /// This should only be called once.
+    pub unsafe fn init() -> Self {
+        write(CR, 0);
+        write(LCR_H, LCR_H_FEN | LCR_H_WLEN_8);
+        // Interrupt when the TX FIFO is at most 1/2 full, and when the RX
+        // FIFO is at least 1/8 full.
+        write(IFLS, 0b010);
+        write(ICR, 0x7ff);
+        write(IMSC, 0);
+        write(CR, CR_UARTEN | CR_TXE | CR_RXE);
+        Self { _x: () }
+    }
+
+    /// Obtain a `Pl011` instance for use in e.g. the panic handler
+    ///
+    /// # Safety
+    ///
+    /// 'Tis thine responsibility, that which thou doth summon.
+    pub unsafe fn summon() -> Self {
+        Self { _x: () }
+    }
+
+    /// Writes `bytes` to the UART, busy-waiting for space in the FIFO.
+    ///
+    /// This is used by the panic handler, where interrupts can't be used.
+    pub fn write_blocking(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            unsafe {
+                while read(FR) & FR_TXFF != 0 {}
+                write(DR, byte as u32);
+            }
+        }
+    }
+
+    pub fn handle_uart0_int() {
+        let mis = unsafe { read(MIS) };
+
+        if mis & INT_TX != 0 {
+            // The sender task re-enables the TX interrupt when it's waiting
+            // for space in the FIFO.
+            crate::without_interrupts(|| unsafe { write(IMSC, read(IMSC) & !INT_TX) });
+            TX_READY.wake();
+        }
+
+        if mis & (INT_RX | INT_RT) != 0 {
+            let prod = UART_RX.load(Ordering::Acquire);
+            let mut handled_all = false;
+
+            if !prod.is_null() {
+                let prod = unsafe { &*prod };
+
+                'read: while let Some(mut wgr) = prod.send_grant_max_sync(64) {
+                    for (used, b) in wgr.iter_mut().enumerate() {
+                        if unsafe { read(FR) } & FR_RXFE != 0 {
+                            wgr.commit(used);
+                            handled_all = true;
+                            break 'read;
+                        }
+                        *b = unsafe { read(DR) } as u8;
+                    }
+
+                    let len = wgr.len();
+                    wgr.commit(len);
+                }
+            }
+
+            // If we have no producer, or it's full, discard whatever's left
+            // in the FIFO so that the interrupt is cleared.
+            if !handled_all {
+                while unsafe { read(FR) } & FR_RXFE == 0 {
+                    let _byte = unsafe { read(DR) };
+                }
+            }
+            unsafe { write(ICR, INT_RX | INT_RT) };
+        }
+    }
+
+    async fn sending(cons: Consumer) {
+        loop {
+            let rx = cons.read_grant().await;
+            let len = rx.len();
+
+            for &byte in rx.iter() {
+                while unsafe { read(FR) } & FR_TXFF != 0 {
+                    // pre-register wait future to ensure the waker is in
+                    // place before enabling the interrupt.
+                    let wait = TX_READY.subscribe().await;
+                    crate::without_interrupts(|| unsafe { write(IMSC, read(IMSC) | INT_TX) });
+                    wait.await.expect("UART TX_READY WaitCell is never closed!");
+                }
+                unsafe { write(DR, byte as u32) };
+            }
+
+            rx.release(len);
+        }
+    }
+
+    async fn serial_server(
+        handle: BidiHandle,
+        reqs: registry::listener::RequestStream<SimpleSerialService>,
+    ) {
+        let req = reqs.next_request().await;
+        let Request::GetPort = req.msg.body;
+        let resp = req.msg.reply_with(Ok(Response::PortHandle { handle }));
+        let _ = req.reply.reply_konly(resp).await;
+
+        // And deny all further requests after the first
+        loop {
+            let req = reqs.next_request().await;
+            let Request::GetPort = req.msg.body;
+            let resp = req
+                .msg
+                .reply_with(Err(SimpleSerialError::AlreadyAssignedPort));
+            let _ = req.reply.reply_konly(resp).await;
+        }
+    }
+
+    pub async fn register(
+        self,
+        k: &'static Kernel,
+        cap_in: usize,
+        cap_out: usize,
+    ) -> Result<(), registry::RegistrationError> {
+        let (listener, registration) = registry::Listener::new(4).await;
+        let reqs = listener.into_request_stream(4).await;
+        let (fifo_a, fifo_b) = new_bidi_channel(cap_in, cap_out).await;
+
+        let _server_hdl = k.spawn(Self::serial_server(fifo_b, reqs)).await;
+
+        let (prod, cons) = fifo_a.split();
+        let _send_hdl = k.spawn(Self::sending(cons)).await;
+
+        let boxed_prod = Box::new(prod).await;
+        let leaked_prod = Box::into_raw(boxed_prod);
+        let old = UART_RX.swap(leaked_prod, Ordering::AcqRel);
+        assert_eq!(old, null_mut());
+
+        unsafe {
+            let gic = Gic::summon();
+            gic.register(intid::UART0, Self::handle_uart0_int);
+            gic.activate(intid::UART0, IsrConfig::new(Priority::High))
+                .expect("UART0 interrupt should have a handler");
+            crate::without_interrupts(|| write(IMSC, read(IMSC) | INT_RX | INT_RT));
+        }
+
+        k.registry()
+            .register_konly::<SimpleSerialService>(registration)
+            .await?;
+
+        Ok(())
+    }
+}
+
+impl core::fmt::Write for Pl011 {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        self.write_blocking(s.as_bytes());
+        Ok(())
+    }
+}
+
+unsafe fn read(reg: usize) -> u32 {
+    ptr::read_volatile((BASE + reg) as *const u32)
+}
+
+unsafe fn write(reg: usize, value: u32) {
+    ptr::write_volatile((BASE + reg) as *mut u32, value)
+}
@@ -0,0 +1,85 @@
+//! The ARM generic timer.
+//!
+//! The kernel's clock is the EL1 virtual counter (`CNTVCT_EL0`), and the
+//! virtual timer is used to wake the CPU at the next timer deadline.
+use core::{arch::asm, time::Duration};
+
+use kernel::isr::IsrConfig;
+
+use crate::gic::{intid, Gic};
+
+// CNTV_CTL_EL0 bits
+const ENABLE: u64 = 1 << 0;
+const IMASK: u64 = 1 << 1;
+
+/// Returns the frequency of the system counter, in Hz.
+pub fn frequency() -> u64 {
+    let freq: u64;
+    unsafe { asm!("mrs {}, cntfrq_el0", out(reg) freq, options(nomem, nostack)) };
+    freq
+}
+
+/// Returns the period of the system counter.
+///
+/// QEMU's counter runs at 62.5MHz, so its period is a whole number of
+/// nanoseconds. On hardware where it isn't, this rounds down.
+pub fn tick_duration() -> Duration {
+    Duration::from_nanos(1_000_000_000 / frequency())
+}
+
+/// Returns the current value of the virtual counter.
+///
+/// This is used as the kernel's [`Clock`](kernel::maitake::time::Clock).
+pub fn now() -> u64 {
+    let ticks: u64;
+    unsafe { asm!("isb", "mrs {}, cntvct_el0", out(reg) ticks, options(nomem, nostack)) };
+    ticks
+}
+
+/// Arms the virtual timer to fire an interrupt `ticks` counter ticks from
+/// now.
+pub fn set_alarm(ticks: u64) {
+    let deadline = now().saturating_add(ticks);
+    unsafe {
+        asm!(
+            "msr cntv_cval_el0, {deadline}",
+            "msr cntv_ctl_el0, {ctl}",
+            "isb",
+            deadline = in(reg) deadline,
+            ctl = in(reg) ENABLE,
+            options(nomem, nostack),
+        )
+    };
+}
+
+/// Disarms the virtual timer.
+pub fn clear_alarm() {
+    unsafe {
+        asm!(
+            "msr cntv_ctl_el0, {ctl}",
+            "isb",
+            ctl = in(reg) IMASK,
+            options(nomem, nostack),
+        )
+    };
+}
+
+/// Registers and unmasks the virtual timer interrupt.
+///
+/// # Safety
+///
+/// The GIC must have been initialized.
+pub(crate) unsafe fn enable_interrupt(gic: &Gic) {
+    clear_alarm();
+    gic.register(intid::VIRTUAL_TIMER, handle_interrupt);
+    gic.activate(intid::VIRTUAL_TIMER, IsrConfig::DEFAULT)
+        .expect("virtual timer interrupt should have a handler");
+}
+
+/// Virtual timer interrupt handler
+///
+/// We don't actually do anything in the timer interrupt. It is only here to
+/// knock us out of WFI. Just disarm the timer to prevent refires
+fn handle_interrupt() {
+    clear_alarm();
+}
@@ -1,5 +1,6 @@
 #![no_std]
 use core::time::Duration;
+use mnemos_kernel::services::emb_display::{fonts::FontSize, DisplayTransform};
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -7,6 +8,71 @@ pub struct PlatformConfig {
     pub i2c: I2cConfiguration,
     pub i2c_puppet: I2cPuppetConfiguration,
     pub blink_service: LedBlinkService,
+    #[serde(default)]
+    pub smart_led: SmartLedConfiguration,
+    #[serde(default)]
+    pub sharp_display: SharpDisplayConfiguration,
+    #[serde(default)]
+    pub display: DisplayConfiguration,
+    #[serde(default)]
+    pub sleep_timer: SleepTimer,
+    #[serde(default)]
+    pub uart: UartConfiguration,
+    #[serde(default)]
+    pub spi_flash: SpiFlashConfiguration,
+}
+
+// Sleep
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SleepTimer {
+    /// The run loop is woken from sleep by `TIMER1`.
+    #[default]
+    Timer1,
+    /// The run loop is woken from long sleeps by the RTC alarm, and from
+    /// sleeps shorter than the alarm's one second resolution by `TIMER1`.
+    Rtc,
+}
+
+// UART
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct UartConfiguration {
+    /// RTS/CTS flow control for the kernel UART. If unset, flow control is
+    /// disabled.
+    #[serde(default)]
+    pub flow_control: Option<UartFlowControl>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct UartFlowControl {
+    /// The RTS output, which is driven low while the D1 is ready to receive.
+    pub rts: UartFlowControlPin,
+    /// The CTS input, which the other end drives low while it is ready to
+    /// receive.
+    pub cts: UartFlowControlPin,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum UartFlowControlPin {
+    PB10,
+    PB11,
+    PB12,
+    PE16,
+    PE17,
+}
+
+// SPI flash
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SpiFlashConfiguration {
+    /// Look for SPI NOR flash on SPI0 (pins PC2-PC7), and give the kernel
+    /// access to the config and key-value regions of the image on it. The
+    /// image must have been written by `cargo mnemos flash --to spi-nor`.
+    #[serde(default)]
+    pub enabled: bool,
 }
 
 // I2C
@@ -17,6 +83,8 @@ pub struct I2cConfiguration {
     pub enabled: bool,
     #[serde(default = "I2cConfiguration::default_mapping")]
     pub mapping: Mapping,
+    #[serde(default)]
+    pub mode: I2cMode,
 }
 
 impl I2cConfiguration {
@@ -34,6 +102,16 @@ pub enum Mapping {
     Twi3,
 }
 
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum I2cMode {
+    /// The TWI is the I2C bus controller.
+    #[default]
+    Controller,
+    /// The TWI is a target device on a bus driven by another controller.
+    Target,
+}
+
 // I2C Puppet
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -85,3 +163,119 @@ pub enum LedBlinkPin {
     PC1,
     PD18,
 }
+
+// Smart LEDs
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SmartLedConfiguration {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "SmartLedConfiguration::default_pin")]
+    pub pin: SmartLedPin,
+    /// The number of LEDs in each row.
+    #[serde(default = "SmartLedConfiguration::default_width")]
+    pub width: u32,
+    /// The number of rows of LEDs. For a single strip, this is 1.
+    #[serde(default = "SmartLedConfiguration::default_height")]
+    pub height: u32,
+    /// Global brightness, out of 255.
+    #[serde(default = "SmartLedConfiguration::default_brightness")]
+    pub brightness: u8,
+}
+
+impl SmartLedConfiguration {
+    const fn default_pin() -> SmartLedPin {
+        SmartLedPin::PC0
+    }
+
+    const fn default_width() -> u32 {
+        1
+    }
+
+    const fn default_height() -> u32 {
+        1
+    }
+
+    const fn default_brightness() -> u8 {
+        255
+    }
+}
+
+impl Default for SmartLedConfiguration {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            pin: Self::default_pin(),
+            width: Self::default_width(),
+            height: Self::default_height(),
+            brightness: Self::default_brightness(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum SmartLedPin {
+    PC0,
+}
+
+// SHARP memory display
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SharpDisplayConfiguration {
+    /// Rotation and scaling of the display's contents, for example when the
+    /// display is mounted sideways.
+    #[serde(default)]
+    pub transform: DisplayTransform,
+}
+
+// Display engine (HDMI/RGB)
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct DisplayConfiguration {
+    /// If enabled, the graphical shell is displayed using the display engine,
+    /// rather than on a SHARP memory display.
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default)]
+    pub output: DisplayOutput,
+    /// Rotation and scaling of the display's contents. Monitors attached over
+    /// HDMI are usually easier to read with a `scale` of 2 or more.
+    #[serde(default)]
+    pub transform: DisplayTransform,
+    /// Font size used by the graphical shell.
+    #[serde(default)]
+    pub font: FontSize,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DisplayOutput {
+    /// 1280x720 at 60 Hz, over HDMI.
+    #[default]
+    Hdmi,
+    /// A parallel RGB666 panel on pins PD0-PD21, with the given timings.
+    ///
+    /// These pins are shared with SPI1, so this can't be used with a SHARP
+    /// memory display.
+    Rgb(RgbTiming),
+}
+
+/// Timings for a parallel RGB panel. Horizontal values are in pixels, and
+/// vertical values are in lines.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct RgbTiming {
+    pub pixel_clock_hz: u32,
+    pub width: u32,
+    pub height: u32,
+    pub hfront_porch: u32,
+    pub hsync_len: u32,
+    pub hback_porch: u32,
+    pub vfront_porch: u32,
+    pub vsync_len: u32,
+    pub vback_porch: u32,
+    #[serde(default)]
+    pub hsync_active_high: bool,
+    #[serde(default)]
+    pub vsync_active_high: bool,
+}
@@ -0,0 +1,84 @@
+//! Data cache maintenance for DMA.
+//!
+//! The D1's XuanTie C906 core has a data cache which is not coherent with
+//! DMA, so memory shared with DMA-capable peripherals (the DMAC, and the SMHC's
+//! internal DMA controller) must be maintained in software. This module
+//! provides the DMA [`Attributes`] of the D1, which are used to reserve a DMA
+//! region with [`mnemos_alloc::dma::init`](kernel::mnemos_alloc::dma::init).
+//!
+//! Maintenance is performed using T-Head's vendor cache instructions, which
+//! operate on one cache line at a time, by physical address. Since we don't
+//! use the MMU, physical addresses are the same as virtual ones.
+use core::ptr::NonNull;
+
+use kernel::mnemos_alloc::dma::{Attributes, CacheOps, Coherence};
+
+/// The size of a C906 data cache line, in bytes.
+pub const LINE_SIZE: usize = 64;
+
+/// Returns the attributes of DMA memory on the D1.
+///
+/// DMA allocations are aligned to (and padded to) whole cache lines, and are
+/// cleaned and invalidated by the CPU.
+#[must_use]
+pub fn dma_attributes() -> Attributes {
+    Attributes {
+        align: LINE_SIZE,
+        coherence: Coherence::Manual(CacheOps { clean, invalidate }),
+    }
+}
+
+/// Enables the T-Head vendor instructions used for cache maintenance.
+///
+/// These instructions trap as illegal unless the `THEADISAEE` bit in
+/// `mxstatus` is set, which the boot ROM does not guarantee.
+///
+/// # Safety
+///
+/// This must be called before any cache maintenance is performed, and should
+/// only be called while running on an Allwinner D1.
+pub unsafe fn enable_vendor_extensions() {
+    #[cfg(any(target_arch = "riscv64", target_arch = "riscv32"))]
+    {
+        /// The bit in the `mxstatus` CSR which enables T-Head's vendor
+        /// instructions.
+        const MXSTATUS_THEADISAEE: usize = 1 << 22;
+        core::arch::asm!("csrs 0x7c0, {0}", in(reg) MXSTATUS_THEADISAEE);
+    }
+}
+
+/// Writes back any dirty cache lines overlapping the range to memory.
+unsafe fn clean(ptr: NonNull<u8>, len: usize) {
+    for _line in lines(ptr, len) {
+        // th.dcache.cpa {line}
+        #[cfg(any(target_arch = "riscv64", target_arch = "riscv32"))]
+        core::arch::asm!(".insn i 0x0b, 0, x0, {0}, 0x029", in(reg) _line, options(nostack));
+    }
+    sync();
+}
+
+/// Discards any cache lines overlapping the range.
+unsafe fn invalidate(ptr: NonNull<u8>, len: usize) {
+    for _line in lines(ptr, len) {
+        // th.dcache.ipa {line}
+        #[cfg(any(target_arch = "riscv64", target_arch = "riscv32"))]
+        core::arch::asm!(".insn i 0x0b, 0, x0, {0}, 0x02a", in(reg) _line, options(nostack));
+    }
+    sync();
+}
+
+/// Waits for all preceding cache operations to complete.
+fn sync() {
+    // th.sync.s
+    #[cfg(any(target_arch = "riscv64", target_arch = "riscv32"))]
+    unsafe {
+        core::arch::asm!(".insn i 0x0b, 0, x0, x0, 0x019", options(nostack));
+    }
+}
+
+/// Returns the address of each cache line which overlaps the range.
+fn lines(ptr: NonNull<u8>, len: usize) -> impl Iterator<Item = usize> {
+    let start = ptr.as_ptr() as usize & !(LINE_SIZE - 1);
+    let end = (ptr.as_ptr() as usize).saturating_add(len);
+    (start..end).step_by(LINE_SIZE)
+}
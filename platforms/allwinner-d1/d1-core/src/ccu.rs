@@ -9,12 +9,13 @@
 //! Copyright(c) 2007-2022 Jianjun Jiang <8192542@qq.com>
 use d1_pac::CCU;
 use d1_pac::DMAC;
+use d1_pac::LEDC;
 use d1_pac::{SMHC0, SMHC1, SMHC2};
 use d1_pac::{SPI0, SPI_DBI};
 use d1_pac::{TWI0, TWI1, TWI2, TWI3};
 use d1_pac::{UART0, UART1, UART2, UART3, UART4, UART5};
 
-use crate::clint::Clint;
+use crate::{clint::Clint, mmio};
 
 pub struct Ccu {
     ccu: CCU,
@@ -86,21 +87,27 @@ impl Ccu {
 
         macro_rules! set_module {
             ($module: ident) => {
-                if (self.ccu.$module.read().pll_en().bit_is_clear()) {
-                    self.ccu.$module.modify(|_, w| {
-                        w.pll_ldo_en().enable();
-                        w.pll_en().enable();
-                        w
+                if (mmio::read_reg(&self.ccu.$module, |r| r.read().pll_en().bit_is_clear())) {
+                    mmio::modify_reg(&self.ccu.$module, |r| {
+                        r.modify(|_, w| {
+                            w.pll_ldo_en().enable();
+                            w.pll_en().enable();
+                            w
+                        })
                     });
 
-                    self.ccu.$module.modify(|_, w| w.lock_enable().enable());
+                    mmio::modify_reg(&self.ccu.$module, |r| {
+                        r.modify(|_, w| w.lock_enable().enable())
+                    });
 
-                    while self.ccu.$module.read().lock().bit_is_clear() {
+                    while mmio::read_reg(&self.ccu.$module, |r| r.read().lock().bit_is_clear()) {
                         core::hint::spin_loop();
                     }
                     Clint::spin_delay_us(20);
 
-                    self.ccu.$module.modify(|_, w| w.lock_enable().disable());
+                    mmio::modify_reg(&self.ccu.$module, |r| {
+                        r.modify(|_, w| w.lock_enable().disable())
+                    });
                 }
             };
         }
@@ -116,134 +123,158 @@ impl Ccu {
     fn set_pll_cpux_axi(&mut self) {
         // Select DCXO (24 MHz) as CPU clock source.
         // AXI divide ratio is 3, system APB clock ratio is 4.
-        self.ccu.riscv_clk.write(|w| {
-            w.clk_src_sel().hosc();
-            w.axi_div_cfg().variant(3);
-            w.div_cfg().variant(1);
-            w
+        mmio::write_reg(&self.ccu.riscv_clk, |r| {
+            r.write(|w| {
+                w.clk_src_sel().hosc();
+                w.axi_div_cfg().variant(3);
+                w.div_cfg().variant(1);
+                w
+            })
         });
         Clint::spin_delay_us(1);
 
         // Disable PLL gating
-        self.ccu
-            .pll_cpu_ctrl
-            .modify(|_, w| w.pll_output_gate().disable());
+        mmio::modify_reg(&self.ccu.pll_cpu_ctrl, |r| {
+            r.modify(|_, w| w.pll_output_gate().disable())
+        });
 
         // Enable PLL LDO
-        self.ccu.pll_cpu_ctrl.modify(|_, w| w.pll_ldo_en().enable());
+        mmio::modify_reg(&self.ccu.pll_cpu_ctrl, |r| {
+            r.modify(|_, w| w.pll_ldo_en().enable())
+        });
         Clint::spin_delay_us(5);
 
         // Set default clock to 1008 MHz
-        self.ccu.pll_cpu_ctrl.modify(|r, w| {
-            // undocumented part of register is cleared by xboot
-            unsafe { w.bits(r.bits() & !(0x3 << 16)) };
-            w.pll_m().variant(0);
-            w.pll_n().variant(41);
-            w
+        mmio::modify_reg(&self.ccu.pll_cpu_ctrl, |r| {
+            r.modify(|r, w| {
+                // undocumented part of register is cleared by xboot
+                unsafe { w.bits(r.bits() & !(0x3 << 16)) };
+                w.pll_m().variant(0);
+                w.pll_n().variant(41);
+                w
+            })
         });
 
         // Lock the CPU PLL
-        self.ccu
-            .pll_cpu_ctrl
-            .modify(|_, w| w.lock_enable().enable());
+        mmio::modify_reg(&self.ccu.pll_cpu_ctrl, |r| {
+            r.modify(|_, w| w.lock_enable().enable())
+        });
 
         // Enable PLL
-        self.ccu.pll_cpu_ctrl.modify(|_, w| w.pll_en().enable());
+        mmio::modify_reg(&self.ccu.pll_cpu_ctrl, |r| {
+            r.modify(|_, w| w.pll_en().enable())
+        });
 
         // Wait until PLL is stable
-        while self.ccu.pll_cpu_ctrl.read().lock().bit_is_clear() {
+        while mmio::read_reg(&self.ccu.pll_cpu_ctrl, |r| r.read().lock().bit_is_clear()) {
             core::hint::spin_loop();
         }
         Clint::spin_delay_us(20);
 
         // Enable PLL gating
-        self.ccu
-            .pll_cpu_ctrl
-            .modify(|_, w| w.pll_output_gate().enable());
+        mmio::modify_reg(&self.ccu.pll_cpu_ctrl, |r| {
+            r.modify(|_, w| w.pll_output_gate().enable())
+        });
 
         // Unlock the CPU PLL
-        self.ccu
-            .pll_cpu_ctrl
-            .modify(|_, w| w.lock_enable().disable());
+        mmio::modify_reg(&self.ccu.pll_cpu_ctrl, |r| {
+            r.modify(|_, w| w.lock_enable().disable())
+        });
         Clint::spin_delay_us(1);
 
         // Change the CPU clock source to PLL_CPU.
         // Sets the RISC-V clock to 1008 MHz and the RISC-V AXI clock to 504 MHz.
-        self.ccu.riscv_clk.modify(|_, w| {
-            w.clk_src_sel().pll_cpu();
-            w.axi_div_cfg().variant(1);
-            w.div_cfg().variant(0);
-            w
+        mmio::modify_reg(&self.ccu.riscv_clk, |r| {
+            r.modify(|_, w| {
+                w.clk_src_sel().pll_cpu();
+                w.axi_div_cfg().variant(1);
+                w.div_cfg().variant(0);
+                w
+            })
         });
         Clint::spin_delay_us(1);
     }
 
     fn set_pll_periph0(&mut self) {
         // Check if PLL_PERI has already been enabled
-        if self.ccu.pll_peri_ctrl.read().pll_en().bit_is_set() {
+        if mmio::read_reg(&self.ccu.pll_peri_ctrl, |r| r.read().pll_en().bit_is_set()) {
             return;
         }
 
         // Change PSI source to DCXO (24 MHz)
-        self.ccu.psi_clk.modify(|_, w| w.clk_src_sel().hosc());
+        mmio::modify_reg(&self.ccu.psi_clk, |r| {
+            r.modify(|_, w| w.clk_src_sel().hosc())
+        });
 
         // Set the default value for PLL_N
-        self.ccu.pll_peri_ctrl.write(|w| w.pll_n().variant(0x63));
+        mmio::write_reg(&self.ccu.pll_peri_ctrl, |r| {
+            r.write(|w| w.pll_n().variant(0x63))
+        });
 
         // Lock the PLL
-        self.ccu
-            .pll_peri_ctrl
-            .modify(|_, w| w.lock_enable().enable());
+        mmio::modify_reg(&self.ccu.pll_peri_ctrl, |r| {
+            r.modify(|_, w| w.lock_enable().enable())
+        });
 
         // Enable 'PLL_PERI(1X)', 'PLL_PERI(2X)' and 'PLL_PERI(800M)'
-        self.ccu.pll_peri_ctrl.modify(|_, w| w.pll_en().enable());
+        mmio::modify_reg(&self.ccu.pll_peri_ctrl, |r| {
+            r.modify(|_, w| w.pll_en().enable())
+        });
 
         // Wait until PLL is stable
-        while self.ccu.pll_peri_ctrl.read().lock().bit_is_clear() {
+        while mmio::read_reg(&self.ccu.pll_peri_ctrl, |r| r.read().lock().bit_is_clear()) {
             core::hint::spin_loop();
         }
         Clint::spin_delay_us(20);
 
         // Unlock the PLL
-        self.ccu
-            .pll_peri_ctrl
-            .modify(|_, w| w.lock_enable().disable());
+        mmio::modify_reg(&self.ccu.pll_peri_ctrl, |r| {
+            r.modify(|_, w| w.lock_enable().disable())
+        });
     }
 
     fn set_ahb(&mut self) {
         // This could potentially be done in a single write, but we follow
         // the `xboot` implementation which also splits this in 2 operations.
-        self.ccu
-            .psi_clk
-            .write(|w| w.factor_m().variant(2).factor_n().n1());
-        self.ccu
-            .psi_clk
-            .modify(|_, w| w.clk_src_sel().pll_peri_1x());
+        mmio::write_reg(&self.ccu.psi_clk, |r| {
+            r.write(|w| w.factor_m().variant(2).factor_n().n1())
+        });
+        mmio::modify_reg(&self.ccu.psi_clk, |r| {
+            r.modify(|_, w| w.clk_src_sel().pll_peri_1x())
+        });
         Clint::spin_delay_us(1);
     }
 
     fn set_apb(&mut self) {
         // This could potentially be done in a single write, but we follow
         // the `xboot` implementation which also splits this in 2 operations.
-        self.ccu.apb_clk[0].write(|w| w.factor_m().variant(2).factor_n().n2());
-        self.ccu.apb_clk[0].modify(|_, w| w.clk_src_sel().pll_peri_1x());
+        mmio::write_reg(&self.ccu.apb_clk[0], |r| {
+            r.write(|w| w.factor_m().variant(2).factor_n().n2())
+        });
+        mmio::modify_reg(&self.ccu.apb_clk[0], |r| {
+            r.modify(|_, w| w.clk_src_sel().pll_peri_1x())
+        });
         Clint::spin_delay_us(1);
     }
 
     fn set_mbus(&mut self) {
         // Reset the MBUS domain
-        self.ccu.mbus_clk.modify(|_, w| w.mbus_rst().deassert());
+        mmio::modify_reg(&self.ccu.mbus_clk, |r| {
+            r.modify(|_, w| w.mbus_rst().deassert())
+        });
         Clint::spin_delay_us(1);
         // Enable MBUS master clock gating
-        self.ccu.mbus_mat_clk_gating.write(|w| {
-            w.dma_mclk_en().pass();
-            w.ve_mclk_en().pass();
-            w.ce_mclk_en().pass();
-            w.tvin_mclk_en().pass();
-            w.csi_mclk_en().pass();
-            w.g2d_mclk_en().pass();
-            w.riscv_mclk_en().pass();
-            w
+        mmio::write_reg(&self.ccu.mbus_mat_clk_gating, |r| {
+            r.write(|w| {
+                w.dma_mclk_en().pass();
+                w.ve_mclk_en().pass();
+                w.ce_mclk_en().pass();
+                w.tvin_mclk_en().pass();
+                w.csi_mclk_en().pass();
+                w.g2d_mclk_en().pass();
+                w.riscv_mclk_en().pass();
+                w
+            })
         });
     }
 }
@@ -253,15 +284,15 @@ macro_rules! impl_bgr {
         $(
             impl BusGatingResetRegister for $MODULE {
                 fn gating(ccu: &mut CCU, gating: BusGating) {
-                    ccu.$reg.modify(|_, w| {
+                    mmio::modify_reg(&ccu.$reg, |r| r.modify(|_, w| {
                         w.$gating().bit(gating == BusGating::Pass)
-                    });
+                    }));
                 }
 
                 fn reset(ccu: &mut CCU, reset: BusReset) {
-                    ccu.$reg.modify(|_, w| {
+                    mmio::modify_reg(&ccu.$reg, |r| r.modify(|_, w| {
                         w.$reset().bit(reset == BusReset::Deassert)
-                    });
+                    }));
                 }
             }
         )+
@@ -270,6 +301,7 @@ macro_rules! impl_bgr {
 
 impl_bgr! {
     DMAC:    (dma_bgr, gating, rst),
+    LEDC:    (ledc_bgr, gating, rst),
     SMHC0:   (smhc_bgr, smhc0_gating, smhc0_rst),
     SMHC1:   (smhc_bgr, smhc1_gating, smhc1_rst),
     SMHC2:   (smhc_bgr, smhc2_gating, smhc2_rst),
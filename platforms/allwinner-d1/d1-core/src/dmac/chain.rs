@@ -0,0 +1,401 @@
+//! Scatter-gather DMA transfers using chains of linked [`Descriptor`]s.
+//!
+//! A single DMAC [`Descriptor`] describes a transfer between one contiguous
+//! source and one contiguous destination. However, a descriptor may link to
+//! another descriptor, which the DMAC will process once the first one
+//! completes. A [`DescriptorChain`] uses this to perform a transfer to or from
+//! several non-contiguous buffers (or a buffer longer than
+//! [`Descriptor::MAX_LEN`]) as a single DMA transfer.
+//!
+//! Descriptors in a chain are allocated from a static pool of
+//! [`POOL_SIZE`] descriptors, rather than from the heap or the stack. Link
+//! addresses must be 32 bits wide and 4-byte aligned, which is always true of
+//! the pool, and keeping descriptors out of the heap means that a chain can be
+//! built without waiting for an allocation. Since the pool isn't allocated
+//! from [DMA memory](kernel::mnemos_alloc::dma), each descriptor is cleaned
+//! from the data cache when the chain is transferred. The DMAC never writes to
+//! descriptors, so they never need to be invalidated.
+//!
+//! Because the DMAC only raises the channel's queue interrupt when it reaches
+//! the end of the chain, a chained transfer completes when its final
+//! descriptor completes, rather than waking the transferring task once per
+//! descriptor.
+use core::{
+    cell::UnsafeCell,
+    fmt,
+    marker::PhantomData,
+    mem::{self, MaybeUninit},
+    pin::pin,
+    ptr::NonNull,
+    slice,
+};
+
+use kernel::{maitake::sync::WaitQueue, mnemos_alloc::dma};
+use mnemos_bitslab::index::IndexAlloc64;
+
+use super::{
+    descriptor::{errors::InvalidDescriptor, Descriptor, DescriptorBuilder, DestBuf},
+    Channel,
+};
+
+/// The total number of [`Descriptor`]s available to all [`DescriptorChain`]s.
+pub const POOL_SIZE: usize = 64;
+
+/// The maximum number of descriptors in a single [`DescriptorChain`].
+pub const MAX_CHAIN_LEN: usize = 16;
+
+/// A chain of linked DMA [`Descriptor`]s.
+///
+/// The chain borrows the memory operands of each of its descriptors for
+/// `'buf`, so those buffers cannot be used (or dropped) while the chain
+/// exists. Dropping the chain returns its descriptors to the pool.
+///
+/// Chains are transferred using [`Channel::transfer_chain`].
+#[must_use = "a `DescriptorChain` does nothing unless it is transferred"]
+pub struct DescriptorChain<'buf> {
+    /// Indices of this chain's descriptors in the pool, in order.
+    slots: [u8; MAX_CHAIN_LEN],
+    len: usize,
+    _buf: PhantomData<&'buf mut [u8]>,
+}
+
+/// A [`DescriptorBuilder`] whose source and destination operands are
+/// configured, and which can be added to a [`DescriptorChain`].
+///
+/// This is implemented for every [`DescriptorBuilder`] with at least one
+/// memory operand. Register-to-register transfers cannot be chained, since
+/// they don't borrow any memory.
+pub trait ChainLink<'buf> {
+    /// Builds the configured [`Descriptor`].
+    fn build_link(self) -> Descriptor;
+}
+
+/// Errors returned by [`DescriptorChain::push`] and
+/// [`DescriptorChain::try_push`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ChainError {
+    /// The chain already contains [`MAX_CHAIN_LEN`] descriptors.
+    TooLong,
+    /// All of the descriptors in the pool are in use.
+    PoolExhausted,
+    /// The descriptor could not be linked to the previous descriptor in the
+    /// chain.
+    Invalid(InvalidDescriptor),
+}
+
+struct Pool {
+    descriptors: [UnsafeCell<MaybeUninit<Descriptor>>; POOL_SIZE],
+    slots: IndexAlloc64,
+    slot_wait: WaitQueue,
+}
+
+// Safety: a descriptor in the pool is only accessed through the
+// `DescriptorChain` which allocated its slot.
+unsafe impl Sync for Pool {}
+
+static POOL: Pool = {
+    // This `const` is used as a static initializer, so clippy is wrong here...
+    #[allow(clippy::declare_interior_mutable_const)]
+    const EMPTY: UnsafeCell<MaybeUninit<Descriptor>> = UnsafeCell::new(MaybeUninit::uninit());
+    Pool {
+        descriptors: [EMPTY; POOL_SIZE],
+        slots: IndexAlloc64::new(),
+        slot_wait: WaitQueue::new(),
+    }
+};
+
+// === impl DescriptorChain ===
+
+impl<'buf> DescriptorChain<'buf> {
+    /// Returns a new, empty chain.
+    pub const fn new() -> Self {
+        Self {
+            slots: [0; MAX_CHAIN_LEN],
+            len: 0,
+            _buf: PhantomData,
+        }
+    }
+
+    /// Appends a descriptor to the end of the chain, waiting for a descriptor
+    /// to become available in the pool if they are all in use.
+    ///
+    /// # Cancel Safety
+    ///
+    /// This future can be cancelled freely. If it is cancelled, the chain is
+    /// unchanged.
+    pub async fn push(&mut self, link: impl ChainLink<'buf>) -> Result<(), ChainError> {
+        if self.len == MAX_CHAIN_LEN {
+            return Err(ChainError::TooLong);
+        }
+
+        let slot = match POOL.slots.allocate() {
+            Some(slot) => slot,
+            None => loop {
+                let mut wait = pin!(POOL.slot_wait.wait());
+                // ensure the `WaitQueue` entry is registered before we actually
+                // check the pool again.
+                let _ = wait.as_mut().subscribe();
+                if let Some(slot) = POOL.slots.allocate() {
+                    break slot;
+                }
+                // the pool's `WaitQueue` is never closed, but if it were, we'd
+                // just try to allocate again.
+                let _ = wait.await;
+            },
+        };
+        self.link(slot, link.build_link())
+    }
+
+    /// Appends a descriptor to the end of the chain, if one is available in
+    /// the pool.
+    pub fn try_push(&mut self, link: impl ChainLink<'buf>) -> Result<(), ChainError> {
+        if self.len == MAX_CHAIN_LEN {
+            return Err(ChainError::TooLong);
+        }
+
+        let slot = POOL.slots.allocate().ok_or(ChainError::PoolExhausted)?;
+        self.link(slot, link.build_link())
+    }
+
+    /// Returns the number of descriptors in the chain.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if the chain contains no descriptors.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the first descriptor in the chain, which the DMAC starts the
+    /// transfer from.
+    pub(super) fn first(&self) -> Option<NonNull<Descriptor>> {
+        (!self.is_empty()).then(|| Self::descriptor(self.slots[0]))
+    }
+
+    /// Cleans each of the chain's descriptors from the data cache, so that
+    /// the DMAC sees them.
+    fn sync_for_device(&self) {
+        for &slot in &self.slots[..self.len] {
+            let descriptor = Self::descriptor(slot).cast::<u8>();
+            // Safety: we own these slots, and they were initialized when they
+            // were pushed.
+            let bytes =
+                unsafe { slice::from_raw_parts(descriptor.as_ptr(), mem::size_of::<Descriptor>()) };
+            dma::sync_for_device(bytes);
+        }
+    }
+
+    fn link(&mut self, slot: u8, mut descriptor: Descriptor) -> Result<(), ChainError> {
+        let next = Self::descriptor(slot);
+        // This is now the end of the chain. Unlinking a descriptor can't fail.
+        let _ = descriptor.set_link(None);
+
+        if let Some(&prev) = self.slots[..self.len].last() {
+            // Safety: we own the previous slot, and it's not being transferred,
+            // since we have `&mut self`.
+            let prev = unsafe { &mut *Self::descriptor(prev).as_ptr() };
+            if let Err(error) = prev.set_link(next) {
+                Self::free(slot);
+                return Err(ChainError::Invalid(InvalidDescriptor::LinkAddr(error)));
+            }
+        }
+
+        // Safety: we just allocated this slot, so no one else is using it.
+        unsafe { next.as_ptr().write(descriptor) };
+        self.slots[self.len] = slot;
+        self.len += 1;
+        Ok(())
+    }
+
+    fn descriptor(slot: u8) -> NonNull<Descriptor> {
+        // Safety: `UnsafeCell::get` never returns a null pointer.
+        unsafe { NonNull::new_unchecked(POOL.descriptors[slot as usize].get()) }.cast()
+    }
+
+    fn free(slot: u8) {
+        POOL.slots.free(slot);
+        POOL.slot_wait.wake();
+    }
+}
+
+impl Default for DescriptorChain<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for DescriptorChain<'_> {
+    fn drop(&mut self) {
+        for &slot in &self.slots[..self.len] {
+            Self::free(slot);
+        }
+    }
+}
+
+impl fmt::Debug for DescriptorChain<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(
+                self.slots[..self.len]
+                    .iter()
+                    // Safety: we own these slots, and they were initialized
+                    // when they were pushed.
+                    .map(|&slot| unsafe { Self::descriptor(slot).as_ref() }),
+            )
+            .finish()
+    }
+}
+
+// === impl ChainLink ===
+
+impl<'buf> ChainLink<'buf> for DescriptorBuilder<&'buf [u8], DestBuf<'buf>> {
+    fn build_link(self) -> Descriptor {
+        self.build()
+    }
+}
+
+impl<'buf> ChainLink<'buf> for DescriptorBuilder<*const (), DestBuf<'buf>> {
+    fn build_link(self) -> Descriptor {
+        self.build()
+    }
+}
+
+impl<'buf> ChainLink<'buf> for DescriptorBuilder<&'buf [u8], *mut ()> {
+    fn build_link(self) -> Descriptor {
+        self.build()
+    }
+}
+
+// === impl ChainError ===
+
+impl fmt::Display for ChainError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TooLong => write!(
+                f,
+                "descriptor chains may not be longer than {}",
+                MAX_CHAIN_LEN
+            ),
+            Self::PoolExhausted => f.write_str("all DMA descriptors are in use"),
+            Self::Invalid(error) => fmt::Display::fmt(error, f),
+        }
+    }
+}
+
+// === impl Channel ===
+
+impl Channel {
+    /// Performs a scatter-gather DMA transfer of every descriptor in `chain`
+    /// on this channel.
+    ///
+    /// The transfer completes once the final descriptor in the chain has
+    /// completed. If the chain is empty, this completes immediately.
+    ///
+    /// # Safety
+    ///
+    /// Because `chain` borrows the memory used by the transfer, the memory is
+    /// guaranteed to be valid for as long as this future exists. However, as
+    /// with [`Channel::transfer`], it is **not okay** to
+    /// [`core::mem::forget`] this future, as the transfer would continue
+    /// after the chain and its buffers are released.
+    ///
+    /// # Cancel Safety
+    ///
+    /// Dropping this future cancels the DMA transfer, as with
+    /// [`Channel::transfer`]. Any number of the chain's descriptors may have
+    /// already completed.
+    ///
+    /// The chain's descriptors are made visible to the DMAC before the
+    /// transfer starts, but its buffers are not. As with
+    /// [`Channel::transfer`], the caller is responsible for maintaining the
+    /// data cache for the memory the chain reads and writes.
+    pub async unsafe fn transfer_chain(&mut self, chain: &DescriptorChain<'_>) {
+        chain.sync_for_device();
+        if let Some(first) = chain.first() {
+            self.transfer(first).await
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Link;
+
+    impl ChainLink<'static> for Link {
+        fn build_link(self) -> Descriptor {
+            // Every field of a descriptor is an integer, so a zeroed
+            // descriptor is valid.
+            unsafe { mem::zeroed() }
+        }
+    }
+
+    fn chain_of(len: usize) -> Result<DescriptorChain<'static>, ChainError> {
+        let mut chain = DescriptorChain::new();
+        for _ in 0..len {
+            chain.try_push(Link)?;
+        }
+        Ok(chain)
+    }
+
+    // The pool is shared, so everything that uses it is tested in one test.
+    #[test]
+    fn pool() {
+        let mut chains = (0..POOL_SIZE)
+            .map(|_| chain_of(1).unwrap())
+            .collect::<Vec<_>>();
+        let mut firsts = chains
+            .iter()
+            .map(|chain| chain.first().unwrap())
+            .collect::<Vec<_>>();
+        firsts.sort();
+        firsts.dedup();
+        assert_eq!(firsts.len(), POOL_SIZE, "each chain has its own slot");
+
+        assert_eq!(chain_of(1).unwrap_err(), ChainError::PoolExhausted);
+        // Dropping a chain returns its descriptors to the pool.
+        drop(chains.pop());
+        let empty = chain_of(0).unwrap();
+        assert!(empty.is_empty());
+        assert_eq!(empty.first(), None);
+        chains.push(chain_of(1).unwrap());
+
+        // Linking needs the next descriptor's address to fit in 32 bits,
+        // which it always does on the D1, but may not on the host.
+        let last = DescriptorChain::descriptor(POOL_SIZE as u8 - 1);
+        let can_link = last.as_ptr() as usize <= Descriptor::LINK_ADDR_MAX;
+        drop(chains.pop());
+        let linked = chains[0].try_push(Link);
+        if can_link {
+            linked.unwrap();
+            assert_eq!(chains[0].len(), 2);
+        } else {
+            assert!(
+                matches!(
+                    linked,
+                    Err(ChainError::Invalid(InvalidDescriptor::LinkAddr(_)))
+                ),
+                "{linked:?}"
+            );
+            assert_eq!(chains[0].len(), 1);
+            // The descriptor that couldn't be linked was returned to the pool.
+            chains.push(chain_of(1).unwrap());
+        }
+        drop(chains);
+
+        if can_link {
+            let mut chain = chain_of(MAX_CHAIN_LEN).unwrap();
+            assert_eq!(chain.try_push(Link), Err(ChainError::TooLong));
+        }
+
+        // Every descriptor is back in the pool.
+        let chains = (0..POOL_SIZE)
+            .map(|_| chain_of(1).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(chains.len(), POOL_SIZE);
+    }
+}
@@ -215,7 +215,7 @@ impl DescriptorBuilder {
     }
 }
 
-type DestBuf<'dest> = &'dest mut [mem::MaybeUninit<u8>];
+pub(super) type DestBuf<'dest> = &'dest mut [mem::MaybeUninit<u8>];
 
 impl<S, D> DescriptorBuilder<S, D> {
     pub fn src_block_size(self, val: BlockSize) -> Self {
@@ -715,6 +715,41 @@ mod tests {
         Cfg::assert_valid();
     }
 
+    #[test]
+    fn link_encoding() {
+        let mut descriptor = DescriptorBuilder::new().build_inner(0, 0, 0);
+        assert_eq!(descriptor.link, Descriptor::END_LINK);
+
+        let next = NonNull::new(0x4000_1000 as *mut Descriptor).unwrap();
+        descriptor.set_link(next).unwrap();
+        assert_eq!(descriptor.link, 0x4000_1000);
+        descriptor.set_link(None).unwrap();
+        assert_eq!(descriptor.link, Descriptor::END_LINK);
+
+        // Invalid links leave the descriptor unchanged.
+        let misaligned = NonNull::new(0x4000_1002 as *mut Descriptor).unwrap();
+        assert_eq!(
+            descriptor.set_link(misaligned),
+            Err(InvalidLink::Misaligned(0x4000_1002))
+        );
+        #[cfg(target_pointer_width = "64")]
+        {
+            let high = NonNull::new(0x1_0000_0000 as *mut Descriptor).unwrap();
+            assert_eq!(
+                descriptor.set_link(high),
+                Err(InvalidLink::TooLong(0x1_0000_0000))
+            );
+        }
+        assert_eq!(descriptor.link, Descriptor::END_LINK);
+
+        // The builder encodes links the same way.
+        let descriptor = DescriptorBuilder::new()
+            .link(next)
+            .unwrap()
+            .build_inner(0, 0, 0);
+        assert_eq!(descriptor.link, 0x4000_1000);
+    }
+
     #[derive(proptest_derive::Arbitrary, Debug)]
     struct ArbitraryConfig {
         src_drq_type: SrcDrqType,
@@ -13,12 +13,13 @@ use d1_pac::{
     DMAC,
 };
 
-use crate::ccu::Ccu;
+use crate::{ccu::Ccu, mmio};
 use kernel::maitake::sync::{WaitCell, WaitQueue};
 use mnemos_bitslab::index::IndexAlloc16;
 
 use self::descriptor::Descriptor;
 
+pub mod chain;
 pub mod descriptor;
 
 /// A handle to the DMA controller (DMAC) peripheral.
@@ -173,13 +174,15 @@ impl Dmac {
             for idx in 0..16 {
                 if idx < 8 {
                     // if the channel number is 0-7, it's in the `DMAC_IRQ_EN0` register.
-                    dmac.dmac_irq_en0
-                        .modify(|r, w| w.bits(set_queue_irq_en(idx, r.bits())));
+                    mmio::modify_reg(&dmac.dmac_irq_en0, |r| {
+                        r.modify(|r, w| w.bits(set_queue_irq_en(idx, r.bits())))
+                    });
                 } else {
                     // otherwise, if the channel number is 8-15, it's in the
                     // `DMAC_IRQ_EN1` register, instead.
-                    dmac.dmac_irq_en1
-                        .modify(|r, w| w.bits(set_queue_irq_en(idx - 8, r.bits())));
+                    mmio::modify_reg(&dmac.dmac_irq_en1, |r| {
+                        r.modify(|r, w| w.bits(set_queue_irq_en(idx - 8, r.bits())))
+                    });
                 }
             }
         });
@@ -212,6 +215,12 @@ impl Dmac {
     /// `mem::forget` a DMA transfer future inside your driver, you deserve
     /// whatever happens next.
     ///
+    /// The D1's data cache is not coherent with DMA, so the descriptor and any
+    /// memory read by the transfer must also have been made visible to the
+    /// DMAC, and memory written by the transfer must be made visible to the
+    /// CPU before it is read. This is easiest if the descriptor and buffers
+    /// are allocated using [`kernel::mnemos_alloc::dma`].
+    ///
     /// # Cancel Safety
     ///
     /// Dropping this future cancels the DMA transfer. If this future is
@@ -273,8 +282,9 @@ impl Dmac {
             }
 
             // welp, someone else got it. oh well. wait for the next one.
-            wait.await
-                .expect("DMAC channel allocation WaitQueue is never closed.");
+            // the claim `WaitQueue` is never closed, but if it were, we'd
+            // just try to claim a channel again.
+            let _ = wait.await;
         }
     }
 
@@ -303,7 +313,8 @@ impl Dmac {
         // there are two registers that contain DMA channel IRQ status bits,
         // `DMAC_IRQ_PEND0` and `DMAC_IRQ_PEND1`. the first 8 channels (0-7) set
         // bits in `DMA_IRQ_PEND0` when their IRQs fire...
-        dmac.dmac_irq_pend0.modify(|r, w| {
+        mmio::modify_reg(&dmac.dmac_irq_pend0, |r| {
+            r.modify(|r, w| {
             tracing::trace!(dmac_irq_pend0 = ?format_args!("{:#b}", r.bits()), "DMAC interrupt");
             for i in 0..8 {
                 if unsafe { r.dma_queue_irq_pend(i) }.bit_is_set() {
@@ -313,11 +324,13 @@ impl Dmac {
 
             // Will write-back any high bits, clearing the interrupt.
             w
+        })
         });
 
         // ...and the second 8 channels (8-15) set their status bits in
         // `DMAC_IRQ_PEND1` instead
-        dmac.dmac_irq_pend1.modify(|r, w| {
+        mmio::modify_reg(&dmac.dmac_irq_pend1, |r| {
+            r.modify(|r, w| {
             tracing::trace!(dmac_irq_pend1 = ?format_args!("{:#b}", r.bits()), "DMAC interrupt");
             for i in 8..16 {
                 if unsafe { r.dma_queue_irq_pend(i) }.bit_is_set() {
@@ -327,6 +340,7 @@ impl Dmac {
 
             // Will write-back any high bits, clearing the interrupt.
             w
+        })
         });
     }
 
@@ -392,6 +406,12 @@ impl Channel {
     /// `mem::forget` a DMA transfer future inside your driver, you deserve
     /// whatever happens next.
     ///
+    /// The D1's data cache is not coherent with DMA, so the descriptor and any
+    /// memory read by the transfer must also have been made visible to the
+    /// DMAC, and memory written by the transfer must be made visible to the
+    /// CPU before it is read. This is easiest if the descriptor and buffers
+    /// are allocated using [`kernel::mnemos_alloc::dma`].
+    ///
     /// # Cancel Safety
     ///
     /// Dropping this future cancels the DMA transfer. If this future is
@@ -452,16 +472,18 @@ impl Channel {
     /// the transfer is in progress, preventing the channel modes from being
     /// changed.
     pub unsafe fn set_channel_modes(&mut self, src: ChannelMode, dst: ChannelMode) {
-        self.mode_reg().write(|w| {
-            match src {
-                ChannelMode::Wait => w.dma_src_mode().waiting(),
-                ChannelMode::Handshake => w.dma_src_mode().handshake(),
-            };
-            match dst {
-                ChannelMode::Wait => w.dma_dst_mode().waiting(),
-                ChannelMode::Handshake => w.dma_dst_mode().handshake(),
-            };
-            w
+        mmio::write_reg(self.mode_reg(), |r| {
+            r.write(|w| {
+                match src {
+                    ChannelMode::Wait => w.dma_src_mode().waiting(),
+                    ChannelMode::Handshake => w.dma_src_mode().handshake(),
+                };
+                match dst {
+                    ChannelMode::Wait => w.dma_dst_mode().waiting(),
+                    ChannelMode::Handshake => w.dma_dst_mode().handshake(),
+                };
+                w
+            })
         })
     }
 
@@ -608,13 +630,15 @@ impl Channel {
         fence(Ordering::SeqCst); //////
 
         let desc_addr = desc.as_ptr() as usize;
-        self.desc_addr_reg().write(|w| {
-            w.dma_desc_addr().variant((desc_addr >> 2) as u32);
-            w.dma_desc_high_addr()
-                .variant(((desc_addr >> 32) as u8) & 0b11);
-            w
+        mmio::write_reg(self.desc_addr_reg(), |r| {
+            r.write(|w| {
+                w.dma_desc_addr().variant((desc_addr >> 2) as u32);
+                w.dma_desc_high_addr()
+                    .variant(((desc_addr >> 32) as u8) & 0b11);
+                w
+            })
         });
-        self.en_reg().write(|w| w.dma_en().enabled());
+        mmio::write_reg(self.en_reg(), |r| r.write(|w| w.dma_en().enabled()));
 
         fence(Ordering::SeqCst); //////
     }
@@ -631,7 +655,7 @@ impl Channel {
     /// with no transfer currently in flight seems fine, actually. But, this
     /// does a raw MMIO register write, so.
     unsafe fn stop_dma(&mut self) {
-        self.en_reg().write(|w| w.dma_en().disabled());
+        mmio::write_reg(self.en_reg(), |r| r.write(|w| w.dma_en().disabled()));
         fence(Ordering::SeqCst); //////
     }
 }
@@ -0,0 +1,1080 @@
+//! Driver for the Allwinner D1's display pipeline: the display engine (DE2),
+//! and the TCON timing controllers which drive its HDMI and parallel RGB
+//! outputs.
+//!
+//! Unlike the [SHARP memory display](super::sharp_display), which is drawn to
+//! over SPI, the display engine continuously scans out a framebuffer in RAM.
+//! This driver allocates one 32-bit XRGB framebuffer for the whole display,
+//! and provides an [`EmbDisplayService`] server which draws chunks sent by
+//! clients directly into it. Since the framebuffer is scanned out by hardware,
+//! there is no separate redraw task: a chunk is visible as soon as the driver
+//! has replied to the request that drew it.
+//!
+//! ## Pipeline
+//!
+//! ```text
+//! framebuffer -> DE2 mixer 0 -> TCON_TV0  -> HDMI -> monitor
+//!                            \-> TCON_LCD0 -> PD0..PD21 -> RGB panel
+//! ```
+//!
+//! The framebuffer is displayed on the first layer of mixer 0's UI channel,
+//! which is blended onto an opaque black background. The mixer's output is
+//! routed to one of the TCONs by the `DISP_IF_TOP` block, depending on the
+//! selected [`Output`]:
+//!
+//! - [`Output::Hdmi`] drives the HDMI transmitter from `TCON_TV0`. The HDMI
+//!   transmitter is a Synopsys DesignWare HDMI controller and PHY, which is
+//!   configured in DVI mode, so no InfoFrames are sent. Currently, only the
+//!   1280x720 at 60 Hz CEA mode ([`Timing::HDMI_720P60`]) is supported, as the
+//!   PHY is only configured for its 74.25 MHz pixel clock. This is the output
+//!   connected to the HDMI connectors of the MangoPi MQ Pro and the Lichee RV
+//!   Dock.
+//! - [`Output::Rgb`] drives a parallel RGB666 LCD panel with arbitrary timings
+//!   from `TCON_LCD0`, on pins `PD0` through `PD21`. Note that these pins
+//!   overlap with those used by SPI1 (and therefore the SHARP memory display),
+//!   and with the MQ Pro's on-board LED.
+//!
+//! ## References
+//!
+//! Most of this hardware is only partially documented in the D1 user manual.
+//! Register layouts and initialization sequences which aren't documented there
+//! follow the Linux kernel's `sun4i` and `sun8i` DRM drivers
+//! (`sun4i_tcon.c`, `sun8i_mixer.c`, `sun8i_ui_layer.c`, `sun8i_tcon_top.c`,
+//! `sun8i_hdmi_phy.c`) and the DesignWare HDMI driver (`dw-hdmi.c`).
+#![warn(missing_docs)]
+
+use core::time::Duration;
+
+use crate::{ccu::Ccu, clint::Clint, mmio};
+use d1_pac::{CCU, GPIO};
+use kernel::{
+    mnemos_alloc::containers::HeapArray,
+    registry::{self, listener},
+    services::emb_display::{
+        capabilities, DisplayMetadata, DisplayTransform, EmbDisplayService, FrameChunk, FrameError,
+        FrameKind, MonoChunk, Request, Response,
+    },
+    Kernel,
+};
+
+/// A handle to the initialized display pipeline.
+pub struct DisplayEngine {
+    timing: Timing,
+    output: Output,
+}
+
+/// Which of the D1's display outputs is driven by the [`DisplayEngine`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Output {
+    /// HDMI, driven by `TCON_TV0`.
+    Hdmi,
+    /// Parallel RGB666 on pins `PD0`-`PD21`, driven by `TCON_LCD0`.
+    Rgb,
+}
+
+/// Video timings.
+///
+/// All horizontal values are in pixels, and all vertical values are in lines.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Timing {
+    /// The pixel clock, in Hz.
+    pub pixel_clock_hz: u32,
+    /// Width of the visible area.
+    pub width: u32,
+    /// Height of the visible area.
+    pub height: u32,
+    /// Horizontal front porch.
+    pub hfront_porch: u32,
+    /// Width of the horizontal sync pulse.
+    pub hsync_len: u32,
+    /// Horizontal back porch.
+    pub hback_porch: u32,
+    /// Vertical front porch.
+    pub vfront_porch: u32,
+    /// Height of the vertical sync pulse.
+    pub vsync_len: u32,
+    /// Vertical back porch.
+    pub vback_porch: u32,
+    /// Whether the horizontal sync pulse is active-high.
+    pub hsync_active_high: bool,
+    /// Whether the vertical sync pulse is active-high.
+    pub vsync_active_high: bool,
+}
+
+/// Errors returned by [`DisplayEngine::register`].
+#[derive(Debug)]
+pub enum RegistrationError {
+    /// Failed to register a display: either the kernel reported that there is
+    /// already an existing EmbDisplay, or the registry is full.
+    Registration(registry::RegistrationError),
+}
+
+/// Pixel color used for set pixels.
+const FOREGROUND: u32 = 0x00FF_FFFF;
+/// Pixel color used for cleared pixels.
+const BACKGROUND: u32 = 0x0000_0000;
+
+/// Pin function select value which routes `PD0`-`PD21` to `LCD0`.
+const FUNCTION_LCD0: u32 = 0x2;
+
+/// The `PLL_VIDEO0(1X)` output frequency. Both TCONs derive their pixel clocks
+/// from this.
+const PLL_VIDEO0_1X_HZ: u32 = 297_000_000;
+
+// Base addresses of blocks which aren't in the PAC.
+const DE_BASE: usize = 0x0500_0000;
+const MIXER0_BASE: usize = DE_BASE + 0x0010_0000;
+const DISP_IF_TOP_BASE: usize = 0x0546_0000;
+const TCON_LCD0_BASE: usize = 0x0546_1000;
+const TCON_TV0_BASE: usize = 0x0547_0000;
+const HDMI_BASE: usize = 0x0550_0000;
+const HDMI_PHY_BASE: usize = HDMI_BASE + 0x0001_0000;
+
+/// CCU register offsets and bits. The PAC doesn't cover all of the display
+/// clocks, so these are written by hand.
+mod ccu_regs {
+    pub const PLL_VIDEO0_CTRL: usize = 0x040;
+    pub const DE_CLK: usize = 0x600;
+    pub const DE_BGR: usize = 0x60C;
+    pub const DPSS_TOP_BGR: usize = 0xABC;
+    pub const HDMI_24M_CLK: usize = 0xB04;
+    pub const HDMI_BGR: usize = 0xB1C;
+    pub const TCON_LCD0_CLK: usize = 0xB60;
+    pub const TCON_LCD_BGR: usize = 0xB7C;
+    pub const TCON_TV_CLK: usize = 0xB80;
+    pub const TCON_TV_BGR: usize = 0xB9C;
+
+    // `PLL_VIDEO0_CTRL` bits.
+    pub const PLL_EN: u32 = 1 << 31;
+    pub const PLL_LDO_EN: u32 = 1 << 30;
+    pub const PLL_LOCK_EN: u32 = 1 << 29;
+    pub const PLL_LOCK: u32 = 1 << 28;
+    pub const PLL_OUTPUT_EN: u32 = 1 << 27;
+    pub const PLL_N_SHIFT: u32 = 8;
+    pub const PLL_INPUT_DIV2: u32 = 1 << 1;
+
+    // Module clock register bits.
+    pub const CLK_GATING: u32 = 1 << 31;
+    pub const CLK_SRC_SHIFT: u32 = 24;
+    /// `DE_CLK` source: `PLL_PERI(2X)`, 1.2 GHz.
+    pub const DE_CLK_SRC_PLL_PERI_2X: u32 = 0b000;
+    /// `TCON_*_CLK` source: `PLL_VIDEO0(1X)`.
+    pub const TCON_CLK_SRC_PLL_VIDEO0_1X: u32 = 0b000;
+
+    // Bus gating/reset register bits.
+    pub const BGR_GATING: u32 = 1 << 0;
+    pub const BGR_RST: u32 = 1 << 16;
+    /// `HDMI_BGR` has a second reset, for the HDMI sub-module.
+    pub const HDMI_SUB_RST: u32 = 1 << 17;
+}
+
+/// DE2 register offsets and bits.
+mod de {
+    // DE top-level clock registers, relative to `DE_BASE`. Bit 0 of each is
+    // for mixer 0.
+    pub const SCLK_GATE: usize = 0x00;
+    pub const HCLK_GATE: usize = 0x04;
+    pub const AHB_RESET: usize = 0x08;
+    pub const MIXER0: u32 = 1 << 0;
+
+    // Global control registers, relative to the mixer.
+    pub const GLB_CTL: usize = 0x0000;
+    pub const GLB_DBUFFER: usize = 0x0008;
+    pub const GLB_SIZE: usize = 0x000C;
+    pub const GLB_CTL_RT_EN: u32 = 1 << 0;
+    pub const GLB_DBUFFER_AUTO_LOAD: u32 = 1 << 0;
+
+    // Blender registers, relative to the mixer.
+    pub const BLD_BASE: usize = 0x1000;
+    pub const BLD_PIPE_CTL: usize = BLD_BASE;
+    pub const fn bld_insize(pipe: usize) -> usize {
+        BLD_BASE + 0x08 + pipe * 0x10
+    }
+    pub const fn bld_coord(pipe: usize) -> usize {
+        BLD_BASE + 0x0C + pipe * 0x10
+    }
+    pub const BLD_ROUTE: usize = BLD_BASE + 0x80;
+    pub const BLD_PREMULTIPLY: usize = BLD_BASE + 0x84;
+    pub const BLD_BKCOLOR: usize = BLD_BASE + 0x88;
+    pub const BLD_OUTSIZE: usize = BLD_BASE + 0x8C;
+    pub const fn bld_mode(pipe: usize) -> usize {
+        BLD_BASE + 0x90 + pipe * 4
+    }
+    pub const BLD_CK_CTL: usize = BLD_BASE + 0xB0;
+    pub const BLD_OUTCTL: usize = BLD_BASE + 0xFC;
+    pub const fn bld_pipe_en(pipe: usize) -> u32 {
+        1 << (8 + pipe)
+    }
+    /// Source-over blending.
+    pub const BLD_MODE_DEFAULT: u32 = 0x0301_0301;
+
+    /// The UI channel. Mixer 0 has one VI channel (channel 0) and one UI
+    /// channel (channel 1).
+    pub const UI_CHANNEL: usize = 1;
+    pub const UI_BASE: usize = 0x2000 + UI_CHANNEL * 0x1000;
+    pub const UI_ATTR: usize = UI_BASE;
+    pub const UI_SIZE: usize = UI_BASE + 0x04;
+    pub const UI_COORD: usize = UI_BASE + 0x08;
+    pub const UI_PITCH: usize = UI_BASE + 0x0C;
+    pub const UI_TOP_LADDR: usize = UI_BASE + 0x10;
+    pub const UI_TOP_HADDR: usize = UI_BASE + 0x80;
+    pub const UI_OVL_SIZE: usize = UI_BASE + 0x88;
+    pub const UI_ATTR_EN: u32 = 1 << 0;
+    /// Use the layer's global alpha, rather than per-pixel alpha.
+    pub const UI_ATTR_ALPHA_MODE_GLOBAL: u32 = 1 << 1;
+    pub const UI_ATTR_FBFMT_SHIFT: u32 = 8;
+    pub const UI_ATTR_ALPHA_SHIFT: u32 = 24;
+    pub const FORMAT_XRGB_8888: u32 = 0x04;
+
+    /// Post-processing blocks, relative to the mixer, which must be disabled
+    /// when they aren't used.
+    pub const UNUSED_BLOCKS: [usize; 7] = [
+        0xA_0000, // FCE
+        0xA_2000, // BWS
+        0xA_4000, // LTI
+        0xA_6000, // PEAKING
+        0xA_8000, // ASE
+        0xA_A000, // FCC
+        0xB_0000, // DCSC
+    ];
+}
+
+/// `DISP_IF_TOP` register offsets and bits.
+mod disp_if_top {
+    pub const PORT_SEL: usize = 0x1C;
+    pub const GATE_SRC: usize = 0x20;
+    pub const PORT_DE0_MASK: u32 = 0b11;
+    pub const PORT_DE0_TCON_LCD0: u32 = 0;
+    pub const PORT_DE0_TCON_TV0: u32 = 2;
+    pub const GATE_TCON_TV0: u32 = 1 << 20;
+    pub const HDMI_SRC_SHIFT: u32 = 28;
+    pub const HDMI_SRC_MASK: u32 = 0b11 << HDMI_SRC_SHIFT;
+    pub const HDMI_SRC_TCON_TV0: u32 = 1 << HDMI_SRC_SHIFT;
+}
+
+/// TCON register offsets and bits. `TCON_LCD0` uses the `TCON0_*` registers,
+/// and `TCON_TV0` uses the `TCON1_*` registers.
+mod tcon {
+    pub const GCTL: usize = 0x000;
+    pub const GINT0: usize = 0x004;
+    pub const GINT1: usize = 0x008;
+    pub const GCTL_EN: u32 = 1 << 31;
+
+    pub const TCON0_CTL: usize = 0x040;
+    pub const TCON0_DCLK: usize = 0x044;
+    pub const TCON0_BASIC0: usize = 0x048;
+    pub const TCON0_BASIC1: usize = 0x04C;
+    pub const TCON0_BASIC2: usize = 0x050;
+    pub const TCON0_BASIC3: usize = 0x054;
+    pub const TCON0_HV_IF: usize = 0x058;
+    pub const TCON0_IO_POL: usize = 0x088;
+    pub const TCON0_IO_TRI: usize = 0x08C;
+    /// Enables all four of `TCON0_DCLK`'s clock outputs.
+    pub const TCON0_DCLK_EN: u32 = 0xF << 28;
+    pub const TCON0_DCLK_DIV_MAX: u32 = 0x7F;
+    /// `TCON0_HV_IF` value for 24-bit parallel RGB.
+    pub const TCON0_HV_IF_PARALLEL_RGB: u32 = 0;
+    pub const TCON0_IO_POL_VSYNC_POSITIVE: u32 = 1 << 24;
+    pub const TCON0_IO_POL_HSYNC_POSITIVE: u32 = 1 << 25;
+
+    pub const TCON1_CTL: usize = 0x090;
+    pub const TCON1_BASIC0: usize = 0x094;
+    pub const TCON1_BASIC1: usize = 0x098;
+    pub const TCON1_BASIC2: usize = 0x09C;
+    pub const TCON1_BASIC3: usize = 0x0A0;
+    pub const TCON1_BASIC4: usize = 0x0A4;
+    pub const TCON1_BASIC5: usize = 0x0A8;
+    pub const TCON1_IO_POL: usize = 0x0F0;
+    pub const TCON1_IO_TRI: usize = 0x0F4;
+
+    /// `TCON0_CTL` and `TCON1_CTL` enable bit.
+    pub const CTL_EN: u32 = 1 << 31;
+    pub const CTL_CLK_DELAY_SHIFT: u32 = 4;
+    pub const CTL_CLK_DELAY_MAX: u32 = 0x1F;
+}
+
+/// DesignWare HDMI controller register offsets and bits. These registers are
+/// 8 bits wide.
+mod hdmi {
+    pub const IH_I2CMPHY_STAT0: usize = 0x0108;
+    pub const IH_I2CMPHY_STAT0_DONE: u8 = 1 << 1;
+    pub const IH_I2CMPHY_STAT0_ERROR: u8 = 1 << 0;
+
+    pub const TX_INVID0: usize = 0x0200;
+    pub const TX_INSTUFFING: usize = 0x0201;
+    /// `TX_INVID0` video mapping for 8-bit RGB 4:4:4.
+    pub const TX_INVID0_RGB444_8B: u8 = 0x01;
+    pub const TX_INSTUFFING_ALL: u8 = 0x07;
+
+    pub const VP_PR_CD: usize = 0x0801;
+    pub const VP_STUFF: usize = 0x0802;
+    pub const VP_REMAP: usize = 0x0803;
+    pub const VP_CONF: usize = 0x0804;
+    /// `VP_PR_CD` color depth value for 24 bits per pixel.
+    pub const VP_PR_CD_COLOR_DEPTH_24: u8 = 4 << 4;
+    pub const VP_STUFF_ALL: u8 = 0x07;
+    pub const VP_CONF_BYPASS_EN: u8 = 1 << 6;
+    pub const VP_CONF_BYPASS_SELECT: u8 = 1 << 2;
+    pub const VP_CONF_OUTPUT_BYPASS: u8 = 0b10;
+
+    pub const FC_INVIDCONF: usize = 0x1000;
+    pub const FC_INHACTV0: usize = 0x1001;
+    pub const FC_INHBLANK0: usize = 0x1003;
+    pub const FC_INVACTV0: usize = 0x1005;
+    pub const FC_INVBLANK: usize = 0x1007;
+    pub const FC_HSYNCINDELAY0: usize = 0x1008;
+    pub const FC_HSYNCINWIDTH0: usize = 0x100A;
+    pub const FC_VSYNCINDELAY: usize = 0x100C;
+    pub const FC_VSYNCINWIDTH: usize = 0x100D;
+    pub const FC_CTRLDUR: usize = 0x1011;
+    pub const FC_EXCTRLDUR: usize = 0x1012;
+    pub const FC_EXCTRLSPAC: usize = 0x1013;
+    pub const FC_CH0PREAM: usize = 0x1014;
+    pub const FC_CH1PREAM: usize = 0x1015;
+    pub const FC_CH2PREAM: usize = 0x1016;
+    pub const FC_INVIDCONF_VSYNC_ACTIVE_HIGH: u8 = 1 << 6;
+    pub const FC_INVIDCONF_HSYNC_ACTIVE_HIGH: u8 = 1 << 5;
+    pub const FC_INVIDCONF_DE_ACTIVE_HIGH: u8 = 1 << 4;
+    /// DVI mode is selected by leaving `DVI_MODEZ` clear.
+    pub const FC_INVIDCONF_DVI_MODE: u8 = 0;
+
+    pub const PHY_CONF0: usize = 0x3000;
+    pub const PHY_STAT0: usize = 0x3004;
+    pub const PHY_CONF0_PDZ: u8 = 1 << 7;
+    pub const PHY_CONF0_ENTMDS: u8 = 1 << 6;
+    pub const PHY_CONF0_PDDQ: u8 = 1 << 4;
+    pub const PHY_CONF0_TXPWRON: u8 = 1 << 3;
+    pub const PHY_CONF0_SELDATAENPOL: u8 = 1 << 1;
+    pub const PHY_STAT0_TX_PHY_LOCK: u8 = 1 << 0;
+    pub const PHY_STAT0_HPD: u8 = 1 << 1;
+
+    pub const PHY_I2CM_SLAVE: usize = 0x3020;
+    pub const PHY_I2CM_ADDRESS: usize = 0x3021;
+    pub const PHY_I2CM_DATAO_1: usize = 0x3022;
+    pub const PHY_I2CM_DATAO_0: usize = 0x3023;
+    pub const PHY_I2CM_OPERATION: usize = 0x3026;
+    pub const PHY_I2CM_OPERATION_WRITE: u8 = 1 << 4;
+    /// The PHY's address on the controller's internal I2C bus.
+    pub const PHY_I2C_ADDR: u8 = 0x69;
+
+    pub const MC_CLKDIS: usize = 0x4001;
+    pub const MC_SWRSTZ: usize = 0x4002;
+    pub const MC_FLOWCTRL: usize = 0x4004;
+    pub const MC_PHYRSTZ: usize = 0x4005;
+    pub const MC_HEACPHY_RST: usize = 0x4007;
+    /// Disables every clock except the pixel and TMDS clocks.
+    pub const MC_CLKDIS_ALL_BUT_PIXEL_TMDS: u8 = 0x7C;
+    pub const MC_SWRSTZ_TMDS: u8 = 1 << 1;
+    pub const MC_FLOWCTRL_CSC_BYPASS: u8 = 0;
+    pub const MC_PHYRSTZ_ASSERT: u8 = 1 << 0;
+    pub const MC_HEACPHY_RST_ASSERT: u8 = 1 << 0;
+
+    /// Allwinner's PHY glue registers, relative to `HDMI_PHY_BASE`.
+    pub const GLUE_REXT_CTRL: usize = 0x0004;
+    pub const GLUE_READ_EN: usize = 0x0010;
+    pub const GLUE_UNSCRAMBLE: usize = 0x0014;
+    pub const GLUE_REXT_CTRL_EN: u32 = 1 << 31;
+    pub const GLUE_REXT_CTRL_MASK: u32 = 0xFFFF_0000;
+    pub const GLUE_REXT_CTRL_VALUE: u32 = 0x80C0_0000;
+    /// Enables reading the controller's registers.
+    pub const GLUE_READ_EN_MAGIC: u32 = 0x5452_4545;
+    /// Unscrambles the controller's register offsets.
+    pub const GLUE_UNSCRAMBLE_MAGIC: u32 = 0x4249_4E47;
+
+    // PHY registers, written over the controller's I2C master.
+    pub const PHY_OPMODE_PLLCFG: u8 = 0x06;
+    pub const PHY_CKSYMTXCTRL: u8 = 0x09;
+    pub const PHY_VLEVCTRL: u8 = 0x0E;
+    pub const PHY_PLLCURRCTRL: u8 = 0x10;
+    pub const PHY_PLLPHBYCTRL: u8 = 0x13;
+    pub const PHY_PLLGMPCTRL: u8 = 0x15;
+    pub const PHY_MSM_CTRL: u8 = 0x17;
+    pub const PHY_TXTERM: u8 = 0x19;
+    /// `MSM_CTRL` value selecting the feedback clock for the clock output.
+    pub const PHY_MSM_CTRL_CKO_SEL_FB_CLK: u8 = 0x3 << 1;
+
+    /// PHY settings for a 74.25 MHz pixel clock at 8 bits per component,
+    /// as `(register, value)` pairs.
+    pub const PHY_74_25_MHZ: [(u8, u16); 7] = [
+        (PHY_OPMODE_PLLCFG, 0x0013),
+        (PHY_PLLGMPCTRL, 0x001A),
+        (PHY_PLLCURRCTRL, 0x0013),
+        (PHY_PLLPHBYCTRL, 0x0000),
+        (PHY_CKSYMTXCTRL, 0x8009),
+        (PHY_TXTERM, 0x0006),
+        (PHY_VLEVCTRL, 0x022D),
+    ];
+}
+
+// === impl DisplayEngine ===
+
+impl DisplayEngine {
+    /// The highest resolution supported by the display engine's mixer.
+    pub const MAX_WIDTH: u32 = 2048;
+    /// The highest resolution supported by the display engine's mixer.
+    pub const MAX_HEIGHT: u32 = 2048;
+
+    const CAPACITY: usize = 2;
+
+    /// Initializes the display engine, `TCON_TV0`, and the HDMI transmitter,
+    /// to output 1280x720 at 60 Hz over HDMI.
+    ///
+    /// # Safety
+    ///
+    /// - The display engine, TCON, `DISP_IF_TOP`, and HDMI register blocks
+    ///   must not be concurrently written to.
+    /// - This function should be called only while running on an Allwinner D1.
+    pub unsafe fn hdmi(ccu: &mut Ccu) -> Self {
+        let timing = Timing::HDMI_720P60;
+        init_clocks(ccu, Output::Hdmi, &timing);
+        init_mixer(&timing);
+
+        // Route mixer 0 to TCON_TV0, and TCON_TV0 to the HDMI transmitter.
+        modify(DISP_IF_TOP_BASE, disp_if_top::PORT_SEL, |r| {
+            (r & !disp_if_top::PORT_DE0_MASK) | disp_if_top::PORT_DE0_TCON_TV0
+        });
+        modify(DISP_IF_TOP_BASE, disp_if_top::GATE_SRC, |r| {
+            (r & !disp_if_top::HDMI_SRC_MASK)
+                | disp_if_top::HDMI_SRC_TCON_TV0
+                | disp_if_top::GATE_TCON_TV0
+        });
+
+        init_tcon_tv(&timing);
+        init_hdmi(&timing);
+
+        Self {
+            timing,
+            output: Output::Hdmi,
+        }
+    }
+
+    /// Initializes the display engine and `TCON_LCD0`, to drive a parallel
+    /// RGB666 panel with the given `timing` on pins `PD0`-`PD21`.
+    ///
+    /// The pixel clock is divided down from 297 MHz, so the actual pixel clock
+    /// may be slightly faster than `timing.pixel_clock_hz`.
+    ///
+    /// # Safety
+    ///
+    /// - The display engine, TCON, and `DISP_IF_TOP` register blocks must not
+    ///   be concurrently written to.
+    /// - Pins `PD0`-`PD21` must not be in use by any other driver.
+    /// - This function should be called only while running on an Allwinner D1.
+    ///
+    /// # Panics
+    ///
+    /// If `timing` is larger than [`Self::MAX_WIDTH`] by [`Self::MAX_HEIGHT`].
+    pub unsafe fn rgb(ccu: &mut Ccu, gpio: &mut GPIO, timing: Timing) -> Self {
+        init_clocks(ccu, Output::Rgb, &timing);
+        init_mixer(&timing);
+
+        // Map the pins. Each pin's function is selected by a 4-bit field.
+        let all_lcd0 = (0..8).fold(0, |cfg, pin| cfg | (FUNCTION_LCD0 << (pin * 4)));
+        gpio.pd_cfg0.write(|w| w.bits(all_lcd0));
+        gpio.pd_cfg1.write(|w| w.bits(all_lcd0));
+        // Only PD16-PD21 are in PD_CFG2.
+        gpio.pd_cfg2.modify(|r, w| {
+            let mask = 0x00FF_FFFF;
+            w.bits((r.bits() & !mask) | (all_lcd0 & mask))
+        });
+
+        // Route mixer 0 to TCON_LCD0.
+        modify(DISP_IF_TOP_BASE, disp_if_top::PORT_SEL, |r| {
+            (r & !disp_if_top::PORT_DE0_MASK) | disp_if_top::PORT_DE0_TCON_LCD0
+        });
+
+        init_tcon_lcd(&timing);
+
+        Self {
+            timing,
+            output: Output::Rgb,
+        }
+    }
+
+    /// Returns the timings of the display.
+    #[must_use]
+    pub fn timing(&self) -> &Timing {
+        &self.timing
+    }
+
+    /// Returns which output the display engine drives.
+    #[must_use]
+    pub fn output(&self) -> Output {
+        self.output
+    }
+
+    /// Registers an [`EmbDisplayService`] server for the display.
+    ///
+    /// This allocates a framebuffer for the entire display, and starts
+    /// scanning it out.
+    ///
+    /// `transform` is reported to clients, which rotate and scale their frames
+    /// before sending them to the driver.
+    #[tracing::instrument(
+        name = "DisplayEngine::register",
+        level = tracing::Level::INFO,
+        skip(self, kernel),
+        err(Debug),
+    )]
+    pub async fn register(
+        self,
+        kernel: &'static Kernel,
+        transform: DisplayTransform,
+    ) -> Result<(), RegistrationError> {
+        let Timing { width, height, .. } = self.timing;
+        tracing::info!(
+            width,
+            height,
+            output = ?self.output,
+            ?transform,
+            queue_capacity = Self::CAPACITY,
+            "Starting DisplayEngine driver",
+        );
+
+        let cmd = kernel
+            .registry()
+            .bind_konly(Self::CAPACITY)
+            .await
+            .map_err(RegistrationError::Registration)?
+            .into_request_stream(Self::CAPACITY)
+            .await
+            .with_capabilities(capabilities::TRANSFORM);
+
+        let frame = HeapArray::new(width as usize * height as usize, BACKGROUND).await;
+
+        // Point the UI layer at the framebuffer, and commit the mixer's
+        // configuration. The mixer latches its registers at the next vblank.
+        unsafe {
+            let addr = frame.as_ptr() as usize;
+            write(MIXER0_BASE, de::UI_TOP_LADDR, addr as u32);
+            write(MIXER0_BASE, de::UI_TOP_HADDR, ((addr as u64) >> 32) as u32);
+            write(
+                MIXER0_BASE,
+                de::UI_ATTR,
+                de::UI_ATTR_EN
+                    | de::UI_ATTR_ALPHA_MODE_GLOBAL
+                    | (de::FORMAT_XRGB_8888 << de::UI_ATTR_FBFMT_SHIFT)
+                    | (0xFF << de::UI_ATTR_ALPHA_SHIFT),
+            );
+            write(MIXER0_BASE, de::GLB_DBUFFER, de::GLB_DBUFFER_AUTO_LOAD);
+        }
+
+        let commander = CommanderTask {
+            cmd,
+            frame,
+            width,
+            height,
+            transform,
+        };
+        kernel.spawn(commander.cmd_run()).await;
+
+        Ok(())
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////
+// Driver task
+//////////////////////////////////////////////////////////////////////////////
+
+/// This task is spawned by the call to [`DisplayEngine::register`]. It draws
+/// each chunk it receives straight into the framebuffer.
+struct CommanderTask {
+    cmd: listener::RequestStream<EmbDisplayService>,
+    frame: HeapArray<u32>,
+    width: u32,
+    height: u32,
+    transform: DisplayTransform,
+}
+
+impl CommanderTask {
+    #[tracing::instrument(skip(self))]
+    async fn cmd_run(mut self) {
+        // Generally, don't handle errors when replying to clients, this
+        // indicates that they sent us a message and "hung up" without waiting
+        // for a response.
+        loop {
+            let (req, env, reply_tx) = self.cmd.next_request().await.split();
+            let response = match req {
+                Request::Draw(FrameChunk::Mono(fc)) => {
+                    tracing::trace!("Processing Draw Mono command");
+                    draw_to(&mut self.frame, &fc, self.width, self.height);
+                    Ok(Response::DrawComplete(fc.into()))
+                }
+                Request::GetMeta => Ok(Response::FrameMeta(DisplayMetadata {
+                    kind: FrameKind::Mono,
+                    width: self.width,
+                    height: self.height,
+                    transform: self.transform,
+                })),
+                _ => Err(FrameError::InternalError),
+            };
+            let _ = reply_tx.reply_konly(env.fill(response)).await;
+        }
+    }
+}
+
+fn draw_to(dest: &mut [u32], src: &MonoChunk, width: u32, height: u32) {
+    let meta = src.meta();
+    let (start_x, start_y) = (meta.start_x(), meta.start_y());
+    let src_width = meta.width() as usize;
+    if start_x >= width || start_y >= height || src_width == 0 {
+        return;
+    }
+
+    // Clip the chunk to the display.
+    let visible_width = src_width.min((width - start_x) as usize);
+    let rows = src
+        .data()
+        .chunks(src_width)
+        .zip(src.mask().chunks(src_width))
+        .take((height - start_y) as usize);
+
+    for (src_y, (data, mask)) in rows.enumerate() {
+        let line = (start_y as usize + src_y) * width as usize + start_x as usize;
+        let dest = &mut dest[line..line + visible_width];
+        for ((px, &data), &mask) in dest.iter_mut().zip(data).zip(mask) {
+            if mask != 0 {
+                *px = if data < 128 { BACKGROUND } else { FOREGROUND };
+            }
+        }
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////
+// Hardware initialization
+//////////////////////////////////////////////////////////////////////////////
+
+/// Configures the display clocks. The `Ccu` isn't otherwise used, but is
+/// borrowed to ensure that nothing else is writing to the CCU.
+unsafe fn init_clocks(_ccu: &mut Ccu, output: Output, timing: &Timing) {
+    use ccu_regs::*;
+
+    let ccu_base = CCU::PTR as usize;
+
+    // PLL_VIDEO0(4X) = 24 MHz * N / M = 24 MHz * 99 / 2 = 1188 MHz, so
+    // PLL_VIDEO0(1X) = 297 MHz.
+    write(
+        ccu_base,
+        PLL_VIDEO0_CTRL,
+        PLL_LDO_EN | PLL_INPUT_DIV2 | (98 << PLL_N_SHIFT),
+    );
+    modify(ccu_base, PLL_VIDEO0_CTRL, |r| r | PLL_EN | PLL_LOCK_EN);
+    while read(ccu_base, PLL_VIDEO0_CTRL) & PLL_LOCK == 0 {}
+    Clint::spin_delay_us(20);
+    modify(ccu_base, PLL_VIDEO0_CTRL, |r| r | PLL_OUTPUT_EN);
+
+    // DE clock: PLL_PERI(2X) / 4 = 300 MHz.
+    write(
+        ccu_base,
+        DE_CLK,
+        CLK_GATING | (DE_CLK_SRC_PLL_PERI_2X << CLK_SRC_SHIFT) | 3,
+    );
+    enable_bus(ccu_base, DE_BGR, BGR_GATING | BGR_RST);
+    enable_bus(ccu_base, DPSS_TOP_BGR, BGR_GATING | BGR_RST);
+
+    match output {
+        Output::Hdmi => {
+            // TCON_TV0 generates the pixel clock itself, so divide
+            // PLL_VIDEO0(1X) down to the pixel clock here.
+            let m = PLL_VIDEO0_1X_HZ
+                .div_ceil(timing.pixel_clock_hz)
+                .clamp(1, 16);
+            write(
+                ccu_base,
+                TCON_TV_CLK,
+                CLK_GATING | (TCON_CLK_SRC_PLL_VIDEO0_1X << CLK_SRC_SHIFT) | (m - 1),
+            );
+            enable_bus(ccu_base, TCON_TV_BGR, BGR_GATING | BGR_RST);
+
+            modify(ccu_base, HDMI_24M_CLK, |r| r | CLK_GATING);
+            enable_bus(ccu_base, HDMI_BGR, BGR_GATING | BGR_RST | HDMI_SUB_RST);
+        }
+        Output::Rgb => {
+            // TCON_LCD0 divides this down to the pixel clock using its DCLK
+            // divider.
+            write(
+                ccu_base,
+                TCON_LCD0_CLK,
+                CLK_GATING | (TCON_CLK_SRC_PLL_VIDEO0_1X << CLK_SRC_SHIFT),
+            );
+            enable_bus(ccu_base, TCON_LCD_BGR, BGR_GATING | BGR_RST);
+        }
+    }
+
+    // Enable mixer 0's clocks in the display engine itself.
+    modify(DE_BASE, de::AHB_RESET, |r| r | de::MIXER0);
+    modify(DE_BASE, de::SCLK_GATE, |r| r | de::MIXER0);
+    modify(DE_BASE, de::HCLK_GATE, |r| r | de::MIXER0);
+}
+
+/// Deasserts a module's reset and then enables its bus clock, in the same
+/// manner as [`Ccu::enable_module`].
+unsafe fn enable_bus(ccu_base: usize, bgr: usize, bits: u32) {
+    let reset = bits & !ccu_regs::BGR_GATING;
+    modify(ccu_base, bgr, |r| r | reset);
+    Clint::spin_delay_us(20);
+    modify(ccu_base, bgr, |r| r | ccu_regs::BGR_GATING);
+}
+
+/// Configures mixer 0 to display one full-screen layer on the UI channel.
+/// The layer isn't enabled until a framebuffer is allocated for it by
+/// [`DisplayEngine::register`].
+unsafe fn init_mixer(timing: &Timing) {
+    assert!(
+        timing.width <= DisplayEngine::MAX_WIDTH && timing.height <= DisplayEngine::MAX_HEIGHT,
+        "the display engine supports resolutions up to {}x{}",
+        DisplayEngine::MAX_WIDTH,
+        DisplayEngine::MAX_HEIGHT,
+    );
+    let size = ((timing.height - 1) << 16) | (timing.width - 1);
+
+    // Clear all of the mixer's registers, so that nothing left over from a
+    // bootloader is displayed.
+    for offset in (0..0x6000).step_by(4) {
+        write(MIXER0_BASE, offset, 0);
+    }
+    for block in de::UNUSED_BLOCKS {
+        write(MIXER0_BASE, block, 0);
+    }
+
+    write(MIXER0_BASE, de::GLB_CTL, de::GLB_CTL_RT_EN);
+    write(MIXER0_BASE, de::GLB_SIZE, size);
+
+    // Blend the UI channel, on pipe 0, over an opaque black background.
+    write(MIXER0_BASE, de::BLD_PIPE_CTL, de::bld_pipe_en(0));
+    write(MIXER0_BASE, de::BLD_ROUTE, de::UI_CHANNEL as u32);
+    write(MIXER0_BASE, de::bld_insize(0), size);
+    write(MIXER0_BASE, de::bld_coord(0), 0);
+    write(MIXER0_BASE, de::bld_mode(0), de::BLD_MODE_DEFAULT);
+    write(MIXER0_BASE, de::BLD_PREMULTIPLY, 0);
+    write(MIXER0_BASE, de::BLD_BKCOLOR, 0xFF00_0000);
+    write(MIXER0_BASE, de::BLD_OUTSIZE, size);
+    write(MIXER0_BASE, de::BLD_CK_CTL, 0);
+    write(MIXER0_BASE, de::BLD_OUTCTL, 0);
+
+    write(MIXER0_BASE, de::UI_SIZE, size);
+    write(MIXER0_BASE, de::UI_COORD, 0);
+    write(MIXER0_BASE, de::UI_PITCH, timing.width * 4);
+    write(MIXER0_BASE, de::UI_OVL_SIZE, size);
+}
+
+unsafe fn init_tcon_tv(timing: &Timing) {
+    use tcon::*;
+
+    let size = ((timing.width - 1) << 16) | (timing.height - 1);
+    write(TCON_TV0_BASE, GCTL, 0);
+    write(TCON_TV0_BASE, GINT0, 0);
+    write(TCON_TV0_BASE, GINT1, 0);
+
+    // No scaling: the input, upscaled, and output sizes are all the same.
+    write(TCON_TV0_BASE, TCON1_BASIC0, size);
+    write(TCON_TV0_BASE, TCON1_BASIC1, size);
+    write(TCON_TV0_BASE, TCON1_BASIC2, size);
+    write(
+        TCON_TV0_BASE,
+        TCON1_BASIC3,
+        ((timing.htotal() - 1) << 16) | (timing.hsync_to_active() - 1),
+    );
+    write(
+        TCON_TV0_BASE,
+        TCON1_BASIC4,
+        ((timing.vtotal() * 2) << 16) | (timing.vsync_to_active() - 1),
+    );
+    write(
+        TCON_TV0_BASE,
+        TCON1_BASIC5,
+        ((timing.hsync_len - 1) << 16) | (timing.vsync_len - 1),
+    );
+    // The HDMI transmitter handles sync polarity itself.
+    write(TCON_TV0_BASE, TCON1_IO_POL, 0);
+    write(TCON_TV0_BASE, TCON1_IO_TRI, 0xFFFF_FFFF);
+
+    write(
+        TCON_TV0_BASE,
+        TCON1_CTL,
+        CTL_EN | (timing.clk_delay() << CTL_CLK_DELAY_SHIFT),
+    );
+    write(TCON_TV0_BASE, GCTL, GCTL_EN);
+}
+
+unsafe fn init_tcon_lcd(timing: &Timing) {
+    use tcon::*;
+
+    write(TCON_LCD0_BASE, GCTL, 0);
+    write(TCON_LCD0_BASE, GINT0, 0);
+    write(TCON_LCD0_BASE, GINT1, 0);
+
+    let div = PLL_VIDEO0_1X_HZ
+        .div_ceil(timing.pixel_clock_hz)
+        .clamp(1, TCON0_DCLK_DIV_MAX);
+    tracing::debug!(
+        requested_hz = timing.pixel_clock_hz,
+        actual_hz = PLL_VIDEO0_1X_HZ / div,
+        "TCON_LCD0 pixel clock"
+    );
+    write(TCON_LCD0_BASE, TCON0_DCLK, TCON0_DCLK_EN | div);
+
+    write(
+        TCON_LCD0_BASE,
+        TCON0_BASIC0,
+        ((timing.width - 1) << 16) | (timing.height - 1),
+    );
+    write(
+        TCON_LCD0_BASE,
+        TCON0_BASIC1,
+        ((timing.htotal() - 1) << 16) | (timing.hsync_to_active() - 1),
+    );
+    write(
+        TCON_LCD0_BASE,
+        TCON0_BASIC2,
+        ((timing.vtotal() * 2) << 16) | (timing.vsync_to_active() - 1),
+    );
+    write(
+        TCON_LCD0_BASE,
+        TCON0_BASIC3,
+        ((timing.hsync_len - 1) << 16) | (timing.vsync_len - 1),
+    );
+    write(TCON_LCD0_BASE, TCON0_HV_IF, TCON0_HV_IF_PARALLEL_RGB);
+
+    let mut pol = 0;
+    if timing.hsync_active_high {
+        pol |= TCON0_IO_POL_HSYNC_POSITIVE;
+    }
+    if timing.vsync_active_high {
+        pol |= TCON0_IO_POL_VSYNC_POSITIVE;
+    }
+    write(TCON_LCD0_BASE, TCON0_IO_POL, pol);
+    // Enable all of the outputs.
+    write(TCON_LCD0_BASE, TCON0_IO_TRI, 0);
+
+    write(
+        TCON_LCD0_BASE,
+        TCON0_CTL,
+        CTL_EN | (timing.clk_delay() << CTL_CLK_DELAY_SHIFT),
+    );
+    write(TCON_LCD0_BASE, GCTL, GCTL_EN);
+}
+
+unsafe fn init_hdmi(timing: &Timing) {
+    use hdmi::*;
+
+    // Unlock the controller's registers, and enable the external reference
+    // resistor.
+    write(HDMI_PHY_BASE, GLUE_READ_EN, GLUE_READ_EN_MAGIC);
+    write(HDMI_PHY_BASE, GLUE_UNSCRAMBLE, GLUE_UNSCRAMBLE_MAGIC);
+    modify(HDMI_PHY_BASE, GLUE_REXT_CTRL, |r| {
+        (r & !GLUE_REXT_CTRL_MASK) | GLUE_REXT_CTRL_EN | GLUE_REXT_CTRL_VALUE
+    });
+
+    // Frame composer: describe the incoming video.
+    let hblank = timing.htotal() - timing.width;
+    let vblank = timing.vtotal() - timing.height;
+    let mut invidconf = FC_INVIDCONF_DE_ACTIVE_HIGH | FC_INVIDCONF_DVI_MODE;
+    if timing.hsync_active_high {
+        invidconf |= FC_INVIDCONF_HSYNC_ACTIVE_HIGH;
+    }
+    if timing.vsync_active_high {
+        invidconf |= FC_INVIDCONF_VSYNC_ACTIVE_HIGH;
+    }
+    write8(FC_INVIDCONF, invidconf);
+    write16(FC_INHACTV0, timing.width as u16);
+    write16(FC_INHBLANK0, hblank as u16);
+    write16(FC_INVACTV0, timing.height as u16);
+    write8(FC_INVBLANK, vblank as u8);
+    write16(FC_HSYNCINDELAY0, timing.hfront_porch as u16);
+    write16(FC_HSYNCINWIDTH0, timing.hsync_len as u16);
+    write8(FC_VSYNCINDELAY, timing.vfront_porch as u8);
+    write8(FC_VSYNCINWIDTH, timing.vsync_len as u8);
+    write8(FC_CTRLDUR, 12);
+    write8(FC_EXCTRLDUR, 32);
+    write8(FC_EXCTRLSPAC, 1);
+    write8(FC_CH0PREAM, 0x0B);
+    write8(FC_CH1PREAM, 0x16);
+    write8(FC_CH2PREAM, 0x21);
+
+    // Video packetizer and sampler: 8-bit RGB, passed through unchanged.
+    write8(VP_PR_CD, VP_PR_CD_COLOR_DEPTH_24);
+    write8(VP_STUFF, VP_STUFF_ALL);
+    write8(VP_REMAP, 0);
+    write8(
+        VP_CONF,
+        VP_CONF_BYPASS_EN | VP_CONF_BYPASS_SELECT | VP_CONF_OUTPUT_BYPASS,
+    );
+    write8(TX_INVID0, TX_INVID0_RGB444_8B);
+    write8(TX_INSTUFFING, TX_INSTUFFING_ALL);
+
+    init_hdmi_phy();
+
+    // Start the TMDS clock, and reset the TMDS encoder.
+    write8(MC_CLKDIS, MC_CLKDIS_ALL_BUT_PIXEL_TMDS);
+    write8(MC_FLOWCTRL, MC_FLOWCTRL_CSC_BYPASS);
+    write8(MC_SWRSTZ, !MC_SWRSTZ_TMDS);
+
+    if read8(PHY_STAT0) & PHY_STAT0_HPD == 0 {
+        tracing::warn!("no HDMI monitor detected; is one connected?");
+    }
+}
+
+unsafe fn init_hdmi_phy() {
+    use hdmi::*;
+
+    // Power down and reset the PHY.
+    write8(PHY_CONF0, PHY_CONF0_SELDATAENPOL | PHY_CONF0_PDDQ);
+    write8(MC_PHYRSTZ, MC_PHYRSTZ_ASSERT);
+    write8(MC_PHYRSTZ, 0);
+    write8(MC_HEACPHY_RST, MC_HEACPHY_RST_ASSERT);
+
+    write8(PHY_I2CM_SLAVE, PHY_I2C_ADDR);
+    for (reg, value) in PHY_74_25_MHZ {
+        phy_i2c_write(reg, value);
+    }
+    phy_i2c_write(PHY_MSM_CTRL, u16::from(PHY_MSM_CTRL_CKO_SEL_FB_CLK));
+
+    // Power the PHY back up, and wait for its PLL to lock.
+    write8(
+        PHY_CONF0,
+        PHY_CONF0_PDZ | PHY_CONF0_ENTMDS | PHY_CONF0_SELDATAENPOL | PHY_CONF0_TXPWRON,
+    );
+    for _ in 0..5 {
+        if read8(PHY_STAT0) & PHY_STAT0_TX_PHY_LOCK != 0 {
+            return;
+        }
+        Clint::spin_delay_us(1000);
+    }
+    tracing::error!("HDMI PHY PLL did not lock!");
+}
+
+unsafe fn phy_i2c_write(reg: u8, value: u16) {
+    use hdmi::*;
+
+    // Clear any stale status before starting the write.
+    write8(
+        IH_I2CMPHY_STAT0,
+        IH_I2CMPHY_STAT0_DONE | IH_I2CMPHY_STAT0_ERROR,
+    );
+    write8(PHY_I2CM_ADDRESS, reg);
+    write8(PHY_I2CM_DATAO_1, (value >> 8) as u8);
+    write8(PHY_I2CM_DATAO_0, value as u8);
+    write8(PHY_I2CM_OPERATION, PHY_I2CM_OPERATION_WRITE);
+
+    for _ in 0..1000 {
+        let stat = read8(IH_I2CMPHY_STAT0);
+        if stat & IH_I2CMPHY_STAT0_ERROR != 0 {
+            break;
+        }
+        if stat & IH_I2CMPHY_STAT0_DONE != 0 {
+            return;
+        }
+        Clint::spin_delay_us(1);
+    }
+    tracing::error!(reg, value, "HDMI PHY register write failed!");
+}
+
+// === impl Timing ===
+
+impl Timing {
+    /// CEA-861 mode 4: 1280x720 at 60 Hz.
+    pub const HDMI_720P60: Self = Self {
+        pixel_clock_hz: 74_250_000,
+        width: 1280,
+        height: 720,
+        hfront_porch: 110,
+        hsync_len: 40,
+        hback_porch: 220,
+        vfront_porch: 5,
+        vsync_len: 5,
+        vback_porch: 20,
+        hsync_active_high: true,
+        vsync_active_high: true,
+    };
+
+    /// Returns the total width of a line, including blanking.
+    #[must_use]
+    pub fn htotal(&self) -> u32 {
+        self.width + self.hfront_porch + self.hsync_len + self.hback_porch
+    }
+
+    /// Returns the total height of a frame, including blanking.
+    #[must_use]
+    pub fn vtotal(&self) -> u32 {
+        self.height + self.vfront_porch + self.vsync_len + self.vback_porch
+    }
+
+    /// Returns the time taken to scan out one frame.
+    #[must_use]
+    pub fn frame_period(&self) -> Duration {
+        let frame_ns = u64::from(self.htotal()) * u64::from(self.vtotal()) * 1_000_000_000
+            / u64::from(self.pixel_clock_hz.max(1));
+        Duration::from_nanos(frame_ns)
+    }
+
+    /// Pixels from the start of the horizontal sync pulse to the start of the
+    /// visible area.
+    fn hsync_to_active(&self) -> u32 {
+        self.hsync_len + self.hback_porch
+    }
+
+    /// Lines from the start of the vertical sync pulse to the start of the
+    /// visible area.
+    fn vsync_to_active(&self) -> u32 {
+        self.vsync_len + self.vback_porch
+    }
+
+    /// The delay between the start of the vertical blanking interval and the
+    /// TCON starting to fetch pixels from the mixer.
+    fn clk_delay(&self) -> u32 {
+        (self.vtotal() - self.height).clamp(1, tcon::CTL_CLK_DELAY_MAX + 1) - 1
+    }
+}
+
+fn reg(base: usize, offset: usize) -> *mut u32 {
+    (base + offset) as *mut u32
+}
+
+#[inline(always)]
+unsafe fn read(base: usize, offset: usize) -> u32 {
+    mmio::read(reg(base, offset))
+}
+
+#[inline(always)]
+unsafe fn write(base: usize, offset: usize, val: u32) {
+    mmio::write(reg(base, offset), val)
+}
+
+#[inline(always)]
+unsafe fn modify(base: usize, offset: usize, f: impl FnOnce(u32) -> u32) {
+    mmio::modify(reg(base, offset), f)
+}
+
+#[inline(always)]
+unsafe fn read8(offset: usize) -> u8 {
+    mmio::read_u8((HDMI_BASE + offset) as *const u8)
+}
+
+#[inline(always)]
+unsafe fn write8(offset: usize, val: u8) {
+    mmio::write_u8((HDMI_BASE + offset) as *mut u8, val)
+}
+
+/// Writes a 16-bit value to a pair of HDMI controller registers, low byte
+/// first.
+#[inline(always)]
+unsafe fn write16(offset: usize, val: u16) {
+    let [lo, hi] = val.to_le_bytes();
+    write8(offset, lo);
+    write8(offset + 1, hi);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hdmi_720p60_timing() {
+        let timing = Timing::HDMI_720P60;
+        assert_eq!(timing.htotal(), 1650);
+        assert_eq!(timing.vtotal(), 750);
+        // 74.25 MHz / (1650 * 750) = 60 Hz
+        assert_eq!(timing.frame_period(), Duration::from_nanos(16_666_666));
+    }
+}
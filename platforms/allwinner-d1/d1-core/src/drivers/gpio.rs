@@ -0,0 +1,473 @@
+//! GPIO external interrupt (EINT) support for the Allwinner D1.
+//!
+//! Every pin on GPIO ports B through F can be configured as an external
+//! interrupt source. Each port has a single interrupt line into the PLIC, so
+//! the per-port interrupt handlers ([`Gpio::handle_pb_interrupt`] and
+//! friends) read the port's EINT status register and wake whichever tasks are
+//! waiting on the pins that fired.
+//!
+//! Tasks wait for an interrupt on a pin using [`Gpio::wait_for_edge`]. The
+//! pin's interrupt is only enabled while a task is waiting on it: the
+//! interrupt handler disables each pin's interrupt when it fires, so that
+//! level-triggered pins don't continuously re-trigger the interrupt before the
+//! waiting task has had a chance to run.
+//!
+//! Alternatively, [`Gpio::on_edge`] schedules [deferred work] each time a
+//! pin's interrupt fires, without needing a task to wait on the pin.
+//!
+//! Pins may also be used as simple outputs, using [`Gpio::configure_output`]
+//! and [`Gpio::set_output`].
+//!
+//! [deferred work]: kernel::deferred
+#![warn(missing_docs)]
+
+use core::{
+    ptr::null_mut,
+    sync::atomic::{AtomicPtr, Ordering},
+};
+
+use d1_pac::{Interrupt, GPIO};
+use kernel::{
+    deferred::{Deferred, DeferredWork},
+    isr::IsrConfig,
+    maitake::sync::WaitCell,
+    Kernel,
+};
+
+use crate::{mmio, plic::Plic};
+
+/// A handle to the GPIO external interrupt controller.
+///
+/// Like [`Dmac`](crate::dmac::Dmac), this is a token representing that the
+/// EINT state has been initialized, and may be freely copied into any driver
+/// that wishes to wait for pin interrupts.
+#[derive(Copy, Clone, Debug)]
+pub struct Gpio {
+    _p: (),
+}
+
+/// A GPIO port which supports external interrupts.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Port {
+    /// Port B (`PB0`-`PB12`)
+    B,
+    /// Port C (`PC0`-`PC7`)
+    C,
+    /// Port D (`PD0`-`PD22`)
+    D,
+    /// Port E (`PE0`-`PE17`)
+    E,
+    /// Port F (`PF0`-`PF6`)
+    F,
+}
+
+/// A GPIO pin which supports external interrupts.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Pin {
+    port: Port,
+    num: u8,
+}
+
+/// The condition on which a pin's external interrupt fires.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[repr(u32)]
+pub enum Trigger {
+    /// Fire on a low-to-high transition.
+    RisingEdge = 0x0,
+    /// Fire on a high-to-low transition.
+    FallingEdge = 0x1,
+    /// Fire while the pin is high.
+    HighLevel = 0x2,
+    /// Fire while the pin is low.
+    LowLevel = 0x3,
+    /// Fire on any transition.
+    BothEdges = 0x4,
+}
+
+/// Debounce configuration for a GPIO port's external interrupts.
+///
+/// The debounce setting applies to every pin on a port. Interrupt inputs are
+/// sampled at `clock / 2^prescale`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Debounce {
+    /// The clock used to sample interrupt inputs.
+    pub clock: DebounceClock,
+    /// The power-of-two prescaler applied to the sample clock. Values greater
+    /// than 7 are clamped to 7.
+    pub prescale: u8,
+}
+
+/// Sample clock source for [`Debounce`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DebounceClock {
+    /// The 32 KHz low-speed oscillator (LOSC). This is the reset default.
+    Losc32K,
+    /// The 24 MHz high-speed oscillator (HOSC).
+    Hosc24M,
+}
+
+/// WaitCells for EINT interrupts, one per pin, for each port.
+static PIN_WAIT: [[WaitCell; 32]; Port::COUNT] = {
+    // This `const` is used as a static initializer, so clippy is wrong here...
+    #[allow(clippy::declare_interior_mutable_const)]
+    const NEW_WAITCELL: WaitCell = WaitCell::new();
+    #[allow(clippy::declare_interior_mutable_const)]
+    const NEW_PORT: [WaitCell; 32] = [NEW_WAITCELL; 32];
+    [NEW_PORT; Port::COUNT]
+};
+
+/// Deferred work scheduled by EINT interrupts, one per pin, for each port.
+static PIN_WORK: [[AtomicPtr<Deferred>; 32]; Port::COUNT] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const NEW_PTR: AtomicPtr<Deferred> = AtomicPtr::new(null_mut());
+    #[allow(clippy::declare_interior_mutable_const)]
+    const NEW_PORT: [AtomicPtr<Deferred>; 32] = [NEW_PTR; 32];
+    [NEW_PORT; Port::COUNT]
+};
+
+/// The kernel's deferred work queue, set by [`Gpio::on_edge`].
+static DEFERRED: AtomicPtr<DeferredWork> = AtomicPtr::new(null_mut());
+
+/// Pin function select value which routes a pin to its EINT.
+const FUNCTION_EINT: u32 = 0xE;
+/// Pin function select value which makes a pin a general-purpose output.
+const FUNCTION_OUTPUT: u32 = 0x1;
+
+// === impl Gpio ===
+
+impl Gpio {
+    /// Initializes external interrupt support, disabling and clearing any
+    /// EINTs that were left enabled by the bootloader.
+    ///
+    /// Taking the [`GPIO`] peripheral by `&mut` ensures that this is called
+    /// during initialization, before pins are handed out to other drivers.
+    #[must_use]
+    pub fn new(_gpio: &mut GPIO) -> Self {
+        for port in Port::ALL {
+            unsafe {
+                write(port.eint_reg(Port::EINT_CTL), 0);
+                write(port.eint_reg(Port::EINT_STATUS), u32::MAX);
+            }
+        }
+        Self { _p: () }
+    }
+
+    /// Configures `pin` as an external interrupt source which fires on
+    /// `trigger`.
+    ///
+    /// This switches the pin to its EINT function, so it may no longer be
+    /// used as a general-purpose input or output. The interrupt remains
+    /// disabled until a task calls [`Gpio::wait_for_edge`] on the pin.
+    pub fn configure_eint(&self, pin: Pin, trigger: Trigger) {
+        let (reg, shift) = pin.nibble();
+        critical_section::with(|_| unsafe {
+            modify(pin.port.cfg_reg(Port::CFG0 + reg), |r| {
+                (r & !(0xF << shift)) | (FUNCTION_EINT << shift)
+            });
+            modify(pin.port.eint_reg(Port::EINT_CFG0 + reg), |r| {
+                (r & !(0xF << shift)) | ((trigger as u32) << shift)
+            });
+        });
+    }
+
+    /// Configures `pin` as a general-purpose output, initially driven high if
+    /// `high` is `true`, and low otherwise.
+    pub fn configure_output(&self, pin: Pin, high: bool) {
+        // set the output level first, so that the pin doesn't glitch when
+        // its function changes.
+        self.set_output(pin, high);
+        let (reg, shift) = pin.nibble();
+        critical_section::with(|_| unsafe {
+            modify(pin.port.cfg_reg(Port::CFG0 + reg), |r| {
+                (r & !(0xF << shift)) | (FUNCTION_OUTPUT << shift)
+            });
+        });
+    }
+
+    /// Drives `pin` high if `high` is `true`, and low otherwise.
+    ///
+    /// The pin must first be configured using [`Gpio::configure_output`].
+    /// This may be called from an interrupt handler.
+    pub fn set_output(&self, pin: Pin, high: bool) {
+        let bit = 1 << pin.num;
+        critical_section::with(|_| unsafe {
+            modify(pin.port.cfg_reg(Port::DAT), |r| {
+                if high {
+                    r | bit
+                } else {
+                    r & !bit
+                }
+            });
+        });
+    }
+
+    /// Sets the debounce configuration for all external interrupts on `port`.
+    pub fn set_debounce(&self, port: Port, debounce: Debounce) {
+        let clock = match debounce.clock {
+            DebounceClock::Losc32K => 0,
+            DebounceClock::Hosc24M => 1,
+        };
+        let prescale = u32::from(debounce.prescale.min(7));
+        unsafe {
+            write(port.eint_reg(Port::EINT_DEB), (prescale << 4) | clock);
+        }
+    }
+
+    /// Waits for `pin`'s external interrupt to fire.
+    ///
+    /// The pin must first be configured using [`Gpio::configure_eint`]. Any
+    /// interrupt that fired while no task was waiting on the pin is
+    /// discarded, so this always waits for the *next* edge (or level).
+    ///
+    /// Only one task may wait on a given pin at a time.
+    pub async fn wait_for_edge(&self, pin: Pin) {
+        let bit = 1 << pin.num;
+        let wait = pin.wait_cell().subscribe().await;
+
+        critical_section::with(|_| unsafe {
+            write(pin.port.eint_reg(Port::EINT_STATUS), bit);
+            modify(pin.port.eint_reg(Port::EINT_CTL), |r| r | bit);
+        });
+
+        // The WaitCells are never closed, so this can't fail.
+        let _ = wait.await;
+    }
+
+    /// Schedules `work` as [deferred work] each time `pin`'s external
+    /// interrupt fires.
+    ///
+    /// The pin must first be configured using [`Gpio::configure_eint`].
+    /// Unlike [`Gpio::wait_for_edge`], this leaves the pin's interrupt
+    /// enabled after it fires, so it should only be used with edge-triggered
+    /// pins. If the interrupt fires again before `work` has run, `work` only
+    /// runs once.
+    ///
+    /// Tasks must not [wait](Gpio::wait_for_edge) on a pin which has deferred
+    /// work.
+    ///
+    /// [deferred work]: kernel::deferred
+    pub fn on_edge(&self, kernel: &'static Kernel, pin: Pin, work: &'static Deferred) {
+        let bit = 1 << pin.num;
+        let deferred: *const DeferredWork = kernel.deferred();
+        DEFERRED.store(deferred.cast_mut(), Ordering::Release);
+        let work: *const Deferred = work;
+        PIN_WORK[pin.port.index()][pin.num as usize].store(work.cast_mut(), Ordering::Release);
+
+        critical_section::with(|_| unsafe {
+            write(pin.port.eint_reg(Port::EINT_STATUS), bit);
+            modify(pin.port.eint_reg(Port::EINT_CTL), |r| r | bit);
+        });
+    }
+
+    /// Registers and enables the EINT interrupt handlers for all ports.
+    ///
+    /// # Safety
+    ///
+    /// May effect normal interrupt processing.
+    pub unsafe fn register_interrupts(plic: &Plic) {
+        for port in Port::ALL {
+            let (interrupt, handler) = port.interrupt();
+            plic.register(interrupt, handler);
+            plic.activate(interrupt, IsrConfig::DEFAULT)
+                .expect("could not activate GPIO EINT ISR");
+        }
+    }
+
+    /// Handle a GPIO port B interrupt.
+    pub fn handle_pb_interrupt() {
+        Self::handle_interrupt(Port::B)
+    }
+
+    /// Handle a GPIO port C interrupt.
+    pub fn handle_pc_interrupt() {
+        Self::handle_interrupt(Port::C)
+    }
+
+    /// Handle a GPIO port D interrupt.
+    pub fn handle_pd_interrupt() {
+        Self::handle_interrupt(Port::D)
+    }
+
+    /// Handle a GPIO port E interrupt.
+    pub fn handle_pe_interrupt() {
+        Self::handle_interrupt(Port::E)
+    }
+
+    /// Handle a GPIO port F interrupt.
+    pub fn handle_pf_interrupt() {
+        Self::handle_interrupt(Port::F)
+    }
+
+    fn handle_interrupt(port: Port) {
+        let pending = unsafe { read(port.eint_reg(Port::EINT_STATUS)) };
+        tracing::trace!(?port, pending = ?format_args!("{pending:#b}"), "GPIO EINT interrupt");
+
+        let works = &PIN_WORK[port.index()];
+        let mut deferred_pins = 0;
+        for (num, work) in works.iter().enumerate() {
+            if !work.load(Ordering::Acquire).is_null() {
+                deferred_pins |= 1 << num;
+            }
+        }
+
+        unsafe {
+            // Disable the pins that fired until they are waited on again, so
+            // level-triggered pins don't immediately re-fire. Pins with
+            // deferred work stay enabled...
+            modify(port.eint_reg(Port::EINT_CTL), |r| {
+                r & !(pending & !deferred_pins)
+            });
+            // ...and write back the pending bits, clearing them.
+            write(port.eint_reg(Port::EINT_STATUS), pending);
+        }
+
+        let wakers = &PIN_WAIT[port.index()];
+        for (num, waker) in wakers.iter().enumerate() {
+            if pending & !deferred_pins & (1 << num) != 0 {
+                waker.wake();
+            }
+        }
+
+        if pending & deferred_pins != 0 {
+            // Safety: `DEFERRED` is always set before any `PIN_WORK`, and
+            // points to a `&'static DeferredWork`.
+            let deferred = unsafe { &*DEFERRED.load(Ordering::Acquire) };
+            for (num, work) in works.iter().enumerate() {
+                if pending & deferred_pins & (1 << num) != 0 {
+                    // Safety: `PIN_WORK` only contains `&'static Deferred`s.
+                    let work = unsafe { &*work.load(Ordering::Acquire) };
+                    if deferred.schedule(work).is_err() {
+                        tracing::warn!(?port, num, "deferred work queue is full!");
+                    }
+                }
+            }
+        }
+
+        // wait for the bits to clear to avoid spurious IRQs
+        while unsafe { read(port.eint_reg(Port::EINT_STATUS)) } & pending != 0 {}
+    }
+}
+
+// === impl Pin ===
+
+impl Pin {
+    /// Returns the pin numbered `num` on `port`, or `None` if `port` doesn't
+    /// have that many pins.
+    #[must_use]
+    pub const fn new(port: Port, num: u8) -> Option<Self> {
+        if num < port.pins() {
+            Some(Self { port, num })
+        } else {
+            None
+        }
+    }
+
+    /// Returns the port this pin belongs to.
+    #[must_use]
+    pub const fn port(&self) -> Port {
+        self.port
+    }
+
+    /// Returns this pin's number within its port.
+    #[must_use]
+    pub const fn num(&self) -> u8 {
+        self.num
+    }
+
+    /// Returns the `WaitCell` for this pin's EINT.
+    fn wait_cell(&self) -> &'static WaitCell {
+        // `num` is always less than 32, but the compiler doesn't know that.
+        // Masking it means indexing can't panic, which matters as the UART's
+        // TX path waits on pins.
+        &PIN_WAIT[self.port.index()][usize::from(self.num) % 32]
+    }
+
+    /// Returns the offset of the register (relative to the first register in
+    /// a bank) and the bit shift of this pin's 4-bit field, for register
+    /// banks with 8 pins per register, such as `Pn_CFGx` and `Pn_EINT_CFGx`.
+    const fn nibble(&self) -> (usize, u32) {
+        let num = self.num as usize;
+        ((num / 8) * 4, ((num % 8) * 4) as u32)
+    }
+}
+
+// === impl Port ===
+
+impl Port {
+    const COUNT: usize = 5;
+    const ALL: [Port; Self::COUNT] = [Port::B, Port::C, Port::D, Port::E, Port::F];
+
+    // Register offsets within each port's configuration block.
+    const CFG0: usize = 0x00;
+    const DAT: usize = 0x10;
+
+    // Register offsets within each port's EINT block.
+    const EINT_CFG0: usize = 0x00;
+    const EINT_CTL: usize = 0x10;
+    const EINT_STATUS: usize = 0x14;
+    const EINT_DEB: usize = 0x18;
+
+    /// Returns the number of pins on this port.
+    #[must_use]
+    pub const fn pins(self) -> u8 {
+        match self {
+            Port::B => 13,
+            Port::C => 8,
+            Port::D => 23,
+            Port::E => 18,
+            Port::F => 7,
+        }
+    }
+
+    const fn index(self) -> usize {
+        self as usize
+    }
+
+    /// The port number used to compute register addresses, where port A
+    /// (which doesn't exist on the D1) would be 0.
+    const fn number(self) -> usize {
+        self.index() + 1
+    }
+
+    fn interrupt(self) -> (Interrupt, fn()) {
+        match self {
+            Port::B => (Interrupt::GPIOB_NS, Gpio::handle_pb_interrupt),
+            Port::C => (Interrupt::GPIOC_NS, Gpio::handle_pc_interrupt),
+            Port::D => (Interrupt::GPIOD_NS, Gpio::handle_pd_interrupt),
+            Port::E => (Interrupt::GPIOE_NS, Gpio::handle_pe_interrupt),
+            Port::F => (Interrupt::GPIOF_NS, Gpio::handle_pf_interrupt),
+        }
+    }
+
+    // The PAC models each port's registers as distinct fields, so we compute
+    // register addresses by hand, rather than matching on every port and pin.
+
+    /// Returns a pointer to a register in this port's configuration block.
+    /// Configuration blocks are `0x30` bytes apart, starting at `PB_CFG0`.
+    fn cfg_reg(self, offset: usize) -> *mut u32 {
+        let base = GPIO::PTR as *mut u8;
+        unsafe { base.add(self.number() * 0x30 + offset).cast() }
+    }
+
+    /// Returns a pointer to a register in this port's EINT block. EINT
+    /// blocks are `0x20` bytes apart, starting at `PB_EINT_CFG0`.
+    fn eint_reg(self, offset: usize) -> *mut u32 {
+        let base = GPIO::PTR as *mut u8;
+        unsafe { base.add(0x200 + self.number() * 0x20 + offset).cast() }
+    }
+}
+
+#[inline(always)]
+unsafe fn read(reg: *mut u32) -> u32 {
+    mmio::read(reg)
+}
+
+#[inline(always)]
+unsafe fn write(reg: *mut u32, val: u32) {
+    mmio::write(reg, val)
+}
+
+#[inline(always)]
+unsafe fn modify(reg: *mut u32, f: impl FnOnce(u32) -> u32) {
+    mmio::modify(reg, f)
+}
@@ -0,0 +1,272 @@
+//! Driver for the Allwinner D1's LEDC (addressable LED controller).
+//!
+//! The LEDC generates the single-wire protocol used by WS2812-style "smart"
+//! LEDs in hardware, with LED data supplied by the DMAC. This module provides
+//! a server for the kernel's [`SmartLedService`], so the LEDs can be driven
+//! using a [`SmartLedClient`](kernel::services::smart_led::SmartLedClient).
+//!
+//! Currently, the LEDC's `LEDC_DO` output is always mapped to pin `PC0`,
+//! which is where the Lichee RV Dock's on-board WS2812 is connected.
+#![warn(missing_docs)]
+
+use crate::ccu::Ccu;
+use crate::dmac::{
+    descriptor::{BlockSize, DataWidth, Descriptor, DestDrqType},
+    ChannelMode, Dmac,
+};
+use crate::mmio;
+use d1_pac::{GPIO, LEDC};
+use embedded_graphics::pixelcolor::RgbColor;
+use kernel::{
+    maitake::sync::WaitCell,
+    mnemos_alloc::dma::{DmaBox, DmaBuf},
+    registry::Message,
+    services::smart_led::{
+        self, RegistrationError, Request, Response, SmartLedError, SmartLedService, StripMetadata,
+    },
+    Kernel,
+};
+
+/// Woken by the LEDC interrupt when a frame has been sent to the LEDs.
+static LEDC_DONE: WaitCell = WaitCell::new();
+
+/// A handle to the initialized LEDC peripheral.
+pub struct Ledc {
+    _p: (),
+}
+
+/// Pin function select value which routes `PC0` to `LEDC_DO`.
+const FUNCTION_LEDC_DO: u32 = 0x4;
+
+// Register offsets. The bitfields of these registers are written by hand,
+// rather than through the PAC, in the same manner as the GPIO EINT driver.
+const LEDC_CTRL: usize = 0x00;
+const LED_T01_TIMING_CTRL: usize = 0x04;
+const LED_RESET_TIMING_CTRL: usize = 0x0C;
+const LEDC_DMA_CTRL: usize = 0x18;
+const LEDC_INT_CTRL: usize = 0x1C;
+const LEDC_INT_STS: usize = 0x20;
+
+// `LEDC_CTRL` bits.
+const CTRL_EN: u32 = 1 << 0;
+const CTRL_SOFT_RESET: u32 = 1 << 1;
+const CTRL_TOTAL_DATA_LENGTH_SHIFT: u32 = 16;
+
+// `LEDC_DMA_CTRL` bits.
+const DMA_EN: u32 = 1 << 5;
+const DMA_FIFO_TRIG_LEVEL: u32 = 15;
+
+// `LEDC_INT_CTRL` and `LEDC_INT_STS` bits.
+const INT_TRANS_FINISH: u32 = 1 << 0;
+const INT_GLOBAL_EN: u32 = 1 << 5;
+
+// WS2812 bit timings, in ticks of the 24 MHz LEDC clock (~41.7ns each).
+const T0H: u32 = 8; // ~333ns
+const T0L: u32 = 20; // ~833ns
+const T1H: u32 = 21; // ~875ns
+const T1L: u32 = 7; // ~292ns
+/// Reset (latch) time between frames: 300us.
+const TRESET: u32 = 7200;
+
+// === impl Ledc ===
+
+impl Ledc {
+    /// The maximum number of LEDs the LEDC can drive.
+    pub const MAX_LEDS: usize = 1024;
+
+    /// Initializes the LEDC, with `LEDC_DO` mapped to pin `PC0`.
+    ///
+    /// # Safety
+    ///
+    /// - The `LEDC`'s register block must not be concurrently written to.
+    /// - This function should be called only while running on an Allwinner D1.
+    pub unsafe fn pc0(mut ledc: LEDC, ccu: &mut Ccu, gpio: &mut GPIO) -> Self {
+        // TODO: ccu should provide a higher-level abstraction for this
+        ccu.borrow_raw().ledc_clk.write(|w| {
+            // Enable clock
+            w.clk_gating().on();
+            // base:  24 MHz
+            w.clk_src_sel().hosc();
+            // /1:    24 MHz
+            w.factor_n().n1();
+            w.factor_m().variant(0);
+            w
+        });
+        ccu.enable_module(&mut ledc);
+
+        // Map the pin
+        gpio.pc_cfg0
+            .modify(|r, w| w.bits((r.bits() & !0xF) | FUNCTION_LEDC_DO));
+
+        write(LEDC_CTRL, CTRL_SOFT_RESET);
+        while read(LEDC_CTRL) & CTRL_SOFT_RESET != 0 {}
+
+        write(
+            LED_T01_TIMING_CTRL,
+            (T1H << 21) | (T1L << 16) | (T0H << 6) | T0L,
+        );
+        write(LEDC_DMA_CTRL, DMA_EN | DMA_FIFO_TRIG_LEVEL);
+        write(LEDC_INT_CTRL, INT_TRANS_FINISH | INT_GLOBAL_EN);
+
+        Self { _p: () }
+    }
+
+    /// Registers a [`SmartLedService`] server for a strip of LEDs with the
+    /// layout described by `meta`, scaling every pixel to `brightness` (out
+    /// of 255).
+    ///
+    /// Returns [`RegistrationError::InvalidSize`] if the strip has no LEDs,
+    /// or more than [`Ledc::MAX_LEDS`].
+    #[tracing::instrument(
+        name = "Ledc::register",
+        level = tracing::Level::INFO,
+        skip(self, kernel, dmac),
+        ret(Debug),
+        err(Debug),
+    )]
+    pub async fn register(
+        self,
+        kernel: &'static Kernel,
+        dmac: Dmac,
+        meta: StripMetadata,
+        brightness: u8,
+        queued: usize,
+    ) -> Result<(), RegistrationError> {
+        let len = meta.len();
+        if !(1..=Self::MAX_LEDS).contains(&len) {
+            return Err(RegistrationError::InvalidSize(meta));
+        }
+
+        let reqs = kernel
+            .registry()
+            .bind_konly::<SmartLedService>(queued)
+            .await
+            .map_err(RegistrationError::Registry)?
+            .into_request_stream(queued)
+            .await;
+
+        unsafe {
+            write(LED_RESET_TIMING_CTRL, (TRESET << 16) | (len as u32 - 1));
+        }
+
+        // Each LED is sent as one 32-bit word.
+        let mut buf = DmaBuf::new(len * 4).await;
+
+        kernel
+            .spawn(async move {
+                let ledc = unsafe { &*LEDC::PTR };
+                let descr_cfg = Descriptor::builder()
+                    .dest_data_width(DataWidth::Bit32)
+                    .dest_block_size(BlockSize::Byte4)
+                    .src_data_width(DataWidth::Bit32)
+                    .src_block_size(BlockSize::Byte4)
+                    .wait_clock_cycles(0)
+                    .dest_reg(&ledc.ledc_data, DestDrqType::Ledc)
+                    .expect(
+                        "LEDC_DATA register should be a valid destination register for DMA transfers",
+                    );
+
+                tracing::info!(?meta, brightness, "LEDC worker task running");
+                loop {
+                    let Message { msg, reply } = reqs.next_request().await;
+                    let res = match msg.body {
+                        Request::GetMeta => Ok(()),
+                        Request::Show(ref frame) if frame.len() != len => {
+                            Err(SmartLedError::WrongSize {
+                                expected: len,
+                                actual: frame.len(),
+                            })
+                        }
+                        Request::Show(ref frame) => {
+                            for (word_buf, &pixel) in
+                                buf.chunks_exact_mut(4).zip(frame.pixels())
+                            {
+                                let pixel = smart_led::correct(pixel, brightness);
+                                // The LEDC shifts out the low 24 bits of each
+                                // word, MSB first. In its default GRB mode,
+                                // that's green, red, then blue, which is the
+                                // order WS2812s expect.
+                                let word = u32::from(pixel.g()) << 16
+                                    | u32::from(pixel.r()) << 8
+                                    | u32::from(pixel.b());
+                                word_buf.copy_from_slice(&word.to_le_bytes());
+                            }
+
+                            let descriptor = descr_cfg
+                                .source_slice(&buf)
+                                .expect("slice should be a valid DMA source")
+                                .build();
+                            let descriptor = DmaBox::new(descriptor).await;
+                            descriptor.sync_for_device();
+                            buf.sync_for_device();
+
+                            let mut chan = dmac.claim_channel().await;
+                            unsafe {
+                                chan.set_channel_modes(ChannelMode::Wait, ChannelMode::Handshake);
+                            }
+                            // pre-subscribe, so that the finish interrupt
+                            // can't be missed.
+                            let done = LEDC_DONE.subscribe().await;
+                            unsafe {
+                                write(
+                                    LEDC_CTRL,
+                                    ((len as u32) << CTRL_TOTAL_DATA_LENGTH_SHIFT) | CTRL_EN,
+                                );
+                                chan.transfer(descriptor.as_non_null()).await;
+                            }
+                            // The WaitCell is never closed, so this can't fail.
+                            let _ = done.await;
+                            Ok(())
+                        }
+                    };
+
+                    reply
+                        .reply_konly(msg.reply_with_body(|req| {
+                            res.map(|()| match req {
+                                Request::GetMeta => Response::Meta(meta),
+                                Request::Show(frame) => Response::Shown(frame),
+                            })
+                        }))
+                        .await
+                        .unwrap();
+                }
+            })
+            .await;
+
+        Ok(())
+    }
+
+    /// Handle an LEDC interrupt.
+    pub fn handle_interrupt() {
+        let status = unsafe { read(LEDC_INT_STS) };
+        tracing::trace!(status = ?format_args!("{status:#b}"), "LEDC interrupt");
+
+        // Clear the pending bits by writing them back.
+        unsafe { write(LEDC_INT_STS, status) };
+
+        if status & INT_TRANS_FINISH != 0 {
+            unsafe { modify(LEDC_CTRL, |r| r & !CTRL_EN) };
+            LEDC_DONE.wake();
+        }
+    }
+}
+
+fn reg(offset: usize) -> *mut u32 {
+    let base = LEDC::PTR as *mut u8;
+    unsafe { base.add(offset).cast() }
+}
+
+#[inline(always)]
+unsafe fn read(offset: usize) -> u32 {
+    mmio::read(reg(offset))
+}
+
+#[inline(always)]
+unsafe fn write(offset: usize, val: u32) {
+    mmio::write(reg(offset), val)
+}
+
+#[inline(always)]
+unsafe fn modify(offset: usize, f: impl FnOnce(u32) -> u32) {
+    mmio::modify(reg(offset), f)
+}
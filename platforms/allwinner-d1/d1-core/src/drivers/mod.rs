@@ -1,6 +1,10 @@
+pub mod display_engine;
+pub mod gpio;
+pub mod ledc;
 #[cfg(feature = "sharp-display")]
 pub mod sharp_display;
 pub mod smhc;
+pub mod spi_nor;
 pub mod spim;
 pub mod twi;
 pub mod uart;
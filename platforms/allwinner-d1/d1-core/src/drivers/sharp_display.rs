@@ -29,10 +29,10 @@ use embedded_graphics::{pixelcolor::Gray8, prelude::*, primitives::Rectangle};
 use kernel::{
     maitake::sync::{Mutex, WaitQueue},
     mnemos_alloc::containers::{Arc, FixedVec},
-    registry::{self, listener},
+    registry::{self, listener, RateLimit},
     services::emb_display::{
-        DisplayMetadata, EmbDisplayService, FrameChunk, FrameError, FrameKind, MonoChunk, Request,
-        Response,
+        capabilities, DisplayMetadata, DisplayTransform, EmbDisplayService, FrameChunk, FrameError,
+        FrameKind, MonoChunk, Request, Response,
     },
     Kernel,
 };
@@ -82,21 +82,32 @@ impl SharpDisplay {
     pub const WIDTH: usize = WIDTH;
     pub const HEIGHT: usize = HEIGHT;
     const CAPACITY: usize = 2;
+    /// Each client's budget of draw requests. This is far more than a client
+    /// redrawing at the display's refresh rate needs, but keeps a client stuck
+    /// in a drawing loop from locking every other client out of the display.
+    const RATE_LIMIT: RateLimit = RateLimit::new(240, 32);
 
     /// Register the driver instance
     ///
     /// Registration will also start the simulated display, meaning that the display
     /// window will appear.
+    ///
+    /// `transform` is reported to clients, which rotate and scale their frames
+    /// before sending them to the driver.
     #[tracing::instrument(
         name = "SharpDisplay::register",
         level = tracing::Level::INFO,
         skip(kernel),
         err(Debug),
     )]
-    pub async fn register(kernel: &'static Kernel) -> Result<(), RegistrationError> {
+    pub async fn register(
+        kernel: &'static Kernel,
+        transform: DisplayTransform,
+    ) -> Result<(), RegistrationError> {
         tracing::info!(
             width = WIDTH,
             height = HEIGHT,
+            ?transform,
             queue_capacity = Self::CAPACITY,
             "Starting SharpDisplay driver",
         );
@@ -108,13 +119,16 @@ impl SharpDisplay {
             .map_err(RegistrationError::NoSpiSender)?;
 
         // bind a listener
-        let cmd = kernel
+        let (listener, registration) = listener::Listener::new(Self::CAPACITY).await;
+        kernel
             .registry()
-            .bind_konly(Self::CAPACITY)
+            .register_konly(registration.with_rate_limit(Self::RATE_LIMIT))
             .await
-            .map_err(RegistrationError::Registration)?
+            .map_err(RegistrationError::Registration)?;
+        let cmd = listener
             .into_request_stream(Self::CAPACITY)
-            .await;
+            .await
+            .with_capabilities(capabilities::TRANSFORM | capabilities::SCREENSHOT);
 
         let linebuf = FixedVec::new(FRAME_BYTES).await;
 
@@ -129,6 +143,7 @@ impl SharpDisplay {
             ctxt: ctxt.clone(),
             height: HEIGHT as u32,
             width: WIDTH as u32,
+            transform,
         };
 
         let vcom = VCom {
@@ -186,6 +201,15 @@ impl FullFrame {
             self.frame[y][byte_x] &= !(1 << (bit_x as u8))
         }
     }
+
+    /// Returns `true` if the pixel is on. Pixels outside of the display are
+    /// off.
+    fn get_px(&self, x: usize, y: usize) -> bool {
+        if x >= WIDTH || y >= HEIGHT {
+            return false;
+        }
+        self.frame[y][x / 8] & (1 << (x % 8) as u8) != 0
+    }
 }
 
 impl Dimensions for FullFrame {
@@ -331,6 +355,7 @@ struct CommanderTask {
     ctxt: Arc<Mutex<Context>>,
     width: u32,
     height: u32,
+    transform: DisplayTransform,
 }
 
 impl CommanderTask {
@@ -356,10 +381,20 @@ impl CommanderTask {
                         kind: FrameKind::Mono,
                         width: self.width,
                         height: self.height,
+                        transform: self.transform,
                     };
                     let response = env.fill(Ok(Response::FrameMeta(meta)));
                     let _ = reply_tx.reply_konly(response).await;
                 }
+                Request::Screenshot(FrameChunk::Mono(mut fc)) => {
+                    tracing::debug!("Processing Screenshot command");
+                    {
+                        let ctx = self.ctxt.lock().await;
+                        fc.capture(|x, y| ctx.sdisp.get_px(x as usize, y as usize));
+                    }
+                    let response = env.fill(Ok(Response::Screenshot(fc.into())));
+                    let _ = reply_tx.reply_konly(response).await;
+                }
                 _ => {
                     let response = env.fill(Err(FrameError::InternalError));
                     let _ = reply_tx.reply_konly(response).await;
@@ -10,6 +10,9 @@
 //!
 //! Each SMHC also has an internal DMA controller that can be used for offloading
 //! the transfer and reception of large amounts of data to/from the device.
+//! Data is transferred through a bounce buffer in [DMA
+//! memory](kernel::mnemos_alloc::dma), rather than directly to or from the
+//! buffers in requests, which may share cache lines with other data.
 use core::{
     cell::UnsafeCell,
     future,
@@ -19,7 +22,10 @@ use core::{
 
 use d1_pac::{smhc, Interrupt, GPIO, SMHC0, SMHC1, SMHC2};
 use kernel::{
-    mnemos_alloc::containers::FixedVec,
+    mnemos_alloc::{
+        containers::FixedVec,
+        dma::{DmaBox, DmaBuf},
+    },
     registry,
     services::sdmmc::{self, SdmmcService},
     tracing, Kernel,
@@ -34,6 +40,16 @@ pub struct Smhc {
     num: u8,
 }
 
+/// Memory accessed by the SMHC's internal DMA controller.
+struct SmhcDma {
+    /// Space for 16 DMA descriptors.
+    /// Each descriptor can do a transfer of 4KB, giving a max total transfer of 64KB.
+    descriptors: DmaBox<[idmac::Descriptor; 16]>,
+    /// The buffer data is transferred through, which is large enough for the
+    /// maximum transfer.
+    bounce: DmaBuf,
+}
+
 /// Data used by a SMHC interrupt.
 struct IsrData {
     data: UnsafeCell<SmhcData>,
@@ -363,9 +379,14 @@ impl Smhc {
     #[tracing::instrument(name = "SMHC", fields(num = self.num), level = tracing::Level::INFO, skip(self, rx))]
     async fn run(self, rx: registry::listener::RequestStream<SdmmcService>) {
         tracing::info!("starting SMHC driver task");
+        let mut dma = SmhcDma {
+            // Safety: a zero'ed descriptor is valid and will simply be ignored by the IDMAC.
+            descriptors: DmaBox::new(unsafe { core::mem::zeroed() }).await,
+            bounce: DmaBuf::new(SmhcDma::DESCR_BUFF_SIZE * 16).await,
+        };
         loop {
             let registry::Message { mut msg, reply } = rx.next_request().await;
-            let response = self.command(msg.body).await;
+            let response = self.command(&mut dma, msg.body).await;
             // TODO: we don't need `msg.body` anymore, but since it has been moved
             // we need to supply another value if we want to use `msg` later to reply.
             msg.body = sdmmc::Command::default();
@@ -375,8 +396,12 @@ impl Smhc {
         }
     }
 
-    #[tracing::instrument(level = tracing::Level::DEBUG, skip(self, params))]
-    async fn command(&self, params: sdmmc::Command) -> Result<sdmmc::Response, sdmmc::Error> {
+    #[tracing::instrument(level = tracing::Level::DEBUG, skip(self, dma, params))]
+    async fn command(
+        &self,
+        dma: &mut SmhcDma,
+        params: sdmmc::Command,
+    ) -> Result<sdmmc::Response, sdmmc::Error> {
         if self.smhc.smhc_cmd.read().cmd_load().bit_is_set() {
             return Err(sdmmc::Error::from(sdmmc::ErrorKind::Busy));
         }
@@ -424,53 +449,57 @@ impl Smhc {
             }
         };
 
-        // Statically allocate space for 16 DMA descriptors.
-        // Each descriptor can do a transfer of 4KB, giving a max total transfer of 64KB.
-        // By declaring them in this scope the descriptor memory will live long enough,
-        // however currently this is up to the user to guarantuee.
-        // Safety: a zero'ed descriptor is valid and will simply be ignored by the IDMAC.
-        let mut descriptors: [idmac::Descriptor; 16] = unsafe { core::mem::zeroed() };
-
         // Perform checks on arguments to make sure we won't overflow the buffer
-        match &mut guard.data.op {
+        let cnt = match &guard.data.op {
             SmhcOp::Read { buf, cnt, .. } | SmhcOp::Write { buf, cnt, .. } => {
-                const DESCR_BUFF_SIZE: usize = 0x1000;
-
                 // Currently we limit the number of data that can be read at once
-                if *cnt > DESCR_BUFF_SIZE * descriptors.len() || buf.capacity() < *cnt {
+                if *cnt > dma.bounce.len() || buf.capacity() < *cnt {
                     return Err(sdmmc::Error::from(sdmmc::ErrorKind::Buffer));
                 }
+                Some(*cnt)
+            }
+            _ => None,
+        };
 
-                tracing::debug!(cnt, "Creating descriptor chain from buffer");
-                let buf_ptr = buf.as_slice_mut().as_mut_ptr();
-                let mut remaining = *cnt;
-                let mut index = 0;
-                while remaining > 0 {
-                    let buff_size = core::cmp::min(DESCR_BUFF_SIZE, remaining);
-                    let buff_addr = unsafe { buf_ptr.add(index * DESCR_BUFF_SIZE) };
-                    // Having to construct the slice in this manual way is not ideal,
-                    // but it allows us to use `&mut [u8]` in the DescriptorBuilder.
-                    let slice = unsafe { core::slice::from_raw_parts_mut(buff_addr, buff_size) };
-                    remaining = remaining.saturating_sub(DESCR_BUFF_SIZE);
-                    let first = index == 0;
-                    let last = remaining == 0;
-                    descriptors[index] = idmac::DescriptorBuilder::new()
-                        .disable_irq(!last)
-                        .first(first)
-                        .last(last)
-                        .link((!last).then(|| (&descriptors[index + 1]).into()))
-                        .expect("Should be able to link to next descriptor")
-                        .buff_slice(slice)
-                        .map_err(|_| sdmmc::Error::from(sdmmc::ErrorKind::Buffer))?
-                        .build();
-                    index += 1;
-                }
+        // The IDMAC reads the data to write from the bounce buffer
+        if let SmhcOp::Write { buf, cnt, .. } = &guard.data.op {
+            let data = buf
+                .as_slice()
+                .get(..*cnt)
+                .ok_or_else(|| sdmmc::Error::from(sdmmc::ErrorKind::Buffer))?;
+            dma.bounce[..*cnt].copy_from_slice(data);
+        }
 
-                // TODO: should this return some kind of guard, which needs to be used later
-                // to make sure the descriptor has a long enough lifetime?
-                unsafe { self.prepare_dma(&descriptors[0], *cnt as u32) };
+        if let Some(cnt) = cnt {
+            tracing::debug!(cnt, "Creating descriptor chain from buffer");
+            let SmhcDma {
+                descriptors,
+                bounce,
+            } = dma;
+            let chunks = bounce[..cnt].chunks_mut(SmhcDma::DESCR_BUFF_SIZE);
+            let last_index = chunks.len().saturating_sub(1);
+            for (index, slice) in chunks.enumerate() {
+                let first = index == 0;
+                let last = index == last_index;
+                descriptors[index] = idmac::DescriptorBuilder::new()
+                    .disable_irq(!last)
+                    .first(first)
+                    .last(last)
+                    .link((!last).then(|| (&descriptors[index + 1]).into()))
+                    .expect("Should be able to link to next descriptor")
+                    .buff_slice(slice)
+                    .map_err(|_| sdmmc::Error::from(sdmmc::ErrorKind::Buffer))?
+                    .build();
             }
-            _ => (),
+
+            // The D1's data cache is not coherent with DMA, so make sure the
+            // IDMAC sees the descriptors and any data it's writing out.
+            descriptors.sync_for_device();
+            bounce.sync_for_device();
+
+            // Safety: the descriptors and bounce buffer are owned by the
+            // driver task, so they live long enough.
+            unsafe { self.prepare_dma(&descriptors[0], cnt as u32) };
         }
 
         // Set the argument to be sent
@@ -527,7 +556,13 @@ impl Smhc {
             Ok(sdmmc::Response::Short {
                 value: self.smhc.smhc_resp0.read().bits(),
                 data: match core::mem::replace(&mut guard.data.op, SmhcOp::None) {
-                    SmhcOp::Read { buf, .. } => Some(buf),
+                    SmhcOp::Read { mut buf, .. } => {
+                        // The IDMAC wrote the data to the bounce buffer
+                        dma.bounce.sync_for_cpu();
+                        let data = buf.as_slice_mut();
+                        data.copy_from_slice(&dma.bounce[..data.len()]);
+                        Some(buf)
+                    }
                     _ => None,
                 },
             })
@@ -535,6 +570,11 @@ impl Smhc {
     }
 }
 
+impl SmhcDma {
+    /// The size of the buffer used by each descriptor.
+    const DESCR_BUFF_SIZE: usize = 0x1000;
+}
+
 impl IsrData {
     #[must_use]
     fn lock<'a>(&'a self, smhc: &'a smhc::RegisterBlock) -> SmhcDataGuard<'a> {
@@ -0,0 +1,272 @@
+// Note: We sometimes force a pass by ref mut to enforce exclusive access
+#![allow(clippy::needless_pass_by_ref_mut)]
+
+//! Driver for SPI NOR flash on the Allwinner D1's `SPI0`.
+//!
+//! D1 boards which boot from SPI NOR have the flash chip on `SPI0`, on pins
+//! PC2-PC7. This driver implements [`FlashDevice`] for such a chip, so that
+//! the kernel's [`FlashServer`] can give the rest of the kernel access to the
+//! config and key-value regions of the flash image it was booted from.
+//!
+//! Flash accesses are small and infrequent, so the controller's FIFOs are
+//! read and written directly, rather than using DMA. Only standard
+//! single-bit SPI and 3-byte addresses are used, so at most the first 16 MiB
+//! of the chip can be accessed, which every SPI NOR chip supports.
+//!
+//! The controller's registers are accessed by address, in the same manner as
+//! the LEDC driver, and the register bitfields are written by hand.
+//!
+//! [`FlashServer`]: kernel::services::flash::FlashServer
+#![warn(missing_docs)]
+
+use d1_pac::{GPIO, SPI0};
+use kernel::services::flash::{FlashDevice, FlashError};
+
+use crate::{ccu::Ccu, mmio};
+
+/// `SPI0`'s base address.
+const SPI0_BASE: usize = 0x0402_5000;
+
+// Register offsets.
+const SPI_GCR: usize = 0x04;
+const SPI_TCR: usize = 0x08;
+const SPI_ISR: usize = 0x14;
+const SPI_FCR: usize = 0x18;
+const SPI_FSR: usize = 0x1C;
+const SPI_MBC: usize = 0x30;
+const SPI_MTC: usize = 0x34;
+const SPI_BCC: usize = 0x38;
+const SPI_TXD: usize = 0x200;
+const SPI_RXD: usize = 0x300;
+
+// `SPI_GCR` bits.
+const GCR_EN: u32 = 1 << 0;
+const GCR_MODE_MASTER: u32 = 1 << 1;
+/// Stop transmitting while the RX FIFO is full, rather than overflowing it.
+const GCR_TP_EN: u32 = 1 << 7;
+const GCR_SRST: u32 = 1 << 31;
+
+// `SPI_TCR` bits.
+/// Drive the chip select from `TCR_SS_LEVEL`, rather than automatically.
+const TCR_SS_OWNER: u32 = 1 << 6;
+const TCR_SS_LEVEL: u32 = 1 << 7;
+/// Discard the bytes received while transmitting.
+const TCR_DHB: u32 = 1 << 8;
+const TCR_XCH: u32 = 1 << 31;
+
+// `SPI_ISR` bits.
+const ISR_TC: u32 = 1 << 12;
+
+// `SPI_FCR` bits.
+const FCR_RF_RST: u32 = 1 << 15;
+const FCR_TF_RST: u32 = 1 << 31;
+
+/// The depth of each of the controller's FIFOs, in bytes.
+const FIFO_LEN: u32 = 64;
+
+/// The pin function which connects PC2-PC7 to `SPI0`.
+const FUNCTION_SPI0: u32 = 0x2;
+
+// SPI NOR commands, which are the same for every chip.
+const CMD_READ: u8 = 0x03;
+const CMD_PAGE_PROGRAM: u8 = 0x02;
+const CMD_SECTOR_ERASE: u8 = 0x20;
+const CMD_WRITE_ENABLE: u8 = 0x06;
+const CMD_READ_STATUS: u8 = 0x05;
+const CMD_READ_JEDEC_ID: u8 = 0x9F;
+
+/// The status register's write-in-progress bit.
+const STATUS_WIP: u8 = 1 << 0;
+
+/// The size of a program page.
+const PAGE_SIZE: u32 = 256;
+
+/// The most that can be addressed with 3-byte addresses.
+const MAX_CAPACITY: u32 = 16 * 1024 * 1024;
+
+/// A SPI NOR flash chip on `SPI0`.
+pub struct SpiNor {
+    capacity: u32,
+}
+
+// === impl SpiNor ===
+
+impl SpiNor {
+    /// Maps PC2-PC7 to `SPI0`, and looks for a SPI NOR flash chip on it.
+    ///
+    /// Returns [`None`] if no chip answers with a JEDEC ID.
+    ///
+    /// # Safety
+    ///
+    /// - The `SPI0`'s register block must not be concurrently written to.
+    /// - This function should be called only while running on an Allwinner D1.
+    pub unsafe fn spi0(mut spi0: SPI0, ccu: &mut Ccu, gpio: &mut GPIO) -> Option<Self> {
+        // TODO: ccu should provide a higher-level abstraction for this
+        ccu.borrow_raw().spi0_clk.write(|w| {
+            // Enable clock
+            w.clk_gating().on();
+            // base:  24 MHz
+            w.clk_src_sel().hosc();
+            // /1:    24 MHz
+            w.factor_n().n1();
+            // /2:    12 MHz
+            w.factor_m().variant(1);
+            w
+        });
+        ccu.enable_module(&mut spi0);
+
+        // Map the pins: CLK, CS0, MOSI, MISO, WP and HOLD. PC0 and PC1 are
+        // left alone, as they may be used by the LEDC or the blink service.
+        gpio.pc_cfg0.modify(|r, w| {
+            let functions = (2..8).fold(0, |cfg, pin| cfg | (FUNCTION_SPI0 << (pin * 4)));
+            w.bits((r.bits() & 0xFF) | functions)
+        });
+
+        write(SPI_GCR, GCR_SRST);
+        while read(SPI_GCR) & GCR_SRST != 0 {}
+        write(SPI_GCR, GCR_EN | GCR_MODE_MASTER | GCR_TP_EN);
+        // mode 0, with the chip select deasserted (high) between commands.
+        write(SPI_TCR, TCR_SS_OWNER | TCR_SS_LEVEL | TCR_DHB);
+
+        let mut this = Self { capacity: 0 };
+        let mut id = [0; 3];
+        this.command(&[CMD_READ_JEDEC_ID], &mut id);
+        let [manufacturer, kind, capacity] = id;
+        // a missing chip reads back as all zeros or all ones, depending on
+        // the pull on MISO.
+        if manufacturer == 0x00 || manufacturer == 0xFF || !(16..32).contains(&capacity) {
+            tracing::warn!(?id, "no SPI NOR flash found on SPI0");
+            return None;
+        }
+        this.capacity = (1u32 << capacity).min(MAX_CAPACITY);
+        tracing::info!(
+            manufacturer,
+            kind,
+            capacity = this.capacity,
+            "found SPI NOR flash on SPI0"
+        );
+        Some(this)
+    }
+
+    /// Sends `tx`, and then reads `rx.len()` bytes, with the chip selected.
+    fn command(&mut self, tx: &[u8], rx: &mut [u8]) {
+        unsafe {
+            write(SPI_FCR, FCR_TF_RST | FCR_RF_RST);
+            write(SPI_ISR, ISR_TC);
+            write(SPI_MBC, (tx.len() + rx.len()) as u32);
+            write(SPI_MTC, tx.len() as u32);
+            write(SPI_BCC, tx.len() as u32);
+            modify(SPI_TCR, |tcr| (tcr & !TCR_SS_LEVEL) | TCR_XCH);
+
+            // keep the TX FIFO topped up, and the RX FIFO drained, until the
+            // transfer is complete.
+            let mut tx = tx.iter();
+            let mut received = 0;
+            loop {
+                let fsr = read(SPI_FSR);
+                let tx_free = FIFO_LEN - ((fsr >> 16) & 0xFF);
+                for &byte in tx.by_ref().take(tx_free as usize) {
+                    mmio::write_u8(reg(SPI_TXD) as *mut u8, byte);
+                }
+                for _ in 0..(fsr & 0xFF) {
+                    if let Some(byte) = rx.get_mut(received) {
+                        *byte = mmio::read_u8(reg(SPI_RXD) as *const u8);
+                        received += 1;
+                    }
+                }
+                if received == rx.len() && read(SPI_ISR) & ISR_TC != 0 {
+                    break;
+                }
+            }
+
+            write(SPI_ISR, ISR_TC);
+            modify(SPI_TCR, |tcr| tcr | TCR_SS_LEVEL);
+        }
+    }
+
+    fn write_enable(&mut self) {
+        self.command(&[CMD_WRITE_ENABLE], &mut []);
+    }
+
+    /// Returns a command followed by `addr`, which is sent most significant
+    /// byte first.
+    fn addressed(cmd: u8, addr: u32) -> [u8; 4] {
+        let [_, a2, a1, a0] = addr.to_be_bytes();
+        [cmd, a2, a1, a0]
+    }
+
+    fn check(&self, addr: u32, len: usize) -> Result<(), FlashError> {
+        let end = addr as u64 + len as u64;
+        if end > self.capacity as u64 {
+            return Err(FlashError::OutOfBounds);
+        }
+        Ok(())
+    }
+}
+
+impl FlashDevice for SpiNor {
+    fn capacity(&self) -> u32 {
+        self.capacity
+    }
+
+    fn page_size(&self) -> u32 {
+        PAGE_SIZE
+    }
+
+    fn read(&mut self, addr: u32, buf: &mut [u8]) -> Result<(), FlashError> {
+        self.check(addr, buf.len())?;
+        // the controller discards bytes received while the command is sent,
+        // so each read can fill the RX FIFO.
+        let mut addr = addr;
+        for chunk in buf.chunks_mut(FIFO_LEN as usize) {
+            self.command(&Self::addressed(CMD_READ, addr), chunk);
+            addr += chunk.len() as u32;
+        }
+        Ok(())
+    }
+
+    fn start_program(&mut self, addr: u32, data: &[u8]) -> Result<(), FlashError> {
+        self.check(addr, data.len())?;
+        if data.is_empty() || addr % PAGE_SIZE + data.len() as u32 > PAGE_SIZE {
+            return Err(FlashError::OutOfBounds);
+        }
+        let mut cmd = [0; 4 + PAGE_SIZE as usize];
+        cmd[..4].copy_from_slice(&Self::addressed(CMD_PAGE_PROGRAM, addr));
+        cmd[4..4 + data.len()].copy_from_slice(data);
+        self.write_enable();
+        self.command(&cmd[..4 + data.len()], &mut []);
+        Ok(())
+    }
+
+    fn start_erase(&mut self, addr: u32) -> Result<(), FlashError> {
+        self.check(addr, 1)?;
+        self.write_enable();
+        self.command(&Self::addressed(CMD_SECTOR_ERASE, addr), &mut []);
+        Ok(())
+    }
+
+    fn is_busy(&mut self) -> Result<bool, FlashError> {
+        let mut status = [0];
+        self.command(&[CMD_READ_STATUS], &mut status);
+        Ok(status[0] & STATUS_WIP != 0)
+    }
+}
+
+fn reg(offset: usize) -> *mut u32 {
+    (SPI0_BASE + offset) as *mut u32
+}
+
+#[inline(always)]
+unsafe fn read(offset: usize) -> u32 {
+    mmio::read(reg(offset))
+}
+
+#[inline(always)]
+unsafe fn write(offset: usize, val: u32) {
+    mmio::write(reg(offset), val)
+}
+
+#[inline(always)]
+unsafe fn modify(offset: usize, f: impl FnOnce(u32) -> u32) {
+    mmio::modify(reg(offset), f)
+}
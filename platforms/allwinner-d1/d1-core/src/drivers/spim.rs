@@ -3,24 +3,23 @@
 
 //! Spi Sender
 
-use core::ptr::NonNull;
-
 use crate::ccu::Ccu;
 use crate::dmac::{
     descriptor::{BlockSize, DataWidth, Descriptor, DestDrqType},
     ChannelMode, Dmac,
 };
+use crate::mmio;
 use d1_pac::{GPIO, SPI_DBI};
 use kernel::{
     comms::oneshot::Reusable,
-    maitake::sync::WaitCell,
-    mnemos_alloc::containers::FixedVec,
+    mnemos_alloc::{
+        containers::FixedVec,
+        dma::{self, DmaBox},
+    },
     registry::{self, uuid, Envelope, KernelHandle, Message, RegisteredDriver, ReplyTo, Uuid},
     Kernel,
 };
 
-pub static SPI1_TX_DONE: WaitCell = WaitCell::new();
-
 pub struct Spim1 {
     _x: (),
 }
@@ -32,58 +31,70 @@ pub struct Spim1 {
 pub unsafe fn kernel_spim1(mut spi1: SPI_DBI, ccu: &mut Ccu, gpio: &mut GPIO) -> Spim1 {
     // Set clock rate (fixed to 2MHz), and enable the SPI peripheral
     // TODO: ccu should provide a higher-level abstraction for this
-    ccu.borrow_raw().spi1_clk.write(|w| {
-        // Enable clock
-        w.clk_gating().on();
-        // base:  24 MHz
-        w.clk_src_sel().hosc();
-        // /1:    24 MHz
-        w.factor_n().n1();
-        // /12:    2 MHz
-        w.factor_m().variant(11);
-        w
+    mmio::write_reg(&ccu.borrow_raw().spi1_clk, |r| {
+        r.write(|w| {
+            // Enable clock
+            w.clk_gating().on();
+            // base:  24 MHz
+            w.clk_src_sel().hosc();
+            // /1:    24 MHz
+            w.factor_n().n1();
+            // /12:    2 MHz
+            w.factor_m().variant(11);
+            w
+        })
     });
     ccu.enable_module(&mut spi1);
 
     // Map the pins
-    gpio.pd_cfg1.write(|w| {
-        // Select SPI pin mode
-        w.pd10_select().spi1_cs_dbi_csx();
-        w.pd11_select().spi1_clk_dbi_sclk();
-        w.pd12_select().spi1_mosi_dbi_sdo();
-        w
+    mmio::write_reg(&gpio.pd_cfg1, |r| {
+        r.write(|w| {
+            // Select SPI pin mode
+            w.pd10_select().spi1_cs_dbi_csx();
+            w.pd11_select().spi1_clk_dbi_sclk();
+            w.pd12_select().spi1_mosi_dbi_sdo();
+            w
+        })
     });
-    gpio.pd_pull0.write(|w| {
-        // Disable pull up/downs
-        w.pd10_pull().pull_disable();
-        w.pd11_pull().pull_disable();
-        w.pd12_pull().pull_disable();
-        w
+    mmio::write_reg(&gpio.pd_pull0, |r| {
+        r.write(|w| {
+            // Disable pull up/downs
+            w.pd10_pull().pull_disable();
+            w.pd11_pull().pull_disable();
+            w.pd12_pull().pull_disable();
+            w
+        })
     });
 
     // Hard coded configuration for specifically supporting the SHARP memory display
-    spi1.spi_tcr.write(|w| {
-        // Allow the hardware to control the chip select
-        w.ss_owner().spi_controller();
-        // LSB first bit-order
-        w.fbs().lsb();
-        // Chip select active HIGH (the sharp display is weird and is active high)
-        w.spol().clear_bit();
-        w
+    mmio::write_reg(&spi1.spi_tcr, |r| {
+        r.write(|w| {
+            // Allow the hardware to control the chip select
+            w.ss_owner().spi_controller();
+            // LSB first bit-order
+            w.fbs().lsb();
+            // Chip select active HIGH (the sharp display is weird and is active high)
+            w.spol().clear_bit();
+            w
+        })
     });
-    spi1.spi_gcr.write(|w| {
-        // Transmit pause Enable - ignore RXFIFO being full
-        w.tp_en().normal();
-        // Master/Controller mode
-        w.mode().master();
-        // Enable
-        w.en().enable();
-        w
+    mmio::write_reg(&spi1.spi_gcr, |r| {
+        r.write(|w| {
+            // Transmit pause Enable - ignore RXFIFO being full
+            w.tp_en().normal();
+            // Master/Controller mode
+            w.mode().master();
+            // Enable
+            w.en().enable();
+            w
+        })
     });
-    spi1.spi_fcr.modify(|_r, w| {
-        // TX FIFO DMA Request Enable
-        w.tf_drq_en().enable();
-        w
+    mmio::modify_reg(&spi1.spi_fcr, |r| {
+        r.modify(|_r, w| {
+            // TX FIFO DMA Request Enable
+            w.tf_drq_en().enable();
+            w
+        })
     });
 
     Spim1 { _x: () }
@@ -165,24 +176,32 @@ impl SpiSenderServer {
                         debug_assert!(chunk.len() <= Descriptor::MAX_LEN as usize);
                         let len = chunk.len() as u32;
 
-                        spi.spi_bcc.modify(|_r, w| {
-                            // "Single Mode Transmit Counter" - the number of bytes to send
-                            w.stc().variant(len);
-                            w
+                        mmio::modify_reg(&spi.spi_bcc, |r| {
+                            r.modify(|_r, w| {
+                                // "Single Mode Transmit Counter" - the number of bytes to send
+                                w.stc().variant(len);
+                                w
+                            })
                         });
-                        spi.spi_mbc.modify(|_r, w| {
-                            // Master Burst Counter
-                            w.mbc().variant(len);
-                            w
+                        mmio::modify_reg(&spi.spi_mbc, |r| {
+                            r.modify(|_r, w| {
+                                // Master Burst Counter
+                                w.mbc().variant(len);
+                                w
+                            })
                         });
-                        spi.spi_mtc.modify(|_r, w| {
-                            w.mwtc().variant(len);
-                            w
+                        mmio::modify_reg(&spi.spi_mtc, |r| {
+                            r.modify(|_r, w| {
+                                w.mwtc().variant(len);
+                                w
+                            })
                         });
                         // Start transfer
-                        spi.spi_tcr.modify(|_r, w| {
-                            w.xch().initiate_exchange();
-                            w
+                        mmio::modify_reg(&spi.spi_tcr, |r| {
+                            r.modify(|_r, w| {
+                                w.xch().initiate_exchange();
+                                w
+                            })
                         });
 
                         // TODO(eliza): we could use the `Descriptor::link`
@@ -194,10 +213,13 @@ impl SpiSenderServer {
                             .source_slice(chunk)
                             .expect("slice should be a valid DMA source")
                             .build();
+                        let descriptor = DmaBox::new(descriptor).await;
+                        descriptor.sync_for_device();
+                        dma::sync_for_device(chunk);
 
                         // start the DMA transfer.
                         unsafe {
-                            chan.transfer(NonNull::from(&descriptor)).await;
+                            chan.transfer(descriptor.as_non_null()).await;
                         }
                     }
 
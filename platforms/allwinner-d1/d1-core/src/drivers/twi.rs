@@ -19,6 +19,11 @@
 //! [`I2c0::mq_pro`] and [`I2c0::lichee_rv_dock`], are provided to
 //! configure the appropriate pins for each board.
 //!
+//! The TWI may instead be used as an I²C _target_ device, responding to
+//! operations performed by another bus controller, by registering it with
+//! [`I2c0::register_target`] rather than [`I2c0::register`]. In that case, it
+//! implements the [`I2cTargetService`] trait instead.
+//!
 //! ## Implementation Notes
 //!
 //! The TWI hardware can be used in one of two modes: "TWI engine" mode, where
@@ -46,6 +51,13 @@
 //! writes to this shared state, but this doesn't actually matter, because the
 //! D1 is an inherently single-core CPU.
 //!
+//! In target mode, the ISR handles individual data bytes on its own, but
+//! wakes the driver task at the end of each write from the controller, and at
+//! the start and end of each read. The ISR leaves the interrupt flag set when
+//! it does so, which causes the TWI to hold the bus clock low until the driver
+//! task has handed the data off to (or received data from) the task using the
+//! [`Target`], and re-enabled interrupts.
+//!
 //! I believe that the I²C controller used in the D1 is from the
 //! Marvell MV64xxx family, although I'm not sure which one in particular. Linux
 //! has a driver for this device, which can be found [here][linux-driver].
@@ -53,21 +65,27 @@
 //! [linux-driver]: https://github.com/torvalds/linux/blob/995b406c7e972fab181a4bb57f3b95e59b8e5bf3/drivers/i2c/busses/i2c-mv64xxx.c
 use core::{
     cell::UnsafeCell,
-    future,
+    future, mem,
     ops::{Deref, DerefMut},
     task::{Poll, Waker},
 };
 
-use crate::ccu::Ccu;
+use crate::{ccu::Ccu, mmio};
 use d1_pac::{twi, Interrupt, GPIO, TWI0, TWI1, TWI2, TWI3};
 use kernel::{
-    comms::kchannel::KConsumer,
+    comms::{
+        kchannel::{KConsumer, KProducer},
+        oneshot::Reusable,
+    },
     embedded_hal_async::i2c::{ErrorKind, NoAcknowledgeSource},
     mnemos_alloc::containers::FixedVec,
     registry,
-    services::i2c::{
-        messages::{OpKind, Transfer},
-        Addr, I2cService, Transaction,
+    services::{
+        i2c::{
+            messages::{OpKind, Transfer},
+            Addr, I2cService, Transaction,
+        },
+        i2c_target::{I2cTargetService, ReadRequest, Target, TargetRequest},
     },
     Kernel,
 };
@@ -120,6 +138,15 @@ enum TwiOp {
         amt: usize,
         end: bool,
     },
+    /// Target mode: receiving bytes written by the controller into `buf`.
+    TargetRx {
+        buf: FixedVec<u8>,
+    },
+    /// Target mode: sending the bytes in `buf` to the controller.
+    TargetTx {
+        buf: FixedVec<u8>,
+        pos: usize,
+    },
     None,
 }
 
@@ -140,6 +167,19 @@ enum State {
     WaitForAck(Addr),
     /// Waiting for the target device to send a data byte.
     WaitForData(Addr),
+    /// Target mode: waiting to be addressed by the controller.
+    TargetListen,
+    /// Target mode: receiving data bytes from the controller.
+    TargetRx,
+    /// Target mode: the controller has finished writing to us.
+    TargetRxDone,
+    /// Target mode: the controller has addressed us for a read, and is
+    /// waiting for the driver task to provide data.
+    TargetReadRequested,
+    /// Target mode: sending data bytes to the controller.
+    TargetTx,
+    /// Target mode: the controller has finished reading from us.
+    TargetTxDone,
 }
 
 // === impl I2c0 ===
@@ -156,13 +196,15 @@ impl I2c0 {
     ///   board.
     pub unsafe fn mq_pro(mut twi: TWI0, ccu: &mut Ccu, gpio: &mut GPIO) -> Self {
         // Step 1: Configure GPIO pin mappings.
-        gpio.pg_cfg1.modify(|_r, w| {
-            // on the Mango Pi MQ Pro, the pi header's I2C0 pins are mapped to
-            // TWI0 on PG12 and PG13:
-            // https://mangopi.org/_media/mq-pro-sch-v12.pdf
-            w.pg12_select().twi0_sck();
-            w.pg13_select().twi0_sda();
-            w
+        mmio::modify_reg(&gpio.pg_cfg1, |r| {
+            r.modify(|_r, w| {
+                // on the Mango Pi MQ Pro, the pi header's I2C0 pins are mapped to
+                // TWI0 on PG12 and PG13:
+                // https://mangopi.org/_media/mq-pro-sch-v12.pdf
+                w.pg12_select().twi0_sck();
+                w.pg13_select().twi0_sda();
+                w
+            })
         });
 
         ccu.disable_module(&mut twi);
@@ -187,14 +229,16 @@ impl I2c0 {
     ///   board.
     pub unsafe fn lichee_rv_dock(mut twi: TWI2, ccu: &mut Ccu, gpio: &mut GPIO) -> Self {
         // Step 1: Configure GPIO pin mappings.
-        gpio.pb_cfg0.modify(|_r, w| {
-            // on the Lichee RV Dock, the Pi header's I2C0 corresponds to TWI2, not
-            // TWI0 as on the MQ Pro.
-            // I2C0 SDA is mapped to TWI2 PB1, and I2C0 SCL is mapped to TWI2 PB0:
-            // https://dl.sipeed.com/fileList/LICHEE/D1/Lichee_RV-Dock/2_Schematic/Lichee_RV_DOCK_3516(Schematic).pdf
-            w.pb0_select().twi2_sck();
-            w.pb1_select().twi2_sda();
-            w
+        mmio::modify_reg(&gpio.pb_cfg0, |r| {
+            r.modify(|_r, w| {
+                // on the Lichee RV Dock, the Pi header's I2C0 corresponds to TWI2, not
+                // TWI0 as on the MQ Pro.
+                // I2C0 SDA is mapped to TWI2 PB1, and I2C0 SCL is mapped to TWI2 PB0:
+                // https://dl.sipeed.com/fileList/LICHEE/D1/Lichee_RV-Dock/2_Schematic/Lichee_RV_DOCK_3516(Schematic).pdf
+                w.pb0_select().twi2_sck();
+                w.pb1_select().twi2_sda();
+                w
+            })
         });
 
         ccu.disable_module(&mut twi);
@@ -270,29 +314,33 @@ impl I2c0 {
     /// This assumes the GPIO pin mappings are already configured.
     unsafe fn init(twi: &'static twi::RegisterBlock, int: Interrupt, isr: fn()) -> Self {
         // soft reset bit
-        twi.twi_srst.write(|w| w.soft_rst().set_bit());
-
-        twi.twi_ccr.modify(|_r, w| {
-            // according to the data sheet, setting CLK_M = 11, CLK_N = 1
-            // means 100kHz.
-            // setting CLK_M to 2 instead would get us 400kHz.
-            w.clk_m().variant(2);
-            w.clk_n().variant(1);
-            w
+        mmio::write_reg(&twi.twi_srst, |r| r.write(|w| w.soft_rst().set_bit()));
+
+        mmio::modify_reg(&twi.twi_ccr, |r| {
+            r.modify(|_r, w| {
+                // according to the data sheet, setting CLK_M = 11, CLK_N = 1
+                // means 100kHz.
+                // setting CLK_M to 2 instead would get us 400kHz.
+                w.clk_m().variant(2);
+                w.clk_n().variant(1);
+                w
+            })
         });
 
         // Step 6: Configure TWI_CNTR[BUS_EN] and TWI_CNTR[A_ACK], when using interrupt mode, set
         // TWI_CNTR[INT_EN] to 1, and register the system interrupt. In slave mode, configure TWI_ADDR and
         // TWI_XADDR registers to finish TWI initialization configuration
-        twi.twi_cntr.write(|w| {
-            w.bus_en().respond();
-            w.m_stp().set_bit();
-            w
+        mmio::write_reg(&twi.twi_cntr, |r| {
+            r.write(|w| {
+                w.bus_en().respond();
+                w.m_stp().set_bit();
+                w
+            })
         });
 
         // we only want to be the bus controller, so zero our address
-        twi.twi_addr.write(|w| w.sla().variant(0));
-        twi.twi_xaddr.write(|w| w.slax().variant(0));
+        mmio::write_reg(&twi.twi_addr, |r| r.write(|w| w.sla().variant(0)));
+        mmio::write_reg(&twi.twi_xaddr, |r| r.write(|w| w.slax().variant(0)));
 
         Self {
             twi,
@@ -411,13 +459,180 @@ impl I2c0 {
         // transaction ended!
         tracing::trace!("I2C transaction ended");
     }
+
+    /// Registers this TWI as an [`I2cTargetService`], so that it responds to
+    /// operations performed by another I²C bus controller, rather than
+    /// acting as the bus controller itself.
+    #[tracing::instrument(
+        name = "I2c0::register_target",
+        level = Level::INFO,
+        skip(kernel, self),
+        err(Debug),
+    )]
+    pub async fn register_target(
+        self,
+        kernel: &'static Kernel,
+        queued: usize,
+    ) -> Result<(), registry::RegistrationError> {
+        let rx = kernel
+            .registry()
+            .bind_konly::<I2cTargetService>(queued)
+            .await?
+            .into_request_stream(queued)
+            .await;
+
+        kernel.spawn(self.run_target(rx)).await;
+        tracing::info!("TWI target driver task started");
+
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "TWI target", level = tracing::Level::INFO, skip(self, rx))]
+    async fn run_target(self, rx: registry::listener::RequestStream<I2cTargetService>) {
+        tracing::info!("starting TWI target driver task");
+        let read_rsp = Reusable::new_async().await;
+        loop {
+            let registry::Message { msg, reply } = rx.next_request().await;
+            let listen = msg.body;
+
+            let (target, tx) = Target::new(listen).await;
+            if let Err(error) = reply.reply_konly(msg.reply_with_body(|_| Ok(target))).await {
+                tracing::warn!(?error, "client hung up...");
+                continue;
+            }
+            self.listen(listen.addr, listen.max_write, tx, &read_rsp)
+                .await;
+        }
+    }
+
+    #[tracing::instrument(level = tracing::Level::DEBUG, skip(self, tx, read_rsp))]
+    async fn listen(
+        &self,
+        addr: Addr,
+        max_write: usize,
+        tx: KProducer<TargetRequest>,
+        read_rsp: &Reusable<FixedVec<u8>>,
+    ) {
+        tracing::debug!("listening as I2C target");
+        let mut guard = self.isr.lock(self.twi);
+        self.set_addr(Some(addr));
+
+        // the receive buffer, while a read is in progress.
+        let mut rx_buf = None;
+        guard.data.state = State::TargetListen;
+        guard.data.op = TwiOp::TargetRx {
+            buf: FixedVec::new(max_write).await,
+        };
+        let mut ack = max_write > 0;
+
+        loop {
+            guard.wait_for_target_irq(ack).await;
+            match guard.data.state {
+                State::TargetRxDone => {
+                    let buf = FixedVec::new(max_write).await;
+                    let TwiOp::TargetRx { buf: data } =
+                        mem::replace(&mut guard.data.op, TwiOp::TargetRx { buf })
+                    else {
+                        unreachable!("target RX should have a receive buffer")
+                    };
+                    guard.data.state = State::TargetListen;
+                    ack = max_write > 0;
+
+                    tracing::trace!(len = data.len(), "I2C target write completed");
+                    if tx.enqueue_async(TargetRequest::Write(data)).await.is_err() {
+                        break;
+                    }
+                }
+                State::TargetReadRequested => {
+                    let rsp = read_rsp
+                        .sender()
+                        .await
+                        .expect("sender should not be in use");
+                    if tx
+                        .enqueue_async(TargetRequest::Read(ReadRequest::new(rsp)))
+                        .await
+                        .is_err()
+                    {
+                        break;
+                    }
+                    // if the `ReadRequest` was dropped, just send an empty
+                    // buffer, which will be padded with 0xFF.
+                    let buf = match read_rsp.receive().await {
+                        Ok(buf) => buf,
+                        Err(_) => FixedVec::new(0).await,
+                    };
+                    tracing::trace!(len = buf.len(), "I2C target read started");
+
+                    let prev = mem::replace(&mut guard.data.op, TwiOp::TargetTx { buf, pos: 0 });
+                    if let TwiOp::TargetRx { buf } = prev {
+                        rx_buf = Some(buf);
+                    }
+                    let (byte, more) = guard.data.next_target_byte();
+                    mmio::write_reg(&self.twi.twi_data, |r| r.write(|w| w.data().variant(byte)));
+                    guard.data.state = State::TargetTx;
+                    ack = more;
+                }
+                State::TargetTxDone => {
+                    tracing::trace!("I2C target read completed");
+                    let buf = match rx_buf.take() {
+                        Some(buf) => buf,
+                        None => FixedVec::new(max_write).await,
+                    };
+                    guard.data.op = TwiOp::TargetRx { buf };
+                    guard.data.state = State::TargetListen;
+                    ack = max_write > 0;
+                }
+                state => unreachable!("unexpected target state {state:?}"),
+            }
+        }
+
+        // the `Target` was dropped, so stop responding to its address.
+        tracing::debug!("I2C target dropped");
+        self.set_addr(None);
+        guard.data.state = State::Idle;
+        guard.data.op = TwiOp::None;
+        // release any clock stretching from the last event.
+        mmio::modify_reg(&self.twi.twi_cntr, |r| {
+            r.modify(|_r, w| {
+                w.int_flag().set_bit();
+                w.a_ack().clear_bit();
+                w
+            })
+        });
+    }
+
+    /// Sets the target address that this TWI responds to, or stops responding
+    /// to any address if `addr` is `None`.
+    fn set_addr(&self, addr: Option<Addr>) {
+        match addr {
+            Some(Addr::SevenBit(addr)) => {
+                mmio::write_reg(&self.twi.twi_addr, |r| {
+                    r.write(|w| w.sla().variant(addr & 0x7f))
+                });
+                mmio::write_reg(&self.twi.twi_xaddr, |r| r.write(|w| w.slax().variant(0)));
+            }
+            Some(Addr::TenBit(addr)) => {
+                // the high bits of a 10-bit address are sent as `0b11110XX`,
+                // where `XX` are bits 9 and 8 of the address.
+                let high = 0b111_1000 | ((addr >> 8) & 0b11) as u8;
+                mmio::write_reg(&self.twi.twi_addr, |r| r.write(|w| w.sla().variant(high)));
+                mmio::write_reg(&self.twi.twi_xaddr, |r| {
+                    r.write(|w| w.slax().variant(addr as u8))
+                });
+            }
+            None => {
+                mmio::write_reg(&self.twi.twi_addr, |r| r.write(|w| w.sla().variant(0)));
+                mmio::write_reg(&self.twi.twi_xaddr, |r| r.write(|w| w.slax().variant(0)));
+            }
+        }
+    }
 }
 
 impl IsrData {
     #[must_use]
     fn lock<'a>(&'a self, twi: &'a twi::RegisterBlock) -> TwiDataGuard<'a> {
         // disable TWI interrupts while holding the guard.
-        twi.twi_cntr.modify(|_r, w| w.int_en().low());
+        mmio::modify_reg(&twi.twi_cntr, |r| r.modify(|_r, w| w.int_en().low()));
         let data = unsafe { &mut *(self.data.get()) };
         TwiDataGuard { data, twi }
     }
@@ -425,7 +640,7 @@ impl IsrData {
 
 impl Drop for TwiDataGuard<'_> {
     fn drop(&mut self) {
-        self.twi.twi_cntr.modify(|_r, w| w.int_en().high());
+        mmio::modify_reg(&self.twi.twi_cntr, |r| r.modify(|_r, w| w.int_en().high()));
     }
 }
 
@@ -434,22 +649,67 @@ impl TwiDataGuard<'_> {
         let mut waiting = false;
         future::poll_fn(|cx| {
             if waiting {
-                self.twi.twi_cntr.modify(|_r, w| w.int_en().low());
+                mmio::modify_reg(&self.twi.twi_cntr, |r| r.modify(|_r, w| w.int_en().low()));
                 return Poll::Ready(());
             }
 
             self.data.waker = Some(cx.waker().clone());
             waiting = true;
-            self.twi.twi_cntr.modify(|_r, w| {
-                // we have to set M_STA and A_ACK as part of the same write that
-                // sets the INT_EN bit, or else we will potentially do something
-                // weird if we do two separate TWI_CNTR writes. setting all of
-                // these now, atomically, avoids weird cases where we send a
-                // START for some random address, as far as i can tell.
-                w.m_sta().set_bit();
-                w.int_en().high();
-                w.bus_en().respond();
-                w
+            mmio::modify_reg(&self.twi.twi_cntr, |r| {
+                r.modify(|_r, w| {
+                    // we have to set M_STA and A_ACK as part of the same write that
+                    // sets the INT_EN bit, or else we will potentially do something
+                    // weird if we do two separate TWI_CNTR writes. setting all of
+                    // these now, atomically, avoids weird cases where we send a
+                    // START for some random address, as far as i can tell.
+                    w.m_sta().set_bit();
+                    w.int_en().high();
+                    w.bus_en().respond();
+                    w
+                })
+            });
+
+            Poll::Pending
+        })
+        .await;
+    }
+
+    /// Waits for the ISR to wake the driver task in target mode.
+    ///
+    /// If `ack` is `true`, the next data byte received or sent will be
+    /// acknowledged (when receiving), or is not the last byte (when sending).
+    async fn wait_for_target_irq(&mut self, ack: bool) {
+        let mut waiting = false;
+        future::poll_fn(|cx| {
+            if waiting {
+                mmio::modify_reg(&self.twi.twi_cntr, |r| {
+                    r.modify(|_r, w| {
+                        w.int_en().low();
+                        // don't clear the interrupt flag, so that the bus is held
+                        // until we're ready for the next event.
+                        w.int_flag().clear_bit();
+                        w
+                    })
+                });
+                return Poll::Ready(());
+            }
+
+            self.data.waker = Some(cx.waker().clone());
+            waiting = true;
+            mmio::modify_reg(&self.twi.twi_cntr, |r| {
+                r.modify(|_r, w| {
+                    if ack {
+                        w.a_ack().set_bit();
+                    } else {
+                        w.a_ack().clear_bit();
+                    }
+                    w.int_en().high();
+                    w.bus_en().respond();
+                    // clearing the interrupt flag releases the bus clock, if it
+                    // was held for the last event.
+                    w.int_flag().set_bit();
+                    w
+                })
             });
 
             Poll::Pending
@@ -475,7 +735,7 @@ impl DerefMut for TwiDataGuard<'_> {
 impl TwiData {
     fn advance_isr(&mut self, twi: &twi::RegisterBlock, num: u8) {
         let status = {
-            let byte = twi.twi_stat.read().sta().bits();
+            let byte = mmio::read_reg(&twi.twi_stat, |r| r.read().sta().bits());
             match Status::try_from(byte) {
                 Ok(status) => status,
                 Err(error) => {
@@ -484,197 +744,339 @@ impl TwiData {
                 }
             }
         };
+        if self.state.is_target() {
+            self.advance_target_isr(twi, num, status);
+            return;
+        }
+
         let mut needs_wake = false;
         tracing::trace!(?status, state = ?self.state, twi = num, "TWI{num} interrupt");
-        twi.twi_cntr.modify(|_cntr_r, cntr_w| {
-            self.state = match (self.state, status)  {
-                (State::Idle, _) => {
-                    cntr_w.m_stp().set_bit();
-                    State::Idle
-                }
-                (State::WaitForStart(addr), Status::StartTransmitted) |
-                (State::WaitForRestart(addr), Status::RepeatedStartTransmitted) => {
-                    let bits = {
-                        // lowest bit is 1 if reading, 0 if writing.
-                        let dir = match self.op {
-                            TwiOp::Read { .. } => 0b1,
-                            _ => 0b0,
-                        };
-                        let addr = match addr {
-                            Addr::SevenBit(addr) => (addr & 0x7f) << 1,
-                            Addr::TenBit(addr) => 0xf0 | ((addr & 0x300) >> 7) as u8,
+        mmio::modify_reg(&twi.twi_cntr, |r| {
+            r.modify(|_cntr_r, cntr_w| {
+                self.state = match (self.state, status)  {
+                    (State::Idle, _) => {
+                        cntr_w.m_stp().set_bit();
+                        State::Idle
+                    }
+                    (State::WaitForStart(addr), Status::StartTransmitted) |
+                    (State::WaitForRestart(addr), Status::RepeatedStartTransmitted) => {
+                        let bits = {
+                            // lowest bit is 1 if reading, 0 if writing.
+                            let dir = match self.op {
+                                TwiOp::Read { .. } => 0b1,
+                                _ => 0b0,
+                            };
+                            let addr = match addr {
+                                Addr::SevenBit(addr) => (addr & 0x7f) << 1,
+                                Addr::TenBit(addr) => 0xf0 | ((addr & 0x300) >> 7) as u8,
+                            };
+                            addr | dir
                         };
-                        addr | dir
-                    };
-                    // send the address
-                    twi.twi_data.write(|w| w.data().variant(bits));
-                    State::WaitForAddr1Ack(addr)
-                }
-                // Write address byte 2.
-                (State::WaitForAddr1Ack(Addr::TenBit(addr)), Status::Addr1ReadAcked) |
-                (State::WaitForAddr1Ack(Addr::TenBit(addr)), Status::Addr1WriteAcked) => {
-                    twi.twi_data.write(|w| w.data().variant(addr as u8));
-                    State::WaitForAddr2Ack(Addr::TenBit(addr))
-                }
-                // Last address byte was ACKed (write), so we can start sending data.
-                (State::WaitForAddr1Ack(addr @ Addr::SevenBit(_)), Status::Addr1WriteAcked) |
-                (State::WaitForAddr2Ack(addr @ Addr::TenBit(_)), Status::Addr2WriteAcked) =>
-                {
-                    match &mut self.op {
-                        TwiOp::Write { buf, ref mut pos, .. } => {
-                            // send the first byte of data
-                            let byte = buf.as_slice()[0];
-                            tracing::trace!(twi = num, data = ?format_args!("{byte:#x}"), "TWI{num} write data");
-                            twi.twi_data.write(|w| w.data().variant(byte));
-                            *pos += 1;
-                            State::WaitForAck(addr)
-                        },
-                        TwiOp::Read { .. } => unreachable!(
-                            "if we sent an address with a write bit, we should be in a write state (was Read)"
-                        ),
-                        TwiOp::None => unreachable!(
-                            "if we sent an address with a write bit, we should be in a write state (was None)"
-                        ),
+                        // send the address
+                        mmio::write_reg(&twi.twi_data, |r| r.write(|w| w.data().variant(bits)));
+                        State::WaitForAddr1Ack(addr)
+                    }
+                    // Write address byte 2.
+                    (State::WaitForAddr1Ack(Addr::TenBit(addr)), Status::Addr1ReadAcked) |
+                    (State::WaitForAddr1Ack(Addr::TenBit(addr)), Status::Addr1WriteAcked) => {
+                        mmio::write_reg(&twi.twi_data, |r| r.write(|w| w.data().variant(addr as u8)));
+                        State::WaitForAddr2Ack(Addr::TenBit(addr))
+                    }
+                    // Last address byte was ACKed (write), so we can start sending data.
+                    (State::WaitForAddr1Ack(addr @ Addr::SevenBit(_)), Status::Addr1WriteAcked) |
+                    (State::WaitForAddr2Ack(addr @ Addr::TenBit(_)), Status::Addr2WriteAcked) =>
+                    {
+                        match &mut self.op {
+                            TwiOp::Write { buf, ref mut pos, .. } => {
+                                // send the first byte of data
+                                let byte = buf.as_slice()[0];
+                                tracing::trace!(twi = num, data = ?format_args!("{byte:#x}"), "TWI{num} write data");
+                                mmio::write_reg(&twi.twi_data, |r| r.write(|w| w.data().variant(byte)));
+                                *pos += 1;
+                                State::WaitForAck(addr)
+                            },
+                            TwiOp::Read { .. } => unreachable!(
+                                "if we sent an address with a write bit, we should be in a write state (was Read)"
+                            ),
+                            TwiOp::None => unreachable!(
+                                "if we sent an address with a write bit, we should be in a write state (was None)"
+                            ),
+                        }
                     }
-                }
 
-                // Last address byte was ACKed (read), so we can start reading data.
-                (State::WaitForAddr1Ack(addr @ Addr::SevenBit(_)), Status::Addr1ReadAcked) |
-                (State::WaitForAddr2Ack(addr @ Addr::TenBit(_)), Status::Addr2ReadAcked) =>
-                // TODO(eliza): handle 10 bit addr...
-                {
-                    match self.op {
-                        TwiOp::Read { len, .. } => {
-                            if len == 1 {
-                                // if we are reading a single byte, clear the A_ACK
-                                // flag so that we don't ACK the byte.
-                                cntr_w.a_ack().clear_bit();
-                            } else {
-                                // otherwise, we'll want to ACK all but the
-                                // final byte.
-                                cntr_w.a_ack().set_bit();
+                    // Last address byte was ACKed (read), so we can start reading data.
+                    (State::WaitForAddr1Ack(addr @ Addr::SevenBit(_)), Status::Addr1ReadAcked) |
+                    (State::WaitForAddr2Ack(addr @ Addr::TenBit(_)), Status::Addr2ReadAcked) =>
+                    // TODO(eliza): handle 10 bit addr...
+                    {
+                        match self.op {
+                            TwiOp::Read { len, .. } => {
+                                if len == 1 {
+                                    // if we are reading a single byte, clear the A_ACK
+                                    // flag so that we don't ACK the byte.
+                                    cntr_w.a_ack().clear_bit();
+                                } else {
+                                    // otherwise, we'll want to ACK all but the
+                                    // final byte.
+                                    cntr_w.a_ack().set_bit();
+                                }
+                                State::WaitForData(addr)
                             }
-                            State::WaitForData(addr)
+                            TwiOp::None => unreachable!(
+                                "if we sent an address with a read bit, we should be in a read state (was None)"
+                            ),
+                            TwiOp::Write { .. } => unreachable!(
+                                "if we sent an address with a read bit, we should be in a read state (was Write)"
+                            ),
                         }
-                        TwiOp::None => unreachable!(
-                            "if we sent an address with a read bit, we should be in a read state (was None)"
-                        ),
-                        TwiOp::Write { .. } => unreachable!(
-                            "if we sent an address with a read bit, we should be in a read state (was Write)"
-                        ),
                     }
-                }
-                (State::WaitForData(addr), Status::RxDataAcked) | (State::WaitForData(addr), Status::RxDataNacked) => {
-                    match &mut self.op {
-                        &mut TwiOp::Read { ref mut buf, len, amt: ref mut read, end } => {
-                            let data = twi.twi_data.read().data().bits();
-                            buf.try_push(data).expect("read buf should have space for data");
-                            *read += 1;
-                            let remaining = len - *read;
-                            tracing::trace!(
-                                twi = num,
-                                data = ?format_args!("{data:#x}"),
-                                end,
-                                remaining,
-                                "TWI{num} read data",
-                            );
+                    (State::WaitForData(addr), Status::RxDataAcked) | (State::WaitForData(addr), Status::RxDataNacked) => {
+                        match &mut self.op {
+                            &mut TwiOp::Read { ref mut buf, len, amt: ref mut read, end } => {
+                                let data = mmio::read_reg(&twi.twi_data, |r| r.read().data().bits());
+                                buf.try_push(data).expect("read buf should have space for data");
+                                *read += 1;
+                                let remaining = len - *read;
+                                tracing::trace!(
+                                    twi = num,
+                                    data = ?format_args!("{data:#x}"),
+                                    end,
+                                    remaining,
+                                    "TWI{num} read data",
+                                );
 
-                            if remaining <= 1 {
-                                // We have one byte left to read, so stop ACKing.
-                                cntr_w.a_ack().clear_bit();
-                            } else {
-                                // We have more than one byte remaining, so
-                                // continue ACKing.
-                                cntr_w.a_ack().set_bit();
-                            }
+                                if remaining <= 1 {
+                                    // We have one byte left to read, so stop ACKing.
+                                    cntr_w.a_ack().clear_bit();
+                                } else {
+                                    // We have more than one byte remaining, so
+                                    // continue ACKing.
+                                    cntr_w.a_ack().set_bit();
+                                }
 
-                            if remaining > 0 {
-                                State::WaitForData(addr)
-                            } else {
-                                needs_wake = true;
-                                // if this is the last operation in the
-                                // transaction, send a STOP.
-                                if end {
-                                    tracing::trace!(twi = num, "TWI{num} send STOP");
-                                    cntr_w.m_stp().set_bit();
-                                    State::Idle
+                                if remaining > 0 {
+                                    State::WaitForData(addr)
                                 } else {
-                                    // otherwise, send a repeated START for the
-                                    // next operation.
-                                    tracing::trace!(twi = num, "TWI{num} send repeated START");
-                                    cntr_w.m_sta().set_bit();
-                                    State::WaitForRestart(addr)
+                                    needs_wake = true;
+                                    // if this is the last operation in the
+                                    // transaction, send a STOP.
+                                    if end {
+                                        tracing::trace!(twi = num, "TWI{num} send STOP");
+                                        cntr_w.m_stp().set_bit();
+                                        State::Idle
+                                    } else {
+                                        // otherwise, send a repeated START for the
+                                        // next operation.
+                                        tracing::trace!(twi = num, "TWI{num} send repeated START");
+                                        cntr_w.m_sta().set_bit();
+                                        State::WaitForRestart(addr)
+                                    }
                                 }
                             }
+                            _ => unreachable!(),
                         }
-                        _ => unreachable!(),
                     }
-                }
-                (State::WaitForAck(addr), Status::TxDataAcked) => {
-                    match &mut self.op {
-                        &mut TwiOp::Write { ref mut buf, ref mut pos, len, end } => {
-                            if *pos == len {
-                                needs_wake = true;
-                                // Send a repeated START for the read portion of
-                                // the transaction.
-                                if end {
-                                    tracing::trace!(twi = num, "TWI{num} send STOP");
-                                    cntr_w.m_stp().set_bit();
-                                    State::Idle
+                    (State::WaitForAck(addr), Status::TxDataAcked) => {
+                        match &mut self.op {
+                            &mut TwiOp::Write { ref mut buf, ref mut pos, len, end } => {
+                                if *pos == len {
+                                    needs_wake = true;
+                                    // Send a repeated START for the read portion of
+                                    // the transaction.
+                                    if end {
+                                        tracing::trace!(twi = num, "TWI{num} send STOP");
+                                        cntr_w.m_stp().set_bit();
+                                        State::Idle
+                                    } else {
+                                        // otherwise, send a repeated START for the
+                                        // next operation.
+                                        cntr_w.m_sta().set_bit();
+                                        tracing::trace!(twi = num, "TWI{num} send repeated START");
+                                        State::WaitForRestart(addr)
+                                    }
                                 } else {
-                                    // otherwise, send a repeated START for the
-                                    // next operation.
-                                    cntr_w.m_sta().set_bit();
-                                    tracing::trace!(twi = num, "TWI{num} send repeated START");
-                                    State::WaitForRestart(addr)
-                                }
-                            } else {
-                                // Send the next byte of data
-                                let byte = buf.as_slice()[*pos];
-                                tracing::trace!(
-                                    twi = num,
-                                    remaining = len - *pos,
-                                    data = ?format_args!("{byte:#x}"),
-                                    "TWI{num} write data"
-                                );
+                                    // Send the next byte of data
+                                    let byte = buf.as_slice()[*pos];
+                                    tracing::trace!(
+                                        twi = num,
+                                        remaining = len - *pos,
+                                        data = ?format_args!("{byte:#x}"),
+                                        "TWI{num} write data"
+                                    );
 
-                                twi.twi_data.write(|w| w.data().variant(byte));
+                                    mmio::write_reg(&twi.twi_data, |r| r.write(|w| w.data().variant(byte)));
 
-                                *pos += 1;
-                                State::WaitForAck(addr)
+                                    *pos += 1;
+                                    State::WaitForAck(addr)
+                                }
                             }
+                            _ => unimplemented!(),
                         }
-                        _ => unimplemented!(),
+                    }
+                    (_, status) => {
+                        let error: ErrorKind = status.into_error();
+                        tracing::warn!(?error, ?status, state = ?self.state, twi = num, "TWI{num} error");
+                        self.err = Some(error);
+                        cntr_w.m_stp().variant(true);
+                        needs_wake = true;
+                        State::Idle
+                    }
+                };
+
+                if needs_wake {
+                    if let Some(waker) = self.waker.take() {
+                        waker.wake();
+                        // If we are waking the driver task, we need to disable interrupts
+                        // until the driver can prepare the next phase of the transaction.
+                        cntr_w.int_en().low();
                     }
                 }
-                (_, status) => {
-                    let error: ErrorKind = status.into_error();
-                    tracing::warn!(?error, ?status, state = ?self.state, twi = num, "TWI{num} error");
-                    self.err = Some(error);
-                    cntr_w.m_stp().variant(true);
-                    needs_wake = true;
-                    State::Idle
-                }
-            };
 
-            if needs_wake {
-                if let Some(waker) = self.waker.take() {
-                    waker.wake();
-                    // If we are waking the driver task, we need to disable interrupts
-                    // until the driver can prepare the next phase of the transaction.
+                // Writing back to the TWI_CNTR register *with the INT_FLAG bit
+                // high* clears the interrupt. the D1 user manual never explains
+                // this, but it's the same behavior as the DMAC interrupts, and the
+                // Linux driver for the Marvell family mv64xxx has a special flag
+                // which changes it to write back to TWI_CNTR with INT_FLAG set on
+                // Allwinner hardware.
+                cntr_w.int_flag().set_bit();
+                cntr_w
+            })
+        });
+    }
+
+    fn advance_target_isr(&mut self, twi: &twi::RegisterBlock, num: u8, status: Status) {
+        let mut needs_wake = false;
+        tracing::trace!(?status, state = ?self.state, twi = num, "TWI{num} target interrupt");
+        mmio::modify_reg(&twi.twi_cntr, |r| {
+            r.modify(|_cntr_r, cntr_w| {
+                self.state = match (self.state, status) {
+                    // Addressed for a write by the controller.
+                    (State::TargetListen, Status::TargetAddrWriteAcked) => State::TargetRx,
+                    (
+                        State::TargetRx,
+                        status @ (Status::TargetRxDataAcked | Status::TargetRxDataNacked),
+                    ) => {
+                        let data = mmio::read_reg(&twi.twi_data, |r| r.read().data().bits());
+                        let remaining = match &mut self.op {
+                            TwiOp::TargetRx { buf } => {
+                                if buf.try_push(data).is_err() {
+                                    tracing::debug!(twi = num, "TWI{num} target receive buffer full");
+                                }
+                                buf.capacity() - buf.len()
+                            }
+                            _ => unreachable!("target should be receiving into a buffer"),
+                        };
+                        tracing::trace!(twi = num, data = ?format_args!("{data:#x}"), remaining, "TWI{num} target read data");
+
+                        if status == Status::TargetRxDataNacked {
+                            // We NACKed this byte because the buffer is full, so
+                            // the write is over. The TWI won't report a STOP now
+                            // that it's no longer addressed.
+                            needs_wake = true;
+                            State::TargetRxDone
+                        } else {
+                            // If the next byte fills the buffer, NACK it so the
+                            // controller knows to stop.
+                            if remaining <= 1 {
+                                cntr_w.a_ack().clear_bit();
+                            } else {
+                                cntr_w.a_ack().set_bit();
+                            }
+                            State::TargetRx
+                        }
+                    }
+                    (State::TargetRx, Status::TargetStopOrRepeatedStart) => {
+                        needs_wake = true;
+                        State::TargetRxDone
+                    }
+                    // Addressed for a read by the controller; the driver task must
+                    // provide the data to send.
+                    (State::TargetListen, Status::TargetAddrReadAcked) => {
+                        needs_wake = true;
+                        State::TargetReadRequested
+                    }
+                    (State::TargetTx, Status::TargetTxDataAcked) => {
+                        let (byte, more) = self.next_target_byte();
+                        tracing::trace!(twi = num, data = ?format_args!("{byte:#x}"), more, "TWI{num} target write data");
+                        mmio::write_reg(&twi.twi_data, |r| r.write(|w| w.data().variant(byte)));
+                        if more {
+                            cntr_w.a_ack().set_bit();
+                        } else {
+                            // This is the last byte we have to send.
+                            cntr_w.a_ack().clear_bit();
+                        }
+                        State::TargetTx
+                    }
+                    (State::TargetTx, Status::TargetTxDataNacked)
+                    | (State::TargetTx, Status::TargetTxLastDataAcked) => {
+                        needs_wake = true;
+                        State::TargetTxDone
+                    }
+                    (state, status) => {
+                        // Something unexpected happened, such as a bus error. Go
+                        // back to waiting to be addressed.
+                        tracing::warn!(?status, ?state, twi = num, "TWI{num} unexpected target status");
+                        cntr_w.a_ack().set_bit();
+                        match state {
+                            State::TargetRx => {
+                                needs_wake = true;
+                                State::TargetRxDone
+                            }
+                            State::TargetTx => {
+                                needs_wake = true;
+                                State::TargetTxDone
+                            }
+                            state => state,
+                        }
+                    }
+                };
+
+                if needs_wake {
+                    if let Some(waker) = self.waker.take() {
+                        waker.wake();
+                    }
+                    // Disable interrupts *without* clearing the interrupt flag, so
+                    // that the TWI holds the bus clock low until the driver task
+                    // has handled this event.
                     cntr_w.int_en().low();
+                    cntr_w.int_flag().clear_bit();
+                } else {
+                    // See the comment in `advance_isr` about clearing the interrupt.
+                    cntr_w.int_flag().set_bit();
                 }
+                cntr_w
+            })
+        });
+    }
+
+    /// Returns the next byte to send to the controller in target mode, and
+    /// whether there are more bytes to send after it.
+    ///
+    /// If we've run out of data to send, `0xFF` is sent, as though the bus
+    /// were idle.
+    fn next_target_byte(&mut self) -> (u8, bool) {
+        match &mut self.op {
+            TwiOp::TargetTx { buf, pos } => {
+                let byte = buf.as_slice().get(*pos).copied().unwrap_or(0xFF);
+                *pos += 1;
+                (byte, *pos < buf.len())
             }
+            _ => (0xFF, false),
+        }
+    }
+}
 
-            // Writing back to the TWI_CNTR register *with the INT_FLAG bit
-            // high* clears the interrupt. the D1 user manual never explains
-            // this, but it's the same behavior as the DMAC interrupts, and the
-            // Linux driver for the Marvell family mv64xxx has a special flag
-            // which changes it to write back to TWI_CNTR with INT_FLAG set on
-            // Allwinner hardware.
-            cntr_w.int_flag().set_bit();
-            cntr_w
-        });
+impl State {
+    fn is_target(&self) -> bool {
+        matches!(
+            self,
+            Self::TargetListen
+                | Self::TargetRx
+                | Self::TargetRxDone
+                | Self::TargetReadRequested
+                | Self::TargetTx
+                | Self::TargetTxDone
+        )
     }
 }
 
@@ -2,7 +2,7 @@
 #![allow(clippy::needless_pass_by_ref_mut)]
 
 use core::{
-    ptr::{null_mut, NonNull},
+    ptr::null_mut,
     sync::atomic::{AtomicPtr, Ordering},
 };
 
@@ -10,7 +10,10 @@ use d1_pac::{GPIO, UART0};
 use kernel::{
     comms::bbq::{new_bidi_channel, BidiHandle, Consumer, GrantW, SpscProducer},
     maitake::sync::WaitCell,
-    mnemos_alloc::containers::Box,
+    mnemos_alloc::{
+        containers::Box,
+        dma::{self, DmaBox},
+    },
     registry,
     services::simple_serial::{Request, Response, SimpleSerialError, SimpleSerialService},
     Kernel,
@@ -20,9 +23,14 @@ use tracing::Level;
 use crate::{
     ccu::Ccu,
     dmac::{
-        descriptor::{BlockSize, DataWidth, Descriptor, DestDrqType},
+        descriptor::{
+            errors::InvalidOperand, BlockSize, DataWidth, Descriptor, DescriptorBuilder,
+            DestDrqType,
+        },
         ChannelMode, Dmac,
     },
+    drivers::gpio::{Gpio, Pin, Trigger},
+    mmio,
 };
 
 #[allow(dead_code)]
@@ -46,17 +54,59 @@ impl core::fmt::Write for GrantWriter {
     }
 }
 
-static TX_DONE: WaitCell = WaitCell::new();
 static UART_RX: AtomicPtr<SpscProducer> = AtomicPtr::new(null_mut());
 
+/// RX flow control state, if flow control is enabled.
+static RX_FLOW: AtomicPtr<RxFlow> = AtomicPtr::new(null_mut());
+
+/// The length of the UART's TX and RX FIFOs.
+const FIFO_LEN: usize = 64;
+
+/// With flow control enabled, RTS is deasserted once the RX buffer no longer
+/// has room for this many bytes: a full RX FIFO, plus whatever the other end
+/// sends before it notices.
+const RX_HEADROOM: usize = 2 * FIFO_LEN;
+
+/// With flow control enabled, RTS is asserted again once the RX buffer has
+/// room for this many bytes (or half of its capacity, if that's smaller).
+const RX_RESUME: usize = 8 * FIFO_LEN;
+
 pub struct D1Uart {
     _x: (),
 }
 
+/// RTS/CTS flow control for the kernel UART.
+///
+/// UART0's RTS and CTS signals aren't available on any of the D1's pins, so
+/// rather than using the UART's automatic flow control, the driver drives RTS
+/// and waits on CTS using GPIOs. Both are active low.
+///
+/// The other end may send up to a FIFO's worth of data after RTS is
+/// deasserted, and the driver may send up to two FIFOs' worth after CTS is
+/// deasserted.
+#[derive(Debug, Clone, Copy)]
+pub struct FlowControl {
+    pub gpio: Gpio,
+    /// An output, which is asserted while the D1 is ready to receive data.
+    pub rts: Pin,
+    /// An input, which the other end asserts while it is ready to receive
+    /// data.
+    pub cts: Pin,
+}
+
+struct RxFlow {
+    gpio: Gpio,
+    rts: Pin,
+    /// Woken when reception is paused.
+    paused: WaitCell,
+}
+
 #[derive(Debug)]
 pub enum RegistrationError {
     Registry(registry::RegistrationError),
     NoDmaChannels,
+    /// The UART's TX register is not a valid DMA destination.
+    InvalidDmaRegister(InvalidOperand),
 }
 
 #[derive(Debug)]
@@ -64,16 +114,16 @@ pub struct D1UartSettings {
     pub capacity_in: usize,
     pub capacity_out: usize,
     pub request_capacity: usize,
+    /// RTS/CTS flow control. If `None`, flow control is disabled, and data
+    /// received while the RX buffer is full is discarded.
+    pub flow_control: Option<FlowControl>,
 }
 
 impl D1Uart {
-    pub fn tx_done_waker() -> &'static WaitCell {
-        &TX_DONE
-    }
-
     pub fn handle_uart0_int() {
         let uart0 = unsafe { &*UART0::PTR };
         let prod = UART_RX.load(Ordering::Acquire);
+        let flow = unsafe { RX_FLOW.load(Ordering::Acquire).as_ref() };
         let mut handled_all = false;
 
         if !prod.is_null() {
@@ -84,7 +134,7 @@ impl D1Uart {
                 // For each byte in the grant...
                 for (used, b) in wgr.iter_mut().enumerate() {
                     // Check if there is NOT a data byte available...
-                    if !uart0.usr.read().rfne().bit_is_set() {
+                    if !mmio::read_reg(&uart0.usr, |r| r.read().rfne().bit_is_set()) {
                         // If not, commit the grant (with the number of used bytes),
                         // and mark that we have fully drained the FIFO.
                         wgr.commit(used);
@@ -95,7 +145,7 @@ impl D1Uart {
                     //
                     // Reading this register has the side effect of clearing the byte
                     // from the hardware fifo.
-                    *b = uart0.rbr().read().rbr().bits();
+                    *b = mmio::read_reg(uart0.rbr(), |r| r.read().rbr().bits());
                 }
 
                 // If we made it here - we've completely filled the grant.
@@ -103,6 +153,24 @@ impl D1Uart {
                 let len = wgr.len();
                 wgr.commit(len);
             }
+
+            if let Some(flow) = flow {
+                if !handled_all {
+                    // The buffer is full. Leave the rest of the data in the
+                    // FIFO, and stop taking RX interrupts until there's room
+                    // for it.
+                    mmio::modify_reg(uart0.ier(), |r| r.modify(|_r, w| w.erbfi().clear_bit()));
+                    flow.pause();
+                    return;
+                }
+
+                // Ask the other end to stop sending while there's still room
+                // for the data it sends before it notices. The grant is
+                // dropped without committing anything.
+                if prod.send_grant_exact_sync(RX_HEADROOM).is_none() {
+                    flow.pause();
+                }
+            }
         }
 
         // If we didn't hit the "empty" case while draining, that means one of the following:
@@ -114,32 +182,42 @@ impl D1Uart {
         // is cleared, which won't happen until we discard at least enough bytes to drop
         // below the "threshold" level. For now: we just drain everything to make sure.
         if !handled_all {
-            while uart0.usr.read().rfne().bit_is_set() {
-                let _byte = uart0.rbr().read().rbr().bits();
+            while mmio::read_reg(&uart0.usr, |r| r.read().rfne().bit_is_set()) {
+                let _byte = mmio::read_reg(uart0.rbr(), |r| r.read().rbr().bits());
             }
         }
     }
 
     // Send loop that listens to the bbqueue consumer, and sends it as DMA
-    // transactions on the UART
+    // transactions on the UART.
+    //
+    // This is the kernel's only path to the outside world once the system is
+    // up, so it must not panic. It's checked by the `just panic-audit-d1`
+    // recipe; see `panic-audit.txt` in the D1 platform crate.
     #[tracing::instrument(
         name = "D1Uart::sending",
         level = Level::INFO,
-        skip(cons, dmac)
+        skip(cons, dmac, descr_cfg, descriptor, cts)
     )]
-    async fn sending(cons: Consumer, dmac: Dmac) {
-        let thr = unsafe { (*UART0::PTR).thr() };
-
-        let descr_cfg = Descriptor::builder()
-            .dest_data_width(DataWidth::Bit8)
-            .dest_block_size(BlockSize::Byte1)
-            .src_data_width(DataWidth::Bit8)
-            .src_block_size(BlockSize::Byte1)
-            .wait_clock_cycles(0)
-            .dest_reg(thr, DestDrqType::Uart0Tx)
-            .expect("UART0 THR register should be a valid destination register for DMA transfers");
-
-        tracing::info!(?descr_cfg, "UART sender task running");
+    async fn sending(
+        cons: Consumer,
+        dmac: Dmac,
+        descr_cfg: DescriptorBuilder<(), *mut ()>,
+        mut descriptor: DmaBox<Descriptor>,
+        cts: Option<(Gpio, Pin)>,
+    ) {
+        tracing::info!(
+            ?descr_cfg,
+            flow_control = cts.is_some(),
+            "UART sender task running"
+        );
+
+        // with flow control, send a FIFO's worth at a time, so that we stop
+        // soon after CTS is deasserted.
+        let max_len = match cts {
+            Some(_) => FIFO_LEN,
+            None => Descriptor::MAX_LEN as usize,
+        };
 
         loop {
             let rx = cons.read_grant().await;
@@ -157,25 +235,67 @@ impl D1Uart {
                 // TODO(eliza): since the `byte_counter_max` is a constant,
                 // we could consider using `slice::array_chunks` instead to
                 // get these as fixed-size arrays, once that function is stable?
-                .chunks(Descriptor::MAX_LEN as usize);
+                .chunks(max_len);
 
             for chunk in chunks {
-                // this cast will never truncate because
-                // `BYTE_COUNTER_MAX` is less than 32 bits.
-                debug_assert!(chunk.len() <= Descriptor::MAX_LEN as usize);
-                let descriptor = descr_cfg
-                    .source_slice(chunk)
-                    .expect("slice should be a valid DMA source operand")
-                    .build();
+                // `chunk` is never longer than `Descriptor::MAX_LEN`, so this
+                // can only fail if the grant's address is too high for the
+                // DMAC, which would be a bug in the heap. Drop the data rather
+                // than panicking, so that we can still report the error.
+                *descriptor = match descr_cfg.source_slice(chunk) {
+                    Ok(builder) => builder.build(),
+                    Err(error) => {
+                        tracing::error!(?error, "UART TX buffer is not a valid DMA source");
+                        continue;
+                    }
+                };
+
+                // wait until the other end is ready to receive. CTS is a
+                // low-level interrupt, so this returns right away if it's
+                // already asserted.
+                if let Some((gpio, cts)) = cts {
+                    gpio.wait_for_edge(cts).await;
+                }
+
+                // the DMAC reads the descriptor and the chunk from memory,
+                // so make sure they aren't still sitting in the data cache.
+                descriptor.sync_for_device();
+                dma::sync_for_device(chunk);
 
                 // start the DMA transfer.
-                unsafe { chan.transfer(NonNull::from(&descriptor)).await }
+                unsafe { chan.transfer(descriptor.as_non_null()).await }
             }
 
             rx.release(rx_len);
         }
     }
 
+    // Resumes reception each time the RX interrupt handler pauses it, once
+    // the RX buffer has room for at least `resume_at` bytes.
+    #[tracing::instrument(
+        name = "D1Uart::rx_flow",
+        level = Level::INFO,
+        skip(prod, flow)
+    )]
+    async fn rx_flow(prod: &'static SpscProducer, flow: &'static RxFlow, resume_at: usize) {
+        loop {
+            // The WaitCell is never closed, so this can't fail.
+            let _ = flow.paused.wait().await;
+            tracing::trace!("UART RX paused");
+
+            // The grant is dropped without committing anything.
+            drop(prod.send_grant_exact(resume_at).await);
+
+            tracing::trace!("UART RX resumed");
+            flow.gpio.set_output(flow.rts, false);
+            critical_section::with(|_| unsafe {
+                mmio::modify_reg((*UART0::PTR).ier(), |r| {
+                    r.modify(|_r, w| w.erbfi().set_bit())
+                });
+            });
+        }
+    }
+
     async fn serial_server(
         handle: BidiHandle,
         reqs: registry::listener::RequestStream<SimpleSerialService>,
@@ -214,6 +334,7 @@ impl D1Uart {
             capacity_in,
             capacity_out,
             request_capacity,
+            flow_control,
         } = settings;
         let (fifo_a, fifo_b) = new_bidi_channel(capacity_in, capacity_out).await;
 
@@ -227,18 +348,71 @@ impl D1Uart {
 
         let _server_hdl = k.spawn(D1Uart::serial_server(fifo_b, reqs)).await;
 
+        let thr = unsafe { (*UART0::PTR).thr() };
+        let descr_cfg = Descriptor::builder()
+            .dest_data_width(DataWidth::Bit8)
+            .dest_block_size(BlockSize::Byte1)
+            .src_data_width(DataWidth::Bit8)
+            .src_block_size(BlockSize::Byte1)
+            .wait_clock_cycles(0)
+            .dest_reg(thr, DestDrqType::Uart0Tx)
+            .map_err(RegistrationError::InvalidDmaRegister)?;
+
+        let cts = flow_control.map(|FlowControl { gpio, cts, .. }| {
+            gpio.configure_eint(cts, Trigger::LowLevel);
+            (gpio, cts)
+        });
+
+        // the sending task reuses one descriptor for every transfer, so that
+        // it never has to allocate.
+        //
+        // Safety: a descriptor is just integers, so all zeroes is a valid
+        // (if useless) descriptor. it's overwritten before each transfer.
+        let descriptor = DmaBox::new(unsafe { core::mem::zeroed::<Descriptor>() }).await;
+
         let (prod, cons) = fifo_a.split();
-        let _send_hdl = k.spawn(D1Uart::sending(cons, dmac)).await;
+        let _send_hdl = k
+            .spawn(D1Uart::sending(cons, dmac, descr_cfg, descriptor, cts))
+            .await;
 
         let boxed_prod = Box::new(prod).await;
         let leaked_prod = Box::into_raw(boxed_prod);
         let old = UART_RX.swap(leaked_prod, Ordering::AcqRel);
         assert_eq!(old, null_mut());
 
+        if let Some(FlowControl { gpio, rts, .. }) = flow_control {
+            let flow = Box::new(RxFlow {
+                gpio,
+                rts,
+                paused: WaitCell::new(),
+            })
+            .await;
+            let flow = Box::into_raw(flow);
+            let old = RX_FLOW.swap(flow, Ordering::AcqRel);
+            assert_eq!(old, null_mut());
+
+            // Safety: both were leaked above, and are never freed.
+            let (prod, flow) = unsafe { (&*leaked_prod, &*flow) };
+            let resume_at = RX_RESUME.min(capacity_in / 2);
+            let _flow_hdl = k.spawn(D1Uart::rx_flow(prod, flow, resume_at)).await;
+
+            // Ready to receive.
+            gpio.configure_output(rts, false);
+        }
+
         Ok(())
     }
 }
 
+impl RxFlow {
+    /// Asks the other end to stop sending, by deasserting RTS, and wakes the
+    /// task which resumes reception once there's room.
+    fn pause(&self) {
+        self.gpio.set_output(self.rts, true);
+        self.paused.wake();
+    }
+}
+
 /// # Safety
 ///
 /// - The `UART0` register block must not be concurrently written to.
@@ -248,10 +422,12 @@ pub unsafe fn kernel_uart(ccu: &mut Ccu, gpio: &mut GPIO, mut uart0: UART0) -> U
     ccu.enable_module(&mut uart0);
 
     // Set PB8 and PB9 to function 6, UART0, internal pullup.
-    gpio.pb_cfg1
-        .write(|w| w.pb8_select().uart0_tx().pb9_select().uart0_rx());
-    gpio.pb_pull0
-        .write(|w| w.pc8_pull().pull_up().pc9_pull().pull_up());
+    mmio::write_reg(&gpio.pb_cfg1, |r| {
+        r.write(|w| w.pb8_select().uart0_tx().pb9_select().uart0_rx())
+    });
+    mmio::write_reg(&gpio.pb_pull0, |r| {
+        r.write(|w| w.pc8_pull().pull_up().pc9_pull().pull_up())
+    });
 
     // Configure UART0 for 115200 8n1.
     // By default APB1 is 24MHz, use divisor 13 for 115200.
@@ -261,44 +437,50 @@ pub unsafe fn kernel_uart(ccu: &mut Ccu, gpio: &mut GPIO, mut uart0: UART0) -> U
     // No Loop Back
     // No RTS_N
     // No DTR_N
-    uart0.mcr.write(|w| unsafe { w.bits(0) });
+    mmio::write_reg(&uart0.mcr, |r| r.write(|w| unsafe { w.bits(0) }));
 
     // RCVR INT Trigger: 1 char in FIFO
     // TXMT INT Trigger: FIFO Empty
     // DMA Mode 0 - (???)
     // FIFOs Enabled
-    uart0.hsk.write(|w| w.hsk().handshake());
-    uart0
-        .dma_req_en
-        .modify(|_r, w| w.timeout_enable().set_bit());
+    mmio::write_reg(&uart0.hsk, |r| r.write(|w| w.hsk().handshake()));
+    mmio::modify_reg(&uart0.dma_req_en, |r| {
+        r.modify(|_r, w| w.timeout_enable().set_bit())
+    });
     // uart0.fcr().write(|w| w.fifoe().set_bit().dmam().mode_1());
-    uart0.fcr().write(|w| {
-        w.fifoe().set_bit();
-        w.dmam().mode_1();
-        w.rt().half_full();
-        w
+    mmio::write_reg(uart0.fcr(), |r| {
+        r.write(|w| {
+            w.fifoe().set_bit();
+            w.dmam().mode_1();
+            w.rt().half_full();
+            w
+        })
     });
-    uart0.ier().write(|w| {
-        w.erbfi().set_bit();
-        w
+    mmio::write_reg(uart0.ier(), |r| {
+        r.write(|w| {
+            w.erbfi().set_bit();
+            w
+        })
     });
 
     // TX Halted
     // Also has some DMA relevant things? Not set currently
-    uart0.halt.write(|w| w.halt_tx().enabled());
+    mmio::write_reg(&uart0.halt, |r| r.write(|w| w.halt_tx().enabled()));
 
     // Enable control of baudrates
-    uart0.lcr.write(|w| w.dlab().divisor_latch());
+    mmio::write_reg(&uart0.lcr, |r| r.write(|w| w.dlab().divisor_latch()));
 
     // Baudrates
-    uart0.dll().write(|w| unsafe { w.dll().bits(13) });
-    uart0.dlh().write(|w| unsafe { w.dlh().bits(0) });
+    mmio::write_reg(uart0.dll(), |r| r.write(|w| unsafe { w.dll().bits(13) }));
+    mmio::write_reg(uart0.dlh(), |r| r.write(|w| unsafe { w.dlh().bits(0) }));
 
     // Unlatch baud rate, set width
-    uart0.lcr.write(|w| w.dlab().rx_buffer().dls().eight());
+    mmio::write_reg(&uart0.lcr, |r| {
+        r.write(|w| w.dlab().rx_buffer().dls().eight())
+    });
 
     // Re-enable sending
-    uart0.halt.write(|w| w.halt_tx().disabled());
+    mmio::write_reg(&uart0.halt, |r| r.write(|w| w.halt_tx().disabled()));
 
     Uart(uart0)
 }
@@ -313,10 +495,12 @@ impl core::fmt::Write for Uart {
 
 impl Uart {
     pub fn write(&mut self, buf: &[u8]) {
-        while self.0.usr.read().tfnf().bit_is_clear() {}
+        while mmio::read_reg(&self.0.usr, |r| r.read().tfnf().bit_is_clear()) {}
         for byte in buf {
-            self.0.thr().write(|w| unsafe { w.thr().bits(*byte) });
-            while self.0.usr.read().tfnf().bit_is_clear() {}
+            mmio::write_reg(self.0.thr(), |r| {
+                r.write(|w| unsafe { w.thr().bits(*byte) })
+            });
+            while mmio::read_reg(&self.0.usr, |r| r.read().tfnf().bit_is_clear()) {}
         }
     }
 }
@@ -327,6 +511,7 @@ impl Default for D1UartSettings {
             capacity_in: 4096,
             capacity_out: 4096,
             request_capacity: 4,
+            flow_control: None,
         }
     }
 }
@@ -0,0 +1,369 @@
+//! [GDB stub](kernel::gdb) target for the Allwinner D1.
+//!
+//! This provides an implementation of [`kernel::gdb::Target`] for the D1's
+//! XuanTie C906 core, supporting memory reads and writes in DRAM and software
+//! breakpoints.
+//!
+//! While the kernel is running, the stub is served by the kernel's
+//! [`gdb::serve`](kernel::gdb::serve) task. When a breakpoint is hit, the
+//! platform's exception handler calls [`handle_breakpoint`], which takes over
+//! the UART and talks to GDB directly, with the registers at the time of the
+//! trap, until GDB continues execution.
+#![warn(missing_docs)]
+
+use core::sync::atomic::{AtomicU16, Ordering};
+use kernel::{
+    gdb::{Error, Register, Target},
+    maitake::sync::{blocking::Mutex, spin::Spinlock},
+};
+
+/// The D1's GDB stub target.
+pub static TARGET: D1Target = D1Target::new();
+
+/// A [`Target`] for the D1.
+pub struct D1Target {
+    breakpoints: Mutex<[Option<Breakpoint>; MAX_BREAKPOINTS], Spinlock>,
+    regs: Mutex<Option<TrapRegisters>, Spinlock>,
+}
+
+/// The SerMux port used by the GDB stub, or 0 if the stub is disabled.
+static PORT: AtomicU16 = AtomicU16::new(0);
+
+#[derive(Copy, Clone)]
+struct Breakpoint {
+    addr: usize,
+    kind: usize,
+    /// The instruction replaced by the breakpoint.
+    orig: u32,
+}
+
+/// Registers saved at the time of a trap, in GDB's order for RISC-V: `x0`
+/// through `x31`, then `pc`.
+#[derive(Copy, Clone)]
+struct TrapRegisters {
+    values: [u64; NUM_REGS],
+    /// Bitmap of the registers in `values` that were saved.
+    saved: u64,
+}
+
+const MAX_BREAKPOINTS: usize = 16;
+const NUM_REGS: usize = 33;
+
+/// The D1's DRAM. Accesses outside of DRAM are rejected, rather than risking
+/// a fault on an unmapped or read-sensitive MMIO address.
+const DRAM: core::ops::Range<usize> = 0x4000_0000..0x8000_0000;
+
+/// `c.ebreak`
+const C_EBREAK: u16 = 0x9002;
+/// `ebreak`
+const EBREAK: u32 = 0x0010_0073;
+
+/// The largest SerMux frame the trap-mode stub will send or receive.
+const MAX_FRAME: usize = kernel::gdb::MAX_PACKET_SIZE + 32;
+
+// === impl D1Target ===
+
+impl D1Target {
+    const fn new() -> Self {
+        Self {
+            breakpoints: Mutex::new_with_raw_mutex([None; MAX_BREAKPOINTS], Spinlock::new()),
+            regs: Mutex::new_with_raw_mutex(None, Spinlock::new()),
+        }
+    }
+
+    /// Enables handling breakpoints in [`handle_breakpoint`], using SerMux
+    /// port `port`.
+    pub fn enable(&'static self, port: u16) -> &'static Self {
+        PORT.store(port, Ordering::Release);
+        self
+    }
+
+    fn check_range(addr: usize, len: usize) -> Result<(), Error> {
+        let end = addr.checked_add(len).ok_or(Error::BadAddress)?;
+        if addr >= DRAM.start && end <= DRAM.end {
+            Ok(())
+        } else {
+            Err(Error::BadAddress)
+        }
+    }
+
+    fn is_breakpoint(&self, addr: usize) -> bool {
+        self.breakpoints
+            .lock()
+            .iter()
+            .flatten()
+            .any(|bp| bp.addr == addr)
+    }
+}
+
+impl Target for D1Target {
+    fn read_registers(&self, f: &mut dyn FnMut(Register<'_>)) {
+        let regs = *self.regs.lock();
+        for i in 0..NUM_REGS {
+            match regs {
+                Some(ref regs) if regs.saved & (1 << i) != 0 => {
+                    f(Register::Value(&regs.values[i].to_le_bytes()))
+                }
+                _ => f(Register::Unavailable(8)),
+            }
+        }
+    }
+
+    fn read_memory(&self, addr: usize, buf: &mut [u8]) -> Result<(), Error> {
+        Self::check_range(addr, buf.len())?;
+        for (i, byte) in buf.iter_mut().enumerate() {
+            *byte = unsafe { core::ptr::read_volatile((addr + i) as *const u8) };
+        }
+        Ok(())
+    }
+
+    fn write_memory(&self, addr: usize, data: &[u8]) -> Result<(), Error> {
+        Self::check_range(addr, data.len())?;
+        for (i, &byte) in data.iter().enumerate() {
+            unsafe { core::ptr::write_volatile((addr + i) as *mut u8, byte) };
+        }
+        // The write may have been to code, such as a breakpoint.
+        fence_i();
+        Ok(())
+    }
+
+    fn insert_breakpoint(&self, addr: usize, kind: usize) -> Result<(), Error> {
+        if kind != 2 && kind != 4 {
+            return Err(Error::Unsupported);
+        }
+        Self::check_range(addr, kind)?;
+
+        let mut bps = self.breakpoints.lock();
+        if bps.iter().flatten().any(|bp| bp.addr == addr) {
+            return Ok(());
+        }
+        let slot = bps
+            .iter_mut()
+            .find(|slot| slot.is_none())
+            .ok_or(Error::TooManyBreakpoints)?;
+
+        let orig = unsafe {
+            if kind == 2 {
+                let orig = core::ptr::read_volatile(addr as *const u16);
+                core::ptr::write_volatile(addr as *mut u16, C_EBREAK);
+                u32::from(orig)
+            } else {
+                let orig = core::ptr::read_volatile(addr as *const u32);
+                core::ptr::write_volatile(addr as *mut u32, EBREAK);
+                orig
+            }
+        };
+        fence_i();
+        *slot = Some(Breakpoint { addr, kind, orig });
+        Ok(())
+    }
+
+    fn remove_breakpoint(&self, addr: usize, _kind: usize) -> Result<(), Error> {
+        let mut bps = self.breakpoints.lock();
+        let slot = bps
+            .iter_mut()
+            .find(|slot| matches!(slot, Some(bp) if bp.addr == addr))
+            .ok_or(Error::BadAddress)?;
+        let Some(Breakpoint { addr, kind, orig }) = slot.take() else {
+            unreachable!("slot was just matched")
+        };
+        unsafe {
+            if kind == 2 {
+                core::ptr::write_volatile(addr as *mut u16, orig as u16);
+            } else {
+                core::ptr::write_volatile(addr as *mut u32, orig);
+            }
+        }
+        fence_i();
+        Ok(())
+    }
+}
+
+/// Handles a breakpoint exception, by running the GDB stub on the UART until
+/// GDB continues execution.
+///
+/// Returns `false` if the GDB stub is not enabled, in which case the exception
+/// should be treated as fatal. Otherwise, `mepc` is updated so that execution
+/// resumes at the trapping instruction, if it was a breakpoint inserted by
+/// GDB (which GDB will have removed), or after it, if it was an `ebreak`
+/// compiled into the program.
+///
+/// # Notes
+///
+/// This polls the UART with interrupts disabled, so any SerMux frames for
+/// other ports which arrive while stopped at a breakpoint are discarded.
+///
+/// # Safety
+///
+/// This must only be called from the exception handler, with `trap_frame`
+/// pointing at the registers saved by `riscv-rt`.
+#[cfg(any(target_arch = "riscv64", target_arch = "riscv32"))]
+pub unsafe fn handle_breakpoint(trap_frame: &riscv_rt::TrapFrame) -> bool {
+    use kernel::{
+        gdb::{Action, Event, PacketReader, Stub},
+        services::serial_mux::PortChunk,
+    };
+
+    let port = PORT.load(Ordering::Acquire);
+    if port == 0 {
+        return false;
+    }
+
+    let mepc = riscv::register::mepc::read();
+    *TARGET.regs.lock() = Some(TrapRegisters::from_trap(trap_frame, mepc));
+
+    let uart0 = unsafe { &*d1_pac::UART0::PTR };
+    let send = |payload: &[u8]| {
+        let mut frame = [0u8; MAX_FRAME];
+        let Ok(frame) = PortChunk::new(port, payload).encode_to(&mut frame) else {
+            return;
+        };
+        // end any in-progress frame, in case the trap interrupted a UART TX
+        // DMA transfer.
+        for &byte in [0].iter().chain(frame.iter()) {
+            while uart0.usr.read().tfnf().bit_is_clear() {}
+            uart0.thr().write(|w| unsafe { w.thr().bits(byte) });
+        }
+    };
+    let send_packet = |payload: &[u8]| {
+        let mut packet = [0u8; kernel::gdb::MAX_PACKET_SIZE + 4];
+        let mut len = 0;
+        Stub::encode_packet(payload, |bytes| {
+            packet[len..len + bytes.len()].copy_from_slice(bytes);
+            len += bytes.len();
+        });
+        send(&packet[..len]);
+    };
+
+    let mut stub = Stub::new(&TARGET);
+    let mut reader = PacketReader::new();
+    let mut frame = [0u8; MAX_FRAME];
+    let mut frame_len = 0;
+
+    // Tell GDB why we stopped.
+    send_packet(b"S05");
+
+    'stopped: loop {
+        while uart0.usr.read().rfne().bit_is_clear() {}
+        let byte = uart0.rbr().read().rbr().bits();
+        if byte != 0 {
+            if let Some(slot) = frame.get_mut(frame_len) {
+                *slot = byte;
+            }
+            frame_len += 1;
+            continue;
+        }
+
+        // End of a SerMux frame.
+        let len = core::mem::replace(&mut frame_len, 0);
+        let Some(frame) = frame.get_mut(..len) else {
+            continue;
+        };
+        let chunk = match PortChunk::decode_from(frame) {
+            Ok(chunk) if chunk.port == port => chunk.chunk,
+            _ => continue,
+        };
+
+        for &byte in chunk {
+            match reader.push(byte) {
+                Some(Event::Packet) => {}
+                Some(Event::Corrupted) if !stub.no_ack() => {
+                    send(b"-");
+                    continue;
+                }
+                // We're already stopped.
+                Some(Event::Interrupt) => {
+                    send_packet(b"S05");
+                    continue;
+                }
+                _ => continue,
+            }
+
+            if !stub.no_ack() {
+                send(b"+");
+            }
+            match stub.handle_packet(reader.packet()) {
+                Action::Reply => send_packet(stub.reply()),
+                Action::Continue => break 'stopped,
+                Action::Detach => {
+                    send_packet(stub.reply());
+                    break 'stopped;
+                }
+            }
+        }
+    }
+
+    *TARGET.regs.lock() = None;
+
+    // If the trap was caused by an `ebreak` that GDB didn't insert (such as
+    // one compiled into the program), skip over it, or we'll trap again.
+    if !TARGET.is_breakpoint(mepc) {
+        // `mepc` may only be 2-byte aligned, so read the instruction in
+        // halves.
+        let lo = unsafe { core::ptr::read_volatile(mepc as *const u16) };
+        if lo == C_EBREAK {
+            riscv::register::mepc::write(mepc + 2);
+        } else {
+            let hi = unsafe { core::ptr::read_volatile((mepc + 2) as *const u16) };
+            if (u32::from(hi) << 16 | u32::from(lo)) == EBREAK {
+                riscv::register::mepc::write(mepc + 4);
+            }
+        }
+    }
+
+    true
+}
+
+// === impl TrapRegisters ===
+
+impl TrapRegisters {
+    #[cfg(any(target_arch = "riscv64", target_arch = "riscv32"))]
+    fn from_trap(frame: &riscv_rt::TrapFrame, mepc: usize) -> Self {
+        let mut regs = Self {
+            values: [0; NUM_REGS],
+            saved: 0,
+        };
+        let mut save = |i: usize, val: usize| {
+            regs.values[i] = val as u64;
+            regs.saved |= 1 << i;
+        };
+
+        save(0, 0);
+        save(1, frame.ra);
+        // `riscv-rt` saves 16 registers on the stack before calling the
+        // exception handler.
+        save(
+            2,
+            frame as *const _ as usize + 16 * core::mem::size_of::<usize>(),
+        );
+        for (i, val) in [frame.t0, frame.t1, frame.t2].into_iter().enumerate() {
+            save(5 + i, val);
+        }
+        for (i, val) in [
+            frame.a0, frame.a1, frame.a2, frame.a3, frame.a4, frame.a5, frame.a6, frame.a7,
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            save(10 + i, val);
+        }
+        for (i, val) in [frame.t3, frame.t4, frame.t5, frame.t6]
+            .into_iter()
+            .enumerate()
+        {
+            save(28 + i, val);
+        }
+        save(32, mepc);
+        // The callee-saved registers (`s0`-`s11`) are not saved by `riscv-rt`,
+        // and may have been overwritten by the time we get here, so they are
+        // reported as unavailable.
+        regs
+    }
+}
+
+fn fence_i() {
+    #[cfg(any(target_arch = "riscv64", target_arch = "riscv32"))]
+    unsafe {
+        core::arch::asm!("fence.i");
+    }
+}
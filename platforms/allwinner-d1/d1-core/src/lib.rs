@@ -2,12 +2,17 @@
 
 extern crate alloc;
 
+pub mod cache;
 pub mod ccu;
 pub mod clint;
 pub mod dmac;
 pub mod drivers;
+pub mod gdb;
+pub mod mmio;
 pub mod plic;
 mod ram;
+pub mod rtc;
+pub mod sid;
 pub mod timer;
 pub mod trap;
 pub use self::ram::Ram;
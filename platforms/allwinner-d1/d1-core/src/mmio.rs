@@ -0,0 +1,285 @@
+//! Memory-mapped IO register access, with optional tracing.
+//!
+//! Drivers which access registers by address, rather than through the PAC's
+//! register API, should use the [`read`], [`write`], and [`modify`] functions
+//! in this module, or [`read_u8`] and [`write_u8`] for 8-bit registers.
+//!
+//! Drivers which use the PAC should access its registers through
+//! [`read_reg`], [`write_reg`], and [`modify_reg`], rather than calling the
+//! register's methods directly. These pass the closure a copy of the register
+//! which lives in RAM, so that the PAC's field API can be used as usual, and
+//! then perform the actual access using [`read`] and [`write`]. For example:
+//!
+//! ```ignore
+//! // rather than `uart.lcr.modify(|_, w| w.dlab().divisor_latch())`:
+//! mmio::modify_reg(&uart.lcr, |r| r.modify(|_, w| w.dlab().divisor_latch()));
+//! ```
+//!
+//! When the `mmio-trace` feature is enabled, every access made through this
+//! module is recorded (with its address, value, and the program counter it
+//! was made from) in a small ring buffer, which the panic handler dumps using
+//! [`for_each_recent`]. This is intended to help with debugging lockups
+//! caused by bad register sequences. When the feature is disabled, these
+//! functions compile down to plain volatile accesses.
+#![warn(missing_docs)]
+
+use core::{cell::UnsafeCell, fmt, ptr};
+use d1_pac::generic::{Reg, RegisterSpec};
+
+/// A recorded MMIO register access.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Access {
+    /// Whether the register was read or written.
+    pub kind: AccessKind,
+    /// The register's address.
+    pub addr: usize,
+    /// The value read from or written to the register.
+    pub value: u32,
+    /// The program counter at the access.
+    pub pc: usize,
+}
+
+/// The kind of an [`Access`].
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum AccessKind {
+    /// The register was read.
+    Read,
+    /// The register was written.
+    Write,
+}
+
+/// The number of accesses kept in the trace ring.
+pub const TRACE_LEN: usize = 64;
+
+/// Reads the register at `reg`.
+///
+/// # Safety
+///
+/// `reg` must be a valid MMIO register address, and reading it must not
+/// violate the invariants of the driver that owns it.
+#[inline(always)]
+pub unsafe fn read(reg: *const u32) -> u32 {
+    let value = ptr::read_volatile(reg);
+    trace::record(AccessKind::Read, reg as usize, value);
+    value
+}
+
+/// Writes `value` to the register at `reg`.
+///
+/// # Safety
+///
+/// `reg` must be a valid MMIO register address, and writing it must not
+/// violate the invariants of the driver that owns it.
+#[inline(always)]
+pub unsafe fn write(reg: *mut u32, value: u32) {
+    trace::record(AccessKind::Write, reg as usize, value);
+    ptr::write_volatile(reg, value)
+}
+
+/// Reads the register at `reg`, and writes back the value returned by `f`.
+///
+/// # Safety
+///
+/// The same as [`read`] and [`write`].
+#[inline(always)]
+pub unsafe fn modify(reg: *mut u32, f: impl FnOnce(u32) -> u32) {
+    write(reg, f(read(reg)))
+}
+
+/// Reads the 8-bit register at `reg`.
+///
+/// # Safety
+///
+/// The same as [`read`].
+#[inline(always)]
+pub unsafe fn read_u8(reg: *const u8) -> u8 {
+    let value = ptr::read_volatile(reg);
+    trace::record(AccessKind::Read, reg as usize, u32::from(value));
+    value
+}
+
+/// Writes `value` to the 8-bit register at `reg`.
+///
+/// # Safety
+///
+/// The same as [`write`].
+#[inline(always)]
+pub unsafe fn write_u8(reg: *mut u8, value: u8) {
+    trace::record(AccessKind::Write, reg as usize, u32::from(value));
+    ptr::write_volatile(reg, value)
+}
+
+/// Reads a PAC register, and calls `f` with a copy of it.
+///
+/// `f` should only call the copy's `read` method; anything written to it is
+/// discarded.
+#[inline(always)]
+pub fn read_reg<REG: RegisterSpec<Ux = u32>, T>(
+    reg: &Reg<REG>,
+    f: impl FnOnce(&Reg<REG>) -> T,
+) -> T {
+    // Safety: holding a reference to a PAC register is the PAC's proof that
+    // the caller may access it.
+    let value = unsafe { read(reg.as_ptr()) };
+    with_copy(value, f).0
+}
+
+/// Calls `f` with a copy of a PAC register, and writes the copy's value to
+/// the register.
+///
+/// `f` should only call the copy's `write` (or `write_with_zero`) method, as
+/// the copy's value is not read from the register first.
+#[inline(always)]
+pub fn write_reg<REG: RegisterSpec<Ux = u32>>(reg: &Reg<REG>, f: impl FnOnce(&Reg<REG>)) {
+    let ((), value) = with_copy(0, f);
+    // Safety: see `read_reg`.
+    unsafe { write(reg.as_ptr(), value) }
+}
+
+/// Reads a PAC register, calls `f` with a copy of it, and writes the copy's
+/// value back to the register.
+#[inline(always)]
+pub fn modify_reg<REG: RegisterSpec<Ux = u32>>(reg: &Reg<REG>, f: impl FnOnce(&Reg<REG>)) {
+    // Safety: see `read_reg`.
+    unsafe { modify(reg.as_ptr(), |value| with_copy(value, f).1) }
+}
+
+/// Calls `f` with a PAC register which lives in RAM, holding `value`, and
+/// returns its result along with the register's value afterwards.
+#[inline(always)]
+fn with_copy<REG: RegisterSpec<Ux = u32>, T>(
+    value: u32,
+    f: impl FnOnce(&Reg<REG>) -> T,
+) -> (T, u32) {
+    let cell = UnsafeCell::new(value);
+    let ret = {
+        // Safety: a `Reg` is a `repr(transparent)` `VolatileCell`, which is a
+        // `repr(transparent)` `UnsafeCell`, so it has the same layout as
+        // `cell`. The reference does not outlive this block.
+        let copy = unsafe { &*cell.get().cast::<Reg<REG>>() };
+        f(copy)
+    };
+    (ret, cell.into_inner())
+}
+
+/// Calls `f` with each recorded access, oldest first.
+///
+/// If the `mmio-trace` feature is disabled, no accesses are recorded, and `f`
+/// is never called.
+pub fn for_each_recent(f: impl FnMut(&Access)) {
+    trace::for_each(f)
+}
+
+impl fmt::Display for Access {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Self {
+            kind,
+            addr,
+            value,
+            pc,
+        } = self;
+        let op = match kind {
+            AccessKind::Read => "R",
+            AccessKind::Write => "W",
+        };
+        write!(f, "{op} {addr:#010x} = {value:#010x} (pc {pc:#x})")
+    }
+}
+
+#[cfg(feature = "mmio-trace")]
+mod trace {
+    use super::*;
+    use core::cell::RefCell;
+    use critical_section::Mutex;
+
+    struct Ring {
+        accesses: [Option<Access>; TRACE_LEN],
+        next: usize,
+    }
+
+    static RING: Mutex<RefCell<Ring>> = Mutex::new(RefCell::new(Ring {
+        accesses: [None; TRACE_LEN],
+        next: 0,
+    }));
+
+    #[inline(always)]
+    pub(super) fn record(kind: AccessKind, addr: usize, value: u32) {
+        let pc = pc();
+        critical_section::with(|cs| {
+            // this may be called from the panic handler, so drop the access
+            // rather than panicking if the ring is already borrowed.
+            let Ok(mut ring) = RING.borrow(cs).try_borrow_mut() else {
+                return;
+            };
+            let next = ring.next;
+            ring.accesses[next] = Some(Access {
+                kind,
+                addr,
+                value,
+                pc,
+            });
+            ring.next = (next + 1) % TRACE_LEN;
+        })
+    }
+
+    pub(super) fn for_each(mut f: impl FnMut(&Access)) {
+        // copy the ring out, so that `f` may itself access registers (and
+        // so that a panic in `f` doesn't leave the ring borrowed). if the
+        // panic happened while recording an access, the ring is still
+        // borrowed, so there's nothing to dump.
+        let Some((accesses, next)) = critical_section::with(|cs| {
+            let ring = RING.borrow(cs).try_borrow().ok()?;
+            Some((ring.accesses, ring.next))
+        }) else {
+            return;
+        };
+        let (newer, older) = accesses.split_at(next);
+        older.iter().chain(newer).flatten().for_each(&mut f);
+    }
+
+    /// Returns the current program counter. Since this is always inlined,
+    /// this is the address of the access in the caller.
+    #[inline(always)]
+    fn pc() -> usize {
+        #[cfg(any(target_arch = "riscv64", target_arch = "riscv32"))]
+        {
+            let pc: usize;
+            unsafe { core::arch::asm!("auipc {}, 0", out(reg) pc) };
+            pc
+        }
+        #[cfg(not(any(target_arch = "riscv64", target_arch = "riscv32")))]
+        {
+            0
+        }
+    }
+}
+
+#[cfg(not(feature = "mmio-trace"))]
+mod trace {
+    use super::*;
+
+    #[inline(always)]
+    pub(super) fn record(_: AccessKind, _: usize, _: u32) {}
+
+    pub(super) fn for_each(_: impl FnMut(&Access)) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pac_register_access() {
+        // stand in for the PLIC's `MTH` register with a word of RAM.
+        let word = UnsafeCell::new(0u32);
+        unsafe {
+            let mth = &*word.get().cast::<d1_pac::plic::MTH>();
+
+            write_reg(mth, |r| r.write(|w| w.bits(3)));
+            assert_eq!(read_reg(mth, |r| r.read().bits()), 3);
+
+            modify_reg(mth, |r| r.modify(|r, w| w.bits(r.bits() + 1)));
+            assert_eq!(word.get().read(), 4);
+        }
+    }
+}
@@ -3,11 +3,13 @@
 
 use core::{
     ptr::null_mut,
-    sync::atomic::{AtomicPtr, Ordering},
+    sync::atomic::{AtomicBool, AtomicPtr, Ordering},
 };
 
 use d1_pac::{plic, Interrupt, PLIC};
-use kernel::isr::Isr;
+use kernel::isr::{self, Isr, IsrConfig};
+
+use crate::mmio;
 
 /// Interrupt Priority from 0..31
 pub type Priority = plic::prio::PRIORITY_A;
@@ -55,13 +57,15 @@ impl Plic {
     /// May effect normal interrupt processing
     pub unsafe fn unmask(&self, interrupt: Interrupt) {
         let (mie, irq_en) = self.index_mie(interrupt);
-        mie.modify(|r, w| w.bits(r.bits() | irq_en));
+        mmio::modify_reg(mie, |r| r.modify(|r, w| w.bits(r.bits() | irq_en)));
     }
 
     /// Disable an interrupt
     pub fn mask(&self, interrupt: Interrupt) {
         let (mie, irq_en) = self.index_mie(interrupt);
-        mie.modify(|r, w| unsafe { w.bits(r.bits() & !irq_en) });
+        mmio::modify_reg(mie, |r| {
+            r.modify(|r, w| unsafe { w.bits(r.bits() & !irq_en) })
+        });
     }
 
     /// Globally set priority for one interrupt
@@ -71,11 +75,13 @@ impl Plic {
     /// May effect normal interrupt processing
     pub unsafe fn set_priority(&self, interrupt: Interrupt, priority: Priority) {
         let nr = interrupt.into_bits() as usize;
-        self.plic.prio[nr].write(|w| w.bits(priority.into_bits()));
+        mmio::write_reg(&self.plic.prio[nr], |r| {
+            r.write(|w| w.bits(priority.into_bits()))
+        });
     }
 
     pub fn claim(&self) -> Interrupt {
-        let claim = self.plic.mclaim.read().mclaim().bits() as u8;
+        let claim = mmio::read_reg(&self.plic.mclaim, |r| r.read().mclaim().bits()) as u8;
         match Interrupt::try_from(claim) {
             Ok(interrupt) => interrupt,
             Err(e) => {
@@ -84,8 +90,29 @@ impl Plic {
         }
     }
 
+    /// Returns the PLIC priority used for interrupts with the given
+    /// [`isr::Priority`].
+    ///
+    /// The kernel's priorities are spread out across the PLIC's priorities,
+    /// leaving room for [`Plic::set_priority`] to place interrupts between
+    /// them. Priority 0 is never used, since it disables an interrupt.
+    pub const fn priority(priority: isr::Priority) -> Priority {
+        match priority {
+            isr::Priority::Low => Priority::P1,
+            isr::Priority::Normal => Priority::P8,
+            isr::Priority::High => Priority::P16,
+            isr::Priority::Critical => Priority::P24,
+        }
+    }
+
     /// Dispatch an interrupt to a vectored handler.
     ///
+    /// If the handler was [activated](Plic::activate) with an [`IsrConfig`]
+    /// that [allows nesting](isr::Nesting::Allowed), the PLIC's threshold is
+    /// raised to the interrupt's priority and interrupts are re-enabled while
+    /// the handler runs, so that it may be preempted by higher-priority
+    /// interrupts. Otherwise, the handler runs with interrupts disabled.
+    ///
     /// # Safety
     ///
     /// Should only be called in an ISR such as `MachineInternal`!
@@ -94,42 +121,96 @@ impl Plic {
             Isr::is_in_isr(),
             "Plic::dispatch should only be called in an ISR!"
         );
-        let claim = self.claim();
-        let claim_u16 = claim as u16;
-
-        // Is this a known interrupt?
-        // N.B. that the index is always in bounds, because `claim()` has
-        // panicked already if the interrupt is not a valid interrupt number.
-        // Theoretically, we could probably optimize this to use
-        // `get_unchecked`, if we cared to.
-        let &Vectored { id, ref handler } = &INTERRUPT_ARRAY[claim_u16 as usize];
-        debug_assert_eq!(
+        let claim_u16 = mmio::read_reg(&self.plic.mclaim, |r| r.read().mclaim().bits());
+
+        // Is this a known interrupt? Interrupts which aren't in the PAC's
+        // `Interrupt` enum are only known if they are in `RAW_INTERRUPT_LIST`.
+        let Some(&Vectored {
             id,
-            Some(claim),
-            "FLAGRANT ERROR: interrupt ID {id:?} does not match index \
-                ({claim_u16}); perhaps the interrupt dispatch table has \
-                somehow been corrupted?"
-        );
+            ref handler,
+            ref nesting,
+        }) = INTERRUPT_ARRAY.get(claim_u16 as usize)
+        else {
+            panic!("error claiming interrupt: no such vector {claim_u16}");
+        };
+        if let Ok(claim) = Interrupt::try_from(claim_u16 as u8) {
+            debug_assert_eq!(
+                id,
+                Some(claim),
+                "FLAGRANT ERROR: interrupt ID {id:?} does not match index \
+                    ({claim_u16}); perhaps the interrupt dispatch table has \
+                    somehow been corrupted?"
+            );
+        }
         let ptr = handler.load(Ordering::SeqCst); // todo: ordering
         if !ptr.is_null() {
             let hdlr: fn() = unsafe { core::mem::transmute(ptr) };
-            (hdlr)();
+            if nesting.load(Ordering::Acquire) {
+                self.dispatch_nested(claim_u16 as usize, hdlr);
+            } else {
+                (hdlr)();
+            }
         } // otherwise, the ISR hasn't been registered yet; just do nothing.
 
         // Release claim
-        self.complete(claim);
+        mmio::write_reg(&self.plic.mclaim, |r| {
+            r.write(|w| w.mclaim().variant(claim_u16))
+        });
+    }
+
+    /// Run `hdlr` for the claimed interrupt `nr`, allowing it to be preempted
+    /// by interrupts with a higher priority.
+    ///
+    /// # Safety
+    ///
+    /// Must only be called from [`Plic::dispatch_interrupt`], after claiming
+    /// `nr`.
+    #[cfg(any(target_arch = "riscv64", target_arch = "riscv32"))]
+    unsafe fn dispatch_nested(&self, nr: usize, hdlr: fn()) {
+        use riscv::register::{mepc, mstatus};
+
+        // a nested trap overwrites `mepc` and the previous privilege and
+        // interrupt-enable bits in `mstatus`, so save them, to restore them
+        // before returning from this trap.
+        let epc = mepc::read();
+        let status = mstatus::read();
+
+        // only interrupts with a priority above the threshold are delivered,
+        // so raise the threshold to this interrupt's priority.
+        let threshold = mmio::read_reg(&self.plic.mth, |r| r.read().bits());
+        let priority = mmio::read_reg(&self.plic.prio[nr], |r| r.read().bits());
+        mmio::write_reg(&self.plic.mth, |r| r.write(|w| w.bits(priority)));
+
+        mstatus::set_mie();
+        (hdlr)();
+        mstatus::clear_mie();
+
+        mmio::write_reg(&self.plic.mth, |r| r.write(|w| w.bits(threshold)));
+        mepc::write(epc);
+        mstatus::set_mpp(status.mpp());
+        if status.mpie() {
+            mstatus::set_mpie();
+        }
+    }
+
+    #[cfg(not(any(target_arch = "riscv64", target_arch = "riscv32")))]
+    unsafe fn dispatch_nested(&self, _nr: usize, hdlr: fn()) {
+        (hdlr)();
     }
 
     pub fn complete(&self, interrupt: Interrupt) {
-        self.plic
-            .mclaim
-            .write(|w| w.mclaim().variant(interrupt.into_bits() as u16));
+        mmio::write_reg(&self.plic.mclaim, |r| {
+            r.write(|w| w.mclaim().variant(interrupt.into_bits() as u16))
+        });
     }
 
     #[track_caller]
     pub unsafe fn register(&self, interrupt: Interrupt, new_hdl: fn()) {
         let idx = interrupt as usize;
-        let Some(&Vectored { id, ref handler }) = INTERRUPT_ARRAY.get(idx) else {
+        let Some(&Vectored {
+            id, ref handler, ..
+        }) = INTERRUPT_ARRAY.get(idx)
+        else {
             panic!("interrupt not found in dispatch table: {interrupt:?} (index {idx})")
         };
         assert_eq!(
@@ -142,9 +223,72 @@ impl Plic {
         handler.store(new_hdl as *mut fn() as *mut (), Ordering::Release);
     }
 
-    pub unsafe fn activate(&self, interrupt: Interrupt, prio: Priority) -> Result<(), MaskError> {
+    /// Register a handler for an interrupt which is not in the PAC's
+    /// [`Interrupt`] enum, by its vector number.
+    ///
+    /// # Safety
+    ///
+    /// The same as [`Plic::register`].
+    #[track_caller]
+    pub unsafe fn register_vector(&self, vector: u16, new_hdl: fn()) {
+        assert!(
+            RAW_INTERRUPT_LIST.contains(&vector),
+            "interrupt vector {vector} is not in the raw interrupt list"
+        );
+        INTERRUPT_ARRAY[vector as usize]
+            .handler
+            .store(new_hdl as *mut fn() as *mut (), Ordering::Release);
+    }
+
+    /// Activate an interrupt registered with [`Plic::register_vector`].
+    ///
+    /// # Safety
+    ///
+    /// The same as [`Plic::activate`].
+    pub unsafe fn activate_vector(&self, vector: u16, config: IsrConfig) {
+        let nr = vector as usize;
+        let vectored = &INTERRUPT_ARRAY[nr];
+        debug_assert!(
+            !vectored.handler.load(Ordering::SeqCst).is_null(),
+            "interrupt vector {vector} has no handler"
+        );
+        vectored
+            .nesting
+            .store(config.allows_nesting(), Ordering::Release);
+        let prio = Self::priority(config.priority);
+        mmio::write_reg(&self.plic.prio[nr], |r| {
+            r.write(|w| w.bits(prio.into_bits()))
+        });
+        mmio::modify_reg(&self.plic.mie[nr / 32], |r| {
+            r.modify(|r, w| w.bits(r.bits() | (1 << (nr % 32))))
+        });
+    }
+
+    /// Deactivate an interrupt registered with [`Plic::register_vector`].
+    pub fn deactivate_vector(&self, vector: u16) {
+        let nr = vector as usize;
+        mmio::modify_reg(&self.plic.mie[nr / 32], |r| {
+            r.modify(|r, w| unsafe { w.bits(r.bits() & !(1 << (nr % 32))) })
+        });
+    }
+
+    /// Activate an interrupt registered with [`Plic::register`], with the
+    /// priority and nesting requested by `config`.
+    ///
+    /// # Safety
+    ///
+    /// May effect normal interrupt processing. If `config` allows nesting,
+    /// the interrupt's handler must be safe to preempt.
+    pub unsafe fn activate(
+        &self,
+        interrupt: Interrupt,
+        config: IsrConfig,
+    ) -> Result<(), MaskError> {
         self.can_mask(interrupt)?;
-        self.set_priority(interrupt, prio);
+        INTERRUPT_ARRAY[interrupt as usize]
+            .nesting
+            .store(config.allows_nesting(), Ordering::Release);
+        self.set_priority(interrupt, Self::priority(config.priority));
         self.unmask(interrupt);
         Ok(())
     }
@@ -156,7 +300,9 @@ impl Plic {
     }
 
     fn can_mask(&self, interrupt: Interrupt) -> Result<(), MaskError> {
-        let &Vectored { id, ref handler } = INTERRUPT_ARRAY
+        let &Vectored {
+            id, ref handler, ..
+        } = INTERRUPT_ARRAY
             .get(interrupt as usize)
             .ok_or(MaskError::NotFound(interrupt))?;
 
@@ -245,6 +391,8 @@ impl TryFromBits for Priority {
 struct Vectored {
     id: Option<Interrupt>,
     handler: AtomicPtr<()>,
+    /// Whether the handler may be preempted by higher-priority interrupts.
+    nesting: AtomicBool,
 }
 
 impl Vectored {
@@ -252,6 +400,16 @@ impl Vectored {
         Self {
             id: Some(interrupt),
             handler: AtomicPtr::new(null_mut()),
+            nesting: AtomicBool::new(false),
+        }
+    }
+
+    /// A vector for an interrupt which isn't in the PAC's [`Interrupt`] enum.
+    const fn raw() -> Self {
+        Self {
+            id: None,
+            handler: AtomicPtr::new(null_mut()),
+            nesting: AtomicBool::new(false),
         }
     }
 
@@ -259,6 +417,7 @@ impl Vectored {
         Self {
             id: None,
             handler: AtomicPtr::new(no_such_interrupt as *mut fn() as *mut ()),
+            nesting: AtomicBool::new(false),
         }
     }
 }
@@ -322,6 +481,9 @@ const INTERRUPT_LIST: &[Interrupt] = &[
     Interrupt::IR_RX,
 ];
 
+/// Interrupts which are not in the PAC's [`Interrupt`] enum, by vector number.
+const RAW_INTERRUPT_LIST: &[u16] = &[crate::rtc::ALARM0_IRQ];
+
 const N_INTERRUPTS: usize = {
     let mut max = 0;
     // INTERRUPT_LIST is sorted, but --- because we're doing this in a const fn
@@ -340,6 +502,14 @@ const N_INTERRUPTS: usize = {
         }
         i += 1;
     }
+    let mut i = 0;
+    while i < RAW_INTERRUPT_LIST.len() {
+        let vector = RAW_INTERRUPT_LIST[i] as usize;
+        if vector > max {
+            max = vector;
+        }
+        i += 1;
+    }
     max + 1
 };
 
@@ -357,6 +527,11 @@ static INTERRUPT_ARRAY: [Vectored; N_INTERRUPTS] = {
         array[vector] = Vectored::new(interrupt);
         i += 1;
     }
+    let mut i = 0;
+    while i < RAW_INTERRUPT_LIST.len() {
+        array[RAW_INTERRUPT_LIST[i] as usize] = Vectored::raw();
+        i += 1;
+    }
     array
 };
 
@@ -0,0 +1,244 @@
+//! Driver for the Allwinner D1's RTC alarm, as a wakeup source.
+//!
+//! The RTC keeps running from the 32 kHz oscillator while the rest of the SoC
+//! is clock gated, so waking from its alarm, rather than from `TIMER1`, is a
+//! step towards deeper sleep states. However, the alarm only has a resolution
+//! of one second, so it is only used for long sleeps: see
+//! [`Rtc::sleep_secs`].
+//!
+//! The RTC counts time as a 16-bit day counter, plus hours, minutes and
+//! seconds within the day. This module converts between that and a linear
+//! count of seconds, which wraps around every [`WRAP_SECS`] seconds (just
+//! under 180 years), and all arithmetic on RTC times is done modulo that
+//! period.
+//!
+//! The alarm's interrupt is not in the PAC's [`Interrupt`](d1_pac::Interrupt)
+//! enum, so it is registered with the PLIC by vector number, as
+//! [`ALARM0_IRQ`].
+#![warn(missing_docs)]
+
+use crate::{mmio, timer::Ticks};
+
+/// The RTC's base address.
+const RTC_BASE: usize = 0x0709_0000;
+
+/// The PLIC vector of the RTC's alarm 0 interrupt.
+pub const ALARM0_IRQ: u16 = 160;
+
+// Register offsets. The bitfields of these registers are written by hand,
+// rather than through the PAC, in the same manner as the LEDC driver.
+const RTC_DAY: usize = 0x10;
+const RTC_HH_MM_SS: usize = 0x14;
+const ALARM0_DAY_SET: usize = 0x20;
+const ALARM0_HH_MM_SS_SET: usize = 0x24;
+const ALARM0_ENABLE: usize = 0x28;
+const ALARM0_IRQ_EN: usize = 0x2C;
+const ALARM0_IRQ_STA: usize = 0x30;
+const ALARM_CONFIG: usize = 0x50;
+
+// `ALARM0_ENABLE`, `ALARM0_IRQ_EN`, and `ALARM0_IRQ_STA` bits.
+const ALARM0_BIT: u32 = 1 << 0;
+// `ALARM_CONFIG` bits.
+const ALARM_WAKEUP: u32 = 1 << 0;
+
+const SECS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// The number of seconds after which RTC times wrap around, as the day
+/// counter is 16 bits wide.
+pub const WRAP_SECS: u64 = (u16::MAX as u64 + 1) * SECS_PER_DAY;
+
+/// A handle to the RTC's alarm 0.
+pub struct Rtc {
+    _p: (),
+}
+
+/// A time read from the RTC, as the number of seconds since the RTC's epoch,
+/// modulo [`WRAP_SECS`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct RtcTime(u64);
+
+// === impl Rtc ===
+
+impl Rtc {
+    /// Takes the RTC's alarm 0, disabling it.
+    ///
+    /// # Safety
+    ///
+    /// Nothing else may use the RTC's alarm 0.
+    pub unsafe fn new() -> Self {
+        let mut rtc = Self { _p: () };
+        rtc.cancel();
+        rtc
+    }
+
+    /// Returns the current time.
+    pub fn now(&self) -> RtcTime {
+        loop {
+            // if the day ticks over between reading the day and the time of
+            // day, the time would be a day off, so read the day again.
+            let day = unsafe { read(RTC_DAY) } & 0xFFFF;
+            let hms = unsafe { read(RTC_HH_MM_SS) };
+            if unsafe { read(RTC_DAY) } & 0xFFFF == day {
+                return RtcTime::from_regs(day, hms);
+            }
+        }
+    }
+
+    /// Returns how many seconds an alarm should be set for, to wake from a
+    /// sleep of `ticks`, or `None` if the sleep is too short for the alarm.
+    ///
+    /// The alarm fires when the RTC's seconds roll over, which may be almost
+    /// a second sooner than a whole number of seconds from now. Waking early
+    /// is harmless, as the run loop just sleeps again, but waking late would
+    /// delay the kernel's timers, so this rounds down, and sleeps shorter
+    /// than two seconds are left to `TIMER1`.
+    #[must_use]
+    pub fn sleep_secs(ticks: Ticks) -> Option<u32> {
+        let secs = ticks.to_duration().as_secs();
+        if secs < 2 {
+            return None;
+        }
+        Some(u32::try_from(secs).unwrap_or(u32::MAX))
+    }
+
+    /// Sets the alarm to fire `secs` seconds from now, enabling its interrupt.
+    pub fn set_alarm_in(&mut self, secs: u32) {
+        let (day, hms) = self.now().add_secs(secs as u64).to_regs();
+        unsafe {
+            write(ALARM0_IRQ_STA, ALARM0_BIT);
+            write(ALARM0_DAY_SET, day);
+            write(ALARM0_HH_MM_SS_SET, hms);
+            modify(ALARM_CONFIG, |r| r | ALARM_WAKEUP);
+            write(ALARM0_IRQ_EN, ALARM0_BIT);
+            write(ALARM0_ENABLE, ALARM0_BIT);
+        }
+    }
+
+    /// Disables the alarm, and its interrupt.
+    pub fn cancel(&mut self) {
+        unsafe {
+            write(ALARM0_ENABLE, 0);
+            write(ALARM0_IRQ_EN, 0);
+            write(ALARM0_IRQ_STA, ALARM0_BIT);
+        }
+    }
+
+    /// Handle the RTC alarm interrupt.
+    ///
+    /// Like the `TIMER1` interrupt, this only exists to wake the run loop from
+    /// `wfi`, so it just disables the alarm.
+    pub fn handle_alarm_interrupt() {
+        unsafe {
+            write(ALARM0_ENABLE, 0);
+            write(ALARM0_IRQ_STA, ALARM0_BIT);
+            // Wait for the interrupt to clear to avoid repeat interrupts
+            while read(ALARM0_IRQ_STA) & ALARM0_BIT != 0 {}
+        }
+    }
+}
+
+// === impl RtcTime ===
+
+impl RtcTime {
+    /// Converts the contents of the `RTC_DAY` and `RTC_HH_MM_SS` registers
+    /// (or the equivalent alarm registers) into a time.
+    #[must_use]
+    pub fn from_regs(day: u32, hms: u32) -> Self {
+        let hours = (hms >> 16) & 0x1F;
+        let minutes = (hms >> 8) & 0x3F;
+        let seconds = hms & 0x3F;
+        let secs = (day & 0xFFFF) as u64 * SECS_PER_DAY
+            + hours as u64 * 3600
+            + minutes as u64 * 60
+            + seconds as u64;
+        Self(secs % WRAP_SECS)
+    }
+
+    /// Converts this time into the contents of the `ALARM0_DAY_SET` and
+    /// `ALARM0_HH_MM_SS_SET` registers.
+    #[must_use]
+    pub fn to_regs(self) -> (u32, u32) {
+        let day = (self.0 / SECS_PER_DAY) as u32;
+        let secs = (self.0 % SECS_PER_DAY) as u32;
+        let hms = ((secs / 3600) << 16) | (((secs / 60) % 60) << 8) | (secs % 60);
+        (day, hms)
+    }
+
+    /// Returns the time `secs` seconds after this one, wrapping around.
+    #[must_use]
+    pub fn add_secs(self, secs: u64) -> Self {
+        Self((self.0 + secs % WRAP_SECS) % WRAP_SECS)
+    }
+
+    /// Returns the number of seconds from `earlier` to this time, assuming
+    /// that less than [`WRAP_SECS`] seconds have passed.
+    #[must_use]
+    pub fn secs_since(self, earlier: Self) -> u64 {
+        (self.0 + WRAP_SECS - earlier.0) % WRAP_SECS
+    }
+}
+
+fn reg(offset: usize) -> *mut u32 {
+    (RTC_BASE + offset) as *mut u32
+}
+
+#[inline(always)]
+unsafe fn read(offset: usize) -> u32 {
+    mmio::read(reg(offset))
+}
+
+#[inline(always)]
+unsafe fn write(offset: usize, val: u32) {
+    mmio::write(reg(offset), val)
+}
+
+#[inline(always)]
+unsafe fn modify(offset: usize, f: impl FnOnce(u32) -> u32) {
+    mmio::modify(reg(offset), f)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::time::Duration;
+    use proptest::prelude::*;
+
+    #[test]
+    fn regs_round_trip() {
+        // day 2, 13:45:30
+        let hms = (13 << 16) | (45 << 8) | 30;
+        let time = RtcTime::from_regs(2, hms);
+        assert_eq!(time, RtcTime(2 * SECS_PER_DAY + 13 * 3600 + 45 * 60 + 30));
+        assert_eq!(time.to_regs(), (2, hms));
+    }
+
+    #[test]
+    fn wraps_around() {
+        let last = RtcTime::from_regs(0xFFFF, (23 << 16) | (59 << 8) | 59);
+        let next = last.add_secs(1);
+        assert_eq!(next.to_regs(), (0, 0));
+        assert_eq!(next.secs_since(last), 1);
+        assert_eq!(last.add_secs(WRAP_SECS), last);
+    }
+
+    #[test]
+    fn sleep_secs() {
+        let two_secs = Ticks::from_duration(Duration::from_secs(2)).unwrap();
+        assert_eq!(Rtc::sleep_secs(Ticks::ZERO), None);
+        assert_eq!(Rtc::sleep_secs(Ticks::new(two_secs.get() - 1)), None);
+        assert_eq!(Rtc::sleep_secs(two_secs), Some(2));
+        assert_eq!(Rtc::sleep_secs(Ticks::MAX), Some(u32::MAX));
+    }
+
+    proptest! {
+        #[test]
+        fn add_then_since(start in 0..WRAP_SECS, secs in 0..WRAP_SECS) {
+            let start = RtcTime(start);
+            let end = start.add_secs(secs);
+            prop_assert_eq!(end.secs_since(start), secs);
+            let (day, hms) = end.to_regs();
+            prop_assert!(day <= 0xFFFF);
+            prop_assert_eq!(RtcTime::from_regs(day, hms), end);
+        }
+    }
+}
@@ -0,0 +1,29 @@
+//! The Allwinner D1's Security ID (SID) eFuses.
+//!
+//! The first 128 bits of the eFuse array are a chip ID, which is burned in at
+//! the factory and is unique to each chip, so it can be used as a stable
+//! device ID. The SID block isn't in the PAC, so its registers are accessed
+//! by address.
+#![warn(missing_docs)]
+
+use crate::mmio;
+
+/// The SID's base address.
+const SID_BASE: usize = 0x0300_6000;
+
+/// The offset of the SID's SRAM copy of the eFuse array, which the boot ROM
+/// loads on reset.
+const SID_SRAM: usize = 0x200;
+
+/// Returns the chip's 128-bit unique ID.
+#[must_use]
+pub fn chip_id() -> [u8; 16] {
+    let mut id = [0; 16];
+    for (i, word) in id.chunks_exact_mut(4).enumerate() {
+        // Safety: the SID SRAM is read-only, and reading it has no side
+        // effects.
+        let value = unsafe { mmio::read((SID_BASE + SID_SRAM + i * 4) as *const u32) };
+        word.copy_from_slice(&value.to_le_bytes());
+    }
+    id
+}
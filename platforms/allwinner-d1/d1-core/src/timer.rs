@@ -1,10 +1,23 @@
-use crate::plic::{Plic, Priority};
+use crate::plic::Plic;
 use core::sync::atomic::{AtomicU32, Ordering};
 pub use d1_pac::timer::tmr_ctrl::{
     TMR_CLK_PRES_A as TimerPrescaler, TMR_CLK_SRC_A as TimerSource, TMR_MODE_A as TimerMode,
 };
 use d1_pac::TIMER;
-use kernel::maitake::time::Clock;
+use kernel::{
+    isr::{IsrConfig, Priority},
+    maitake::time::Clock,
+    units,
+};
+
+/// The rate of `TIMER0` and `TIMER1`, which count the 24MHz oscillator
+/// divided by 8.
+pub const TIMER_HZ: u64 = 3_000_000;
+
+/// Ticks of `TIMER0` and `TIMER1`.
+///
+/// `TIMER0` is the kernel's clock, so the kernel's timer counts these ticks.
+pub type Ticks = units::Ticks<TIMER_HZ>;
 
 pub struct Timers {
     pub timer0: Timer0,
@@ -27,13 +40,14 @@ impl Timer0 {
         // Register the interrupt handler for when the timer rolls over.
         unsafe {
             plic.register(Interrupt::TIMER0, Self::maitake_timer_interrupt);
-            plic.activate(Interrupt::TIMER0, Priority::P1).unwrap();
+            plic.activate(Interrupt::TIMER0, IsrConfig::new(Priority::Critical))
+                .unwrap();
         }
 
         // Start the timer counting down from u32::MAX;.
         self.reset();
 
-        Clock::new(core::time::Duration::from_nanos(333), || {
+        Clock::new(Ticks::TICK_DURATION, || {
             let timer0 = unsafe {
                 // Safety: we are just reading the current value and will not be
                 // concurrently mutating the timer.
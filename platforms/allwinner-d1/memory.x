@@ -1,6 +1,7 @@
 MEMORY {
     RAM   : ORIGIN = 0x40000000, LENGTH = 128M
-    AHEAP : ORIGIN = 0x48000000, LENGTH = 384M
+    AHEAP : ORIGIN = 0x48000000, LENGTH = 376M
+    DMA   : ORIGIN = 0x5F800000, LENGTH = 8M
 }
 
 SECTIONS
@@ -11,6 +12,13 @@ SECTIONS
         KEEP(*(.aheap .aheap.*));
         . = ALIGN(8);
     } > AHEAP
+
+    .dma (NOLOAD) : ALIGN(64)
+    {
+        *(.dma .dma.*);
+        KEEP(*(.dma .dma.*));
+        . = ALIGN(64);
+    } > DMA
 }
 
 REGION_ALIAS("REGION_TEXT", RAM)
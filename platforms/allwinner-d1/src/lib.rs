@@ -8,40 +8,72 @@ use self::{
     ccu::Ccu,
     dmac::Dmac,
     drivers::{
+        display_engine::{self, DisplayEngine},
+        gpio::{self, Gpio},
+        ledc::Ledc,
         smhc::Smhc,
+        spi_nor::SpiNor,
         spim::{self, SpiSenderServer},
         twi,
         uart::{self, D1Uart, Uart},
     },
-    plic::{Plic, Priority},
-    timer::{Timer, TimerMode, TimerPrescaler, Timers},
+    plic::Plic,
+    rtc::Rtc,
+    timer::{Ticks, Timer, TimerMode, TimerPrescaler, Timers, TIMER_HZ},
     trap::Trap,
 };
 use core::{
     fmt::Write,
     panic::PanicInfo,
     sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
 };
 use d1_pac::{Interrupt, TIMER};
 use kernel::{
+    isr::{IsrConfig, Priority},
     mnemos_alloc::containers::Box,
+    registry::RegisteredDriver,
+    services::{
+        device_identity::{DeviceId, DeviceIdentityServer},
+        flash::FlashServer,
+        smart_led::StripMetadata,
+    },
     tracing::{self, Instrument},
     Kernel, KernelServiceSettings, KernelSettings,
 };
 pub use mnemos_d1_core::*;
 
 pub use d1_config::PlatformConfig;
-use d1_config::{LedBlinkPin, Mapping};
+use d1_config::{
+    DisplayConfiguration, DisplayOutput, I2cMode, LedBlinkPin, Mapping, SleepTimer,
+    SmartLedConfiguration, SmartLedPin, UartFlowControl, UartFlowControlPin,
+};
+
+const HEAP_SIZE: usize = 376 * 1024 * 1024;
 
-const HEAP_SIZE: usize = 384 * 1024 * 1024;
+/// Memory reserved for DMA buffers and descriptors. See [`initialize_dma`].
+const DMA_SIZE: usize = 8 * 1024 * 1024;
+
+// Fail the build if a driver service defined for this platform has the same
+// UUID as another, or as a kernel service.
+const _: () = kernel::registry::known_uuids::assert_unique(&[
+    <spim::SpiSender as RegisteredDriver>::UUID,
+    #[cfg(feature = "i2c_puppet")]
+    <mnemos_beepy::i2c_puppet::I2cPuppetService as RegisteredDriver>::UUID,
+]);
 
 #[link_section = ".aheap.AHEAP"]
 #[used]
 static AHEAP_BUF: Ram<HEAP_SIZE> = Ram::new();
 
+#[link_section = ".dma.DMA"]
+#[used]
+static DMA_BUF: Ram<DMA_SIZE> = Ram::new();
+
 pub fn kernel_entry(config: mnemos_config::MnemosConfig<PlatformConfig>) -> ! {
     unsafe {
         initialize_heap(&AHEAP_BUF);
+        initialize_dma(&DMA_BUF);
     }
 
     let mut p = unsafe { d1_pac::Peripherals::steal() };
@@ -52,34 +84,99 @@ pub fn kernel_entry(config: mnemos_config::MnemosConfig<PlatformConfig>) -> ! {
     let uart = unsafe { uart::kernel_uart(&mut ccu, &mut p.GPIO, p.UART0) };
     let spim = unsafe { spim::kernel_spim1(p.SPI_DBI, &mut ccu, &mut p.GPIO) };
     let smhc0 = unsafe { Smhc::smhc0(p.SMHC0, &mut ccu, &mut p.GPIO) };
+    let spi_flash = if config.platform.spi_flash.enabled {
+        unsafe { SpiNor::spi0(p.SPI0, &mut ccu, &mut p.GPIO) }
+    } else {
+        None
+    };
+    let gpio = Gpio::new(&mut p.GPIO);
+
+    let uart_settings = uart::D1UartSettings {
+        flow_control: config
+            .platform
+            .uart
+            .flow_control
+            .map(|UartFlowControl { rts, cts }| uart::FlowControl {
+                gpio,
+                rts: uart_flow_control_pin(rts),
+                cts: uart_flow_control_pin(cts),
+            }),
+        ..Default::default()
+    };
 
     let i2c0 = match config.platform.i2c {
         d1_config::I2cConfiguration { enabled: false, .. } => None,
         d1_config::I2cConfiguration {
             enabled: true,
             mapping: Mapping::Twi2,
-        } => unsafe { Some(twi::I2c0::lichee_rv_dock(p.TWI2, &mut ccu, &mut p.GPIO)) },
+            mode,
+        } => unsafe {
+            Some((
+                twi::I2c0::lichee_rv_dock(p.TWI2, &mut ccu, &mut p.GPIO),
+                mode,
+            ))
+        },
         d1_config::I2cConfiguration {
             enabled: true,
             mapping: Mapping::Twi0,
-        } => unsafe { Some(twi::I2c0::mq_pro(p.TWI0, &mut ccu, &mut p.GPIO)) },
+            mode,
+        } => unsafe { Some((twi::I2c0::mq_pro(p.TWI0, &mut ccu, &mut p.GPIO), mode)) },
         d1_config::I2cConfiguration {
             enabled: true,
             mapping,
+            ..
         } => unimplemented!("unsupported I2C0 TWI mapping: {mapping:?}"),
     };
 
+    let smart_led = config.platform.smart_led;
+    let ledc = match smart_led {
+        SmartLedConfiguration { enabled: false, .. } => None,
+        SmartLedConfiguration {
+            pin: SmartLedPin::PC0,
+            ..
+        } => unsafe { Some(Ledc::pc0(p.LEDC, &mut ccu, &mut p.GPIO)) },
+    };
+
+    // The display engine must be initialized before the blink service, which
+    // takes ownership of the GPIO block.
+    let display = &config.platform.display;
+    let display_engine = display.enabled.then(|| unsafe {
+        match display.output {
+            DisplayOutput::Hdmi => DisplayEngine::hdmi(&mut ccu),
+            DisplayOutput::Rgb(t) => DisplayEngine::rgb(
+                &mut ccu,
+                &mut p.GPIO,
+                display_engine::Timing {
+                    pixel_clock_hz: t.pixel_clock_hz,
+                    width: t.width,
+                    height: t.height,
+                    hfront_porch: t.hfront_porch,
+                    hsync_len: t.hsync_len,
+                    hback_porch: t.hback_porch,
+                    vfront_porch: t.vfront_porch,
+                    vsync_len: t.vsync_len,
+                    vback_porch: t.vback_porch,
+                    hsync_active_high: t.hsync_active_high,
+                    vsync_active_high: t.vsync_active_high,
+                },
+            ),
+        }
+    });
+
     #[cfg(feature = "i2c_puppet")]
-    let i2c_puppet_enabled = i2c0.is_some() && config.platform.i2c_puppet.enabled;
+    let i2c_puppet_enabled =
+        matches!(i2c0, Some((_, I2cMode::Controller))) && config.platform.i2c_puppet.enabled;
 
     let timers = Timers::new(p.TIMER);
     let dmac = Dmac::new(p.DMAC, &mut ccu);
     let plic = Plic::new(p.PLIC);
 
-    let d1 = D1::initialize(
+    let mut d1 = D1::initialize(
         timers,
         dmac,
+        gpio,
         uart,
+        uart_settings,
         spim,
         smhc0,
         plic,
@@ -88,13 +185,28 @@ pub fn kernel_entry(config: mnemos_config::MnemosConfig<PlatformConfig>) -> ! {
         config.services,
     );
 
+    if config.platform.sleep_timer == SleepTimer::Rtc {
+        d1.use_rtc_wakeup();
+    }
+
     #[cfg(feature = "i2c_puppet")]
     if i2c_puppet_enabled {
-        i2c_puppet::initialize(config.platform.i2c_puppet, d1.kernel, &p.GPIO, &d1.plic);
+        i2c_puppet::initialize(config.platform.i2c_puppet, d1.kernel, d1.gpio);
+    }
+
+    if let Some(ledc) = ledc {
+        d1.initialize_smart_led(ledc, &smart_led);
+    }
+
+    if let Some(flash) = spi_flash {
+        d1.initialize_spi_flash(flash);
     }
 
     if config.platform.blink_service.enabled {
         let interval = config.platform.blink_service.blink_interval;
+        // no one minds if the LED blinks a little late, so let it share
+        // wakeups with other timers.
+        let slack = interval / 4;
         match config.platform.blink_service.blink_pin {
             LedBlinkPin::PC1 => {
                 p.GPIO.pc_cfg0.modify(|_r, w| {
@@ -114,12 +226,12 @@ pub fn kernel_entry(config: mnemos_config::MnemosConfig<PlatformConfig>) -> ! {
                                 w.pc_dat().variant(0b0000_0010);
                                 w
                             });
-                            d1.kernel.sleep(interval).await;
+                            d1.kernel.sleep_with_slack(interval, slack).await;
                             p.GPIO.pc_dat.modify(|_r, w| {
                                 w.pc_dat().variant(0b0000_0000);
                                 w
                             });
-                            d1.kernel.sleep(interval).await;
+                            d1.kernel.sleep_with_slack(interval, slack).await;
                         }
                     })
                     .unwrap();
@@ -142,12 +254,12 @@ pub fn kernel_entry(config: mnemos_config::MnemosConfig<PlatformConfig>) -> ! {
                                 w.pd_dat().variant(1 << 18);
                                 w
                             });
-                            d1.kernel.sleep(interval).await;
+                            d1.kernel.sleep_with_slack(interval, slack).await;
                             p.GPIO.pd_dat.modify(|_r, w| {
                                 w.pd_dat().variant(0);
                                 w
                             });
-                            d1.kernel.sleep(interval).await;
+                            d1.kernel.sleep_with_slack(interval, slack).await;
                         }
                     })
                     .unwrap();
@@ -155,17 +267,35 @@ pub fn kernel_entry(config: mnemos_config::MnemosConfig<PlatformConfig>) -> ! {
         }
     }
 
-    #[cfg(feature = "sharp-display")]
-    d1.initialize_sharp_display();
+    if let Some(display_engine) = display_engine {
+        d1.initialize_display_engine(display_engine, display);
+    } else {
+        #[cfg(feature = "sharp-display")]
+        d1.initialize_sharp_display(&config.platform.sharp_display);
+    }
 
     d1.run()
 }
 
+fn uart_flow_control_pin(pin: UartFlowControlPin) -> gpio::Pin {
+    let (port, num) = match pin {
+        UartFlowControlPin::PB10 => (gpio::Port::B, 10),
+        UartFlowControlPin::PB11 => (gpio::Port::B, 11),
+        UartFlowControlPin::PB12 => (gpio::Port::B, 12),
+        UartFlowControlPin::PE16 => (gpio::Port::E, 16),
+        UartFlowControlPin::PE17 => (gpio::Port::E, 17),
+    };
+    gpio::Pin::new(port, num).expect("flow control pins are valid")
+}
+
 pub struct D1 {
     pub kernel: &'static Kernel,
     pub timer1: mnemos_d1_core::timer::Timer1,
+    /// If set, long sleeps are woken by the RTC alarm, rather than `TIMER1`.
+    pub rtc: Option<Rtc>,
     pub plic: Plic,
     pub dmac: Dmac,
+    pub gpio: Gpio,
     _uart: Uart,
     _spim: spim::Spim1,
     i2c0_int: Option<(Interrupt, fn())>,
@@ -184,13 +314,15 @@ impl D1 {
     pub fn initialize(
         timers: Timers,
         dmac: Dmac,
+        gpio: Gpio,
         uart: Uart,
+        uart_settings: uart::D1UartSettings,
         spim: spim::Spim1,
         smhc: Smhc,
         plic: Plic,
-        i2c0: Option<twi::I2c0>,
+        i2c0: Option<(twi::I2c0, I2cMode)>,
         kernel_settings: KernelSettings,
-        service_settings: KernelServiceSettings,
+        mut service_settings: KernelServiceSettings,
     ) -> Self {
         let timer0_clock = timers.timer0.into_maitake_clock(&plic);
         let k = unsafe {
@@ -201,28 +333,44 @@ impl D1 {
             .unwrap()
         };
 
+        if service_settings.sermux_gdb.enabled {
+            let port = service_settings.sermux_gdb.port;
+            service_settings.sermux_gdb.target = Some(gdb::TARGET.enable(port));
+        }
+        let identity_settings = service_settings.device_identity.clone();
         k.initialize_default_services(service_settings);
 
-        // Initialize SPI stuff
-        k.initialize(async move {
-            // Register a new SpiSenderServer
-            SpiSenderServer::register(k, dmac, 4).await.unwrap();
-        })
+        // Identify the device by the chip ID in the SID eFuses.
+        k.initialize_step(
+            "device_identity",
+            &[],
+            DeviceIdentityServer::register(k, identity_settings, DeviceId::new(sid::chip_id())),
+        )
         .unwrap();
 
+        // TODO: register an `EntropyServer`. The D1's TRNG is part of its
+        // crypto engine, which has no driver yet, so until then, secure
+        // sermux ports can't be opened on the D1.
+
+        // Initialize SPI stuff
+        // Register a new SpiSenderServer
+        k.initialize_step("spim1", &[], SpiSenderServer::register(k, dmac, 4))
+            .unwrap();
+
         // Initialize SimpleSerial driver
-        k.initialize(async move {
-            D1Uart::register(k, dmac, Default::default()).await.unwrap();
-        })
-        .unwrap();
+        k.initialize_step("uart0", &[], D1Uart::register(k, dmac, uart_settings))
+            .unwrap();
 
         // Initialize the I2C0 TWI
-        let i2c0_int = i2c0.map(|i2c0| {
+        let i2c0_int = i2c0.map(|(i2c0, mode)| {
             let i2c0_int = i2c0.interrupt();
             k.initialize(
-                async {
-                    tracing::debug!("initializing I2C0 TWI...");
-                    i2c0.register(k, 4).await.unwrap();
+                async move {
+                    tracing::debug!(?mode, "initializing I2C0 TWI...");
+                    match mode {
+                        I2cMode::Controller => i2c0.register(k, 4).await.unwrap(),
+                        I2cMode::Target => i2c0.register_target(k, 4).await.unwrap(),
+                    }
                     tracing::info!("I2C0 TWI initialized!");
                 }
                 .instrument(tracing::info_span!("I2C0")),
@@ -232,63 +380,154 @@ impl D1 {
         });
 
         // Initialize SMHC driver
-        k.initialize(async {
-            tracing::debug!("initializing SMHC...");
-            smhc.register(k, 4).await.unwrap();
-            tracing::debug!("SMHC initialized!");
-        })
-        .unwrap();
+        k.initialize_step("smhc0", &[], smhc.register(k, 4))
+            .unwrap();
 
         Self {
             kernel: k,
             _uart: uart,
             _spim: spim,
             timer1: timers.timer1,
+            rtc: None,
             plic,
             dmac,
+            gpio,
             i2c0_int,
         }
     }
 
+    /// Wake the run loop from long sleeps using the RTC alarm, rather than
+    /// `TIMER1`. `TIMER1` is still used for sleeps shorter than the alarm's
+    /// one second resolution.
+    pub fn use_rtc_wakeup(&mut self) {
+        // Safety: nothing else uses the RTC alarm.
+        self.rtc = Some(unsafe { Rtc::new() });
+    }
+
+    /// Registers a [`FlashService`](kernel::services::flash::FlashService)
+    /// server for the SPI NOR flash on `SPI0`, using the flash map written to
+    /// it along with the kernel.
+    ///
+    /// # Panics
+    ///
+    /// If the flash driver task could not be spawned.
+    pub fn initialize_spi_flash(&self, flash: SpiNor) {
+        let k = self.kernel;
+        k.initialize_step("spi_flash", &[], async move {
+            FlashServer::register(k, flash, 4).await.map(drop)
+        })
+        .expect("failed to spawn SPI flash driver");
+    }
+
+    /// Spawns a [`SmartLedService`](kernel::services::smart_led::SmartLedService)
+    /// server driving LEDs connected to the LEDC, and registers the LEDC
+    /// interrupt handler.
+    ///
+    /// # Panics
+    ///
+    /// If the LEDC driver task could not be spawned, or the interrupt could
+    /// not be activated.
+    pub fn initialize_smart_led(&self, ledc: Ledc, config: &SmartLedConfiguration) {
+        let meta = StripMetadata {
+            width: config.width,
+            height: config.height,
+        };
+        self.kernel
+            .initialize_step(
+                "ledc",
+                &[],
+                ledc.register(self.kernel, self.dmac, meta, config.brightness, 4),
+            )
+            .expect("failed to spawn LEDC driver");
+
+        unsafe {
+            self.plic.register(Interrupt::LEDC, Ledc::handle_interrupt);
+            // the LEDC handler only touches its own registers, and wakes the
+            // driver task.
+            let config = IsrConfig::new(Priority::Low).nesting_safe();
+            self.plic.activate(Interrupt::LEDC, config).unwrap();
+        }
+    }
+
     #[cfg(feature = "sharp-display")]
     /// Spawns a SHARP Memory Display driver and a graphical Forth REPL on the Sharp
     /// Memory Display.
     ///
     /// This function requires a SHARP memory display to be connected to the D1's
-    /// SPI_DBI pins (SPI1).
+    /// SPI_DBI pins (SPI1). The display's contents are rotated and scaled
+    /// according to `config`'s transform.
     ///
     /// # Panics
     ///
     /// If the SHARP Memory Display driver or the graphical Forth REPL tasks
     /// could not be spawned.
-    pub fn initialize_sharp_display(&self) {
+    pub fn initialize_sharp_display(&self, config: &d1_config::SharpDisplayConfiguration) {
         use drivers::sharp_display::SharpDisplay;
         use kernel::daemons::shells;
 
         // the `'static` kernel reference is the only thing from `self` that
         // must be moved into the spawned tasks.
         let k = self.kernel;
+        let transform = config.transform;
 
-        let sharp_display = self
-            .kernel
-            .initialize(SharpDisplay::register(k))
+        // the driver and shell are spawned as boot steps, so that the memory
+        // they use is attributed to them.
+        self.kernel
+            .initialize_step("sharp_display", &[], SharpDisplay::register(k, transform))
             .expect("failed to spawn SHARP display driver");
 
-        // spawn Forth shell
+        // spawn Forth shell, once the display driver is up
         self.kernel
-            .initialize(async move {
-                tracing::debug!("waiting for SHARP display driver...");
-                sharp_display
-                    .await
-                    .expect("display driver task isn't cancelled")
-                    .expect("display driver must come up");
-                tracing::debug!("display driver ready!");
-                let settings = shells::GraphicalShellSettings::with_display_size(
-                    SharpDisplay::WIDTH as u32,
-                    SharpDisplay::HEIGHT as u32,
-                );
+            .initialize_step("graphical_shell", &["sharp_display"], async move {
+                let size =
+                    transform.logical_size(SharpDisplay::WIDTH as u32, SharpDisplay::HEIGHT as u32);
+                let settings =
+                    shells::GraphicalShellSettings::with_display_size(size.width, size.height);
                 k.spawn(shells::graphical_shell_mono(k, settings)).await;
                 tracing::info!("graphical shell running.");
+                Ok::<(), core::convert::Infallible>(())
+            })
+            .expect("failed to spawn graphical forth shell");
+    }
+
+    /// Spawns a display engine driver and a graphical Forth REPL on the
+    /// display attached to it.
+    ///
+    /// The display's contents are rotated and scaled according to `config`'s
+    /// transform, and the shell uses `config`'s font size.
+    ///
+    /// # Panics
+    ///
+    /// If the display engine driver or the graphical Forth REPL tasks could
+    /// not be spawned.
+    pub fn initialize_display_engine(
+        &self,
+        display_engine: DisplayEngine,
+        config: &DisplayConfiguration,
+    ) {
+        use kernel::daemons::shells;
+
+        let k = self.kernel;
+        let transform = config.transform;
+        let font = config.font;
+        let timing = *display_engine.timing();
+
+        // the driver and shell are spawned as boot steps, so that the memory
+        // they use is attributed to them.
+        self.kernel
+            .initialize_step("display_engine", &[], display_engine.register(k, transform))
+            .expect("failed to spawn display engine driver");
+
+        // spawn Forth shell, once the display driver is up
+        self.kernel
+            .initialize_step("graphical_shell", &["display_engine"], async move {
+                let size = transform.logical_size(timing.width, timing.height);
+                let mut settings =
+                    shells::GraphicalShellSettings::with_display_size(size.width, size.height);
+                settings.font = font;
+                k.spawn(shells::graphical_shell_mono(k, settings)).await;
+                tracing::info!("graphical shell running.");
+                Ok::<(), core::convert::Infallible>(())
             })
             .expect("failed to spawn graphical forth shell");
     }
@@ -297,8 +536,10 @@ impl D1 {
         let Self {
             kernel: k,
             mut timer1,
+            mut rtc,
             plic,
             dmac: _,
+            gpio: _,
             _uart,
             _spim,
             i2c0_int,
@@ -309,8 +550,10 @@ impl D1 {
         //
         // Both are at a time base of 3M ticks/s.
         //
-        // In the future, we probably want to rework this to use the RTC timer for
-        // both purposes, as this will likely play better with sleep power usage.
+        // If the RTC alarm is enabled, it is used instead of Timer1 for sleeps
+        // long enough for its one second resolution, as it will likely play
+        // better with sleep power usage. In the future, we probably want to
+        // use the RTC for the rolling timer, too.
 
         timer1.set_prescaler(TimerPrescaler::P8);
         timer1.set_mode(TimerMode::SINGLE_COUNTING);
@@ -323,17 +566,30 @@ impl D1 {
 
         unsafe {
             plic.register(Interrupt::TIMER1, Self::timer1_int);
+            if rtc.is_some() {
+                plic.register_vector(rtc::ALARM0_IRQ, Rtc::handle_alarm_interrupt);
+            }
             plic.register(Interrupt::DMAC_NS, Dmac::handle_interrupt);
             plic.register(Interrupt::UART0, D1Uart::handle_uart0_int);
             plic.register(Interrupt::SMHC0, Smhc::handle_smhc0_interrupt);
-
-            plic.activate(Interrupt::DMAC_NS, Priority::P1).unwrap();
-            plic.activate(Interrupt::UART0, Priority::P1).unwrap();
-            plic.activate(Interrupt::SMHC0, Priority::P1).unwrap();
+            Gpio::register_interrupts(&plic);
+
+            plic.activate(Interrupt::DMAC_NS, IsrConfig::DEFAULT)
+                .unwrap();
+            // the UART's RX FIFO overflows if it isn't drained promptly.
+            plic.activate(Interrupt::UART0, IsrConfig::new(Priority::High))
+                .unwrap();
+            // SD card transfers can wait, and the SMHC handler only touches its
+            // own state.
+            plic.activate(
+                Interrupt::SMHC0,
+                IsrConfig::new(Priority::Low).nesting_safe(),
+            )
+            .unwrap();
 
             if let Some((i2c0_int, i2c0_isr)) = i2c0_int {
                 plic.register(i2c0_int, i2c0_isr);
-                plic.activate(i2c0_int, Priority::P1).unwrap();
+                plic.activate(i2c0_int, IsrConfig::DEFAULT).unwrap();
             }
         }
 
@@ -342,36 +598,56 @@ impl D1 {
             let tick = k.tick();
 
             // Timer is downcounting
-            let turn = k.timer().turn();
+            let turn = k.turn_timer();
 
-            // If there is nothing else scheduled, and we didn't just wake something up,
-            // sleep for some amount of time
-            if turn.expired == 0 && !tick.has_remaining {
+            // If there is nothing else scheduled, and we didn't just wake something up
+            // or queue deferred work, sleep for some amount of time
+            if turn.expired == 0 && !tick.has_remaining && k.deferred().is_empty() {
                 // TODO(AJM): Sometimes there is no "next" in the timer wheel, even though there should
                 // be. Don't take lack of timer wheel presence as the ONLY heuristic of whether we
                 // should just wait for SOME interrupt to occur. For now, force a max sleep of 100ms
-                // which is still probably wrong.
-                let amount = turn.ticks_to_next_deadline().unwrap_or(100 * 1000 * 3); // 3 ticks per us, 1000 us per ms, 100ms sleep
-
-                // Don't sleep for too long until james figures out wrapping timers
-                let amount = amount.min(0x4000_0000) as u32;
-                let _ = timer1.get_and_clear_interrupt();
-                unsafe {
-                    plic.activate(Interrupt::TIMER1, Priority::P1).unwrap();
+                // which is still probably wrong. How often this happens is recorded in
+                // `Kernel::timer_stats`.
+                let amount = k
+                    .ticks_to_next_deadline(&turn, Duration::from_millis(100))
+                    .to_ticks::<TIMER_HZ>()
+                    .unwrap_or(Ticks::MAX);
+
+                // Long sleeps are woken by the RTC alarm, if it's enabled.
+                if let Some((rtc, secs)) = rtc
+                    .as_mut()
+                    .and_then(|rtc| Some((rtc, Rtc::sleep_secs(amount)?)))
+                {
+                    rtc.set_alarm_in(secs);
+                    unsafe {
+                        plic.activate_vector(rtc::ALARM0_IRQ, IsrConfig::DEFAULT);
+                        riscv::asm::wfi();
+                    }
+                    // Disable the alarm in case that wasn't what woke us up
+                    plic.deactivate_vector(rtc::ALARM0_IRQ);
+                    rtc.cancel();
+                } else {
+                    // Don't sleep for too long until james figures out wrapping timers
+                    let amount = amount.get().min(0x4000_0000) as u32;
+                    let _ = timer1.get_and_clear_interrupt();
+                    unsafe {
+                        plic.activate(Interrupt::TIMER1, IsrConfig::DEFAULT)
+                            .unwrap();
+                    }
+                    timer1.set_interrupt_en(true);
+                    timer1.start_counter(amount);
+
+                    unsafe {
+                        riscv::asm::wfi();
+                    }
+                    // Disable the timer interrupt in case that wasn't what woke us up
+                    plic.deactivate(Interrupt::TIMER1).unwrap();
+                    timer1.set_interrupt_en(false);
+                    timer1.stop();
                 }
-                timer1.set_interrupt_en(true);
-                timer1.start_counter(amount);
-
-                unsafe {
-                    riscv::asm::wfi();
-                }
-                // Disable the timer interrupt in case that wasn't what woke us up
-                plic.deactivate(Interrupt::TIMER1).unwrap();
-                timer1.set_interrupt_en(false);
-                timer1.stop();
 
                 // Account for time slept
-                let _turn = k.timer().turn();
+                let _turn = k.turn_timer();
             }
         }
     }
@@ -421,6 +697,16 @@ impl D1 {
         // end the SerMux frame so crowtty can decode the panic message as utf8
         uart.write(&[0]);
 
+        // if MMIO tracing is enabled, dump the most recent register accesses,
+        // in case the panic was caused by a bad register sequence.
+        if cfg!(feature = "mmio-trace") {
+            write!(&mut uart, "recent MMIO accesses (oldest first):\r\n").ok();
+            mmio::for_each_recent(|access| {
+                write!(&mut uart, "  {access}\r\n").ok();
+            });
+            uart.write(&[0]);
+        }
+
         write!(
             &mut uart,
             "you've met with a terrible fate, haven't you?\r\n"
@@ -464,17 +750,40 @@ pub unsafe fn initialize_heap<const HEAP_SIZE: usize>(buf: &'static Ram<HEAP_SIZ
         .expect("heap should only be initialized once!");
 }
 
+/// Initialize the region used for DMA allocations.
+///
+/// The D1's data cache is not coherent with DMA, so DMA buffers are allocated
+/// from a separate region, in whole cache lines, and maintained using the
+/// [`cache`] module.
+///
+/// # Safety
+///
+/// Only call this once!
+pub unsafe fn initialize_dma<const DMA_SIZE: usize>(buf: &'static Ram<DMA_SIZE>) {
+    cache::enable_vendor_extensions();
+    kernel::mnemos_alloc::dma::init(
+        NonNull::new(buf.as_ptr()).unwrap(),
+        DMA_SIZE,
+        cache::dma_attributes(),
+    )
+    .expect("DMA region should only be initialized once!");
+}
+
 #[panic_handler]
 fn handler(info: &PanicInfo) -> ! {
     D1::handle_panic(info)
 }
 
 #[export_name = "ExceptionHandler"]
-fn exception_handler(trap_frame: &riscv_rt::TrapFrame) -> ! {
+fn exception_handler(trap_frame: &riscv_rt::TrapFrame) {
     match Trap::from_mcause().expect("mcause should never be invalid") {
         Trap::Interrupt(int) => {
             unreachable!("the exception handler should only recieve exception traps, but got {int}")
         }
+        // If the GDB stub is enabled, let GDB handle breakpoints, and then
+        // resume execution.
+        Trap::Exception(trap::Exception::Breakpoint)
+            if unsafe { gdb::handle_breakpoint(trap_frame) } => {}
         Trap::Exception(exn) => {
             let mepc = riscv::register::mepc::read();
             panic!(
@@ -15,7 +15,11 @@ fn main() -> ! {
 
     let peripherals = Peripherals::take();
     let mut system = peripherals.SYSTEM.split();
+    #[cfg(not(feature = "wifi"))]
     let clocks = ClockControl::boot_defaults(system.clock_control).freeze();
+    // the WiFi radio requires the CPU to run at its maximum clock speed.
+    #[cfg(feature = "wifi")]
+    let clocks = ClockControl::max(system.clock_control).freeze();
 
     // Disable the RTC and TIMG watchdog timers
     let mut rtc = Rtc::new(peripherals.RTC_CNTL);
@@ -46,8 +50,21 @@ fn main() -> ! {
 
     // configure system timer
     let syst = SystemTimer::new(peripherals.SYSTIMER);
+    // Alarm 0 drives esp-wifi's scheduler.
+    #[cfg(feature = "wifi")]
+    mnemos_esp32c3_buddy::spawn_wifi(
+        k,
+        syst.alarm0,
+        peripherals.RNG,
+        system.radio_clock_control,
+        &clocks,
+        peripherals.WIFI,
+    );
     // Alarm 1 will be used to generate "sleep until" interrupts.
     let alarm1 = syst.alarm1;
 
-    mnemos_esp32c3_buddy::run(k, alarm1)
+    // Enter light sleep when idle, waking on UART activity.
+    let light_sleep = mnemos_esp32c3_buddy::sleep::LightSleep::new(Default::default());
+
+    mnemos_esp32c3_buddy::run(k, alarm1, Some(light_sleep))
 }
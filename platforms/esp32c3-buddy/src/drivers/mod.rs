@@ -1,2 +1,4 @@
 pub mod uart;
 pub mod usb_serial;
+#[cfg(feature = "wifi")]
+pub mod wifi;
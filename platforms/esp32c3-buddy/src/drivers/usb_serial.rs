@@ -7,6 +7,7 @@ use kernel::{
     services::simple_serial::{Request, Response, SimpleSerialError, SimpleSerialService},
     Kernel,
 };
+use portable_atomic::{AtomicU64, Ordering};
 
 pub struct UsbSerialServer {
     dev: USB_DEVICE,
@@ -44,6 +45,31 @@ static RX_READY: WaitCell = WaitCell::new();
 /// [1]: https://www.espressif.com/sites/default/files/documentation/esp32-c3_technical_reference_manual_en.pdf#usbserialjtag
 const FIFO_CAPACITY: usize = 64;
 
+/// A USB host sends a start-of-frame packet every millisecond, so if we
+/// haven't seen one in this many kernel clock ticks (10ms), no host is
+/// connected.
+const SOF_TIMEOUT_TICKS: u64 = 80_000;
+
+/// The time of the last start-of-frame packet seen by [`host_connected`], in
+/// kernel clock ticks.
+static LAST_SOF: AtomicU64 = AtomicU64::new(0);
+
+/// Returns `true` if a USB host appears to be connected to the USB
+/// Serial/JTAG peripheral.
+///
+/// This is used to avoid entering light sleep while a host is connected,
+/// since that would drop the connection.
+pub fn host_connected() -> bool {
+    let dev = unsafe { &*USB_DEVICE::PTR };
+    let now = crate::sleep::now();
+    if dev.int_raw.read().sof_int_raw().bit_is_set() {
+        dev.int_clr.write(|w| w.sof_int_clr().set_bit());
+        LAST_SOF.store(now, Ordering::Release);
+        return true;
+    }
+    now.saturating_sub(LAST_SOF.load(Ordering::Acquire)) < SOF_TIMEOUT_TICKS
+}
+
 impl UsbSerialServer {
     pub fn new(dev: USB_DEVICE) -> Self {
         dev.int_ena.modify(|_r, w| {
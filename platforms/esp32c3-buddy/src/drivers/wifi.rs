@@ -0,0 +1,245 @@
+//! WiFi radio support, using [`esp_wifi`].
+//!
+//! This is scaffolding for bringing up the radio: it initializes esp-wifi and
+//! serves the kernel's [`WifiBuddyService`], so that scanning for and joining
+//! networks can be tested before the data path (the [`NetworkService`] and the
+//! link to the CPU) exists. The radio's [`WifiDevice`] is held, but not yet
+//! used.
+//!
+//! This module is only built with the `wifi` feature.
+//!
+//! # Scheduling
+//!
+//! esp-wifi runs the WiFi firmware's internal tasks on its own preemptive
+//! scheduler, which is driven by SYSTIMER alarm 0. The kernel's run loop only
+//! uses alarm 1 (to wake from `wfi`), so the two don't conflict, and the
+//! scheduler's timer interrupt wakes the run loop whenever the radio needs
+//! attention. esp-wifi's async APIs wake kernel tasks from the radio's
+//! interrupt handlers, like any other driver.
+//!
+//! Light sleep gates the radio's clocks, so the driver holds a
+//! [`SleepInhibitor`] for as long as the radio is running. The radio also
+//! requires the CPU to run at its maximum clock speed.
+//!
+//! # Memory
+//!
+//! The WiFi firmware needs much more memory than the rest of MnemOS, so the
+//! [heap](crate::heap) is enlarged when the `wifi` feature is enabled.
+//!
+//! # Entropy
+//!
+//! The ESP32-C3's random number generator only produces true random numbers
+//! while the radio is running; otherwise, its output is predictable. So the
+//! [`RadioRng`] entropy source, for the kernel's [`EntropyServer`], lives
+//! here, and refuses requests until the radio has started.
+//!
+//! [`NetworkService`]: kernel::services::network::NetworkService
+//! [`EntropyServer`]: kernel::services::entropy::EntropyServer
+use core::sync::atomic::{AtomicBool, Ordering};
+use esp32c3_hal::{
+    clock::Clocks,
+    peripherals::{RNG, WIFI},
+    system::RadioClockControl,
+    systimer::{Alarm, Target},
+    Rng,
+};
+use esp_wifi::{
+    wifi::{ClientConfiguration, Configuration, WifiController, WifiDevice, WifiMode},
+    EspWifiInitFor,
+};
+use kernel::{
+    mnemos_alloc::containers::FixedVec,
+    registry::{self, listener::RequestStream},
+    services::{
+        entropy::{EntropyError, EntropySource},
+        wifi_buddy::{AccessPoint, Credentials, Request, Response, WifiBuddyService, WifiError},
+    },
+    Kernel,
+};
+
+use crate::sleep::SleepInhibitor;
+
+/// The most access points returned by a scan.
+const MAX_SCAN_RESULTS: usize = 16;
+
+/// Set once the radio has started, so that the [`RadioRng`] is random.
+static RADIO_STARTED: AtomicBool = AtomicBool::new(false);
+
+pub struct Wifi {
+    controller: WifiController<'static>,
+    /// The data path, which is not yet bridged to a `NetworkService`.
+    _device: WifiDevice<'static>,
+}
+
+/// Random bytes from the RNG peripheral, which are only served while the
+/// radio is running.
+pub struct RadioRng {
+    _p: (),
+}
+
+/// Errors returned by [`Wifi::new`].
+#[derive(Debug)]
+pub enum InitError {
+    /// esp-wifi could not be initialized.
+    Init(esp_wifi::InitializationError),
+    /// The radio could not be put into station mode.
+    Mode(esp_wifi::wifi::WifiError),
+}
+
+impl Wifi {
+    /// Initializes esp-wifi, using `timer` to drive its scheduler, and puts
+    /// the radio into station mode.
+    ///
+    /// `clocks` must be the maximum CPU clock configuration.
+    pub fn new(
+        timer: Alarm<Target, 0>,
+        rng: RNG,
+        radio_clocks: RadioClockControl,
+        clocks: &Clocks,
+        wifi: WIFI,
+    ) -> Result<Self, InitError> {
+        let init = esp_wifi::initialize(
+            EspWifiInitFor::Wifi,
+            timer,
+            Rng::new(rng),
+            radio_clocks,
+            clocks,
+        )
+        .map_err(InitError::Init)?;
+        let (device, controller) =
+            esp_wifi::wifi::new_with_mode(&init, wifi, WifiMode::Sta).map_err(InitError::Mode)?;
+        Ok(Self {
+            controller,
+            _device: device,
+        })
+    }
+
+    /// Registers the [`WifiBuddyService`], and spawns a task that starts the
+    /// radio and serves requests.
+    pub async fn register(self, k: &'static Kernel) -> Result<(), registry::RegistrationError> {
+        let reqs = k
+            .registry()
+            .bind_konly::<WifiBuddyService>(4)
+            .await?
+            .into_request_stream(4)
+            .await;
+        k.spawn(self.serve(reqs)).await;
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "Wifi::serve", level = "info", skip_all)]
+    async fn serve(mut self, reqs: RequestStream<WifiBuddyService>) {
+        if let Err(error) = self.controller.start().await {
+            tracing::error!(?error, "failed to start WiFi radio");
+            return;
+        }
+        let _inhibit = SleepInhibitor::new();
+        RADIO_STARTED.store(true, Ordering::Release);
+        tracing::info!("WiFi radio started");
+
+        loop {
+            let req = reqs.next_request().await;
+            let resp = match req.msg.body {
+                Request::Scan => self.scan().await.map(Response::Scan),
+                Request::Connect(ref creds) => {
+                    self.connect(creds).await.map(|()| Response::Connected)
+                }
+                Request::Disconnect => self.disconnect().await.map(|()| Response::Disconnected),
+            };
+            let resp = req.msg.reply_with(resp);
+            let _ = req.reply.reply_konly(resp).await;
+        }
+    }
+
+    async fn scan(&mut self) -> Result<FixedVec<AccessPoint>, WifiError> {
+        let (found, _total) =
+            self.controller
+                .scan_n::<MAX_SCAN_RESULTS>()
+                .await
+                .map_err(|error| {
+                    tracing::warn!(?error, "WiFi scan failed");
+                    WifiError::Scan
+                })?;
+
+        let mut aps = FixedVec::new(found.len()).await;
+        for ap in &found {
+            let mut ssid = heapless::String::new();
+            // esp-wifi's SSIDs have the same maximum length as ours.
+            let _ = ssid.push_str(ap.ssid.as_str());
+            let _ = aps.try_push(AccessPoint {
+                ssid,
+                bssid: ap.bssid,
+                channel: ap.channel,
+                rssi: ap.signal_strength,
+            });
+        }
+        tracing::debug!(found = aps.len(), "WiFi scan complete");
+        Ok(aps)
+    }
+
+    async fn connect(&mut self, creds: &Credentials) -> Result<(), WifiError> {
+        if matches!(self.controller.is_connected(), Ok(true)) {
+            self.disconnect().await?;
+        }
+
+        let config = Configuration::Client(ClientConfiguration {
+            ssid: creds.ssid.as_str().into(),
+            password: creds.password.as_str().into(),
+            ..Default::default()
+        });
+        self.controller
+            .set_configuration(&config)
+            .map_err(|error| {
+                tracing::warn!(?error, "invalid WiFi configuration");
+                WifiError::InvalidCredentials
+            })?;
+        self.controller.connect().await.map_err(|error| {
+            tracing::warn!(ssid = %creds.ssid, ?error, "failed to join WiFi network");
+            WifiError::Connect
+        })?;
+        tracing::info!(ssid = %creds.ssid, "joined WiFi network");
+        Ok(())
+    }
+
+    async fn disconnect(&mut self) -> Result<(), WifiError> {
+        self.controller.disconnect().await.map_err(|error| {
+            tracing::warn!(?error, "failed to leave WiFi network");
+            WifiError::Disconnect
+        })
+    }
+}
+
+// === impl RadioRng ===
+
+impl RadioRng {
+    /// The RNG peripheral's data register. The peripheral itself is owned by
+    /// esp-wifi, so the register is read by address.
+    const DATA: *const u32 = 0x6002_60B0 as *const u32;
+
+    #[must_use]
+    pub fn new() -> Self {
+        Self { _p: () }
+    }
+}
+
+impl Default for RadioRng {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EntropySource for RadioRng {
+    fn fill(&mut self, buf: &mut [u8]) -> Result<(), EntropyError> {
+        if !RADIO_STARTED.load(Ordering::Acquire) {
+            tracing::warn!("WiFi radio isn't running, refusing to generate random bytes");
+            return Err(EntropyError::Unavailable);
+        }
+        for chunk in buf.chunks_mut(4) {
+            // Safety: reading the data register has no side effects, other
+            // than producing the next random number.
+            let value = unsafe { Self::DATA.read_volatile() }.to_ne_bytes();
+            chunk.copy_from_slice(&value[..chunk.len()]);
+        }
+        Ok(())
+    }
+}
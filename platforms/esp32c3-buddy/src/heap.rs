@@ -6,7 +6,15 @@ use kernel::mnemos_alloc::heap::{MnemosAlloc, UnderlyingAllocator};
 #[global_allocator]
 static AHEAP: MnemosAlloc<UnderlyingEspHeap> = MnemosAlloc::new();
 
-pub const HEAP_SIZE: usize = 1024 * 32;
+/// The size of the heap.
+///
+/// The WiFi firmware needs much more memory than the rest of MnemOS, so the
+/// heap is enlarged when the `wifi` feature is enabled.
+pub const HEAP_SIZE: usize = if cfg!(feature = "wifi") {
+    1024 * 96
+} else {
+    1024 * 32
+};
 
 /// Initialize the heap.
 pub fn init() {
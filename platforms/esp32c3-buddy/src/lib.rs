@@ -3,9 +3,11 @@ extern crate alloc;
 
 pub mod drivers;
 pub mod heap;
+pub mod sleep;
 
 use critical_section::Mutex;
 use esp32c3_hal::{
+    efuse::Efuse,
     interrupt,
     peripherals::{self, Interrupt},
     prelude::*,
@@ -22,17 +24,10 @@ static ALARM1: Mutex<RefCell<Option<Alarm<Target, 1>>>> = Mutex::new(RefCell::ne
 
 pub fn init() -> &'static Kernel {
     let k_settings = KernelSettings { max_drivers: 16 };
-    let clock = {
-        // the system timer has a period of `SystemTimer::TICKS_PER_SECOND` ticks.
-        // `TICKS_PER_SECOND` is 16_000_000, so the base granularity is
-        // 62.5ns. let's multiply it by 2 so that we have a non-fractional
-        // number of nanoseconds.
-        maitake::time::Clock::new(
-            Duration::from_nanos(125),
-            || SystemTimer::now() / 2u64, // well...that was easy!
-        )
-        .named("CLOCK_SYSTEM_TIMER_NOW")
-    };
+    // the system timer stops during light sleep, so the clock includes the
+    // time spent asleep. see `sleep::now` for details.
+    let clock = maitake::time::Clock::new(sleep::ClockTicks::TICK_DURATION, sleep::now)
+        .named("CLOCK_SYSTEM_TIMER_NOW");
     unsafe {
         Box::into_raw(Kernel::new(k_settings, clock).expect("cannot initialize kernel"))
             .as_ref()
@@ -41,44 +36,106 @@ pub fn init() -> &'static Kernel {
 }
 
 pub fn spawn_daemons(k: &'static Kernel) {
-    // initialize tracing first, so we can trace the boot process.
-    k.initialize(async move {
-        use kernel::serial_trace;
-        let trace_settings = serial_trace::SerialTraceSettings::default()
-            // our heap is only 32KB, so allocate a much smaller trace buffer.
-            .with_tracebuf_capacity(1024);
-        serial_trace::SerialSubscriber::start(k, trace_settings).await;
-    })
-    .expect("failed to spawn serial tracing daemon");
-
-    // Initialize the SerialMuxServer
-    k.initialize(services::serial_mux::SerialMuxServer::register(
+    // Identify the device by its factory MAC address.
+    k.initialize(services::device_identity::DeviceIdentityServer::register(
         k,
         Default::default(),
+        services::device_identity::DeviceId::from_mac(Efuse::get_mac_address()),
     ))
-    .expect("failed to spawn SerialMuxService initialization");
+    .expect("failed to spawn DeviceIdentityService initialization");
+}
 
-    // Initialize Serial Mux daemons.
-    k.initialize(daemons::sermux::hello(k, Default::default()))
-        .expect("failed to spawn default serial mux service initialization");
+/// Initializes the WiFi radio, and spawns the [`WifiBuddyService`] for it.
+///
+/// The radio is also the source of the [`EntropyServer`]'s random bytes, so
+/// without the `wifi` feature, no entropy server is registered.
+///
+/// [`WifiBuddyService`]: kernel::services::wifi_buddy::WifiBuddyService
+/// [`EntropyServer`]: kernel::services::entropy::EntropyServer
+#[cfg(feature = "wifi")]
+pub fn spawn_wifi(
+    k: &'static Kernel,
+    timer: Alarm<Target, 0>,
+    rng: peripherals::RNG,
+    radio_clocks: system::RadioClockControl,
+    clocks: &esp32c3_hal::clock::Clocks,
+    wifi: peripherals::WIFI,
+) {
+    let wifi = drivers::wifi::Wifi::new(timer, rng, radio_clocks, clocks, wifi)
+        .expect("failed to initialize WiFi radio");
+    k.initialize(wifi.register(k))
+        .expect("failed to spawn WiFi driver initialization");
+    k.initialize(services::entropy::EntropyServer::register(
+        k,
+        Default::default(),
+        drivers::wifi::RadioRng::new(),
+    ))
+    .expect("failed to spawn EntropyService initialization");
 }
 
+/// Spawns the USB serial driver, and shares the serial port between the
+/// kernel's services using [`SerialMuxServer`].
+///
+/// Binary tracing is served on the [`WellKnown::BinaryTracing`] port, so that
+/// the buddy can be debugged with `crowtty` over the same USB cable used to
+/// flash it. Our heap is tiny, so the sermux and trace buffers are much smaller
+/// than the kernel's defaults.
+///
+/// [`SerialMuxServer`]: services::serial_mux::SerialMuxServer
+/// [`WellKnown::BinaryTracing`]: services::serial_mux::WellKnown::BinaryTracing
 pub fn spawn_serial(
     k: &'static Kernel,
     dev: peripherals::USB_DEVICE,
     pcc: &mut system::PeripheralClockControl,
 ) {
+    use kernel::serial_trace;
+    use services::serial_mux::{SerialMuxServer, SerialMuxSettings};
+
     pcc.enable(system::Peripheral::Sha);
 
+    // initialize tracing first, so we can trace the boot process.
+    k.initialize(async move {
+        let trace_settings = serial_trace::SerialTraceSettings::default()
+            .with_sendbuf_capacity(512)
+            .with_tracebuf_capacity(1024);
+        let subscriber = serial_trace::SerialSubscriber::start(k, trace_settings).await;
+        tracing::subscriber::set_global_default(subscriber)
+            .expect("no other tracing subscriber should be set");
+    })
+    .expect("failed to spawn serial tracing daemon");
+
     // spawn SimpleSerial service
     k.initialize(drivers::usb_serial::UsbSerialServer::new(dev).register(k, 512, 512))
         .expect("failed to spawn UsbSerialServer!");
 
     interrupt::enable(Interrupt::USB_DEVICE, interrupt::Priority::Priority1)
         .expect("failed to enable USB_DEVICE interrupt");
+
+    // Initialize the SerialMuxServer
+    let sermux_settings = SerialMuxSettings {
+        enabled: true,
+        max_ports: 4,
+        max_jumbo: 1024,
+        ..Default::default()
+    };
+    k.initialize(SerialMuxServer::register(k, sermux_settings))
+        .expect("failed to spawn SerialMuxService initialization");
+
+    // Initialize Serial Mux daemons.
+    k.initialize(daemons::sermux::hello(k, Default::default()))
+        .expect("failed to spawn default serial mux service initialization");
 }
 
-pub fn run(k: &'static Kernel, alarm1: Alarm<Target, 1>) -> ! {
+/// Runs the kernel.
+///
+/// If `light_sleep` is provided, the chip enters light sleep when there is
+/// nothing to do until the next timer deadline. Otherwise, it waits for an
+/// interrupt with `wfi`.
+pub fn run(
+    k: &'static Kernel,
+    alarm1: Alarm<Target, 1>,
+    mut light_sleep: Option<sleep::LightSleep>,
+) -> ! {
     // Alarm 1 will be used to generate "sleep until" interrupts.
     critical_section::with(|cs| {
         ALARM1.borrow_ref_mut(cs).replace(alarm1);
@@ -95,7 +152,7 @@ pub fn run(k: &'static Kernel, alarm1: Alarm<Target, 1>) -> ! {
     loop {
         tracing::debug!("tick");
         let tick = k.tick();
-        let turn = k.timer().turn();
+        let turn = k.turn_timer();
 
         // If there is nothing else scheduled, and we didn't just wake something up,
         // sleep for some amount of time
@@ -104,14 +161,30 @@ pub fn run(k: &'static Kernel, alarm1: Alarm<Target, 1>) -> ! {
             // be. Don't take lack of timer wheel presence as the ONLY heuristic of whether we
             // should just wait for SOME interrupt to occur. For now, force a max sleep of 100ms
             // which is still probably wrong.
-            let amount = turn.ticks_to_next_deadline().unwrap_or(800_000); // 100 ms / 125 ms ticks = 800,000
+            let amount = k
+                .ticks_to_next_deadline(&turn, Duration::from_millis(100))
+                .to_ticks::<{ sleep::CLOCK_HZ }>()
+                .unwrap_or(sleep::ClockTicks::MAX);
+
+            // If the next deadline is far enough away, and nothing needs us
+            // to stay awake, enter light sleep.
+            if let Some(light_sleep) = light_sleep.as_mut() {
+                if light_sleep.should_sleep(amount) && light_sleep.sleep(amount) {
+                    // Account for time slept
+                    let _turn = k.turn_timer();
+                    continue;
+                }
+            }
 
             // TODO(eliza): what is the max duration of the C3's timer?
             critical_section::with(|cs| {
                 let mut alarm1 = ALARM1.borrow_ref_mut(cs);
                 let alarm1 = alarm1.as_mut().unwrap();
                 alarm1.clear_interrupt();
-                alarm1.set_target(SystemTimer::now() + (amount * 2));
+                let amount = amount
+                    .convert::<{ sleep::SYSTIMER_HZ }>()
+                    .unwrap_or(sleep::SysTimerTicks::MAX);
+                alarm1.set_target(SystemTimer::now().saturating_add(amount.get()));
                 alarm1.interrupt_enable(true);
             });
 
@@ -128,7 +201,7 @@ pub fn run(k: &'static Kernel, alarm1: Alarm<Target, 1>) -> ! {
             });
 
             // Account for time slept
-            let _turn = k.timer().turn();
+            let _turn = k.turn_timer();
         }
     }
 }
@@ -0,0 +1,298 @@
+//! Light sleep support.
+//!
+//! When the kernel has nothing to do until a timer deadline that is far
+//! enough away, the run loop puts the ESP32-C3 into light sleep, rather than
+//! just waiting for an interrupt with `wfi`. In light sleep, the CPU and most
+//! digital peripherals are clock gated and the crystal oscillator is powered
+//! down, so the system timer stops counting. The time spent asleep is measured
+//! using the RTC timer, which keeps running from the RTC slow clock, and is
+//! added to the kernel's clock on wakeup (see [`now`]).
+//!
+//! The chip is woken by the RTC timer when the next deadline arrives, or
+//! earlier by activity on UART0's RX line or a GPIO pin, if configured.
+//!
+//! Light sleep is skipped while anything holds a [`SleepInhibitor`] (such as
+//! a WiFi driver with traffic in flight), and while a USB host is connected,
+//! since light sleep gates the USB Serial/JTAG peripheral's clock and would
+//! drop the host's connection.
+//!
+//! # Notes
+//!
+//! Power domains are left in their reset configuration, so this saves less
+//! power than ESP-IDF's light sleep, which also powers down the RF circuitry
+//! and some memories.
+//!
+//! Waking on UART activity consumes the first few edges on the RX line, so
+//! the first byte(s) sent to a sleeping board may be lost.
+use core::time::Duration;
+use esp32c3_hal::{
+    peripherals::{GPIO, RTC_CNTL, TIMG0, UART0},
+    systimer::SystemTimer,
+};
+use kernel::units::Ticks;
+use portable_atomic::{AtomicU64, AtomicUsize, Ordering};
+
+/// The rate of the system timer.
+pub(crate) const SYSTIMER_HZ: u64 = 16_000_000;
+
+/// The rate of the kernel's clock, which counts every other system timer
+/// tick. See [`now`].
+pub const CLOCK_HZ: u64 = SYSTIMER_HZ / 2;
+
+/// Ticks of the kernel's clock.
+pub type ClockTicks = Ticks<CLOCK_HZ>;
+
+/// Ticks of the system timer.
+pub(crate) type SysTimerTicks = Ticks<SYSTIMER_HZ>;
+
+/// The kernel clock's tick period, in picoseconds.
+const CLOCK_TICK_PS: u64 = 1_000_000_000_000 / CLOCK_HZ;
+
+/// Kernel clock ticks spent in light sleep, during which the system timer was
+/// stopped.
+static SLEPT_TICKS: AtomicU64 = AtomicU64::new(0);
+
+/// The number of live [`SleepInhibitor`]s.
+static INHIBITORS: AtomicUsize = AtomicUsize::new(0);
+
+/// Light sleep configuration.
+#[derive(Debug, Clone, Copy)]
+pub struct LightSleepConfig {
+    /// Only enter light sleep if the next timer deadline is at least this
+    /// far away. Otherwise, the run loop waits with `wfi`, as entering and
+    /// leaving light sleep takes some time. Defaults to 20ms.
+    pub min_sleep: Duration,
+    /// Wake on activity on UART0's RX line. Defaults to `true`.
+    pub uart_wakeup: bool,
+    /// Wake when a GPIO pin is at a given level. Defaults to `None`.
+    pub gpio_wakeup: Option<GpioWakeup>,
+}
+
+/// A GPIO pin that wakes the chip from light sleep.
+#[derive(Debug, Clone, Copy)]
+pub struct GpioWakeup {
+    /// The GPIO pin number.
+    pub pin: u8,
+    /// Wake when the pin is at this level.
+    pub level: WakeLevel,
+}
+
+/// The level of a [`GpioWakeup`] pin.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WakeLevel {
+    Low,
+    High,
+}
+
+/// Puts the chip into light sleep from the run loop.
+pub struct LightSleep {
+    min_ticks: ClockTicks,
+    wakeup_mask: u32,
+    /// The calibrated period of the RTC slow clock, in picoseconds.
+    slow_clk_period_ps: u64,
+}
+
+/// Prevents the run loop from entering light sleep while it is held.
+///
+/// Drivers which must not be clock gated while they are busy (for example,
+/// a WiFi stack with a connection in progress) should hold one of these while
+/// busy.
+#[must_use = "sleep is only inhibited while the `SleepInhibitor` is held"]
+pub struct SleepInhibitor(());
+
+// RTC_CNTL_WAKEUP_STATE_REG wakeup source bits
+const GPIO_TRIG_EN: u32 = 1 << 2;
+const TIMER_TRIG_EN: u32 = 1 << 3;
+const UART0_TRIG_EN: u32 = 1 << 6;
+
+// RTC_CNTL_SLP_TIMER1_REG
+const MAIN_TIMER_ALARM_EN: u32 = 1 << 16;
+
+/// The number of RTC slow clock cycles to count when calibrating it.
+const CAL_CYCLES: u16 = 1024;
+/// The period of the 40MHz crystal, in picoseconds.
+const XTAL_PERIOD_PS: u64 = 25_000;
+
+/// The number of UART RX edges which wake the chip.
+const UART_WAKEUP_EDGES: u16 = 3;
+
+/// Returns the current time, in kernel clock ticks, including any time spent
+/// in light sleep.
+///
+/// This is used as the kernel's [`Clock`](kernel::maitake::time::Clock).
+pub fn now() -> u64 {
+    // the system timer has a period of `SystemTimer::TICKS_PER_SECOND` ticks.
+    // `TICKS_PER_SECOND` is 16_000_000, so the base granularity is
+    // 62.5ns. let's multiply it by 2 so that we have a non-fractional
+    // number of nanoseconds.
+    SystemTimer::now() / 2 + SLEPT_TICKS.load(Ordering::Acquire)
+}
+
+// === impl LightSleep ===
+
+impl LightSleep {
+    /// Configures the wakeup sources in `config`, and calibrates the RTC slow
+    /// clock, which is used to measure time spent asleep.
+    pub fn new(config: LightSleepConfig) -> Self {
+        let mut wakeup_mask = TIMER_TRIG_EN;
+
+        if config.uart_wakeup {
+            let uart0 = unsafe { &*UART0::PTR };
+            uart0
+                .sleep_conf
+                .write(|w| unsafe { w.active_threshold().bits(UART_WAKEUP_EDGES) });
+            wakeup_mask |= UART0_TRIG_EN;
+        }
+
+        if let Some(GpioWakeup { pin, level }) = config.gpio_wakeup {
+            let gpio = unsafe { &*GPIO::PTR };
+            let int_type = match level {
+                WakeLevel::Low => 4,
+                WakeLevel::High => 5,
+            };
+            gpio.pin[pin as usize].modify(|_, w| unsafe {
+                w.pin_int_type()
+                    .bits(int_type)
+                    .pin_wakeup_enable()
+                    .set_bit()
+            });
+            wakeup_mask |= GPIO_TRIG_EN;
+        }
+
+        let slow_clk_period_ps = calibrate_slow_clock();
+        tracing::info!(?config, slow_clk_period_ps, "light sleep enabled");
+
+        Self {
+            min_ticks: ClockTicks::from_duration_saturating(config.min_sleep),
+            wakeup_mask,
+            slow_clk_period_ps,
+        }
+    }
+
+    /// Returns `true` if the run loop should enter light sleep for `ticks`,
+    /// rather than waiting with `wfi`.
+    pub fn should_sleep(&self, ticks: ClockTicks) -> bool {
+        ticks >= self.min_ticks
+            && INHIBITORS.load(Ordering::Acquire) == 0
+            && !crate::drivers::usb_serial::host_connected()
+    }
+
+    /// Enters light sleep for up to `ticks`, returning once the chip wakes
+    /// up.
+    ///
+    /// Returns `false` if the hardware rejected the sleep request, in which
+    /// case no time was spent asleep.
+    pub fn sleep(&mut self, ticks: ClockTicks) -> bool {
+        let rtc = unsafe { &*RTC_CNTL::PTR };
+
+        let slow_ticks = ticks.get().saturating_mul(CLOCK_TICK_PS) / self.slow_clk_period_ps;
+        let start = rtc_time();
+        let wake_at = start + slow_ticks;
+        rtc.slp_timer0.write(|w| unsafe { w.bits(wake_at as u32) });
+        rtc.slp_timer1
+            .write(|w| unsafe { w.bits(((wake_at >> 32) as u32 & 0xffff) | MAIN_TIMER_ALARM_EN) });
+        rtc.int_clr_rtc.write(|w| {
+            w.slp_reject_int_clr()
+                .set_bit()
+                .slp_wakeup_int_clr()
+                .set_bit()
+        });
+        rtc.wakeup_state
+            .modify(|_, w| unsafe { w.wakeup_ena().bits(self.wakeup_mask) });
+
+        // Go to sleep. The CPU stalls here until a wakeup source fires.
+        rtc.state0.modify(|_, w| w.sleep_en().set_bit());
+        let rejected = loop {
+            let raw = rtc.int_raw_rtc.read();
+            if raw.slp_wakeup_int_raw().bit_is_set() {
+                break false;
+            }
+            if raw.slp_reject_int_raw().bit_is_set() {
+                break true;
+            }
+        };
+        rtc.int_clr_rtc.write(|w| {
+            w.slp_reject_int_clr()
+                .set_bit()
+                .slp_wakeup_int_clr()
+                .set_bit()
+        });
+
+        // Account for the time the system timer was stopped.
+        let slept = (rtc_time() - start) * self.slow_clk_period_ps / CLOCK_TICK_PS;
+        SLEPT_TICKS.fetch_add(slept, Ordering::Release);
+        tracing::trace!(ticks, slept, rejected, "woke from light sleep");
+
+        !rejected
+    }
+}
+
+impl Default for LightSleepConfig {
+    fn default() -> Self {
+        Self {
+            min_sleep: Duration::from_millis(20),
+            uart_wakeup: true,
+            gpio_wakeup: None,
+        }
+    }
+}
+
+// === impl SleepInhibitor ===
+
+impl SleepInhibitor {
+    /// Inhibits light sleep until the returned `SleepInhibitor` is dropped.
+    pub fn new() -> Self {
+        INHIBITORS.fetch_add(1, Ordering::AcqRel);
+        Self(())
+    }
+}
+
+impl Default for SleepInhibitor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for SleepInhibitor {
+    fn drop(&mut self) {
+        INHIBITORS.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Reads the RTC timer, which counts RTC slow clock cycles.
+fn rtc_time() -> u64 {
+    let rtc = unsafe { &*RTC_CNTL::PTR };
+    rtc.time_update.write(|w| w.time_update().set_bit());
+    let lo = rtc.time_low0.read().bits() as u64;
+    let hi = rtc.time_high0.read().bits() as u64;
+    (hi << 32) | lo
+}
+
+/// Measures the period of the RTC slow clock against the crystal, in
+/// picoseconds.
+///
+/// The RTC slow clock is an RC oscillator, whose frequency varies by a few
+/// percent between chips and with temperature, so it must be calibrated
+/// before it can be used to measure time.
+fn calibrate_slow_clock() -> u64 {
+    let timg0 = unsafe { &*TIMG0::PTR };
+    timg0.rtccalicfg.modify(|_, w| unsafe {
+        // 0 selects the clock currently used as the RTC slow clock.
+        w.rtc_cali_clk_sel()
+            .bits(0)
+            .rtc_cali_max()
+            .bits(CAL_CYCLES)
+            .rtc_cali_start_cycling()
+            .clear_bit()
+            .rtc_cali_start()
+            .clear_bit()
+    });
+    timg0.rtccalicfg.modify(|_, w| w.rtc_cali_start().set_bit());
+    while timg0.rtccalicfg.read().rtc_cali_rdy().bit_is_clear() {}
+    let xtal_cycles = timg0.rtccalicfg1.read().rtc_cali_value().bits() as u64;
+    timg0
+        .rtccalicfg
+        .modify(|_, w| w.rtc_cali_start().clear_bit());
+
+    xtal_cycles * XTAL_PERIOD_PS / CAL_CYCLES as u64
+}
@@ -2,18 +2,22 @@
 //!
 //! Separate crate so it can be used from the build.rs script
 
-use std::{net::SocketAddr, time::Duration};
+use std::{net::SocketAddr, path::PathBuf, time::Duration};
 
-use mnemos_kernel::forth::Params;
+use mnemos_kernel::{
+    forth::Params,
+    services::emb_display::{fonts::FontSize, DisplayTransform},
+};
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct PlatformConfig {
     /// TCP simulated uart driver settings
     ///
-    /// If this field is None, then the tcp uart service will not
-    /// be spawned.
-    pub tcp_uart: TcpUartConfig,
+    /// A simulated UART is spawned for each enabled entry. At most one entry
+    /// may be in [`TcpUartMode::Sermux`] mode.
+    #[serde(default)]
+    pub tcp_uart: Vec<TcpUartConfig>,
 
     /// Embedded Graphics Simulator display settings
     ///
@@ -27,15 +31,30 @@ pub struct PlatformConfig {
     /// be spawned.
     pub forth_shell: ForthShell,
 
+    /// Prometheus metrics server settings
+    #[serde(default)]
+    pub metrics: MetricsConfig,
+
     /// The maximum amount of time to sleep before repolling the
     /// executor (even if no simulated IRQs are received)
     pub sleep_cap: Option<Duration>,
+
+    /// How many times faster than the host's clock the kernel's clock runs.
+    ///
+    /// For example, with a factor of 60, an hour of kernel time passes in a
+    /// minute of real time. Defaults to 1 (no warping).
+    #[serde(default = "PlatformConfig::default_time_warp")]
+    pub time_warp: f64,
 }
 
 impl PlatformConfig {
     pub const fn default_sleep_cap() -> Duration {
         Duration::from_millis(100)
     }
+
+    pub const fn default_time_warp() -> f64 {
+        1.0
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -57,6 +76,24 @@ pub struct TcpUartConfig {
     /// For example: "127.0.0.1:9999"
     #[serde(default = "TcpUartConfig::default_socket_addr")]
     pub socket_addr: SocketAddr,
+    /// What the UART is attached to, one of "sermux" or "raw"
+    #[serde(default)]
+    pub mode: TcpUartMode,
+    /// Forth shell parameters, for a UART in [`TcpUartMode::Raw`] mode
+    #[serde(default = "TcpUartConfig::default_forth_params")]
+    pub forth_params: Params,
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TcpUartMode {
+    /// The UART is served as the kernel's `SimpleSerialService`, which the
+    /// serial mux attaches to.
+    #[default]
+    Sermux,
+    /// A Forth shell is run directly on the UART, as plain text, like a
+    /// console on a UART without the serial mux.
+    Raw,
 }
 
 impl TcpUartConfig {
@@ -77,6 +114,38 @@ impl TcpUartConfig {
     fn default_socket_addr() -> SocketAddr {
         Self::DEFAULT_SOCKET_ADDR_STR.parse().unwrap()
     }
+    const fn default_forth_params() -> Params {
+        Params::new()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MetricsConfig {
+    /// Should kernel statistics be served as Prometheus metrics?
+    #[serde(default)]
+    pub enabled: bool,
+    /// Socket addr to serve metrics on, at the `/metrics` path
+    ///
+    /// For example: "127.0.0.1:9185"
+    #[serde(default = "MetricsConfig::default_socket_addr")]
+    pub socket_addr: SocketAddr,
+}
+
+impl MetricsConfig {
+    pub const DEFAULT_SOCKET_ADDR_STR: &str = "127.0.0.1:9185";
+
+    fn default_socket_addr() -> SocketAddr {
+        Self::DEFAULT_SOCKET_ADDR_STR.parse().unwrap()
+    }
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            socket_addr: Self::default_socket_addr(),
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -91,8 +160,41 @@ pub struct DisplayConfig {
     #[serde(default = "DisplayConfig::default_frames_per_second")]
     pub frames_per_second: usize,
     /// The scaling of the display
+    ///
+    /// This scales the simulator window, and is not visible to clients of the
+    /// display service. To scale the display's contents, use `transform`.
     #[serde(default = "DisplayConfig::default_scaling")]
     pub scaling: u32,
+    /// Rotation and scaling of the display's contents, applied by the
+    /// display service's clients.
+    #[serde(default)]
+    pub transform: DisplayTransform,
+    /// Should a clickable on-screen keyboard be drawn below the display?
+    ///
+    /// Clicked keys are published to the keyboard mux service.
+    #[serde(default)]
+    pub keyboard_panel: bool,
+    /// The longest a draw request should wait for the display to reply.
+    ///
+    /// In debug builds, slower replies are traced as warnings, including the
+    /// request that was slow. If this field is None, replies aren't checked.
+    #[serde(default)]
+    pub max_latency: Option<Duration>,
+    /// Should the display be rendered without a window?
+    ///
+    /// A headless display renders into an off-screen buffer, so that the
+    /// display service (and everything that draws with it) can run without a
+    /// display server. Keyboard and mouse input on the window, and the
+    /// on-screen keyboard, aren't available.
+    #[serde(default)]
+    pub headless: bool,
+    /// A directory to save rendered frames to, as PNG images.
+    ///
+    /// If this is set, each frame that changes the display is saved as
+    /// `frame-NNNNNN.png`, numbered in the order it was rendered. This works
+    /// with or without a window.
+    #[serde(default)]
+    pub dump_frames: Option<PathBuf>,
 }
 
 impl DisplayConfig {
@@ -122,6 +224,9 @@ pub struct ForthShell {
     /// Forth shell parameters
     #[serde(default = "ForthShell::default_params")]
     pub params: Params,
+    /// Font size, one of "small", "medium", or "large"
+    #[serde(default)]
+    pub font: FontSize,
 }
 
 impl ForthShell {
@@ -0,0 +1,331 @@
+//! Fault injection for chaos testing.
+//!
+//! Melpomene can inject faults into the simulated hardware, to test that the
+//! kernel and its services recover from failures that are hard to provoke on
+//! real hardware. The following faults are supported:
+//!
+//! - **Allocation failures**: a percentage of allocations made by the kernel
+//!   fail, as though the heap were out of memory (see
+//!   [`mnemos_alloc::heap::fault`]).
+//! - **Serial delays and drops**: data written to or read from the simulated
+//!   TCP UART (and therefore, the frames of the serial mux) can be delayed, or
+//!   a percentage of it dropped.
+//! - **Display stalls**: the simulated display can delay servicing each
+//!   request, or stop servicing requests entirely.
+//!
+//! Faults are initially configured using command-line flags (see
+//! [`FaultOpts`]), and can be changed while the simulator is running by
+//! connecting to the control port (`--chaos-addr`) and sending commands, one
+//! per line:
+//!
+//! ```text
+//! alloc-fail <PERCENT>
+//! sermux-delay <MILLISECONDS>
+//! sermux-drop <PERCENT>
+//! display-delay <MILLISECONDS>
+//! display-stall <on|off>
+//! status
+//! reset
+//! ```
+//!
+//! Each command is answered with a single line, which is either `ok`, the
+//! current fault configuration (for `status`), or `error: ` followed by a
+//! description of the error.
+use std::{
+    fmt,
+    net::SocketAddr,
+    sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicU8, Ordering},
+    time::{Duration, SystemTime},
+};
+
+use mnemos_alloc::heap::fault;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+};
+use tracing::{info, info_span, warn, Instrument};
+
+/// The faults currently being injected.
+pub static FAULTS: Faults = Faults::new();
+
+/// Command-line flags for configuring fault injection.
+#[derive(Debug, clap::Args)]
+#[clap(
+    next_help_heading = "FAULT INJECTION OPTIONS",
+    group = clap::ArgGroup::new("fault-opts")
+)]
+pub struct FaultOpts {
+    /// Address to listen for fault injection commands on.
+    ///
+    /// If this is not set, faults can only be configured on the command line.
+    #[clap(long = "chaos-addr")]
+    pub chaos_addr: Option<SocketAddr>,
+
+    /// Percentage of kernel heap allocations which fail.
+    #[clap(long = "alloc-fail-percent", default_value_t = 0)]
+    pub alloc_fail_percent: u8,
+
+    /// Seed for the random number generators used to inject faults.
+    ///
+    /// If this is not set, the current time is used.
+    #[clap(long = "chaos-seed")]
+    pub seed: Option<u32>,
+
+    /// Milliseconds to delay each chunk of data sent or received over the
+    /// simulated UART.
+    #[clap(long = "sermux-delay-ms", default_value_t = 0)]
+    pub sermux_delay_ms: u64,
+
+    /// Percentage of chunks of data sent or received over the simulated UART
+    /// which are dropped.
+    #[clap(long = "sermux-drop-percent", default_value_t = 0)]
+    pub sermux_drop_percent: u8,
+
+    /// Milliseconds to delay servicing each request to the simulated display.
+    #[clap(long = "display-delay-ms", default_value_t = 0)]
+    pub display_delay_ms: u64,
+}
+
+/// The set of faults being injected into the simulator.
+#[derive(Debug)]
+pub struct Faults {
+    sermux_delay_ms: AtomicU64,
+    sermux_drop_percent: AtomicU8,
+    display_delay_ms: AtomicU64,
+    display_stalled: AtomicBool,
+    rng: AtomicU32,
+}
+
+#[derive(Debug, Eq, PartialEq)]
+enum Command {
+    AllocFail(u8),
+    SermuxDelay(u64),
+    SermuxDrop(u8),
+    DisplayDelay(u64),
+    DisplayStall(bool),
+    Status,
+    Reset,
+}
+
+// === impl FaultOpts ===
+
+impl FaultOpts {
+    /// Applies the configured faults, and spawns the control port listener,
+    /// if one was requested.
+    ///
+    /// This must be called from within a Tokio runtime.
+    pub fn setup_faults(&self) {
+        let seed = self.seed.unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap()
+                .subsec_nanos()
+        });
+        FAULTS.seed(seed);
+        fault::seed(seed);
+
+        fault::set_failure_rate(self.alloc_fail_percent);
+        FAULTS
+            .sermux_delay_ms
+            .store(self.sermux_delay_ms, Ordering::Release);
+        FAULTS
+            .sermux_drop_percent
+            .store(self.sermux_drop_percent.min(100), Ordering::Release);
+        FAULTS
+            .display_delay_ms
+            .store(self.display_delay_ms, Ordering::Release);
+        info!(seed, faults = %FAULTS, "fault injection configured");
+
+        if let Some(addr) = self.chaos_addr {
+            tokio::spawn(serve(addr).instrument(info_span!("Chaos", %addr)));
+        }
+    }
+}
+
+// === impl Faults ===
+
+impl Faults {
+    const fn new() -> Self {
+        Self {
+            sermux_delay_ms: AtomicU64::new(0),
+            sermux_drop_percent: AtomicU8::new(0),
+            display_delay_ms: AtomicU64::new(0),
+            display_stalled: AtomicBool::new(false),
+            rng: AtomicU32::new(0x9E37_79B9),
+        }
+    }
+
+    /// Returns how long each chunk of UART data should be delayed, if at all.
+    pub fn sermux_delay(&self) -> Option<Duration> {
+        match self.sermux_delay_ms.load(Ordering::Acquire) {
+            0 => None,
+            ms => Some(Duration::from_millis(ms)),
+        }
+    }
+
+    /// Returns `true` if the next chunk of UART data should be dropped.
+    pub fn should_drop_sermux(&self) -> bool {
+        self.roll(self.sermux_drop_percent.load(Ordering::Acquire))
+    }
+
+    /// Returns how long the display should wait before servicing the next
+    /// request, if at all.
+    pub fn display_delay(&self) -> Option<Duration> {
+        match self.display_delay_ms.load(Ordering::Acquire) {
+            0 => None,
+            ms => Some(Duration::from_millis(ms)),
+        }
+    }
+
+    /// Returns `true` if the display should not service requests at all.
+    pub fn display_stalled(&self) -> bool {
+        self.display_stalled.load(Ordering::Acquire)
+    }
+
+    fn seed(&self, seed: u32) {
+        // xorshift gets stuck at zero.
+        self.rng.store(seed.max(1), Ordering::Release);
+    }
+
+    /// Returns `true` with a probability of `percent`%.
+    fn roll(&self, percent: u8) -> bool {
+        if percent == 0 {
+            return false;
+        }
+        let mut x = self.rng.load(Ordering::Relaxed);
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.rng.store(x, Ordering::Relaxed);
+        x % 100 < percent as u32
+    }
+
+    fn apply(&self, cmd: Command) {
+        match cmd {
+            Command::AllocFail(percent) => fault::set_failure_rate(percent),
+            Command::SermuxDelay(ms) => self.sermux_delay_ms.store(ms, Ordering::Release),
+            Command::SermuxDrop(percent) => self
+                .sermux_drop_percent
+                .store(percent.min(100), Ordering::Release),
+            Command::DisplayDelay(ms) => self.display_delay_ms.store(ms, Ordering::Release),
+            Command::DisplayStall(stalled) => {
+                self.display_stalled.store(stalled, Ordering::Release)
+            }
+            Command::Reset => {
+                fault::set_failure_rate(0);
+                self.sermux_delay_ms.store(0, Ordering::Release);
+                self.sermux_drop_percent.store(0, Ordering::Release);
+                self.display_delay_ms.store(0, Ordering::Release);
+                self.display_stalled.store(false, Ordering::Release);
+            }
+            Command::Status => {}
+        }
+    }
+}
+
+impl fmt::Display for Faults {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "alloc-fail={}% (injected {}) sermux-delay={}ms sermux-drop={}% \
+            display-delay={}ms display-stall={}",
+            fault::failure_rate(),
+            fault::injected_failures(),
+            self.sermux_delay_ms.load(Ordering::Acquire),
+            self.sermux_drop_percent.load(Ordering::Acquire),
+            self.display_delay_ms.load(Ordering::Acquire),
+            if self.display_stalled.load(Ordering::Acquire) {
+                "on"
+            } else {
+                "off"
+            },
+        )
+    }
+}
+
+// === impl Command ===
+
+impl Command {
+    fn parse(line: &str) -> Result<Self, String> {
+        let mut words = line.split_whitespace();
+        let cmd = words.next().ok_or_else(|| "empty command".to_string())?;
+        let mut arg = || {
+            words
+                .next()
+                .ok_or_else(|| format!("{cmd}: missing argument"))
+        };
+        let percent = |s: &str| match s.parse::<u8>() {
+            Ok(p) if p <= 100 => Ok(p),
+            _ => Err(format!("{cmd}: invalid percentage {s:?}")),
+        };
+        let ms = |s: &str| {
+            s.parse::<u64>()
+                .map_err(|_| format!("{cmd}: invalid duration {s:?}"))
+        };
+
+        match cmd {
+            "alloc-fail" => percent(arg()?).map(Self::AllocFail),
+            "sermux-delay" => ms(arg()?).map(Self::SermuxDelay),
+            "sermux-drop" => percent(arg()?).map(Self::SermuxDrop),
+            "display-delay" => ms(arg()?).map(Self::DisplayDelay),
+            "display-stall" => match arg()? {
+                "on" => Ok(Self::DisplayStall(true)),
+                "off" => Ok(Self::DisplayStall(false)),
+                s => Err(format!("{cmd}: expected \"on\" or \"off\", not {s:?}")),
+            },
+            "status" => Ok(Self::Status),
+            "reset" => Ok(Self::Reset),
+            _ => Err(format!("unknown command {cmd:?}")),
+        }
+    }
+}
+
+async fn serve(addr: SocketAddr) {
+    let listener = match TcpListener::bind(addr).await {
+        Ok(listener) => listener,
+        Err(error) => {
+            warn!(%error, "failed to bind fault injection control port");
+            return;
+        }
+    };
+    info!("fault injection control port listening on {addr}");
+
+    loop {
+        match listener.accept().await {
+            Ok((stream, addr)) => {
+                tokio::spawn(
+                    handle_client(stream).instrument(info_span!("client", client.addr = %addr)),
+                );
+            }
+            Err(error) => {
+                warn!(%error, "error accepting fault injection connection");
+                return;
+            }
+        }
+    }
+}
+
+async fn handle_client(stream: TcpStream) {
+    let (rx, mut tx) = stream.into_split();
+    let mut lines = BufReader::new(rx).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let rsp = match Command::parse(line) {
+            Ok(Command::Status) => FAULTS.to_string(),
+            Ok(cmd) => {
+                info!(?cmd, "changing injected faults");
+                FAULTS.apply(cmd);
+                "ok".to_string()
+            }
+            Err(error) => format!("error: {error}"),
+        };
+
+        if tx.write_all(format!("{rsp}\n").as_bytes()).await.is_err() {
+            return;
+        }
+    }
+}
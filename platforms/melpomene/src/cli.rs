@@ -1,4 +1,6 @@
-use crate::sim_tracing;
+use std::path::PathBuf;
+
+use crate::{chaos, sim_tracing};
 use clap::Parser;
 
 #[derive(Parser, Debug)]
@@ -6,4 +8,44 @@ use clap::Parser;
 pub struct Args {
     #[clap(flatten)]
     pub tracing: sim_tracing::TracingOpts,
+
+    #[clap(flatten)]
+    pub faults: chaos::FaultOpts,
+
+    #[clap(flatten)]
+    pub display: DisplayOpts,
+
+    /// Run the kernel's clock this many times faster than real time,
+    /// overriding `platform.time_warp` in `melpo.toml`.
+    ///
+    /// For example, with `--time-warp 60`, an hour of kernel time passes in a
+    /// minute.
+    #[clap(long = "time-warp", value_name = "FACTOR")]
+    pub time_warp: Option<f64>,
+}
+
+/// Command-line flags for the simulated display, which override the settings
+/// in `melpo.toml`.
+#[derive(Debug, clap::Args)]
+#[clap(next_help_heading = "DISPLAY OPTIONS")]
+pub struct DisplayOpts {
+    /// Render the display without a window.
+    ///
+    /// This allows Melpomene to run without a display server, such as in CI.
+    #[clap(long)]
+    pub headless: bool,
+
+    /// A directory to save each rendered frame to, as a PNG image.
+    #[clap(long = "dump-frames", value_name = "DIR")]
+    pub dump_frames: Option<PathBuf>,
+}
+
+impl DisplayOpts {
+    /// Applies these flags to the display's configuration.
+    pub fn apply(self, config: &mut melpo_config::DisplayConfig) {
+        config.headless |= self.headless;
+        if self.dump_frames.is_some() {
+            config.dump_frames = self.dump_frames;
+        }
+    }
 }
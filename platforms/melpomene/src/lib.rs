@@ -1,3 +1,6 @@
+pub mod chaos;
 pub mod cli;
+pub mod metrics;
 pub mod sim_drivers;
 pub mod sim_tracing;
+pub mod time_warp;
@@ -1,16 +1,22 @@
-use std::{alloc::System, sync::Arc};
+use std::{alloc::System, rc::Rc, sync::Arc};
 
 use clap::Parser;
 use futures::FutureExt;
-use melpo_config::PlatformConfig;
+use melpo_config::{PlatformConfig, TcpUartMode};
 use melpomene::{
-    cli,
-    sim_drivers::{emb_display::SimDisplay, tcp_serial::TcpSerial},
+    chaos, cli,
+    metrics::Metrics,
+    sim_drivers::{emb_display::SimDisplay, entropy::HostEntropy, tcp_serial::TcpSerial},
+    time_warp::TimeWarp,
 };
 use mnemos_alloc::heap::MnemosAlloc;
 use mnemos_kernel::{
-    daemons::shells::{graphical_shell_mono, GraphicalShellSettings},
-    maitake, Kernel,
+    daemons::{
+        sermux::HeapStats,
+        shells::{graphical_shell_mono, GraphicalShellSettings},
+    },
+    services::entropy::EntropyServer,
+    Kernel,
 };
 use tokio::{
     task,
@@ -24,19 +30,38 @@ fn main() {
     let args = cli::Args::parse();
     args.tracing.setup_tracing();
     let _span = tracing::info_span!("Melpo").entered();
-    run_melpomene();
+    run_melpomene(args.faults, args.display, args.time_warp);
 }
 
+// The simulator has no DMA, so `mnemos_alloc::dma` allocations simply come
+// from this heap, too.
 #[global_allocator]
 static AHEAP: MnemosAlloc<System> = MnemosAlloc::new();
 
+/// Reports the simulated heap's statistics to the kernel's debug agent.
+fn heap_stats() -> HeapStats {
+    let state = AHEAP.state();
+    HeapStats {
+        total_bytes: state.total_bytes,
+        allocated_bytes: state.allocated_bytes,
+        alloc_success_count: state.alloc_success_count,
+        alloc_oom_count: state.alloc_oom_count,
+        dealloc_count: state.dealloc_count,
+    }
+}
+
 #[tokio::main(flavor = "current_thread")]
-async fn run_melpomene() {
+async fn run_melpomene(
+    faults: chaos::FaultOpts,
+    display: cli::DisplayOpts,
+    time_warp: Option<f64>,
+) {
+    faults.setup_faults();
     let local = tokio::task::LocalSet::new();
     println!("========================================");
     local
         .run_until(async move {
-            let kernel = task::spawn_local(kernel_entry());
+            let kernel = task::spawn_local(kernel_entry(display, time_warp));
             tracing::info!("Kernel started.");
 
             println!("========================================");
@@ -55,24 +80,17 @@ async fn run_melpomene() {
 }
 
 #[tracing::instrument(name = "Kernel", level = "info")]
-async fn kernel_entry() {
-    let config = mnemos_config::include_config!(PlatformConfig).unwrap();
+async fn kernel_entry(display: cli::DisplayOpts, time_warp: Option<f64>) {
+    let mut config = mnemos_config::include_config!(PlatformConfig).unwrap();
+    display.apply(&mut config.platform.display);
 
     tracing::info!(
         settings = ?config,
         "Loaded settings",
     );
 
-    let clock = {
-        use std::time::{Duration, SystemTime};
-        maitake::time::Clock::new(Duration::from_micros(1), || {
-            SystemTime::now()
-                .duration_since(SystemTime::UNIX_EPOCH)
-                .unwrap()
-                .as_micros() as u64
-        })
-        .named("CLOCK_SYSTEMTIME_NOW")
-    };
+    let warp = TimeWarp::init(time_warp.unwrap_or(config.platform.time_warp));
+    let clock = TimeWarp::clock();
     let k = unsafe {
         let kernel = Kernel::new(config.kernel, clock).unwrap();
         mnemos_alloc::containers::Box::into_raw(kernel)
@@ -80,18 +98,42 @@ async fn kernel_entry() {
             .unwrap()
     };
 
+    // The host's clock stands in for a real-time clock.
+    k.set_wall_clock(
+        std::time::SystemTime::now()
+            .duration_since(std::time::SystemTime::UNIX_EPOCH)
+            .unwrap(),
+    );
+
     // Simulates the kernel main loop being woken by an IRQ.
     let irq = Arc::new(tokio::sync::Notify::new());
 
-    // Initialize the UART
-    if config.platform.tcp_uart.enabled {
+    // Initialize the UARTs
+    let tcp_uarts = config
+        .platform
+        .tcp_uart
+        .into_iter()
+        .filter(|tcp_uart| tcp_uart.enabled)
+        .collect::<Vec<_>>();
+    assert!(
+        tcp_uarts
+            .iter()
+            .filter(|tcp_uart| tcp_uart.mode == TcpUartMode::Sermux)
+            .count()
+            <= 1,
+        "at most one TCP UART may be in sermux mode"
+    );
+    if tcp_uarts.is_empty() {
+        tracing::warn!("Not spawning TCP UART server!");
+    }
+    for tcp_uart in tcp_uarts {
         k.initialize({
             let irq = irq.clone();
-            let tcp_uart = config.platform.tcp_uart;
             let socket_addr = tcp_uart.socket_addr;
             async move {
                 // Set up the bidirectional, async bbqueue channel between the TCP port
-                // (acting as a serial port) and the virtual serial port mux.
+                // (acting as a serial port) and the virtual serial port mux, or
+                // a raw Forth shell.
                 //
                 // Create the buffer, and spawn the worker task, giving it one of the
                 // queue handles
@@ -101,15 +143,20 @@ async fn kernel_entry() {
             }
         })
         .unwrap();
-    } else {
-        tracing::warn!("Not spawning TCP UART server!");
     }
 
     let mut debounce_period = Duration::from_millis(50);
+    let display_size = config
+        .platform
+        .display
+        .transform
+        .logical_size(DISPLAY_WIDTH_PX, DISPLAY_HEIGHT_PX);
 
     // Spawn the graphics driver
     if config.platform.display.enabled {
-        debounce_period = Duration::from_secs(1) / config.platform.display.frames_per_second as u32;
+        // the shell redraws at most once per frame, which is in host time.
+        debounce_period = warp
+            .to_kernel(Duration::from_secs(1) / config.platform.display.frames_per_second as u32);
         k.initialize(async move {
             SimDisplay::register(
                 k,
@@ -125,15 +172,30 @@ async fn kernel_entry() {
         tracing::warn!("Not spawning graphics driver!");
     }
 
-    k.initialize_default_services(config.services);
+    // Serve random bytes from the host, for secure sermux ports
+    k.initialize(EntropyServer::register(k, Default::default(), HostEntropy))
+        .unwrap();
+
+    let mut services = config.services;
+    services.sermux_debug.heap_stats = Some(heap_stats);
+    k.initialize_default_services(services);
+
+    // Serve kernel stats to Prometheus
+    let metrics = Rc::new(Metrics::default());
+    task::spawn_local(
+        metrics
+            .clone()
+            .serve(k, config.platform.metrics, heap_stats),
+    );
 
     // Spawn a graphical shell
     if config.platform.forth_shell.enabled {
         let mut guish =
-            GraphicalShellSettings::with_display_size(DISPLAY_WIDTH_PX, DISPLAY_HEIGHT_PX);
+            GraphicalShellSettings::with_display_size(display_size.width, display_size.height);
         let forth_shell = config.platform.forth_shell;
         guish.capacity = forth_shell.capacity;
         guish.forth_settings = forth_shell.params;
+        guish.font = forth_shell.font;
         guish.redraw_debounce = debounce_period;
         k.initialize(graphical_shell_mono(k, guish)).unwrap();
     } else {
@@ -143,14 +205,15 @@ async fn kernel_entry() {
     let sleep_cap = config
         .platform
         .sleep_cap
-        .unwrap_or_else(PlatformConfig::default_sleep_cap)
-        .as_micros() as u64;
+        .unwrap_or_else(PlatformConfig::default_sleep_cap);
     loop {
         // Tick the scheduler
+        let tick_start = tokio::time::Instant::now();
         let tick = k.tick();
+        metrics.record_tick(tick_start.elapsed());
 
         // advance the timer (don't take more than 500k years)
-        let turn = k.timer().turn();
+        let turn = k.turn_timer();
         tracing::trace!(?turn, "turned the wheel");
 
         // If there is nothing else scheduled, and we didn't just wake something up,
@@ -162,8 +225,9 @@ async fn kernel_entry() {
             // hardware platform waiting for an interrupt.
             tracing::trace!("waiting for an interrupt...");
 
-            let amount = turn.ticks_to_next_deadline().unwrap_or(sleep_cap);
-            tracing::trace!("next timer expires in {amount:?}us");
+            let amount = k.ticks_to_next_deadline(&turn, sleep_cap).to_duration();
+            tracing::trace!("next timer expires in {amount:?}");
+            let amount = warp.to_host(amount);
             // wait for an "interrupt"
             futures::select! {
                 _ = irq.notified().fuse() => {
@@ -172,7 +236,7 @@ async fn kernel_entry() {
                         "...woken by I/O interrupt",
                     );
                },
-               _ = tokio::time::sleep(Duration::from_micros(amount)).fuse() => {
+               _ = tokio::time::sleep(amount).fuse() => {
                     tracing::trace!(
                         slept_for = ?wfi_start.elapsed(),
                         "woken by timer",
@@ -181,7 +245,7 @@ async fn kernel_entry() {
             }
 
             // Account for time slept
-            let turn = k.timer().turn();
+            let turn = k.turn_timer();
             tracing::trace!(?turn, "turned the wheel");
         } else {
             // let other tokio tasks (simulated hardware devices) run.
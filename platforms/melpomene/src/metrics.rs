@@ -0,0 +1,331 @@
+//! Exports kernel statistics as [Prometheus] metrics over HTTP.
+//!
+//! When enabled, Melpomene serves the same statistics that the kernel's
+//! SerMux debug agent reports to `mnemos-dbg` (heap usage, task counts, boot
+//! step states, connection queue depths, and userspace ring stats), plus a histogram of how
+//! long each scheduler tick took, in the Prometheus text exposition format.
+//! This makes it possible to watch a long-running simulation for leaks and
+//! regressions with a Prometheus server and a Grafana dashboard.
+//!
+//! Metrics are served from `GET /metrics` on the address configured by
+//! [`MetricsConfig::socket_addr`]. For example:
+//!
+//! ```text
+//! $ curl http://127.0.0.1:9185/metrics
+//! ```
+//!
+//! [Prometheus]: https://prometheus.io/docs/instrumenting/exposition_formats/
+use std::{cell::Cell, fmt::Write, rc::Rc, time::Duration};
+
+use melpo_config::MetricsConfig;
+use mnemos_kernel::{daemons::sermux::HeapStats, registry::ServiceInfo, Kernel};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    task,
+};
+
+/// Upper bounds of the tick duration histogram's buckets, in seconds.
+const TICK_BUCKETS: [f64; 9] = [
+    0.000_01, 0.000_05, 0.000_1, 0.000_5, 0.001, 0.005, 0.01, 0.05, 0.1,
+];
+
+/// The largest HTTP request header that will be read.
+const MAX_REQUEST_LEN: usize = 4096;
+
+/// Statistics recorded by Melpomene itself, rather than the kernel.
+///
+/// This is shared between the simulator's main loop, which records how long
+/// each tick took, and the metrics server. Both run on the same thread, so no
+/// synchronization is needed.
+#[derive(Default)]
+pub struct Metrics {
+    /// Cumulative counts of ticks at or below each of [`TICK_BUCKETS`].
+    tick_buckets: [Cell<u64>; TICK_BUCKETS.len()],
+    tick_count: Cell<u64>,
+    tick_seconds: Cell<f64>,
+}
+
+// === impl Metrics ===
+
+impl Metrics {
+    /// Records that a scheduler tick took `elapsed`.
+    pub fn record_tick(&self, elapsed: Duration) {
+        let secs = elapsed.as_secs_f64();
+        for (bucket, &le) in self.tick_buckets.iter().zip(&TICK_BUCKETS) {
+            if secs <= le {
+                bucket.set(bucket.get() + 1);
+            }
+        }
+        self.tick_count.set(self.tick_count.get() + 1);
+        self.tick_seconds.set(self.tick_seconds.get() + secs);
+    }
+
+    /// Spawns the metrics HTTP server, if it is enabled by `config`.
+    ///
+    /// `heap_stats` reports the simulator's heap statistics, in the same way
+    /// as it does for the kernel's debug agent.
+    pub async fn serve(
+        self: Rc<Self>,
+        kernel: &'static Kernel,
+        config: MetricsConfig,
+        heap_stats: fn() -> HeapStats,
+    ) {
+        if !config.enabled {
+            tracing::debug!("Not spawning Prometheus metrics server");
+            return;
+        }
+
+        let listener = match TcpListener::bind(config.socket_addr).await {
+            Ok(listener) => listener,
+            Err(error) => {
+                tracing::error!(%error, addr = %config.socket_addr, "failed to bind metrics server");
+                return;
+            }
+        };
+        tracing::info!(
+            "Serving Prometheus metrics on http://{}/metrics",
+            config.socket_addr
+        );
+
+        loop {
+            let (conn, peer) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(error) => {
+                    tracing::warn!(%error, "failed to accept metrics connection");
+                    continue;
+                }
+            };
+            let metrics = self.clone();
+            task::spawn_local(async move {
+                if let Err(error) = metrics.respond(kernel, heap_stats, conn).await {
+                    tracing::debug!(%peer, %error, "metrics connection failed");
+                }
+            });
+        }
+    }
+
+    async fn respond(
+        &self,
+        kernel: &'static Kernel,
+        heap_stats: fn() -> HeapStats,
+        mut conn: TcpStream,
+    ) -> std::io::Result<()> {
+        // Read the request line and headers. The body (if any) is ignored.
+        let mut req = Vec::new();
+        let mut buf = [0u8; 512];
+        while !req.windows(4).any(|w| w == b"\r\n\r\n") && req.len() < MAX_REQUEST_LEN {
+            let n = conn.read(&mut buf).await?;
+            if n == 0 {
+                break;
+            }
+            req.extend_from_slice(&buf[..n]);
+        }
+
+        let request_line = req.split(|&b| b == b'\r').next().unwrap_or_default();
+        let rsp = if request_line.starts_with(b"GET /metrics ") {
+            let body = self.render(kernel, heap_stats).await;
+            format!(
+                "HTTP/1.1 200 OK\r\n\
+                Content-Type: text/plain; version=0.0.4\r\n\
+                Content-Length: {}\r\n\
+                Connection: close\r\n\r\n{body}",
+                body.len(),
+            )
+        } else {
+            "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string()
+        };
+        conn.write_all(rsp.as_bytes()).await?;
+        conn.shutdown().await
+    }
+
+    /// Renders a snapshot of every metric.
+    async fn render(&self, kernel: &'static Kernel, heap_stats: fn() -> HeapStats) -> String {
+        let mut out = Exposition::default();
+
+        let heap = heap_stats();
+        out.metric("mnemos_heap_size_bytes", "gauge", "Total size of the heap.")
+            .sample(heap.total_bytes);
+        out.metric(
+            "mnemos_heap_allocated_bytes",
+            "gauge",
+            "Memory currently allocated from the heap.",
+        )
+        .sample(heap.allocated_bytes);
+        out.metric(
+            "mnemos_heap_allocations_total",
+            "counter",
+            "Successful heap allocations.",
+        )
+        .sample(heap.alloc_success_count);
+        out.metric(
+            "mnemos_heap_deallocations_total",
+            "counter",
+            "Heap deallocations.",
+        )
+        .sample(heap.dealloc_count);
+        out.metric(
+            "mnemos_heap_oom_total",
+            "counter",
+            "Heap allocations which failed due to insufficient space.",
+        )
+        .sample(heap.alloc_oom_count);
+
+        let tasks = kernel.task_counts();
+        out.metric(
+            "mnemos_tasks_spawned_total",
+            "counter",
+            "Tasks spawned on the kernel's scheduler.",
+        )
+        .sample(tasks.spawned);
+        out.metric(
+            "mnemos_tasks_completed_total",
+            "counter",
+            "Tasks which have run to completion.",
+        )
+        .sample(tasks.completed);
+        out.metric(
+            "mnemos_tasks_live",
+            "gauge",
+            "Tasks which have not completed.",
+        )
+        .sample(tasks.spawned.saturating_sub(tasks.completed));
+
+        let boot = kernel.boot_progress().summary().await;
+        out.metric(
+            "mnemos_boot_steps",
+            "gauge",
+            "Named boot steps in each state.",
+        );
+        for (state, count) in [
+            ("pending", boot.pending),
+            ("running", boot.running),
+            ("complete", boot.complete),
+            ("failed", boot.failed),
+            ("blocked", boot.blocked),
+        ] {
+            let _ = writeln!(out.buf, "mnemos_boot_steps{{state=\"{state}\"}} {count}");
+        }
+
+        out.metric(
+            "mnemos_uptime_seconds",
+            "gauge",
+            "Time since the kernel was created.",
+        )
+        .sample(kernel.uptime().as_secs_f64());
+        out.metric(
+            "mnemos_scheduler_ticks_total",
+            "counter",
+            "Times the kernel's scheduler has been ticked.",
+        )
+        .sample(kernel.tick_count());
+        self.render_ticks(&mut out);
+
+        let mut services = Vec::new();
+        kernel
+            .registry()
+            .for_each_service(|svc| services.push(*svc))
+            .await;
+        render_channels(&mut out, &services);
+
+        let rings = kernel.rings().stats();
+        out.metric(
+            "mnemos_ring_processes",
+            "gauge",
+            "Userspace processes with registered rings.",
+        )
+        .sample(rings.processes);
+        out.metric(
+            "mnemos_ring_polls_total",
+            "counter",
+            "Times the userspace rings have been polled.",
+        )
+        .sample(rings.polls);
+        out.metric(
+            "mnemos_ring_budget_exhausted_total",
+            "counter",
+            "Ring polls which used their whole budget while requests were waiting.",
+        )
+        .sample(rings.exhausted);
+
+        out.buf
+    }
+
+    fn render_ticks(&self, out: &mut Exposition) {
+        let name = "mnemos_tick_duration_seconds";
+        out.metric(
+            name,
+            "histogram",
+            "How long each tick of the kernel's scheduler took.",
+        );
+        for (bucket, le) in self.tick_buckets.iter().zip(TICK_BUCKETS) {
+            let _ = writeln!(out.buf, "{name}_bucket{{le=\"{le}\"}} {}", bucket.get());
+        }
+        let count = self.tick_count.get();
+        let _ = writeln!(out.buf, "{name}_bucket{{le=\"+Inf\"}} {count}");
+        let _ = writeln!(out.buf, "{name}_sum {}", self.tick_seconds.get());
+        let _ = writeln!(out.buf, "{name}_count {count}");
+    }
+}
+
+fn render_channels(out: &mut Exposition, services: &[ServiceInfo]) {
+    let per_service = |out: &mut Exposition, value: fn(&ServiceInfo) -> usize| {
+        for svc in services {
+            let _ = writeln!(
+                out.buf,
+                "{}{{service=\"{}\"}} {}",
+                out.current,
+                svc.uuid,
+                value(svc)
+            );
+        }
+    };
+
+    out.metric(
+        "mnemos_conn_queue_depth",
+        "gauge",
+        "Connection requests waiting in each service's queue.",
+    );
+    per_service(out, |svc| svc.conn_queue.len);
+    out.metric(
+        "mnemos_conn_queue_capacity",
+        "gauge",
+        "Capacity of each service's connection queue.",
+    );
+    per_service(out, |svc| svc.conn_queue.capacity);
+    out.metric(
+        "mnemos_conn_queue_high_water",
+        "gauge",
+        "The most connection requests each service's queue has held at once.",
+    );
+    per_service(out, |svc| svc.conn_queue.high_water);
+    out.metric(
+        "mnemos_conn_queue_overflows_total",
+        "counter",
+        "Times a client found a service's connection queue full.",
+    );
+    per_service(out, |svc| svc.conn_queue.overflows);
+}
+
+/// A Prometheus text format document being built.
+#[derive(Default)]
+struct Exposition {
+    buf: String,
+    /// The name of the metric currently being written.
+    current: &'static str,
+}
+
+impl Exposition {
+    /// Starts a new metric, writing its `HELP` and `TYPE` lines.
+    fn metric(&mut self, name: &'static str, kind: &str, help: &str) -> &mut Self {
+        let _ = writeln!(self.buf, "# HELP {name} {help}");
+        let _ = writeln!(self.buf, "# TYPE {name} {kind}");
+        self.current = name;
+        self
+    }
+
+    /// Writes an unlabeled sample of the current metric.
+    fn sample(&mut self, value: impl std::fmt::Display) {
+        let _ = writeln!(self.buf, "{} {value}", self.current);
+    }
+}
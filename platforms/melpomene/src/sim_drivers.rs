@@ -1,2 +1,4 @@
 pub mod emb_display;
+pub mod entropy;
+pub mod keyboard_panel;
 pub mod tcp_serial;
@@ -17,8 +17,21 @@
 //! Clients of the driver can draw into the sub-frames that they receive, then send
 //! them back to be rendered into the total frame. Any data in the client's sub-frame
 //! will replace the current contents of the whole frame buffer.
+//!
+//! Frames are rendered at the configured rate, paced against the wall clock.
+//! If the display is [headless](DisplayConfig::headless), frames are rendered
+//! into the off-screen simulator display without opening a window, so the
+//! driver also runs without a display server. Either way, rendered frames can
+//! be saved as PNGs by setting [`DisplayConfig::dump_frames`].
+//!
+//! The framebuffer can also be leased, and written to directly. The whole
+//! framebuffer is rendered again whenever the lease holder submits damage.
 
-use std::{process::exit, time::Duration};
+use std::{
+    path::{Path, PathBuf},
+    process::exit,
+    time::Duration,
+};
 
 use embedded_graphics::{
     image::{Image, ImageRaw},
@@ -27,22 +40,28 @@ use embedded_graphics::{
 };
 use embedded_graphics_simulator::{
     sdl2::{Keycode, Mod},
-    BinaryColorTheme, OutputSettingsBuilder, SimulatorDisplay, SimulatorEvent, Window,
+    BinaryColorTheme, OutputSettings, OutputSettingsBuilder, SimulatorDisplay, SimulatorEvent,
+    Window,
 };
 use maitake::sync::Mutex;
 use melpo_config::DisplayConfig;
+
+use super::keyboard_panel::KeyboardPanel;
+use crate::{chaos, time_warp::TimeWarp};
 use mnemos_alloc::containers::{Arc, HeapArray};
 use mnemos_kernel::{
     registry,
     services::{
         emb_display::{
-            DisplayMetadata, EmbDisplayService, FrameChunk, FrameKind, MonoChunk, Request, Response,
+            capabilities, DisplayMetadata, DisplayTransform, EmbDisplayService, FrameChunk,
+            FrameError, FrameKind, Leases, MonoChunk, Request, Response,
         },
         keyboard::{
             key_event::{self, KeyCode, Modifiers},
             mux::KeyboardMuxClient,
             KeyEvent,
         },
+        pointer::{mux::PointerMuxClient, Buttons, PointerEvent},
     },
     Kernel,
 };
@@ -55,7 +74,7 @@ impl SimDisplay {
     /// Register the driver instance
     ///
     /// Registration will also start the simulated display, meaning that the display
-    /// window will appear.
+    /// window will appear, unless the display is headless.
     #[tracing::instrument(skip(kernel))]
     pub async fn register(
         kernel: &'static Kernel,
@@ -69,13 +88,18 @@ impl SimDisplay {
             .bind_konly(settings.kchannel_depth)
             .await?
             .into_request_stream(settings.kchannel_depth)
-            .await;
+            .await
+            .with_capabilities(
+                capabilities::TRANSFORM | capabilities::SCREENSHOT | capabilities::LEASE,
+            )
+            .with_max_latency(settings.max_latency);
 
         let commander = CommanderTask {
             kernel,
             cmd,
             width,
             height,
+            transform: settings.transform,
         };
 
         kernel.spawn(commander.run(width, height, settings)).await;
@@ -98,13 +122,18 @@ struct CommanderTask {
     cmd: registry::listener::RequestStream<EmbDisplayService>,
     width: u32,
     height: u32,
+    transform: DisplayTransform,
 }
 
 struct Context {
     sdisp: SimulatorDisplay<Gray8>,
     framebuf: HeapArray<u8>,
-    window: Window,
+    /// The simulator window, or `None` if the display is headless.
+    window: Option<Window>,
+    /// The on-screen keyboard, drawn below the framebuffer, if enabled.
+    panel: Option<KeyboardPanel>,
     dirty: bool,
+    leases: Leases,
 }
 
 impl CommanderTask {
@@ -128,22 +157,45 @@ impl CommanderTask {
         // The update loop *needs* to drop the egsim items, otherwise they just exist
         // in the mutex until the next time a frame is displayed, which right now is
         // only whenever line characters actually arrive.
-        let sdisp = SimulatorDisplay::<Gray8>::new(Size::new(width, height));
-        let window = Window::new("mnemOS", &output_settings);
+        // The on-screen keyboard can't be clicked without a window.
+        let panel = (settings.keyboard_panel && !settings.headless)
+            .then(|| KeyboardPanel::new(Point::new(0, height as i32), width));
+        let panel_height = if panel.is_some() {
+            KeyboardPanel::HEIGHT
+        } else {
+            0
+        };
+        let mut sdisp = SimulatorDisplay::<Gray8>::new(Size::new(width, height + panel_height));
+        if let Some(ref panel) = panel {
+            panel.draw(&mut sdisp).unwrap();
+        }
+        let window = if settings.headless {
+            tracing::info!("rendering the display headless");
+            None
+        } else {
+            Some(Window::new("mnemOS", &output_settings))
+        };
+        let dump = settings.dump_frames.map(|dir| FrameDump {
+            dir,
+            output_settings,
+            frames: 0,
+        });
         let framebuf = HeapArray::new(bytes, 0x00).await;
         let mutex = Arc::new(Mutex::new(Some(Context {
             sdisp,
             framebuf,
             window,
+            panel,
             dirty: true,
+            leases: Leases::new(),
         })))
         .await;
 
-        // Spawn a task that draws the framebuffer at a regular rate of 15Hz.
+        // Spawn a task that draws the framebuffer at a regular rate.
         self.kernel
             .spawn({
                 let mutex = mutex.clone();
-                render_loop(self.kernel, mutex, settings.frames_per_second)
+                render_loop(self.kernel, mutex, settings.frames_per_second, dump)
             })
             .await;
 
@@ -157,6 +209,13 @@ impl CommanderTask {
     async fn message_loop(&self, mutex: Arc<Mutex<Option<Context>>>) {
         loop {
             let msg = self.cmd.next_request().await;
+            // simulate a slow (or hung) display, if we've been asked to.
+            if let Some(delay) = chaos::FAULTS.display_delay() {
+                self.kernel.sleep(delay).await;
+            }
+            while chaos::FAULTS.display_stalled() {
+                self.kernel.sleep(Duration::from_millis(100)).await;
+            }
             let (req, env, reply_tx) = msg.split();
             match req {
                 Request::Draw(FrameChunk::Mono(fc)) => {
@@ -172,15 +231,69 @@ impl CommanderTask {
                         kind: FrameKind::Mono,
                         width: self.width,
                         height: self.height,
+                        transform: self.transform,
                     };
                     let response = env.fill(Ok(Response::FrameMeta(meta)));
                     let _ = reply_tx.reply_konly(response).await;
                 }
+                Request::Screenshot(FrameChunk::Mono(mut fc)) => {
+                    if self.screenshot(&mut fc, &mutex).await.is_err() {
+                        break;
+                    } else {
+                        let response = env.fill(Ok(Response::Screenshot(fc.into())));
+                        let _ = reply_tx.reply_konly(response).await;
+                    }
+                }
+                Request::Lease | Request::Damage { .. } | Request::Release(_) | Request::Revoke => {
+                    let Ok(response) = self.lease(req, &mutex).await else {
+                        break;
+                    };
+                    let response = env.fill(response);
+                    let _ = reply_tx.reply_konly(response).await;
+                }
                 _ => todo!(),
             }
         }
     }
 
+    /// Handle a request to lease the framebuffer, or to use a lease
+    async fn lease(
+        &self,
+        req: Request,
+        mutex: &Mutex<Option<Context>>,
+    ) -> Result<Result<Response, FrameError>, ()> {
+        let mut guard = mutex.lock().await;
+        let Some(Context {
+            sdisp,
+            dirty,
+            framebuf,
+            leases,
+            ..
+        }) = (*guard).as_mut()
+        else {
+            return Err(());
+        };
+
+        let response = match req {
+            Request::Lease => leases
+                .lease(framebuf, self.width, self.height)
+                .map(Response::Leased),
+            Request::Damage { lease, area } => leases
+                .damage(lease, &area, self.width, self.height)
+                .map(|()| {
+                    let raw_img = frame_display(framebuf, self.width).unwrap();
+                    let image = Image::new(&raw_img, Point::new(0, 0));
+                    image.draw(sdisp).unwrap();
+                    *dirty = true;
+                    Response::Damaged
+                }),
+            Request::Release(lease) => leases.release(lease).map(|()| Response::Released),
+            Request::Revoke => Ok(Response::Revoked(leases.revoke())),
+            _ => Err(FrameError::Unsupported),
+        };
+        Ok(response)
+    }
+
     /// Draw the given MonoChunk to the persistent framebuffer
     async fn draw_mono(&self, fc: &MonoChunk, mutex: &Mutex<Option<Context>>) -> Result<(), ()> {
         let mut guard = mutex.lock().await;
@@ -205,6 +318,28 @@ impl CommanderTask {
 
         Ok(())
     }
+
+    /// Copy the persistent framebuffer into the given MonoChunk
+    async fn screenshot(
+        &self,
+        fc: &mut MonoChunk,
+        mutex: &Mutex<Option<Context>>,
+    ) -> Result<(), ()> {
+        let guard = mutex.lock().await;
+        let Some(Context { framebuf, .. }) = (*guard).as_ref() else {
+            return Err(());
+        };
+
+        let width = self.width;
+        fc.capture(|x, y| {
+            x < width
+                && framebuf
+                    .get((y * width + x) as usize)
+                    .is_some_and(|&px| px != 0)
+        });
+
+        Ok(())
+    }
 }
 
 async fn handle_key_event(kmc: &mut KeyboardMuxClient, evt: SimulatorEvent) -> bool {
@@ -238,24 +373,133 @@ async fn handle_key_event(kmc: &mut KeyboardMuxClient, evt: SimulatorEvent) -> b
     }
 }
 
+/// Publish a key clicked on the on-screen keyboard, followed by its release.
+async fn handle_panel_key(kmc: &mut KeyboardMuxClient, key: KeyEvent) -> bool {
+    tracing::trace!(?key, "Got key event from keyboard panel");
+    let released = KeyEvent {
+        kind: key_event::Kind::Released,
+        ..key
+    };
+    kmc.publish_key(key).await.is_err() || kmc.publish_key(released).await.is_err()
+}
+
+/// Forwards mouse input on the simulated display to the pointer mux, if one
+/// is registered, so that pointer clients such as the [software keyboard] can
+/// be tested in the simulator.
+///
+/// The simulated display has no notion of which mouse button was pressed, so
+/// every button is reported as the left button, like a touchscreen.
+///
+/// [software keyboard]: mnemos_kernel::daemons::soft_keyboard
+#[derive(Default)]
+struct SimPointer {
+    mux: Option<PointerMuxClient>,
+    last: Option<Point>,
+    buttons: Buttons,
+}
+
+impl SimPointer {
+    async fn handle(&mut self, kernel: &'static Kernel, evt: &SimulatorEvent) {
+        let (point, buttons) = match *evt {
+            SimulatorEvent::MouseMove { point } => (point, self.buttons),
+            SimulatorEvent::MouseButtonDown { point, .. } => (point, Buttons::LEFT),
+            SimulatorEvent::MouseButtonUp { point, .. } => (point, Buttons::NONE),
+            _ => return,
+        };
+        let delta = point - self.last.unwrap_or(point);
+        self.last = Some(point);
+        self.buttons = buttons;
+
+        if self.mux.is_none() {
+            // The pointer mux is disabled by default, so don't complain if
+            // it isn't there.
+            self.mux = PointerMuxClient::from_registry_no_retry(kernel).await.ok();
+        }
+        let Some(mux) = self.mux.as_mut() else {
+            return;
+        };
+        let event = PointerEvent {
+            dx: delta.x.clamp(i16::MIN.into(), i16::MAX.into()) as i16,
+            dy: delta.y.clamp(i16::MIN.into(), i16::MAX.into()) as i16,
+            wheel: 0,
+            buttons,
+        };
+        if mux.publish(event).await.is_err() {
+            tracing::warn!("pointer mux is gone, not forwarding mouse input");
+            self.mux = None;
+        }
+    }
+}
+
+/// Saves rendered frames to a directory, as PNG images.
+struct FrameDump {
+    dir: PathBuf,
+    output_settings: OutputSettings,
+    /// The number of frames saved so far.
+    frames: u64,
+}
+
+impl FrameDump {
+    fn save(&mut self, sdisp: &SimulatorDisplay<Gray8>) {
+        let path = self.dir.join(format!("frame-{:06}.png", self.frames));
+        if let Err(error) = self.try_save(sdisp, &path) {
+            tracing::warn!(%error, path = %path.display(), "failed to save frame");
+            return;
+        }
+        tracing::trace!(path = %path.display(), "saved frame");
+        self.frames += 1;
+    }
+
+    fn try_save(
+        &self,
+        sdisp: &SimulatorDisplay<Gray8>,
+        path: &Path,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        std::fs::create_dir_all(&self.dir)?;
+        sdisp
+            .to_rgb_output_image(&self.output_settings)
+            .save_png(path)?;
+        Ok(())
+    }
+}
+
 async fn render_loop(
     kernel: &'static Kernel,
     mutex: Arc<Mutex<Option<Context>>>,
     frames_per_second: usize,
+    mut dump: Option<FrameDump>,
 ) {
     let mut idle_ticks = 0;
     let mut keymux = KeyboardMuxClient::from_registry(kernel)
         .await
         .expect("no keyboard mux service!");
+    let mut pointer = SimPointer::default();
     let mut first_done = false;
-    let sleep_time = Duration::from_micros(1_000_000 / (frames_per_second as u64));
+    // The window is refreshed at `frames_per_second` in real time, regardless
+    // of how fast the kernel's clock is running.
+    let frame_time = TimeWarp::global().to_kernel(Duration::from_micros(
+        1_000_000 / (frames_per_second as u64),
+    ));
+    // Frames are due at fixed times on the wall clock, rather than a whole
+    // frame after the previous one finished, so that the time spent rendering
+    // doesn't slow the frame rate down.
+    let mut next_frame = kernel.timer().now();
     loop {
-        kernel.sleep(sleep_time).await;
+        next_frame += frame_time;
+        let now = kernel.timer().now();
+        if next_frame > now {
+            kernel.sleep(next_frame.duration_since(now)).await;
+        } else {
+            // We've fallen behind (or the host was suspended); skip the
+            // frames we missed, rather than rendering them back to back.
+            next_frame = now;
+        }
         let mut guard = mutex.lock().await;
         let mut done = false;
         if let Some(Context {
             sdisp,
             window,
+            panel,
             dirty,
             ..
         }) = (*guard).as_mut()
@@ -264,8 +508,25 @@ async fn render_loop(
             // But once we have: we want to always process events, even if there
             // is nothing to draw, to potentially feed the keymux or catch
             // a "time to die" event.
-            if first_done {
+            if let (Some(window), true) = (window.as_mut(), first_done) {
                 for evt in window.events() {
+                    pointer.handle(kernel, &evt).await;
+                    if let (Some(panel), SimulatorEvent::MouseButtonUp { point, .. }) =
+                        (panel.as_mut(), &evt)
+                    {
+                        let (key, redraw) = panel.click(*point);
+                        if redraw {
+                            panel.draw(sdisp).unwrap();
+                            *dirty = true;
+                        }
+                        if let Some(key) = key {
+                            if handle_panel_key(&mut keymux, key).await {
+                                done = true;
+                            }
+                        }
+                        continue;
+                    }
+
                     if handle_key_event(&mut keymux, evt).await {
                         done = true;
                     }
@@ -276,8 +537,13 @@ async fn render_loop(
             // CPU usage
             if *dirty || idle_ticks >= 4 {
                 idle_ticks = 0;
+                if let (Some(dump), true) = (dump.as_mut(), *dirty) {
+                    dump.save(sdisp);
+                }
                 *dirty = false;
-                window.update(sdisp);
+                if let Some(window) = window.as_mut() {
+                    window.update(sdisp);
+                }
                 first_done = true;
             } else {
                 idle_ticks += 1;
@@ -0,0 +1,17 @@
+//! Simulated hardware random number generator
+//!
+//! Serves random bytes from the host's operating system, using [`getrandom`].
+
+use mnemos_kernel::services::entropy::{EntropyError, EntropySource};
+
+/// Random bytes from the host's operating system.
+pub struct HostEntropy;
+
+impl EntropySource for HostEntropy {
+    fn fill(&mut self, buf: &mut [u8]) -> Result<(), EntropyError> {
+        getrandom::getrandom(buf).map_err(|error| {
+            tracing::error!(%error, "Host random number generator failed");
+            EntropyError::Unavailable
+        })
+    }
+}
@@ -0,0 +1,233 @@
+//! Simulated i2c_puppet keyboard front panel
+//!
+//! Renders a clickable BBQ10-style keyboard (as found on the Beepy) below the
+//! simulated display, so that the graphical shell can be driven with the mouse
+//! without relying on host keyboard input.
+//!
+//! Like the real keyboard, `Shift` and `Sym` are "sticky": clicking them
+//! applies the modifier to the next key that is clicked.
+
+use embedded_graphics::{
+    mono_font::{ascii::FONT_6X10, MonoTextStyle},
+    pixelcolor::Gray8,
+    prelude::*,
+    primitives::{PrimitiveStyle, PrimitiveStyleBuilder, Rectangle},
+    text::{Alignment, Baseline, Text, TextStyleBuilder},
+};
+use mnemos_kernel::services::keyboard::{
+    key_event::{self, KeyCode, Modifiers},
+    KeyEvent,
+};
+
+/// Height of each row of keys, in pixels.
+const ROW_HEIGHT: u32 = 20;
+/// Gap between keys and rows, in pixels.
+const GAP: u32 = 2;
+/// Number of key "units" in each row. Most keys are one unit wide.
+const ROW_UNITS: u32 = 10;
+
+#[derive(Copy, Clone)]
+enum Key {
+    /// A key that produces a character. The second character is produced when
+    /// `Sym` is active.
+    Char(char, char),
+    Backspace,
+    Enter,
+    Shift,
+    Sym,
+    Space,
+}
+
+/// Key layout, with each key's width in units.
+const ROWS: [&[(Key, u32)]; 4] = [
+    &[
+        (Key::Char('q', '#'), 1),
+        (Key::Char('w', '1'), 1),
+        (Key::Char('e', '2'), 1),
+        (Key::Char('r', '3'), 1),
+        (Key::Char('t', '('), 1),
+        (Key::Char('y', ')'), 1),
+        (Key::Char('u', '_'), 1),
+        (Key::Char('i', '-'), 1),
+        (Key::Char('o', '+'), 1),
+        (Key::Char('p', '@'), 1),
+    ],
+    &[
+        (Key::Char('a', '*'), 1),
+        (Key::Char('s', '4'), 1),
+        (Key::Char('d', '5'), 1),
+        (Key::Char('f', '6'), 1),
+        (Key::Char('g', '/'), 1),
+        (Key::Char('h', ':'), 1),
+        (Key::Char('j', ';'), 1),
+        (Key::Char('k', '\''), 1),
+        (Key::Char('l', '"'), 1),
+        (Key::Backspace, 1),
+    ],
+    &[
+        (Key::Shift, 1),
+        (Key::Char('z', '7'), 1),
+        (Key::Char('x', '8'), 1),
+        (Key::Char('c', '9'), 1),
+        (Key::Char('v', '?'), 1),
+        (Key::Char('b', '!'), 1),
+        (Key::Char('n', ','), 1),
+        (Key::Char('m', '.'), 1),
+        (Key::Char('$', '0'), 1),
+        (Key::Enter, 1),
+    ],
+    &[(Key::Sym, 2), (Key::Space, 6), (Key::Char('.', '='), 2)],
+];
+
+/// The on-screen keyboard state.
+pub(crate) struct KeyboardPanel {
+    /// The top-left corner of the panel.
+    origin: Point,
+    /// The width of the panel, in pixels.
+    width: u32,
+    shift: bool,
+    sym: bool,
+}
+
+impl KeyboardPanel {
+    /// The total height of the panel, in pixels.
+    pub(crate) const HEIGHT: u32 = ROWS.len() as u32 * (ROW_HEIGHT + GAP) + GAP;
+
+    pub(crate) fn new(origin: Point, width: u32) -> Self {
+        Self {
+            origin,
+            width,
+            shift: false,
+            sym: false,
+        }
+    }
+
+    /// Draw the keyboard to `target`.
+    pub(crate) fn draw<D>(&self, target: &mut D) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = Gray8>,
+    {
+        Rectangle::new(self.origin, Size::new(self.width, Self::HEIGHT))
+            .into_styled(PrimitiveStyle::with_fill(Gray8::BLACK))
+            .draw(target)?;
+
+        let text_style = TextStyleBuilder::new()
+            .alignment(Alignment::Center)
+            .baseline(Baseline::Middle)
+            .build();
+
+        for (rect, key) in self.keys() {
+            let active = match key {
+                Key::Shift => self.shift,
+                Key::Sym => self.sym,
+                _ => false,
+            };
+            let (fill, text) = if active {
+                (Gray8::WHITE, Gray8::BLACK)
+            } else {
+                (Gray8::BLACK, Gray8::WHITE)
+            };
+            rect.into_styled(
+                PrimitiveStyleBuilder::new()
+                    .fill_color(fill)
+                    .stroke_color(Gray8::WHITE)
+                    .stroke_width(1)
+                    .build(),
+            )
+            .draw(target)?;
+
+            let mut buf = [0u8; 4];
+            let label = match key {
+                Key::Char(c, sym) => {
+                    let c = if self.sym {
+                        sym
+                    } else if self.shift {
+                        c.to_ascii_uppercase()
+                    } else {
+                        c
+                    };
+                    &*c.encode_utf8(&mut buf)
+                }
+                Key::Backspace => "Del",
+                Key::Enter => "Ent",
+                Key::Shift => "Sh",
+                Key::Sym => "Sym",
+                Key::Space => "Space",
+            };
+            Text::with_text_style(
+                label,
+                rect.center(),
+                MonoTextStyle::new(&FONT_6X10, text),
+                text_style,
+            )
+            .draw(target)?;
+        }
+
+        Ok(())
+    }
+
+    /// Handle a mouse click at `point`.
+    ///
+    /// Returns the key event to publish if a key was clicked, and whether the
+    /// panel needs to be redrawn.
+    pub(crate) fn click(&mut self, point: Point) -> (Option<KeyEvent>, bool) {
+        let Some(key) = self
+            .keys()
+            .find(|(rect, _)| rect.contains(point))
+            .map(|(_, key)| key)
+        else {
+            return (None, false);
+        };
+
+        let code = match key {
+            Key::Shift => {
+                self.shift = !self.shift;
+                return (None, true);
+            }
+            Key::Sym => {
+                self.sym = !self.sym;
+                return (None, true);
+            }
+            Key::Char(c, sym) => KeyCode::Char(if self.sym {
+                sym
+            } else if self.shift {
+                c.to_ascii_uppercase()
+            } else {
+                c
+            }),
+            Key::Backspace => KeyCode::Backspace,
+            Key::Enter => KeyCode::Enter,
+            Key::Space => KeyCode::Char(' '),
+        };
+
+        let mut modifiers = Modifiers::new();
+        modifiers.set(Modifiers::SHIFT, self.shift);
+
+        // Sticky modifiers only apply to a single key.
+        let redraw = self.shift || self.sym;
+        self.shift = false;
+        self.sym = false;
+
+        let event = KeyEvent {
+            kind: key_event::Kind::Pressed,
+            modifiers,
+            code,
+        };
+        (Some(event), redraw)
+    }
+
+    /// Returns an iterator over the bounding box of every key.
+    fn keys(&self) -> impl Iterator<Item = (Rectangle, Key)> + '_ {
+        let unit = (self.width - GAP) / ROW_UNITS;
+        ROWS.iter().enumerate().flat_map(move |(row, keys)| {
+            let y = self.origin.y + (GAP + row as u32 * (ROW_HEIGHT + GAP)) as i32;
+            let mut x = self.origin.x + GAP as i32;
+            keys.iter().map(move |&(key, units)| {
+                let width = unit * units - GAP;
+                let rect = Rectangle::new(Point::new(x, y), Size::new(width, ROW_HEIGHT));
+                x += (unit * units) as i32;
+                (rect, key)
+            })
+        })
+    }
+}
@@ -1,6 +1,8 @@
-use melpo_config::TcpUartConfig;
+use crate::{chaos, time_warp::TimeWarp};
+use melpo_config::{TcpUartConfig, TcpUartMode};
 use mnemos_kernel::{
     comms::bbq::{new_bidi_channel, BidiHandle},
+    forth::Forth,
     registry,
     services::simple_serial::{Request, Response, SimpleSerialError, SimpleSerialService},
     Kernel,
@@ -25,23 +27,64 @@ impl TcpSerial {
     ) -> Result<(), registry::RegistrationError> {
         let (a_ring, b_ring) =
             new_bidi_channel(settings.incoming_size, settings.outgoing_size).await;
-        let reqs = kernel
-            .registry()
-            .bind_konly::<SimpleSerialService>(settings.kchannel_depth)
-            .await?
-            .into_request_stream(settings.kchannel_depth)
-            .await;
+        match settings.mode {
+            TcpUartMode::Sermux => {
+                Self::serve_simple_serial(kernel, b_ring, settings.kchannel_depth).await?
+            }
+            TcpUartMode::Raw => {
+                let forth = Forth::new_with_stdio(kernel, settings.forth_params, b_ring)
+                    .await
+                    .expect("Forth spawning must succeed");
+                kernel.spawn(forth.run()).await;
+            }
+        }
         let socket_addr = &settings.socket_addr;
         let listener = TcpListener::bind(socket_addr).await.unwrap();
         tracing::info!(
+            mode = ?settings.mode,
             "TCP serial port driver listening on {}",
             settings.socket_addr
         );
 
+        let _hdl = tokio::spawn(
+            async move {
+                let handle = a_ring;
+                loop {
+                    match listener.accept().await {
+                        Ok((stream, addr)) => {
+                            process_stream(&handle, stream, irq.clone())
+                                .instrument(info_span!("process_stream", client.addr = %addr))
+                                .await
+                        }
+                        Err(error) => {
+                            warn!(%error, "Error accepting incoming TCP connection");
+                            return;
+                        }
+                    };
+                }
+            }
+            .instrument(info_span!("TCP Serial", ?socket_addr)),
+        );
+
+        Ok(())
+    }
+
+    /// Serves the UART as the [`SimpleSerialService`], giving it to the first
+    /// client that asks for it.
+    async fn serve_simple_serial(
+        kernel: &'static Kernel,
+        handle: BidiHandle,
+        kchannel_depth: usize,
+    ) -> Result<(), registry::RegistrationError> {
+        let reqs = kernel
+            .registry()
+            .bind_konly::<SimpleSerialService>(kchannel_depth)
+            .await?
+            .into_request_stream(kchannel_depth)
+            .await;
+
         kernel
             .spawn(async move {
-                let handle = b_ring;
-
                 // Reply to the first request, giving away the serial port
                 let req = reqs.next_request().await;
                 let Request::GetPort = req.msg.body;
@@ -62,26 +105,6 @@ impl TcpSerial {
             })
             .await;
 
-        let _hdl = tokio::spawn(
-            async move {
-                let handle = a_ring;
-                loop {
-                    match listener.accept().await {
-                        Ok((stream, addr)) => {
-                            process_stream(&handle, stream, irq.clone())
-                                .instrument(info_span!("process_stream", client.addr = %addr))
-                                .await
-                        }
-                        Err(error) => {
-                            warn!(%error, "Error accepting incoming TCP connection");
-                            return;
-                        }
-                    };
-                }
-            }
-            .instrument(info_span!("TCP Serial", ?socket_addr)),
-        );
-
         Ok(())
     }
 }
@@ -94,8 +117,15 @@ async fn process_stream(handle: &BidiHandle, mut stream: TcpStream, irq: Arc<Not
             // The kernel wants to write something.
             outmsg = handle.consumer().read_grant() => {
                 trace!(len = outmsg.len(), "Got outgoing message",);
-                let wall = stream.write_all(&outmsg);
-                wall.await.unwrap();
+                if let Some(delay) = chaos::FAULTS.sermux_delay() {
+                    tokio::time::sleep(TimeWarp::global().to_host(delay)).await;
+                }
+                if chaos::FAULTS.should_drop_sermux() {
+                    warn!(len = outmsg.len(), "Dropping outgoing message (fault injection)");
+                } else {
+                    let wall = stream.write_all(&outmsg);
+                    wall.await.unwrap();
+                }
                 let len = outmsg.len();
                 outmsg.release(len);
                 // Simulate an "interrupt", waking the kernel if it's waiting
@@ -115,6 +145,13 @@ async fn process_stream(handle: &BidiHandle, mut stream: TcpStream, irq: Arc<Not
                     },
                     Ok(used) => {
                         trace!(len = used, "Got incoming message",);
+                        if let Some(delay) = chaos::FAULTS.sermux_delay() {
+                            tokio::time::sleep(TimeWarp::global().to_host(delay)).await;
+                        }
+                        if chaos::FAULTS.should_drop_sermux() {
+                            warn!(len = used, "Dropping incoming message (fault injection)");
+                            continue;
+                        }
                         in_grant.commit(used);
                         // Simulate an "interrupt", waking the kernel if it's waiting
                         // an IRQ.
@@ -0,0 +1,115 @@
+//! Virtual time warping.
+//!
+//! Some of the kernel's behaviors only show up after it has been running for
+//! hours, such as heap fragmentation, or how periodic daemons and watchdogs
+//! interact. To observe them without waiting hours, Melpomene can run the
+//! kernel's clock faster than the host's, by a constant *warp factor*. With a
+//! warp factor of 60, an hour of kernel time passes in a minute on the host.
+//! Factors less than 1 slow the kernel's clock down instead.
+//!
+//! Everything inside the kernel (timers, sleeps, the wall clock, and
+//! timestamps in traces) runs on warped time. Anything which waits on the
+//! host's clock must translate between the two at the boundary:
+//!
+//! - the kernel's main loop sleeps on the host until the next timer deadline,
+//!   so it converts the deadline [to host time](TimeWarp::to_host),
+//! - the simulated UART's injected delays are latencies of the simulated
+//!   serial link, so they're given in kernel time, and converted to host time
+//!   before sleeping, and
+//! - the simulated display refreshes its window at a frame rate in host time,
+//!   so its frame interval is converted [to kernel time](TimeWarp::to_kernel).
+
+use std::{
+    sync::OnceLock,
+    time::{Duration, Instant, SystemTime},
+};
+
+use mnemos_kernel::maitake;
+
+static WARP: OnceLock<TimeWarp> = OnceLock::new();
+
+/// Converts between the kernel's warped time and the host's time.
+#[derive(Debug)]
+pub struct TimeWarp {
+    factor: f64,
+    /// When the warp was set, on the host's clock.
+    start: Instant,
+    /// When the warp was set, in microseconds since the Unix epoch.
+    start_micros: u64,
+}
+
+impl TimeWarp {
+    /// Sets the warp factor used by the kernel's [clock](Self::clock).
+    ///
+    /// # Panics
+    ///
+    /// - If `factor` is not a finite, positive number.
+    /// - If the warp factor has already been set, or the global time warp has
+    ///   already been used.
+    pub fn init(factor: f64) -> &'static Self {
+        assert!(
+            factor.is_finite() && factor > 0.0,
+            "time warp factor must be a positive number, not {factor}"
+        );
+        let mut warp = Some(Self::new(factor));
+        let global = WARP.get_or_init(|| warp.take().unwrap());
+        assert!(warp.is_none(), "time warp was already set");
+        if factor != 1.0 {
+            tracing::info!(factor, "warping kernel time");
+        }
+        global
+    }
+
+    /// Returns the global time warp.
+    ///
+    /// If [`TimeWarp::init`] has not been called, time is not warped.
+    #[must_use]
+    pub fn global() -> &'static Self {
+        WARP.get_or_init(|| Self::new(1.0))
+    }
+
+    /// Returns a [`maitake::time::Clock`] which runs on warped time.
+    ///
+    /// The clock counts microseconds since the Unix epoch, starting from the
+    /// time at which the warp was set.
+    #[must_use]
+    pub fn clock() -> maitake::time::Clock {
+        maitake::time::Clock::new(Duration::from_micros(1), || Self::global().now_micros())
+            .named("CLOCK_WARPED_SYSTEMTIME")
+    }
+
+    /// Returns the warp factor.
+    #[must_use]
+    pub fn factor(&self) -> f64 {
+        self.factor
+    }
+
+    /// Converts a duration of kernel time into host time.
+    #[must_use]
+    pub fn to_host(&self, kernel: Duration) -> Duration {
+        kernel.div_f64(self.factor)
+    }
+
+    /// Converts a duration of host time into kernel time.
+    #[must_use]
+    pub fn to_kernel(&self, host: Duration) -> Duration {
+        host.mul_f64(self.factor)
+    }
+
+    fn new(factor: f64) -> Self {
+        let start_micros = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_micros() as u64;
+        Self {
+            factor,
+            start: Instant::now(),
+            start_micros,
+        }
+    }
+
+    fn now_micros(&self) -> u64 {
+        let elapsed = self.start.elapsed().as_micros() as f64 * self.factor;
+        self.start_micros + elapsed as u64
+    }
+}
@@ -161,9 +161,7 @@ async fn kernel_entry() {
                         rgr = port.consumer().read_grant().fuse() => {
                             let needed = rgr.len();
                             trace!(needed, "Forth: received input");
-                            let mut tid_io_wgr = tid_io.producer().send_grant_exact(needed).await;
-                            tid_io_wgr.copy_from_slice(&rgr);
-                            tid_io_wgr.commit(needed);
+                            tid_io.producer().write_all(&rgr).await;
                             rgr.release(needed);
                         },
                         output = tid_io.consumer().read_grant().fuse() => {
@@ -34,8 +34,8 @@ use mnemos_kernel::{
     registry::{self, listener, Envelope, OpenEnvelope, ReplyTo},
     services::{
         emb_display::{
-            DisplayMetadata, EmbDisplayService, FrameChunk, FrameError, FrameKind, MonoChunk,
-            Request, Response,
+            DisplayMetadata, DisplayTransform, EmbDisplayService, FrameChunk, FrameError,
+            FrameKind, MonoChunk, Request, Response,
         },
         keyboard::{
             key_event::{self, KeyCode, Modifiers},
@@ -254,6 +254,7 @@ impl CommanderTask {
                         kind: FrameKind::Mono,
                         width: self.width,
                         height: self.height,
+                        transform: DisplayTransform::IDENTITY,
                     };
                     let response = env.fill(Ok(Response::FrameMeta(meta)));
                     let _ = reply_tx.reply_konly(response).await;
@@ -0,0 +1,30 @@
+//! Places `memory.x` somewhere the linker can find it, and checks whether the
+//! CYW43439 firmware blobs are present.
+
+use std::{env, fs, path::PathBuf};
+
+const FIRMWARE: &[&str] = &["firmware/43439A0.bin", "firmware/43439A0_clm.bin"];
+
+fn main() {
+    let out = PathBuf::from(env::var_os("OUT_DIR").unwrap());
+    fs::copy("memory.x", out.join("memory.x")).expect("failed to copy memory.x");
+    println!("cargo:rustc-link-search={}", out.display());
+    println!("cargo:rerun-if-changed=memory.x");
+
+    // The CYW43439 firmware can't be redistributed with MnemOS, so it must be
+    // downloaded separately (see `firmware/README.md`). If it's missing, build
+    // without WiFi rather than failing the build, so that the crate can still
+    // be checked on CI.
+    println!("cargo:rustc-check-cfg=cfg(cyw43_firmware)");
+    let mut missing = false;
+    for path in FIRMWARE {
+        println!("cargo:rerun-if-changed={path}");
+        if fs::metadata(path).is_err() {
+            println!("cargo:warning={path} not found, building without WiFi support");
+            missing = true;
+        }
+    }
+    if !missing {
+        println!("cargo:rustc-cfg=cyw43_firmware");
+    }
+}
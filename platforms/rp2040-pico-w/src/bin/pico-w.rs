@@ -0,0 +1,67 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use cortex_m_rt::entry;
+use cyw43_pio::PioSpi;
+use embassy_rp::{
+    gpio::{Level, Output},
+    pio::Pio,
+    uart::{self, BufferedUart},
+};
+use mnemos_rp2040_pico_w::{
+    drivers::cyw43::{Cyw43, WifiSettings},
+    Irqs,
+};
+
+#[entry]
+fn main() -> ! {
+    mnemos_rp2040_pico_w::heap::init();
+
+    let p = embassy_rp::init(Default::default());
+    let core = cortex_m::Peripherals::take().unwrap();
+
+    let k = mnemos_rp2040_pico_w::init();
+
+    // UART0 on GP0 (TX) and GP1 (RX), pins 1 and 2 on the Pico W's header.
+    let uart = {
+        static mut TX_BUF: [u8; 256] = [0; 256];
+        static mut RX_BUF: [u8; 256] = [0; 256];
+        let mut config = uart::Config::default();
+        config.baudrate = 115_200;
+        // Safety: `main` is only called once, so these are only borrowed
+        // once.
+        let (tx_buf, rx_buf) = unsafe {
+            (
+                &mut *core::ptr::addr_of_mut!(TX_BUF),
+                &mut *core::ptr::addr_of_mut!(RX_BUF),
+            )
+        };
+        BufferedUart::new(p.UART0, Irqs, p.PIN_0, p.PIN_1, tx_buf, rx_buf, config)
+    };
+    mnemos_rp2040_pico_w::spawn_serial(k, uart);
+    mnemos_rp2040_pico_w::spawn_daemons(k);
+
+    // The CYW43439 is powered by GP23, and its SPI bus is on GP24 (data),
+    // GP25 (chip select), and GP29 (clock).
+    let pwr = Output::new(p.PIN_23, Level::Low);
+    let cs = Output::new(p.PIN_25, Level::High);
+    let mut pio = Pio::new(p.PIO0, Irqs);
+    let spi = PioSpi::new(
+        &mut pio.common,
+        pio.sm0,
+        pio.irq0,
+        cs,
+        p.PIN_24,
+        p.PIN_29,
+        p.DMA_CH0,
+    );
+    let wifi = WifiSettings {
+        ssid: option_env!("MNEMOS_WIFI_SSID"),
+        password: option_env!("MNEMOS_WIFI_PASSWORD"),
+    };
+    mnemos_rp2040_pico_w::spawn_wifi(k, Cyw43::new(pwr, spi, wifi));
+
+    mnemos_rp2040_pico_w::run(k, core.SYST)
+}
@@ -0,0 +1,232 @@
+//! Glue between the [`cyw43`] WiFi driver and the kernel's
+//! [`NetworkService`].
+//!
+//! The CYW43439 is connected to the RP2040 over a half-duplex SPI bus, which
+//! is implemented using PIO by [`cyw43_pio`]. The driver's [`Runner`] task
+//! handles all communication with the chip, and exchanges Ethernet frames
+//! with a [`NetDriver`], which this module bridges to the framed channels of
+//! the [`NetInterface`] handed out by the [`NetworkService`].
+//!
+//! The CYW43439's firmware isn't included in the MnemOS repository (see
+//! `firmware/README.md`). If it wasn't present at build time, [`Cyw43::register`]
+//! logs a warning and doesn't register a [`NetworkService`].
+//!
+//! [`Runner`]: cyw43::Runner
+//! [`NetDriver`]: cyw43::NetDriver
+#![cfg_attr(not(cyw43_firmware), allow(dead_code, unused_imports))]
+
+use core::{future::poll_fn, task::Poll, time::Duration};
+use cyw43_pio::PioSpi;
+use embassy_net_driver::{Driver, HardwareAddress, RxToken, TxToken};
+use embassy_rp::{
+    gpio::Output,
+    peripherals::{DMA_CH0, PIO0},
+};
+use futures::FutureExt;
+use kernel::{
+    comms::bbq::{new_framed_spsc_channel, FramedConsumer, FramedProducer},
+    mnemos_alloc::containers::Box,
+    registry::{self, listener::RequestStream},
+    services::network::{NetInterface, NetworkError, NetworkService, Request, Response},
+    Kernel,
+};
+
+/// The SPI bus used to talk to the CYW43439.
+pub type Cyw43Spi = PioSpi<'static, PIO0, 0, DMA_CH0>;
+
+/// The largest Ethernet frame the CYW43439 sends or receives, not including
+/// the frame check sequence.
+const MTU: usize = 1514;
+
+/// How long to wait before retrying a failed attempt to join the network.
+const JOIN_RETRY: Duration = Duration::from_secs(5);
+
+#[cfg(cyw43_firmware)]
+static FIRMWARE: &[u8] = include_bytes!("../../firmware/43439A0.bin");
+#[cfg(cyw43_firmware)]
+static CLM: &[u8] = include_bytes!("../../firmware/43439A0_clm.bin");
+
+/// The credentials for the network to join, set at build time.
+#[derive(Debug, Clone, Copy)]
+pub struct WifiSettings {
+    /// The network's SSID. If this is `None`, the CYW43439 is brought up but
+    /// doesn't join a network.
+    pub ssid: Option<&'static str>,
+    /// The network's WPA2 passphrase, or `None` for an open network.
+    pub password: Option<&'static str>,
+}
+
+pub struct Cyw43 {
+    pwr: Output<'static>,
+    spi: Cyw43Spi,
+    settings: WifiSettings,
+}
+
+impl Cyw43 {
+    pub fn new(pwr: Output<'static>, spi: Cyw43Spi, settings: WifiSettings) -> Self {
+        Self { pwr, spi, settings }
+    }
+
+    /// Loads the CYW43439's firmware, spawns the driver's tasks, and
+    /// registers the [`NetworkService`].
+    ///
+    /// `rx_capacity` and `tx_capacity` are the sizes of the channels for
+    /// received frames and frames to send, respectively, and should each be
+    /// a few times the [MTU](NetInterface::mtu).
+    #[cfg(cyw43_firmware)]
+    pub async fn register(
+        self,
+        k: &'static Kernel,
+        rx_capacity: usize,
+        tx_capacity: usize,
+    ) -> Result<(), registry::RegistrationError> {
+        let Self { pwr, spi, settings } = self;
+
+        // The driver's state must outlive its tasks, which run forever.
+        let state = unsafe { &mut *Box::into_raw(Box::new(cyw43::State::new()).await) };
+        let (net, mut control, runner) = cyw43::new(state, pwr, spi, FIRMWARE).await;
+        let _runner_hdl = k.spawn(runner.run()).await;
+
+        control.init(CLM).await;
+        control
+            .set_power_management(cyw43::PowerManagementMode::PowerSave)
+            .await;
+
+        let HardwareAddress::Ethernet(mac) = net.hardware_address() else {
+            unreachable!("the CYW43439 is an Ethernet device");
+        };
+        tracing::info!(?mac, "CYW43439 initialized");
+
+        let (rx_prod, rx_cons) = new_framed_spsc_channel(rx_capacity).await;
+        let (tx_prod, tx_cons) = new_framed_spsc_channel(tx_capacity).await;
+        let iface = NetInterface {
+            mac,
+            mtu: MTU,
+            rx: rx_cons,
+            tx: tx_prod,
+        };
+        let _pump_hdl = k.spawn(pump(net, rx_prod, tx_cons)).await;
+        let _join_hdl = k.spawn(join(k, control, settings)).await;
+
+        let reqs = k
+            .registry()
+            .bind_konly::<NetworkService>(4)
+            .await?
+            .into_request_stream(4)
+            .await;
+        let _server_hdl = k.spawn(network_server(iface, reqs)).await;
+
+        Ok(())
+    }
+
+    /// The CYW43439's firmware wasn't present at build time, so WiFi isn't
+    /// available.
+    #[cfg(not(cyw43_firmware))]
+    pub async fn register(
+        self,
+        _: &'static Kernel,
+        _: usize,
+        _: usize,
+    ) -> Result<(), registry::RegistrationError> {
+        tracing::warn!("built without CYW43439 firmware, WiFi is disabled");
+        Ok(())
+    }
+}
+
+/// Hands out the interface to the first client, and denies all further
+/// requests.
+async fn network_server(iface: NetInterface, reqs: RequestStream<NetworkService>) {
+    let req = reqs.next_request().await;
+    let Request::GetInterface = req.msg.body;
+    let resp = req.msg.reply_with(Ok(Response::Interface(iface)));
+    let _ = req.reply.reply_konly(resp).await;
+
+    loop {
+        let req = reqs.next_request().await;
+        let Request::GetInterface = req.msg.body;
+        let resp = req
+            .msg
+            .reply_with(Err(NetworkError::AlreadyAssignedInterface));
+        let _ = req.reply.reply_konly(resp).await;
+    }
+}
+
+/// Joins the configured network, retrying until it succeeds.
+#[cfg(cyw43_firmware)]
+async fn join(k: &'static Kernel, mut control: cyw43::Control<'static>, settings: WifiSettings) {
+    let Some(ssid) = settings.ssid else {
+        tracing::warn!("no WiFi SSID configured, not joining a network");
+        return;
+    };
+
+    loop {
+        let res = match settings.password {
+            Some(password) => control.join_wpa2(ssid, password).await,
+            None => control.join_open(ssid).await,
+        };
+        match res {
+            Ok(()) => {
+                tracing::info!(ssid, "joined WiFi network");
+                return;
+            }
+            Err(error) => {
+                tracing::warn!(ssid, ?error, "failed to join WiFi network, retrying...");
+                k.sleep(JOIN_RETRY).await;
+            }
+        }
+    }
+}
+
+/// Moves frames between the driver and the interface's channels.
+///
+/// Both directions are handled by one task, since the driver's
+/// [`Driver::receive`] and [`Driver::transmit`] share a waker for the TX
+/// side, which would be clobbered if two tasks polled them.
+async fn pump(
+    mut net: cyw43::NetDriver<'static>,
+    to_kernel: FramedProducer,
+    from_kernel: FramedConsumer,
+) {
+    enum Event {
+        Received(usize),
+        Send(kernel::comms::bbq::FrameGrantR),
+    }
+
+    loop {
+        // Make sure there's somewhere to put a received frame before taking
+        // it from the driver. If the kernel isn't keeping up, this leaves
+        // frames in the driver's queue, which drops frames once it's full.
+        let mut wgr = to_kernel.send_grant(MTU).await;
+
+        let event = futures::select_biased! {
+            frame = from_kernel.read_grant().fuse() => Event::Send(frame),
+            len = poll_fn(|cx| match net.receive(cx) {
+                Some((rx, _)) => Poll::Ready(rx.consume(|frame| {
+                    let len = frame.len().min(wgr.len());
+                    wgr[..len].copy_from_slice(&frame[..len]);
+                    len
+                })),
+                None => Poll::Pending,
+            }).fuse() => Event::Received(len),
+        };
+
+        match event {
+            Event::Received(len) => wgr.commit(len),
+            Event::Send(frame) if frame.len() > MTU => {
+                tracing::warn!(len = frame.len(), "dropping frame larger than the MTU");
+                frame.release();
+            }
+            Event::Send(frame) => {
+                drop(wgr);
+                poll_fn(|cx| match net.transmit(cx) {
+                    Some(tx) => {
+                        Poll::Ready(tx.consume(frame.len(), |buf| buf.copy_from_slice(&frame)))
+                    }
+                    None => Poll::Pending,
+                })
+                .await;
+                frame.release();
+            }
+        }
+    }
+}
@@ -0,0 +1,2 @@
+pub mod cyw43;
+pub mod uart;
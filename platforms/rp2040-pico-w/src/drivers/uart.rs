@@ -0,0 +1,92 @@
+//! A [`SimpleSerialService`] implementation for the RP2040's UARTs.
+//!
+//! This uses `embassy-rp`'s interrupt-driven [`BufferedUart`] driver, whose
+//! wakers work with any executor, including the kernel's.
+use embassy_rp::uart::{BufferedUart, BufferedUartRx, BufferedUartTx, Instance};
+use embedded_io_async::{Read, Write};
+use kernel::{
+    comms::bbq::{new_bidi_channel, BidiHandle, Consumer, SpscProducer},
+    registry,
+    services::simple_serial::{Request, Response, SimpleSerialError, SimpleSerialService},
+    Kernel,
+};
+
+/// The largest chunk of bytes read from the UART at once.
+const RX_CHUNK: usize = 64;
+
+pub struct PicoUart<T: Instance + 'static> {
+    uart: BufferedUart<'static, T>,
+}
+
+impl<T: Instance> PicoUart<T> {
+    pub fn new(uart: BufferedUart<'static, T>) -> Self {
+        Self { uart }
+    }
+
+    async fn serial_server(
+        handle: BidiHandle,
+        reqs: registry::listener::RequestStream<SimpleSerialService>,
+    ) {
+        let req = reqs.next_request().await;
+        let Request::GetPort = req.msg.body;
+        let resp = req.msg.reply_with(Ok(Response::PortHandle { handle }));
+        let _ = req.reply.reply_konly(resp).await;
+
+        // And deny all further requests after the first
+        loop {
+            let req = reqs.next_request().await;
+            let Request::GetPort = req.msg.body;
+            let resp = req
+                .msg
+                .reply_with(Err(SimpleSerialError::AlreadyAssignedPort));
+            let _ = req.reply.reply_konly(resp).await;
+        }
+    }
+
+    async fn sending(mut tx: BufferedUartTx<'static, T>, cons: Consumer) {
+        loop {
+            let rx = cons.read_grant().await;
+            let len = rx.len();
+            if let Err(error) = tx.write_all(&rx).await {
+                tracing::warn!(?error, "UART write failed");
+            }
+            rx.release(len);
+        }
+    }
+
+    async fn receiving(mut rx: BufferedUartRx<'static, T>, prod: SpscProducer) {
+        loop {
+            let mut wgr = prod.send_grant_max(RX_CHUNK).await;
+            match rx.read(&mut wgr).await {
+                Ok(len) => wgr.commit(len),
+                // Overruns and framing errors lose some bytes, but the UART
+                // is still usable.
+                Err(error) => tracing::warn!(?error, "UART read failed"),
+            }
+        }
+    }
+
+    pub async fn register(
+        self,
+        k: &'static Kernel,
+        cap_in: usize,
+        cap_out: usize,
+    ) -> Result<(), registry::RegistrationError> {
+        let (listener, registration) = registry::Listener::new(4).await;
+        let reqs = listener.into_request_stream(4).await;
+        let (fifo_a, fifo_b) = new_bidi_channel(cap_in, cap_out).await;
+
+        let _server_hdl = k.spawn(Self::serial_server(fifo_b, reqs)).await;
+
+        let (prod, cons) = fifo_a.split();
+        let (tx, rx) = self.uart.split();
+        let _send_hdl = k.spawn(Self::sending(tx, cons)).await;
+        let _recv_hdl = k.spawn(Self::receiving(rx, prod)).await;
+
+        k.registry()
+            .register_konly::<SimpleSerialService>(registration)
+            .await?;
+
+        Ok(())
+    }
+}
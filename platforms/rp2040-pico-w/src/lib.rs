@@ -0,0 +1,140 @@
+#![no_std]
+extern crate alloc;
+
+pub mod drivers;
+pub mod heap;
+
+use core::time::Duration;
+use cortex_m::peripheral::{syst::SystClkSource, SYST};
+use cortex_m_rt::exception;
+use embassy_rp::{
+    bind_interrupts,
+    peripherals::{PIO0, UART0},
+    pio, uart,
+};
+use kernel::{
+    daemons, maitake, mnemos_alloc::containers::Box, services, units::Ticks, Kernel, KernelSettings,
+};
+use panic_probe as _;
+
+bind_interrupts!(pub struct Irqs {
+    PIO0_IRQ_0 => pio::InterruptHandler<PIO0>;
+    UART0_IRQ => uart::BufferedInterruptHandler<UART0>;
+});
+
+/// The largest value that can be loaded into the 24-bit SysTick counter.
+const SYST_MAX_RELOAD: u32 = 0x00ff_ffff;
+
+/// The rate of the RP2040's `TIMER` peripheral, which is the kernel's clock.
+const TIMER_HZ: u64 = 1_000_000;
+type TimerTicks = Ticks<TIMER_HZ>;
+
+pub fn init() -> &'static Kernel {
+    let k_settings = KernelSettings { max_drivers: 16 };
+    // `embassy-time`'s driver counts microseconds using the RP2040's 64-bit
+    // TIMER peripheral, which is also used by the CYW43 driver. Use it as the
+    // kernel's clock as well, so that they agree on the time.
+    let clock = maitake::time::Clock::new(TimerTicks::TICK_DURATION, || {
+        embassy_time::Instant::now().as_ticks()
+    })
+    .named("CLOCK_RP2040_TIMER");
+    unsafe {
+        Box::into_raw(Kernel::new(k_settings, clock).expect("cannot initialize kernel"))
+            .as_ref()
+            .unwrap()
+    }
+}
+
+pub fn spawn_daemons(k: &'static Kernel) {
+    // initialize tracing first, so we can trace the boot process.
+    k.initialize(async move {
+        use kernel::serial_trace;
+        let trace_settings =
+            serial_trace::SerialTraceSettings::default().with_tracebuf_capacity(4096);
+        serial_trace::SerialSubscriber::start(k, trace_settings).await;
+    })
+    .expect("failed to spawn serial tracing daemon");
+
+    // Initialize the SerialMuxServer
+    k.initialize(services::serial_mux::SerialMuxServer::register(
+        k,
+        Default::default(),
+    ))
+    .expect("failed to spawn SerialMuxService initialization");
+
+    // Initialize Serial Mux daemons.
+    k.initialize(daemons::sermux::hello(k, Default::default()))
+        .expect("failed to spawn default serial mux service initialization");
+}
+
+/// Spawns the UART0 [`SimpleSerialService`](services::simple_serial) server,
+/// used by the serial mux.
+pub fn spawn_serial(k: &'static Kernel, uart: uart::BufferedUart<'static, UART0>) {
+    k.initialize(drivers::uart::PicoUart::new(uart).register(k, 512, 512))
+        .expect("failed to spawn PicoUart server!");
+}
+
+/// Spawns the CYW43439 WiFi driver and its
+/// [`NetworkService`](services::network) server.
+pub fn spawn_wifi(k: &'static Kernel, cyw43: drivers::cyw43::Cyw43) {
+    k.initialize(async move {
+        cyw43
+            .register(k, 4096, 4096)
+            .await
+            .expect("failed to register NetworkService");
+    })
+    .expect("failed to spawn CYW43 initialization");
+}
+
+/// Runs the kernel.
+///
+/// When there's nothing to do until the next timer deadline, the core waits
+/// for an interrupt with `wfi`, using SysTick to wake it at the deadline.
+/// Interrupts from peripherals, including `embassy-time`'s alarm, wake it
+/// earlier.
+pub fn run(k: &'static Kernel, mut syst: SYST) -> ! {
+    syst.set_clock_source(SystClkSource::Core);
+    let cycles_per_tick = embassy_rp::clocks::clk_sys_freq() as u64 / TIMER_HZ;
+
+    loop {
+        let tick = k.tick();
+        let turn = k.turn_timer();
+
+        // If there is nothing else scheduled, and we didn't just wake something up,
+        // sleep for some amount of time
+        if turn.expired == 0 && !tick.has_remaining {
+            // Sleep for at most 100ms if there's no deadline, or until the
+            // next deadline. At 125MHz, SysTick can count for at most ~134ms,
+            // so long sleeps are split up; we'll just come back around.
+            let amount = k
+                .ticks_to_next_deadline(&turn, Duration::from_millis(100))
+                .to_ticks::<TIMER_HZ>()
+                .unwrap_or(TimerTicks::MAX);
+            let reload = amount
+                .get()
+                .saturating_mul(cycles_per_tick)
+                .clamp(1, SYST_MAX_RELOAD as u64);
+
+            syst.set_reload(reload as u32);
+            syst.clear_current();
+            syst.enable_interrupt();
+            syst.enable_counter();
+
+            cortex_m::asm::wfi();
+
+            // Disable the timer in case that wasn't what woke us up
+            syst.disable_counter();
+            syst.disable_interrupt();
+
+            // Account for time slept
+            let _turn = k.turn_timer();
+        }
+    }
+}
+
+/// SysTick exception handler
+///
+/// We don't actually do anything in the SysTick exception. It is only here to
+/// knock us out of WFI.
+#[exception]
+fn SysTick() {}
@@ -1,4 +1,4 @@
-pub use acpi::{AcpiError, AcpiHandler, AcpiTables};
+pub use acpi::{AcpiError, AcpiHandler, AcpiTables, HpetInfo};
 use core::{fmt, ptr::NonNull};
 use hal_core::{Address, PAddr};
 use hal_x86_64::mm;
@@ -17,6 +17,8 @@ const MIN_HEAP_SIZE: usize = 32;
 #[derive(Debug)]
 pub struct Heap(());
 
+// DMA is cache-coherent on x86_64, so no DMA region is reserved, and
+// `mnemos_alloc::dma` allocates DMA buffers from this heap.
 #[global_allocator]
 pub static AHEAP: MnemosAlloc<Heap> = MnemosAlloc::new();
 
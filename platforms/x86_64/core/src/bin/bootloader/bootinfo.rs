@@ -68,6 +68,11 @@ impl BootInfo for BootloaderApiBootInfo {
 }
 
 impl BootloaderApiBootInfo {
+    /// Returns `true` if the bootloader set up a framebuffer.
+    pub(super) fn has_framebuffer(&self) -> bool {
+        self.has_framebuffer
+    }
+
     fn vm_offset(&self) -> VAddr {
         VAddr::from_u64(self.inner.physical_memory_offset.into_option().unwrap_or(0))
     }
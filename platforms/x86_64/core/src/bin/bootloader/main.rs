@@ -42,9 +42,15 @@ pub fn kernel_start(info: &'static mut bootloader_api::BootInfo) -> ! {
             // mapped" or "we don't know where the physical mem is mapped"?
             // check the bootloader docs...
             .unwrap_or(0);
+        // the bootimager passes the boot bundle to the kernel as the
+        // bootloader's ramdisk, which is mapped into virtual memory for us.
+        let bundle = info.ramdisk_addr.into_option().map(|addr| unsafe {
+            core::slice::from_raw_parts(addr as *const u8, info.ramdisk_len as usize)
+        });
         mnemos_x86_64::PlatformConfig {
             rsdp_addr: info.rsdp_addr.into_option().map(PAddr::from_u64),
             physical_mem_offset: VAddr::from_u64(phys_offset),
+            bundle,
         }
     };
     let bootinfo = bootinfo::BootloaderApiBootInfo::from_bootloader(info);
@@ -58,6 +64,13 @@ pub fn kernel_start(info: &'static mut bootloader_api::BootInfo) -> ! {
         .expect("tracing subscriber should not have already been set!");
 
     let k = mnemos_x86_64::init(&bootinfo, cfg);
+    if bootinfo.has_framebuffer() {
+        mnemos_x86_64::init_graphical_shell(
+            k,
+            || unsafe { framebuf::mk_framebuf() },
+            mnemos_x86_64::drivers::framebuf::FramebufDisplaySettings::default(),
+        );
+    }
     mnemos_x86_64::run(&bootinfo, k)
 }
 
@@ -1,15 +1,51 @@
-use core::fmt;
+//! Drivers for the framebuffer set up by the bootloader.
+//!
+//! [`TextWriter`] writes text directly to the framebuffer, and is used for
+//! kernel logs (and panics) until the [`FramebufDisplay`] driver takes over the
+//! framebuffer, providing an [`EmbDisplayService`] server for it.
+use core::{
+    fmt,
+    ops::{Deref, DerefMut},
+};
 use embedded_graphics::{
     draw_target::DrawTarget,
-    geometry::Point,
+    geometry::{Point, Size},
     mono_font::MonoTextStyle,
     pixelcolor::PixelColor,
     text::{self, Text},
     Drawable,
 };
-use hal_core::framebuffer::{self, Draw};
+use hal_core::framebuffer::{self, Draw, RgbColor};
+use hal_x86_64::framebuffer::Framebuffer;
+use kernel::{
+    registry::{self, listener},
+    services::emb_display::{
+        capabilities, DisplayMetadata, DisplayTransform, EmbDisplayService, FrameChunk, FrameError,
+        FrameKind, MonoChunk, Request, Response,
+    },
+    Kernel,
+};
+
+/// Implements the [`EmbDisplayService`] for the framebuffer set up by the
+/// bootloader.
+///
+/// Frames are monochrome, and are drawn in white on black.
+pub struct FramebufDisplay;
 
-// TODO(eliza): add emb_display_service implementation!
+#[derive(Debug, Copy, Clone)]
+#[non_exhaustive]
+pub struct FramebufDisplaySettings {
+    /// Should the framebuffer display driver and the graphical Forth shell be
+    /// started?
+    pub enabled: bool,
+    /// Each pixel drawn by clients is drawn as a square of `scale` by `scale`
+    /// framebuffer pixels.
+    ///
+    /// If this is `None`, the largest scale at which the display is still at
+    /// least [`FramebufDisplaySettings::MIN_SCALED_SIZE`] is used, so that text
+    /// stays legible at very large resolutions.
+    pub scale: Option<u32>,
+}
 
 #[derive(Debug)]
 pub struct TextWriter<'style, 'target, D, C> {
@@ -160,3 +196,166 @@ where
         Ok(())
     }
 }
+
+// === impl FramebufDisplay ===
+
+impl FramebufDisplay {
+    const CAPACITY: usize = 2;
+
+    /// Register the driver, which draws to the framebuffer returned by
+    /// `framebuf`.
+    ///
+    /// Once the driver is registered, kernel logs are no longer written to the
+    /// framebuffer.
+    #[tracing::instrument(
+        name = "FramebufDisplay::register",
+        level = tracing::Level::INFO,
+        skip(kernel, framebuf),
+        err(Debug),
+    )]
+    pub async fn register<F>(
+        kernel: &'static Kernel,
+        framebuf: fn() -> Framebuffer<'static, F>,
+        settings: FramebufDisplaySettings,
+    ) -> Result<(), registry::RegistrationError>
+    where
+        F: Deref<Target = [u8]> + DerefMut + 'static,
+    {
+        let (width, height) = {
+            let fb = framebuf();
+            (fb.width() as u32, fb.height() as u32)
+        };
+        let transform = settings.transform(width, height);
+        tracing::info!(width, height, ?transform, "Starting FramebufDisplay driver");
+
+        let cmd = kernel
+            .registry()
+            .bind_konly(Self::CAPACITY)
+            .await?
+            .into_request_stream(Self::CAPACITY)
+            .await
+            .with_capabilities(capabilities::TRANSFORM);
+
+        // from now on, the framebuffer belongs to the display's clients.
+        crate::trace::release_framebuf();
+        framebuf().fill(BACKGROUND);
+
+        let commander = CommanderTask {
+            cmd,
+            framebuf,
+            width,
+            height,
+            transform,
+        };
+        kernel.spawn(commander.cmd_run()).await;
+
+        Ok(())
+    }
+}
+
+// === impl FramebufDisplaySettings ===
+
+impl FramebufDisplaySettings {
+    /// The smallest display size that automatic scaling scales the
+    /// framebuffer down to.
+    pub const MIN_SCALED_SIZE: Size = Size::new(640, 400);
+
+    /// Returns the transform for a framebuffer of `width` by `height` pixels.
+    #[must_use]
+    pub fn transform(&self, width: u32, height: u32) -> DisplayTransform {
+        let scale = self.scale.unwrap_or_else(|| {
+            (width / Self::MIN_SCALED_SIZE.width)
+                .min(height / Self::MIN_SCALED_SIZE.height)
+                .max(1)
+        });
+        DisplayTransform {
+            scale,
+            ..DisplayTransform::IDENTITY
+        }
+    }
+}
+
+impl Default for FramebufDisplaySettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            scale: None,
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Driver task
+////////////////////////////////////////////////////////////////////////////////
+
+const BACKGROUND: RgbColor = RgbColor::BLACK;
+const FOREGROUND: RgbColor = RgbColor::WHITE;
+
+/// This task is spawned by the call to [`FramebufDisplay::register`]. It
+/// draws each chunk it receives straight into the framebuffer.
+struct CommanderTask<F: Deref<Target = [u8]> + DerefMut + 'static> {
+    cmd: listener::RequestStream<EmbDisplayService>,
+    framebuf: fn() -> Framebuffer<'static, F>,
+    width: u32,
+    height: u32,
+    transform: DisplayTransform,
+}
+
+impl<F> CommanderTask<F>
+where
+    F: Deref<Target = [u8]> + DerefMut + 'static,
+{
+    #[tracing::instrument(skip(self))]
+    async fn cmd_run(self) {
+        // Generally, don't handle errors when replying to clients, this
+        // indicates that they sent us a message and "hung up" without waiting
+        // for a response.
+        loop {
+            let (req, env, reply_tx) = self.cmd.next_request().await.split();
+            let response = match req {
+                Request::Draw(FrameChunk::Mono(fc)) => {
+                    tracing::trace!("Processing Draw Mono command");
+                    self.draw(&fc);
+                    Ok(Response::DrawComplete(fc.into()))
+                }
+                Request::GetMeta => Ok(Response::FrameMeta(DisplayMetadata {
+                    kind: FrameKind::Mono,
+                    width: self.width,
+                    height: self.height,
+                    transform: self.transform,
+                })),
+                _ => Err(FrameError::InternalError),
+            };
+            let _ = reply_tx.reply_konly(env.fill(response)).await;
+        }
+    }
+
+    fn draw(&self, src: &MonoChunk) {
+        let meta = src.meta();
+        let (start_x, start_y) = (meta.start_x(), meta.start_y());
+        let src_width = meta.width() as usize;
+        if start_x >= self.width || start_y >= self.height || src_width == 0 {
+            return;
+        }
+
+        // Clip the chunk to the display.
+        let visible_width = src_width.min((self.width - start_x) as usize);
+        let rows = src
+            .data()
+            .chunks(src_width)
+            .zip(src.mask().chunks(src_width))
+            .take((self.height - start_y) as usize);
+
+        let mut fb = (self.framebuf)();
+        for (src_y, (data, mask)) in rows.enumerate() {
+            let y = start_y as usize + src_y;
+            let pixels = data.iter().zip(mask).take(visible_width).enumerate();
+            for (src_x, (&data, &mask)) in pixels {
+                if mask != 0 {
+                    let color = if data < 128 { BACKGROUND } else { FOREGROUND };
+                    fb.set_pixel(start_x as usize + src_x, y, color);
+                }
+            }
+        }
+    }
+}
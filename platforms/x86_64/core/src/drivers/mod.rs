@@ -1 +1,4 @@
 pub mod framebuf;
+pub mod ps2_keyboard;
+pub mod ps2_mouse;
+pub mod rdrand;
@@ -0,0 +1,260 @@
+//! PS/2 keyboard driver, for the keyboard port of an Intel 8042 compatible
+//! PS/2 controller, such as the one emulated by QEMU.
+//!
+//! Key events are published to the kernel's
+//! [`KeyboardMuxService`](kernel::services::keyboard::mux::KeyboardMuxService).
+//!
+//! The HAL reads scancodes from the controller in the keyboard's interrupt
+//! handler, which passes them to the driver task using a small lock-free
+//! queue. The controller translates scancodes to scancode set 1 by default,
+//! which is the only set the driver understands, and keys are mapped using a
+//! US layout.
+use core::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
+use kernel::{
+    maitake::sync::WaitCell,
+    services::keyboard::{
+        key_event::{KeyCode, Kind, Modifiers},
+        mux::KeyboardMuxClient,
+        KeyEvent,
+    },
+    Kernel,
+};
+
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct Ps2KeyboardSettings {
+    pub enabled: bool,
+}
+
+/// Scancodes received by the keyboard interrupt, which haven't been decoded
+/// by the driver task yet.
+static SCANCODES: Scancodes = Scancodes::new();
+
+/// A single-producer, single-consumer queue of scancodes. The producer is the
+/// keyboard interrupt handler, so pushing must never block.
+struct Scancodes {
+    buf: [AtomicU8; Scancodes::CAPACITY],
+    /// The index of the next scancode to pop. Only written by the driver.
+    head: AtomicUsize,
+    /// The index of the next scancode to push. Only written by the interrupt.
+    tail: AtomicUsize,
+    ready: WaitCell,
+}
+
+/// Turns a stream of set 1 scancodes into [`KeyEvent`]s.
+#[derive(Debug)]
+struct Decoder {
+    /// Was the previous scancode the `0xE0` prefix of an extended key?
+    extended: bool,
+    modifiers: Modifiers,
+}
+
+/// Run the PS/2 keyboard driver.
+///
+/// This forwards key events from the keyboard interrupt to the
+/// [`KeyboardMuxService`](kernel::services::keyboard::mux::KeyboardMuxService)
+/// forever.
+#[tracing::instrument(level = tracing::Level::INFO, skip(kernel, settings))]
+pub async fn run(kernel: &'static Kernel, settings: Ps2KeyboardSettings) {
+    let _ = settings;
+    let mut mux = match KeyboardMuxClient::from_registry(kernel).await {
+        Ok(mux) => mux,
+        Err(error) => {
+            tracing::warn!(
+                ?error,
+                "no keyboard mux, not starting the PS/2 keyboard driver"
+            );
+            return;
+        }
+    };
+    tracing::info!("PS/2 keyboard driver running");
+
+    let mut decoder = Decoder::new();
+    loop {
+        while let Some(scancode) = SCANCODES.pop() {
+            let Some(event) = decoder.push(scancode) else {
+                continue;
+            };
+            tracing::trace!(?event, "PS/2 keyboard event");
+            if mux.publish_key(event).await.is_err() {
+                tracing::warn!("keyboard mux is gone, stopping the PS/2 keyboard driver");
+                return;
+            }
+        }
+        // a scancode pushed since the queue was drained wakes the cell even
+        // though we weren't waiting yet, so this won't miss it.
+        let _ = SCANCODES.ready.wait().await;
+    }
+}
+
+/// Called by the keyboard interrupt handler with each scancode it receives.
+pub(crate) fn handle_scancode(scancode: u8) {
+    if !SCANCODES.push(scancode) {
+        tracing::warn!(
+            scancode,
+            "PS/2 keyboard scancode queue full, dropping scancode"
+        );
+    }
+    SCANCODES.ready.wake();
+}
+
+// === impl Ps2KeyboardSettings ===
+
+impl Default for Ps2KeyboardSettings {
+    fn default() -> Self {
+        Self { enabled: true }
+    }
+}
+
+// === impl Scancodes ===
+
+impl Scancodes {
+    const CAPACITY: usize = 64;
+
+    const fn new() -> Self {
+        #[allow(clippy::declare_interior_mutable_const)]
+        const EMPTY: AtomicU8 = AtomicU8::new(0);
+        Self {
+            buf: [EMPTY; Self::CAPACITY],
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+            ready: WaitCell::new(),
+        }
+    }
+
+    /// Pushes a scancode, returning `false` if the queue is full.
+    fn push(&self, scancode: u8) -> bool {
+        let tail = self.tail.load(Ordering::Relaxed);
+        if tail.wrapping_sub(self.head.load(Ordering::Acquire)) >= Self::CAPACITY {
+            return false;
+        }
+        self.buf[tail % Self::CAPACITY].store(scancode, Ordering::Relaxed);
+        self.tail.store(tail.wrapping_add(1), Ordering::Release);
+        true
+    }
+
+    fn pop(&self) -> Option<u8> {
+        let head = self.head.load(Ordering::Relaxed);
+        if head == self.tail.load(Ordering::Acquire) {
+            return None;
+        }
+        let scancode = self.buf[head % Self::CAPACITY].load(Ordering::Relaxed);
+        self.head.store(head.wrapping_add(1), Ordering::Release);
+        Some(scancode)
+    }
+}
+
+// === impl Decoder ===
+
+impl Decoder {
+    const EXTENDED: u8 = 0xE0;
+    const RELEASED: u8 = 0x80;
+
+    /// Characters typed by the keys with scancodes `0x00..=0x39`, without and
+    /// with shift held. Zero means the key doesn't type a character.
+    const CHARS: [&'static [u8; 0x3A]; 2] = [
+        b"\0\x001234567890-=\0\0qwertyuiop[]\0\0asdfghjkl;'`\0\\zxcvbnm,./\0*\0 ",
+        b"\0\0!@#$%^&*()_+\0\0QWERTYUIOP{}\0\0ASDFGHJKL:\"~\0|ZXCVBNM<>?\0*\0 ",
+    ];
+
+    const fn new() -> Self {
+        Self {
+            extended: false,
+            modifiers: Modifiers::new(),
+        }
+    }
+
+    fn push(&mut self, scancode: u8) -> Option<KeyEvent> {
+        if scancode == Self::EXTENDED {
+            self.extended = true;
+            return None;
+        }
+        let extended = core::mem::take(&mut self.extended);
+        let kind = if scancode & Self::RELEASED != 0 {
+            Kind::Released
+        } else {
+            Kind::Pressed
+        };
+        let pressed = kind == Kind::Pressed;
+        let code = scancode & !Self::RELEASED;
+
+        // modifier keys update the decoder's state, but aren't published.
+        match code {
+            0x2A | 0x36 if !extended => {
+                self.modifiers.set(Modifiers::SHIFT, pressed);
+                return None;
+            }
+            0x1D => {
+                self.modifiers.set(Modifiers::CTRL, pressed);
+                return None;
+            }
+            0x38 => {
+                self.modifiers.set(Modifiers::ALT, pressed);
+                return None;
+            }
+            0x3A if !extended => {
+                if pressed {
+                    let caps = self.modifiers.get(Modifiers::CAPSLOCK);
+                    self.modifiers.set(Modifiers::CAPSLOCK, !caps);
+                }
+                return None;
+            }
+            _ => {}
+        }
+
+        let code = if extended {
+            Self::extended_key(code)?
+        } else {
+            self.key(code)?
+        };
+        Some(KeyEvent {
+            kind,
+            modifiers: self.modifiers,
+            code,
+        })
+    }
+
+    fn key(&self, code: u8) -> Option<KeyCode> {
+        let key = match code {
+            0x01 => KeyCode::Esc,
+            0x0E => KeyCode::Backspace,
+            0x0F if self.modifiers.get(Modifiers::SHIFT) => KeyCode::BackTab,
+            0x0F => KeyCode::Tab,
+            0x1C => KeyCode::Enter,
+            0x3B..=0x44 => KeyCode::F(code - 0x3B + 1),
+            0x57 => KeyCode::F(11),
+            0x58 => KeyCode::F(12),
+            _ => {
+                let unshifted = *Self::CHARS[0].get(code as usize)?;
+                // caps lock only affects letters.
+                let shift = self.modifiers.get(Modifiers::SHIFT)
+                    ^ (self.modifiers.get(Modifiers::CAPSLOCK) && unshifted.is_ascii_lowercase());
+                let c = Self::CHARS[shift as usize][code as usize];
+                if c == 0 {
+                    return None;
+                }
+                KeyCode::Char(c as char)
+            }
+        };
+        Some(key)
+    }
+
+    fn extended_key(code: u8) -> Option<KeyCode> {
+        let key = match code {
+            0x1C => KeyCode::Enter,
+            0x35 => KeyCode::Char('/'),
+            0x47 => KeyCode::Home,
+            0x48 => KeyCode::Up,
+            0x49 => KeyCode::PageUp,
+            0x4B => KeyCode::Left,
+            0x4D => KeyCode::Right,
+            0x4F => KeyCode::End,
+            0x50 => KeyCode::Down,
+            0x51 => KeyCode::PageDown,
+            0x52 => KeyCode::Insert,
+            0x53 => KeyCode::Delete,
+            _ => return None,
+        };
+        Some(key)
+    }
+}
@@ -0,0 +1,238 @@
+//! PS/2 mouse driver, for the mouse port (the "auxiliary device") of an Intel
+//! 8042 compatible PS/2 controller, such as the one emulated by QEMU.
+//!
+//! Mouse packets are decoded by [`ps2::Decoder`], and published to the
+//! kernel's
+//! [`PointerMuxService`](kernel::services::pointer::mux::PointerMuxService).
+//!
+//! If the mouse supports the IntelliMouse extension, which QEMU's does, the
+//! driver enables it, so that scroll wheel motion is reported as well.
+//!
+//! The mouse's IRQ (IRQ 12) is not yet routed to us by the HAL, which only
+//! exposes the keyboard's IRQ. Instead, the driver polls the controller's
+//! status register, leaving the mouse's interrupt disabled in the
+//! controller's configuration byte.
+// TODO: use IRQ 12 instead, once `hal_core::interrupt::Handlers` has a hook
+// for it. this needs an upstream change to mycelium's HAL.
+use core::time::Duration;
+use hal_x86_64::cpu::Port;
+use kernel::{
+    services::pointer::{mux::PointerMuxClient, ps2},
+    Kernel,
+};
+
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct Ps2MouseSettings {
+    pub enabled: bool,
+    /// How often the controller is polled for mouse data.
+    pub poll_interval: Duration,
+    /// The number of reports per second the mouse is asked to send.
+    ///
+    /// The PS/2 protocol only permits 10, 20, 40, 60, 80, 100, and 200.
+    pub sample_rate: u8,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Error {
+    /// The controller did not become ready, or the mouse did not answer, in
+    /// time. Usually, this means there is no mouse.
+    Timeout,
+    /// The mouse answered a command with something other than an ACK.
+    NoAck { command: u8, response: u8 },
+}
+
+/// Run the PS/2 mouse driver.
+///
+/// This initializes the mouse, and then forwards its reports to the
+/// [`PointerMuxService`](kernel::services::pointer::mux::PointerMuxService)
+/// forever. If no mouse is found, this returns immediately.
+#[tracing::instrument(level = tracing::Level::INFO, skip(kernel, settings))]
+pub async fn run(kernel: &'static Kernel, settings: Ps2MouseSettings) {
+    let mut mux = match PointerMuxClient::from_registry(kernel).await {
+        Ok(mux) => mux,
+        Err(error) => {
+            tracing::warn!(?error, "no pointer mux, not starting the PS/2 mouse driver");
+            return;
+        }
+    };
+
+    let controller = Controller::new();
+    let mut mouse = match controller.init_mouse(settings.sample_rate) {
+        Ok(mouse) => mouse,
+        Err(error) => {
+            tracing::warn!(?error, "failed to initialize PS/2 mouse");
+            return;
+        }
+    };
+    tracing::info!(wheel = mouse.has_wheel(), "PS/2 mouse initialized");
+
+    loop {
+        kernel.sleep(settings.poll_interval).await;
+        while let Some(byte) = controller.try_read_aux() {
+            let Some(event) = mouse.push(byte) else {
+                continue;
+            };
+            tracing::trace!(?event, "PS/2 mouse event");
+            if mux.publish(event).await.is_err() {
+                tracing::warn!("pointer mux is gone, stopping the PS/2 mouse driver");
+                return;
+            }
+        }
+    }
+}
+
+// === impl Ps2MouseSettings ===
+
+impl Ps2MouseSettings {
+    pub const DEFAULT_POLL_INTERVAL: Duration = Duration::from_millis(10);
+    pub const DEFAULT_SAMPLE_RATE: u8 = 100;
+}
+
+impl Default for Ps2MouseSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            poll_interval: Self::DEFAULT_POLL_INTERVAL,
+            sample_rate: Self::DEFAULT_SAMPLE_RATE,
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// The 8042 controller
+////////////////////////////////////////////////////////////////////////////////
+
+struct Controller {
+    data: Port,
+    status_cmd: Port,
+}
+
+impl Controller {
+    // Status register bits.
+    const OUTPUT_FULL: u8 = 1 << 0;
+    const INPUT_FULL: u8 = 1 << 1;
+    const AUX_DATA: u8 = 1 << 5;
+
+    // Controller commands.
+    const READ_CONFIG: u8 = 0x20;
+    const WRITE_CONFIG: u8 = 0x60;
+    const ENABLE_AUX: u8 = 0xA8;
+    const WRITE_AUX: u8 = 0xD4;
+
+    // Configuration byte bits.
+    const CONFIG_AUX_IRQ: u8 = 1 << 1;
+    const CONFIG_AUX_CLOCK_DISABLED: u8 = 1 << 5;
+
+    // Mouse commands.
+    const MOUSE_SET_SAMPLE_RATE: u8 = 0xF3;
+    const MOUSE_GET_ID: u8 = 0xF2;
+    const MOUSE_ENABLE_REPORTING: u8 = 0xF4;
+    const MOUSE_SET_DEFAULTS: u8 = 0xF6;
+    const MOUSE_ACK: u8 = 0xFA;
+
+    /// Device ID reported by mice with the IntelliMouse scroll wheel
+    /// extension enabled.
+    const INTELLIMOUSE_ID: u8 = 3;
+
+    /// How many times to poll the status register before giving up on the
+    /// controller or the mouse. Each port read takes about a microsecond.
+    const SPINS: usize = 100_000;
+
+    fn new() -> Self {
+        Self {
+            data: Port::at(0x60),
+            status_cmd: Port::at(0x64),
+        }
+    }
+
+    fn init_mouse(&self, sample_rate: u8) -> Result<ps2::Decoder, Error> {
+        // discard anything left over from the firmware.
+        while self.status() & Self::OUTPUT_FULL != 0 {
+            let _ = unsafe { self.data.readb() };
+        }
+
+        self.command(Self::ENABLE_AUX)?;
+        self.command(Self::READ_CONFIG)?;
+        let config = self.read()?;
+        let config = config & !(Self::CONFIG_AUX_CLOCK_DISABLED | Self::CONFIG_AUX_IRQ);
+        self.command(Self::WRITE_CONFIG)?;
+        self.write(config)?;
+
+        self.mouse_command(Self::MOUSE_SET_DEFAULTS)?;
+
+        // The IntelliMouse "knock": setting the sample rate to 200, 100, and
+        // then 80 switches a wheel mouse into 4-byte packet mode, which it
+        // acknowledges by changing its device ID.
+        for rate in [200, 100, 80] {
+            self.set_sample_rate(rate)?;
+        }
+        self.mouse_command(Self::MOUSE_GET_ID)?;
+        let id = self.read()?;
+        tracing::debug!(id, "PS/2 mouse ID");
+
+        self.set_sample_rate(sample_rate)?;
+        self.mouse_command(Self::MOUSE_ENABLE_REPORTING)?;
+
+        Ok(ps2::Decoder::new(id == Self::INTELLIMOUSE_ID))
+    }
+
+    fn set_sample_rate(&self, rate: u8) -> Result<(), Error> {
+        self.mouse_command(Self::MOUSE_SET_SAMPLE_RATE)?;
+        self.mouse_command(rate)
+    }
+
+    /// Sends a byte to the mouse, and waits for it to be acknowledged.
+    fn mouse_command(&self, command: u8) -> Result<(), Error> {
+        self.command(Self::WRITE_AUX)?;
+        self.write(command)?;
+        match self.read()? {
+            Self::MOUSE_ACK => Ok(()),
+            response => Err(Error::NoAck { command, response }),
+        }
+    }
+
+    fn command(&self, command: u8) -> Result<(), Error> {
+        self.wait_for(|status| status & Self::INPUT_FULL == 0)?;
+        unsafe { self.status_cmd.writeb(command) };
+        Ok(())
+    }
+
+    fn write(&self, byte: u8) -> Result<(), Error> {
+        self.wait_for(|status| status & Self::INPUT_FULL == 0)?;
+        unsafe { self.data.writeb(byte) };
+        Ok(())
+    }
+
+    fn read(&self) -> Result<u8, Error> {
+        self.wait_for(|status| status & Self::OUTPUT_FULL != 0)?;
+        Ok(unsafe { self.data.readb() })
+    }
+
+    /// Reads a byte from the mouse, if one is waiting.
+    ///
+    /// Bytes from the keyboard are left alone, for the keyboard's interrupt
+    /// handler.
+    fn try_read_aux(&self) -> Option<u8> {
+        let status = self.status();
+        if status & (Self::OUTPUT_FULL | Self::AUX_DATA) == (Self::OUTPUT_FULL | Self::AUX_DATA) {
+            Some(unsafe { self.data.readb() })
+        } else {
+            None
+        }
+    }
+
+    fn wait_for(&self, f: impl Fn(u8) -> bool) -> Result<(), Error> {
+        for _ in 0..Self::SPINS {
+            if f(self.status()) {
+                return Ok(());
+            }
+            core::hint::spin_loop();
+        }
+        Err(Error::Timeout)
+    }
+
+    fn status(&self) -> u8 {
+        unsafe { self.status_cmd.readb() }
+    }
+}
@@ -0,0 +1,53 @@
+//! Random bytes from the CPU's `RDRAND` instruction.
+//!
+//! `RDRAND` returns values from a CSPRNG which the CPU continuously reseeds
+//! from its on-chip entropy source. It isn't available on every CPU (for
+//! example, QEMU's default `qemu64` CPU model lacks it), so [`Rdrand::new`]
+//! checks for it first.
+use core::arch::x86_64::{CpuidResult, __cpuid, _rdrand64_step};
+use kernel::services::entropy::{EntropyError, EntropySource};
+
+/// The `RDRAND` instruction, if this CPU has it.
+pub struct Rdrand {
+    _p: (),
+}
+
+impl Rdrand {
+    /// `CPUID` leaf 1, `ECX` bit 30 is set if `RDRAND` is supported.
+    const CPUID_RDRAND: u32 = 1 << 30;
+
+    /// Intel recommends retrying a failed `RDRAND` 10 times before deciding
+    /// that the generator has failed.
+    const RETRIES: usize = 10;
+
+    /// Returns an `Rdrand` if this CPU supports the instruction.
+    pub fn new() -> Option<Self> {
+        // Safety: `CPUID` is always available in 64-bit mode.
+        let CpuidResult { ecx, .. } = unsafe { __cpuid(1) };
+        (ecx & Self::CPUID_RDRAND != 0).then_some(Self { _p: () })
+    }
+
+    fn next(&mut self) -> Result<u64, EntropyError> {
+        for _ in 0..Self::RETRIES {
+            let mut value = 0;
+            // Safety: an `Rdrand` is only constructed if the CPU supports the
+            // instruction.
+            if unsafe { _rdrand64_step(&mut value) } == 1 {
+                return Ok(value);
+            }
+            core::hint::spin_loop();
+        }
+        tracing::error!("RDRAND failed {} times in a row", Self::RETRIES);
+        Err(EntropyError::Unavailable)
+    }
+}
+
+impl EntropySource for Rdrand {
+    fn fill(&mut self, buf: &mut [u8]) -> Result<(), EntropyError> {
+        for chunk in buf.chunks_mut(8) {
+            let value = self.next()?.to_ne_bytes();
+            chunk.copy_from_slice(&value[..chunk.len()]);
+        }
+        Ok(())
+    }
+}
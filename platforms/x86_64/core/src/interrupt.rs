@@ -7,7 +7,10 @@ use hal_x86_64::{
     segment::{self, Gdt},
     task,
 };
-use kernel::maitake::time;
+use kernel::{
+    isr::{self, Isr},
+    maitake::time,
+};
 use mycelium_util::{fmt, sync};
 
 #[tracing::instrument]
@@ -19,15 +22,47 @@ pub fn enable_exceptions() {
     tracing::info!("IDT initialized!");
 }
 
-#[tracing::instrument(skip(acpi))]
-pub fn enable_hardware_interrupts(acpi: Option<&acpi::InterruptModel>) {
+#[tracing::instrument(skip(acpi, hpet))]
+pub fn enable_hardware_interrupts(
+    acpi: Option<&acpi::InterruptModel>,
+    hpet: Option<&acpi::HpetInfo>,
+) {
     let controller = Controller::enable_hardware_interrupts(acpi, &crate::allocator::HEAP);
-    controller
-        .start_periodic_timer(TIMER_INTERVAL)
-        .expect("10ms should be a reasonable interval for the PIT or local APIC timer...");
+    if let Some(acpi::InterruptModel::Apic(_)) = acpi {
+        // calibrate the local APIC timer ourselves, so that the same
+        // calibration can be used for application processors' timers.
+        crate::timer::init(hpet);
+    } else {
+        controller
+            .start_periodic_timer(TIMER_INTERVAL)
+            .expect("10ms should be a reasonable interval for the PIT...");
+    }
     tracing::info!(granularity = ?TIMER_INTERVAL, "global timer initialized")
 }
 
+/// Returns the local APIC priority class used for interrupts with the given
+/// [`isr::Priority`].
+///
+/// The local APIC has no per-interrupt priority registers. Instead, an
+/// interrupt's priority class is the upper 4 bits of its IDT vector, and
+/// higher classes preempt lower ones. Classes 0 and 1 are reserved for CPU
+/// exceptions, so an interrupt is given a priority by assigning it a vector in
+/// the range `class << 4..=(class << 4) | 0xf`. The timer and PS/2 keyboard
+/// vectors are assigned by `hal-x86_64`, in class 2.
+///
+/// Interrupt handlers are entered through interrupt gates, which disable
+/// interrupts, and never re-enable them, so handlers are never nested on
+/// x86_64, regardless of an [`isr::IsrConfig`]'s nesting.
+#[must_use]
+pub const fn priority_class(priority: isr::Priority) -> u8 {
+    match priority {
+        isr::Priority::Low => 0x3,
+        isr::Priority::Normal => 0x6,
+        isr::Priority::High => 0x9,
+        isr::Priority::Critical => 0xc,
+    }
+}
+
 /// Wait for an interrupt in a spin loop.
 ///
 /// This is distinct from `core::hint::spin_loop`, as it is intended
@@ -122,18 +157,24 @@ impl hal_core::interrupt::Handlers<Registers> for InterruptHandlers {
     }
 
     fn timer_tick() {
-        IDIOTIC_CLOCK_TICKS.fetch_add(1, Ordering::Relaxed);
+        let _isr = Isr::enter();
+        // only the boot processor's timer advances the clock. other cores'
+        // timer interrupts just wake them up to turn their timer wheels.
+        if crate::timer::is_boot_core() {
+            IDIOTIC_CLOCK_TICKS.fetch_add(1, Ordering::Relaxed);
+        }
     }
 
     fn ps2_keyboard(scancode: u8) {
-        // TODO(eliza): add a keyboard driver
-        tracing::info!(scancode, "keyoard interrupt!!!");
+        let _isr = Isr::enter();
+        crate::drivers::ps2_keyboard::handle_scancode(scancode);
     }
 
     fn test_interrupt<C>(cx: C)
     where
         C: hal_core::interrupt::ctx::Context<Registers = Registers>,
     {
+        let _isr = Isr::enter();
         let fired = TEST_INTERRUPT_WAS_FIRED.fetch_add(1, Ordering::Release) + 1;
         tracing::info!(registers = ?cx.registers(), fired, "lol im in ur test interrupt");
     }
@@ -1,22 +1,42 @@
 #![no_std]
 extern crate alloc;
 
-use core::time::Duration;
-use hal_core::{boot::BootInfo, PAddr, VAddr};
-use hal_x86_64::cpu::local::GsLocalData;
+use core::{
+    ops::{Deref, DerefMut},
+    time::Duration,
+};
+use hal_core::{boot::BootInfo, framebuffer::Draw, PAddr, VAddr};
 pub use hal_x86_64::cpu::{local::LocalKey, wait_for_interrupt};
-use kernel::{mnemos_alloc::containers::Box, Kernel, KernelSettings};
+use hal_x86_64::{cpu::local::GsLocalData, framebuffer::Framebuffer};
+use kernel::{
+    bundle::BootBundle,
+    daemons::{shells, soft_keyboard},
+    mnemos_alloc::containers::Box,
+    services::{
+        device_identity::{DeviceId, DeviceIdentityServer},
+        entropy::EntropyServer,
+        forth_spawnulator::SpawnulatorServer,
+        keyboard::mux::KeyboardMuxServer,
+        pointer::mux::PointerMuxServer,
+    },
+    Kernel, KernelServiceSettings, KernelSettings,
+};
 
 pub mod acpi;
 pub mod allocator;
 pub mod drivers;
 pub mod interrupt;
+pub mod smbios;
+pub mod timer;
 pub mod trace;
 
 #[derive(Debug)]
 pub struct PlatformConfig {
     pub rsdp_addr: Option<PAddr>,
     pub physical_mem_offset: VAddr,
+    /// The [boot bundle](kernel::bundle) passed to the kernel by the
+    /// bootimager, if there is one.
+    pub bundle: Option<&'static [u8]>,
 }
 
 pub fn init(bootinfo: &impl BootInfo, cfg: PlatformConfig) -> &'static Kernel {
@@ -48,10 +68,77 @@ pub fn init(bootinfo: &impl BootInfo, cfg: PlatformConfig) -> &'static Kernel {
     GsLocalData::init();
     tracing::info!("set up the boot processor's local data");
 
+    let services = service_settings(cfg.bundle);
+
     // TODO: spawn drivers (UART, keyboard, ...)
+    if let Some(uuid) = smbios::system_uuid() {
+        k.initialize_step(
+            "device_identity",
+            &[],
+            DeviceIdentityServer::register(k, Default::default(), DeviceId::new(uuid)),
+        )
+        .unwrap();
+    } else {
+        tracing::warn!("no SMBIOS system UUID, this machine has no device identity");
+    }
+
+    if let Some(rdrand) = drivers::rdrand::Rdrand::new() {
+        k.initialize_step(
+            "entropy",
+            &[],
+            EntropyServer::register(k, Default::default(), rdrand),
+        )
+        .unwrap();
+    } else {
+        tracing::warn!("no RDRAND, this machine has no source of entropy");
+    }
+
+    k.initialize_step(
+        "pointer_mux",
+        &[],
+        PointerMuxServer::register(k, services.pointer_mux),
+    )
+    .unwrap();
+
+    k.initialize_step(
+        "keyboard_mux",
+        &[],
+        // there's no serial mux to take keyboard input from.
+        KeyboardMuxServer::register(k, services.keyboard_mux.with_sermux_port(None)),
+    )
+    .unwrap();
+
+    if services.spawnulator.enabled {
+        k.initialize_step(
+            "spawnulator",
+            &[],
+            SpawnulatorServer::register(k, services.spawnulator),
+        )
+        .unwrap();
+    }
+
+    let keyboard = drivers::ps2_keyboard::Ps2KeyboardSettings::default();
+    if keyboard.enabled {
+        k.initialize(drivers::ps2_keyboard::run(k, keyboard))
+            .unwrap();
+    }
+
+    let mouse = drivers::ps2_mouse::Ps2MouseSettings::default();
+    if mouse.enabled {
+        k.initialize(drivers::ps2_mouse::run(k, mouse)).unwrap();
+    }
+
+    // the on-screen keyboard waits for the framebuffer display, so it's fine
+    // to spawn it before the graphical shell is initialized.
+    if services.soft_keyboard.enabled {
+        k.initialize(soft_keyboard::run(k, services.soft_keyboard))
+            .unwrap();
+    }
+
     k.initialize(async {
         loop {
-            k.timer().sleep(Duration::from_secs(5)).await;
+            k.sleep_with_slack(Duration::from_secs(5), Duration::from_secs(1))
+                .await;
             tracing::info!("help im trapped in an x86_64 operating system kernel!");
         }
     })
@@ -60,6 +147,80 @@ pub fn init(bootinfo: &impl BootInfo, cfg: PlatformConfig) -> &'static Kernel {
     k
 }
 
+/// Spawns the [`FramebufDisplay`] driver for the framebuffer returned by
+/// `framebuf`, and a graphical Forth shell on it, if `settings` are enabled.
+///
+/// The shell is sized to fit the framebuffer, after it is scaled according to
+/// `settings`. It takes its input from the PS/2 keyboard.
+///
+/// [`FramebufDisplay`]: drivers::framebuf::FramebufDisplay
+pub fn init_graphical_shell<F>(
+    k: &'static Kernel,
+    framebuf: fn() -> Framebuffer<'static, F>,
+    settings: drivers::framebuf::FramebufDisplaySettings,
+) where
+    F: Deref<Target = [u8]> + DerefMut + 'static,
+{
+    use drivers::framebuf::FramebufDisplay;
+
+    if !settings.enabled {
+        tracing::info!("graphical shell disabled");
+        return;
+    }
+
+    let driver = k
+        .initialize(FramebufDisplay::register(k, framebuf, settings))
+        .expect("failed to spawn framebuffer display driver");
+
+    k.initialize(async move {
+        tracing::debug!("waiting for framebuffer display driver...");
+        driver
+            .await
+            .expect("display driver task isn't cancelled")
+            .expect("display driver must come up");
+        tracing::debug!("display driver ready!");
+        let size = {
+            let fb = framebuf();
+            settings
+                .transform(fb.width() as u32, fb.height() as u32)
+                .logical_size(fb.width() as u32, fb.height() as u32)
+        };
+        let settings = shells::GraphicalShellSettings::with_display_size(size.width, size.height);
+        k.spawn(shells::graphical_shell_mono(k, settings)).await;
+        tracing::info!("graphical shell running.");
+    })
+    .expect("failed to spawn graphical forth shell");
+}
+
+/// Returns the settings for the kernel services registered by [`init`],
+/// applying the boot bundle, if there is one.
+fn service_settings(bundle: Option<&'static [u8]>) -> KernelServiceSettings {
+    let mut services = KernelServiceSettings {
+        pointer_mux: kernel::services::pointer::mux::PointerMuxSettings {
+            enabled: true,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+
+    let Some(bytes) = bundle else {
+        tracing::debug!("no boot bundle");
+        return services;
+    };
+    // `BootBundle::parse` logs the error.
+    let Ok(bundle) = BootBundle::parse(bytes) else {
+        return services;
+    };
+    bundle.apply(&mut services);
+    if let Some(image) = bundle.fs_image() {
+        tracing::warn!(
+            len = image.len(),
+            "boot bundle has a filesystem image, but there's no filesystem service to mount it yet"
+        );
+    }
+    services
+}
+
 pub fn run(bootinfo: &impl BootInfo, kernel: &'static Kernel) -> ! {
     let _ = bootinfo;
     tracing::info!("started kernel run loop\n--------------------\n");
@@ -81,12 +242,16 @@ pub fn run(bootinfo: &impl BootInfo, kernel: &'static Kernel) -> ! {
         // turn the timer wheel if it wasn't turned recently and no one else is
         // holding a lock, ensuring any pending timer ticks are consumed.
         let turn = kernel.timer().turn();
+        if turn.expired() > 0 {
+            // tasks woken by those timers may run on other cores.
+            timer::wake_idle_cores();
+        }
 
         // if there are no woken tasks, wait for an interrupt. otherwise,
         // continue ticking.
         let has_remaining = tick.has_remaining || turn.has_remaining();
         if !has_remaining {
-            interrupt::wait_for_interrupt();
+            timer::idle(interrupt::wait_for_interrupt);
         }
 
         // turn the timer a second time to account for time spent in WFI.
@@ -98,11 +263,17 @@ fn init_acpi(rsdp_addr: Option<PAddr>) {
     tracing::info!("init acpi");
     if let Some(rsdp) = rsdp_addr {
         let acpi = acpi::acpi_tables(rsdp);
-        let platform_info = acpi.and_then(|acpi| acpi.platform_info());
+        let platform_info = acpi.and_then(|acpi| {
+            let hpet = acpi::HpetInfo::new(&acpi).ok();
+            Ok((acpi.platform_info()?, hpet))
+        });
         match platform_info {
-            Ok(platform) => {
-                tracing::debug!("found ACPI platform info");
-                interrupt::enable_hardware_interrupts(Some(&platform.interrupt_model));
+            Ok((platform, hpet)) => {
+                tracing::debug!(has_hpet = hpet.is_some(), "found ACPI platform info");
+                interrupt::enable_hardware_interrupts(
+                    Some(&platform.interrupt_model),
+                    hpet.as_ref(),
+                );
                 acpi::bringup_smp(&platform)
                     .expect("failed to bring up application processors! this is bad news!");
                 return;
@@ -115,5 +286,5 @@ fn init_acpi(rsdp_addr: Option<PAddr>) {
     }
 
     // no ACPI
-    interrupt::enable_hardware_interrupts(None)
+    interrupt::enable_hardware_interrupts(None, None)
 }
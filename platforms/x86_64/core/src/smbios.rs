@@ -0,0 +1,115 @@
+//! Reads the system UUID from the SMBIOS tables, to identify the machine.
+//!
+//! Only the legacy entry point search is implemented: a BIOS places the SMBIOS
+//! entry point on a 16-byte boundary in the physical address range
+//! `0xF0000..0x100000`. UEFI firmware passes it in the EFI configuration table
+//! instead, which the bootloader doesn't tell us about, so on UEFI machines
+//! the UUID is usually not found.
+use core::slice;
+use hal_core::{Address, PAddr};
+use hal_x86_64::mm;
+
+const SEARCH_START: u64 = 0xF_0000;
+const SEARCH_END: u64 = 0x10_0000;
+
+/// Structure type of the System Information structure, which contains the
+/// UUID.
+const SYSTEM_INFORMATION: u8 = 1;
+/// Structure type of the End-of-Table structure.
+const END_OF_TABLE: u8 = 127;
+/// Offset of the UUID in the System Information structure.
+const UUID_OFFSET: usize = 0x08;
+
+/// Returns the system UUID from the SMBIOS System Information structure, in
+/// RFC 4122 byte order, or `None` if there are no SMBIOS tables, or the
+/// firmware doesn't set the UUID.
+#[tracing::instrument(level = "debug")]
+pub fn system_uuid() -> Option<[u8; 16]> {
+    let (table, len) = find_table()?;
+    tracing::debug!(
+        table = format_args!("{table:#x}"),
+        len,
+        "found SMBIOS table"
+    );
+
+    // Safety: the SMBIOS table is in firmware-reserved memory, and all of
+    // physical memory is mapped.
+    let mut rest = unsafe { phys_slice(table, len) };
+    while let [ty, header_len, ..] = *rest {
+        let header_len = header_len as usize;
+        if header_len < 4 || header_len > rest.len() {
+            tracing::warn!(ty, header_len, "malformed SMBIOS structure");
+            return None;
+        }
+
+        if ty == SYSTEM_INFORMATION && header_len >= UUID_OFFSET + 16 {
+            let mut uuid = [0; 16];
+            uuid.copy_from_slice(&rest[UUID_OFFSET..UUID_OFFSET + 16]);
+            // all zeroes means there is no UUID, and all ones that it was
+            // never set.
+            if uuid.iter().all(|&b| b == 0) || uuid.iter().all(|&b| b == 0xFF) {
+                return None;
+            }
+            // since SMBIOS 2.6, the first three fields are little-endian.
+            uuid[0..4].reverse();
+            uuid[4..6].reverse();
+            uuid[6..8].reverse();
+            return Some(uuid);
+        }
+
+        if ty == END_OF_TABLE {
+            break;
+        }
+
+        // skip the formatted area, and the string set after it, which ends
+        // with two NULs.
+        let strings = &rest[header_len..];
+        let end = strings.windows(2).position(|w| w == [0, 0])?;
+        rest = &strings[end + 2..];
+    }
+
+    None
+}
+
+/// Searches for the SMBIOS entry point, returning the physical address and
+/// length of the structure table.
+fn find_table() -> Option<(u64, usize)> {
+    // Safety: the BIOS area is always mapped.
+    let area = unsafe { phys_slice(SEARCH_START, (SEARCH_END - SEARCH_START) as usize) };
+    for entry in (0..area.len()).step_by(16).map(|offset| &area[offset..]) {
+        if entry.starts_with(b"_SM3_") {
+            let len = entry[6] as usize;
+            if checksum_ok(entry, len) {
+                let max_size = u32::from_le_bytes(entry[0x0C..0x10].try_into().ok()?);
+                let addr = u64::from_le_bytes(entry[0x10..0x18].try_into().ok()?);
+                return Some((addr, max_size as usize));
+            }
+        } else if entry.starts_with(b"_SM_") {
+            let len = entry[5] as usize;
+            if checksum_ok(entry, len) {
+                let table_len = u16::from_le_bytes(entry[0x16..0x18].try_into().ok()?);
+                let addr = u32::from_le_bytes(entry[0x18..0x1C].try_into().ok()?);
+                return Some((addr as u64, table_len as usize));
+            }
+        }
+    }
+    None
+}
+
+fn checksum_ok(entry: &[u8], len: usize) -> bool {
+    entry
+        .get(..len)
+        .map(|bytes| bytes.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)) == 0)
+        .unwrap_or(false)
+}
+
+/// Returns the `len` bytes of physical memory starting at `paddr`.
+///
+/// # Safety
+///
+/// The memory must be mapped, and must not be written to for the lifetime of
+/// the slice.
+unsafe fn phys_slice(paddr: u64, len: usize) -> &'static [u8] {
+    let vaddr = mm::kernel_vaddr_of(PAddr::from_u64(paddr));
+    slice::from_raw_parts(vaddr.as_ptr::<u8>(), len)
+}
@@ -0,0 +1,467 @@
+//! Per-core local APIC timers.
+//!
+//! Each core has its own local APIC timer, which counts down at a rate derived
+//! from the core's bus clock. That rate isn't reported anywhere, so each core
+//! calibrates its timer against a reference clock with a known frequency: the
+//! HPET, if ACPI tells us where one is, or channel 2 of the legacy PIT
+//! otherwise.
+//!
+//! Every core also has its own timer wheel, which its run loop turns when the
+//! core's timer interrupt wakes it. The boot processor's wheel is the kernel's [`Timer`], which drives
+//! [`Kernel::timer`]; each application processor has a wheel of its own (see
+//! [`local_timer`]), so that tasks running on those cores don't all serialize
+//! on the boot processor's wheel.
+//!
+//! When a core's wheel fires timers, the tasks they wake may belong to a core
+//! which is halted waiting for an interrupt. [`wake_idle_cores`] sends those
+//! cores a wakeup IPI, so that they notice the woken tasks right away, rather
+//! than on their next timer tick.
+//!
+//! # Notes
+//!
+//! Application processors aren't started yet (see [`crate::acpi::bringup_smp`]),
+//! so for now only the boot processor's timer is running. Once they are, each
+//! AP should call [`init_local`] before it starts running tasks.
+//!
+//! The kernel's clock still counts the boot processor's timer ticks, and the
+//! other cores' wheels share that clock.
+use core::{
+    arch::{asm, global_asm},
+    sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering},
+};
+use hal_core::{Address, PAddr};
+use hal_x86_64::{cpu::Port, interrupt::Idt, mm};
+use kernel::{maitake::time::Timer, Kernel};
+
+use crate::interrupt::{IDIOTIC_CLOCK, TIMER_INTERVAL};
+
+/// The maximum number of cores with their own timers.
+pub const MAX_CORES: usize = 16;
+
+/// The interrupt vector used for wakeup IPIs.
+pub const WAKE_VECTOR: u8 = Idt::LOCAL_APIC_TIMER as u8 - 1;
+
+/// Calibration results for a core's local APIC timer.
+#[derive(Debug, Clone, Copy)]
+pub struct Calibration {
+    /// The clock the timer was calibrated against.
+    pub reference: ReferenceKind,
+    /// The number of timer ticks per millisecond, with the timer's divider
+    /// set to 16.
+    pub ticks_per_ms: u32,
+}
+
+/// The kind of reference clock used to calibrate local APIC timers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReferenceKind {
+    Hpet,
+    Pit,
+}
+
+/// Per-core timer state.
+struct Core {
+    /// The core's local APIC ID, or `UNCLAIMED` if no core is using this slot.
+    apic_id: AtomicU32,
+    /// Set while the core is halted waiting for an interrupt.
+    idle: AtomicBool,
+    /// The core's timer wheel. The boot processor uses the kernel's timer
+    /// instead.
+    timer: Timer,
+}
+
+enum Reference {
+    Hpet { counter: *const u64, period_fs: u64 },
+    Pit,
+}
+
+const UNCLAIMED: u32 = u32::MAX;
+
+static CORES: [Core; MAX_CORES] = [const { Core::new() }; MAX_CORES];
+static BOOT_APIC_ID: AtomicU32 = AtomicU32::new(UNCLAIMED);
+
+/// The virtual address of the local APIC's registers. Each core sees its own
+/// local APIC at the same address.
+static LAPIC_BASE: AtomicUsize = AtomicUsize::new(0);
+/// The virtual address of the local APIC's EOI register, used by the wakeup
+/// IPI's handler.
+static LAPIC_EOI: AtomicUsize = AtomicUsize::new(0);
+
+/// The reference clock. This is locked while calibrating, as calibration
+/// reprograms the PIT.
+static REFERENCE: spin::Mutex<Option<Reference>> = spin::Mutex::new(None);
+
+// Local APIC registers
+const LAPIC_ID: usize = 0x20;
+const LAPIC_EOI_REG: usize = 0xB0;
+const LAPIC_SVR: usize = 0xF0;
+const LAPIC_ICR_LOW: usize = 0x300;
+const LAPIC_ICR_HIGH: usize = 0x310;
+const LAPIC_LVT_TIMER: usize = 0x320;
+const LAPIC_TIMER_INITIAL: usize = 0x380;
+const LAPIC_TIMER_CURRENT: usize = 0x390;
+const LAPIC_TIMER_DIVIDE: usize = 0x3E0;
+
+const IA32_APIC_BASE: u32 = 0x1B;
+const SVR_ENABLE: u32 = 1 << 8;
+const LVT_MASKED: u32 = 1 << 16;
+const LVT_PERIODIC: u32 = 1 << 17;
+const TIMER_DIVIDE_16: u32 = 0b0011;
+const ICR_PENDING: u32 = 1 << 12;
+const ICR_ASSERT: u32 = 1 << 14;
+
+// HPET registers
+const HPET_CAPABILITIES: usize = 0x00;
+const HPET_CONFIG: usize = 0x10;
+const HPET_COUNTER: usize = 0xF0;
+const HPET_ENABLE: u64 = 1 << 0;
+
+// PIT channel 2, which is gated by the PC speaker port rather than wired to an
+// IRQ, so it can be polled without disturbing the rest of the system.
+const PIT_HZ: u32 = 1_193_182;
+const PIT_CHANNEL2: u16 = 0x42;
+const PIT_COMMAND: u16 = 0x43;
+const PIT_GATE: u16 = 0x61;
+/// Channel 2, lobyte/hibyte access, mode 0 (interrupt on terminal count).
+const PIT_CH2_ONESHOT: u8 = 0b1011_0000;
+const GATE_ENABLE: u8 = 1 << 0;
+const GATE_SPEAKER: u8 = 1 << 1;
+const GATE_OUT2: u8 = 1 << 5;
+
+/// How long to run the timer for when calibrating it. This must be less than
+/// 54ms, the PIT's longest one-shot period.
+const CALIBRATION_MS: u32 = 10;
+
+/// Initializes the boot processor's local APIC timer, and the reference
+/// clock used to calibrate every core's timer.
+///
+/// The local APIC must already be enabled.
+pub(crate) fn init(hpet: Option<&acpi::HpetInfo>) {
+    let base = {
+        let (lo, hi): (u32, u32);
+        unsafe {
+            asm!(
+                "rdmsr",
+                in("ecx") IA32_APIC_BASE,
+                out("eax") lo,
+                out("edx") hi,
+                options(nomem, nostack, preserves_flags),
+            );
+        }
+        let paddr = ((hi as u64) << 32 | lo as u64) & !0xfff;
+        mm::kernel_vaddr_of(PAddr::from_u64(paddr)).as_usize()
+    };
+    LAPIC_BASE.store(base, Ordering::Release);
+    LAPIC_EOI.store(base + LAPIC_EOI_REG, Ordering::Release);
+
+    let reference = match hpet {
+        Some(hpet) => {
+            // Like the ACPI tables, the HPET's registers are in the physical
+            // memory region mapped by the bootloader.
+            let regs = mm::kernel_vaddr_of(PAddr::from_u64(hpet.base_address as u64)).as_usize();
+            let period_fs = unsafe { read_hpet(regs, HPET_CAPABILITIES) } >> 32;
+            unsafe {
+                let config = read_hpet(regs, HPET_CONFIG);
+                write_hpet(regs, HPET_CONFIG, config | HPET_ENABLE);
+            }
+            tracing::info!(period_fs, "using the HPET to calibrate local APIC timers");
+            Reference::Hpet {
+                counter: (regs + HPET_COUNTER) as *const u64,
+                period_fs,
+            }
+        }
+        None => {
+            tracing::info!("no HPET, using the PIT to calibrate local APIC timers");
+            Reference::Pit
+        }
+    };
+    *REFERENCE.lock() = Some(reference);
+
+    unsafe { install_wake_ipi() };
+
+    let apic_id = current_apic_id();
+    BOOT_APIC_ID.store(apic_id, Ordering::Release);
+    start_local_timer(apic_id);
+}
+
+/// Initializes the current application processor's local APIC and timer.
+///
+/// This must be called on each application processor after [`init`] has
+/// been called on the boot processor. The core's run loop should then turn
+/// the wheel returned by [`local_timer`] whenever the timer interrupt wakes
+/// it.
+pub fn init_local() -> Calibration {
+    unsafe {
+        lapic_write(LAPIC_SVR, Idt::LOCAL_APIC_SPURIOUS as u32 | SVR_ENABLE);
+    }
+    start_local_timer(current_apic_id())
+}
+
+/// Returns the current core's timer wheel.
+///
+/// On the boot processor, this is the kernel's timer.
+pub fn local_timer(kernel: &'static Kernel) -> &'static Timer {
+    match current_core() {
+        Some(core) => &core.timer,
+        None => kernel.timer(),
+    }
+}
+
+/// Returns `true` if this is the boot processor, or if local APIC timers
+/// aren't in use.
+pub(crate) fn is_boot_core() -> bool {
+    LAPIC_BASE.load(Ordering::Acquire) == 0
+        || current_apic_id() == BOOT_APIC_ID.load(Ordering::Acquire)
+}
+
+/// Runs `wait` with the current core marked as idle, so that
+/// [`wake_idle_cores`] on another core will wake it.
+///
+/// `wait` should halt the core until an interrupt occurs.
+pub(crate) fn idle(wait: impl FnOnce()) {
+    let Some(idle) = current_idle_flag() else {
+        // Local APIC timers aren't in use, so there are no other cores to
+        // wake us.
+        return wait();
+    };
+    idle.store(true, Ordering::Release);
+    wait();
+    idle.store(false, Ordering::Release);
+}
+
+/// Sends a wakeup IPI to every other core that is halted waiting for an
+/// interrupt.
+///
+/// This should be called after a core's timer wheel fires timers, as the
+/// tasks those timers woke may run on other cores.
+pub fn wake_idle_cores() {
+    if LAPIC_BASE.load(Ordering::Acquire) == 0 {
+        return;
+    }
+    let me = current_apic_id();
+    for core in &CORES {
+        let apic_id = core.apic_id.load(Ordering::Acquire);
+        if apic_id == UNCLAIMED || apic_id == me || !core.idle.load(Ordering::Acquire) {
+            continue;
+        }
+        unsafe { send_wake_ipi(apic_id) };
+    }
+}
+
+/// Calibrates the current core's local APIC timer, and starts it ticking
+/// every [`TIMER_INTERVAL`].
+fn start_local_timer(apic_id: u32) -> Calibration {
+    let core = CORES
+        .iter()
+        .position(|core| {
+            core.apic_id
+                .compare_exchange(UNCLAIMED, apic_id, Ordering::AcqRel, Ordering::Acquire)
+                .is_ok()
+        })
+        .expect("more cores than `timer::MAX_CORES`");
+
+    let calibration = calibrate();
+    let interval_ms = TIMER_INTERVAL.as_millis() as u32;
+    unsafe {
+        lapic_write(LAPIC_TIMER_DIVIDE, TIMER_DIVIDE_16);
+        lapic_write(LAPIC_LVT_TIMER, Idt::LOCAL_APIC_TIMER as u32 | LVT_PERIODIC);
+        lapic_write(LAPIC_TIMER_INITIAL, calibration.ticks_per_ms * interval_ms);
+    }
+
+    tracing::info!(
+        apic_id,
+        core,
+        ?calibration,
+        interval = ?TIMER_INTERVAL,
+        "local APIC timer started"
+    );
+    calibration
+}
+
+/// Measures how many times the current core's local APIC timer ticks in a
+/// millisecond.
+fn calibrate() -> Calibration {
+    let reference = REFERENCE.lock();
+    let reference = reference
+        .as_ref()
+        .expect("`timer::init` must be called before calibrating a local APIC timer");
+
+    unsafe {
+        lapic_write(LAPIC_TIMER_DIVIDE, TIMER_DIVIDE_16);
+        lapic_write(LAPIC_LVT_TIMER, LVT_MASKED);
+        lapic_write(LAPIC_TIMER_INITIAL, u32::MAX);
+        reference.wait_ms(CALIBRATION_MS);
+        let elapsed = u32::MAX - lapic_read(LAPIC_TIMER_CURRENT);
+        lapic_write(LAPIC_TIMER_INITIAL, 0);
+
+        Calibration {
+            reference: reference.kind(),
+            ticks_per_ms: elapsed / CALIBRATION_MS,
+        }
+    }
+}
+
+// === impl Core ===
+
+impl Core {
+    const fn new() -> Self {
+        Self {
+            apic_id: AtomicU32::new(UNCLAIMED),
+            idle: AtomicBool::new(false),
+            timer: Timer::new(IDIOTIC_CLOCK),
+        }
+    }
+}
+
+fn current_slot() -> Option<&'static Core> {
+    if LAPIC_BASE.load(Ordering::Acquire) == 0 {
+        return None;
+    }
+    let apic_id = current_apic_id();
+    CORES
+        .iter()
+        .find(|core| core.apic_id.load(Ordering::Acquire) == apic_id)
+}
+
+/// Returns the current core's state, if it's an application processor.
+fn current_core() -> Option<&'static Core> {
+    current_slot().filter(|_| !is_boot_core())
+}
+
+fn current_idle_flag() -> Option<&'static AtomicBool> {
+    current_slot().map(|core| &core.idle)
+}
+
+// === impl Reference ===
+
+// Safety: the HPET's registers may be read from any core.
+unsafe impl Send for Reference {}
+
+impl Reference {
+    fn kind(&self) -> ReferenceKind {
+        match self {
+            Self::Hpet { .. } => ReferenceKind::Hpet,
+            Self::Pit => ReferenceKind::Pit,
+        }
+    }
+
+    /// Busy-waits for `ms` milliseconds.
+    unsafe fn wait_ms(&self, ms: u32) {
+        match *self {
+            Self::Hpet { counter, period_fs } => {
+                let ticks = ms as u64 * 1_000_000_000_000 / period_fs;
+                let start = counter.read_volatile();
+                while counter.read_volatile().wrapping_sub(start) < ticks {
+                    core::hint::spin_loop();
+                }
+            }
+            Self::Pit => {
+                let gate = Port::at(PIT_GATE);
+                let count = (PIT_HZ / 1000 * ms) as u16;
+
+                // Disconnect the speaker and hold the gate low while
+                // programming the counter.
+                let bits = gate.readb() & !(GATE_SPEAKER | GATE_ENABLE);
+                gate.writeb(bits);
+                Port::at(PIT_COMMAND).writeb(PIT_CH2_ONESHOT);
+                Port::at(PIT_CHANNEL2).writeb(count as u8);
+                Port::at(PIT_CHANNEL2).writeb((count >> 8) as u8);
+
+                // Raising the gate starts the countdown. OUT2 goes high when
+                // the count reaches zero.
+                gate.writeb(bits | GATE_ENABLE);
+                while gate.readb() & GATE_OUT2 == 0 {
+                    core::hint::spin_loop();
+                }
+                gate.writeb(bits);
+            }
+        }
+    }
+}
+
+// === register access ===
+
+fn current_apic_id() -> u32 {
+    unsafe { lapic_read(LAPIC_ID) >> 24 }
+}
+
+unsafe fn lapic_read(reg: usize) -> u32 {
+    let base = LAPIC_BASE.load(Ordering::Acquire);
+    ((base + reg) as *const u32).read_volatile()
+}
+
+unsafe fn lapic_write(reg: usize, value: u32) {
+    let base = LAPIC_BASE.load(Ordering::Acquire);
+    ((base + reg) as *mut u32).write_volatile(value)
+}
+
+unsafe fn read_hpet(base: usize, reg: usize) -> u64 {
+    ((base + reg) as *const u64).read_volatile()
+}
+
+unsafe fn write_hpet(base: usize, reg: usize, value: u64) {
+    ((base + reg) as *mut u64).write_volatile(value)
+}
+
+unsafe fn send_wake_ipi(apic_id: u32) {
+    // wait for any previous IPI from this core to be delivered.
+    while lapic_read(LAPIC_ICR_LOW) & ICR_PENDING != 0 {
+        core::hint::spin_loop();
+    }
+    lapic_write(LAPIC_ICR_HIGH, apic_id << 24);
+    // writing the low half sends the IPI (fixed delivery, physical
+    // destination).
+    lapic_write(LAPIC_ICR_LOW, WAKE_VECTOR as u32 | ICR_ASSERT);
+}
+
+// === wakeup IPI handler ===
+
+// The wakeup IPI only needs to bring the core out of `hlt`, so its handler
+// just acknowledges the interrupt. It doesn't touch any registers besides
+// `rax`, so it doesn't need to save anything else.
+global_asm!(
+    ".global mnemos_x86_64_wake_ipi",
+    "mnemos_x86_64_wake_ipi:",
+    "push rax",
+    "mov rax, qword ptr [rip + {eoi}]",
+    "mov dword ptr [rax], 0",
+    "pop rax",
+    "iretq",
+    eoi = sym LAPIC_EOI,
+);
+
+extern "C" {
+    fn mnemos_x86_64_wake_ipi();
+}
+
+/// Installs the wakeup IPI handler in the IDT.
+///
+/// The IDT is owned by the HAL's interrupt controller, which doesn't have a
+/// way to register handlers for arbitrary vectors, so this writes the gate
+/// descriptor for [`WAKE_VECTOR`] directly.
+///
+/// # Safety
+///
+/// The IDT must have been loaded, and [`WAKE_VECTOR`] must not be used for
+/// anything else.
+unsafe fn install_wake_ipi() {
+    #[repr(C, packed)]
+    struct Idtr {
+        limit: u16,
+        base: u64,
+    }
+
+    let mut idtr = Idtr { limit: 0, base: 0 };
+    asm!("sidt [{}]", in(reg) &mut idtr, options(nostack, preserves_flags));
+    let gate = (idtr.base as *mut u8).add(WAKE_VECTOR as usize * 16);
+
+    let handler = mnemos_x86_64_wake_ipi as usize as u64;
+    let cs: u16;
+    asm!("mov {:x}, cs", out(reg) cs, options(nomem, nostack, preserves_flags));
+
+    // A 64-bit interrupt gate: present, DPL 0, type 0xE.
+    let low =
+        (handler & 0xffff) | (cs as u64) << 16 | 0x8e << 40 | ((handler >> 16) & 0xffff) << 48;
+    let high = handler >> 32;
+    (gate as *mut u64).write_volatile(low);
+    (gate as *mut u64).add(1).write_volatile(high);
+}
@@ -2,7 +2,7 @@ use crate::drivers::framebuf::TextWriter;
 use core::{
     marker::PhantomData,
     ops::{Deref, DerefMut},
-    sync::atomic::{AtomicU64, Ordering},
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
 };
 use embedded_graphics::{
     draw_target::DrawTarget,
@@ -22,6 +22,11 @@ use mycelium_util::sync::InitOnce;
 
 static SERIAL: InitOnce<SerialSubscriber> = InitOnce::uninitialized();
 
+/// Set once something else, such as the
+/// [`FramebufDisplay`](crate::drivers::framebuf::FramebufDisplay) driver, has
+/// taken over the framebuffer.
+static FRAMEBUF_RELEASED: AtomicBool = AtomicBool::new(false);
+
 pub struct TraceSubscriber<F>
 where
     F: Deref<Target = [u8]> + DerefMut + 'static,
@@ -31,6 +36,14 @@ where
     _f: PhantomData<fn(&'static F)>,
 }
 
+/// Stops writing events to the framebuffer, so that it can be used by
+/// something else.
+///
+/// Events are still sent over serial, once it is available.
+pub fn release_framebuf() {
+    FRAMEBUF_RELEASED.store(true, Ordering::Release);
+}
+
 #[inline]
 fn with_serial<T>(f: impl FnOnce(&SerialSubscriber) -> T) -> Option<T> {
     SERIAL.try_get().map(f)
@@ -97,7 +110,9 @@ where
     fn event(&self, event: &Event<'_>) {
         use core::fmt::Write;
 
-        if with_serial(|serial| serial.event(event)).is_none() {
+        if with_serial(|serial| serial.event(event)).is_none()
+            && !FRAMEBUF_RELEASED.load(Ordering::Acquire)
+        {
             let point = unpack_point(self.point.load(Ordering::Acquire));
             let mut framebuf = (self.framebuf)();
             let meta = event.metadata();
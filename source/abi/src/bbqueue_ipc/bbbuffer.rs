@@ -20,17 +20,53 @@ use crate::bbqueue_ipc::{
 #[repr(C)]
 /// A backing structure for a BBQueue. Can be used to create either
 /// a BBQueue or a split Producer/Consumer pair
+///
+/// # Cross-core use
+///
+/// The producer and consumer may run on different cores. The indices written
+/// by the producer and by the consumer are kept on separate cache lines, so
+/// that each side's updates don't evict the other's.
+///
+/// Bytes in the buffer are handed between the two sides as follows:
+///
+/// * The producer writes a grant's bytes, then publishes them by storing
+///   `last` and then `write` with `Release` ordering, when the grant is
+///   committed. The consumer loads `write` and then `last` with `Acquire`
+///   ordering before handing out a read grant, so the committed bytes (and
+///   the `last` written before `write`) are visible to it.
+/// * The consumer reads a grant's bytes, then hands them back by storing
+///   `read` with `Release` ordering, when the grant is released. The producer
+///   loads `read` with `Acquire` ordering before handing out a write grant,
+///   so the consumer's reads happen before the producer overwrites them.
+/// * `write_in_progress` and `read_in_progress` are only contended by
+///   multiple handles to the *same* side, and are swapped with `AcqRel`
+///   ordering.
+///
+/// The buffer itself is only written by [`BBBuffer::initialize`], which must
+/// happen before the producer and consumer are handed to other cores, for
+/// example by publishing the `BBBuffer`'s address with `Release` ordering.
+///
+/// On targets without atomic read-modify-write operations, those operations
+/// are emulated with critical sections that only disable interrupts on the
+/// current core, so such targets must not share a `BBBuffer` between cores.
 pub struct BBBuffer {
     buf: AtomicPtr<u8>,
 
     buf_len: AtomicUsize,
 
+    /// Indices written by the producer.
+    producer: CachePadded<ProducerIndices>,
+
+    /// Indices written by the consumer.
+    consumer: CachePadded<ConsumerIndices>,
+}
+
+#[derive(Debug)]
+#[repr(C)]
+struct ProducerIndices {
     /// Where the next byte will be written
     write: AtomicUsize,
 
-    /// Where the next byte will be read from
-    read: AtomicUsize,
-
     /// Used in the inverted case to mark the end of the
     /// readable streak. Otherwise will == sizeof::<self.buf>().
     /// Writer is responsible for placing this at the correct
@@ -44,11 +80,51 @@ pub struct BBBuffer {
     /// read from
     reserve: AtomicUsize,
 
+    /// Is there an active write grant?
+    write_in_progress: AtomicBool,
+}
+
+#[derive(Debug)]
+#[repr(C)]
+struct ConsumerIndices {
+    /// Where the next byte will be read from
+    read: AtomicUsize,
+
     /// Is there an active read grant?
     read_in_progress: AtomicBool,
+}
 
-    /// Is there an active write grant?
-    write_in_progress: AtomicBool,
+/// Aligns a value to the size of a cache line, so that it doesn't share a
+/// cache line with values written by another core.
+///
+/// The alignments are the same as those used by `crossbeam-utils`'
+/// `CachePadded`. On x86_64 and aarch64, the spatial prefetcher fetches
+/// cache lines in pairs, so values are padded to 128 bytes. Microcontrollers
+/// without data caches aren't padded at all, so that single-core targets
+/// with little memory don't pay for it.
+#[derive(Debug)]
+#[cfg_attr(
+    any(target_arch = "x86_64", target_arch = "aarch64"),
+    repr(C, align(128))
+)]
+#[cfg_attr(any(target_arch = "riscv64", target_arch = "x86"), repr(C, align(64)))]
+#[cfg_attr(
+    not(any(
+        target_arch = "x86_64",
+        target_arch = "aarch64",
+        target_arch = "riscv64",
+        target_arch = "x86"
+    )),
+    repr(C)
+)]
+struct CachePadded<T>(T);
+
+impl<T> Deref for CachePadded<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
 }
 
 unsafe impl Sync for BBBuffer {}
@@ -64,6 +140,33 @@ impl<'a> BBBuffer {
         self.buf.store(buf_start, SeqCst);
     }
 
+    /// Returns the size of the buffer, in bytes.
+    #[must_use]
+    pub fn capacity(&self) -> usize {
+        self.buf_len.load(Relaxed)
+    }
+
+    /// Returns the number of bytes which have been committed by the producer,
+    /// but not yet released by the consumer.
+    ///
+    /// This is only a snapshot: if the producer or consumer are in use
+    /// concurrently, it may be out of date as soon as it is returned. Space
+    /// skipped at the end of the ring when a grant wraps around is not
+    /// counted.
+    #[must_use]
+    pub fn committed(&self) -> usize {
+        let write = self.producer.write.load(Acquire);
+        let last = self.producer.last.load(Acquire);
+        let read = self.consumer.read.load(Acquire);
+        if write < read {
+            // Inverted: the bytes from `read` up to `last`, and then from the
+            // start of the buffer up to `write`.
+            last.saturating_sub(read) + write
+        } else {
+            write - read
+        }
+    }
+
     #[inline]
     pub unsafe fn take_producer(me: *mut Self) -> Producer<'static> {
         let nn_me = NonNull::new_unchecked(me);
@@ -126,33 +229,37 @@ impl BBBuffer {
 
             buf_len: AtomicUsize::new(0),
 
-            // Owned by the writer
-            write: AtomicUsize::new(0),
-
-            // Owned by the reader
-            read: AtomicUsize::new(0),
-
-            // Cooperatively owned
-            //
-            // NOTE: This should generally be initialized as size_of::<self.buf>(), however
-            // this would prevent the structure from being entirely zero-initialized,
-            // and can cause the .data section to be much larger than necessary. By
-            // forcing the `last` pointer to be zero initially, we place the structure
-            // in an "inverted" condition, which will be resolved on the first commited
-            // bytes that are written to the structure.
-            //
-            // When read == last == write, no bytes will be allowed to be read (good), but
-            // write grants can be given out (also good).
-            last: AtomicUsize::new(0),
-
-            // Owned by the Writer, "private"
-            reserve: AtomicUsize::new(0),
-
-            // Owned by the Reader, "private"
-            read_in_progress: AtomicBool::new(false),
-
-            // Owned by the Writer, "private"
-            write_in_progress: AtomicBool::new(false),
+            producer: CachePadded(ProducerIndices {
+                // Owned by the writer
+                write: AtomicUsize::new(0),
+
+                // Cooperatively owned
+                //
+                // NOTE: This should generally be initialized as size_of::<self.buf>(), however
+                // this would prevent the structure from being entirely zero-initialized,
+                // and can cause the .data section to be much larger than necessary. By
+                // forcing the `last` pointer to be zero initially, we place the structure
+                // in an "inverted" condition, which will be resolved on the first commited
+                // bytes that are written to the structure.
+                //
+                // When read == last == write, no bytes will be allowed to be read (good), but
+                // write grants can be given out (also good).
+                last: AtomicUsize::new(0),
+
+                // Owned by the Writer, "private"
+                reserve: AtomicUsize::new(0),
+
+                // Owned by the Writer, "private"
+                write_in_progress: AtomicBool::new(false),
+            }),
+
+            consumer: CachePadded(ConsumerIndices {
+                // Owned by the reader
+                read: AtomicUsize::new(0),
+
+                // Owned by the Reader, "private"
+                read_in_progress: AtomicBool::new(false),
+            }),
         }
     }
 }
@@ -200,14 +307,14 @@ impl<'a> Producer<'a> {
     pub fn grant_exact(&self, sz: usize) -> Result<GrantW<'a>> {
         let inner = unsafe { &self.bbq.as_ref() };
 
-        if atomic::swap(&inner.write_in_progress, true, AcqRel) {
+        if atomic::swap(&inner.producer.write_in_progress, true, AcqRel) {
             return Err(Error::GrantInProgress);
         }
 
         // Writer component. Must never write to `read`,
         // be careful writing to `load`
-        let write = inner.write.load(Acquire);
-        let read = inner.read.load(Acquire);
+        let write = inner.producer.write.load(Acquire);
+        let read = inner.consumer.read.load(Acquire);
         let max = inner.buf_len.load(Relaxed);
         let already_inverted = write < read;
 
@@ -217,7 +324,7 @@ impl<'a> Producer<'a> {
                 write
             } else {
                 // Inverted, no room is available
-                inner.write_in_progress.store(false, Release);
+                inner.producer.write_in_progress.store(false, Release);
                 return Err(Error::InsufficientSize);
             }
         } else if write + sz <= max {
@@ -234,12 +341,12 @@ impl<'a> Producer<'a> {
                 0
             } else {
                 // Not invertible, no space
-                inner.write_in_progress.store(false, Release);
+                inner.producer.write_in_progress.store(false, Release);
                 return Err(Error::InsufficientSize);
             }
         };
         // Safe write, only viewed by this task
-        inner.reserve.store(start + sz, Release);
+        inner.producer.reserve.store(start + sz, Release);
 
         // This is sound, as UnsafeCell, MaybeUninit, and GenericArray
         // are all `#[repr(Transparent)]
@@ -264,14 +371,14 @@ impl<'a> Producer<'a> {
     pub fn grant_max_remaining(&self, mut sz: usize) -> Result<GrantW<'a>> {
         let inner = unsafe { &self.bbq.as_ref() };
 
-        if atomic::swap(&inner.write_in_progress, true, AcqRel) {
+        if atomic::swap(&inner.producer.write_in_progress, true, AcqRel) {
             return Err(Error::GrantInProgress);
         }
 
         // Writer component. Must never write to `read`,
         // be careful writing to `load`
-        let write = inner.write.load(Acquire);
-        let read = inner.read.load(Acquire);
+        let write = inner.producer.write.load(Acquire);
+        let read = inner.consumer.read.load(Acquire);
         let max = inner.buf_len.load(Relaxed);
 
         let already_inverted = write < read;
@@ -285,7 +392,7 @@ impl<'a> Producer<'a> {
                 write
             } else {
                 // Inverted, no room is available
-                inner.write_in_progress.store(false, Release);
+                inner.producer.write_in_progress.store(false, Release);
                 return Err(Error::InsufficientSize);
             }
         } else if write != max {
@@ -303,13 +410,13 @@ impl<'a> Producer<'a> {
                 0
             } else {
                 // Not invertible, no space
-                inner.write_in_progress.store(false, Release);
+                inner.producer.write_in_progress.store(false, Release);
                 return Err(Error::InsufficientSize);
             }
         };
 
         // Safe write, only viewed by this task
-        inner.reserve.store(start + sz, Release);
+        inner.producer.reserve.store(start + sz, Release);
 
         // This is sound, as UnsafeCell, MaybeUninit, and GenericArray
         // are all `#[repr(Transparent)]
@@ -341,13 +448,13 @@ impl<'a> Consumer<'a> {
     pub fn read(&self) -> Result<GrantR<'a>> {
         let inner = unsafe { &self.bbq.as_ref() };
 
-        if atomic::swap(&inner.read_in_progress, true, AcqRel) {
+        if atomic::swap(&inner.consumer.read_in_progress, true, AcqRel) {
             return Err(Error::GrantInProgress);
         }
 
-        let write = inner.write.load(Acquire);
-        let last = inner.last.load(Acquire);
-        let mut read = inner.read.load(Acquire);
+        let write = inner.producer.write.load(Acquire);
+        let last = inner.producer.last.load(Acquire);
+        let mut read = inner.consumer.read.load(Acquire);
 
         // Resolve the inverted case or end of read
         if (read == last) && (write < read) {
@@ -360,7 +467,7 @@ impl<'a> Consumer<'a> {
             //   Commit does not check read, but if Grant has started an inversion,
             //   grant could move Last to the prior write position
             // MOVING READ BACKWARDS!
-            inner.read.store(0, Release);
+            inner.consumer.read.store(0, Release);
         }
 
         let sz = if write < read {
@@ -372,7 +479,7 @@ impl<'a> Consumer<'a> {
         } - read;
 
         if sz == 0 {
-            inner.read_in_progress.store(false, Release);
+            inner.consumer.read_in_progress.store(false, Release);
             return Err(Error::InsufficientSize);
         }
 
@@ -390,17 +497,16 @@ impl<'a> Consumer<'a> {
 
     /// Obtains two disjoint slices, which are each contiguous of committed bytes.
     /// Combined these contain all previously commited data.
-    #[allow(dead_code)] // TODO(AJM): Not used in mnemos
     pub fn split_read(&self) -> Result<SplitGrantR<'a>> {
         let inner = unsafe { &self.bbq.as_ref() };
 
-        if atomic::swap(&inner.read_in_progress, true, AcqRel) {
+        if atomic::swap(&inner.consumer.read_in_progress, true, AcqRel) {
             return Err(Error::GrantInProgress);
         }
 
-        let write = inner.write.load(Acquire);
-        let last = inner.last.load(Acquire);
-        let mut read = inner.read.load(Acquire);
+        let write = inner.producer.write.load(Acquire);
+        let last = inner.producer.last.load(Acquire);
+        let mut read = inner.consumer.read.load(Acquire);
 
         // Resolve the inverted case or end of read
         if (read == last) && (write < read) {
@@ -413,7 +519,7 @@ impl<'a> Consumer<'a> {
             //   Commit does not check read, but if Grant has started an inversion,
             //   grant could move Last to the prior write position
             // MOVING READ BACKWARDS!
-            inner.read.store(0, Release);
+            inner.consumer.read.store(0, Release);
         }
 
         let (sz1, sz2) = if write < read {
@@ -425,7 +531,7 @@ impl<'a> Consumer<'a> {
         };
 
         if sz1 == 0 {
-            inner.read_in_progress.store(false, Release);
+            inner.consumer.read_in_progress.store(false, Release);
             return Err(Error::InsufficientSize);
         }
 
@@ -537,7 +643,7 @@ impl<'a> GrantW<'a> {
         // If there is no grant in progress, return early. This
         // generally means we are dropping the grant within a
         // wrapper structure
-        if !inner.write_in_progress.load(Acquire) {
+        if !inner.producer.write_in_progress.load(Acquire) {
             return;
         }
 
@@ -548,17 +654,17 @@ impl<'a> GrantW<'a> {
         let len = self.buf.len();
         let used = min(len, used);
 
-        let write = inner.write.load(Acquire);
-        atomic::fetch_sub(&inner.reserve, len - used, AcqRel);
+        let write = inner.producer.write.load(Acquire);
+        atomic::fetch_sub(&inner.producer.reserve, len - used, AcqRel);
 
         let max = inner.buf_len.load(Relaxed);
-        let last = inner.last.load(Acquire);
-        let new_write = inner.reserve.load(Acquire);
+        let last = inner.producer.last.load(Acquire);
+        let new_write = inner.producer.reserve.load(Acquire);
 
         if (new_write < write) && (write != max) {
             // We have already wrapped, but we are skipping some bytes at the end of the ring.
             // Mark `last` where the write pointer used to be to hold the line here
-            inner.last.store(write, Release);
+            inner.producer.last.store(write, Release);
         } else if new_write > last {
             // We're about to pass the last pointer, which was previously the artificial
             // end of the ring. Now that we've passed it, we can "unlock" the section
@@ -567,7 +673,7 @@ impl<'a> GrantW<'a> {
             // Since new_write is strictly larger than last, it is safe to move this as
             // the other thread will still be halted by the (about to be updated) write
             // value
-            inner.last.store(max, Release);
+            inner.producer.last.store(max, Release);
         }
         // else: If new_write == last, either:
         // * last == max, so no need to write, OR
@@ -577,10 +683,10 @@ impl<'a> GrantW<'a> {
 
         // Write must be updated AFTER last, otherwise read could think it was
         // time to invert early!
-        inner.write.store(new_write, Release);
+        inner.producer.write.store(new_write, Release);
 
         // Allow subsequent grants
-        inner.write_in_progress.store(false, Release);
+        inner.producer.write_in_progress.store(false, Release);
     }
 
     /// Configures the amount of bytes to be commited on drop.
@@ -648,7 +754,7 @@ impl<'a> GrantR<'a> {
         // If there is no grant in progress, return early. This
         // generally means we are dropping the grant within a
         // wrapper structure
-        if !inner.read_in_progress.load(Acquire) {
+        if !inner.consumer.read_in_progress.load(Acquire) {
             return;
         }
 
@@ -656,9 +762,9 @@ impl<'a> GrantR<'a> {
         debug_assert!(used <= self.buf.len());
 
         // This should be fine, purely incrementing
-        let _ = atomic::fetch_add(&inner.read, used, Release);
+        let _ = atomic::fetch_add(&inner.consumer.read, used, Release);
 
-        inner.read_in_progress.store(false, Release);
+        inner.consumer.read_in_progress.store(false, Release);
     }
 
     /// Configures the amount of bytes to be released on drop.
@@ -704,7 +810,7 @@ impl<'a> SplitGrantR<'a> {
         // If there is no grant in progress, return early. This
         // generally means we are dropping the grant within a
         // wrapper structure
-        if !inner.read_in_progress.load(Acquire) {
+        if !inner.consumer.read_in_progress.load(Acquire) {
             return;
         }
 
@@ -713,13 +819,13 @@ impl<'a> SplitGrantR<'a> {
 
         if used <= self.buf1.len() {
             // This should be fine, purely incrementing
-            let _ = atomic::fetch_add(&inner.read, used, Release);
+            let _ = atomic::fetch_add(&inner.consumer.read, used, Release);
         } else {
             // Also release parts of the second buffer
-            inner.read.store(used - self.buf1.len(), Release);
+            inner.consumer.read.store(used - self.buf1.len(), Release);
         }
 
-        inner.read_in_progress.store(false, Release);
+        inner.consumer.read_in_progress.store(false, Release);
     }
 
     /// Configures the amount of bytes to be released on drop.
@@ -835,3 +941,44 @@ mod atomic {
         atomic.swap(val, order)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn committed() {
+        let buf = Box::leak(vec![0u8; 16].into_boxed_slice());
+        let bbq: &'static mut BBBuffer = Box::leak(Box::new(BBBuffer::new()));
+        let bbq_ptr = bbq as *mut BBBuffer;
+        let (prod, cons) = unsafe {
+            (*bbq_ptr).initialize(buf.as_mut_ptr(), buf.len());
+            (
+                BBBuffer::take_producer(bbq_ptr),
+                BBBuffer::take_consumer(bbq_ptr),
+            )
+        };
+        let bbq = unsafe { &*bbq_ptr };
+        assert_eq!(bbq.capacity(), 16);
+        assert_eq!(bbq.committed(), 0);
+
+        prod.grant_exact(10).unwrap().commit(10);
+        assert_eq!(bbq.committed(), 10);
+        // uncommitted grants aren't counted.
+        let grant = prod.grant_exact(4).unwrap();
+        assert_eq!(bbq.committed(), 10);
+        grant.commit(4);
+        assert_eq!(bbq.committed(), 14);
+
+        cons.read().unwrap().release(12);
+        assert_eq!(bbq.committed(), 2);
+
+        // wrap around to the start of the buffer.
+        prod.grant_exact(6).unwrap().commit(6);
+        assert_eq!(bbq.committed(), 8);
+        cons.read().unwrap().release(2);
+        assert_eq!(bbq.committed(), 6);
+        cons.read().unwrap().release(6);
+        assert_eq!(bbq.committed(), 0);
+    }
+}
@@ -36,6 +36,9 @@ pub use bbbuffer::*;
 
 pub mod framed;
 
+#[cfg(test)]
+mod stress;
+
 use core::result::Result as CoreResult;
 
 /// Result type used by the `BBQueue` interfaces
@@ -0,0 +1,135 @@
+//! Stress tests with the producer and consumer on separate threads.
+//!
+//! On a multi-core host, these exercise the ring's memory orderings the same
+//! way as sharing a ring between cores on the target. The rings are small, so
+//! that both sides wrap around often.
+
+use super::{BBBuffer, Consumer, Error, Producer};
+use std::thread;
+
+/// The number of bytes sent through the ring in each test.
+const TOTAL: usize = 1 << 18;
+
+/// Returns a producer and consumer for a ring of `capacity` bytes.
+///
+/// The ring and its buffer are leaked, as the handles borrow them for
+/// `'static`.
+fn ring(capacity: usize) -> (Producer<'static>, Consumer<'static>) {
+    let buf = Box::leak(vec![0u8; capacity].into_boxed_slice());
+    let bbq: &'static mut BBBuffer = Box::leak(Box::new(BBBuffer::new()));
+    unsafe {
+        bbq.initialize(buf.as_mut_ptr(), buf.len());
+        (
+            BBBuffer::take_producer(bbq as *mut _),
+            BBBuffer::take_consumer(bbq as *mut _),
+        )
+    }
+}
+
+/// Sends [`TOTAL`] bytes of a repeating counter, in grants of varying sizes.
+fn produce(prod: Producer<'static>) {
+    let mut sent = 0;
+    while sent < TOTAL {
+        let len = (1 + sent % 13).min(TOTAL - sent);
+        let mut grant = match prod.grant_exact(len) {
+            Ok(grant) => grant,
+            Err(Error::InsufficientSize) => {
+                thread::yield_now();
+                continue;
+            }
+            Err(error) => panic!("unexpected error: {error:?}"),
+        };
+        for (i, byte) in grant.buf().iter_mut().enumerate() {
+            *byte = (sent + i) as u8;
+        }
+        grant.commit(len);
+        sent += len;
+    }
+}
+
+#[test]
+fn read() {
+    let (prod, cons) = ring(61);
+    let producer = thread::spawn(move || produce(prod));
+
+    let mut received = 0;
+    while received < TOTAL {
+        let Ok(grant) = cons.read() else {
+            thread::yield_now();
+            continue;
+        };
+        // release some, but not necessarily all, of the grant.
+        let used = grant.buf().len().min(1 + received % 7);
+        for (i, &byte) in grant.buf()[..used].iter().enumerate() {
+            assert_eq!(byte, (received + i) as u8, "byte {}", received + i);
+        }
+        grant.release(used);
+        received += used;
+    }
+
+    producer.join().unwrap();
+    assert!(cons.read().is_err(), "no bytes should be left over");
+}
+
+#[test]
+fn split_read() {
+    let (prod, cons) = ring(61);
+    let producer = thread::spawn(move || produce(prod));
+
+    let mut received = 0;
+    while received < TOTAL {
+        let Ok(grant) = cons.split_read() else {
+            thread::yield_now();
+            continue;
+        };
+        let (first, second) = grant.bufs();
+        for (i, &byte) in first.iter().chain(second).enumerate() {
+            assert_eq!(byte, (received + i) as u8, "byte {}", received + i);
+        }
+        let used = grant.combined_len();
+        grant.release(used);
+        received += used;
+    }
+
+    producer.join().unwrap();
+}
+
+#[test]
+fn frames() {
+    const FRAMES: usize = TOTAL / 8;
+
+    let (prod, cons) = ring(64);
+    let prod = super::framed::FrameProducer { producer: prod };
+    let cons = super::framed::FrameConsumer { consumer: cons };
+
+    let producer = thread::spawn(move || {
+        for seq in 0..FRAMES {
+            // each frame is filled with its sequence number, so a torn frame
+            // can be detected.
+            let len = 1 + seq % 11;
+            let mut grant = loop {
+                match prod.grant(len) {
+                    Ok(grant) => break grant,
+                    Err(_) => thread::yield_now(),
+                }
+            };
+            grant[..len].fill(seq as u8);
+            grant.commit(len);
+        }
+    });
+
+    let mut seq = 0;
+    while seq < FRAMES {
+        let Some(frame) = cons.read() else {
+            thread::yield_now();
+            continue;
+        };
+        assert_eq!(frame.len(), 1 + seq % 11, "frame {seq}");
+        assert!(frame.iter().all(|&b| b == seq as u8), "frame {seq} is torn");
+        frame.release();
+        seq += 1;
+    }
+
+    producer.join().unwrap();
+    assert!(cons.read().is_none(), "no frames should be left over");
+}
@@ -0,0 +1,384 @@
+//! # Boot Bundles
+//!
+//! A simple archive format for auxiliary payloads which are loaded alongside
+//! the kernel at boot, such as boot Forth scripts, postcard-encoded
+//! configuration, or a small read-only filesystem image. Bundling these
+//! payloads separately allows them to be changed without rebuilding the
+//! kernel.
+//!
+//! A bundle consists of three parts, laid out back-to-back:
+//!
+//! * A fixed-size [`Header`], [`Header::SIZE`] bytes long
+//! * The entry table, `entry_count` [`EntryHeader`]s, each
+//!   [`EntryHeader::SIZE`] bytes long
+//! * The payloads of each entry
+//!
+//! Each entry header contains the [`Kind`] of payload, a short UTF-8 name,
+//! and the offset and length of the payload, relative to the start of the
+//! bundle. Payloads are aligned to [`ALIGN`] bytes, so that they may be
+//! used in place.
+//!
+//! All integers are little-endian.
+//!
+//! On x86_64, bundles are produced by the bootimager, which passes them to
+//! the kernel as the bootloader's ramdisk.
+
+use core::fmt;
+
+/// The alignment of each payload, relative to the start of the bundle.
+pub const ALIGN: usize = 8;
+
+/// A parsed bundle header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Header {
+    /// Number of entries in the entry table.
+    pub entry_count: u16,
+    /// Length of the whole bundle, including the header.
+    pub len: u32,
+}
+
+/// A parsed entry table entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EntryHeader {
+    /// What the payload contains.
+    pub kind: Kind,
+    /// Offset of the payload from the start of the bundle.
+    pub offset: u32,
+    /// Length of the payload.
+    pub len: u32,
+    name: [u8; EntryHeader::MAX_NAME_LEN],
+    name_len: u8,
+}
+
+/// What a bundle entry's payload contains.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "use-defmt", derive(defmt::Format))]
+pub enum Kind {
+    /// Forth source, run once the kernel has booted.
+    ForthScript,
+    /// Postcard-encoded settings.
+    Config,
+    /// A read-only filesystem image.
+    FsImage,
+    /// A kind of payload unknown to this version of the format.
+    ///
+    /// Unknown entries are skipped over, rather than rejected, so that older
+    /// kernels can boot with newer bundles.
+    Unknown(u8),
+}
+
+/// A bundle, borrowed from a byte buffer.
+#[derive(Debug, Clone, Copy)]
+pub struct Bundle<'a> {
+    header: Header,
+    bytes: &'a [u8],
+}
+
+/// A single entry in a [`Bundle`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Entry<'a> {
+    pub kind: Kind,
+    pub name: &'a str,
+    pub data: &'a [u8],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "use-defmt", derive(defmt::Format))]
+pub enum Error {
+    /// The buffer is too short to contain the header or entry table.
+    Truncated,
+    /// The header magic did not match [`Header::MAGIC`].
+    BadMagic,
+    /// The header version is not supported by this parser.
+    UnsupportedVersion(u16),
+    /// The payload of the entry at this index lies outside of the bundle.
+    BadEntry(u16),
+    /// The name of the entry at this index is not valid UTF-8.
+    BadName(u16),
+    /// An entry name is longer than [`EntryHeader::MAX_NAME_LEN`].
+    NameTooLong,
+}
+
+// === impl Header ===
+
+impl Header {
+    /// Magic bytes at the start of every bundle.
+    pub const MAGIC: [u8; 4] = *b"MnBd";
+    /// The current version of the bundle format.
+    pub const VERSION: u16 = 1;
+    /// Size of the encoded header, in bytes.
+    pub const SIZE: usize = 16;
+
+    /// Parse a header from the start of `bytes`.
+    pub fn parse(bytes: &[u8]) -> Result<Self, Error> {
+        let bytes = bytes.get(..Self::SIZE).ok_or(Error::Truncated)?;
+        if bytes[0..4] != Self::MAGIC {
+            return Err(Error::BadMagic);
+        }
+        let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+        if version != Self::VERSION {
+            return Err(Error::UnsupportedVersion(version));
+        }
+        Ok(Self {
+            entry_count: u16::from_le_bytes([bytes[6], bytes[7]]),
+            len: u32::from_le_bytes([bytes[8], bytes[9], bytes[10], bytes[11]]),
+            // bytes[12..16] are reserved.
+        })
+    }
+
+    /// Encode this header into its on-disk representation.
+    pub fn to_bytes(&self) -> [u8; Self::SIZE] {
+        let mut out = [0u8; Self::SIZE];
+        out[0..4].copy_from_slice(&Self::MAGIC);
+        out[4..6].copy_from_slice(&Self::VERSION.to_le_bytes());
+        out[6..8].copy_from_slice(&self.entry_count.to_le_bytes());
+        out[8..12].copy_from_slice(&self.len.to_le_bytes());
+        out
+    }
+
+    /// The offset of the first payload in a bundle with this header.
+    pub fn payloads_offset(&self) -> usize {
+        let table_end = Self::SIZE + self.entry_count as usize * EntryHeader::SIZE;
+        (table_end + ALIGN - 1) & !(ALIGN - 1)
+    }
+}
+
+// === impl EntryHeader ===
+
+impl EntryHeader {
+    /// Size of an encoded entry header, in bytes.
+    pub const SIZE: usize = 32;
+    /// The maximum length of an entry's name, in bytes.
+    pub const MAX_NAME_LEN: usize = 20;
+
+    /// Returns a new entry header, or [`Error::NameTooLong`] if `name` is
+    /// longer than [`EntryHeader::MAX_NAME_LEN`].
+    pub fn new(kind: Kind, name: &str, offset: u32, len: u32) -> Result<Self, Error> {
+        let mut buf = [0u8; Self::MAX_NAME_LEN];
+        buf.get_mut(..name.len())
+            .ok_or(Error::NameTooLong)?
+            .copy_from_slice(name.as_bytes());
+        Ok(Self {
+            kind,
+            offset,
+            len,
+            name: buf,
+            name_len: name.len() as u8,
+        })
+    }
+
+    /// Parse an entry header from the start of `bytes`.
+    ///
+    /// This does not check that the payload lies within the bundle, or that
+    /// the name is valid UTF-8. [`Bundle::parse`] does both.
+    pub fn parse(bytes: &[u8]) -> Result<Self, Error> {
+        let bytes = bytes.get(..Self::SIZE).ok_or(Error::Truncated)?;
+        let name_len = bytes[1];
+        if name_len as usize > Self::MAX_NAME_LEN {
+            return Err(Error::NameTooLong);
+        }
+        let mut name = [0u8; Self::MAX_NAME_LEN];
+        name.copy_from_slice(&bytes[12..Self::SIZE]);
+        Ok(Self {
+            kind: Kind::from_u8(bytes[0]),
+            // bytes[2..4] are reserved.
+            offset: u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]),
+            len: u32::from_le_bytes([bytes[8], bytes[9], bytes[10], bytes[11]]),
+            name,
+            name_len,
+        })
+    }
+
+    /// Encode this entry header into its on-disk representation.
+    pub fn to_bytes(&self) -> [u8; Self::SIZE] {
+        let mut out = [0u8; Self::SIZE];
+        out[0] = self.kind.as_u8();
+        out[1] = self.name_len;
+        out[4..8].copy_from_slice(&self.offset.to_le_bytes());
+        out[8..12].copy_from_slice(&self.len.to_le_bytes());
+        out[12..Self::SIZE].copy_from_slice(&self.name);
+        out
+    }
+
+    /// The raw bytes of this entry's name.
+    pub fn name_bytes(&self) -> &[u8] {
+        &self.name[..self.name_len as usize]
+    }
+}
+
+// === impl Kind ===
+
+impl Kind {
+    pub fn from_u8(kind: u8) -> Self {
+        match kind {
+            1 => Self::ForthScript,
+            2 => Self::Config,
+            3 => Self::FsImage,
+            kind => Self::Unknown(kind),
+        }
+    }
+
+    pub fn as_u8(&self) -> u8 {
+        match *self {
+            Self::ForthScript => 1,
+            Self::Config => 2,
+            Self::FsImage => 3,
+            Self::Unknown(kind) => kind,
+        }
+    }
+}
+
+// === impl Bundle ===
+
+impl<'a> Bundle<'a> {
+    /// Parse a bundle from `bytes`, validating the header and every entry in
+    /// the entry table.
+    ///
+    /// Any bytes past the header's `len` are ignored, as the bundle may have
+    /// been padded (for example, to a whole number of disk sectors).
+    pub fn parse(bytes: &'a [u8]) -> Result<Self, Error> {
+        let header = Header::parse(bytes)?;
+        let bytes = bytes.get(..header.len as usize).ok_or(Error::Truncated)?;
+        let bundle = Self { header, bytes };
+        for index in 0..header.entry_count {
+            bundle.entry(index)?;
+        }
+        Ok(bundle)
+    }
+
+    #[inline]
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    /// Returns an iterator over every entry in the bundle, in the order they
+    /// appear in the entry table.
+    pub fn entries(&self) -> impl Iterator<Item = Entry<'a>> + 'a {
+        let bundle = *self;
+        // entries were validated by `parse`.
+        (0..self.header.entry_count).filter_map(move |index| bundle.entry(index).ok())
+    }
+
+    /// Returns the first entry with the given kind and name.
+    pub fn get(&self, kind: Kind, name: &str) -> Option<Entry<'a>> {
+        self.entries()
+            .find(|entry| entry.kind == kind && entry.name == name)
+    }
+
+    fn entry(&self, index: u16) -> Result<Entry<'a>, Error> {
+        let at = Header::SIZE + index as usize * EntryHeader::SIZE;
+        let raw = self.bytes.get(at..).ok_or(Error::Truncated)?;
+        let header = EntryHeader::parse(raw)?;
+        let name = &raw[12..][..header.name_len as usize];
+        let name = core::str::from_utf8(name).map_err(|_| Error::BadName(index))?;
+        let start = header.offset as usize;
+        let data = start
+            .checked_add(header.len as usize)
+            .and_then(|end| self.bytes.get(start..end))
+            .ok_or(Error::BadEntry(index))?;
+        Ok(Entry {
+            kind: header.kind,
+            name,
+            data,
+        })
+    }
+}
+
+// === impl Error ===
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Truncated => f.pad("bundle is truncated"),
+            Self::BadMagic => f.pad("not a bundle (bad magic)"),
+            Self::UnsupportedVersion(v) => write!(f, "unsupported bundle version {v}"),
+            Self::BadEntry(i) => write!(f, "entry {i} is outside the bundle"),
+            Self::BadName(i) => write!(f, "entry {i} has a name that isn't UTF-8"),
+            Self::NameTooLong => write!(
+                f,
+                "entry names may be at most {} bytes long",
+                EntryHeader::MAX_NAME_LEN
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn build(entries: &[(Kind, &str, &[u8])]) -> Vec<u8> {
+        let mut header = Header {
+            entry_count: entries.len() as u16,
+            len: 0,
+        };
+        let mut table = Vec::new();
+        let mut payloads = Vec::new();
+        let base = header.payloads_offset();
+        for (kind, name, data) in entries {
+            let offset = (base + payloads.len()) as u32;
+            let entry = EntryHeader::new(*kind, name, offset, data.len() as u32).unwrap();
+            table.extend_from_slice(&entry.to_bytes());
+            payloads.extend_from_slice(data);
+            payloads.resize((payloads.len() + ALIGN - 1) & !(ALIGN - 1), 0);
+        }
+        header.len = (base + payloads.len()) as u32;
+
+        let mut out = header.to_bytes().to_vec();
+        out.extend_from_slice(&table);
+        out.resize(base, 0);
+        out.extend_from_slice(&payloads);
+        out
+    }
+
+    #[test]
+    fn roundtrip() {
+        let bytes = build(&[
+            (Kind::ForthScript, "boot.fth", b"1 2 + ."),
+            (Kind::Config, "services", &[1, 2, 3]),
+            (Kind::Unknown(42), "future", &[]),
+        ]);
+        let bundle = Bundle::parse(&bytes).unwrap();
+        let entries = bundle.entries().collect::<Vec<_>>();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].name, "boot.fth");
+        assert_eq!(entries[0].data, b"1 2 + .");
+        assert_eq!(entries[2].kind, Kind::Unknown(42));
+        assert_eq!(
+            bundle.get(Kind::Config, "services").map(|e| e.data),
+            Some(&[1, 2, 3][..])
+        );
+        assert_eq!(bundle.get(Kind::FsImage, "services"), None);
+
+        for entry in &entries {
+            let offset = entry.data.as_ptr() as usize - bytes.as_ptr() as usize;
+            assert_eq!(offset % ALIGN, 0, "{} is aligned", entry.name);
+        }
+    }
+
+    #[test]
+    fn ignores_padding() {
+        let mut bytes = build(&[(Kind::FsImage, "root", &[0xAA; 13])]);
+        let len = bytes.len();
+        bytes.resize(512, 0);
+        let bundle = Bundle::parse(&bytes).unwrap();
+        assert_eq!(bundle.header().len as usize, len);
+    }
+
+    #[test]
+    fn rejects_out_of_bounds_entry() {
+        let mut bytes = build(&[(Kind::Config, "services", &[1, 2, 3])]);
+        let entry = EntryHeader::parse(&bytes[Header::SIZE..]).unwrap();
+        let entry = EntryHeader::new(entry.kind, "services", entry.offset, 1024).unwrap();
+        bytes[Header::SIZE..][..EntryHeader::SIZE].copy_from_slice(&entry.to_bytes());
+        assert_eq!(Bundle::parse(&bytes).unwrap_err(), Error::BadEntry(0));
+    }
+
+    #[test]
+    fn rejects_long_names() {
+        assert_eq!(
+            EntryHeader::new(Kind::Config, "a-very-long-entry-name", 0, 0),
+            Err(Error::NameTooLong)
+        );
+    }
+}
@@ -0,0 +1,319 @@
+//! # Relocatable Flat Binaries
+//!
+//! A minimal userspace binary format that the kernel loader can place at an
+//! arbitrary load address.
+//!
+//! A flat binary consists of three parts, laid out back-to-back:
+//!
+//! * A fixed-size [`Header`], [`Header::SIZE`] bytes long
+//! * The program image (text, rodata, and initialized data), `image_len`
+//!   bytes long
+//! * The relocation table, `reloc_count` little-endian `u32`s
+//!
+//! Each entry in the relocation table is an offset into the program image.
+//! At that offset, there is a little-endian, `ptr_width`-byte word containing
+//! an address *relative to the start of the image*. When loading, the loader
+//! adds the actual load address to each of these words. This is the same
+//! thing as an ELF `R_*_RELATIVE` relocation, which is the only kind of
+//! relocation a statically linked PIE executable needs.
+//!
+//! After the image, `bss_len` bytes of zero-initialized memory are reserved
+//! when loading. The bss region is not stored in the file.
+//!
+//! Flat binaries are generally produced from PIE ELF files by the `flatpack`
+//! host tool.
+
+use core::fmt;
+
+/// A parsed flat binary header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Header {
+    /// Width of a pointer, in bytes, on the target this binary was built for.
+    pub ptr_width: u8,
+    /// Offset of the entry point from the start of the image.
+    pub entry: u32,
+    /// Length of the program image stored in the file.
+    pub image_len: u32,
+    /// Length of the zero-initialized region following the image.
+    pub bss_len: u32,
+    /// Required alignment of the load address.
+    pub align: u32,
+    /// Number of entries in the relocation table.
+    pub reloc_count: u32,
+}
+
+/// A flat binary, borrowed from a byte buffer.
+#[derive(Debug, Clone, Copy)]
+pub struct FlatBinary<'a> {
+    header: Header,
+    image: &'a [u8],
+    relocs: &'a [u8],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "use-defmt", derive(defmt::Format))]
+pub enum Error {
+    /// The buffer is too short to contain the header, image, or relocations.
+    Truncated,
+    /// The header magic did not match [`Header::MAGIC`].
+    BadMagic,
+    /// The header version is not supported by this loader.
+    UnsupportedVersion(u16),
+    /// The pointer width is not 4 or 8, or doesn't match the loading target.
+    BadPointerWidth(u8),
+    /// The alignment is zero or not a power of two.
+    BadAlignment(u32),
+    /// The entry point lies outside of the program image.
+    BadEntry(u32),
+    /// A relocation targets a word that lies outside of the program image.
+    BadRelocation(u32),
+    /// The destination buffer is too small to hold the image and bss.
+    DestinationTooSmall,
+}
+
+// === impl Header ===
+
+impl Header {
+    /// Magic bytes at the start of every flat binary.
+    pub const MAGIC: [u8; 4] = *b"MnRf";
+    /// The current version of the flat binary format.
+    pub const VERSION: u16 = 1;
+    /// Size of the encoded header, in bytes.
+    pub const SIZE: usize = 28;
+
+    /// Parse a header from the start of `bytes`.
+    pub fn parse(bytes: &[u8]) -> Result<Self, Error> {
+        let bytes = bytes.get(..Self::SIZE).ok_or(Error::Truncated)?;
+        if bytes[0..4] != Self::MAGIC {
+            return Err(Error::BadMagic);
+        }
+        let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+        if version != Self::VERSION {
+            return Err(Error::UnsupportedVersion(version));
+        }
+        let word = |at: usize| {
+            u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+        };
+        let header = Self {
+            ptr_width: bytes[6],
+            // bytes[7] is reserved.
+            entry: word(8),
+            image_len: word(12),
+            bss_len: word(16),
+            align: word(20),
+            reloc_count: word(24),
+        };
+
+        if !matches!(header.ptr_width, 4 | 8) {
+            return Err(Error::BadPointerWidth(header.ptr_width));
+        }
+        if !header.align.is_power_of_two() {
+            return Err(Error::BadAlignment(header.align));
+        }
+        if header.entry >= header.image_len {
+            return Err(Error::BadEntry(header.entry));
+        }
+
+        Ok(header)
+    }
+
+    /// Encode this header into its on-disk representation.
+    pub fn to_bytes(&self) -> [u8; Self::SIZE] {
+        let mut out = [0u8; Self::SIZE];
+        out[0..4].copy_from_slice(&Self::MAGIC);
+        out[4..6].copy_from_slice(&Self::VERSION.to_le_bytes());
+        out[6] = self.ptr_width;
+        out[8..12].copy_from_slice(&self.entry.to_le_bytes());
+        out[12..16].copy_from_slice(&self.image_len.to_le_bytes());
+        out[16..20].copy_from_slice(&self.bss_len.to_le_bytes());
+        out[20..24].copy_from_slice(&self.align.to_le_bytes());
+        out[24..28].copy_from_slice(&self.reloc_count.to_le_bytes());
+        out
+    }
+
+    /// The total amount of memory required to load this binary, including
+    /// the bss region.
+    pub fn load_len(&self) -> usize {
+        self.image_len as usize + self.bss_len as usize
+    }
+}
+
+// === impl FlatBinary ===
+
+impl<'a> FlatBinary<'a> {
+    /// Parse a flat binary from `bytes`, validating the header and that the
+    /// image and relocation table are present.
+    ///
+    /// Individual relocations are validated when they are applied.
+    pub fn parse(bytes: &'a [u8]) -> Result<Self, Error> {
+        let header = Header::parse(bytes)?;
+        let rest = &bytes[Header::SIZE..];
+        let image_len = header.image_len as usize;
+        let relocs_len = (header.reloc_count as usize)
+            .checked_mul(4)
+            .ok_or(Error::Truncated)?;
+        let image = rest.get(..image_len).ok_or(Error::Truncated)?;
+        let relocs = rest
+            .get(image_len..)
+            .and_then(|r| r.get(..relocs_len))
+            .ok_or(Error::Truncated)?;
+        Ok(Self {
+            header,
+            image,
+            relocs,
+        })
+    }
+
+    #[inline]
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    #[inline]
+    pub fn image(&self) -> &'a [u8] {
+        self.image
+    }
+
+    /// Returns an iterator over the image offsets that must be relocated.
+    pub fn relocations(&self) -> impl Iterator<Item = u32> + 'a {
+        self.relocs
+            .chunks_exact(4)
+            .map(|c| u32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+    }
+
+    /// Copy the image into `dest`, zero the bss region, and apply all
+    /// relocations as if `dest` will be located at address `base`.
+    ///
+    /// `dest` must be at least [`Header::load_len`] bytes long. Any bytes past
+    /// the end of the bss region are left untouched.
+    pub fn relocate_into(&self, dest: &mut [u8], base: u64) -> Result<(), Error> {
+        let load_len = self.header.load_len();
+        let dest = dest.get_mut(..load_len).ok_or(Error::DestinationTooSmall)?;
+        let (image, bss) = dest.split_at_mut(self.image.len());
+        image.copy_from_slice(self.image);
+        bss.fill(0);
+
+        let width = self.header.ptr_width as usize;
+        for offset in self.relocations() {
+            let start = offset as usize;
+            let word = start
+                .checked_add(width)
+                .and_then(|end| image.get_mut(start..end))
+                .ok_or(Error::BadRelocation(offset))?;
+            match width {
+                4 => {
+                    let mut val = [0u8; 4];
+                    val.copy_from_slice(word);
+                    let val = u32::from_le_bytes(val).wrapping_add(base as u32);
+                    word.copy_from_slice(&val.to_le_bytes());
+                }
+                _ => {
+                    let mut val = [0u8; 8];
+                    val.copy_from_slice(word);
+                    let val = u64::from_le_bytes(val).wrapping_add(base);
+                    word.copy_from_slice(&val.to_le_bytes());
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// === impl Error ===
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Truncated => f.pad("flat binary is truncated"),
+            Self::BadMagic => f.pad("not a flat binary (bad magic)"),
+            Self::UnsupportedVersion(v) => write!(f, "unsupported flat binary version {v}"),
+            Self::BadPointerWidth(w) => write!(f, "unsupported pointer width {w}"),
+            Self::BadAlignment(a) => write!(f, "invalid load alignment {a}"),
+            Self::BadEntry(e) => write!(f, "entry point {e:#x} is outside the image"),
+            Self::BadRelocation(r) => write!(f, "relocation at {r:#x} is outside the image"),
+            Self::DestinationTooSmall => f.pad("destination buffer too small"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn build(header: Header, image: &[u8], relocs: &[u32]) -> Vec<u8> {
+        let mut out = header.to_bytes().to_vec();
+        out.extend_from_slice(image);
+        for r in relocs {
+            out.extend_from_slice(&r.to_le_bytes());
+        }
+        out
+    }
+
+    #[test]
+    fn header_roundtrip() {
+        let header = Header {
+            ptr_width: 8,
+            entry: 4,
+            image_len: 16,
+            bss_len: 32,
+            align: 8,
+            reloc_count: 1,
+        };
+        assert_eq!(Header::parse(&header.to_bytes()), Ok(header));
+    }
+
+    #[test]
+    fn relocates_words() {
+        let header = Header {
+            ptr_width: 4,
+            entry: 0,
+            image_len: 8,
+            bss_len: 4,
+            align: 4,
+            reloc_count: 1,
+        };
+        let image = [0xAA, 0xBB, 0xCC, 0xDD, 0x10, 0x00, 0x00, 0x00];
+        let bytes = build(header, &image, &[4]);
+        let flat = FlatBinary::parse(&bytes).unwrap();
+
+        let mut dest = [0xFFu8; 12];
+        flat.relocate_into(&mut dest, 0x8000_0000).unwrap();
+        assert_eq!(&dest[..4], &image[..4]);
+        assert_eq!(&dest[4..8], &0x8000_0010u32.to_le_bytes());
+        assert_eq!(&dest[8..], &[0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn rejects_out_of_bounds_reloc() {
+        let header = Header {
+            ptr_width: 8,
+            entry: 0,
+            image_len: 8,
+            bss_len: 0,
+            align: 8,
+            reloc_count: 1,
+        };
+        let bytes = build(header, &[0; 8], &[4]);
+        let flat = FlatBinary::parse(&bytes).unwrap();
+        let mut dest = [0u8; 8];
+        assert_eq!(
+            flat.relocate_into(&mut dest, 0x1000),
+            Err(Error::BadRelocation(4))
+        );
+    }
+
+    #[test]
+    fn rejects_truncated() {
+        let header = Header {
+            ptr_width: 8,
+            entry: 0,
+            image_len: 8,
+            bss_len: 0,
+            align: 8,
+            reloc_count: 2,
+        };
+        let bytes = build(header, &[0; 8], &[0]);
+        assert_eq!(FlatBinary::parse(&bytes).unwrap_err(), Error::Truncated);
+    }
+}
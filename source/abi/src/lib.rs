@@ -16,6 +16,8 @@ pub static HEAP_LEN: AtomicUsize = AtomicUsize::new(0);
 // pub mod porcelain;
 pub mod bbqueue_ipc;
 pub mod boxes;
+pub mod bundle;
+pub mod flat;
 pub mod syscall;
 
 // This will always live at the TOP of the user memory region, and will be
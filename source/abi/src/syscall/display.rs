@@ -0,0 +1,61 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug)]
+#[cfg_attr(feature = "use-defmt", derive(defmt::Format))]
+pub enum DisplayRequest {
+    /// Lease the display's framebuffer, so that it can be written to
+    /// directly.
+    LeaseFramebuffer,
+    /// Show the pixels written to an area of the leased framebuffer.
+    ///
+    /// The area is given in the display's physical coordinates.
+    SubmitDamage {
+        lease: u32,
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+    },
+    /// End a lease. The framebuffer must not be written to afterwards.
+    ReleaseFramebuffer { lease: u32 },
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[cfg_attr(feature = "use-defmt", derive(defmt::Format))]
+pub enum DisplayResponse {
+    Leased(FramebufferLease),
+    DamageSubmitted { lease: u32 },
+    Released { lease: u32 },
+}
+
+/// A lease on the display's framebuffer.
+///
+/// The framebuffer holds `height` rows of `width` pixels, one byte per pixel,
+/// with rows starting every `stride` bytes. A pixel is lit if its byte is
+/// non-zero.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "use-defmt", derive(defmt::Format))]
+pub struct FramebufferLease {
+    pub lease: u32,
+    /// The physical address of the framebuffer's first byte.
+    pub addr: usize,
+    /// The length of the framebuffer, in bytes.
+    pub len: usize,
+    pub width: u32,
+    pub height: u32,
+    pub stride: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[cfg_attr(feature = "use-defmt", derive(defmt::Format))]
+pub enum DisplayError {
+    /// The display can't lease its framebuffer.
+    Unsupported,
+    /// The framebuffer is already leased.
+    Leased,
+    /// The lease was released, or revoked by the kernel.
+    Revoked,
+    /// The damaged area is outside of the framebuffer.
+    OutOfBounds,
+    Unknown,
+}
@@ -22,6 +22,7 @@
 //! moment. If this is important to you, pin the exact `common` crate version
 //! you plan to support, or open an issue to discuss changing this policy.
 
+pub mod display;
 pub mod serial;
 
 use serde::{Deserialize, Serialize};
@@ -33,6 +34,7 @@ use serde::{Deserialize, Serialize};
 #[derive(PartialEq, Eq)]
 pub enum DriverKind {
     Serial,
+    Display,
 
     // I'm not sure if I actually want to keep the "driverkind" paradigm.
     Todo,
@@ -55,12 +57,14 @@ pub struct UserRequestHeader {
 #[cfg_attr(feature = "use-defmt", derive(defmt::Format))]
 pub enum UserRequestBody {
     Serial(serial::SerialRequest),
+    Display(display::DisplayRequest),
 }
 
 impl UserRequest {
     pub fn driver_kind(&self) -> DriverKind {
         match self.body {
             UserRequestBody::Serial(_) => DriverKind::Serial,
+            UserRequestBody::Display(_) => DriverKind::Display,
         }
     }
 }
@@ -90,6 +94,7 @@ pub struct KernelResponseHeader {
 #[cfg_attr(feature = "use-defmt", derive(defmt::Format))]
 pub enum KernelResponseBody {
     Serial(Result<serial::SerialResponse, serial::SerialError>),
+    Display(Result<display::DisplayResponse, display::DisplayError>),
     TodoLoopback,
 }
 
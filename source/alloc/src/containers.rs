@@ -5,12 +5,13 @@
 use core::{
     alloc::Layout,
     cell::UnsafeCell,
+    future::Future,
     mem::MaybeUninit,
     ops::{Deref, DerefMut},
     ptr::NonNull,
 };
 
-use crate::heap::alloc;
+use crate::heap::{alloc_from, try_alloc, Caller};
 
 //
 // Arc
@@ -34,9 +35,10 @@ impl<T> Arc<T> {
     ///
     /// NOTE/TODO: Today this will panic if not immediately successful. This should
     /// be fixed in the future
+    #[track_caller]
     pub fn try_new(t: T) -> Result<Self, T> {
         Ok(Self {
-            inner: alloc::sync::Arc::new(t),
+            inner: Caller::get().attribute(|| alloc::sync::Arc::new(t)),
         })
     }
 
@@ -46,9 +48,13 @@ impl<T> Arc<T> {
     ///
     /// NOTE/TODO: Today this will panic if not immediately successful. This should
     /// be fixed in the future
-    pub async fn new(t: T) -> Self {
-        Self {
-            inner: alloc::sync::Arc::new(t),
+    #[track_caller]
+    pub fn new(t: T) -> impl Future<Output = Self> {
+        let caller = Caller::get();
+        async move {
+            Self {
+                inner: caller.attribute(|| alloc::sync::Arc::new(t)),
+            }
         }
     }
 
@@ -117,11 +123,15 @@ impl<T> Box<T> {
     /// Attempt to allocate a new owned T.
     ///
     /// Will not complete until the allocation succeeds.
-    pub async fn new(t: T) -> Self {
-        let ptr: *mut T = alloc(Layout::new::<T>()).await.cast().as_ptr();
-        unsafe {
-            ptr.write(t);
-            Self::from_raw(ptr)
+    #[track_caller]
+    pub fn new(t: T) -> impl Future<Output = Self> {
+        let caller = Caller::get();
+        async move {
+            let ptr: *mut T = alloc_from(Layout::new::<T>(), caller).await.cast().as_ptr();
+            unsafe {
+                ptr.write(t);
+                Self::from_raw(ptr)
+            }
         }
     }
 
@@ -129,8 +139,9 @@ impl<T> Box<T> {
     ///
     /// Returns an error containing the provided value if the allocation
     /// could not immediately succeed.
+    #[track_caller]
     pub fn try_new(t: T) -> Result<Self, T> {
-        match NonNull::new(unsafe { alloc::alloc::alloc(Layout::new::<T>()) }) {
+        match unsafe { try_alloc(Layout::new::<T>(), Caller::get()) } {
             Some(ptr) => unsafe {
                 let ptr = ptr.cast::<T>().as_ptr();
                 ptr.write(t);
@@ -212,10 +223,11 @@ impl<T> ArrayBuf<T> {
     /// Returns None if the allocation does not succeed immediately.
     ///
     /// Panics if the len is zero, or large enough that creating the layout would fail
+    #[track_caller]
     pub fn try_new_uninit(len: usize) -> Option<Self> {
         assert_ne!(len, 0, "ZST ArrayBuf doesn't make sense");
         let layout = Self::layout(len);
-        let ptr = NonNull::new(unsafe { alloc::alloc::alloc(layout) })?.cast();
+        let ptr = unsafe { try_alloc(layout, Caller::get()) }?.cast();
         Some(ArrayBuf { ptr, len })
     }
 
@@ -224,11 +236,15 @@ impl<T> ArrayBuf<T> {
     /// Will not return until allocation succeeds.
     ///
     /// Panics if the len is zero, or large enough that creating the layout would fail
-    pub async fn new_uninit(len: usize) -> Self {
-        assert_ne!(len, 0, "ZST ArrayBuf doesn't make sense");
-        let layout = Self::layout(len);
-        let ptr = alloc(layout).await.cast();
-        ArrayBuf { ptr, len }
+    #[track_caller]
+    pub fn new_uninit(len: usize) -> impl Future<Output = Self> {
+        let caller = Caller::get();
+        async move {
+            assert_ne!(len, 0, "ZST ArrayBuf doesn't make sense");
+            let layout = Self::layout(len);
+            let ptr = alloc_from(layout, caller).await.cast();
+            ArrayBuf { ptr, len }
+        }
     }
 
     /// Obtain a pointer to the heap allocated storage, as well as the length of items
@@ -310,20 +326,24 @@ impl<T> HeapArray<T> {
     /// Will not return until allocation succeeds.
     ///
     /// Panics if the len is zero, or large enough that creating the layout would fail
-    pub async fn new(len: usize, init: T) -> Self
+    #[track_caller]
+    pub fn new(len: usize, init: T) -> impl Future<Output = Self>
     where
         T: Copy,
     {
-        assert_ne!(len, 0, "ZST HeapArray doesn't make sense");
-        let layout = Self::layout(len);
-        let ptr: NonNull<T> = alloc(layout).await.cast();
-        unsafe {
-            let ptr = ptr.as_ptr();
-            for i in 0..len {
-                ptr.add(i).write(init);
+        let caller = Caller::get();
+        async move {
+            assert_ne!(len, 0, "ZST HeapArray doesn't make sense");
+            let layout = Self::layout(len);
+            let ptr: NonNull<T> = alloc_from(layout, caller).await.cast();
+            unsafe {
+                let ptr = ptr.as_ptr();
+                for i in 0..len {
+                    ptr.add(i).write(init);
+                }
             }
+            HeapArray { ptr, len }
         }
-        HeapArray { ptr, len }
     }
 
     /// Returns the length of the `HeapArray`.
@@ -392,12 +412,13 @@ impl<T> FixedVec<T> {
     /// Returns None if the allocation does not succeed immediately.
     ///
     /// Panics if the len is zero, or large enough that creating the layout would fail
+    #[track_caller]
     pub fn try_new(capacity: usize) -> Option<Self> {
         assert_ne!(capacity, 0, "ZST FixedVec doesn't make sense");
         let layout = Layout::array::<T>(capacity).unwrap();
 
         unsafe {
-            let ptr = NonNull::new(alloc::alloc::alloc(layout))?;
+            let ptr = try_alloc(layout, Caller::get())?;
             Some(FixedVec {
                 inner: alloc::vec::Vec::from_raw_parts(ptr.cast().as_ptr(), 0, capacity),
             })
@@ -409,14 +430,18 @@ impl<T> FixedVec<T> {
     /// Will not return until allocation succeeds.
     ///
     /// Panics if the len is zero, or large enough that creating the layout would fail
-    pub async fn new(capacity: usize) -> Self {
-        assert_ne!(capacity, 0, "ZST FixedVec doesn't make sense");
-        let layout = Layout::array::<T>(capacity).unwrap();
-
-        unsafe {
-            let ptr = alloc(layout).await;
-            FixedVec {
-                inner: alloc::vec::Vec::from_raw_parts(ptr.cast().as_ptr(), 0, capacity),
+    #[track_caller]
+    pub fn new(capacity: usize) -> impl Future<Output = Self> {
+        let caller = Caller::get();
+        async move {
+            assert_ne!(capacity, 0, "ZST FixedVec doesn't make sense");
+            let layout = Layout::array::<T>(capacity).unwrap();
+
+            unsafe {
+                let ptr = alloc_from(layout, caller).await;
+                FixedVec {
+                    inner: alloc::vec::Vec::from_raw_parts(ptr.cast().as_ptr(), 0, capacity),
+                }
             }
         }
     }
@@ -0,0 +1,667 @@
+//! Memory for DMA
+//!
+//! Buffers and descriptors which are accessed by DMA-capable peripherals may
+//! need to be allocated differently from the rest of the heap. On some
+//! platforms, the CPU's data cache is not coherent with DMA, so memory written
+//! by the CPU must be cleaned from the cache before a peripheral reads it, and
+//! memory written by a peripheral must be invalidated in the cache before the
+//! CPU reads it. Invalidating a cache line discards any CPU writes to it, so
+//! DMA buffers must not share a cache line with anything else.
+//!
+//! A platform describes these requirements with [`Attributes`], and reserves a
+//! region of memory for DMA with [`init`]. The types in this module then
+//! allocate from that region:
+//!
+//! * [`DmaBuf`] is a buffer of bytes, for data transferred by DMA.
+//! * [`DmaBox<T>`] is a single value, for things like DMA descriptors.
+//!
+//! On platforms where DMA is coherent with the CPU (or which have no DMA at
+//! all), no region needs to be reserved. If [`init`] is never called, these
+//! types are allocated from the normal heap, and the cache maintenance methods
+//! do nothing.
+//!
+//! ## Cache Maintenance
+//!
+//! Regardless of the platform, drivers should:
+//!
+//! * call `sync_for_device` before starting *any* DMA transfer which uses a
+//!   buffer, even if the peripheral will only write to it. Otherwise, dirty
+//!   cache lines may be written back over the peripheral's data.
+//! * call `sync_for_cpu` after a transfer in which the peripheral wrote to the
+//!   buffer, before reading it.
+//!
+//! Memory which is only *read* by peripherals, such as an outgoing buffer that
+//! was allocated from the normal heap, may also be cleaned with
+//! [`sync_for_device`], which is always safe. There is no corresponding
+//! function for memory written by peripherals, since it's only safe to
+//! invalidate memory allocated by this module.
+
+use core::{
+    alloc::Layout,
+    fmt,
+    future::Future,
+    mem,
+    ops::{Deref, DerefMut},
+    ptr::{self, NonNull},
+};
+
+use linked_list_allocator::Heap;
+use maitake::sync::{blocking::Mutex, spin::Spinlock, WaitQueue};
+
+use crate::heap::{self, alloc_from, try_alloc, Caller};
+
+/// The attributes of the memory provided for DMA by a platform.
+#[derive(Copy, Clone, Debug)]
+pub struct Attributes {
+    /// The minimum alignment of DMA allocations.
+    ///
+    /// The sizes of allocations are also rounded up to a multiple of this
+    /// alignment. If DMA is not coherent, this must be at least the size of a
+    /// cache line, so that allocations never share a cache line.
+    ///
+    /// This must be a power of two.
+    pub align: usize,
+
+    /// How the CPU's view of DMA memory is kept consistent with peripherals'.
+    pub coherence: Coherence,
+}
+
+/// How the CPU's view of DMA memory is kept consistent with peripherals'.
+#[derive(Copy, Clone)]
+pub enum Coherence {
+    /// DMA memory is coherent with the CPU, either because it is uncached, or
+    /// because the hardware keeps the caches coherent. No cache maintenance is
+    /// necessary.
+    Coherent,
+
+    /// DMA memory is cached, and the cache must be maintained in software.
+    Manual(CacheOps),
+}
+
+/// Cache maintenance operations for [`Coherence::Manual`].
+///
+/// Each operation is passed the start and length of a range of memory. The
+/// range may not be aligned to cache lines, in which case every line which
+/// overlaps it must be maintained.
+#[derive(Copy, Clone)]
+pub struct CacheOps {
+    /// Writes back any dirty cache lines in the range to memory, so that
+    /// peripherals see what the CPU wrote.
+    pub clean: unsafe fn(NonNull<u8>, usize),
+
+    /// Discards any cache lines in the range, so that the CPU sees what
+    /// peripherals wrote.
+    ///
+    /// This is only ever called on memory allocated from the DMA region.
+    pub invalidate: unsafe fn(NonNull<u8>, usize),
+}
+
+/// Errors returned by [`init`].
+#[derive(Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum InitError {
+    /// The DMA region has already been initialized.
+    AlreadyInitialized,
+}
+
+/// A buffer of bytes which can be used for DMA transfers.
+///
+/// The buffer's contents are zeroed when it is allocated.
+pub struct DmaBuf {
+    ptr: NonNull<u8>,
+    len: usize,
+    alloc: Alloc,
+}
+
+/// A single owned `T` which can be accessed by DMA, such as a DMA descriptor.
+///
+/// The value is aligned to at least [`Attributes::align`].
+pub struct DmaBox<T> {
+    ptr: NonNull<T>,
+    alloc: Alloc,
+}
+
+/// Where a DMA allocation came from, and how to maintain it.
+#[derive(Copy, Clone)]
+struct Alloc {
+    layout: Layout,
+    coherence: Coherence,
+    from_region: bool,
+}
+
+struct Region {
+    heap: Heap,
+    attrs: Attributes,
+}
+
+/// Why an allocation from the DMA region failed.
+#[derive(Debug, Eq, PartialEq)]
+enum RegionError {
+    /// The region is out of space.
+    Full,
+    /// The allocation will never fit in the region, which is `size` bytes
+    /// long.
+    TooLarge { size: usize },
+}
+
+/// The DMA region, if the platform has reserved one.
+static REGION: Mutex<Option<Region>, Spinlock> = Mutex::new_with_raw_mutex(None, Spinlock::new());
+
+/// A [WaitQueue] for tasks waiting for space in the DMA region.
+static REGION_WAIT: WaitQueue = WaitQueue::new();
+
+/// Reserves the memory region starting at `start` and ending at `start + len`
+/// for DMA allocations, which have the provided [`Attributes`].
+///
+/// This should be called by platforms where DMA is not coherent, or which
+/// otherwise require DMA memory to be treated differently, before any DMA
+/// allocations are made. DMA allocations made before calling this are
+/// allocated from the heap instead.
+///
+/// # Returns
+///
+/// - [`Ok`]`(())` if the region was successfully initialized.
+/// - [`Err`]`(`[`InitError::AlreadyInitialized`]`)` if this function has
+///   already been called.
+///
+/// # Safety
+///
+/// This function requires the caller to uphold the same invariants as
+/// [`MnemosAlloc::init`](crate::heap::MnemosAlloc::init). In addition, the
+/// region must have the provided `attrs`, and must not overlap the heap.
+///
+/// # Panics
+///
+/// If `attrs.align` is not a power of two.
+pub unsafe fn init(start: NonNull<u8>, len: usize, attrs: Attributes) -> Result<(), InitError> {
+    assert!(
+        attrs.align.is_power_of_two(),
+        "DMA alignment must be a power of two"
+    );
+
+    let mut region = REGION.lock();
+    if region.is_some() {
+        return Err(InitError::AlreadyInitialized);
+    }
+
+    let mut heap = Heap::empty();
+    heap.init(start.as_ptr(), len);
+    *region = Some(Region { heap, attrs });
+    Ok(())
+}
+
+/// Returns the [`Attributes`] of DMA memory.
+///
+/// If the platform has not reserved a DMA region with [`init`], DMA memory is
+/// assumed to be coherent.
+#[must_use]
+pub fn attributes() -> Attributes {
+    REGION
+        .lock()
+        .as_ref()
+        .map_or(Attributes::COHERENT, |region| region.attrs)
+}
+
+/// Makes the CPU's writes to `buf` visible to peripherals.
+///
+/// This is always safe to call on any memory, not just memory allocated for
+/// DMA. See [the module-level docs](self#cache-maintenance) for details.
+pub fn sync_for_device(buf: &[u8]) {
+    if let Some(ptr) = NonNull::new(buf.as_ptr().cast_mut()) {
+        unsafe { attributes().coherence.clean(ptr, buf.len()) }
+    }
+}
+
+// === impl Attributes ===
+
+impl Attributes {
+    /// The attributes of memory which needs no special treatment for DMA.
+    pub const COHERENT: Self = Self {
+        align: 1,
+        coherence: Coherence::Coherent,
+    };
+}
+
+// === impl Coherence ===
+
+impl Coherence {
+    unsafe fn clean(&self, ptr: NonNull<u8>, len: usize) {
+        if let Self::Manual(ops) = self {
+            (ops.clean)(ptr, len)
+        }
+    }
+
+    unsafe fn invalidate(&self, ptr: NonNull<u8>, len: usize) {
+        if let Self::Manual(ops) = self {
+            (ops.invalidate)(ptr, len)
+        }
+    }
+}
+
+impl fmt::Debug for Coherence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Coherent => f.write_str("Coherent"),
+            Self::Manual(_) => f.write_str("Manual"),
+        }
+    }
+}
+
+// === impl Alloc ===
+
+impl Alloc {
+    /// Allocates `layout` from the DMA region, waiting for space to become
+    /// available, or from the heap, if there is no DMA region.
+    async fn new(layout: Layout, caller: Caller) -> (NonNull<u8>, Self) {
+        loop {
+            // start waiting *before* trying to allocate, so that a
+            // deallocation which happens in between isn't missed.
+            let wait = REGION_WAIT.wait();
+            match Self::from_region(layout) {
+                Some(Ok(alloc)) => return alloc,
+                Some(Err(RegionError::Full)) => {}
+                // waiting for space would never end.
+                Some(Err(RegionError::TooLarge { size })) => {
+                    panic!(
+                        "DMA allocation of {layout:?} will never fit in the {size}-byte DMA region"
+                    )
+                }
+                None => return (alloc_from(layout, caller).await, Self::heap(layout)),
+            }
+            let _ = wait.await;
+        }
+    }
+
+    /// Allocates `layout` from the DMA region, or from the heap, if there is
+    /// no DMA region, returning `None` if the allocation does not succeed
+    /// immediately.
+    fn try_new(layout: Layout, caller: Caller) -> Option<(NonNull<u8>, Self)> {
+        match Self::from_region(layout) {
+            Some(alloc) => alloc.ok(),
+            None => {
+                let ptr = unsafe { try_alloc(layout, caller) }?;
+                Some((ptr, Self::heap(layout)))
+            }
+        }
+    }
+
+    /// Tries to allocate `layout` from the DMA region.
+    ///
+    /// Returns `None` if there is no DMA region.
+    fn from_region(layout: Layout) -> Option<Result<(NonNull<u8>, Self), RegionError>> {
+        let mut region = REGION.lock();
+        Some(region.as_mut()?.alloc(layout))
+    }
+
+    fn heap(layout: Layout) -> Self {
+        Self {
+            layout,
+            coherence: Coherence::Coherent,
+            from_region: false,
+        }
+    }
+
+    /// Frees the allocation at `ptr`.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have been allocated along with `self`.
+    unsafe fn free(&self, ptr: NonNull<u8>) {
+        if !self.from_region {
+            heap::dealloc(ptr.as_ptr(), self.layout);
+            return;
+        }
+
+        // if we're freeing DMA memory, the region exists.
+        if let Some(region) = REGION.lock().as_mut() {
+            region.heap.deallocate(ptr, self.layout);
+        }
+        REGION_WAIT.wake_all();
+    }
+}
+
+// === impl Region ===
+
+impl Region {
+    /// Tries to allocate `layout`.
+    fn alloc(&mut self, layout: Layout) -> Result<(NonNull<u8>, Alloc), RegionError> {
+        let attrs = self.attrs;
+        let padded = Layout::from_size_align(layout.size(), layout.align().max(attrs.align))
+            .ok()
+            .map(|layout| layout.pad_to_align())
+            .filter(|layout| self.fits(layout))
+            .ok_or(RegionError::TooLarge {
+                size: self.heap.size(),
+            })?;
+        let ptr = self
+            .heap
+            .allocate_first_fit(padded)
+            .map_err(|_| RegionError::Full)?;
+        let alloc = Alloc {
+            layout: padded,
+            coherence: attrs.coherence,
+            from_region: true,
+        };
+        Ok((ptr, alloc))
+    }
+
+    /// Returns `true` if `layout` would fit in the region if nothing else
+    /// were allocated from it.
+    fn fits(&self, layout: &Layout) -> bool {
+        let top = self.heap.top() as usize;
+        let mask = layout.align() - 1;
+        (self.heap.bottom() as usize)
+            .checked_add(mask)
+            .map(|start| start & !mask)
+            .is_some_and(|start| top.saturating_sub(start) >= layout.size())
+    }
+}
+
+// === impl DmaBuf ===
+
+impl DmaBuf {
+    /// Allocates a new zeroed buffer of `len` bytes.
+    ///
+    /// Will not complete until the allocation succeeds.
+    ///
+    /// Panics if the len is zero, large enough that creating the layout would
+    /// fail, or larger than the DMA region
+    #[track_caller]
+    pub fn new(len: usize) -> impl Future<Output = Self> {
+        let caller = Caller::get();
+        async move {
+            let (ptr, alloc) = Alloc::new(Self::layout(len), caller).await;
+            Self::zeroed(ptr, len, alloc)
+        }
+    }
+
+    /// Attempts to allocate a new zeroed buffer of `len` bytes.
+    ///
+    /// Returns `None` if the allocation does not succeed immediately,
+    /// including if `len` is larger than the DMA region.
+    ///
+    /// Panics if the len is zero, or large enough that creating the layout would fail
+    #[track_caller]
+    pub fn try_new(len: usize) -> Option<Self> {
+        let (ptr, alloc) = Alloc::try_new(Self::layout(len), Caller::get())?;
+        Some(Self::zeroed(ptr, len, alloc))
+    }
+
+    /// Returns the length of the buffer, in bytes.
+    #[inline]
+    #[must_use]
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Makes the CPU's writes to this buffer visible to peripherals.
+    ///
+    /// This must be called before starting any DMA transfer which uses the
+    /// buffer. See [the module-level docs](self#cache-maintenance) for
+    /// details.
+    pub fn sync_for_device(&self) {
+        unsafe { self.alloc.coherence.clean(self.ptr, self.len) }
+    }
+
+    /// Makes peripherals' writes to this buffer visible to the CPU.
+    ///
+    /// This must be called after a DMA transfer which wrote to the buffer
+    /// has completed. See [the module-level docs](self#cache-maintenance) for
+    /// details.
+    pub fn sync_for_cpu(&mut self) {
+        unsafe { self.alloc.coherence.invalidate(self.ptr, self.len) }
+    }
+
+    fn layout(len: usize) -> Layout {
+        assert_ne!(len, 0, "ZST DmaBuf doesn't make sense");
+        Layout::array::<u8>(len).unwrap()
+    }
+
+    fn zeroed(ptr: NonNull<u8>, len: usize, alloc: Alloc) -> Self {
+        unsafe { ptr.as_ptr().write_bytes(0, len) };
+        Self { ptr, len, alloc }
+    }
+}
+
+unsafe impl Send for DmaBuf {}
+unsafe impl Sync for DmaBuf {}
+
+impl Drop for DmaBuf {
+    fn drop(&mut self) {
+        unsafe { self.alloc.free(self.ptr) }
+    }
+}
+
+impl Deref for DmaBuf {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        unsafe {
+            // Safety: the `DmaBuf` logically owns `self.ptr`, and it is only
+            // deallocated when the `DmaBuf` is dropped. It was allocated with
+            // room for at least `self.len` bytes, which were zeroed.
+            core::slice::from_raw_parts(self.ptr.as_ptr(), self.len)
+        }
+    }
+}
+
+impl DerefMut for DmaBuf {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe {
+            // Safety: the `DmaBuf` logically owns `self.ptr`, and it is only
+            // deallocated when the `DmaBuf` is dropped. It was allocated with
+            // room for at least `self.len` bytes, which were zeroed.
+            core::slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len)
+        }
+    }
+}
+
+impl fmt::Debug for DmaBuf {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DmaBuf")
+            .field("ptr", &self.ptr)
+            .field("len", &self.len)
+            .field("coherence", &self.alloc.coherence)
+            .finish()
+    }
+}
+
+// === impl DmaBox ===
+
+impl<T> DmaBox<T> {
+    /// Allocates a new owned `T` in DMA memory.
+    ///
+    /// Will not complete until the allocation succeeds.
+    ///
+    /// Panics if `T` is larger than the DMA region.
+    #[track_caller]
+    pub fn new(t: T) -> impl Future<Output = Self> {
+        let caller = Caller::get();
+        async move {
+            let (ptr, alloc) = Alloc::new(Self::layout(), caller).await;
+            Self::write(ptr, alloc, t)
+        }
+    }
+
+    /// Attempts to allocate a new owned `T` in DMA memory.
+    ///
+    /// Returns an error containing the provided value if the allocation
+    /// could not immediately succeed, including if `T` is larger than the DMA
+    /// region.
+    #[track_caller]
+    pub fn try_new(t: T) -> Result<Self, T> {
+        match Alloc::try_new(Self::layout(), Caller::get()) {
+            Some((ptr, alloc)) => Ok(Self::write(ptr, alloc, t)),
+            None => Err(t),
+        }
+    }
+
+    /// Returns a pointer to the value, which may be handed to a peripheral.
+    ///
+    /// The pointer is valid for as long as the `DmaBox` exists.
+    #[inline]
+    #[must_use]
+    pub fn as_non_null(&self) -> NonNull<T> {
+        self.ptr
+    }
+
+    /// Makes the CPU's writes to the value visible to peripherals.
+    ///
+    /// This must be called before a peripheral reads the value. See [the
+    /// module-level docs](self#cache-maintenance) for details.
+    pub fn sync_for_device(&self) {
+        unsafe {
+            self.alloc
+                .coherence
+                .clean(self.ptr.cast(), mem::size_of::<T>())
+        }
+    }
+
+    /// Makes peripherals' writes to the value visible to the CPU.
+    ///
+    /// This must be called after a peripheral has written to the value,
+    /// before reading it. See [the module-level docs](self#cache-maintenance)
+    /// for details.
+    pub fn sync_for_cpu(&mut self) {
+        unsafe {
+            self.alloc
+                .coherence
+                .invalidate(self.ptr.cast(), mem::size_of::<T>())
+        }
+    }
+
+    fn layout() -> Layout {
+        let layout = Layout::new::<T>();
+        assert_ne!(layout.size(), 0, "ZST DmaBox doesn't make sense");
+        layout
+    }
+
+    fn write(ptr: NonNull<u8>, alloc: Alloc, t: T) -> Self {
+        let ptr = ptr.cast::<T>();
+        unsafe { ptr.as_ptr().write(t) };
+        Self { ptr, alloc }
+    }
+}
+
+unsafe impl<T: Send> Send for DmaBox<T> {}
+unsafe impl<T: Sync> Sync for DmaBox<T> {}
+
+impl<T> Drop for DmaBox<T> {
+    fn drop(&mut self) {
+        unsafe {
+            ptr::drop_in_place(self.ptr.as_ptr());
+            self.alloc.free(self.ptr.cast());
+        }
+    }
+}
+
+impl<T> Deref for DmaBox<T> {
+    type Target = T;
+
+    #[inline(always)]
+    fn deref(&self) -> &Self::Target {
+        // Safety: the `DmaBox` owns the value, and it was initialized when
+        // the `DmaBox` was created.
+        unsafe { self.ptr.as_ref() }
+    }
+}
+
+impl<T> DerefMut for DmaBox<T> {
+    #[inline(always)]
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        // Safety: the `DmaBox` owns the value, and it was initialized when
+        // the `DmaBox` was created.
+        unsafe { self.ptr.as_mut() }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for DmaBox<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REGION_SIZE: usize = 256;
+
+    #[repr(align(64))]
+    struct Memory([u8; REGION_SIZE]);
+
+    /// Runs `f` with a fresh DMA region, aligned to `align`.
+    ///
+    /// This doesn't touch the global region, so the tests which use it don't
+    /// interfere with each other.
+    fn with_region(align: usize, f: impl FnOnce(&mut Region)) {
+        let mut memory = Memory([0; REGION_SIZE]);
+        let mut heap = Heap::empty();
+        unsafe { heap.init(memory.0.as_mut_ptr(), REGION_SIZE) };
+        let attrs = Attributes {
+            align,
+            coherence: Coherence::Coherent,
+        };
+        f(&mut Region { heap, attrs });
+    }
+
+    #[test]
+    fn alignment() {
+        with_region(64, |region| {
+            let (ptr, alloc) = region.alloc(Layout::new::<[u8; 10]>()).unwrap();
+            assert_eq!(ptr.as_ptr() as usize % 64, 0);
+            assert!(alloc.from_region);
+            // allocations never share a cache line.
+            assert_eq!(alloc.layout.size(), 64);
+            assert_eq!(alloc.layout.align(), 64);
+        });
+    }
+
+    #[test]
+    fn exhausted() {
+        with_region(64, |region| {
+            let layout = Layout::new::<[u8; 64]>();
+            let allocs = (0..4)
+                .map(|_| region.alloc(layout).unwrap())
+                .collect::<Vec<_>>();
+            assert_eq!(region.alloc(layout).err(), Some(RegionError::Full));
+
+            let (ptr, alloc) = allocs[2];
+            unsafe { region.heap.deallocate(ptr, alloc.layout) };
+            assert!(region.alloc(layout).is_ok());
+        });
+    }
+
+    #[test]
+    fn whole_region() {
+        with_region(64, |region| {
+            assert!(region.alloc(Layout::new::<[u8; REGION_SIZE]>()).is_ok());
+        });
+    }
+
+    #[test]
+    fn too_large() {
+        with_region(64, |region| {
+            assert_eq!(
+                region.alloc(Layout::new::<[u8; REGION_SIZE + 1]>()).err(),
+                Some(RegionError::TooLarge { size: REGION_SIZE })
+            );
+        });
+    }
+
+    #[test]
+    fn heap_fallback() {
+        // the tests never initialize the global DMA region, so allocations
+        // come from the heap.
+        let buf = DmaBuf::try_new(10).unwrap();
+        assert_eq!(&buf[..], &[0; 10]);
+        assert!(!buf.alloc.from_region);
+        buf.sync_for_device();
+
+        let mut desc = DmaBox::try_new(0xDEAD_BEEF_u32).unwrap();
+        assert_eq!(*desc, 0xDEAD_BEEF);
+        assert_eq!(desc.as_non_null().as_ptr() as usize % 4, 0);
+        desc.sync_for_cpu();
+    }
+}
@@ -2,6 +2,7 @@
 
 use core::{
     alloc::{GlobalAlloc, Layout},
+    future::Future,
     hint,
     ptr::{null_mut, NonNull},
 };
@@ -31,11 +32,36 @@ use portable_atomic::{AtomicBool, AtomicUsize, Ordering::*};
 /// * On **dealloc**:
 ///     * The "inhibit allocations" flag is cleared
 ///     * If any tasks are waiting on the "OOM" queue, they are ALL awoken if
-///       the inhibit flag was previously set
+///       the inhibit flag was previously set, or if a task has reserved heap
+///       space (see below)
 ///
 /// These two details are intended to allow the "async allocation aware" types
 /// defined in [crate::containers] to yield if allocation is not currently possible.
 ///
+/// ## Fairness
+///
+/// Tasks waiting in [`alloc()`] are served in FIFO order. Each waiting task
+/// takes a ticket, and the oldest waiting task *reserves* space for its
+/// allocation. While space is reserved, allocations by other tasks (including
+/// allocations which would not have to wait at all) are only admitted if
+/// enough free space would remain afterwards to serve the reservation. This
+/// prevents a large allocation from being starved forever by a stream of
+/// small ones, at the cost of delaying small allocations that *could*
+/// potentially succeed. Once the oldest task's allocation succeeds (or it
+/// stops waiting), the reservation passes to the next oldest task. A task
+/// whose allocation keeps failing while it holds the reservation (such as one
+/// which can't fit until long-lived allocations are freed) gives it up after
+/// a few attempts, so that it can't hold up every other allocation forever.
+/// Allocations larger than the whole heap panic, rather than waiting.
+///
+/// Since free space may be fragmented, an allocation admitted ahead of the
+/// oldest task is only kept if the reserved allocation could still be made
+/// after it.
+///
+/// Free space is only known once the heap has been [initialized](Self::init).
+/// Until then (such as when wrapping the system allocator), nothing is
+/// reserved, and waiting tasks simply retry after each deallocation.
+///
 /// By wrapping the [UnderlyingAllocator], we allow non-async-aware allocations
 /// (like those through [alloc::alloc::alloc()] or [alloc::alloc::dealloc()]) to
 /// trigger these behaviors. However, non-async-aware allocations are still subject
@@ -125,6 +151,7 @@ impl<U: UnderlyingAllocator> MnemosAlloc<U> {
 
         self.heap_size.compare_exchange(Self::INITIALIZING, len, AcqRel, Acquire)
             .expect("if we changed the heap state to INITIALIZING, no other CPU core should have changed its state");
+        HEAP_TOTAL.store(len, Release);
         Ok(())
     }
 
@@ -156,11 +183,14 @@ unsafe impl<U: UnderlyingAllocator> GlobalAlloc for MnemosAlloc<U> {
                 self.stats.alloc_oom_count.fetch_add(1, Release);
             }
         } else {
+            ALLOCATED.fetch_add(layout.size(), Release);
             #[cfg(feature = "stats")]
             {
                 self.stats.allocated.fetch_add(layout.size(), Release);
                 self.stats.alloc_success_count.fetch_add(1, Release);
             }
+            #[cfg(feature = "tags")]
+            tags::record(ptr, layout.size());
         }
         ptr
     }
@@ -171,6 +201,10 @@ unsafe impl<U: UnderlyingAllocator> GlobalAlloc for MnemosAlloc<U> {
         let _allocating = stats::start_context(&self.stats.deallocating);
 
         self.allocator.dealloc(ptr, layout);
+        ALLOCATED.fetch_sub(layout.size(), Release);
+
+        #[cfg(feature = "tags")]
+        tags::forget(ptr);
 
         #[cfg(feature = "stats")]
         {
@@ -179,7 +213,11 @@ unsafe impl<U: UnderlyingAllocator> GlobalAlloc for MnemosAlloc<U> {
         }
 
         let was_inhib = INHIBIT_ALLOC.swap(false, AcqRel);
-        if was_inhib {
+        #[cfg(feature = "fault-injection")]
+        let was_inhib = fault::WAKE_ON_DEALLOC.swap(false, AcqRel) || was_inhib;
+        // tasks waiting behind a reservation may now be admitted, even if
+        // nothing actually failed to allocate.
+        if was_inhib || RESERVATION.is_held() {
             OOM_WAITER.wake_all();
         }
     }
@@ -196,23 +234,348 @@ static OOM_WAITER: WaitQueue = WaitQueue::new();
 /// that *could* potentially succeed
 static INHIBIT_ALLOC: AtomicBool = AtomicBool::new(false);
 
+/// The total size of the heap, in bytes, or 0 if it is not known.
+static HEAP_TOTAL: AtomicUsize = AtomicUsize::new(0);
+
+/// The amount of memory currently allocated, in bytes.
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+/// Heap space reserved for the oldest task waiting in [`alloc()`].
+static RESERVATION: Reservation = Reservation::new();
+
+/// The next ticket to hand out to a task waiting in [`alloc()`]. Older
+/// waiters have lower tickets.
+static NEXT_TICKET: AtomicUsize = AtomicUsize::new(0);
+
+/// Reserves heap space for the oldest task waiting in [`alloc()`].
+///
+/// See [the `MnemosAlloc` docs](MnemosAlloc#fairness) for details.
+struct Reservation {
+    /// The ticket of the task holding the reservation, or
+    /// [`Reservation::NONE`].
+    ticket: AtomicUsize,
+    /// The size of the holder's allocation, in bytes.
+    size: AtomicUsize,
+    /// The alignment of the holder's allocation.
+    align: AtomicUsize,
+}
+
+/// A task's place in line in [`alloc()`]. Dropping it (whether the
+/// allocation succeeded, or the task stopped waiting) gives up the task's
+/// reservation, if it holds it.
+struct Ticket {
+    /// Older waiters have lower numbers.
+    number: usize,
+    /// The number of times the task's allocation has failed while it held
+    /// the reservation.
+    misses: usize,
+}
+
+/// The allocator registered with `#[global_allocator]`, which allocations
+/// admitted by the [`RESERVATION`] are made with.
+struct Global;
+
 /// Asynchronously allocate with the given [Layout].
 ///
 /// Analogous to [alloc::alloc::alloc()], but will never return a null pointer,
 /// and will instead yield until allocation succeeds (which could theoretically
 /// be never).
-pub async fn alloc(layout: Layout) -> NonNull<u8> {
+///
+/// When the "tags" feature of this crate is enabled, the allocation is
+/// [tagged](tags) with the location of the caller.
+#[track_caller]
+pub fn alloc(layout: Layout) -> impl Future<Output = NonNull<u8>> {
+    alloc_from(layout, Caller::get())
+}
+
+/// Asynchronously allocate with the given [Layout], attributing the
+/// allocation to `caller`.
+///
+/// # Panics
+///
+/// If `layout` is larger than the whole heap, since waiting for space would
+/// never end.
+pub(crate) async fn alloc_from(layout: Layout, caller: Caller) -> NonNull<u8> {
+    let total = HEAP_TOTAL.load(Acquire);
+    assert!(
+        total == 0 || layout.size() <= total,
+        "allocation of {layout:?} will never fit in the {total}-byte heap"
+    );
+
+    // if nobody is waiting, or there's room to spare, try to allocate without
+    // waiting in line.
+    if let Some(nn) = attempt_alloc(None, layout, caller) {
+        return nn;
+    }
+
+    let mut ticket = Ticket::new(NEXT_TICKET.fetch_add(1, AcqRel));
     loop {
-        unsafe {
-            match NonNull::new(alloc::alloc::alloc(layout)) {
-                Some(nn) => return nn,
-                None => {
-                    let _ = OOM_WAITER.wait().await;
-                    continue;
+        // start waiting *before* trying to allocate, so that a deallocation
+        // which happens in between isn't missed.
+        let wait = OOM_WAITER.wait();
+        if let Some(nn) = attempt_alloc(Some(&mut ticket), layout, caller) {
+            return nn;
+        }
+        let _ = wait.await;
+    }
+}
+
+/// Tries to allocate once, on behalf of [`alloc_from`].
+fn attempt_alloc(
+    ticket: Option<&mut Ticket>,
+    layout: Layout,
+    caller: Caller,
+) -> Option<NonNull<u8>> {
+    #[cfg(feature = "fault-injection")]
+    if fault::should_fail() {
+        // wait as though we were actually out of memory, until the next
+        // deallocation.
+        fault::WAKE_ON_DEALLOC.store(true, Release);
+        return None;
+    }
+
+    caller.attribute(|| unsafe {
+        match ticket {
+            Some(ticket) => RESERVATION.attempt(ticket, layout, free_bytes(), &Global),
+            None => RESERVATION.alloc(None, layout, free_bytes(), &Global),
+        }
+    })
+}
+
+/// Returns the amount of free heap space, in bytes, or `None` if the size of
+/// the heap is not known.
+fn free_bytes() -> Option<usize> {
+    match HEAP_TOTAL.load(Acquire) {
+        0 => None,
+        total => Some(total.saturating_sub(ALLOCATED.load(Acquire))),
+    }
+}
+
+/// Immediately allocate with the given [Layout], returning `None` if the
+/// allocation does not succeed.
+///
+/// This is used by the fallible constructors of the types in
+/// [crate::containers], rather than calling [alloc::alloc::alloc()]
+/// directly, so that they are subject to [fault injection](fault) and
+/// [tagged](tags) with their caller. Like [`alloc()`], this does not
+/// allocate space [reserved](MnemosAlloc#fairness) for a waiting task.
+///
+/// # Safety
+///
+/// This has the same safety invariants as [alloc::alloc::alloc()].
+#[inline(always)]
+pub(crate) unsafe fn try_alloc(layout: Layout, caller: Caller) -> Option<NonNull<u8>> {
+    #[cfg(feature = "fault-injection")]
+    if fault::should_fail() {
+        return None;
+    }
+
+    caller.attribute(|| RESERVATION.alloc(None, layout, free_bytes(), &Global))
+}
+
+// === impl Reservation ===
+
+impl Reservation {
+    const NONE: usize = usize::MAX;
+
+    /// The number of failed attempts after which a task gives up the
+    /// reservation.
+    const MAX_MISSES: usize = 8;
+
+    const fn new() -> Self {
+        Self {
+            ticket: AtomicUsize::new(Self::NONE),
+            size: AtomicUsize::new(0),
+            align: AtomicUsize::new(1),
+        }
+    }
+
+    /// Returns `true` if a task holds the reservation.
+    #[inline]
+    fn is_held(&self) -> bool {
+        self.ticket.load(Acquire) != Self::NONE
+    }
+
+    /// Takes the reservation for `ticket`, if no older task holds it.
+    fn claim(&self, ticket: &Ticket, layout: Layout) {
+        let mut holder = self.ticket.load(Acquire);
+        while ticket.number < holder {
+            match self
+                .ticket
+                .compare_exchange_weak(holder, ticket.number, AcqRel, Acquire)
+            {
+                Ok(_) => {
+                    self.size.store(layout.size(), Release);
+                    self.align.store(layout.align(), Release);
+                    return;
                 }
+                Err(actual) => holder = actual,
+            }
+        }
+    }
+
+    /// Tries to allocate `layout` from `heap` for the waiting task with
+    /// `ticket`, claiming the reservation for it if no older task holds it.
+    ///
+    /// A task gives up the reservation for good once its allocation has
+    /// failed [`Self::MAX_MISSES`] times while holding it. Otherwise, an
+    /// allocation which can't be made while other allocations are live
+    /// would hold up every other task forever.
+    ///
+    /// # Safety
+    ///
+    /// This has the same safety invariants as [`UnderlyingAllocator::alloc`].
+    unsafe fn attempt(
+        &self,
+        ticket: &mut Ticket,
+        layout: Layout,
+        free: Option<usize>,
+        heap: &impl UnderlyingAllocator,
+    ) -> Option<NonNull<u8>> {
+        // without a known heap size, there's no way to tell whether space is
+        // left for the reservation, so don't reserve any.
+        if free.is_some() && ticket.misses < Self::MAX_MISSES {
+            self.claim(ticket, layout);
+        }
+        let ptr = self.alloc(Some(ticket), layout, free, heap);
+        if ptr.is_none() && self.ticket.load(Acquire) == ticket.number {
+            ticket.misses += 1;
+            if ticket.misses >= Self::MAX_MISSES {
+                self.release(ticket);
+            }
+        }
+        ptr
+    }
+
+    /// Allocates `layout` from `heap` for the task with `ticket` (or a task
+    /// that isn't waiting, if `None`), unless that would leave no room for
+    /// the holder of the reservation.
+    ///
+    /// `free` is the amount of free space in `heap`, in bytes. If it is
+    /// `None`, nothing is reserved.
+    ///
+    /// # Safety
+    ///
+    /// This has the same safety invariants as [`UnderlyingAllocator::alloc`].
+    unsafe fn alloc(
+        &self,
+        ticket: Option<&Ticket>,
+        layout: Layout,
+        free: Option<usize>,
+        heap: &impl UnderlyingAllocator,
+    ) -> Option<NonNull<u8>> {
+        let holder = self.ticket.load(Acquire);
+        let unreserved = holder == Self::NONE || ticket.map(|t| t.number) == Some(holder);
+        let Some(free) = free.filter(|_| !unreserved) else {
+            return NonNull::new(heap.alloc(layout));
+        };
+
+        // someone else holds the reservation, so only allocate if there will
+        // still be room for them afterwards.
+        let reserved = Layout::from_size_align(self.size.load(Acquire), self.align.load(Acquire))
+            .unwrap_or_else(|_| Layout::new::<u8>());
+        if free.saturating_sub(layout.size()) < reserved.size() {
+            return None;
+        }
+
+        // even if there's enough free space in total, it may be fragmented.
+        // the only way to find out whether the reserved allocation still fits
+        // is to try making it. if it doesn't fit, freeing our allocation
+        // again also ends the OOM state this puts the heap in.
+        let ptr = NonNull::new(heap.alloc(layout))?;
+        match NonNull::new(heap.alloc(reserved)) {
+            Some(probe) => {
+                heap.dealloc(probe.as_ptr(), reserved);
+                Some(ptr)
+            }
+            None => {
+                heap.dealloc(ptr.as_ptr(), layout);
+                None
             }
         }
     }
+
+    /// Gives up the reservation, if `ticket` holds it, and wakes the other
+    /// waiting tasks so that the next oldest can claim it.
+    fn release(&self, ticket: &Ticket) {
+        if self
+            .ticket
+            .compare_exchange(ticket.number, Self::NONE, AcqRel, Acquire)
+            .is_ok()
+        {
+            self.size.store(0, Release);
+            self.align.store(1, Release);
+            OOM_WAITER.wake_all();
+        }
+    }
+}
+
+impl Ticket {
+    fn new(number: usize) -> Self {
+        Self { number, misses: 0 }
+    }
+}
+
+impl Drop for Ticket {
+    fn drop(&mut self) {
+        RESERVATION.release(self);
+    }
+}
+
+// === impl Global ===
+
+impl UnderlyingAllocator for Global {
+    const INIT: Self = Global;
+
+    unsafe fn init(&self, _start: NonNull<u8>, _len: usize) {
+        panic!("Don't initialize the global allocator through the reservation.");
+    }
+
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        alloc::alloc::alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        alloc::alloc::dealloc(ptr, layout)
+    }
+}
+
+/// The location of the code which requested an allocation.
+///
+/// This is captured by `#[track_caller]` functions which allocate, so that
+/// allocations can be [tagged](tags) with their caller. When the "tags"
+/// feature is disabled, this is a zero-sized type which does nothing.
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct Caller {
+    #[cfg(feature = "tags")]
+    location: &'static core::panic::Location<'static>,
+}
+
+impl Caller {
+    /// Returns the location of the caller of the current `#[track_caller]`
+    /// function.
+    #[track_caller]
+    #[inline(always)]
+    pub(crate) fn get() -> Self {
+        Self {
+            #[cfg(feature = "tags")]
+            location: core::panic::Location::caller(),
+        }
+    }
+
+    /// Runs `f`, attributing any allocations it makes to this caller.
+    #[cfg(feature = "tags")]
+    #[inline(always)]
+    pub(crate) fn attribute<R>(self, f: impl FnOnce() -> R) -> R {
+        tags::with_location(self.location, f)
+    }
+
+    /// Runs `f`, attributing any allocations it makes to this caller.
+    #[cfg(not(feature = "tags"))]
+    #[inline(always)]
+    pub(crate) fn attribute<R>(self, f: impl FnOnce() -> R) -> R {
+        f()
+    }
 }
 
 /// Immediately deallocate the given ptr + [Layout]
@@ -344,6 +707,421 @@ impl UnderlyingAllocator for std::alloc::System {
     }
 }
 
+/// Injecting allocation failures, for testing.
+///
+/// When the "fault-injection" feature of this crate is enabled, allocations
+/// made through the async-aware types in [crate::containers] can be made to
+/// fail at a configurable rate, as though the heap were out of memory. Async
+/// allocations which fail wait until the next deallocation before trying
+/// again, and fallible allocations return an error. This allows testing how
+/// the system behaves under heap pressure, without actually exhausting the
+/// heap.
+///
+/// Allocations which do not go through [crate::containers] (such as those
+/// made by the standard library when running in a simulator) are never
+/// failed, since they typically cannot handle allocation failures.
+#[cfg(feature = "fault-injection")]
+pub mod fault {
+    use portable_atomic::{AtomicBool, AtomicU32, AtomicU8, AtomicUsize, Ordering::*};
+
+    /// The percentage of allocations which should fail.
+    static FAILURE_RATE: AtomicU8 = AtomicU8::new(0);
+
+    /// The number of allocations which have been failed.
+    static INJECTED: AtomicUsize = AtomicUsize::new(0);
+
+    /// State of the xorshift PRNG used to decide which allocations fail.
+    static RNG: AtomicU32 = AtomicU32::new(0x9E37_79B9);
+
+    /// Set when an async allocation has been failed, so that the next
+    /// deallocation wakes the failed task.
+    pub(super) static WAKE_ON_DEALLOC: AtomicBool = AtomicBool::new(false);
+
+    /// Sets the percentage of allocations which will fail.
+    ///
+    /// Values greater than 100 are treated as 100. A rate of 0 disables fault
+    /// injection.
+    pub fn set_failure_rate(percent: u8) {
+        FAILURE_RATE.store(percent.min(100), Release);
+    }
+
+    /// Returns the percentage of allocations which will fail.
+    #[must_use]
+    pub fn failure_rate() -> u8 {
+        FAILURE_RATE.load(Acquire)
+    }
+
+    /// Returns the number of allocations which have been failed by fault
+    /// injection.
+    #[must_use]
+    pub fn injected_failures() -> usize {
+        INJECTED.load(Acquire)
+    }
+
+    /// Seeds the random number generator used to decide which allocations
+    /// fail, so that a sequence of failures can be reproduced.
+    pub fn seed(seed: u32) {
+        // xorshift gets stuck at zero.
+        RNG.store(seed.max(1), Release);
+    }
+
+    pub(super) fn should_fail() -> bool {
+        let rate = FAILURE_RATE.load(Relaxed);
+        if rate == 0 {
+            return false;
+        }
+
+        let mut x = RNG.load(Relaxed);
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        RNG.store(x, Relaxed);
+
+        let fail = x % 100 < rate as u32;
+        if fail {
+            INJECTED.fetch_add(1, Release);
+        }
+        fail
+    }
+}
+
+/// Allocation tagging, for attributing heap usage and finding leaks.
+///
+/// When the "tags" feature of this crate is enabled, [`MnemosAlloc`] records
+/// live allocations in a fixed-size side table, along with a [`Tag`]
+/// describing where they came from:
+///
+/// - The **location** of the code which allocated it. This is recorded for
+///   allocations made through the types in [`crate::containers`] and
+///   [`heap::alloc`](crate::heap::alloc()), which use `#[track_caller]` to
+///   find their caller. Other allocations (such as those made by
+///   `alloc::vec::Vec`) have no location.
+/// - The name of the enclosing **scope**, if the allocation was made while
+///   polling a future wrapped with [`scope`]. This allows attributing
+///   allocations to a particular service or task.
+///
+/// Allocations with neither a location nor a scope cannot be attributed to
+/// anything, and are not recorded. In particular, this means that allocations
+/// made by the standard library when running in a simulator are ignored,
+/// unless they are made within a scope.
+///
+/// A snapshot of the live allocations for each tag can be taken with
+/// [`snapshot`]. Comparing snapshots taken over time shows which tags are
+/// responsible for slow heap growth.
+///
+/// Because the side tables are statically sized, at most [`MAX_TRACKED`]
+/// allocations and [`MAX_TAGS`] distinct tags can be tracked. Allocations
+/// which don't fit are not tracked, and are counted by [`untracked`]. The
+/// tables are protected by spinlocks; an allocation made while they are
+/// locked (such as by an interrupt handler) is also not tracked.
+///
+/// Since this adds overhead to every allocation and deallocation, it is
+/// intended for use in debug builds.
+///
+/// [`MnemosAlloc`]: crate::heap::MnemosAlloc
+#[cfg(feature = "tags")]
+pub mod tags {
+    use core::{
+        future::Future,
+        panic::Location,
+        pin::Pin,
+        task::{Context, Poll},
+    };
+
+    use maitake::sync::{blocking::Mutex, spin::Spinlock};
+    use portable_atomic::{AtomicUsize, Ordering::*};
+
+    use crate::containers::FixedVec;
+
+    /// The maximum number of distinct [`Tag`]s which can be tracked.
+    pub const MAX_TAGS: usize = 128;
+
+    /// The maximum number of live allocations which can be tracked.
+    pub const MAX_TRACKED: usize = 4096;
+
+    /// Describes where an allocation came from.
+    #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+    pub struct Tag {
+        /// The location of the code which made the allocation, if it is known.
+        pub location: Option<&'static Location<'static>>,
+        /// The name of the [`scope`] the allocation was made in, if any.
+        pub scope: Option<&'static str>,
+    }
+
+    /// The live allocations with a particular [`Tag`].
+    #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+    pub struct TagStats {
+        /// The tag.
+        pub tag: Tag,
+        /// The number of live allocations with this tag.
+        pub live_count: usize,
+        /// The total size of the live allocations with this tag, in bytes.
+        pub live_bytes: usize,
+    }
+
+    /// A future which attributes allocations made while it is polled to a
+    /// [`scope`].
+    #[must_use = "futures do nothing unless `.await`ed or polled"]
+    pub struct Scoped<F> {
+        name: Option<&'static str>,
+        future: F,
+    }
+
+    #[derive(Copy, Clone)]
+    struct Entry {
+        /// The address of the allocation, or 0 if this entry is empty.
+        addr: usize,
+        size: usize,
+        tag: u16,
+    }
+
+    struct Table {
+        /// An open-addressed hash table of live allocations, keyed by address.
+        entries: [Entry; MAX_TRACKED],
+        tags: heapless::Vec<TagStats, MAX_TAGS>,
+    }
+
+    static TABLE: Mutex<Table, Spinlock> = Mutex::new_with_raw_mutex(
+        Table {
+            entries: [Entry::EMPTY; MAX_TRACKED],
+            tags: heapless::Vec::new(),
+        },
+        Spinlock::new(),
+    );
+
+    /// The tag for allocations made right now.
+    static CURRENT: Mutex<Tag, Spinlock> = Mutex::new_with_raw_mutex(Tag::NONE, Spinlock::new());
+
+    static UNTRACKED: AtomicUsize = AtomicUsize::new(0);
+
+    /// Attributes all allocations made while polling `future` to the scope
+    /// `name`.
+    ///
+    /// Scopes may be nested, in which case the innermost scope's name is used.
+    pub fn scope<F: Future>(name: &'static str, future: F) -> Scoped<F> {
+        Scoped {
+            name: Some(name),
+            future,
+        }
+    }
+
+    /// Attributes all allocations made while polling `future` to the scope
+    /// which is current when `inherit` is called, if there is one.
+    ///
+    /// This is used when spawning a task, so that allocations made by the
+    /// task are attributed to the scope which spawned it, rather than to
+    /// whichever scope happens to be polling it.
+    pub fn inherit<F: Future>(future: F) -> Scoped<F> {
+        Scoped {
+            name: current_scope(),
+            future,
+        }
+    }
+
+    /// Returns the name of the current [`scope`], if any.
+    #[must_use]
+    pub fn current_scope() -> Option<&'static str> {
+        CURRENT.lock().scope
+    }
+
+    /// Returns the total size of the live allocations made in the [`scope`]
+    /// named `name`, in bytes.
+    ///
+    /// Allocations which could not be tracked (see [`untracked`]) are not
+    /// included.
+    #[must_use]
+    pub fn live_bytes(name: &str) -> usize {
+        TABLE
+            .lock()
+            .tags
+            .iter()
+            .filter(|stats| stats.tag.scope == Some(name))
+            .map(|stats| stats.live_bytes)
+            .sum()
+    }
+
+    /// Replaces the contents of `buf` with the stats for each [`Tag`] which
+    /// has live allocations, returning the number of such tags.
+    ///
+    /// If `buf`'s capacity is less than the number of tags with live
+    /// allocations, the remaining tags are left out. A capacity of
+    /// [`MAX_TAGS`] is always sufficient.
+    pub fn snapshot(buf: &mut FixedVec<TagStats>) -> usize {
+        buf.clear();
+        let table = TABLE.lock();
+        let mut live = 0;
+        for stats in table.tags.iter().filter(|stats| stats.live_count > 0) {
+            let _ = buf.try_push(*stats);
+            live += 1;
+        }
+        live
+    }
+
+    /// Returns the number of allocations which could not be tracked.
+    #[must_use]
+    pub fn untracked() -> usize {
+        UNTRACKED.load(Acquire)
+    }
+
+    /// Runs `f`, attributing any allocations it makes to `location`.
+    pub(crate) fn with_location<R>(
+        location: &'static Location<'static>,
+        f: impl FnOnce() -> R,
+    ) -> R {
+        let prev = CURRENT.lock().location.replace(location);
+        let ret = f();
+        CURRENT.lock().location = prev;
+        ret
+    }
+
+    /// Records a new allocation.
+    pub(crate) fn record(ptr: *mut u8, size: usize) {
+        let Some(tag) = CURRENT.try_lock().map(|tag| *tag) else {
+            UNTRACKED.fetch_add(1, Relaxed);
+            return;
+        };
+        if tag == Tag::NONE {
+            return;
+        }
+        let tracked = TABLE
+            .try_lock()
+            .map_or(false, |mut table| table.insert(ptr as usize, size, tag));
+        if !tracked {
+            UNTRACKED.fetch_add(1, Relaxed);
+        }
+    }
+
+    /// Records that an allocation has been freed.
+    pub(crate) fn forget(ptr: *mut u8) {
+        // if the table is locked, this allocation will appear to live forever.
+        // that's unfortunate, but better than deadlocking.
+        if let Some(mut table) = TABLE.try_lock() {
+            table.remove(ptr as usize);
+        }
+    }
+
+    // === impl Tag ===
+
+    impl Tag {
+        const NONE: Self = Self {
+            location: None,
+            scope: None,
+        };
+    }
+
+    // === impl Scoped ===
+
+    impl<F: Future> Future for Scoped<F> {
+        type Output = F::Output;
+
+        fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+            // Safety: we never move `future` out of `self`.
+            let this = unsafe { self.get_unchecked_mut() };
+            let future = unsafe { Pin::new_unchecked(&mut this.future) };
+
+            let Some(name) = this.name else {
+                return future.poll(cx);
+            };
+            let prev = CURRENT.lock().scope.replace(name);
+            let poll = future.poll(cx);
+            CURRENT.lock().scope = prev;
+            poll
+        }
+    }
+
+    // === impl Table ===
+
+    impl Table {
+        const MASK: usize = MAX_TRACKED - 1;
+
+        fn home(addr: usize) -> usize {
+            // Fibonacci hashing. allocations are at least word-aligned, so the low
+            // bits are discarded first.
+            ((addr >> 3).wrapping_mul(0x9E37_79B9)) & Self::MASK
+        }
+
+        fn insert(&mut self, addr: usize, size: usize, tag: Tag) -> bool {
+            let idx = match self.tags.iter().position(|stats| stats.tag == tag) {
+                Some(idx) => idx,
+                None => {
+                    let stats = TagStats {
+                        tag,
+                        live_count: 0,
+                        live_bytes: 0,
+                    };
+                    if self.tags.push(stats).is_err() {
+                        return false;
+                    }
+                    self.tags.len() - 1
+                }
+            };
+
+            let mut i = Self::home(addr);
+            for _ in 0..MAX_TRACKED {
+                let entry = &mut self.entries[i];
+                if entry.addr == 0 {
+                    *entry = Entry {
+                        addr,
+                        size,
+                        tag: idx as u16,
+                    };
+                    let stats = &mut self.tags[idx];
+                    stats.live_count += 1;
+                    stats.live_bytes += size;
+                    return true;
+                }
+                i = (i + 1) & Self::MASK;
+            }
+
+            false
+        }
+
+        fn remove(&mut self, addr: usize) {
+            let mut hole = Self::home(addr);
+            loop {
+                match self.entries[hole].addr {
+                    // not tracked.
+                    0 => return,
+                    a if a == addr => break,
+                    _ => hole = (hole + 1) & Self::MASK,
+                }
+            }
+
+            let Entry { size, tag, .. } = self.entries[hole];
+            let stats = &mut self.tags[tag as usize];
+            stats.live_count -= 1;
+            stats.live_bytes -= size;
+
+            // shift back any entries which were displaced past the removed entry,
+            // so that lookups don't stop at the new hole.
+            let mut j = hole;
+            loop {
+                j = (j + 1) & Self::MASK;
+                let addr = self.entries[j].addr;
+                if addr == 0 {
+                    break;
+                }
+                let home = Self::home(addr);
+                if (j.wrapping_sub(home) & Self::MASK) >= (j.wrapping_sub(hole) & Self::MASK) {
+                    self.entries[hole] = self.entries[j];
+                    hole = j;
+                }
+            }
+            self.entries[hole] = Entry::EMPTY;
+        }
+    }
+
+    // === impl Entry ===
+
+    impl Entry {
+        const EMPTY: Self = Self {
+            addr: 0,
+            size: 0,
+            tag: 0,
+        };
+    }
+}
+
 #[cfg(feature = "stats")]
 mod stats {
     use super::*;
@@ -550,3 +1328,135 @@ mod stats {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEAP_SIZE: usize = 256;
+
+    #[repr(align(16))]
+    struct Memory([u8; HEAP_SIZE]);
+
+    /// Runs `f` with a fresh heap, and a reservation of `reserved` held by
+    /// ticket 0.
+    fn with_heap(
+        reserved: Layout,
+        f: impl FnOnce(&Reservation, &SingleThreadedLinkedListAllocator),
+    ) {
+        let mut memory = Memory([0; HEAP_SIZE]);
+        let heap = SingleThreadedLinkedListAllocator::INIT;
+        unsafe { heap.init(NonNull::new(memory.0.as_mut_ptr()).unwrap(), HEAP_SIZE) };
+        let reservation = Reservation::new();
+        let holder = Ticket::new(0);
+        reservation.claim(&holder, reserved);
+        f(&reservation, &heap);
+    }
+
+    fn layout(size: usize) -> Layout {
+        Layout::from_size_align(size, 16).unwrap()
+    }
+
+    #[test]
+    fn holder_admitted() {
+        with_heap(layout(128), |reservation, heap| unsafe {
+            let holder = Ticket::new(0);
+            assert!(reservation
+                .alloc(Some(&holder), layout(128), Some(HEAP_SIZE), heap)
+                .is_some());
+        });
+    }
+
+    #[test]
+    fn leaves_room() {
+        with_heap(layout(128), |reservation, heap| unsafe {
+            let waiter = Ticket::new(1);
+            assert!(reservation
+                .alloc(None, layout(64), Some(HEAP_SIZE), heap)
+                .is_some());
+            assert!(reservation
+                .alloc(Some(&waiter), layout(64), Some(HEAP_SIZE - 64), heap)
+                .is_some());
+            // this would eat into the reserved space.
+            assert!(reservation
+                .alloc(None, layout(16), Some(HEAP_SIZE - 128), heap)
+                .is_none());
+            assert!(reservation
+                .alloc(
+                    Some(&Ticket::new(0)),
+                    layout(128),
+                    Some(HEAP_SIZE - 128),
+                    heap
+                )
+                .is_some());
+        });
+    }
+
+    #[test]
+    fn fragmented() {
+        with_heap(layout(96), |reservation, heap| unsafe {
+            // leave two holes, of 96 and 32 bytes.
+            let a = heap.alloc(layout(96));
+            let _b = heap.alloc(layout(32));
+            let c = heap.alloc(layout(32));
+            let _d = heap.alloc(layout(96));
+            heap.dealloc(a, layout(96));
+            heap.dealloc(c, layout(32));
+
+            // there are 128 free bytes, but a 32-byte allocation would split
+            // the only hole the reservation fits in.
+            assert!(reservation
+                .alloc(None, layout(32), Some(128), heap)
+                .is_none());
+            // the rejected allocation was given back.
+            assert!(reservation
+                .alloc(Some(&Ticket::new(0)), layout(96), Some(128), heap)
+                .is_some());
+        });
+    }
+
+    #[test]
+    fn stuck_holder() {
+        with_heap(layout(192), |reservation, heap| unsafe {
+            // while this stays allocated, the holder's allocation can't be
+            // made.
+            let _long_lived = heap.alloc(layout(128));
+            let mut holder = Ticket::new(0);
+            let mut waiter = Ticket::new(1);
+
+            for _ in 0..Reservation::MAX_MISSES {
+                assert!(reservation
+                    .alloc(None, layout(16), Some(128), heap)
+                    .is_none());
+                assert!(reservation
+                    .attempt(&mut waiter, layout(16), Some(128), heap)
+                    .is_none());
+                assert!(reservation
+                    .attempt(&mut holder, layout(192), Some(128), heap)
+                    .is_none());
+            }
+
+            // the holder gave up the reservation, so the small allocations
+            // are no longer held up behind it.
+            assert!(!reservation.is_held());
+            assert!(reservation
+                .alloc(None, layout(16), Some(128), heap)
+                .is_some());
+            assert!(reservation
+                .attempt(&mut holder, layout(192), Some(112), heap)
+                .is_none());
+            assert!(reservation
+                .attempt(&mut waiter, layout(16), Some(112), heap)
+                .is_some());
+        });
+    }
+
+    #[test]
+    fn unaccounted() {
+        // without a known heap size, nothing is reserved, so allocations
+        // aren't held up behind the reservation.
+        with_heap(layout(HEAP_SIZE), |reservation, heap| unsafe {
+            assert!(reservation.alloc(None, layout(64), None, heap).is_some());
+        });
+    }
+}
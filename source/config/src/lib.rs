@@ -94,14 +94,21 @@ pub mod buildtime {
     use miette::{Context, IntoDiagnostic, Result};
     use serde::de::DeserializeOwned;
 
-    fn from_toml<Platform>(s: &str) -> Result<MnemosConfig<Platform>>
+    /// Parse a configuration from a TOML string.
+    ///
+    /// This is how configuration files are parsed by [`render_file`] and
+    /// [`render_all`], so host tools may use it to check that a file will
+    /// render.
+    pub fn from_toml<Platform>(s: &str) -> Result<MnemosConfig<Platform>>
     where
         Platform: DeserializeOwned + 'static,
     {
         toml::from_str(s).into_diagnostic()
     }
 
-    fn to_postcard<Platform>(mc: &MnemosConfig<Platform>) -> Result<Vec<u8>>
+    /// Serialize a configuration to the postcard format loaded by
+    /// [`include_config!()`].
+    pub fn to_postcard<Platform>(mc: &MnemosConfig<Platform>) -> Result<Vec<u8>>
     where
         Platform: Serialize,
     {
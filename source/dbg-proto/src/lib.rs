@@ -0,0 +1,382 @@
+//! # mnemos-dbg-proto
+//!
+//! Wire types used by the kernel's read-only debug agent. Extracted as a
+//! separate crate to allow host tools (like `mnemos-dbg`) to share protocol
+//! definitions.
+//!
+//! The debug agent listens on the `WellKnown::Debug` SerMux port. Each
+//! message in either direction is [postcard] encoded, and then COBS encoded
+//! with a terminating zero byte.
+//!
+//! The host sends a single [`Request`], and the target answers with zero or
+//! more [`Response`] items, followed by either [`Response::Done`] or
+//! [`Response::Unsupported`]. The host should wait for the end of one answer
+//! before sending the next request.
+//!
+//! [postcard]: https://docs.rs/postcard
+
+#![cfg_attr(not(any(test, feature = "std")), no_std)]
+
+use core::fmt;
+
+/// The maximum size of a single encoded [`Request`] or [`Response`],
+/// including COBS overhead and the terminating zero.
+pub const MAX_FRAME_SIZE: usize = 256;
+
+/// The maximum length of the file path in an [`AllocLocation`].
+///
+/// Longer paths are truncated by the debug agent, keeping the *end* of the
+/// path, so that the file name is preserved.
+pub const MAX_FILE_LEN: usize = 128;
+
+/// The maximum length of the scope name in an [`AllocTagEntry`].
+///
+/// Longer names are truncated by the debug agent.
+pub const MAX_SCOPE_LEN: usize = 48;
+
+/// Requests sent from a host to the debug agent.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum Request {
+    /// List every service in the driver registry, as [`Response::Service`]s.
+    Registry,
+    /// Report heap statistics, as a single [`Response::Heap`].
+    Heap,
+    /// Report scheduler task counts, as a single [`Response::Tasks`], followed
+    /// by the state of every named boot step, as [`Response::BootStep`]s.
+    ///
+    /// Each `BootStep` is followed by a [`Response::BootDependency`] for each
+    /// of the steps it depends on, so that the host can reconstruct the boot
+    /// dependency graph.
+    Tasks,
+    /// Report how full every registered service's connection queue is, as
+    /// [`Response::Channel`]s.
+    ///
+    /// Each `Channel` is followed by a [`Response::RequestQueue`] for the
+    /// same service, if the service serves all of its clients from one
+    /// request queue.
+    Channels,
+    /// Report the heap's live allocations, grouped by where they were
+    /// allocated, as [`Response::AllocTag`]s, followed by a single
+    /// [`Response::UntrackedAllocs`].
+    ///
+    /// This is only supported if the kernel was built with allocation
+    /// tagging enabled.
+    Allocations,
+    /// Report the devices found by the kernel's boot-time I²C bus scan, as
+    /// [`Response::I2cDevice`]s.
+    ///
+    /// This is only supported once a scan has completed.
+    I2cDevices,
+    /// Report how the kernel is polling userspace processes' rings, as a
+    /// single [`Response::Rings`], followed by a [`Response::Process`] for
+    /// each registered process.
+    Rings,
+    /// Report how full the SerMux's buffers are, as a single
+    /// [`Response::SermuxOutgoing`], followed by a [`Response::SermuxPort`]
+    /// for each open port.
+    Ports,
+}
+
+/// Responses sent from the debug agent to the host.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum Response<'a> {
+    Service(ServiceEntry),
+    Heap(HeapStats),
+    Tasks(TaskCounts),
+    BootStep {
+        name: &'a str,
+        #[serde(borrow)]
+        state: StepState<'a>,
+    },
+    Channel(ChannelEntry),
+    AllocTag(#[serde(borrow)] AllocTagEntry<'a>),
+    UntrackedAllocs {
+        /// The number of allocations which could not be tracked, because the
+        /// target's tracking tables were full or busy.
+        count: usize,
+    },
+    I2cDevice {
+        /// The device's 7-bit address.
+        addr: u8,
+    },
+    Rings(RingStats),
+    Process(ProcessEntry),
+    /// The requested information is not available on this target. This ends
+    /// the answer to a request.
+    Unsupported,
+    /// Ends the answer to a request.
+    Done,
+    // Variants below this point were added after `Done`, and come after it so
+    // that older hosts, which don't know about them, still decode `Done`
+    // correctly.
+    /// The preceding [`Response::BootStep`] named `step` depends on the step
+    /// named `dependency`.
+    BootDependency {
+        step: &'a str,
+        dependency: &'a str,
+    },
+    /// How full the preceding [`Response::Channel`]'s service's request queue
+    /// is.
+    RequestQueue(ChannelEntry),
+    /// How full the buffer of data waiting to be sent to the host is.
+    SermuxOutgoing(BufferEntry),
+    /// How full a SerMux port's buffer of data received from the host is.
+    SermuxPort {
+        port: u16,
+        incoming: BufferEntry,
+    },
+}
+
+/// A service in the driver registry.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct ServiceEntry {
+    /// The service's UUID, in big-endian byte order.
+    pub uuid: Uuid,
+    /// The service's registry-assigned ID.
+    pub service_id: u32,
+    /// Whether the service may be connected to from userspace.
+    pub userspace: bool,
+}
+
+/// How full a channel is.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct ChannelEntry {
+    /// The UUID of the service that owns the channel.
+    pub uuid: Uuid,
+    /// The number of items currently in the channel.
+    pub len: usize,
+    /// The maximum number of items the channel can hold.
+    pub capacity: usize,
+    /// The most items the channel has held at once.
+    pub high_water: usize,
+    /// The number of times a sender found the channel full.
+    pub overflows: usize,
+}
+
+/// How full a byte buffer is.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct BufferEntry {
+    /// The number of bytes which have been written, but not yet read.
+    pub committed: usize,
+    /// The size of the buffer, in bytes.
+    pub capacity: usize,
+}
+
+/// The live allocations made at a particular location, in a particular
+/// scope.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct AllocTagEntry<'a> {
+    /// The location of the code which made the allocations, if it is known.
+    #[serde(borrow)]
+    pub location: Option<AllocLocation<'a>>,
+    /// The name of the scope (such as a boot step) the allocations were made
+    /// in, if any.
+    #[serde(borrow)]
+    pub scope: Option<&'a str>,
+    /// The number of live allocations.
+    pub live_count: usize,
+    /// The total size of the live allocations, in bytes.
+    pub live_bytes: usize,
+}
+
+/// A location in the target's source code.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct AllocLocation<'a> {
+    /// The source file's path, truncated to at most [`MAX_FILE_LEN`] bytes.
+    pub file: &'a str,
+    pub line: u32,
+    pub column: u32,
+}
+
+/// Counters for the kernel's polling of userspace rings.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct RingStats {
+    /// The maximum number of requests read from all rings per tick.
+    pub budget: usize,
+    /// The number of times the rings have been polled.
+    pub polls: usize,
+    /// The number of polls which used up the whole budget while requests
+    /// were still waiting.
+    pub exhausted: usize,
+}
+
+/// Counters for a single userspace process's rings.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct ProcessEntry {
+    /// The process's kernel-assigned ID.
+    pub id: u32,
+    /// The number of requests read from the process's ring.
+    pub requests: usize,
+    /// The number of malformed requests which were discarded.
+    pub malformed: usize,
+    /// The number of times the process's ring was skipped because the
+    /// kernel had a backlog of its requests.
+    pub backpressured: usize,
+}
+
+/// A snapshot of the kernel heap's statistics.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct HeapStats {
+    /// The total size of the heap, in bytes.
+    pub total_bytes: usize,
+    /// The amount of memory currently allocated, in bytes.
+    pub allocated_bytes: usize,
+    /// The number of successful allocations over the lifetime of the heap.
+    pub alloc_success_count: usize,
+    /// The number of allocations which failed due to insufficient space.
+    pub alloc_oom_count: usize,
+    /// The number of deallocations over the lifetime of the heap.
+    pub dealloc_count: usize,
+}
+
+/// Counts of tasks spawned on and completed by the kernel's scheduler.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct TaskCounts {
+    /// The number of tasks spawned since boot.
+    pub spawned: usize,
+    /// The number of tasks that have run to completion since boot.
+    pub completed: usize,
+}
+
+/// The state of a named boot step.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum StepState<'a> {
+    Pending,
+    Running {
+        /// Time since the step started, in microseconds.
+        elapsed_us: u64,
+    },
+    Complete {
+        /// Time the step took to complete, in microseconds.
+        elapsed_us: u64,
+    },
+    Failed {
+        /// Time the step ran before failing, in microseconds.
+        elapsed_us: u64,
+    },
+    Blocked {
+        /// The name of the failed dependency.
+        dependency: &'a str,
+    },
+}
+
+/// A UUID, in big-endian byte order.
+///
+/// This is a newtype rather than a `uuid::Uuid` so that this crate does not
+/// depend on a particular version of the `uuid` crate.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub struct Uuid(pub [u8; 16]);
+
+// === impl HeapStats ===
+
+impl HeapStats {
+    /// Returns the amount of free space in the heap, in bytes.
+    #[must_use]
+    pub fn free_bytes(&self) -> usize {
+        self.total_bytes.saturating_sub(self.allocated_bytes)
+    }
+
+    /// Returns the number of currently live allocations.
+    #[must_use]
+    pub fn live_alloc_count(&self) -> usize {
+        self.alloc_success_count.saturating_sub(self.dealloc_count)
+    }
+}
+
+// === impl TaskCounts ===
+
+impl TaskCounts {
+    /// Returns the number of tasks which have been spawned, but have not yet
+    /// completed.
+    #[must_use]
+    pub fn live(&self) -> usize {
+        self.spawned.saturating_sub(self.completed)
+    }
+}
+
+// === impl Uuid ===
+
+impl fmt::Display for Uuid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, byte) in self.0.iter().enumerate() {
+            if matches!(i, 4 | 6 | 8 | 10) {
+                f.write_str("-")?;
+            }
+            write!(f, "{byte:02x}")?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Uuid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Uuid({self})")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uuid_display() {
+        let uuid = Uuid([
+            0x54, 0xc9, 0x83, 0xfa, 0x73, 0x6f, 0x42, 0x23, 0xb9, 0x0d, 0xc4, 0x36, 0x0a, 0x30,
+            0x86, 0x47,
+        ]);
+        assert_eq!(uuid.to_string(), "54c983fa-736f-4223-b90d-c4360a308647");
+    }
+
+    #[test]
+    fn responses_fit_in_a_frame() {
+        let longest = Response::BootStep {
+            name: "a_reasonably_long_boot_step_name",
+            state: StepState::Blocked {
+                dependency: "another_reasonably_long_boot_step_name",
+            },
+        };
+        let mut buf = [0u8; MAX_FRAME_SIZE];
+        let used = postcard::to_slice_cobs(&longest, &mut buf).unwrap();
+        let decoded: Response<'_> = postcard::from_bytes_cobs(used).unwrap();
+        assert_eq!(decoded, longest);
+
+        let longest = Response::BootDependency {
+            step: "a_reasonably_long_boot_step_name",
+            dependency: "another_reasonably_long_boot_step_name",
+        };
+        let used = postcard::to_slice_cobs(&longest, &mut buf).unwrap();
+        let decoded: Response<'_> = postcard::from_bytes_cobs(used).unwrap();
+        assert_eq!(decoded, longest);
+
+        let longest = Response::SermuxPort {
+            port: u16::MAX,
+            incoming: BufferEntry {
+                committed: usize::MAX,
+                capacity: usize::MAX,
+            },
+        };
+        let used = postcard::to_slice_cobs(&longest, &mut buf).unwrap();
+        let decoded: Response<'_> = postcard::from_bytes_cobs(used).unwrap();
+        assert_eq!(decoded, longest);
+    }
+
+    #[test]
+    fn alloc_tags_fit_in_a_frame() {
+        let file = "f".repeat(MAX_FILE_LEN);
+        let scope = "s".repeat(MAX_SCOPE_LEN);
+        let longest = Response::AllocTag(AllocTagEntry {
+            location: Some(AllocLocation {
+                file: &file,
+                line: u32::MAX,
+                column: u32::MAX,
+            }),
+            scope: Some(&scope),
+            live_count: usize::MAX,
+            live_bytes: usize::MAX,
+        });
+        let mut buf = [0u8; MAX_FRAME_SIZE];
+        let used = postcard::to_slice_cobs(&longest, &mut buf).unwrap();
+        let decoded: Response<'_> = postcard::from_bytes_cobs(used).unwrap();
+        assert_eq!(decoded, longest);
+    }
+}
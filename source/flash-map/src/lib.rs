@@ -0,0 +1,375 @@
+//! # mnemos-flash-map
+//!
+//! The layout of mnemOS images on SPI flash. Extracted as a separate crate to
+//! allow host tools (like `mnemos-build`) to write the flash maps that the
+//! kernel reads.
+//!
+//! A bootable SPI flash image is laid out as follows:
+//!
+//! | Offset                  | Contents                                      |
+//! | ----------------------- | --------------------------------------------- |
+//! | 0                       | the first-stage bootloader (SPL)              |
+//! | [`OFFSET`]              | the [`FlashMap`], in a [`SECTOR_SIZE`] sector |
+//! | [`FlashMap::kernel`]    | the kernel, which the SPL loads and starts    |
+//! | [`FlashMap::config`]    | configuration, for the kernel to read         |
+//! | [`FlashMap::kv`]        | key-value storage, for the kernel to write    |
+//!
+//! The SPL is loaded by the boot ROM, and must fit in the space before the
+//! flash map. It loads the kernel from the start of the kernel region, and
+//! may read the flash map to find out how long it is. The kernel finds the
+//! config and key-value regions by reading the flash map.
+//!
+//! The flash map is encoded as [`ENCODED_LEN`] bytes of little-endian
+//! integers, starting with [`MAGIC`] and ending with a CRC-32 of the bytes
+//! before it, so that a kernel booted from a flash chip without a flash map
+//! can tell that there isn't one.
+
+#![cfg_attr(not(test), no_std)]
+
+use core::fmt;
+
+/// Where the flash map is written, in bytes from the start of the flash.
+///
+/// This is after the first 32 KiB of the flash, which is as large as an
+/// Allwinner D1's boot ROM will load an SPL.
+pub const OFFSET: u32 = 32 * 1024;
+
+/// The size of the flash's smallest erasable sector, in bytes.
+///
+/// Every region starts and ends on a sector boundary, so that each can be
+/// erased without erasing any other.
+pub const SECTOR_SIZE: u32 = 4 * 1024;
+
+/// The bytes that an encoded [`FlashMap`] starts with.
+pub const MAGIC: [u8; 8] = *b"MNEMOSFM";
+
+/// The version of the flash map encoding in this crate.
+pub const VERSION: u32 = 1;
+
+/// The length of an encoded [`FlashMap`], in bytes.
+pub const ENCODED_LEN: usize = 44;
+
+/// The regions of a mnemOS SPI flash image.
+///
+/// See the [crate-level documentation](crate) for details.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct FlashMap {
+    /// The region that the kernel is written to.
+    pub kernel: Region,
+    /// The length of the kernel image at the start of the kernel region.
+    pub kernel_len: u32,
+    /// The region that the kernel's configuration is written to.
+    pub config: Region,
+    /// The region that the kernel stores key-value data in.
+    pub kv: Region,
+}
+
+/// A region of flash, in bytes.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Region {
+    /// The offset of the start of the region from the start of the flash.
+    pub offset: u32,
+    /// The length of the region.
+    pub len: u32,
+}
+
+/// The kinds of region in a [`FlashMap`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RegionKind {
+    /// [`FlashMap::kernel`].
+    Kernel,
+    /// [`FlashMap::config`].
+    Config,
+    /// [`FlashMap::kv`].
+    Kv,
+}
+
+/// Errors returned when decoding or validating a [`FlashMap`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// Fewer than [`ENCODED_LEN`] bytes were decoded.
+    TooShort,
+    /// The bytes don't start with [`MAGIC`], so there isn't a flash map.
+    NoMagic,
+    /// The flash map was written by a newer version of this crate.
+    UnsupportedVersion(u32),
+    /// The flash map's checksum is wrong.
+    BadChecksum,
+    /// A region doesn't start and end on a [`SECTOR_SIZE`] boundary.
+    Unaligned(RegionKind),
+    /// A region overlaps the SPL or the flash map itself.
+    OverlapsMap(RegionKind),
+    /// Two regions overlap.
+    Overlap(RegionKind, RegionKind),
+    /// The kernel image is longer than the kernel region.
+    KernelTooLong,
+}
+
+// === impl FlashMap ===
+
+impl FlashMap {
+    /// Returns the region of `kind`.
+    #[must_use]
+    pub fn region(&self, kind: RegionKind) -> Region {
+        match kind {
+            RegionKind::Kernel => self.kernel,
+            RegionKind::Config => self.config,
+            RegionKind::Kv => self.kv,
+        }
+    }
+
+    /// Returns the offset just past the end of the last region.
+    #[must_use]
+    pub fn end(&self) -> u64 {
+        RegionKind::ALL
+            .iter()
+            .map(|&kind| self.region(kind).end())
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Checks that every region is aligned to [`SECTOR_SIZE`], that no
+    /// regions overlap each other or the flash map, and that the kernel image
+    /// fits in its region.
+    pub fn validate(&self) -> Result<(), Error> {
+        let reserved = Region {
+            offset: 0,
+            len: OFFSET + SECTOR_SIZE,
+        };
+        for (i, &kind) in RegionKind::ALL.iter().enumerate() {
+            let region = self.region(kind);
+            if region.offset % SECTOR_SIZE != 0 || region.len % SECTOR_SIZE != 0 {
+                return Err(Error::Unaligned(kind));
+            }
+            if region.overlaps(reserved) {
+                return Err(Error::OverlapsMap(kind));
+            }
+            for &other in &RegionKind::ALL[i + 1..] {
+                if region.overlaps(self.region(other)) {
+                    return Err(Error::Overlap(kind, other));
+                }
+            }
+        }
+        if self.kernel_len > self.kernel.len {
+            return Err(Error::KernelTooLong);
+        }
+        Ok(())
+    }
+
+    /// Encodes this flash map.
+    #[must_use]
+    pub fn encode(&self) -> [u8; ENCODED_LEN] {
+        let mut buf = [0; ENCODED_LEN];
+        buf[..8].copy_from_slice(&MAGIC);
+        let words = [
+            VERSION,
+            self.kernel.offset,
+            self.kernel.len,
+            self.kernel_len,
+            self.config.offset,
+            self.config.len,
+            self.kv.offset,
+            self.kv.len,
+        ];
+        for (chunk, word) in buf[8..ENCODED_LEN - 4].chunks_exact_mut(4).zip(words) {
+            chunk.copy_from_slice(&word.to_le_bytes());
+        }
+        let crc = crc32(&buf[..ENCODED_LEN - 4]);
+        buf[ENCODED_LEN - 4..].copy_from_slice(&crc.to_le_bytes());
+        buf
+    }
+
+    /// Decodes and [validates](Self::validate) a flash map from the start of
+    /// `bytes`.
+    pub fn decode(bytes: &[u8]) -> Result<Self, Error> {
+        let bytes = bytes.get(..ENCODED_LEN).ok_or(Error::TooShort)?;
+        if bytes[..8] != MAGIC {
+            return Err(Error::NoMagic);
+        }
+        let word = |i: usize| {
+            let start = 8 + i * 4;
+            u32::from_le_bytes(bytes[start..start + 4].try_into().unwrap())
+        };
+        // check the version first, so that a newer map isn't reported as
+        // corrupt.
+        match word(0) {
+            VERSION => {}
+            version => return Err(Error::UnsupportedVersion(version)),
+        }
+        if word(8) != crc32(&bytes[..ENCODED_LEN - 4]) {
+            return Err(Error::BadChecksum);
+        }
+
+        let map = Self {
+            kernel: Region {
+                offset: word(1),
+                len: word(2),
+            },
+            kernel_len: word(3),
+            config: Region {
+                offset: word(4),
+                len: word(5),
+            },
+            kv: Region {
+                offset: word(6),
+                len: word(7),
+            },
+        };
+        map.validate()?;
+        Ok(map)
+    }
+}
+
+// === impl Region ===
+
+impl Region {
+    /// Returns the offset just past the end of this region.
+    #[must_use]
+    pub fn end(&self) -> u64 {
+        self.offset as u64 + self.len as u64
+    }
+
+    /// Returns `true` if this region and `other` share any bytes.
+    #[must_use]
+    pub fn overlaps(&self, other: Region) -> bool {
+        self.len != 0
+            && other.len != 0
+            && (self.offset as u64) < other.end()
+            && (other.offset as u64) < self.end()
+    }
+
+    /// Returns the offset from the start of the flash of the `len` bytes
+    /// starting `offset` bytes into this region, or [`None`] if they aren't
+    /// all in the region.
+    #[must_use]
+    pub fn absolute(&self, offset: u32, len: u32) -> Option<u32> {
+        let end = offset.checked_add(len)?;
+        if end > self.len {
+            return None;
+        }
+        Some(self.offset + offset)
+    }
+}
+
+// === impl RegionKind ===
+
+impl RegionKind {
+    /// Every kind of region.
+    pub const ALL: [Self; 3] = [Self::Kernel, Self::Config, Self::Kv];
+}
+
+impl fmt::Display for RegionKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Kernel => "kernel",
+            Self::Config => "config",
+            Self::Kv => "key-value",
+        })
+    }
+}
+
+// === impl Error ===
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TooShort => write!(f, "a flash map is {ENCODED_LEN} bytes long"),
+            Self::NoMagic => f.write_str("no flash map found"),
+            Self::UnsupportedVersion(version) => {
+                write!(f, "unsupported flash map version {version}")
+            }
+            Self::BadChecksum => f.write_str("flash map checksum mismatch"),
+            Self::Unaligned(kind) => write!(
+                f,
+                "the {kind} region is not aligned to {SECTOR_SIZE} byte sectors"
+            ),
+            Self::OverlapsMap(kind) => write!(
+                f,
+                "the {kind} region overlaps the SPL or flash map, in the first {} bytes",
+                OFFSET + SECTOR_SIZE
+            ),
+            Self::Overlap(a, b) => write!(f, "the {a} and {b} regions overlap"),
+            Self::KernelTooLong => f.write_str("the kernel is longer than the kernel region"),
+        }
+    }
+}
+
+/// Returns the CRC-32 (as used by Ethernet, zlib and PNG) of `bytes`.
+fn crc32(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!0, |crc, &byte| {
+        (0..8).fold(crc ^ byte as u32, |crc, _| {
+            (crc >> 1) ^ (0xEDB8_8320 & (crc & 1).wrapping_neg())
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAP: FlashMap = FlashMap {
+        kernel: Region {
+            offset: 40 * 1024,
+            len: 4 * 1024 * 1024 - 40 * 1024,
+        },
+        kernel_len: 1_234_567,
+        config: Region {
+            offset: 4 * 1024 * 1024,
+            len: 64 * 1024,
+        },
+        kv: Region {
+            offset: 4 * 1024 * 1024 + 64 * 1024,
+            len: 1024 * 1024,
+        },
+    };
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn roundtrips() {
+        let bytes = MAP.encode();
+        assert_eq!(FlashMap::decode(&bytes), Ok(MAP));
+        assert_eq!(MAP.end(), 4 * 1024 * 1024 + 1088 * 1024);
+
+        // erased flash has no flash map.
+        assert_eq!(FlashMap::decode(&[0xFF; 64]), Err(Error::NoMagic));
+        assert_eq!(FlashMap::decode(&bytes[..20]), Err(Error::TooShort));
+        let mut corrupt = bytes;
+        corrupt[20] ^= 1;
+        assert_eq!(FlashMap::decode(&corrupt), Err(Error::BadChecksum));
+    }
+
+    #[test]
+    fn validates_regions() {
+        let mut map = MAP;
+        map.kv.len -= 1;
+        assert_eq!(map.validate(), Err(Error::Unaligned(RegionKind::Kv)));
+
+        let mut map = MAP;
+        map.kernel.offset = OFFSET;
+        assert_eq!(map.validate(), Err(Error::OverlapsMap(RegionKind::Kernel)));
+
+        let mut map = MAP;
+        map.config.len *= 2;
+        assert_eq!(
+            map.validate(),
+            Err(Error::Overlap(RegionKind::Config, RegionKind::Kv))
+        );
+
+        let mut map = MAP;
+        map.kernel_len = map.kernel.len + 1;
+        assert_eq!(map.validate(), Err(Error::KernelTooLong));
+    }
+
+    #[test]
+    fn absolute_offsets() {
+        let config = MAP.config;
+        assert_eq!(config.absolute(0, config.len), Some(config.offset));
+        assert_eq!(config.absolute(16, 16), Some(config.offset + 16));
+        assert_eq!(config.absolute(16, config.len), None);
+        assert_eq!(config.absolute(u32::MAX, 1), None);
+    }
+}
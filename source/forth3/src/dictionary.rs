@@ -191,6 +191,40 @@ enum CurrDict<'dict, T: 'static> {
 /// while the builtin [`Future`] executes (e.g. the result of the asynchronous
 /// operation can be pushed to the VM's `data` stack, et cetera).
 ///
+/// # Cancellation
+///
+/// The [`Future`] returned by [`dispatch_async`] may be dropped at any of its
+/// [`.await`] points without being polled to completion, if the
+/// [`AsyncForth::process_line`] future that is awaiting it is dropped. This
+/// happens when the VM's line is raced against a timeout, when the task
+/// running the VM is aborted, or when the VM itself is dropped. Therefore,
+/// async builtins must uphold the following requirements:
+///
+/// - **Host resources must be owned.** Anything the builtin acquires on the
+///   host (such as a connection to a service, or a handle to a port) must be
+///   held by a value that releases it when dropped, rather than being released
+///   by code that runs after an [`.await`]. Once a resource is handed to the
+///   host context (e.g. stored in a table in `T`), the host context becomes
+///   responsible for it, and it must be released when the host context is
+///   dropped.
+/// - **Observable side effects must be idempotent or transactional.** A
+///   builtin should not leave the host context in a state that is only
+///   consistent once the builtin completes. If this cannot be avoided, the
+///   [`cancelled`] hook should restore it.
+/// - **The VM's stacks may be left in any state.** A builtin may pop its
+///   arguments before its first [`.await`], and push its results after its
+///   last one. The VM clears its stacks after a builtin is cancelled, so the
+///   builtin need not restore them.
+///
+/// When a builtin's [`Future`] is dropped before completing and the VM
+/// survives, the [`AsyncForth`] VM invokes the [`cancelled`] hook with the
+/// builtin's name the next time it processes a line (or when
+/// [`AsyncForth::recover`] is called). The default implementation does
+/// nothing, which is correct for builtins that satisfy the requirements
+/// above. If the VM is dropped instead, the hook is not called, and only
+/// the [`Drop`] implementations of the host context and the builtin's
+/// [`Future`] run.
+///
 /// [^1]: If the [`AsyncForth`] type was generic over every possible async
 ///     builtin future, it would have a large number of generic type parameters
 ///     which would all need to be filled in by the user. Additionally, because
@@ -199,6 +233,10 @@ enum CurrDict<'dict, T: 'static> {
 ///     number of async builtin words.
 ///
 /// [`AsyncForth`]: crate::AsyncForth
+/// [`AsyncForth::process_line`]: crate::AsyncForth::process_line
+/// [`AsyncForth::recover`]: crate::AsyncForth::recover
+/// [`.await`]: https://doc.rust-lang.org/stable/std/keyword.await.html
+/// [`cancelled`]: Self::cancelled
 /// [`Future`]: core::future::Future
 /// [async-vms]: crate::AsyncForth#asynchronous-forth-vms
 /// [`async fn`]: https://doc.rust-lang.org/stable/std/keyword.async.html
@@ -248,6 +286,21 @@ pub trait AsyncBuiltins<'forth, T: 'static> {
         id: &'static FaStr,
         forth: &'forth mut crate::Forth<T>,
     ) -> Self::Future;
+
+    /// Called when the [`Future`] returned by [`Self::dispatch_async`] for the
+    /// builtin named `id` was dropped before it completed.
+    ///
+    /// This is called before the VM's stacks are cleared, so that any state
+    /// that the builtin left in the VM's host context can be cleaned up. See
+    /// [the `AsyncBuiltins` trait's documentation][cancel] for details.
+    ///
+    /// By default, this does nothing.
+    ///
+    /// [`Future`]: core::future::Future
+    /// [cancel]: #cancellation
+    fn cancelled(&self, id: &'static FaStr, forth: &mut crate::Forth<T>) {
+        let _ = (id, forth);
+    }
 }
 
 impl<T: 'static> DictionaryEntry<T> {
@@ -595,10 +648,8 @@ impl DictionaryBump {
             return Err(BumpError::CantAllocUtf8);
         }
         let stir = self.bump_u8s(len).ok_or(BumpError::OutOfMemory)?.as_ptr();
-        for (i, ch) in astr.iter().enumerate() {
-            unsafe {
-                stir.add(i).write(ch.to_ascii_lowercase());
-            }
+        unsafe {
+            stir.copy_from_nonoverlapping(astr.as_ptr(), len);
         }
         unsafe { Ok(FaStr::new(stir, len)) }
     }
@@ -619,6 +670,32 @@ impl DictionaryBump {
         }
     }
 
+    /// Writes `bytes` into whole, zero-padded [`Word`]s, so that anything
+    /// bumped afterwards stays word-aligned.
+    ///
+    /// Returns a pointer to the first byte, and the number of words written.
+    /// If `bytes` is empty, no words are written, and the pointer is only
+    /// valid for reads of zero bytes.
+    pub fn bump_word_bytes(&mut self, bytes: &[u8]) -> Result<(NonNull<u8>, usize), BumpError> {
+        let word_size = mem::size_of::<Word>();
+        let offset = self.cur.align_offset(mem::align_of::<Word>());
+        let words = (bytes.len() + (word_size - 1)) / word_size;
+        let size = offset + (words * word_size);
+
+        if size > (self.end as usize) - (self.cur as usize) {
+            return Err(BumpError::OutOfMemory);
+        }
+
+        unsafe {
+            // Zero out the alignment and trailing padding bytes!
+            self.cur.write_bytes(0x00, size);
+            let start = self.cur.add(offset);
+            start.copy_from_nonoverlapping(bytes.as_ptr(), bytes.len());
+            self.cur = self.cur.add(size);
+            Ok((NonNull::new_unchecked(start), words))
+        }
+    }
+
     #[allow(dead_code)]
     pub fn bump_u8(&mut self) -> Option<NonNull<u8>> {
         if self.cur >= self.end {
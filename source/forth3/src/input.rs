@@ -25,9 +25,10 @@ pub enum FillError {
 /// string literal was found.
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum StrLiteralError {
-    /// The current word is not the beginning of a string literal (`."`).
+    /// The current word is not the beginning of a string literal (`."` or
+    /// `s"`).
     NotAStr,
-    /// The `."` was not followed by anything.
+    /// The `."` or `s"` was not followed by anything.
     Empty,
     /// The string literal was not terminated by a `"`.
     Unterminated,
@@ -60,15 +61,12 @@ impl WordStrBuf {
             // TODO: Do I care about this?
             return Err(FillError::NotAscii);
         }
-        // TODO: I probably *don't* want to lowercase everything, this also affects
-        // things like string literals, which don't need to be lowercased.
+        // Note that the input is *not* case-folded here, as that would also
+        // affect string literals. Instead, the VM folds the case of names when
+        // it defines and looks up words.
         unsafe {
-            let istart = input.as_bytes().as_ptr();
-            for i in 0..ilen {
-                self.start
-                    .add(i)
-                    .write((istart.add(i).read()).to_ascii_lowercase());
-            }
+            self.start
+                .copy_from_nonoverlapping(input.as_bytes().as_ptr(), ilen);
             core::ptr::write_bytes(self.start.add(ilen), b' ', cap - ilen);
         }
         self.cur = self.start;
@@ -115,7 +113,7 @@ impl WordStrBuf {
     }
 
     pub fn advance_str(&mut self) -> Result<(), StrLiteralError> {
-        if self.cur_word() == Some(r#".""#) {
+        if matches!(self.cur_word(), Some(r#".""# | r#"s""# | r#"S""#)) {
             self.holding = Holding::None;
         } else {
             return Err(StrLiteralError::NotAStr);
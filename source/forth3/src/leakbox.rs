@@ -75,6 +75,13 @@ pub struct LBForthParams {
     pub input_buf_elems: usize,
     pub output_buf_elems: usize,
     pub dict_buf_elems: usize,
+    /// Whether word names are case-insensitive. See
+    /// [`Forth::set_case_insensitive`].
+    pub case_insensitive: bool,
+    /// Whether to load the [standard prelude](crate::prelude) when the VM is
+    /// constructed. Forked VMs inherit their parent's dictionary, so this is
+    /// ignored by `fork_with_params`.
+    pub prelude: bool,
 }
 
 #[derive(Copy, Clone)]
@@ -89,6 +96,8 @@ impl Default for LBForthParams {
             input_buf_elems: 256,
             output_buf_elems: 256,
             dict_buf_elems: 4096,
+            case_insensitive: true,
+            prelude: false,
         }
     }
 }
@@ -131,7 +140,7 @@ impl<T: 'static> LBForth<T> {
             input: WordStrBuf::new(_input_buf.ptr(), _input_buf.len()),
             output: OutputBuf::new(_output_buf.ptr(), _output_buf.len()),
         };
-        let forth = unsafe {
+        let mut forth = unsafe {
             Forth::<T>::new(
                 bufs,
                 alloc_dict::<T, LeakBoxDict>(params.dict_buf_elems),
@@ -140,6 +149,10 @@ impl<T: 'static> LBForth<T> {
             )
             .unwrap()
         };
+        forth.set_case_insensitive(params.case_insensitive);
+        if params.prelude {
+            forth.load_prelude().unwrap();
+        }
 
         Self {
             forth,
@@ -216,7 +229,7 @@ where
             input: WordStrBuf::new(_input_buf.ptr(), _input_buf.len()),
             output: OutputBuf::new(_output_buf.ptr(), _output_buf.len()),
         };
-        let forth = unsafe {
+        let mut forth = unsafe {
             AsyncForth::<T, D>::new(
                 bufs,
                 alloc_dict::<T, LeakBoxDict>(params.dict_buf_elems),
@@ -226,6 +239,10 @@ where
             )
             .unwrap()
         };
+        forth.set_case_insensitive(params.case_insensitive);
+        if params.prelude {
+            forth.load_prelude().unwrap();
+        }
 
         Self {
             forth,
@@ -7,6 +7,7 @@ pub mod dictionary;
 pub mod fastr;
 pub mod input;
 pub mod output;
+pub mod prelude;
 pub mod stack;
 pub(crate) mod vm;
 pub mod word;
@@ -80,6 +81,11 @@ pub enum Error {
     DivideByZero,
     AddrOfMissingName,
     AddrOfNotAWord,
+    MarkerInDefinition,
+    MarkerNotInCurrentDict,
+    MarkerStillInUse,
+    /// The number base is not between 2 and 36.
+    BadBase(i32),
 
     // Not *really* an error - but signals that a function should be called
     // again. At the moment, only used for internal interpreter functions.
@@ -219,6 +225,7 @@ pub enum Lookup<T: 'static> {
         bi: NonNull<AsyncBuiltinEntry<T>>,
     },
     LQuote,
+    SQuote,
     LParen,
     Semicolon,
     If,
@@ -229,6 +236,7 @@ pub enum Lookup<T: 'static> {
     Constant,
     Variable,
     Array,
+    Marker,
 }
 
 trait ReplaceErr {
@@ -794,6 +802,96 @@ pub mod test {
         );
     }
 
+    #[cfg(feature = "async")]
+    #[test]
+    fn async_forth_cancelled() {
+        use crate::{
+            async_builtin,
+            dictionary::{AsyncBuiltinEntry, AsyncBuiltins},
+            fastr::FaStr,
+            leakbox::{AsyncLBForth, LBForthParams},
+            testutil::async_blockon_runtest_with,
+        };
+        use core::{pin::pin, task::Context};
+
+        struct TestAsyncDispatcher;
+        impl<'forth> AsyncBuiltins<'forth, TestContext> for TestAsyncDispatcher {
+            type Future = CountingFut<'forth>;
+
+            const BUILTINS: &'static [AsyncBuiltinEntry<TestContext>] =
+                &[async_builtin!("counter")];
+
+            fn dispatch_async(
+                &self,
+                id: &FaStr,
+                forth: &'forth mut Forth<TestContext>,
+            ) -> Self::Future {
+                match id.as_str() {
+                    "counter" => {
+                        let val: usize = forth.data_stack.pop().unwrap().try_into().unwrap();
+                        CountingFut {
+                            ctr: 0,
+                            target: val,
+                            forth,
+                        }
+                    }
+                    id => panic!("Unknown async builtin {id}"),
+                }
+            }
+
+            fn cancelled(&self, id: &'static FaStr, forth: &mut Forth<TestContext>) {
+                assert_eq!(id.as_str(), "counter");
+                forth.host_ctxt.contents.push(1);
+            }
+        }
+
+        // `5 counter` is pending for five polls, so drop the line after each
+        // of them, as well as before it is ever polled.
+        for polls in 0..=5 {
+            let mut lbforth = AsyncLBForth::from_params(
+                LBForthParams::default(),
+                TestContext::default(),
+                Forth::FULL_BUILTINS,
+                TestAsyncDispatcher,
+            );
+            let forth = &mut lbforth.forth;
+            forth.input_mut().fill("1 2 5 counter").unwrap();
+            {
+                let mut line = pin!(forth.process_line());
+                let mut cx = Context::from_waker(futures::task::noop_waker_ref());
+                for _ in 0..polls {
+                    assert!(line.as_mut().poll(&mut cx).is_pending());
+                }
+            }
+
+            let interrupted = (polls > 0).then_some("counter");
+            assert_eq!(forth.interrupted(), interrupted, "after {polls} polls");
+
+            // the next line recovers from the interrupted one, without any of
+            // its values left on the stack.
+            async_blockon_runtest_with(
+                forth,
+                r#"
+                    > 3 4 + .
+                    < 7 ok.
+                    x .
+                "#,
+            );
+            assert_eq!(forth.interrupted(), None);
+            let cancelled: &[i32] = if polls > 0 { &[1] } else { &[] };
+            assert_eq!(forth.host_ctxt().contents, cancelled, "after {polls} polls");
+
+            // and async builtins still work.
+            async_blockon_runtest_with(
+                forth,
+                r#"
+                    > 5 counter .
+                    < 5 ok.
+                "#,
+            );
+        }
+    }
+
     #[test]
     fn compile() {
         all_runtest(
@@ -888,6 +986,33 @@ pub mod test {
         );
     }
 
+    #[test]
+    fn s_quote_strings() {
+        all_runtest(
+            r#"
+            > s" interpreted" type
+            < interpretedok.
+            > : greet s" hello, world!" type ;
+            < ok.
+            > greet
+            < hello, world!ok.
+            > : len s" four" swap drop . ;
+            < ok.
+            > len
+            < 4 ok.
+            > : empty s" " swap drop . ;
+            < ok.
+            > empty
+            < 0 ok.
+            ( strings are padded, so anything compiled after them is aligned )
+            > : mixed ." abc" 1 . s" xy" type 2 . ;
+            < ok.
+            > mixed
+            < abc1 xy2 ok.
+        "#,
+        );
+    }
+
     #[test]
     fn constants() {
         all_runtest(
@@ -935,4 +1060,124 @@ pub mod test {
         "#,
         );
     }
+
+    #[test]
+    fn markers() {
+        all_runtest(
+            r#"
+            > : keep 1 . ;
+            < ok.
+            > marker checkpoint
+            < ok.
+            > : star 42 emit ;
+            < ok.
+            > variable v
+            < ok.
+            > : keep 2 . ;
+            < ok.
+            > keep star
+            < 2 *ok.
+            ( restoring the checkpoint removes everything after it )
+            > checkpoint
+            < ok.
+            > keep
+            < 1 ok.
+            x star
+            x v
+            ( including the marker itself )
+            x checkpoint
+            ( markers can't be used inside a definition )
+            x : bad marker oops ;
+            ( or by words defined after them )
+            > marker m2
+            < ok.
+            > : undo m2 ;
+            < ok.
+            x undo
+        "#,
+        );
+    }
+
+    #[test]
+    fn prelude() {
+        all_runtest(
+            r#"
+            ( prelude true )
+            > 1 2 nip .
+            < 2 ok.
+            > 1 2 tuck . . .
+            < 2 1 2 ok.
+            > 0 ?dup . 3 ?dup . .
+            < 0 3 3 ok.
+            > 5 1 10 within . 1 1 10 within . 10 1 10 within . 0 1 10 within .
+            < -1 -1 0 0 ok.
+            ( the prelude's words can be redefined, like any other )
+            > : nip 42 ;
+            < ok.
+            > nip .
+            < 42 ok.
+        "#,
+        );
+    }
+
+    #[test]
+    fn case_insensitive() {
+        all_runtest(
+            r#"
+            > : Star 42 emit ;
+            < ok.
+            > STAR star Star
+            < ***ok.
+            > 1 2 SWAP . .
+            < 1 2 ok.
+            ( string literals are not case-folded )
+            > : greet ." Hello, World!" ;
+            < ok.
+            > GREET
+            < Hello, World!ok.
+            > forget STAR
+            < ok.
+            x star
+        "#,
+        );
+    }
+
+    #[test]
+    fn case_sensitive() {
+        all_runtest(
+            r#"
+            ( case_insensitive false )
+            > : Star 42 emit ;
+            < ok.
+            > Star
+            < *ok.
+            x star
+            x STAR
+            x DUP
+            > 1 dup . .
+            < 1 1 ok.
+        "#,
+        );
+    }
+
+    #[test]
+    fn number_base() {
+        all_runtest(
+            r#"
+            > hex 10 . ff u. -1 .
+            < 10 FF -1 ok.
+            > 1 a .s
+            < <2> 1 A
+            < ok.
+            > decimal 2 base ! 1010 .
+            < 1010 ok.
+            > decimal 16 2 .r 3 3 u.r
+            < 16  3ok.
+            > 37 base !
+            x 1 .
+            > decimal 0 <# #s #> type
+            < 0ok.
+        "#,
+        );
+    }
 }
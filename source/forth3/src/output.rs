@@ -12,6 +12,13 @@ pub enum OutputError {
     FormattingErr,
 }
 
+/// A buffer for building a number's digits right-to-left, as used by
+/// pictured numeric output (`<# # #s #>`).
+pub struct HoldBuf {
+    buf: [u8; HoldBuf::CAPACITY],
+    start: usize,
+}
+
 impl From<core::fmt::Error> for OutputError {
     fn from(_oe: core::fmt::Error) -> Self {
         OutputError::FormattingErr
@@ -74,3 +81,60 @@ impl core::fmt::Write for OutputBuf {
         self.push_str(s).replace_err(core::fmt::Error)
     }
 }
+
+impl HoldBuf {
+    /// Enough space for a 32-bit number in binary, plus a sign and some
+    /// extra characters added with `hold`.
+    pub const CAPACITY: usize = 64;
+
+    const DIGITS: &'static [u8; 36] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+
+    pub const fn new() -> Self {
+        Self {
+            buf: [0; Self::CAPACITY],
+            start: Self::CAPACITY,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.start = Self::CAPACITY;
+    }
+
+    /// Prepends a character to the buffer.
+    pub fn hold(&mut self, c: u8) -> Result<(), OutputError> {
+        self.start = self.start.checked_sub(1).ok_or(OutputError::OutputFull)?;
+        self.buf[self.start] = c;
+        Ok(())
+    }
+
+    /// Prepends the least significant digit of `val` in `base`, returning
+    /// the remaining digits.
+    ///
+    /// `base` must be between 2 and 36.
+    pub fn digit(&mut self, val: u32, base: u32) -> Result<u32, OutputError> {
+        debug_assert!((2..=36).contains(&base));
+        self.hold(Self::DIGITS[(val % base) as usize])?;
+        Ok(val / base)
+    }
+
+    /// Prepends every digit of `val` in `base`. At least one digit is always
+    /// added, even if `val` is zero.
+    pub fn digits(&mut self, mut val: u32, base: u32) -> Result<(), OutputError> {
+        loop {
+            val = self.digit(val, base)?;
+            if val == 0 {
+                return Ok(());
+            }
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf[self.start..]
+    }
+}
+
+impl Default for HoldBuf {
+    fn default() -> Self {
+        Self::new()
+    }
+}
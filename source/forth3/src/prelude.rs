@@ -0,0 +1,20 @@
+//! The standard prelude: common words that are implemented in Forth itself,
+//! rather than as builtins.
+//!
+//! The prelude is optional. It can be loaded into a VM using
+//! [`Forth::load_prelude`](crate::Forth::load_prelude), or by setting
+//! `LBForthParams::prelude` when constructing a leakbox VM, so that every VM
+//! configured with it shares the same base vocabulary.
+//!
+//! Some words that are often defined in a prelude, such as `2dup`, `2drop`,
+//! and `rot`, are builtins in forth3, and are not redefined here.
+
+/// The source of the standard prelude.
+///
+/// Each definition is on its own line, as the VM processes one line at a time.
+pub const PRELUDE: &str = r#"
+: nip ( a b -- b ) swap drop ;
+: tuck ( a b -- b a b ) swap over ;
+: ?dup ( n -- 0 | n n ) dup if dup then ;
+: within ( n lo hi -- flag ) d>r over > not swap r>d < and ;
+"#;
@@ -16,6 +16,8 @@
 //!     * `( input_buf_elems USIZE )`
 //!     * `( output_buf_elems USIZE )`
 //!     * `( dict_buf_elems USIZE )`
+//!     * `( case_insensitive BOOL )`
+//!     * `( prelude BOOL )`
 //! * Comment lines. These are any lines just containing a `( ... )` style forth comment.
 //! * Successful input lines, starting with `> ...`.
 //! * Successful output lines, starting with `< ...`.
@@ -111,7 +113,7 @@ pub fn async_blockon_runtest(contents: &str) {
     let tokd = tokenize(contents, true).unwrap();
     let mut forth =
         AsyncLBForth::from_params(tokd.settings, (), Forth::FULL_BUILTINS, TestAsyncDispatcher);
-    async_blockon_runtest_with(&mut forth.forth, contents);
+    async_blockon_steps_with(tokd.steps.as_slice(), &mut forth.forth);
 }
 
 /// Like `async_blockon_runtest`, but with provided context + dispatcher
@@ -126,7 +128,7 @@ where
     let tokd = tokenize(contents, true).unwrap();
     let mut forth =
         AsyncLBForth::from_params(tokd.settings, context, Forth::FULL_BUILTINS, dispatcher);
-    async_blockon_runtest_with(&mut forth.forth, contents);
+    async_blockon_steps_with(tokd.steps.as_slice(), &mut forth.forth);
 }
 
 /// Like `async_blockon_runtest`, but with provided async vm
@@ -137,10 +139,19 @@ where
     D: for<'forth> crate::dictionary::AsyncBuiltins<'forth, T>,
 {
     let tokd = tokenize(contents, false).unwrap();
+    async_blockon_steps_with(tokd.steps.as_slice(), forth);
+}
+
+#[cfg(feature = "async")]
+fn async_blockon_steps_with<T, D>(steps: &[Step], forth: &mut crate::AsyncForth<T, D>)
+where
+    T: 'static,
+    D: for<'forth> crate::dictionary::AsyncBuiltins<'forth, T>,
+{
     for Step {
-        ref input,
-        output: ref outcome,
-    } in tokd.steps
+        input,
+        output: outcome,
+    } in steps
     {
         #[cfg(not(miri))]
         println!("> {input}");
@@ -281,6 +292,13 @@ fn tokenize(contents: &str, allow_frontmatter: bool) -> Result<Tokenized, ()> {
                         output.settings.dict_buf_elems =
                             split.next().unwrap().parse::<usize>().unwrap();
                     }
+                    Some("case_insensitive") => {
+                        output.settings.case_insensitive =
+                            split.next().unwrap().parse::<bool>().unwrap();
+                    }
+                    Some("prelude") => {
+                        output.settings.prelude = split.next().unwrap().parse::<bool>().unwrap();
+                    }
                     Some(_) => {
                         is_comment = true;
                     }
@@ -40,12 +40,34 @@ use super::*;
 /// identical to the [`Forth::add_builtin`] and
 /// [`Forth::add_builtin_static_name`] methods.
 ///
+/// # Cancellation
+///
+/// The [`Future`] returned by [`AsyncForth::process_line`] may be dropped
+/// before it completes, such as when it is raced against a timeout, or when
+/// the task running the VM is torn down. If it is dropped while an async
+/// builtin is pending, the builtin's [`Future`] is dropped at whatever
+/// [`.await`] point it had reached, and the VM is left partway through
+/// executing the line.
+///
+/// The VM records which async builtin was pending. The next time
+/// [`AsyncForth::process_line`] is called (or if [`AsyncForth::recover`] is
+/// called explicitly), the interrupted builtin's
+/// [`AsyncBuiltins::cancelled`] hook is invoked, and the VM's stacks are
+/// cleared, exactly as they are when a line fails with an error. The VM can
+/// then be used as though the interrupted line had never been entered. See
+/// [the `AsyncBuiltins` documentation][cancel] for what async builtins must
+/// do to be cancelled safely.
+///
 /// [`Future`]: core::future::Future
 /// [`async fn`]: https://doc.rust-lang.org/stable/std/keyword.async.html
 /// [`.await`]: https://doc.rust-lang.org/stable/std/keyword.await.html
+/// [cancel]: AsyncBuiltins#cancellation
 pub struct AsyncForth<T: 'static, A> {
     vm: Forth<T>,
     builtins: A,
+    /// The async builtin that was executing when a `process_line` future was
+    /// last dropped, if it was dropped before that builtin completed.
+    pending: Option<&'static FaStr>,
 }
 
 impl<T, A> AsyncForth<T, A>
@@ -56,7 +78,11 @@ where
     /// Construct a new `AsyncForth` from the provided synchronous VM and async
     /// builtins.
     pub fn from_forth(vm: Forth<T>, builtins: A) -> Self {
-        Self { vm, builtins }
+        Self {
+            vm,
+            builtins,
+            pending: None,
+        }
     }
 
     pub unsafe fn new(
@@ -70,6 +96,7 @@ where
         Ok(Self {
             vm,
             builtins: async_builtins,
+            pending: None,
         })
     }
 
@@ -106,6 +133,7 @@ where
         Ok(Self {
             vm,
             builtins: self.builtins.clone(),
+            pending: None,
         })
     }
 
@@ -156,13 +184,70 @@ where
         self.vm.add_builtin(name, bi)
     }
 
+    /// Sets whether word names are case-insensitive.
+    ///
+    /// See [`Forth::set_case_insensitive`] for details.
+    pub fn set_case_insensitive(&mut self, case_insensitive: bool) {
+        self.vm.set_case_insensitive(case_insensitive)
+    }
+
+    /// Sets the radix used to parse and print numbers.
+    ///
+    /// See [`Forth::set_base`] for details.
+    pub fn set_base(&mut self, base: u32) -> Result<(), Error> {
+        self.vm.set_base(base)
+    }
+
+    /// Loads the [standard prelude](crate::prelude::PRELUDE) into this VM's
+    /// dictionary.
+    ///
+    /// The prelude only uses synchronous builtins, so this need not be
+    /// `async`. See [`Forth::load_prelude`] for details.
+    pub fn load_prelude(&mut self) -> Result<(), Error> {
+        self.vm.load_prelude()
+    }
+
     #[cfg(test)]
     #[allow(dead_code)]
     pub(crate) fn vm_mut(&mut self) -> &mut Forth<T> {
         &mut self.vm
     }
 
+    /// Returns the name of the async builtin that was interrupted when a
+    /// [`process_line`](Self::process_line) future was dropped, if the VM has
+    /// not yet recovered from it.
+    #[inline]
+    #[must_use]
+    pub fn interrupted(&self) -> Option<&'static str> {
+        self.pending.map(FaStr::as_str)
+    }
+
+    /// Recovers from a [`process_line`](Self::process_line) future that was
+    /// dropped while an async builtin was pending.
+    ///
+    /// This invokes the interrupted builtin's [`AsyncBuiltins::cancelled`]
+    /// hook, and clears the VM's stacks. It returns the name of the
+    /// interrupted builtin, or [`None`] if nothing was interrupted, in which
+    /// case this does nothing.
+    ///
+    /// [`process_line`](Self::process_line) calls this before processing
+    /// each line, so it only needs to be called explicitly in order to
+    /// release the interrupted builtin's resources sooner.
+    pub fn recover(&mut self) -> Option<&'static str> {
+        let id = self.pending.take()?;
+        self.builtins.cancelled(id, &mut self.vm);
+        self.vm.data_stack.clear();
+        self.vm.return_stack.clear();
+        self.vm.call_stack.clear();
+        Some(id.as_str())
+    }
+
+    /// Processes the line in this VM's input buffer.
+    ///
+    /// This future may be dropped before it completes; see [the section on
+    /// cancellation](Self#cancellation) for details.
     pub async fn process_line(&mut self) -> Result<(), Error> {
+        self.recover();
         let res = async {
             loop {
                 match self.vm.start_processing_line()? {
@@ -192,6 +277,7 @@ where
         let Self {
             ref mut vm,
             ref builtins,
+            ref mut pending,
         } = self;
 
         let top = match vm.call_stack.try_peek() {
@@ -206,7 +292,16 @@ where
                 EntryKind::StaticBuiltin => (top.eh.cast::<BuiltinEntry<T>>().as_ref().func)(vm),
                 EntryKind::RuntimeBuiltin => (top.eh.cast::<BuiltinEntry<T>>().as_ref().func)(vm),
                 EntryKind::Dictionary => (top.eh.cast::<DictionaryEntry<T>>().as_ref().func)(vm),
-                EntryKind::AsyncBuiltin => builtins.dispatch_async(&top.eh.as_ref().name, vm).await,
+                EntryKind::AsyncBuiltin => {
+                    let id = &top.eh.as_ref().name;
+                    // if this future is dropped before the builtin completes,
+                    // `pending` stays set, so that the next call to
+                    // `process_line` can clean up after it.
+                    *pending = Some(id);
+                    let res = builtins.dispatch_async(id, vm).await;
+                    *pending = None;
+                    res
+                }
             }
         };
 
@@ -227,3 +322,12 @@ where
         self.vm.release()
     }
 }
+
+/// # Safety
+///
+/// An `AsyncForth` VM is [`Send`] and [`Sync`] under the same conditions as
+/// the [`Forth`] VM it wraps. The only additional pointer it holds is the name
+/// of an interrupted builtin, which is borrowed from the `'static` slice of
+/// [`AsyncBuiltins::BUILTINS`] and is never mutated.
+unsafe impl<T: Send, A: Send> Send for AsyncForth<T, A> {}
+unsafe impl<T: Sync, A: Sync> Sync for AsyncForth<T, A> {}
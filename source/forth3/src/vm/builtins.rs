@@ -3,6 +3,7 @@ use core::{fmt::Write, marker::PhantomData, mem::size_of, ptr::NonNull};
 use crate::{
     dictionary::{BuiltinEntry, DictLocation, DictionaryEntry, EntryHeader, EntryKind},
     fastr::comptime_fastr,
+    output::HoldBuf,
     vm::TmpFaStr,
     word::Word,
     Error, Forth, Lookup, Mode, ReplaceErr,
@@ -127,12 +128,29 @@ impl<T: 'static> Forth<T> {
         builtin!("spaces", Self::spaces),
         builtin!(".", Self::pop_print),
         builtin!("u.", Self::unsigned_pop_print),
+        builtin!(".r", Self::pop_print_right),
+        builtin!("u.r", Self::unsigned_pop_print_right),
+        builtin!("type", Self::type_str),
+        //
+        // Number base/pictured numeric output
+        //
+        builtin!("base", Self::base_addr),
+        builtin!("hex", Self::hex),
+        builtin!("decimal", Self::decimal),
+        builtin!("<#", Self::hold_start),
+        builtin!("#", Self::hold_digit),
+        builtin!("#s", Self::hold_digits),
+        builtin!("hold", Self::hold_char),
+        builtin!("sign", Self::hold_sign),
+        builtin!("#>", Self::hold_end),
         builtin_if_feature!("floats", "f.", Self::float_pop_print),
         //
         // Define/forget
         //
         builtin!(":", Self::colon),
         builtin!("forget", Self::forget),
+        // NOTE: REQUIRED for `marker`
+        builtin!("(marker)", Self::restore_marker),
         //
         // Stack/Retstack operations
         //
@@ -174,6 +192,8 @@ impl<T: 'static> Forth<T> {
         //
         // NOTE: REQUIRED for `."`
         builtin!("(write-str)", Self::write_str_lit),
+        // NOTE: REQUIRED for `s"`
+        builtin!("(str-lit)", Self::str_lit),
         // NOTE: REQUIRED for `do/loop`
         builtin!("(jmp-doloop)", Self::jump_doloop),
         // NOTE: REQUIRED for `if/then` and `if/else/then`
@@ -233,7 +253,8 @@ impl<T: 'static> Forth<T> {
         write!(&mut self.output, "<{}> ", depth)?;
         for d in (0..depth).rev() {
             let val = self.data_stack.try_peek_back_n(d)?;
-            write!(&mut self.output, "{} ", val.into_data())?;
+            self.write_num(val.into_data(), true, 0)?;
+            self.output.push_str(" ")?;
         }
         self.output.push_str("\n")?;
         Ok(())
@@ -358,6 +379,8 @@ impl<T: 'static> Forth<T> {
             None => return Err(Error::ForgetWithoutWordName),
             Some(s) => s,
         };
+        let mut buf = [0; super::MAX_NAME_LEN];
+        let word = super::fold_name(self.case_insensitive, word, &mut buf);
         let word_tmp = TmpFaStr::new_from(word);
         let defn = match self.find_in_dict(&word_tmp) {
             None => {
@@ -404,6 +427,43 @@ impl<T: 'static> Forth<T> {
         Ok(())
     }
 
+    /// The run-time behavior of a word defined by `marker`.
+    ///
+    /// Restores the dictionary to its state when the marker was created,
+    /// removing the marker and every word defined after it.
+    pub fn restore_marker(&mut self) -> Result<(), Error> {
+        let me = self.call_stack.try_peek()?;
+        let de = me.eh.cast::<DictionaryEntry<T>>();
+        let pfa = unsafe { DictionaryEntry::<T>::pfa(de) };
+        let (tail, cur) = unsafe { (pfa.as_ptr().read().ptr, pfa.as_ptr().add(1).read().ptr) };
+        let tail = NonNull::new(tail.cast::<DictionaryEntry<T>>());
+        let cur = cur.cast::<u8>();
+
+        // If the marker was inherited from a parent dictionary, the state it
+        // recorded is the parent's, which we can't roll back.
+        let alloc = &self.dict.alloc;
+        if !alloc.contains(cur.cast()) || (cur as usize) > (alloc.cur as usize) {
+            return Err(Error::MarkerNotInCurrentDict);
+        }
+
+        // Don't pull the rug out from under any words that are currently
+        // executing (i.e. a word defined after the marker which calls it).
+        let removed = (cur as usize)..(alloc.cur as usize);
+        for n in 1..self.call_stack.depth() {
+            let caller = self.call_stack.try_peek_back_n(n)?;
+            if removed.contains(&(caller.eh.as_ptr() as usize)) {
+                return Err(Error::MarkerStillInUse);
+            }
+        }
+
+        unsafe {
+            cur.write_bytes(0x00, removed.len());
+        }
+        self.dict.alloc.cur = cur;
+        self.dict.tail = tail;
+        Ok(())
+    }
+
     pub fn over(&mut self) -> Result<(), Error> {
         let a = self.data_stack.try_peek_back_n(1)?;
         self.data_stack.push(a)?;
@@ -639,6 +699,20 @@ impl<T: 'static> Forth<T> {
         }
     }
 
+    /// `type` ( addr len -- ) writes `len` bytes starting at `addr` to the
+    /// output buffer.
+    pub fn type_str(&mut self) -> Result<(), Error> {
+        let len = self.data_stack.try_pop()?.into_data();
+        let addr = self.data_stack.try_pop()?;
+        // A negative length writes nothing, rather than the whole address space.
+        let len = usize::try_from(len).unwrap_or(0);
+        if len > 0 {
+            let u8_sli = unsafe { core::slice::from_raw_parts(addr.ptr.cast::<u8>(), len) };
+            self.output.push_bstr(u8_sli)?;
+        }
+        Ok(())
+    }
+
     pub fn emit(&mut self) -> Result<(), Error> {
         let val = self.data_stack.try_pop()?;
         let val = val.into_data();
@@ -703,13 +777,198 @@ impl<T: 'static> Forth<T> {
 
     pub fn pop_print(&mut self) -> Result<(), Error> {
         let a = self.data_stack.try_pop()?;
-        write!(&mut self.output, "{} ", a.into_data())?;
+        self.write_num(a.into_data(), true, 0)?;
+        self.output.push_str(" ")?;
         Ok(())
     }
 
     pub fn unsigned_pop_print(&mut self) -> Result<(), Error> {
         let a = self.data_stack.try_pop()?;
-        write!(&mut self.output, "{} ", a.into_data() as u32)?;
+        self.write_num(a.into_data(), false, 0)?;
+        self.output.push_str(" ")?;
+        Ok(())
+    }
+
+    /// # Right-justified print (`.r`)
+    ///
+    /// Prints a number right-justified in a field `width` characters wide,
+    /// without a trailing space. Numbers wider than the field are printed in
+    /// full.
+    ///
+    /// ```rust
+    /// # use forth3::testutil::blocking_runtest;
+    /// #
+    /// # blocking_runtest(r#"
+    /// > -42 5 .r
+    /// <   -42ok.
+    /// # "#)
+    /// ```
+    pub fn pop_print_right(&mut self) -> Result<(), Error> {
+        let width = self.data_stack.try_pop()?.into_data();
+        let a = self.data_stack.try_pop()?;
+        self.write_num(a.into_data(), true, usize::try_from(width).unwrap_or(0))
+    }
+
+    /// # Unsigned right-justified print (`u.r`)
+    ///
+    /// Like `.r`, but prints the number as unsigned.
+    pub fn unsigned_pop_print_right(&mut self) -> Result<(), Error> {
+        let width = self.data_stack.try_pop()?.into_data();
+        let a = self.data_stack.try_pop()?;
+        self.write_num(a.into_data(), false, usize::try_from(width).unwrap_or(0))
+    }
+
+    /// Writes `val` in the current base, right-justified in a field `width`
+    /// characters wide.
+    fn write_num(&mut self, val: i32, signed: bool, width: usize) -> Result<(), Error> {
+        let base = self.base()?;
+        let mut buf = HoldBuf::new();
+        if signed {
+            buf.digits(val.unsigned_abs(), base)?;
+            if val < 0 {
+                buf.hold(b'-')?;
+            }
+        } else {
+            buf.digits(val as u32, base)?;
+        }
+        let digits = buf.as_bytes();
+        for _ in digits.len()..width {
+            self.output.push_bstr(b" ")?;
+        }
+        self.output.push_bstr(digits)?;
+        Ok(())
+    }
+
+    /// # Number base (`base`)
+    ///
+    /// Pushes the address of the variable containing the radix used to parse
+    /// and print numbers, which may be between 2 and 36.
+    ///
+    /// ```rust
+    /// # use forth3::testutil::blocking_runtest;
+    /// #
+    /// # blocking_runtest(r#"
+    /// > 2 base ! 101 decimal .
+    /// < 5 ok.
+    /// > base @ .
+    /// < 10 ok.
+    /// # "#)
+    /// ```
+    pub fn base_addr(&mut self) -> Result<(), Error> {
+        let base = Word::ptr(core::ptr::addr_of_mut!(self.base));
+        self.data_stack.push(base)?;
+        Ok(())
+    }
+
+    /// # Hexadecimal (`hex`)
+    ///
+    /// Parses and prints numbers in base 16.
+    ///
+    /// ```rust
+    /// # use forth3::testutil::blocking_runtest;
+    /// #
+    /// # blocking_runtest(r#"
+    /// > hex ff 10 + .
+    /// < 10F ok.
+    /// > -1 u. decimal
+    /// < FFFFFFFF ok.
+    /// # "#)
+    /// ```
+    pub fn hex(&mut self) -> Result<(), Error> {
+        self.set_base(16)
+    }
+
+    /// # Decimal (`decimal`)
+    ///
+    /// Parses and prints numbers in base 10.
+    pub fn decimal(&mut self) -> Result<(), Error> {
+        self.set_base(10)
+    }
+
+    /// # Begin pictured numeric output (`<#`)
+    ///
+    /// Clears the pictured numeric output buffer. The number's string is then
+    /// built from right to left using `#`, `#s`, `hold`, and `sign`, and
+    /// finished with `#>`.
+    ///
+    /// Unlike standard Forth, which operates on double-cell numbers here,
+    /// forth3 only has single-cell numbers, so these words take a single,
+    /// unsigned, number.
+    ///
+    /// ```rust
+    /// # use forth3::testutil::blocking_runtest;
+    /// #
+    /// # blocking_runtest(r#"
+    /// > : .addr hex <# # # # # 95 hold # # # # #> type decimal ;
+    /// > hex deadbeef .addr
+    /// < DEAD_BEEFok.
+    /// > : .signed dup abs <# #s swap sign #> type ;
+    /// > -123 .signed
+    /// < -123ok.
+    /// # "#)
+    /// ```
+    pub fn hold_start(&mut self) -> Result<(), Error> {
+        self.hold.clear();
+        Ok(())
+    }
+
+    /// # Pictured digit (`#`)
+    ///
+    /// `( u1 -- u2 )` Adds the least significant digit of `u1` to the
+    /// pictured numeric output, leaving the remaining digits.
+    pub fn hold_digit(&mut self) -> Result<(), Error> {
+        let base = self.base()?;
+        let val = self.data_stack.try_pop()?.into_data() as u32;
+        let rest = self.hold.digit(val, base)?;
+        self.data_stack.push(Word::data(rest as i32))?;
+        Ok(())
+    }
+
+    /// # Pictured digits (`#s`)
+    ///
+    /// `( u -- 0 )` Adds every remaining digit of `u` to the pictured numeric
+    /// output. At least one digit is added, even if `u` is zero.
+    pub fn hold_digits(&mut self) -> Result<(), Error> {
+        let base = self.base()?;
+        let val = self.data_stack.try_pop()?.into_data() as u32;
+        self.hold.digits(val, base)?;
+        self.data_stack.push(Word::data(0))?;
+        Ok(())
+    }
+
+    /// # Pictured character (`hold`)
+    ///
+    /// `( char -- )` Adds a character to the pictured numeric output.
+    pub fn hold_char(&mut self) -> Result<(), Error> {
+        let c = self.data_stack.try_pop()?.into_data();
+        self.hold.hold(c as u8)?;
+        Ok(())
+    }
+
+    /// # Pictured sign (`sign`)
+    ///
+    /// `( n -- )` Adds a minus sign to the pictured numeric output if `n` is
+    /// negative.
+    pub fn hold_sign(&mut self) -> Result<(), Error> {
+        let n = self.data_stack.try_pop()?.into_data();
+        if n < 0 {
+            self.hold.hold(b'-')?;
+        }
+        Ok(())
+    }
+
+    /// # End pictured numeric output (`#>`)
+    ///
+    /// `( u -- addr len )` Drops the remaining digits, and pushes the address
+    /// and length of the pictured numeric output, which can be printed with
+    /// `type`. The string is only valid until the next `<#`.
+    pub fn hold_end(&mut self) -> Result<(), Error> {
+        self.data_stack.try_pop()?;
+        let digits = self.hold.as_bytes();
+        let len = Word::try_from(digits.len())?;
+        self.data_stack
+            .push(Word::ptr(digits.as_ptr().cast_mut()))?;
+        self.data_stack.push(len)?;
         Ok(())
     }
 
@@ -864,25 +1123,43 @@ impl<T: 'static> Forth<T> {
         }
     }
 
-    pub fn write_str_lit(&mut self) -> Result<(), Error> {
+    /// Returns the string compiled into the parent's CFA array after the
+    /// current builtin, and moves the parent past it.
+    ///
+    /// See `munch_str` for the layout of compiled strings.
+    fn inline_str(&mut self) -> Result<(*mut u8, usize), Error> {
         let parent = self.call_stack.try_peek_back_n_mut(1)?;
 
         // The length in bytes is stored in the next word.
-        let len = parent.get_current_val()?;
-        let len_u16 = u16::try_from(len).replace_err(Error::LiteralStringTooLong)?;
+        let len =
+            usize::try_from(parent.get_current_val()?).replace_err(Error::LiteralStringTooLong)?;
 
         // Now we need to figure out how many words our inline string takes up
         let word_size = size_of::<Word>();
-        let len_words = 1 + ((usize::from(len_u16) + (word_size - 1)) / word_size);
-        let len_and_str = parent.get_next_n_words(len_words as u16)?;
-        unsafe {
-            // Skip the "len" word
-            let start = len_and_str.as_ptr().add(1).cast::<u8>();
-            // Then push the literal into the output buffer
-            let u8_sli = core::slice::from_raw_parts(start, len_u16.into());
-            self.output.push_bstr(u8_sli)?;
-        }
-        parent.offset(len_words as i32)?;
+        let len_words = u16::try_from(1 + ((len + (word_size - 1)) / word_size))
+            .replace_err(Error::LiteralStringTooLong)?;
+        let len_and_str = parent.get_next_n_words(len_words)?;
+        // Skip the "len" word
+        let start = unsafe { len_and_str.as_ptr().add(1).cast::<u8>().cast_mut() };
+        parent.offset(len_words.into())?;
+        Ok((start, len))
+    }
+
+    /// `(write-str)` is used mid-interpret to write the string compiled by `."`
+    /// to the output buffer.
+    pub fn write_str_lit(&mut self) -> Result<(), Error> {
+        let (start, len) = self.inline_str()?;
+        let u8_sli = unsafe { core::slice::from_raw_parts(start, len) };
+        self.output.push_bstr(u8_sli)?;
+        Ok(())
+    }
+
+    /// `(str-lit)` is used mid-interpret to put the address and length of the
+    /// string compiled by `s"` onto the stack.
+    pub fn str_lit(&mut self) -> Result<(), Error> {
+        let (start, len) = self.inline_str()?;
+        self.data_stack.push(Word::ptr(start))?;
+        self.data_stack.push(Word::data(len as i32))?;
         Ok(())
     }
 
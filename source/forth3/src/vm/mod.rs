@@ -1,18 +1,14 @@
 use core::{
-    mem::size_of,
     num::NonZeroU16,
     ops::{Deref, Neg},
     ptr::NonNull,
-    str::FromStr,
 };
 
 use crate::{
-    dictionary::{
-        BuiltinEntry, BumpError, DictLocation, DictionaryEntry, EntryHeader, EntryKind, OwnedDict,
-    },
+    dictionary::{BuiltinEntry, DictLocation, DictionaryEntry, EntryHeader, EntryKind, OwnedDict},
     fastr::{FaStr, TmpFaStr},
     input::WordStrBuf,
-    output::OutputBuf,
+    output::{HoldBuf, OutputBuf},
     stack::{Stack, StackError},
     word::Word,
     CallContext, Error, Lookup, Mode, ReplaceErr, WordFunc,
@@ -42,8 +38,18 @@ pub struct Forth<T: 'static> {
     builtins: &'static [BuiltinEntry<T>],
     #[cfg(feature = "async")]
     async_builtins: &'static [AsyncBuiltinEntry<T>],
+    case_insensitive: bool,
+    /// The radix used to parse and print numbers. This is a [`Word`], rather
+    /// than an integer, so that the `base` word can push its address.
+    pub(crate) base: Word,
+    /// The buffer for pictured numeric output.
+    pub(crate) hold: HoldBuf,
 }
 
+/// Only the first 31 characters of a name are significant (see
+/// [`LenHash`](crate::fastr::LenHash)).
+const MAX_NAME_LEN: usize = 31;
+
 enum ProcessAction {
     Continue,
     Execute,
@@ -96,6 +102,9 @@ impl<T> Forth<T> {
 
             #[cfg(feature = "async")]
             async_builtins: &[],
+            case_insensitive: true,
+            base: Word::data(10),
+            hold: HoldBuf::new(),
         })
     }
 
@@ -132,6 +141,9 @@ impl<T> Forth<T> {
             host_ctxt,
             builtins,
             async_builtins,
+            case_insensitive: true,
+            base: Word::data(10),
+            hold: HoldBuf::new(),
         })
     }
 
@@ -147,7 +159,8 @@ impl<T> Forth<T> {
     /// dictionary, as it will become frozen).
     ///
     /// The child VM is created with empty stacks, and the provided input and
-    /// output buffers.
+    /// output buffers. It inherits this VM's [case
+    /// sensitivity](Self::set_case_insensitive) and [number base](Self::base).
     ///
     /// # Safety
     ///
@@ -161,7 +174,75 @@ impl<T> Forth<T> {
     ) -> Result<Self, Error> {
         let shared_dict = self.dict.fork_onto(my_dict);
         new_dict.set_parent(shared_dict);
-        Self::new(bufs, new_dict, host_ctxt, self.builtins)
+        let mut child = Self::new(bufs, new_dict, host_ctxt, self.builtins)?;
+        child.case_insensitive = self.case_insensitive;
+        child.base = self.base;
+        Ok(child)
+    }
+
+    /// Sets whether word names are case-insensitive.
+    ///
+    /// When enabled (the default), names are folded to lowercase when words
+    /// are defined and looked up, so `DUP`, `Dup`, and `dup` all name the same
+    /// word, as in classic Forth systems. When disabled, names must match
+    /// exactly. String literals are never case-folded.
+    ///
+    /// This should be set before any words are defined, as names defined
+    /// while the VM is case-sensitive are stored as written.
+    pub fn set_case_insensitive(&mut self, case_insensitive: bool) {
+        self.case_insensitive = case_insensitive;
+    }
+
+    /// Returns `true` if word names are case-insensitive.
+    ///
+    /// See [`Forth::set_case_insensitive`] for details.
+    #[must_use]
+    pub fn is_case_insensitive(&self) -> bool {
+        self.case_insensitive
+    }
+
+    /// Returns the radix used to parse and print numbers.
+    ///
+    /// This is 10 by default, and may be changed from Forth using `base`,
+    /// `hex`, and `decimal`, or with [`Forth::set_base`].
+    ///
+    /// Returns [`Error::BadBase`] if the base has been set to a value that is
+    /// not between 2 and 36.
+    pub fn base(&self) -> Result<u32, Error> {
+        match self.base.into_data() {
+            base @ 2..=36 => Ok(base as u32),
+            base => Err(Error::BadBase(base)),
+        }
+    }
+
+    /// Sets the radix used to parse and print numbers.
+    ///
+    /// Returns [`Error::BadBase`] if `base` is not between 2 and 36.
+    pub fn set_base(&mut self, base: u32) -> Result<(), Error> {
+        if !(2..=36).contains(&base) {
+            return Err(Error::BadBase(base as i32));
+        }
+        self.base = Word::data(base as i32);
+        Ok(())
+    }
+
+    /// Loads the [standard prelude](crate::prelude::PRELUDE) into this VM's
+    /// dictionary.
+    ///
+    /// This clears the VM's input and output buffers, so it should be called
+    /// before the VM is handed any input, typically right after it's
+    /// constructed.
+    pub fn load_prelude(&mut self) -> Result<(), Error> {
+        for line in crate::prelude::PRELUDE.lines() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            self.input.fill(line).replace_err(Error::InternalError)?;
+            let res = self.process_line();
+            self.output.clear();
+            res?;
+        }
+        Ok(())
     }
 
     pub fn add_builtin_static_name(
@@ -175,13 +256,22 @@ impl<T> Forth<T> {
     }
 
     pub fn add_builtin(&mut self, name: &str, bi: WordFunc<T>) -> Result<(), Error> {
+        let mut buf = [0; MAX_NAME_LEN];
+        let name = fold_name(self.case_insensitive, name, &mut buf);
         let name = self.dict.alloc.bump_str(name)?;
         self.dict.add_bi_fastr(name, bi)?;
         Ok(())
     }
 
-    fn parse_num(word: &str) -> Option<i32> {
-        i32::from_str(word).ok()
+    fn parse_num(&self, word: &str) -> Option<i32> {
+        // if the base is invalid, parse numbers in decimal, so that it can be
+        // fixed with `10 base !`.
+        let base = self.base().unwrap_or(10);
+        i32::from_str_radix(word, base)
+            .ok()
+            // numbers too large for an `i32`, such as addresses, wrap around,
+            // and can be printed with `u.`.
+            .or_else(|| u32::from_str_radix(word, base).ok().map(|val| val as i32))
     }
 
     fn find_word(&self, word: &str) -> Option<NonNull<EntryHeader<T>>> {
@@ -216,7 +306,9 @@ impl<T> Forth<T> {
     }
 
     pub fn lookup(&self, word: &str) -> Result<Lookup<T>, Error> {
-        match word {
+        let mut buf = [0; MAX_NAME_LEN];
+        let name = fold_name(self.case_insensitive, word, &mut buf);
+        match name {
             ";" => Ok(Lookup::Semicolon),
             "if" => Ok(Lookup::If),
             "else" => Ok(Lookup::Else),
@@ -227,9 +319,11 @@ impl<T> Forth<T> {
             "constant" => Ok(Lookup::Constant),
             "variable" => Ok(Lookup::Variable),
             "array" => Ok(Lookup::Array),
+            "marker" => Ok(Lookup::Marker),
             r#".""# => Ok(Lookup::LQuote),
+            r#"s""# => Ok(Lookup::SQuote),
             _ => {
-                let fastr = TmpFaStr::new_from(word);
+                let fastr = TmpFaStr::new_from(name);
                 if let Some(entry) = self.find_in_dict(&fastr) {
                     return Ok(Lookup::Dict(entry));
                 }
@@ -242,7 +336,7 @@ impl<T> Forth<T> {
                     return Ok(Lookup::Async { bi });
                 }
 
-                if let Some(val) = Self::parse_num(word) {
+                if let Some(val) = self.parse_num(word) {
                     return Ok(Lookup::Literal { val });
                 }
 
@@ -383,6 +477,16 @@ impl<T> Forth<T> {
                 let lit = self.input.cur_str_literal().unwrap();
                 self.output.push_str(lit)?;
             }
+            Lookup::SQuote => {
+                // Outside of a definition, the string is placed in the
+                // dictionary, so that it outlives the input buffer.
+                self.input.advance_str().map_err(Error::BadStrLiteral)?;
+                let lit = self.input.cur_str_literal().unwrap();
+                let len = i32::try_from(lit.len()).replace_err(Error::LiteralStringTooLong)?;
+                let (start, _) = self.dict.alloc.bump_word_bytes(lit.as_bytes())?;
+                self.data_stack.push(Word::ptr(start.as_ptr()))?;
+                self.data_stack.push(Word::data(len))?;
+            }
             Lookup::Constant => {
                 self.munch_constant(&mut 0)?;
             }
@@ -392,6 +496,9 @@ impl<T> Forth<T> {
             Lookup::Array => {
                 self.munch_array(&mut 0)?;
             }
+            Lookup::Marker => {
+                self.munch_marker()?;
+            }
         }
 
         Ok(ProcessAction::Continue)
@@ -658,10 +765,14 @@ impl<T> Forth<T> {
             Lookup::Do => return self.munch_do(len),
             Lookup::Loop => return Err(Error::LoopBeforeDo),
             Lookup::LParen => return self.munch_comment(len),
-            Lookup::LQuote => return self.munch_str(len),
+            Lookup::LQuote => return self.munch_str(len, "(write-str)"),
+            Lookup::SQuote => return self.munch_str(len, "(str-lit)"),
             Lookup::Constant => return self.munch_constant(len),
             Lookup::Variable => return self.munch_variable(len),
             Lookup::Array => return self.munch_array(len),
+            // A marker created partway through compiling a definition would
+            // restore the dictionary to a state containing half of a word.
+            Lookup::Marker => return Err(Error::MarkerInDefinition),
         }
         Ok(*len - start)
     }
@@ -684,7 +795,10 @@ impl<T> Forth<T> {
         }
     }
 
-    fn munch_str(&mut self, len: &mut u16) -> Result<u16, Error> {
+    /// Compiles a string literal as a call to the `builtin` which handles
+    /// it, followed by the string's length in bytes, and the string itself,
+    /// zero-padded to a whole number of words.
+    fn munch_str(&mut self, len: &mut u16, builtin: &str) -> Result<u16, Error> {
         let start = *len;
         self.input
             .advance_str()
@@ -693,32 +807,21 @@ impl<T> Forth<T> {
             .input
             .cur_str_literal()
             .ok_or(Error::LQuoteMissingRQuote)?;
-        let str_len =
-            u16::try_from(lit_str.as_bytes().len()).replace_err(Error::LiteralStringTooLong)?;
+        let str_len = i32::try_from(lit_str.len()).replace_err(Error::LiteralStringTooLong)?;
 
-        let literal_writestr = self.find_word("(write-str)").ok_or(Error::WordNotInDict)?;
-        self.dict
-            .alloc
-            .bump_write::<Word>(Word::ptr(literal_writestr.as_ptr()))?;
+        let literal_bi = self.find_word(builtin).ok_or(Error::WordNotInDict)?;
         self.dict
             .alloc
-            .bump_write::<Word>(Word::data(str_len.into()))?;
-        *len += 2;
-
-        let start_ptr = self
-            .dict
-            .alloc
-            .bump_u8s(lit_str.as_bytes().len())
-            .ok_or(Error::Bump(BumpError::OutOfMemory))?;
+            .bump_write::<Word>(Word::ptr(literal_bi.as_ptr()))?;
+        self.dict.alloc.bump_write::<Word>(Word::data(str_len))?;
+        let (_, words_written) = self.dict.alloc.bump_word_bytes(lit_str.as_bytes())?;
 
-        unsafe {
-            start_ptr
-                .as_ptr()
-                .copy_from_nonoverlapping(lit_str.as_bytes().as_ptr(), lit_str.as_bytes().len());
-        }
-        let word_size = size_of::<Word>();
-        let words_written = (str_len as usize + (word_size - 1)) / word_size;
-        *len += words_written as u16;
+        // The string may be as long as the dictionary allows, but it must
+        // still fit in the definition's parameter field.
+        *len = u16::try_from(words_written)
+            .ok()
+            .and_then(|words| len.checked_add(2)?.checked_add(words))
+            .ok_or(Error::LiteralStringTooLong)?;
 
         Ok(*len - start)
     }
@@ -731,6 +834,8 @@ impl<T> Forth<T> {
             .input
             .cur_word()
             .ok_or(Error::ColonCompileMissingName)?;
+        let mut buf = [0; MAX_NAME_LEN];
+        let name = fold_name(self.case_insensitive, name, &mut buf);
         self.dict.alloc.bump_str(name).map_err(Into::into)
     }
 
@@ -790,6 +895,25 @@ impl<T> Forth<T> {
         entry.finish(name, Self::variable);
         Ok(0)
     }
+
+    // marker NAME
+    fn munch_marker(&mut self) -> Result<(), Error> {
+        // Record the state of the dictionary *before* the marker's name is
+        // allocated, so that executing the marker also removes the marker
+        // itself.
+        let tail = self
+            .dict
+            .tail
+            .map_or(core::ptr::null_mut(), NonNull::as_ptr);
+        let cur = self.dict.alloc.cur;
+        let name = self.munch_name()?;
+        self.dict
+            .build_entry()?
+            .write_word(Word::ptr(tail))?
+            .write_word(Word::ptr(cur))?
+            .finish(name, Self::restore_marker);
+        Ok(())
+    }
 }
 
 /// # Safety
@@ -807,3 +931,25 @@ impl<T> Forth<T> {
 // buffers, but without requiring `liballoc`...idk what that would look like.
 unsafe impl<T: Send> Send for Forth<T> {}
 unsafe impl<T: Sync> Sync for Forth<T> {}
+
+/// Folds `name` to lowercase if the VM is case-insensitive, using `buf` as
+/// scratch space if it contains any uppercase characters.
+///
+/// The folded name is truncated to [`MAX_NAME_LEN`] characters, beyond which
+/// names are not significant.
+fn fold_name<'a>(
+    case_insensitive: bool,
+    name: &'a str,
+    buf: &'a mut [u8; MAX_NAME_LEN],
+) -> &'a str {
+    if !case_insensitive || !name.bytes().any(|b| b.is_ascii_uppercase()) {
+        return name;
+    }
+    let len = name.len().min(MAX_NAME_LEN);
+    for (folded, b) in buf.iter_mut().zip(name.bytes()) {
+        *folded = b.to_ascii_lowercase();
+    }
+    // input is always ASCII, so truncating it can't split a character, but
+    // fall back to the unfolded name rather than panicking if it somehow did.
+    core::str::from_utf8(&buf[..len]).unwrap_or(name)
+}
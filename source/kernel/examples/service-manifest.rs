@@ -0,0 +1,8 @@
+//! Prints the manifest of every service defined by the kernel.
+//!
+//! See the [`kernel::registry::manifest`] module for the manifest's format.
+use kernel::registry::manifest::Manifest;
+
+fn main() {
+    print!("{}", Manifest::kernel());
+}
@@ -0,0 +1,574 @@
+//! # Boot Progress Tracking
+//!
+//! Kernel and platform initialization is made up of many futures spawned with
+//! [`Kernel::initialize`], most of which register a driver service. If one of
+//! them fails, or never completes, the service it provides silently never
+//! comes up, and anything that depends on it waits forever.
+//!
+//! To make this visible, initialization futures may instead be spawned as
+//! named *boot steps*, using [`Kernel::initialize_step`]. Each step may
+//! declare the names of other steps that it depends on; a step does not start
+//! until all of its dependencies have completed, and is marked as
+//! [`StepState::Blocked`] if any of them fail. Structured `tracing` events are
+//! emitted as each step starts, completes, or fails, and once every
+//! registered step has finished, a summary of the whole boot is logged.
+//!
+//! The current state of every step can be inspected through
+//! [`Kernel::boot_progress`], and is reported to the host by the SerMux debug
+//! agent (see [`crate::daemons::sermux`]), along with each step's
+//! dependencies.
+//!
+//! Since boot steps do not run until the scheduler is first ticked, all steps
+//! registered before the platform starts running the kernel are considered
+//! part of the same boot.
+
+use core::{any::Any, fmt, future::Future};
+
+use maitake::{
+    sync::{Mutex, WaitQueue},
+    task::JoinHandle,
+    time::{Duration, Instant, Timer},
+};
+use tracing::{self, Instrument, Level};
+
+use crate::{registry::RegistrationError, Error, InitializeError, Kernel};
+
+/// The maximum number of boot steps that may be registered.
+pub const MAX_STEPS: usize = 32;
+
+/// Tracks the progress of named boot steps.
+pub struct BootProgress {
+    steps: Mutex<heapless::Vec<Step, MAX_STEPS>>,
+    /// Woken whenever a step finishes, so that steps waiting on their
+    /// dependencies can check whether they may start.
+    finished: WaitQueue,
+}
+
+/// A snapshot of a single boot step's progress.
+#[derive(Debug, Clone, Copy)]
+pub struct Step {
+    /// The name of this step.
+    pub name: &'static str,
+    /// The names of the steps this step depends on.
+    pub deps: &'static [&'static str],
+    /// The current state of this step.
+    pub state: StepState,
+    /// If the step failed with a kernel [`Error`] (or a
+    /// [`RegistrationError`], which is converted into one), the error.
+    ///
+    /// Errors of other types are only logged when the step fails.
+    pub error: Option<Error>,
+}
+
+/// The state of a boot step.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepState {
+    /// The step is waiting for its dependencies to complete.
+    Pending,
+    /// The step is running.
+    Running { started: Instant },
+    /// The step completed successfully.
+    Complete { elapsed: Duration },
+    /// The step returned an error.
+    Failed { elapsed: Duration },
+    /// One of the step's dependencies failed (or was itself blocked), so the
+    /// step never ran.
+    Blocked { dependency: &'static str },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegisterError {
+    /// A step with this name has already been registered.
+    Duplicate(&'static str),
+    /// [`MAX_STEPS`] steps have already been registered.
+    Full,
+    /// The step list was locked by a running task.
+    ///
+    /// Steps should be registered before the kernel starts running. Once it
+    /// has started, a step may not be registered while another task is
+    /// updating or inspecting boot progress.
+    Busy,
+}
+
+/// Counts of boot steps in each [`StepState`], returned by
+/// [`BootProgress::summary`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BootSummary {
+    pub pending: usize,
+    pub running: usize,
+    pub complete: usize,
+    pub failed: usize,
+    pub blocked: usize,
+}
+
+// === impl BootProgress ===
+
+impl BootProgress {
+    pub(crate) const fn new() -> Self {
+        Self {
+            steps: Mutex::new(heapless::Vec::new()),
+            finished: WaitQueue::new(),
+        }
+    }
+
+    /// Returns a snapshot of the step named `name`, if one has been
+    /// registered.
+    pub async fn step(&self, name: &str) -> Option<Step> {
+        self.steps
+            .lock()
+            .await
+            .iter()
+            .find(|step| step.name == name)
+            .copied()
+    }
+
+    /// Calls `f` with a snapshot of every registered step, in the order they
+    /// were registered.
+    pub async fn for_each_step(&self, mut f: impl FnMut(&Step)) {
+        for step in self.steps.lock().await.iter() {
+            f(step);
+        }
+    }
+
+    /// Returns the number of registered steps in each state.
+    pub async fn summary(&self) -> BootSummary {
+        let mut summary = BootSummary::default();
+        for step in self.steps.lock().await.iter() {
+            let count = match step.state {
+                StepState::Pending => &mut summary.pending,
+                StepState::Running { .. } => &mut summary.running,
+                StepState::Complete { .. } => &mut summary.complete,
+                StepState::Failed { .. } => &mut summary.failed,
+                StepState::Blocked { .. } => &mut summary.blocked,
+            };
+            *count += 1;
+        }
+        summary
+    }
+
+    /// Returns `true` if every registered step has finished, successfully or
+    /// otherwise.
+    pub async fn is_finished(&self) -> bool {
+        self.steps.lock().await.iter().all(Step::is_finished)
+    }
+
+    /// Waits until every registered step has finished, successfully or
+    /// otherwise.
+    ///
+    /// This must not be called from within a boot step, as that step would
+    /// then never finish.
+    pub async fn wait_until_finished(&self) {
+        loop {
+            let wait = self.finished.wait();
+            if self.is_finished().await {
+                return;
+            }
+            let _ = wait.await;
+        }
+    }
+
+    /// Waits until every registered step has finished, and returns an
+    /// [`InitializeError`] for each step that failed or was blocked, in the
+    /// order the steps were registered.
+    ///
+    /// If a step failed with an error that the kernel doesn't recognize (see
+    /// [`Step::error`]), its reason is [`Error::StepFailed`]. Like
+    /// [`BootProgress::wait_until_finished`], this must not be called from
+    /// within a boot step.
+    pub async fn failures(&self) -> heapless::Vec<InitializeError, MAX_STEPS> {
+        self.wait_until_finished().await;
+        self.steps
+            .lock()
+            .await
+            .iter()
+            .filter_map(|step| {
+                let reason = match step.state {
+                    StepState::Failed { .. } => step.error.unwrap_or(Error::StepFailed),
+                    StepState::Blocked { dependency } => Error::StepBlocked { dependency },
+                    _ => return None,
+                };
+                Some(InitializeError {
+                    name: step.name,
+                    reason,
+                })
+            })
+            .collect()
+    }
+
+    fn register(
+        &self,
+        name: &'static str,
+        deps: &'static [&'static str],
+    ) -> Result<(), RegisterError> {
+        // Steps are normally registered synchronously during initialization,
+        // before the scheduler runs, so nothing else can be holding the lock.
+        // If a step is registered later, another task may be holding it.
+        let mut steps = self.steps.try_lock().ok_or(RegisterError::Busy)?;
+        if steps.iter().any(|step| step.name == name) {
+            return Err(RegisterError::Duplicate(name));
+        }
+        steps
+            .push(Step {
+                name,
+                deps,
+                state: StepState::Pending,
+                error: None,
+            })
+            .map_err(|_| RegisterError::Full)
+    }
+
+    /// Waits until all of `deps` have completed, returning the name of the
+    /// first dependency that failed or was blocked, if any did.
+    async fn wait_for_deps(&self, deps: &[&'static str]) -> Result<(), &'static str> {
+        loop {
+            let wait = self.finished.wait();
+            {
+                let steps = self.steps.lock().await;
+                let mut ready = true;
+                for &dep in deps {
+                    match steps.iter().find(|step| step.name == dep) {
+                        Some(Step {
+                            state: StepState::Complete { .. },
+                            ..
+                        }) => {}
+                        Some(step) if step.is_finished() => return Err(dep),
+                        Some(_) => ready = false,
+                        None => {
+                            tracing::warn!(
+                                dependency = dep,
+                                "boot step depends on an unknown step"
+                            );
+                            return Err(dep);
+                        }
+                    }
+                }
+                if ready {
+                    return Ok(());
+                }
+            }
+            let _ = wait.await;
+        }
+    }
+
+    async fn set_state(&self, name: &'static str, state: StepState) {
+        self.update(name, |step| step.state = state).await;
+    }
+
+    async fn update(&self, name: &'static str, f: impl FnOnce(&mut Step)) {
+        let mut steps = self.steps.lock().await;
+        if let Some(step) = steps.iter_mut().find(|step| step.name == name) {
+            f(step);
+        }
+    }
+
+    /// Marks a step as finished, waking any steps waiting on it, and logs the
+    /// boot summary if it was the last step to finish.
+    async fn finish(
+        &self,
+        name: &'static str,
+        state: StepState,
+        error: Option<Error>,
+        timer: &Timer,
+        boot_started: Instant,
+    ) {
+        self.update(name, |step| {
+            step.state = state;
+            step.error = error;
+        })
+        .await;
+        self.finished.wake_all();
+
+        let steps = self.steps.lock().await;
+        if !steps.iter().all(Step::is_finished) {
+            return;
+        }
+
+        let mut failed = 0;
+        let mut blocked = 0;
+        for step in steps.iter() {
+            match step.state {
+                StepState::Failed { .. } => failed += 1,
+                StepState::Blocked { .. } => blocked += 1,
+                _ => {}
+            }
+        }
+        let elapsed = timer.now().duration_since(boot_started);
+        if failed == 0 && blocked == 0 {
+            tracing::info!(
+                steps = steps.len(),
+                elapsed_ms = elapsed.as_millis() as u64,
+                "boot complete in {elapsed:?}"
+            );
+        } else {
+            tracing::error!(
+                steps = steps.len(),
+                failed,
+                blocked,
+                elapsed_ms = elapsed.as_millis() as u64,
+                "boot finished with errors in {elapsed:?}"
+            );
+        }
+    }
+}
+
+impl fmt::Debug for BootProgress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut s = f.debug_struct("BootProgress");
+        match self.steps.try_lock() {
+            Some(steps) => s.field("steps", &&steps[..]),
+            None => s.field("steps", &format_args!("<locked>")),
+        };
+        s.finish()
+    }
+}
+
+// === impl Step ===
+
+impl Step {
+    /// Returns `true` if this step has finished, successfully or otherwise.
+    #[must_use]
+    pub fn is_finished(&self) -> bool {
+        !matches!(self.state, StepState::Pending | StepState::Running { .. })
+    }
+}
+
+// === impl Kernel ===
+
+impl Kernel {
+    /// Returns the kernel's [`BootProgress`] tracker.
+    #[inline]
+    #[must_use]
+    pub fn boot_progress(&'static self) -> &'static BootProgress {
+        &self.boot
+    }
+
+    /// Spawn an initialization future as a named boot step.
+    ///
+    /// This behaves like [`Kernel::initialize`], except that the future does
+    /// not start until every step named in `deps` has completed, and its
+    /// progress is recorded in the kernel's [`BootProgress`]. If any
+    /// dependency fails, `fut` is never polled, and the returned
+    /// [`JoinHandle`] completes with `None`.
+    ///
+    /// If `fut` fails with a kernel [`Error`] or a [`RegistrationError`], the
+    /// error is recorded in the step's [`Step::error`].
+    #[track_caller]
+    pub fn initialize_step<F, E>(
+        &'static self,
+        name: &'static str,
+        deps: &'static [&'static str],
+        fut: F,
+    ) -> Result<JoinHandle<Option<F::Output>>, Error>
+    where
+        F: Future<Output = Result<(), E>> + 'static,
+        E: fmt::Debug + 'static,
+    {
+        let progress = self.boot_progress();
+        progress.register(name, deps)?;
+
+        // attribute allocations made by the step to it, so that they can be
+        // found when looking for leaks.
+        #[cfg(feature = "alloc-tags")]
+        let fut = crate::mnemos_alloc::heap::tags::scope(name, fut);
+
+        let timer = self.timer();
+        let boot_started = timer.now();
+        let step = async move {
+            if let Err(dependency) = progress.wait_for_deps(deps).await {
+                tracing::error!(dependency, "boot step blocked by failed dependency");
+                progress
+                    .finish(
+                        name,
+                        StepState::Blocked { dependency },
+                        None,
+                        timer,
+                        boot_started,
+                    )
+                    .await;
+                return None;
+            }
+
+            let started = timer.now();
+            tracing::debug!("boot step started");
+            progress
+                .set_state(name, StepState::Running { started })
+                .await;
+
+            let res = fut.await;
+            let elapsed = timer.now().duration_since(started);
+            let (state, error) = match res {
+                Ok(()) => {
+                    tracing::info!(
+                        elapsed_ms = elapsed.as_millis() as u64,
+                        "boot step complete"
+                    );
+                    (StepState::Complete { elapsed }, None)
+                }
+                Err(ref error) => {
+                    tracing::error!(
+                        ?error,
+                        elapsed_ms = elapsed.as_millis() as u64,
+                        "boot step failed"
+                    );
+                    (StepState::Failed { elapsed }, kernel_error(error))
+                }
+            };
+            progress
+                .finish(name, state, error, timer, boot_started)
+                .await;
+            Some(res)
+        }
+        .instrument(tracing::span!(Level::INFO, "boot_step", step = name));
+
+        self.initialize(step)
+    }
+}
+
+/// Returns the kernel [`Error`] that a failed step's `error` represents, if
+/// it is one.
+fn kernel_error(error: &dyn Any) -> Option<Error> {
+    if let Some(&error) = error.downcast_ref::<Error>() {
+        return Some(error);
+    }
+    error
+        .downcast_ref::<RegistrationError>()
+        .map(|&error| Error::from(error))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TestKernel;
+
+    #[test]
+    fn failed_dependency_blocks_step() {
+        TestKernel::run(|k| async move {
+            let a = k
+                .initialize_step("a", &[], async { Err::<(), _>("oh no") })
+                .unwrap();
+            let b = k
+                .initialize_step("b", &["a"], async { Ok::<(), &str>(()) })
+                .unwrap();
+            let c = k
+                .initialize_step("c", &[], async { Ok::<(), &str>(()) })
+                .unwrap();
+
+            assert_eq!(a.await.unwrap(), Some(Err("oh no")));
+            assert_eq!(b.await.unwrap(), None);
+            assert_eq!(c.await.unwrap(), Some(Ok(())));
+
+            let progress = k.boot_progress();
+            assert!(progress.is_finished().await);
+            assert!(matches!(
+                progress.step("a").await.unwrap().state,
+                StepState::Failed { .. }
+            ));
+            assert_eq!(
+                progress.step("b").await.unwrap().state,
+                StepState::Blocked { dependency: "a" }
+            );
+            assert!(matches!(
+                progress.step("c").await.unwrap().state,
+                StepState::Complete { .. }
+            ));
+            assert_eq!(
+                progress.summary().await,
+                BootSummary {
+                    complete: 1,
+                    failed: 1,
+                    blocked: 1,
+                    ..Default::default()
+                }
+            );
+        })
+    }
+
+    #[test]
+    fn step_errors() {
+        TestKernel::run(|k| async move {
+            let full = k
+                .initialize_step("full", &[], async {
+                    Err::<(), _>(RegistrationError::RegistryFull)
+                })
+                .unwrap();
+            let other = k
+                .initialize_step("other", &[], async { Err::<(), _>("oh no") })
+                .unwrap();
+            assert!(full.await.unwrap().is_some());
+            assert!(other.await.unwrap().is_some());
+
+            let progress = k.boot_progress();
+            assert_eq!(
+                progress.step("full").await.unwrap().error,
+                Some(Error::RegistryFull)
+            );
+            // the kernel doesn't know what this error means.
+            assert_eq!(progress.step("other").await.unwrap().error, None);
+        })
+    }
+
+    #[test]
+    fn failures() {
+        TestKernel::run(|k| async move {
+            k.initialize_step("full", &[], async {
+                Err::<(), _>(RegistrationError::RegistryFull)
+            })
+            .unwrap();
+            k.initialize_step("other", &[], async { Err::<(), _>("oh no") })
+                .unwrap();
+            k.initialize_step("blocked", &["full"], async { Ok::<(), &str>(()) })
+                .unwrap();
+            k.initialize_step("ok", &[], async { Ok::<(), &str>(()) })
+                .unwrap();
+
+            let failures = k.boot_progress().failures().await;
+            assert_eq!(
+                &failures[..],
+                &[
+                    InitializeError {
+                        name: "full",
+                        reason: Error::RegistryFull,
+                    },
+                    InitializeError {
+                        name: "other",
+                        reason: Error::StepFailed,
+                    },
+                    InitializeError {
+                        name: "blocked",
+                        reason: Error::StepBlocked { dependency: "full" },
+                    },
+                ]
+            );
+        })
+    }
+
+    #[test]
+    fn register_while_locked() {
+        TestKernel::run(|k| async move {
+            let steps = k.boot_progress().steps.lock().await;
+            let res = k.initialize_step("late", &[], async { Ok::<(), &str>(()) });
+            assert_eq!(res.err(), Some(Error::BootStep(RegisterError::Busy)));
+            drop(steps);
+
+            let late = k
+                .initialize_step("late", &[], async { Ok::<(), &str>(()) })
+                .unwrap();
+            assert_eq!(late.await.unwrap(), Some(Ok(())));
+        })
+    }
+
+    #[test]
+    fn duplicate_step() {
+        TestKernel::run(|k| async move {
+            let a = k
+                .initialize_step("a", &[], async { Ok::<(), &str>(()) })
+                .unwrap();
+            let dup = k.initialize_step("a", &[], async { Ok::<(), &str>(()) });
+            assert_eq!(
+                dup.err(),
+                Some(Error::BootStep(RegisterError::Duplicate("a")))
+            );
+            assert_eq!(a.await.unwrap(), Some(Ok(())));
+        })
+    }
+}
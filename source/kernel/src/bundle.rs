@@ -0,0 +1,184 @@
+//! # Boot Bundles
+//!
+//! Loads the auxiliary payloads in a [boot bundle](abi::bundle), which the
+//! platform implementation locates at boot (for example, the x86_64
+//! bootloader's ramdisk). This allows a board's configuration and boot
+//! scripts to be changed without rebuilding the kernel.
+//!
+//! A [`BootBundle`] is applied to the [`KernelServiceSettings`] before the
+//! kernel's services are registered, using [`BootBundle::apply`]:
+//!
+//! - A [config](Kind::Config) entry named [`BootBundle::SERVICES_CONFIG`]
+//!   replaces the service settings. It must contain a postcard-encoded
+//!   [`KernelServiceSettings`].
+//! - [Forth script](Kind::ForthScript) entries are run as the spawnulator's
+//!   [boot script](crate::services::forth_spawnulator#boot-scripts), in the
+//!   order they appear in the bundle.
+//!
+//! Other config entries may be read by platform implementations using
+//! [`BootBundle::config`]. There is not yet a filesystem service to mount a
+//! bundled [filesystem image](Kind::FsImage), but it can be located with
+//! [`BootBundle::fs_image`].
+
+use abi::bundle::{self, Bundle, Entry, Kind};
+use serde::Deserialize;
+use tracing::Level;
+
+use crate::{services::forth_spawnulator::MAX_BOOT_SCRIPT_LEN, KernelServiceSettings};
+
+/// A boot bundle, which has been located by the platform implementation.
+#[derive(Debug, Clone, Copy)]
+pub struct BootBundle {
+    bundle: Bundle<'static>,
+}
+
+impl BootBundle {
+    /// The name of the config entry containing the [`KernelServiceSettings`].
+    pub const SERVICES_CONFIG: &'static str = "services";
+
+    /// Parse a boot bundle from `bytes`.
+    #[tracing::instrument(
+        name = "BootBundle::parse",
+        level = Level::INFO,
+        skip(bytes),
+        fields(len = bytes.len()),
+        err(Display),
+    )]
+    pub fn parse(bytes: &'static [u8]) -> Result<Self, bundle::Error> {
+        let bundle = Bundle::parse(bytes)?;
+        for Entry { kind, name, data } in bundle.entries() {
+            tracing::info!(?kind, name, len = data.len(), "found boot bundle entry");
+        }
+        Ok(Self { bundle })
+    }
+
+    /// Returns an iterator over every entry in the bundle.
+    pub fn entries(&self) -> impl Iterator<Item = Entry<'static>> {
+        self.bundle.entries()
+    }
+
+    /// Decodes the [config](Kind::Config) entry named `name`, if there is
+    /// one.
+    pub fn config<T: Deserialize<'static>>(
+        &self,
+        name: &str,
+    ) -> Option<Result<T, postcard::Error>> {
+        let entry = self.bundle.get(Kind::Config, name)?;
+        Some(postcard::from_bytes(entry.data))
+    }
+
+    /// Returns the bundle's [Forth script](Kind::ForthScript) entries,
+    /// concatenated into a single boot script, or `None` if there aren't any.
+    pub fn boot_script(
+        &self,
+    ) -> Result<Option<heapless::String<MAX_BOOT_SCRIPT_LEN>>, &'static str> {
+        let mut scripts = self
+            .bundle
+            .entries()
+            .filter(|entry| entry.kind == Kind::ForthScript)
+            .peekable();
+        if scripts.peek().is_none() {
+            return Ok(None);
+        }
+
+        const TOO_LONG: &str = "boot scripts are too long";
+        let mut script = heapless::String::new();
+        for entry in scripts {
+            let src = core::str::from_utf8(entry.data).map_err(|_| "boot script is not UTF-8")?;
+            script.push_str(src).map_err(|_| TOO_LONG)?;
+            if !src.ends_with('\n') {
+                script.push('\n').map_err(|_| TOO_LONG)?;
+            }
+        }
+        Ok(Some(script))
+    }
+
+    /// Returns the bundle's [filesystem image](Kind::FsImage), if it has one.
+    pub fn fs_image(&self) -> Option<&'static [u8]> {
+        self.bundle
+            .entries()
+            .find(|entry| entry.kind == Kind::FsImage)
+            .map(|entry| entry.data)
+    }
+
+    /// Applies the bundle to the kernel's service `settings`, as described
+    /// in the [module-level documentation](self).
+    ///
+    /// Invalid entries are logged and skipped, so that a bad bundle does not
+    /// keep the kernel from booting.
+    pub fn apply(&self, settings: &mut KernelServiceSettings) {
+        match self.config(Self::SERVICES_CONFIG) {
+            Some(Ok(services)) => {
+                tracing::info!("using service settings from the boot bundle");
+                *settings = services;
+            }
+            Some(Err(error)) => {
+                tracing::error!(%error, "invalid service settings in the boot bundle")
+            }
+            None => {}
+        }
+
+        match self.boot_script() {
+            Ok(Some(script)) => {
+                tracing::info!(len = script.len(), "using boot script from the boot bundle");
+                settings.spawnulator.enabled = true;
+                settings.spawnulator.boot_script = Some(script);
+            }
+            Ok(None) => {}
+            Err(error) => tracing::error!(error, "invalid boot script in the boot bundle"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use abi::bundle::{EntryHeader, Header};
+
+    fn build(entries: &[(Kind, &str, &[u8])]) -> &'static [u8] {
+        let header = Header {
+            entry_count: entries.len() as u16,
+            len: 0,
+        };
+        let mut out = header.to_bytes().to_vec();
+        let mut offset = header.payloads_offset();
+        for (kind, name, data) in entries {
+            let entry = EntryHeader::new(*kind, name, offset as u32, data.len() as u32).unwrap();
+            out.extend_from_slice(&entry.to_bytes());
+            offset = (offset + data.len()).next_multiple_of(bundle::ALIGN);
+        }
+        for (_, _, data) in entries {
+            out.resize(out.len().next_multiple_of(bundle::ALIGN), 0);
+            out.extend_from_slice(data);
+        }
+        out[8..12].copy_from_slice(&(out.len() as u32).to_le_bytes());
+        out.leak()
+    }
+
+    #[test]
+    fn applies_config_and_scripts() {
+        let services = KernelServiceSettings {
+            alarm: crate::services::alarm::AlarmSettings {
+                enabled: false,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let services = postcard::to_stdvec(&services).unwrap();
+        let bytes = build(&[
+            (Kind::ForthScript, "leds", b"1 2 +"),
+            (Kind::Config, BootBundle::SERVICES_CONFIG, &services),
+            (Kind::ForthScript, "ports", b"3 4 +\n"),
+        ]);
+
+        let bundle = BootBundle::parse(bytes).unwrap();
+        let mut settings = KernelServiceSettings::default();
+        bundle.apply(&mut settings);
+        assert!(!settings.alarm.enabled);
+        assert_eq!(
+            settings.spawnulator.boot_script.as_deref(),
+            Some("1 2 +\n3 4 +\n")
+        );
+        assert_eq!(bundle.fs_image(), None);
+    }
+}
@@ -3,15 +3,45 @@
 //!
 //! This extends the underlying bbqueue type exposed by the ABI crate, allowing
 //! for async kernel-to-kernel (including driver services) usage.
+//!
+//! Channels carry a stream of bytes by default. Channels created with
+//! [`new_framed_spsc_channel`] instead carry discrete, length-prefixed
+//! frames, for consumers that would otherwise need to delimit the stream
+//! themselves.
+//!
+//! # Closing
+//!
+//! A channel is *closed* when either of its halves is dropped (for
+//! [`MpscProducer`]s, once every clone has been dropped), or when either half
+//! calls `close()`. Closing a channel does not discard data that has already
+//! been committed, and it does not wake tasks waiting for a grant, so tasks
+//! which need to notice that the other side has gone away should also wait
+//! for `closed()`, for example in a `select!`.
+//!
+//! A [`BidiHandle`] is closed if either of its directions is closed, so
+//! dropping one end of a bidirectional channel is seen by the other end.
 
-use core::ops::{Deref, DerefMut};
+use core::{
+    mem::ManuallyDrop,
+    ops::{Deref, DerefMut},
+    ptr,
+};
 
 use crate::fmt;
+use abi::bbqueue_ipc::framed::{
+    FrameConsumer as InnerFrameConsumer, FrameGrantR as InnerFrameGrantR,
+    FrameGrantW as InnerFrameGrantW, FrameProducer as InnerFrameProducer,
+};
 use abi::bbqueue_ipc::{BBBuffer, Consumer as InnerConsumer, Producer as InnerProducer};
-use abi::bbqueue_ipc::{GrantR as InnerGrantR, GrantW as InnerGrantW};
+use abi::bbqueue_ipc::{
+    Error as InnerError, GrantR as InnerGrantR, GrantW as InnerGrantW,
+    SplitGrantR as InnerSplitGrantR,
+};
+use futures::FutureExt;
 use maitake::sync::Mutex;
-use maitake::sync::WaitCell;
+use maitake::sync::{WaitCell, WaitQueue};
 use mnemos_alloc::containers::{Arc, ArrayBuf};
+use portable_atomic::{AtomicBool, AtomicUsize, Ordering};
 use tracing::{self, info, trace};
 
 struct BBQStorage {
@@ -20,11 +50,31 @@ struct BBQStorage {
     // note: producer lives here so we don't need a separate Arc just for the
     // Mutex<InnerProducer>. consumer is owned by the consumer handle.
     producer: Mutex<Option<InnerProducer<'static>>>,
+    /// The number of live [`MpscProducer`]s.
+    mpsc_producers: AtomicUsize,
+    closed: AtomicBool,
+    /// Closed when the channel is closed.
+    closed_waitq: WaitQueue,
+    /// Woken when a read grant is released or dropped, so that readers
+    /// waiting for another read grant to finish can try again.
+    read_waitq: WaitQueue,
 
     ring: BBBuffer,
     _array: ArrayBuf<u8>,
 }
 
+/// A snapshot of how full a channel is, returned by the `depth` method of
+/// each channel half.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BufferDepth {
+    /// The number of bytes which have been written, but not yet read.
+    ///
+    /// For framed channels, this includes each frame's length header.
+    pub committed: usize,
+    /// The size of the channel's buffer, in bytes.
+    pub capacity: usize,
+}
+
 pub struct BidiHandle {
     producer: SpscProducer,
     consumer: Consumer,
@@ -42,6 +92,26 @@ impl BidiHandle {
     pub fn split(self) -> (SpscProducer, Consumer) {
         (self.producer, self.consumer)
     }
+
+    /// Closes both directions of the channel.
+    pub fn close(&self) {
+        self.producer.close();
+        self.consumer.close();
+    }
+
+    /// Returns `true` if either direction of the channel has been closed.
+    #[must_use]
+    pub fn is_closed(&self) -> bool {
+        self.producer.is_closed() || self.consumer.is_closed()
+    }
+
+    /// Waits until either direction of the channel has been closed.
+    pub async fn closed(&self) {
+        futures::select_biased! {
+            _ = self.producer.closed().fuse() => {},
+            _ = self.consumer.closed().fuse() => {},
+        }
+    }
 }
 
 pub async fn new_bidi_channel(capacity_a: usize, capacity_b: usize) -> (BidiHandle, BidiHandle) {
@@ -63,7 +133,6 @@ pub struct SpscProducer {
     producer: InnerProducer<'static>,
 }
 
-#[derive(Clone)]
 pub struct MpscProducer {
     storage: Arc<BBQStorage>,
 }
@@ -73,33 +142,34 @@ pub struct Consumer {
     consumer: InnerConsumer<'static>,
 }
 
+/// The producer half of a framed channel, created by [`new_framed_spsc_channel`].
+pub struct FramedProducer {
+    storage: Arc<BBQStorage>,
+    producer: InnerFrameProducer<'static>,
+}
+
+/// The consumer half of a framed channel, created by [`new_framed_spsc_channel`].
+pub struct FramedConsumer {
+    storage: Arc<BBQStorage>,
+    consumer: InnerFrameConsumer<'static>,
+}
+
 impl SpscProducer {
     pub async fn into_mpmc_producer(self) -> MpscProducer {
-        let SpscProducer { storage, producer } = self;
+        // Don't run `SpscProducer`'s `Drop` impl, which would close the
+        // channel.
+        let this = ManuallyDrop::new(self);
+        // Safety: `this` is never used again, or dropped.
+        let (storage, producer) = unsafe { (ptr::read(&this.storage), ptr::read(&this.producer)) };
         *storage.producer.lock().await = Some(producer);
+        storage.mpsc_producers.fetch_add(1, Ordering::Relaxed);
         MpscProducer { storage }
     }
 }
 
 pub async fn new_spsc_channel(capacity: usize) -> (SpscProducer, Consumer) {
     info!(capacity, "Creating new mpsc BBQueue channel");
-    let mut _array = ArrayBuf::new_uninit(capacity).await;
-
-    let ring = BBBuffer::new();
-
-    unsafe {
-        let (ptr, len) = _array.ptrlen();
-        ring.initialize(ptr.as_ptr().cast(), len);
-    }
-
-    let storage = Arc::new(BBQStorage {
-        commit_waitcell: WaitCell::new(),
-        release_waitcell: WaitCell::new(),
-        producer: Mutex::new(None),
-        ring,
-        _array,
-    })
-    .await;
+    let storage = new_storage(capacity).await;
 
     // Now that we've allocated storage, the producer can be created.
 
@@ -126,6 +196,188 @@ pub async fn new_spsc_channel(capacity: usize) -> (SpscProducer, Consumer) {
     (prod, cons)
 }
 
+/// Create a new single-producer, single-consumer channel in *framed* mode.
+///
+/// Rather than a stream of bytes, a framed channel carries discrete frames:
+/// each write grant is committed as a single frame, and each read grant
+/// contains exactly one frame, as it was committed. This means consumers of
+/// framed data don't need to scan the stream for frame delimiters.
+///
+/// Each frame is stored with a two byte length header, and is never split
+/// across the end of the ring, so the channel's `capacity` should be
+/// comfortably larger than the largest frame that will be sent. Frames may be
+/// at most `u16::MAX - 2` bytes long.
+pub async fn new_framed_spsc_channel(capacity: usize) -> (FramedProducer, FramedConsumer) {
+    info!(capacity, "Creating new framed spsc BBQueue channel");
+    let storage = new_storage(capacity).await;
+
+    let bbbuffer = &storage.ring as *const BBBuffer as *mut BBBuffer;
+
+    let (prod, cons) = unsafe {
+        let prod = BBBuffer::take_framed_producer(bbbuffer);
+        let cons = BBBuffer::take_framed_consumer(bbbuffer);
+
+        (prod, cons)
+    };
+
+    let prod = FramedProducer {
+        storage: storage.clone(),
+        producer: prod,
+    };
+    let cons = FramedConsumer {
+        storage,
+        consumer: cons,
+    };
+
+    info!("Channel created successfully");
+
+    (prod, cons)
+}
+
+async fn new_storage(capacity: usize) -> Arc<BBQStorage> {
+    let mut _array = ArrayBuf::new_uninit(capacity).await;
+
+    let ring = BBBuffer::new();
+
+    unsafe {
+        let (ptr, len) = _array.ptrlen();
+        ring.initialize(ptr.as_ptr().cast(), len);
+    }
+
+    Arc::new(BBQStorage {
+        commit_waitcell: WaitCell::new(),
+        release_waitcell: WaitCell::new(),
+        producer: Mutex::new(None),
+        mpsc_producers: AtomicUsize::new(0),
+        closed: AtomicBool::new(false),
+        closed_waitq: WaitQueue::new(),
+        read_waitq: WaitQueue::new(),
+        ring,
+        _array,
+    })
+    .await
+}
+
+impl BBQStorage {
+    fn close(&self) {
+        if !self.closed.swap(true, Ordering::AcqRel) {
+            trace!(queue = ?fmt::ptr(self), "Closed bbqueue channel");
+            self.closed_waitq.close();
+        }
+    }
+
+    fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::Acquire)
+    }
+
+    async fn closed(&self) {
+        // The wait queue is only ever woken by closing it.
+        let _ = self.closed_waitq.wait().await;
+    }
+}
+
+/// Implements the [closing](self#closing) methods for a channel half.
+macro_rules! impl_close {
+    ($($half:ty),+ $(,)?) => {
+        $(
+            impl $half {
+                /// Closes the channel.
+                ///
+                /// Data which has already been committed can still be read.
+                pub fn close(&self) {
+                    self.storage.close()
+                }
+
+                /// Returns `true` if the channel has been closed.
+                #[must_use]
+                pub fn is_closed(&self) -> bool {
+                    self.storage.is_closed()
+                }
+
+                /// Waits until the channel has been closed.
+                pub async fn closed(&self) {
+                    self.storage.closed().await
+                }
+            }
+        )+
+    };
+}
+
+impl_close!(
+    SpscProducer,
+    MpscProducer,
+    Consumer,
+    FramedProducer,
+    FramedConsumer,
+);
+
+/// Implements [`depth`](SpscProducer::depth) for a channel half.
+macro_rules! impl_depth {
+    ($($half:ty),+ $(,)?) => {
+        $(
+            impl $half {
+                /// Returns how full the channel currently is.
+                #[must_use]
+                pub fn depth(&self) -> BufferDepth {
+                    BufferDepth {
+                        committed: self.storage.ring.committed(),
+                        capacity: self.storage.ring.capacity(),
+                    }
+                }
+            }
+        )+
+    };
+}
+
+impl_depth!(
+    SpscProducer,
+    MpscProducer,
+    Consumer,
+    FramedProducer,
+    FramedConsumer,
+);
+
+impl Drop for SpscProducer {
+    fn drop(&mut self) {
+        self.storage.close();
+    }
+}
+
+impl Clone for MpscProducer {
+    fn clone(&self) -> Self {
+        self.storage.mpsc_producers.fetch_add(1, Ordering::Relaxed);
+        Self {
+            storage: self.storage.clone(),
+        }
+    }
+}
+
+impl Drop for MpscProducer {
+    fn drop(&mut self) {
+        if self.storage.mpsc_producers.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.storage.close();
+        }
+    }
+}
+
+impl Drop for Consumer {
+    fn drop(&mut self) {
+        self.storage.close();
+    }
+}
+
+impl Drop for FramedProducer {
+    fn drop(&mut self) {
+        self.storage.close();
+    }
+}
+
+impl Drop for FramedConsumer {
+    fn drop(&mut self) {
+        self.storage.close();
+    }
+}
+
 pub struct GrantW {
     grant: InnerGrantW<'static>,
     storage: Arc<BBQStorage>,
@@ -155,10 +407,18 @@ impl GrantW {
             self.storage.commit_waitcell.wake();
         }
     }
+
+    /// Copies `buf` to the start of the grant, and commits it.
+    fn write_all(mut self, buf: &[u8]) {
+        self[..buf.len()].copy_from_slice(buf);
+        self.commit(buf.len());
+    }
 }
 
 pub struct GrantR {
-    grant: InnerGrantR<'static>,
+    // Dropped by hand, so that readers waiting for the grant are only woken
+    // once it's gone.
+    grant: ManuallyDrop<InnerGrantR<'static>>,
     storage: Arc<BBQStorage>,
 }
 
@@ -167,30 +427,119 @@ impl Deref for GrantR {
 
     #[inline(always)]
     fn deref(&self) -> &Self::Target {
-        self.grant.deref()
+        &self.grant
     }
 }
 
 impl DerefMut for GrantR {
     #[inline(always)]
     fn deref_mut(&mut self) -> &mut Self::Target {
-        self.grant.deref_mut()
+        &mut self.grant
     }
 }
 
 impl GrantR {
-    pub fn release(self, used: usize) {
-        self.grant.release(used);
+    fn new(grant: InnerGrantR<'static>, storage: Arc<BBQStorage>) -> Self {
+        Self {
+            grant: ManuallyDrop::new(grant),
+            storage,
+        }
+    }
+
+    pub fn release(mut self, used: usize) {
+        // Dropping the grant releases the bytes.
+        self.grant.to_release(used);
+        let storage = self.storage.clone();
+        drop(self);
         // If we freed up any space, notify the waker on the reader side
         if used != 0 {
-            self.storage.release_waitcell.wake();
+            storage.release_waitcell.wake();
         }
     }
 }
 
+impl Drop for GrantR {
+    fn drop(&mut self) {
+        // Safety: the grant is never used again.
+        unsafe { ManuallyDrop::drop(&mut self.grant) };
+        self.storage.read_waitq.wake_all();
+    }
+}
+
 unsafe impl Send for GrantR {}
 unsafe impl Sync for GrantR {}
 
+/// A write grant for a single frame on a framed channel.
+///
+/// If the grant is dropped without being committed, no frame is sent.
+pub struct FrameGrantW {
+    grant: InnerFrameGrantW<'static>,
+    storage: Arc<BBQStorage>,
+}
+
+impl Deref for FrameGrantW {
+    type Target = [u8];
+
+    #[inline(always)]
+    fn deref(&self) -> &Self::Target {
+        self.grant.deref()
+    }
+}
+
+impl DerefMut for FrameGrantW {
+    #[inline(always)]
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.grant.deref_mut()
+    }
+}
+
+impl FrameGrantW {
+    /// Commit the first `used` bytes of the grant as a single frame.
+    ///
+    /// Note that committing zero bytes sends an *empty* frame. To discard the
+    /// grant without sending a frame, drop it instead.
+    pub fn commit(self, used: usize) {
+        self.grant.commit(used);
+        self.storage.commit_waitcell.wake();
+    }
+}
+
+/// A read grant for a single frame on a framed channel.
+///
+/// If the grant is dropped without being released, the frame will be read
+/// again by the next read grant.
+pub struct FrameGrantR {
+    grant: InnerFrameGrantR<'static>,
+    storage: Arc<BBQStorage>,
+}
+
+impl Deref for FrameGrantR {
+    type Target = [u8];
+
+    #[inline(always)]
+    fn deref(&self) -> &Self::Target {
+        self.grant.deref()
+    }
+}
+
+impl DerefMut for FrameGrantR {
+    #[inline(always)]
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.grant.deref_mut()
+    }
+}
+
+impl FrameGrantR {
+    /// Release the whole frame, freeing its space for the producer.
+    pub fn release(self) {
+        self.grant.release();
+        self.storage.release_waitcell.wake();
+    }
+}
+
+unsafe impl Send for FrameGrantR {}
+unsafe impl Sync for FrameGrantR {}
+
 #[inline]
 async fn producer_send_grant_max(
     max: usize,
@@ -270,6 +619,29 @@ impl MpscProducer {
         let producer = producer.as_ref().unwrap();
         producer_send_grant_exact(size, producer, &self.storage).await
     }
+
+    /// Writes all of `buf` to the channel, waiting until there is room for
+    /// all of it.
+    ///
+    /// `buf` must be no larger than the channel's capacity, or this will wait
+    /// forever.
+    ///
+    /// # Cancellation Safety
+    ///
+    /// This method is cancellation safe. If the returned future is dropped
+    /// before it completes, none of `buf` was written.
+    #[tracing::instrument(
+        name = "MpscProducer::write_all",
+        level = "trace",
+        skip(self, buf),
+        fields(queue = ?fmt::ptr(self.storage.deref()), len = buf.len()),
+    )]
+    pub async fn write_all(&self, buf: &[u8]) {
+        if buf.is_empty() {
+            return;
+        }
+        self.send_grant_exact(buf.len()).await.write_all(buf);
+    }
 }
 
 impl SpscProducer {
@@ -292,6 +664,29 @@ impl SpscProducer {
     pub async fn send_grant_exact(&self, size: usize) -> GrantW {
         producer_send_grant_exact(size, &self.producer, &self.storage).await
     }
+
+    /// Writes all of `buf` to the channel, waiting until there is room for
+    /// all of it.
+    ///
+    /// `buf` must be no larger than the channel's capacity, or this will wait
+    /// forever.
+    ///
+    /// # Cancellation Safety
+    ///
+    /// This method is cancellation safe. If the returned future is dropped
+    /// before it completes, none of `buf` was written.
+    #[tracing::instrument(
+        name = "SpscProducer::write_all",
+        level = "trace",
+        skip(self, buf),
+        fields(queue = ?fmt::ptr(self.storage.deref()), len = buf.len()),
+    )]
+    pub async fn write_all(&self, buf: &[u8]) {
+        if buf.is_empty() {
+            return;
+        }
+        self.send_grant_exact(buf.len()).await.write_all(buf);
+    }
 }
 
 impl Consumer {
@@ -307,10 +702,7 @@ impl Consumer {
             match self.consumer.read() {
                 Ok(rgr) => {
                     trace!(size = rgr.len(), "Got bbqueue read grant",);
-                    return GrantR {
-                        grant: rgr,
-                        storage: self.storage.clone(),
-                    };
+                    return GrantR::new(rgr, self.storage.clone());
                 }
                 Err(_) => {
                     trace!("awaiting bbqueue read grant");
@@ -322,6 +714,172 @@ impl Consumer {
             }
         }
     }
+
+    /// Reads exactly `buf.len()` bytes from the channel into `buf`, waiting
+    /// until that many bytes are available. If a [`GrantR`] from this
+    /// consumer is outstanding, this also waits for it to be released.
+    ///
+    /// `buf` must be no larger than the channel's capacity, or this will wait
+    /// forever.
+    ///
+    /// # Cancellation Safety
+    ///
+    /// This method is cancellation safe. Bytes are only consumed from the
+    /// channel once all of them are available, so if the returned future is
+    /// dropped before it completes, no data was read.
+    #[tracing::instrument(
+        name = "Consumer::read_exact",
+        level = "trace",
+        skip(self, buf),
+        fields(queue = ?fmt::ptr(self.storage.deref()), len = buf.len()),
+    )]
+    pub async fn read_exact(&self, buf: &mut [u8]) {
+        if buf.is_empty() {
+            return;
+        }
+        let rgr = self.split_read_exact(buf.len()).await;
+        copy_from_split(&rgr, buf);
+        self.release_split(rgr, buf.len());
+    }
+
+    /// Moves exactly `len` bytes from this channel to `other`, waiting until
+    /// `len` bytes are available to read and there is room for `len` bytes in
+    /// `other`.
+    ///
+    /// `len` must be no larger than either channel's capacity, or this will
+    /// wait forever.
+    ///
+    /// # Cancellation Safety
+    ///
+    /// This method is cancellation safe. No bytes are consumed from this
+    /// channel or written to `other` until the whole copy can be completed,
+    /// so if the returned future is dropped before it completes, neither
+    /// channel was changed.
+    #[tracing::instrument(
+        name = "Consumer::copy_to",
+        level = "trace",
+        skip(self, other),
+        fields(queue = ?fmt::ptr(self.storage.deref())),
+    )]
+    pub async fn copy_to(&self, other: &SpscProducer, len: usize) {
+        if len == 0 {
+            return;
+        }
+        // Holding a write grant while we wait for data doesn't block anyone
+        // else, since an `SpscProducer` has no other writers.
+        let mut wgr = other.send_grant_exact(len).await;
+        let rgr = self.split_read_exact(len).await;
+        copy_from_split(&rgr, &mut wgr);
+        self.release_split(rgr, len);
+        wgr.commit(len);
+    }
+
+    /// Waits for a split read grant containing at least `len` bytes.
+    async fn split_read_exact(&self, len: usize) -> InnerSplitGrantR<'static> {
+        loop {
+            let wait = self.storage.commit_waitcell.subscribe().await;
+            match self.consumer.split_read() {
+                Ok(rgr) if rgr.combined_len() >= len => {
+                    trace!(size = rgr.combined_len(), "Got bbqueue split read grant");
+                    return rgr;
+                }
+                // Someone else is holding a read grant. Wait for them to
+                // finish with it, and try again.
+                Err(InnerError::GrantInProgress) => {
+                    trace!(len, "awaiting bbqueue read grant release");
+                    drop(wait);
+                    // `read_waitq` is never closed.
+                    let _ = self.storage.read_waitq.wait().await;
+                    trace!("awoke for bbqueue read grant release");
+                }
+                // Not enough data yet. Dropping the grant releases nothing.
+                _ => {
+                    trace!(len, "awaiting bbqueue split read grant");
+                    wait.await.unwrap();
+                    trace!("awoke for bbqueue split read grant");
+                }
+            }
+        }
+    }
+
+    fn release_split(&self, rgr: InnerSplitGrantR<'static>, used: usize) {
+        rgr.release(used);
+        self.storage.read_waitq.wake_all();
+        if used != 0 {
+            self.storage.release_waitcell.wake();
+        }
+    }
+}
+
+/// Copies the first `dst.len()` bytes of a split read grant into `dst`.
+fn copy_from_split(rgr: &InnerSplitGrantR<'static>, dst: &mut [u8]) {
+    let (first, second) = rgr.bufs();
+    let from_first = first.len().min(dst.len());
+    let (dst_first, dst_second) = dst.split_at_mut(from_first);
+    dst_first.copy_from_slice(&first[..from_first]);
+    dst_second.copy_from_slice(&second[..dst_second.len()]);
+}
+
+impl FramedProducer {
+    /// Wait for a write grant for a frame of up to `max` bytes.
+    #[tracing::instrument(
+        name = "FramedProducer::send_grant",
+        level = "trace",
+        skip(self),
+        fields(queue = ?fmt::ptr(self.storage.deref())),
+    )]
+    pub async fn send_grant(&self, max: usize) -> FrameGrantW {
+        loop {
+            let wait = self.storage.release_waitcell.subscribe().await;
+            match self.producer.grant(max) {
+                Ok(wgr) => {
+                    trace!("Got bbqueue frame write grant");
+                    return FrameGrantW {
+                        grant: wgr,
+                        storage: self.storage.clone(),
+                    };
+                }
+                Err(_) => {
+                    trace!("awaiting bbqueue frame write grant");
+                    // Couldn't get a send grant. We need to wait for the reader
+                    // to release some frames first.
+                    wait.await.unwrap();
+                    trace!("awoke for bbqueue frame write grant");
+                }
+            }
+        }
+    }
+}
+
+impl FramedConsumer {
+    /// Wait for the next frame.
+    #[tracing::instrument(
+        name = "FramedConsumer::read_grant",
+        level = "trace",
+        skip(self),
+        fields(queue = ?fmt::ptr(self.storage.deref())),
+    )]
+    pub async fn read_grant(&self) -> FrameGrantR {
+        loop {
+            let wait = self.storage.commit_waitcell.subscribe().await;
+            match self.consumer.read() {
+                Some(rgr) => {
+                    trace!(size = rgr.len(), "Got bbqueue frame read grant");
+                    return FrameGrantR {
+                        grant: rgr,
+                        storage: self.storage.clone(),
+                    };
+                }
+                None => {
+                    trace!("awaiting bbqueue frame read grant");
+                    // No frames yet. We need to wait for the writer to commit
+                    // one first.
+                    wait.await.unwrap();
+                    trace!("awoke for bbqueue frame read grant");
+                }
+            }
+        }
+    }
 }
 
 // sync methods
@@ -396,9 +954,211 @@ impl Consumer {
         fields(queue = ?fmt::ptr(self.storage.deref())),
     )]
     pub fn read_grant_sync(&self) -> Option<GrantR> {
-        self.consumer.read().ok().map(|rgr| GrantR {
+        self.consumer
+            .read()
+            .ok()
+            .map(|rgr| GrantR::new(rgr, self.storage.clone()))
+    }
+}
+
+impl FramedProducer {
+    #[tracing::instrument(
+        name = "FramedProducer::send_grant_sync",
+        level = "trace",
+        skip(self),
+        fields(queue = ?fmt::ptr(self.storage.deref())),
+    )]
+    pub fn send_grant_sync(&self, max: usize) -> Option<FrameGrantW> {
+        self.producer.grant(max).ok().map(|wgr| FrameGrantW {
+            grant: wgr,
+            storage: self.storage.clone(),
+        })
+    }
+}
+
+impl FramedConsumer {
+    #[tracing::instrument(
+        name = "FramedConsumer::read_grant_sync",
+        level = "trace",
+        skip(self),
+        fields(queue = ?fmt::ptr(self.storage.deref())),
+    )]
+    pub fn read_grant_sync(&self) -> Option<FrameGrantR> {
+        self.consumer.read().map(|rgr| FrameGrantR {
             grant: rgr,
             storage: self.storage.clone(),
         })
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn framed_preserves_boundaries() {
+        let (prod, cons) = futures::executor::block_on(new_framed_spsc_channel(128));
+
+        for frame in [&b"hello"[..], b"", b"world!"] {
+            let mut wgr = prod.send_grant_sync(16).unwrap();
+            wgr[..frame.len()].copy_from_slice(frame);
+            wgr.commit(frame.len());
+        }
+        // dropping a grant sends nothing.
+        drop(prod.send_grant_sync(16).unwrap());
+
+        for frame in [&b"hello"[..], b"", b"world!"] {
+            let rgr = cons.read_grant_sync().unwrap();
+            assert_eq!(&rgr[..], frame);
+            rgr.release();
+        }
+        assert!(cons.read_grant_sync().is_none());
+    }
+
+    #[test]
+    fn read_exact_across_wraparound() {
+        use futures::executor::block_on;
+
+        let (prod, cons) = block_on(new_spsc_channel(8));
+        // Move the read and write positions to the end of the buffer, so the
+        // next write wraps around.
+        block_on(prod.write_all(b"abcdef"));
+        let mut buf = [0; 6];
+        block_on(cons.read_exact(&mut buf));
+        assert_eq!(&buf, b"abcdef");
+
+        block_on(prod.write_all(b"gh"));
+        block_on(prod.write_all(b"ijk"));
+        let mut buf = [0; 5];
+        block_on(cons.read_exact(&mut buf));
+        assert_eq!(&buf, b"ghijk");
+        assert!(cons.read_grant_sync().is_none());
+    }
+
+    #[test]
+    fn cancelled_read_exact_consumes_nothing() {
+        use futures::{executor::block_on, FutureExt};
+
+        let (prod, cons) = block_on(new_spsc_channel(16));
+        block_on(prod.write_all(b"abc"));
+
+        // Only 3 of 4 bytes are available, so this can't complete.
+        let mut buf = [0; 4];
+        assert!(cons.read_exact(&mut buf).now_or_never().is_none());
+
+        block_on(prod.write_all(b"d"));
+        block_on(cons.read_exact(&mut buf));
+        assert_eq!(&buf, b"abcd");
+    }
+
+    #[test]
+    fn read_exact_waits_for_grant_in_progress() {
+        use core::{
+            future::Future,
+            pin::pin,
+            sync::atomic::{AtomicBool, Ordering},
+            task::Context,
+        };
+        use futures::{
+            executor::block_on,
+            task::{waker, ArcWake},
+        };
+
+        struct Woken(AtomicBool);
+        impl ArcWake for Woken {
+            fn wake_by_ref(arc_self: &std::sync::Arc<Self>) {
+                arc_self.0.store(true, Ordering::SeqCst);
+            }
+        }
+
+        let (prod, cons) = block_on(new_spsc_channel(16));
+        block_on(prod.write_all(b"abcd"));
+        let rgr = cons.read_grant_sync().unwrap();
+
+        let woken = std::sync::Arc::new(Woken(AtomicBool::new(false)));
+        let waker = waker(woken.clone());
+        let mut cx = Context::from_waker(&waker);
+        let mut buf = [0; 4];
+        {
+            // The data is available, but another grant is reading it.
+            let mut read = pin!(cons.read_exact(&mut buf));
+            assert!(read.as_mut().poll(&mut cx).is_pending());
+
+            // Releasing the other grant, even without consuming anything,
+            // lets the read finish.
+            rgr.release(0);
+            assert!(woken.0.load(Ordering::SeqCst));
+            assert!(read.as_mut().poll(&mut cx).is_ready());
+        }
+        assert_eq!(&buf, b"abcd");
+    }
+
+    #[test]
+    fn copy_to_moves_bytes() {
+        use futures::{executor::block_on, FutureExt};
+
+        let (a_prod, a_cons) = block_on(new_spsc_channel(16));
+        let (b_prod, b_cons) = block_on(new_spsc_channel(16));
+        block_on(a_prod.write_all(b"hello"));
+
+        // Cancelling a copy that can't complete leaves both channels alone.
+        assert!(a_cons.copy_to(&b_prod, 6).now_or_never().is_none());
+        assert!(b_cons.read_grant_sync().is_none());
+
+        block_on(a_cons.copy_to(&b_prod, 5));
+        assert!(a_cons.read_grant_sync().is_none());
+        let mut buf = [0; 5];
+        block_on(b_cons.read_exact(&mut buf));
+        assert_eq!(&buf, b"hello");
+    }
+
+    #[test]
+    fn dropping_a_half_closes() {
+        use futures::{executor::block_on, FutureExt};
+
+        let (prod, cons) = block_on(new_spsc_channel(16));
+        assert!(!cons.is_closed());
+        assert!(cons.closed().now_or_never().is_none());
+
+        block_on(prod.write_all(b"bye"));
+        drop(prod);
+        assert!(cons.is_closed());
+        assert!(cons.closed().now_or_never().is_some());
+        // data committed before closing can still be read.
+        let mut buf = [0; 3];
+        block_on(cons.read_exact(&mut buf));
+        assert_eq!(&buf, b"bye");
+
+        let (prod, cons) = block_on(new_framed_spsc_channel(16));
+        cons.close();
+        assert!(prod.is_closed());
+    }
+
+    #[test]
+    fn mpsc_closes_after_last_clone() {
+        use futures::executor::block_on;
+
+        let (prod, cons) = block_on(new_spsc_channel(16));
+        let prod = block_on(prod.into_mpmc_producer());
+        assert!(!cons.is_closed());
+
+        let prod2 = prod.clone();
+        drop(prod);
+        assert!(!cons.is_closed());
+        drop(prod2);
+        assert!(cons.is_closed());
+    }
+
+    #[test]
+    fn bidi_sees_other_end_drop() {
+        use futures::{executor::block_on, FutureExt};
+
+        let (a, b) = block_on(new_bidi_channel(16, 16));
+        assert!(!a.is_closed());
+        assert!(a.closed().now_or_never().is_none());
+
+        drop(b);
+        assert!(a.is_closed());
+        assert!(a.closed().now_or_never().is_some());
+    }
+}
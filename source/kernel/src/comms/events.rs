@@ -0,0 +1,405 @@
+//! # System Event Bus
+//!
+//! Some events are of interest to many parts of the system at once: a task
+//! that saves a document wants to know when the battery is about to run out,
+//! and so does the display driver, which may want to dim the backlight. Rather
+//! than requiring every producer of these events to know about every consumer
+//! (or every consumer to poll a service), the kernel's [`EventBus`] allows any
+//! task to publish events which are delivered to every subscriber.
+//!
+//! Events are published on typed topics: each topic has its own [`Event`]
+//! type, and subscribers only receive events of the type they subscribed to.
+//! The available topics are:
+//!
+//! - [`PowerEvent`]: changes to the power supply and battery.
+//! - [`StorageHotplug`]: storage devices being inserted or removed.
+//! - [`NetworkLinkChange`]: network interfaces going up or down.
+//! - [`LowMemory`]: changes in memory pressure.
+//! - [`AlarmFired`]: alarms set with the [alarm service] going off.
+//! - [`Notification`]: notifications posted with the [notification service].
+//!
+//! Each [`Subscriber`] has its own bounded queue, so a slow subscriber never
+//! blocks publishers or other subscribers. If a subscriber's queue is full
+//! when an event is published, that subscriber misses the event, and the
+//! topic's [dropped event count](EventBus::dropped) is incremented.
+//!
+//! The bus is accessed through [`Kernel::events`](crate::Kernel::events).
+//! Publishing is synchronous and never allocates, but it locks the topic's
+//! list of subscribers, so it must not be done from an interrupt handler.
+//! Interrupt handlers can instead schedule
+//! [deferred work](crate::deferred) which publishes the event.
+//!
+//! [alarm service]: crate::services::alarm
+//! [notification service]: crate::services::notification
+
+use core::{fmt, time::Duration};
+
+use maitake::sync::{blocking::Mutex, spin::Spinlock};
+use portable_atomic::{AtomicUsize, Ordering};
+
+use super::kchannel::{EnqueueError, KChannel, KConsumer, KProducer};
+use crate::services::alarm::AlarmId;
+pub use crate::services::notification::Notification;
+
+/// The maximum number of subscribers to each topic.
+pub const MAX_SUBSCRIBERS: usize = 16;
+
+/// The kernel's event bus.
+///
+/// See the [module-level documentation](self) for details.
+pub struct EventBus {
+    power: Topic<PowerEvent>,
+    storage: Topic<StorageHotplug>,
+    network: Topic<NetworkLinkChange>,
+    low_memory: Topic<LowMemory>,
+    alarms: Topic<AlarmFired>,
+    notifications: Topic<Notification>,
+}
+
+/// A type of event which may be published on the [`EventBus`].
+///
+/// This trait is sealed: the set of topics is defined by the kernel.
+pub trait Event: Clone + 'static + sealed::Sealed {}
+
+/// A subscription to a topic on the [`EventBus`].
+///
+/// This is returned by [`EventBus::subscribe`]. Dropping a `Subscriber`
+/// unsubscribes from the topic.
+#[must_use = "a `Subscriber` does nothing unless events are received from it"]
+pub struct Subscriber<E> {
+    rx: KConsumer<E>,
+}
+
+/// Errors returned by [`EventBus::subscribe`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubscribeError {
+    /// The topic already has [`MAX_SUBSCRIBERS`] subscribers.
+    TooManySubscribers,
+}
+
+/// Changes to the system's power supply.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PowerEvent {
+    /// The system is now running on external power.
+    ExternalPower,
+    /// The system is now running on battery power.
+    Battery,
+    /// The battery's charge level has changed.
+    BatteryLevel {
+        /// The charge level, as a percentage.
+        percent: u8,
+    },
+    /// The battery is almost empty, and the system will lose power soon.
+    BatteryLow,
+}
+
+/// A storage device was inserted or removed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StorageHotplug {
+    /// A storage device was inserted into the given slot.
+    Inserted {
+        /// The platform-defined index of the slot, such as the SD card
+        /// controller number.
+        slot: u8,
+    },
+    /// The storage device in the given slot was removed.
+    Removed {
+        /// The platform-defined index of the slot.
+        slot: u8,
+    },
+}
+
+/// A network interface's link went up or down.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NetworkLinkChange {
+    /// The platform-defined index of the network interface.
+    pub interface: u8,
+    /// Whether the link is now up.
+    pub up: bool,
+}
+
+/// A change in the level of memory pressure.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LowMemory {
+    /// Free memory is running low. Caches should be trimmed.
+    Warning,
+    /// Allocations are failing.
+    Critical,
+    /// Memory pressure has returned to normal.
+    Recovered,
+}
+
+/// An alarm set with the [alarm service](crate::services::alarm) went off.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AlarmFired {
+    /// The alarm's ID, as returned when it was set.
+    pub id: AlarmId,
+    /// The time the alarm was set for, as the time since the Unix epoch
+    /// (UTC).
+    pub at: Duration,
+    /// The tag provided when the alarm was set.
+    pub tag: u32,
+}
+
+struct Topic<E> {
+    subscribers: Mutex<heapless::Vec<KProducer<E>, MAX_SUBSCRIBERS>, Spinlock>,
+    dropped: AtomicUsize,
+}
+
+mod sealed {
+    use super::*;
+
+    pub trait Sealed: Sized {
+        fn topic(bus: &EventBus) -> &Topic<Self>;
+    }
+
+    impl Sealed for PowerEvent {
+        fn topic(bus: &EventBus) -> &Topic<Self> {
+            &bus.power
+        }
+    }
+
+    impl Sealed for StorageHotplug {
+        fn topic(bus: &EventBus) -> &Topic<Self> {
+            &bus.storage
+        }
+    }
+
+    impl Sealed for NetworkLinkChange {
+        fn topic(bus: &EventBus) -> &Topic<Self> {
+            &bus.network
+        }
+    }
+
+    impl Sealed for LowMemory {
+        fn topic(bus: &EventBus) -> &Topic<Self> {
+            &bus.low_memory
+        }
+    }
+
+    impl Sealed for AlarmFired {
+        fn topic(bus: &EventBus) -> &Topic<Self> {
+            &bus.alarms
+        }
+    }
+
+    impl Sealed for Notification {
+        fn topic(bus: &EventBus) -> &Topic<Self> {
+            &bus.notifications
+        }
+    }
+}
+
+impl Event for PowerEvent {}
+impl Event for StorageHotplug {}
+impl Event for NetworkLinkChange {}
+impl Event for LowMemory {}
+impl Event for AlarmFired {}
+impl Event for Notification {}
+
+// === impl EventBus ===
+
+impl EventBus {
+    pub(crate) const fn new() -> Self {
+        Self {
+            power: Topic::new(),
+            storage: Topic::new(),
+            network: Topic::new(),
+            low_memory: Topic::new(),
+            alarms: Topic::new(),
+            notifications: Topic::new(),
+        }
+    }
+
+    /// Subscribes to events of type `E`.
+    ///
+    /// The returned [`Subscriber`] can buffer up to `capacity` events which
+    /// have not yet been received. Events published while its queue is full
+    /// are not delivered to it. As with [`KChannel::new_async`], `capacity`
+    /// is rounded up to a power of two.
+    pub async fn subscribe<E: Event>(
+        &self,
+        capacity: usize,
+    ) -> Result<Subscriber<E>, SubscribeError> {
+        let (tx, rx) = KChannel::new_async(capacity).await.split();
+        let mut subscribers = E::topic(self).subscribers.lock();
+        // make room for the new subscriber, if any old ones have gone away.
+        subscribers.retain(|tx| !tx.is_closed());
+        subscribers
+            .push(tx)
+            .map_err(|_| SubscribeError::TooManySubscribers)?;
+        Ok(Subscriber { rx })
+    }
+
+    /// Publishes `event` to every subscriber of its topic, returning the
+    /// number of subscribers it was delivered to.
+    ///
+    /// This never waits. Subscribers whose queues are full miss the event.
+    ///
+    /// This must not be called from an interrupt handler.
+    pub fn publish<E: Event>(&self, event: E) -> usize {
+        let topic = E::topic(self);
+        let mut subscribers = topic.subscribers.lock();
+        let mut delivered = 0;
+        subscribers.retain(|tx| match tx.enqueue_sync(event.clone()) {
+            Ok(()) => {
+                delivered += 1;
+                true
+            }
+            Err(EnqueueError::Full(_)) => {
+                topic.dropped.fetch_add(1, Ordering::Relaxed);
+                true
+            }
+            // the subscriber has been dropped.
+            Err(EnqueueError::Closed(_)) => false,
+        });
+        delivered
+    }
+
+    /// Returns the number of subscribers to events of type `E`.
+    #[must_use]
+    pub fn subscribers<E: Event>(&self) -> usize {
+        let mut subscribers = E::topic(self).subscribers.lock();
+        subscribers.retain(|tx| !tx.is_closed());
+        subscribers.len()
+    }
+
+    /// Returns the number of times an event of type `E` was not delivered to
+    /// a subscriber because its queue was full.
+    #[must_use]
+    pub fn dropped<E: Event>(&self) -> usize {
+        E::topic(self).dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl fmt::Debug for EventBus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EventBus")
+            .field("power", &self.power)
+            .field("storage", &self.storage)
+            .field("network", &self.network)
+            .field("low_memory", &self.low_memory)
+            .field("alarms", &self.alarms)
+            .field("notifications", &self.notifications)
+            .finish()
+    }
+}
+
+// === impl Topic ===
+
+impl<E> Topic<E> {
+    const fn new() -> Self {
+        Self {
+            subscribers: Mutex::new_with_raw_mutex(heapless::Vec::new(), Spinlock::new()),
+            dropped: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl<E> fmt::Debug for Topic<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Topic")
+            .field("subscribers", &self.subscribers.lock().len())
+            .field("dropped", &self.dropped.load(Ordering::Relaxed))
+            .finish()
+    }
+}
+
+// === impl Subscriber ===
+
+impl<E: Event> Subscriber<E> {
+    /// Waits for the next event to be published.
+    pub async fn next(&self) -> E {
+        self.rx
+            .dequeue_async()
+            .await
+            // the bus never closes a subscriber's queue, only the subscriber
+            // does, when it's dropped.
+            .expect("event bus subscriptions are never closed by the bus")
+    }
+
+    /// Returns the next event, if one has been published and not yet
+    /// received, without waiting.
+    pub fn try_next(&self) -> Option<E> {
+        self.rx.dequeue_sync()
+    }
+}
+
+impl<E> Drop for Subscriber<E> {
+    fn drop(&mut self) {
+        self.rx.close();
+    }
+}
+
+impl<E> fmt::Debug for Subscriber<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Subscriber")
+            .field("queue", &self.rx.depth())
+            .finish()
+    }
+}
+
+// === impl SubscribeError ===
+
+impl fmt::Display for SubscribeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TooManySubscribers => {
+                write!(
+                    f,
+                    "topics may not have more than {MAX_SUBSCRIBERS} subscribers"
+                )
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TestKernel;
+
+    #[test]
+    fn delivers_to_every_subscriber() {
+        TestKernel::run(|k| async move {
+            let bus = k.events();
+            let sub1 = bus.subscribe::<PowerEvent>(4).await.unwrap();
+            let sub2 = bus.subscribe::<PowerEvent>(4).await.unwrap();
+            let storage = bus.subscribe::<StorageHotplug>(4).await.unwrap();
+
+            assert_eq!(bus.publish(PowerEvent::BatteryLow), 2);
+            assert_eq!(sub1.next().await, PowerEvent::BatteryLow);
+            assert_eq!(sub2.next().await, PowerEvent::BatteryLow);
+            // other topics don't receive the event.
+            assert_eq!(storage.try_next(), None);
+
+            drop(sub2);
+            assert_eq!(bus.subscribers::<PowerEvent>(), 1);
+            assert_eq!(bus.publish(PowerEvent::Battery), 1);
+            assert_eq!(sub1.try_next(), Some(PowerEvent::Battery));
+        })
+    }
+
+    #[test]
+    fn full_subscriber_misses_events() {
+        TestKernel::run(|k| async move {
+            let bus = k.events();
+            let slow = bus.subscribe::<LowMemory>(2).await.unwrap();
+            let fast = bus.subscribe::<LowMemory>(8).await.unwrap();
+
+            for _ in 0..3 {
+                bus.publish(LowMemory::Warning);
+            }
+            assert_eq!(bus.dropped::<LowMemory>(), 1);
+
+            let mut received = 0;
+            while slow.try_next().is_some() {
+                received += 1;
+            }
+            assert_eq!(received, 2);
+            for _ in 0..3 {
+                assert_eq!(fast.next().await, LowMemory::Warning);
+            }
+        })
+    }
+}
@@ -1,15 +1,22 @@
 //! Kernel Channels
 //!
 //! Kernel Channels are an async/await, MPSC queue, with a fixed backing storage (e.g. they are bounded).
-use core::{cell::UnsafeCell, ops::Deref, ptr::NonNull};
+//!
+//! Each channel records how full it has been, and how often senders found it
+//! full, in its [`ChannelDepth`]. Since a full channel usually means that its
+//! consumer is stuck, [`KProducer::enqueue_async_watched`] can also warn when
+//! a sender has been waiting on a full channel for too long.
+use core::{cell::UnsafeCell, ops::Deref, pin::pin, ptr::NonNull, time::Duration};
+use maitake::time::Timer;
 use mnemos_alloc::containers::{Arc, ArrayBuf};
+use portable_atomic::{AtomicBool, AtomicUsize, Ordering};
 use spitebuf::MpScQueue;
 pub use spitebuf::{DequeueError, EnqueueError};
 use tracing;
 
 /// A Kernel Channel
 pub struct KChannel<T> {
-    q: Arc<MpScQueue<T, sealed::SpiteData<T>>>,
+    q: Arc<Chan<T>>,
 }
 
 /// A Producer for a [KChannel].
@@ -17,7 +24,7 @@ pub struct KChannel<T> {
 /// A `KProducer` can be cloned multiple times, as the backing [KChannel]
 /// is an MPSC queue.
 pub struct KProducer<T> {
-    q: Arc<MpScQueue<T, sealed::SpiteData<T>>>,
+    q: Arc<Chan<T>>,
 }
 
 /// A Consumer for a [KChannel].
@@ -26,7 +33,7 @@ pub struct KProducer<T> {
 /// as it is an MPSC queue. A `KConsumer` can also be used to create a new
 /// [KProducer] instance.
 pub struct KConsumer<T> {
-    q: Arc<MpScQueue<T, sealed::SpiteData<T>>>,
+    q: Arc<Chan<T>>,
 }
 
 /// A type-erased [KProducer]. This is currently used only for implementing
@@ -35,9 +42,35 @@ pub struct KConsumer<T> {
 /// It contains a VTable of functions necessary for operations while type-erased,
 /// namely cloning and dropping.
 pub(crate) struct ErasedKProducer {
-    erased_q: NonNull<MpScQueue<(), sealed::SpiteData<()>>>,
-    dropper: unsafe fn(NonNull<MpScQueue<(), sealed::SpiteData<()>>>),
+    erased_q: NonNull<Chan<()>>,
+    dropper: unsafe fn(NonNull<Chan<()>>),
     cloner: unsafe fn(&Self) -> Self,
+    depth: unsafe fn(&Self) -> ChannelDepth,
+}
+
+/// A snapshot of how full a [KChannel] is, and has been.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChannelDepth {
+    /// The number of items currently in the channel.
+    pub len: usize,
+    /// The maximum number of items the channel can hold.
+    pub capacity: usize,
+    /// The most items the channel has held at once.
+    pub high_water: usize,
+    /// The number of times a sender found the channel full, and either had
+    /// to wait or had its item rejected.
+    pub overflows: usize,
+}
+
+/// A [KChannel]'s queue, and statistics about how it has been used.
+pub(crate) struct Chan<T> {
+    q: MpScQueue<T, sealed::SpiteData<T>>,
+    /// The most items the channel has held at once.
+    high_water: AtomicUsize,
+    /// The number of times a sender found the channel full.
+    overflows: AtomicUsize,
+    /// Set while a sender is reporting that the channel has stayed full.
+    stall_reported: AtomicBool,
 }
 
 // KChannel
@@ -52,7 +85,7 @@ impl<T> Deref for KChannel<T> {
     type Target = MpScQueue<T, sealed::SpiteData<T>>;
 
     fn deref(&self) -> &Self::Target {
-        &self.q
+        &self.q.q
     }
 }
 
@@ -84,7 +117,7 @@ impl<T> KChannel<T> {
     /// automatically.
     pub async fn new_async(count: usize) -> Self {
         let ba = ArrayBuf::new_uninit(right_size(count)).await;
-        let q = MpScQueue::new(sealed::SpiteData { data: ba });
+        let q = Chan::new(sealed::SpiteData { data: ba });
         Self {
             q: Arc::new(q).await,
         }
@@ -97,7 +130,7 @@ impl<T> KChannel<T> {
     /// automatically.
     pub fn new(count: usize) -> Self {
         let ba = ArrayBuf::try_new_uninit(right_size(count)).unwrap();
-        let q = MpScQueue::new(sealed::SpiteData { data: ba });
+        let q = Chan::new(sealed::SpiteData { data: ba });
         Self {
             q: Arc::try_new(q).map_err(drop).unwrap(),
         }
@@ -146,20 +179,90 @@ impl<T> KProducer<T> {
         self.q.enqueue_async(item).await
     }
 
+    /// Like [`KProducer::enqueue_async`], but traces a warning if the queue
+    /// stays full for longer than `warn_after`.
+    ///
+    /// `name` identifies the channel in the warning. Only one sender reports
+    /// each time the channel stalls, and it waits twice as long before each
+    /// further warning, so a stuck consumer doesn't flood the trace output.
+    pub async fn enqueue_async_watched(
+        &self,
+        item: T,
+        timer: &Timer,
+        warn_after: Duration,
+        name: &str,
+    ) -> Result<(), EnqueueError<T>> {
+        let item = match self.q.enqueue_sync(item) {
+            Err(EnqueueError::Full(item)) => item,
+            res => return res,
+        };
+
+        let start = timer.now();
+        let mut reporting = false;
+        let mut wait = warn_after;
+        // the overflow was counted by `enqueue_sync`, so wait on the queue
+        // itself, rather than `Chan::enqueue_async`.
+        let mut enqueue = pin!(self.q.q.enqueue_async(item));
+        let res = loop {
+            match timer.timeout(wait, enqueue.as_mut()).await {
+                Ok(res) => break res,
+                Err(_) => {
+                    if !reporting && self.q.stall_reported.swap(true, Ordering::AcqRel) {
+                        // another sender is already reporting this stall.
+                        continue;
+                    }
+                    reporting = true;
+                    tracing::warn!(
+                        channel = name,
+                        waited = ?timer.now().duration_since(start),
+                        depth = ?self.depth(),
+                        "channel has been full for too long; is its consumer stuck?",
+                    );
+                    wait = wait.saturating_mul(2);
+                }
+            }
+        };
+
+        if res.is_ok() {
+            self.q.record_enqueue();
+        }
+        if reporting {
+            self.q.stall_reported.store(false, Ordering::Release);
+            tracing::info!(
+                channel = name,
+                waited = ?timer.now().duration_since(start),
+                "full channel accepted an item",
+            );
+        }
+        res
+    }
+
+    /// Returns the current [`ChannelDepth`] of the backing [KChannel].
+    pub fn depth(&self) -> ChannelDepth {
+        ChannelDepth::of(&self.q)
+    }
+
+    /// Returns `true` if the backing [KChannel] has been closed, and no
+    /// further items can be enqueued.
+    pub fn is_closed(&self) -> bool {
+        self.q.q.is_closed()
+    }
+
     pub(crate) fn type_erase(self) -> ErasedKProducer {
-        let typed_q: NonNull<MpScQueue<T, sealed::SpiteData<T>>> = Arc::into_raw(self.q);
-        let erased_q: NonNull<MpScQueue<(), sealed::SpiteData<()>>> = typed_q.cast();
+        let typed_q: NonNull<Chan<T>> = Arc::into_raw(self.q);
+        let erased_q: NonNull<Chan<()>> = typed_q.cast();
 
         ErasedKProducer {
             erased_q,
             dropper: ErasedKProducer::drop_erased::<T>,
             cloner: ErasedKProducer::clone_erased::<T>,
+            depth: ErasedKProducer::depth_erased::<T>,
         }
     }
 
     // TODO(eliza): replace this with "close on drop" behavior...
     pub(crate) fn close(&mut self) {
-        self.q.close()
+        self.q.q.close()
     }
 }
 
@@ -170,7 +273,7 @@ impl<T> KConsumer<T> {
     /// `None` if the queue is empty
     #[inline(always)]
     pub fn dequeue_sync(&self) -> Option<T> {
-        self.q.dequeue_sync()
+        self.q.q.dequeue_sync()
     }
 
     /// Await the availability of an item from the front of the queue.
@@ -179,13 +282,24 @@ impl<T> KConsumer<T> {
     /// has been enqueued
     #[inline(always)]
     pub async fn dequeue_async(&self) -> Result<T, DequeueError> {
-        self.q.dequeue_async().await
+        self.q.q.dequeue_async().await
+    }
+
+    /// Returns the current [`ChannelDepth`] of the backing [KChannel].
+    pub fn depth(&self) -> ChannelDepth {
+        ChannelDepth::of(&self.q)
     }
 
     /// Create a [KProducer] for this KConsumer (and its backing [KChannel]).
     pub fn producer(&self) -> KProducer<T> {
         KProducer { q: self.q.clone() }
     }
+
+    /// Close the backing [KChannel]. Items already in the queue can still be
+    /// dequeued, but any further attempts to enqueue items will fail.
+    pub(crate) fn close(&self) {
+        self.q.q.close()
+    }
 }
 
 // ErasedKProducer
@@ -200,7 +314,7 @@ impl ErasedKProducer {
     /// Clone the ErasedKProducer. The resulting ErasedKProducer will be for the same
     /// underlying [KChannel] and type.
     pub(crate) fn clone_erased<T>(&self) -> Self {
-        let typed_q: NonNull<MpScQueue<T, sealed::SpiteData<T>>> = self.erased_q.cast();
+        let typed_q: NonNull<Chan<T>> = self.erased_q.cast();
         unsafe {
             Arc::increment_strong_count(typed_q.as_ptr());
         }
@@ -209,9 +323,27 @@ impl ErasedKProducer {
             erased_q: self.erased_q,
             dropper: self.dropper,
             cloner: self.cloner,
+            depth: self.depth,
         }
     }
 
+    /// Returns the current [`ChannelDepth`] of the underlying [KChannel].
+    pub(crate) fn depth(&self) -> ChannelDepth {
+        unsafe { (self.depth)(self) }
+    }
+
+    /// Returns the depth of the channel, while also re-typing to the leaked
+    /// [KProducer] type.
+    ///
+    /// SAFETY:
+    ///
+    /// The type `T` MUST be the same `T` that was used to create this ErasedKProducer,
+    /// otherwise undefined behavior will occur.
+    unsafe fn depth_erased<T>(&self) -> ChannelDepth {
+        let typed_q = self.erased_q.cast::<Chan<T>>();
+        ChannelDepth::of(typed_q.as_ref())
+    }
+
     /// Clone the ErasedKProducer, while also re-typing to the unleaked [KProducer] type.
     ///
     /// SAFETY:
@@ -219,7 +351,7 @@ impl ErasedKProducer {
     /// The type `T` MUST be the same `T` that was used to create this ErasedKProducer,
     /// otherwise undefined behavior will occur.
     pub(crate) unsafe fn clone_typed<T>(&self) -> KProducer<T> {
-        let typed_q: NonNull<MpScQueue<T, sealed::SpiteData<T>>> = self.erased_q.cast();
+        let typed_q: NonNull<Chan<T>> = self.erased_q.cast();
         let q = unsafe {
             Arc::increment_strong_count(typed_q.as_ptr());
             Arc::from_raw(typed_q)
@@ -234,8 +366,8 @@ impl ErasedKProducer {
     ///
     /// The type `T` MUST be the same `T` that was used to create this ErasedKProducer,
     /// otherwise undefined behavior will occur.
-    pub(crate) unsafe fn drop_erased<T>(ptr: NonNull<MpScQueue<(), sealed::SpiteData<()>>>) {
-        let ptr = ptr.cast::<MpScQueue<T, sealed::SpiteData<T>>>();
+    pub(crate) unsafe fn drop_erased<T>(ptr: NonNull<Chan<()>>) {
+        let ptr = ptr.cast::<Chan<T>>();
         let _ = Arc::from_raw(ptr);
     }
 }
@@ -248,6 +380,60 @@ impl Drop for ErasedKProducer {
     }
 }
 
+// ChannelDepth
+
+impl ChannelDepth {
+    fn of<T>(chan: &Chan<T>) -> Self {
+        Self {
+            len: chan.q.len(),
+            capacity: chan.q.capacity(),
+            high_water: chan.high_water.load(Ordering::Relaxed),
+            overflows: chan.overflows.load(Ordering::Relaxed),
+        }
+    }
+}
+
+// Chan
+
+impl<T> Chan<T> {
+    fn new(storage: sealed::SpiteData<T>) -> Self {
+        Self {
+            q: MpScQueue::new(storage),
+            high_water: AtomicUsize::new(0),
+            overflows: AtomicUsize::new(0),
+            stall_reported: AtomicBool::new(false),
+        }
+    }
+
+    fn enqueue_sync(&self, item: T) -> Result<(), EnqueueError<T>> {
+        let res = self.q.enqueue_sync(item);
+        match res {
+            Ok(()) => self.record_enqueue(),
+            Err(EnqueueError::Full(_)) => {
+                self.overflows.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(EnqueueError::Closed(_)) => {}
+        }
+        res
+    }
+
+    async fn enqueue_async(&self, item: T) -> Result<(), EnqueueError<T>> {
+        let item = match self.enqueue_sync(item) {
+            Err(EnqueueError::Full(item)) => item,
+            res => return res,
+        };
+        let res = self.q.enqueue_async(item).await;
+        if res.is_ok() {
+            self.record_enqueue();
+        }
+        res
+    }
+
+    fn record_enqueue(&self) {
+        self.high_water.fetch_max(self.q.len(), Ordering::Relaxed);
+    }
+}
+
 pub(crate) mod sealed {
     use mnemos_alloc::containers::ArrayBuf;
 
@@ -264,3 +450,33 @@ pub(crate) mod sealed {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TestKernel;
+
+    #[test]
+    fn depth_tracks_high_water_and_overflows() {
+        TestKernel::run(|_| async move {
+            let (tx, rx) = KChannel::<usize>::new_async(4).await.split();
+            let mut sent = 0;
+            while tx.enqueue_sync(sent).is_ok() {
+                sent += 1;
+            }
+
+            let depth = tx.depth();
+            assert_eq!(depth.len, sent);
+            assert_eq!(depth.high_water, sent);
+            assert_eq!(depth.overflows, 1);
+
+            // draining the channel doesn't lower its high-water mark.
+            while rx.dequeue_sync().is_some() {}
+            tx.enqueue_sync(0).unwrap();
+            let depth = rx.depth();
+            assert_eq!(depth.len, 1);
+            assert_eq!(depth.high_water, sent);
+            assert_eq!(depth.overflows, 1);
+        })
+    }
+}
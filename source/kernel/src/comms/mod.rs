@@ -1,5 +1,6 @@
 //! Kernel Communications Interfaces
 
 pub mod bbq;
+pub mod events;
 pub mod kchannel;
 pub mod oneshot;
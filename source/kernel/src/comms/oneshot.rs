@@ -3,14 +3,25 @@
 //! Often, clients of drivers only want to process one "in-flight" message at
 //! a time. If request pipelining is not required, then a One-Shot Channel
 //! is an easy way to perform an async/await request/response cycle.
+//!
+//! ## Poisoning
+//!
+//! A [Reusable] can only have one reply outstanding at a time. If a client
+//! stops waiting for a reply (for example, because its request future was
+//! dropped by a timeout) while the service still holds the [Sender], the
+//! channel can't be used again until the service replies or drops the
+//! [Sender]. In the meantime, the channel is *poisoned*, and
+//! [Reusable::sender] returns [ReusableError::Poisoned] rather than waiting
+//! on a reply which nobody wants. A poisoned channel can be recovered
+//! immediately with [Reusable::reset], which abandons the outstanding reply.
 
 use core::{
     cell::UnsafeCell,
     mem::MaybeUninit,
-    sync::atomic::{AtomicU8, Ordering},
+    sync::atomic::{AtomicBool, AtomicU8, Ordering},
 };
 
-use maitake::sync::{Closed, WaitCell};
+use maitake::sync::{blocking::Mutex, spin::Spinlock, Closed, WaitCell};
 use mnemos_alloc::containers::Arc;
 
 /// Not waiting for anything.
@@ -25,6 +36,11 @@ const ROSC_READY: u8 = 3;
 const ROSC_READING: u8 = 4;
 /// The receiver has been manually closed or dropped.
 const ROSC_CLOSED: u8 = 5;
+/// The Sender was dropped without sending a reply.
+const ROSC_DROPPED: u8 = 6;
+
+/// The counterpart name used when none was provided to [Reusable::sender_to].
+const UNKNOWN_COUNTERPART: &str = "<unknown>";
 
 /// A reusable One-Shot channel.
 ///
@@ -52,8 +68,16 @@ pub struct Sender<T> {
 // An error type for the Reusable channel and Sender
 #[derive(Debug, Eq, PartialEq)]
 pub enum ReusableError {
+    /// A [Sender] is already live, and the [Reusable] is still waiting on it.
     SenderAlreadyActive,
+    /// No [Sender] has been created.
     NoSenderActive,
+    /// The [Sender] was dropped without sending a reply.
+    SenderDropped,
+    /// The receiver stopped waiting for a reply while a [Sender] is still
+    /// live. The channel can't be used until the [Sender] completes or is
+    /// dropped, or until [Reusable::reset] is called.
+    Poisoned,
     ChannelClosed,
     InternalError,
 }
@@ -69,6 +93,16 @@ struct Inner<T> {
     state: AtomicU8,
     cell: UnsafeCell<MaybeUninit<T>>,
     wait: WaitCell,
+    /// Set when a receive is cancelled while the reply is outstanding.
+    abandoned: AtomicBool,
+    /// The name of the service holding the current [Sender], for diagnostics.
+    counterpart: Mutex<&'static str, Spinlock>,
+}
+
+/// Marks the channel as abandoned if a receive is cancelled while the reply
+/// is still outstanding.
+struct AbandonOnDrop<'a, T> {
+    inner: &'a Inner<T>,
 }
 
 // impl Reusable
@@ -86,7 +120,22 @@ impl<T> Reusable<T> {
     /// error will be immediately returned.
     ///
     /// This error can be cleared by awaiting [Reusable::receive].
+    ///
+    /// If a previous receive was cancelled and its reply has since arrived,
+    /// the stale reply is discarded. If the reply is still outstanding, the
+    /// channel is poisoned, and [ReusableError::Poisoned] is returned; see
+    /// [Reusable::reset].
     pub async fn sender(&self) -> Result<Sender<T>, ReusableError> {
+        self.sender_to(UNKNOWN_COUNTERPART).await
+    }
+
+    /// Create a sender for the given `Reusable<T>`, which will be handed to
+    /// `counterpart`.
+    ///
+    /// This behaves identically to [Reusable::sender], but `counterpart`
+    /// (usually the name of the service the request is sent to) is included
+    /// in diagnostics if the reply is dropped or abandoned.
+    pub async fn sender_to(&self, counterpart: &'static str) -> Result<Sender<T>, ReusableError> {
         loop {
             let swap = self.inner.state.compare_exchange(
                 ROSC_IDLE,
@@ -97,23 +146,78 @@ impl<T> Reusable<T> {
 
             match swap {
                 Ok(_) => {
+                    self.inner.abandoned.store(false, Ordering::Release);
+                    *self.inner.counterpart.lock() = counterpart;
                     return Ok(Sender {
                         inner: self.inner.clone(),
-                    })
+                    });
                 }
-                Err(val) => {
-                    if val == ROSC_READY {
-                        let _ = self.receive().await;
-                    } else if (val == ROSC_WAITING) | (val == ROSC_WRITING) {
-                        return Err(ReusableError::SenderAlreadyActive);
-                    } else {
-                        return Err(ReusableError::InternalError);
+                Err(ROSC_READY) => {
+                    if self.inner.abandoned.load(Ordering::Acquire) {
+                        tracing::debug!(
+                            counterpart = self.inner.counterpart(),
+                            "discarding reply to a cancelled request"
+                        );
+                    }
+                    let _ = self.receive().await;
+                }
+                Err(ROSC_DROPPED) => {
+                    // The previous sender was dropped, but the receiver never
+                    // noticed. Go back to idle and try again.
+                    let _ = self.inner.state.compare_exchange(
+                        ROSC_DROPPED,
+                        ROSC_IDLE,
+                        Ordering::AcqRel,
+                        Ordering::Relaxed,
+                    );
+                }
+                Err(ROSC_WAITING | ROSC_WRITING) => {
+                    if self.inner.abandoned.load(Ordering::Acquire) {
+                        tracing::warn!(
+                            counterpart = self.inner.counterpart(),
+                            "oneshot poisoned: a reply to a cancelled request \
+                             is still outstanding",
+                        );
+                        return Err(ReusableError::Poisoned);
                     }
+                    return Err(ReusableError::SenderAlreadyActive);
                 }
+                Err(_) => return Err(ReusableError::InternalError),
             }
         }
     }
 
+    /// Returns `true` if a receive was cancelled while its reply is still
+    /// outstanding, so that the channel can't be used until the reply
+    /// arrives, or [Reusable::reset] is called.
+    #[must_use]
+    pub fn is_poisoned(&self) -> bool {
+        matches!(
+            self.inner.state.load(Ordering::Acquire),
+            ROSC_WAITING | ROSC_WRITING
+        ) && self.inner.abandoned.load(Ordering::Acquire)
+    }
+
+    /// Return the channel to a usable state, abandoning any outstanding
+    /// reply.
+    ///
+    /// If a [Sender] is still live, it is detached from this `Reusable`, and
+    /// sending on it will fail with [ReusableError::ChannelClosed]. Any reply
+    /// which has been sent but not received is dropped.
+    pub async fn reset(&mut self) {
+        if self.inner.state.load(Ordering::Acquire) == ROSC_IDLE {
+            self.inner.abandoned.store(false, Ordering::Release);
+            return;
+        }
+
+        tracing::debug!(
+            counterpart = self.inner.counterpart(),
+            "resetting oneshot channel"
+        );
+        let old = core::mem::replace(&mut self.inner, Arc::new(Inner::new()).await);
+        old.close();
+    }
+
     /// Await the response from a created sender.
     ///
     /// If a sender has not been created, this function will immediately return
@@ -122,6 +226,7 @@ impl<T> Reusable<T> {
     /// If the sender is dropped without sending a response, this function will
     /// return an error after the sender has been dropped.
     pub async fn receive(&self) -> Result<T, ReusableError> {
+        let _guard = AbandonOnDrop { inner: &self.inner };
         loop {
             let wait = self.inner.wait.subscribe().await;
             let swap = self.inner.state.compare_exchange(
@@ -155,10 +260,18 @@ impl<T> Reusable<T> {
                     wait.await?;
                 }
                 Err(ROSC_IDLE) => {
-                    // We are currently idle, i.e. no sender has been created,
-                    // or the existing one was dropped unused.
+                    // We are currently idle, i.e. no sender has been created.
                     break Err(ReusableError::NoSenderActive);
                 }
+                Err(ROSC_DROPPED) => {
+                    // The existing sender was dropped unused.
+                    tracing::warn!(
+                        counterpart = self.inner.counterpart(),
+                        "oneshot sender dropped without replying"
+                    );
+                    self.inner.state.store(ROSC_IDLE, Ordering::Release);
+                    break Err(ReusableError::SenderDropped);
+                }
                 Err(_) => {
                     // Something has gone terribly wrong. Return an error.
                     break Err(ReusableError::InternalError);
@@ -175,49 +288,23 @@ impl<T> Reusable<T> {
 
 impl<T> Drop for Reusable<T> {
     fn drop(&mut self) {
-        // Immediately mark the state as closed
-        let old = self.inner.state.swap(ROSC_CLOSED, Ordering::AcqRel);
-        // Mark the waiter as closed (shouldn't be necessary - you can only create
-        // a waiter from the Reusable type, which we are now dropping).
-        self.inner.wait.close();
+        self.inner.close();
+    }
+}
 
-        // Determine if we need to drop the payload, if there is one.
-        match old {
-            ROSC_IDLE => {
-                // Nothing to do, already idle, no contents
-            }
-            ROSC_WAITING => {
-                // We are waiting for the sender, but it will fail to send.
-                // Nothing to do.
-            }
-            ROSC_WRITING => {
-                // We are cancelling mid-send. This will cause the sender
-                // to fail, and IT is responsible for dropping the almost-
-                // sent message.
-            }
-            ROSC_READY => {
-                // We have received a message, but are dropping before reception.
-                // We are responsible to drop the contents.
-                unsafe {
-                    let ptr: *mut MaybeUninit<T> = self.inner.cell.get();
-                    let ptr: *mut T = ptr.cast();
-                    core::ptr::drop_in_place(ptr);
-                }
-            }
-            ROSC_READING => {
-                // This SHOULD be impossible, as this is a transient state while
-                // receiving, which shouldn't be possible if we are dropping the
-                // receiver. Make this a debug assert to catch if this ever happens
-                // during development or testing, otherwise do nothing.
-                debug_assert!(false, "Dropped receiver while reading?");
-            }
-            ROSC_CLOSED => {
-                // This SHOULD be impossible, as closing requires dropping the
-                // receiver. Make this a debug assert to catch if this ever happens
-                // during development or testing, otherwise do nothing.
-                debug_assert!(false, "Receiver already closed while closing?");
-            }
-            _ => {}
+impl<T> Drop for AbandonOnDrop<'_, T> {
+    fn drop(&mut self) {
+        // If the receive completed, the state is no longer WAITING or
+        // WRITING. Otherwise, it was cancelled with the reply outstanding.
+        if matches!(
+            self.inner.state.load(Ordering::Acquire),
+            ROSC_WAITING | ROSC_WRITING
+        ) {
+            self.inner.abandoned.store(true, Ordering::Release);
+            tracing::debug!(
+                counterpart = self.inner.counterpart(),
+                "oneshot receive cancelled with reply outstanding"
+            );
         }
     }
 }
@@ -273,11 +360,12 @@ impl<T> Sender<T> {
 
 impl<T> Drop for Sender<T> {
     fn drop(&mut self) {
-        // Attempt to move the state from WAITING to IDLE, and wake any
-        // pending waiters. This will cause an Err(()) on the receive side.
+        // Attempt to move the state from WAITING to DROPPED, and wake any
+        // pending waiters. This will cause an Err(SenderDropped) on the
+        // receive side.
         let _ = self.inner.state.compare_exchange(
             ROSC_WAITING,
-            ROSC_IDLE,
+            ROSC_DROPPED,
             Ordering::AcqRel,
             Ordering::Relaxed,
         );
@@ -298,6 +386,162 @@ impl<T> Inner<T> {
             state: AtomicU8::new(ROSC_IDLE),
             cell: UnsafeCell::new(MaybeUninit::uninit()),
             wait: WaitCell::new(),
+            abandoned: AtomicBool::new(false),
+            counterpart: Mutex::new_with_raw_mutex(UNKNOWN_COUNTERPART, Spinlock::new()),
         }
     }
+
+    fn counterpart(&self) -> &'static str {
+        *self.counterpart.lock()
+    }
+
+    /// Close the channel, dropping any reply which was sent but not yet
+    /// received.
+    fn close(&self) {
+        // Immediately mark the state as closed
+        let old = self.state.swap(ROSC_CLOSED, Ordering::AcqRel);
+        // Mark the waiter as closed (shouldn't be necessary - you can only create
+        // a waiter from the Reusable type, which we are now dropping).
+        self.wait.close();
+
+        // Determine if we need to drop the payload, if there is one.
+        match old {
+            ROSC_IDLE => {
+                // Nothing to do, already idle, no contents
+            }
+            ROSC_WAITING | ROSC_DROPPED => {
+                // We are waiting for the sender, but it will fail to send.
+                // Nothing to do.
+            }
+            ROSC_WRITING => {
+                // We are cancelling mid-send. This will cause the sender
+                // to fail, and IT is responsible for dropping the almost-
+                // sent message.
+            }
+            ROSC_READY => {
+                // We have received a message, but are dropping before reception.
+                // We are responsible to drop the contents.
+                unsafe {
+                    let ptr: *mut MaybeUninit<T> = self.cell.get();
+                    let ptr: *mut T = ptr.cast();
+                    core::ptr::drop_in_place(ptr);
+                }
+            }
+            ROSC_READING => {
+                // This SHOULD be impossible, as this is a transient state while
+                // receiving, which shouldn't be possible if we are dropping the
+                // receiver. Make this a debug assert to catch if this ever happens
+                // during development or testing, otherwise do nothing.
+                debug_assert!(false, "Dropped receiver while reading?");
+            }
+            ROSC_CLOSED => {
+                // This SHOULD be impossible, as closing requires dropping the
+                // receiver. Make this a debug assert to catch if this ever happens
+                // during development or testing, otherwise do nothing.
+                debug_assert!(false, "Receiver already closed while closing?");
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TestKernel;
+    use futures::FutureExt;
+
+    #[test]
+    fn sender_dropped() {
+        TestKernel::run(|_| async move {
+            let reply = Reusable::<usize>::new_async().await;
+            let tx = reply.sender_to("test_service").await.unwrap();
+            drop(tx);
+            assert_eq!(reply.receive().await, Err(ReusableError::SenderDropped));
+
+            // the channel can be used again.
+            let tx = reply.sender().await.unwrap();
+            tx.send(1).unwrap();
+            assert_eq!(reply.receive().await, Ok(1));
+        })
+    }
+
+    #[test]
+    fn sender_dropped_unobserved() {
+        TestKernel::run(|_| async move {
+            let reply = Reusable::<usize>::new_async().await;
+            drop(reply.sender().await.unwrap());
+
+            // nobody received the error, but a new sender can be created.
+            let tx = reply.sender().await.unwrap();
+            tx.send(2).unwrap();
+            assert_eq!(reply.receive().await, Ok(2));
+        })
+    }
+
+    #[test]
+    fn cancelled_receive_discards_stale_reply() {
+        TestKernel::run(|_| async move {
+            let reply = Reusable::<usize>::new_async().await;
+            let tx = reply.sender_to("test_service").await.unwrap();
+            assert!(reply.receive().now_or_never().is_none());
+            assert!(reply.is_poisoned());
+
+            // the reply arrives after the receiver gave up on it.
+            tx.send(1).unwrap();
+            assert!(!reply.is_poisoned());
+
+            let tx = reply.sender().await.unwrap();
+            tx.send(2).unwrap();
+            assert_eq!(reply.receive().await, Ok(2));
+        })
+    }
+
+    #[test]
+    fn cancelled_receive_poisons() {
+        TestKernel::run(|_| async move {
+            let mut reply = Reusable::<usize>::new_async().await;
+            let stale = reply.sender_to("test_service").await.unwrap();
+            assert!(reply.receive().now_or_never().is_none());
+            assert_eq!(reply.sender().await.err(), Some(ReusableError::Poisoned));
+
+            reply.reset().await;
+            assert!(!reply.is_poisoned());
+            let tx = reply.sender().await.unwrap();
+
+            // the stale sender is detached from the reset channel.
+            assert_eq!(stale.send(1), Err(ReusableError::ChannelClosed));
+            tx.send(2).unwrap();
+            assert_eq!(reply.receive().await, Ok(2));
+        })
+    }
+
+    #[test]
+    fn cancelled_receive_then_sender_dropped() {
+        TestKernel::run(|_| async move {
+            let reply = Reusable::<usize>::new_async().await;
+            let tx = reply.sender().await.unwrap();
+            assert!(reply.receive().now_or_never().is_none());
+            drop(tx);
+
+            // the abandoned request is cleared once its sender is dropped.
+            assert!(!reply.is_poisoned());
+            let tx = reply.sender().await.unwrap();
+            tx.send(3).unwrap();
+            assert_eq!(reply.receive().await, Ok(3));
+        })
+    }
+
+    #[test]
+    fn reset_drops_unreceived_reply() {
+        TestKernel::run(|_| async move {
+            let mut reply = Reusable::<std::rc::Rc<()>>::new_async().await;
+            let item = std::rc::Rc::new(());
+            reply.sender().await.unwrap().send(item.clone()).unwrap();
+            assert_eq!(std::rc::Rc::strong_count(&item), 2);
+
+            reply.reset().await;
+            assert_eq!(std::rc::Rc::strong_count(&item), 1);
+        })
+    }
 }
@@ -0,0 +1,63 @@
+//! I²C bus scanner
+//!
+//! A daemon which scans the I²C bus once at boot, and logs every device
+//! it finds. This is mostly useful when bringing up a new board, to check
+//! which devices are actually present before writing drivers for them.
+//!
+//! The most recent scan is also published through the
+//! [debug agent](crate::daemons::sermux::debug_agent), and can be read using
+//! [`last_report`]. The bus can be rescanned at any time from a Forth shell,
+//! using the `i2c-scan` word.
+
+use maitake::sync::{blocking::Mutex, spin::Spinlock};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    services::i2c::{I2cClient, ScanReport},
+    Kernel,
+};
+
+/// The most recently completed scan.
+static LAST_REPORT: Mutex<Option<ScanReport>, Spinlock> =
+    Mutex::new_with_raw_mutex(None, Spinlock::new());
+
+/// I²C Scanner Settings
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct I2cScanSettings {
+    /// Should the bus be scanned at boot?
+    #[serde(default)]
+    pub enabled: bool,
+}
+
+/// Scans the I²C bus, and logs every device found.
+#[tracing::instrument(skip(kernel, _settings))]
+pub async fn scan(kernel: &'static Kernel, _settings: I2cScanSettings) {
+    // wait for the platform to register an I²C driver.
+    let mut i2c = match I2cClient::from_registry(kernel).await {
+        Ok(i2c) => i2c,
+        Err(error) => {
+            tracing::warn!(?error, "Failed to connect to the I²C service");
+            return;
+        }
+    };
+
+    tracing::info!("Scanning the I²C bus...");
+    let report = i2c.scan().await;
+    for addr in report.devices() {
+        tracing::info!(addr = %format_args!("{addr:#04x}"), "Found I²C device");
+    }
+    for addr in report.errors() {
+        tracing::warn!(addr = %format_args!("{addr:#04x}"), "Failed to probe I²C address");
+    }
+    tracing::info!(devices = report.len(), "I²C scan complete");
+
+    *LAST_REPORT.lock() = Some(report);
+}
+
+/// Returns the result of the most recent boot-time scan, or `None` if no scan
+/// has completed.
+#[must_use]
+pub fn last_report() -> Option<ScanReport> {
+    *LAST_REPORT.lock()
+}
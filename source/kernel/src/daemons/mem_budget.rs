@@ -0,0 +1,113 @@
+//! Per-service memory budgets
+//!
+//! With the "alloc-tags" feature flag enabled, heap allocations are
+//! attributed to the [boot step](crate::boot) which made them. Tasks
+//! spawned by a boot step (such as a service's server task, or the Forth
+//! tasks spawned by the spawnulator) inherit its scope, so allocations made
+//! long after boot are still attributed to the service or daemon which owns
+//! them.
+//!
+//! This daemon periodically totals up the live allocations in each scope
+//! which has a configured [`MemoryBudget`], and logs a warning when a scope
+//! grows past its budget. Budgets are *soft*: allocations are never refused
+//! for exceeding them. The current usage of every scope can also be read
+//! using the debug agent (`mnemos-dbg allocs`).
+
+use core::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "alloc-tags")]
+use crate::Kernel;
+
+/// The maximum number of budgets that may be configured.
+pub const MAX_BUDGETS: usize = 16;
+
+/// The maximum length of a scope name in a [`MemoryBudget`].
+pub const MAX_SCOPE_LEN: usize = 32;
+
+/// Memory Budget Settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MemoryBudgetSettings {
+    /// Should memory budgets be checked? This requires the "alloc-tags"
+    /// feature flag.
+    #[serde(default)]
+    pub enabled: bool,
+    /// How often to check each scope's usage. Defaults to 5 seconds.
+    #[serde(default = "MemoryBudgetSettings::default_check_interval")]
+    pub check_interval: Duration,
+    /// The budgets to check.
+    #[serde(default)]
+    pub budgets: heapless::Vec<MemoryBudget, MAX_BUDGETS>,
+}
+
+/// A soft limit on the memory used by a single scope.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MemoryBudget {
+    /// The name of the scope, which is the name of the boot step which
+    /// started the service or daemon, such as `"serial_mux"` or
+    /// `"spawnulator"`.
+    pub scope: heapless::String<MAX_SCOPE_LEN>,
+    /// A warning is logged when the scope's live allocations total more than
+    /// this many bytes.
+    pub max_bytes: usize,
+}
+
+/// Periodically checks each scope's live allocations against its budget in
+/// `settings`, logging a warning when a scope exceeds its budget, and again
+/// when it returns to within it.
+#[cfg(feature = "alloc-tags")]
+#[tracing::instrument(skip(kernel, settings))]
+pub async fn watch(kernel: &'static Kernel, settings: MemoryBudgetSettings) {
+    use crate::mnemos_alloc::heap::tags;
+
+    tracing::info!(
+        budgets = settings.budgets.len(),
+        "memory budget watcher running"
+    );
+
+    let mut over = [false; MAX_BUDGETS];
+    loop {
+        let interval = settings.check_interval;
+        kernel.sleep_with_slack(interval, interval / 2).await;
+        for (budget, over) in settings.budgets.iter().zip(over.iter_mut()) {
+            let live_bytes = tags::live_bytes(&budget.scope);
+            match (live_bytes > budget.max_bytes, *over) {
+                (true, false) => tracing::warn!(
+                    scope = %budget.scope,
+                    live_bytes,
+                    max_bytes = budget.max_bytes,
+                    "scope exceeded its memory budget"
+                ),
+                (false, true) => tracing::info!(
+                    scope = %budget.scope,
+                    live_bytes,
+                    max_bytes = budget.max_bytes,
+                    "scope is back within its memory budget"
+                ),
+                _ => continue,
+            }
+            *over = !*over;
+        }
+    }
+}
+
+// === impl MemoryBudgetSettings ===
+
+impl MemoryBudgetSettings {
+    pub const DEFAULT_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+    const fn default_check_interval() -> Duration {
+        Self::DEFAULT_CHECK_INTERVAL
+    }
+}
+
+impl Default for MemoryBudgetSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            check_interval: Self::DEFAULT_CHECK_INTERVAL,
+            budgets: heapless::Vec::new(),
+        }
+    }
+}
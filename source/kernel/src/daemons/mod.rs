@@ -6,5 +6,9 @@
 //! Unlike [services][crate::services], daemons are not exposed as a
 //! client/server via the [registry][crate::registry].
 
+pub mod i2c_scan;
+pub mod mem_budget;
+pub mod screenshot;
 pub mod sermux;
 pub mod shells;
+pub mod soft_keyboard;
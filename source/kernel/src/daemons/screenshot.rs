@@ -0,0 +1,179 @@
+//! Display screenshots.
+//!
+//! This daemon captures the display's contents whenever Ctrl + Alt and the
+//! configured [hotkey](ScreenshotSettings::hotkey) are pressed, and sends
+//! them to the host on a SerMux port ([`WellKnown::Screenshot`], by default).
+//! This requires a display server with the
+//! [`SCREENSHOT`](crate::services::emb_display::capabilities::SCREENSHOT)
+//! capability.
+//!
+//! Each screenshot is sent as a binary [PBM] image, which most image viewers
+//! and converters can open. Screenshots are sent back to back, so a stream of
+//! them can be saved to a single file, which tools such as netpbm read as a
+//! sequence of images. Screenshots show the display in its physical
+//! orientation; see [`EmbDisplayClient::screenshot`].
+//!
+//! There is not yet a filesystem or key-value storage service to save
+//! screenshots on the target itself.
+//!
+//! [PBM]: https://netpbm.sourceforge.net/doc/pbm.html
+
+use core::fmt::Write;
+
+use mnemos_alloc::containers::FixedVec;
+use serde::{Deserialize, Serialize};
+use tracing::Level;
+
+use crate::{
+    services::{
+        emb_display::{capabilities, EmbDisplayClient, MonoChunk},
+        keyboard::KeyClient,
+        serial_mux::{PortHandle, WellKnown},
+    },
+    Kernel,
+};
+
+/// Settings for the [screenshot](self) daemon.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScreenshotSettings {
+    #[serde(default)]
+    pub enabled: bool,
+    /// The key which, pressed together with Ctrl and Alt, takes a
+    /// screenshot. Defaults to `p`.
+    #[serde(default = "ScreenshotSettings::default_hotkey")]
+    pub hotkey: char,
+    /// Port number. Defaults to [`WellKnown::Screenshot`].
+    #[serde(default = "ScreenshotSettings::default_port")]
+    pub port: u16,
+}
+
+#[tracing::instrument(name = "screenshot", level = Level::INFO, skip(kernel))]
+pub async fn run(kernel: &'static Kernel, settings: ScreenshotSettings) {
+    let mut keys = match KeyClient::from_registry(kernel, Default::default()).await {
+        Ok(keys) => keys,
+        Err(error) => {
+            tracing::warn!(?error, "no keyboard service, not taking screenshots");
+            return;
+        }
+    };
+    let mut display = match EmbDisplayClient::from_registry(kernel).await {
+        Ok(display) => display,
+        Err(error) => {
+            tracing::warn!(?error, "no display service, not taking screenshots");
+            return;
+        }
+    };
+    if !display.capabilities().contains(capabilities::SCREENSHOT) {
+        tracing::warn!("the display can't take screenshots");
+        return;
+    }
+    // The port is output-only, so it only needs a small buffer.
+    let Some(port) = PortHandle::open(kernel, settings.port, 16).await else {
+        tracing::warn!(port = settings.port, "failed to open the screenshot port");
+        return;
+    };
+    tracing::info!(hotkey = ?settings.hotkey, "screenshots running");
+
+    loop {
+        let event = match keys.next().await {
+            Ok(event) => event,
+            Err(_) => {
+                tracing::warn!("keyboard service is gone, not taking any more screenshots");
+                return;
+            }
+        };
+        if !event.is_hotkey(settings.hotkey) {
+            continue;
+        }
+
+        let chunk = match display.screenshot().await {
+            Ok(chunk) => chunk,
+            Err(error) => {
+                tracing::warn!(?error, "failed to take a screenshot");
+                continue;
+            }
+        };
+        let pbm = encode_pbm(&chunk).await;
+        port.send(pbm.as_slice()).await;
+        tracing::info!(
+            width = chunk.meta().width(),
+            height = chunk.meta().height(),
+            len = pbm.as_slice().len(),
+            "sent screenshot"
+        );
+    }
+}
+
+/// Encodes `chunk` as a binary PBM image.
+async fn encode_pbm(chunk: &MonoChunk) -> FixedVec<u8> {
+    let (width, height) = (chunk.meta().width(), chunk.meta().height());
+    let mut header = heapless::String::<32>::new();
+    // two `u32`s always fit.
+    let _ = write!(header, "P4\n{width} {height}\n");
+
+    let row_len = (width as usize).div_ceil(8);
+    // The buffer is allocated with room for the header and every row.
+    let mut pbm = FixedVec::new(header.len() + row_len * height as usize).await;
+    let _ = pbm.try_extend_from_slice(header.as_bytes());
+    for row in chunk.data().chunks(width as usize) {
+        // PBM rows are packed eight pixels to a byte, most significant bit
+        // first, and padded to a whole byte. A set bit is black.
+        for pixels in row.chunks(8) {
+            let byte = pixels
+                .iter()
+                .enumerate()
+                .filter(|(_, &px)| px == 0)
+                .fold(0u8, |byte, (i, _)| byte | (0x80 >> i));
+            let _ = pbm.try_push(byte);
+        }
+    }
+    pbm
+}
+
+// === impl ScreenshotSettings ===
+
+impl ScreenshotSettings {
+    pub const DEFAULT_HOTKEY: char = 'p';
+    pub const DEFAULT_PORT: u16 = WellKnown::Screenshot as u16;
+
+    const fn default_hotkey() -> char {
+        Self::DEFAULT_HOTKEY
+    }
+    const fn default_port() -> u16 {
+        Self::DEFAULT_PORT
+    }
+}
+
+impl Default for ScreenshotSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            hotkey: Self::DEFAULT_HOTKEY,
+            port: Self::DEFAULT_PORT,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{services::emb_display::FrameLocSize, test_util::TestKernel};
+
+    #[test]
+    fn encodes_pbm() {
+        TestKernel::run(|_| async {
+            let mut chunk = MonoChunk::allocate_mono(FrameLocSize {
+                offset_x: 0,
+                offset_y: 0,
+                width: 10,
+                height: 2,
+            })
+            .await;
+            // a lit diagonal line, on a dark background.
+            chunk.capture(|x, y| x == y || x == 9);
+
+            let pbm = encode_pbm(&chunk).await;
+            assert_eq!(pbm.as_slice(), b"P4\n10 2\n\x7f\x80\xbf\x80".as_slice());
+        })
+    }
+}
@@ -4,13 +4,28 @@
 
 use core::time::Duration;
 
+use futures::FutureExt;
+use mnemos_alloc::containers::FixedVec;
+use mnemos_dbg_proto as dbg;
+use postcard::accumulator::{CobsAccumulator, FeedResult};
 use serde::{Deserialize, Serialize};
+use sermux_proto::{Notice, Ping};
 
 use crate::{
-    services::serial_mux::{PortHandle, WellKnown},
+    boot::{self, StepState},
+    comms::{bbq, kchannel},
+    registry::ServiceInfo,
+    rings,
+    services::{
+        clipboard::{ClipboardClient, ClipboardSettings},
+        notification::{self, Notification},
+        serial_mux::{PortHandle, SerialMuxClient, WellKnown},
+    },
     Kernel,
 };
 
+pub use mnemos_dbg_proto::HeapStats;
+
 //
 // Sermux Loopback
 //
@@ -61,7 +76,10 @@ pub async fn loopback(kernel: &'static Kernel, settings: LoopbackSettings) {
         port, buffer_size, ..
     } = settings;
     tracing::debug!("initializing SerMux loopback...");
-    let p0 = PortHandle::open(kernel, port, buffer_size).await.unwrap();
+    let Some(p0) = PortHandle::open(kernel, port, buffer_size).await else {
+        tracing::error!(port, "Can't open the SerMux loopback port");
+        return;
+    };
     tracing::info!("SerMux Loopback running!");
 
     loop {
@@ -90,19 +108,24 @@ pub struct HelloSettings {
     /// Buffer size, in bytes. Defaults to 32
     #[serde(default = "HelloSettings::default_buffer_size")]
     pub buffer_size: usize,
-    /// Message to print. Defaults to `b"hello\r\n"`
+    /// Payload sent with each ping. Defaults to `"hello"`
     #[serde(default = "HelloSettings::default_message")]
     pub message: heapless::String<32>,
-    /// Interval between messages. Defaults to 1 second
+    /// Interval between pings. Defaults to 1 second
     #[serde(default = "HelloSettings::default_interval")]
     pub interval: Duration,
+    /// Send a ping as soon as a [notification](crate::services::notification)
+    /// is posted, carrying it as a [`Notice`]. Defaults to `true`
+    #[serde(default = "HelloSettings::default_notifications")]
+    pub notifications: bool,
 }
 
 impl HelloSettings {
     pub const DEFAULT_PORT: u16 = WellKnown::HelloWorld as u16;
     pub const DEFAULT_BUFFER_SIZE: usize = 32;
-    pub const DEFAULT_MESSAGE_STR: &str = "hello\r\n";
+    pub const DEFAULT_MESSAGE_STR: &str = "hello";
     pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(1);
+    pub const DEFAULT_NOTIFICATIONS: bool = true;
 
     const fn default_port() -> u16 {
         Self::DEFAULT_PORT
@@ -116,6 +139,9 @@ impl HelloSettings {
     const fn default_interval() -> Duration {
         Self::DEFAULT_INTERVAL
     }
+    const fn default_notifications() -> bool {
+        Self::DEFAULT_NOTIFICATIONS
+    }
 }
 
 impl Default for HelloSettings {
@@ -126,13 +152,22 @@ impl Default for HelloSettings {
             buffer_size: Self::DEFAULT_BUFFER_SIZE,
             message: heapless::String::from(Self::DEFAULT_MESSAGE_STR),
             interval: Self::DEFAULT_INTERVAL,
+            notifications: Self::DEFAULT_NOTIFICATIONS,
         }
     }
 }
 
 /// Spawns a hello server
 ///
-/// Periodically prints a message as a sign of life
+/// Periodically sends a [`Ping`] as a sign of life, carrying the kernel's
+/// uptime, the number of scheduler ticks, and a sequence number. A host can
+/// use these to tell that the target is still running, and to detect dropped
+/// pings or a reboot.
+///
+/// If [`HelloSettings::notifications`] is set, a ping is also sent as soon as
+/// a [notification](crate::services::notification) is posted, with the
+/// notification as a [`Notice`] in place of the configured payload, so that
+/// a host can show notifications from a target without a display.
 #[tracing::instrument(skip(kernel))]
 pub async fn hello(kernel: &'static Kernel, settings: HelloSettings) {
     let HelloSettings {
@@ -140,14 +175,704 @@ pub async fn hello(kernel: &'static Kernel, settings: HelloSettings) {
         buffer_size,
         message,
         interval,
+        notifications,
         ..
     } = settings;
     tracing::debug!("Starting SerMux 'hello world'...");
-    let p1 = PortHandle::open(kernel, port, buffer_size).await.unwrap();
+    let Some(p1) = PortHandle::open(kernel, port, buffer_size).await else {
+        tracing::error!(port, "Can't open the SerMux 'hello world' port");
+        return;
+    };
+    let notifications = if notifications {
+        kernel
+            .events()
+            .subscribe::<Notification>(4)
+            .await
+            .map_err(|error| tracing::warn!(%error, "Can't send notifications to the host"))
+            .ok()
+    } else {
+        None
+    };
     tracing::info!("SerMux 'hello world' running!");
 
+    let mut buf = [0u8; Ping::HEADER_SIZE + Notice::HEADER_SIZE + notification::MAX_LEN];
+    let mut notice_buf = [0u8; Notice::HEADER_SIZE + notification::MAX_LEN];
+    let mut seq = 0u32;
+    loop {
+        // pings are only a sign of life, so they can share wakeups with
+        // other timers.
+        let sleep = kernel.sleep_with_slack(interval, interval / 2);
+        let note = match notifications {
+            Some(ref notifications) => futures::select_biased! {
+                note = notifications.next().fuse() => Some(note),
+                _ = sleep.fuse() => None,
+            },
+            None => {
+                sleep.await;
+                None
+            }
+        };
+        let payload = match note {
+            Some(Notification {
+                severity, ref text, ..
+            }) => {
+                let notice = Notice {
+                    severity: severity.into(),
+                    text: text.as_str(),
+                };
+                // The buffer is sized for the longest notification, so this
+                // shouldn't fail, but a lost notice is better than a panic.
+                match notice.encode_to(&mut notice_buf) {
+                    Ok(notice) => &*notice,
+                    Err(error) => {
+                        tracing::warn!(?error, "Can't encode notice, sending a plain ping");
+                        message.as_bytes()
+                    }
+                }
+            }
+            None => message.as_bytes(),
+        };
+        let ping = Ping {
+            seq,
+            uptime_ms: kernel.uptime().as_millis() as u64,
+            ticks: kernel.tick_count() as u64,
+            payload,
+        };
+        // The buffer is sized for the largest possible message, so this
+        // shouldn't fail.
+        let frame = match ping.encode_to(&mut buf) {
+            Ok(frame) => frame,
+            Err(error) => {
+                tracing::warn!(?error, "Can't encode ping, skipping it");
+                continue;
+            }
+        };
+        // the host decodes each ping from a single frame.
+        p1.send_frame(frame).await;
+        seq = seq.wrapping_add(1);
+    }
+}
+
+//
+// Sermux Clipboard Bridge
+//
+
+/// Clipboard Bridge Settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct ClipboardBridgeSettings {
+    /// Should the clipboard bridge be enabled?
+    #[serde(default)]
+    pub enabled: bool,
+    /// Port number. Defaults to [WellKnown::Clipboard]
+    #[serde(default = "ClipboardBridgeSettings::default_port")]
+    pub port: u16,
+    /// Buffer size, in bytes. Defaults to 256
+    #[serde(default = "ClipboardBridgeSettings::default_buffer_size")]
+    pub buffer_size: usize,
+    /// The longest clipboard contents accepted from the host, in bytes.
+    /// Longer contents are discarded. Defaults to
+    /// [`ClipboardSettings::DEFAULT_MAX_LEN`]
+    #[serde(default = "ClipboardBridgeSettings::default_max_len")]
+    pub max_len: usize,
+}
+
+impl ClipboardBridgeSettings {
+    pub const DEFAULT_PORT: u16 = WellKnown::Clipboard as u16;
+    pub const DEFAULT_BUFFER_SIZE: usize = 256;
+
+    const fn default_port() -> u16 {
+        Self::DEFAULT_PORT
+    }
+    const fn default_buffer_size() -> usize {
+        Self::DEFAULT_BUFFER_SIZE
+    }
+    const fn default_max_len() -> usize {
+        ClipboardSettings::DEFAULT_MAX_LEN
+    }
+}
+
+impl Default for ClipboardBridgeSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            port: Self::DEFAULT_PORT,
+            buffer_size: Self::DEFAULT_BUFFER_SIZE,
+            max_len: Self::default_max_len(),
+        }
+    }
+}
+
+/// Spawns a clipboard bridge
+///
+/// Bridges the [`ClipboardService`](crate::services::clipboard) to the host,
+/// so that the host can paste into, and copy from, the target's clipboard.
+///
+/// Each message from the host starts with a one byte opcode:
+///
+/// - [`CLIPBOARD_GET`] asks for the clipboard's contents, which are sent back
+///   as a little-endian `u32` length, followed by that many bytes.
+/// - [`CLIPBOARD_SET`] is followed by a little-endian `u32` length, and that
+///   many bytes, which replace the clipboard's contents.
+///
+/// Since messages are length-prefixed, the clipboard's contents may contain
+/// any bytes, including zeroes. Contents longer than
+/// [`ClipboardBridgeSettings::max_len`] are discarded.
+#[tracing::instrument(skip(kernel))]
+pub async fn clipboard(kernel: &'static Kernel, settings: ClipboardBridgeSettings) {
+    let ClipboardBridgeSettings {
+        port,
+        buffer_size,
+        max_len,
+        ..
+    } = settings;
+    tracing::debug!("Starting SerMux clipboard bridge...");
+    let mut clip = match ClipboardClient::from_registry(kernel).await {
+        Ok(clip) => clip,
+        Err(error) => {
+            tracing::error!(?error, "no clipboard service, not starting the bridge");
+            return;
+        }
+    };
+    let Some(port) = PortHandle::open(kernel, port, buffer_size).await else {
+        tracing::error!(port, "Can't open the SerMux clipboard bridge port");
+        return;
+    };
+    tracing::info!("SerMux clipboard bridge running!");
+
+    let mut decoder = ClipboardDecoder::new(max_len).await;
+    loop {
+        let rgr = port.consumer().read_grant().await;
+        let len = rgr.len();
+        for &byte in rgr.iter() {
+            match decoder.push(byte) {
+                None => {}
+                Some(ClipboardMsg::Get) => match clip.get().await {
+                    Ok(contents) => {
+                        let prefix = (contents.len() as u32).to_le_bytes();
+                        port.send(&prefix).await;
+                        port.send(contents.as_slice()).await;
+                    }
+                    Err(error) => tracing::warn!(?error, "Failed to get clipboard"),
+                },
+                Some(ClipboardMsg::Set(data)) => {
+                    if let Err(error) = clip.set_from_slice(data).await {
+                        tracing::warn!(?error, "Failed to set clipboard");
+                    }
+                }
+                Some(ClipboardMsg::TooLong { len }) => {
+                    tracing::warn!(
+                        len,
+                        max_len,
+                        "Clipboard message from host too long, discarding"
+                    );
+                }
+                Some(ClipboardMsg::BadOp(op)) => {
+                    tracing::warn!(op, "Unknown clipboard opcode from host, discarding");
+                }
+            }
+        }
+        rgr.release(len);
+    }
+}
+
+/// Opcode asking the [clipboard bridge](clipboard) for the clipboard's
+/// contents.
+pub const CLIPBOARD_GET: u8 = 0;
+/// Opcode replacing the clipboard's contents, through the
+/// [clipboard bridge](clipboard).
+pub const CLIPBOARD_SET: u8 = 1;
+
+/// Decodes the messages sent to the [clipboard bridge](clipboard) by the
+/// host, a byte at a time.
+struct ClipboardDecoder {
+    state: ClipboardState,
+    data: FixedVec<u8>,
+}
+
+enum ClipboardState {
+    /// Waiting for an opcode.
+    Op,
+    /// Reading a `CLIPBOARD_SET`'s length.
+    Len { len: [u8; 4], read: usize },
+    /// Reading a `CLIPBOARD_SET`'s data.
+    Data { remaining: usize },
+    /// Skipping the data of a `CLIPBOARD_SET` which was too long.
+    Discard { remaining: usize },
+}
+
+enum ClipboardMsg<'a> {
+    Get,
+    Set(&'a [u8]),
+    TooLong { len: usize },
+    BadOp(u8),
+}
+
+impl ClipboardDecoder {
+    async fn new(max_len: usize) -> Self {
+        Self {
+            state: ClipboardState::Op,
+            // `FixedVec`s must have a non-zero capacity.
+            data: FixedVec::new(max_len.max(1)).await,
+        }
+    }
+
+    fn push(&mut self, byte: u8) -> Option<ClipboardMsg<'_>> {
+        match self.state {
+            ClipboardState::Op => match byte {
+                CLIPBOARD_GET => return Some(ClipboardMsg::Get),
+                CLIPBOARD_SET => {
+                    self.state = ClipboardState::Len {
+                        len: [0; 4],
+                        read: 0,
+                    }
+                }
+                op => return Some(ClipboardMsg::BadOp(op)),
+            },
+            ClipboardState::Len { mut len, mut read } => {
+                len[read] = byte;
+                read += 1;
+                if read < len.len() {
+                    self.state = ClipboardState::Len { len, read };
+                    return None;
+                }
+                let len = u32::from_le_bytes(len) as usize;
+                self.data.clear();
+                if len > self.data.capacity() {
+                    self.state = ClipboardState::Discard { remaining: len };
+                    return Some(ClipboardMsg::TooLong { len });
+                }
+                self.state = ClipboardState::Data { remaining: len };
+                if len == 0 {
+                    return self.finish();
+                }
+            }
+            ClipboardState::Data { remaining } => {
+                // the length was checked against the buffer's capacity.
+                let _ = self.data.try_push(byte);
+                self.state = ClipboardState::Data {
+                    remaining: remaining - 1,
+                };
+                if remaining == 1 {
+                    return self.finish();
+                }
+            }
+            ClipboardState::Discard { remaining } => {
+                self.state = if remaining == 1 {
+                    ClipboardState::Op
+                } else {
+                    ClipboardState::Discard {
+                        remaining: remaining - 1,
+                    }
+                };
+            }
+        }
+        None
+    }
+
+    fn finish(&mut self) -> Option<ClipboardMsg<'_>> {
+        self.state = ClipboardState::Op;
+        Some(ClipboardMsg::Set(self.data.as_slice()))
+    }
+}
+
+//
+// Sermux Debug Agent
+//
+
+/// Debug Agent Settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct DebugAgentSettings {
+    /// Should the debug agent be enabled?
+    ///
+    /// Defaults to `false`, as the agent answers requests from anything
+    /// connected to the serial port.
+    #[serde(default)]
+    pub enabled: bool,
+    /// Port number. Defaults to [WellKnown::Debug]
+    #[serde(default = "DebugAgentSettings::default_port")]
+    pub port: u16,
+    /// Buffer size, in bytes. Defaults to 256
+    #[serde(default = "DebugAgentSettings::default_buffer_size")]
+    pub buffer_size: usize,
+    /// Returns the current heap statistics, if the platform's allocator
+    /// tracks them.
+    ///
+    /// The kernel does not have access to the platform's global allocator, so
+    /// this must be set by the platform. If it is `None`, heap statistics
+    /// requests are answered with [`dbg::Response::Unsupported`].
+    #[serde(skip)]
+    pub heap_stats: Option<fn() -> HeapStats>,
+}
+
+impl DebugAgentSettings {
+    pub const DEFAULT_PORT: u16 = WellKnown::Debug as u16;
+    pub const DEFAULT_BUFFER_SIZE: usize = 256;
+
+    const fn default_port() -> u16 {
+        Self::DEFAULT_PORT
+    }
+    const fn default_buffer_size() -> usize {
+        Self::DEFAULT_BUFFER_SIZE
+    }
+}
+
+impl Default for DebugAgentSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            port: Self::DEFAULT_PORT,
+            buffer_size: Self::DEFAULT_BUFFER_SIZE,
+            heap_stats: None,
+        }
+    }
+}
+
+/// Spawns a read-only debug agent
+///
+/// Answers [`mnemos_dbg_proto::Request`]s from a host tool (such as
+/// `mnemos-dbg`) with snapshots of the registry, heap, and scheduler.
+#[tracing::instrument(skip(kernel, settings))]
+pub async fn debug_agent(kernel: &'static Kernel, settings: DebugAgentSettings) {
+    let DebugAgentSettings {
+        port,
+        buffer_size,
+        heap_stats,
+        ..
+    } = settings;
+    tracing::debug!("Starting SerMux debug agent...");
+    let Some(port) = PortHandle::open(kernel, port, buffer_size).await else {
+        tracing::error!(port, "Can't open the SerMux debug agent port");
+        return;
+    };
+    tracing::info!("SerMux debug agent running!");
+
+    // Requests are all tiny, fixed-size enums.
+    let mut cobs_buf: CobsAccumulator<16> = CobsAccumulator::new();
+    let mut agent = DebugAgent {
+        kernel,
+        port: &port,
+        heap_stats,
+        buf: [0; dbg::MAX_FRAME_SIZE],
+    };
     loop {
-        kernel.sleep(interval).await;
-        p1.send(message.as_bytes()).await;
+        let rgr = port.consumer().read_grant().await;
+        let len = rgr.len();
+        let mut window = &rgr[..];
+        while !window.is_empty() {
+            window = match cobs_buf.feed::<dbg::Request>(window) {
+                FeedResult::Consumed => break,
+                FeedResult::OverFull(new_wind) => new_wind,
+                FeedResult::DeserError(new_wind) => {
+                    tracing::warn!("failed to decode debug agent request");
+                    new_wind
+                }
+                FeedResult::Success { data, remaining } => {
+                    tracing::debug!(request = ?data, "debug agent request");
+                    agent.answer(data).await;
+                    remaining
+                }
+            };
+        }
+        rgr.release(len);
+    }
+}
+
+struct DebugAgent<'port> {
+    kernel: &'static Kernel,
+    port: &'port PortHandle,
+    heap_stats: Option<fn() -> HeapStats>,
+    buf: [u8; dbg::MAX_FRAME_SIZE],
+}
+
+impl DebugAgent<'_> {
+    async fn answer(&mut self, req: dbg::Request) {
+        match req {
+            dbg::Request::Registry => {
+                for svc in self.services().await.as_slice() {
+                    let entry = dbg::ServiceEntry {
+                        uuid: dbg::Uuid(*svc.uuid.as_bytes()),
+                        service_id: svc.service_id.get(),
+                        userspace: svc.userspace,
+                    };
+                    self.send(&dbg::Response::Service(entry)).await;
+                }
+            }
+            dbg::Request::Heap => match self.heap_stats {
+                Some(heap_stats) => self.send(&dbg::Response::Heap(heap_stats())).await,
+                None => return self.send(&dbg::Response::Unsupported).await,
+            },
+            dbg::Request::Tasks => {
+                let counts = self.kernel.task_counts();
+                let counts = dbg::TaskCounts {
+                    spawned: counts.spawned,
+                    completed: counts.completed,
+                };
+                self.send(&dbg::Response::Tasks(counts)).await;
+
+                let mut steps = heapless::Vec::<boot::Step, { boot::MAX_STEPS }>::new();
+                self.kernel
+                    .boot_progress()
+                    .for_each_step(|step| {
+                        let _ = steps.push(*step);
+                    })
+                    .await;
+                let now = self.kernel.timer().now();
+                for step in steps {
+                    let state = match step.state {
+                        StepState::Pending => dbg::StepState::Pending,
+                        StepState::Running { started } => dbg::StepState::Running {
+                            elapsed_us: now.duration_since(started).as_micros() as u64,
+                        },
+                        StepState::Complete { elapsed } => dbg::StepState::Complete {
+                            elapsed_us: elapsed.as_micros() as u64,
+                        },
+                        StepState::Failed { elapsed } => dbg::StepState::Failed {
+                            elapsed_us: elapsed.as_micros() as u64,
+                        },
+                        StepState::Blocked { dependency } => dbg::StepState::Blocked { dependency },
+                    };
+                    self.send(&dbg::Response::BootStep {
+                        name: step.name,
+                        state,
+                    })
+                    .await;
+                    for &dependency in step.deps {
+                        self.send(&dbg::Response::BootDependency {
+                            step: step.name,
+                            dependency,
+                        })
+                        .await;
+                    }
+                }
+            }
+            dbg::Request::Channels => {
+                for svc in self.services().await.as_slice() {
+                    let uuid = dbg::Uuid(*svc.uuid.as_bytes());
+                    let entry = channel_entry(uuid, &svc.conn_queue);
+                    self.send(&dbg::Response::Channel(entry)).await;
+                    if let Some(ref req_queue) = svc.req_queue {
+                        let entry = channel_entry(uuid, req_queue);
+                        self.send(&dbg::Response::RequestQueue(entry)).await;
+                    }
+                }
+            }
+            #[cfg(feature = "alloc-tags")]
+            dbg::Request::Allocations => {
+                use crate::mnemos_alloc::heap::tags;
+
+                let mut snapshot = FixedVec::new(tags::MAX_TAGS).await;
+                tags::snapshot(&mut snapshot);
+                for stats in snapshot.as_slice() {
+                    let location = stats.tag.location.map(|loc| dbg::AllocLocation {
+                        file: truncate_start(loc.file(), dbg::MAX_FILE_LEN),
+                        line: loc.line(),
+                        column: loc.column(),
+                    });
+                    let scope = stats
+                        .tag
+                        .scope
+                        .map(|scope| truncate_end(scope, dbg::MAX_SCOPE_LEN));
+                    let entry = dbg::AllocTagEntry {
+                        location,
+                        scope,
+                        live_count: stats.live_count,
+                        live_bytes: stats.live_bytes,
+                    };
+                    self.send(&dbg::Response::AllocTag(entry)).await;
+                }
+                let count = tags::untracked();
+                self.send(&dbg::Response::UntrackedAllocs { count }).await;
+            }
+            #[cfg(not(feature = "alloc-tags"))]
+            dbg::Request::Allocations => return self.send(&dbg::Response::Unsupported).await,
+            dbg::Request::I2cDevices => match crate::daemons::i2c_scan::last_report() {
+                Some(report) => {
+                    for addr in report.devices() {
+                        self.send(&dbg::Response::I2cDevice { addr }).await;
+                    }
+                }
+                None => return self.send(&dbg::Response::Unsupported).await,
+            },
+            dbg::Request::Rings => {
+                let rings = self.kernel.rings();
+                let stats = rings.stats();
+                self.send(&dbg::Response::Rings(dbg::RingStats {
+                    budget: rings.budget(),
+                    polls: stats.polls,
+                    exhausted: stats.exhausted,
+                }))
+                .await;
+
+                let mut procs = heapless::Vec::<_, { rings::MAX_PROCESSES }>::new();
+                rings.for_each_process(|process| {
+                    let _ = procs.push(dbg::ProcessEntry {
+                        id: process.id.as_u32(),
+                        requests: process.requests,
+                        malformed: process.malformed,
+                        backpressured: process.backpressured,
+                    });
+                });
+                for process in procs {
+                    self.send(&dbg::Response::Process(process)).await;
+                }
+            }
+            dbg::Request::Ports => {
+                let depths = match SerialMuxClient::from_registry_no_retry(self.kernel).await {
+                    Ok(mut client) => client.port_depths().await,
+                    Err(_) => None,
+                };
+                let Some(depths) = depths else {
+                    return self.send(&dbg::Response::Unsupported).await;
+                };
+                self.send(&dbg::Response::SermuxOutgoing(buffer_entry(
+                    depths.outgoing,
+                )))
+                .await;
+                for port in depths.ports.as_slice() {
+                    self.send(&dbg::Response::SermuxPort {
+                        port: port.port,
+                        incoming: buffer_entry(port.incoming),
+                    })
+                    .await;
+                }
+            }
+        }
+        self.send(&dbg::Response::Done).await;
+    }
+
+    /// Returns a snapshot of every service in the registry.
+    async fn services(&self) -> FixedVec<ServiceInfo> {
+        let registry = self.kernel.registry();
+        let mut count = 0;
+        registry.for_each_service(|_| count += 1).await;
+
+        // If any services are registered between counting them and taking the
+        // snapshot, they're left out; this is only a snapshot, after all.
+        // `FixedVec`s must have a non-zero capacity.
+        let mut services = FixedVec::new(count.max(1)).await;
+        registry
+            .for_each_service(|svc| {
+                let _ = services.try_push(*svc);
+            })
+            .await;
+        services
+    }
+
+    async fn send(&mut self, rsp: &dbg::Response<'_>) {
+        match postcard::to_slice_cobs(rsp, &mut self.buf[..]) {
+            Ok(frame) => self.port.send(frame).await,
+            Err(error) => tracing::warn!(?error, "failed to encode debug agent response"),
+        }
+    }
+}
+
+fn channel_entry(uuid: dbg::Uuid, depth: &kchannel::ChannelDepth) -> dbg::ChannelEntry {
+    dbg::ChannelEntry {
+        uuid,
+        len: depth.len,
+        capacity: depth.capacity,
+        high_water: depth.high_water,
+        overflows: depth.overflows,
+    }
+}
+
+fn buffer_entry(depth: bbq::BufferDepth) -> dbg::BufferEntry {
+    dbg::BufferEntry {
+        committed: depth.committed,
+        capacity: depth.capacity,
+    }
+}
+
+/// Returns at most the last `max` bytes of `s`, on a character boundary.
+#[cfg(feature = "alloc-tags")]
+fn truncate_start(s: &str, max: usize) -> &str {
+    let mut start = s.len().saturating_sub(max);
+    while !s.is_char_boundary(start) {
+        start += 1;
+    }
+    &s[start..]
+}
+
+/// Returns at most the first `max` bytes of `s`, on a character boundary.
+#[cfg(feature = "alloc-tags")]
+fn truncate_end(s: &str, max: usize) -> &str {
+    let mut end = s.len().min(max);
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    &s[..end]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        services::clipboard::ClipboardServer,
+        test_util::{SerialHost, TestKernel},
+    };
+
+    fn set(data: &[u8]) -> Vec<u8> {
+        let mut msg = vec![CLIPBOARD_SET];
+        msg.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        msg.extend_from_slice(data);
+        msg
+    }
+
+    /// Returns the clipboard's contents, as sent by the bridge.
+    async fn get(host: &mut SerialHost) -> Vec<u8> {
+        let port = WellKnown::Clipboard;
+        host.send(port, &[CLIPBOARD_GET]).await;
+        let mut msg = Vec::new();
+        while msg.len() < 4 {
+            msg.extend(host.recv(port).await);
+        }
+        let len = u32::from_le_bytes(msg[..4].try_into().unwrap()) as usize;
+        while msg.len() < 4 + len {
+            msg.extend(host.recv(port).await);
+        }
+        assert_eq!(msg.len(), 4 + len, "bridge sent more than the contents");
+        msg.split_off(4)
+    }
+
+    #[test]
+    fn clipboard_bridge() {
+        TestKernel::run(|k| async move {
+            let mut host = SerialHost::setup(k).await;
+            ClipboardServer::register(k, ClipboardSettings::default())
+                .await
+                .unwrap();
+            let settings = ClipboardBridgeSettings {
+                enabled: true,
+                max_len: 16,
+                ..Default::default()
+            };
+            k.spawn(clipboard(k, settings)).await;
+            let port = WellKnown::Clipboard;
+
+            assert_eq!(get(&mut host).await, b"");
+
+            // contents may contain zeroes...
+            host.send(port, &set(b"a\0b\0")).await;
+            assert_eq!(get(&mut host).await, b"a\0b\0");
+
+            // ...and may be split across frames.
+            let msg = set(b"hello world");
+            host.send(port, &msg[..3]).await;
+            host.send(port, &msg[3..]).await;
+            assert_eq!(get(&mut host).await, b"hello world");
+
+            // the clipboard may be emptied.
+            host.send(port, &set(b"")).await;
+            assert_eq!(get(&mut host).await, b"");
+
+            // contents which are too long are skipped, without losing track
+            // of the messages after them.
+            let mut msg = set(&[CLIPBOARD_SET; 17]);
+            msg.extend(set(b"after"));
+            host.send(port, &msg).await;
+            assert_eq!(get(&mut host).await, b"after");
+        })
     }
 }
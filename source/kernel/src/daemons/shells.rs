@@ -5,17 +5,30 @@
 use core::time::Duration;
 
 use crate::{
-    comms::bbq::{BidiHandle, GrantR},
-    forth::Params,
+    comms::{
+        bbq::{BidiHandle, GrantR},
+        events::Subscriber,
+        kchannel::KConsumer,
+    },
+    forth::{Params, SpawnedStdio},
     services::{
-        emb_display::{EmbDisplayClient, FrameLocSize, MonoChunk},
-        keyboard::{key_event, KeyClient, KeyClientError},
+        emb_display::{
+            fonts::{FontMetrics, FontSize},
+            EmbDisplayClient, FrameLocSize, MonoChunk,
+        },
+        keyboard::{
+            key_event::{self, Modifiers},
+            KeyClient, KeyClientError,
+        },
+        notification::{self, Notification, Severity},
+        serial_console::SerialConsoleClient,
         serial_mux::{PortHandle, WellKnown},
     },
     Kernel,
 };
 use embedded_graphics::{
-    mono_font::{MonoFont, MonoTextStyle},
+    draw_target::DrawTarget,
+    mono_font::{MonoTextStyle, MonoTextStyleBuilder},
     pixelcolor::BinaryColor,
     prelude::Point,
     primitives::{Line, Primitive, PrimitiveStyle},
@@ -23,10 +36,9 @@ use embedded_graphics::{
     Drawable,
 };
 
-use futures::FutureExt;
+use futures::{future, FutureExt};
 use input_mgr::RingLine;
 use key_event::KeyEvent;
-use profont::PROFONT_12_POINT;
 
 use crate::forth::Forth;
 
@@ -44,8 +56,16 @@ pub struct SermuxShellSettings {
     pub capacity: usize,
     /// Forth parameters for the shell
     ///
-    /// Uses the default value of [Params]
+    /// Uses the default value of [Params], but with [colored
+    /// output](Params::color) enabled
     pub forth_settings: Params,
+    /// Serve the shell on a secure port, so that only a host which knows the
+    /// serial mux's pre-shared key can use it. See
+    /// [`SerialMuxClient::open_secure_port`](crate::services::serial_mux::SerialMuxClient::open_secure_port).
+    ///
+    /// Defaults to `false`
+    #[cfg(feature = "secure-sermux")]
+    pub secure: bool,
 }
 
 impl Default for SermuxShellSettings {
@@ -53,39 +73,156 @@ impl Default for SermuxShellSettings {
         Self {
             port: WellKnown::ForthShell0.into(),
             capacity: 256,
-            forth_settings: Default::default(),
+            forth_settings: Params {
+                color: true,
+                ..Params::new()
+            },
+            #[cfg(feature = "secure-sermux")]
+            secure: false,
         }
     }
 }
 
 /// Spawns a forth shell on the given port
+///
+/// If the host [resets](PortHandle::reset) the port, the shell is restarted
+/// with a new Forth VM.
 #[tracing::instrument(skip(k))]
 pub async fn sermux_shell(k: &'static Kernel, settings: SermuxShellSettings) {
     let SermuxShellSettings {
         port,
         capacity,
         forth_settings,
+        #[cfg(feature = "secure-sermux")]
+        secure,
     } = settings;
-    let port = PortHandle::open(k, port, capacity).await.unwrap();
-    let (task, tid_io) = Forth::new(k, forth_settings)
-        .await
-        .expect("Forth spawning must succeed");
+    #[cfg(feature = "secure-sermux")]
+    let port = if secure {
+        PortHandle::open_secure(k, port, capacity).await
+    } else {
+        PortHandle::open(k, port, capacity).await
+    };
+    #[cfg(not(feature = "secure-sermux"))]
+    let port = PortHandle::open(k, port, capacity).await;
+    let port = port.unwrap();
+    k.spawn(async move {
+        loop {
+            let (task, tid_io) = Forth::new(k, forth_settings)
+                .await
+                .expect("Forth spawning must succeed");
+            k.spawn(task.run()).await;
+            loop {
+                let reset = futures::select_biased! {
+                    _ = port.reset().fuse() => true,
+                    rgr = port.consumer().read_grant().fuse() => {
+                        // take the input out of the port's buffer before
+                        // forwarding it, so that a reset can discard the rest.
+                        let mut input = [0u8; 64];
+                        let len = rgr.len().min(input.len());
+                        input[..len].copy_from_slice(&rgr[..len]);
+                        rgr.release(len);
+                        // a wedged VM may never read its input, so stop
+                        // waiting for it if the port is reset.
+                        futures::select_biased! {
+                            _ = port.reset().fuse() => true,
+                            _ = tid_io.producer().write_all(&input[..len]).fuse() => false,
+                        }
+                    },
+                    output = tid_io.consumer().read_grant().fuse() => {
+                        let needed = output.len();
+                        port.send(&output).await;
+                        output.release(needed);
+                        false
+                    },
+                    _ = tid_io.closed().fuse() => {
+                        tracing::info!(port = port.port(), "Forth VM exited, closing shell");
+                        return;
+                    },
+                };
+                if reset {
+                    tracing::info!(port = port.port(), "Shell port reset, restarting Forth VM");
+                    // the old VM exits once it's idle.
+                    tid_io.close();
+                    break;
+                }
+            }
+        }
+    })
+    .await;
+}
+
+/// Settings for the [console_shell] daemon
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct ConsoleShellSettings {
+    /// Forth parameters for the shell
+    ///
+    /// Uses the default value of [Params], but with [colored
+    /// output](Params::color) enabled
+    pub forth_settings: Params,
+}
+
+impl Default for ConsoleShellSettings {
+    fn default() -> Self {
+        Self {
+            forth_settings: Params {
+                color: true,
+                ..Params::new()
+            },
+        }
+    }
+}
+
+/// Spawns a forth shell on the [serial console](crate::services::serial_console)
+///
+/// This is used in place of [sermux_shell] when the serial mux is disabled.
+/// The shell holds the console open until its Forth VM exits.
+#[tracing::instrument(skip(k))]
+pub async fn console_shell(k: &'static Kernel, settings: ConsoleShellSettings) {
+    let ConsoleShellSettings { forth_settings } = settings;
+    let mut client = match SerialConsoleClient::from_registry(k).await {
+        Ok(client) => client,
+        Err(error) => {
+            tracing::error!(?error, "no serial console, not starting the shell");
+            return;
+        }
+    };
+    let console = match client.open().await {
+        Ok(console) => console,
+        Err(error) => {
+            tracing::error!(?error, "failed to open the serial console");
+            return;
+        }
+    };
+    let (task, tid_io) = match Forth::new(k, forth_settings).await {
+        Ok(forth) => forth,
+        Err(error) => {
+            tracing::error!(?error, "failed to spawn the Forth VM");
+            return;
+        }
+    };
     k.spawn(task.run()).await;
     k.spawn(async move {
         loop {
             futures::select_biased! {
-                rgr = port.consumer().read_grant().fuse() => {
+                rgr = console.consumer().read_grant().fuse() => {
                     let needed = rgr.len();
-                    let mut tid_io_wgr = tid_io.producer().send_grant_exact(needed).await;
-                    tid_io_wgr.copy_from_slice(&rgr);
-                    tid_io_wgr.commit(needed);
+                    tid_io.producer().write_all(&rgr).await;
                     rgr.release(needed);
                 },
                 output = tid_io.consumer().read_grant().fuse() => {
-                    let needed = output.len();
-                    port.send(&output).await;
-                    output.release(needed);
-                }
+                    // the console's buffer may be smaller than the VM's
+                    // stdout, so write as much as fits.
+                    let mut wgr = console.producer().send_grant_max(output.len()).await;
+                    let len = wgr.len();
+                    wgr.copy_from_slice(&output[..len]);
+                    wgr.commit(len);
+                    output.release(len);
+                },
+                _ = tid_io.closed().fuse() => {
+                    tracing::info!("Forth VM exited, closing console shell");
+                    return;
+                },
             }
         }
     })
@@ -123,10 +260,25 @@ pub struct GraphicalShellSettings {
     pub disp_height_px: u32,
     /// Redraw debounce time
     pub redraw_debounce: Duration,
-    /// Font used for the shell
+    /// Font size used for the shell
+    ///
+    /// Defaults to [FontSize::Medium]. The font size can be changed at runtime
+    /// by pressing Ctrl and `+` or `-`, and reset to this size with Ctrl and `0`.
+    pub font: FontSize,
+    /// How long each [notification](crate::services::notification) is shown
+    /// in the titlebar
+    ///
+    /// Defaults to 5 seconds
+    pub toast_duration: Duration,
+    /// The key which, pressed together with Ctrl and Alt, switches to the next
+    /// output pane.
     ///
-    /// Defaults to [PROFONT_12_POINT]
-    pub font: MonoFont<'static>,
+    /// Each task spawned by the shell's Forth VM (using `spawn`) gets its own
+    /// pane, which shows its output, and receives keyboard input while it's
+    /// focused. See [graphical_shell_mono] for details.
+    ///
+    /// Defaults to `n`
+    pub pane_hotkey: char,
 }
 
 impl GraphicalShellSettings {
@@ -137,13 +289,40 @@ impl GraphicalShellSettings {
             disp_width_px: width_px,
             disp_height_px: height_px,
             redraw_debounce: Duration::from_millis(50),
-            font: PROFONT_12_POINT,
+            font: FontSize::default(),
+            toast_duration: Duration::from_secs(5),
+            pane_hotkey: 'n',
         }
     }
 }
 
+/// The most rows of text that the graphical shell keeps, regardless of font
+/// size.
+const MAX_ROWS: usize = 16;
+/// The most columns of text that the graphical shell keeps, regardless of font
+/// size.
+const MAX_COLS: usize = 46;
+/// The most output panes that the graphical shell keeps, including the pane
+/// for its own Forth VM.
+const MAX_PANES: usize = 4;
+
+type ShellLines = RingLine<MAX_ROWS, MAX_COLS>;
+
 /// Spawns a graphical shell using the [EmbDisplayService](crate::services::emb_display::EmbDisplayService) service
-// TODO: tracing the `settings` field draws the whole PROFONT_12_POINT, which is hilarious but annoying
+///
+/// # Panes
+///
+/// The output of each task spawned by the shell's Forth VM (or by one of the
+/// tasks it spawned) using `spawn` is shown in a separate pane, rather than
+/// being mixed in with the shell's output. One pane is shown at a time, and
+/// the titlebar shows which. Pressing Ctrl, Alt, and the [pane
+/// hotkey](GraphicalShellSettings::pane_hotkey) switches to the next pane, and
+/// keyboard input is sent to the task whose pane is shown.
+///
+/// The shell keeps up to 3 panes for spawned tasks. When another task is
+/// spawned, it replaces the pane of a task which has exited, or if there are
+/// none, the least recently spawned task whose pane isn't shown. That task's
+/// stdio is closed, so it exits once it's idle.
 #[tracing::instrument(skip(k, settings))]
 pub async fn graphical_shell_mono(k: &'static Kernel, settings: GraphicalShellSettings) {
     let GraphicalShellSettings {
@@ -153,6 +332,8 @@ pub async fn graphical_shell_mono(k: &'static Kernel, settings: GraphicalShellSe
         disp_height_px,
         redraw_debounce,
         font,
+        toast_duration,
+        pane_hotkey,
     } = settings;
 
     let mut keyboard = KeyClient::from_registry(k, Default::default())
@@ -161,92 +342,467 @@ pub async fn graphical_shell_mono(k: &'static Kernel, settings: GraphicalShellSe
     let mut disp_hdl = EmbDisplayClient::from_registry(k)
         .await
         .expect("failed to get EmbDisplayClient");
-    let char_y = font.character_size.height;
-    let char_x = font.character_size.width + font.character_spacing;
 
-    // Draw titlebar
-    {
-        let mut fc_0 = MonoChunk::allocate_mono(FrameLocSize {
-            height: char_y,
-            width: disp_width_px,
-            offset_x: 0,
-            offset_y: 0,
-        })
-        .await;
-
-        let text_style = MonoTextStyle::new(&font, BinaryColor::On);
-        let text1 = Text::new("mnemOS", Point::new(0, font.baseline as i32), text_style);
-        text1.draw(&mut fc_0).unwrap();
-
-        let title = "forth shell";
-        let text2 = Text::new(
-            title,
-            Point::new(
-                (disp_width_px as i32) - ((title.len() as u32) * char_x) as i32,
-                font.baseline as i32,
-            ),
-            text_style,
-        );
-        text2.draw(&mut fc_0).unwrap();
-
-        let line_style = PrimitiveStyle::with_stroke(BinaryColor::On, 1);
-        Line::new(
-            Point {
-                x: 0,
-                y: font.underline.offset as i32,
-            },
-            Point {
-                x: disp_width_px as i32,
-                y: font.underline.offset as i32,
-            },
-        )
-        .into_styled(line_style)
-        .draw(&mut fc_0)
-        .unwrap();
-        disp_hdl.draw(fc_0).await.unwrap();
-    }
-
-    let style = ring_drawer::BwStyle {
-        background: BinaryColor::Off,
-        font: MonoTextStyle::new(&font, BinaryColor::On),
+    let mut toasts = Toasts {
+        sub: k
+            .events()
+            .subscribe::<Notification>(4)
+            .await
+            .map_err(|error| tracing::warn!(%error, "Can't show notifications"))
+            .ok(),
+        current: None,
+        duration: toast_duration,
+        changed: false,
     };
 
-    // At 12-pt font, there is enough room for 16 lines, with 50 chars/line.
-    //
-    // Leave out 4 for the implicit margin of two characters on each gutter.
-    let mut rline = RingLine::<16, 46>::new();
+    let mut console = Console {
+        font,
+        default_font: font,
+        cols: MAX_COLS,
+    };
 
-    let (task, tid_io) = Forth::new(k, forth_settings)
+    let (mut task, tid_io) = Forth::new(k, forth_settings)
         .await
         .expect("Forth spawning must succeed");
+    let spawned = task.spawned_stdio(MAX_PANES).await;
 
     // Spawn the forth task
     k.spawn(task.run()).await;
 
+    let mut panes = Panes::new(tid_io, spawned, pane_hotkey);
+
+    loop {
+        // (Re)draw the titlebar and lay out the text area for the current
+        // font. This happens again whenever the font is changed.
+        let font = console.font;
+        let metrics = font.metrics();
+        let char_y = metrics.char_height;
+        console.layout(metrics, disp_width_px, disp_height_px - char_y);
+        draw_titlebar(
+            &mut disp_hdl,
+            font,
+            disp_width_px,
+            &panes.title(),
+            toasts.current(),
+        )
+        .await;
+        toasts.changed = false;
+        panes.changed = false;
+
+        let style = ring_drawer::BwStyle {
+            background: BinaryColor::Off,
+            font: MonoTextStyle::new(font.font(), BinaryColor::On),
+        };
+
+        let mut fc_0 = MonoChunk::allocate_mono(FrameLocSize {
+            offset_x: 0,
+            offset_y: char_y,
+            width: disp_width_px,
+            height: disp_height_px - char_y,
+        })
+        .await;
+        // don't leave any text drawn in the previous font behind.
+        DrawTarget::clear(&mut fc_0, BinaryColor::Off).unwrap();
+
+        while console.font == font {
+            // Draw to the display
+            if toasts.changed || panes.changed {
+                draw_titlebar(
+                    &mut disp_hdl,
+                    font,
+                    disp_width_px,
+                    &panes.title(),
+                    toasts.current(),
+                )
+                .await;
+                // a different pane's text may be shorter than the last one's.
+                if panes.changed {
+                    DrawTarget::clear(&mut fc_0, BinaryColor::Off).unwrap();
+                }
+                toasts.changed = false;
+                panes.changed = false;
+            }
+            ring_drawer::drawer_bw(&mut fc_0, &panes.focused().lines, style.clone()).unwrap();
+            fc_0 = disp_hdl.draw_mono(fc_0).await.unwrap();
+
+            if panes.shell().exited {
+                tracing::warn!("Forth VM exited, closing shell");
+                return;
+            }
+
+            // Poll ONCE until there is progress, with unlimited time
+            io_poll(
+                k,
+                PollStyle::OneShot,
+                &mut keyboard,
+                &mut toasts,
+                &mut panes,
+                &mut console,
+            )
+            .await;
+
+            // SOMETHING happened, so now try and grab as many things as possible
+            // until the debounce timer expires
+            let _ = k
+                .timeout(
+                    redraw_debounce,
+                    io_poll(
+                        k,
+                        PollStyle::Forever,
+                        &mut keyboard,
+                        &mut toasts,
+                        &mut panes,
+                        &mut console,
+                    ),
+                )
+                .await;
+        }
+    }
+}
+
+/// Draws the graphical shell's titlebar, using `font`, with `toast` in place
+/// of the shell's `title` if there is one.
+async fn draw_titlebar(
+    disp_hdl: &mut EmbDisplayClient,
+    font: FontSize,
+    disp_width_px: u32,
+    title: &str,
+    toast: Option<&Notification>,
+) {
+    let metrics = font.metrics();
     let mut fc_0 = MonoChunk::allocate_mono(FrameLocSize {
-        offset_x: 0,
-        offset_y: char_y,
+        height: metrics.char_height,
         width: disp_width_px,
-        height: disp_height_px - char_y,
+        offset_x: 0,
+        offset_y: 0,
     })
     .await;
+    DrawTarget::clear(&mut fc_0, BinaryColor::Off).unwrap();
 
-    loop {
-        // Draw to the display
-        ring_drawer::drawer_bw(&mut fc_0, &rline, style.clone()).unwrap();
-        fc_0 = disp_hdl.draw_mono(fc_0).await.unwrap();
-
-        // Poll ONCE until there is progress, with unlimited time
-        io_poll(PollStyle::OneShot, &mut keyboard, &mut rline, &tid_io).await;
-
-        // SOMETHING happened, so now try and grab as many things as possible
-        // until the debounce timer expires
-        let _ = k
-            .timeout(
-                redraw_debounce,
-                io_poll(PollStyle::Forever, &mut keyboard, &mut rline, &tid_io),
-            )
-            .await;
+    let text_style = MonoTextStyle::new(font.font(), BinaryColor::On);
+    let text1 = Text::new("mnemOS", Point::new(0, metrics.baseline as i32), text_style);
+    text1.draw(&mut fc_0).unwrap();
+
+    // toasts are drawn inverted, so that they stand out.
+    let toast_style = MonoTextStyleBuilder::new()
+        .font(font.font())
+        .text_color(BinaryColor::Off)
+        .background_color(BinaryColor::On)
+        .build();
+    let mut toast_text = heapless::String::<{ notification::MAX_LEN + 3 }>::new();
+    let (title, style) = match toast {
+        Some(Notification { severity, text, .. }) => {
+            let marker = match severity {
+                Severity::Info => "",
+                Severity::Warn => "! ",
+                Severity::Error => "!! ",
+            };
+            // leave room for "mnemOS" and a space.
+            let cols = (disp_width_px / metrics.char_width).saturating_sub(7) as usize;
+            for ch in marker.chars().chain(text.chars()).take(cols) {
+                // the font can only draw ASCII.
+                let _ = toast_text.push(if ch.is_ascii() { ch } else { '?' });
+            }
+            (toast_text.as_str(), toast_style)
+        }
+        None => (title, text_style),
+    };
+    let text2 = Text::new(
+        title,
+        Point::new(
+            (disp_width_px as i32) - ((title.len() as u32) * metrics.char_width) as i32,
+            metrics.baseline as i32,
+        ),
+        style,
+    );
+    text2.draw(&mut fc_0).unwrap();
+
+    let line_style = PrimitiveStyle::with_stroke(BinaryColor::On, 1);
+    Line::new(
+        Point {
+            x: 0,
+            y: metrics.underline_offset as i32,
+        },
+        Point {
+            x: disp_width_px as i32,
+            y: metrics.underline_offset as i32,
+        },
+    )
+    .into_styled(line_style)
+    .draw(&mut fc_0)
+    .unwrap();
+    disp_hdl.draw(fc_0).await.unwrap();
+}
+
+/// Notifications shown in the graphical shell's titlebar, one at a time.
+struct Toasts {
+    sub: Option<Subscriber<Notification>>,
+    /// The notification being shown, and the uptime at which to stop showing
+    /// it.
+    current: Option<(Notification, Duration)>,
+    /// How long each notification is shown.
+    duration: Duration,
+    /// Set when the titlebar must be redrawn.
+    changed: bool,
+}
+
+impl Toasts {
+    fn current(&self) -> Option<&Notification> {
+        self.current.as_ref().map(|(note, _)| note)
+    }
+
+    /// Waits until a notification is posted, which replaces the one being
+    /// shown, or until the one being shown expires.
+    async fn next(&mut self, k: &'static Kernel) -> Productive {
+        let note = {
+            let posted = match self.sub {
+                Some(ref sub) => future::Either::Left(sub.next()),
+                None => future::Either::Right(future::pending()),
+            };
+            let expired = match self.current {
+                Some((_, until)) => future::Either::Left(k.sleep(until.saturating_sub(k.uptime()))),
+                None => future::Either::Right(future::pending()),
+            };
+            futures::select_biased! {
+                note = posted.fuse() => Some(note),
+                _ = expired.fuse() => None,
+            }
+        };
+        self.current = note.map(|note| {
+            let until = k.uptime() + self.duration;
+            (note, until)
+        });
+        self.changed = true;
+        Productive::Yes
+    }
+}
+
+/// The graphical shell's font, and the layout of its text for that font.
+struct Console {
+    font: FontSize,
+    default_font: FontSize,
+    /// The number of columns of text that fit on a line in the current font.
+    cols: usize,
+}
+
+/// The output panes of the graphical shell, one of which is shown at a time.
+struct Panes {
+    /// The shell's own pane is always first, followed by the panes of spawned
+    /// tasks, from least to most recently spawned.
+    panes: heapless::Vec<Pane, MAX_PANES>,
+    /// The index of the pane being shown.
+    focus: usize,
+    /// Receives the stdio of tasks spawned by the shell's Forth VM.
+    spawned: Option<KConsumer<SpawnedStdio>>,
+    /// The key which switches to the next pane.
+    hotkey: char,
+    /// Set when the titlebar must be redrawn, and the text area cleared.
+    changed: bool,
+}
+
+/// The text output by one Forth VM.
+struct Pane {
+    /// The ID of the VM, if it was spawned, or `None` for the shell's own VM.
+    id: Option<usize>,
+    stdio: BidiHandle,
+    lines: ShellLines,
+    /// The column of the next character of output from the VM.
+    remote_col: usize,
+    /// Progress through an ANSI escape sequence in the VM's output.
+    escape: Escape,
+    /// Set once the VM's stdio has closed, after which its pane is kept until
+    /// it's needed for another task.
+    exited: bool,
+}
+
+/// The display can't show colors, so ANSI escape sequences in the Forth
+/// task's output (such as when `color on` is used) are skipped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Escape {
+    None,
+    /// An `ESC` has been received.
+    Started,
+    /// Inside a control sequence (`ESC [`), which ends with a byte in
+    /// `0x40..=0x7E`.
+    Csi,
+}
+
+impl Console {
+    /// Recomputes the text grid for a font with the given metrics.
+    fn layout(&mut self, metrics: FontMetrics, width_px: u32, height_px: u32) {
+        let grid = metrics.grid(width_px, height_px);
+        // Leave out 4 columns for the implicit margin of two characters on
+        // each gutter.
+        self.cols = (grid.cols as usize).saturating_sub(4).clamp(1, MAX_COLS);
+        let rows = (grid.rows as usize).min(MAX_ROWS);
+        tracing::info!(font = ?self.font, cols = self.cols, rows, "Laid out shell");
+    }
+
+    /// Handles a font size shortcut, returning `true` if `event` was one.
+    fn font_shortcut(&mut self, event: &KeyEvent) -> bool {
+        if !event.modifiers.get(Modifiers::CTRL) {
+            return false;
+        }
+        self.font = match event.code.into_char() {
+            Some('+' | '=') => self.font.larger(),
+            Some('-') => self.font.smaller(),
+            Some('0') => self.default_font,
+            _ => return false,
+        };
+        true
+    }
+}
+
+impl Panes {
+    fn new(tid_io: BidiHandle, spawned: KConsumer<SpawnedStdio>, hotkey: char) -> Self {
+        let mut panes = heapless::Vec::new();
+        let _ = panes.push(Pane::new(None, tid_io));
+        Self {
+            panes,
+            focus: 0,
+            spawned: Some(spawned),
+            hotkey,
+            changed: false,
+        }
+    }
+
+    /// Returns the shell's own pane.
+    fn shell(&self) -> &Pane {
+        &self.panes[0]
+    }
+
+    fn focused(&self) -> &Pane {
+        &self.panes[self.focus]
+    }
+
+    fn focused_mut(&mut self) -> &mut Pane {
+        &mut self.panes[self.focus]
+    }
+
+    /// Shows the next pane, returning `true` if `event` was the pane hotkey.
+    fn focus_shortcut(&mut self, event: &KeyEvent) -> bool {
+        if !event.is_hotkey(self.hotkey) {
+            return false;
+        }
+        self.focus = (self.focus + 1) % self.panes.len();
+        self.changed = true;
+        true
+    }
+
+    /// Adds a pane for a spawned task, replacing an existing pane if there's
+    /// no room.
+    fn add(&mut self, spawned: SpawnedStdio) {
+        let pane = Pane::new(Some(spawned.id), spawned.stdio);
+        if self.panes.len() < MAX_PANES {
+            let _ = self.panes.push(pane);
+        } else {
+            // replace a task which has exited, or the least recently spawned
+            // task which isn't being shown. the shell's own pane is never
+            // replaced.
+            let exited = self.panes.iter().skip(1).position(|pane| pane.exited);
+            let idx = match exited {
+                Some(idx) => idx + 1,
+                None if self.focus == 1 => 2,
+                None => 1,
+            };
+            tracing::debug!(
+                replaced.id = self.panes[idx].id,
+                spawned.id = spawned.id,
+                "Replacing pane"
+            );
+            // keep the panes in the order their tasks were spawned.
+            self.panes.remove(idx);
+            if self.focus > idx {
+                self.focus -= 1;
+            }
+            let _ = self.panes.push(pane);
+        }
+        self.changed = true;
+    }
+
+    /// Returns the title of the pane being shown.
+    fn title(&self) -> heapless::String<32> {
+        use core::fmt::Write;
+
+        let mut title = heapless::String::new();
+        let pane = self.focused();
+        let _ = match pane.id {
+            None => write!(title, "forth shell"),
+            Some(id) => write!(title, "task {id}"),
+        };
+        if pane.exited {
+            let _ = write!(title, " exited");
+        }
+        if self.panes.len() > 1 {
+            let _ = write!(title, " {}/{}", self.focus + 1, self.panes.len());
+        }
+        title
+    }
+
+    /// Waits for output from any pane's VM, returning the index of the pane,
+    /// and `None` if the VM has exited.
+    async fn next_output(&self) -> (usize, Option<GrantR>) {
+        // this can't select over a variable number of futures without
+        // allocating, so there's one branch for each pane.
+        const _: () = assert!(MAX_PANES == 4, "Panes::next_output has a branch per pane");
+        futures::select_biased! {
+            output = self.output(0).fuse() => (0, output),
+            output = self.output(1).fuse() => (1, output),
+            output = self.output(2).fuse() => (2, output),
+            output = self.output(3).fuse() => (3, output),
+        }
+    }
+
+    /// Waits for output from the VM of the pane at `idx`, if there is one, and
+    /// it hasn't exited.
+    async fn output(&self, idx: usize) -> Option<GrantR> {
+        match self.panes.get(idx) {
+            Some(pane) if !pane.exited => pane.output().await,
+            _ => future::pending().await,
+        }
+    }
+
+    /// Waits for a task to be spawned.
+    async fn next_spawned(&self) -> Option<SpawnedStdio> {
+        match self.spawned {
+            Some(ref spawned) => spawned.dequeue_async().await.ok(),
+            None => future::pending().await,
+        }
+    }
+}
+
+impl Pane {
+    fn new(id: Option<usize>, stdio: BidiHandle) -> Self {
+        Self {
+            id,
+            stdio,
+            lines: ShellLines::new(),
+            remote_col: 0,
+            escape: Escape::None,
+            exited: false,
+        }
+    }
+
+    /// Waits for output from this pane's VM, returning `None` once it has
+    /// exited and all of its output has been read.
+    async fn output(&self) -> Option<GrantR> {
+        futures::select_biased! {
+            output = self.stdio.consumer().read_grant().fuse() => Some(output),
+            _ = self.stdio.closed().fuse() => self.stdio.consumer().read_grant_sync(),
+        }
+    }
+
+    /// Returns `true` if `b` is part of an ANSI escape sequence, and should
+    /// not be displayed.
+    fn skip_escape(&mut self, b: u8) -> bool {
+        self.escape = match (self.escape, b) {
+            (Escape::None, 0x1B) => Escape::Started,
+            (Escape::None, _) => return false,
+            (Escape::Started, b'[') => Escape::Csi,
+            (Escape::Csi, 0x40..=0x7E) | (Escape::Started, _) => Escape::None,
+            (Escape::Csi, _) => Escape::Csi,
+        };
+        true
     }
 }
 
@@ -262,42 +818,96 @@ enum PollStyle {
     Forever,
 }
 
+/// Something which [io_poll] woke up for.
+enum Wake {
+    Key(Result<KeyEvent, KeyClientError>),
+    Output(usize, Option<GrantR>),
+    Spawned(Option<SpawnedStdio>),
+    Toast(Productive),
+}
+
 /// Poll the IO interfaces until something interesting happens.
 ///
 /// If called with `OneShot` style: return as soon as SOMETHING
 /// productive occurs.
 ///
 /// If called with `Forever` style: Never return, requires the
-/// use of an outer timeout.
+/// use of an outer timeout. Returns early if the font is changed, so that the
+/// shell can be redrawn in the new font, or if the Forth VM has exited.
 async fn io_poll(
+    k: &'static Kernel,
     style: PollStyle,
     keyboard: &mut KeyClient,
-    rline: &mut RingLine<16, 46>,
-    tid_io: &BidiHandle,
+    toasts: &mut Toasts,
+    panes: &mut Panes,
+    console: &mut Console,
 ) {
+    let font = console.font;
     loop {
-        let was_productive = futures::select_biased! {
-            event = keyboard.next().fuse() => kbd_event(event, rline, tid_io).await,
-            output = tid_io.consumer().read_grant().fuse() => {
-                stdout_event(output, rline).await
+        let wake = futures::select_biased! {
+            event = keyboard.next().fuse() => Wake::Key(event),
+            (idx, output) = panes.next_output().fuse() => Wake::Output(idx, output),
+            spawned = panes.next_spawned().fuse() => Wake::Spawned(spawned),
+            productive = toasts.next(k).fuse() => Wake::Toast(productive),
+        };
+        let was_productive = match wake {
+            Wake::Key(Ok(event)) if panes.focus_shortcut(&event) => Productive::Yes,
+            Wake::Key(event) => kbd_event(event, panes.focused_mut(), console).await,
+            Wake::Output(idx, Some(output)) => {
+                stdout_event(output, &mut panes.panes[idx], console).await
             }
+            Wake::Output(idx, None) => {
+                tracing::info!(id = panes.panes[idx].id, "Forth VM exited");
+                panes.panes[idx].exited = true;
+                panes.changed = true;
+                if idx == 0 {
+                    return;
+                }
+                Productive::Yes
+            }
+            Wake::Spawned(Some(spawned)) => {
+                panes.add(spawned);
+                Productive::Yes
+            }
+            Wake::Spawned(None) => {
+                tracing::warn!("Spawned task channel closed");
+                panes.spawned = None;
+                Productive::No
+            }
+            Wake::Toast(productive) => productive,
         };
 
+        if console.font != font {
+            return;
+        }
+
         if let (Productive::Yes, PollStyle::OneShot) = (was_productive, style) {
             return;
         }
     }
 }
 
-async fn stdout_event(output: GrantR, rline: &mut RingLine<16, 46>) -> Productive {
+async fn stdout_event(output: GrantR, pane: &mut Pane, console: &Console) -> Productive {
     let len = output.len();
-    tracing::trace!(len, "Received output from tid_io");
+    tracing::trace!(len, id = pane.id, "Received output from Forth VM");
     for &b in output.iter() {
+        if pane.skip_escape(b) {
+            continue;
+        }
+
+        // wrap lines that are too long for the current font.
+        if b != b'\n' && pane.remote_col >= console.cols {
+            pane.lines.submit_remote_editing();
+            pane.remote_col = 0;
+        }
+
         // TODO(eliza): what if this errors lol
         if b == b'\n' {
-            rline.submit_remote_editing();
+            pane.lines.submit_remote_editing();
+            pane.remote_col = 0;
         } else {
-            let _ = rline.append_remote_char(b);
+            let _ = pane.lines.append_remote_char(b);
+            pane.remote_col += 1;
         }
     }
     output.release(len);
@@ -306,8 +916,8 @@ async fn stdout_event(output: GrantR, rline: &mut RingLine<16, 46>) -> Productiv
 
 async fn kbd_event(
     event: Result<KeyEvent, KeyClientError>,
-    rline: &mut RingLine<16, 46>,
-    tid_io: &BidiHandle,
+    pane: &mut Pane,
+    console: &mut Console,
 ) -> Productive {
     let Ok(event) = event else {
         tracing::error!("Keyboard service is dead???");
@@ -318,6 +928,16 @@ async fn kbd_event(
         return Productive::No;
     }
 
+    if console.font_shortcut(&event) {
+        return Productive::Yes;
+    }
+
+    // there's nobody to send input to.
+    if pane.exited {
+        return Productive::No;
+    }
+
+    let rline = &mut pane.lines;
     if matches!(
         event.code,
         key_event::KeyCode::Backspace | key_event::KeyCode::Delete
@@ -334,6 +954,13 @@ async fn kbd_event(
         }
 
         let b = ch as u8;
+        // don't let the line being edited grow past the edge of the display
+        // in the current font.
+        if b != b'\n' && b != 0x7F && rline.local_editing_len() >= console.cols {
+            tracing::debug!(cols = console.cols, "Line full, skipping {ch:?}");
+            return Productive::No;
+        }
+
         match rline.append_local_char(b) {
             Ok(_) => Productive::Yes,
             // backspace
@@ -344,7 +971,7 @@ async fn kbd_event(
             Err(_) if b == b'\n' => {
                 let needed = rline.local_editing_len();
                 if needed != 0 {
-                    let mut tid_io_wgr = tid_io.producer().send_grant_exact(needed).await;
+                    let mut tid_io_wgr = pane.stdio.producer().send_grant_exact(needed).await;
                     rline.copy_local_editing_to(&mut tid_io_wgr).unwrap();
                     tid_io_wgr.commit(needed);
                     rline.submit_local_editing();
@@ -0,0 +1,654 @@
+//! On-screen software keyboard.
+//!
+//! This daemon draws a keyboard along the bottom of the display, using the
+//! [`EmbDisplayService`](crate::services::emb_display::EmbDisplayService), and
+//! publishes a [`KeyEvent`] to the [`KeyboardMuxService`] whenever one of its
+//! keys is tapped with a pointing device. This makes the graphical shell usable
+//! on targets without a physical keyboard, such as touchscreens, and lets it
+//! be driven by a mouse when testing UIs in the simulator.
+//!
+//! The keyboard starts out hidden. It is shown by tapping along the bottom
+//! edge of the display, or by pressing Ctrl + Alt and the configured
+//! [hotkey](SoftKeyboardSettings::hotkey) on a physical keyboard, and hidden
+//! again using its "hide" key or the hotkey.
+//!
+//! Like the keyboards on phones, Shift is "sticky": tapping it shifts the next
+//! key that is tapped.
+//!
+//! The display service has no notion of layers, so other clients may draw
+//! over the keyboard. While it is visible, the keyboard is redrawn every
+//! [`redraw_interval`](SoftKeyboardSettings::redraw_interval), so that it
+//! stays on top.
+//!
+//! [`KeyboardMuxService`]: crate::services::keyboard::mux::KeyboardMuxService
+
+use core::time::Duration;
+
+use embedded_graphics::{
+    mono_font::MonoTextStyle,
+    pixelcolor::BinaryColor,
+    prelude::*,
+    primitives::{ContainsPoint, PrimitiveStyle, Rectangle},
+    text::{Alignment, Baseline, Text, TextStyleBuilder},
+};
+use futures::{future, FutureExt};
+use serde::{Deserialize, Serialize};
+use tracing::Level;
+
+use crate::{
+    services::{
+        emb_display::{fonts::FontSize, EmbDisplayClient, FrameLocSize, MonoChunk},
+        keyboard::{
+            key_event::{self, KeyCode, Modifiers},
+            mux::KeyboardMuxClient,
+            KeyClient, KeyClientError, KeyEvent,
+        },
+        pointer::{Buttons, PointerClient, PointerClientError, PointerEvent},
+    },
+    Kernel,
+};
+
+/// Settings for the [software keyboard](self) daemon.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SoftKeyboardSettings {
+    #[serde(default)]
+    pub enabled: bool,
+    /// The font used for the labels on the keys, which also determines the
+    /// size of the keyboard.
+    #[serde(default = "SoftKeyboardSettings::default_font")]
+    pub font: FontSize,
+    /// The key which, pressed together with Ctrl and Alt, shows or hides the
+    /// keyboard. If this is [`None`], the keyboard can only be shown with the
+    /// pointer.
+    #[serde(default = "SoftKeyboardSettings::default_hotkey")]
+    pub hotkey: Option<char>,
+    /// How often the keyboard is redrawn while it is visible.
+    #[serde(default = "SoftKeyboardSettings::default_redraw_interval")]
+    pub redraw_interval: Duration,
+}
+
+/// A key on the software keyboard.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Key {
+    /// A key that types a character. The second character is typed when
+    /// Shift is active.
+    Char(char, char),
+    Backspace,
+    Enter,
+    Shift,
+    Space,
+    Hide,
+}
+
+/// The position and size of the keyboard's keys on the display.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct Layout {
+    /// The area of the display covered by the keyboard.
+    panel: Rectangle,
+    /// The size of a one unit wide key.
+    key: Size,
+}
+
+/// The software keyboard's state.
+#[derive(Debug)]
+struct Keyboard {
+    layout: Layout,
+    /// The size of the whole display.
+    display: Size,
+    visible: bool,
+    shift: bool,
+    /// Where the pointer is, in display coordinates. Pointer events are
+    /// relative, so the keyboard keeps track of this itself.
+    cursor: Point,
+    /// The buttons that were held down in the previous pointer event.
+    buttons: Buttons,
+}
+
+/// Run the software keyboard.
+///
+/// This waits for the display, pointer, and keyboard mux services to be
+/// registered, and returns if any of them are unavailable.
+#[tracing::instrument(name = "soft_keyboard", level = Level::INFO, skip(kernel, settings))]
+pub async fn run(kernel: &'static Kernel, settings: SoftKeyboardSettings) {
+    let mut display = match EmbDisplayClient::from_registry(kernel).await {
+        Ok(display) => display,
+        Err(error) => {
+            tracing::warn!(?error, "no display, not starting the software keyboard");
+            return;
+        }
+    };
+    let mut pointer = match PointerClient::from_registry(kernel, Default::default()).await {
+        Ok(pointer) => pointer,
+        Err(error) => {
+            tracing::warn!(
+                ?error,
+                "no pointer service, not starting the software keyboard"
+            );
+            return;
+        }
+    };
+    let mut mux = match KeyboardMuxClient::from_registry(kernel).await {
+        Ok(mux) => mux,
+        Err(error) => {
+            tracing::warn!(
+                ?error,
+                "no keyboard mux, not starting the software keyboard"
+            );
+            return;
+        }
+    };
+    // The hotkey is optional, so carry on without it if there's no keyboard
+    // service to watch for it.
+    let mut hotkeys = match settings.hotkey {
+        Some(_) => match KeyClient::from_registry(kernel, Default::default()).await {
+            Ok(keys) => Some(keys),
+            Err(error) => {
+                tracing::warn!(?error, "no keyboard service, hotkey disabled");
+                None
+            }
+        },
+        None => None,
+    };
+    let meta = match display.get_meta().await {
+        Ok(meta) => meta,
+        Err(error) => {
+            tracing::warn!(?error, "failed to get display metadata");
+            return;
+        }
+    };
+
+    let display_size = Size::new(meta.width, meta.height);
+    let mut keyboard = Keyboard::new(Layout::new(display_size, settings.font), display_size);
+    let style = MonoTextStyle::new(settings.font.font(), BinaryColor::On);
+    let mut chunk = MonoChunk::allocate_mono(keyboard.layout.frame()).await;
+    tracing::info!(panel = ?keyboard.layout.panel, "software keyboard running");
+
+    loop {
+        let redraw = if keyboard.visible {
+            future::Either::Left(kernel.sleep(settings.redraw_interval))
+        } else {
+            future::Either::Right(future::pending())
+        };
+        let hotkey = match hotkeys.as_mut() {
+            Some(keys) => future::Either::Left(keys.next()),
+            None => future::Either::Right(future::pending()),
+        };
+
+        let wake = futures::select_biased! {
+            event = pointer.next().fuse() => Wake::Pointer(event),
+            event = hotkey.fuse() => Wake::Key(event),
+            _ = redraw.fuse() => Wake::Redraw,
+        };
+
+        let was_visible = keyboard.visible;
+        let dirty = match wake {
+            Wake::Pointer(Ok(event)) => {
+                let hovered = keyboard.hovered();
+                let key = keyboard.pointer(event);
+                if let Some(event) = key.and_then(|key| keyboard.press(key)) {
+                    tracing::debug!(?event, "software keyboard key tapped");
+                    let released = KeyEvent {
+                        kind: key_event::Kind::Released,
+                        ..event
+                    };
+                    if mux.publish_key(event).await.is_err()
+                        || mux.publish_key(released).await.is_err()
+                    {
+                        tracing::warn!("keyboard mux is gone, stopping the software keyboard");
+                        return;
+                    }
+                }
+                key.is_some() || keyboard.hovered() != hovered
+            }
+            Wake::Pointer(Err(_)) => {
+                tracing::warn!("pointer service is gone, stopping the software keyboard");
+                return;
+            }
+            Wake::Key(Ok(event)) => {
+                if is_hotkey(&event, settings.hotkey) {
+                    keyboard.visible = !keyboard.visible;
+                    keyboard.shift = false;
+                }
+                false
+            }
+            Wake::Key(Err(_)) => {
+                tracing::warn!("keyboard service is gone, hotkey disabled");
+                hotkeys = None;
+                false
+            }
+            Wake::Redraw => true,
+        };
+
+        if keyboard.visible && (dirty || !was_visible) {
+            keyboard.draw(&mut chunk, style);
+        } else if was_visible && !keyboard.visible {
+            // Leave a blank space behind, which the other clients will draw
+            // over the next time they redraw.
+            let _ = DrawTarget::clear(&mut chunk, BinaryColor::Off);
+        } else {
+            continue;
+        }
+        chunk = match display.draw_mono(chunk).await {
+            Ok(chunk) => chunk,
+            Err(error) => {
+                tracing::warn!(?error, "display is gone, stopping the software keyboard");
+                return;
+            }
+        };
+    }
+}
+
+fn is_hotkey(event: &KeyEvent, hotkey: Option<char>) -> bool {
+    hotkey.is_some_and(|hotkey| event.is_hotkey(hotkey))
+}
+
+// === impl SoftKeyboardSettings ===
+
+impl SoftKeyboardSettings {
+    pub const DEFAULT_FONT: FontSize = FontSize::Small;
+    pub const DEFAULT_HOTKEY: Option<char> = Some('k');
+    pub const DEFAULT_REDRAW_INTERVAL: Duration = Duration::from_millis(250);
+
+    const fn default_font() -> FontSize {
+        Self::DEFAULT_FONT
+    }
+    const fn default_hotkey() -> Option<char> {
+        Self::DEFAULT_HOTKEY
+    }
+    const fn default_redraw_interval() -> Duration {
+        Self::DEFAULT_REDRAW_INTERVAL
+    }
+}
+
+impl Default for SoftKeyboardSettings {
+    fn default() -> Self {
+        Self {
+            // Only targets without a physical keyboard need this, so leave it
+            // to the platform to enable it.
+            enabled: false,
+            font: Self::DEFAULT_FONT,
+            hotkey: Self::DEFAULT_HOTKEY,
+            redraw_interval: Self::DEFAULT_REDRAW_INTERVAL,
+        }
+    }
+}
+
+// === impl Key ===
+
+impl Key {
+    /// The keys in each row of the keyboard, with their widths in units.
+    const ROWS: [&'static [(Key, u32)]; 5] = [
+        &[
+            (Key::Char('1', '!'), 1),
+            (Key::Char('2', '@'), 1),
+            (Key::Char('3', '#'), 1),
+            (Key::Char('4', '$'), 1),
+            (Key::Char('5', '%'), 1),
+            (Key::Char('6', '^'), 1),
+            (Key::Char('7', '&'), 1),
+            (Key::Char('8', '*'), 1),
+            (Key::Char('9', '('), 1),
+            (Key::Char('0', ')'), 1),
+            (Key::Char('-', '_'), 1),
+            (Key::Char('=', '+'), 1),
+            (Key::Backspace, 2),
+        ],
+        &[
+            (Key::Char('q', 'Q'), 1),
+            (Key::Char('w', 'W'), 1),
+            (Key::Char('e', 'E'), 1),
+            (Key::Char('r', 'R'), 1),
+            (Key::Char('t', 'T'), 1),
+            (Key::Char('y', 'Y'), 1),
+            (Key::Char('u', 'U'), 1),
+            (Key::Char('i', 'I'), 1),
+            (Key::Char('o', 'O'), 1),
+            (Key::Char('p', 'P'), 1),
+            (Key::Char('[', '{'), 1),
+            (Key::Char(']', '}'), 1),
+            (Key::Char('\\', '|'), 2),
+        ],
+        &[
+            (Key::Char('a', 'A'), 1),
+            (Key::Char('s', 'S'), 1),
+            (Key::Char('d', 'D'), 1),
+            (Key::Char('f', 'F'), 1),
+            (Key::Char('g', 'G'), 1),
+            (Key::Char('h', 'H'), 1),
+            (Key::Char('j', 'J'), 1),
+            (Key::Char('k', 'K'), 1),
+            (Key::Char('l', 'L'), 1),
+            (Key::Char(';', ':'), 1),
+            (Key::Char('\'', '"'), 1),
+            (Key::Enter, 3),
+        ],
+        &[
+            (Key::Shift, 2),
+            (Key::Char('z', 'Z'), 1),
+            (Key::Char('x', 'X'), 1),
+            (Key::Char('c', 'C'), 1),
+            (Key::Char('v', 'V'), 1),
+            (Key::Char('b', 'B'), 1),
+            (Key::Char('n', 'N'), 1),
+            (Key::Char('m', 'M'), 1),
+            (Key::Char(',', '<'), 1),
+            (Key::Char('.', '>'), 1),
+            (Key::Char('/', '?'), 1),
+            (Key::Char('`', '~'), 2),
+        ],
+        &[(Key::Hide, 3), (Key::Space, 11)],
+    ];
+    /// The width of each row, in units.
+    const ROW_UNITS: u32 = 14;
+
+    fn label(self, shift: bool) -> Label {
+        match self {
+            Key::Char(c, _) if !shift => Label::Char(c),
+            Key::Char(_, c) => Label::Char(c),
+            Key::Backspace => Label::Str("bksp"),
+            Key::Enter => Label::Str("enter"),
+            Key::Shift => Label::Str("shift"),
+            Key::Space => Label::Str("space"),
+            Key::Hide => Label::Str("hide"),
+        }
+    }
+}
+
+/// What happened while the keyboard was waiting.
+enum Wake {
+    Pointer(Result<PointerEvent, PointerClientError>),
+    Key(Result<KeyEvent, KeyClientError>),
+    Redraw,
+}
+
+enum Label {
+    Char(char),
+    Str(&'static str),
+}
+
+// === impl Layout ===
+
+impl Layout {
+    /// Padding between the edges of a key and its label, in pixels.
+    const PADDING: u32 = 2;
+
+    /// Lays out the keyboard along the bottom of a display of `display`
+    /// pixels, with labels in `font`.
+    fn new(display: Size, font: FontSize) -> Self {
+        let metrics = font.metrics();
+        let key = Size::new(
+            display.width / Key::ROW_UNITS,
+            metrics.char_height + Self::PADDING * 2,
+        );
+        let height = (key.height * Key::ROWS.len() as u32).min(display.height);
+        Self {
+            panel: Rectangle::new(
+                Point::new(0, (display.height - height) as i32),
+                Size::new(display.width, height),
+            ),
+            key,
+        }
+    }
+
+    /// Returns every key on the keyboard, with the area of the display it
+    /// covers.
+    fn keys(&self) -> impl Iterator<Item = (Key, Rectangle)> + '_ {
+        Key::ROWS.iter().enumerate().flat_map(move |(row, keys)| {
+            let y = self.panel.top_left.y + (row as u32 * self.key.height) as i32;
+            keys.iter().scan(0, move |x, &(key, units)| {
+                let width = self.key.width * units;
+                let area = Rectangle::new(Point::new(*x, y), Size::new(width, self.key.height));
+                *x += width as i32;
+                Some((key, area))
+            })
+        })
+    }
+
+    /// Returns the key at `point`, if there is one.
+    fn key_at(&self, point: Point) -> Option<(Key, Rectangle)> {
+        if !self.panel.contains(point) {
+            return None;
+        }
+        self.keys().find(|(_, area)| area.contains(point))
+    }
+
+    /// Returns the location and size of a chunk covering the keyboard.
+    fn frame(&self) -> FrameLocSize {
+        FrameLocSize {
+            offset_x: self.panel.top_left.x as u32,
+            offset_y: self.panel.top_left.y as u32,
+            width: self.panel.size.width,
+            height: self.panel.size.height,
+        }
+    }
+}
+
+// === impl Keyboard ===
+
+impl Keyboard {
+    fn new(layout: Layout, display: Size) -> Self {
+        Self {
+            layout,
+            display,
+            visible: false,
+            shift: false,
+            cursor: Point::zero(),
+            buttons: Buttons::NONE,
+        }
+    }
+
+    /// Returns the key under the pointer, if the keyboard is visible.
+    fn hovered(&self) -> Option<Key> {
+        if !self.visible {
+            return None;
+        }
+        self.layout.key_at(self.cursor).map(|(key, _)| key)
+    }
+
+    /// Handles a pointer event, returning the key that was tapped, if any.
+    ///
+    /// A tap is the left button being pressed. While the keyboard is hidden,
+    /// a tap along the bottom edge of the display shows it, rather than
+    /// tapping a key.
+    fn pointer(&mut self, event: PointerEvent) -> Option<Key> {
+        let max = Point::new(
+            self.display.width.saturating_sub(1) as i32,
+            self.display.height.saturating_sub(1) as i32,
+        );
+        self.cursor = (self.cursor + Point::new(event.dx.into(), event.dy.into()))
+            .component_max(Point::zero())
+            .component_min(max);
+        let tapped = event.buttons.contains(Buttons::LEFT) && !self.buttons.contains(Buttons::LEFT);
+        self.buttons = event.buttons;
+        if !tapped {
+            return None;
+        }
+
+        if !self.visible {
+            // The bottom row of keys is where the keyboard would be tapped
+            // first anyway, so tapping there shows it.
+            let edge = max.y - self.layout.key.height as i32;
+            if self.cursor.y > edge {
+                self.visible = true;
+            }
+            return None;
+        }
+        self.hovered()
+    }
+
+    /// Presses `key`, returning the key event it types, if any.
+    fn press(&mut self, key: Key) -> Option<KeyEvent> {
+        let shift = core::mem::take(&mut self.shift);
+        let code = match key {
+            Key::Char(c, _) if !shift => KeyCode::Char(c),
+            Key::Char(_, c) => KeyCode::Char(c),
+            Key::Space => KeyCode::Char(' '),
+            Key::Backspace => KeyCode::Backspace,
+            Key::Enter => KeyCode::Enter,
+            Key::Shift => {
+                self.shift = !shift;
+                return None;
+            }
+            Key::Hide => {
+                self.visible = false;
+                return None;
+            }
+        };
+        let mut modifiers = Modifiers::new();
+        modifiers.set(Modifiers::SHIFT, shift);
+        Some(KeyEvent {
+            kind: key_event::Kind::Pressed,
+            modifiers,
+            code,
+        })
+    }
+
+    /// Draws the keyboard into `chunk`, which covers the keyboard's panel.
+    fn draw(&self, chunk: &mut MonoChunk, style: MonoTextStyle<'static, BinaryColor>) {
+        DrawTarget::clear(chunk, BinaryColor::Off).unwrap();
+        let origin = self.layout.panel.top_left;
+        let hovered = self.layout.key_at(self.cursor);
+        let centered = TextStyleBuilder::new()
+            .alignment(Alignment::Center)
+            .baseline(Baseline::Middle)
+            .build();
+        let mut buf = [0; 4];
+        for (key, area) in self.layout.keys() {
+            let area = area.translate(-origin);
+            // Highlight the key under the pointer, and Shift while it's
+            // active, by inverting them.
+            let inverted =
+                hovered.map(|(k, _)| k) == Some(key) || (key == Key::Shift && self.shift);
+            let (fill, text) = if inverted {
+                let mut text = style;
+                text.text_color = Some(BinaryColor::Off);
+                (BinaryColor::On, text)
+            } else {
+                (BinaryColor::Off, style)
+            };
+            area.into_styled(PrimitiveStyle::with_fill(fill))
+                .draw(chunk)
+                .unwrap();
+            area.into_styled(PrimitiveStyle::with_stroke(BinaryColor::On, 1))
+                .draw(chunk)
+                .unwrap();
+            let label = match key.label(self.shift) {
+                Label::Char(c) => &*c.encode_utf8(&mut buf),
+                Label::Str(s) => s,
+            };
+            Text::with_text_style(label, area.center(), text, centered)
+                .draw(chunk)
+                .unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keyboard() -> Keyboard {
+        let display = Size::new(400, 240);
+        Keyboard::new(Layout::new(display, FontSize::Small), display)
+    }
+
+    fn tap(keyboard: &mut Keyboard, to: Point) -> Option<Key> {
+        let delta = to - keyboard.cursor;
+        keyboard.pointer(PointerEvent {
+            dx: delta.x as i16,
+            dy: delta.y as i16,
+            ..Default::default()
+        });
+        keyboard.pointer(PointerEvent {
+            buttons: Buttons::LEFT,
+            ..Default::default()
+        })
+    }
+
+    /// Taps the key at `to`, returning the key event it types.
+    fn type_at(keyboard: &mut Keyboard, to: Point) -> Option<KeyEvent> {
+        let key = tap(keyboard, to)?;
+        keyboard.press(key)
+    }
+
+    #[test]
+    fn layout_fits_display() {
+        let keyboard = keyboard();
+        let layout = keyboard.layout;
+        assert_eq!(
+            layout.panel.bottom_right(),
+            Some(Point::new(399, 239)),
+            "panel should be at the bottom of the display"
+        );
+        for (key, area) in layout.keys() {
+            assert!(
+                layout.panel.contains(area.top_left)
+                    && layout.panel.contains(area.bottom_right().unwrap()),
+                "{key:?} at {area:?} is outside the panel"
+            );
+        }
+    }
+
+    #[test]
+    fn tap_to_show_and_type() {
+        let mut keyboard = keyboard();
+        let layout = keyboard.layout;
+        let (_, q) = layout
+            .keys()
+            .find(|(key, _)| *key == Key::Char('q', 'Q'))
+            .unwrap();
+        let (_, shift) = layout.keys().find(|(key, _)| *key == Key::Shift).unwrap();
+
+        // tapping a key while the keyboard is hidden doesn't type anything.
+        assert_eq!(tap(&mut keyboard, q.center()), None);
+        assert!(!keyboard.visible);
+
+        // tapping the bottom edge shows it.
+        assert_eq!(tap(&mut keyboard, Point::new(200, 239)), None);
+        assert!(keyboard.visible);
+
+        let key = tap(&mut keyboard, q.center()).unwrap();
+        assert_eq!(key, Key::Char('q', 'Q'));
+        assert_eq!(keyboard.press(key).unwrap().code, KeyCode::Char('q'));
+
+        // shift only applies to the next key.
+        assert_eq!(type_at(&mut keyboard, shift.center()), None);
+        assert!(keyboard.shift);
+        let event = type_at(&mut keyboard, q.center()).unwrap();
+        assert_eq!(event.code, KeyCode::Char('Q'));
+        assert!(event.modifiers.get(Modifiers::SHIFT));
+        let event = type_at(&mut keyboard, q.center()).unwrap();
+        assert_eq!(event.code, KeyCode::Char('q'));
+    }
+
+    #[test]
+    fn holding_isnt_tapping() {
+        let mut keyboard = keyboard();
+        keyboard.visible = true;
+        let (_, q) = keyboard
+            .layout
+            .keys()
+            .find(|(key, _)| *key == Key::Char('q', 'Q'))
+            .unwrap();
+        assert!(tap(&mut keyboard, q.center()).is_some());
+        // dragging with the button held down doesn't tap the key it's
+        // dragged to.
+        let dragged = keyboard.pointer(PointerEvent {
+            dx: q.size.width as i16,
+            buttons: Buttons::LEFT,
+            ..Default::default()
+        });
+        assert_eq!(dragged, None);
+    }
+
+    #[test]
+    fn hotkey() {
+        let mut event = KeyEvent::from_char('K');
+        assert!(!is_hotkey(&event, Some('k')));
+        event.modifiers.set(Modifiers::CTRL, true);
+        event.modifiers.set(Modifiers::ALT, true);
+        assert!(is_hotkey(&event, Some('k')));
+        assert!(!is_hotkey(&event, Some('j')));
+        assert!(!is_hotkey(&event, None));
+    }
+}
@@ -0,0 +1,298 @@
+//! # Deferred Work
+//!
+//! Interrupt handlers and other code running outside of the async executor
+//! sometimes need to run a small amount of code "soon", in normal (non-ISR)
+//! context, without spawning a whole task to do it. The kernel's
+//! [`DeferredWork`] queue is a fixed-capacity MPSC queue of such work, which
+//! is drained each time the kernel is [ticked](crate::Kernel::tick), before
+//! the scheduler runs.
+//!
+//! There are two kinds of deferred work:
+//!
+//! - A [`Deferred`] is a function pointer stored in a `static`, which can be
+//!   scheduled from an interrupt handler using [`DeferredWork::schedule`].
+//!   Scheduling a [`Deferred`] never allocates, and scheduling one which is
+//!   already queued does nothing, so an interrupt which fires repeatedly
+//!   before the queue is drained can't fill the queue.
+//! - Closures can be queued from outside of interrupt context using
+//!   [`DeferredWork::defer`] or [`DeferredWork::try_defer`]. These are boxed,
+//!   so they may capture state, but they must not be queued from ISRs, as
+//!   they allocate.
+//!
+//! Deferred work runs with the scheduler stopped, so, like an interrupt
+//! handler, it should be brief. Work which needs to wait for anything should
+//! wake a task instead.
+
+use core::{fmt, mem};
+
+use portable_atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use crate::{comms::kchannel::KChannel, isr::Isr, mnemos_alloc::containers::Box};
+
+/// The maximum number of items of work which may be queued at once.
+pub const CAPACITY: usize = 32;
+
+/// The kernel's queue of deferred work.
+///
+/// This is returned by [`Kernel::deferred`](crate::Kernel::deferred).
+pub struct DeferredWork {
+    queue: KChannel<Work>,
+    dropped: AtomicUsize,
+}
+
+/// A function which may be scheduled to run as deferred work from an
+/// interrupt handler.
+///
+/// `Deferred`s are intended to be stored in `static`s:
+///
+/// ```rust
+/// use kernel::deferred::Deferred;
+///
+/// static NOTIFY: Deferred = Deferred::new(|| {
+///     // ...
+/// });
+/// ```
+pub struct Deferred {
+    f: fn(),
+    queued: AtomicBool,
+}
+
+/// Errors returned when deferred work could not be queued.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeferError {
+    /// The deferred work queue is full.
+    Full,
+    /// A closure could not be boxed, because allocation is not currently
+    /// possible.
+    NoMemory,
+}
+
+enum Work {
+    Static(&'static Deferred),
+    Boxed(alloc::boxed::Box<dyn FnOnce()>),
+}
+
+// === impl DeferredWork ===
+
+impl DeferredWork {
+    pub(crate) fn new() -> Self {
+        Self {
+            queue: KChannel::new(CAPACITY),
+            dropped: AtomicUsize::new(0),
+        }
+    }
+
+    /// Schedules `work` to run the next time the kernel is ticked.
+    ///
+    /// This never allocates or blocks, so it may be called from interrupt
+    /// handlers. If `work` is already queued, this does nothing.
+    pub fn schedule(&self, work: &'static Deferred) -> Result<(), DeferError> {
+        if work.queued.swap(true, Ordering::AcqRel) {
+            return Ok(());
+        }
+
+        self.queue.enqueue_sync(Work::Static(work)).map_err(|_| {
+            work.queued.store(false, Ordering::Release);
+            self.full()
+        })
+    }
+
+    /// Queues the closure `f` to run the next time the kernel is ticked,
+    /// waiting for allocation and for room in the queue if necessary.
+    ///
+    /// This must not be called from an interrupt handler.
+    pub async fn defer(&self, f: impl FnOnce() + 'static) {
+        let work = match boxed(f) {
+            Ok(work) => work,
+            Err(f) => Work::Boxed(Box::new(f).await.into_alloc_box()),
+        };
+        // The queue is never closed, so this can only fail if the queue is
+        // full, and `enqueue_async` waits for room instead.
+        let _ = self.queue.enqueue_async(work).await;
+    }
+
+    /// Queues the closure `f` to run the next time the kernel is ticked,
+    /// without waiting.
+    ///
+    /// This must not be called from an interrupt handler, as it allocates.
+    /// Use [`DeferredWork::schedule`] to schedule work from an ISR.
+    pub fn try_defer(&self, f: impl FnOnce() + 'static) -> Result<(), DeferError> {
+        debug_assert!(
+            !Isr::is_in_isr(),
+            "closures must not be deferred from an ISR, use `DeferredWork::schedule` instead",
+        );
+        let work = match boxed(f) {
+            Ok(work) => work,
+            Err(f) => Work::Boxed(
+                Box::try_new(f)
+                    .map_err(|_| DeferError::NoMemory)?
+                    .into_alloc_box(),
+            ),
+        };
+        self.queue.enqueue_sync(work).map_err(|_| self.full())
+    }
+
+    /// Returns the number of items of work which are currently queued.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Returns `true` if no work is currently queued.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    /// Returns the number of times work could not be queued because the queue
+    /// was full.
+    #[must_use]
+    pub fn dropped(&self) -> usize {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Runs the work which is currently queued, returning the number of items
+    /// of work which were run.
+    ///
+    /// Work queued while this runs, including by the work itself, is left for
+    /// the next call, so that work which re-queues itself can't prevent the
+    /// scheduler from running.
+    pub(crate) fn run(&self) -> usize {
+        let pending = self.queue.len();
+        let mut ran = 0;
+        while ran < pending {
+            let Some(work) = self.queue.dequeue_sync() else {
+                break;
+            };
+            match work {
+                Work::Static(work) => {
+                    // Clear the flag first, so that the work can be
+                    // scheduled again while it runs.
+                    work.queued.store(false, Ordering::Release);
+                    (work.f)();
+                }
+                Work::Boxed(f) => f(),
+            }
+            ran += 1;
+        }
+        ran
+    }
+
+    fn full(&self) -> DeferError {
+        self.dropped.fetch_add(1, Ordering::Relaxed);
+        DeferError::Full
+    }
+}
+
+impl fmt::Debug for DeferredWork {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DeferredWork")
+            .field("queue", &self.queue.depth())
+            .field("dropped", &self.dropped())
+            .finish()
+    }
+}
+
+/// Boxes zero-sized closures (those which capture nothing) without
+/// allocating, or returns the closure if it must be allocated.
+fn boxed<F: FnOnce() + 'static>(f: F) -> Result<Work, F> {
+    if mem::size_of::<F>() == 0 {
+        Ok(Work::Boxed(alloc::boxed::Box::new(f)))
+    } else {
+        Err(f)
+    }
+}
+
+// === impl Deferred ===
+
+impl Deferred {
+    /// Returns a new `Deferred` which runs `f`.
+    #[must_use]
+    pub const fn new(f: fn()) -> Self {
+        Self {
+            f,
+            queued: AtomicBool::new(false),
+        }
+    }
+
+    /// Returns `true` if this work is currently queued.
+    #[must_use]
+    pub fn is_queued(&self) -> bool {
+        self.queued.load(Ordering::Acquire)
+    }
+}
+
+impl fmt::Debug for Deferred {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Deferred")
+            .field("f", &self.f)
+            .field("queued", &self.is_queued())
+            .finish()
+    }
+}
+
+// === impl DeferError ===
+
+impl fmt::Display for DeferError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Full => f.write_str("the deferred work queue is full"),
+            Self::NoMemory => f.write_str("could not allocate deferred work"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TestKernel;
+
+    static RUNS: AtomicUsize = AtomicUsize::new(0);
+    static COUNT: Deferred = Deferred::new(|| {
+        RUNS.fetch_add(1, Ordering::Relaxed);
+    });
+
+    #[test]
+    fn runs_deferred_work() {
+        TestKernel::run(|k| async move {
+            let deferred = k.deferred();
+
+            // Scheduling work which is already queued doesn't queue it twice.
+            deferred.schedule(&COUNT).unwrap();
+            deferred.schedule(&COUNT).unwrap();
+            assert!(COUNT.is_queued());
+            assert_eq!(deferred.len(), 1);
+
+            let captured = std::rc::Rc::new(core::cell::Cell::new(0));
+            deferred
+                .try_defer({
+                    let captured = captured.clone();
+                    move || captured.set(captured.get() + 1)
+                })
+                .unwrap();
+            deferred.defer(|| {}).await;
+            assert_eq!(deferred.len(), 3);
+
+            k.yield_now().await;
+            assert!(deferred.is_empty());
+            assert!(!COUNT.is_queued());
+            assert_eq!(RUNS.load(Ordering::Relaxed), 1);
+            assert_eq!(captured.get(), 1);
+        })
+    }
+
+    #[test]
+    fn full_queue() {
+        TestKernel::run(|k| async move {
+            let deferred = k.deferred();
+            for _ in 0..CAPACITY {
+                deferred.try_defer(|| {}).unwrap();
+            }
+            assert_eq!(deferred.try_defer(|| {}), Err(DeferError::Full));
+            assert_eq!(deferred.dropped(), 1);
+
+            k.yield_now().await;
+            assert!(deferred.is_empty());
+        })
+    }
+}
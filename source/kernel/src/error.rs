@@ -0,0 +1,103 @@
+//! The kernel's error type.
+
+use core::fmt;
+
+use uuid::Uuid;
+
+use crate::{boot, registry::RegistrationError};
+
+/// Errors returned by the [`Kernel`](crate::Kernel) and its initialization
+/// APIs.
+///
+/// Unlike the `&'static str` errors these APIs used to return, platform
+/// implementations can match on an `Error` to decide how to react, such as
+/// continuing to boot without a service that could not be initialized. Its
+/// [`Display`](fmt::Display) implementation describes the error for logging.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Error {
+    /// Memory could not be allocated.
+    Alloc,
+    /// The registry has no room for another service. See
+    /// [`KernelSettings::max_drivers`](crate::KernelSettings::max_drivers).
+    RegistryFull,
+    /// A service could not be initialized.
+    ServiceInit {
+        /// The service's [`RegisteredDriver::UUID`].
+        ///
+        /// [`RegisteredDriver::UUID`]: crate::registry::RegisteredDriver::UUID
+        uuid: Uuid,
+        /// Why the service could not be initialized.
+        reason: &'static str,
+    },
+    /// The kernel's timer could not be set as the global timer, because a
+    /// global timer has already been set.
+    TimerInit,
+    /// A [Forth VM](crate::forth::Forth) could not be constructed.
+    ForthInit(&'static str),
+    /// A boot step could not be registered.
+    BootStep(boot::RegisterError),
+    /// A boot step failed with an error that is not a kernel `Error`. The
+    /// step's error is logged when it fails.
+    StepFailed,
+    /// A boot step never ran, because the step it depends on failed or was
+    /// itself blocked.
+    StepBlocked {
+        /// The name of the dependency which did not complete.
+        dependency: &'static str,
+    },
+}
+
+// === impl Error ===
+
+impl From<RegistrationError> for Error {
+    fn from(error: RegistrationError) -> Self {
+        match error {
+            RegistrationError::RegistryFull => Self::RegistryFull,
+            RegistrationError::UuidAlreadyRegistered(uuid) => Self::ServiceInit {
+                uuid,
+                reason: "a service with this UUID is already registered",
+            },
+        }
+    }
+}
+
+impl From<maitake::time::AlreadyInitialized> for Error {
+    fn from(_: maitake::time::AlreadyInitialized) -> Self {
+        Self::TimerInit
+    }
+}
+
+impl From<boot::RegisterError> for Error {
+    fn from(error: boot::RegisterError) -> Self {
+        Self::BootStep(error)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Alloc => f.write_str("memory allocation failed"),
+            Self::RegistryFull => f.write_str("the service registry is full"),
+            Self::ServiceInit { uuid, reason } => {
+                write!(f, "failed to initialize service {uuid}: {reason}")
+            }
+            Self::TimerInit => f.write_str("a global timer has already been set"),
+            Self::ForthInit(reason) => write!(f, "failed to initialize Forth VM: {reason}"),
+            Self::BootStep(boot::RegisterError::Duplicate(name)) => {
+                write!(f, "a boot step named {name:?} already exists")
+            }
+            Self::BootStep(boot::RegisterError::Full) => f.write_str("too many boot steps"),
+            Self::BootStep(boot::RegisterError::Busy) => {
+                f.write_str("boot progress was being updated while registering a step")
+            }
+            Self::StepFailed => f.write_str("the boot step failed"),
+            Self::StepBlocked { dependency } => {
+                write!(
+                    f,
+                    "blocked by boot step {dependency:?}, which did not complete"
+                )
+            }
+        }
+    }
+}
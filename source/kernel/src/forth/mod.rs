@@ -1,10 +1,22 @@
+mod output;
+
 use crate::services::forth_spawnulator::SpawnulatorClient;
 use crate::{
-    comms::bbq,
-    services::serial_mux::{PortHandle, SerialMuxClient},
-    Kernel,
+    comms::{
+        bbq,
+        kchannel::{KChannel, KConsumer, KProducer},
+    },
+    serial_trace,
+    services::{
+        clipboard::ClipboardClient,
+        i2c::I2cClient,
+        serial_mux::{PortHandle, SerialMuxClient},
+        smart_led::SmartLedClient,
+    },
+    Error, Kernel,
 };
 use core::{any::TypeId, future::Future, ptr::NonNull, time::Duration};
+use embedded_graphics::pixelcolor::Rgb888;
 use forth3::{
     async_builtin,
     dictionary::{self, AsyncBuiltinEntry, AsyncBuiltins, Dictionary, OwnedDict},
@@ -14,13 +26,16 @@ use forth3::{
     word::Word,
     AsyncForth, CallContext,
 };
+use futures::FutureExt;
 use mnemos_alloc::{
     containers::{ArrayBuf, Box, FixedVec},
     heap::{alloc, dealloc},
 };
 use portable_atomic::{AtomicUsize, Ordering};
 use serde::{Deserialize, Serialize};
-use tracing;
+use tracing::{self, level_filters::LevelFilter};
+
+use self::output::{Output, Target};
 
 #[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 #[non_exhaustive]
@@ -41,6 +56,32 @@ pub struct Params {
     pub bag_of_holding_capacity: usize,
     #[serde(default = "Params::default_spawnulator_timeout")]
     pub spawnulator_timeout: Duration,
+    /// Whether word names are case-insensitive, as in classic Forth.
+    #[serde(default = "Params::default_case_insensitive")]
+    pub case_insensitive: bool,
+    /// Whether to load the [standard prelude](forth3::prelude) into new VMs.
+    #[serde(default = "Params::default_prelude")]
+    pub prelude: bool,
+    /// Whether to color the VM's output using ANSI escape sequences, so that
+    /// errors stand out from the `ok.` prompt. This can be changed at runtime
+    /// with `color on` and `color off`.
+    #[serde(default = "Params::default_color")]
+    pub color: bool,
+    /// Whether to echo each line of input back to stdout before running it.
+    #[serde(default = "Params::default_echo")]
+    pub echo: bool,
+}
+
+/// ANSI escape sequences used to color a VM's output, when
+/// [`Params::color`] is enabled.
+mod ansi {
+    pub(super) const RESET: &str = "\x1b[0m";
+    /// The `ok.` prompt is green.
+    pub(super) const OK: &str = "\x1b[32m";
+    /// Errors are bold and red.
+    pub(super) const ERROR: &str = "\x1b[1;31m";
+    /// Echoed input is dimmed.
+    pub(super) const INPUT: &str = "\x1b[2m";
 }
 
 pub struct Forth {
@@ -49,6 +90,20 @@ pub struct Forth {
     _bufs: Bufs,
 }
 
+/// The stdio of a VM created by the `spawn` word.
+///
+/// See [`Forth::spawned_stdio`].
+pub struct SpawnedStdio {
+    /// The spawned VM's ID.
+    pub id: usize,
+    /// The ID of the VM which spawned it.
+    pub parent: usize,
+    /// The other end of the spawned VM's stdin and stdout.
+    ///
+    /// The spawned VM exits once it is idle and this is dropped.
+    pub stdio: bbq::BidiHandle,
+}
+
 /// Owns the heap allocations for a `Forth` task.
 struct Bufs {
     dstack: ArrayBuf<Word>,
@@ -64,7 +119,7 @@ impl Forth {
     pub async fn new(
         kernel: &'static Kernel,
         params: Params,
-    ) -> Result<(Self, bbq::BidiHandle), &'static str> {
+    ) -> Result<(Self, bbq::BidiHandle), Error> {
         let (stdio, streams) = params.alloc_stdio().await;
         let forth = Self::new_with_stdio(kernel, params, stdio).await?;
         Ok((forth, streams))
@@ -74,12 +129,12 @@ impl Forth {
         kernel: &'static Kernel,
         params: Params,
         stdio: bbq::BidiHandle,
-    ) -> Result<Self, &'static str> {
+    ) -> Result<Self, Error> {
         let mut bufs = params.alloc_bufs().await;
         let dict = params.alloc_dict().await?;
         let host_ctxt = MnemosContext::new(kernel, params).await;
 
-        let forth = unsafe {
+        let mut forth = unsafe {
             AsyncForth::new(
                 bufs.take_vm_bufs(),
                 dict,
@@ -89,9 +144,16 @@ impl Forth {
             )
             .map_err(|err| {
                 tracing::error!(?err, "Failed to construct Forth VM");
-                "failed to construct Forth VM"
+                Error::ForthInit("failed to construct Forth VM")
             })?
         };
+        forth.set_case_insensitive(params.case_insensitive);
+        if params.prelude {
+            forth.load_prelude().map_err(|err| {
+                tracing::error!(?err, "Failed to load Forth prelude");
+                Error::ForthInit("failed to load Forth prelude")
+            })?;
+        }
         let forth = Self {
             forth,
             stdio,
@@ -100,6 +162,56 @@ impl Forth {
         Ok(forth)
     }
 
+    /// Returns a channel which receives the stdio of each VM spawned by this
+    /// VM, or by any of its descendants, using `spawn`.
+    ///
+    /// If this is never called, a spawned VM's stdio is dropped, so it can
+    /// only write output using `output!`, and exits as soon as it's finished
+    /// running the word it was spawned with. If `capacity` spawned VMs are
+    /// already waiting to be received, the stdio of any more is dropped.
+    pub async fn spawned_stdio(&mut self, capacity: usize) -> KConsumer<SpawnedStdio> {
+        let (tx, rx) = KChannel::new_async(capacity).await.split();
+        self.forth.host_ctxt_mut().spawned = Some(tx);
+        rx
+    }
+
+    /// Runs each line of `script` in this VM, as though it had been typed at
+    /// the VM's stdin.
+    ///
+    /// As at a shell, each colon definition must fit on a single line. Rather
+    /// than being written to the VM's stdout, the output of each line is
+    /// logged. If a line fails, the rest of the script is not run.
+    #[tracing::instrument(
+        level = tracing::Level::INFO,
+        "Forth",
+        skip(self, script),
+        fields(id = self.forth.host_ctxt().id)
+    )]
+    pub async fn run_script(&mut self, script: &str) -> Result<(), forth3::Error> {
+        for (lineno, line) in script.lines().enumerate() {
+            let lineno = lineno + 1;
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            self.forth.output_mut().clear();
+            self.forth.input_mut().fill(line).map_err(|error| {
+                tracing::error!(lineno, ?error, "Failed to read script line");
+                forth3::Error::InternalError
+            })?;
+            self.forth.process_line().await.map_err(|error| {
+                tracing::error!(lineno, ?error, "> {}", line.trim());
+                error
+            })?;
+
+            let output = self.forth.output().as_str();
+            if !output.trim().is_empty() {
+                tracing::info!(lineno, "< {}", output.trim_end());
+            }
+        }
+        Ok(())
+    }
+
     #[tracing::instrument(
         level = tracing::Level::INFO,
         "Forth",
@@ -113,22 +225,44 @@ impl Forth {
 
             match self.forth.process_line().await {
                 Ok(()) => {
+                    // take the task's output, so that it can be written to
+                    // while the output buffer is borrowed.
+                    let mut output = core::mem::take(&mut self.forth.host_ctxt_mut().output);
+                    let color = self.forth.host_ctxt().params.color;
                     let out_str = self.forth.output().as_str();
-                    let output = out_str.as_bytes();
-                    // write the task's output to stdout
-                    let len = output.len();
-                    tracing::debug!(len, "< {out_str}");
-                    let mut send = self.stdio.producer().send_grant_exact(output.len()).await;
-                    send.copy_from_slice(output);
-                    send.commit(len);
+                    // write the task's output to its selected output, and the
+                    // prompt to stdout.
+                    tracing::debug!(len = out_str.len(), "< {out_str}");
+                    let stdout = self.stdio.producer();
+                    match out_str.strip_suffix("ok.\n") {
+                        Some(out_str) => {
+                            output.write(stdout, out_str.as_bytes()).await;
+                            if color {
+                                stdout.write_all(ansi::OK.as_bytes()).await;
+                                stdout.write_all(b"ok.").await;
+                                stdout.write_all(ansi::RESET.as_bytes()).await;
+                                stdout.write_all(b"\n").await;
+                            } else {
+                                stdout.write_all(b"ok.\n").await;
+                            }
+                        }
+                        None => output.write(stdout, out_str.as_bytes()).await,
+                    }
+                    self.forth.host_ctxt_mut().output = output;
                 }
                 Err(error) => {
                     tracing::error!(?error);
                     // TODO(ajm): Provide some kind of fixed length error string?
-                    const ERROR: &[u8] = b"ERROR.\n";
-                    let mut send = self.stdio.producer().send_grant_exact(ERROR.len()).await;
-                    send.copy_from_slice(ERROR);
-                    send.commit(ERROR.len());
+                    const ERROR: &[u8] = b"ERROR.";
+                    let stdout = self.stdio.producer();
+                    if self.forth.host_ctxt().params.color {
+                        stdout.write_all(ansi::ERROR.as_bytes()).await;
+                        stdout.write_all(ERROR).await;
+                        stdout.write_all(ansi::RESET.as_bytes()).await;
+                    } else {
+                        stdout.write_all(ERROR).await;
+                    }
+                    stdout.write_all(b"\n").await;
                     // TODO(ajm): I need a "clear" function for the input. This wont properly
                     // clear string literals either.
                     let inp = self.forth.input_mut();
@@ -138,13 +272,36 @@ impl Forth {
                 }
             }
 
-            // read from stdin
+            // read from stdin, until whoever is on the other end of stdio
+            // goes away.
             {
-                let read = self.stdio.consumer().read_grant().await;
+                let stdin = self.stdio.consumer();
+                let read = futures::select_biased! {
+                    read = stdin.read_grant().fuse() => read,
+                    _ = self.stdio.closed().fuse() => match stdin.read_grant_sync() {
+                        Some(read) => read,
+                        None => {
+                            tracing::info!("stdio closed, VM exiting");
+                            return;
+                        }
+                    },
+                };
                 let len = read.len();
                 match core::str::from_utf8(&read) {
                     Ok(input) => {
                         tracing::debug!(len, "> {:?}", input.trim());
+                        let params = self.forth.host_ctxt().params;
+                        if params.echo {
+                            let stdout = self.stdio.producer();
+                            if params.color {
+                                stdout.write_all(ansi::INPUT.as_bytes()).await;
+                            }
+                            stdout.write_all(input.trim_end().as_bytes()).await;
+                            if params.color {
+                                stdout.write_all(ansi::RESET.as_bytes()).await;
+                            }
+                            stdout.write_all(b"\n").await;
+                        }
                         self.forth
                             .input_mut()
                             .fill(input)
@@ -168,6 +325,11 @@ pub(crate) struct MnemosContext {
     id: usize,
     /// Handle for spawning child tasks.
     spawnulator: SpawnulatorClient,
+    /// Where the task's output is written. See [`output`].
+    output: Output,
+    /// Where the stdio of spawned tasks is sent. See
+    /// [`Forth::spawned_stdio`].
+    spawned: Option<KProducer<SpawnedStdio>>,
 }
 
 impl MnemosContext {
@@ -181,12 +343,21 @@ pub(crate) struct Dispatcher;
 
 struct DropDict;
 
+/// Every builtin holds whatever it acquires in a value that releases it when
+/// dropped, or in the [`MnemosContext`], so none of them need to implement the
+/// [`AsyncBuiltins::cancelled`] hook.
 impl<'forth> AsyncBuiltins<'forth, MnemosContext> for Dispatcher {
     type Future = impl Future<Output = Result<(), forth3::Error>> + 'forth;
 
     const BUILTINS: &'static [AsyncBuiltinEntry<MnemosContext>] = &[
         async_builtin!("sermux::open_port"),
         async_builtin!("sermux::write_outbuf"),
+        // open a sermux port, returning a BOH token
+        async_builtin!("port-open"),
+        // write bytes from memory to a sermux port, waiting for space
+        async_builtin!("port-write"),
+        // read bytes from a sermux port into memory, with a timeout in ms
+        async_builtin!("port-read-timeout"),
         async_builtin!("spawn"),
         // sleep for a number of microseconds
         async_builtin!("sleep::us"),
@@ -194,6 +365,36 @@ impl<'forth> AsyncBuiltins<'forth, MnemosContext> for Dispatcher {
         async_builtin!("sleep::ms"),
         // sleep for a number of seconds
         async_builtin!("sleep::s"),
+        // get the number of LEDs on the smart LED strip
+        async_builtin!("led::len"),
+        // show a frame of 0xRRGGBB colors on the smart LED strip
+        async_builtin!("led::show"),
+        // copy bytes from memory to the shared clipboard
+        async_builtin!("clip!"),
+        // copy bytes from the shared clipboard to memory
+        async_builtin!("clip@"),
+        // scan the I2C bus, printing the address of every device found
+        async_builtin!("i2c-scan"),
+        // create a channel for sending cells to other tasks
+        async_builtin!("chan-new"),
+        // send cells from memory over a channel, waiting for space
+        async_builtin!("chan-send"),
+        // receive cells from a channel into memory, waiting for a message
+        async_builtin!("chan-recv"),
+        // set the maximum level of traces sent over the serial port
+        async_builtin!("trace-level!"),
+        // get the maximum level of traces sent over the serial port
+        async_builtin!("trace-level@"),
+        // turn colored output on or off: `color on` or `color off`
+        async_builtin!("color"),
+        // select a sermux port as an output, for `output!`
+        async_builtin!(">port"),
+        // select the display as an output, for `output!`
+        async_builtin!(">display"),
+        // select stdout as an output, for `output!`
+        async_builtin!(">stdout"),
+        // write the task's output to the selected output
+        async_builtin!("output!"),
     ];
 
     fn dispatch_async(
@@ -205,10 +406,28 @@ impl<'forth> AsyncBuiltins<'forth, MnemosContext> for Dispatcher {
             match id.as_str() {
                 "sermux::open_port" => sermux_open_port(forth).await,
                 "sermux::write_outbuf" => sermux_write_outbuf(forth).await,
+                "port-open" => sermux_open_port(forth).await,
+                "port-write" => port_write(forth).await,
+                "port-read-timeout" => port_read_timeout(forth).await,
                 "spawn" => spawn_forth_task(forth).await,
                 "sleep::us" => sleep(forth, Duration::from_micros).await,
                 "sleep::ms" => sleep(forth, Duration::from_millis).await,
                 "sleep::s" => sleep(forth, Duration::from_secs).await,
+                "led::len" => led_len(forth).await,
+                "led::show" => led_show(forth).await,
+                "clip!" => clip_store(forth).await,
+                "clip@" => clip_fetch(forth).await,
+                "i2c-scan" => i2c_scan(forth).await,
+                "chan-new" => chan_new(forth).await,
+                "chan-send" => chan_send(forth).await,
+                "chan-recv" => chan_recv(forth).await,
+                "trace-level!" => trace_level_store(forth).await,
+                "trace-level@" => trace_level_fetch(forth).await,
+                "color" => color(forth).await,
+                ">port" => output_port(forth).await,
+                ">display" => output_target(forth, Target::Display).await,
+                ">stdout" => output_target(forth, Target::Stdio).await,
+                "output!" => output_store(forth).await,
                 _ => {
                     tracing::warn!("unimplemented async builtin: {}", id.as_str());
                     Err(forth3::Error::WordNotInDict)
@@ -228,6 +447,10 @@ impl Params {
     pub const DEFAULT_STDOUT_CAPACITY: usize = 1024;
     pub const DEFAULT_BAG_OF_HOLDING_CAPACITY: usize = 16;
     pub const DEFAULT_SPAWNULATOR_TIMEOUT: Duration = Duration::from_secs(5);
+    pub const DEFAULT_CASE_INSENSITIVE: bool = true;
+    pub const DEFAULT_PRELUDE: bool = true;
+    pub const DEFAULT_COLOR: bool = false;
+    pub const DEFAULT_ECHO: bool = false;
 
     const fn default_stack_size() -> usize {
         Self::DEFAULT_STACK_SIZE
@@ -253,6 +476,18 @@ impl Params {
     const fn default_spawnulator_timeout() -> Duration {
         Self::DEFAULT_SPAWNULATOR_TIMEOUT
     }
+    const fn default_case_insensitive() -> bool {
+        Self::DEFAULT_CASE_INSENSITIVE
+    }
+    const fn default_prelude() -> bool {
+        Self::DEFAULT_PRELUDE
+    }
+    const fn default_color() -> bool {
+        Self::DEFAULT_COLOR
+    }
+    const fn default_echo() -> bool {
+        Self::DEFAULT_ECHO
+    }
 
     pub const fn new() -> Self {
         Self {
@@ -264,6 +499,10 @@ impl Params {
             stdout_capacity: Self::DEFAULT_STDOUT_CAPACITY,
             bag_of_holding_capacity: Self::DEFAULT_BAG_OF_HOLDING_CAPACITY,
             spawnulator_timeout: Self::DEFAULT_SPAWNULATOR_TIMEOUT,
+            case_insensitive: Self::DEFAULT_CASE_INSENSITIVE,
+            prelude: Self::DEFAULT_PRELUDE,
+            color: Self::DEFAULT_COLOR,
+            echo: Self::DEFAULT_ECHO,
         }
     }
 
@@ -286,9 +525,9 @@ impl Params {
     }
 
     /// Allocate a new `OwnedDict` with this `Params`' dictionary size.
-    async fn alloc_dict(&self) -> Result<OwnedDict<MnemosContext>, &'static str> {
+    async fn alloc_dict(&self) -> Result<OwnedDict<MnemosContext>, Error> {
         let layout = Dictionary::<MnemosContext>::layout(self.dictionary_size)
-            .map_err(|_| "invalid dictionary size")?;
+            .map_err(|_| Error::ForthInit("invalid dictionary size"))?;
         let dict_buf = alloc(layout)
             .await
             .cast::<core::mem::MaybeUninit<Dictionary<MnemosContext>>>();
@@ -324,6 +563,8 @@ impl MnemosContext {
                 .await
                 .expect("Spawnulator client timed out - is the spawnulator running?")
                 .expect("failed to get spawnulator"),
+            output: Output::Stdio,
+            spawned: None,
         }
     }
 }
@@ -402,11 +643,15 @@ impl ConvertWord for Word {
 
 /// Binding for [`SerialMuxClient::open_port()`]
 ///
-/// Call: `PORT SZ sermux::open_port`
+/// Call: `PORT SZ sermux::open_port` (or `PORT SZ port-open`)
 /// Return: BOH_TOKEN on stack
 ///
 /// Errors on any invalid parameters. See [`BagOfHolding`] for details
 /// on bag of holding tokens
+///
+/// This may be cancelled at any point. If it is cancelled before the port
+/// is registered in the bag of holding, the [`PortHandle`] (or the reply that
+/// would have carried it) is dropped, which frees the port.
 async fn sermux_open_port(forth: &mut forth3::Forth<MnemosContext>) -> Result<(), forth3::Error> {
     let sz = forth.data_stack.try_pop()?.into_usize()?;
     let port = forth.data_stack.try_pop()?.into_u16()?;
@@ -460,6 +705,95 @@ async fn sermux_write_outbuf(
     Ok(())
 }
 
+/// Binding for [`PortHandle::send()`]
+///
+/// Writes `LEN` bytes starting at `ADDR` to the [`PortHandle`]. If the
+/// port's outgoing buffer is full, this waits for space to become available,
+/// rather than discarding data.
+///
+/// Call: `ADDR LEN BOH_TOKEN port-write`
+/// Return: No change
+///
+/// Errors if the provided handle is incorrect. See [`BagOfHolding`] for details
+/// on bag of holding tokens
+async fn port_write(forth: &mut forth3::Forth<MnemosContext>) -> Result<(), forth3::Error> {
+    let idx = forth.data_stack.try_pop()?.into_i32();
+    let len = forth.data_stack.try_pop()?.into_usize()?;
+    let addr = forth.data_stack.try_pop()?;
+    let port: &PortHandle = forth
+        .host_ctxt
+        .boh
+        .get(idx)
+        .ok_or(forth3::Error::InternalError)?;
+
+    if len == 0 {
+        return Ok(());
+    }
+    let ptr = unsafe { addr.ptr.cast::<u8>() };
+    if ptr.is_null() {
+        return Err(forth3::Error::NullPointerInCFA);
+    }
+    let bytes = unsafe { core::slice::from_raw_parts(ptr, len) };
+    port.send(bytes).await;
+    Ok(())
+}
+
+/// Binding for [`bbq::Consumer::read_grant()`] on a [`PortHandle`], with a
+/// timeout.
+///
+/// Reads up to `LEN` bytes from the [`PortHandle`] into memory starting at
+/// `ADDR`. If no data arrives within `TIMEOUT_MS` milliseconds, this gives up.
+/// Any bytes beyond `LEN` are left in the port's buffer for the next read.
+///
+/// Call: `ADDR LEN BOH_TOKEN TIMEOUT_MS port-read-timeout`
+/// Return: the number of bytes read, or `-1` if the read timed out.
+///
+/// Errors if the provided handle is incorrect. See [`BagOfHolding`] for details
+/// on bag of holding tokens
+async fn port_read_timeout(forth: &mut forth3::Forth<MnemosContext>) -> Result<(), forth3::Error> {
+    let timeout = {
+        let ms = forth.data_stack.try_pop()?.into_i32();
+        if ms.is_negative() {
+            tracing::warn!(ms, "Cannot time out after a negative duration!");
+            return Err(forth3::Error::WordToUsizeInvalid(ms));
+        }
+        Duration::from_millis(ms as u64)
+    };
+    let idx = forth.data_stack.try_pop()?.into_i32();
+    let len = forth.data_stack.try_pop()?.into_usize()?;
+    let addr = forth.data_stack.try_pop()?;
+    let ptr = unsafe { addr.ptr.cast::<u8>() };
+    if ptr.is_null() && len != 0 {
+        return Err(forth3::Error::NullPointerInCFA);
+    }
+
+    let kernel = forth.host_ctxt.kernel;
+    let port: &PortHandle = forth
+        .host_ctxt
+        .boh
+        .get(idx)
+        .ok_or(forth3::Error::InternalError)?;
+
+    let read = match kernel.timeout(timeout, port.consumer().read_grant()).await {
+        Ok(rgr) => {
+            let used = core::cmp::min(rgr.len(), len);
+            if used != 0 {
+                let dest = unsafe { core::slice::from_raw_parts_mut(ptr, used) };
+                dest.copy_from_slice(&rgr[..used]);
+            }
+            rgr.release(used);
+            used as i32
+        }
+        Err(_) => {
+            tracing::trace!(?timeout, "port read timed out");
+            -1
+        }
+    };
+
+    forth.data_stack.push(Word::data(read))?;
+    Ok(())
+}
+
 /// Binding for [`Kernel::spawn()`]
 ///
 /// Spawns a new Forth task that inherits from this task's dictionary. The task
@@ -467,15 +801,19 @@ async fn sermux_write_outbuf(
 ///
 /// Call: `XT spawn`.
 /// Return: the task ID of the spawned Forth task.
+///
+/// This may be cancelled at any point. The child VM owns everything allocated
+/// for it, so if this is cancelled before the spawnulator receives the child,
+/// the child is dropped, and if it's cancelled afterwards, the child runs
+/// anyway. If it is cancelled after the fork, the parent keeps its new
+/// dictionary, which is harmless.
 async fn spawn_forth_task(forth: &mut forth3::Forth<MnemosContext>) -> Result<(), forth3::Error> {
     let xt = forth.data_stack.try_pop()?;
     tracing::debug!("Forking Forth VM...");
     let params = forth.host_ctxt.params;
     let kernel = forth.host_ctxt.kernel;
 
-    // TODO(eliza): store the child's stdio in the
-    // parent's host context so we can actually do something with it...
-    let (stdio, _streams) = params.alloc_stdio().await;
+    let (stdio, streams) = params.alloc_stdio().await;
     let mut bufs = params.alloc_bufs().await;
     let new_dict = params.alloc_dict().await.map_err(|error| {
         tracing::error!(?error, "Failed to allocate dictionary for child VM");
@@ -488,8 +826,16 @@ async fn spawn_forth_task(forth: &mut forth3::Forth<MnemosContext>) -> Result<()
         );
         forth3::Error::InternalError
     })?;
-    let host_ctxt = MnemosContext::new(kernel, params).await;
+    let mut host_ctxt = MnemosContext::new(kernel, params).await;
     let child_id = host_ctxt.id;
+    // the child can use any channels the parent has created, with the same
+    // tokens, so that they can talk to each other.
+    host_ctxt
+        .boh
+        .inherit::<ForthChannel>(&forth.host_ctxt.boh)
+        .await;
+    // the child's children are sent to the same place as its siblings.
+    host_ctxt.spawned = forth.host_ctxt.spawned.clone();
 
     let mut child = unsafe { forth.fork(bufs.take_vm_bufs(), new_dict, my_dict, host_ctxt) }
         .map_err(|error| {
@@ -523,7 +869,19 @@ async fn spawn_forth_task(forth: &mut forth3::Forth<MnemosContext>) -> Result<()
     let timeout_res = kernel.timeout(params.spawnulator_timeout, spawn_fut).await;
 
     match timeout_res {
-        Ok(Ok(())) => Ok(()),
+        Ok(Ok(())) => {
+            if let Some(ref spawned) = forth.host_ctxt.spawned {
+                let spawned = spawned.enqueue_sync(SpawnedStdio {
+                    id: child_id,
+                    parent: forth.host_ctxt.id,
+                    stdio: streams,
+                });
+                if spawned.is_err() {
+                    tracing::debug!(child.id = child_id, "Nobody is receiving spawned stdio");
+                }
+            }
+            Ok(())
+        }
         Ok(Err(error)) => {
             tracing::error!(?error, "Failed to enqueue child task to spawn!");
             Err(forth3::Error::InternalError)
@@ -562,6 +920,379 @@ async fn sleep(
     Ok(())
 }
 
+/// Binding for [`SmartLedClient::get_meta()`]
+///
+/// Call: `led::len`
+/// Return: the number of LEDs on the strip
+///
+/// Errors if no smart LED service is running.
+async fn led_len(forth: &mut forth3::Forth<MnemosContext>) -> Result<(), forth3::Error> {
+    let mut leds = SmartLedClient::from_registry_no_retry(forth.host_ctxt.kernel)
+        .await
+        .map_err(|_| forth3::Error::InternalError)?;
+    let meta = leds
+        .get_meta()
+        .await
+        .map_err(|_| forth3::Error::InternalError)?;
+    let len = meta.width as usize * meta.height as usize;
+    forth.data_stack.push(Word::try_from(len)?)?;
+    Ok(())
+}
+
+/// Binding for [`SmartLedClient::show()`]
+///
+/// Shows `N` cells starting at `ADDR` on the smart LED strip, one per LED,
+/// with each cell containing a color as `0xRRGGBB`. If `N` is less than the
+/// number of LEDs, the remaining LEDs are turned off; extra cells are
+/// ignored.
+///
+/// Call: `ADDR N led::show`
+/// Return: No change
+///
+/// Errors if no smart LED service is running.
+async fn led_show(forth: &mut forth3::Forth<MnemosContext>) -> Result<(), forth3::Error> {
+    let len = forth.data_stack.try_pop()?.into_usize()?;
+    let addr = forth.data_stack.try_pop()?;
+    let ptr = unsafe { addr.ptr.cast::<Word>() };
+    if ptr.is_null() && len != 0 {
+        return Err(forth3::Error::NullPointerInCFA);
+    }
+
+    let mut leds = SmartLedClient::from_registry_no_retry(forth.host_ctxt.kernel)
+        .await
+        .map_err(|_| forth3::Error::InternalError)?;
+    let mut frame = leds
+        .new_frame()
+        .await
+        .map_err(|_| forth3::Error::InternalError)?;
+
+    let cells: &[Word] = if len == 0 {
+        &[]
+    } else {
+        unsafe { core::slice::from_raw_parts(ptr, len) }
+    };
+    for (pixel, cell) in frame.pixels_mut().iter_mut().zip(cells) {
+        let [_, r, g, b] = cell.into_data().to_be_bytes();
+        *pixel = Rgb888::new(r, g, b);
+    }
+
+    leds.show(frame)
+        .await
+        .map_err(|_| forth3::Error::InternalError)?;
+    Ok(())
+}
+
+/// Binding for [`ClipboardClient::set_from_slice()`]
+///
+/// Replaces the contents of the shared clipboard with `LEN` bytes starting at
+/// `ADDR`.
+///
+/// Call: `ADDR LEN clip!`
+/// Return: No change
+///
+/// Errors if no clipboard service is running, or if the data is larger than
+/// the clipboard's maximum size.
+async fn clip_store(forth: &mut forth3::Forth<MnemosContext>) -> Result<(), forth3::Error> {
+    let len = forth.data_stack.try_pop()?.into_usize()?;
+    let addr = forth.data_stack.try_pop()?;
+    let ptr = unsafe { addr.ptr.cast::<u8>() };
+    if ptr.is_null() && len != 0 {
+        return Err(forth3::Error::NullPointerInCFA);
+    }
+    let bytes = if len == 0 {
+        &[]
+    } else {
+        unsafe { core::slice::from_raw_parts(ptr, len) }
+    };
+
+    let mut clip = ClipboardClient::from_registry_no_retry(forth.host_ctxt.kernel)
+        .await
+        .map_err(|_| forth3::Error::InternalError)?;
+    clip.set_from_slice(bytes).await.map_err(|error| {
+        tracing::warn!(?error, "Failed to set clipboard");
+        forth3::Error::InternalError
+    })?;
+    Ok(())
+}
+
+/// Binding for [`ClipboardClient::get()`]
+///
+/// Copies up to `LEN` bytes of the shared clipboard's contents into memory
+/// starting at `ADDR`.
+///
+/// Call: `ADDR LEN clip@`
+/// Return: the number of bytes copied.
+///
+/// Errors if no clipboard service is running.
+async fn clip_fetch(forth: &mut forth3::Forth<MnemosContext>) -> Result<(), forth3::Error> {
+    let len = forth.data_stack.try_pop()?.into_usize()?;
+    let addr = forth.data_stack.try_pop()?;
+    let ptr = unsafe { addr.ptr.cast::<u8>() };
+    if ptr.is_null() && len != 0 {
+        return Err(forth3::Error::NullPointerInCFA);
+    }
+
+    let mut clip = ClipboardClient::from_registry_no_retry(forth.host_ctxt.kernel)
+        .await
+        .map_err(|_| forth3::Error::InternalError)?;
+    let contents = clip.get().await.map_err(|_| forth3::Error::InternalError)?;
+    let used = core::cmp::min(contents.len(), len);
+    if used != 0 {
+        let dest = unsafe { core::slice::from_raw_parts_mut(ptr, used) };
+        dest.copy_from_slice(&contents.as_slice()[..used]);
+    }
+
+    forth.data_stack.push(Word::try_from(used)?)?;
+    Ok(())
+}
+
+/// Binding for [`serial_trace::set_max_level()`]
+///
+/// Sets the most verbose level of traces sent over the serial port, from `0`
+/// (off) through `1` (errors), `2` (warnings), `3` (info) and `4` (debug), to
+/// `5` (trace).
+///
+/// Call: `LEVEL trace-level!`
+/// Return: No change
+///
+/// Errors if the level is not between `0` and `5`.
+async fn trace_level_store(forth: &mut forth3::Forth<MnemosContext>) -> Result<(), forth3::Error> {
+    let word = forth.data_stack.try_pop()?.into_i32();
+    let level = TRACE_LEVELS
+        .get(word as usize)
+        .copied()
+        .ok_or(forth3::Error::WordToUsizeInvalid(word))?;
+    serial_trace::set_max_level(level);
+    Ok(())
+}
+
+/// Binding for [`serial_trace::max_level()`]
+///
+/// Call: `trace-level@`
+/// Return: the most verbose level of traces sent over the serial port, as
+/// taken by `trace-level!`.
+async fn trace_level_fetch(forth: &mut forth3::Forth<MnemosContext>) -> Result<(), forth3::Error> {
+    let current = serial_trace::max_level();
+    let level = TRACE_LEVELS
+        .iter()
+        .position(|&level| level == current)
+        .unwrap_or_default();
+    forth.data_stack.push(Word::try_from(level)?)?;
+    Ok(())
+}
+
+/// Turns ANSI colors in the VM's output on or off. See [`Params::color`].
+///
+/// Call: `color on` or `color off`
+/// Return: nothing.
+async fn color(forth: &mut forth3::Forth<MnemosContext>) -> Result<(), forth3::Error> {
+    forth.input.advance();
+    let color = match forth.input.cur_word() {
+        Some(word) if word.eq_ignore_ascii_case("on") => true,
+        Some(word) if word.eq_ignore_ascii_case("off") => false,
+        _ => return Err(forth3::Error::BadLiteral),
+    };
+    forth.host_ctxt.params.color = color;
+    Ok(())
+}
+
+/// Selects SerMux port `PORT` as an output, for `output!`. See [`output`].
+///
+/// Call: `PORT >port`
+/// Return: the selected output.
+///
+/// Errors if `PORT` is not a valid port number.
+async fn output_port(forth: &mut forth3::Forth<MnemosContext>) -> Result<(), forth3::Error> {
+    let port = forth.data_stack.try_pop()?.into_u16()?;
+    output_target(forth, Target::Port(port)).await
+}
+
+/// Binding for `>display` and `>stdout`, which select the display or stdout as
+/// an output, for `output!`. See [`output`].
+///
+/// Call: `>display` or `>stdout`
+/// Return: the selected output.
+async fn output_target(
+    forth: &mut forth3::Forth<MnemosContext>,
+    target: Target,
+) -> Result<(), forth3::Error> {
+    forth.data_stack.push(Word::data(target.into_word()))?;
+    Ok(())
+}
+
+/// Writes the output of every following line to `OUTPUT`, which was
+/// selected with `>port`, `>display` or `>stdout`. See [`output`].
+///
+/// If `OUTPUT` can't be opened, the task's output is written to stdout.
+///
+/// Call: `OUTPUT output!`
+/// Return: No change
+///
+/// Errors if `OUTPUT` is not a selected output, if its port is already open,
+/// or if the service it needs is not running.
+async fn output_store(forth: &mut forth3::Forth<MnemosContext>) -> Result<(), forth3::Error> {
+    let word = forth.data_stack.try_pop()?.into_i32();
+    let target = Target::from_word(word).ok_or(forth3::Error::WordToUsizeInvalid(word))?;
+    let ctxt = &mut forth.host_ctxt;
+    // close the old output first, so that its port can be reopened.
+    ctxt.output = Output::Stdio;
+    ctxt.output = Output::open(ctxt.kernel, target, ctxt.params.output_buf_size)
+        .await
+        .ok_or_else(|| {
+            tracing::warn!(?target, "Failed to open Forth output");
+            forth3::Error::InternalError
+        })?;
+    tracing::debug!(?target, "Selected Forth output");
+    Ok(())
+}
+
+/// Trace levels, as numbered by `trace-level!` and `trace-level@`.
+const TRACE_LEVELS: [LevelFilter; 6] = [
+    LevelFilter::OFF,
+    LevelFilter::ERROR,
+    LevelFilter::WARN,
+    LevelFilter::INFO,
+    LevelFilter::DEBUG,
+    LevelFilter::TRACE,
+];
+
+/// Binding for [`I2cClient::scan()`]
+///
+/// Scans the I2C bus, and prints the 7-bit address of every device found.
+///
+/// Call: `i2c-scan`
+/// Return: the number of devices found.
+///
+/// Errors if no I2C service is running.
+async fn i2c_scan(forth: &mut forth3::Forth<MnemosContext>) -> Result<(), forth3::Error> {
+    use core::fmt::Write;
+
+    let mut i2c = I2cClient::from_registry_no_retry(forth.host_ctxt.kernel)
+        .await
+        .map_err(|_| forth3::Error::InternalError)?;
+    let report = i2c.scan().await;
+    for addr in report.devices() {
+        write!(&mut forth.output, "{addr:#04x} ")?;
+    }
+
+    forth.data_stack.push(Word::try_from(report.len())?)?;
+    Ok(())
+}
+
+/// A channel for sending messages between Forth tasks.
+///
+/// Each message is a heap-allocated array of cells. Channels are inherited by
+/// tasks created with `spawn`, using the same Bag of Holding token as in the
+/// parent, so a task can create a channel and then spawn tasks which send or
+/// receive on it.
+///
+/// Any number of tasks may send on a channel, but only one task should wait to
+/// receive from a channel at a time.
+#[derive(Clone)]
+struct ForthChannel(KChannel<FixedVec<Word>>);
+
+/// Creates a new [`ForthChannel`].
+///
+/// The channel can hold up to `CAPACITY` messages which have been sent but not
+/// yet received. The capacity is rounded up to a power of two.
+///
+/// Call: `CAPACITY chan-new`
+/// Return: BOH_TOKEN on stack
+///
+/// Errors if the Bag of Holding is full. See [`BagOfHolding`] for details on
+/// bag of holding tokens
+async fn chan_new(forth: &mut forth3::Forth<MnemosContext>) -> Result<(), forth3::Error> {
+    let capacity = forth.data_stack.try_pop()?.into_usize()?;
+    let chan = ForthChannel(KChannel::new_async(capacity).await);
+    let idx = forth
+        .host_ctxt
+        .boh
+        .register(chan)
+        .await
+        .ok_or(forth3::Error::InternalError)?;
+    forth.data_stack.push(Word::data(idx))?;
+    Ok(())
+}
+
+/// Sends `N` cells starting at `ADDR` as a single message on a
+/// [`ForthChannel`]. The cells are copied, so the memory may be reused as soon
+/// as this returns. If the channel is full, this waits for space to become
+/// available.
+///
+/// Call: `ADDR N BOH_TOKEN chan-send`
+/// Return: No change
+///
+/// Errors if the provided handle is incorrect. See [`BagOfHolding`] for
+/// details on bag of holding tokens
+async fn chan_send(forth: &mut forth3::Forth<MnemosContext>) -> Result<(), forth3::Error> {
+    let idx = forth.data_stack.try_pop()?.into_i32();
+    let len = forth.data_stack.try_pop()?.into_usize()?;
+    let addr = forth.data_stack.try_pop()?;
+    let ptr = unsafe { addr.ptr.cast::<Word>() };
+    if ptr.is_null() && len != 0 {
+        return Err(forth3::Error::NullPointerInCFA);
+    }
+    let cells: &[Word] = if len == 0 {
+        &[]
+    } else {
+        unsafe { core::slice::from_raw_parts(ptr, len) }
+    };
+
+    let chan: &ForthChannel = forth
+        .host_ctxt
+        .boh
+        .get(idx)
+        .ok_or(forth3::Error::InternalError)?;
+    // `FixedVec`s can't be empty, but the message can be.
+    let mut msg = FixedVec::new(len.max(1)).await;
+    msg.try_extend_from_slice(cells)
+        .expect("message was allocated with enough capacity");
+    chan.0
+        .enqueue_async(msg)
+        .await
+        .map_err(|_| forth3::Error::InternalError)?;
+    Ok(())
+}
+
+/// Receives a message from a [`ForthChannel`], copying up to `N` of its cells
+/// into memory starting at `ADDR`. If no message has been sent, this waits
+/// until one is. Any cells beyond `N` are discarded.
+///
+/// Call: `ADDR N BOH_TOKEN chan-recv`
+/// Return: the number of cells in the message.
+///
+/// Errors if the provided handle is incorrect. See [`BagOfHolding`] for
+/// details on bag of holding tokens
+async fn chan_recv(forth: &mut forth3::Forth<MnemosContext>) -> Result<(), forth3::Error> {
+    let idx = forth.data_stack.try_pop()?.into_i32();
+    let len = forth.data_stack.try_pop()?.into_usize()?;
+    let addr = forth.data_stack.try_pop()?;
+    let ptr = unsafe { addr.ptr.cast::<Word>() };
+    if ptr.is_null() && len != 0 {
+        return Err(forth3::Error::NullPointerInCFA);
+    }
+
+    let chan: &ForthChannel = forth
+        .host_ctxt
+        .boh
+        .get(idx)
+        .ok_or(forth3::Error::InternalError)?;
+    let msg = chan
+        .0
+        .dequeue_async()
+        .await
+        .map_err(|_| forth3::Error::InternalError)?;
+    let msg = msg.as_slice();
+    let used = core::cmp::min(msg.len(), len);
+    if used != 0 {
+        let dest = unsafe { core::slice::from_raw_parts_mut(ptr, used) };
+        dest.copy_from_slice(&msg[..used]);
+    }
+
+    forth.data_stack.push(Word::try_from(msg.len())?)?;
+    Ok(())
+}
+
 impl dictionary::DropDict for DropDict {
     unsafe fn drop_dict(ptr: NonNull<u8>, layout: core::alloc::Layout) {
         dealloc(ptr.as_ptr().cast(), layout);
@@ -660,6 +1391,41 @@ impl BagOfHolding {
         Some(idx)
     }
 
+    /// Copy every item of type `T` from `parent` into this Bag of Holding,
+    /// with the same tokens as in `parent`.
+    ///
+    /// Items whose tokens are already in use in this Bag of Holding are
+    /// skipped, as are any items which don't fit.
+    pub async fn inherit<T>(&mut self, parent: &BagOfHolding)
+    where
+        T: Clone + 'static,
+    {
+        let tid = TypeId::of::<T>();
+        for (idx, val) in parent.inner.as_slice() {
+            let in_use = self.inner.as_slice().iter().any(|(i, _)| i == idx);
+            if val.tid != tid || in_use || self.inner.is_full() {
+                continue;
+            }
+            let item = unsafe { val.value_ptr.cast::<T>().as_ref() }.clone();
+            let value_ptr = NonNull::new(Box::into_raw(Box::new(item).await))
+                .expect("boxes are never null")
+                .cast();
+            self.inner
+                .try_push((
+                    *idx,
+                    BohValue {
+                        tid,
+                        value_ptr,
+                        dropfn: dropfn::<T>,
+                    },
+                ))
+                .ok()
+                .unwrap_or_else(|| {
+                    debug_assert!(false, "Push failed after checking we aren't full?");
+                });
+        }
+    }
+
     /// Attempt to retrieve an item from the Bag of Holding
     ///
     /// This will only succeed if the same `T` is used as was used when calling
@@ -712,3 +1478,134 @@ fn dropfn<T>(bs: NonNull<()>) {
         let _ = Box::from_raw(i.as_ptr());
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        services::{
+            forth_spawnulator::{SpawnulatorServer, SpawnulatorSettings},
+            serial_mux::{SerialMuxServer, SerialMuxSettings},
+            simple_serial::{self, SimpleSerialService},
+        },
+        test_util::TestKernel,
+    };
+    use core::{pin::pin, task::Poll};
+
+    /// Registers the services used by the builtins under test.
+    async fn setup(k: &'static Kernel) {
+        // the serial mux needs a serial port, but nothing reads from the
+        // other end of it.
+        let (port, host) = bbq::new_bidi_channel(1024, 1024).await;
+        let reqs = k
+            .registry()
+            .bind_konly::<SimpleSerialService>(1)
+            .await
+            .unwrap()
+            .into_request_stream(1)
+            .await;
+        k.spawn(async move {
+            let _host = host;
+            let req = reqs.next_request().await;
+            let resp = req
+                .msg
+                .reply_with(Ok(simple_serial::Response::PortHandle { handle: port }));
+            req.reply.reply_konly(resp).await.map_err(drop).unwrap();
+            core::future::pending::<()>().await
+        })
+        .await;
+        SerialMuxServer::register(k, SerialMuxSettings::default())
+            .await
+            .unwrap();
+        SpawnulatorServer::register(k, SpawnulatorSettings::default())
+            .await
+            .unwrap();
+    }
+
+    /// Processes `line` in `vm`, dropping the line's future if it hasn't
+    /// completed after being polled `polls` times. Other tasks run between
+    /// polls. Returns `true` if the line completed.
+    async fn poll_line(k: &'static Kernel, vm: &mut Forth, line: &str, polls: usize) -> bool {
+        vm.forth.input_mut().fill(line).unwrap();
+        let mut process = pin!(vm.forth.process_line());
+        for _ in 0..polls {
+            if let Poll::Ready(res) = futures::poll!(process.as_mut()) {
+                res.unwrap();
+                return true;
+            }
+            k.yield_now().await;
+        }
+        false
+    }
+
+    #[test]
+    fn port_open_cancelled() {
+        TestKernel::run(|k| async move {
+            setup(k).await;
+
+            // drop `port-open` at each of its await points in turn, until it
+            // completes.
+            for polls in 0.. {
+                let (mut vm, _stdio) = Forth::new(k, Params::new()).await.unwrap();
+                if poll_line(k, &mut vm, "100 16 port-open", polls).await {
+                    break;
+                }
+                assert!(polls < 100, "port-open never completed");
+
+                // the VM recovers, and the port was freed, so it can be
+                // opened again...
+                assert!(poll_line(k, &mut vm, "100 16 port-open", usize::MAX).await);
+                assert_eq!(vm.forth.interrupted(), None);
+
+                // ...and when the VM is dropped, so is the port.
+                drop(vm);
+                let port = PortHandle::open(k, 100, 16).await;
+                assert!(port.is_some(), "port 100 leaked after {polls} polls");
+            }
+        })
+    }
+
+    #[test]
+    fn spawn_cancelled() {
+        TestKernel::run(|k| async move {
+            setup(k).await;
+
+            // drop `spawn` at each of its await points in turn, until it
+            // completes.
+            const LINE: &str = ": child ; ' child spawn";
+            for polls in 0.. {
+                let (mut vm, _stdio) = Forth::new(k, Params::new()).await.unwrap();
+                if poll_line(k, &mut vm, LINE, polls).await {
+                    break;
+                }
+                assert!(polls < 100, "spawn never completed");
+
+                // the VM recovers, and can still spawn, even if the
+                // spawnulator hasn't replied to the cancelled spawn yet.
+                assert!(poll_line(k, &mut vm, LINE, usize::MAX).await);
+                assert_eq!(vm.forth.interrupted(), None);
+            }
+        })
+    }
+
+    #[test]
+    fn spawned_stdio() {
+        TestKernel::run(|k| async move {
+            setup(k).await;
+
+            let (mut vm, _stdio) = Forth::new(k, Params::new()).await.unwrap();
+            let spawned = vm.spawned_stdio(1).await;
+            assert!(poll_line(k, &mut vm, ": child 42 . ; ' child spawn", usize::MAX).await);
+
+            // the child's output is written to the stdio it was spawned with.
+            let child = spawned.dequeue_async().await.unwrap();
+            assert_eq!(child.parent, vm.forth.host_ctxt().id());
+            let output = child.stdio.consumer().read_grant().await;
+            assert!(
+                output.starts_with(b"42 "),
+                "unexpected output: {:?}",
+                core::str::from_utf8(&output)
+            );
+        })
+    }
+}
@@ -0,0 +1,210 @@
+//! Where a Forth VM's output is written.
+//!
+//! By default, the output of each line a VM runs is written to its stdout,
+//! along with the `ok.` prompt. A VM can instead send its output to a SerMux
+//! port or to the display, using the `output!` word:
+//!
+//! - `PORT >port output!` writes output to SerMux port `PORT`, which is opened
+//!   by `output!`, and must not already be open.
+//! - `>display output!` draws output on the display, over whatever else is on
+//!   it.
+//! - `>stdout output!` writes output to stdout again.
+//!
+//! The `ok.` prompt and any errors are always written to stdout, so a script
+//! can log its results to one port while it is driven from another. Each VM
+//! has its own output, and VMs created with `spawn` start out writing to
+//! their own stdout.
+
+use embedded_graphics::{mono_font::MonoTextStyle, pixelcolor::BinaryColor, prelude::*};
+use input_mgr::RingLine;
+use mnemos_alloc::containers::Box;
+
+use crate::{
+    comms::bbq::SpscProducer,
+    services::{
+        emb_display::{fonts::FontSize, EmbDisplayClient, FrameLocSize, MonoChunk},
+        serial_mux::PortHandle,
+    },
+    Kernel,
+};
+
+/// The most rows of text that display output keeps.
+const MAX_ROWS: usize = 16;
+/// The most columns of text that display output keeps.
+const MAX_COLS: usize = 46;
+
+/// Where a VM's output is written.
+#[derive(Default)]
+pub(super) enum Output {
+    /// The VM's stdout.
+    #[default]
+    Stdio,
+    /// A SerMux port.
+    Port(PortHandle),
+    /// The display.
+    Display(Box<DisplayOutput>),
+}
+
+/// A VM's output, as selected by `output!`, and encoded on the stack by
+/// `>port`, `>display` and `>stdout`.
+///
+/// Port numbers are encoded as themselves, and everything else as a negative
+/// number.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Target {
+    Stdio,
+    Port(u16),
+    Display,
+}
+
+/// Output drawn on the display.
+pub(super) struct DisplayOutput {
+    display: EmbDisplayClient,
+    lines: RingLine<MAX_ROWS, MAX_COLS>,
+    chunk: Option<MonoChunk>,
+    font: FontSize,
+    /// The number of columns of text that fit on a line.
+    cols: usize,
+    /// The column of the next character of output.
+    col: usize,
+}
+
+// === impl Output ===
+
+impl Output {
+    /// Opens the output for `target`.
+    ///
+    /// Returns `None` if the target's port is already open, or the service
+    /// it needs is not running.
+    pub(super) async fn open(
+        kernel: &'static Kernel,
+        target: Target,
+        capacity: usize,
+    ) -> Option<Self> {
+        match target {
+            Target::Stdio => Some(Self::Stdio),
+            Target::Port(port) => PortHandle::open(kernel, port, capacity)
+                .await
+                .map(Self::Port),
+            Target::Display => {
+                let display = DisplayOutput::new(kernel).await?;
+                Some(Self::Display(Box::new(display).await))
+            }
+        }
+    }
+
+    /// Writes `bytes` to this output, or to `stdout` if this is
+    /// [`Output::Stdio`].
+    pub(super) async fn write(&mut self, stdout: &SpscProducer, bytes: &[u8]) {
+        match self {
+            Self::Stdio => stdout.write_all(bytes).await,
+            Self::Port(port) => port.send(bytes).await,
+            Self::Display(display) => display.write(bytes).await,
+        }
+    }
+}
+
+// === impl Target ===
+
+impl Target {
+    const STDIO: i32 = -1;
+    const DISPLAY: i32 = -2;
+
+    pub(super) fn from_word(word: i32) -> Option<Self> {
+        match word {
+            Self::STDIO => Some(Self::Stdio),
+            Self::DISPLAY => Some(Self::Display),
+            port => u16::try_from(port).ok().map(Self::Port),
+        }
+    }
+
+    pub(super) fn into_word(self) -> i32 {
+        match self {
+            Self::Stdio => Self::STDIO,
+            Self::Port(port) => port.into(),
+            Self::Display => Self::DISPLAY,
+        }
+    }
+}
+
+// === impl DisplayOutput ===
+
+impl DisplayOutput {
+    async fn new(kernel: &'static Kernel) -> Option<Self> {
+        let mut display = EmbDisplayClient::from_registry_no_retry(kernel)
+            .await
+            .ok()?;
+        let meta = display.get_meta().await.ok()?;
+        let font = FontSize::default();
+        let grid = font.metrics().grid(meta.width, meta.height);
+        // Leave out 4 columns for a margin of two characters on each side, as
+        // the graphical shell does.
+        let cols = (grid.cols as usize).saturating_sub(4).clamp(1, MAX_COLS);
+        let chunk = MonoChunk::allocate_mono(FrameLocSize {
+            offset_x: 0,
+            offset_y: 0,
+            width: meta.width,
+            height: meta.height,
+        })
+        .await;
+        Some(Self {
+            display,
+            lines: RingLine::new(),
+            chunk: Some(chunk),
+            font,
+            cols,
+            col: 0,
+        })
+    }
+
+    async fn write(&mut self, bytes: &[u8]) {
+        if bytes.is_empty() {
+            return;
+        }
+        for &b in bytes {
+            // wrap lines that are too long for the display.
+            if b == b'\n' || self.col >= self.cols {
+                self.lines.submit_remote_editing();
+                self.col = 0;
+            }
+            if b != b'\n' && b.is_ascii() && !b.is_ascii_control() {
+                let _ = self.lines.append_remote_char(b);
+                self.col += 1;
+            }
+        }
+
+        let Some(mut chunk) = self.chunk.take() else {
+            // a previous draw failed, so the display is gone.
+            return;
+        };
+        let style = ring_drawer::BwStyle {
+            background: BinaryColor::Off,
+            font: MonoTextStyle::new(self.font.font(), BinaryColor::On),
+        };
+        DrawTarget::clear(&mut chunk, BinaryColor::Off).unwrap();
+        ring_drawer::drawer_bw(&mut chunk, &self.lines, style).unwrap();
+        match self.display.draw_mono(chunk).await {
+            Ok(chunk) => self.chunk = Some(chunk),
+            Err(error) => tracing::warn!(?error, "Failed to draw Forth output"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn targets_roundtrip() {
+        for target in [
+            Target::Stdio,
+            Target::Display,
+            Target::Port(0),
+            Target::Port(u16::MAX),
+        ] {
+            assert_eq!(Target::from_word(target.into_word()), Some(target));
+        }
+        assert_eq!(Target::from_word(-3), None);
+        assert_eq!(Target::from_word(u16::MAX as i32 + 1), None);
+    }
+}
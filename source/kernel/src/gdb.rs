@@ -0,0 +1,670 @@
+//! # GDB Remote Serial Protocol Stub
+//!
+//! This module implements the target side of the [GDB Remote Serial
+//! Protocol][rsp], so that a debugger can be attached to a running kernel
+//! over a SerMux port, rather than over JTAG. `crowtty` forwards the
+//! [`WellKnown::Gdb`] port to TCP port 10006 on the host, by default, so GDB
+//! can be attached with:
+//!
+//! ```text
+//! (gdb) target remote :10006
+//! ```
+//!
+//! The protocol itself is handled by a [`Stub`], which is independent of how
+//! bytes are sent and received. Architecture-specific operations, such as
+//! reading registers and inserting breakpoints, are provided by the platform,
+//! as an implementation of the [`Target`] trait.
+//!
+//! The stub is used in two ways:
+//!
+//! - While the kernel is running, [`serve`] answers requests on the SerMux
+//!   port from an ordinary kernel task. In this mode, memory may be read and
+//!   written, and breakpoints may be inserted, but since the kernel is not
+//!   stopped, registers are reported as unavailable.
+//! - When a breakpoint is hit, the platform's exception handler may run a
+//!   [`Stub`] synchronously, talking directly to the UART, reporting the
+//!   registers at the time of the trap, until GDB continues execution.
+//!
+//! [rsp]: https://sourceware.org/gdb/current/onlinedocs/gdb.html/Remote-Protocol.html
+
+use core::fmt::{self, Write};
+
+use serde::{Deserialize, Serialize};
+
+pub use crate::services::serial_mux::WellKnown;
+use crate::{services::serial_mux::PortHandle, Kernel};
+
+/// The maximum size of a packet's payload, in bytes.
+///
+/// This is advertised to GDB as our `PacketSize`, so GDB will never send a
+/// larger packet.
+pub const MAX_PACKET_SIZE: usize = 1024;
+
+/// Architecture-specific operations required by a [`Stub`].
+pub trait Target: Sync {
+    /// Calls `f` with every register, in the order GDB expects them in a `g`
+    /// packet for this architecture.
+    fn read_registers(&self, f: &mut dyn FnMut(Register<'_>));
+
+    /// Reads `buf.len()` bytes of memory, starting at `addr`.
+    fn read_memory(&self, addr: usize, buf: &mut [u8]) -> Result<(), Error>;
+
+    /// Writes `data` to memory, starting at `addr`.
+    fn write_memory(&self, addr: usize, data: &[u8]) -> Result<(), Error>;
+
+    /// Inserts a software breakpoint at `addr`.
+    ///
+    /// `kind` is the architecture-specific breakpoint kind sent by GDB; usually
+    /// the size of the instruction to replace.
+    fn insert_breakpoint(&self, addr: usize, kind: usize) -> Result<(), Error> {
+        let _ = (addr, kind);
+        Err(Error::Unsupported)
+    }
+
+    /// Removes a software breakpoint inserted by
+    /// [`Target::insert_breakpoint`].
+    fn remove_breakpoint(&self, addr: usize, kind: usize) -> Result<(), Error> {
+        let _ = (addr, kind);
+        Err(Error::Unsupported)
+    }
+}
+
+/// The value of a register, as reported by [`Target::read_registers`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Register<'a> {
+    /// The register's value, in target byte order.
+    Value(&'a [u8]),
+    /// The register's value is not available. Contains the size of the
+    /// register, in bytes.
+    Unavailable(usize),
+}
+
+/// Errors returned by a [`Target`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The operation is not supported by this target.
+    Unsupported,
+    /// The memory range is not accessible.
+    BadAddress,
+    /// No more breakpoints may be inserted.
+    TooManyBreakpoints,
+}
+
+/// What the caller of [`Stub::handle_packet`] should do next.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Action {
+    /// Send the reply, and wait for the next packet.
+    Reply,
+    /// Resume execution. No reply should be sent until execution stops again.
+    Continue,
+    /// Send the reply, and then resume execution without the debugger.
+    Detach,
+}
+
+/// Events returned by [`PacketReader::push`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Event {
+    /// A complete packet was received, and may be read with
+    /// [`PacketReader::packet`]. A `+` acknowledgement should be sent.
+    Packet,
+    /// A packet was received with a bad checksum, or was too long. A `-`
+    /// should be sent, so GDB retransmits it.
+    Corrupted,
+    /// GDB requested that execution be interrupted (usually, because the user
+    /// pressed Ctrl-C).
+    Interrupt,
+}
+
+/// Reassembles packets from bytes received from GDB.
+pub struct PacketReader {
+    buf: heapless::Vec<u8, MAX_PACKET_SIZE>,
+    state: ReadState,
+    checksum: u8,
+    overflowed: bool,
+}
+
+/// Answers packets from GDB.
+pub struct Stub<'t> {
+    target: &'t dyn Target,
+    reply: heapless::Vec<u8, MAX_PACKET_SIZE>,
+    no_ack: bool,
+}
+
+/// GDB Stub Settings
+#[derive(Clone, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct GdbSettings {
+    /// Should the GDB stub be enabled? Defaults to `false`.
+    ///
+    /// The stub is only started if the platform also provides a
+    /// [`GdbSettings::target`].
+    #[serde(default)]
+    pub enabled: bool,
+    /// Port number. Defaults to [WellKnown::Gdb]
+    #[serde(default = "GdbSettings::default_port")]
+    pub port: u16,
+    /// Buffer size, in bytes. Defaults to 1024
+    #[serde(default = "GdbSettings::default_buffer_size")]
+    pub buffer_size: usize,
+    /// The platform's [`Target`] implementation.
+    #[serde(skip)]
+    pub target: Option<&'static dyn Target>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum ReadState {
+    /// Waiting for a `$`.
+    Idle,
+    /// Reading the packet's payload, until a `#`.
+    Payload,
+    /// Reading the first checksum digit.
+    Checksum1,
+    /// Reading the second checksum digit.
+    Checksum2(u8),
+}
+
+/// `errno` values used in `E` replies.
+const EFAULT: u8 = 14;
+const ENOSPC: u8 = 28;
+
+// === impl PacketReader ===
+
+impl PacketReader {
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            buf: heapless::Vec::new(),
+            state: ReadState::Idle,
+            checksum: 0,
+            overflowed: false,
+        }
+    }
+
+    /// Processes a byte received from GDB.
+    pub fn push(&mut self, byte: u8) -> Option<Event> {
+        match (self.state, byte) {
+            (ReadState::Idle, b'$') => {
+                self.buf.clear();
+                self.checksum = 0;
+                self.overflowed = false;
+                self.state = ReadState::Payload;
+            }
+            (ReadState::Idle, 0x03) => return Some(Event::Interrupt),
+            // Acks (`+` and `-`) from GDB, and line noise. We never
+            // retransmit, so acks are ignored.
+            (ReadState::Idle, _) => {}
+            (ReadState::Payload, b'#') => self.state = ReadState::Checksum1,
+            (ReadState::Payload, byte) => {
+                self.checksum = self.checksum.wrapping_add(byte);
+                self.overflowed |= self.buf.push(byte).is_err();
+            }
+            (ReadState::Checksum1, byte) => match hex_digit(byte) {
+                Some(hi) => self.state = ReadState::Checksum2(hi),
+                None => {
+                    self.state = ReadState::Idle;
+                    return Some(Event::Corrupted);
+                }
+            },
+            (ReadState::Checksum2(hi), byte) => {
+                self.state = ReadState::Idle;
+                return match hex_digit(byte) {
+                    Some(lo) if (hi << 4 | lo) == self.checksum && !self.overflowed => {
+                        Some(Event::Packet)
+                    }
+                    _ => Some(Event::Corrupted),
+                };
+            }
+        }
+        None
+    }
+
+    /// Returns the payload of the last packet received.
+    #[must_use]
+    pub fn packet(&self) -> &[u8] {
+        &self.buf
+    }
+}
+
+impl Default for PacketReader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// === impl Stub ===
+
+impl<'t> Stub<'t> {
+    #[must_use]
+    pub fn new(target: &'t dyn Target) -> Self {
+        Self {
+            target,
+            reply: heapless::Vec::new(),
+            no_ack: false,
+        }
+    }
+
+    /// Returns `true` if GDB has disabled acknowledgements, so `+` should not
+    /// be sent after each packet.
+    #[must_use]
+    pub fn no_ack(&self) -> bool {
+        self.no_ack
+    }
+
+    /// Handles a packet from GDB, preparing the reply returned by
+    /// [`Stub::reply`].
+    pub fn handle_packet(&mut self, packet: &[u8]) -> Action {
+        self.reply.clear();
+        let (&cmd, args) = match packet.split_first() {
+            Some(split) => split,
+            None => return Action::Reply,
+        };
+
+        match cmd {
+            // Why did we stop? Always because of a trap, as far as GDB is
+            // concerned.
+            b'?' => self.push_str("S05"),
+            b'g' => self.read_registers(),
+            b'm' => self.read_memory(args),
+            b'M' => self.write_memory(args),
+            b'Z' | b'z' => self.breakpoint(cmd == b'Z', args),
+            b'c' => return Action::Continue,
+            b'D' => {
+                self.push_str("OK");
+                return Action::Detach;
+            }
+            // Don't actually kill the kernel, just let it keep running.
+            b'k' => return Action::Detach,
+            // There is only one thread, so selecting one always succeeds.
+            b'H' | b'T' => self.push_str("OK"),
+            b'q' | b'Q' => self.query(packet),
+            // Anything else is unsupported, which is indicated by an empty
+            // reply.
+            _ => {}
+        }
+
+        Action::Reply
+    }
+
+    /// Returns the payload of the reply to the last packet.
+    #[must_use]
+    pub fn reply(&self) -> &[u8] {
+        &self.reply
+    }
+
+    /// Writes `payload` as a packet, calling `write` with the encoded bytes.
+    ///
+    /// Payloads must not contain `$`, `#`, or `}`, which is always true of the
+    /// replies generated by [`Stub::handle_packet`].
+    pub fn encode_packet(payload: &[u8], mut write: impl FnMut(&[u8])) {
+        let checksum = payload.iter().fold(0u8, |sum, &b| sum.wrapping_add(b));
+        write(b"$");
+        write(payload);
+        write(&[
+            b'#',
+            HEX[(checksum >> 4) as usize],
+            HEX[(checksum & 0xF) as usize],
+        ]);
+    }
+
+    fn query(&mut self, packet: &[u8]) {
+        if packet.starts_with(b"qSupported") {
+            let _ = write!(self, "PacketSize={MAX_PACKET_SIZE:x};QStartNoAckMode+");
+        } else if packet == b"QStartNoAckMode" {
+            self.no_ack = true;
+            self.push_str("OK");
+        } else if packet == b"qAttached" {
+            // We attached to an existing "process", so GDB should detach
+            // rather than kill it when it exits.
+            self.push_str("1");
+        } else if packet == b"qfThreadInfo" {
+            self.push_str("m1");
+        } else if packet == b"qsThreadInfo" {
+            self.push_str("l");
+        } else if packet == b"qC" {
+            self.push_str("QC1");
+        }
+    }
+
+    fn read_registers(&mut self) {
+        let mut full = false;
+        let Self { target, reply, .. } = self;
+        target.read_registers(&mut |reg| {
+            let (bytes, len) = match reg {
+                Register::Value(bytes) => (Some(bytes), bytes.len()),
+                Register::Unavailable(len) => (None, len),
+            };
+            for i in 0..len {
+                let hex = match bytes {
+                    Some(bytes) => hex_byte(bytes[i]),
+                    None => [b'x', b'x'],
+                };
+                full |= reply.extend_from_slice(&hex).is_err();
+            }
+        });
+        if full {
+            self.error(ENOSPC);
+        }
+    }
+
+    fn read_memory(&mut self, args: &[u8]) {
+        let Some((addr, len)) = parse_addr_len(args) else {
+            return self.error(EFAULT);
+        };
+        // Each byte takes two hex digits; GDB will ask for the rest if the
+        // read is short.
+        let len = len.min(MAX_PACKET_SIZE / 2);
+
+        let mut chunk = [0u8; 64];
+        let mut read = 0;
+        while read < len {
+            let n = (len - read).min(chunk.len());
+            if let Err(error) = self.target.read_memory(addr + read, &mut chunk[..n]) {
+                // If some memory was read, report it as a short read.
+                if read == 0 {
+                    self.target_error(error);
+                }
+                return;
+            }
+            for &byte in &chunk[..n] {
+                let _ = self.reply.extend_from_slice(&hex_byte(byte));
+            }
+            read += n;
+        }
+    }
+
+    fn write_memory(&mut self, args: &[u8]) {
+        let Some(colon) = args.iter().position(|&b| b == b':') else {
+            return self.error(EFAULT);
+        };
+        let (header, data) = (&args[..colon], &args[colon + 1..]);
+        let Some((addr, len)) = parse_addr_len(header) else {
+            return self.error(EFAULT);
+        };
+        if data.len() != len * 2 {
+            return self.error(EFAULT);
+        }
+
+        let mut chunk = [0u8; 64];
+        for (i, hex) in data.chunks(chunk.len() * 2).enumerate() {
+            let n = hex.len() / 2;
+            for (byte, digits) in chunk.iter_mut().zip(hex.chunks(2)) {
+                match (hex_digit(digits[0]), hex_digit(digits[1])) {
+                    (Some(hi), Some(lo)) => *byte = hi << 4 | lo,
+                    _ => return self.error(EFAULT),
+                }
+            }
+            let offset = i * chunk.len();
+            if let Err(error) = self.target.write_memory(addr + offset, &chunk[..n]) {
+                return self.target_error(error);
+            }
+        }
+        self.push_str("OK");
+    }
+
+    fn breakpoint(&mut self, insert: bool, args: &[u8]) {
+        // Only software breakpoints (type 0) are supported.
+        let Some(args) = args.strip_prefix(b"0,") else {
+            return;
+        };
+        let Some((addr, kind)) = parse_addr_len(args) else {
+            return self.error(EFAULT);
+        };
+        let res = if insert {
+            self.target.insert_breakpoint(addr, kind)
+        } else {
+            self.target.remove_breakpoint(addr, kind)
+        };
+        match res {
+            Ok(()) => self.push_str("OK"),
+            Err(error) => self.target_error(error),
+        }
+    }
+
+    fn target_error(&mut self, error: Error) {
+        match error {
+            // An empty reply tells GDB the request isn't supported.
+            Error::Unsupported => self.reply.clear(),
+            Error::BadAddress => self.error(EFAULT),
+            Error::TooManyBreakpoints => self.error(ENOSPC),
+        }
+    }
+
+    fn error(&mut self, errno: u8) {
+        self.reply.clear();
+        let _ = write!(self, "E{errno:02x}");
+    }
+
+    fn push_str(&mut self, s: &str) {
+        let _ = self.reply.extend_from_slice(s.as_bytes());
+    }
+}
+
+impl Write for Stub<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.reply
+            .extend_from_slice(s.as_bytes())
+            .map_err(|_| fmt::Error)
+    }
+}
+
+// === impl GdbSettings ===
+
+impl GdbSettings {
+    pub const DEFAULT_PORT: u16 = WellKnown::Gdb as u16;
+    pub const DEFAULT_BUFFER_SIZE: usize = 1024;
+
+    const fn default_port() -> u16 {
+        Self::DEFAULT_PORT
+    }
+    const fn default_buffer_size() -> usize {
+        Self::DEFAULT_BUFFER_SIZE
+    }
+}
+
+impl Default for GdbSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            port: Self::DEFAULT_PORT,
+            buffer_size: Self::DEFAULT_BUFFER_SIZE,
+            target: None,
+        }
+    }
+}
+
+impl fmt::Debug for GdbSettings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GdbSettings")
+            .field("enabled", &self.enabled)
+            .field("port", &self.port)
+            .field("buffer_size", &self.buffer_size)
+            .field("target", &self.target.is_some())
+            .finish()
+    }
+}
+
+/// Serves the GDB stub on a SerMux port, while the kernel is running.
+#[tracing::instrument(skip(kernel))]
+pub async fn serve(kernel: &'static Kernel, settings: GdbSettings) {
+    let GdbSettings {
+        port,
+        buffer_size,
+        target,
+        ..
+    } = settings;
+    let Some(target) = target else {
+        tracing::warn!("GDB stub enabled, but the platform does not provide a target!");
+        return;
+    };
+    tracing::debug!("Starting SerMux GDB stub...");
+    let Some(port) = PortHandle::open(kernel, port, buffer_size).await else {
+        tracing::error!(port, "Can't open the SerMux GDB stub port");
+        return;
+    };
+    tracing::info!("SerMux GDB stub running!");
+
+    let mut reader = PacketReader::new();
+    let mut stub = Stub::new(target);
+    let mut out = heapless::Vec::<u8, { MAX_PACKET_SIZE + 4 }>::new();
+    // Whether GDB thinks the target is running, and is waiting for a stop
+    // reply.
+    let mut running = false;
+    loop {
+        let rgr = port.consumer().read_grant().await;
+        let len = rgr.len();
+        for &byte in rgr.iter() {
+            match reader.push(byte) {
+                None => continue,
+                Some(Event::Corrupted) => {
+                    if !stub.no_ack() {
+                        port.send(b"-").await;
+                    }
+                    continue;
+                }
+                Some(Event::Interrupt) if running => {
+                    running = false;
+                    out.clear();
+                    Stub::encode_packet(b"S02", |b| {
+                        let _ = out.extend_from_slice(b);
+                    });
+                }
+                Some(Event::Interrupt) => continue,
+                Some(Event::Packet) => {
+                    if !stub.no_ack() {
+                        port.send(b"+").await;
+                    }
+                    let action = stub.handle_packet(reader.packet());
+                    tracing::trace!(
+                        packet = ?core::str::from_utf8(reader.packet()),
+                        ?action,
+                        "GDB packet"
+                    );
+                    if action == Action::Continue {
+                        // The kernel never stopped, so there's nothing to
+                        // resume. If a breakpoint is hit, the platform's
+                        // exception handler will send the stop reply.
+                        running = true;
+                        continue;
+                    }
+                    running = false;
+                    out.clear();
+                    Stub::encode_packet(stub.reply(), |b| {
+                        let _ = out.extend_from_slice(b);
+                    });
+                }
+            }
+            port.send(&out).await;
+        }
+        rgr.release(len);
+    }
+}
+
+// === helpers ===
+
+const HEX: &[u8; 16] = b"0123456789abcdef";
+
+fn hex_byte(byte: u8) -> [u8; 2] {
+    [HEX[(byte >> 4) as usize], HEX[(byte & 0xF) as usize]]
+}
+
+fn hex_digit(digit: u8) -> Option<u8> {
+    match digit {
+        b'0'..=b'9' => Some(digit - b'0'),
+        b'a'..=b'f' => Some(digit - b'a' + 10),
+        b'A'..=b'F' => Some(digit - b'A' + 10),
+        _ => None,
+    }
+}
+
+fn parse_hex(digits: &[u8]) -> Option<usize> {
+    if digits.is_empty() {
+        return None;
+    }
+    digits.iter().try_fold(0usize, |acc, &d| {
+        acc.checked_mul(16)?.checked_add(hex_digit(d)? as usize)
+    })
+}
+
+/// Parses an `addr,length` pair.
+fn parse_addr_len(args: &[u8]) -> Option<(usize, usize)> {
+    let comma = args.iter().position(|&b| b == b',')?;
+    Some((parse_hex(&args[..comma])?, parse_hex(&args[comma + 1..])?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    struct MockTarget {
+        mem: Mutex<[u8; 16]>,
+    }
+
+    impl Target for MockTarget {
+        fn read_registers(&self, f: &mut dyn FnMut(Register<'_>)) {
+            f(Register::Value(&[0xef, 0xbe]));
+            f(Register::Unavailable(2));
+        }
+
+        fn read_memory(&self, addr: usize, buf: &mut [u8]) -> Result<(), Error> {
+            let mem = self.mem.lock().unwrap();
+            let src = mem.get(addr..addr + buf.len()).ok_or(Error::BadAddress)?;
+            buf.copy_from_slice(src);
+            Ok(())
+        }
+
+        fn write_memory(&self, addr: usize, data: &[u8]) -> Result<(), Error> {
+            let mut mem = self.mem.lock().unwrap();
+            let dst = mem
+                .get_mut(addr..addr + data.len())
+                .ok_or(Error::BadAddress)?;
+            dst.copy_from_slice(data);
+            Ok(())
+        }
+    }
+
+    fn packet(reader: &mut PacketReader, bytes: &[u8]) -> Option<Event> {
+        bytes.iter().filter_map(|&b| reader.push(b)).last()
+    }
+
+    #[test]
+    fn reads_packets() {
+        let mut reader = PacketReader::new();
+        assert_eq!(packet(&mut reader, b"+$g#67"), Some(Event::Packet));
+        assert_eq!(reader.packet(), b"g");
+        assert_eq!(packet(&mut reader, b"$m0,4#fd"), Some(Event::Packet));
+        assert_eq!(reader.packet(), b"m0,4");
+        assert_eq!(packet(&mut reader, b"$g#00"), Some(Event::Corrupted));
+        assert_eq!(packet(&mut reader, b"\x03"), Some(Event::Interrupt));
+    }
+
+    #[test]
+    fn encodes_packets() {
+        let mut out = std::vec::Vec::new();
+        Stub::encode_packet(b"OK", |b| out.extend_from_slice(b));
+        assert_eq!(out, b"$OK#9a");
+    }
+
+    #[test]
+    fn memory_and_registers() {
+        let target = MockTarget {
+            mem: Mutex::new([0; 16]),
+        };
+        let mut stub = Stub::new(&target);
+
+        assert_eq!(stub.handle_packet(b"M4,2:abcd"), Action::Reply);
+        assert_eq!(stub.reply(), b"OK");
+        stub.handle_packet(b"m3,4");
+        assert_eq!(stub.reply(), b"00abcd00");
+        stub.handle_packet(b"m20,1");
+        assert_eq!(stub.reply(), b"E0e");
+
+        stub.handle_packet(b"g");
+        assert_eq!(stub.reply(), b"efbexxxx");
+
+        // The mock target doesn't support breakpoints.
+        stub.handle_packet(b"Z0,4,2");
+        assert_eq!(stub.reply(), b"");
+        assert_eq!(stub.handle_packet(b"c"), Action::Continue);
+    }
+}
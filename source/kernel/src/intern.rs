@@ -0,0 +1,302 @@
+//! # Interned Names
+//!
+//! Tasks, services, SerMux ports, and trace metadata are all identified by
+//! human-readable names, and the same name is often stored in many places at
+//! once: in the thing it names, in statistics about that thing, and in every
+//! debug dump that mentions it. Rather than each of these owning its own copy
+//! of the name, names can be *interned* in the kernel's [`Interner`], which
+//! stores a single copy of each distinct name for the lifetime of the kernel,
+//! and returns a [`Name`] handle referring to it.
+//!
+//! A [`Name`] is the size of a `&'static str`, and is [`Copy`]. Since the
+//! interner never stores the same name twice, two [`Name`]s are equal if and
+//! only if they refer to the same copy, so comparing them compares two
+//! pointers, rather than two strings. This makes it cheap for the statistics
+//! and debugging services to match up names from different sources.
+//!
+//! Interned names are never freed, so only names drawn from a small, bounded
+//! set (such as the names of services and tasks) should be interned. Names
+//! built from arbitrary user input, or which change over time, should not be.
+//!
+//! The kernel's interner is accessed through
+//! [`Kernel::names`](crate::Kernel::names), or names may be interned directly
+//! with [`Kernel::intern`](crate::Kernel::intern).
+
+use core::{fmt, hash, ops::Deref, ptr, slice, str};
+
+use maitake::sync::{blocking::Mutex, spin::Spinlock};
+use mnemos_alloc::containers::{Box, HeapArray};
+use portable_atomic::{AtomicUsize, Ordering};
+
+/// The number of hash buckets in an [`Interner`].
+///
+/// Buckets are linked lists, so the interner has no maximum capacity, but
+/// lookups get slower once there are many more names than buckets.
+pub const BUCKETS: usize = 64;
+
+/// A set of interned names.
+///
+/// See the [module-level documentation](self) for details.
+pub struct Interner {
+    buckets: Mutex<[Option<&'static Entry>; BUCKETS], Spinlock>,
+    names: AtomicUsize,
+    bytes: AtomicUsize,
+}
+
+/// A handle to an interned name.
+///
+/// Two `Name`s are equal if and only if they were returned by the same
+/// [`Interner`] for the same string, which is checked by comparing pointers.
+/// A `Name` also dereferences to its [`str`], and may be compared with one
+/// directly.
+#[derive(Clone, Copy)]
+pub struct Name(&'static str);
+
+/// Statistics about an [`Interner`], returned by [`Interner::stats`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InternerStats {
+    /// The number of distinct names that have been interned.
+    pub names: usize,
+    /// The number of bytes of names which were copied onto the heap.
+    ///
+    /// This does not include names interned with
+    /// [`Interner::intern_static`], which are not copied.
+    pub bytes: usize,
+}
+
+struct Entry {
+    name: &'static str,
+    next: Option<&'static Entry>,
+    /// The heap copy that `name` points to, if it was not already `'static`.
+    ///
+    /// Entries are leaked once they are added to a bucket, so this is never
+    /// freed while `name` is in use.
+    _storage: Option<HeapArray<u8>>,
+}
+
+// === impl Interner ===
+
+impl Interner {
+    pub(crate) const fn new() -> Self {
+        const EMPTY: Option<&'static Entry> = None;
+        Self {
+            buckets: Mutex::new_with_raw_mutex([EMPTY; BUCKETS], Spinlock::new()),
+            names: AtomicUsize::new(0),
+            bytes: AtomicUsize::new(0),
+        }
+    }
+
+    /// Returns the [`Name`] for `name`, if it has already been interned.
+    ///
+    /// This never allocates.
+    #[must_use]
+    pub fn get(&self, name: &str) -> Option<Name> {
+        let buckets = self.buckets.lock();
+        find(buckets[bucket(name)], name)
+    }
+
+    /// Interns `name`, copying it onto the heap if it has not already been
+    /// interned.
+    pub async fn intern(&self, name: &str) -> Name {
+        if let Some(interned) = self.get(name) {
+            return interned;
+        }
+        // `HeapArray` can't be empty, and there's nothing to copy anyway.
+        if name.is_empty() {
+            return self.intern_static("").await;
+        }
+
+        let mut storage = HeapArray::new(name.len(), 0u8).await;
+        storage.copy_from_slice(name.as_bytes());
+        // Safety: the bytes were copied from a `str`, so they are valid
+        // UTF-8. `HeapArray` never moves its buffer, and the buffer lives as
+        // long as the entry, which is leaked before the name is handed out.
+        let copy = unsafe {
+            str::from_utf8_unchecked(slice::from_raw_parts(storage.as_ptr(), storage.len()))
+        };
+        let entry = Box::new(Entry {
+            name: copy,
+            next: None,
+            _storage: Some(storage),
+        })
+        .await;
+        self.insert(entry, name.len())
+    }
+
+    /// Interns a `'static` name.
+    ///
+    /// Unlike [`Interner::intern`], this does not copy `name` if it has not
+    /// already been interned, so it only allocates space to keep track of it.
+    pub async fn intern_static(&self, name: &'static str) -> Name {
+        if let Some(interned) = self.get(name) {
+            return interned;
+        }
+
+        let entry = Box::new(Entry {
+            name,
+            next: None,
+            _storage: None,
+        })
+        .await;
+        self.insert(entry, 0)
+    }
+
+    /// Returns statistics about the names interned so far.
+    #[must_use]
+    pub fn stats(&self) -> InternerStats {
+        InternerStats {
+            names: self.names.load(Ordering::Relaxed),
+            bytes: self.bytes.load(Ordering::Relaxed),
+        }
+    }
+
+    fn insert(&self, mut entry: Box<Entry>, copied: usize) -> Name {
+        let mut buckets = self.buckets.lock();
+        let head = &mut buckets[bucket(entry.name)];
+        // Another task may have interned the same name while we were
+        // allocating. If so, the new entry is dropped, and theirs is used.
+        if let Some(interned) = find(*head, entry.name) {
+            return interned;
+        }
+
+        entry.next = *head;
+        // Entries are never removed, so they can be leaked.
+        let entry: &'static Entry = unsafe { &*Box::into_raw(entry) };
+        *head = Some(entry);
+        self.names.fetch_add(1, Ordering::Relaxed);
+        self.bytes.fetch_add(copied, Ordering::Relaxed);
+        Name(entry.name)
+    }
+}
+
+impl fmt::Debug for Interner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let InternerStats { names, bytes } = self.stats();
+        f.debug_struct("Interner")
+            .field("names", &names)
+            .field("bytes", &bytes)
+            .finish()
+    }
+}
+
+fn find(mut entry: Option<&'static Entry>, name: &str) -> Option<Name> {
+    while let Some(e) = entry {
+        if e.name == name {
+            return Some(Name(e.name));
+        }
+        entry = e.next;
+    }
+    None
+}
+
+/// Returns the bucket for `name`, using the 32-bit FNV-1a hash.
+fn bucket(name: &str) -> usize {
+    let hash = name.bytes().fold(0x811c_9dc5_u32, |hash, byte| {
+        (hash ^ byte as u32).wrapping_mul(0x0100_0193)
+    });
+    hash as usize % BUCKETS
+}
+
+// === impl Name ===
+
+impl Name {
+    /// Returns the interned string.
+    #[inline]
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        self.0
+    }
+}
+
+impl Deref for Name {
+    type Target = str;
+
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        self.0
+    }
+}
+
+impl AsRef<str> for Name {
+    #[inline]
+    fn as_ref(&self) -> &str {
+        self.0
+    }
+}
+
+impl PartialEq for Name {
+    #[inline]
+    fn eq(&self, other: &Self) -> bool {
+        ptr::eq(self.0, other.0)
+    }
+}
+
+impl Eq for Name {}
+
+impl PartialEq<str> for Name {
+    fn eq(&self, other: &str) -> bool {
+        self.0 == other
+    }
+}
+
+impl PartialEq<&str> for Name {
+    fn eq(&self, other: &&str) -> bool {
+        self.0 == *other
+    }
+}
+
+impl hash::Hash for Name {
+    fn hash<H: hash::Hasher>(&self, state: &mut H) {
+        // Consistent with `PartialEq`, which compares pointers.
+        ptr::hash(self.0, state)
+    }
+}
+
+impl fmt::Debug for Name {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.0, f)
+    }
+}
+
+impl fmt::Display for Name {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self.0, f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TestKernel;
+
+    #[test]
+    fn interns_each_name_once() {
+        TestKernel::run(|k| async move {
+            let names = k.names();
+            let before = names.stats();
+
+            let owned = std::string::String::from("test-interner-keyboard");
+            let keyboard = k.intern(&owned).await;
+            drop(owned);
+            assert_eq!(keyboard, "test-interner-keyboard");
+            assert_eq!(k.intern("test-interner-keyboard").await, keyboard);
+            assert_eq!(
+                names.intern_static("test-interner-keyboard").await,
+                keyboard
+            );
+            assert_eq!(names.get("test-interner-keyboard"), Some(keyboard));
+
+            let display = names.intern_static("test-interner-display").await;
+            assert_ne!(display, keyboard);
+            assert_eq!(names.get("test-interner-nope"), None);
+
+            assert_eq!(
+                names.stats(),
+                InternerStats {
+                    names: before.names + 2,
+                    bytes: before.bytes + "test-interner-keyboard".len(),
+                }
+            );
+        })
+    }
+}
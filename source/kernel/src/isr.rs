@@ -1,9 +1,85 @@
+//! Interrupt service routines (ISRs).
+//!
+//! This module contains the kernel's platform-agnostic interface to interrupt
+//! handling:
+//!
+//! - [`Isr`] tracks whether the CPU is currently running an interrupt
+//!   handler, and how deeply interrupt handlers are nested.
+//! - [`IsrConfig`] describes the requirements of a driver's interrupt handler:
+//!   its [`Priority`] relative to other interrupts, and whether it may be
+//!   preempted by higher-priority interrupts ([`Nesting`]). Each platform maps
+//!   an `IsrConfig` onto its own interrupt controller.
+//!
+//! # Nested Interrupts
+//!
+//! By default, interrupt handlers run with all other interrupts masked, so a
+//! handler never runs concurrently with another handler. A handler which is
+//! configured with [`Nesting::Allowed`] may instead be preempted by any
+//! interrupt with a strictly higher [`Priority`] (but never by itself, or by
+//! interrupts of the same or lower priority). Only allow nesting for handlers
+//! which share no state with higher-priority handlers, other than through
+//! atomics, wakers, and the other ISR-safe parts of the kernel.
+//!
+//! The parts of the kernel which may be used from an ISR are safe to use from
+//! nested ISRs:
+//!
+//! - waking tasks, and [deferred work](crate::deferred), only use atomics, and
+//! - [serial tracing](crate::serial_trace) switches to a separate trace buffer
+//!   while in an ISR. A nested ISR which preempts another ISR while it is
+//!   writing a trace event can't get a write grant on that buffer, so the
+//!   nested ISR's events are dropped (and counted as dropped), rather than
+//!   corrupting the buffer.
 use portable_atomic::{AtomicU8, Ordering};
 
 static IN_ISR: AtomicU8 = AtomicU8::new(0);
 
 pub struct Isr(());
 
+/// The requirements of an interrupt handler, which platforms map onto their
+/// interrupt controllers.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct IsrConfig {
+    /// How urgently the interrupt should be handled.
+    pub priority: Priority,
+    /// Whether the handler may be preempted by higher-priority interrupts.
+    pub nesting: Nesting,
+}
+
+/// The priority of an interrupt, relative to other interrupts.
+///
+/// When several interrupts are pending at once, higher-priority interrupts
+/// are handled first. If a handler allows [nesting](Nesting::Allowed),
+/// higher-priority interrupts may also preempt it.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    /// For interrupts which can tolerate being delayed by any other interrupt,
+    /// such as the completion of bulk transfers to storage.
+    Low,
+    /// The default priority.
+    #[default]
+    Normal,
+    /// For latency-sensitive interrupts, such as those which must be handled
+    /// before a hardware FIFO overflows.
+    High,
+    /// For interrupts which must never be delayed by anything else, such as
+    /// the kernel's clock.
+    Critical,
+}
+
+/// Whether an interrupt handler may be preempted by higher-priority
+/// interrupts.
+///
+/// See [the module-level documentation](self#nested-interrupts) for details.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash)]
+pub enum Nesting {
+    /// The handler runs with all other interrupts masked.
+    #[default]
+    Masked,
+    /// The handler may be preempted by interrupts with a higher
+    /// [`Priority`].
+    Allowed,
+}
+
 impl Drop for Isr {
     fn drop(&mut self) {
         IN_ISR.fetch_sub(1, Ordering::Release);
@@ -14,7 +90,8 @@ impl Isr {
     /// Enter an interrupt service routine (ISR) context.
     ///
     /// When the returned guard is dropped, the system is no longer considered
-    /// to be inside an ISR.
+    /// to be inside an ISR. ISR contexts may be nested: the system is inside
+    /// an ISR until every guard has been dropped.
     #[must_use]
     #[inline]
     pub fn enter() -> Self {
@@ -34,3 +111,40 @@ impl Isr {
         IN_ISR.load(Ordering::Acquire)
     }
 }
+
+// === impl IsrConfig ===
+
+impl IsrConfig {
+    /// An interrupt with [`Priority::Normal`] which is not nesting-safe.
+    pub const DEFAULT: Self = Self {
+        priority: Priority::Normal,
+        nesting: Nesting::Masked,
+    };
+
+    /// Returns a configuration for a handler with the given `priority`, which
+    /// is not nesting-safe.
+    #[must_use]
+    pub const fn new(priority: Priority) -> Self {
+        Self {
+            priority,
+            nesting: Nesting::Masked,
+        }
+    }
+
+    /// Returns this configuration, with the handler declared as safe to be
+    /// preempted by higher-priority interrupts.
+    #[must_use]
+    pub const fn nesting_safe(self) -> Self {
+        Self {
+            nesting: Nesting::Allowed,
+            ..self
+        }
+    }
+
+    /// Returns `true` if the handler may be preempted by higher-priority
+    /// interrupts.
+    #[must_use]
+    pub const fn allows_nesting(&self) -> bool {
+        matches!(self.nesting, Nesting::Allowed)
+    }
+}
@@ -47,18 +47,30 @@
 //!
 //! * The allocator frees any synchronously dropped allocations, making them available for
 //!   asynchronous allocation
+//! * Userspace processes' rings are polled, up to a per-tick budget
 //! * The async executor is polled
+//! * If no tasks are left ready to run, the background executor is polled
 //!
 //! AT THE MOMENT, there is no indication of whether all tasks are blocked, which could be use to
 //! inform whether we should put the CPU into some kind of sleep mode until a hardware event (like
 //! a timer or DMA transaction) is triggered, and an async task has potentially been awoken.
 //!
+//! ## Background tasks
+//!
+//! Maintenance work which is never urgent, like scanning a bus or aggregating
+//! statistics, can be spawned with [`Kernel::spawn_background()`] instead.
+//! Background tasks run on a separate executor, which is only polled when a
+//! tick leaves no other tasks ready to run, so they never add latency to
+//! interactive tasks such as keyboard input and drawing to the display. On a
+//! busy system, background tasks may not run for a long time, so they must not
+//! be used for anything that other tasks wait on.
+//!
 //! ## Not covered: "userspace"
 //!
 //! At the moment, there is SOME concept of a userspace, which interacts with the kernel via a
-//! bidirectional IPC ringbuffer. Space for this ringbuffer is allocated when calling
-//! [`Kernel::new()`]. Additionally this ringbuffer is polled on each call to `tick`, after freeing
-//! allocations and before calling `tick` on the scheduler.
+//! bidirectional IPC ringbuffer per process. These ringbuffers are registered with the kernel's
+//! [`RingPoller`](rings::RingPoller), and are polled on each call to `tick`, before calling `tick`
+//! on the scheduler. See the [`rings`] module for details.
 //!
 //! This is an artifact of how mnemos 0.1 worked, where there was a single userspace executor that
 //! existed and interacted with the kernel executor.
@@ -73,44 +85,76 @@
 
 extern crate alloc;
 
+pub mod boot;
+pub mod bundle;
 pub mod comms;
 pub mod daemons;
+pub mod deferred;
+mod error;
 pub(crate) mod fmt;
 pub mod forth;
+pub mod gdb;
+pub mod intern;
 pub mod isr;
+pub mod loader;
 pub mod registry;
 pub mod retry;
+pub mod rings;
 #[cfg(feature = "serial-trace")]
 pub mod serial_trace;
 pub mod services;
+pub mod shutdown;
+pub mod task_local;
+pub mod timer_stats;
+pub mod units;
+pub mod watchdog;
 
 #[cfg(test)]
 pub(crate) mod test_util;
 
 use core::{convert::identity, future::Future, ptr::NonNull};
 
+pub use self::error::Error;
+
 use abi::{
     bbqueue_ipc::BBBuffer,
     syscall::{KernelResponse, UserRequest},
 };
+use boot::BootProgress;
+use comms::events::EventBus;
 use comms::kchannel::KChannel;
+use deferred::DeferredWork;
 pub use embedded_hal_async;
+use intern::{Interner, Name};
 pub use maitake;
 use maitake::{
     scheduler::LocalScheduler,
     task::{BoxStorage, JoinHandle, Storage},
-    time::{Duration, Sleep, Timeout, Timer},
+    time::{timer::Turn, Duration, Instant, Sleep, Timeout, Timer},
 };
 pub use mnemos_alloc;
 use mnemos_alloc::containers::Box;
+use portable_atomic::{AtomicU64, AtomicUsize, Ordering};
 use registry::Registry;
+use rings::{RingPoller, RingSettings};
 use serde::{Deserialize, Serialize};
 use services::{
+    alarm::{AlarmServer, AlarmSettings},
+    buffer_pool::{BufferPoolServer, BufferPoolSettings},
+    clipboard::{ClipboardServer, ClipboardSettings},
+    device_identity::DeviceIdentitySettings,
     forth_spawnulator::{SpawnulatorServer, SpawnulatorSettings},
     keyboard::mux::{KeyboardMuxServer, KeyboardMuxSettings},
+    notification::{NotificationServer, NotificationSettings},
+    pointer::mux::{PointerMuxServer, PointerMuxSettings},
+    serial_console::{SerialConsoleServer, SerialConsoleSettings},
     serial_mux::{SerialMuxServer, SerialMuxSettings},
 };
+use shutdown::ShutdownHooks;
+use timer_stats::{TimerCounters, TimerStats};
 pub use tracing;
+use units::KernelDuration;
+use watchdog::{Watchdog, WatchdogSettings, YieldNow};
 
 pub struct Rings {
     pub u2k: NonNull<BBBuffer>,
@@ -133,6 +177,10 @@ pub struct Kernel {
     inner: KernelInner,
     /// The run-time driver registry.
     registry: Registry,
+    /// Progress of named boot steps.
+    boot: BootProgress,
+    /// Hooks to run when the kernel shuts down.
+    shutdown: ShutdownHooks,
 }
 
 unsafe impl Sync for Kernel {}
@@ -142,8 +190,69 @@ pub struct KernelInner {
     /// `maitake` scheduler capable of running `!Send` futures.
     scheduler: LocalScheduler,
 
+    /// Scheduler for background tasks, which is only ticked when `scheduler`
+    /// has no tasks ready to run.
+    background: LocalScheduler,
+
     /// Maitake timer wheel.
     timer: Timer,
+
+    /// Diagnostic counters for turns of the timer wheel.
+    timer_counters: TimerCounters,
+
+    /// The length of a tick of `timer`.
+    tick_duration: Duration,
+
+    /// The number of tasks spawned on the scheduler.
+    tasks_spawned: AtomicUsize,
+
+    /// The number of tasks the scheduler has run to completion.
+    tasks_completed: AtomicUsize,
+
+    /// The number of times the scheduler has been ticked.
+    ticks: AtomicUsize,
+
+    /// When the kernel was created.
+    booted_at: Instant,
+
+    /// The wall-clock time when the kernel was created, in microseconds since
+    /// the Unix epoch, or 0 if the wall-clock time is not known.
+    booted_at_unix_us: AtomicU64,
+
+    /// Records tasks which run for too long without yielding.
+    watchdog: Watchdog,
+
+    /// Work queued by ISRs and other non-async code, run on each tick.
+    deferred: DeferredWork,
+
+    /// Delivers system events to subscribed tasks.
+    events: EventBus,
+
+    /// Polls userspace processes' rings on each tick.
+    rings: RingPoller,
+
+    /// Interned names of tasks, services, and other kernel objects.
+    names: Interner,
+}
+
+/// Counts of tasks spawned on, and completed by, the kernel's scheduler.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TaskCounts {
+    /// The number of tasks spawned since the kernel was created.
+    pub spawned: usize,
+    /// The number of tasks that have run to completion.
+    pub completed: usize,
+}
+
+/// An error returned by [`Kernel::try_initialize_default_services`] or
+/// [`BootProgress::failures`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InitializeError {
+    /// The name of the service, daemon, or boot step that could not be
+    /// initialized.
+    pub name: &'static str,
+    /// Why it could not be initialized.
+    pub reason: Error,
 }
 
 /// Settings for all services spawned by default.
@@ -154,8 +263,48 @@ pub struct KernelServiceSettings {
     pub spawnulator: SpawnulatorSettings,
     pub sermux_loopback: daemons::sermux::LoopbackSettings,
     pub sermux_hello: daemons::sermux::HelloSettings,
+    #[serde(default)]
+    pub sermux_debug: daemons::sermux::DebugAgentSettings,
+    #[serde(default)]
+    pub sermux_gdb: gdb::GdbSettings,
+    #[serde(default)]
+    pub sermux_clipboard: daemons::sermux::ClipboardBridgeSettings,
+    #[serde(default)]
+    pub screenshot: daemons::screenshot::ScreenshotSettings,
     #[cfg(feature = "serial-trace")]
     pub sermux_trace: serial_trace::SerialTraceSettings,
+    #[serde(default)]
+    pub buffer_pool: BufferPoolSettings,
+    #[serde(default)]
+    pub clipboard: ClipboardSettings,
+    #[serde(default)]
+    pub alarm: AlarmSettings,
+    #[serde(default)]
+    pub notification: NotificationSettings,
+    #[serde(default)]
+    pub watchdog: WatchdogSettings,
+    #[serde(default)]
+    pub i2c_scan: daemons::i2c_scan::I2cScanSettings,
+    #[serde(default)]
+    pub pointer_mux: PointerMuxSettings,
+    #[serde(default)]
+    pub soft_keyboard: daemons::soft_keyboard::SoftKeyboardSettings,
+    #[serde(default)]
+    pub rings: RingSettings,
+    #[serde(default)]
+    pub request_queues: registry::RequestQueueSettings,
+    /// Settings for the [`DeviceIdentityServer`], which is registered by the
+    /// platform implementation, as only it can read the device's ID.
+    ///
+    /// [`DeviceIdentityServer`]: services::device_identity::DeviceIdentityServer
+    #[serde(default)]
+    pub device_identity: DeviceIdentitySettings,
+    /// Settings for the [`SerialConsoleServer`], which is registered in place
+    /// of the serial mux when [`SerialMuxSettings::enabled`] is `false`.
+    #[serde(default)]
+    pub serial_console: SerialConsoleSettings,
+    #[serde(default)]
+    pub memory_budgets: daemons::mem_budget::MemoryBudgetSettings,
 }
 
 impl Kernel {
@@ -172,18 +321,41 @@ impl Kernel {
     pub unsafe fn new(
         settings: KernelSettings,
         clock: maitake::time::Clock,
-    ) -> Result<Box<Self>, &'static str> {
+    ) -> Result<Box<Self>, Error> {
         let registry = registry::Registry::new(settings.max_drivers);
 
         let scheduler = LocalScheduler::new();
+        let background = LocalScheduler::new();
+
+        let tick_duration = clock.tick_duration();
+        let timer = Timer::new(clock);
+        let booted_at = timer.now();
 
         let inner = KernelInner {
             scheduler,
-            timer: Timer::new(clock),
+            background,
+            timer,
+            timer_counters: TimerCounters::new(),
+            tick_duration,
+            tasks_spawned: AtomicUsize::new(0),
+            tasks_completed: AtomicUsize::new(0),
+            ticks: AtomicUsize::new(0),
+            booted_at,
+            booted_at_unix_us: AtomicU64::new(0),
+            watchdog: Watchdog::new(),
+            deferred: DeferredWork::new(),
+            events: EventBus::new(),
+            rings: RingPoller::new(),
+            names: Interner::new(),
         };
 
-        let new_kernel =
-            Box::try_new(Kernel { inner, registry }).map_err(|_| "Kernel allocation failed.")?;
+        let new_kernel = Box::try_new(Kernel {
+            inner,
+            registry,
+            boot: BootProgress::new(),
+            shutdown: ShutdownHooks::new(),
+        })
+        .map_err(|_| Error::Alloc)?;
 
         Ok(new_kernel)
     }
@@ -200,24 +372,181 @@ impl Kernel {
 
     pub fn tick(&'static self) -> maitake::scheduler::Tick {
         let inner = self.inner();
-        inner.scheduler.tick()
+        inner.deferred.run();
+        inner.rings.poll();
+        let mut tick = inner.scheduler.tick();
+        if !tick.has_remaining {
+            // background tasks only run when no other tasks are ready.
+            let background = inner.background.tick();
+            tick.polled += background.polled;
+            tick.completed += background.completed;
+            tick.spawned += background.spawned;
+            // a background task may have woken a foreground task, which won't
+            // run until the next tick, so keep ticking after any background
+            // task was polled.
+            tick.has_remaining = background.has_remaining || background.polled > 0;
+        }
+        inner
+            .tasks_completed
+            .fetch_add(tick.completed, Ordering::Relaxed);
+        inner.ticks.fetch_add(1, Ordering::Relaxed);
+        tick
         // TODO: Send time to userspace?
     }
 
+    /// Returns the number of tasks that have been spawned on, and completed
+    /// by, the kernel's scheduler.
+    #[must_use]
+    pub fn task_counts(&'static self) -> TaskCounts {
+        let inner = self.inner();
+        TaskCounts {
+            spawned: inner.tasks_spawned.load(Ordering::Relaxed),
+            completed: inner.tasks_completed.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Returns the number of times the kernel's scheduler has been ticked.
+    #[must_use]
+    pub fn tick_count(&'static self) -> usize {
+        self.inner().ticks.load(Ordering::Relaxed)
+    }
+
+    /// Turns the kernel's timer wheel, advancing it to the current time, and
+    /// records the turn in the kernel's [`TimerStats`].
+    ///
+    /// Platform run loops should use this rather than turning
+    /// [`Kernel::timer`] directly.
+    pub fn turn_timer(&'static self) -> Turn {
+        let inner = self.inner();
+        let turn = inner.timer.turn();
+        inner.timer_counters.record_turn(&turn, self.uptime());
+        turn
+    }
+
+    /// Returns how long a run loop should sleep for after `turn`: until its
+    /// next deadline, or for `fallback` if it did not report one.
+    ///
+    /// Using the fallback is counted in the kernel's [`TimerStats`], and
+    /// recorded with a `DEBUG` event.
+    ///
+    /// The returned [`KernelDuration`] is measured in ticks of the kernel's
+    /// timer, which may be converted to ticks of the platform's hardware
+    /// timers with [`KernelDuration::to_ticks`].
+    #[must_use]
+    pub fn ticks_to_next_deadline(
+        &'static self,
+        turn: &Turn,
+        fallback: Duration,
+    ) -> KernelDuration {
+        let tick_duration = self.inner().tick_duration;
+        match turn.ticks_to_next_deadline() {
+            Some(ticks) => KernelDuration::from_ticks(ticks, tick_duration),
+            None => {
+                let fallback = KernelDuration::from_duration_saturating(fallback, tick_duration);
+                self.inner()
+                    .timer_counters
+                    .record_fallback(fallback.ticks());
+                fallback
+            }
+        }
+    }
+
+    /// Returns counters describing how the kernel's timer wheel has been
+    /// turned. See the [`timer_stats`] module for details.
+    #[must_use]
+    pub fn timer_stats(&'static self) -> TimerStats {
+        self.inner().timer_counters.stats()
+    }
+
+    /// Returns the time elapsed since the kernel was created.
+    #[must_use]
+    pub fn uptime(&'static self) -> Duration {
+        let inner = self.inner();
+        inner.timer.now().duration_since(inner.booted_at)
+    }
+
+    /// Sets the current wall-clock time, as the time since the Unix epoch
+    /// (UTC).
+    ///
+    /// This is typically called by an RTC driver, or a service that otherwise
+    /// learns the time, such as over the network. Until it is called,
+    /// [`Kernel::wall_clock`] returns `None`.
+    pub fn set_wall_clock(&'static self, now: Duration) {
+        let booted_at = now.saturating_sub(self.uptime());
+        self.inner()
+            .booted_at_unix_us
+            .store(booted_at.as_micros() as u64, Ordering::Release);
+    }
+
+    /// Returns the current wall-clock time, as the time since the Unix epoch
+    /// (UTC), or `None` if it has not been set by [`Kernel::set_wall_clock`].
+    #[must_use]
+    pub fn wall_clock(&'static self) -> Option<Duration> {
+        match self.inner().booted_at_unix_us.load(Ordering::Acquire) {
+            0 => None,
+            us => Some(Duration::from_micros(us) + self.uptime()),
+        }
+    }
+
+    /// Returns the kernel's starvation [`Watchdog`].
+    #[inline]
+    #[must_use]
+    pub fn watchdog(&'static self) -> &'static Watchdog {
+        &self.inner.watchdog
+    }
+
+    /// Returns the kernel's [`DeferredWork`] queue, which runs work queued by
+    /// interrupt handlers and other non-async code on each tick.
+    #[inline]
+    #[must_use]
+    pub fn deferred(&'static self) -> &'static DeferredWork {
+        &self.inner.deferred
+    }
+
+    /// Returns the kernel's [`RingPoller`], which polls the rings of
+    /// userspace processes on each tick.
+    #[inline]
+    #[must_use]
+    pub fn rings(&'static self) -> &'static RingPoller {
+        &self.inner.rings
+    }
+
+    /// Returns the kernel's system [`EventBus`].
+    #[inline]
+    #[must_use]
+    pub fn events(&'static self) -> &'static EventBus {
+        &self.inner.events
+    }
+
+    /// Returns the kernel's name [`Interner`].
+    #[inline]
+    #[must_use]
+    pub fn names(&'static self) -> &'static Interner {
+        &self.inner.names
+    }
+
+    /// Interns `name` in the kernel's [name interner](intern), copying it
+    /// onto the heap if it has not already been interned.
+    pub async fn intern(&'static self, name: &str) -> Name {
+        self.inner.names.intern(name).await
+    }
+
     /// Initialize the kernel's `maitake` timer as the global default timer.
     ///
     /// This allows the use of `sleep` and `timeout` free functions.
     /// TODO(eliza): can the kernel just "do this" once it becomes active? Or,
     /// have a "kernel.init()" or something that does this and other global inits?
-    pub fn set_global_timer(&'static self) -> Result<(), maitake::time::AlreadyInitialized> {
-        maitake::time::set_global_timer(self.timer())
+    pub fn set_global_timer(&'static self) -> Result<(), Error> {
+        maitake::time::set_global_timer(self.timer())?;
+        Ok(())
     }
 
     #[track_caller]
-    pub fn initialize<F>(&'static self, fut: F) -> Result<JoinHandle<F::Output>, &'static str>
+    pub fn initialize<F>(&'static self, fut: F) -> Result<JoinHandle<F::Output>, Error>
     where
         F: Future + 'static,
     {
+        let fut = self.watch(fut);
         Ok(self.inner.scheduler.spawn(fut))
     }
 
@@ -225,10 +554,78 @@ impl Kernel {
     where
         F: Future + 'static,
     {
+        let fut = self.watch(fut);
         let bx = Box::new(maitake::task::Task::new(fut))
             .await
             .into_alloc_box();
-        self.spawn_allocated(bx)
+        self.inner.scheduler.spawn_allocated(bx)
+    }
+
+    /// Spawns a background task during the initialization phase.
+    ///
+    /// See [`Kernel::spawn_background`] for details on background tasks.
+    #[track_caller]
+    pub fn initialize_background<F>(&'static self, fut: F) -> Result<JoinHandle<F::Output>, Error>
+    where
+        F: Future + 'static,
+    {
+        let fut = self.watch(fut);
+        Ok(self.inner.background.spawn(fut))
+    }
+
+    /// Spawns a background task.
+    ///
+    /// Background tasks are only polled when a tick leaves no other tasks
+    /// ready to run, so they never delay interactive work. They're intended
+    /// for maintenance work which is never urgent. See the [crate-level
+    /// docs](crate#background-tasks) for details.
+    pub async fn spawn_background<F>(&'static self, fut: F) -> JoinHandle<F::Output>
+    where
+        F: Future + 'static,
+    {
+        let fut = self.watch(fut);
+        let bx = Box::new(maitake::task::Task::new(fut))
+            .await
+            .into_alloc_box();
+        self.inner.background.spawn_allocated(bx)
+    }
+
+    /// Returns a future which yields to the scheduler once, letting other
+    /// tasks run before the current task continues.
+    ///
+    /// Tasks which do a lot of work at once should await this periodically,
+    /// so that they don't starve other tasks. See the [`watchdog`] module for
+    /// details.
+    #[inline]
+    pub fn yield_now(&'static self) -> YieldNow {
+        YieldNow::new()
+    }
+
+    /// Counts a newly spawned task, and in debug builds, wraps `fut` so that
+    /// it is timed by the [`Watchdog`].
+    ///
+    /// The task's ID, as reported by the watchdog, is the number of tasks
+    /// spawned before it.
+    ///
+    /// With the "alloc-tags" feature flag, allocations made by `fut` are also
+    /// attributed to the allocation scope of the task which spawned it.
+    fn watch<F>(&'static self, fut: F) -> impl Future<Output = F::Output> + 'static
+    where
+        F: Future + 'static,
+    {
+        let _id = self.inner.tasks_spawned.fetch_add(1, Ordering::Relaxed);
+
+        #[cfg(feature = "alloc-tags")]
+        let fut = crate::mnemos_alloc::heap::tags::inherit(fut);
+
+        #[cfg(debug_assertions)]
+        {
+            self.inner.watchdog.watch(self, _id, fut)
+        }
+        #[cfg(not(debug_assertions))]
+        {
+            fut
+        }
     }
 
     /// Immutably borrow the kernel's [`Registry`].
@@ -246,6 +643,7 @@ impl Kernel {
     where
         F: Future + 'static,
     {
+        self.inner.tasks_spawned.fetch_add(1, Ordering::Relaxed);
         self.inner.scheduler.spawn_allocated(task)
     }
 
@@ -255,6 +653,23 @@ impl Kernel {
         self.inner.timer.sleep(duration)
     }
 
+    /// Returns a [`Sleep`] future that sleeps for at least `duration`, and at
+    /// most `duration + slack`.
+    ///
+    /// Within that window, the deadline is rounded to the coarsest of a whole
+    /// second, 100ms, 10ms, or 1ms since boot that fits, so that timers with
+    /// similar deadlines are likely to expire together. Tasks which wake up
+    /// periodically but don't need precise timing, such as daemons reporting
+    /// their status, should sleep with generous slack, so that they share
+    /// wakeups instead of each waking an idle CPU separately.
+    ///
+    /// With a `slack` of zero, this is the same as [`Kernel::sleep`].
+    pub fn sleep_with_slack(&'static self, duration: Duration, slack: Duration) -> Sleep<'static> {
+        let now = self.uptime();
+        let deadline = coalesce(now + duration, slack);
+        self.inner.timer.sleep(deadline - now)
+    }
+
     /// Returns a [`Timeout`] future that cancels `F` if the specified
     /// [`Duration`] has elapsed before it completes.
     #[inline]
@@ -276,65 +691,155 @@ impl Kernel {
     ///
     /// - The [`KeyboardMuxService`], which multiplexes keyboard input from
     ///   multiple keyboards to tasks that depend on keyboard input,
+    /// - If enabled, the [`PointerMuxService`], which multiplexes input from
+    ///   mice and other pointing devices,
     /// - The [`SerialMuxService`], which multiplexes serial I/O to virtual
-    ///   serial ports
+    ///   serial ports. If the serial mux is disabled, the
+    ///   [`SerialConsoleService`] is registered instead, which gives one task
+    ///   at a time line-oriented access to the serial port, along with a
+    ///   [Forth shell](daemons::shells::console_shell) on it.
     /// - The [`SpawnulatorService`], which is responsible for spawning
     ///   new Forth tasks
+    /// - The [`ClipboardService`], which holds a clipboard shared between
+    ///   shells and other tasks
+    /// - The [`AlarmService`], which publishes events at scheduled wall-clock
+    ///   times
+    /// - The [`NotificationService`], which shows user-visible notifications
+    ///   posted by any task
     ///
     /// In addition, this method will initialize the following non-service
     /// daemons:
     ///
     /// - [`daemons::sermux::loopback`], which serves a loopback service on a
     ///   configured loopback port
-    /// - [`daemons::sermux::hello`], which sends periodic "hello world" pings
+    /// - [`daemons::sermux::hello`], which sends periodic liveness pings
     ///   to a configured serial mux port
+    /// - [`daemons::sermux::debug_agent`], which answers read-only debugging
+    ///   queries from host tools such as `mnemos-dbg`
+    /// - [`daemons::sermux::clipboard`], which lets the host set and read the
+    ///   clipboard
+    /// - If enabled, the [`daemons::i2c_scan::scan`] daemon, which logs the
+    ///   devices found on the I²C bus at boot. This runs as a
+    ///   [background task](crate#background-tasks).
+    /// - In debug builds, the [`watchdog::report`] daemon, which logs tasks
+    ///   that run for too long without yielding
+    /// - If enabled, and the "alloc-tags" feature flag is enabled, the
+    ///   [`daemons::mem_budget`] watcher, which logs services and daemons that
+    ///   use more memory than their configured budgets
     /// - If the "serial-trace" feature flag is enabled, the
     ///   [`serial_trace::SerialSubscriber`] worker task, which sends `tracing`
     ///   events over the serial port.
     ///
+    /// Services are spawned as named [boot steps](boot), so their progress is
+    /// reported by the kernel's [`BootProgress`] tracker.
+    ///
     /// If the kernel's [`maitake::time::Timer`] has not been set as the global
     /// timer, this method will also ensure that the global timer is set as the
     /// default.
     ///
     /// [`KeyboardMuxService`]:
     ///     crate::services::keyboard::mux::KeyboardMuxService
+    /// [`PointerMuxService`]: crate::services::pointer::mux::PointerMuxService
     /// [`SerialMuxService`]: crate::services::serial_mux::SerialMuxService
+    /// [`SerialConsoleService`]:
+    ///     crate::services::serial_console::SerialConsoleService
     /// [`SpawnulatorService`]:
     ///     crate::services::forth_spawnulator::SpawnulatorService
+    /// [`ClipboardService`]: crate::services::clipboard::ClipboardService
+    /// [`AlarmService`]: crate::services::alarm::AlarmService
+    /// [`NotificationService`]:
+    ///     crate::services::notification::NotificationService
+    ///
+    /// # Panics
+    ///
+    /// If a service or daemon could not be spawned. See
+    /// [`Kernel::try_initialize_default_services`] for a version of this
+    /// method which returns an error instead.
     pub fn initialize_default_services(&'static self, settings: KernelServiceSettings) {
+        if let Err(error) = self.try_initialize_default_services(settings) {
+            panic!("{error}");
+        }
+    }
+
+    /// Initialize the default set of cross-platform kernel [`services`],
+    /// returning an error if any of them could not be spawned.
+    ///
+    /// This spawns the same services and daemons as
+    /// [`Kernel::initialize_default_services`], but rather than panicking,
+    /// returns an [`InitializeError`] naming the first service that could not
+    /// be spawned. Services spawned before the failure keep running.
+    ///
+    /// This only reports errors spawning services and registering their boot
+    /// steps. Services start once the kernel runs, so an `Ok` return does not
+    /// mean that they started successfully. To find out which services failed
+    /// to start, await [`BootProgress::failures`] on the kernel's
+    /// [`boot_progress`](Kernel::boot_progress).
+    pub fn try_initialize_default_services(
+        &'static self,
+        settings: KernelServiceSettings,
+    ) -> Result<(), InitializeError> {
         // Set the kernel timer as the global timer.
         // Disregard errors --- they just mean someone else has already set up
         // the global timer.
         let _ = self.set_global_timer();
 
+        self.rings().set_budget(settings.rings.budget);
+        self.registry()
+            .watch_request_queues(self.timer(), &settings.request_queues);
+        self.registry().time_requests(self.timer());
+        self.registry().enforce_rate_limits(self.timer());
+
         if settings.serial_mux.enabled {
             // Initialize tracing first, so that we can collect more traces from
             // the initialization process.
             #[cfg(feature = "serial-trace")]
             if settings.sermux_trace.enabled {
-                self.initialize(async move {
+                self.initialize_step("sermux_trace", &[], async move {
                     let subscriber =
                         crate::serial_trace::SerialSubscriber::start(self, settings.sermux_trace)
                             .await;
                     tracing::subscriber::set_global_default(subscriber)
-                        .expect("default tracing subscriber already set!");
                 })
-                .expect("failed to start tracing subscriber!");
+                .map_err(InitializeError::spawning("sermux_trace"))?;
             }
 
             // Initialize the SerialMuxServer
-            self.initialize(SerialMuxServer::register(self, settings.serial_mux))
-                .expect("failed to spawn SerialMuxService initialization");
+            self.initialize_step("serial_mux", &[], async move {
+                SerialMuxServer::register(self, settings.serial_mux)
+                    .await
+                    .map_err(Error::from)
+            })
+            .map_err(InitializeError::spawning("serial_mux"))?;
 
             // Initialize Serial Mux daemons.
             if settings.sermux_loopback.enabled {
                 self.initialize(daemons::sermux::loopback(self, settings.sermux_loopback))
-                    .expect("failed to spawn SerMux loopback");
+                    .map_err(InitializeError::spawning("sermux_loopback"))?;
             }
 
             if settings.sermux_hello.enabled {
                 self.initialize(daemons::sermux::hello(self, settings.sermux_hello))
-                    .expect("failed to spawn SerMux hello world daemon");
+                    .map_err(InitializeError::spawning("sermux_hello"))?;
+            }
+
+            if settings.sermux_debug.enabled {
+                self.initialize(daemons::sermux::debug_agent(self, settings.sermux_debug))
+                    .map_err(InitializeError::spawning("sermux_debug"))?;
+            }
+
+            if settings.sermux_gdb.enabled {
+                self.initialize(gdb::serve(self, settings.sermux_gdb))
+                    .map_err(InitializeError::spawning("sermux_gdb"))?;
+            }
+
+            if settings.sermux_clipboard.enabled {
+                self.initialize(daemons::sermux::clipboard(self, settings.sermux_clipboard))
+                    .map_err(InitializeError::spawning("sermux_clipboard"))?;
+            }
+
+            if settings.screenshot.enabled {
+                self.initialize(daemons::screenshot::run(self, settings.screenshot))
+                    .map_err(InitializeError::spawning("screenshot"))?;
             }
         } else {
             let deps = [
@@ -342,23 +847,217 @@ impl Kernel {
                 settings.sermux_trace.enabled,
                 settings.sermux_loopback.enabled,
                 settings.sermux_hello.enabled,
+                settings.sermux_debug.enabled,
+                settings.sermux_gdb.enabled,
+                settings.sermux_clipboard.enabled,
+                settings.screenshot.enabled,
             ];
 
             if deps.into_iter().any(identity) {
                 tracing::error!("Sermux services configured without sermux! Skipping.");
             }
+
+            // Without the serial mux, fall back to a raw console on the
+            // serial port.
+            if settings.serial_console.enabled {
+                let shell = settings.serial_console.shell;
+                self.initialize_step("serial_console", &[], async move {
+                    SerialConsoleServer::register(self, settings.serial_console)
+                        .await
+                        .map_err(Error::from)
+                })
+                .map_err(InitializeError::spawning("serial_console"))?;
+
+                if shell {
+                    self.initialize(daemons::shells::console_shell(self, Default::default()))
+                        .map_err(InitializeError::spawning("console_shell"))?;
+                }
+            }
         }
 
         // Initialize the kernel keyboard mux service.
         if settings.keyboard_mux.enabled {
-            self.initialize(KeyboardMuxServer::register(self, settings.keyboard_mux))
-                .expect("failed to spawn KeyboardMuxService initialization");
+            self.initialize_step("keyboard_mux", &[], async move {
+                KeyboardMuxServer::register(self, settings.keyboard_mux)
+                    .await
+                    .map_err(Error::from)
+            })
+            .map_err(InitializeError::spawning("keyboard_mux"))?;
+        }
+
+        // Initialize the kernel pointer mux service.
+        if settings.pointer_mux.enabled {
+            self.initialize_step("pointer_mux", &[], async move {
+                PointerMuxServer::register(self, settings.pointer_mux)
+                    .await
+                    .map_err(Error::from)
+            })
+            .map_err(InitializeError::spawning("pointer_mux"))?;
+        }
+
+        // Spawn the on-screen keyboard, which publishes to the keyboard mux.
+        if settings.soft_keyboard.enabled {
+            if settings.keyboard_mux.enabled && settings.pointer_mux.enabled {
+                self.initialize(daemons::soft_keyboard::run(self, settings.soft_keyboard))
+                    .map_err(InitializeError::spawning("soft_keyboard"))?;
+            } else {
+                tracing::error!(
+                    "Software keyboard configured without the keyboard and pointer muxes! Skipping."
+                );
+            }
         }
 
         // Initialize the Forth spawnulator.
         if settings.spawnulator.enabled {
-            self.initialize(SpawnulatorServer::register(self, settings.spawnulator))
-                .expect("failed to spawn SpawnulatorService initialization");
+            self.initialize_step(
+                "spawnulator",
+                &[],
+                SpawnulatorServer::register(self, settings.spawnulator),
+            )
+            .map_err(InitializeError::spawning("spawnulator"))?;
+        }
+
+        // Initialize the buffer pool service.
+        if settings.buffer_pool.enabled {
+            self.initialize_step("buffer_pool", &[], async move {
+                BufferPoolServer::register(self, settings.buffer_pool)
+                    .await
+                    .map_err(Error::from)
+            })
+            .map_err(InitializeError::spawning("buffer_pool"))?;
         }
+
+        // Initialize the clipboard service.
+        if settings.clipboard.enabled {
+            self.initialize_step(
+                "clipboard",
+                &[],
+                ClipboardServer::register(self, settings.clipboard),
+            )
+            .map_err(InitializeError::spawning("clipboard"))?;
+        }
+
+        // Initialize the alarm service.
+        if settings.alarm.enabled {
+            self.initialize_step("alarm", &[], AlarmServer::register(self, settings.alarm))
+                .map_err(InitializeError::spawning("alarm"))?;
+        }
+
+        // Initialize the notification service.
+        if settings.notification.enabled {
+            self.initialize_step(
+                "notification",
+                &[],
+                NotificationServer::register(self, settings.notification),
+            )
+            .map_err(InitializeError::spawning("notification"))?;
+        }
+
+        // Scan the I²C bus, if configured to.
+        if settings.i2c_scan.enabled {
+            self.initialize_background(daemons::i2c_scan::scan(self, settings.i2c_scan))
+                .map_err(InitializeError::spawning("i2c_scan"))?;
+        }
+
+        // Initialize the starvation watchdog, in debug builds.
+        if cfg!(debug_assertions) && settings.watchdog.enabled {
+            self.initialize(watchdog::report(self, settings.watchdog))
+                .map_err(InitializeError::spawning("watchdog"))?;
+        }
+
+        // Check services' and daemons' memory budgets, if configured to.
+        if settings.memory_budgets.enabled {
+            #[cfg(feature = "alloc-tags")]
+            self.initialize(daemons::mem_budget::watch(self, settings.memory_budgets))
+                .map_err(InitializeError::spawning("mem_budget"))?;
+            #[cfg(not(feature = "alloc-tags"))]
+            tracing::warn!(
+                "memory budgets are configured, but the \"alloc-tags\" feature \
+                 flag is disabled, so they will not be checked"
+            );
+        }
+
+        Ok(())
+    }
+}
+
+impl InitializeError {
+    /// Returns a function which builds an `InitializeError` for the service
+    /// or daemon `name`, for use with [`Result::map_err`].
+    fn spawning(name: &'static str) -> impl FnOnce(Error) -> Self {
+        move |reason| Self { name, reason }
+    }
+}
+
+impl core::fmt::Display for InitializeError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let Self { name, reason } = self;
+        write!(f, "failed to initialize {name}: {reason}")
+    }
+}
+
+/// Returns the time between `deadline` and `deadline + slack` which is a
+/// multiple of the coarsest possible granularity, for
+/// [`Kernel::sleep_with_slack`]. Times are relative to when the kernel booted.
+fn coalesce(deadline: Duration, slack: Duration) -> Duration {
+    const GRAINS: [Duration; 4] = [
+        Duration::from_secs(1),
+        Duration::from_millis(100),
+        Duration::from_millis(10),
+        Duration::from_millis(1),
+    ];
+    let latest = deadline + slack;
+    GRAINS
+        .iter()
+        .find_map(|grain| {
+            let grain = grain.as_nanos();
+            let rounded =
+                Duration::from_nanos((deadline.as_nanos().div_ceil(grain) * grain) as u64);
+            (rounded <= latest).then_some(rounded)
+        })
+        .unwrap_or(deadline)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TestKernel;
+    use core::cell::Cell;
+    use std::rc::Rc;
+
+    #[test]
+    fn background_tasks_wait_for_idle() {
+        TestKernel::run(|k| async move {
+            let ran = Rc::new(Cell::new(false));
+            let task = k
+                .spawn_background({
+                    let ran = ran.clone();
+                    async move { ran.set(true) }
+                })
+                .await;
+
+            // while this task is ready to run, the background task isn't polled.
+            for _ in 0..8 {
+                k.yield_now().await;
+                assert!(!ran.get(), "background task ran while a task was ready");
+            }
+
+            task.await.unwrap();
+            assert!(ran.get());
+        })
+    }
+
+    #[test]
+    fn coalesces_to_coarsest_grain() {
+        let ms = Duration::from_millis;
+        // a whole second fits in the window.
+        assert_eq!(coalesce(ms(1_300), ms(800)), ms(2_000));
+        assert_eq!(coalesce(ms(2_000), ms(800)), ms(2_000));
+        // only a multiple of 100ms does.
+        assert_eq!(coalesce(ms(1_234), ms(500)), ms(1_300));
+        // nothing does, so the deadline is kept.
+        let deadline = Duration::from_micros(1_234_567);
+        assert_eq!(coalesce(deadline, Duration::ZERO), deadline);
+        assert_eq!(coalesce(deadline, Duration::from_micros(500)), ms(1_235));
     }
 }
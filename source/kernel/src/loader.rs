@@ -0,0 +1,151 @@
+//! # Userspace Program Loader
+//!
+//! Loads [relocatable flat binaries][abi::flat] into heap memory, fixing up
+//! the program's absolute addresses so that it can run at whatever address
+//! the allocator hands out. This allows more than one userspace program to be
+//! resident at once, without having to link each one at a fixed address.
+//!
+//! Loading a program does NOT start running it. Before jumping to the
+//! [entry point](LoadedProgram::entry), the platform implementation is
+//! responsible for any architecture-specific steps required to execute
+//! freshly-written code, such as synchronizing the instruction cache (e.g.
+//! `fence.i` on RISC-V).
+
+use core::{alloc::Layout, fmt, mem, ptr::NonNull};
+
+use abi::flat::{self, FlatBinary};
+use mnemos_alloc::heap::{alloc, dealloc};
+use tracing::{self, Level};
+
+/// A userspace program that has been loaded and relocated.
+///
+/// The program's memory is freed when the `LoadedProgram` is dropped.
+pub struct LoadedProgram {
+    base: NonNull<u8>,
+    layout: Layout,
+    entry: usize,
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum LoadError {
+    /// The binary was malformed, or is not loadable on this target.
+    Format(flat::Error),
+    /// The binary's size and alignment do not form a valid [`Layout`].
+    InvalidLayout,
+}
+
+/// Load a relocatable flat binary from `bytes`.
+///
+/// Memory for the program (including its bss region) is allocated from the
+/// kernel heap, waiting for space to become available if necessary.
+#[tracing::instrument(
+    name = "loader::load",
+    level = Level::DEBUG,
+    skip(bytes),
+    fields(len = bytes.len()),
+    err(Debug),
+)]
+pub async fn load(bytes: &[u8]) -> Result<LoadedProgram, LoadError> {
+    let flat = FlatBinary::parse(bytes)?;
+    let header = *flat.header();
+    if header.ptr_width as usize != mem::size_of::<usize>() {
+        return Err(flat::Error::BadPointerWidth(header.ptr_width).into());
+    }
+
+    // Don't ask the allocator for a zero-sized allocation.
+    let size = header.load_len().max(1);
+    let layout = Layout::from_size_align(size, header.align as usize)
+        .map_err(|_| LoadError::InvalidLayout)?;
+    let base = alloc(layout).await;
+
+    let dest = unsafe {
+        // Safety: we just allocated `layout.size()` bytes at `base`, and
+        // zero them before creating a slice so that it is initialized.
+        base.as_ptr().write_bytes(0, layout.size());
+        core::slice::from_raw_parts_mut(base.as_ptr(), layout.size())
+    };
+    if let Err(error) = flat.relocate_into(dest, base.as_ptr() as usize as u64) {
+        unsafe {
+            dealloc(base.as_ptr(), layout);
+        }
+        return Err(error.into());
+    }
+
+    tracing::info!(
+        base = ?base,
+        size,
+        entry = header.entry,
+        relocs = header.reloc_count,
+        "Loaded program",
+    );
+
+    Ok(LoadedProgram {
+        base,
+        layout,
+        entry: header.entry as usize,
+    })
+}
+
+// === impl LoadedProgram ===
+
+impl LoadedProgram {
+    /// Returns the address the program was loaded at.
+    #[inline]
+    #[must_use]
+    pub fn base(&self) -> NonNull<u8> {
+        self.base
+    }
+
+    /// Returns the address of the program's entry point.
+    #[inline]
+    #[must_use]
+    pub fn entry(&self) -> NonNull<u8> {
+        unsafe {
+            // Safety: the entry offset was validated to be within the image
+            // when the binary was parsed.
+            NonNull::new_unchecked(self.base.as_ptr().add(self.entry))
+        }
+    }
+
+    /// Returns the program's memory, including the bss region.
+    #[inline]
+    #[must_use]
+    pub fn memory(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self.base.as_ptr(), self.layout.size()) }
+    }
+}
+
+impl Drop for LoadedProgram {
+    fn drop(&mut self) {
+        unsafe {
+            dealloc(self.base.as_ptr(), self.layout);
+        }
+    }
+}
+
+impl fmt::Debug for LoadedProgram {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LoadedProgram")
+            .field("base", &self.base)
+            .field("len", &self.layout.size())
+            .field("entry", &self.entry())
+            .finish()
+    }
+}
+
+// === impl LoadError ===
+
+impl From<flat::Error> for LoadError {
+    fn from(error: flat::Error) -> Self {
+        Self::Format(error)
+    }
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Format(error) => write!(f, "failed to load program: {error}"),
+            Self::InvalidLayout => f.pad("program size or alignment is invalid"),
+        }
+    }
+}
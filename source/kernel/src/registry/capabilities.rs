@@ -0,0 +1,161 @@
+//! Optional capabilities advertised by a service to its clients.
+//!
+//! Different implementations of the same [`RegisteredDriver`] may not support
+//! the same set of optional features. For example, one display server may
+//! support a feature that another does not. Rather than requiring clients to
+//! guess which features are available based on the platform or a version
+//! number, a service may advertise a set of [`Capabilities`] to each client
+//! when [accepting its connection](super::listener::Accept::accept_with).
+//! Clients can then check for a capability using
+//! [`KernelHandle::capabilities`](super::KernelHandle::capabilities).
+//!
+//! Capabilities are connection-scoped: because they are sent in response to a
+//! client's [`Hello`](RegisteredDriver::Hello), a service may advertise
+//! different capabilities to different clients, such as when a connection is
+//! routed to a particular resource based on its `Hello`.
+//!
+//! ## Defining Capabilities
+//!
+//! Each service defines the meaning of its own capability bits. By
+//! convention, a service's capabilities are declared as constants in a
+//! `capabilities` module alongside the service definition, using
+//! [`Capabilities::bit`]:
+//!
+//! ```rust
+//! pub mod capabilities {
+//!     use kernel::registry::Capabilities;
+//!
+//!     /// The service supports doing the thing.
+//!     pub const DOES_THE_THING: Capabilities = Capabilities::bit(0);
+//! }
+//! ```
+//!
+//! Once a capability has been defined, its bit may never be reused for a
+//! different capability, so that old clients don't misinterpret new servers.
+//!
+//! [`RegisteredDriver`]: super::RegisteredDriver
+use core::{fmt, ops};
+use serde::{Deserialize, Serialize};
+
+/// A set of optional capabilities supported by a connection to a service.
+///
+/// See the [module-level documentation](self) for details.
+#[derive(Copy, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Capabilities(u32);
+
+impl Capabilities {
+    /// No capabilities. Connections accepted without specifying
+    /// capabilities have this set.
+    pub const NONE: Self = Self(0);
+
+    /// Returns a set containing only capability bit `n`.
+    ///
+    /// # Panics
+    ///
+    /// If `n` is 32 or greater. When used to define a `const`, this is a
+    /// compile-time error.
+    #[must_use]
+    pub const fn bit(n: u32) -> Self {
+        assert!(n < u32::BITS, "capability bits must be less than 32");
+        Self(1 << n)
+    }
+
+    /// Returns a set of capabilities from its raw bit representation.
+    #[must_use]
+    pub const fn from_bits(bits: u32) -> Self {
+        Self(bits)
+    }
+
+    /// Returns the raw bit representation of this set of capabilities.
+    #[must_use]
+    pub const fn bits(self) -> u32 {
+        self.0
+    }
+
+    /// Returns `true` if no capabilities are set.
+    #[must_use]
+    pub const fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// Returns `true` if *every* capability in `other` is also in `self`.
+    #[must_use]
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Returns `true` if *any* capability in `other` is also in `self`.
+    #[must_use]
+    pub const fn intersects(self, other: Self) -> bool {
+        self.0 & other.0 != 0
+    }
+
+    /// Returns the capabilities in either `self` or `other`.
+    #[must_use]
+    pub const fn union(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+
+    /// Returns the capabilities in both `self` and `other`.
+    #[must_use]
+    pub const fn intersection(self, other: Self) -> Self {
+        Self(self.0 & other.0)
+    }
+
+    /// Returns the capabilities in `self` that are not in `other`.
+    #[must_use]
+    pub const fn difference(self, other: Self) -> Self {
+        Self(self.0 & !other.0)
+    }
+}
+
+impl ops::BitOr for Capabilities {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self::Output {
+        self.union(rhs)
+    }
+}
+
+impl ops::BitOrAssign for Capabilities {
+    fn bitor_assign(&mut self, rhs: Self) {
+        *self = self.union(rhs);
+    }
+}
+
+impl ops::BitAnd for Capabilities {
+    type Output = Self;
+
+    fn bitand(self, rhs: Self) -> Self::Output {
+        self.intersection(rhs)
+    }
+}
+
+impl fmt::Debug for Capabilities {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Capabilities({:#b})", self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const A: Capabilities = Capabilities::bit(0);
+    const B: Capabilities = Capabilities::bit(5);
+
+    #[test]
+    fn set_operations() {
+        let both = A | B;
+        assert_eq!(both.bits(), 0b10_0001);
+        assert!(both.contains(A));
+        assert!(both.contains(A | B));
+        assert!(!A.contains(both));
+        assert!(A.intersects(both));
+        assert!(!A.intersects(B));
+        assert_eq!(both & B, B);
+        assert_eq!(both.difference(A), B);
+        assert!(Capabilities::NONE.is_empty());
+        assert!(both.contains(Capabilities::NONE));
+    }
+}
@@ -0,0 +1,282 @@
+//! Coalescing identical requests to idempotent services.
+//!
+//! Some services are often sent bursts of identical requests: several tasks
+//! polling the same sensor, asking for the same statistics, or flushing the
+//! same display. If handling each request means a slow transaction on a bus,
+//! handling the same request several times in a row wastes time that other
+//! clients spend waiting. A [`Coalescer`] lets such a service handle each
+//! distinct request once, and send the same response to every client that
+//! asked for it.
+//!
+//! A [`Coalescer`] wraps a service's [`RequestStream`]. Each time the service
+//! asks it for a request, it takes every request which has already been
+//! received (up to its capacity), and groups those which have the same key,
+//! as returned by the service's key function. The service handles the first
+//! request in each group, and [replies](Coalesced::reply) to all of them at
+//! once with clones of the response.
+//!
+//! Only requests which are waiting when the service asks for a request are
+//! coalesced, so a request is never delayed to wait for duplicates. Requests
+//! for which the key function returns [`None`], such as requests which change
+//! the service's state, are never coalesced with any other request.
+//!
+//! Replies are sent with [`ReplyTo::reply_konly`], so a service using a
+//! `Coalescer` can only reply to kernel clients.
+#![warn(missing_docs)]
+use super::{listener::RequestStream, Message, OpenEnvelope, RegisteredDriver, ReplyTo};
+use mnemos_alloc::containers::FixedVec;
+
+/// Groups identical requests from a [`RequestStream`], so that each is only
+/// handled once.
+///
+/// See the [module-level documentation](self) for details.
+#[must_use = "a `Coalescer` does nothing if `next_request` is not called"]
+pub struct Coalescer<D: RegisteredDriver, K> {
+    requests: RequestStream<D>,
+    key: fn(&D::Request) -> Option<K>,
+    /// The requests which were waiting when the batch was taken. Requests are
+    /// taken out of their slots as they are served, or coalesced with an
+    /// earlier request.
+    batch: FixedVec<Option<Message<D>>>,
+    /// The index of the next slot in `batch` to serve.
+    next: usize,
+    waiters: FixedVec<Waiter<D>>,
+    coalesced: usize,
+}
+
+/// A request returned by [`Coalescer::next_request`], along with every client
+/// which sent the same request.
+#[must_use = "clients are not replied to unless `Coalesced::reply` is called"]
+pub struct Coalesced<'a, D: RegisteredDriver> {
+    request: D::Request,
+    waiters: &'a mut FixedVec<Waiter<D>>,
+}
+
+type Waiter<D> = (
+    OpenEnvelope<Result<<D as RegisteredDriver>::Response, <D as RegisteredDriver>::Error>>,
+    ReplyTo<D>,
+);
+
+// === impl Coalescer ===
+
+impl<D, K> Coalescer<D, K>
+where
+    D: RegisteredDriver,
+    K: PartialEq,
+{
+    /// Returns a `Coalescer` which serves requests from `requests`, taking up
+    /// to `capacity` waiting requests at a time.
+    ///
+    /// Requests for which `key` returns the same [`Some`] value are
+    /// coalesced. Requests for which it returns [`None`] are always handled
+    /// on their own.
+    ///
+    /// # Panics
+    ///
+    /// If `capacity` is 0.
+    pub async fn new(
+        requests: RequestStream<D>,
+        capacity: usize,
+        key: fn(&D::Request) -> Option<K>,
+    ) -> Self {
+        Self {
+            requests,
+            key,
+            batch: FixedVec::new(capacity).await,
+            next: 0,
+            waiters: FixedVec::new(capacity).await,
+            coalesced: 0,
+        }
+    }
+
+    /// Returns the next request to handle, along with any identical requests
+    /// which were waiting with it.
+    ///
+    /// This waits for a request if none are waiting. Any requests from a
+    /// previous [`Coalesced`] which was dropped without being
+    /// [replied](Coalesced::reply) to are dropped, and their clients' replies
+    /// are canceled.
+    pub async fn next_request(&mut self) -> Coalesced<'_, D> {
+        self.waiters.clear();
+        let msg = match self.take_next() {
+            Some(msg) => msg,
+            None => {
+                self.fill().await;
+                self.take_next()
+                    .expect("a newly filled batch has at least one request")
+            }
+        };
+
+        let (request, env, reply) = msg.split();
+        // `waiters` has the same capacity as `batch`, so it has room for
+        // every request in it.
+        let _ = self.waiters.try_push((env, reply));
+        if let Some(key) = (self.key)(&request) {
+            for slot in &mut self.batch.as_slice_mut()[self.next..] {
+                let same = match slot {
+                    Some(msg) => (self.key)(&msg.msg.body).as_ref() == Some(&key),
+                    None => false,
+                };
+                if !same {
+                    continue;
+                }
+                if let Some(msg) = slot.take() {
+                    let (_, env, reply) = msg.split();
+                    let _ = self.waiters.try_push((env, reply));
+                    self.coalesced += 1;
+                }
+            }
+        }
+        if self.waiters.len() > 1 {
+            tracing::trace!(
+                clients = self.waiters.len(),
+                svc = %core::any::type_name::<D>(),
+                "Coalesced requests",
+            );
+        }
+
+        Coalesced {
+            request,
+            waiters: &mut self.waiters,
+        }
+    }
+
+    /// Returns the number of requests which have been coalesced with an
+    /// earlier request, rather than handled on their own.
+    #[must_use]
+    pub fn coalesced(&self) -> usize {
+        self.coalesced
+    }
+
+    /// Returns the [`RequestStream`] this `Coalescer` serves requests from.
+    #[must_use]
+    pub fn into_inner(self) -> RequestStream<D> {
+        self.requests
+    }
+
+    fn take_next(&mut self) -> Option<Message<D>> {
+        while let Some(slot) = self.batch.as_slice_mut().get_mut(self.next) {
+            self.next += 1;
+            if let Some(msg) = slot.take() {
+                return Some(msg);
+            }
+        }
+        None
+    }
+
+    /// Waits for a request, and then takes as many other waiting requests as
+    /// fit in the batch.
+    async fn fill(&mut self) {
+        self.batch.clear();
+        self.next = 0;
+        let first = self.requests.next_request().await;
+        let _ = self.batch.try_push(Some(first));
+        while !self.batch.is_full() {
+            match self.requests.try_next_request() {
+                Some(msg) => {
+                    let _ = self.batch.try_push(Some(msg));
+                }
+                None => break,
+            }
+        }
+    }
+}
+
+// === impl Coalesced ===
+
+impl<D: RegisteredDriver> Coalesced<'_, D> {
+    /// Returns the request to handle.
+    #[must_use]
+    pub fn request(&self) -> &D::Request {
+        &self.request
+    }
+
+    /// Returns the number of clients which sent this request.
+    #[must_use]
+    pub fn clients(&self) -> usize {
+        self.waiters.len()
+    }
+
+    /// Sends `response` to every client which sent this request.
+    ///
+    /// Clients which can't be replied to, because they have gone away or are
+    /// in userspace, are skipped.
+    pub async fn reply(mut self, response: Result<D::Response, D::Error>)
+    where
+        D::Response: Clone,
+        D::Error: Clone,
+    {
+        let mut response = Some(response);
+        while let Some((env, reply)) = self.waiters.pop() {
+            // the last client gets the response itself, rather than a clone.
+            let body = if self.waiters.is_empty() {
+                response.take()
+            } else {
+                response.clone()
+            };
+            let Some(body) = body else { break };
+            if let Err(error) = reply.reply_konly(env.fill(body)).await {
+                tracing::debug!(?error, "failed to reply to coalesced request");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        comms::kchannel::KChannel,
+        registry::{
+            listener::Listener,
+            tests::{TestMessage, TestService},
+        },
+        test_util::TestKernel,
+    };
+
+    #[test]
+    fn coalesces_waiting_requests() {
+        TestKernel::run(|k| async move {
+            let (listener, registration) = Listener::<TestService>::new(2).await;
+            k.registry().register_konly(registration).await.unwrap();
+            let requests = listener.into_request_stream(4).await;
+            // every request but 0 may be coalesced.
+            let mut coalescer =
+                Coalescer::new(requests, 4, |&TestMessage(val)| (val != 0).then_some(val)).await;
+
+            // server: responds with the number of clients each request was
+            // coalesced from.
+            k.spawn(async move {
+                loop {
+                    let req = coalescer.next_request().await;
+                    let clients = req.clients();
+                    req.reply(Ok(TestMessage(clients))).await;
+                }
+            })
+            .await;
+
+            let mut client = k
+                .registry()
+                .connect::<TestService>(TestMessage(0))
+                .await
+                .expect("connect should succeed");
+            let (tx, rx) = KChannel::new_async(5).await.split();
+            // send every request before the server gets to run.
+            for val in [1, 2, 1, 0, 0] {
+                client
+                    .send(TestMessage(val), ReplyTo::KChannel(tx.clone()))
+                    .await
+                    .unwrap();
+            }
+
+            let mut responses = [0; 5];
+            for rsp in &mut responses {
+                *rsp = rx.dequeue_async().await.unwrap().body.unwrap().0;
+            }
+            responses.sort_unstable();
+            // the two 1s are replied to together, and the final 0 is outside
+            // of the first batch.
+            assert_eq!(responses, [1, 1, 1, 2, 2]);
+        })
+    }
+}
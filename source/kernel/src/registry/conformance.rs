@@ -0,0 +1,399 @@
+//! Serialization conformance tests for driver services' message types.
+//!
+//! Userspace talks to services registered with [`Registry::register`] by
+//! sending [`UserRequest`]s and receiving [`UserResponse`]s over its rings,
+//! with the service's messages encoded using [`postcard`]. Postcard encodes
+//! enum variants by index and struct fields by position, so reordering a
+//! message type's variants or fields changes its wire format *without*
+//! changing its Rust API. Userspace built against the old definition will then
+//! decode the wrong value, rather than failing to decode.
+//!
+//! These tests check that every serializable message type:
+//!
+//! - survives a round trip through postcard, for randomly generated values,
+//!   and
+//! - encodes a fixed set of values to exactly the bytes snapshotted here.
+//!
+//! If a snapshot test fails, the wire format has changed. If that was
+//! intentional, the service's UUID should be changed too, so that userspace
+//! built against the old format won't connect to it, and the snapshot
+//! updated. Adding new enum variants at the *end* of a type does not change
+//! the encoding of existing values.
+//!
+//! When a new message type derives `Serialize` and `Deserialize`, implement
+//! [`WireType`] for it and add it here. When a new service can be registered
+//! with [`Registry::register`], add it to [`every_service`].
+
+use core::fmt;
+
+use super::*;
+use crate::services::{
+    keyboard::{
+        self,
+        key_event::{KeyCode, Kind, MediaKeyCode, Modifiers},
+        KeyEvent, KeyboardError,
+    },
+    pointer::{self, Buttons, PointerError, PointerEvent},
+};
+
+/// The number of random values round-tripped for each type.
+const ITERATIONS: usize = 256;
+
+/// A message type which can cross the userspace boundary.
+trait WireType: Serialize + DeserializeOwned + PartialEq + fmt::Debug {
+    /// Returns an arbitrary value of this type.
+    fn arbitrary(rng: &mut Rng) -> Self;
+}
+
+/// A xorshift PRNG, so that every run generates the same values, and a
+/// failure can be reproduced.
+struct Rng(u64);
+
+impl Rng {
+    fn new() -> Self {
+        Self(0x2545_f491_4f6c_dd1d)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        let mut x = self.0;
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.0 = x;
+        x
+    }
+
+    /// Returns a value in `0..n`.
+    fn below(&mut self, n: u64) -> u64 {
+        self.next_u64() % n
+    }
+}
+
+/// Checks that [`ITERATIONS`] arbitrary values of `T` round-trip.
+#[track_caller]
+fn round_trip<T: WireType>() {
+    let mut rng = Rng::new();
+    for _ in 0..ITERATIONS {
+        let value = T::arbitrary(&mut rng);
+        let bytes = postcard::to_stdvec(&value).unwrap();
+        let decoded = postcard::from_bytes::<T>(&bytes).unwrap_or_else(|error| {
+            panic!(
+                "{value:?} did not round-trip as {}: {error}\n  encoded: {bytes:02x?}",
+                any::type_name::<T>()
+            )
+        });
+        assert_eq!(decoded, value, "encoded: {bytes:02x?}");
+    }
+}
+
+/// Checks that `value` encodes to `expected`, and decodes from it.
+#[track_caller]
+fn snapshot<T: WireType>(value: T, expected: &[u8]) {
+    let bytes = postcard::to_stdvec(&value).unwrap();
+    assert_eq!(
+        bytes,
+        expected,
+        "the wire encoding of {value:?} changed! userspace built against the \
+        old definition of {} will no longer understand it. see the \
+        `registry::conformance` module docs.",
+        any::type_name::<T>(),
+    );
+    assert_eq!(postcard::from_bytes::<T>(expected).unwrap(), value);
+}
+
+/// Checks that every message type of the service `RD` round-trips.
+fn service<RD>()
+where
+    RD: RegisteredDriver,
+    RD::Request: WireType,
+    RD::Response: WireType,
+    RD::Error: WireType,
+    RD::Hello: WireType,
+    RD::ConnectError: WireType,
+{
+    round_trip::<RD::Request>();
+    round_trip::<RD::Response>();
+    round_trip::<RD::Error>();
+    round_trip::<RD::Hello>();
+    round_trip::<RD::ConnectError>();
+    round_trip::<UserResponse<RD::Response, RD::Error>>();
+}
+
+#[test]
+fn every_service() {
+    // None of the kernel's own services are registered for userspace yet, but
+    // any that are must be listed here.
+    service::<super::tests::TestService>();
+}
+
+#[test]
+fn user_request() {
+    let mut rng = Rng::new();
+    for _ in 0..ITERATIONS {
+        let req_bytes = (0..rng.below(32))
+            .map(|_| rng.next_u64() as u8)
+            .collect::<Vec<_>>();
+        let req = UserRequest {
+            uid: Uuid::arbitrary(&mut rng),
+            nonce: rng.next_u64() as u32,
+            req_bytes: &req_bytes,
+        };
+        let bytes = postcard::to_stdvec(&req).unwrap();
+        let decoded = postcard::from_bytes::<UserRequest<'_>>(&bytes).unwrap();
+        assert_eq!(decoded.uid, req.uid);
+        assert_eq!(decoded.nonce, req.nonce);
+        assert_eq!(decoded.req_bytes, req.req_bytes);
+    }
+
+    let req = UserRequest {
+        uid: known_uuids::kernel::KEYBOARD,
+        nonce: 1,
+        req_bytes: &[0x20],
+    };
+    assert_eq!(
+        postcard::to_stdvec(&req).unwrap(),
+        [
+            0x10, 0x52, 0x4d, 0x77, 0xb1, 0x49, 0x9c, 0x44, 0x0b, 0xbd, 0x62, 0xe6, 0x3c, 0x09,
+            0x18, 0xef, 0xb5, 0x01, 0x01, 0x20,
+        ],
+        "the wire encoding of `UserRequest` changed!",
+    );
+}
+
+#[test]
+fn user_response() {
+    round_trip::<UserResponse<keyboard::Subscribe, KeyboardError>>();
+    snapshot(
+        UserResponse::<keyboard::Subscribe, KeyboardError> {
+            uuid: known_uuids::kernel::KEYBOARD,
+            nonce: 2,
+            reply: Err(KeyboardError::TooManySubscriptions),
+        },
+        &[
+            0x10, 0x52, 0x4d, 0x77, 0xb1, 0x49, 0x9c, 0x44, 0x0b, 0xbd, 0x62, 0xe6, 0x3c, 0x09,
+            0x18, 0xef, 0xb5, 0x02, 0x01, 0x01,
+        ],
+    );
+}
+
+#[test]
+fn keyboard() {
+    round_trip::<keyboard::Subscribe>();
+    round_trip::<KeyboardError>();
+    round_trip::<KeyEvent>();
+
+    snapshot(keyboard::Subscribe::default(), &[0x20]);
+    snapshot(
+        keyboard::Subscribe::default().with_buffer_capacity(300),
+        &[0xac, 0x02],
+    );
+    snapshot(KeyboardError::NoKeyboards, &[0x00]);
+    snapshot(KeyboardError::TooManySubscriptions, &[0x01]);
+    snapshot(
+        KeyEvent {
+            kind: Kind::Pressed,
+            modifiers: Modifiers::new().with(Modifiers::SHIFT, true),
+            code: KeyCode::Char('A'),
+        },
+        &[0x00, 0x01, 0x0f, 0x01, 0x41],
+    );
+    snapshot(
+        KeyEvent {
+            kind: Kind::Released,
+            modifiers: Modifiers::new(),
+            code: KeyCode::F(12),
+        },
+        &[0x01, 0x00, 0x0e, 0x0c],
+    );
+    snapshot(
+        KeyEvent {
+            kind: Kind::Held,
+            modifiers: Modifiers::new()
+                .with(Modifiers::CTRL, true)
+                .with(Modifiers::ALT, true),
+            code: KeyCode::Media(MediaKeyCode::PlayPause),
+        },
+        &[0x02, 0x06, 0x17, 0x02],
+    );
+}
+
+#[test]
+fn pointer() {
+    round_trip::<pointer::Subscribe>();
+    round_trip::<PointerError>();
+    round_trip::<PointerEvent>();
+
+    snapshot(pointer::Subscribe::default(), &[0x20]);
+    snapshot(PointerError::NoPointers, &[0x00]);
+    snapshot(PointerError::TooManySubscriptions, &[0x01]);
+    snapshot(
+        PointerEvent {
+            dx: -3,
+            dy: 300,
+            wheel: -1,
+            buttons: Buttons::from_bits(Buttons::LEFT.bits() | Buttons::MIDDLE.bits()),
+        },
+        &[0x05, 0xd8, 0x04, 0xff, 0x05],
+    );
+}
+
+// === impl WireType ===
+
+impl WireType for () {
+    fn arbitrary(_: &mut Rng) -> Self {}
+}
+
+impl WireType for Uuid {
+    fn arbitrary(rng: &mut Rng) -> Self {
+        Uuid::from_u128(((rng.next_u64() as u128) << 64) | rng.next_u64() as u128)
+    }
+}
+
+impl<T: WireType, E: WireType> WireType for Result<T, E> {
+    fn arbitrary(rng: &mut Rng) -> Self {
+        if rng.below(2) == 0 {
+            Ok(T::arbitrary(rng))
+        } else {
+            Err(E::arbitrary(rng))
+        }
+    }
+}
+
+impl<U: WireType, E: WireType> WireType for UserResponse<U, E> {
+    fn arbitrary(rng: &mut Rng) -> Self {
+        Self {
+            uuid: Uuid::arbitrary(rng),
+            nonce: rng.next_u64() as u32,
+            reply: Result::arbitrary(rng),
+        }
+    }
+}
+
+impl<U: PartialEq, E: PartialEq> PartialEq for UserResponse<U, E> {
+    fn eq(&self, other: &Self) -> bool {
+        self.uuid == other.uuid && self.nonce == other.nonce && self.reply == other.reply
+    }
+}
+
+impl<U: fmt::Debug, E: fmt::Debug> fmt::Debug for UserResponse<U, E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UserResponse")
+            .field("uuid", &self.uuid)
+            .field("nonce", &self.nonce)
+            .field("reply", &self.reply)
+            .finish()
+    }
+}
+
+impl WireType for super::tests::TestMessage {
+    fn arbitrary(rng: &mut Rng) -> Self {
+        Self(rng.next_u64() as usize)
+    }
+}
+
+impl WireType for keyboard::Subscribe {
+    fn arbitrary(rng: &mut Rng) -> Self {
+        Self::default().with_buffer_capacity(rng.below(1 << 16) as usize)
+    }
+}
+
+impl WireType for KeyboardError {
+    fn arbitrary(rng: &mut Rng) -> Self {
+        match rng.below(2) {
+            0 => Self::NoKeyboards,
+            _ => Self::TooManySubscriptions,
+        }
+    }
+}
+
+impl WireType for KeyEvent {
+    fn arbitrary(rng: &mut Rng) -> Self {
+        let kind = match rng.below(3) {
+            0 => Kind::Pressed,
+            1 => Kind::Released,
+            _ => Kind::Held,
+        };
+        const CODES: [KeyCode; 22] = [
+            KeyCode::Backspace,
+            KeyCode::Enter,
+            KeyCode::Left,
+            KeyCode::Right,
+            KeyCode::Up,
+            KeyCode::Down,
+            KeyCode::Home,
+            KeyCode::End,
+            KeyCode::PageUp,
+            KeyCode::PageDown,
+            KeyCode::Tab,
+            KeyCode::BackTab,
+            KeyCode::Delete,
+            KeyCode::Insert,
+            KeyCode::Null,
+            KeyCode::Esc,
+            KeyCode::NumLock,
+            KeyCode::PrintScreen,
+            KeyCode::Pause,
+            KeyCode::Menu,
+            KeyCode::KeypadBegin,
+            KeyCode::Media(MediaKeyCode::Play),
+        ];
+        let code = match rng.below(4) {
+            0 => KeyCode::F(rng.next_u64() as u8),
+            1 => KeyCode::Char(char::from_u32(rng.below(0x11_0000) as u32).unwrap_or('\u{fffd}')),
+            2 => KeyCode::Media(MediaKeyCode::arbitrary(rng)),
+            _ => CODES[rng.below(CODES.len() as u64) as usize],
+        };
+        Self {
+            kind,
+            modifiers: Modifiers::from_bits(rng.below(1 << 6) as u8),
+            code,
+        }
+    }
+}
+
+impl WireType for MediaKeyCode {
+    fn arbitrary(rng: &mut Rng) -> Self {
+        const CODES: [MediaKeyCode; 13] = [
+            MediaKeyCode::Play,
+            MediaKeyCode::Pause,
+            MediaKeyCode::PlayPause,
+            MediaKeyCode::Reverse,
+            MediaKeyCode::Stop,
+            MediaKeyCode::FastForward,
+            MediaKeyCode::Rewind,
+            MediaKeyCode::TrackNext,
+            MediaKeyCode::TrackPrevious,
+            MediaKeyCode::Record,
+            MediaKeyCode::LowerVolume,
+            MediaKeyCode::RaiseVolume,
+            MediaKeyCode::MuteVolume,
+        ];
+        CODES[rng.below(CODES.len() as u64) as usize]
+    }
+}
+
+impl WireType for pointer::Subscribe {
+    fn arbitrary(rng: &mut Rng) -> Self {
+        Self::default().with_buffer_capacity(rng.below(1 << 16) as usize)
+    }
+}
+
+impl WireType for PointerError {
+    fn arbitrary(rng: &mut Rng) -> Self {
+        match rng.below(2) {
+            0 => Self::NoPointers,
+            _ => Self::TooManySubscriptions,
+        }
+    }
+}
+
+impl WireType for PointerEvent {
+    fn arbitrary(rng: &mut Rng) -> Self {
+        Self {
+            dx: rng.next_u64() as i16,
+            dy: rng.next_u64() as i16,
+            wheel: rng.next_u64() as i8,
+            buttons: Buttons::from_bits(rng.next_u64() as u8),
+        }
+    }
+}
@@ -0,0 +1,254 @@
+//! Request latency targets for services.
+//!
+//! A service may declare the longest it expects a client to wait for a reply
+//! to a request, using [`RequestStream::with_max_latency`]. This is a
+//! debugging aid, rather than a guarantee: in debug builds, each request sent
+//! by a [`KernelHandle`] is timestamped, and when the service replies, the
+//! time since the request was sent is compared against the service's target.
+//! If the reply is late, a warning is traced, including the [`Debug`]
+//! representation of the slow request, so that a vague "the display feels
+//! slow sometimes" can be narrowed down to the requests that were slow.
+//!
+//! Requests are only timestamped once the registry has a timer to timestamp
+//! them with (see [`Registry::time_requests`]), which
+//! [`Kernel::initialize_default_services`] provides. Requests sent from
+//! userspace are not timestamped, and responses sent using a
+//! [`StreamSender`] are not checked, as a stream may stay open for as long as
+//! the client likes.
+//!
+//! In release builds, requests are never timestamped, and [`RequestTiming`] is
+//! zero-sized.
+//!
+//! [`RequestStream::with_max_latency`]: super::listener::RequestStream::with_max_latency
+//! [`KernelHandle`]: super::KernelHandle
+//! [`Registry::time_requests`]: super::Registry::time_requests
+//! [`Kernel::initialize_default_services`]: crate::Kernel::initialize_default_services
+//! [`StreamSender`]: super::StreamSender
+#![warn(missing_docs)]
+use core::{fmt, time::Duration};
+use maitake::time::Timer;
+
+#[cfg(debug_assertions)]
+use maitake::time::Instant;
+
+/// The longest [`Debug`](fmt::Debug) representation of a request that is
+/// recorded for latency warnings, in bytes. Longer representations are
+/// truncated.
+#[cfg(debug_assertions)]
+const DESCRIPTION_LEN: usize = 64;
+
+/// When a request was sent, and how long its service expects to take to reply
+/// to it.
+///
+/// Each [`Envelope`](super::Envelope) carries the `RequestTiming` of the
+/// request it contains, or is a reply to. See the [module-level
+/// documentation](self) for details.
+#[derive(Copy, Clone, Default)]
+pub struct RequestTiming {
+    #[cfg(debug_assertions)]
+    sent: Option<Sent>,
+}
+
+#[cfg(debug_assertions)]
+#[derive(Copy, Clone)]
+struct Sent {
+    timer: &'static Timer,
+    at: Instant,
+    target: Option<Target>,
+}
+
+#[cfg(debug_assertions)]
+#[derive(Copy, Clone)]
+struct Target {
+    max: Duration,
+    request: Description,
+}
+
+/// A request's `Debug` representation, truncated to fit in
+/// [`DESCRIPTION_LEN`] bytes.
+#[cfg(debug_assertions)]
+#[derive(Copy, Clone)]
+struct Description {
+    buf: [u8; DESCRIPTION_LEN],
+    len: usize,
+    truncated: bool,
+}
+
+// === impl RequestTiming ===
+
+impl RequestTiming {
+    /// Returns a `RequestTiming` for a request that was not timestamped.
+    #[must_use]
+    pub const fn none() -> Self {
+        Self {
+            #[cfg(debug_assertions)]
+            sent: None,
+        }
+    }
+
+    /// Returns a `RequestTiming` for a request sent now, if there is a
+    /// `timer` to timestamp it with.
+    #[cfg(debug_assertions)]
+    pub(super) fn sent_now(timer: Option<&'static Timer>) -> Self {
+        Self {
+            sent: timer.map(|timer| Sent {
+                timer,
+                at: timer.now(),
+                target: None,
+            }),
+        }
+    }
+
+    /// Returns a `RequestTiming` for a request sent now, if there is a
+    /// `timer` to timestamp it with.
+    #[cfg(not(debug_assertions))]
+    pub(super) fn sent_now(_: Option<&'static Timer>) -> Self {
+        Self::none()
+    }
+
+    /// Sets the service's target latency for a timestamped `request`.
+    pub(super) fn set_target<R: fmt::Debug>(&mut self, max: Duration, request: &R) {
+        #[cfg(debug_assertions)]
+        if let Some(ref mut sent) = self.sent {
+            let mut description = Description {
+                buf: [0; DESCRIPTION_LEN],
+                len: 0,
+                truncated: false,
+            };
+            // the only error is running out of space, which is recorded.
+            let _ = fmt::write(&mut description, format_args!("{request:?}"));
+            sent.target = Some(Target {
+                max,
+                request: description,
+            });
+        }
+        #[cfg(not(debug_assertions))]
+        let _ = (max, request);
+    }
+
+    /// Returns how much longer than its service's target latency it has been
+    /// since the request was sent, or [`None`] if the request has no target,
+    /// or its target has not been exceeded.
+    ///
+    /// This always returns [`None`] in release builds.
+    #[must_use]
+    pub fn late_by(&self) -> Option<Duration> {
+        #[cfg(debug_assertions)]
+        if let Some(Sent {
+            timer,
+            at,
+            target: Some(ref target),
+        }) = self.sent
+        {
+            let elapsed = timer.now().duration_since(at);
+            return elapsed.checked_sub(target.max).filter(|d| !d.is_zero());
+        }
+        None
+    }
+
+    /// Traces a warning if the reply to this request is late.
+    #[cfg_attr(not(debug_assertions), allow(unused_variables))]
+    pub(super) fn check(&self, svc: &str, service_id: u32, client_id: u32, request_id: u32) {
+        #[cfg(debug_assertions)]
+        if let Some(late_by) = self.late_by() {
+            if let Some(Sent {
+                target: Some(ref target),
+                ..
+            }) = self.sent
+            {
+                tracing::warn!(
+                    svc,
+                    service_id,
+                    client_id,
+                    request_id,
+                    max_latency = ?target.max,
+                    ?late_by,
+                    request = %target.request,
+                    "reply exceeded the service's max latency",
+                );
+            }
+        }
+    }
+}
+
+impl fmt::Debug for RequestTiming {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut s = f.debug_struct("RequestTiming");
+        #[cfg(debug_assertions)]
+        if let Some(ref sent) = self.sent {
+            s.field("sent_at", &sent.at);
+            if let Some(ref target) = sent.target {
+                s.field("max_latency", &target.max)
+                    .field("request", &format_args!("{}", target.request));
+            }
+        }
+        s.finish()
+    }
+}
+
+// === impl Description ===
+
+#[cfg(debug_assertions)]
+impl fmt::Write for Description {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            let end = self.len + c.len_utf8();
+            if end > DESCRIPTION_LEN {
+                self.truncated = true;
+                return Err(fmt::Error);
+            }
+            c.encode_utf8(&mut self.buf[self.len..end]);
+            self.len = end;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(debug_assertions)]
+impl fmt::Display for Description {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // only whole characters are written, so this is always valid UTF-8.
+        let s = core::str::from_utf8(&self.buf[..self.len]).unwrap_or_default();
+        f.write_str(s)?;
+        if self.truncated {
+            f.write_str("...")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(test, debug_assertions))]
+mod tests {
+    use super::*;
+
+    fn describe(request: &impl fmt::Debug) -> std::string::String {
+        let mut description = Description {
+            buf: [0; DESCRIPTION_LEN],
+            len: 0,
+            truncated: false,
+        };
+        let _ = fmt::write(&mut description, format_args!("{request:?}"));
+        std::string::ToString::to_string(&description)
+    }
+
+    #[test]
+    fn short_description() {
+        assert_eq!(describe(&Some(42)), "Some(42)");
+    }
+
+    #[test]
+    fn truncated_description() {
+        let description = describe(&[0u8; 64]);
+        assert!(description.ends_with("..."), "{description}");
+        assert_eq!(description.len(), DESCRIPTION_LEN + 3);
+    }
+
+    #[test]
+    fn truncates_whole_chars() {
+        // each `é` is two bytes, and the opening quote is one, so the last `é`
+        // that would fit is cut in half.
+        let description = describe(&"é".repeat(40));
+        assert!(description.ends_with("é..."), "{description}");
+        assert_eq!(description.len(), DESCRIPTION_LEN - 1 + 3);
+    }
+}
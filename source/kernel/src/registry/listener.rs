@@ -1,17 +1,27 @@
 //! A [`Listener`] is used by a [`RegisteredDriver`] to [accept incoming
 //! connections](Handshake) from clients.
 #![warn(missing_docs)]
-use super::{Message, RegisteredDriver};
+use super::{Capabilities, Message, RateLimit, RegisteredDriver, RequestTiming};
 use crate::comms::{
-    kchannel::{KChannel, KConsumer, KProducer},
+    kchannel::{ErasedKProducer, KChannel, KConsumer, KProducer},
     oneshot,
 };
+use core::{fmt, time::Duration};
 use futures::{select_biased, FutureExt};
+use maitake::sync::{blocking::Mutex, spin::Spinlock};
+use mnemos_alloc::containers::Arc;
+
+/// Where a [`RequestStream`] publishes its request channel, so that the
+/// registry can report how full it is.
+///
+/// This is shared between a [`Listener`] and its [`Registration`].
+pub(super) type RequestQueue = Arc<Mutex<Option<ErasedKProducer>, Spinlock>>;
 
 /// A listener for incoming connection [`Handshake`]s to a [`RegisteredDriver`].
 #[must_use = "a `Listener` does nothing if incoming connections are not accepted"]
 pub struct Listener<D: RegisteredDriver> {
     rx: KConsumer<Handshake<D>>,
+    req_queue: RequestQueue,
 }
 
 /// A registration for a [`RegisteredDriver`]. This type is provided to
@@ -23,6 +33,8 @@ pub struct Listener<D: RegisteredDriver> {
 #[must_use = "a `Registration` does nothing if not registered with a `Registry`"]
 pub struct Registration<D: RegisteredDriver> {
     pub(super) tx: KProducer<Handshake<D>>,
+    pub(super) rate_limit: Option<RateLimit>,
+    pub(super) req_queue: RequestQueue,
 }
 
 /// A connection request received from a [`Listener`].
@@ -54,7 +66,7 @@ pub struct Handshake<D: RegisteredDriver> {
 /// Accepts or rejects an incoming connection [`Handshake`].
 #[must_use = "an `Accept` does nothing if not `accept`ed or `reject`ed"]
 pub struct Accept<D: RegisteredDriver> {
-    pub(super) reply: oneshot::Sender<Result<Channel<D>, D::ConnectError>>,
+    pub(super) reply: oneshot::Sender<Result<(Channel<D>, Capabilities), D::ConnectError>>,
 }
 
 /// A stream of incoming requests from all clients.
@@ -89,6 +101,8 @@ pub struct Accept<D: RegisteredDriver> {
 pub struct RequestStream<D: RegisteredDriver> {
     chan: KConsumer<Message<D>>,
     listener: Listener<D>,
+    capabilities: Capabilities,
+    max_latency: Option<MaxLatency<D>>,
 }
 
 /// Errors returned by [`Handshake::accept`], [`Accept::accept`],
@@ -101,6 +115,20 @@ pub enum AcceptError {
 
 type Channel<D> = KProducer<Message<D>>;
 
+/// A [`RequestStream`]'s latency target, and how to record it in a request.
+struct MaxLatency<D: RegisteredDriver> {
+    max: Duration,
+    set_target: fn(&mut RequestTiming, Duration, &D::Request),
+}
+
+impl<D: RegisteredDriver> Clone for MaxLatency<D> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<D: RegisteredDriver> Copy for MaxLatency<D> {}
+
 // === impl Listener ===
 
 impl<D: RegisteredDriver> Listener<D> {
@@ -111,8 +139,13 @@ impl<D: RegisteredDriver> Listener<D> {
     /// to send a connection.
     pub async fn new(incoming_capacity: usize) -> (Self, Registration<D>) {
         let (tx, rx) = KChannel::new(incoming_capacity).split();
-        let registration = Registration { tx };
-        let listener = Self { rx };
+        let req_queue = Arc::new(Mutex::new_with_raw_mutex(None, Spinlock::new())).await;
+        let registration = Registration {
+            tx,
+            rate_limit: None,
+            req_queue: req_queue.clone(),
+        };
+        let listener = Self { rx, req_queue };
         (listener, registration)
     }
 
@@ -164,9 +197,30 @@ impl<D: RegisteredDriver> Listener<D> {
     /// [`Hello`]: RegisteredDriver::Hello
     pub async fn into_request_stream(self, capacity: usize) -> RequestStream<D> {
         let chan = KChannel::new(capacity).into_consumer();
+        // every client shares this channel, so let the registry report how
+        // full it is.
+        *self.req_queue.lock() = Some(chan.producer().type_erase());
         RequestStream {
             chan,
             listener: self,
+            capabilities: Capabilities::NONE,
+            max_latency: None,
+        }
+    }
+}
+
+// === impl Registration ===
+
+impl<D: RegisteredDriver> Registration<D> {
+    /// Limits how quickly each client connected to this service may send
+    /// requests.
+    ///
+    /// See the [`rate_limit`](super::rate_limit) module for details.
+    #[must_use]
+    pub fn with_rate_limit(self, limit: RateLimit) -> Self {
+        Self {
+            rate_limit: Some(limit),
+            ..self
         }
     }
 }
@@ -191,6 +245,18 @@ impl<D: RegisteredDriver> Handshake<D> {
         self.accept.accept(channel)
     }
 
+    /// Accept the connection, returning the provided `channel` to the client
+    /// and advertising `capabilities` on this connection.
+    ///
+    /// See [`Accept::accept_with`] for details.
+    pub fn accept_with(
+        self,
+        channel: Channel<D>,
+        capabilities: Capabilities,
+    ) -> Result<(), AcceptError> {
+        self.accept.accept_with(channel, capabilities)
+    }
+
     /// Reject the connection, returning the provided `error` to the client.
     ///
     /// # Returns
@@ -221,7 +287,27 @@ impl<D: RegisteredDriver> Accept<D> {
     ///   connection (and may or may not still exist), and the service may
     ///   ignore this connection request.
     pub fn accept(self, channel: Channel<D>) -> Result<(), AcceptError> {
-        match self.reply.send(Ok(channel)) {
+        self.accept_with(channel, Capabilities::NONE)
+    }
+
+    /// Accept the connection, returning the provided `channel` to the client
+    /// and advertising `capabilities` on this connection.
+    ///
+    /// The client can check which capabilities were advertised using
+    /// [`KernelHandle::capabilities`]. Connections accepted using
+    /// [`Accept::accept`] advertise [`Capabilities::NONE`].
+    ///
+    /// # Returns
+    ///
+    /// The same as [`Accept::accept`].
+    ///
+    /// [`KernelHandle::capabilities`]: super::KernelHandle::capabilities
+    pub fn accept_with(
+        self,
+        channel: Channel<D>,
+        capabilities: Capabilities,
+    ) -> Result<(), AcceptError> {
+        match self.reply.send(Ok((channel, capabilities))) {
             Ok(()) => Ok(()),
             Err(oneshot::ReusableError::ChannelClosed) => Err(AcceptError::Canceled),
             Err(error) => unreachable!(
@@ -252,6 +338,41 @@ impl<D: RegisteredDriver> Accept<D> {
 // === impl RequestStream ===
 
 impl<D: RegisteredDriver> RequestStream<D> {
+    /// Advertises `capabilities` on every connection accepted by this
+    /// `RequestStream`.
+    ///
+    /// See the [`capabilities`](super::capabilities) module for details.
+    #[must_use]
+    pub fn with_capabilities(self, capabilities: Capabilities) -> Self {
+        Self {
+            capabilities,
+            ..self
+        }
+    }
+
+    /// Declares the longest this service expects a client to wait for a reply
+    /// to a request, or [`None`] for no target.
+    ///
+    /// In debug builds, a warning including the request's [`Debug`]
+    /// representation is traced whenever a reply to a request received from
+    /// this `RequestStream` is sent later than this. See the
+    /// [`latency`](super::latency) module for details.
+    ///
+    /// [`Debug`]: fmt::Debug
+    #[must_use]
+    pub fn with_max_latency(self, max: Option<Duration>) -> Self
+    where
+        D::Request: fmt::Debug,
+    {
+        Self {
+            max_latency: max.map(|max| MaxLatency {
+                max,
+                set_target: RequestTiming::set_target::<D::Request>,
+            }),
+            ..self
+        }
+    }
+
     /// Returns the next incoming message, accepting any new connections until a
     /// message is received.
     ///
@@ -268,7 +389,7 @@ impl<D: RegisteredDriver> RequestStream<D> {
             let conn = select_biased! {
                 msg = self.chan.dequeue_async().fuse() => {
                     match msg {
-                        Ok(msg) => return msg,
+                        Ok(msg) => return self.received(msg),
                         Err(_) => {
                             // if the request stream is "closed", that just
                             // means that all the senders are dropped. That
@@ -286,9 +407,28 @@ impl<D: RegisteredDriver> RequestStream<D> {
             };
 
             tracing::trace!("accepting new connection...");
-            if conn.accept(self.chan.producer()).is_err() {
+            if conn
+                .accept_with(self.chan.producer(), self.capabilities)
+                .is_err()
+            {
                 tracing::debug!("incoming connection canceled");
             }
         }
     }
+
+    /// Returns the next incoming message if one has already been received,
+    /// without waiting.
+    ///
+    /// Unlike [`RequestStream::next_request`], this does not accept new
+    /// connections.
+    pub fn try_next_request(&self) -> Option<Message<D>> {
+        self.chan.dequeue_sync().map(|msg| self.received(msg))
+    }
+
+    fn received(&self, mut msg: Message<D>) -> Message<D> {
+        if let Some(MaxLatency { max, set_target }) = self.max_latency {
+            set_target(&mut msg.msg.timing, max, &msg.msg.body);
+        }
+        msg
+    }
 }
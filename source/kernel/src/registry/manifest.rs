@@ -0,0 +1,316 @@
+//! Machine-readable manifests of driver services.
+//!
+//! Host tools, such as `crowtty`, need to know which service a UUID belongs
+//! to, and whether the service on the other end of a link speaks the same
+//! messages as the tool was built against. A [`Manifest`] lists, for each
+//! service, its:
+//!
+//! - name (the path of its [`RegisteredDriver`] type),
+//! - [UUID](RegisteredDriver::UUID), and
+//! - [schema hash](ManifestEntry::schema), which identifies its message
+//!   types.
+//!
+//! [`Manifest::kernel`] returns the manifest of every service defined by the
+//! kernel, and platforms may add their own services using [`Manifest::with`].
+//!
+//! A manifest is written out (using its [`fmt::Display`] implementation) as
+//! one line per service, with the UUID, the schema hash as 16 hex digits, and
+//! the name, separated by single spaces:
+//!
+//! ```text
+//! 54c983fa-736f-4223-b90d-c4360a308647 1ffc59f3a009d43e kernel::services::serial_mux::SerialMuxService
+//! ```
+//!
+//! The kernel's manifest can be generated on the host by running
+//! `cargo run -p mnemos --example service-manifest`.
+//!
+//! # UUID Collisions
+//!
+//! Two services registered with the same UUID can't both be registered with
+//! the [`Registry`](super::Registry), and the second will fail to start. A
+//! collision between the kernel's own UUIDs is caught at compile time, and
+//! platform crates which define their own services can check their UUIDs
+//! against the kernel's using [`known_uuids::assert_unique`]. At runtime,
+//! [`Manifest::check`] finds collisions in a manifest.
+//!
+//! [`known_uuids::assert_unique`]: super::known_uuids::assert_unique
+use core::{any::type_name, fmt};
+
+use mnemos_alloc::containers::FixedVec;
+use uuid::Uuid;
+
+use super::RegisteredDriver;
+use crate::services::{
+    alarm::AlarmService,
+    buffer_pool::BufferPoolService,
+    clipboard::ClipboardService,
+    device_identity::DeviceIdentityService,
+    emb_display::EmbDisplayService,
+    entropy::EntropyService,
+    flash::FlashService,
+    forth_spawnulator::SpawnulatorService,
+    i2c::I2cService,
+    i2c_target::I2cTargetService,
+    keyboard::{mux::KeyboardMuxService, KeyboardService},
+    network::NetworkService,
+    notification::NotificationService,
+    pointer::{mux::PointerMuxService, PointerService},
+    sdmmc::SdmmcService,
+    serial_console::SerialConsoleService,
+    serial_mux::SerialMuxService,
+    simple_serial::SimpleSerialService,
+    smart_led::SmartLedService,
+    wifi_buddy::WifiBuddyService,
+};
+
+/// Every service defined by the kernel.
+///
+/// When a new kernel service is added, it should be added here, as well as to
+/// [`known_uuids::ALL`](super::known_uuids::ALL).
+static KERNEL: &[fn() -> ManifestEntry] = &[
+    ManifestEntry::of::<SerialMuxService>,
+    ManifestEntry::of::<SimpleSerialService>,
+    ManifestEntry::of::<SpawnulatorService>,
+    ManifestEntry::of::<I2cService>,
+    ManifestEntry::of::<KeyboardService>,
+    ManifestEntry::of::<KeyboardMuxService>,
+    ManifestEntry::of::<EmbDisplayService>,
+    ManifestEntry::of::<SdmmcService>,
+    ManifestEntry::of::<BufferPoolService>,
+    ManifestEntry::of::<SmartLedService>,
+    ManifestEntry::of::<ClipboardService>,
+    ManifestEntry::of::<NetworkService>,
+    ManifestEntry::of::<I2cTargetService>,
+    ManifestEntry::of::<PointerService>,
+    ManifestEntry::of::<PointerMuxService>,
+    ManifestEntry::of::<AlarmService>,
+    ManifestEntry::of::<WifiBuddyService>,
+    ManifestEntry::of::<EntropyService>,
+    ManifestEntry::of::<DeviceIdentityService>,
+    ManifestEntry::of::<FlashService>,
+    ManifestEntry::of::<NotificationService>,
+    ManifestEntry::of::<SerialConsoleService>,
+];
+
+/// A list of driver services.
+///
+/// See the [module-level documentation](self) for details.
+pub struct Manifest {
+    entries: FixedVec<ManifestEntry>,
+}
+
+/// A single service in a [`Manifest`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+pub struct ManifestEntry {
+    /// The name of the service's [`RegisteredDriver`] type.
+    pub name: &'static str,
+    /// The service's [UUID](RegisteredDriver::UUID).
+    pub uuid: Uuid,
+    /// A hash of the names of the service's message types.
+    ///
+    /// This is a 64-bit FNV-1a hash of the [type names] of the service's
+    /// [`Request`], [`Response`], [`Error`], [`Hello`], and [`ConnectError`]
+    /// types. It changes when one of those types is replaced with a
+    /// different type, renamed, or moved to another module, but *not* when a
+    /// type's fields or variants change. Type names are not guaranteed to be
+    /// the same across compiler versions, so schema hashes should only be
+    /// compared between builds made with the same toolchain.
+    ///
+    /// [type names]: core::any::type_name
+    /// [`Request`]: RegisteredDriver::Request
+    /// [`Response`]: RegisteredDriver::Response
+    /// [`Error`]: RegisteredDriver::Error
+    /// [`Hello`]: RegisteredDriver::Hello
+    /// [`ConnectError`]: RegisteredDriver::ConnectError
+    pub schema: u64,
+}
+
+/// Two services in a [`Manifest`] have the same UUID.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Collision {
+    /// The UUID shared by both services.
+    pub uuid: Uuid,
+    /// The name of the service which appears first in the manifest.
+    pub first: &'static str,
+    /// The name of the service which appears second in the manifest.
+    pub second: &'static str,
+}
+
+// === impl Manifest ===
+
+impl Manifest {
+    /// Returns a manifest of every service defined by the kernel.
+    pub fn kernel() -> Self {
+        let mut entries = FixedVec::try_new(KERNEL.len())
+            .expect("allocating the kernel's service manifest should succeed");
+        for entry in KERNEL {
+            entries
+                .try_push(entry())
+                .expect("manifest was allocated with enough capacity");
+        }
+        Self { entries }
+    }
+
+    /// Returns this manifest with the service `RD` added to the end.
+    #[must_use]
+    pub fn with<RD: RegisteredDriver>(self) -> Self {
+        let mut entries = FixedVec::try_new(self.entries.as_slice().len() + 1)
+            .expect("allocating a service manifest should succeed");
+        entries
+            .try_extend_from_slice(self.entries.as_slice())
+            .expect("manifest was allocated with enough capacity");
+        entries
+            .try_push(ManifestEntry::of::<RD>())
+            .expect("manifest was allocated with enough capacity");
+        Self { entries }
+    }
+
+    /// Returns the services in this manifest.
+    #[must_use]
+    pub fn entries(&self) -> &[ManifestEntry] {
+        self.entries.as_slice()
+    }
+
+    /// Returns the entry for the service with the given UUID, if it is in
+    /// this manifest.
+    #[must_use]
+    pub fn get(&self, uuid: Uuid) -> Option<&ManifestEntry> {
+        self.entries().iter().find(|entry| entry.uuid == uuid)
+    }
+
+    /// Checks that no two services in this manifest have the same UUID.
+    ///
+    /// # Returns
+    ///
+    /// - [`Ok`]`(())` if every service's UUID is unique.
+    /// - [`Err`]`(`[`Collision`]`)` with the first two services found which
+    ///   have the same UUID.
+    pub fn check(&self) -> Result<(), Collision> {
+        let entries = self.entries();
+        for (i, first) in entries.iter().enumerate() {
+            if let Some(second) = entries[i + 1..].iter().find(|e| e.uuid == first.uuid) {
+                return Err(Collision {
+                    uuid: first.uuid,
+                    first: first.name,
+                    second: second.name,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Manifest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for entry in self.entries() {
+            writeln!(f, "{entry}")?;
+        }
+        Ok(())
+    }
+}
+
+// === impl ManifestEntry ===
+
+impl ManifestEntry {
+    /// Returns the manifest entry for the service `RD`.
+    #[must_use]
+    pub fn of<RD: RegisteredDriver>() -> Self {
+        let mut hasher = Fnv1a::new();
+        hasher.write(type_name::<RD::Request>());
+        hasher.write(type_name::<RD::Response>());
+        hasher.write(type_name::<RD::Error>());
+        hasher.write(type_name::<RD::Hello>());
+        hasher.write(type_name::<RD::ConnectError>());
+        Self {
+            name: type_name::<RD>(),
+            uuid: RD::UUID,
+            schema: hasher.finish(),
+        }
+    }
+}
+
+impl fmt::Display for ManifestEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {:016x} {}", self.uuid, self.schema, self.name)
+    }
+}
+
+// === impl Collision ===
+
+impl fmt::Display for Collision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "services {} and {} both have the UUID {}",
+            self.first, self.second, self.uuid
+        )
+    }
+}
+
+/// A 64-bit FNV-1a hasher, which is simple enough to reimplement in host tools
+/// and gives the same result on every platform.
+struct Fnv1a(u64);
+
+impl Fnv1a {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0000_0100_0000_01b3;
+
+    fn new() -> Self {
+        Self(Self::OFFSET_BASIS)
+    }
+
+    /// Hashes `name`, followed by a NUL byte, so that the boundaries between
+    /// names are part of the hash.
+    fn write(&mut self, name: &str) {
+        for &byte in name.as_bytes().iter().chain(&[0]) {
+            self.0 ^= byte as u64;
+            self.0 = self.0.wrapping_mul(Self::PRIME);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::registry::{known_uuids, tests::TestService};
+
+    #[test]
+    fn kernel_services_are_unique() {
+        let manifest = Manifest::kernel();
+        if let Err(collision) = manifest.check() {
+            panic!("{collision}");
+        }
+    }
+
+    #[test]
+    fn kernel_services_are_known() {
+        let manifest = Manifest::kernel();
+        for entry in manifest.entries() {
+            assert!(
+                known_uuids::ALL.contains(&entry.uuid),
+                "{} is missing from `known_uuids::ALL`",
+                entry.name
+            );
+        }
+    }
+
+    #[test]
+    fn finds_collisions() {
+        let manifest = Manifest::kernel().with::<TestService>();
+        assert_eq!(manifest.check(), Ok(()));
+
+        let manifest = manifest.with::<SerialMuxService>();
+        assert_eq!(
+            manifest.check(),
+            Err(Collision {
+                uuid: known_uuids::kernel::SERIAL_MUX,
+                first: type_name::<SerialMuxService>(),
+                second: type_name::<SerialMuxService>(),
+            })
+        );
+    }
+}
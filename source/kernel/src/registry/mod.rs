@@ -3,10 +3,14 @@ use core::{
     fmt,
     marker::PhantomData,
     mem, ptr,
+    time::Duration,
 };
 
 use crate::comms::{kchannel, oneshot::Reusable};
-use maitake::sync::{RwLock, WaitQueue};
+use maitake::{
+    sync::{blocking::Mutex, spin::Spinlock, RwLock, WaitQueue},
+    time::Timer,
+};
 use mnemos_alloc::containers::FixedVec;
 use portable_atomic::{AtomicU32, Ordering};
 use postcard::experimental::max_size::MaxSize;
@@ -17,13 +21,27 @@ pub use uuid::{uuid, Uuid};
 
 use crate::comms::{
     bbq,
-    kchannel::{ErasedKProducer, KProducer},
+    kchannel::{ErasedKProducer, KChannel, KConsumer, KProducer},
     oneshot::{ReusableError, Sender},
 };
 
+pub mod capabilities;
+pub mod coalesce;
+pub mod latency;
 pub mod listener;
-pub use self::listener::{Listener, Registration};
+pub mod manifest;
+pub mod rate_limit;
+use self::rate_limit::Limiter;
+pub use self::{
+    capabilities::Capabilities,
+    coalesce::Coalescer,
+    latency::RequestTiming,
+    listener::{Listener, Registration},
+    rate_limit::RateLimit,
+};
 
+#[cfg(test)]
+mod conformance;
 #[cfg(test)]
 mod tests;
 
@@ -45,11 +63,25 @@ pub mod known_uuids {
         pub const KEYBOARD_MUX: Uuid = uuid!("70861d1c-9f01-4e9b-89e6-ede77d8f26d8");
         pub const EMB_DISPLAY_V2: Uuid = uuid!("aa6a2af8-afd8-40e3-83c2-2c501c698aa8");
         pub const SDMMC: Uuid = uuid!("9f4f8244-c986-4212-982e-d35890260de4");
+        pub const BUFFER_POOL: Uuid = uuid!("d3b3c5a2-1f0e-4d6b-9a57-6c2e8f41b7d0");
+        pub const SMART_LED: Uuid = uuid!("88bc8d5a-10fa-4837-bbef-a7ee5d8cf5ad");
+        pub const CLIPBOARD: Uuid = uuid!("3df93c86-6ced-4d37-ae76-1b768bbb607e");
+        pub const NETWORK: Uuid = uuid!("ca176b34-13fb-4789-8fe1-1ba6aeaafb05");
+        pub const I2C_TARGET: Uuid = uuid!("6f1e0c52-7a9d-4b8e-a3c4-2d5b91e07f36");
+        pub const POINTER: Uuid = uuid!("b1d5a7e2-4c3f-4e8a-9d61-0f27c84a3e95");
+        pub const POINTER_MUX: Uuid = uuid!("e3f08c4b-92a6-4d17-b5e0-6a8d1c7f2b49");
+        pub const ALARM: Uuid = uuid!("03f134fa-9db5-49c3-8025-b90a979734fe");
+        pub const WIFI_BUDDY: Uuid = uuid!("3424c7b6-2bf8-4b61-80f8-e64061cb6b85");
+        pub const ENTROPY: Uuid = uuid!("478cb176-04dd-4ca3-87b1-9b61c6ae8809");
+        pub const DEVICE_IDENTITY: Uuid = uuid!("9c2e4b7d-61a3-4f08-b5d9-3e7a0c18f264");
+        pub const FLASH: Uuid = uuid!("5e0b9a73-2d4c-4f61-8a1e-c97b3f260d58");
+        pub const NOTIFICATION: Uuid = uuid!("c4a81f26-5b3e-4d9a-8e07-2f6b19d3a7c5");
+        pub const SERIAL_CONSOLE: Uuid = uuid!("7b3d2e90-8c41-4f5a-b6e2-1d09a4c7f358");
     }
 
     // In case you need to iterate over every UUID
     #[allow(deprecated)]
-    pub static ALL: &[Uuid] = &[
+    pub const ALL: &[Uuid] = &[
         kernel::SERIAL_MUX,
         kernel::SIMPLE_SERIAL_PORT,
         kernel::EMB_DISPLAY,
@@ -58,7 +90,70 @@ pub mod known_uuids {
         kernel::KEYBOARD,
         kernel::KEYBOARD_MUX,
         kernel::EMB_DISPLAY_V2,
+        kernel::SDMMC,
+        kernel::BUFFER_POOL,
+        kernel::SMART_LED,
+        kernel::CLIPBOARD,
+        kernel::NETWORK,
+        kernel::I2C_TARGET,
+        kernel::POINTER,
+        kernel::POINTER_MUX,
+        kernel::ALARM,
+        kernel::WIFI_BUDDY,
+        kernel::ENTROPY,
+        kernel::DEVICE_IDENTITY,
+        kernel::FLASH,
+        kernel::NOTIFICATION,
+        kernel::SERIAL_CONSOLE,
     ];
+
+    // Fail the build if two kernel services have the same UUID.
+    const _: () = assert_unique(&[]);
+
+    /// Checks that none of `uuids` are the same as each other, or as any
+    /// UUID in [`ALL`].
+    ///
+    /// This is a `const fn`, so that platform crates which define their own
+    /// driver services can check their services' UUIDs at compile time:
+    ///
+    /// ```rust
+    /// use kernel::registry::{known_uuids, uuid, Uuid};
+    ///
+    /// const MY_SERVICE: Uuid = uuid!("0c5b6b8e-3a4f-4d27-9e61-b82f7d1a9c04");
+    ///
+    /// // Fails to compile if `MY_SERVICE` collides with a kernel service.
+    /// const _: () = known_uuids::assert_unique(&[MY_SERVICE]);
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// If two UUIDs are the same. When evaluated in a `const` item, this
+    /// is a compile error.
+    pub const fn assert_unique(uuids: &[Uuid]) {
+        let mut i = 0;
+        while i < ALL.len() + uuids.len() {
+            let uuid = nth(uuids, i);
+            let mut j = i + 1;
+            while j < ALL.len() + uuids.len() {
+                assert!(
+                    uuid != nth(uuids, j),
+                    "two driver services have the same UUID"
+                );
+                j += 1;
+            }
+            i += 1;
+        }
+    }
+
+    /// Returns the `n`th UUID in [`ALL`] followed by `uuids`, as a `u128`
+    /// (since `Uuid`'s `PartialEq` impl can't be called in a `const fn`).
+    const fn nth(uuids: &[Uuid], n: usize) -> u128 {
+        if n < ALL.len() {
+            ALL[n].as_u128()
+        } else {
+            uuids[n - ALL.len()].as_u128()
+        }
+    }
 }
 
 /// A marker trait designating a registerable driver service.
@@ -124,6 +219,34 @@ pub struct Registry {
     items: RwLock<FixedVec<RegistryItem>>,
     counter: AtomicU32,
     service_added: WaitQueue,
+    /// Watches new [`KernelHandle`]s' request queues, if set.
+    stall_watch: Mutex<Option<StallWatch>, Spinlock>,
+    /// Timestamps requests sent by new [`KernelHandle`]s, if set.
+    request_timer: Mutex<Option<&'static Timer>, Spinlock>,
+    /// Enforces services' [`RateLimit`]s on new connections, if set.
+    rate_timer: Mutex<Option<&'static Timer>, Spinlock>,
+}
+
+/// Settings for watching services' request queues.
+///
+/// A service whose request queue stays full has almost always stopped
+/// handling requests, so clients waiting on a full queue trace a warning.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RequestQueueSettings {
+    /// Should clients warn about full request queues?
+    #[serde(default = "RequestQueueSettings::default_enabled")]
+    pub enabled: bool,
+    /// Warn when a client has waited on a full request queue for this long.
+    /// Defaults to 1 second.
+    #[serde(default = "RequestQueueSettings::default_stall_warning")]
+    pub stall_warning: Duration,
+}
+
+/// How [`KernelHandle`]s watch their service's request queue.
+#[derive(Copy, Clone)]
+struct StallWatch {
+    timer: &'static Timer,
+    warn_after: Duration,
 }
 
 // TODO: This probably goes into the ABI crate, here is fine for now
@@ -164,6 +287,31 @@ impl<U: MaxSize, E: MaxSize> MaxSize for UserResponse<U, E> {
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub struct ServiceId(pub(crate) u32);
 
+impl ServiceId {
+    /// Returns the numeric value of this ID.
+    #[must_use]
+    pub fn get(self) -> u32 {
+        self.0
+    }
+}
+
+/// A snapshot of a registered driver service, returned by
+/// [`Registry::for_each_service`].
+#[derive(Debug, Copy, Clone)]
+pub struct ServiceInfo {
+    /// The service's [`RegisteredDriver::UUID`].
+    pub uuid: Uuid,
+    /// The service's ID in this registry.
+    pub service_id: ServiceId,
+    /// Whether the service may be connected to from userspace.
+    pub userspace: bool,
+    /// How full the service's incoming connection queue is.
+    pub conn_queue: kchannel::ChannelDepth,
+    /// How full the service's request queue is, if the service serves every
+    /// client from a single [`RequestStream`].
+    pub req_queue: Option<kchannel::ChannelDepth>,
+}
+
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub struct ClientId(pub(crate) u32);
 
@@ -209,6 +357,8 @@ pub struct Envelope<P> {
     service_id: ServiceId,
     client_id: ClientId,
     request_id: RequestResponseId,
+    span_cx: SpanContext,
+    timing: RequestTiming,
 }
 
 pub struct OpenEnvelope<P> {
@@ -216,6 +366,30 @@ pub struct OpenEnvelope<P> {
     service_id: ServiceId,
     client_id: ClientId,
     request_id: RequestResponseId,
+    timing: RequestTiming,
+}
+
+/// The tracing span that a request was sent from.
+///
+/// When the kernel's "trace-context" feature is enabled, each request
+/// [`Envelope`] sent by a [`KernelHandle`] carries the ID of the span that
+/// was current when it was sent. The service handling the request can then
+/// enter [`Envelope::span`] while handling it, so that its traces are recorded
+/// as children of the client's span. This allows a trace viewer (such as
+/// `crowtty`) to follow a request from one service to another, rather than
+/// the trace ending at the registry channel.
+///
+/// Only the span's ID is propagated, so that requests remain cheap to send.
+/// The client's span is not kept open until the request is handled, so a
+/// trace viewer may see the request's span after its parent has closed.
+///
+/// When the "trace-context" feature is disabled, this is a zero-sized type,
+/// and request spans are children of whichever span is current in the
+/// service.
+#[derive(Clone, Debug, Default)]
+pub struct SpanContext {
+    #[cfg(feature = "trace-context")]
+    parent: Option<tracing::span::Id>,
 }
 
 /// The [Message] kind represents a full reply/response sequence to
@@ -243,6 +417,11 @@ pub enum ReplyTo<RD: RegisteredDriver> {
     // without a serialization step
     OneShot(Sender<Envelope<Result<RD::Response, RD::Error>>>),
 
+    // This can be used to reply to another kernel entity with a stream of
+    // zero or more responses, without a serialization step. The stream ends
+    // when the service closes (or drops) the `StreamSender`.
+    Stream(StreamSender<RD>),
+
     // This can be used to reply to userspace. Responses are serialized
     // and sent over the bbq::MpscProducer
     Userspace {
@@ -251,6 +430,26 @@ pub enum ReplyTo<RD: RegisteredDriver> {
     },
 }
 
+/// The sending half of a [`ReplyTo::Stream`].
+///
+/// A service may send any number of responses using a `StreamSender`. Sending
+/// waits for space in the client's bounded [`ResponseStream`] buffer, so a
+/// slow client applies backpressure to the service. The stream is closed when
+/// the `StreamSender` is [closed](StreamSender::close) or dropped.
+pub struct StreamSender<RD: RegisteredDriver> {
+    tx: KProducer<Envelope<Result<RD::Response, RD::Error>>>,
+}
+
+/// The receiving half of a streaming request, returned by
+/// [`KernelHandle::request_stream`].
+///
+/// Dropping the `ResponseStream` closes the stream, causing any further
+/// [`StreamSender::send`] calls from the service to fail.
+#[must_use = "a `ResponseStream` does nothing if responses are not received"]
+pub struct ResponseStream<RD: RegisteredDriver> {
+    rx: KConsumer<Envelope<Result<RD::Response, RD::Error>>>,
+}
+
 #[derive(Debug, Eq, PartialEq)]
 pub enum ReplyError {
     KOnlyUserspaceResponse,
@@ -263,9 +462,11 @@ pub enum ReplyError {
 pub enum UserHandlerError {
     DeserializationFailed,
     QueueFull,
+    /// The client has exceeded the service's [`RateLimit`].
+    RateLimited,
 }
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
 pub enum RegistrationError {
     UuidAlreadyRegistered(Uuid),
     RegistryFull,
@@ -345,6 +546,8 @@ pub struct UserspaceHandle {
     req_deser: ErasedReqDeser,
     service_id: ServiceId,
     client_id: ClientId,
+    capabilities: Capabilities,
+    limiter: Option<Mutex<Limiter, Spinlock>>,
 }
 
 /// A KernelHandle is used to send typed messages to a kernelspace Driver
@@ -354,6 +557,10 @@ pub struct KernelHandle<RD: RegisteredDriver> {
     service_id: ServiceId,
     client_id: ClientId,
     request_ctr: u32,
+    capabilities: Capabilities,
+    stall_watch: Option<StallWatch>,
+    request_timer: Option<&'static Timer>,
+    limiter: Option<Limiter>,
 }
 
 type ErasedReqDeser = unsafe fn(
@@ -381,6 +588,8 @@ struct RegistryValue {
     conn_prod: ErasedKProducer,
     user_vtable: Option<UserVtable>,
     service_id: ServiceId,
+    rate_limit: Option<RateLimit>,
+    req_queue: listener::RequestQueue,
 }
 
 /// A [virtual function pointer table][vtable] (vtable) that specifies how
@@ -410,6 +619,29 @@ impl RegistryType {
     }
 }
 
+// RequestQueueSettings
+
+impl RequestQueueSettings {
+    pub const DEFAULT_STALL_WARNING: Duration = Duration::from_secs(1);
+
+    const fn default_enabled() -> bool {
+        true
+    }
+
+    const fn default_stall_warning() -> Duration {
+        Self::DEFAULT_STALL_WARNING
+    }
+}
+
+impl Default for RequestQueueSettings {
+    fn default() -> Self {
+        Self {
+            enabled: Self::default_enabled(),
+            stall_warning: Self::DEFAULT_STALL_WARNING,
+        }
+    }
+}
+
 // Registry
 
 impl Registry {
@@ -420,9 +652,52 @@ impl Registry {
             items: RwLock::new(items),
             counter: AtomicU32::new(0),
             service_added: WaitQueue::new(),
+            stall_watch: Mutex::new_with_raw_mutex(None, Spinlock::new()),
+            request_timer: Mutex::new_with_raw_mutex(None, Spinlock::new()),
+            rate_timer: Mutex::new_with_raw_mutex(None, Spinlock::new()),
         }
     }
 
+    /// Configures how [`KernelHandle`]s watch their service's request queue.
+    ///
+    /// This only applies to handles connected after it is called.
+    pub fn watch_request_queues(&self, timer: &'static Timer, settings: &RequestQueueSettings) {
+        let watch = settings.enabled.then_some(StallWatch {
+            timer,
+            warn_after: settings.stall_warning,
+        });
+        *self.stall_watch.lock() = watch;
+    }
+
+    /// Timestamps requests sent by [`KernelHandle`]s using `timer`, so that
+    /// replies can be checked against services' [latency targets].
+    ///
+    /// This does nothing in release builds, and only applies to handles
+    /// connected after it is called.
+    ///
+    /// [latency targets]: latency
+    pub fn time_requests(&self, timer: &'static Timer) {
+        if cfg!(debug_assertions) {
+            *self.request_timer.lock() = Some(timer);
+        }
+    }
+
+    /// Enforces services' [rate limits] using `timer`.
+    ///
+    /// This only applies to connections established after it is called.
+    ///
+    /// [rate limits]: rate_limit
+    pub fn enforce_rate_limits(&self, timer: &'static Timer) {
+        *self.rate_timer.lock() = Some(timer);
+    }
+
+    /// Returns a [`Limiter`] for a new connection to a service with the
+    /// given rate limit, if it should be enforced.
+    fn limiter(&self, rate_limit: Option<RateLimit>) -> Option<Limiter> {
+        let timer = (*self.rate_timer.lock())?;
+        rate_limit.map(|limit| Limiter::new(limit, timer))
+    }
+
     /// Bind a kernel-only [`Listener`] for a driver service of type `RD`.
     ///
     /// This is a helper method which creates a [`Listener`] using
@@ -486,6 +761,8 @@ impl Registry {
         &self,
         registration: listener::Registration<RD>,
     ) -> Result<(), RegistrationError> {
+        let rate_limit = registration.rate_limit;
+        let req_queue = registration.req_queue;
         let conn_prod = registration.tx.type_erase();
         let service_id = self.counter.fetch_add(1, Ordering::Relaxed);
         self.insert_item(RegistryItem {
@@ -495,11 +772,13 @@ impl Registry {
                 conn_prod,
                 user_vtable: None,
                 service_id: ServiceId(service_id),
+                rate_limit,
+                req_queue,
             },
         })
         .await?;
 
-        info!(uuid = ?RD::UUID, service_id, "Registered KOnly");
+        info!(uuid = ?RD::UUID, service_id, ?rate_limit, "Registered KOnly");
 
         Ok(())
     }
@@ -528,6 +807,8 @@ impl Registry {
         RD::Response: Serialize + DeserializeOwned,
     {
         let service_id = self.counter.fetch_add(1, Ordering::Relaxed);
+        let rate_limit = registration.rate_limit;
+        let req_queue = registration.req_queue;
         let conn_prod = registration.tx.type_erase();
         self.insert_item(RegistryItem {
             key: RD::UUID,
@@ -536,11 +817,13 @@ impl Registry {
                 conn_prod,
                 user_vtable: Some(UserVtable::new::<RD>()),
                 service_id: ServiceId(service_id),
+                rate_limit,
+                req_queue,
             },
         })
         .await?;
 
-        info!(uuid = ?RD::UUID, service_id, "Registered");
+        info!(uuid = ?RD::UUID, service_id, ?rate_limit, "Registered");
 
         Ok(())
     }
@@ -584,7 +867,7 @@ impl Registry {
         &self,
         hello: RD::Hello,
     ) -> Result<KernelHandle<RD>, ConnectError<RD>> {
-        let (tx, service_id) = {
+        let (tx, service_id, rate_limit) = {
             // /!\ WARNING: Load-bearing scope /!\
             //
             // We need to ensure that we only hold the lock on `self.items`
@@ -606,7 +889,7 @@ impl Registry {
                     .conn_prod
                     .clone_typed::<listener::Handshake<RD>>()
             };
-            (tx, item.value.service_id)
+            (tx, item.value.service_id, item.value.rate_limit)
         };
 
         // TODO(eliza): it would be nice if we could reuse the oneshot receiver
@@ -626,10 +909,10 @@ impl Registry {
             kchannel::EnqueueError::Full(_) => unreachable!("the channel should not be full, as we are using `enqueue_async`, which waits for capacity")
         })?;
         // ...and wait for a response with an established connection.
-        let prod = rx
+        let (prod, capabilities) = rx
             .receive()
             .await
-            // this is a `Reusable<Result<KProducer, RD::ConnectError>>>`, so
+            // this is a `Reusable<Result<(KProducer, Capabilities), RD::ConnectError>>>`, so
             // the outer `Result` is the error returned by `receive()`...
             .map_err(|_| ConnectError::DriverDead)?
             // ...and the inner `Result` is the error returned by the driver.
@@ -641,6 +924,10 @@ impl Registry {
             service_id,
             client_id: ClientId(client_id),
             request_ctr: 0,
+            capabilities,
+            stall_watch: *self.stall_watch.lock(),
+            request_timer: *self.request_timer.lock(),
+            limiter: self.limiter(rate_limit),
         });
 
         info!(
@@ -782,7 +1069,7 @@ impl Registry {
         RD::Request: Serialize + DeserializeOwned,
         RD::Response: Serialize + DeserializeOwned,
     {
-        let (vtable, conn_prod, service_id) = {
+        let (vtable, conn_prod, service_id, rate_limit) = {
             // /!\ WARNING: Load-bearing scope /!\
             //
             // We need to ensure that we only hold the lock on `self.items`
@@ -805,14 +1092,14 @@ impl Registry {
                 .ok_or(UserConnectError::NotUserspace)?;
             let conn_prod = item.value.conn_prod.clone();
             let service_id = item.value.service_id;
-            (vtable, conn_prod, service_id)
+            (vtable, conn_prod, service_id, item.value.rate_limit)
         };
 
         let mut handshake_result = mem::MaybeUninit::<UserHandshakeResult<RD>>::uninit();
         let outptr = ptr::NonNull::from(&mut handshake_result).cast::<()>();
 
         let handshake = unsafe { (vtable.handshake)(scheduler, user_hello, &conn_prod, outptr) };
-        let req_producer_leaked = match handshake.await {
+        let (req_producer_leaked, capabilities) = match handshake.await {
             // Outer `Result` is the `JoinError` from `maitake` --- it should
             // always succeed, because we own the task's joinhandle, and we
             // never cancel it.
@@ -826,7 +1113,8 @@ impl Registry {
                 // Safety: `handshake_result` is guaranteed to be initialized by
                 // `erased_handshake` if and only if its future completes with
                 // an `Ok(())`. and it did!
-                handshake_result.assume_init()?.type_erase()
+                let (prod, capabilities) = handshake_result.assume_init()?;
+                (prod.type_erase(), capabilities)
             },
         };
 
@@ -844,9 +1132,36 @@ impl Registry {
             req_deser: vtable.req_deser,
             service_id,
             client_id: ClientId(client_id),
+            capabilities,
+            limiter: self
+                .limiter(rate_limit)
+                .map(|limiter| Mutex::new_with_raw_mutex(limiter, Spinlock::new())),
         })
     }
 
+    /// Calls `f` with a snapshot of every registered driver service, in the
+    /// order they were registered.
+    ///
+    /// The registry is locked while `f` is called, so `f` must not attempt to
+    /// register or connect to services.
+    pub async fn for_each_service(&self, mut f: impl FnMut(&ServiceInfo)) {
+        let items = self.items.read().await;
+        for item in items.as_slice() {
+            f(&ServiceInfo {
+                uuid: item.key,
+                service_id: item.value.service_id,
+                userspace: item.value.user_vtable.is_some(),
+                conn_queue: item.value.conn_prod.depth(),
+                req_queue: item
+                    .value
+                    .req_queue
+                    .lock()
+                    .as_ref()
+                    .map(ErasedKProducer::depth),
+            });
+        }
+    }
+
     async fn insert_item(&self, item: RegistryItem) -> Result<(), RegistrationError> {
         {
             let mut items = self.items.write().await;
@@ -899,6 +1214,16 @@ impl Registry {
 
 // Envelope
 
+impl<P> Clone for OpenEnvelope<P> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+// An `OpenEnvelope` may be filled more than once, when sending multiple
+// responses on a `ReplyTo::Stream`.
+impl<P> Copy for OpenEnvelope<P> {}
+
 impl<P> OpenEnvelope<P> {
     pub fn fill(self, contents: P) -> Envelope<P> {
         Envelope {
@@ -906,6 +1231,8 @@ impl<P> OpenEnvelope<P> {
             service_id: self.service_id,
             client_id: self.client_id,
             request_id: self.request_id,
+            span_cx: SpanContext::none(),
+            timing: self.timing,
         }
     }
 }
@@ -918,6 +1245,7 @@ impl<P> Envelope<P> {
             service_id: self.service_id,
             client_id: self.client_id,
             request_id: RequestResponseId::new(self.request_id.id(), MessageKind::Response),
+            timing: self.timing,
         };
         (self.body, env)
     }
@@ -932,6 +1260,8 @@ impl<P> Envelope<P> {
             service_id: self.service_id,
             client_id: self.client_id,
             request_id: RequestResponseId::new(self.request_id.id(), MessageKind::Response),
+            span_cx: SpanContext::none(),
+            timing: self.timing,
         }
     }
 
@@ -950,9 +1280,84 @@ impl<P> Envelope<P> {
             service_id: self.service_id,
             client_id: self.client_id,
             request_id: RequestResponseId::new(self.request_id.id(), MessageKind::Response),
+            span_cx: SpanContext::none(),
+            timing: self.timing,
             body: f(self.body),
         }
     }
+
+    /// Returns the [`SpanContext`] this message was sent with.
+    #[must_use]
+    pub fn span_context(&self) -> &SpanContext {
+        &self.span_cx
+    }
+
+    /// Returns the [`RequestTiming`] of the request this message contains, or
+    /// is a reply to.
+    #[must_use]
+    pub fn timing(&self) -> &RequestTiming {
+        &self.timing
+    }
+
+    /// Returns a new span for handling this message.
+    ///
+    /// If the message was sent with a [`SpanContext`], the returned span is a
+    /// child of the sender's span. Otherwise, it is a child of the current
+    /// span. Services should [instrument] the handling of each request with
+    /// this span, so that requests can be traced across services.
+    ///
+    /// [instrument]: tracing::Instrument::instrument
+    pub fn span(&self) -> tracing::Span {
+        let service_id = self.service_id.0;
+        let client_id = self.client_id.0;
+        let request_id = self.request_id.id();
+        match self.span_cx.parent() {
+            Some(parent) => tracing::debug_span!(
+                parent: parent,
+                "request",
+                service_id,
+                client_id,
+                request_id
+            ),
+            None => tracing::debug_span!("request", service_id, client_id, request_id),
+        }
+    }
+}
+
+// SpanContext
+
+impl SpanContext {
+    /// Returns a `SpanContext` for the current span.
+    #[must_use]
+    pub fn current() -> Self {
+        Self {
+            #[cfg(feature = "trace-context")]
+            parent: tracing::Span::current().id(),
+        }
+    }
+
+    /// Returns a `SpanContext` with no parent span.
+    #[must_use]
+    pub const fn none() -> Self {
+        Self {
+            #[cfg(feature = "trace-context")]
+            parent: None,
+        }
+    }
+
+    /// Returns the ID of the span a request was sent from, if it is known.
+    #[cfg(feature = "trace-context")]
+    #[must_use]
+    pub fn parent(&self) -> Option<&tracing::span::Id> {
+        self.parent.as_ref()
+    }
+
+    /// Returns the ID of the span a request was sent from, if it is known.
+    #[cfg(not(feature = "trace-context"))]
+    #[must_use]
+    pub fn parent(&self) -> Option<&tracing::span::Id> {
+        None
+    }
 }
 
 // Message
@@ -987,6 +1392,12 @@ impl<RD: RegisteredDriver> ReplyTo<RD> {
             svc = %any::type_name::<RD>(),
             "Replying KOnly",
         );
+        envelope.timing.check(
+            any::type_name::<RD>(),
+            envelope.service_id.0,
+            envelope.client_id.0,
+            envelope.request_id.id(),
+        );
         match self {
             ReplyTo::KChannel(kprod) => {
                 kprod.enqueue_async(envelope).await?;
@@ -994,12 +1405,94 @@ impl<RD: RegisteredDriver> ReplyTo<RD> {
             ReplyTo::OneShot(sender) => {
                 sender.send(envelope)?;
             }
+            ReplyTo::Stream(stream) => {
+                // A single reply to a stream is a stream with one item; the
+                // stream is closed when `stream` is dropped.
+                stream.send(envelope).await?;
+            }
             ReplyTo::Userspace { .. } => return Err(ReplyError::KOnlyUserspaceResponse),
         }
         Ok(())
     }
 }
 
+impl<RD: RegisteredDriver> ReplyTo<RD> {
+    /// Returns the [`StreamSender`] if this is a [`ReplyTo::Stream`], or
+    /// gives back `self` if it is not.
+    ///
+    /// This may be used by services which produce multiple responses to a
+    /// request, such as subscriptions, to determine whether the client is
+    /// able to receive more than one response.
+    pub fn into_stream(self) -> Result<StreamSender<RD>, Self> {
+        match self {
+            ReplyTo::Stream(stream) => Ok(stream),
+            other => Err(other),
+        }
+    }
+}
+
+// StreamSender
+
+impl<RD: RegisteredDriver> StreamSender<RD> {
+    /// Send a response on the stream, waiting for buffer space if the client
+    /// has not yet received earlier responses.
+    ///
+    /// Returns [`ReplyError::ReplyChannelClosed`] if the client has dropped
+    /// its [`ResponseStream`].
+    pub async fn send(
+        &self,
+        envelope: Envelope<Result<RD::Response, RD::Error>>,
+    ) -> Result<(), ReplyError> {
+        trace!(
+            service_id = envelope.service_id.0,
+            client_id = envelope.client_id.0,
+            response_id = envelope.request_id.id(),
+            svc = %any::type_name::<RD>(),
+            "Replying on stream",
+        );
+        self.tx.enqueue_async(envelope).await?;
+        Ok(())
+    }
+
+    /// Close the stream. The client will receive any responses that were
+    /// already sent, and then the end of the stream.
+    ///
+    /// This is equivalent to dropping the `StreamSender`.
+    pub fn close(self) {
+        drop(self);
+    }
+}
+
+impl<RD: RegisteredDriver> Drop for StreamSender<RD> {
+    fn drop(&mut self) {
+        self.tx.close();
+    }
+}
+
+// ResponseStream
+
+impl<RD: RegisteredDriver> ResponseStream<RD> {
+    /// Await the next response from the service.
+    ///
+    /// Returns [`None`] once the service has closed the stream and all
+    /// responses sent before closing have been received.
+    pub async fn next(&self) -> Option<Envelope<Result<RD::Response, RD::Error>>> {
+        self.rx.dequeue_async().await.ok()
+    }
+
+    /// Returns the next response, if one has already been sent, without
+    /// waiting.
+    pub fn try_next(&self) -> Option<Envelope<Result<RD::Response, RD::Error>>> {
+        self.rx.dequeue_sync()
+    }
+}
+
+impl<RD: RegisteredDriver> Drop for ResponseStream<RD> {
+    fn drop(&mut self) {
+        self.rx.close();
+    }
+}
+
 impl<RD: RegisteredDriver> ReplyTo<RD>
 where
     RD::Response: Serialize + MaxSize,
@@ -1017,6 +1510,12 @@ where
             svc = %any::type_name::<RD>(),
             "Replying",
         );
+        envelope.timing.check(
+            any::type_name::<RD>(),
+            envelope.service_id.0,
+            envelope.client_id.0,
+            envelope.request_id.id(),
+        );
         match self {
             ReplyTo::KChannel(kprod) => {
                 kprod.enqueue_async(envelope).await?;
@@ -1026,6 +1525,7 @@ where
                 sender.send(envelope)?;
                 Ok(())
             }
+            ReplyTo::Stream(stream) => stream.send(envelope).await,
             ReplyTo::Userspace { nonce, outgoing } => {
                 let mut wgr = outgoing
                     .send_grant_exact(
@@ -1057,6 +1557,17 @@ impl UserspaceHandle {
         user_msg: UserRequest<'_>,
         user_ring: &bbq::MpscProducer,
     ) -> Result<(), UserHandlerError> {
+        if let Some(ref limiter) = self.limiter {
+            if let Some(retry_after) = limiter.lock().check() {
+                debug!(
+                    service_id = self.service_id.0,
+                    client_id = self.client_id.0,
+                    ?retry_after,
+                    "Userspace request rate limited",
+                );
+                return Err(UserHandlerError::RateLimited);
+            }
+        }
         unsafe {
             (self.req_deser)(
                 user_msg,
@@ -1067,26 +1578,65 @@ impl UserspaceHandle {
             )
         }
     }
+
+    /// Returns the [`Capabilities`] advertised by the service on this
+    /// connection.
+    #[must_use]
+    pub fn capabilities(&self) -> Capabilities {
+        self.capabilities
+    }
 }
 
 // KernelHandle
 
 impl<RD: RegisteredDriver> KernelHandle<RD> {
+    /// Returns the [`Capabilities`] advertised by the service on this
+    /// connection.
+    ///
+    /// See the [`capabilities`] module for details.
+    #[must_use]
+    pub fn capabilities(&self) -> Capabilities {
+        self.capabilities
+    }
+
+    /// Send a request to the service, with the given [`ReplyTo`].
+    ///
+    /// If the service has a [`RateLimit`], and this client has exceeded it,
+    /// this waits until the request may be sent.
     pub async fn send(&mut self, msg: RD::Request, reply: ReplyTo<RD>) -> Result<(), SendError> {
+        if let Some(ref mut limiter) = self.limiter {
+            if limiter.check().is_some() {
+                trace!(
+                    service_id = self.service_id.0,
+                    client_id = self.client_id.0,
+                    svc = %any::type_name::<RD>(),
+                    "Request rate limited; waiting",
+                );
+                limiter.acquire().await;
+            }
+        }
         let request_id = RequestResponseId::new(self.request_ctr, MessageKind::Request);
         self.request_ctr = self.request_ctr.wrapping_add(1);
-        self.prod
-            .enqueue_async(Message {
-                msg: Envelope {
-                    body: msg,
-                    service_id: self.service_id,
-                    client_id: self.client_id,
-                    request_id,
-                },
-                reply,
-            })
-            .await
-            .map_err(|_| SendError::Closed)?;
+        let msg = Message {
+            msg: Envelope {
+                body: msg,
+                service_id: self.service_id,
+                client_id: self.client_id,
+                request_id,
+                span_cx: SpanContext::current(),
+                timing: RequestTiming::sent_now(self.request_timer),
+            },
+            reply,
+        };
+        match self.stall_watch {
+            Some(StallWatch { timer, warn_after }) => {
+                self.prod
+                    .enqueue_async_watched(msg, timer, warn_after, any::type_name::<RD>())
+                    .await
+            }
+            None => self.prod.enqueue_async(msg).await,
+        }
+        .map_err(|_| SendError::Closed)?;
         trace!(
             service_id = self.service_id.0,
             client_id = self.client_id.0,
@@ -1104,12 +1654,31 @@ impl<RD: RegisteredDriver> KernelHandle<RD> {
         msg: RD::Request,
         reply: &Reusable<Envelope<Result<RD::Response, RD::Error>>>,
     ) -> Result<Envelope<Result<RD::Response, RD::Error>>, OneshotRequestError> {
-        let tx = reply.sender().await.map_err(OneshotRequestError::Sender)?;
+        let tx = reply
+            .sender_to(any::type_name::<RD>())
+            .await
+            .map_err(OneshotRequestError::Sender)?;
         self.send(msg, ReplyTo::OneShot(tx))
             .await
             .map_err(|_| OneshotRequestError::Send)?;
         reply.receive().await.map_err(OneshotRequestError::Receive)
     }
+
+    /// Send a [`ReplyTo::Stream`] request, returning a [`ResponseStream`]
+    /// that receives responses from the service until it closes the stream.
+    ///
+    /// Up to `capacity` responses are buffered before the service must wait
+    /// for the client to receive them. `capacity` should be a power of two
+    /// >= 2, or it will be increased automatically.
+    pub async fn request_stream(
+        &mut self,
+        msg: RD::Request,
+        capacity: usize,
+    ) -> Result<ResponseStream<RD>, SendError> {
+        let (tx, rx) = KChannel::new_async(capacity).await.split();
+        self.send(msg, ReplyTo::Stream(StreamSender { tx })).await?;
+        Ok(ResponseStream { rx })
+    }
 }
 
 // UserVtable
@@ -1171,6 +1740,9 @@ where
             service_id,
             client_id,
             request_id: RequestResponseId::new(umsg.nonce, MessageKind::Request),
+            // userspace doesn't send its spans to the kernel.
+            span_cx: SpanContext::none(),
+            timing: RequestTiming::none(),
         },
         reply: ReplyTo::Userspace {
             nonce: umsg.nonce,
@@ -1184,7 +1756,7 @@ where
         .map_err(|_| UserHandlerError::QueueFull)
 }
 
-type UserHandshakeResult<RD> = Result<KProducer<Message<RD>>, UserConnectError<RD>>;
+type UserHandshakeResult<RD> = Result<(KProducer<Message<RD>>, Capabilities), UserConnectError<RD>>;
 
 /// Perform a type-erased userspace handshake, deserializing the
 /// [`RegisteredDriver::Hello`] message from `hello_bytes` and returning a
@@ -1244,7 +1816,7 @@ where
         let result = rx
             .receive()
             .await
-            // this is a `Reusable<Result<KProducer, RD::ConnectError>>>`, so
+            // this is a `Reusable<Result<(KProducer, Capabilities), RD::ConnectError>>>`, so
             // the outer `Result` is the error returned by `receive()`...
             .map_err(|_| UserConnectError::DriverDead)
             // ...and the inner result is the connect error returned by the service.
@@ -1268,6 +1840,7 @@ impl fmt::Display for UserHandlerError {
         match self {
             Self::QueueFull => f.pad("service queue full"),
             Self::DeserializationFailed => f.pad("failed to deserialize user request"),
+            Self::RateLimited => f.pad("service rate limit exceeded"),
         }
     }
 }
@@ -0,0 +1,157 @@
+//! Per-connection request rate limits for services.
+//!
+//! A service may limit how quickly each of its clients can send requests, by
+//! registering with a [`Registration`] configured using
+//! [`Registration::with_rate_limit`]. Every connection to the service then
+//! gets its own budget of requests: a client may send a short burst of
+//! requests at once, but after that, it may only send requests at the
+//! service's sustained rate. This keeps a single misbehaving client (such as a
+//! Forth script stuck in a drawing loop) from filling the service's request
+//! queue and locking out every other client.
+//!
+//! The limit is enforced on the client's side of the connection:
+//!
+//! - A [`KernelHandle`] which is over its budget waits until it may send
+//!   again, so kernel clients are slowed down rather than failing.
+//! - A [`UserspaceHandle`] can't wait, so requests from userspace which are
+//!   over budget are rejected with [`UserHandlerError::RateLimited`].
+//!
+//! Limits are only enforced once the registry has a timer to measure rates
+//! with (see [`Registry::enforce_rate_limits`]), which
+//! [`Kernel::initialize_default_services`] provides, and only on connections
+//! established after that.
+//!
+//! [`Registration`]: super::Registration
+//! [`Registration::with_rate_limit`]: super::Registration::with_rate_limit
+//! [`KernelHandle`]: super::KernelHandle
+//! [`UserspaceHandle`]: super::UserspaceHandle
+//! [`UserHandlerError::RateLimited`]: super::UserHandlerError::RateLimited
+//! [`Registry::enforce_rate_limits`]: super::Registry::enforce_rate_limits
+//! [`Kernel::initialize_default_services`]: crate::Kernel::initialize_default_services
+#![warn(missing_docs)]
+use core::time::Duration;
+use maitake::time::{Instant, Timer};
+
+/// How quickly each client of a service may send requests.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct RateLimit {
+    per_second: u32,
+    burst: u32,
+}
+
+/// Tracks a single connection's request budget.
+///
+/// This is the "generic cell rate algorithm": rather than counting tokens,
+/// the limiter tracks when the next request would be due if the client sent
+/// requests at exactly the sustained rate, and allows clients to get ahead of
+/// that schedule by up to the burst size.
+pub(super) struct Limiter {
+    timer: &'static Timer,
+    /// The time between requests at the sustained rate.
+    interval: Duration,
+    /// How far ahead of schedule the client may get.
+    tolerance: Duration,
+    /// When the next request is due, if the client has sent any requests.
+    due: Option<Instant>,
+}
+
+// === impl RateLimit ===
+
+impl RateLimit {
+    /// Returns a rate limit allowing `per_second` requests per second, with
+    /// bursts of up to `burst` requests at once.
+    ///
+    /// A `burst` of 0 is treated as 1.
+    ///
+    /// # Panics
+    ///
+    /// If `per_second` is 0.
+    #[must_use]
+    pub const fn new(per_second: u32, burst: u32) -> Self {
+        assert!(per_second > 0, "a rate limit must allow some requests");
+        Self {
+            per_second,
+            burst: if burst == 0 { 1 } else { burst },
+        }
+    }
+
+    /// Returns the number of requests per second allowed by this limit.
+    #[must_use]
+    pub const fn per_second(&self) -> u32 {
+        self.per_second
+    }
+
+    /// Returns the number of requests which may be sent at once.
+    #[must_use]
+    pub const fn burst(&self) -> u32 {
+        self.burst
+    }
+}
+
+// === impl Limiter ===
+
+impl Limiter {
+    pub(super) fn new(limit: RateLimit, timer: &'static Timer) -> Self {
+        let interval = Duration::from_secs(1) / limit.per_second;
+        Self {
+            timer,
+            interval,
+            tolerance: interval * (limit.burst - 1),
+            due: None,
+        }
+    }
+
+    /// Records a request if the client has budget for it, returning [`None`].
+    /// Otherwise, returns how long the client must wait before sending the
+    /// request.
+    pub(super) fn check(&mut self) -> Option<Duration> {
+        let now = self.timer.now();
+        let due = match self.due {
+            Some(due) if due > now => due,
+            // the client has been idle long enough to get a full burst back.
+            _ => now,
+        };
+        let ahead = due.duration_since(now);
+        if ahead > self.tolerance {
+            return Some(ahead - self.tolerance);
+        }
+        self.due = Some(due + self.interval);
+        None
+    }
+
+    /// Waits until the client has budget for a request, and records it.
+    pub(super) async fn acquire(&mut self) {
+        while let Some(wait) = self.check() {
+            self.timer.sleep(wait).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TestKernel;
+
+    #[test]
+    fn allows_bursts() {
+        TestKernel::run(|k| async move {
+            let mut limiter = Limiter::new(RateLimit::new(10, 3), k.timer());
+            for i in 0..3 {
+                assert_eq!(limiter.check(), None, "request {i} is within the burst");
+            }
+            let wait = limiter.check().expect("the burst is used up");
+            assert!(wait <= Duration::from_millis(100), "waits {wait:?}");
+        })
+    }
+
+    #[test]
+    fn zero_burst() {
+        TestKernel::run(|k| async move {
+            let limit = RateLimit::new(10, 0);
+            assert_eq!(limit.burst(), 1);
+            let mut limiter = Limiter::new(limit, k.timer());
+            assert_eq!(limiter.check(), None);
+            assert!(limiter.check().is_some());
+        })
+    }
+}
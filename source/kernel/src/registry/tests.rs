@@ -3,7 +3,7 @@ use core::sync::atomic::{AtomicU32, Ordering};
 use super::*;
 use crate::{comms, test_util::TestKernel, Kernel};
 
-struct TestService;
+pub(super) struct TestService;
 
 impl RegisteredDriver for TestService {
     type Request = TestMessage;
@@ -15,7 +15,7 @@ impl RegisteredDriver for TestService {
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
-struct TestMessage(usize);
+pub(super) struct TestMessage(pub(super) usize);
 
 #[test]
 fn konly_connect() {
@@ -207,3 +207,239 @@ fn user_connect() {
         assert_eq!(Ok(TestMessage(4)), rsp);
     })
 }
+
+#[test]
+fn konly_stream() {
+    TestKernel::run(|k| async move {
+        let (listener, registration) = listener::Listener::<TestService>::new(2).await;
+
+        // server: replies to a request for `n` with a stream of `n` responses
+        k.spawn(async move {
+            let conn = listener.handshake().await;
+            let (tx, rx) = crate::comms::kchannel::KChannel::new_async(2).await.split();
+            k.spawn(async move {
+                while let Ok(msg) = rx.dequeue_async().await {
+                    let (TestMessage(n), env, reply) = msg.split();
+                    let stream = match reply.into_stream() {
+                        Ok(stream) => stream,
+                        Err(_) => panic!("all requests should be streaming"),
+                    };
+                    for i in 0..n {
+                        stream.send(env.fill(Ok(TestMessage(i)))).await.unwrap();
+                    }
+                    stream.close();
+                }
+            })
+            .await;
+            conn.accept(tx).unwrap();
+        })
+        .await;
+
+        k.registry().register_konly(registration).await.unwrap();
+
+        let mut client = k
+            .registry()
+            .connect::<TestService>(TestMessage(1))
+            .await
+            .expect("connect should succeed");
+
+        // more responses than the stream's buffer capacity
+        let stream = client
+            .request_stream(TestMessage(5), 2)
+            .await
+            .expect("request should succeed");
+        for i in 0..5 {
+            let rsp = stream.next().await.expect("stream should not end yet");
+            assert_eq!(rsp.body, Ok(TestMessage(i)));
+        }
+        assert!(stream.next().await.is_none(), "stream should have ended");
+
+        // an empty stream ends immediately
+        let stream = client
+            .request_stream(TestMessage(0), 2)
+            .await
+            .expect("request should succeed");
+        assert!(stream.next().await.is_none(), "stream should have ended");
+    })
+}
+
+#[test]
+fn connection_capabilities() {
+    const FANCY: Capabilities = Capabilities::bit(0);
+    const FANCIER: Capabilities = Capabilities::bit(1);
+
+    TestKernel::run(|k| async move {
+        let (listener, registration) = listener::Listener::<TestService>::new(2).await;
+
+        // server: advertises capabilities based on the client's `Hello`
+        k.spawn(async move {
+            loop {
+                let conn = listener.handshake().await;
+                let (tx, _rx) = crate::comms::kchannel::KChannel::new_async(2).await.split();
+                match conn.hello {
+                    TestMessage(0) => conn.accept(tx).unwrap(),
+                    TestMessage(1) => conn.accept_with(tx, FANCY).unwrap(),
+                    _ => conn.accept_with(tx, FANCY | FANCIER).unwrap(),
+                }
+            }
+        })
+        .await;
+
+        k.registry().register_konly(registration).await.unwrap();
+
+        let plain = k
+            .registry()
+            .connect::<TestService>(TestMessage(0))
+            .await
+            .expect("connect should succeed");
+        assert_eq!(plain.capabilities(), Capabilities::NONE);
+
+        let fancy = k
+            .registry()
+            .connect::<TestService>(TestMessage(1))
+            .await
+            .expect("connect should succeed");
+        assert!(fancy.capabilities().contains(FANCY));
+        assert!(!fancy.capabilities().contains(FANCIER));
+
+        let fancier = k
+            .registry()
+            .connect::<TestService>(TestMessage(2))
+            .await
+            .expect("connect should succeed");
+        assert!(fancier.capabilities().contains(FANCY | FANCIER));
+    })
+}
+
+#[test]
+#[cfg(debug_assertions)]
+fn max_latency() {
+    const MAX: Duration = Duration::from_millis(1);
+    const SLOW: Duration = Duration::from_millis(5);
+
+    TestKernel::run(|k| async move {
+        k.registry().time_requests(k.timer());
+
+        let requests = k
+            .registry()
+            .bind_konly::<TestService>(2)
+            .await
+            .unwrap()
+            .into_request_stream(2)
+            .await
+            .with_max_latency(Some(MAX));
+
+        // server: takes longer than its target to reply
+        k.spawn(async move {
+            loop {
+                let Message { msg, reply } = requests.next_request().await;
+                std::thread::sleep(SLOW);
+                reply
+                    .reply_konly(msg.reply_with_body(|TestMessage(val)| Ok(TestMessage(val))))
+                    .await
+                    .unwrap();
+            }
+        })
+        .await;
+
+        let reply = comms::oneshot::Reusable::new_async().await;
+        let mut client = k
+            .registry()
+            .connect::<TestService>(TestMessage(0))
+            .await
+            .expect("connect should succeed");
+
+        let rsp = client
+            .request_oneshot(TestMessage(1), &reply)
+            .await
+            .expect("request should succeed");
+        let late_by = rsp.timing().late_by();
+        assert!(
+            matches!(late_by, Some(late_by) if late_by >= SLOW - MAX),
+            "reply should be late: {:?}",
+            rsp.timing(),
+        );
+    })
+}
+
+#[test]
+fn rate_limited_connections() {
+    TestKernel::run(|k| async move {
+        let (listener, registration) = listener::Listener::<TestService>::new(2).await;
+        let registration = registration.with_rate_limit(RateLimit::new(10, 2));
+        k.registry().register_konly(registration).await.unwrap();
+        let requests = listener.into_request_stream(2).await;
+
+        // server
+        k.spawn(async move {
+            loop {
+                let Message { msg, reply } = requests.next_request().await;
+                reply
+                    .reply_konly(msg.reply_with_body(|TestMessage(val)| Ok(TestMessage(val))))
+                    .await
+                    .unwrap();
+            }
+        })
+        .await;
+
+        // rate limits aren't enforced until the registry has a timer.
+        let unlimited = k
+            .registry()
+            .connect::<TestService>(TestMessage(0))
+            .await
+            .expect("connect should succeed");
+        assert!(unlimited.limiter.is_none());
+
+        k.registry().enforce_rate_limits(k.timer());
+        let reply = comms::oneshot::Reusable::new_async().await;
+        let mut client = k
+            .registry()
+            .connect::<TestService>(TestMessage(0))
+            .await
+            .expect("connect should succeed");
+
+        // a burst of requests is sent without waiting...
+        for i in 0..2 {
+            let rsp = client
+                .request_oneshot(TestMessage(i), &reply)
+                .await
+                .expect("request should succeed");
+            assert_eq!(rsp.body, Ok(TestMessage(i)));
+        }
+
+        // ...but the next request would have to wait.
+        let limiter = client.limiter.as_mut().expect("client should be limited");
+        assert!(limiter.check().is_some());
+    })
+}
+
+#[test]
+fn request_queue_depth() {
+    async fn req_queue(k: &Kernel) -> Option<kchannel::ChannelDepth> {
+        let mut depth = None;
+        k.registry()
+            .for_each_service(|svc| {
+                if svc.uuid == TestService::UUID {
+                    depth = svc.req_queue;
+                }
+            })
+            .await;
+        depth
+    }
+
+    TestKernel::run(|k| async move {
+        let (listener, registration) = listener::Listener::<TestService>::new(2).await;
+        k.registry().register_konly(registration).await.unwrap();
+
+        // services which hand each client its own channel have no request
+        // queue to report.
+        assert_eq!(req_queue(k).await, None);
+
+        let _requests = listener.into_request_stream(4).await;
+        let depth = req_queue(k)
+            .await
+            .expect("request queue should be reported");
+        assert_eq!(depth.capacity, 4);
+        assert_eq!(depth.len, 0);
+    })
+}
@@ -0,0 +1,534 @@
+//! # Userspace Rings
+//!
+//! Each userspace process talks to the kernel over a pair of [`Rings`]: the
+//! process writes [`UserRequest`]s to its `u2k` ring, and the kernel writes
+//! [`KernelMsg`]s back on its `k2u` ring. The kernel's [`RingPoller`] reads
+//! requests from every registered process each time the kernel is
+//! [ticked](crate::Kernel::tick), before the scheduler runs, and passes them
+//! on to the [`ProcessHandle`] returned when the process was registered.
+//!
+//! ## Fairness
+//!
+//! A process which sends requests faster than they can be handled must not be
+//! able to starve kernel tasks, or other processes. So, rather than draining
+//! each ring before running the scheduler, the poller reads at most a
+//! *budget* of requests per tick (see [`RingPoller::set_budget`]), taking one
+//! request from each process in turn. The next tick picks up with the process
+//! after the last one served, so no process is always first in line.
+//!
+//! A process's requests are also left in its ring while its
+//! [`ProcessHandle`] has a backlog of requests it has not yet handled,
+//! putting backpressure on the process rather than queueing without bound.
+//!
+//! How often each of these happens is counted, and reported by
+//! [`RingPoller::stats`] and [`RingPoller::for_each_process`].
+//!
+//! [`KernelMsg`]: abi::syscall::KernelMsg
+
+use core::fmt;
+
+use abi::{
+    bbqueue_ipc::{
+        framed::{FrameConsumer, FrameProducer},
+        BBBuffer,
+    },
+    syscall::UserRequest,
+};
+use maitake::sync::{blocking::Mutex, spin::Spinlock};
+use portable_atomic::{AtomicU32, AtomicUsize, Ordering};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    comms::kchannel::{DequeueError, KChannel, KConsumer, KProducer},
+    Rings,
+};
+
+/// The maximum number of processes whose rings may be polled at once.
+pub const MAX_PROCESSES: usize = 8;
+
+/// Polls the rings of every registered userspace process.
+///
+/// This is returned by [`Kernel::rings`](crate::Kernel::rings).
+pub struct RingPoller {
+    table: Mutex<Table, Spinlock>,
+    /// The maximum number of requests read per tick.
+    budget: AtomicUsize,
+    next_id: AtomicU32,
+    polls: AtomicUsize,
+    exhausted: AtomicUsize,
+}
+
+/// Identifies a registered userspace process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ProcessId(u32);
+
+/// The kernel's end of a registered process's rings.
+///
+/// This is returned by [`RingPoller::register`]. Dropping it does not
+/// unregister the process; use [`RingPoller::unregister`] for that.
+pub struct ProcessHandle {
+    id: ProcessId,
+    requests: KConsumer<UserRequest>,
+    k2u: FrameProducer<'static>,
+}
+
+/// Errors returned by [`RingPoller::register`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegisterError {
+    /// [`MAX_PROCESSES`] processes are already registered.
+    Full,
+}
+
+/// Counters for the [`RingPoller`] as a whole.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RingStats {
+    /// The number of processes currently registered.
+    pub processes: usize,
+    /// The number of times the rings have been polled.
+    pub polls: usize,
+    /// The number of polls which used up the whole budget while requests
+    /// were still waiting.
+    pub exhausted: usize,
+}
+
+/// Counters for a single registered process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProcessStats {
+    pub id: ProcessId,
+    /// The number of requests read from the process's ring.
+    pub requests: usize,
+    /// The number of frames which could not be decoded as a
+    /// [`UserRequest`], and were discarded.
+    pub malformed: usize,
+    /// The number of times the process's ring was skipped because its
+    /// [`ProcessHandle`] had no room for more requests.
+    pub backpressured: usize,
+}
+
+/// Settings for the [`RingPoller`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RingSettings {
+    /// The maximum number of requests read from all processes' rings, per
+    /// tick.
+    #[serde(default = "RingSettings::default_budget")]
+    pub budget: usize,
+}
+
+struct Table {
+    procs: heapless::Vec<Process, MAX_PROCESSES>,
+    /// The index of the process to poll first on the next tick.
+    cursor: usize,
+}
+
+struct Process {
+    u2k: FrameConsumer<'static>,
+    requests: KProducer<UserRequest>,
+    stats: ProcessStats,
+}
+
+enum Polled {
+    Request,
+    Empty,
+    Backpressured,
+}
+
+// === impl RingPoller ===
+
+impl RingPoller {
+    pub const DEFAULT_BUDGET: usize = 16;
+
+    pub(crate) fn new() -> Self {
+        Self {
+            table: Mutex::new_with_raw_mutex(
+                Table {
+                    procs: heapless::Vec::new(),
+                    cursor: 0,
+                },
+                Spinlock::new(),
+            ),
+            budget: AtomicUsize::new(Self::DEFAULT_BUDGET),
+            next_id: AtomicU32::new(0),
+            polls: AtomicUsize::new(0),
+            exhausted: AtomicUsize::new(0),
+        }
+    }
+
+    /// Registers a process's rings, so that they are polled on each tick.
+    ///
+    /// Up to `capacity` requests read from the process are queued in the
+    /// returned [`ProcessHandle`]. Once that many are waiting, the process's
+    /// ring is no longer read until the handle catches up.
+    ///
+    /// # Safety
+    ///
+    /// `rings` must point to [initialized](BBBuffer::initialize) buffers,
+    /// which live forever, and which the kernel is the only consumer of
+    /// (`u2k`) and producer to (`k2u`).
+    pub async unsafe fn register(
+        &self,
+        rings: Rings,
+        capacity: usize,
+    ) -> Result<ProcessHandle, RegisterError> {
+        let (requests_tx, requests) = KChannel::new_async(capacity).await.split();
+        let id = ProcessId(self.next_id.fetch_add(1, Ordering::Relaxed));
+        let u2k = BBBuffer::take_framed_consumer(rings.u2k.as_ptr());
+        let k2u = BBBuffer::take_framed_producer(rings.k2u.as_ptr());
+
+        self.table
+            .lock()
+            .procs
+            .push(Process {
+                u2k,
+                requests: requests_tx,
+                stats: ProcessStats {
+                    id,
+                    requests: 0,
+                    malformed: 0,
+                    backpressured: 0,
+                },
+            })
+            .map_err(|_| RegisterError::Full)?;
+
+        tracing::info!(process = id.0, capacity, "registered process rings");
+        Ok(ProcessHandle { id, requests, k2u })
+    }
+
+    /// Stops polling the rings of the process with the given `id`, returning
+    /// `false` if it was not registered.
+    ///
+    /// Any requests already queued in the process's [`ProcessHandle`] may
+    /// still be received, after which it will return an error.
+    pub fn unregister(&self, id: ProcessId) -> bool {
+        let mut table = self.table.lock();
+        let Some(idx) = table.procs.iter().position(|p| p.stats.id == id) else {
+            return false;
+        };
+        let mut process = table.procs.remove(idx);
+        process.requests.close();
+        // keep the cursor pointing at the same process, if it's still there.
+        if table.cursor > idx {
+            table.cursor -= 1;
+        }
+        tracing::info!(process = id.0, "unregistered process rings");
+        true
+    }
+
+    /// Sets the maximum number of requests read from all processes' rings on
+    /// each tick.
+    ///
+    /// A budget of 0 stops the rings from being polled.
+    pub fn set_budget(&self, budget: usize) {
+        self.budget.store(budget, Ordering::Relaxed);
+    }
+
+    /// Returns the maximum number of requests read on each tick.
+    #[must_use]
+    pub fn budget(&self) -> usize {
+        self.budget.load(Ordering::Relaxed)
+    }
+
+    /// Returns counters for the poller as a whole.
+    #[must_use]
+    pub fn stats(&self) -> RingStats {
+        RingStats {
+            processes: self.table.lock().procs.len(),
+            polls: self.polls.load(Ordering::Relaxed),
+            exhausted: self.exhausted.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Calls `f` with the counters for each registered process.
+    pub fn for_each_process(&self, mut f: impl FnMut(&ProcessStats)) {
+        for process in self.table.lock().procs.iter() {
+            f(&process.stats);
+        }
+    }
+
+    /// Reads requests from the registered processes' rings, round-robin,
+    /// until every ring is empty or the budget is used up. Returns the number
+    /// of frames read.
+    pub(crate) fn poll(&self) -> usize {
+        let budget = self.budget();
+        let mut table = self.table.lock();
+        let Table { procs, cursor } = &mut *table;
+        let len = procs.len();
+        if len == 0 || budget == 0 {
+            return 0;
+        }
+        self.polls.fetch_add(1, Ordering::Relaxed);
+
+        let mut read = 0;
+        // the number of processes in a row which had nothing for us. once
+        // every process has been skipped, there's nothing left to do.
+        let mut skipped = 0;
+        let mut idx = *cursor % len;
+        while read < budget && skipped < len {
+            match procs[idx].poll_one() {
+                Polled::Request => {
+                    read += 1;
+                    skipped = 0;
+                }
+                Polled::Empty | Polled::Backpressured => skipped += 1,
+            }
+            idx = (idx + 1) % len;
+        }
+        *cursor = idx;
+
+        if read == budget && procs.iter().any(Process::has_pending) {
+            self.exhausted.fetch_add(1, Ordering::Relaxed);
+        }
+
+        read
+    }
+}
+
+impl fmt::Debug for RingPoller {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RingPoller")
+            .field("budget", &self.budget())
+            .field("stats", &self.stats())
+            .finish()
+    }
+}
+
+// === impl Process ===
+
+impl Process {
+    fn poll_one(&mut self) -> Polled {
+        let depth = self.requests.depth();
+        if depth.len >= depth.capacity {
+            self.stats.backpressured += 1;
+            return Polled::Backpressured;
+        }
+
+        let Some(frame) = self.u2k.read() else {
+            return Polled::Empty;
+        };
+        match postcard::from_bytes::<UserRequest>(&frame) {
+            Ok(request) => {
+                // we checked that there's room, and we're the only producer.
+                let _ = self.requests.enqueue_sync(request);
+                self.stats.requests += 1;
+            }
+            Err(error) => {
+                tracing::warn!(
+                    process = self.stats.id.0,
+                    len = frame.len(),
+                    %error,
+                    "discarding malformed request"
+                );
+                self.stats.malformed += 1;
+            }
+        }
+        frame.release();
+        Polled::Request
+    }
+
+    /// Returns `true` if a frame is waiting in the process's ring, and
+    /// there's room to accept it.
+    fn has_pending(&self) -> bool {
+        let depth = self.requests.depth();
+        // dropping the grant without releasing it leaves the frame in place.
+        depth.len < depth.capacity && self.u2k.read().is_some()
+    }
+}
+
+// === impl ProcessHandle ===
+
+impl ProcessHandle {
+    #[must_use]
+    pub fn id(&self) -> ProcessId {
+        self.id
+    }
+
+    /// Waits for the next request from the process.
+    ///
+    /// Returns an error once the process has been
+    /// [unregistered](RingPoller::unregister) and every queued request has
+    /// been received.
+    pub async fn next_request(&self) -> Result<UserRequest, DequeueError> {
+        self.requests.dequeue_async().await
+    }
+
+    /// Returns the producer for the process's `k2u` ring, which is used to
+    /// send messages to the process.
+    #[must_use]
+    pub fn k2u(&self) -> &FrameProducer<'static> {
+        &self.k2u
+    }
+}
+
+impl fmt::Debug for ProcessHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ProcessHandle")
+            .field("id", &self.id)
+            .field("requests", &self.requests.depth())
+            .finish_non_exhaustive()
+    }
+}
+
+// === impl ProcessId ===
+
+impl ProcessId {
+    #[must_use]
+    pub fn as_u32(self) -> u32 {
+        self.0
+    }
+}
+
+impl fmt::Display for ProcessId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+// === impl RegisterError ===
+
+impl fmt::Display for RegisterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Full => write!(f, "at most {MAX_PROCESSES} processes may be registered"),
+        }
+    }
+}
+
+// === impl RingSettings ===
+
+impl RingSettings {
+    const fn default_budget() -> usize {
+        RingPoller::DEFAULT_BUDGET
+    }
+}
+
+impl Default for RingSettings {
+    fn default() -> Self {
+        Self {
+            budget: RingPoller::DEFAULT_BUDGET,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TestKernel;
+    use abi::syscall::{serial::SerialRequest, UserRequestBody, UserRequestHeader};
+    use core::ptr::NonNull;
+
+    /// Returns a leaked, initialized ring buffer.
+    fn ring() -> NonNull<BBBuffer> {
+        let buf = std::boxed::Box::leak(std::vec![0u8; 1024].into_boxed_slice());
+        let bbq = std::boxed::Box::leak(std::boxed::Box::new(BBBuffer::new()));
+        unsafe { bbq.initialize(buf.as_mut_ptr(), buf.len()) };
+        NonNull::from(bbq)
+    }
+
+    /// Sends `count` requests on `u2k`.
+    fn send(u2k: NonNull<BBBuffer>, count: u32) {
+        let prod = unsafe { BBBuffer::take_framed_producer(u2k.as_ptr()) };
+        for nonce in 0..count {
+            let req = UserRequest {
+                header: UserRequestHeader { nonce },
+                body: UserRequestBody::Serial(SerialRequest::Flush { port: 0 }),
+            };
+            let mut wgr = prod.grant(32).unwrap();
+            let used = postcard::to_slice(&req, &mut wgr).unwrap().len();
+            wgr.commit(used);
+        }
+    }
+
+    async fn register(poller: &RingPoller, capacity: usize) -> (NonNull<BBBuffer>, ProcessHandle) {
+        let u2k = ring();
+        let handle = unsafe { poller.register(Rings { u2k, k2u: ring() }, capacity) }
+            .await
+            .unwrap();
+        (u2k, handle)
+    }
+
+    fn requests(poller: &RingPoller) -> std::vec::Vec<usize> {
+        let mut counts = std::vec::Vec::new();
+        poller.for_each_process(|process| counts.push(process.requests));
+        counts
+    }
+
+    #[test]
+    fn round_robin_budget() {
+        TestKernel::run(|_| async move {
+            let poller = RingPoller::new();
+            poller.set_budget(5);
+            let (chatty, _chatty) = register(&poller, 64).await;
+            let (quiet, _quiet) = register(&poller, 64).await;
+            send(chatty, 20);
+            send(quiet, 2);
+
+            // the quiet process gets its turn even though the chatty process
+            // could use the whole budget.
+            assert_eq!(poller.poll(), 5);
+            assert_eq!(requests(&poller), [3, 2]);
+            assert_eq!(poller.stats().exhausted, 1);
+
+            // once the quiet process is empty, the chatty one gets everything.
+            assert_eq!(poller.poll(), 5);
+            assert_eq!(requests(&poller), [8, 2]);
+
+            for _ in 0..3 {
+                poller.poll();
+            }
+            assert_eq!(requests(&poller), [20, 2]);
+            assert_eq!(poller.poll(), 0);
+            let stats = poller.stats();
+            assert_eq!(stats.polls, 6);
+            assert_eq!(stats.exhausted, 4);
+        })
+    }
+
+    #[test]
+    fn cursor_rotates() {
+        TestKernel::run(|_| async move {
+            let poller = RingPoller::new();
+            poller.set_budget(1);
+            let (a, _a) = register(&poller, 64).await;
+            let (b, _b) = register(&poller, 64).await;
+            send(a, 2);
+            send(b, 2);
+
+            // with a budget of one, each process gets a turn on alternate
+            // ticks, rather than the first process always going first.
+            poller.poll();
+            assert_eq!(requests(&poller), [1, 0]);
+            poller.poll();
+            assert_eq!(requests(&poller), [1, 1]);
+            poller.poll();
+            assert_eq!(requests(&poller), [2, 1]);
+        })
+    }
+
+    #[test]
+    fn backpressure() {
+        TestKernel::run(|_| async move {
+            let poller = RingPoller::new();
+            let (u2k, handle) = register(&poller, 2).await;
+            send(u2k, 4);
+
+            assert_eq!(poller.poll(), 2);
+            assert_eq!(poller.poll(), 0);
+            let mut backpressured = 0;
+            poller.for_each_process(|process| backpressured = process.backpressured);
+            assert!(backpressured > 0);
+
+            // once the handle catches up, the rest are read.
+            for nonce in 0..2 {
+                let req = handle.next_request().await.unwrap();
+                assert_eq!(req.header.nonce, nonce);
+            }
+            assert_eq!(poller.poll(), 2);
+            let req = handle.next_request().await.unwrap();
+            assert_eq!(req.header.nonce, 2);
+
+            assert!(poller.unregister(handle.id()));
+            assert!(!poller.unregister(handle.id()));
+            assert!(handle.next_request().await.is_ok());
+            assert!(handle.next_request().await.is_err());
+        })
+    }
+}
@@ -1,16 +1,24 @@
 use core::time::Duration;
 
-use mnemos_trace_proto::{HostRequest, TraceEvent};
+use maitake::{
+    sync::{blocking::Mutex, spin::Spinlock},
+    time::Instant,
+};
+use mnemos_trace_proto::{DeviceInfo, HostRequest, MetaId, Timestamp, TraceEvent};
 use portable_atomic::{AtomicBool, AtomicU64, AtomicU8, AtomicUsize, Ordering};
 pub use tracing::*;
 use tracing::{metadata::LevelFilter, subscriber::Interest};
 use tracing_serde_structured::{AsSerde, SerializeRecordFields, SerializeSpanFields};
 
-use crate::{comms::bbq, services::serial_mux};
+use crate::{
+    comms::bbq,
+    services::{device_identity, serial_mux},
+};
 
 pub struct SerialSubscriber {
-    tx: bbq::SpscProducer,
-    isr_tx: bbq::SpscProducer,
+    /// Each encoded `TraceEvent` is sent as a single frame.
+    tx: bbq::FramedProducer,
+    isr_tx: bbq::FramedProducer,
 
     /// ID of the next span.
     next_id: AtomicU64,
@@ -19,6 +27,12 @@ pub struct SerialSubscriber {
     /// that BBQueue tracing can be disabled.
     in_send: AtomicBool,
 
+    /// If `true`, span activity is aggregated into [`Shared::summaries`]
+    /// rather than streamed.
+    summarize: bool,
+
+    kernel: &'static crate::Kernel,
+
     shared: &'static Shared,
 }
 
@@ -37,16 +51,90 @@ struct Shared {
     dropped_span_activity: AtomicUsize,
 
     max_level: AtomicU8,
+
+    /// Span timing aggregated in [`TraceMode::Summary`].
+    ///
+    /// This lock may be contended by an ISR, so the subscriber only ever uses
+    /// `try_lock`, and counts span activity it couldn't record in
+    /// `dropped_summary`.
+    summaries: Mutex<Summaries, Spinlock>,
+
+    /// Counter of span exits that could not be included in a summary.
+    dropped_summary: AtomicUsize,
 }
 
+/// Span timing state used in [`TraceMode::Summary`].
+struct Summaries {
+    /// Spans which currently exist, and when they were entered (if they are
+    /// currently entered).
+    spans: heapless::Vec<LiveSpan, MAX_SUMMARY_SPANS>,
+    /// Timing statistics for each span callsite in the current period.
+    callsites: heapless::Vec<CallsiteStats, MAX_SUMMARY_CALLSITES>,
+}
+
+struct LiveSpan {
+    id: u64,
+    meta: MetaId,
+    entered: Option<Instant>,
+    /// The number of handles to the span. It's forgotten once they've all
+    /// been dropped.
+    refs: usize,
+}
+
+#[derive(Clone, Copy)]
+struct CallsiteStats {
+    meta: MetaId,
+    count: u32,
+    total: Duration,
+    max: Duration,
+}
+
+/// Maximum number of live spans tracked in summary mode.
+const MAX_SUMMARY_SPANS: usize = 64;
+
+/// Maximum number of span callsites included in each summary.
+const MAX_SUMMARY_CALLSITES: usize = 32;
+
 static SHARED: Shared = Shared {
     dropped_events: AtomicUsize::new(0),
     dropped_spans: AtomicUsize::new(0),
     dropped_metas: AtomicUsize::new(0),
     dropped_span_activity: AtomicUsize::new(0),
     max_level: AtomicU8::new(level_to_u8(LevelFilter::OFF)),
+    summaries: Mutex::new_with_raw_mutex(
+        Summaries {
+            spans: heapless::Vec::new(),
+            callsites: heapless::Vec::new(),
+        },
+        Spinlock::new(),
+    ),
+    dropped_summary: AtomicUsize::new(0),
 };
 
+/// Returns the maximum level of traces sent by the serial trace subscriber.
+pub fn max_level() -> LevelFilter {
+    u8_to_level(SHARED.max_level.load(Ordering::Acquire))
+}
+
+/// Sets the maximum level of traces sent by the serial trace subscriber.
+///
+/// This is the same setting that the host changes with a
+/// [`HostRequest::SetMaxLevel`], so that verbosity can be changed on the
+/// target when no host is attached. The new level is reported to the host in
+/// the subscriber's next heartbeat.
+pub fn set_max_level(level: LevelFilter) {
+    SHARED.set_max_level(level_to_u8(level));
+}
+
+// === impl Shared ===
+
+impl Shared {
+    fn set_max_level(&self, level: u8) {
+        self.max_level.store(level, Ordering::Release);
+        tracing::callsite::rebuild_interest_cache();
+    }
+}
+
 // === impl SerialSubscriber ===
 
 impl SerialSubscriber {
@@ -55,24 +143,35 @@ impl SerialSubscriber {
             .max_level
             .store(level_to_u8(settings.initial_level), Ordering::Release);
 
-        let (tx, rx) = bbq::new_spsc_channel(settings.tracebuf_capacity).await;
-        let (isr_tx, isr_rx) = bbq::new_spsc_channel(settings.tracebuf_capacity).await;
+        let (tx, rx) = bbq::new_framed_spsc_channel(settings.tracebuf_capacity).await;
+        let (isr_tx, isr_rx) = bbq::new_framed_spsc_channel(settings.tracebuf_capacity).await;
+
+        let summary_interval = match settings.mode {
+            TraceMode::Stream => None,
+            TraceMode::Summary => Some(settings.summary_interval),
+        };
 
         let subscriber = Self {
             tx,
             isr_tx,
             next_id: AtomicU64::new(1),
             in_send: AtomicBool::new(false),
+            summarize: summary_interval.is_some(),
+            kernel: k,
             shared: &SHARED,
         };
 
         // spawn a worker to read from the channel and write to the serial port.
         k.spawn(async move {
             // acquire sermux port 3
-            let port = serial_mux::PortHandle::open(k, settings.port, settings.sendbuf_capacity)
-                .await
-                .expect("cannot initialize serial tracing, cannot open port 3!");
-            Self::worker(&SHARED, rx, isr_rx, port, k).await
+            let port = serial_mux::FramedPortHandle::open_framed(
+                k,
+                settings.port,
+                settings.sendbuf_capacity,
+            )
+            .await
+            .expect("cannot initialize serial tracing, cannot open port 3!");
+            Self::worker(&SHARED, rx, isr_rx, port, k, summary_interval).await
         })
         .await;
 
@@ -80,136 +179,142 @@ impl SerialSubscriber {
     }
 
     /// Serialize a `TraceEvent`, returning `true` if the event was correctly serialized.
+    ///
+    /// This may be called from a nested ISR which preempted another call to
+    /// `send_event`, so `in_send` is restored to its previous value when this
+    /// returns, rather than cleared.
     fn send_event<'a>(&self, sz: usize, event: impl FnOnce() -> TraceEvent<'a>) -> bool {
-        self.in_send.store(true, Ordering::Release);
+        let was_sending = self.in_send.swap(true, Ordering::AcqRel);
         let tx = if crate::isr::Isr::is_in_isr() {
             &self.isr_tx
         } else {
             &self.tx
         };
-        let Some(mut wgr) = tx.send_grant_exact_sync(sz) else {
+        // if an ISR preempted another ISR (or this task) while it held a write
+        // grant on the same buffer, the grant fails, and the event is dropped.
+        let Some(mut wgr) = tx.send_grant_sync(sz) else {
+            self.in_send.store(was_sending, Ordering::Release);
             return false;
         };
 
         // got a write grant! generate the event payload.
         let ev = event();
 
-        // encode the event to our write grant, and commit however many bytes
-        // were written. if encoding fails, commit 0 bytes, so the region we got
-        // a write grant for can be reused.
+        // encode the event to our write grant, and commit it as a single
+        // frame. if encoding fails, drop the grant without committing, so the
+        // region we got a write grant for can be reused.
         let len = match postcard::to_slice_cobs(&ev, &mut wgr[..]) {
             Ok(encoded) => encoded.len(),
             Err(_) => 0,
         };
-        wgr.commit(len);
-        self.in_send.store(false, Ordering::Release);
+        if len > 0 {
+            wgr.commit(len);
+        }
+        self.in_send.store(was_sending, Ordering::Release);
 
-        // return true if we committed a non-zero number of bytes.
+        // return true if we committed an event.
         len > 0
     }
 
+    /// Returns the current time, to be sent with a `TraceEvent`.
+    fn timestamp(&self) -> Timestamp {
+        Timestamp {
+            uptime_us: self.kernel.uptime().as_micros() as u64,
+            unix_us: self.kernel.wall_clock().map(|now| now.as_micros() as u64),
+        }
+    }
+
     async fn worker(
         shared: &'static Shared,
-        rx: bbq::Consumer,
-        isr_rx: bbq::Consumer,
-        port: serial_mux::PortHandle,
+        rx: bbq::FramedConsumer,
+        isr_rx: bbq::FramedConsumer,
+        port: serial_mux::FramedPortHandle,
         k: &'static crate::Kernel,
+        summary_interval: Option<Duration>,
     ) {
         use futures::FutureExt;
         use maitake::time;
-        use postcard::accumulator::{CobsAccumulator, FeedResult};
-
-        // we probably won't use 16 whole bytes of cobs yet since all the host
-        // -> target messages are quite small
-        let mut cobs_buf: CobsAccumulator<16> = CobsAccumulator::new();
-        let mut read_level = |rgr: bbq::GrantR| {
-            let mut window = &rgr[..];
-            let len = rgr.len();
-            'cobs: while !window.is_empty() {
-                window = match cobs_buf.feed_ref::<HostRequest>(window) {
-                    FeedResult::Consumed => break 'cobs,
-                    FeedResult::OverFull(new_wind) => new_wind,
-                    FeedResult::DeserError(new_wind) => new_wind,
-                    FeedResult::Success { data, remaining } => {
-                        match data {
-                            HostRequest::SetMaxLevel(lvl) => {
-                                let level = lvl
-                                    .map(|lvl| lvl as u8)
-                                    .unwrap_or(level_to_u8(LevelFilter::OFF));
-                                shared.max_level.store(level, Ordering::Release);
-                                tracing::callsite::rebuild_interest_cache();
-                                info!(
-                                    message = %"hello from mnemOS",
-                                    version = %env!("CARGO_PKG_VERSION"),
-                                    git = %format_args!(
-                                        "{}@{}",
-                                        env!("VERGEN_GIT_BRANCH"),
-                                        env!("VERGEN_GIT_DESCRIBE")
-                                    ),
-                                    target = %env!("VERGEN_CARGO_TARGET_TRIPLE"),
-                                    profile = %if cfg!(debug_assertions) { "debug" } else { "release" },
-                                );
-                            }
-                        }
 
-                        remaining
-                    }
-                };
+        // the host sends each request as a single sermux chunk, which the
+        // framed port delivers as a single frame.
+        let read_request = |mut rgr: bbq::FrameGrantR| {
+            match postcard::from_bytes_cobs::<HostRequest>(&mut rgr[..]) {
+                Ok(HostRequest::SetMaxLevel(lvl)) => {
+                    let level = lvl
+                        .map(|lvl| lvl as u8)
+                        .unwrap_or(level_to_u8(LevelFilter::OFF));
+                    shared.set_max_level(level);
+                    info!(
+                        message = %"hello from mnemOS",
+                        version = %env!("CARGO_PKG_VERSION"),
+                        git = %format_args!(
+                            "{}@{}",
+                            env!("VERGEN_GIT_BRANCH"),
+                            env!("VERGEN_GIT_DESCRIBE")
+                        ),
+                        target = %env!("VERGEN_CARGO_TARGET_TRIPLE"),
+                        profile = %if cfg!(debug_assertions) { "debug" } else { "release" },
+                    );
+                }
+                Ok(HostRequest::ResyncMetadata) => {
+                    debug!("host requested metadata resync");
+                    // re-registering every callsite sends its metadata again.
+                    tracing::callsite::rebuild_interest_cache();
+                }
+                Err(error) => warn!(?error, "failed to decode host request"),
             }
-            rgr.release(len);
+            rgr.release();
         };
 
-        let mut encode_buf = [0u8; 32];
-        // TODO see TODO(eliza) at bottom of second inner loop
-        #[allow(clippy::never_loop)]
+        // large enough for a heartbeat with the longest possible hostname.
+        let mut encode_buf = [0u8; 128];
+        let mut last_summary = k.timer().now();
+        // if the host resets the port, go back to waiting for it to set the
+        // max level, as if it had just connected.
         loop {
             'idle: loop {
-                let heartbeat = {
-                    let level = u8_to_level(shared.max_level.load(Ordering::Acquire))
-                        .into_level()
-                        .as_ref()
-                        .map(AsSerde::as_serde);
-                    postcard::to_slice_cobs(&TraceEvent::Heartbeat(level), &mut encode_buf[..])
-                        .expect("failed to encode heartbeat msg")
+                port.send(encode_heartbeat(shared, &mut encode_buf)).await;
+                let request = futures::select_biased! {
+                    _ = port.reset().fuse() => continue 'idle,
+                    res = k
+                        .timer()
+                        .timeout(time::Duration::from_secs(1), port.consumer().read_grant())
+                        .fuse() => res,
                 };
-                port.send(heartbeat).await;
-                if let Ok(rgr) = k
-                    .timer()
-                    .timeout(time::Duration::from_secs(1), port.consumer().read_grant())
-                    .await
-                {
-                    read_level(rgr);
+                if let Ok(rgr) = request {
+                    read_request(rgr);
 
                     // ack the new max level
-                    let ack = {
-                        let level = u8_to_level(shared.max_level.load(Ordering::Acquire))
-                            .into_level()
-                            .as_ref()
-                            .map(AsSerde::as_serde);
-                        postcard::to_slice_cobs(&TraceEvent::Heartbeat(level), &mut encode_buf[..])
-                            .expect("failed to encode heartbeat msg")
-                    };
-                    port.send(ack).await;
+                    port.send(encode_heartbeat(shared, &mut encode_buf)).await;
                     break 'idle;
                 }
             }
 
             loop {
                 futures::select_biased! {
+                    _ = port.reset().fuse() => {
+                        debug!("host reset the tracing port");
+                        break;
+                    },
                     // something to send to the serial port!
                     rgr = isr_rx.read_grant().fuse() => {
-                        let len = rgr.len();
                         port.send(&rgr[..]).await;
-                        rgr.release(len);
+                        rgr.release();
                     },
                     rgr = rx.read_grant().fuse() => {
-                        let len = rgr.len();
                         port.send(&rgr[..]).await;
-                        rgr.release(len);
+                        rgr.release();
                     },
                     // got a host message!
                     rgr = port.consumer().read_grant().fuse() => {
-                        read_level(rgr);
+                        read_request(rgr);
+                    },
+                    // in summary mode, periodically send the span summaries.
+                    _ = Self::summary_tick(k, summary_interval, last_summary).fuse() => {
+                        let now = k.timer().now();
+                        let period = now.duration_since(last_summary);
+                        last_summary = now;
+                        Self::send_summaries(shared, &port, period, &mut encode_buf).await;
                     },
                     // every few seconds, check if we left anything good on the floor
                     _ = k.sleep(Duration::from_secs(3)).fuse() => {
@@ -238,6 +343,62 @@ impl SerialSubscriber {
         }
     }
 
+    /// Completes when the next span summary is due, or never, if we are not
+    /// in summary mode.
+    async fn summary_tick(
+        k: &'static crate::Kernel,
+        interval: Option<Duration>,
+        last_summary: Instant,
+    ) {
+        let Some(interval) = interval else {
+            return futures::future::pending().await;
+        };
+        let elapsed = k.timer().now().duration_since(last_summary);
+        k.sleep(interval.saturating_sub(elapsed)).await
+    }
+
+    async fn send_summaries(
+        shared: &'static Shared,
+        port: &serial_mux::FramedPortHandle,
+        period: Duration,
+        encode_buf: &mut [u8],
+    ) {
+        // take this period's stats, and release the lock before sending them.
+        let callsites = core::mem::take(&mut shared.summaries.lock().callsites);
+        for stats in &callsites {
+            let ev = TraceEvent::SpanSummary {
+                meta: stats.meta,
+                count: stats.count,
+                total_us: stats.total.as_micros() as u64,
+                max_us: stats.max.as_micros() as u64,
+            };
+            let buf = postcard::to_slice_cobs(&ev, encode_buf)
+                .expect("failed to encode span summary msg");
+            port.send(buf).await;
+        }
+
+        let ev = TraceEvent::SummaryEnd {
+            period_ms: period.as_millis() as u32,
+            dropped: shared.dropped_summary.swap(0, Ordering::Relaxed),
+        };
+        let buf =
+            postcard::to_slice_cobs(&ev, encode_buf).expect("failed to encode summary end msg");
+        port.send(buf).await;
+    }
+
+    /// Apply `f` to the span summary state, if it isn't currently locked.
+    fn with_summaries(&self, f: impl FnOnce(&mut Summaries) -> bool) {
+        let recorded = self
+            .shared
+            .summaries
+            .try_lock()
+            .map(|mut summaries| f(&mut summaries))
+            .unwrap_or(false);
+        if !recorded {
+            self.shared.dropped_summary.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
     #[inline]
     fn level_enabled(&self, metadata: &Metadata<'_>) -> bool {
         // TODO(eliza): more sophisticated filtering
@@ -300,12 +461,19 @@ impl Subscriber for SerialSubscriber {
             span::Id::from_u64(id)
         };
 
+        if self.summarize {
+            let meta = span.metadata().callsite().into();
+            self.with_summaries(|summaries| summaries.new_span(id.into_u64(), meta));
+            return id;
+        }
+
         if !self.send_event(BIGMSG_GRANT_SZ, || TraceEvent::NewSpan {
             id: id.as_serde(),
             meta: span.metadata().callsite().into(),
             parent: span.parent().map(AsSerde::as_serde),
             is_root: span.is_root(),
             fields: SerializeSpanFields::Ser(span.values()),
+            timestamp: self.timestamp(),
         }) {
             self.shared.dropped_spans.fetch_add(1, Ordering::Relaxed);
         }
@@ -318,6 +486,12 @@ impl Subscriber for SerialSubscriber {
     }
 
     fn enter(&self, span: &span::Id) {
+        if self.summarize {
+            let now = self.kernel.timer().now();
+            self.with_summaries(|summaries| summaries.enter(span.into_u64(), now));
+            return;
+        }
+
         if !self.send_event(TINYMSG_GRANT_SZ, || TraceEvent::Enter(span.as_serde())) {
             self.shared
                 .dropped_span_activity
@@ -326,6 +500,12 @@ impl Subscriber for SerialSubscriber {
     }
 
     fn exit(&self, span: &span::Id) {
+        if self.summarize {
+            let now = self.kernel.timer().now();
+            self.with_summaries(|summaries| summaries.exit(span.into_u64(), now));
+            return;
+        }
+
         if !self.send_event(TINYMSG_GRANT_SZ, || TraceEvent::Exit(span.as_serde())) {
             self.shared
                 .dropped_span_activity
@@ -342,12 +522,21 @@ impl Subscriber for SerialSubscriber {
             meta: event.metadata().callsite().into(),
             fields: SerializeRecordFields::Ser(event),
             parent: event.parent().map(AsSerde::as_serde),
+            timestamp: self.timestamp(),
         }) {
             self.shared.dropped_events.fetch_add(1, Ordering::Relaxed);
         }
     }
 
     fn clone_span(&self, span: &span::Id) -> span::Id {
+        if self.summarize {
+            self.with_summaries(|summaries| {
+                summaries.clone_span(span.into_u64());
+                true
+            });
+            return span.clone();
+        }
+
         if !self.send_event(TINYMSG_GRANT_SZ, || TraceEvent::CloneSpan(span.as_serde())) {
             self.shared
                 .dropped_span_activity
@@ -357,6 +546,15 @@ impl Subscriber for SerialSubscriber {
     }
 
     fn try_close(&self, span: span::Id) -> bool {
+        if self.summarize {
+            let mut closed = false;
+            self.with_summaries(|summaries| {
+                closed = summaries.close(span.into_u64());
+                true
+            });
+            return closed;
+        }
+
         if !self.send_event(TINYMSG_GRANT_SZ, || TraceEvent::DropSpan(span.as_serde())) {
             self.shared
                 .dropped_span_activity
@@ -366,6 +564,83 @@ impl Subscriber for SerialSubscriber {
     }
 }
 
+// === impl Summaries ===
+
+impl Summaries {
+    fn new_span(&mut self, id: u64, meta: MetaId) -> bool {
+        let live = LiveSpan {
+            id,
+            meta,
+            entered: None,
+            refs: 1,
+        };
+        self.spans.push(live).is_ok()
+    }
+
+    fn clone_span(&mut self, id: u64) {
+        if let Some(live) = self.spans.iter_mut().find(|live| live.id == id) {
+            live.refs += 1;
+        }
+    }
+
+    /// Drops a handle to the span, returning `true` if it was the last one.
+    fn close(&mut self, id: u64) -> bool {
+        let Some(idx) = self.spans.iter().position(|live| live.id == id) else {
+            return false;
+        };
+        let live = &mut self.spans[idx];
+        live.refs -= 1;
+        if live.refs > 0 {
+            return false;
+        }
+        self.spans.swap_remove(idx);
+        true
+    }
+
+    fn enter(&mut self, id: u64, now: Instant) -> bool {
+        match self.spans.iter_mut().find(|live| live.id == id) {
+            Some(live) => {
+                live.entered = Some(now);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn exit(&mut self, id: u64, now: Instant) -> bool {
+        let Some(live) = self.spans.iter_mut().find(|live| live.id == id) else {
+            return false;
+        };
+        let Some(entered) = live.entered.take() else {
+            return false;
+        };
+        let meta = live.meta;
+        let elapsed = now.duration_since(entered);
+
+        let stats = match self.callsites.iter_mut().find(|stats| stats.meta == meta) {
+            Some(stats) => stats,
+            None => {
+                let new = CallsiteStats {
+                    meta,
+                    count: 0,
+                    total: Duration::ZERO,
+                    max: Duration::ZERO,
+                };
+                if self.callsites.push(new).is_err() {
+                    return false;
+                }
+                self.callsites
+                    .last_mut()
+                    .expect("we just pushed a callsite")
+            }
+        };
+        stats.count = stats.count.saturating_add(1);
+        stats.total = stats.total.saturating_add(elapsed);
+        stats.max = stats.max.max(elapsed);
+        true
+    }
+}
+
 use serde::{Deserialize, Serialize};
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -394,6 +669,31 @@ pub struct SerialTraceSettings {
     #[serde(with = "level_filter")]
     #[serde(default = "SerialTraceSettings::default_initial_level")]
     pub initial_level: LevelFilter,
+
+    /// Whether span activity is streamed to the host, or summarized on the
+    /// target.
+    #[serde(default)]
+    pub mode: TraceMode,
+
+    /// How often span summaries are sent, in [`TraceMode::Summary`].
+    #[serde(default = "SerialTraceSettings::default_summary_interval")]
+    pub summary_interval: Duration,
+}
+
+/// How span activity is reported by the [`SerialSubscriber`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TraceMode {
+    /// Every span creation, enter, and exit is sent to the host.
+    #[default]
+    Stream,
+    /// Spans are not sent individually. Instead, the time spent in spans is
+    /// aggregated per callsite, and summaries are sent to the host
+    /// periodically. Events are still streamed.
+    ///
+    /// This uses much less bandwidth than [`TraceMode::Stream`], which can
+    /// be useful on slow serial links.
+    Summary,
 }
 
 pub const fn level_to_u8(level: LevelFilter) -> u8 {
@@ -407,6 +707,21 @@ pub const fn level_to_u8(level: LevelFilter) -> u8 {
     }
 }
 
+/// Encodes a heartbeat, reporting the current max level and the device's
+/// identity (if the platform has registered it) to the host.
+fn encode_heartbeat<'buf>(shared: &Shared, buf: &'buf mut [u8]) -> &'buf mut [u8] {
+    let level = u8_to_level(shared.max_level.load(Ordering::Acquire))
+        .into_level()
+        .as_ref()
+        .map(AsSerde::as_serde);
+    let device = device_identity::current().map(|identity| DeviceInfo {
+        id: *identity.id().as_bytes(),
+        hostname: identity.hostname(),
+    });
+    postcard::to_slice_cobs(&TraceEvent::Heartbeat(level, device), buf)
+        .expect("failed to encode heartbeat msg")
+}
+
 pub const fn u8_to_level(level: u8) -> LevelFilter {
     match level {
         0 => LevelFilter::TRACE,
@@ -479,6 +794,7 @@ impl SerialTraceSettings {
     pub const DEFAULT_SENDBUF_CAPACITY: usize = BIGMSG_GRANT_SZ * 4;
     pub const DEFAULT_TRACEBUF_CAPACITY: usize = Self::DEFAULT_SENDBUF_CAPACITY * 4;
     pub const DEFAULT_INITIAL_LEVEL: LevelFilter = LevelFilter::INFO;
+    pub const DEFAULT_SUMMARY_INTERVAL: Duration = Duration::from_secs(5);
 
     const fn default_port() -> u16 {
         Self::DEFAULT_PORT
@@ -492,6 +808,9 @@ impl SerialTraceSettings {
     const fn default_initial_level() -> LevelFilter {
         Self::DEFAULT_INITIAL_LEVEL
     }
+    const fn default_summary_interval() -> Duration {
+        Self::DEFAULT_SUMMARY_INTERVAL
+    }
 
     #[must_use]
     pub const fn new() -> Self {
@@ -501,6 +820,8 @@ impl SerialTraceSettings {
             sendbuf_capacity: Self::DEFAULT_SENDBUF_CAPACITY,
             tracebuf_capacity: Self::DEFAULT_TRACEBUF_CAPACITY,
             initial_level: Self::DEFAULT_INITIAL_LEVEL,
+            mode: TraceMode::Stream,
+            summary_interval: Self::DEFAULT_SUMMARY_INTERVAL,
         }
     }
 
@@ -529,6 +850,19 @@ impl SerialTraceSettings {
         }
     }
 
+    /// Enables [`TraceMode::Summary`], sending span summaries every
+    /// `interval`.
+    ///
+    /// By default, every span's activity is streamed ([`TraceMode::Stream`]).
+    #[must_use]
+    pub const fn with_summary_mode(self, interval: Duration) -> Self {
+        Self {
+            mode: TraceMode::Summary,
+            summary_interval: interval,
+            ..self
+        }
+    }
+
     /// Sets the maximum capacity of the serial port send buffer (the buffer
     /// used for communication between the trace service task and the serial mux
     /// server).
@@ -564,3 +898,43 @@ impl Default for SerialTraceSettings {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TestKernel;
+
+    fn summaries() -> Summaries {
+        Summaries {
+            spans: heapless::Vec::new(),
+            callsites: heapless::Vec::new(),
+        }
+    }
+
+    #[test]
+    fn cloned_span_summary() {
+        TestKernel::run(|k| async move {
+            let meta: MetaId = postcard::from_bytes(&[1]).unwrap();
+            let mut summaries = summaries();
+            assert!(summaries.new_span(1, meta));
+
+            // e.g. a `Span::current()` which goes out of scope while the span
+            // is still entered.
+            summaries.clone_span(1);
+            let start = k.timer().now();
+            assert!(summaries.enter(1, start));
+            assert!(!summaries.close(1));
+
+            assert!(summaries.exit(1, start + Duration::from_millis(5)));
+            let stats = summaries.callsites[0];
+            assert_eq!(stats.meta, meta);
+            assert_eq!(stats.count, 1);
+            assert_eq!(stats.total, Duration::from_millis(5));
+
+            // the span is forgotten once the last handle is dropped.
+            assert!(summaries.close(1));
+            assert!(summaries.spans.is_empty());
+            assert!(!summaries.exit(1, start));
+        });
+    }
+}
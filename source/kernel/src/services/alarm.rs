@@ -0,0 +1,453 @@
+//! # Alarm Service
+//!
+//! Schedules events at absolute wall-clock times, such as "at 07:00 UTC",
+//! rather than after a duration has elapsed, as the kernel's
+//! [timer](crate::Kernel::timer) does.
+//!
+//! Each alarm is set for a time since the Unix epoch, and carries a `tag`
+//! chosen by whoever set it. When the wall-clock time reaches an alarm's
+//! time, the alarm is removed, and an [`AlarmFired`] event is published on
+//! the kernel's [event bus](crate::comms::events). Alarms set for a time that
+//! has already passed fire immediately.
+//!
+//! The wall-clock time is provided by [`Kernel::wall_clock`], which is set
+//! by an RTC driver (or anything else that knows the time) using
+//! [`Kernel::set_wall_clock`]. Alarms may be set before the wall-clock time
+//! is known, but they won't fire until it is. The service re-reads the wall
+//! clock at least every [`AlarmSettings::max_sleep`], so alarms still fire
+//! at the right time if the clock is set, or changed, while they're pending.
+//!
+//! Pending alarms are only held in memory, and do not persist across
+//! reboots. A platform with persistent storage can save the alarms returned
+//! by [`AlarmClient::pending`], which are serializable, and set them again
+//! after booting. Alarm IDs are only unique within a single boot, so the
+//! `tag` should be used to identify an alarm across reboots.
+//!
+//! [`Kernel::wall_clock`]: crate::Kernel::wall_clock
+//! [`Kernel::set_wall_clock`]: crate::Kernel::set_wall_clock
+
+use crate::{
+    comms::{events::AlarmFired, oneshot::Reusable},
+    mnemos_alloc::containers::FixedVec,
+    registry::{self, known_uuids, Envelope, KernelHandle, Message, RegisteredDriver},
+    Kernel,
+};
+use core::{convert::Infallible, fmt, time::Duration};
+use serde::{Deserialize, Serialize};
+use tracing::{Instrument, Level};
+use uuid::Uuid;
+
+////////////////////////////////////////////////////////////////////////////////
+// Service Definition
+////////////////////////////////////////////////////////////////////////////////
+
+/// Service definition for the alarm service.
+pub struct AlarmService;
+
+impl RegisteredDriver for AlarmService {
+    type Request = Request;
+    type Response = Response;
+    type Error = AlarmError;
+    type Hello = ();
+    type ConnectError = Infallible;
+    const UUID: Uuid = known_uuids::kernel::ALARM;
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Message and Error Types
+////////////////////////////////////////////////////////////////////////////////
+
+pub enum Request {
+    /// Set an alarm for the given time since the Unix epoch, with the given
+    /// tag.
+    Set { at: Duration, tag: u32 },
+    /// Cancel a pending alarm.
+    Cancel(AlarmId),
+    /// Get a copy of every pending alarm.
+    Pending,
+}
+
+pub enum Response {
+    /// The alarm was set.
+    Set(AlarmId),
+    /// The alarm was cancelled.
+    Cancelled,
+    /// Every pending alarm, soonest first.
+    Pending(FixedVec<Alarm>),
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum AlarmError {
+    /// The service already has its maximum number of pending alarms.
+    TooManyAlarms { max: usize },
+    /// There is no pending alarm with this ID. It may have already fired, or
+    /// been cancelled.
+    NoSuchAlarm(AlarmId),
+    /// An error occurred sending the request to the service.
+    Request(registry::OneshotRequestError),
+}
+
+/// Identifies a pending alarm.
+///
+/// IDs are unique within a single boot.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct AlarmId(u32);
+
+/// A pending alarm.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Alarm {
+    pub id: AlarmId,
+    /// When the alarm fires, as the time since the Unix epoch (UTC).
+    pub at: Duration,
+    /// The tag provided when the alarm was set.
+    pub tag: u32,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Client Definition
+////////////////////////////////////////////////////////////////////////////////
+
+/// A client for the [`AlarmService`].
+pub struct AlarmClient {
+    handle: KernelHandle<AlarmService>,
+    reply: Reusable<Envelope<Result<Response, AlarmError>>>,
+}
+
+impl AlarmClient {
+    /// Obtain an `AlarmClient`
+    ///
+    /// If the [`AlarmService`] hasn't been registered yet, we will retry
+    /// until it has been registered.
+    pub async fn from_registry(
+        kernel: &'static Kernel,
+    ) -> Result<Self, registry::ConnectError<AlarmService>> {
+        let handle = kernel.registry().connect::<AlarmService>(()).await?;
+        Ok(Self {
+            handle,
+            reply: Reusable::new_async().await,
+        })
+    }
+
+    /// Obtain an `AlarmClient`
+    ///
+    /// Does NOT attempt to get an [`AlarmService`] handle more than once.
+    ///
+    /// Prefer [`AlarmClient::from_registry`] unless you will not be
+    /// spawning one around the same time as obtaining a client.
+    pub async fn from_registry_no_retry(
+        kernel: &'static Kernel,
+    ) -> Result<Self, registry::ConnectError<AlarmService>> {
+        let handle = kernel.registry().try_connect::<AlarmService>(()).await?;
+        Ok(Self {
+            handle,
+            reply: Reusable::new_async().await,
+        })
+    }
+
+    /// Set an alarm for `at`, the time since the Unix epoch (UTC).
+    ///
+    /// When the alarm fires, an [`AlarmFired`] event carrying `tag` is
+    /// published on the event bus.
+    pub async fn set(&mut self, at: Duration, tag: u32) -> Result<AlarmId, AlarmError> {
+        match self.request(Request::Set { at, tag }).await? {
+            Response::Set(id) => Ok(id),
+            _ => unreachable!("service must reply to a Set with Set"),
+        }
+    }
+
+    /// Cancel the pending alarm `id`.
+    pub async fn cancel(&mut self, id: AlarmId) -> Result<(), AlarmError> {
+        match self.request(Request::Cancel(id)).await? {
+            Response::Cancelled => Ok(()),
+            _ => unreachable!("service must reply to a Cancel with Cancelled"),
+        }
+    }
+
+    /// Get a copy of every pending alarm, soonest first.
+    pub async fn pending(&mut self) -> Result<FixedVec<Alarm>, AlarmError> {
+        match self.request(Request::Pending).await? {
+            Response::Pending(alarms) => Ok(alarms),
+            _ => unreachable!("service must reply to a Pending with Pending"),
+        }
+    }
+
+    async fn request(&mut self, req: Request) -> Result<Response, AlarmError> {
+        self.handle
+            .request_oneshot(req, &self.reply)
+            .await
+            .map_err(AlarmError::Request)?
+            .body
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Server Definition
+////////////////////////////////////////////////////////////////////////////////
+
+/// Server implementation for the [`AlarmService`].
+pub struct AlarmServer;
+
+/// Settings for the [`AlarmServer`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AlarmSettings {
+    /// Should the alarm service be enabled?
+    #[serde(default)]
+    pub enabled: bool,
+    /// Maximum number of outstanding requests from clients.
+    #[serde(default = "AlarmSettings::default_capacity")]
+    pub capacity: usize,
+    /// Maximum number of pending alarms.
+    #[serde(default = "AlarmSettings::default_max_alarms")]
+    pub max_alarms: usize,
+    /// The longest the service sleeps before re-reading the wall clock.
+    ///
+    /// This bounds how late an alarm fires if the wall clock is set or
+    /// changed while it is pending.
+    #[serde(default = "AlarmSettings::default_max_sleep")]
+    pub max_sleep: Duration,
+}
+
+impl AlarmServer {
+    /// Register the `AlarmServer`.
+    #[tracing::instrument(
+        name = "AlarmServer::register",
+        level = Level::INFO,
+        skip(kernel, settings),
+        err(Debug),
+    )]
+    pub async fn register(
+        kernel: &'static Kernel,
+        settings: AlarmSettings,
+    ) -> Result<(), registry::RegistrationError> {
+        tracing::info!(?settings, "Starting AlarmServer");
+        let reqs = kernel
+            .registry()
+            .bind_konly::<AlarmService>(settings.capacity)
+            .await?
+            .into_request_stream(settings.capacity)
+            .await;
+
+        let mut alarms = Alarms::new(settings.max_alarms).await;
+        let max_sleep = settings.max_sleep;
+        kernel
+            .spawn(async move {
+                loop {
+                    let now = kernel.wall_clock();
+                    if let Some(now) = now {
+                        alarms.fire_due(now, |alarm| {
+                            tracing::info!(?alarm, "Alarm fired");
+                            kernel.events().publish(AlarmFired {
+                                id: alarm.id,
+                                at: alarm.at,
+                                tag: alarm.tag,
+                            });
+                        });
+                    }
+
+                    let sleep = alarms
+                        .until_next(now)
+                        .map_or(max_sleep, |until| until.min(max_sleep));
+                    let Ok(Message { msg, reply }) =
+                        kernel.timeout(sleep, reqs.next_request()).await
+                    else {
+                        // Time to check the wall clock again.
+                        continue;
+                    };
+
+                    let span = msg.span();
+                    async {
+                        // Copying the pending alarms requires an allocation,
+                        // which must happen before we take the request's body.
+                        let mut copy = None;
+                        if let Request::Pending = msg.body {
+                            copy = Some(alarms.copy().await);
+                        }
+                        let resp = msg.reply_with_body(|req| match req {
+                            Request::Set { at, tag } => alarms.set(at, tag).map(Response::Set),
+                            Request::Cancel(id) => alarms.cancel(id).map(|()| Response::Cancelled),
+                            Request::Pending => Ok(Response::Pending(
+                                copy.take().expect("copy was made for a Pending request"),
+                            )),
+                        });
+                        if let Err(error) = reply.reply_konly(resp).await {
+                            tracing::warn!(?error, "Failed to reply to alarm request");
+                        }
+                    }
+                    .instrument(span)
+                    .await;
+                }
+            })
+            .await;
+
+        Ok(())
+    }
+}
+
+/// The pending alarms, soonest first.
+struct Alarms {
+    pending: FixedVec<Alarm>,
+    next_id: u32,
+}
+
+impl Alarms {
+    async fn new(max: usize) -> Self {
+        Self {
+            // `FixedVec`s can't have a capacity of zero.
+            pending: FixedVec::new(max.max(1)).await,
+            next_id: 0,
+        }
+    }
+
+    fn set(&mut self, at: Duration, tag: u32) -> Result<AlarmId, AlarmError> {
+        let id = AlarmId(self.next_id);
+        let alarm = Alarm { id, at, tag };
+        self.pending
+            .try_push(alarm)
+            .map_err(|_| AlarmError::TooManyAlarms {
+                max: self.pending.capacity(),
+            })?;
+        self.next_id = self.next_id.wrapping_add(1);
+
+        // keep the soonest alarm first, by moving the new alarm back past any
+        // later ones. alarms set for the same time fire in the order they were
+        // set.
+        let pending = self.pending.as_slice_mut();
+        let mut i = pending.len() - 1;
+        while i > 0 && pending[i - 1].at > pending[i].at {
+            pending.swap(i - 1, i);
+            i -= 1;
+        }
+        tracing::debug!(?alarm, "Alarm set");
+        Ok(id)
+    }
+
+    fn cancel(&mut self, id: AlarmId) -> Result<(), AlarmError> {
+        let len = self.pending.len();
+        self.pending.retain(|alarm| alarm.id != id);
+        if self.pending.len() == len {
+            return Err(AlarmError::NoSuchAlarm(id));
+        }
+        tracing::debug!(?id, "Alarm cancelled");
+        Ok(())
+    }
+
+    /// Removes every alarm due at or before `now`, calling `fire` with each.
+    fn fire_due(&mut self, now: Duration, mut fire: impl FnMut(&Alarm)) {
+        self.pending.retain(|alarm| {
+            let due = alarm.at <= now;
+            if due {
+                fire(alarm);
+            }
+            !due
+        });
+    }
+
+    /// Returns how long it is until the next alarm is due, or `None` if there
+    /// are no pending alarms, or the wall-clock time isn't known.
+    fn until_next(&self, now: Option<Duration>) -> Option<Duration> {
+        let next = self.pending.as_slice().first()?;
+        Some(next.at.saturating_sub(now?))
+    }
+
+    async fn copy(&self) -> FixedVec<Alarm> {
+        let mut copy = FixedVec::new(self.pending.len().max(1)).await;
+        // The buffer was allocated with enough capacity.
+        let _ = copy.try_extend_from_slice(self.pending.as_slice());
+        copy
+    }
+}
+
+impl AlarmSettings {
+    pub const DEFAULT_CAPACITY: usize = 4;
+    pub const DEFAULT_MAX_ALARMS: usize = 16;
+    pub const DEFAULT_MAX_SLEEP: Duration = Duration::from_secs(1);
+
+    const fn default_capacity() -> usize {
+        Self::DEFAULT_CAPACITY
+    }
+
+    const fn default_max_alarms() -> usize {
+        Self::DEFAULT_MAX_ALARMS
+    }
+
+    const fn default_max_sleep() -> Duration {
+        Self::DEFAULT_MAX_SLEEP
+    }
+}
+
+impl Default for AlarmSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            capacity: Self::DEFAULT_CAPACITY,
+            max_alarms: Self::DEFAULT_MAX_ALARMS,
+            max_sleep: Self::DEFAULT_MAX_SLEEP,
+        }
+    }
+}
+
+impl fmt::Display for AlarmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TooManyAlarms { max } => write!(f, "no more than {max} alarms may be pending"),
+            Self::NoSuchAlarm(id) => write!(f, "no pending alarm with ID {}", id.0),
+            Self::Request(error) => write!(f, "failed to send alarm request: {error:?}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TestKernel;
+
+    const fn secs(secs: u64) -> Duration {
+        Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn fires_due_alarms_in_order() {
+        TestKernel::run(|_| async move {
+            let mut alarms = Alarms::new(4).await;
+            let late = alarms.set(secs(30), 1).unwrap();
+            let early = alarms.set(secs(10), 2).unwrap();
+            let cancelled = alarms.set(secs(20), 3).unwrap();
+            alarms.cancel(cancelled).unwrap();
+            assert_eq!(
+                alarms.cancel(cancelled),
+                Err(AlarmError::NoSuchAlarm(cancelled))
+            );
+
+            // without a wall-clock time, nothing is due.
+            assert_eq!(alarms.until_next(None), None);
+            assert_eq!(alarms.until_next(Some(secs(5))), Some(secs(5)));
+
+            let mut fired = Vec::new();
+            alarms.fire_due(secs(10), |alarm| fired.push(alarm.id));
+            assert_eq!(fired, [early]);
+            assert_eq!(alarms.until_next(Some(secs(10))), Some(secs(20)));
+
+            // an alarm set in the past is due immediately.
+            let past = alarms.set(secs(1), 4).unwrap();
+            assert_eq!(alarms.until_next(Some(secs(10))), Some(Duration::ZERO));
+            fired.clear();
+            alarms.fire_due(secs(60), |alarm| fired.push(alarm.id));
+            assert_eq!(fired, [past, late]);
+            assert_eq!(alarms.until_next(Some(secs(60))), None);
+        })
+    }
+
+    #[test]
+    fn limits_pending_alarms() {
+        TestKernel::run(|_| async move {
+            let mut alarms = Alarms::new(2).await;
+            alarms.set(secs(1), 0).unwrap();
+            alarms.set(secs(2), 0).unwrap();
+            assert_eq!(
+                alarms.set(secs(3), 0),
+                Err(AlarmError::TooManyAlarms { max: 2 })
+            );
+            assert_eq!(alarms.copy().await.len(), 2);
+        })
+    }
+}
@@ -0,0 +1,608 @@
+//! # Buffer Pools
+//!
+//! Hands out fixed-size, aligned buffers from preallocated pools.
+//!
+//! Drivers with steady-state buffer churn, such as UART RX, network RX, or
+//! audio, can acquire buffers from a [`BufferPool`] without going through the
+//! general-purpose allocator for every packet. Each pool's memory is allocated
+//! once, when the [`BufferPoolServer`] is registered, and every buffer in a
+//! pool is aligned to the pool's configured alignment, making them suitable
+//! for use as DMA targets.
+//!
+//! Clients use the [`BufferPoolClient`] to obtain a [`BufferPool`] handle for
+//! the smallest pool whose buffers are at least a requested size. Buffers are
+//! then [acquired](BufferPool::acquire) directly from the pool handle, without
+//! involving the registry, and are returned to the pool when the
+//! [`PoolBuffer`] is dropped.
+
+use core::{
+    alloc::Layout,
+    convert::Infallible,
+    fmt,
+    ops::{Deref, DerefMut},
+    ptr::NonNull,
+};
+
+use crate::{
+    comms::{
+        kchannel::{KChannel, KConsumer, KProducer},
+        oneshot::Reusable,
+    },
+    registry::{self, known_uuids, Envelope, KernelHandle, Message, RegisteredDriver},
+    Kernel,
+};
+use maitake::sync::Mutex;
+use mnemos_alloc::{
+    containers::{Arc, FixedVec},
+    heap::{alloc, dealloc},
+};
+use serde::{Deserialize, Serialize};
+use tracing::{self, Instrument, Level};
+use uuid::Uuid;
+
+////////////////////////////////////////////////////////////////////////////////
+// Service Definition
+////////////////////////////////////////////////////////////////////////////////
+
+/// Service definition for the buffer pool service.
+pub struct BufferPoolService;
+
+impl RegisteredDriver for BufferPoolService {
+    type Request = Request;
+    type Response = Response;
+    type Error = BufferPoolError;
+    type Hello = ();
+    type ConnectError = Infallible;
+    const UUID: Uuid = known_uuids::kernel::BUFFER_POOL;
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Message and Error Types
+////////////////////////////////////////////////////////////////////////////////
+
+pub enum Request {
+    /// Get a handle to the pool with the smallest buffers that are at least
+    /// `min_size` bytes long.
+    GetPool { min_size: usize },
+}
+
+pub enum Response {
+    Pool(BufferPool),
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum BufferPoolError {
+    /// No configured pool has buffers of at least the requested size.
+    NoPoolLargeEnough { requested: usize },
+    /// An error occurred sending the request to the service.
+    Request(registry::OneshotRequestError),
+}
+
+/// A handle to a pool of fixed-size buffers.
+///
+/// `BufferPool` handles are obtained from the [`BufferPoolService`] using a
+/// [`BufferPoolClient`], and may be cloned freely.
+#[derive(Clone)]
+pub struct BufferPool {
+    inner: Arc<PoolInner>,
+}
+
+/// A buffer acquired from a [`BufferPool`].
+///
+/// The buffer is returned to its pool when the `PoolBuffer` is dropped.
+///
+/// Buffers are *not* zeroed when they are returned to the pool, so a newly
+/// acquired buffer may contain data from a previous user.
+#[must_use = "a `PoolBuffer` is returned to the pool immediately if dropped"]
+pub struct PoolBuffer {
+    pool: Arc<PoolInner>,
+    idx: usize,
+}
+
+struct PoolInner {
+    base: NonNull<u8>,
+    layout: Layout,
+    buf_size: usize,
+    /// Distance between the start of each buffer. This is `buf_size` rounded
+    /// up to the pool's alignment.
+    stride: usize,
+    count: usize,
+    /// Indices of the buffers that are currently free.
+    ///
+    /// The consumer is behind a mutex, as the underlying queue only supports
+    /// a single consumer, but many tasks may wait to acquire buffers at once.
+    free_rx: Mutex<KConsumer<usize>>,
+    free_tx: KProducer<usize>,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Client Definition
+////////////////////////////////////////////////////////////////////////////////
+
+/// A client for the [`BufferPoolService`].
+pub struct BufferPoolClient {
+    handle: KernelHandle<BufferPoolService>,
+    reply: Reusable<Envelope<Result<Response, BufferPoolError>>>,
+}
+
+impl BufferPoolClient {
+    /// Obtain a `BufferPoolClient`
+    ///
+    /// If the [`BufferPoolService`] hasn't been registered yet, we will retry
+    /// until it has been registered.
+    pub async fn from_registry(
+        kernel: &'static Kernel,
+    ) -> Result<Self, registry::ConnectError<BufferPoolService>> {
+        let handle = kernel.registry().connect::<BufferPoolService>(()).await?;
+        Ok(Self {
+            handle,
+            reply: Reusable::new_async().await,
+        })
+    }
+
+    /// Obtain a `BufferPoolClient`
+    ///
+    /// Does NOT attempt to get a [`BufferPoolService`] handle more than once.
+    ///
+    /// Prefer [`BufferPoolClient::from_registry`] unless you will not be
+    /// spawning one around the same time as obtaining a client.
+    pub async fn from_registry_no_retry(
+        kernel: &'static Kernel,
+    ) -> Result<Self, registry::ConnectError<BufferPoolService>> {
+        let handle = kernel
+            .registry()
+            .try_connect::<BufferPoolService>(())
+            .await?;
+        Ok(Self {
+            handle,
+            reply: Reusable::new_async().await,
+        })
+    }
+
+    /// Get a handle to the pool with the smallest buffers that are at least
+    /// `min_size` bytes long.
+    pub async fn get_pool(&mut self, min_size: usize) -> Result<BufferPool, BufferPoolError> {
+        let resp = self
+            .handle
+            .request_oneshot(Request::GetPool { min_size }, &self.reply)
+            .await
+            .map_err(BufferPoolError::Request)?;
+        let Response::Pool(pool) = resp.body?;
+        Ok(pool)
+    }
+}
+
+impl BufferPool {
+    /// Acquire a buffer from the pool, waiting for one to be returned if all
+    /// buffers are currently in use.
+    pub async fn acquire(&self) -> PoolBuffer {
+        let idx = {
+            let free = self.inner.free_rx.lock().await;
+            free.dequeue_async()
+                .await
+                .expect("the free list is never closed while the pool exists")
+        };
+        PoolBuffer {
+            pool: self.inner.clone(),
+            idx,
+        }
+    }
+
+    /// Acquire a buffer from the pool, if one is immediately available.
+    pub fn try_acquire(&self) -> Option<PoolBuffer> {
+        let idx = self.inner.free_rx.try_lock()?.dequeue_sync()?;
+        Some(PoolBuffer {
+            pool: self.inner.clone(),
+            idx,
+        })
+    }
+
+    /// Returns the size, in bytes, of each buffer in this pool.
+    #[must_use]
+    pub fn buffer_size(&self) -> usize {
+        self.inner.buf_size
+    }
+
+    /// Returns the alignment of each buffer in this pool.
+    #[must_use]
+    pub fn align(&self) -> usize {
+        self.inner.layout.align()
+    }
+
+    /// Returns the total number of buffers in this pool.
+    #[must_use]
+    pub fn capacity(&self) -> usize {
+        self.inner.count
+    }
+}
+
+impl fmt::Debug for BufferPool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BufferPool")
+            .field("buffer_size", &self.buffer_size())
+            .field("align", &self.align())
+            .field("capacity", &self.capacity())
+            .finish()
+    }
+}
+
+impl PoolBuffer {
+    fn ptr(&self) -> *mut u8 {
+        unsafe {
+            // Safety: `idx` is always less than `count`, so this is within the
+            // pool's allocation.
+            self.pool.base.as_ptr().add(self.idx * self.pool.stride)
+        }
+    }
+}
+
+impl Deref for PoolBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        // Safety: this buffer's index was removed from the free list, so no
+        // other `PoolBuffer` refers to this memory.
+        unsafe { core::slice::from_raw_parts(self.ptr(), self.pool.buf_size) }
+    }
+}
+
+impl DerefMut for PoolBuffer {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        // Safety: this buffer's index was removed from the free list, so no
+        // other `PoolBuffer` refers to this memory.
+        unsafe { core::slice::from_raw_parts_mut(self.ptr(), self.pool.buf_size) }
+    }
+}
+
+impl Drop for PoolBuffer {
+    fn drop(&mut self) {
+        // The free list has room for every buffer in the pool, so this can only
+        // fail if the free list was closed, which never happens.
+        let res = self.pool.free_tx.enqueue_sync(self.idx);
+        debug_assert!(res.is_ok(), "returning a buffer to its pool failed");
+    }
+}
+
+impl fmt::Debug for PoolBuffer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PoolBuffer")
+            .field("addr", &self.ptr())
+            .field("len", &self.pool.buf_size)
+            .finish()
+    }
+}
+
+impl PoolInner {
+    async fn new(settings: &PoolSettings) -> Option<Self> {
+        let PoolSettings { size, count, align } = *settings;
+        if size == 0 || count == 0 {
+            return None;
+        }
+        let stride = size.checked_next_multiple_of(align)?;
+        let layout = Layout::from_size_align(stride.checked_mul(count)?, align).ok()?;
+        let base = alloc(layout).await;
+
+        // The free list needs room for every buffer, so returning a buffer
+        // never has to wait.
+        let (free_tx, free_rx) = KChannel::new_async(count + 1).await.split();
+        for idx in 0..count {
+            free_tx
+                .enqueue_sync(idx)
+                .unwrap_or_else(|_| unreachable!("free list has room for all buffers"));
+        }
+
+        Some(Self {
+            base,
+            layout,
+            buf_size: size,
+            stride,
+            count,
+            free_rx: Mutex::new(free_rx),
+            free_tx,
+        })
+    }
+}
+
+impl Drop for PoolInner {
+    fn drop(&mut self) {
+        // Every `PoolBuffer` holds a reference to the pool, so if we are being
+        // dropped, no buffers are in use.
+        unsafe { dealloc(self.base.as_ptr(), self.layout) }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Server Definition
+////////////////////////////////////////////////////////////////////////////////
+
+/// Server implementation for the [`BufferPoolService`].
+pub struct BufferPoolServer;
+
+/// The maximum number of pools that may be configured.
+pub const MAX_POOLS: usize = 8;
+
+/// Settings for the [`BufferPoolServer`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BufferPoolSettings {
+    /// Should the buffer pool service be enabled?
+    ///
+    /// Defaults to `false`, as the pools' memory is allocated up front.
+    #[serde(default)]
+    pub enabled: bool,
+    /// Maximum number of outstanding requests from clients.
+    #[serde(default = "BufferPoolSettings::default_capacity")]
+    pub capacity: usize,
+    /// The pools to allocate.
+    #[serde(default = "BufferPoolSettings::default_pools")]
+    pub pools: heapless::Vec<PoolSettings, MAX_POOLS>,
+}
+
+/// Settings for a single pool of buffers.
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+pub struct PoolSettings {
+    /// Size of each buffer in the pool, in bytes.
+    pub size: usize,
+    /// Number of buffers in the pool.
+    pub count: usize,
+    /// Alignment of each buffer in the pool. Must be a power of two.
+    #[serde(default = "PoolSettings::default_align")]
+    pub align: usize,
+}
+
+#[derive(Debug)]
+pub enum RegistrationError {
+    Registry(registry::RegistrationError),
+    /// No pools were configured.
+    NoPools,
+    /// A pool's settings were invalid.
+    InvalidPool(PoolSettings),
+}
+
+impl From<RegistrationError> for crate::Error {
+    fn from(error: RegistrationError) -> Self {
+        let reason = match error {
+            RegistrationError::Registry(error) => return error.into(),
+            RegistrationError::NoPools => "no pools were configured",
+            RegistrationError::InvalidPool(_) => "a pool's settings were invalid",
+        };
+        Self::ServiceInit {
+            uuid: BufferPoolService::UUID,
+            reason,
+        }
+    }
+}
+
+impl BufferPoolServer {
+    /// Register the `BufferPoolServer`, allocating all configured pools.
+    ///
+    /// Returns [`RegistrationError::NoPools`] if no pools are configured, as
+    /// such a server could never satisfy a request.
+    #[tracing::instrument(
+        name = "BufferPoolServer::register",
+        level = Level::INFO,
+        skip(kernel, settings),
+        err(Debug),
+    )]
+    pub async fn register(
+        kernel: &'static Kernel,
+        settings: BufferPoolSettings,
+    ) -> Result<(), RegistrationError> {
+        tracing::info!(?settings, "Starting BufferPoolServer");
+
+        if settings.pools.is_empty() {
+            return Err(RegistrationError::NoPools);
+        }
+
+        let mut pools = FixedVec::new(settings.pools.len()).await;
+        for pool in &settings.pools {
+            let inner = PoolInner::new(pool)
+                .await
+                .ok_or(RegistrationError::InvalidPool(*pool))?;
+            tracing::debug!(
+                size = pool.size,
+                count = pool.count,
+                align = pool.align,
+                "Allocated buffer pool"
+            );
+            let pool = BufferPool {
+                inner: Arc::new(inner).await,
+            };
+            let _ = pools.try_push(pool);
+        }
+        // Sort pools by buffer size, so that the first pool large enough to
+        // satisfy a request is also the smallest.
+        pools
+            .as_slice_mut()
+            .sort_unstable_by_key(BufferPool::buffer_size);
+
+        let reqs = kernel
+            .registry()
+            .bind_konly::<BufferPoolService>(settings.capacity)
+            .await
+            .map_err(RegistrationError::Registry)?
+            .into_request_stream(settings.capacity)
+            .await;
+
+        kernel
+            .spawn(async move {
+                loop {
+                    let Message { msg, reply } = reqs.next_request().await;
+                    let span = msg.span();
+                    async {
+                        let Request::GetPool { min_size } = msg.body;
+                        let res = pools
+                            .as_slice()
+                            .iter()
+                            .find(|pool| pool.buffer_size() >= min_size)
+                            .map(|pool| Response::Pool(pool.clone()))
+                            .ok_or(BufferPoolError::NoPoolLargeEnough {
+                                requested: min_size,
+                            });
+                        if let Err(error) = reply.reply_konly(msg.reply_with(res)).await {
+                            tracing::warn!(?error, "Failed to reply to buffer pool request");
+                        }
+                    }
+                    .instrument(span)
+                    .await;
+                }
+            })
+            .await;
+
+        Ok(())
+    }
+}
+
+impl BufferPoolSettings {
+    pub const DEFAULT_CAPACITY: usize = 4;
+
+    const fn default_capacity() -> usize {
+        Self::DEFAULT_CAPACITY
+    }
+
+    fn default_pools() -> heapless::Vec<PoolSettings, MAX_POOLS> {
+        let mut pools = heapless::Vec::new();
+        let _ = pools.push(PoolSettings::new(64, 16));
+        let _ = pools.push(PoolSettings::new(512, 8));
+        let _ = pools.push(PoolSettings::new(2048, 4));
+        pools
+    }
+}
+
+impl Default for BufferPoolSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            capacity: Self::DEFAULT_CAPACITY,
+            pools: Self::default_pools(),
+        }
+    }
+}
+
+impl PoolSettings {
+    /// The default alignment of buffers: a common cache line size, so that
+    /// buffers used for DMA never share a cache line.
+    pub const DEFAULT_ALIGN: usize = 64;
+
+    const fn default_align() -> usize {
+        Self::DEFAULT_ALIGN
+    }
+
+    pub const fn new(size: usize, count: usize) -> Self {
+        Self {
+            size,
+            count,
+            align: Self::DEFAULT_ALIGN,
+        }
+    }
+
+    pub const fn with_align(self, align: usize) -> Self {
+        Self { align, ..self }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TestKernel;
+
+    fn settings(pools: &[PoolSettings]) -> BufferPoolSettings {
+        BufferPoolSettings {
+            enabled: true,
+            pools: heapless::Vec::from_slice(pools).unwrap(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn acquire_and_release() {
+        TestKernel::run(|k| async move {
+            let pools = [
+                PoolSettings::new(512, 2),
+                PoolSettings::new(64, 4).with_align(16),
+            ];
+            BufferPoolServer::register(k, settings(&pools))
+                .await
+                .unwrap();
+            let mut client = BufferPoolClient::from_registry(k).await.unwrap();
+
+            // the smallest pool that is large enough is chosen.
+            let small = client.get_pool(10).await.unwrap();
+            assert_eq!(small.buffer_size(), 64);
+            assert_eq!(small.align(), 16);
+            assert_eq!(small.capacity(), 4);
+            let large = client.get_pool(65).await.unwrap();
+            assert_eq!(large.buffer_size(), 512);
+            assert_eq!(
+                client.get_pool(513).await.unwrap_err(),
+                BufferPoolError::NoPoolLargeEnough { requested: 513 }
+            );
+
+            let mut a = small.acquire().await;
+            let mut b = small.try_acquire().unwrap();
+            assert_eq!(a.len(), 64);
+            assert_eq!(a.as_ptr() as usize % 16, 0);
+            assert_eq!(b.as_ptr() as usize % 16, 0);
+            assert_ne!(a.as_ptr(), b.as_ptr());
+            a.fill(0xAA);
+            b.fill(0x55);
+            assert!(a.iter().all(|&byte| byte == 0xAA));
+
+            // a released buffer can be acquired again.
+            let addr = a.as_ptr();
+            drop(a);
+            let mut again = Vec::new();
+            while let Some(buf) = small.try_acquire() {
+                again.push(buf);
+            }
+            assert_eq!(again.len(), 3);
+            assert!(again.iter().any(|buf| buf.as_ptr() == addr));
+        })
+    }
+
+    #[test]
+    fn exhaustion() {
+        TestKernel::run(|k| async move {
+            BufferPoolServer::register(k, settings(&[PoolSettings::new(32, 2)]))
+                .await
+                .unwrap();
+            let pool = BufferPoolClient::from_registry(k)
+                .await
+                .unwrap()
+                .get_pool(32)
+                .await
+                .unwrap();
+
+            let a = pool.acquire().await;
+            let b = pool.acquire().await;
+            assert!(pool.try_acquire().is_none());
+
+            // a task waiting to acquire a buffer is woken when one is released.
+            let waiter = k
+                .spawn({
+                    let pool = pool.clone();
+                    async move { pool.acquire().await.as_ptr() as usize }
+                })
+                .await;
+            for _ in 0..4 {
+                k.yield_now().await;
+            }
+            let addr = b.as_ptr() as usize;
+            drop(b);
+            assert_eq!(waiter.await.unwrap(), addr);
+            drop(a);
+            assert!(pool.try_acquire().is_some());
+        })
+    }
+
+    #[test]
+    fn rejects_invalid_settings() {
+        TestKernel::run(|k| async move {
+            let res = BufferPoolServer::register(k, settings(&[])).await;
+            assert!(matches!(res, Err(RegistrationError::NoPools)));
+
+            let empty = PoolSettings::new(64, 0);
+            let res = BufferPoolServer::register(k, settings(&[empty])).await;
+            assert!(matches!(res, Err(RegistrationError::InvalidPool(_))));
+        })
+    }
+}
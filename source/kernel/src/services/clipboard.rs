@@ -0,0 +1,295 @@
+//! # Clipboard Service
+//!
+//! A shared clipboard, holding a single buffer of bytes, so that shells,
+//! editors, and the window manager can exchange "kill" and "yank" data.
+//!
+//! The clipboard's contents are stored on the heap, and are limited to a
+//! configured maximum size. Setting the clipboard replaces its contents
+//! entirely; getting the clipboard returns a copy of its contents.
+//!
+//! The clipboard may also be set and read from the host over a SerMux port,
+//! using the [`daemons::sermux::clipboard`](crate::daemons::sermux::clipboard)
+//! bridge.
+
+use crate::{
+    comms::oneshot::Reusable,
+    mnemos_alloc::containers::FixedVec,
+    registry::{self, known_uuids, Envelope, KernelHandle, Message, RegisteredDriver},
+    Kernel,
+};
+use core::convert::Infallible;
+use serde::{Deserialize, Serialize};
+use tracing::{Instrument, Level};
+use uuid::Uuid;
+
+////////////////////////////////////////////////////////////////////////////////
+// Service Definition
+////////////////////////////////////////////////////////////////////////////////
+
+/// Service definition for the clipboard service.
+pub struct ClipboardService;
+
+impl RegisteredDriver for ClipboardService {
+    type Request = Request;
+    type Response = Response;
+    type Error = ClipboardError;
+    type Hello = ();
+    type ConnectError = Infallible;
+    const UUID: Uuid = known_uuids::kernel::CLIPBOARD;
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Message and Error Types
+////////////////////////////////////////////////////////////////////////////////
+
+pub enum Request {
+    /// Replace the clipboard's contents.
+    Set(FixedVec<u8>),
+    /// Get a copy of the clipboard's contents.
+    Get,
+}
+
+pub enum Response {
+    /// The clipboard's contents were replaced.
+    Set,
+    /// A copy of the clipboard's contents.
+    Contents(FixedVec<u8>),
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum ClipboardError {
+    /// The data is larger than the clipboard's maximum size.
+    TooLarge { len: usize, max: usize },
+    /// An error occurred sending the request to the service.
+    Request(registry::OneshotRequestError),
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Client Definition
+////////////////////////////////////////////////////////////////////////////////
+
+/// A client for the [`ClipboardService`].
+pub struct ClipboardClient {
+    handle: KernelHandle<ClipboardService>,
+    reply: Reusable<Envelope<Result<Response, ClipboardError>>>,
+}
+
+impl ClipboardClient {
+    /// Obtain a `ClipboardClient`
+    ///
+    /// If the [`ClipboardService`] hasn't been registered yet, we will retry
+    /// until it has been registered.
+    pub async fn from_registry(
+        kernel: &'static Kernel,
+    ) -> Result<Self, registry::ConnectError<ClipboardService>> {
+        let handle = kernel.registry().connect::<ClipboardService>(()).await?;
+        Ok(Self {
+            handle,
+            reply: Reusable::new_async().await,
+        })
+    }
+
+    /// Obtain a `ClipboardClient`
+    ///
+    /// Does NOT attempt to get a [`ClipboardService`] handle more than once.
+    ///
+    /// Prefer [`ClipboardClient::from_registry`] unless you will not be
+    /// spawning one around the same time as obtaining a client.
+    pub async fn from_registry_no_retry(
+        kernel: &'static Kernel,
+    ) -> Result<Self, registry::ConnectError<ClipboardService>> {
+        let handle = kernel
+            .registry()
+            .try_connect::<ClipboardService>(())
+            .await?;
+        Ok(Self {
+            handle,
+            reply: Reusable::new_async().await,
+        })
+    }
+
+    /// Replace the clipboard's contents with `data`.
+    pub async fn set(&mut self, data: FixedVec<u8>) -> Result<(), ClipboardError> {
+        self.request(Request::Set(data)).await?;
+        Ok(())
+    }
+
+    /// Replace the clipboard's contents with a copy of `data`.
+    pub async fn set_from_slice(&mut self, data: &[u8]) -> Result<(), ClipboardError> {
+        self.set(alloc_copy(data).await).await
+    }
+
+    /// Get a copy of the clipboard's contents.
+    ///
+    /// If the clipboard has never been set, this returns an empty buffer.
+    pub async fn get(&mut self) -> Result<FixedVec<u8>, ClipboardError> {
+        match self.request(Request::Get).await? {
+            Response::Contents(data) => Ok(data),
+            Response::Set => unreachable!("service must reply to a Get with Contents"),
+        }
+    }
+
+    async fn request(&mut self, req: Request) -> Result<Response, ClipboardError> {
+        self.handle
+            .request_oneshot(req, &self.reply)
+            .await
+            .map_err(ClipboardError::Request)?
+            .body
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Server Definition
+////////////////////////////////////////////////////////////////////////////////
+
+/// Server implementation for the [`ClipboardService`].
+pub struct ClipboardServer;
+
+/// Settings for the [`ClipboardServer`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClipboardSettings {
+    /// Should the clipboard service be enabled?
+    #[serde(default)]
+    pub enabled: bool,
+    /// Maximum number of outstanding requests from clients.
+    #[serde(default = "ClipboardSettings::default_capacity")]
+    pub capacity: usize,
+    /// Maximum size of the clipboard's contents, in bytes.
+    #[serde(default = "ClipboardSettings::default_max_len")]
+    pub max_len: usize,
+}
+
+impl ClipboardServer {
+    /// Register the `ClipboardServer`.
+    #[tracing::instrument(
+        name = "ClipboardServer::register",
+        level = Level::INFO,
+        skip(kernel, settings),
+        err(Debug),
+    )]
+    pub async fn register(
+        kernel: &'static Kernel,
+        settings: ClipboardSettings,
+    ) -> Result<(), registry::RegistrationError> {
+        tracing::info!(?settings, "Starting ClipboardServer");
+        let reqs = kernel
+            .registry()
+            .bind_konly::<ClipboardService>(settings.capacity)
+            .await?
+            .into_request_stream(settings.capacity)
+            .await;
+
+        let max = settings.max_len;
+        kernel
+            .spawn(async move {
+                let mut contents: Option<FixedVec<u8>> = None;
+                loop {
+                    let Message { msg, reply } = reqs.next_request().await;
+                    let span = msg.span();
+                    async {
+                        // Copying the contents requires an allocation, which must
+                        // happen before we take the request's body.
+                        let mut copy = None;
+                        if let Request::Get = msg.body {
+                            let data = contents.as_ref().map(FixedVec::as_slice).unwrap_or(&[]);
+                            copy = Some(alloc_copy(data).await);
+                        }
+                        let resp = msg.reply_with_body(|req| match req {
+                            Request::Set(data) if data.len() > max => {
+                                Err(ClipboardError::TooLarge {
+                                    len: data.len(),
+                                    max,
+                                })
+                            }
+                            Request::Set(data) => {
+                                tracing::debug!(len = data.len(), "Clipboard set");
+                                contents = Some(data);
+                                Ok(Response::Set)
+                            }
+                            Request::Get => Ok(Response::Contents(
+                                copy.take().expect("copy was made for a Get request"),
+                            )),
+                        });
+                        if let Err(error) = reply.reply_konly(resp).await {
+                            tracing::warn!(?error, "Failed to reply to clipboard request");
+                        }
+                    }
+                    .instrument(span)
+                    .await;
+                }
+            })
+            .await;
+
+        Ok(())
+    }
+}
+
+/// Allocates a `FixedVec` containing a copy of `data`.
+async fn alloc_copy(data: &[u8]) -> FixedVec<u8> {
+    // `FixedVec`s can't have a capacity of zero.
+    let mut buf = FixedVec::new(data.len().max(1)).await;
+    // The buffer was allocated with enough capacity.
+    let _ = buf.try_extend_from_slice(data);
+    buf
+}
+
+impl ClipboardSettings {
+    pub const DEFAULT_CAPACITY: usize = 4;
+    pub const DEFAULT_MAX_LEN: usize = 4096;
+
+    const fn default_capacity() -> usize {
+        Self::DEFAULT_CAPACITY
+    }
+
+    const fn default_max_len() -> usize {
+        Self::DEFAULT_MAX_LEN
+    }
+}
+
+impl Default for ClipboardSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true, // Should this default to false?
+            capacity: Self::DEFAULT_CAPACITY,
+            max_len: Self::DEFAULT_MAX_LEN,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TestKernel;
+
+    #[test]
+    fn set_and_get() {
+        TestKernel::run(|k| async move {
+            let settings = ClipboardSettings {
+                max_len: 8,
+                ..Default::default()
+            };
+            ClipboardServer::register(k, settings).await.unwrap();
+            let mut a = ClipboardClient::from_registry(k).await.unwrap();
+            let mut b = ClipboardClient::from_registry(k).await.unwrap();
+
+            // the clipboard starts out empty.
+            assert_eq!(a.get().await.unwrap().as_slice(), b"");
+
+            // it's shared between clients.
+            a.set_from_slice(b"yank\0").await.unwrap();
+            assert_eq!(b.get().await.unwrap().as_slice(), b"yank\0");
+
+            // setting replaces the contents entirely.
+            b.set_from_slice(b"kill").await.unwrap();
+            assert_eq!(a.get().await.unwrap().as_slice(), b"kill");
+
+            // contents larger than the maximum are rejected, and the old
+            // contents are kept.
+            assert_eq!(
+                a.set_from_slice(b"too large").await,
+                Err(ClipboardError::TooLarge { len: 9, max: 8 })
+            );
+            assert_eq!(b.get().await.unwrap().as_slice(), b"kill");
+        })
+    }
+}
@@ -0,0 +1,424 @@
+//! # Device Identity Service
+//!
+//! A stable, unique ID for the device the kernel is running on, and a
+//! human-readable hostname for it, so that host tools, networks, and other
+//! devices can tell several MnemOS devices apart.
+//!
+//! The ID is derived from something burned into the hardware (such as the
+//! Allwinner D1's SID eFuses, an x86 machine's SMBIOS system UUID, or an
+//! ESP32's factory MAC address), so it must be read by the platform
+//! implementation, which then registers the [`DeviceIdentityServer`] with it.
+//! The hostname is taken from the [`DeviceIdentitySettings`], or, if none is
+//! configured, derived from the ID (such as `mnemos-5a0c3e`).
+//!
+//! Once the server is registered, the identity is also available without
+//! making a request, using [`current`]. The [serial trace
+//! subscriber](crate::serial_trace) includes it in its heartbeats, so that
+//! `crowtty` can report which device it is connected to, and a network stack
+//! should use [`DeviceIdentity::hostname`] as its DHCP hostname.
+
+use core::{fmt, ptr};
+
+use portable_atomic::{AtomicPtr, Ordering};
+use serde::{Deserialize, Serialize};
+use tracing::{Instrument, Level};
+use uuid::Uuid;
+
+use crate::{
+    comms::oneshot::Reusable,
+    mnemos_alloc::containers::Box,
+    registry::{self, known_uuids, Envelope, KernelHandle, Message, RegisteredDriver},
+    Kernel,
+};
+
+/// The maximum length of a hostname, in bytes.
+///
+/// This is the maximum length of a single DNS label.
+pub const HOSTNAME_MAX_LEN: usize = 63;
+
+////////////////////////////////////////////////////////////////////////////////
+// Service Definition
+////////////////////////////////////////////////////////////////////////////////
+
+/// Service definition for the device identity service.
+pub struct DeviceIdentityService;
+
+impl RegisteredDriver for DeviceIdentityService {
+    type Request = Request;
+    type Response = Response;
+    type Error = DeviceIdentityError;
+    type Hello = ();
+    type ConnectError = core::convert::Infallible;
+
+    const UUID: Uuid = known_uuids::kernel::DEVICE_IDENTITY;
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Message and Error Types
+////////////////////////////////////////////////////////////////////////////////
+
+pub enum Request {
+    /// Get the device's identity.
+    GetIdentity,
+}
+
+pub enum Response {
+    /// The device's identity.
+    Identity(DeviceIdentity),
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum DeviceIdentityError {
+    /// An error occurred sending the request to the service.
+    Request(registry::OneshotRequestError),
+}
+
+/// A stable, unique ID for a device.
+///
+/// IDs shorter than 16 bytes (such as MAC addresses) are zero-padded at the
+/// *start*, so that the last bytes of an ID are always the most
+/// device-specific.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct DeviceId([u8; 16]);
+
+/// The identity of the device the kernel is running on.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DeviceIdentity {
+    id: DeviceId,
+    hostname: heapless::String<HOSTNAME_MAX_LEN>,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Client Definition
+////////////////////////////////////////////////////////////////////////////////
+
+/// A client for the [`DeviceIdentityService`].
+pub struct DeviceIdentityClient {
+    handle: KernelHandle<DeviceIdentityService>,
+    reply: Reusable<Envelope<Result<Response, DeviceIdentityError>>>,
+}
+
+impl DeviceIdentityClient {
+    /// Obtain a `DeviceIdentityClient`
+    ///
+    /// If the [`DeviceIdentityService`] hasn't been registered yet, we will
+    /// retry until it has been registered.
+    pub async fn from_registry(
+        kernel: &'static Kernel,
+    ) -> Result<Self, registry::ConnectError<DeviceIdentityService>> {
+        let handle = kernel
+            .registry()
+            .connect::<DeviceIdentityService>(())
+            .await?;
+
+        Ok(Self {
+            handle,
+            reply: Reusable::new_async().await,
+        })
+    }
+
+    /// Obtain a `DeviceIdentityClient`
+    ///
+    /// Does NOT attempt to get a [`DeviceIdentityService`] handle more than
+    /// once.
+    pub async fn from_registry_no_retry(
+        kernel: &'static Kernel,
+    ) -> Result<Self, registry::ConnectError<DeviceIdentityService>> {
+        let handle = kernel
+            .registry()
+            .try_connect::<DeviceIdentityService>(())
+            .await?;
+
+        Ok(Self {
+            handle,
+            reply: Reusable::new_async().await,
+        })
+    }
+
+    /// Get the device's identity.
+    pub async fn identity(&mut self) -> Result<DeviceIdentity, DeviceIdentityError> {
+        let Response::Identity(identity) = self
+            .handle
+            .request_oneshot(Request::GetIdentity, &self.reply)
+            .await
+            .map_err(DeviceIdentityError::Request)?
+            .body?;
+        Ok(identity)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Server Definition
+////////////////////////////////////////////////////////////////////////////////
+
+/// Server implementation for the [`DeviceIdentityService`].
+pub struct DeviceIdentityServer;
+
+/// Settings for the [`DeviceIdentityServer`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeviceIdentitySettings {
+    /// Maximum number of outstanding requests from clients.
+    #[serde(default = "DeviceIdentitySettings::default_capacity")]
+    pub capacity: usize,
+    /// The device's hostname.
+    ///
+    /// If this is not set, the hostname is derived from the device's ID and
+    /// the [`hostname_prefix`](Self::hostname_prefix).
+    #[serde(default)]
+    pub hostname: Option<heapless::String<HOSTNAME_MAX_LEN>>,
+    /// The prefix of hostnames derived from the device's ID.
+    #[serde(default = "DeviceIdentitySettings::default_hostname_prefix")]
+    pub hostname_prefix: heapless::String<32>,
+}
+
+/// The device's identity, once the [`DeviceIdentityServer`] is registered.
+static CURRENT: AtomicPtr<DeviceIdentity> = AtomicPtr::new(ptr::null_mut());
+
+/// Returns the identity of the device the kernel is running on, or [`None`]
+/// if the platform has not registered the [`DeviceIdentityServer`] (yet).
+#[must_use]
+pub fn current() -> Option<&'static DeviceIdentity> {
+    // Safety: the identity is leaked when it's published, and never changed
+    // afterwards.
+    unsafe { CURRENT.load(Ordering::Acquire).as_ref() }
+}
+
+impl DeviceIdentityServer {
+    /// Register the `DeviceIdentityServer`, for the device with the given
+    /// `id`.
+    #[tracing::instrument(
+        name = "DeviceIdentityServer::register",
+        level = Level::INFO,
+        skip(kernel, settings),
+        err(Debug),
+    )]
+    pub async fn register(
+        kernel: &'static Kernel,
+        settings: DeviceIdentitySettings,
+        id: DeviceId,
+    ) -> Result<(), registry::RegistrationError> {
+        let reqs = kernel
+            .registry()
+            .bind_konly::<DeviceIdentityService>(settings.capacity)
+            .await?
+            .into_request_stream(settings.capacity)
+            .await;
+
+        let identity = DeviceIdentity::new(id, &settings);
+        tracing::info!(
+            id = %identity.id,
+            hostname = %identity.hostname,
+            "Starting DeviceIdentityServer"
+        );
+
+        // Only one server can be bound, so the identity is only ever
+        // published once.
+        CURRENT.store(
+            Box::into_raw(Box::new(identity.clone()).await),
+            Ordering::Release,
+        );
+
+        kernel
+            .spawn(async move {
+                loop {
+                    let Message { msg, reply } = reqs.next_request().await;
+                    let span = msg.span();
+                    async {
+                        let resp = msg.reply_with_body(|Request::GetIdentity| {
+                            Ok(Response::Identity(identity.clone()))
+                        });
+                        if let Err(error) = reply.reply_konly(resp).await {
+                            tracing::warn!(?error, "Failed to reply to device identity request");
+                        }
+                    }
+                    .instrument(span)
+                    .await;
+                }
+            })
+            .await;
+
+        Ok(())
+    }
+}
+
+// === impl DeviceId ===
+
+impl DeviceId {
+    /// Returns a `DeviceId` from 16 bytes, such as a UUID.
+    #[must_use]
+    pub const fn new(bytes: [u8; 16]) -> Self {
+        Self(bytes)
+    }
+
+    /// Returns a `DeviceId` from a (factory-assigned) MAC address.
+    #[must_use]
+    pub const fn from_mac(mac: [u8; 6]) -> Self {
+        let mut bytes = [0; 16];
+        let mut i = 0;
+        while i < mac.len() {
+            bytes[10 + i] = mac[i];
+            i += 1;
+        }
+        Self(bytes)
+    }
+
+    /// Returns the ID's bytes.
+    #[must_use]
+    pub const fn as_bytes(&self) -> &[u8; 16] {
+        &self.0
+    }
+}
+
+impl fmt::Display for DeviceId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for byte in self.0 {
+            write!(f, "{byte:02x}")?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for DeviceId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "DeviceId({self})")
+    }
+}
+
+// === impl DeviceIdentity ===
+
+impl DeviceIdentity {
+    fn new(id: DeviceId, settings: &DeviceIdentitySettings) -> Self {
+        let mut hostname = heapless::String::new();
+        match settings.hostname {
+            Some(ref configured) => push_label(&mut hostname, configured),
+            None => {
+                push_label(&mut hostname, &settings.hostname_prefix);
+                if !hostname.is_empty() {
+                    let _ = hostname.push('-');
+                }
+                let [.., a, b, c] = id.0;
+                let _ = fmt::write(&mut hostname, format_args!("{a:02x}{b:02x}{c:02x}"));
+            }
+        }
+        if hostname.is_empty() {
+            push_label(
+                &mut hostname,
+                DeviceIdentitySettings::DEFAULT_HOSTNAME_PREFIX,
+            );
+        }
+        Self { id, hostname }
+    }
+
+    /// Returns the device's unique ID.
+    #[must_use]
+    pub fn id(&self) -> DeviceId {
+        self.id
+    }
+
+    /// Returns the device's hostname.
+    ///
+    /// This is always a valid DNS label: at most [`HOSTNAME_MAX_LEN`] bytes
+    /// of lowercase ASCII letters, digits, and hyphens, not starting or
+    /// ending with a hyphen.
+    #[must_use]
+    pub fn hostname(&self) -> &str {
+        &self.hostname
+    }
+}
+
+impl fmt::Display for DeviceIdentity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.hostname, self.id)
+    }
+}
+
+/// Appends `s` to `hostname`, replacing anything that isn't valid in a DNS
+/// label with hyphens, and dropping leading, trailing, and repeated hyphens.
+fn push_label(hostname: &mut heapless::String<HOSTNAME_MAX_LEN>, s: &str) {
+    let start = hostname.len();
+    for c in s.chars() {
+        let c = match c.to_ascii_lowercase() {
+            c @ ('a'..='z' | '0'..='9') => c,
+            _ if hostname.len() == start || hostname.ends_with('-') => continue,
+            _ => '-',
+        };
+        if hostname.push(c).is_err() {
+            break;
+        }
+    }
+    while hostname.len() > start && hostname.ends_with('-') {
+        hostname.pop();
+    }
+}
+
+// === impl DeviceIdentitySettings ===
+
+impl DeviceIdentitySettings {
+    pub const DEFAULT_CAPACITY: usize = 4;
+    pub const DEFAULT_HOSTNAME_PREFIX: &'static str = "mnemos";
+
+    const fn default_capacity() -> usize {
+        Self::DEFAULT_CAPACITY
+    }
+
+    fn default_hostname_prefix() -> heapless::String<32> {
+        heapless::String::from(Self::DEFAULT_HOSTNAME_PREFIX)
+    }
+}
+
+impl Default for DeviceIdentitySettings {
+    fn default() -> Self {
+        Self {
+            capacity: Self::DEFAULT_CAPACITY,
+            hostname: None,
+            hostname_prefix: Self::default_hostname_prefix(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ID: DeviceId = DeviceId::from_mac([0x58, 0xcf, 0x79, 0x5a, 0x0c, 0x3e]);
+
+    fn settings(hostname: Option<&str>, prefix: &str) -> DeviceIdentitySettings {
+        DeviceIdentitySettings {
+            hostname: hostname.map(heapless::String::from),
+            hostname_prefix: heapless::String::from(prefix),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn derived_hostname() {
+        let identity = DeviceIdentity::new(ID, &DeviceIdentitySettings::default());
+        assert_eq!(identity.hostname(), "mnemos-5a0c3e");
+
+        let identity = DeviceIdentity::new(ID, &settings(None, "Lichee RV"));
+        assert_eq!(identity.hostname(), "lichee-rv-5a0c3e");
+
+        let identity = DeviceIdentity::new(ID, &settings(None, ""));
+        assert_eq!(identity.hostname(), "5a0c3e");
+    }
+
+    #[test]
+    fn configured_hostname() {
+        let identity = DeviceIdentity::new(ID, &settings(Some("beepy"), "mnemos"));
+        assert_eq!(identity.hostname(), "beepy");
+
+        let identity = DeviceIdentity::new(ID, &settings(Some("-Lab Beepy #2!-"), "mnemos"));
+        assert_eq!(identity.hostname(), "lab-beepy-2");
+
+        let identity = DeviceIdentity::new(ID, &settings(Some("!!!"), "mnemos"));
+        assert_eq!(identity.hostname(), "mnemos");
+    }
+
+    #[test]
+    fn device_id_display() {
+        assert_eq!(
+            std::string::ToString::to_string(&ID),
+            "0000000000000000000058cf795a0c3e"
+        );
+    }
+}
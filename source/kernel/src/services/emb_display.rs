@@ -8,16 +8,76 @@
 //!
 //! See the docs of [FrameChunk] and [EmbDisplayClient] for additional details
 //! of use.
+//!
+//! ## Rotation and Scaling
+//!
+//! Displays are not always mounted the way up their pixels are addressed, and
+//! small, high-DPI panels may be easier to read with larger pixels. A display
+//! server may report a [DisplayTransform] in its [DisplayMetadata], which is
+//! applied by the [EmbDisplayClient]: clients draw in rotated and scaled
+//! ("logical") coordinates, and the client transforms each chunk into the
+//! display's physical coordinates before sending it to the server. This means
+//! that individual display drivers only need to report their configured
+//! transform, and never need to implement rotation or scaling themselves.
+//!
+//! ## Off-screen Surfaces
+//!
+//! Scenes composed of many primitives are best drawn into a [Surface], an
+//! off-screen framebuffer allocated with [EmbDisplayClient::allocate_surface].
+//! Surfaces implement embedded-graphics' `DrawTarget`, so they can be drawn
+//! into without involving the display server at all, and composed onto each
+//! other with [Surface::blit_from]. Once the scene is ready, any region of a
+//! surface is drawn to the display in a single request with
+//! [EmbDisplayClient::blit].
+//!
+//! ## Screenshots
+//!
+//! Servers with the [`capabilities::SCREENSHOT`] capability can copy the
+//! display's current contents into a chunk, using
+//! [EmbDisplayClient::screenshot]. The [screenshot daemon] uses this to send
+//! screenshots to the host.
+//!
+//! [screenshot daemon]: crate::daemons::screenshot
+//!
+//! ## Framebuffer Leases
+//!
+//! Servers with the [`capabilities::LEASE`] capability can lend their
+//! framebuffer out, so that it can be written to directly rather than a
+//! chunk at a time. [EmbDisplayClient::lease_framebuffer] returns a
+//! [FramebufferLease], with the framebuffer's address and layout, and the
+//! holder reports the areas it has written to with
+//! [EmbDisplayClient::submit_damage], so that the server can show them.
+//! Leases are made for userspace processes, through the display syscalls
+//! handled by [EmbDisplayClient::handle_syscall].
+//!
+//! Only one lease is held at a time. It ends when its holder releases it, or
+//! when the kernel revokes it with [EmbDisplayClient::revoke_lease], after
+//! which damage submitted with the lease is refused. As MnemOS does not (yet)
+//! have memory protection, revoking a lease cannot stop a misbehaving holder
+//! from writing to the framebuffer, but its writes are no longer shown.
+//!
+//! ## Fonts
+//!
+//! Text is drawn using one of the built-in fonts in the [fonts] module, which
+//! also reports each font's metrics, so that text consoles can lay themselves
+//! out for whichever font is selected.
+use abi::syscall::display::{
+    DisplayError, DisplayRequest, DisplayResponse, FramebufferLease as SyscallLease,
+};
 use embedded_graphics::{
     pixelcolor::{BinaryColor, Gray8},
     prelude::*,
+    primitives::Rectangle,
 };
+use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+pub mod fonts;
+
 use crate::{
     comms::oneshot::Reusable,
     mnemos_alloc::containers::HeapArray,
-    registry::{self, Envelope, KernelHandle, RegisteredDriver},
+    registry::{self, Capabilities, Envelope, KernelHandle, RegisteredDriver},
     Kernel,
 };
 
@@ -42,20 +102,71 @@ impl RegisteredDriver for EmbDisplayService {
     const UUID: Uuid = registry::known_uuids::kernel::EMB_DISPLAY_V2;
 }
 
+/// Optional [capabilities](registry::capabilities) advertised by
+/// [`EmbDisplayService`] servers.
+pub mod capabilities {
+    use crate::registry::Capabilities;
+
+    /// The server reports the [`DisplayTransform`](super::DisplayTransform)
+    /// configured for its display. Servers without this capability always
+    /// report [`DisplayTransform::IDENTITY`](super::DisplayTransform::IDENTITY).
+    pub const TRANSFORM: Capabilities = Capabilities::bit(0);
+
+    /// The server can copy the display's current contents into a chunk, in
+    /// response to a [`Request::Screenshot`](super::Request::Screenshot).
+    pub const SCREENSHOT: Capabilities = Capabilities::bit(1);
+
+    /// The server can lease its framebuffer, in response to a
+    /// [`Request::Lease`](super::Request::Lease). See [the module-level
+    /// docs](super#framebuffer-leases) for details.
+    pub const LEASE: Capabilities = Capabilities::bit(2);
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 // Message and Error Types
 ////////////////////////////////////////////////////////////////////////////////
 
 /// These are all of the possible requests from client to server
+#[derive(Debug)]
 pub enum Request {
     GetMeta,
     Draw(FrameChunk),
+    /// Copy the display's current contents into the chunk, which is
+    /// positioned in the display's physical coordinates.
+    ///
+    /// Only servers with the [`capabilities::SCREENSHOT`] capability handle
+    /// this request.
+    Screenshot(FrameChunk),
+    /// Lease the display's framebuffer.
+    ///
+    /// This, and the rest of the lease requests, are only handled by servers
+    /// with the [`capabilities::LEASE`] capability.
+    Lease,
+    /// Show the pixels written to `area` of the leased framebuffer, which is
+    /// given in the display's physical coordinates.
+    Damage {
+        lease: LeaseId,
+        area: FrameLocSize,
+    },
+    /// End a lease, on behalf of its holder.
+    Release(LeaseId),
+    /// End the current lease, if there is one, regardless of its holder.
+    Revoke,
 }
 
 pub enum Response {
     FrameMeta(DisplayMetadata),
     /// Successful draw
     DrawComplete(FrameChunk),
+    /// The chunk from a [`Request::Screenshot`], filled with the display's
+    /// contents.
+    Screenshot(FrameChunk),
+    Leased(FramebufferLease),
+    /// The damaged area of the framebuffer is being shown.
+    Damaged,
+    Released,
+    /// The lease that was revoked, if one was held.
+    Revoked(Option<LeaseId>),
 }
 
 #[derive(Debug, Eq, PartialEq)]
@@ -64,6 +175,14 @@ pub enum FrameError {
     Busy,
     /// Internal Error
     InternalError,
+    /// The display server does not support this request.
+    Unsupported,
+    /// The framebuffer is already leased.
+    Leased,
+    /// The lease has ended, because it was released or revoked.
+    NoLease,
+    /// The damaged area is not within the framebuffer.
+    OutOfBounds,
 }
 
 ////////////////////////////////////////////////////////////////////////////////
@@ -71,9 +190,20 @@ pub enum FrameError {
 ////////////////////////////////////////////////////////////////////////////////
 
 /// Client interface to [`EmbDisplayService`].
+///
+/// If the display server reports a [DisplayTransform], the client applies it
+/// to every chunk it draws; see the [module-level docs](self) for details.
 pub struct EmbDisplayClient {
     prod: KernelHandle<EmbDisplayService>,
     reply: Reusable<Envelope<Result<Response, FrameError>>>,
+    /// The display's physical metadata, fetched on first use.
+    meta: Option<DisplayMetadata>,
+    /// A chunk in the display's physical coordinates, reused across
+    /// transformed draws.
+    scratch: Option<MonoChunk>,
+    /// A chunk which regions of [Surface]s are copied into, reused across
+    /// blits.
+    staging: Option<MonoChunk>,
 }
 
 impl EmbDisplayClient {
@@ -89,6 +219,9 @@ impl EmbDisplayClient {
         Ok(EmbDisplayClient {
             prod,
             reply: Reusable::new_async().await,
+            meta: None,
+            scratch: None,
+            staging: None,
         })
     }
 
@@ -107,11 +240,268 @@ impl EmbDisplayClient {
         Ok(EmbDisplayClient {
             prod,
             reply: Reusable::new_async().await,
+            meta: None,
+            scratch: None,
+            staging: None,
         })
     }
 
+    /// Returns the [`capabilities`] advertised by the display server.
+    #[must_use]
+    pub fn capabilities(&self) -> Capabilities {
+        self.prod.capabilities()
+    }
+
+    /// Draw a [FrameChunk], positioned in the display's logical coordinates.
+    ///
+    /// If the display has a [DisplayTransform], the chunk is rotated and
+    /// scaled before it is drawn. Parts of the chunk which lie outside of the
+    /// display are not drawn.
     pub async fn draw<C: Into<FrameChunk>>(&mut self, chunk: C) -> Result<FrameChunk, FrameError> {
         let chunk = chunk.into();
+        let meta = self.physical_meta().await?;
+        if meta.transform.is_identity() {
+            return self.draw_physical(chunk).await;
+        }
+
+        let FrameChunk::Mono(chunk) = chunk;
+        let scratch = self.scratch.take();
+        let Some(physical) = meta.transform.apply(&meta, &chunk, scratch).await else {
+            // Nothing to draw, the chunk is entirely outside of the display.
+            return Ok(chunk.into());
+        };
+        let FrameChunk::Mono(physical) = self.draw_physical(physical.into()).await?;
+        self.scratch = Some(physical);
+        Ok(chunk.into())
+    }
+
+    pub async fn draw_mono(&mut self, chunk: MonoChunk) -> Result<MonoChunk, FrameError> {
+        match self.draw(chunk).await {
+            Ok(FrameChunk::Mono(mfc)) => Ok(mfc),
+            _ => Err(FrameError::InternalError),
+        }
+    }
+
+    /// Allocate an off-screen [Surface] of `width` by `height` pixels, in
+    /// the display's pixel format.
+    ///
+    /// Surfaces may be larger than the display. All of a new surface's pixels
+    /// are transparent.
+    pub async fn allocate_surface(
+        &mut self,
+        width: u32,
+        height: u32,
+    ) -> Result<Surface, FrameError> {
+        let meta = self.physical_meta().await?;
+        match meta.kind {
+            FrameKind::Mono => Ok(Surface::allocate_mono(width, height).await),
+        }
+    }
+
+    /// Draw the region `area` of `surface` to the display, with its top left
+    /// corner at `at`, in the display's logical coordinates.
+    ///
+    /// The region is drawn in a single request. Transparent pixels in the
+    /// surface are not drawn, and parts of the region which lie outside of
+    /// the surface or the display are skipped.
+    pub async fn blit(
+        &mut self,
+        surface: &Surface,
+        area: Rectangle,
+        at: Point,
+    ) -> Result<(), FrameError> {
+        let mut area = area.intersection(&surface.bounding_box());
+        let mut at = at;
+        // chunks can't be placed at negative coordinates, so skip the part of
+        // the region which is above or left of the display.
+        if at.x < 0 {
+            area.top_left.x -= at.x;
+            area.size.width = area.size.width.saturating_sub(at.x.unsigned_abs());
+            at.x = 0;
+        }
+        if at.y < 0 {
+            area.top_left.y -= at.y;
+            area.size.height = area.size.height.saturating_sub(at.y.unsigned_abs());
+            at.y = 0;
+        }
+        if area.is_zero_sized() {
+            return Ok(());
+        }
+
+        let Size { width, height } = area.size;
+        let mut staging = match self.staging.take() {
+            Some(mut staging) if staging.data.bytes.len() == (width * height) as usize => {
+                staging.meta.width = width;
+                staging.meta.height = height;
+                staging.clear();
+                staging
+            }
+            _ => {
+                MonoChunk::allocate_mono(FrameLocSize {
+                    offset_x: 0,
+                    offset_y: 0,
+                    width,
+                    height,
+                })
+                .await
+            }
+        };
+        staging.meta.start_x = at.x as u32;
+        staging.meta.start_y = at.y as u32;
+        blit_mono(&surface.chunk, area, &mut staging, Point::zero());
+
+        self.staging = Some(self.draw_mono(staging).await?);
+        Ok(())
+    }
+
+    /// Capture the display's current contents.
+    ///
+    /// The returned chunk covers the whole display, in its physical
+    /// coordinates: the display's [DisplayTransform] is not applied, so the
+    /// screenshot shows the panel as it is mounted. Returns
+    /// [`FrameError::Unsupported`] if the display server does not have the
+    /// [`capabilities::SCREENSHOT`] capability.
+    pub async fn screenshot(&mut self) -> Result<MonoChunk, FrameError> {
+        if !self.capabilities().contains(capabilities::SCREENSHOT) {
+            return Err(FrameError::Unsupported);
+        }
+        let meta = self.physical_meta().await?;
+        let chunk = MonoChunk::allocate_mono(FrameLocSize {
+            offset_x: 0,
+            offset_y: 0,
+            width: meta.width,
+            height: meta.height,
+        })
+        .await;
+        let resp = self
+            .prod
+            .request_oneshot(Request::Screenshot(chunk.into()), &self.reply)
+            .await
+            .map_err(|_| FrameError::InternalError)?
+            .body?;
+        match resp {
+            Response::Screenshot(FrameChunk::Mono(chunk)) => Ok(chunk),
+            _ => Err(FrameError::InternalError),
+        }
+    }
+
+    /// Get the display's metadata.
+    ///
+    /// The returned width and height are in the display's logical
+    /// coordinates, after its [DisplayTransform] has been applied.
+    pub async fn get_meta(&mut self) -> Result<DisplayMetadata, FrameError> {
+        self.meta = None;
+        let meta = self.physical_meta().await?;
+        let size = meta.transform.logical_size(meta.width, meta.height);
+        Ok(DisplayMetadata {
+            width: size.width,
+            height: size.height,
+            ..meta
+        })
+    }
+
+    /// Lease the display's framebuffer, so that it can be written to
+    /// directly.
+    ///
+    /// Returns [`FrameError::Leased`] if the framebuffer is already leased.
+    /// See [the module-level docs](self#framebuffer-leases) for details.
+    pub async fn lease_framebuffer(&mut self) -> Result<FramebufferLease, FrameError> {
+        match self.lease_request(Request::Lease).await? {
+            Response::Leased(lease) => Ok(lease),
+            _ => Err(FrameError::InternalError),
+        }
+    }
+
+    /// Show the pixels written to `area` of the framebuffer leased as
+    /// `lease`. The area is given in the display's physical coordinates.
+    pub async fn submit_damage(
+        &mut self,
+        lease: LeaseId,
+        area: FrameLocSize,
+    ) -> Result<(), FrameError> {
+        match self.lease_request(Request::Damage { lease, area }).await? {
+            Response::Damaged => Ok(()),
+            _ => Err(FrameError::InternalError),
+        }
+    }
+
+    /// End the lease `lease`. The framebuffer must not be written to once
+    /// this returns.
+    pub async fn release_framebuffer(&mut self, lease: LeaseId) -> Result<(), FrameError> {
+        match self.lease_request(Request::Release(lease)).await? {
+            Response::Released => Ok(()),
+            _ => Err(FrameError::InternalError),
+        }
+    }
+
+    /// End the current lease, if there is one, returning its ID.
+    ///
+    /// Damage submitted with a revoked lease is refused with
+    /// [`FrameError::NoLease`].
+    pub async fn revoke_lease(&mut self) -> Result<Option<LeaseId>, FrameError> {
+        match self.lease_request(Request::Revoke).await? {
+            Response::Revoked(lease) => Ok(lease),
+            _ => Err(FrameError::InternalError),
+        }
+    }
+
+    /// Handle a display syscall made by a userspace process.
+    ///
+    /// Whatever serves a process's syscalls should also
+    /// [revoke](Self::revoke_lease) any lease the process holds when it
+    /// exits.
+    pub async fn handle_syscall(
+        &mut self,
+        req: DisplayRequest,
+    ) -> Result<DisplayResponse, DisplayError> {
+        let resp = match req {
+            DisplayRequest::LeaseFramebuffer => {
+                let lease = self.lease_framebuffer().await?;
+                DisplayResponse::Leased(SyscallLease {
+                    lease: lease.id.0,
+                    addr: lease.addr,
+                    len: lease.len,
+                    width: lease.width,
+                    height: lease.height,
+                    stride: lease.stride,
+                })
+            }
+            DisplayRequest::SubmitDamage {
+                lease,
+                x,
+                y,
+                width,
+                height,
+            } => {
+                let area = FrameLocSize {
+                    offset_x: x,
+                    offset_y: y,
+                    width,
+                    height,
+                };
+                self.submit_damage(LeaseId(lease), area).await?;
+                DisplayResponse::DamageSubmitted { lease }
+            }
+            DisplayRequest::ReleaseFramebuffer { lease } => {
+                self.release_framebuffer(LeaseId(lease)).await?;
+                DisplayResponse::Released { lease }
+            }
+        };
+        Ok(resp)
+    }
+
+    async fn lease_request(&mut self, req: Request) -> Result<Response, FrameError> {
+        if !self.capabilities().contains(capabilities::LEASE) {
+            return Err(FrameError::Unsupported);
+        }
+        self.prod
+            .request_oneshot(req, &self.reply)
+            .await
+            .map_err(|_| FrameError::InternalError)?
+            .body
+    }
+
+    async fn draw_physical(&mut self, chunk: FrameChunk) -> Result<FrameChunk, FrameError> {
         let resp = self
             .prod
             .request_oneshot(Request::Draw(chunk), &self.reply)
@@ -124,14 +514,11 @@ impl EmbDisplayClient {
         })
     }
 
-    pub async fn draw_mono(&mut self, chunk: MonoChunk) -> Result<MonoChunk, FrameError> {
-        match self.draw(chunk).await {
-            Ok(FrameChunk::Mono(mfc)) => Ok(mfc),
-            _ => Err(FrameError::InternalError),
+    async fn physical_meta(&mut self) -> Result<DisplayMetadata, FrameError> {
+        if let Some(meta) = self.meta {
+            return Ok(meta);
         }
-    }
 
-    pub async fn get_meta(&mut self) -> Result<DisplayMetadata, FrameError> {
         let resp = self
             .prod
             .request_oneshot(Request::GetMeta, &self.reply)
@@ -139,10 +526,12 @@ impl EmbDisplayClient {
             .map_err(|_| FrameError::InternalError)?
             .body?;
 
-        Ok(match resp {
+        let meta = match resp {
             Response::FrameMeta(m) => m,
-            Response::DrawComplete(_) => return Err(FrameError::InternalError),
-        })
+            _ => return Err(FrameError::InternalError),
+        };
+        self.meta = Some(meta);
+        Ok(meta)
     }
 }
 
@@ -190,6 +579,7 @@ impl EmbDisplayClient {
 ///
 /// [alpha channel]: https://en.wikipedia.org/wiki/Alpha_compositing
 /// [sprites]: https://en.wikipedia.org/wiki/Sprite_(computer_graphics)
+#[derive(Debug)]
 #[non_exhaustive]
 pub enum FrameChunk {
     Mono(MonoChunk),
@@ -314,6 +704,24 @@ impl MonoChunk {
         self.mask.bytes[idx] = 0xFF;
     }
 
+    /// Set every pixel of the chunk to the state returned by `pixel`, given
+    /// the pixel's coordinates on the display, and mark them all as solid.
+    ///
+    /// This is used by display servers to answer a [`Request::Screenshot`].
+    pub fn capture(&mut self, mut pixel: impl FnMut(u32, u32) -> bool) {
+        let FrameChunkMetadata {
+            start_x,
+            start_y,
+            width,
+            height,
+        } = self.meta;
+        for y in 0..height {
+            for x in 0..width {
+                self.draw_pixel(x, y, pixel(start_x + x, start_y + y));
+            }
+        }
+    }
+
     /// Get the data/mask array index of the given X/Y coordinates
     ///
     /// This does NOT account for `offset`, this is only in the coordinate system
@@ -365,7 +773,99 @@ impl DrawTarget for MonoChunk {
     }
 }
 
+/// An off-screen framebuffer.
+///
+/// A surface is drawn into using embedded-graphics, like a [MonoChunk], but
+/// isn't positioned on the display. Instead, regions of it are drawn to the
+/// display using [EmbDisplayClient::blit], or onto other surfaces using
+/// [Surface::blit_from]. Surfaces are allocated with
+/// [EmbDisplayClient::allocate_surface].
+pub struct Surface {
+    chunk: MonoChunk,
+}
+
+impl Surface {
+    async fn allocate_mono(width: u32, height: u32) -> Self {
+        let chunk = MonoChunk::allocate_mono(FrameLocSize {
+            offset_x: 0,
+            offset_y: 0,
+            width,
+            height,
+        })
+        .await;
+        Self { chunk }
+    }
+
+    /// Mark every pixel of the surface as transparent.
+    pub fn clear(&mut self) {
+        self.chunk.clear();
+    }
+
+    /// Copy the region `area` of `src` onto this surface, with its top left
+    /// corner at `at`.
+    ///
+    /// Transparent pixels in `src` are not copied, so this can be used to
+    /// compose sprites onto a background. Parts of the region which lie
+    /// outside of either surface are skipped.
+    pub fn blit_from(&mut self, src: &Surface, area: Rectangle, at: Point) {
+        blit_mono(&src.chunk, area, &mut self.chunk, at);
+    }
+}
+
+impl DrawTarget for Surface {
+    type Color = BinaryColor;
+    type Error = core::convert::Infallible;
+
+    #[inline]
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        self.chunk.draw_iter(pixels)
+    }
+}
+
+impl OriginDimensions for Surface {
+    #[inline]
+    fn size(&self) -> Size {
+        self.chunk.size()
+    }
+}
+
+impl core::fmt::Debug for Surface {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Surface")
+            .field("size", &self.size())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Copies the solid pixels of `src` within `area` (in `src`'s own coordinates,
+/// ignoring its position on the display) to `dst`, with the top left corner of
+/// `area` at `at` in `dst`'s coordinates.
+fn blit_mono(src: &MonoChunk, area: Rectangle, dst: &mut MonoChunk, at: Point) {
+    let area = area.intersection(&src.bounding_box());
+    for point in area.points() {
+        let (sx, sy) = (point.x as u32, point.y as u32);
+        let Some(src_idx) = src.pix_idx(sx, sy) else {
+            continue;
+        };
+        if src.mask.bytes[src_idx] == 0 {
+            continue;
+        }
+        let Ok((dx, dy)): Result<(u32, u32), _> = (point - area.top_left + at).try_into() else {
+            continue;
+        };
+        let Some(dst_idx) = dst.pix_idx(dx, dy) else {
+            continue;
+        };
+        dst.data.bytes[dst_idx] = src.data.bytes[src_idx];
+        dst.mask.bytes[dst_idx] = 0xFF;
+    }
+}
+
 /// This is used for placing the [FrameChunk] in the overall display
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct FrameLocSize {
     /// Offset in pixels from the top left corner (rightward)
     pub offset_x: u32,
@@ -378,13 +878,23 @@ pub struct FrameLocSize {
 }
 
 /// Kinds of [FrameChunk]s
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 #[non_exhaustive]
 pub enum FrameKind {
     /// Monochrome - matches [FrameChunk::Mono]
     Mono,
 }
 
+// Only the chunk's position and size are printed, as its pixels would be
+// rather a lot of output.
+impl core::fmt::Debug for MonoChunk {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("MonoChunk")
+            .field("meta", &self.meta)
+            .finish_non_exhaustive()
+    }
+}
+
 impl OriginDimensions for MonoChunk {
     fn size(&self) -> Size {
         Size::new(self.meta.width, self.meta.height)
@@ -404,12 +914,48 @@ impl OriginDimensions for FrameChunk {
 #[derive(Copy, Clone, Debug)]
 pub struct DisplayMetadata {
     pub kind: FrameKind,
+    /// Width of the display in pixels.
+    ///
+    /// Servers report the physical width of the display, while
+    /// [EmbDisplayClient::get_meta] returns the width after the display's
+    /// [DisplayTransform] is applied.
     pub width: u32,
+    /// Height of the display in pixels.
+    ///
+    /// Servers report the physical height of the display, while
+    /// [EmbDisplayClient::get_meta] returns the height after the display's
+    /// [DisplayTransform] is applied.
     pub height: u32,
+    /// How the display's contents should be rotated and scaled.
+    pub transform: DisplayTransform,
+}
+
+/// Rotation and scaling applied to everything drawn on a display.
+///
+/// See the [module-level docs](self) for details.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DisplayTransform {
+    /// Clockwise rotation of the display's contents.
+    #[serde(default)]
+    pub rotation: Rotation,
+    /// Integer scaling factor: each logical pixel is drawn as a square of
+    /// `scale` by `scale` physical pixels. Zero is treated as one.
+    #[serde(default = "DisplayTransform::default_scale")]
+    pub scale: u32,
+}
+
+/// Clockwise rotation of a display's contents.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Rotation {
+    #[default]
+    R0,
+    R90,
+    R180,
+    R270,
 }
 
 /// Metadata of the [FrameChunk]
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug)]
 pub struct FrameChunkMetadata {
     start_x: u32,
     start_y: u32,
@@ -443,6 +989,443 @@ impl FrameChunkMetadata {
     }
 }
 
+// === impl DisplayTransform ===
+
+impl DisplayTransform {
+    /// A transform which leaves the display's contents unchanged.
+    pub const IDENTITY: Self = Self {
+        rotation: Rotation::R0,
+        scale: 1,
+    };
+
+    const fn default_scale() -> u32 {
+        1
+    }
+
+    /// Returns `true` if this transform leaves the display's contents
+    /// unchanged.
+    pub fn is_identity(&self) -> bool {
+        self.rotation == Rotation::R0 && self.scale() == 1
+    }
+
+    /// Returns the logical size of a display with the given physical size.
+    ///
+    /// If the physical size isn't a multiple of the scaling factor, the
+    /// remaining rows and columns of physical pixels are left unused.
+    pub fn logical_size(&self, width: u32, height: u32) -> Size {
+        let (width, height) = (width / self.scale(), height / self.scale());
+        match self.rotation {
+            Rotation::R0 | Rotation::R180 => Size::new(width, height),
+            Rotation::R90 | Rotation::R270 => Size::new(height, width),
+        }
+    }
+
+    fn scale(&self) -> u32 {
+        self.scale.max(1)
+    }
+
+    /// Rotates a point in a display of `logical` size, returning its position
+    /// in physical (but not yet scaled) coordinates.
+    fn rotate(&self, logical: Size, x: u32, y: u32) -> (u32, u32) {
+        match self.rotation {
+            Rotation::R0 => (x, y),
+            Rotation::R90 => (logical.height - 1 - y, x),
+            Rotation::R180 => (logical.width - 1 - x, logical.height - 1 - y),
+            Rotation::R270 => (y, logical.width - 1 - x),
+        }
+    }
+
+    /// Renders the visible portion of `src`, which is positioned in logical
+    /// coordinates, into a chunk in the physical coordinates of the display
+    /// described by `meta`.
+    ///
+    /// `scratch` is reused if it has the right number of pixels, otherwise a
+    /// new chunk is allocated. Returns `None` if no part of `src` is visible.
+    async fn apply(
+        &self,
+        meta: &DisplayMetadata,
+        src: &MonoChunk,
+        scratch: Option<MonoChunk>,
+    ) -> Option<MonoChunk> {
+        let logical = self.logical_size(meta.width, meta.height);
+        let scale = self.scale();
+        let src_meta = *src.meta();
+
+        // Clip the chunk to the display.
+        let (x0, y0) = (src_meta.start_x, src_meta.start_y);
+        let x1 = x0.saturating_add(src_meta.width).min(logical.width);
+        let y1 = y0.saturating_add(src_meta.height).min(logical.height);
+        if x0 >= x1 || y0 >= y1 {
+            return None;
+        }
+
+        // Opposite corners of the clipped chunk are also opposite corners of
+        // the rotated chunk.
+        let (ax, ay) = self.rotate(logical, x0, y0);
+        let (bx, by) = self.rotate(logical, x1 - 1, y1 - 1);
+        let (min_x, min_y) = (ax.min(bx), ay.min(by));
+        let width = (ax.abs_diff(bx) + 1) * scale;
+        let height = (ay.abs_diff(by) + 1) * scale;
+
+        let mut dst = match scratch {
+            Some(mut dst) if dst.data.bytes.len() == (width * height) as usize => {
+                dst.meta.width = width;
+                dst.meta.height = height;
+                dst
+            }
+            _ => {
+                MonoChunk::allocate_mono(FrameLocSize {
+                    offset_x: 0,
+                    offset_y: 0,
+                    width,
+                    height,
+                })
+                .await
+            }
+        };
+        dst.meta.start_x = min_x * scale;
+        dst.meta.start_y = min_y * scale;
+
+        for y in y0..y1 {
+            for x in x0..x1 {
+                let src_idx = ((y - y0) * src_meta.width + (x - x0)) as usize;
+                let (data, mask) = (src.data.bytes[src_idx], src.mask.bytes[src_idx]);
+                let (px, py) = self.rotate(logical, x, y);
+                let (dx, dy) = ((px - min_x) * scale, (py - min_y) * scale);
+                for row in dy..dy + scale {
+                    let start = (row * width + dx) as usize;
+                    let end = start + scale as usize;
+                    dst.data.bytes[start..end].fill(data);
+                    dst.mask.bytes[start..end].fill(mask);
+                }
+            }
+        }
+
+        Some(dst)
+    }
+}
+
+/// Identifies a [FramebufferLease].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LeaseId(u32);
+
+/// A lease on a display server's framebuffer, from
+/// [EmbDisplayClient::lease_framebuffer].
+///
+/// The framebuffer holds `height` rows of `width` pixels, in the display's
+/// physical coordinates, with rows starting every `stride` bytes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FramebufferLease {
+    pub id: LeaseId,
+    /// The address of the framebuffer's first byte. MnemOS does not (yet)
+    /// use virtual memory, so this is also its physical address.
+    pub addr: usize,
+    /// The length of the framebuffer, in bytes.
+    pub len: usize,
+    pub width: u32,
+    pub height: u32,
+    pub stride: u32,
+    /// The framebuffer's pixel format. For [FrameKind::Mono], each pixel is
+    /// one byte, and is lit if the byte is non-zero.
+    pub kind: FrameKind,
+}
+
+/// Lease bookkeeping for display servers with the [`capabilities::LEASE`]
+/// capability.
+///
+/// This tracks which lease, if any, is currently held, and checks the lease
+/// requests made against it, so that servers only need to show the damaged
+/// areas of their framebuffer.
+#[derive(Debug, Default)]
+pub struct Leases {
+    current: Option<LeaseId>,
+    next_id: u32,
+}
+
+impl Leases {
+    pub const fn new() -> Self {
+        Self {
+            current: None,
+            next_id: 0,
+        }
+    }
+
+    /// Lease `framebuf`, a [FrameKind::Mono] framebuffer of `width` by
+    /// `height` pixels, one byte per pixel.
+    ///
+    /// The framebuffer must not move or be freed while the lease is held.
+    pub fn lease(
+        &mut self,
+        framebuf: &mut [u8],
+        width: u32,
+        height: u32,
+    ) -> Result<FramebufferLease, FrameError> {
+        if self.current.is_some() {
+            return Err(FrameError::Leased);
+        }
+        if framebuf.len() < width as usize * height as usize {
+            return Err(FrameError::InternalError);
+        }
+        let id = LeaseId(self.next_id);
+        self.next_id = self.next_id.wrapping_add(1);
+        self.current = Some(id);
+        Ok(FramebufferLease {
+            id,
+            addr: framebuf.as_mut_ptr() as usize,
+            len: framebuf.len(),
+            width,
+            height,
+            stride: width,
+            kind: FrameKind::Mono,
+        })
+    }
+
+    /// Checks that `lease` is held, and that `area` is within a `width` by
+    /// `height` framebuffer.
+    pub fn damage(
+        &self,
+        lease: LeaseId,
+        area: &FrameLocSize,
+        width: u32,
+        height: u32,
+    ) -> Result<(), FrameError> {
+        self.check(lease)?;
+        let fits = |offset: u32, len: u32, max: u32| {
+            offset.checked_add(len).is_some_and(|end| end <= max)
+        };
+        if !fits(area.offset_x, area.width, width) || !fits(area.offset_y, area.height, height) {
+            return Err(FrameError::OutOfBounds);
+        }
+        Ok(())
+    }
+
+    /// Ends `lease`, if it is held.
+    pub fn release(&mut self, lease: LeaseId) -> Result<(), FrameError> {
+        self.check(lease)?;
+        self.current = None;
+        Ok(())
+    }
+
+    /// Ends the current lease, if there is one, returning its ID.
+    pub fn revoke(&mut self) -> Option<LeaseId> {
+        self.current.take()
+    }
+
+    fn check(&self, lease: LeaseId) -> Result<(), FrameError> {
+        if self.current != Some(lease) {
+            return Err(FrameError::NoLease);
+        }
+        Ok(())
+    }
+}
+
+impl From<FrameError> for DisplayError {
+    fn from(error: FrameError) -> Self {
+        match error {
+            FrameError::Unsupported => DisplayError::Unsupported,
+            FrameError::Leased => DisplayError::Leased,
+            FrameError::NoLease => DisplayError::Revoked,
+            FrameError::OutOfBounds => DisplayError::OutOfBounds,
+            FrameError::Busy | FrameError::InternalError => DisplayError::Unknown,
+        }
+    }
+}
+
+impl Default for DisplayTransform {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
 struct Buf8 {
     bytes: HeapArray<u8>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TestKernel;
+
+    async fn chunk(x: u32, y: u32, width: u32, height: u32, on: &[(u32, u32)]) -> MonoChunk {
+        let mut chunk = MonoChunk::allocate_mono(FrameLocSize {
+            offset_x: x,
+            offset_y: y,
+            width,
+            height,
+        })
+        .await;
+        for cy in 0..height {
+            for cx in 0..width {
+                chunk.draw_pixel(cx, cy, on.contains(&(cx, cy)));
+            }
+        }
+        chunk
+    }
+
+    fn meta(width: u32, height: u32, rotation: Rotation, scale: u32) -> DisplayMetadata {
+        DisplayMetadata {
+            kind: FrameKind::Mono,
+            width,
+            height,
+            transform: DisplayTransform { rotation, scale },
+        }
+    }
+
+    fn lit(chunk: &MonoChunk) -> Vec<(u32, u32)> {
+        let width = chunk.meta().width();
+        chunk
+            .data()
+            .iter()
+            .enumerate()
+            .filter(|(_, &px)| px != 0)
+            .map(|(i, _)| (i as u32 % width, i as u32 / width))
+            .collect()
+    }
+
+    #[test]
+    fn rotate_90() {
+        TestKernel::run(|_| async {
+            // A 4x2 panel mounted sideways is a 2x4 display.
+            let meta = meta(4, 2, Rotation::R90, 1);
+            let size = meta.transform.logical_size(meta.width, meta.height);
+            assert_eq!(size, Size::new(2, 4));
+
+            // The top row of the display is the right column of the panel.
+            let src = chunk(0, 0, 2, 1, &[(0, 0)]).await;
+            let dst = meta.transform.apply(&meta, &src, None).await.unwrap();
+            assert_eq!((dst.meta().start_x(), dst.meta().start_y()), (3, 0));
+            assert_eq!((dst.meta().width(), dst.meta().height()), (1, 2));
+            assert_eq!(lit(&dst), [(0, 0)]);
+            assert!(dst.mask().iter().all(|&m| m == 0xFF));
+        })
+    }
+
+    #[test]
+    fn rotate_180_and_scale() {
+        TestKernel::run(|_| async {
+            let meta = meta(8, 4, Rotation::R180, 2);
+
+            let src = chunk(0, 0, 2, 1, &[(0, 0)]).await;
+            let dst = meta.transform.apply(&meta, &src, None).await.unwrap();
+            assert_eq!((dst.meta().start_x(), dst.meta().start_y()), (4, 2));
+            assert_eq!((dst.meta().width(), dst.meta().height()), (4, 2));
+            assert_eq!(lit(&dst), [(2, 0), (3, 0), (2, 1), (3, 1)]);
+
+            // The scratch chunk is reused when it's the right size.
+            let src = chunk(1, 1, 2, 1, &[(1, 0)]).await;
+            let dst = meta.transform.apply(&meta, &src, Some(dst)).await.unwrap();
+            assert_eq!((dst.meta().start_x(), dst.meta().start_y()), (2, 0));
+            assert_eq!(lit(&dst), [(0, 0), (1, 0), (0, 1), (1, 1)]);
+        })
+    }
+
+    #[test]
+    fn blit_composes_solid_pixels() {
+        TestKernel::run(|_| async {
+            let mut background = Surface::allocate_mono(4, 4).await;
+            background
+                .chunk
+                .draw_iter((0..4).map(|x| Pixel(Point::new(x, 1), BinaryColor::On)))
+                .unwrap();
+
+            // Only the sprite's solid pixel is copied; its transparent pixel
+            // leaves the background as it was.
+            let mut sprite = Surface::allocate_mono(2, 1).await;
+            sprite.chunk.draw_pixel(0, 0, false);
+            background.blit_from(
+                &sprite,
+                Rectangle::new(Point::zero(), Size::new(2, 1)),
+                Point::new(1, 1),
+            );
+            assert_eq!(lit(&background.chunk), [(0, 1), (2, 1), (3, 1)]);
+        })
+    }
+
+    #[test]
+    fn blit_clips() {
+        TestKernel::run(|_| async {
+            let src = chunk(0, 0, 3, 3, &[(0, 0), (1, 1), (2, 2)]).await;
+            let mut dst = chunk(0, 0, 2, 2, &[]).await;
+            dst.clear();
+
+            // The region extends past the source, and is placed partly
+            // outside of the destination.
+            blit_mono(
+                &src,
+                Rectangle::new(Point::new(1, 1), Size::new(4, 4)),
+                &mut dst,
+                Point::new(-1, 0),
+            );
+            assert_eq!(lit(&dst), [(0, 1)]);
+            assert_eq!(dst.mask(), [0xFF, 0x00, 0xFF, 0x00]);
+        })
+    }
+
+    #[test]
+    fn capture() {
+        TestKernel::run(|_| async {
+            let mut chunk = chunk(1, 2, 2, 2, &[]).await;
+            chunk.clear();
+            // the pixel is given in display coordinates.
+            chunk.capture(|x, y| (x, y) == (2, 3));
+            assert_eq!(lit(&chunk), [(1, 1)]);
+            assert!(chunk.mask().iter().all(|&m| m == 0xFF));
+        })
+    }
+
+    #[test]
+    fn clips_to_display() {
+        TestKernel::run(|_| async {
+            let meta = meta(4, 2, Rotation::R270, 1);
+
+            // Only the first row of this chunk is on the display.
+            let src = chunk(0, 3, 2, 2, &[(1, 0), (1, 1)]).await;
+            let dst = meta.transform.apply(&meta, &src, None).await.unwrap();
+            assert_eq!((dst.meta().start_x(), dst.meta().start_y()), (3, 0));
+            assert_eq!((dst.meta().width(), dst.meta().height()), (1, 2));
+            assert_eq!(lit(&dst), [(0, 0)]);
+
+            let src = chunk(2, 0, 2, 2, &[]).await;
+            assert!(meta.transform.apply(&meta, &src, None).await.is_none());
+        })
+    }
+
+    #[test]
+    fn leases() {
+        let area = |offset_x, offset_y, width, height| FrameLocSize {
+            offset_x,
+            offset_y,
+            width,
+            height,
+        };
+        let mut framebuf = [0u8; 8];
+        let mut leases = Leases::new();
+
+        let lease = leases.lease(&mut framebuf, 4, 2).unwrap();
+        assert_eq!(lease.addr, framebuf.as_ptr() as usize);
+        assert_eq!((lease.len, lease.stride), (8, 4));
+        assert_eq!(leases.lease(&mut framebuf, 4, 2), Err(FrameError::Leased));
+
+        assert_eq!(leases.damage(lease.id, &area(0, 0, 4, 2), 4, 2), Ok(()));
+        assert_eq!(
+            leases.damage(lease.id, &area(3, 1, 2, 1), 4, 2),
+            Err(FrameError::OutOfBounds)
+        );
+        assert_eq!(
+            leases.damage(lease.id, &area(u32::MAX, 0, 2, 1), 4, 2),
+            Err(FrameError::OutOfBounds)
+        );
+
+        // Once revoked, the lease can't be used, but a new one can be made.
+        assert_eq!(leases.revoke(), Some(lease.id));
+        assert_eq!(
+            leases.damage(lease.id, &area(0, 0, 1, 1), 4, 2),
+            Err(FrameError::NoLease)
+        );
+        let next = leases.lease(&mut framebuf, 4, 2).unwrap();
+        assert_ne!(next.id, lease.id);
+        assert_eq!(leases.release(lease.id), Err(FrameError::NoLease));
+        assert_eq!(leases.release(next.id), Ok(()));
+        assert_eq!(leases.revoke(), None);
+    }
+}
@@ -0,0 +1,141 @@
+//! Built-in fonts for text drawn to an [`EmbDisplayService`](super::EmbDisplayService).
+//!
+//! Text consoles, such as the [graphical shell], select one of the built-in
+//! [`FontSize`]s, rather than a specific [`MonoFont`]. This lets the font be
+//! chosen in configuration files and changed at runtime. When the font
+//! changes, a console can use the new font's [`FontMetrics`] to work out how
+//! many rows and columns of text now fit on the display.
+//!
+//! [graphical shell]: crate::daemons::shells::graphical_shell_mono
+use embedded_graphics::mono_font::MonoFont;
+use profont::{PROFONT_12_POINT, PROFONT_18_POINT, PROFONT_9_POINT};
+use serde::{Deserialize, Serialize};
+
+/// One of the built-in font sizes.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FontSize {
+    /// ProFont, 9 point.
+    Small,
+    /// ProFont, 12 point.
+    #[default]
+    Medium,
+    /// ProFont, 18 point.
+    Large,
+}
+
+/// The dimensions of a monospaced font, in pixels.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct FontMetrics {
+    /// Horizontal distance from the start of one character to the start of
+    /// the next, including any spacing between characters.
+    pub char_width: u32,
+    /// Height of a line of text.
+    pub char_height: u32,
+    /// Offset from the top of a line to the text baseline.
+    pub baseline: u32,
+    /// Offset from the top of a line to the underline.
+    pub underline_offset: u32,
+}
+
+/// The number of whole characters that fit in an area.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct TextGrid {
+    pub cols: u32,
+    pub rows: u32,
+}
+
+// === impl FontSize ===
+
+impl FontSize {
+    /// Every built-in font size, from smallest to largest.
+    pub const ALL: [Self; 3] = [Self::Small, Self::Medium, Self::Large];
+
+    /// Returns the font for this size.
+    #[must_use]
+    pub fn font(self) -> &'static MonoFont<'static> {
+        match self {
+            Self::Small => &PROFONT_9_POINT,
+            Self::Medium => &PROFONT_12_POINT,
+            Self::Large => &PROFONT_18_POINT,
+        }
+    }
+
+    /// Returns the metrics of the font for this size.
+    #[must_use]
+    pub fn metrics(self) -> FontMetrics {
+        FontMetrics::of(self.font())
+    }
+
+    /// Returns the next larger size, or `self` if this is the largest.
+    #[must_use]
+    pub fn larger(self) -> Self {
+        match self {
+            Self::Small => Self::Medium,
+            Self::Medium | Self::Large => Self::Large,
+        }
+    }
+
+    /// Returns the next smaller size, or `self` if this is the smallest.
+    #[must_use]
+    pub fn smaller(self) -> Self {
+        match self {
+            Self::Small | Self::Medium => Self::Small,
+            Self::Large => Self::Medium,
+        }
+    }
+}
+
+// === impl FontMetrics ===
+
+impl FontMetrics {
+    /// Returns the metrics of `font`.
+    #[must_use]
+    pub fn of(font: &MonoFont<'_>) -> Self {
+        Self {
+            char_width: font.character_size.width + font.character_spacing,
+            char_height: font.character_size.height,
+            baseline: font.baseline,
+            underline_offset: font.underline.offset,
+        }
+    }
+
+    /// Returns how many characters of this font fit in an area of
+    /// `width_px` by `height_px` pixels.
+    #[must_use]
+    pub fn grid(&self, width_px: u32, height_px: u32) -> TextGrid {
+        TextGrid {
+            cols: width_px / self.char_width.max(1),
+            rows: height_px / self.char_height.max(1),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sizes_are_ordered() {
+        for pair in FontSize::ALL.windows(2) {
+            let (smaller, larger) = (pair[0].metrics(), pair[1].metrics());
+            assert!(smaller.char_width < larger.char_width);
+            assert!(smaller.char_height < larger.char_height);
+            assert_eq!(pair[0].larger(), pair[1]);
+            assert_eq!(pair[1].smaller(), pair[0]);
+        }
+    }
+
+    #[test]
+    fn grid() {
+        for size in FontSize::ALL {
+            let metrics = size.metrics();
+            let (width, height) = (metrics.char_width * 50, metrics.char_height * 16);
+            assert_eq!(metrics.grid(width, height), TextGrid { cols: 50, rows: 16 });
+            assert_eq!(
+                metrics.grid(width - 1, height - 1),
+                TextGrid { cols: 49, rows: 15 }
+            );
+        }
+    }
+}
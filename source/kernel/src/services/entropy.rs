@@ -0,0 +1,287 @@
+//! # Entropy Service
+//!
+//! A source of cryptographically secure random bytes, for generating keys and
+//! nonces.
+//!
+//! The bytes must come from a hardware random number generator (or a CSPRNG
+//! seeded from one), which is specific to the target platform. The platform
+//! implements [`EntropySource`] for its generator, and registers an
+//! [`EntropyServer`] for it. On platforms without a suitable source of
+//! entropy, no server is registered, and clients should refuse to do anything
+//! that requires one, rather than falling back to a predictable source.
+
+use serde::{Deserialize, Serialize};
+use tracing::{Instrument, Level};
+use uuid::Uuid;
+
+use crate::{
+    comms::oneshot::Reusable,
+    mnemos_alloc::containers::FixedVec,
+    registry::{self, known_uuids, Envelope, KernelHandle, Message, RegisteredDriver},
+    Kernel,
+};
+
+////////////////////////////////////////////////////////////////////////////////
+// Service Definition
+////////////////////////////////////////////////////////////////////////////////
+
+/// Service definition for the entropy service.
+pub struct EntropyService;
+
+impl RegisteredDriver for EntropyService {
+    type Request = Request;
+    type Response = Response;
+    type Error = EntropyError;
+    type Hello = ();
+    type ConnectError = core::convert::Infallible;
+
+    const UUID: Uuid = known_uuids::kernel::ENTROPY;
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Message and Error Types
+////////////////////////////////////////////////////////////////////////////////
+
+pub enum Request {
+    /// Fill the buffer with random bytes, up to its capacity.
+    Fill(FixedVec<u8>),
+}
+
+pub enum Response {
+    /// The buffer, full of random bytes.
+    Filled(FixedVec<u8>),
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum EntropyError {
+    /// The random number generator failed, such as a hardware health check
+    /// failing.
+    Unavailable,
+    /// An error occurred sending the request to the service.
+    Request(registry::OneshotRequestError),
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Client Definition
+////////////////////////////////////////////////////////////////////////////////
+
+/// A client for the [`EntropyService`].
+pub struct EntropyClient {
+    handle: KernelHandle<EntropyService>,
+    reply: Reusable<Envelope<Result<Response, EntropyError>>>,
+}
+
+impl EntropyClient {
+    /// Obtain an `EntropyClient`
+    ///
+    /// If the [`EntropyService`] hasn't been registered yet, we will retry
+    /// until it has been registered.
+    pub async fn from_registry(
+        kernel: &'static Kernel,
+    ) -> Result<Self, registry::ConnectError<EntropyService>> {
+        let handle = kernel.registry().connect::<EntropyService>(()).await?;
+
+        Ok(Self {
+            handle,
+            reply: Reusable::new_async().await,
+        })
+    }
+
+    /// Obtain an `EntropyClient`
+    ///
+    /// Does NOT attempt to get an [`EntropyService`] handle more than once.
+    pub async fn from_registry_no_retry(
+        kernel: &'static Kernel,
+    ) -> Result<Self, registry::ConnectError<EntropyService>> {
+        let handle = kernel.registry().try_connect::<EntropyService>(()).await?;
+
+        Ok(Self {
+            handle,
+            reply: Reusable::new_async().await,
+        })
+    }
+
+    /// Fill `buf` with random bytes, up to its capacity.
+    pub async fn fill(&mut self, buf: FixedVec<u8>) -> Result<FixedVec<u8>, EntropyError> {
+        let Response::Filled(buf) = self
+            .handle
+            .request_oneshot(Request::Fill(buf), &self.reply)
+            .await
+            .map_err(EntropyError::Request)?
+            .body?;
+        Ok(buf)
+    }
+
+    /// Returns `N` random bytes.
+    pub async fn random_bytes<const N: usize>(&mut self) -> Result<[u8; N], EntropyError> {
+        let buf = self.fill(FixedVec::new(N).await).await?;
+        let mut bytes = [0; N];
+        if buf.len() != N {
+            // a server that returns fewer bytes than asked for is broken, and
+            // zero-padding them would be a disaster.
+            return Err(EntropyError::Unavailable);
+        }
+        bytes.copy_from_slice(buf.as_slice());
+        Ok(bytes)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Server Definition
+////////////////////////////////////////////////////////////////////////////////
+
+/// A platform's source of random bytes, served by an [`EntropyServer`].
+pub trait EntropySource: 'static {
+    /// Fill `buf` with random bytes.
+    ///
+    /// If the generator can't produce enough entropy, this must return an
+    /// error, rather than leaving any of `buf` predictable.
+    fn fill(&mut self, buf: &mut [u8]) -> Result<(), EntropyError>;
+}
+
+/// Serves random bytes from an [`EntropySource`].
+pub struct EntropyServer;
+
+/// Settings for the [`EntropyServer`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EntropySettings {
+    /// Maximum number of outstanding requests from clients.
+    #[serde(default = "EntropySettings::default_capacity")]
+    pub capacity: usize,
+}
+
+impl EntropyServer {
+    /// Register the `EntropyServer`, serving random bytes from `source`.
+    #[tracing::instrument(
+        name = "EntropyServer::register",
+        level = Level::INFO,
+        skip(kernel, settings, source),
+        err(Debug),
+    )]
+    pub async fn register(
+        kernel: &'static Kernel,
+        settings: EntropySettings,
+        mut source: impl EntropySource,
+    ) -> Result<(), registry::RegistrationError> {
+        let reqs = kernel
+            .registry()
+            .bind_konly::<EntropyService>(settings.capacity)
+            .await?
+            .into_request_stream(settings.capacity)
+            .await;
+
+        kernel
+            .spawn(async move {
+                loop {
+                    let Message { msg, reply } = reqs.next_request().await;
+                    let span = msg.span();
+                    async {
+                        let resp = msg.reply_with_body(|Request::Fill(mut buf)| {
+                            fill(&mut source, &mut buf).map(|()| Response::Filled(buf))
+                        });
+                        if let Err(error) = reply.reply_konly(resp).await {
+                            tracing::warn!(?error, "Failed to reply to entropy request");
+                        }
+                    }
+                    .instrument(span)
+                    .await;
+                }
+            })
+            .await;
+
+        Ok(())
+    }
+}
+
+/// Fills `buf` from `source`, up to its capacity.
+fn fill(source: &mut impl EntropySource, buf: &mut FixedVec<u8>) -> Result<(), EntropyError> {
+    buf.clear();
+    let mut chunk = [0u8; 32];
+    while !buf.is_full() {
+        let len = core::cmp::min(chunk.len(), buf.capacity() - buf.as_slice().len());
+        let chunk = &mut chunk[..len];
+        if let Err(error) = source.fill(chunk) {
+            tracing::warn!(?error, "Entropy source failed");
+            buf.clear();
+            return Err(error);
+        }
+        // can't fail, we checked the remaining capacity.
+        let _ = buf.try_extend_from_slice(chunk);
+    }
+    Ok(())
+}
+
+// === impl EntropySettings ===
+
+impl EntropySettings {
+    pub const DEFAULT_CAPACITY: usize = 4;
+
+    const fn default_capacity() -> usize {
+        Self::DEFAULT_CAPACITY
+    }
+}
+
+impl Default for EntropySettings {
+    fn default() -> Self {
+        Self {
+            capacity: Self::DEFAULT_CAPACITY,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TestKernel;
+
+    /// Counts up from zero, which is fine for a test.
+    struct Counter(u8);
+
+    impl EntropySource for Counter {
+        fn fill(&mut self, buf: &mut [u8]) -> Result<(), EntropyError> {
+            for byte in buf {
+                *byte = self.0;
+                self.0 = self.0.wrapping_add(1);
+            }
+            Ok(())
+        }
+    }
+
+    struct Broken;
+
+    impl EntropySource for Broken {
+        fn fill(&mut self, _: &mut [u8]) -> Result<(), EntropyError> {
+            Err(EntropyError::Unavailable)
+        }
+    }
+
+    #[test]
+    fn fills() {
+        TestKernel::run(|k| async move {
+            EntropyServer::register(k, Default::default(), Counter(0))
+                .await
+                .unwrap();
+            let mut client = EntropyClient::from_registry(k).await.unwrap();
+
+            // more than one chunk from the source.
+            let bytes = client.random_bytes::<40>().await.unwrap();
+            assert!(bytes.iter().copied().eq(0..40));
+            let bytes = client.random_bytes::<4>().await.unwrap();
+            assert_eq!(bytes, [40, 41, 42, 43]);
+        })
+    }
+
+    #[test]
+    fn unavailable() {
+        TestKernel::run(|k| async move {
+            EntropyServer::register(k, Default::default(), Broken)
+                .await
+                .unwrap();
+            let mut client = EntropyClient::from_registry(k).await.unwrap();
+            assert_eq!(
+                client.random_bytes::<32>().await,
+                Err(EntropyError::Unavailable)
+            );
+        })
+    }
+}
@@ -0,0 +1,584 @@
+//! # Flash Service
+//!
+//! Access to the regions of a mnemOS SPI flash image which the kernel may
+//! use at runtime: the *config* region, which holds configuration written
+//! alongside the kernel image, and the *key-value* region, which holds data
+//! the kernel stores for itself.
+//!
+//! A bootable flash image starts with a first-stage bootloader and a
+//! [`FlashMap`] describing where each region is, as written by
+//! `cargo mnemos image`; see the [`mnemos_flash_map`] crate for the layout.
+//! The kernel region, and the bootloader and flash map before it, can't be
+//! accessed through this service, so that a misbehaving client can't make
+//! the device unbootable.
+//!
+//! The flash chip itself is driven by a platform driver, which implements
+//! [`FlashDevice`] and registers the [`FlashServer`] with it. The server
+//! reads the flash map when it is registered, and translates each request's
+//! region-relative offsets into offsets on the chip, refusing any which
+//! would fall outside the region.
+//!
+//! Like the flash chip, the regions are NOR flash: a write can only clear
+//! bits, so a range must be [erased](FlashClient::erase) (setting every byte
+//! to `0xFF`) before it can be written again. Erases are done in whole
+//! [`SECTOR_SIZE`] sectors.
+#![warn(missing_docs)]
+use maitake::time::Duration;
+use tracing::{Instrument, Level};
+use uuid::Uuid;
+
+pub use mnemos_flash_map::{self as flash_map, FlashMap, Region, RegionKind, SECTOR_SIZE};
+
+use crate::{
+    comms::oneshot::Reusable,
+    mnemos_alloc::containers::FixedVec,
+    registry::{self, known_uuids, Envelope, KernelHandle, Message, RegisteredDriver},
+    services::notification::NotificationClient,
+    Kernel,
+};
+
+////////////////////////////////////////////////////////////////////////////////
+// Service Definition
+////////////////////////////////////////////////////////////////////////////////
+
+/// [Service](crate::services) definition for the flash service.
+pub struct FlashService;
+
+impl RegisteredDriver for FlashService {
+    type Request = Request;
+    type Response = Response;
+    type Error = FlashError;
+    type Hello = ();
+    type ConnectError = core::convert::Infallible;
+
+    const UUID: Uuid = known_uuids::kernel::FLASH;
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Message and Error Types
+////////////////////////////////////////////////////////////////////////////////
+
+/// Requests to the [`FlashService`].
+///
+/// Offsets are in bytes from the start of the request's region.
+pub enum Request {
+    /// Get the flash map and the size of the flash chip.
+    GetInfo,
+    /// Read `offset..offset + buf.capacity()` of `region` into `buf`.
+    Read {
+        /// The region to read from.
+        region: RegionKind,
+        /// Where to start reading.
+        offset: u32,
+        /// The buffer to read into. Any data already in it is discarded.
+        buf: FixedVec<u8>,
+    },
+    /// Write the contents of `buf` to `region`, starting at `offset`.
+    ///
+    /// The bytes being written should have been erased.
+    Write {
+        /// The region to write to.
+        region: RegionKind,
+        /// Where to start writing.
+        offset: u32,
+        /// The data to write.
+        buf: FixedVec<u8>,
+    },
+    /// Erase `offset..offset + len` of `region`, which must be whole
+    /// [`SECTOR_SIZE`] sectors.
+    Erase {
+        /// The region to erase.
+        region: RegionKind,
+        /// Where to start erasing.
+        offset: u32,
+        /// How many bytes to erase.
+        len: u32,
+    },
+}
+
+/// Responses from the [`FlashService`].
+pub enum Response {
+    /// The flash chip's [`FlashInfo`].
+    Info(FlashInfo),
+    /// The buffer from a [`Request::Read`], holding the bytes that were read.
+    Read(FixedVec<u8>),
+    /// The buffer from a [`Request::Write`], which was written.
+    Written(FixedVec<u8>),
+    /// The range was erased.
+    Erased,
+}
+
+/// Information about the flash chip, returned by [`FlashClient::info`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct FlashInfo {
+    /// The flash map read from the chip.
+    pub map: FlashMap,
+    /// The size of the flash chip, in bytes.
+    pub capacity: u32,
+}
+
+/// Errors returned by the [`FlashService`], and by [`FlashDevice`]s.
+#[derive(Debug, Eq, PartialEq)]
+pub enum FlashError {
+    /// The region can't be accessed through the flash service.
+    Protected(RegionKind),
+    /// The request would access bytes outside of its region.
+    OutOfBounds,
+    /// An erase didn't start and end on a [`SECTOR_SIZE`] boundary.
+    Unaligned,
+    /// The flash chip failed, or didn't respond.
+    Device,
+    /// An error occurred sending the request to the service.
+    Request(registry::OneshotRequestError),
+}
+
+/// Errors returned by [`FlashServer::register`].
+#[derive(Debug)]
+pub enum RegistrationError {
+    /// An error occurred registering the [`FlashService`].
+    Registry(registry::RegistrationError),
+    /// The flash map couldn't be read from the chip.
+    Device(FlashError),
+    /// The flash map on the chip is missing or invalid.
+    NoFlashMap(flash_map::Error),
+    /// The flash map describes regions past the end of the chip.
+    MapTooLarge(FlashMap),
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Client Definition
+////////////////////////////////////////////////////////////////////////////////
+
+/// A client for the [`FlashService`].
+pub struct FlashClient {
+    handle: KernelHandle<FlashService>,
+    reply: Reusable<Envelope<Result<Response, FlashError>>>,
+}
+
+impl FlashClient {
+    /// Obtain a `FlashClient`
+    ///
+    /// If the [`FlashService`] hasn't been registered yet, we will retry
+    /// until it has been registered.
+    pub async fn from_registry(
+        kernel: &'static Kernel,
+    ) -> Result<Self, registry::ConnectError<FlashService>> {
+        let handle = kernel.registry().connect::<FlashService>(()).await?;
+
+        Ok(Self {
+            handle,
+            reply: Reusable::new_async().await,
+        })
+    }
+
+    /// Obtain a `FlashClient`
+    ///
+    /// Does NOT attempt to get a [`FlashService`] handle more than once.
+    pub async fn from_registry_no_retry(
+        kernel: &'static Kernel,
+    ) -> Result<Self, registry::ConnectError<FlashService>> {
+        let handle = kernel.registry().try_connect::<FlashService>(()).await?;
+
+        Ok(Self {
+            handle,
+            reply: Reusable::new_async().await,
+        })
+    }
+
+    /// Returns the flash map, and the size of the flash chip.
+    pub async fn info(&mut self) -> Result<FlashInfo, FlashError> {
+        match self.request(Request::GetInfo).await? {
+            Response::Info(info) => Ok(info),
+            _ => Err(FlashError::Device),
+        }
+    }
+
+    /// Reads `buf.capacity()` bytes of `region` into `buf`, starting at
+    /// `offset`.
+    pub async fn read(
+        &mut self,
+        region: RegionKind,
+        offset: u32,
+        buf: FixedVec<u8>,
+    ) -> Result<FixedVec<u8>, FlashError> {
+        let req = Request::Read {
+            region,
+            offset,
+            buf,
+        };
+        match self.request(req).await? {
+            Response::Read(buf) => Ok(buf),
+            _ => Err(FlashError::Device),
+        }
+    }
+
+    /// Writes the contents of `buf` to `region`, starting at `offset`,
+    /// returning `buf` once it has been written.
+    ///
+    /// The bytes being written should have been [erased](Self::erase) first.
+    pub async fn write(
+        &mut self,
+        region: RegionKind,
+        offset: u32,
+        buf: FixedVec<u8>,
+    ) -> Result<FixedVec<u8>, FlashError> {
+        let req = Request::Write {
+            region,
+            offset,
+            buf,
+        };
+        match self.request(req).await? {
+            Response::Written(buf) => Ok(buf),
+            _ => Err(FlashError::Device),
+        }
+    }
+
+    /// Erases `len` bytes of `region`, starting at `offset`, which must both
+    /// be multiples of [`SECTOR_SIZE`].
+    pub async fn erase(
+        &mut self,
+        region: RegionKind,
+        offset: u32,
+        len: u32,
+    ) -> Result<(), FlashError> {
+        let req = Request::Erase {
+            region,
+            offset,
+            len,
+        };
+        match self.request(req).await? {
+            Response::Erased => Ok(()),
+            _ => Err(FlashError::Device),
+        }
+    }
+
+    async fn request(&mut self, req: Request) -> Result<Response, FlashError> {
+        self.handle
+            .request_oneshot(req, &self.reply)
+            .await
+            .map_err(FlashError::Request)?
+            .body
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Server Definition
+////////////////////////////////////////////////////////////////////////////////
+
+/// A flash chip, driven by a platform driver.
+///
+/// Addresses are in bytes from the start of the chip. Programs and erases
+/// are started by one call and polled with [`FlashDevice::is_busy`], so that
+/// the [`FlashServer`] can let other tasks run while they finish.
+pub trait FlashDevice {
+    /// Returns the size of the chip, in bytes.
+    fn capacity(&self) -> u32;
+
+    /// Returns the size of the chip's program pages, in bytes.
+    ///
+    /// A single [`FlashDevice::start_program`] never crosses a page
+    /// boundary.
+    fn page_size(&self) -> u32;
+
+    /// Reads `buf.len()` bytes, starting at `addr`.
+    fn read(&mut self, addr: u32, buf: &mut [u8]) -> Result<(), FlashError>;
+
+    /// Starts programming `data` at `addr`, within a single page.
+    fn start_program(&mut self, addr: u32, data: &[u8]) -> Result<(), FlashError>;
+
+    /// Starts erasing the [`SECTOR_SIZE`] sector starting at `addr`.
+    fn start_erase(&mut self, addr: u32) -> Result<(), FlashError>;
+
+    /// Returns `true` while a program or erase is in progress.
+    fn is_busy(&mut self) -> Result<bool, FlashError>;
+}
+
+/// Server implementation for the [`FlashService`].
+pub struct FlashServer<D> {
+    kernel: &'static Kernel,
+    device: D,
+    map: FlashMap,
+    /// Used to tell the user when the flash chip fails. This is connected to
+    /// when it is first needed, as the notification service may be
+    /// registered after the flash service.
+    notifications: Option<NotificationClient>,
+}
+
+impl<D: FlashDevice + 'static> FlashServer<D> {
+    /// How long to wait between polls of an erase, which usually takes tens
+    /// of milliseconds.
+    const ERASE_POLL: Duration = Duration::from_millis(1);
+
+    /// Reads the flash map from `device`, and registers the `FlashServer` for
+    /// it, returning the flash map.
+    #[tracing::instrument(
+        name = "FlashServer::register",
+        level = Level::INFO,
+        skip(kernel, device),
+        err(Debug),
+    )]
+    pub async fn register(
+        kernel: &'static Kernel,
+        mut device: D,
+        capacity: usize,
+    ) -> Result<FlashMap, RegistrationError> {
+        let mut encoded = [0; flash_map::ENCODED_LEN];
+        device
+            .read(flash_map::OFFSET, &mut encoded)
+            .map_err(RegistrationError::Device)?;
+        let map = FlashMap::decode(&encoded).map_err(RegistrationError::NoFlashMap)?;
+        if map.end() > device.capacity() as u64 {
+            return Err(RegistrationError::MapTooLarge(map));
+        }
+
+        let reqs = kernel
+            .registry()
+            .bind_konly::<FlashService>(capacity)
+            .await
+            .map_err(RegistrationError::Registry)?
+            .into_request_stream(capacity)
+            .await;
+        tracing::info!(?map, capacity = device.capacity(), "Starting FlashServer");
+
+        let mut server = Self {
+            kernel,
+            device,
+            map,
+            notifications: None,
+        };
+        kernel
+            .spawn(async move {
+                loop {
+                    let msg: Message<FlashService> = reqs.next_request().await;
+                    let span = msg.msg.span();
+                    let (req, env, reply) = msg.split();
+                    async {
+                        let rsp = server.handle(req).await;
+                        let failed = matches!(rsp, Err(FlashError::Device));
+                        if let Err(error) = reply.reply_konly(env.fill(rsp)).await {
+                            tracing::warn!(?error, "Failed to reply to flash request");
+                        }
+                        if failed {
+                            server.report_device_error().await;
+                        }
+                    }
+                    .instrument(span)
+                    .await;
+                }
+            })
+            .await;
+
+        Ok(map)
+    }
+
+    async fn handle(&mut self, req: Request) -> Result<Response, FlashError> {
+        match req {
+            Request::GetInfo => Ok(Response::Info(FlashInfo {
+                map: self.map,
+                capacity: self.device.capacity(),
+            })),
+            Request::Read {
+                region,
+                offset,
+                mut buf,
+            } => {
+                let len = buf.capacity();
+                let mut addr = self.absolute(region, offset, len)?;
+                buf.clear();
+                let mut scratch = [0; 256];
+                while buf.len() < len {
+                    let chunk = &mut scratch[..(len - buf.len()).min(scratch.len())];
+                    self.device.read(addr, chunk)?;
+                    // `buf` has room for the rest of the read.
+                    let _ = buf.try_extend_from_slice(chunk);
+                    addr += chunk.len() as u32;
+                }
+                Ok(Response::Read(buf))
+            }
+            Request::Write {
+                region,
+                offset,
+                buf,
+            } => {
+                let mut addr = self.absolute(region, offset, buf.len())?;
+                let page = self.device.page_size();
+                let mut data = buf.as_slice();
+                while !data.is_empty() {
+                    let (this, rest) =
+                        data.split_at(((page - addr % page) as usize).min(data.len()));
+                    self.device.start_program(addr, this)?;
+                    // page programs take a few hundred microseconds, which
+                    // isn't worth sleeping for.
+                    while self.device.is_busy()? {
+                        self.kernel.yield_now().await;
+                    }
+                    addr += this.len() as u32;
+                    data = rest;
+                }
+                Ok(Response::Written(buf))
+            }
+            Request::Erase {
+                region,
+                offset,
+                len,
+            } => {
+                if offset % SECTOR_SIZE != 0 || len % SECTOR_SIZE != 0 {
+                    return Err(FlashError::Unaligned);
+                }
+                let start = self.absolute(region, offset, len as usize)?;
+                for addr in (start..start + len).step_by(SECTOR_SIZE as usize) {
+                    self.device.start_erase(addr)?;
+                    while self.device.is_busy()? {
+                        self.kernel.sleep(Self::ERASE_POLL).await;
+                    }
+                }
+                Ok(Response::Erased)
+            }
+        }
+    }
+
+    /// Tells the user that the flash chip failed, as data stored in the
+    /// config or key-value regions may have been lost.
+    async fn report_device_error(&mut self) {
+        if self.notifications.is_none() {
+            self.notifications = NotificationClient::from_registry_no_retry(self.kernel)
+                .await
+                .ok();
+        }
+        if let Some(ref mut notifications) = self.notifications {
+            let _ = notifications.error("Flash: device error").await;
+        }
+    }
+
+    /// Returns the address of `len` bytes starting `offset` bytes into
+    /// `region`.
+    fn absolute(&self, region: RegionKind, offset: u32, len: usize) -> Result<u32, FlashError> {
+        if region == RegionKind::Kernel {
+            return Err(FlashError::Protected(region));
+        }
+        let len = u32::try_from(len).map_err(|_| FlashError::OutOfBounds)?;
+        self.map
+            .region(region)
+            .absolute(offset, len)
+            .ok_or(FlashError::OutOfBounds)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TestKernel;
+    use std::{vec, vec::Vec};
+
+    /// 64 KiB of flash, which is never busy.
+    struct RamFlash(Vec<u8>);
+
+    const MAP: FlashMap = FlashMap {
+        kernel: Region {
+            offset: 40 * 1024,
+            len: 8 * 1024,
+        },
+        kernel_len: 1024,
+        config: Region {
+            offset: 48 * 1024,
+            len: 4 * 1024,
+        },
+        kv: Region {
+            offset: 52 * 1024,
+            len: 8 * 1024,
+        },
+    };
+
+    impl FlashDevice for RamFlash {
+        fn capacity(&self) -> u32 {
+            self.0.len() as u32
+        }
+
+        fn page_size(&self) -> u32 {
+            256
+        }
+
+        fn read(&mut self, addr: u32, buf: &mut [u8]) -> Result<(), FlashError> {
+            let addr = addr as usize;
+            buf.copy_from_slice(&self.0[addr..addr + buf.len()]);
+            Ok(())
+        }
+
+        fn start_program(&mut self, addr: u32, data: &[u8]) -> Result<(), FlashError> {
+            assert_eq!(addr / 256, (addr + data.len() as u32 - 1) / 256);
+            for (byte, &new) in self.0[addr as usize..].iter_mut().zip(data) {
+                *byte &= new;
+            }
+            Ok(())
+        }
+
+        fn start_erase(&mut self, addr: u32) -> Result<(), FlashError> {
+            let addr = addr as usize;
+            self.0[addr..addr + SECTOR_SIZE as usize].fill(0xFF);
+            Ok(())
+        }
+
+        fn is_busy(&mut self) -> Result<bool, FlashError> {
+            Ok(false)
+        }
+    }
+
+    #[test]
+    fn accesses_regions() {
+        TestKernel::run(|k| async move {
+            let mut flash = RamFlash(vec![0xFF; 64 * 1024]);
+            let start = flash_map::OFFSET as usize;
+            flash.0[start..start + flash_map::ENCODED_LEN].copy_from_slice(&MAP.encode());
+            flash.0[MAP.config.offset as usize..][..5].copy_from_slice(b"hello");
+
+            let map = FlashServer::register(k, flash, 2).await.unwrap();
+            assert_eq!(map, MAP);
+            let mut client = FlashClient::from_registry(k).await.unwrap();
+            assert_eq!(client.info().await.unwrap().capacity, 64 * 1024);
+
+            let buf = client
+                .read(RegionKind::Config, 0, FixedVec::new(5).await)
+                .await
+                .unwrap();
+            assert_eq!(buf.as_slice(), b"hello");
+
+            // a write that crosses a page boundary.
+            let mut buf = FixedVec::new(300).await;
+            buf.try_extend_from_slice(&[0xA5; 300]).unwrap();
+            client.write(RegionKind::Kv, 200, buf).await.unwrap();
+            let buf = client
+                .read(RegionKind::Kv, 199, FixedVec::new(302).await)
+                .await
+                .unwrap();
+            assert_eq!(buf.as_slice()[0], 0xFF);
+            assert!(buf.as_slice()[1..301].iter().all(|&b| b == 0xA5));
+            assert_eq!(buf.as_slice()[301], 0xFF);
+
+            client.erase(RegionKind::Kv, 0, SECTOR_SIZE).await.unwrap();
+            let buf = client
+                .read(RegionKind::Kv, 200, FixedVec::new(300).await)
+                .await
+                .unwrap();
+            assert!(buf.as_slice().iter().all(|&b| b == 0xFF));
+
+            assert_eq!(
+                client
+                    .read(RegionKind::Kernel, 0, FixedVec::new(4).await)
+                    .await
+                    .err(),
+                Some(FlashError::Protected(RegionKind::Kernel))
+            );
+            assert_eq!(
+                client
+                    .read(RegionKind::Config, 4094, FixedVec::new(4).await)
+                    .await
+                    .err(),
+                Some(FlashError::OutOfBounds)
+            );
+            assert_eq!(
+                client.erase(RegionKind::Kv, 0, 1024).await,
+                Err(FlashError::Unaligned)
+            );
+        })
+    }
+}
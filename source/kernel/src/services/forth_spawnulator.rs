@@ -37,6 +37,26 @@
 //! tasks in a queue in order to wait for the priveliege of being put in a
 //! different queue (the scheduler's run queue), but I couldn't easily come up
 //! with another solution...
+//!
+//! # Boot Scripts
+//!
+//! The spawnulator may optionally be configured with a boot script, using
+//! [`SpawnulatorSettings::boot_script`]. Once every boot step has finished
+//! (see [`BootProgress`]), the boot script is run by a dedicated Forth task,
+//! one line at a time, as though it had been typed at a shell. This allows a
+//! board to configure itself at boot (setting LED colors, opening ports, or
+//! `spawn`ing background tasks) without rebuilding the kernel.
+//!
+//! After the script has finished, its Forth VM is kept alive, so that any
+//! ports or channels it opened remain open, and any tasks it spawned can
+//! still use them.
+//!
+//! Boot scripts may also be loaded from a [boot bundle](crate::bundle), so
+//! that they can be changed without rebuilding the kernel. Loading boot
+//! scripts from storage is not yet supported, as there is not yet a storage
+//! service to load them from.
+//!
+//! [`BootProgress`]: crate::boot::BootProgress
 
 use core::convert::Infallible;
 
@@ -49,6 +69,7 @@ use crate::{
     Kernel,
 };
 use serde::{Deserialize, Serialize};
+use tracing::Instrument;
 use uuid::Uuid;
 
 ////////////////////////////////////////////////////////////////////////////////
@@ -111,6 +132,11 @@ impl SpawnulatorClient {
     pub async fn spawn(&mut self, vm: Forth) -> Result<(), forth3::Error> {
         let id = vm.forth.host_ctxt().id();
         tracing::trace!(task.id = id, "spawn u later...");
+        // if a previous spawn was cancelled before the spawnulator replied,
+        // don't wait for its reply, which carries nothing we need.
+        if self.reply.is_poisoned() {
+            self.reply.reset().await;
+        }
         match self.hdl.request_oneshot(Request(vm), &self.reply).await {
             Ok(_) => {
                 tracing::trace!(task.id = id, "enqueued");
@@ -130,14 +156,24 @@ impl SpawnulatorClient {
 
 pub struct SpawnulatorServer;
 
-#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SpawnulatorSettings {
     #[serde(default)]
     pub enabled: bool,
     #[serde(default = "SpawnulatorSettings::default_capacity")]
     pub capacity: usize,
+    /// Forth source to run once the kernel has booted. See the
+    /// [module-level documentation](self#boot-scripts) for details.
+    #[serde(default)]
+    pub boot_script: Option<heapless::String<MAX_BOOT_SCRIPT_LEN>>,
+    /// Parameters for the Forth VM which runs the boot script.
+    #[serde(default)]
+    pub boot_script_params: forth::Params,
 }
 
+/// The maximum length of a [boot script](self#boot-scripts), in bytes.
+pub const MAX_BOOT_SCRIPT_LEN: usize = 1024;
+
 impl SpawnulatorServer {
     /// Start the spawnulator background task, returning a handle that can be
     /// used to spawn new `Forth` VMs.
@@ -151,7 +187,11 @@ impl SpawnulatorServer {
         kernel: &'static Kernel,
         settings: SpawnulatorSettings,
     ) -> Result<(), registry::RegistrationError> {
-        tracing::info!(?settings, "Who spawns the spawnulator?");
+        tracing::info!(
+            capacity = settings.capacity,
+            boot_script = settings.boot_script.is_some(),
+            "Who spawns the spawnulator?"
+        );
         let vms = kernel
             .registry()
             .bind_konly::<SpawnulatorService>(settings.capacity)
@@ -163,6 +203,16 @@ impl SpawnulatorServer {
             .await;
         tracing::debug!("spawnulator spawnulated!");
 
+        if let Some(script) = settings.boot_script {
+            kernel
+                .spawn(SpawnulatorServer::run_boot_script(
+                    kernel,
+                    script,
+                    settings.boot_script_params,
+                ))
+                .await;
+        }
+
         tracing::info!("ForthSpawnulatorService registered");
         Ok(())
     }
@@ -175,21 +225,56 @@ impl SpawnulatorServer {
         tracing::debug!("spawnulator running...");
         loop {
             let msg = vms.next_request().await;
-            let mut vm = None;
-
-            // TODO(AJM): I really need a better "extract request contents" function
-            let resp = msg.msg.reply_with_body(|msg| {
-                vm = Some(msg.0);
-                Ok(Response)
-            });
-
-            let vm = vm.unwrap();
-            let id = vm.forth.host_ctxt().id();
-            kernel.spawn(vm.run()).await;
-            let _ = msg.reply.reply_konly(resp).await;
-            tracing::trace!(task.id = id, "spawnulated!");
+            let span = msg.msg.span();
+            async {
+                let mut vm = None;
+
+                // TODO(AJM): I really need a better "extract request contents" function
+                let resp = msg.msg.reply_with_body(|msg| {
+                    vm = Some(msg.0);
+                    Ok(Response)
+                });
+
+                // `reply_with_body` always takes the body.
+                let Some(vm) = vm else {
+                    return;
+                };
+                let id = vm.forth.host_ctxt().id();
+                kernel.spawn(vm.run()).await;
+                let _ = msg.reply.reply_konly(resp).await;
+                tracing::trace!(task.id = id, "spawnulated!");
+            }
+            .instrument(span)
+            .await;
         }
     }
+
+    #[tracing::instrument(skip(kernel, script, params))]
+    async fn run_boot_script(
+        kernel: &'static Kernel,
+        script: heapless::String<MAX_BOOT_SCRIPT_LEN>,
+        params: forth::Params,
+    ) {
+        // wait for everything else to come up before running the script, so
+        // that it can use any service registered at boot.
+        kernel.boot_progress().wait_until_finished().await;
+
+        let (mut vm, _stdio) = match Forth::new(kernel, params).await {
+            Ok(vm) => vm,
+            Err(error) => {
+                tracing::error!(%error, "Failed to create boot script VM");
+                return;
+            }
+        };
+        tracing::info!(len = script.len(), "running boot script...");
+        match vm.run_script(&script).await {
+            Ok(()) => tracing::info!("boot script finished"),
+            Err(error) => tracing::error!(?error, "boot script failed"),
+        }
+
+        // keep the VM (and anything it opened) alive.
+        core::future::pending::<()>().await;
+    }
 }
 
 impl SpawnulatorSettings {
@@ -203,6 +288,7 @@ impl SpawnulatorSettings {
         Self {
             enabled: true, // Should this default to false?
             capacity,
+            ..self
         }
     }
 }
@@ -212,6 +298,8 @@ impl Default for SpawnulatorSettings {
         Self {
             enabled: true, // Should this default to false?
             capacity: Self::DEFAULT_CAPACITY,
+            boot_script: None,
+            boot_script_params: forth::Params::new(),
         }
     }
 }
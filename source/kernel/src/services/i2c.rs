@@ -85,6 +85,19 @@
 //! limited by the buffer's *total capacity*, rather than the current length of
 //! the initialized portion.
 //!
+//! ## Scanning the Bus
+//!
+//! When bringing up a new board, it's useful to know which devices are
+//! actually connected to the bus. [`I2cClient::scan`] probes every
+//! non-reserved 7-bit address, and returns a [`ScanReport`] of the addresses
+//! which were acknowledged by a device. Individual addresses can be probed
+//! using [`I2cClient::probe`].
+//!
+//! Drivers which support zero-length writes (which send only a target's
+//! address) advertise the [`capabilities::QUICK_WRITE`] capability, and are
+//! probed using zero-length writes. Other drivers are probed by reading a
+//! single byte, which is harmless for almost all devices.
+//!
 //! [RP2040 datasheet]: https://datasheets.raspberrypi.com/rp2040/rp2040-datasheet.pdf
 //! [impl-i2c]: I2cClient#impl-I2c<u8>-for-I2cClient
 //! [`I2c::transaction`]: embedded_hal_async::i2c::I2c::transaction
@@ -97,7 +110,7 @@ use crate::{
         oneshot::{self, Reusable},
     },
     mnemos_alloc::containers::FixedVec,
-    registry::{self, known_uuids, Envelope, KernelHandle, RegisteredDriver},
+    registry::{self, known_uuids, Capabilities, Envelope, KernelHandle, RegisteredDriver},
     Kernel,
 };
 use core::{convert::Infallible, fmt};
@@ -124,6 +137,18 @@ impl RegisteredDriver for I2cService {
     const UUID: Uuid = known_uuids::kernel::I2C;
 }
 
+/// Optional [capabilities](registry::capabilities) advertised by
+/// [`I2cService`] drivers.
+pub mod capabilities {
+    use crate::registry::Capabilities;
+
+    /// The driver supports zero-length [`Transaction::write`]s, which send
+    /// only the target's address, followed by a `STOP`.
+    ///
+    /// [`Transaction::write`]: super::Transaction::write
+    pub const QUICK_WRITE: Capabilities = Capabilities::bit(0);
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 // Message and Error Types
 ////////////////////////////////////////////////////////////////////////////////
@@ -165,6 +190,29 @@ pub struct Transaction {
     ended: bool,
 }
 
+/// The result of probing an address on the I²C bus, returned by
+/// [`I2cClient::probe`].
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Probe {
+    /// A device acknowledged the address.
+    Present,
+    /// No device acknowledged the address.
+    Absent,
+    /// The probe failed for some other reason, such as a bus error or loss of
+    /// arbitration. Whether a device is present is unknown.
+    Error(i2c::ErrorKind),
+}
+
+/// The addresses found by scanning the I²C bus, returned by
+/// [`I2cClient::scan`].
+#[derive(Copy, Clone, Default, Eq, PartialEq)]
+pub struct ScanReport {
+    /// A bitmap of addresses which were acknowledged.
+    present: u128,
+    /// A bitmap of addresses which could not be probed.
+    errors: u128,
+}
+
 /// Errors returned by the [`I2cService`]
 #[derive(Debug)]
 pub struct I2cError {
@@ -366,6 +414,56 @@ impl I2cClient {
             .unwrap();
         resp.body.expect("transaction should be created")
     }
+
+    /// Returns the [`capabilities`] advertised by the I²C driver.
+    #[must_use]
+    pub fn capabilities(&self) -> Capabilities {
+        self.handle.capabilities()
+    }
+
+    /// Probes for a device at the 7-bit address `addr`.
+    ///
+    /// If the driver advertises [`capabilities::QUICK_WRITE`], this performs
+    /// a zero-length write to the address. Otherwise, it reads a single byte
+    /// from the address.
+    pub async fn probe(&mut self, addr: u8) -> Probe {
+        let quick = self.capabilities().contains(capabilities::QUICK_WRITE);
+        let buf = FixedVec::new(1).await;
+        let mut txn = self.start_transaction(Addr::SevenBit(addr)).await;
+        let res = if quick {
+            txn.write(buf, true).await
+        } else {
+            txn.read(buf, 1, true).await
+        };
+
+        match res.map_err(|error| i2c::Error::kind(&error)) {
+            Ok(_) => Probe::Present,
+            // the address was acknowledged, so something is there.
+            Err(i2c::ErrorKind::NoAcknowledge(i2c::NoAcknowledgeSource::Data)) => Probe::Present,
+            Err(i2c::ErrorKind::NoAcknowledge(_)) => Probe::Absent,
+            Err(kind) => Probe::Error(kind),
+        }
+    }
+
+    /// Scans the I²C bus for devices, by [probing](Self::probe) every
+    /// address from [`ScanReport::FIRST_ADDR`] to [`ScanReport::LAST_ADDR`].
+    ///
+    /// Addresses outside of this range are reserved by the I²C specification,
+    /// and are not probed.
+    pub async fn scan(&mut self) -> ScanReport {
+        let mut report = ScanReport::default();
+        for addr in ScanReport::FIRST_ADDR..=ScanReport::LAST_ADDR {
+            match self.probe(addr).await {
+                Probe::Present => report.present |= 1 << addr,
+                Probe::Absent => {}
+                Probe::Error(kind) => {
+                    tracing::debug!(addr = %format_args!("{addr:#04x}"), ?kind, "I2C probe failed");
+                    report.errors |= 1 << addr;
+                }
+            }
+        }
+        report
+    }
 }
 
 impl i2c::ErrorType for I2cClient {
@@ -626,6 +724,70 @@ impl From<i2c::TenBitAddress> for Addr {
     }
 }
 
+// === impl ScanReport ===
+
+impl ScanReport {
+    /// The first address probed by [`I2cClient::scan`]. Lower addresses are
+    /// reserved.
+    pub const FIRST_ADDR: u8 = 0x08;
+    /// The last address probed by [`I2cClient::scan`]. Higher addresses are
+    /// reserved.
+    pub const LAST_ADDR: u8 = 0x77;
+
+    /// Returns `true` if a device acknowledged the 7-bit address `addr`.
+    #[must_use]
+    pub fn is_present(&self, addr: u8) -> bool {
+        addr < 128 && self.present & (1 << addr) != 0
+    }
+
+    /// Returns an iterator over the addresses which were acknowledged by a
+    /// device, in ascending order.
+    pub fn devices(&self) -> impl Iterator<Item = u8> {
+        bits(self.present)
+    }
+
+    /// Returns an iterator over the addresses which could not be probed, due
+    /// to a bus error, in ascending order.
+    pub fn errors(&self) -> impl Iterator<Item = u8> {
+        bits(self.errors)
+    }
+
+    /// Returns the number of devices found.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.present.count_ones() as usize
+    }
+
+    /// Returns `true` if no devices were found.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.present == 0
+    }
+}
+
+impl fmt::Debug for ScanReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        struct Addrs(u128);
+        impl fmt::Debug for Addrs {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.debug_list()
+                    .entries(bits(self.0).map(|addr| format_args!("{addr:#04x}")))
+                    .finish()
+            }
+        }
+
+        f.debug_struct("ScanReport")
+            .field("devices", &Addrs(self.present))
+            .field("errors", &Addrs(self.errors))
+            .finish()
+    }
+}
+
+/// Returns an iterator over the indices of the set bits in `bitmap`.
+fn bits(bitmap: u128) -> impl Iterator<Item = u8> {
+    (0..128u8).filter(move |bit| bitmap & (1 << bit) != 0)
+}
+
 // === impl I2cError ===
 
 impl I2cError {
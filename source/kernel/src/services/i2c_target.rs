@@ -0,0 +1,259 @@
+//! I²C Target Service
+//!
+//! The [`I2cService`] assumes that the MnemOS kernel is running on the
+//! device acting as the I²C bus _controller_. This module contains a
+//! service definition for drivers that allow the kernel to act as an I²C
+//! _target_ device instead, responding to operations performed by another
+//! controller on the bus. This allows a MnemOS device to appear as a
+//! peripheral to another computer, for example, to emulate a device for
+//! testing, or to exchange messages with another system.
+//!
+//! ## Usage
+//!
+//! A task which wishes to respond to bus operations acquires an
+//! [`I2cTargetClient`] using [`I2cTargetClient::from_registry`], and calls
+//! [`I2cTargetClient::listen`] with the address it wishes to respond to. This
+//! returns a [`Target`], which yields a [`TargetRequest`] each time the bus
+//! controller performs an operation at that address:
+//!
+//! - [`TargetRequest::Write`] contains the bytes written to the target by the
+//!   controller, once the controller has ended the write with a `STOP` or
+//!   repeated `START` condition.
+//! - [`TargetRequest::Read`] indicates that the controller has begun reading
+//!   from the target. The task must respond with the bytes to send to the
+//!   controller using [`ReadRequest::respond`]. While the task prepares its
+//!   response, the driver holds the bus clock low, which is permitted by the
+//!   I²C specification (and called "clock stretching"), so the task should
+//!   respond promptly.
+//!
+//! The driver responds to the address only as long as the [`Target`] exists.
+//! A driver responds to a single address at a time: if a [`Target`] already
+//! exists, [`I2cTargetClient::listen`] will wait until it is dropped.
+//!
+//! [`I2cService`]: crate::services::i2c::I2cService
+#![warn(missing_docs)]
+use self::messages::*;
+use crate::{
+    comms::{
+        kchannel::{KChannel, KConsumer, KProducer},
+        oneshot::{self, Reusable},
+    },
+    mnemos_alloc::containers::FixedVec,
+    registry::{self, known_uuids, Envelope, KernelHandle, RegisteredDriver},
+    services::i2c::Addr,
+    Kernel,
+};
+use core::convert::Infallible;
+use uuid::Uuid;
+
+////////////////////////////////////////////////////////////////////////////////
+// Service Definition
+////////////////////////////////////////////////////////////////////////////////
+
+/// [Service](crate::services) definition for I²C target-mode drivers.
+///
+/// See the [module-level documentation](crate::services::i2c_target) for
+/// details on using this service.
+pub struct I2cTargetService;
+
+impl RegisteredDriver for I2cTargetService {
+    type Request = Listen;
+    type Response = Target;
+    type Error = Infallible;
+    type Hello = ();
+    type ConnectError = Infallible;
+
+    const UUID: Uuid = known_uuids::kernel::I2C_TARGET;
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Message and Error Types
+////////////////////////////////////////////////////////////////////////////////
+
+/// A handle for responding to I²C bus operations at an address.
+///
+/// This type is returned by [`I2cTargetClient::listen`]. The driver responds
+/// to bus operations at [`Target::addr`] for as long as the `Target` exists.
+#[must_use = "a `Target` should be used to respond to bus operations"]
+pub struct Target {
+    addr: Addr,
+    rx: KConsumer<TargetRequest>,
+}
+
+/// An I²C bus operation performed on a [`Target`] by the bus controller.
+pub enum TargetRequest {
+    /// The controller wrote these bytes to the target.
+    ///
+    /// If the controller wrote more bytes than the `max_write` passed to
+    /// [`I2cTargetClient::listen`], the target stops acknowledging bytes once
+    /// the buffer is full, and the remaining bytes are discarded.
+    Write(FixedVec<u8>),
+    /// The controller is reading from the target.
+    Read(ReadRequest),
+}
+
+/// A request from the bus controller to read from a [`Target`].
+///
+/// The bus is held until this request is responded to using
+/// [`ReadRequest::respond`], or dropped.
+#[must_use = "the I²C bus is held until a `ReadRequest` is responded to"]
+pub struct ReadRequest {
+    rsp: oneshot::Sender<FixedVec<u8>>,
+}
+
+/// Messages used to communicate with an [`I2cTargetService`] implementation.
+///
+/// The types in this module are primarily used by implementations of the
+/// [`I2cTargetService`], and are not relevant to users of the
+/// [`I2cTargetClient`] interface.
+pub mod messages {
+    use super::*;
+
+    /// Message sent to an [`I2cTargetService`] by an [`I2cTargetClient`] in
+    /// order to begin responding to bus operations at an address.
+    #[derive(Copy, Clone, Debug, Eq, PartialEq)]
+    pub struct Listen {
+        /// The address to respond to.
+        pub addr: Addr,
+        /// The maximum number of bytes the controller may write to the target
+        /// in a single [`TargetRequest::Write`].
+        pub max_write: usize,
+        pub(super) capacity: usize,
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Client Definition
+////////////////////////////////////////////////////////////////////////////////
+
+/// A client for the [`I2cTargetService`].
+///
+/// This type is used to begin responding to I²C bus operations at an
+/// address. It is obtained using [`I2cTargetClient::from_registry`] (or
+/// [`I2cTargetClient::from_registry_no_retry`]).
+#[must_use = "an `I2cTargetClient` does nothing if it is not used to listen at an address"]
+pub struct I2cTargetClient {
+    handle: KernelHandle<I2cTargetService>,
+    reply: Reusable<Envelope<Result<Target, Infallible>>>,
+}
+
+impl I2cTargetClient {
+    /// Obtain an `I2cTargetClient`
+    ///
+    /// If the [`I2cTargetService`] hasn't been registered yet, we will retry
+    /// until it has been registered.
+    pub async fn from_registry(
+        kernel: &'static Kernel,
+    ) -> Result<Self, registry::ConnectError<I2cTargetService>> {
+        let handle = kernel.registry().connect::<I2cTargetService>(()).await?;
+
+        Ok(I2cTargetClient {
+            handle,
+            reply: Reusable::new_async().await,
+        })
+    }
+
+    /// Obtain an `I2cTargetClient`
+    ///
+    /// Does NOT attempt to get an [`I2cTargetService`] handle more than once.
+    ///
+    /// Prefer [`I2cTargetClient::from_registry`] unless you will not be
+    /// spawning one around the same time as obtaining a client.
+    pub async fn from_registry_no_retry(
+        kernel: &'static Kernel,
+    ) -> Result<Self, registry::ConnectError<I2cTargetService>> {
+        let handle = kernel
+            .registry()
+            .try_connect::<I2cTargetService>(())
+            .await?;
+
+        Ok(I2cTargetClient {
+            handle,
+            reply: Reusable::new_async().await,
+        })
+    }
+
+    /// Begins responding to I²C bus operations at `addr`.
+    ///
+    /// Writes of up to `max_write` bytes are accepted by the target; see
+    /// [`TargetRequest::Write`].
+    ///
+    /// If another [`Target`] is currently listening on this driver, this
+    /// method waits until that [`Target`] is dropped.
+    pub async fn listen(&mut self, addr: Addr, max_write: usize) -> Target {
+        let resp = self
+            .handle
+            .request_oneshot(
+                Listen {
+                    addr,
+                    max_write,
+                    capacity: 2,
+                },
+                &self.reply,
+            )
+            .await
+            .unwrap();
+        resp.body.expect("target should be created")
+    }
+}
+
+// === impl Target ===
+
+impl Target {
+    /// Constructs a new `Target` from the provided [`Listen`] message,
+    /// returning the `Target` and a [`KProducer`] for sending
+    /// [`TargetRequest`]s to it.
+    ///
+    /// This is intended to be used by server implementations of the
+    /// [`I2cTargetService`] when handling [`Listen`] messages. Once sending a
+    /// [`TargetRequest`] fails, the `Target` has been dropped, and the driver
+    /// should stop responding to its address.
+    pub async fn new(Listen { addr, capacity, .. }: Listen) -> (Self, KProducer<TargetRequest>) {
+        let (tx, rx) = KChannel::new_async(capacity).await.split();
+        (Target { addr, rx }, tx)
+    }
+
+    /// Returns the address this `Target` responds to.
+    #[must_use]
+    pub fn addr(&self) -> Addr {
+        self.addr
+    }
+
+    /// Waits for the bus controller to perform an operation on this target.
+    ///
+    /// Returns [`None`] if the driver is no longer running.
+    pub async fn next_request(&self) -> Option<TargetRequest> {
+        self.rx.dequeue_async().await.ok()
+    }
+}
+
+impl Drop for Target {
+    fn drop(&mut self) {
+        self.rx.close();
+    }
+}
+
+// === impl ReadRequest ===
+
+impl ReadRequest {
+    /// Constructs a new `ReadRequest` which sends its response to `rsp`.
+    ///
+    /// This is intended to be used by server implementations of the
+    /// [`I2cTargetService`]. If the `ReadRequest` is dropped without
+    /// responding, the driver should send `0xFF` bytes, as though the bus
+    /// were idle.
+    #[must_use]
+    pub fn new(rsp: oneshot::Sender<FixedVec<u8>>) -> Self {
+        Self { rsp }
+    }
+
+    /// Responds to the read with the bytes in `data`.
+    ///
+    /// If the controller reads fewer bytes than `data` contains, the rest are
+    /// discarded. If it reads more, the target sends `0xFF` once `data` has
+    /// been sent.
+    pub fn respond(self, data: FixedVec<u8>) {
+        // if the driver has gone away, there's no one to respond to.
+        let _ = self.rsp.send(data);
+    }
+}
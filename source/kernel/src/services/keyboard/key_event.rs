@@ -166,6 +166,16 @@ impl KeyEvent {
             code: KeyCode::Char(c as char),
         })
     }
+
+    /// Returns `true` if this event is `hotkey` being pressed while Ctrl and
+    /// Alt are held. The hotkey's case is ignored.
+    #[must_use]
+    pub fn is_hotkey(&self, hotkey: char) -> bool {
+        self.kind == Kind::Pressed
+            && self.modifiers.get(Modifiers::CTRL)
+            && self.modifiers.get(Modifiers::ALT)
+            && matches!(self.code, KeyCode::Char(c) if c.eq_ignore_ascii_case(&hotkey))
+    }
 }
 
 impl From<char> for KeyEvent {
@@ -155,6 +155,22 @@ pub enum RegistrationError {
     NoSermuxPort,
 }
 
+impl From<RegistrationError> for crate::Error {
+    fn from(error: RegistrationError) -> Self {
+        let reason = match error {
+            RegistrationError::RegisterMux(error) | RegistrationError::RegisterKeyboard(error) => {
+                return error.into()
+            }
+            RegistrationError::NoSermux(_) => "could not connect to the serial mux",
+            RegistrationError::NoSermuxPort => "the serial mux port is unavailable",
+        };
+        Self::ServiceInit {
+            uuid: KeyboardMuxService::UUID,
+            reason,
+        }
+    }
+}
+
 impl KeyboardMuxServer {
     /// Register the `KeyboardMuxServer`.
     ///
@@ -199,8 +215,7 @@ impl KeyboardMuxServer {
                 client
                     .open_port(port, settings.buffer_capacity)
                     .await
-                    // TODO(eliza): this could be a custom RegistrationError variant...
-                    .expect("failed to acquire serial mux keyboard port!"),
+                    .ok_or(RegistrationError::NoSermuxPort)?,
             )
         } else {
             None
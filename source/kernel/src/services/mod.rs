@@ -18,10 +18,23 @@
 //!
 //! For examples of using these services, see the [daemons][crate::daemons] module.
 
+pub mod alarm;
+pub mod buffer_pool;
+pub mod clipboard;
+pub mod device_identity;
 pub mod emb_display;
+pub mod entropy;
+pub mod flash;
 pub mod forth_spawnulator;
 pub mod i2c;
+pub mod i2c_target;
 pub mod keyboard;
+pub mod network;
+pub mod notification;
+pub mod pointer;
 pub mod sdmmc;
+pub mod serial_console;
 pub mod serial_mux;
 pub mod simple_serial;
+pub mod smart_led;
+pub mod wifi_buddy;
@@ -0,0 +1,123 @@
+//! # Network Interface
+//!
+//! This is a basic service that defines a network interface, which sends and
+//! receives raw Ethernet frames.
+//!
+//! Frames are exchanged over a pair of [framed channels](crate::comms::bbq),
+//! so each read grant on the [`NetInterface::rx`] channel contains exactly one
+//! received frame, and each frame committed to the [`NetInterface::tx`]
+//! channel is sent as one frame. Frames do not include the Ethernet preamble
+//! or frame check sequence.
+//!
+//! This module only contains the service definition and client definition,
+//! the server must be implemented for the given target platform.
+
+use uuid::Uuid;
+
+use crate::comms::bbq::{FramedConsumer, FramedProducer};
+use crate::comms::oneshot::Reusable;
+use crate::Kernel;
+
+use crate::registry::{self, known_uuids, Envelope, KernelHandle, RegisteredDriver};
+
+////////////////////////////////////////////////////////////////////////////////
+// Service Definition
+////////////////////////////////////////////////////////////////////////////////
+
+pub struct NetworkService;
+
+impl RegisteredDriver for NetworkService {
+    type Request = Request;
+    type Response = Response;
+    type Error = NetworkError;
+    type Hello = ();
+    type ConnectError = core::convert::Infallible;
+
+    const UUID: Uuid = known_uuids::kernel::NETWORK;
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Message and Error Types
+////////////////////////////////////////////////////////////////////////////////
+
+pub enum Request {
+    GetInterface,
+}
+
+pub enum Response {
+    Interface(NetInterface),
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum NetworkError {
+    /// The interface has already been handed out to another client.
+    AlreadyAssignedInterface,
+    /// An error occurred sending the request to the service.
+    Request(registry::OneshotRequestError),
+}
+
+/// A network interface, obtained from the [`NetworkService`].
+pub struct NetInterface {
+    /// The interface's MAC address.
+    pub mac: [u8; 6],
+    /// The largest frame the interface can send or receive, in bytes,
+    /// including the Ethernet header.
+    pub mtu: usize,
+    /// Frames received by the interface.
+    pub rx: FramedConsumer,
+    /// Frames to be sent by the interface.
+    pub tx: FramedProducer,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Client Definition
+////////////////////////////////////////////////////////////////////////////////
+
+pub struct NetworkClient {
+    handle: KernelHandle<NetworkService>,
+    reply: Reusable<Envelope<Result<Response, NetworkError>>>,
+}
+
+impl NetworkClient {
+    /// Obtain a `NetworkClient`
+    ///
+    /// If the [`NetworkService`] hasn't been registered yet, we will retry
+    /// until it has been registered.
+    pub async fn from_registry(
+        kernel: &'static Kernel,
+    ) -> Result<Self, registry::ConnectError<NetworkService>> {
+        let handle = kernel.registry().connect::<NetworkService>(()).await?;
+
+        Ok(NetworkClient {
+            handle,
+            reply: Reusable::new_async().await,
+        })
+    }
+
+    /// Obtain a `NetworkClient`
+    ///
+    /// Does NOT attempt to get a [`NetworkService`] handle more than once.
+    pub async fn from_registry_no_retry(
+        kernel: &'static Kernel,
+    ) -> Result<Self, registry::ConnectError<NetworkService>> {
+        let handle = kernel.registry().try_connect::<NetworkService>(()).await?;
+
+        Ok(NetworkClient {
+            handle,
+            reply: Reusable::new_async().await,
+        })
+    }
+
+    /// Take the network interface.
+    ///
+    /// Only one client may hold the interface at a time.
+    pub async fn get_interface(&mut self) -> Result<NetInterface, NetworkError> {
+        let resp = self
+            .handle
+            .request_oneshot(Request::GetInterface, &self.reply)
+            .await
+            .map_err(NetworkError::Request)?;
+        let Response::Interface(iface) = resp.body?;
+        Ok(iface)
+    }
+}
@@ -0,0 +1,413 @@
+//! # Notification Service
+//!
+//! Short, user-visible messages, such as "battery low" or "flash: device
+//! error", which any task may post. Unlike `tracing` events, which only
+//! reach a host that is watching the trace stream, notifications are shown
+//! to the user of the device: the
+//! [graphical shell](crate::daemons::shells::graphical_shell_mono) shows each
+//! one as a transient toast in its titlebar, and the
+//! [hello daemon](crate::daemons::sermux::hello) sends each one to the host
+//! on its serial mux port, for headless devices.
+//!
+//! Each [`Notification`] is stamped with the kernel's uptime when it was
+//! posted, logged at the `tracing` level matching its [`Severity`], and
+//! published on the kernel's [event bus](crate::comms::events), where anything
+//! which displays notifications subscribes to them. Notifications are not
+//! stored: one posted while nothing is subscribed is only logged.
+//!
+//! If [`NotificationSettings::watch_events`] is set, the server also posts
+//! notifications for events on the event bus which the user should know
+//! about, such as the battery running low or a network link going down.
+
+use core::{convert::Infallible, fmt::Write, time::Duration};
+
+use futures::{future, FutureExt};
+use serde::{Deserialize, Serialize};
+use tracing::{Instrument, Level};
+use uuid::Uuid;
+
+use crate::{
+    comms::{
+        events::{Event, LowMemory, NetworkLinkChange, PowerEvent, Subscriber},
+        oneshot::Reusable,
+    },
+    registry::{self, known_uuids, Envelope, KernelHandle, Message, RegisteredDriver},
+    Kernel,
+};
+
+/// The longest a notification's text may be, in bytes. Longer text is
+/// truncated.
+pub const MAX_LEN: usize = 48;
+
+/// A notification's text.
+pub type Text = heapless::String<MAX_LEN>;
+
+////////////////////////////////////////////////////////////////////////////////
+// Service Definition
+////////////////////////////////////////////////////////////////////////////////
+
+/// Service definition for the notification service.
+pub struct NotificationService;
+
+impl RegisteredDriver for NotificationService {
+    type Request = Post;
+    type Response = Posted;
+    type Error = NotificationError;
+    type Hello = ();
+    type ConnectError = Infallible;
+    const UUID: Uuid = known_uuids::kernel::NOTIFICATION;
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Message and Error Types
+////////////////////////////////////////////////////////////////////////////////
+
+/// Post a notification.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Post {
+    pub severity: Severity,
+    pub text: Text,
+}
+
+/// A notification was posted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Posted {
+    /// The number of subscribers the notification was delivered to.
+    pub delivered: usize,
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum NotificationError {
+    /// An error occurred sending the request to the service.
+    Request(registry::OneshotRequestError),
+}
+
+/// How severe a [`Notification`] is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Severity {
+    /// Something the user may want to know, such as a device being
+    /// connected.
+    Info,
+    /// Something the user should act on soon, such as the battery running
+    /// low.
+    Warn,
+    /// Something has failed, such as a storage device.
+    Error,
+}
+
+/// A notification, as published on the [event bus](crate::comms::events).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Notification {
+    pub severity: Severity,
+    /// The kernel's [uptime](Kernel::uptime) when the notification was
+    /// posted.
+    pub at: Duration,
+    pub text: Text,
+}
+
+/// Returns at most the first [`MAX_LEN`] bytes of `text`, on a character
+/// boundary.
+#[must_use]
+pub fn text(text: &str) -> Text {
+    let mut end = text.len().min(MAX_LEN);
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    let mut truncated = Text::new();
+    // `end` is at most `MAX_LEN`, so this always fits.
+    let _ = truncated.push_str(&text[..end]);
+    truncated
+}
+
+impl From<Severity> for sermux_proto::NoticeSeverity {
+    fn from(severity: Severity) -> Self {
+        match severity {
+            Severity::Info => Self::Info,
+            Severity::Warn => Self::Warn,
+            Severity::Error => Self::Error,
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Client Definition
+////////////////////////////////////////////////////////////////////////////////
+
+/// A client for the [`NotificationService`].
+pub struct NotificationClient {
+    handle: KernelHandle<NotificationService>,
+    reply: Reusable<Envelope<Result<Posted, NotificationError>>>,
+}
+
+impl NotificationClient {
+    /// Obtain a `NotificationClient`
+    ///
+    /// If the [`NotificationService`] hasn't been registered yet, we will
+    /// retry until it has been registered.
+    pub async fn from_registry(
+        kernel: &'static Kernel,
+    ) -> Result<Self, registry::ConnectError<NotificationService>> {
+        let handle = kernel.registry().connect::<NotificationService>(()).await?;
+        Ok(Self {
+            handle,
+            reply: Reusable::new_async().await,
+        })
+    }
+
+    /// Obtain a `NotificationClient`
+    ///
+    /// Does NOT attempt to get a [`NotificationService`] handle more than
+    /// once.
+    ///
+    /// Prefer [`NotificationClient::from_registry`] unless you will not be
+    /// spawning one around the same time as obtaining a client.
+    pub async fn from_registry_no_retry(
+        kernel: &'static Kernel,
+    ) -> Result<Self, registry::ConnectError<NotificationService>> {
+        let handle = kernel
+            .registry()
+            .try_connect::<NotificationService>(())
+            .await?;
+        Ok(Self {
+            handle,
+            reply: Reusable::new_async().await,
+        })
+    }
+
+    /// Posts a notification, returning the number of subscribers it was
+    /// delivered to.
+    ///
+    /// `text` is truncated to [`MAX_LEN`] bytes.
+    pub async fn post(
+        &mut self,
+        severity: Severity,
+        text: &str,
+    ) -> Result<usize, NotificationError> {
+        let req = Post {
+            severity,
+            text: self::text(text),
+        };
+        let Posted { delivered } = self
+            .handle
+            .request_oneshot(req, &self.reply)
+            .await
+            .map_err(NotificationError::Request)?
+            .body?;
+        Ok(delivered)
+    }
+
+    /// Posts an [`Info`](Severity::Info) notification.
+    pub async fn info(&mut self, text: &str) -> Result<usize, NotificationError> {
+        self.post(Severity::Info, text).await
+    }
+
+    /// Posts a [`Warn`](Severity::Warn) notification.
+    pub async fn warn(&mut self, text: &str) -> Result<usize, NotificationError> {
+        self.post(Severity::Warn, text).await
+    }
+
+    /// Posts an [`Error`](Severity::Error) notification.
+    pub async fn error(&mut self, text: &str) -> Result<usize, NotificationError> {
+        self.post(Severity::Error, text).await
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Server Definition
+////////////////////////////////////////////////////////////////////////////////
+
+/// Server implementation for the [`NotificationService`].
+pub struct NotificationServer;
+
+/// Settings for the [`NotificationServer`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NotificationSettings {
+    /// Should the notification service be enabled?
+    #[serde(default)]
+    pub enabled: bool,
+    /// Maximum number of outstanding requests from clients.
+    #[serde(default = "NotificationSettings::default_capacity")]
+    pub capacity: usize,
+    /// Post notifications for events on the event bus which the user should
+    /// know about: the battery running low, network links going down, and
+    /// allocations failing.
+    #[serde(default = "NotificationSettings::default_watch_events")]
+    pub watch_events: bool,
+}
+
+impl NotificationServer {
+    /// The number of unhandled events to buffer from each watched event bus
+    /// topic.
+    const EVENT_CAPACITY: usize = 4;
+
+    /// Register the `NotificationServer`.
+    #[tracing::instrument(
+        name = "NotificationServer::register",
+        level = Level::INFO,
+        skip(kernel, settings),
+        err(Debug),
+    )]
+    pub async fn register(
+        kernel: &'static Kernel,
+        settings: NotificationSettings,
+    ) -> Result<(), registry::RegistrationError> {
+        tracing::info!(?settings, "Starting NotificationServer");
+        let reqs = kernel
+            .registry()
+            .bind_konly::<NotificationService>(settings.capacity)
+            .await?
+            .into_request_stream(settings.capacity)
+            .await;
+
+        let (power, network, memory) = if settings.watch_events {
+            (
+                watch::<PowerEvent>(kernel).await,
+                watch::<NetworkLinkChange>(kernel).await,
+                watch::<LowMemory>(kernel).await,
+            )
+        } else {
+            (None, None, None)
+        };
+
+        kernel
+            .spawn(async move {
+                loop {
+                    futures::select_biased! {
+                        Message { msg, reply } = reqs.next_request().fuse() => {
+                            let span = msg.span();
+                            async {
+                                let resp = msg.reply_with_body(|Post { severity, text }| {
+                                    let delivered = publish(kernel, severity, text);
+                                    Ok(Posted { delivered })
+                                });
+                                if let Err(error) = reply.reply_konly(resp).await {
+                                    tracing::warn!(?error, "Failed to reply to notification request");
+                                }
+                            }
+                            .instrument(span)
+                            .await;
+                        },
+                        event = next(&power).fuse() => {
+                            if let PowerEvent::BatteryLow = event {
+                                publish(kernel, Severity::Warn, text("Battery low"));
+                            }
+                        },
+                        event = next(&network).fuse() => {
+                            if !event.up {
+                                let mut text = Text::new();
+                                let _ = write!(text, "Network link {} down", event.interface);
+                                publish(kernel, Severity::Warn, text);
+                            }
+                        },
+                        event = next(&memory).fuse() => {
+                            if let LowMemory::Critical = event {
+                                publish(kernel, Severity::Error, text("Out of memory"));
+                            }
+                        },
+                    }
+                }
+            })
+            .await;
+
+        Ok(())
+    }
+}
+
+/// Subscribes to events of type `E`, for the server to post notifications
+/// about.
+async fn watch<E: Event>(kernel: &'static Kernel) -> Option<Subscriber<E>> {
+    match kernel
+        .events()
+        .subscribe::<E>(NotificationServer::EVENT_CAPACITY)
+        .await
+    {
+        Ok(sub) => Some(sub),
+        Err(error) => {
+            tracing::warn!(%error, "Can't watch events for notifications");
+            None
+        }
+    }
+}
+
+/// Waits for the next event from `sub`, if there is one, or forever if
+/// there isn't.
+async fn next<E: Event>(sub: &Option<Subscriber<E>>) -> E {
+    match sub {
+        Some(sub) => sub.next().await,
+        None => future::pending().await,
+    }
+}
+
+/// Logs a notification, and publishes it on the event bus, returning the
+/// number of subscribers it was delivered to.
+fn publish(kernel: &'static Kernel, severity: Severity, text: Text) -> usize {
+    match severity {
+        Severity::Info => tracing::info!(%text, "Notification"),
+        Severity::Warn => tracing::warn!(%text, "Notification"),
+        Severity::Error => tracing::error!(%text, "Notification"),
+    }
+    kernel.events().publish(Notification {
+        severity,
+        at: kernel.uptime(),
+        text,
+    })
+}
+
+impl NotificationSettings {
+    pub const DEFAULT_CAPACITY: usize = 4;
+    pub const DEFAULT_WATCH_EVENTS: bool = true;
+
+    const fn default_capacity() -> usize {
+        Self::DEFAULT_CAPACITY
+    }
+
+    const fn default_watch_events() -> bool {
+        Self::DEFAULT_WATCH_EVENTS
+    }
+}
+
+impl Default for NotificationSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true, // Should this default to false?
+            capacity: Self::DEFAULT_CAPACITY,
+            watch_events: Self::DEFAULT_WATCH_EVENTS,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TestKernel;
+
+    #[test]
+    fn posts_notifications() {
+        TestKernel::run(|k| async move {
+            NotificationServer::register(k, NotificationSettings::default())
+                .await
+                .unwrap();
+            let sub = k.events().subscribe::<Notification>(4).await.unwrap();
+            let mut client = NotificationClient::from_registry(k).await.unwrap();
+
+            assert_eq!(client.warn("SD card error").await, Ok(1));
+            let note = sub.next().await;
+            assert_eq!(note.severity, Severity::Warn);
+            assert_eq!(note.text, "SD card error");
+
+            // long text is truncated on a character boundary.
+            let long = ["a", &"é".repeat(MAX_LEN)].concat();
+            client.info(&long).await.unwrap();
+            assert_eq!(sub.next().await.text, long[..MAX_LEN - 1]);
+
+            // events the user should know about are posted, too.
+            k.events().publish(NetworkLinkChange {
+                interface: 1,
+                up: false,
+            });
+            let note = sub.next().await;
+            assert_eq!(note.severity, Severity::Warn);
+            assert_eq!(note.text, "Network link 1 down");
+        })
+    }
+}
@@ -0,0 +1,264 @@
+//! # Pointer Service
+//!
+//! This module defines a generic service for modeling pointing devices, such
+//! as mice, trackpads, and trackballs. Like the [keyboard
+//! service](crate::services::keyboard), it may be implemented by drivers for
+//! specific devices, or by the generic ["pointer multiplexer"
+//! service](self::mux::PointerMuxService) in the [`mux`] submodule, which
+//! allows clients to subscribe to events from *all* pointing devices at once.
+//!
+//! Pointer events are *relative*: each [`PointerEvent`] describes how far the
+//! pointer moved since the previous event, rather than where it is. Tracking
+//! an absolute cursor position (and clamping it to the bounds of a display) is
+//! left to the client, as only the client knows what the pointer is pointing
+//! at.
+use uuid::Uuid;
+
+use crate::{
+    comms::{
+        kchannel::{self, KChannel},
+        oneshot,
+    },
+    registry::{self, known_uuids, RegisteredDriver},
+    Kernel,
+};
+
+pub mod mux;
+pub mod ps2;
+
+////////////////////////////////////////////////////////////////////////////////
+// Service Definition
+////////////////////////////////////////////////////////////////////////////////
+
+pub struct PointerService;
+
+impl RegisteredDriver for PointerService {
+    type Request = Subscribe;
+    type Response = Subscribed;
+    type Error = PointerError;
+    type Hello = ();
+    type ConnectError = core::convert::Infallible;
+
+    const UUID: Uuid = known_uuids::kernel::POINTER;
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Message and Error Types
+////////////////////////////////////////////////////////////////////////////////
+
+/// A single report from a pointing device.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct PointerEvent {
+    /// Horizontal motion since the previous event. Positive values move the
+    /// pointer to the right.
+    pub dx: i16,
+    /// Vertical motion since the previous event. Positive values move the
+    /// pointer *down*, matching display coordinates.
+    pub dy: i16,
+    /// Scroll wheel motion since the previous event. Positive values scroll
+    /// *down* (towards the user).
+    ///
+    /// This is always 0 for devices without a scroll wheel.
+    pub wheel: i8,
+    /// The buttons held down when the event was reported.
+    pub buttons: Buttons,
+}
+
+/// The set of buttons held down on a pointing device.
+#[derive(Copy, Clone, Default, Eq, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Buttons(u8);
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Subscribe {
+    buffer_capacity: usize,
+}
+
+pub struct Subscribed {
+    rx: kchannel::KConsumer<PointerEvent>,
+}
+
+#[derive(Debug, Eq, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum PointerError {
+    NoPointers,
+    TooManySubscriptions,
+}
+
+#[derive(Debug, Eq, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum PointerClientError {
+    NoPointerService,
+}
+
+impl Default for Subscribe {
+    fn default() -> Self {
+        Self {
+            buffer_capacity: Self::DEFAULT_BUFFER_CAPACITY,
+        }
+    }
+}
+
+impl Subscribe {
+    pub const DEFAULT_BUFFER_CAPACITY: usize = 32;
+
+    pub fn with_buffer_capacity(self, buffer_capacity: usize) -> Self {
+        Self { buffer_capacity }
+    }
+}
+
+impl Subscribed {
+    pub fn new(
+        Subscribe { buffer_capacity }: Subscribe,
+    ) -> (kchannel::KProducer<PointerEvent>, Self) {
+        let (tx, rx) = KChannel::new(buffer_capacity).split();
+        (tx, Self { rx })
+    }
+}
+
+// === impl PointerEvent ===
+
+impl PointerEvent {
+    /// Returns `true` if this event reports neither motion nor scrolling.
+    ///
+    /// Such events are still meaningful, as they are reported when a button
+    /// is pressed or released.
+    #[must_use]
+    pub fn is_stationary(&self) -> bool {
+        self.dx == 0 && self.dy == 0 && self.wheel == 0
+    }
+}
+
+// === impl Buttons ===
+
+impl Buttons {
+    pub const NONE: Self = Self(0);
+    pub const LEFT: Self = Self(1 << 0);
+    pub const RIGHT: Self = Self(1 << 1);
+    pub const MIDDLE: Self = Self(1 << 2);
+
+    /// Returns a set of buttons from its raw bits.
+    ///
+    /// Bits 0, 1, and 2 are the left, right, and middle buttons, respectively.
+    /// Any other bits are ignored.
+    #[must_use]
+    pub const fn from_bits(bits: u8) -> Self {
+        Self(bits & 0b111)
+    }
+
+    #[must_use]
+    pub const fn bits(self) -> u8 {
+        self.0
+    }
+
+    /// Returns `true` if every button in `other` is held down.
+    #[must_use]
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    #[must_use]
+    pub const fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+}
+
+impl core::ops::BitOr for Buttons {
+    type Output = Self;
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl core::fmt::Debug for Buttons {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let mut set = f.debug_set();
+        for (button, name) in [
+            (Self::LEFT, "LEFT"),
+            (Self::RIGHT, "RIGHT"),
+            (Self::MIDDLE, "MIDDLE"),
+        ] {
+            if self.contains(button) {
+                set.entry(&format_args!("{name}"));
+            }
+        }
+        set.finish()
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Client types
+////////////////////////////////////////////////////////////////////////////////
+
+/// A client that receives [`PointerEvent`]s from a [`PointerService`].
+pub struct PointerClient {
+    rx: kchannel::KConsumer<PointerEvent>,
+}
+
+#[derive(Debug)]
+pub enum FromRegistryError {
+    Connect(registry::ConnectError<PointerService>),
+    Service(PointerError),
+    Request(registry::OneshotRequestError),
+}
+
+impl PointerClient {
+    /// Obtain a `PointerClient`
+    ///
+    /// If the [`PointerService`] hasn't been registered yet, we will retry until it
+    /// has been registered.
+    pub async fn from_registry(
+        kernel: &'static Kernel,
+        subscribe: Subscribe,
+    ) -> Result<Self, FromRegistryError> {
+        let handle = kernel
+            .registry()
+            .connect::<PointerService>(())
+            .await
+            .map_err(FromRegistryError::Connect)?;
+        Self::from_handle(subscribe, handle).await
+    }
+
+    /// Obtain a `PointerClient`
+    ///
+    /// Does NOT attempt to get a [`PointerService`] handle more than once.
+    ///
+    /// Prefer [`PointerClient::from_registry`] unless you will not be spawning one
+    /// around the same time as obtaining a client.
+    pub async fn from_registry_no_retry(
+        kernel: &'static Kernel,
+        subscribe: Subscribe,
+    ) -> Result<Self, FromRegistryError> {
+        let handle = kernel
+            .registry()
+            .try_connect::<PointerService>(())
+            .await
+            .map_err(FromRegistryError::Connect)?;
+        Self::from_handle(subscribe, handle).await
+    }
+
+    async fn from_handle(
+        subscribe: Subscribe,
+        mut handle: registry::KernelHandle<PointerService>,
+    ) -> Result<Self, FromRegistryError> {
+        let reply = oneshot::Reusable::new_async().await;
+        let Subscribed { rx } = handle
+            .request_oneshot(subscribe, &reply)
+            .await
+            .map_err(FromRegistryError::Request)?
+            .body
+            .map_err(FromRegistryError::Service)?;
+        Ok(Self { rx })
+    }
+
+    /// Returns the next [`PointerEvent`] received from the [`PointerService`].
+    ///
+    /// # Returns
+    ///
+    /// - [`Ok`]`(`[`PointerEvent`]`)` when a pointer event is received.
+    /// - [`Err`]`(`[`PointerClientError`]`)` if the [`PointerService`] is no
+    ///   longer available.
+    pub async fn next(&mut self) -> Result<PointerEvent, PointerClientError> {
+        self.rx
+            .dequeue_async()
+            .await
+            .map_err(|_| PointerClientError::NoPointerService)
+    }
+}
@@ -0,0 +1,262 @@
+//! Pointer multiplexer service.
+//!
+//! This module contains the [`PointerMuxServer`] type, which implements both
+//! [`PointerService`] and a [`PointerMuxService`] defined in this module. It
+//! allows clients to subscribe to events from *any* pointing device (using
+//! its [`PointerService`] implementation), while pointer drivers use the
+//! [`PointerMuxService`] to publish events from their devices to the
+//! multiplexer, which broadcasts those events to all clients.
+use super::{PointerError, PointerEvent, PointerService, Subscribed};
+use crate::{
+    comms::{
+        kchannel::{KChannel, KProducer},
+        oneshot::Reusable,
+    },
+    mnemos_alloc::containers::FixedVec,
+    registry::{
+        self, known_uuids, listener, Envelope, KernelHandle, OneshotRequestError, RegisteredDriver,
+    },
+    Kernel,
+};
+use core::convert::Infallible;
+use futures::FutureExt;
+use serde::{Deserialize, Serialize};
+use tracing::Level;
+use uuid::Uuid;
+
+////////////////////////////////////////////////////////////////////////////////
+// Service Definition
+////////////////////////////////////////////////////////////////////////////////
+
+/// Service definition for the pointer multiplexer.
+pub struct PointerMuxService;
+
+impl RegisteredDriver for PointerMuxService {
+    type Request = Publish;
+    type Response = Response;
+    type Error = core::convert::Infallible;
+    type Hello = ();
+    type ConnectError = core::convert::Infallible;
+
+    const UUID: Uuid = known_uuids::kernel::POINTER_MUX;
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Message and Error Types
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Debug, Eq, PartialEq)]
+pub struct Publish(PointerEvent);
+
+pub struct Response {
+    _p: (),
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Client Definition
+////////////////////////////////////////////////////////////////////////////////
+
+/// A client for the [`PointerMuxService`].
+///
+/// This type is used by pointer drivers to broadcast events from their
+/// hardware to the [`PointerMuxService`]. It is obtained using
+/// [`PointerMuxClient::from_registry`].
+pub struct PointerMuxClient {
+    handle: KernelHandle<PointerMuxService>,
+    reply: Reusable<Envelope<Result<Response, Infallible>>>,
+}
+
+impl PointerMuxClient {
+    /// Obtain a `PointerMuxClient`
+    ///
+    /// If the [`PointerMuxService`] hasn't been registered yet, we will retry until it
+    /// has been registered.
+    pub async fn from_registry(
+        kernel: &'static Kernel,
+    ) -> Result<Self, registry::ConnectError<PointerMuxService>> {
+        let handle = kernel.registry().connect::<PointerMuxService>(()).await?;
+        Ok(Self {
+            handle,
+            reply: Reusable::new_async().await,
+        })
+    }
+
+    /// Obtain a `PointerMuxClient`
+    ///
+    /// Does NOT attempt to get a [`PointerMuxService`] handle more than once.
+    ///
+    /// Prefer [`PointerMuxClient::from_registry`] unless you will not be spawning one
+    /// around the same time as obtaining a client.
+    pub async fn from_registry_no_retry(
+        kernel: &'static Kernel,
+    ) -> Result<Self, registry::ConnectError<PointerMuxService>> {
+        let handle = kernel
+            .registry()
+            .try_connect::<PointerMuxService>(())
+            .await?;
+        Ok(Self {
+            handle,
+            reply: Reusable::new_async().await,
+        })
+    }
+
+    pub async fn publish(&mut self, event: PointerEvent) -> Result<(), OneshotRequestError> {
+        let _ = self
+            .handle
+            .request_oneshot(Publish(event), &self.reply)
+            .await?;
+        Ok(())
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Server Definition
+////////////////////////////////////////////////////////////////////////////////
+
+/// The pointer multiplexer.
+///
+/// This type implements both [`PointerMuxService`] *and* [`PointerService`].
+/// The [`PointerMuxService`] implementation is used by pointer drivers to
+/// publish their events to the multiplexer, while the [`PointerService`]
+/// implementation is used by tasks that consume pointer input to subscribe to
+/// pointer events.
+pub struct PointerMuxServer {
+    event_rx: listener::RequestStream<PointerMuxService>,
+    sub_rx: listener::RequestStream<PointerService>,
+    subscriptions: FixedVec<KProducer<PointerEvent>>,
+    settings: PointerMuxSettings,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PointerMuxSettings {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "PointerMuxSettings::default_max_subscriptions")]
+    pub max_subscriptions: usize,
+    #[serde(default = "PointerMuxSettings::default_buffer_capacity")]
+    pub buffer_capacity: usize,
+}
+
+#[derive(Debug)]
+pub enum RegistrationError {
+    RegisterMux(registry::RegistrationError),
+    RegisterPointer(registry::RegistrationError),
+}
+
+impl From<RegistrationError> for crate::Error {
+    fn from(error: RegistrationError) -> Self {
+        match error {
+            RegistrationError::RegisterMux(error) | RegistrationError::RegisterPointer(error) => {
+                error.into()
+            }
+        }
+    }
+}
+
+impl PointerMuxServer {
+    /// Register the `PointerMuxServer`.
+    #[tracing::instrument(
+        name = "PointerMuxServer::register",
+        level = Level::INFO,
+        skip(kernel, settings),
+        err(Debug),
+        ret(Debug),
+    )]
+    pub async fn register(
+        kernel: &'static Kernel,
+        settings: PointerMuxSettings,
+    ) -> Result<(), RegistrationError> {
+        tracing::info!(?settings, "Registering pointer mux");
+
+        let event_rx = kernel
+            .registry()
+            .bind_konly::<PointerMuxService>(settings.buffer_capacity)
+            .await
+            .map_err(RegistrationError::RegisterMux)?
+            .into_request_stream(settings.buffer_capacity)
+            .await;
+        let sub_rx = kernel
+            .registry()
+            .bind_konly::<PointerService>(8)
+            .await
+            .map_err(RegistrationError::RegisterPointer)?
+            .into_request_stream(8)
+            .await;
+
+        let subscriptions = FixedVec::new(settings.max_subscriptions).await;
+
+        kernel
+            .spawn(
+                Self {
+                    event_rx,
+                    sub_rx,
+                    subscriptions,
+                    settings,
+                }
+                .run(),
+            )
+            .await;
+
+        tracing::info!("PointerMuxServer registered!");
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "PointerMuxServer", level = Level::INFO, skip(self))]
+    pub async fn run(mut self) {
+        loop {
+            futures::select_biased! {
+                registry::Message { msg, reply } = self.sub_rx.next_request().fuse() => {
+                    let (tx, rx) = KChannel::new_async(self.settings.buffer_capacity).await.split();
+                    match self.subscriptions.try_push(tx) {
+                        Ok(()) => {
+                            if reply.reply_konly(msg.reply_with(Ok(Subscribed { rx }))).await.is_err() {
+                                // requester is gone, so remove its subscription
+                                tracing::warn!("Pointer subscription requester is gone!");
+                                self.subscriptions.pop();
+                            } else {
+                                tracing::info!("New pointer subscription");
+                            }
+                        },
+                        Err(_) => {
+                            let _ = reply.reply_konly(msg.reply_with(Err(PointerError::TooManySubscriptions))).await;
+                        }
+                    }
+                },
+                registry::Message { msg, reply } = self.event_rx.next_request().fuse() => {
+                    let Publish(event) = msg.body;
+                    tracing::trace!(?event, "publishing pointer event");
+
+                    for sub in self.subscriptions.as_slice_mut() {
+                        let _ = sub.enqueue_async(event).await;
+                    }
+
+                    let _ = reply.reply_konly(msg.reply_with(Ok(Response { _p: () }))).await;
+                },
+            }
+        }
+    }
+}
+
+impl PointerMuxSettings {
+    pub const DEFAULT_BUFFER_CAPACITY: usize = 32;
+    pub const DEFAULT_MAX_SUBSCRIPTIONS: usize = 8;
+
+    const fn default_buffer_capacity() -> usize {
+        Self::DEFAULT_BUFFER_CAPACITY
+    }
+    const fn default_max_subscriptions() -> usize {
+        Self::DEFAULT_MAX_SUBSCRIPTIONS
+    }
+}
+
+impl Default for PointerMuxSettings {
+    fn default() -> Self {
+        Self {
+            // Most platforms don't have a pointing device, so don't spend
+            // memory on the mux unless asked to.
+            enabled: false,
+            max_subscriptions: Self::DEFAULT_MAX_SUBSCRIPTIONS,
+            buffer_capacity: Self::DEFAULT_BUFFER_CAPACITY,
+        }
+    }
+}
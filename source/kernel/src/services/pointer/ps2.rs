@@ -0,0 +1,172 @@
+//! Decoding for PS/2 mouse packets.
+//!
+//! This is independent of how the bytes are read from the mouse, so that it
+//! can be shared by drivers for any PS/2 controller.
+use super::{Buttons, PointerEvent};
+
+/// Assembles bytes from a PS/2 mouse into [`PointerEvent`]s.
+///
+/// Standard PS/2 mice send 3-byte packets. Mice with the IntelliMouse scroll
+/// wheel extension enabled send a fourth byte, with the wheel's motion.
+#[derive(Debug)]
+pub struct Decoder {
+    packet: [u8; 4],
+    len: usize,
+    packet_len: usize,
+}
+
+impl Decoder {
+    // First packet byte bits.
+    const ALWAYS_ONE: u8 = 1 << 3;
+    const X_SIGN: u8 = 1 << 4;
+    const Y_SIGN: u8 = 1 << 5;
+    const X_OVERFLOW: u8 = 1 << 6;
+    const Y_OVERFLOW: u8 = 1 << 7;
+
+    /// Returns a new decoder, for a mouse which sends 4-byte packets if
+    /// `has_wheel` is set, or 3-byte packets otherwise.
+    #[must_use]
+    pub const fn new(has_wheel: bool) -> Self {
+        Self {
+            packet: [0; 4],
+            len: 0,
+            packet_len: if has_wheel { 4 } else { 3 },
+        }
+    }
+
+    #[must_use]
+    pub const fn has_wheel(&self) -> bool {
+        self.packet_len == 4
+    }
+
+    /// Adds a byte to the current packet, returning an event if the packet
+    /// is complete.
+    pub fn push(&mut self, byte: u8) -> Option<PointerEvent> {
+        // the first byte of every packet has bit 3 set. if it doesn't, we
+        // missed a byte somewhere, so skip ahead until we're back in sync.
+        if self.len == 0 && byte & Self::ALWAYS_ONE == 0 {
+            tracing::trace!(byte, "PS/2 mouse out of sync");
+            return None;
+        }
+
+        self.packet[self.len] = byte;
+        self.len += 1;
+        if self.len < self.packet_len {
+            return None;
+        }
+        self.len = 0;
+
+        let [flags, x, y, z] = self.packet;
+        let axis = |value: u8, sign: u8, overflow: u8| -> i16 {
+            if flags & overflow != 0 {
+                // the motion is meaningless, so ignore it.
+                return 0;
+            }
+            // each axis is a 9-bit two's complement value, with the sign bit
+            // in the flags byte.
+            if flags & sign != 0 {
+                value as i16 - 0x100
+            } else {
+                value as i16
+            }
+        };
+
+        Some(PointerEvent {
+            dx: axis(x, Self::X_SIGN, Self::X_OVERFLOW),
+            // PS/2 mice report upwards motion as positive, but pointer
+            // events use display coordinates.
+            dy: -axis(y, Self::Y_SIGN, Self::Y_OVERFLOW),
+            wheel: if self.has_wheel() { z as i8 } else { 0 },
+            buttons: Buttons::from_bits(flags),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(decoder: &mut Decoder, bytes: &[u8]) -> Vec<PointerEvent> {
+        bytes.iter().filter_map(|&b| decoder.push(b)).collect()
+    }
+
+    #[test]
+    fn three_byte_packets() {
+        let mut decoder = Decoder::new(false);
+        assert!(!decoder.has_wheel());
+
+        // left button, moving right and up.
+        let events = decode(&mut decoder, &[0b0000_1001, 5, 3]);
+        assert_eq!(
+            events,
+            [PointerEvent {
+                dx: 5,
+                dy: -3,
+                wheel: 0,
+                buttons: Buttons::LEFT,
+            }]
+        );
+
+        // right and middle buttons, moving left and down.
+        let events = decode(&mut decoder, &[0b0011_1110, 0xFB, 0xFE]);
+        assert_eq!(
+            events,
+            [PointerEvent {
+                dx: -5,
+                dy: 2,
+                wheel: 0,
+                buttons: Buttons::RIGHT | Buttons::MIDDLE,
+            }]
+        );
+    }
+
+    #[test]
+    fn wheel() {
+        let mut decoder = Decoder::new(true);
+        assert!(decoder.has_wheel());
+
+        // no event until the fourth byte arrives.
+        assert!(decode(&mut decoder, &[0b0000_1000, 1, 1]).is_empty());
+        let events = decode(&mut decoder, &[0xFF]);
+        assert_eq!(
+            events,
+            [PointerEvent {
+                dx: 1,
+                dy: -1,
+                wheel: -1,
+                buttons: Buttons::NONE,
+            }]
+        );
+    }
+
+    #[test]
+    fn overflow() {
+        let mut decoder = Decoder::new(false);
+        let events = decode(&mut decoder, &[0b0101_1000, 0x80, 7]);
+        assert_eq!(
+            events,
+            [PointerEvent {
+                dx: 0,
+                dy: -7,
+                wheel: 0,
+                buttons: Buttons::NONE,
+            }]
+        );
+    }
+
+    #[test]
+    fn resync() {
+        let mut decoder = Decoder::new(false);
+        // bytes without bit 3 set can't start a packet, so they're skipped.
+        let events = decode(&mut decoder, &[0x01, 0x02, 0b0000_1000, 4, 0]);
+        assert_eq!(
+            events,
+            [PointerEvent {
+                dx: 4,
+                dy: 0,
+                wheel: 0,
+                buttons: Buttons::NONE,
+            }]
+        );
+    }
+}
@@ -0,0 +1,581 @@
+//! # Serial Console
+//!
+//! A raw, line-oriented console on the [`SimpleSerialService`]'s serial port,
+//! for when the [serial mux](crate::services::serial_mux) is disabled.
+//! Without the serial mux, nothing else can share the serial port, so the
+//! console gives exclusive access to it to one task at a time: the task which
+//! [opens](SerialConsoleClient::open) the console, such as the
+//! [console shell](crate::daemons::shells::console_shell).
+//!
+//! The console is meant to be used from a terminal emulator. Input is
+//! buffered and echoed by the console, and is only passed on to the task
+//! holding the console a whole line at a time, once enter is pressed.
+//! Backspace erases the last character of the line being typed. Output is
+//! written to the serial port as it is, except that each `\n` is written as
+//! `\r\n`.
+//!
+//! Any task may also [write a line](SerialConsoleClient::write_line) to the
+//! console, such as an early boot log or an error report, whether or not the
+//! console is open. Lines are only written between lines of the holder's
+//! output, rather than in the middle of them.
+//!
+//! [`Kernel::initialize_default_services`] registers the console in place of
+//! the serial mux, if [`SerialMuxSettings::enabled`] is `false`.
+//!
+//! [`SerialMuxSettings::enabled`]: crate::services::serial_mux::SerialMuxSettings::enabled
+
+use core::convert::Infallible;
+
+use futures::{future, FutureExt};
+use serde::{Deserialize, Serialize};
+use tracing::{Instrument, Level};
+use uuid::Uuid;
+
+use crate::{
+    comms::{
+        bbq::{self, BidiHandle, GrantR},
+        oneshot::Reusable,
+    },
+    registry::{self, known_uuids, Envelope, KernelHandle, Message, RegisteredDriver},
+    services::simple_serial::{SimpleSerialClient, SimpleSerialService},
+    Kernel,
+};
+
+/// The longest line, in bytes, which can be typed on the console or written
+/// with [`SerialConsoleClient::write_line`]. Longer lines are truncated.
+pub const MAX_LINE: usize = 128;
+
+/// A line written with [`SerialConsoleClient::write_line`].
+pub type Line = heapless::String<MAX_LINE>;
+
+////////////////////////////////////////////////////////////////////////////////
+// Service Definition
+////////////////////////////////////////////////////////////////////////////////
+
+/// Service definition for the serial console.
+pub struct SerialConsoleService;
+
+impl RegisteredDriver for SerialConsoleService {
+    type Request = Request;
+    type Response = Response;
+    type Error = SerialConsoleError;
+    type Hello = ();
+    type ConnectError = Infallible;
+    const UUID: Uuid = known_uuids::kernel::SERIAL_CONSOLE;
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Message and Error Types
+////////////////////////////////////////////////////////////////////////////////
+
+pub enum Request {
+    /// Open the console, for exclusive use until the returned handle is
+    /// dropped.
+    Open,
+    /// Write a line to the console, without opening it.
+    WriteLine(Line),
+}
+
+pub enum Response {
+    Opened { handle: BidiHandle },
+    LineWritten,
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum SerialConsoleError {
+    /// The console is already open.
+    AlreadyOpen,
+    /// An error occurred sending the request to the service.
+    Request(registry::OneshotRequestError),
+}
+
+/// Returns at most the first [`MAX_LINE`] bytes of `text`, on a character
+/// boundary.
+#[must_use]
+pub fn line(text: &str) -> Line {
+    let mut end = text.len().min(MAX_LINE);
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    let mut truncated = Line::new();
+    // `end` is at most `MAX_LINE`, so this always fits.
+    let _ = truncated.push_str(&text[..end]);
+    truncated
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Client Definition
+////////////////////////////////////////////////////////////////////////////////
+
+/// A client for the [`SerialConsoleService`].
+pub struct SerialConsoleClient {
+    handle: KernelHandle<SerialConsoleService>,
+    reply: Reusable<Envelope<Result<Response, SerialConsoleError>>>,
+}
+
+impl SerialConsoleClient {
+    /// Obtain a `SerialConsoleClient`
+    ///
+    /// If the [`SerialConsoleService`] hasn't been registered yet, we will
+    /// retry until it has been registered.
+    pub async fn from_registry(
+        kernel: &'static Kernel,
+    ) -> Result<Self, registry::ConnectError<SerialConsoleService>> {
+        let handle = kernel
+            .registry()
+            .connect::<SerialConsoleService>(())
+            .await?;
+        Ok(Self {
+            handle,
+            reply: Reusable::new_async().await,
+        })
+    }
+
+    /// Obtain a `SerialConsoleClient`
+    ///
+    /// Does NOT attempt to get a [`SerialConsoleService`] handle more than
+    /// once.
+    ///
+    /// Prefer [`SerialConsoleClient::from_registry`] unless you will not be
+    /// spawning one around the same time as obtaining a client.
+    pub async fn from_registry_no_retry(
+        kernel: &'static Kernel,
+    ) -> Result<Self, registry::ConnectError<SerialConsoleService>> {
+        let handle = kernel
+            .registry()
+            .try_connect::<SerialConsoleService>(())
+            .await?;
+        Ok(Self {
+            handle,
+            reply: Reusable::new_async().await,
+        })
+    }
+
+    /// Opens the console, returning a handle which receives each line typed
+    /// on the console, ending in `\n`, and sends its output to the console.
+    ///
+    /// The console stays open until the handle is dropped. Returns
+    /// [`SerialConsoleError::AlreadyOpen`] if another task has it open.
+    pub async fn open(&mut self) -> Result<BidiHandle, SerialConsoleError> {
+        match self.request(Request::Open).await? {
+            Response::Opened { handle } => Ok(handle),
+            Response::LineWritten => {
+                unreachable!("the console replied to `Open` with `LineWritten`")
+            }
+        }
+    }
+
+    /// Writes `text` to the console as a line of its own.
+    ///
+    /// `text` is truncated to [`MAX_LINE`] bytes.
+    pub async fn write_line(&mut self, text: &str) -> Result<(), SerialConsoleError> {
+        self.request(Request::WriteLine(line(text))).await?;
+        Ok(())
+    }
+
+    async fn request(&mut self, req: Request) -> Result<Response, SerialConsoleError> {
+        self.handle
+            .request_oneshot(req, &self.reply)
+            .await
+            .map_err(SerialConsoleError::Request)?
+            .body
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Server Definition
+////////////////////////////////////////////////////////////////////////////////
+
+/// Server implementation for the [`SerialConsoleService`].
+pub struct SerialConsoleServer {
+    serial: BidiHandle,
+    /// The server's end of the handle given to the task with the console
+    /// open, if it is open.
+    holder: Option<BidiHandle>,
+    /// The line being typed.
+    line: heapless::Vec<u8, MAX_LINE>,
+    /// Whether the last byte received was a `\r`, so that a `\n` following
+    /// it doesn't end another line.
+    last_cr: bool,
+    /// Whether the last byte written to the serial port ended a line.
+    at_line_start: bool,
+    settings: SerialConsoleSettings,
+}
+
+/// Settings for the [`SerialConsoleServer`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SerialConsoleSettings {
+    /// Should the serial console be registered, when the serial mux is
+    /// disabled?
+    #[serde(default = "SerialConsoleSettings::default_enabled")]
+    pub enabled: bool,
+    /// Maximum number of outstanding requests from clients.
+    #[serde(default = "SerialConsoleSettings::default_capacity")]
+    pub capacity: usize,
+    /// The size of the buffers between the console and the task holding it,
+    /// in each direction. This must be larger than [`MAX_LINE`].
+    #[serde(default = "SerialConsoleSettings::default_buffer_size")]
+    pub buffer_size: usize,
+    /// Echo input back to the terminal, as it is typed.
+    #[serde(default = "SerialConsoleSettings::default_echo")]
+    pub echo: bool,
+    /// Serve a Forth shell on the console, with the
+    /// [console shell](crate::daemons::shells::console_shell) daemon.
+    #[serde(default = "SerialConsoleSettings::default_shell")]
+    pub shell: bool,
+}
+
+/// Errors returned by [`SerialConsoleServer::register`].
+#[derive(Debug)]
+pub enum RegistrationError {
+    /// An error occurred connecting to the [`SimpleSerialService`].
+    Connect(registry::ConnectError<SimpleSerialService>),
+    NoSerialPortAvailable,
+    Registry(registry::RegistrationError),
+}
+
+impl From<RegistrationError> for crate::Error {
+    fn from(error: RegistrationError) -> Self {
+        let reason = match error {
+            RegistrationError::Connect(_) => "could not connect to the serial port",
+            RegistrationError::NoSerialPortAvailable => "no serial port is available",
+            RegistrationError::Registry(error) => return error.into(),
+        };
+        Self::ServiceInit {
+            uuid: SerialConsoleService::UUID,
+            reason,
+        }
+    }
+}
+
+/// What the server was woken by.
+enum Event {
+    Request(Message<SerialConsoleService>),
+    Input(GrantR),
+    Output(GrantR),
+    Closed,
+}
+
+impl SerialConsoleServer {
+    /// Register the `SerialConsoleServer`, using a [`SimpleSerialClient`] to
+    /// access the serial port.
+    ///
+    /// Will retry to obtain a [`SimpleSerialClient`] until success.
+    #[tracing::instrument(
+        name = "SerialConsoleServer::register",
+        level = Level::INFO,
+        skip(kernel, settings),
+        err(Debug),
+    )]
+    pub async fn register(
+        kernel: &'static Kernel,
+        settings: SerialConsoleSettings,
+    ) -> Result<(), RegistrationError> {
+        tracing::info!(?settings, "Starting SerialConsoleServer");
+        let serial = SimpleSerialClient::from_registry(kernel)
+            .await
+            .map_err(RegistrationError::Connect)?
+            .get_port()
+            .await
+            .ok_or(RegistrationError::NoSerialPortAvailable)?;
+        let reqs = kernel
+            .registry()
+            .bind_konly::<SerialConsoleService>(settings.capacity)
+            .await
+            .map_err(RegistrationError::Registry)?
+            .into_request_stream(settings.capacity)
+            .await;
+
+        let mut server = Self {
+            serial,
+            holder: None,
+            line: heapless::Vec::new(),
+            last_cr: false,
+            at_line_start: true,
+            settings,
+        };
+        kernel
+            .spawn(async move {
+                loop {
+                    let event = futures::select_biased! {
+                        msg = reqs.next_request().fuse() => Event::Request(msg),
+                        // flush the holder's output before noticing that it
+                        // closed the console.
+                        output = read(&server.holder).fuse() => Event::Output(output),
+                        _ = closed(&server.holder).fuse() => Event::Closed,
+                        input = server.serial.consumer().read_grant().fuse() => Event::Input(input),
+                    };
+                    match event {
+                        Event::Request(msg) => {
+                            let span = msg.msg.span();
+                            server.request(msg).instrument(span).await;
+                        }
+                        Event::Input(input) => {
+                            let len = input.len();
+                            server.input(&input).await;
+                            input.release(len);
+                        }
+                        Event::Output(output) => {
+                            let len = output.len();
+                            server.write(&output).await;
+                            output.release(len);
+                        }
+                        Event::Closed => {
+                            tracing::info!("Serial console closed");
+                            server.holder = None;
+                            server.line.clear();
+                        }
+                    }
+                }
+            })
+            .await;
+
+        Ok(())
+    }
+
+    async fn request(&mut self, msg: Message<SerialConsoleService>) {
+        let (req, env, reply) = msg.split();
+        let resp = match req {
+            Request::Open if self.holder.as_ref().is_some_and(|h| !h.is_closed()) => {
+                Err(SerialConsoleError::AlreadyOpen)
+            }
+            Request::Open => {
+                let (ours, theirs) =
+                    bbq::new_bidi_channel(self.settings.buffer_size, self.settings.buffer_size)
+                        .await;
+                self.holder = Some(ours);
+                self.line.clear();
+                tracing::info!("Serial console opened");
+                Ok(Response::Opened { handle: theirs })
+            }
+            Request::WriteLine(text) => {
+                if !self.at_line_start {
+                    self.write(b"\n").await;
+                }
+                self.write(text.as_bytes()).await;
+                self.write(b"\n").await;
+                // put back the line that was being typed, if it was erased.
+                if self.echoing() && !self.line.is_empty() {
+                    let line = self.line.clone();
+                    self.write_raw(&line).await;
+                }
+                Ok(Response::LineWritten)
+            }
+        };
+        if let Err(error) = reply.reply_konly(env.fill(resp)).await {
+            tracing::warn!(?error, "Failed to reply to serial console request");
+        }
+    }
+
+    /// Handles bytes typed on the console.
+    async fn input(&mut self, input: &[u8]) {
+        // input is discarded while the console isn't open.
+        if self.holder.is_none() {
+            return;
+        }
+        for &byte in input {
+            let last_cr = core::mem::replace(&mut self.last_cr, byte == b'\r');
+            match byte {
+                b'\n' if last_cr => {}
+                b'\r' | b'\n' => {
+                    if self.echoing() {
+                        self.write(b"\n").await;
+                    }
+                    let _ = self.line.push(b'\n');
+                    if let Some(ref holder) = self.holder {
+                        holder.producer().write_all(&self.line).await;
+                    }
+                    self.line.clear();
+                }
+                // backspace and delete
+                0x08 | 0x7F => {
+                    if self.line.pop().is_some() && self.echoing() {
+                        self.write_raw(b"\x08 \x08").await;
+                    }
+                }
+                // leave room for the newline.
+                byte if !byte.is_ascii_control() || byte == b'\t' => {
+                    if self.line.len() + 1 < MAX_LINE
+                        && self.line.push(byte).is_ok()
+                        && self.echoing()
+                    {
+                        self.write_raw(&[byte]).await;
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn echoing(&self) -> bool {
+        self.settings.echo && self.holder.is_some()
+    }
+
+    /// Writes `data` to the serial port, as `\r\n` for each `\n`.
+    async fn write(&mut self, data: &[u8]) {
+        for (i, part) in data.split(|&b| b == b'\n').enumerate() {
+            if i > 0 {
+                self.write_raw(b"\r\n").await;
+            }
+            self.write_raw(part).await;
+        }
+    }
+
+    async fn write_raw(&mut self, data: &[u8]) {
+        let Some(&last) = data.last() else {
+            return;
+        };
+        self.at_line_start = last == b'\n';
+        let producer = self.serial.producer();
+        // Writes of up to half the channel's capacity always fit once it has
+        // drained, wherever the write position is.
+        let max = (producer.depth().capacity / 2).max(1);
+        for part in data.chunks(max) {
+            producer.write_all(part).await;
+        }
+    }
+}
+
+/// Waits for output from the task holding the console, or forever if it
+/// isn't open.
+async fn read(holder: &Option<BidiHandle>) -> GrantR {
+    match holder {
+        Some(holder) => holder.consumer().read_grant().await,
+        None => future::pending().await,
+    }
+}
+
+/// Waits for the task holding the console to drop its handle, or forever if
+/// it isn't open.
+async fn closed(holder: &Option<BidiHandle>) {
+    match holder {
+        Some(holder) => holder.closed().await,
+        None => future::pending().await,
+    }
+}
+
+impl SerialConsoleSettings {
+    pub const DEFAULT_ENABLED: bool = true;
+    pub const DEFAULT_CAPACITY: usize = 4;
+    pub const DEFAULT_BUFFER_SIZE: usize = 256;
+    pub const DEFAULT_ECHO: bool = true;
+    pub const DEFAULT_SHELL: bool = true;
+
+    const fn default_enabled() -> bool {
+        Self::DEFAULT_ENABLED
+    }
+
+    const fn default_capacity() -> usize {
+        Self::DEFAULT_CAPACITY
+    }
+
+    const fn default_buffer_size() -> usize {
+        Self::DEFAULT_BUFFER_SIZE
+    }
+
+    const fn default_echo() -> bool {
+        Self::DEFAULT_ECHO
+    }
+
+    const fn default_shell() -> bool {
+        Self::DEFAULT_SHELL
+    }
+}
+
+impl Default for SerialConsoleSettings {
+    fn default() -> Self {
+        Self {
+            enabled: Self::DEFAULT_ENABLED,
+            capacity: Self::DEFAULT_CAPACITY,
+            buffer_size: Self::DEFAULT_BUFFER_SIZE,
+            echo: Self::DEFAULT_ECHO,
+            shell: Self::DEFAULT_SHELL,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{services::simple_serial, test_util::TestKernel};
+
+    /// Registers a serial port and the console, returning the host's end of
+    /// the serial port.
+    async fn setup(k: &'static Kernel) -> BidiHandle {
+        let (port, host) = bbq::new_bidi_channel(256, 256).await;
+        let reqs = k
+            .registry()
+            .bind_konly::<SimpleSerialService>(1)
+            .await
+            .unwrap()
+            .into_request_stream(1)
+            .await;
+        k.spawn(async move {
+            let req = reqs.next_request().await;
+            let resp = req
+                .msg
+                .reply_with(Ok(simple_serial::Response::PortHandle { handle: port }));
+            req.reply.reply_konly(resp).await.map_err(drop).unwrap();
+        })
+        .await;
+        SerialConsoleServer::register(k, SerialConsoleSettings::default())
+            .await
+            .unwrap();
+        host
+    }
+
+    async fn expect(host: &BidiHandle, expected: &[u8]) {
+        let mut buf = vec![0; expected.len()];
+        host.consumer().read_exact(&mut buf).await;
+        assert_eq!(
+            buf,
+            expected,
+            "{:?}",
+            core::str::from_utf8(&buf).unwrap_or("<not UTF-8>")
+        );
+    }
+
+    #[test]
+    fn edits_and_echoes_lines() {
+        TestKernel::run(|k| async move {
+            let host = setup(k).await;
+            let mut client = SerialConsoleClient::from_registry(k).await.unwrap();
+            let console = client.open().await.unwrap();
+            assert_eq!(
+                client.open().await.err(),
+                Some(SerialConsoleError::AlreadyOpen)
+            );
+
+            host.producer().write_all(b"1 x\x7f2 +\r\n").await;
+            let mut line = [0; 6];
+            console.consumer().read_exact(&mut line).await;
+            assert_eq!(&line, b"1 2 +\n");
+            expect(&host, b"1 x\x08 \x082 +\r\n").await;
+
+            console.producer().write_all(b"3 ok.\n> ").await;
+            expect(&host, b"3 ok.\r\n> ").await;
+        })
+    }
+
+    #[test]
+    fn writes_lines_between_output() {
+        TestKernel::run(|k| async move {
+            let host = setup(k).await;
+            let mut client = SerialConsoleClient::from_registry(k).await.unwrap();
+            client.write_line("booting").await.unwrap();
+            expect(&host, b"booting\r\n").await;
+
+            // the line being typed is written again after the line.
+            let console = client.open().await.unwrap();
+            console.producer().write_all(b"> ").await;
+            host.producer().write_all(b"ab").await;
+            expect(&host, b"> ab").await;
+            client.write_line("low battery").await.unwrap();
+            expect(&host, b"\r\nlow battery\r\nab").await;
+
+            // once the console is closed, it can be opened again.
+            drop(console);
+            client.open().await.unwrap();
+        })
+    }
+}
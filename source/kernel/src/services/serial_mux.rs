@@ -5,22 +5,83 @@
 //! This module includes the service definition, client definition, as well
 //! as a server definition that relies on the [`SimpleSerial`][crate::services::simple_serial]
 //! service to provide the service implementation.
+//!
+//! ## Frame Versions
+//!
+//! The server decodes frames in either version of the [`sermux_proto`] frame
+//! encoding. Outgoing frames are encoded as version 1 frames, until the host
+//! advertises that it can decode version 2 frames, by sending a
+//! [`VersionAdvert`] on the [`WellKnown::PortDirectory`] port. The server
+//! sends its own advert when it starts, and in reply to the host's advert.
+//!
+//! Version 2 allows the host to send *jumbo messages*, which are larger than
+//! the server's [`max_frame`](SerialMuxSettings::max_frame). Jumbo messages
+//! sent to [framed ports](SerialMuxClient::open_framed_port) are reassembled,
+//! and delivered as a single frame. Other ports receive each part of a jumbo
+//! message as it arrives, just like any other data.
+//!
+//! ## Secure Ports
+//!
+//! With the `secure-sermux` feature, and a
+//! [pre-shared key](SerialMuxSettings::psk), ports may be opened with
+//! [`SerialMuxClient::open_secure_port`]. Data on a secure port is encrypted
+//! and authenticated, using keys negotiated with the host by exchanging
+//! [`SecureHello`](sermux_proto::SecureHello)s on the
+//! [`WellKnown::PortDirectory`] port; see [`sermux_proto::secure`] for the
+//! details. The host always starts the handshake, and the server answers
+//! using an ephemeral key from the
+//! [`EntropyService`](crate::services::entropy::EntropyService). If no
+//! entropy service is registered, handshakes are refused.
+//!
+//! Until a handshake completes, data sent by the host to a secure port is
+//! discarded, and so is data sent by the port's [`PortHandle`]. This is what
+//! makes it reasonable to serve a Forth shell over a network link.
+//!
+//! ## Port Resets
+//!
+//! The host may reset a port, to recover it without rebooting the target, by
+//! sending a [`PortReset`] on the [`WellKnown::Control`] port. Only ports whose
+//! owners wait for resets, using [`PortHandle::reset`], can be reset; resets of
+//! other ports are refused.
+//!
+//! Once a reset has been requested, data sent by the host to the port is
+//! discarded until the port's owner handles the reset. The owner discards any
+//! data that it hasn't read yet, returns to its initial state, and then lets
+//! the host know that the port has been reset.
 use crate::comms::bbq::GrantR;
 use crate::{
-    comms::{bbq, oneshot::Reusable},
-    registry::{self, Envelope, KernelHandle, Message, RegisteredDriver},
+    comms::{
+        bbq,
+        kchannel::{KChannel, KConsumer, KProducer},
+        oneshot::Reusable,
+    },
+    registry::{self, Capabilities, Envelope, KernelHandle, Message, RegisteredDriver},
     services::simple_serial::{SimpleSerialClient, SimpleSerialService},
     Kernel,
 };
-use maitake::sync::Mutex;
+use futures::FutureExt;
+use maitake::sync::{Mutex, WaitCell};
+#[cfg(feature = "secure-sermux")]
+use mnemos_alloc::containers::HeapArray;
 use mnemos_alloc::containers::{Arc, FixedVec};
+use portable_atomic::{AtomicBool, AtomicU8, Ordering};
 use serde::{Deserialize, Serialize};
-use sermux_proto::PortChunk;
+use sermux_proto::{
+    DirectoryEntry, Fragment, Frame, FrameVersion, PortReset, VersionAdvert, RESERVED_PORT,
+};
 use tracing::{self, debug, warn, Level};
 use uuid::Uuid;
 
+#[cfg(feature = "secure-sermux")]
+use crate::services::entropy::EntropyClient;
+#[cfg(feature = "secure-sermux")]
+use sermux_proto::{
+    secure::{Handshake, Opener, Role, Sealer, OVERHEAD},
+    SecureHello,
+};
+
 // Well known ports live in the sermux_proto crate
-pub use sermux_proto::WellKnown;
+pub use sermux_proto::{PortChunk, WellKnown, DYNAMIC_PORTS};
 
 ////////////////////////////////////////////////////////////////////////////////
 // Service Definition
@@ -41,33 +102,120 @@ impl RegisteredDriver for SerialMuxService {
     const UUID: Uuid = crate::registry::known_uuids::kernel::SERIAL_MUX;
 }
 
+/// Optional [capabilities](registry::capabilities) advertised by
+/// [`SerialMuxService`] servers.
+pub mod capabilities {
+    use crate::registry::Capabilities;
+
+    /// The server supports [`Request::RegisterFramedPort`](super::Request::RegisterFramedPort).
+    pub const FRAMED_PORTS: Capabilities = Capabilities::bit(0);
+    /// The server supports [`Request::OpenDynamic`](super::Request::OpenDynamic).
+    pub const DYNAMIC_PORTS: Capabilities = Capabilities::bit(1);
+    /// The server supports [`Request::RegisterSecurePort`](super::Request::RegisterSecurePort).
+    #[cfg(feature = "secure-sermux")]
+    pub const SECURE_PORTS: Capabilities = Capabilities::bit(2);
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 // Message and Error Types
 ////////////////////////////////////////////////////////////////////////////////
 
 pub enum Request {
-    RegisterPort { port_id: u16, capacity: usize },
+    RegisterPort {
+        port_id: u16,
+        capacity: usize,
+    },
+    /// Open a port in framed mode. See [`SerialMuxClient::open_framed_port`].
+    RegisterFramedPort {
+        port_id: u16,
+        capacity: usize,
+    },
+    /// Open a port with any free port number in [`DYNAMIC_PORTS`].
+    OpenDynamic {
+        capacity: usize,
+    },
+    /// Open a secure port. See [`SerialMuxClient::open_secure_port`].
+    #[cfg(feature = "secure-sermux")]
+    RegisterSecurePort {
+        port_id: u16,
+        capacity: usize,
+    },
+    /// Report how full every open port's incoming buffer is, and how full the
+    /// buffer of data waiting to be sent to the host is.
+    PortDepths,
 }
 
 pub enum Response {
     PortRegistered(PortHandle),
+    FramedPortRegistered(FramedPortHandle),
+    PortDepths(PortDepths),
+}
+
+/// How full the serial mux's buffers are, returned by
+/// [`SerialMuxClient::port_depths`].
+pub struct PortDepths {
+    /// Data waiting to be sent to the host, from every port.
+    pub outgoing: bbq::BufferDepth,
+    /// Data received from the host, which each port's [`PortHandle`] has not
+    /// yet read.
+    pub ports: FixedVec<PortDepth>,
+}
+
+/// How full a single port's incoming buffer is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PortDepth {
+    pub port: u16,
+    pub incoming: bbq::BufferDepth,
 }
 
 #[derive(Debug, Eq, PartialEq)]
 pub enum SerialMuxError {
     DuplicateItem,
     RegistryFull,
+    /// The requested port is in the [`DYNAMIC_PORTS`] range, and must be
+    /// opened with [`SerialMuxClient::open_dynamic`], or is the
+    /// [`RESERVED_PORT`], which may never be opened.
+    ReservedPort,
+    /// Every port in the [`DYNAMIC_PORTS`] range is in use.
+    NoDynamicPorts,
+    /// A secure port was requested, but the server has no
+    /// [pre-shared key](SerialMuxSettings::psk).
+    #[cfg(feature = "secure-sermux")]
+    NoPsk,
 }
 
 /// A `PortHandle` is the interface received after opening a virtual serial port
 /// using a [`SerialMuxClient`].
-pub struct PortHandle {
+///
+/// If the port was opened with [`SerialMuxClient::open_dynamic`], the port is
+/// closed, and its port number freed, when the `PortHandle` is dropped.
+///
+/// By default, data received on the port is read from a byte stream
+/// ([`bbq::Consumer`]). Ports opened in framed mode are instead read from a
+/// [`bbq::FramedConsumer`]; see [`FramedPortHandle`].
+pub struct PortHandle<C = bbq::Consumer> {
     port: u16,
-    cons: bbq::Consumer,
+    cons: C,
     outgoing: bbq::MpscProducer,
     max_frame: usize,
+    encoding: Arc<Encoding>,
+    /// Used to free a dynamically allocated port when the handle is dropped.
+    release: Option<KProducer<u16>>,
+    /// Shared with the mux, which signals resets requested by the host.
+    reset: Arc<Reset>,
+    /// Seals outgoing data, if this is a secure port.
+    #[cfg(feature = "secure-sermux")]
+    secure: Option<Arc<Mutex<SecureTx>>>,
 }
 
+/// A [`PortHandle`] opened in framed mode, using
+/// [`SerialMuxClient::open_framed_port`].
+///
+/// Each chunk of data received from the host is delivered as a single frame,
+/// so if the host sends one message per chunk, consumers can decode messages
+/// directly from each frame, without scanning for delimiters.
+pub type FramedPortHandle = PortHandle<bbq::FramedConsumer>;
+
 ////////////////////////////////////////////////////////////////////////////////
 // Client Definition
 ////////////////////////////////////////////////////////////////////////////////
@@ -116,16 +264,102 @@ impl SerialMuxClient {
         })
     }
 
+    /// Returns the [`capabilities`] advertised by the serial mux server.
+    #[must_use]
+    pub fn capabilities(&self) -> Capabilities {
+        self.prod.capabilities()
+    }
+
     pub async fn open_port(&mut self, port_id: u16, capacity: usize) -> Option<PortHandle> {
         let resp = self
             .prod
             .request_oneshot(Request::RegisterPort { port_id, capacity }, &self.reply)
             .await
             .ok()?;
-        let body = resp.body.ok()?;
+        match resp.body.ok()? {
+            Response::PortRegistered(port) => Some(port),
+            _ => None,
+        }
+    }
+
+    /// Open a port in framed mode.
+    ///
+    /// Unlike [`SerialMuxClient::open_port`], each chunk of data received on
+    /// the port is delivered to the returned [`FramedPortHandle`] as a single
+    /// frame. If there is not enough space for a whole chunk, it is
+    /// discarded, rather than partially delivered.
+    pub async fn open_framed_port(
+        &mut self,
+        port_id: u16,
+        capacity: usize,
+    ) -> Option<FramedPortHandle> {
+        let resp = self
+            .prod
+            .request_oneshot(
+                Request::RegisterFramedPort { port_id, capacity },
+                &self.reply,
+            )
+            .await
+            .ok()?;
+        match resp.body.ok()? {
+            Response::FramedPortRegistered(port) => Some(port),
+            _ => None,
+        }
+    }
 
-        let Response::PortRegistered(port) = body;
-        Some(port)
+    /// Open a port with a dynamically allocated port number.
+    ///
+    /// The port number is chosen from the [`DYNAMIC_PORTS`] range, and may be
+    /// read using [`PortHandle::port`]. The port is announced to the host on
+    /// the [`WellKnown::PortDirectory`] port, and remains open until the
+    /// returned [`PortHandle`] is dropped.
+    pub async fn open_dynamic(&mut self, capacity: usize) -> Option<PortHandle> {
+        let resp = self
+            .prod
+            .request_oneshot(Request::OpenDynamic { capacity }, &self.reply)
+            .await
+            .ok()?;
+        match resp.body.ok()? {
+            Response::PortRegistered(port) => Some(port),
+            _ => None,
+        }
+    }
+
+    /// Open a secure port.
+    ///
+    /// Data sent and received on the port is encrypted and authenticated
+    /// with keys negotiated with the host, using the server's
+    /// [pre-shared key](SerialMuxSettings::psk). Until the host completes a
+    /// handshake for the port, all data sent or received on it is discarded.
+    ///
+    /// Returns `None` if the server has no pre-shared key.
+    #[cfg(feature = "secure-sermux")]
+    pub async fn open_secure_port(&mut self, port_id: u16, capacity: usize) -> Option<PortHandle> {
+        let resp = self
+            .prod
+            .request_oneshot(
+                Request::RegisterSecurePort { port_id, capacity },
+                &self.reply,
+            )
+            .await
+            .ok()?;
+        match resp.body.ok()? {
+            Response::PortRegistered(port) => Some(port),
+            _ => None,
+        }
+    }
+
+    /// Returns how full the serial mux's buffers are.
+    pub async fn port_depths(&mut self) -> Option<PortDepths> {
+        let resp = self
+            .prod
+            .request_oneshot(Request::PortDepths, &self.reply)
+            .await
+            .ok()?;
+        match resp.body.ok()? {
+            Response::PortDepths(depths) => Some(depths),
+            _ => None,
+        }
     }
 }
 
@@ -142,27 +376,205 @@ impl PortHandle {
         client.open_port(port_id, capacity).await
     }
 
+    /// Helper method if you only need to open one dynamically allocated port.
+    ///
+    /// Same as calling [SerialMuxClient::from_registry()] then immediately calling
+    /// [SerialMuxClient::open_dynamic()].
+    pub async fn open_dynamic(kernel: &'static Kernel, capacity: usize) -> Option<Self> {
+        let mut client = SerialMuxClient::from_registry(kernel).await.ok()?;
+        client.open_dynamic(capacity).await
+    }
+
+    /// Helper method if you only need to open one secure port.
+    ///
+    /// Same as calling [SerialMuxClient::from_registry()] then immediately calling
+    /// [SerialMuxClient::open_secure_port()].
+    #[cfg(feature = "secure-sermux")]
+    pub async fn open_secure(
+        kernel: &'static Kernel,
+        port_id: u16,
+        capacity: usize,
+    ) -> Option<Self> {
+        let mut client = SerialMuxClient::from_registry(kernel).await.ok()?;
+        client.open_secure_port(port_id, capacity).await
+    }
+
+    /// Waits until the host resets this port.
+    ///
+    /// When this returns, any data received on the port which hadn't been
+    /// read yet has been discarded, and the host has been told that the port
+    /// was reset. The owner of the port should then return to its initial
+    /// state, for example by restarting the shell it serves on the port.
+    ///
+    /// Resets of a port are refused until its owner first waits for one. See
+    /// [the module-level documentation](self#port-resets) for details.
+    pub async fn reset(&self) {
+        self.reset_requested().await;
+        while let Some(rgr) = self.cons.read_grant_sync() {
+            let len = rgr.len();
+            rgr.release(len);
+        }
+        self.reset_done().await;
+    }
+}
+
+impl FramedPortHandle {
+    /// Waits until the host resets this port.
+    ///
+    /// This is the same as [`PortHandle::reset`], but discards unread frames.
+    pub async fn reset(&self) {
+        self.reset_requested().await;
+        while let Some(rgr) = self.cons.read_grant_sync() {
+            rgr.release();
+        }
+        self.reset_done().await;
+    }
+
+    /// Helper method if you only need to open one framed port.
+    ///
+    /// Same as calling [SerialMuxClient::from_registry()] then immediately calling
+    /// [SerialMuxClient::open_framed_port()].
+    pub async fn open_framed(
+        kernel: &'static Kernel,
+        port_id: u16,
+        capacity: usize,
+    ) -> Option<Self> {
+        let mut client = SerialMuxClient::from_registry(kernel).await.ok()?;
+        client.open_framed_port(port_id, capacity).await
+    }
+}
+
+impl<C> PortHandle<C> {
     pub fn port(&self) -> u16 {
         self.port
     }
 
-    pub fn consumer(&self) -> &bbq::Consumer {
+    pub fn consumer(&self) -> &C {
         &self.cons
     }
 
     pub async fn send(&self, data: &[u8]) {
+        #[cfg(feature = "secure-sermux")]
+        if let Some(ref secure) = self.secure {
+            return self.send_sealed(secure, data, false).await;
+        }
+
         // This is lazy, and could probably be done with bigger chunks.
         let msg_chunk = self.max_frame / 2;
+        let version = self.encoding.get();
 
         for chunk in data.chunks(msg_chunk) {
-            let pc = PortChunk::new(self.port, chunk);
-            let needed = pc.buffer_required();
-            let mut wgr = self.outgoing.send_grant_exact(needed).await;
-            let used = pc
-                .encode_to(&mut wgr)
-                .expect("sermux encoding should not fail")
-                .len();
-            wgr.commit(used);
+            send_frame(&self.outgoing, Frame::new(version, self.port, chunk)).await;
+        }
+    }
+
+    /// Sends `data` to the host as a single frame, rather than in chunks, as
+    /// [`PortHandle::send`] may.
+    ///
+    /// This is for messages which the host decodes one frame at a time, such
+    /// as [`Ping`](sermux_proto::Ping)s. Messages which don't fit in one
+    /// frame (longer than the server's
+    /// [`max_frame`](SerialMuxSettings::max_frame), less the sealing overhead
+    /// on a secure port) are discarded.
+    pub async fn send_frame(&self, data: &[u8]) {
+        #[cfg(feature = "secure-sermux")]
+        if let Some(ref secure) = self.secure {
+            return self.send_sealed(secure, data, true).await;
+        }
+
+        if data.len() > self.max_frame {
+            warn!(
+                port_id = self.port,
+                len = data.len(),
+                "Discarded bytes, too long for one frame"
+            );
+            return;
+        }
+        let version = self.encoding.get();
+        send_frame(&self.outgoing, Frame::new(version, self.port, data)).await;
+    }
+
+    /// Waits until the host has requested a reset of this port.
+    async fn reset_requested(&self) {
+        self.reset.handled.store(true, Ordering::Release);
+        loop {
+            let wait = self.reset.wait.subscribe().await;
+            if self.reset.pending.load(Ordering::Acquire) {
+                return;
+            }
+            // the cell is never closed.
+            let _ = wait.await;
+        }
+    }
+
+    /// Tells the host that a requested reset has been handled, and starts
+    /// accepting data on the port again.
+    async fn reset_done(&self) {
+        let reply = PortReset {
+            port: self.port,
+            reply: true,
+            ok: true,
+        }
+        .to_bytes();
+        send_frame(
+            &self.outgoing,
+            Frame::new(self.encoding.get(), WellKnown::Control, &reply),
+        )
+        .await;
+        // if this is cancelled before the reply is sent, the reset is still
+        // pending, and is handled again by the next call to `reset`.
+        self.reset.pending.store(false, Ordering::Release);
+        debug!(port_id = self.port, "Port reset");
+    }
+
+    /// Seals each chunk of `data` separately, and sends it as a whole frame,
+    /// so that the host can open each frame as it arrives.
+    ///
+    /// If `one_frame` is set, `data` is discarded unless it fits in a single
+    /// frame.
+    #[cfg(feature = "secure-sermux")]
+    async fn send_sealed(&self, secure: &Mutex<SecureTx>, data: &[u8], one_frame: bool) {
+        let mut secure = secure.lock().await;
+        let SecureTx { sealer, scratch } = &mut *secure;
+        let Some(sealer) = sealer.as_mut() else {
+            warn!(
+                port_id = self.port,
+                len = data.len(),
+                "Discarded bytes, no secure session"
+            );
+            return;
+        };
+        let msg_chunk = scratch.len() - OVERHEAD;
+        if one_frame && data.len() > msg_chunk {
+            warn!(
+                port_id = self.port,
+                len = data.len(),
+                "Discarded bytes, too long for one frame"
+            );
+            return;
+        }
+        let version = self.encoding.get();
+
+        for chunk in data.chunks(msg_chunk) {
+            match sealer.seal(chunk, scratch) {
+                Ok(sealed) => {
+                    send_frame(&self.outgoing, Frame::new(version, self.port, sealed)).await
+                }
+                Err(error) => {
+                    warn!(port_id = self.port, %error, "Discarded bytes, sealing failed");
+                    return;
+                }
+            }
+        }
+    }
+}
+
+impl<C> Drop for PortHandle<C> {
+    fn drop(&mut self) {
+        if let Some(release) = self.release.take() {
+            // The release channel has room for every port, so this only fails
+            // if the mux has gone away, in which case there's nothing to free.
+            let _ = release.enqueue_sync(self.port);
         }
     }
 }
@@ -182,6 +594,18 @@ pub struct SerialMuxSettings {
     pub max_ports: u16,
     #[serde(default = "SerialMuxSettings::default_max_frame")]
     pub max_frame: usize,
+    /// The largest jumbo message that will be reassembled for a framed port,
+    /// in bytes. Larger messages are discarded. Each framed port reassembles
+    /// its own jumbo message, so jumbo messages for different ports may be
+    /// interleaved.
+    #[serde(default = "SerialMuxSettings::default_max_jumbo")]
+    pub max_jumbo: usize,
+    /// The key shared with the host, used to authenticate it when it opens a
+    /// session on a [secure port](SerialMuxClient::open_secure_port). If this
+    /// is `None`, secure ports can't be opened.
+    #[cfg(feature = "secure-sermux")]
+    #[serde(default)]
+    pub psk: Option<[u8; 32]>,
 }
 
 impl SerialMuxServer {
@@ -241,8 +665,15 @@ impl SerialMuxServer {
         let SerialMuxSettings {
             max_ports,
             max_frame,
+            max_jumbo,
             ..
         } = settings;
+        #[allow(unused_mut)]
+        let mut caps = capabilities::FRAMED_PORTS | capabilities::DYNAMIC_PORTS;
+        #[cfg(feature = "secure-sermux")]
+        if settings.psk.is_some() {
+            caps |= capabilities::SECURE_PORTS;
+        }
         let max_ports = max_ports as usize;
         let serial_port = serial_handle
             .get_port()
@@ -252,7 +683,17 @@ impl SerialMuxServer {
         let sprod = sprod.into_mpmc_producer().await;
 
         let ports = FixedVec::new(max_ports).await;
-        let imutex = Arc::new(Mutex::new(MuxingInfo { ports, max_frame })).await;
+        let (release, released) = KChannel::new_async(max_ports).await.split();
+        let encoding = Arc::new(Encoding(AtomicU8::new(FrameVersion::V1 as u8))).await;
+        let imutex = Arc::new(Mutex::new(MuxingInfo {
+            ports,
+            max_frame,
+            encoding: encoding.clone(),
+            release,
+            #[cfg(feature = "secure-sermux")]
+            psk: settings.psk,
+        }))
+        .await;
 
         let listener = kernel
             .registry()
@@ -262,14 +703,30 @@ impl SerialMuxServer {
 
         let buf = FixedVec::new(max_frame).await;
         let commander = CommanderTask {
-            cmd: listener.into_request_stream(max_ports).await,
-            out: sprod,
+            cmd: listener
+                .into_request_stream(max_ports)
+                .await
+                .with_capabilities(caps),
+            out: sprod.clone(),
             mux: imutex.clone(),
+            encoding: encoding.clone(),
+            released,
         };
         let muxer = IncomingMuxerTask {
             incoming: scons,
-            mux: imutex,
             buf,
+            router: Router {
+                mux: imutex,
+                out: sprod,
+                encoding,
+                max_jumbo,
+                #[cfg(feature = "secure-sermux")]
+                secure: SecureRouter {
+                    kernel,
+                    entropy: None,
+                    scratch: FixedVec::new(max_frame).await,
+                },
+            },
         };
 
         kernel.spawn(commander.run()).await;
@@ -287,6 +744,7 @@ impl SerialMuxServer {
 impl SerialMuxSettings {
     pub const DEFAULT_MAX_PORTS: u16 = 16;
     pub const DEFAULT_MAX_FRAME: usize = 512;
+    pub const DEFAULT_MAX_JUMBO: usize = 8 * 1024;
 
     const fn default_max_ports() -> u16 {
         Self::DEFAULT_MAX_PORTS
@@ -294,6 +752,9 @@ impl SerialMuxSettings {
     const fn default_max_frame() -> usize {
         Self::DEFAULT_MAX_FRAME
     }
+    const fn default_max_jumbo() -> usize {
+        Self::DEFAULT_MAX_JUMBO
+    }
 
     pub fn with_max_ports(self, max_ports: u16) -> Self {
         Self { max_ports, ..self }
@@ -310,6 +771,9 @@ impl Default for SerialMuxSettings {
             enabled: true, // Should this default to false?
             max_ports: Self::DEFAULT_MAX_PORTS,
             max_frame: Self::DEFAULT_MAX_FRAME,
+            max_jumbo: Self::DEFAULT_MAX_JUMBO,
+            #[cfg(feature = "secure-sermux")]
+            psk: None,
         }
     }
 }
@@ -322,26 +786,156 @@ pub enum RegistrationError {
     MuxAlreadyRegistered,
 }
 
+impl From<RegistrationError> for crate::Error {
+    fn from(error: RegistrationError) -> Self {
+        let reason = match error {
+            RegistrationError::Connect(_) => "could not connect to the serial port",
+            RegistrationError::NoSerialPortAvailable => "no serial port is available",
+            RegistrationError::MuxAlreadyRegistered => "a serial mux is already registered",
+        };
+        Self::ServiceInit {
+            uuid: SerialMuxService::UUID,
+            reason,
+        }
+    }
+}
+
 struct PortInfo {
     port: u16,
-    upstream: bbq::SpscProducer,
+    upstream: Upstream,
+    reset: Arc<Reset>,
+    /// The jumbo message currently being reassembled for this port, if any.
+    jumbo: Option<Jumbo>,
+    #[cfg(feature = "secure-sermux")]
+    secure: Option<SecurePort>,
+}
+
+/// The state of a secure port's session, if one has been established.
+#[cfg(feature = "secure-sermux")]
+struct SecurePort {
+    /// Opens data received from the host.
+    opener: Option<Opener>,
+    /// Shared with the port's [`PortHandle`].
+    tx: Arc<Mutex<SecureTx>>,
+}
+
+/// Seals data sent to the host on a secure port.
+#[cfg(feature = "secure-sermux")]
+struct SecureTx {
+    sealer: Option<Sealer>,
+    /// Holds each sealed chunk before it is framed.
+    scratch: HeapArray<u8>,
+}
+
+/// The producer for data received on a port.
+enum Upstream {
+    Stream(bbq::SpscProducer),
+    Framed(bbq::FramedProducer),
+}
+
+/// The [`FrameVersion`] used to encode outgoing frames, as negotiated with the
+/// host.
+struct Encoding(AtomicU8);
+
+/// Signals resets requested by the host to a port's [`PortHandle`].
+struct Reset {
+    /// Set once the port's owner first waits for a reset.
+    handled: AtomicBool,
+    /// Set from when the host requests a reset until the owner has handled
+    /// it. Data sent to the port in the meantime is discarded.
+    pending: AtomicBool,
+    wait: WaitCell,
 }
 
 struct MuxingInfo {
     ports: FixedVec<PortInfo>,
     max_frame: usize,
+    encoding: Arc<Encoding>,
+    release: KProducer<u16>,
+    #[cfg(feature = "secure-sermux")]
+    psk: Option<[u8; 32]>,
 }
 
 struct CommanderTask {
     cmd: registry::listener::RequestStream<SerialMuxService>,
     out: bbq::MpscProducer,
     mux: Arc<Mutex<MuxingInfo>>,
+    encoding: Arc<Encoding>,
+    /// Dynamically allocated ports whose handles have been dropped.
+    released: KConsumer<u16>,
 }
 
 struct IncomingMuxerTask {
     buf: FixedVec<u8>,
     incoming: bbq::Consumer,
+    router: Router,
+}
+
+/// Routes decoded frames to the ports they were sent to.
+struct Router {
     mux: Arc<Mutex<MuxingInfo>>,
+    out: bbq::MpscProducer,
+    encoding: Arc<Encoding>,
+    max_jumbo: usize,
+    #[cfg(feature = "secure-sermux")]
+    secure: SecureRouter,
+}
+
+/// The parts of the [`Router`] which handle secure ports.
+#[cfg(feature = "secure-sermux")]
+struct SecureRouter {
+    kernel: &'static Kernel,
+    /// Connected when the first handshake is started.
+    entropy: Option<EntropyClient>,
+    /// Holds each sealed chunk while it is opened.
+    scratch: FixedVec<u8>,
+}
+
+/// A partially reassembled jumbo message for a framed port.
+struct Jumbo {
+    buf: FixedVec<u8>,
+}
+
+// impl Encoding
+
+impl Encoding {
+    fn get(&self) -> FrameVersion {
+        match self.0.load(Ordering::Acquire) {
+            2 => FrameVersion::V2,
+            _ => FrameVersion::V1,
+        }
+    }
+
+    fn set(&self, version: FrameVersion) {
+        self.0.store(version as u8, Ordering::Release);
+    }
+}
+
+// impl Reset
+
+impl Reset {
+    const fn new() -> Self {
+        Self {
+            handled: AtomicBool::new(false),
+            pending: AtomicBool::new(false),
+            wait: WaitCell::new(),
+        }
+    }
+
+    /// Requests a reset, returning `false` if the port's owner doesn't
+    /// handle resets.
+    fn request(&self) -> bool {
+        if !self.handled.load(Ordering::Acquire) {
+            return false;
+        }
+        self.pending.store(true, Ordering::Release);
+        self.wait.wake();
+        true
+    }
+
+    fn is_pending(&self) -> bool {
+        self.pending.load(Ordering::Acquire)
+    }
 }
 
 impl MuxingInfo {
@@ -351,18 +945,127 @@ impl MuxingInfo {
         capacity: usize,
         outgoing: &bbq::MpscProducer,
     ) -> Result<PortHandle, SerialMuxError> {
+        if DYNAMIC_PORTS.contains(&port_id) || port_id == RESERVED_PORT {
+            return Err(SerialMuxError::ReservedPort);
+        }
+        self.check_free(port_id)?;
+        let (prod, cons) = bbq::new_spsc_channel(capacity).await;
+        self.add_port(port_id, Upstream::Stream(prod), cons, outgoing, None)
+            .await
+    }
+
+    async fn register_framed_port(
+        &mut self,
+        port_id: u16,
+        capacity: usize,
+        outgoing: &bbq::MpscProducer,
+    ) -> Result<FramedPortHandle, SerialMuxError> {
+        if DYNAMIC_PORTS.contains(&port_id) || port_id == RESERVED_PORT {
+            return Err(SerialMuxError::ReservedPort);
+        }
+        self.check_free(port_id)?;
+        let (prod, cons) = bbq::new_framed_spsc_channel(capacity).await;
+        self.add_port(port_id, Upstream::Framed(prod), cons, outgoing, None)
+            .await
+    }
+
+    async fn open_dynamic(
+        &mut self,
+        capacity: usize,
+        outgoing: &bbq::MpscProducer,
+    ) -> Result<PortHandle, SerialMuxError> {
+        if self.ports.is_full() {
+            return Err(SerialMuxError::RegistryFull);
+        }
+        let port_id = DYNAMIC_PORTS
+            .clone()
+            .find(|&id| !self.ports.as_slice().iter().any(|p| p.port == id))
+            .ok_or(SerialMuxError::NoDynamicPorts)?;
+        let release = Some(self.release.clone());
+        let (prod, cons) = bbq::new_spsc_channel(capacity).await;
+        self.add_port(port_id, Upstream::Stream(prod), cons, outgoing, release)
+            .await
+    }
+
+    #[cfg(feature = "secure-sermux")]
+    async fn register_secure_port(
+        &mut self,
+        port_id: u16,
+        capacity: usize,
+        outgoing: &bbq::MpscProducer,
+    ) -> Result<PortHandle, SerialMuxError> {
+        if DYNAMIC_PORTS.contains(&port_id) || port_id == RESERVED_PORT {
+            return Err(SerialMuxError::ReservedPort);
+        }
+        if self.psk.is_none() {
+            return Err(SerialMuxError::NoPsk);
+        }
+        self.check_free(port_id)?;
+        // sealed chunks must fit in half a frame, like any other chunk sent
+        // by a `PortHandle`.
+        let scratch_len = core::cmp::max(self.max_frame / 2, OVERHEAD + 1);
+        let tx = Arc::new(Mutex::new(SecureTx {
+            sealer: None,
+            scratch: HeapArray::new(scratch_len, 0).await,
+        }))
+        .await;
+        let (prod, cons) = bbq::new_spsc_channel(capacity).await;
+        let mut ph = self
+            .add_port(port_id, Upstream::Stream(prod), cons, outgoing, None)
+            .await?;
+        if let Some(info) = self.ports.as_slice_mut().last_mut() {
+            info.secure = Some(SecurePort {
+                opener: None,
+                tx: tx.clone(),
+            });
+        }
+        ph.secure = Some(tx);
+        Ok(ph)
+    }
+
+    /// Returns how full each open port's incoming buffer is.
+    async fn port_depths(&self) -> FixedVec<PortDepth> {
+        let mut depths = FixedVec::new(self.ports.len().max(1)).await;
+        for port in self.ports.as_slice() {
+            let _ = depths.try_push(PortDepth {
+                port: port.port,
+                incoming: port.upstream.depth(),
+            });
+        }
+        depths
+    }
+
+    /// Checks that a new port numbered `port_id` may be added, before
+    /// allocating its buffer.
+    fn check_free(&mut self, port_id: u16) -> Result<(), SerialMuxError> {
+        // ports whose handles have been dropped may be reused.
+        self.ports.retain(|p| !p.upstream.is_closed());
         if self.ports.is_full() {
             return Err(SerialMuxError::RegistryFull);
         }
         if self.ports.as_slice().iter().any(|p| p.port == port_id) {
             return Err(SerialMuxError::DuplicateItem);
         }
-        let (prod, cons) = bbq::new_spsc_channel(capacity).await;
+        Ok(())
+    }
 
+    async fn add_port<C>(
+        &mut self,
+        port_id: u16,
+        upstream: Upstream,
+        cons: C,
+        outgoing: &bbq::MpscProducer,
+        release: Option<KProducer<u16>>,
+    ) -> Result<PortHandle<C>, SerialMuxError> {
+        let reset = Arc::new(Reset::new()).await;
         self.ports
             .try_push(PortInfo {
                 port: port_id,
-                upstream: prod,
+                upstream,
+                reset: reset.clone(),
+                jumbo: None,
+                #[cfg(feature = "secure-sermux")]
+                secure: None,
             })
             .map_err(|_| SerialMuxError::RegistryFull)?;
 
@@ -371,6 +1074,11 @@ impl MuxingInfo {
             cons,
             outgoing: outgoing.clone(),
             max_frame: self.max_frame,
+            encoding: self.encoding.clone(),
+            release,
+            reset,
+            #[cfg(feature = "secure-sermux")]
+            secure: None,
         };
 
         Ok(ph)
@@ -381,8 +1089,25 @@ impl MuxingInfo {
 
 impl CommanderTask {
     async fn run(self) {
+        // let the host know which frame versions we can decode.
+        send_advert(&self.out, false).await;
+
         loop {
-            let Message { msg: req, reply } = self.cmd.next_request().await;
+            let Message { msg: req, reply } = futures::select_biased! {
+                // free any dynamic ports whose handles were dropped before
+                // handling new requests, so their port numbers may be reused.
+                port_id = self.released.dequeue_async().fuse() => {
+                    let Ok(port_id) = port_id else {
+                        // we hold a producer, so this can't happen.
+                        continue;
+                    };
+                    self.mux.lock().await.ports.retain(|p| p.port != port_id);
+                    debug!(port_id, "Closed dynamic port");
+                    self.announce(port_id, false).await;
+                    continue;
+                },
+                msg = self.cmd.next_request().fuse() => msg,
+            };
             match req.body {
                 Request::RegisterPort { port_id, capacity } => {
                     let res = {
@@ -393,11 +1118,79 @@ impl CommanderTask {
 
                     let resp = req.reply_with(res);
 
-                    reply.reply_konly(resp).await.map_err(drop).unwrap();
+                    // if the client stopped waiting for its reply, the port's
+                    // handle is dropped with the reply, which frees the port.
+                    if let Err(error) = reply.reply_konly(resp).await {
+                        warn!(?error, "Failed to reply to serial mux request");
+                    }
+                }
+                Request::RegisterFramedPort { port_id, capacity } => {
+                    let res = {
+                        let mut mux = self.mux.lock().await;
+                        mux.register_framed_port(port_id, capacity, &self.out).await
+                    }
+                    .map(Response::FramedPortRegistered);
+
+                    let resp = req.reply_with(res);
+
+                    if let Err(error) = reply.reply_konly(resp).await {
+                        warn!(?error, "Failed to reply to serial mux request");
+                    }
+                }
+                Request::OpenDynamic { capacity } => {
+                    let res = {
+                        let mut mux = self.mux.lock().await;
+                        mux.open_dynamic(capacity, &self.out).await
+                    };
+                    let port_id = res.as_ref().ok().map(PortHandle::port);
+                    let resp = req.reply_with(res.map(Response::PortRegistered));
+
+                    if let Err(error) = reply.reply_konly(resp).await {
+                        warn!(?error, "Failed to reply to serial mux request");
+                    }
+
+                    if let Some(port_id) = port_id {
+                        debug!(port_id, "Opened dynamic port");
+                        self.announce(port_id, true).await;
+                    }
+                }
+                #[cfg(feature = "secure-sermux")]
+                Request::RegisterSecurePort { port_id, capacity } => {
+                    let res = {
+                        let mut mux = self.mux.lock().await;
+                        mux.register_secure_port(port_id, capacity, &self.out).await
+                    }
+                    .map(Response::PortRegistered);
+
+                    let resp = req.reply_with(res);
+
+                    if let Err(error) = reply.reply_konly(resp).await {
+                        warn!(?error, "Failed to reply to serial mux request");
+                    }
+                }
+                Request::PortDepths => {
+                    let ports = self.mux.lock().await.port_depths().await;
+                    let depths = PortDepths {
+                        outgoing: self.out.depth(),
+                        ports,
+                    };
+                    let resp = req.reply_with(Ok(Response::PortDepths(depths)));
+
+                    if let Err(error) = reply.reply_konly(resp).await {
+                        warn!(?error, "Failed to reply to serial mux request");
+                    }
                 }
             }
         }
     }
+
+    /// Announce that a dynamic port has been opened or closed on the
+    /// [`WellKnown::PortDirectory`] port.
+    async fn announce(&self, port: u16, open: bool) {
+        let entry = DirectoryEntry { port, open }.to_bytes();
+        let frame = Frame::new(self.encoding.get(), WellKnown::PortDirectory, &entry);
+        send_frame(&self.out, frame).await;
+    }
 }
 
 // impl IncomingMuxerTask
@@ -412,42 +1205,373 @@ impl IncomingMuxerTask {
                 continue;
             }
 
-            //////////////////////////////////////////////////////////////////
-            // No early returns/continues until the jerb is done unless you
-            // clear the buffer!
-            //
-            let (port_id, datab) = match try_decode(self.buf.as_slice_mut()) {
-                Some(a) => a,
-                None => {
-                    // Nothing decoded, which means decoding has failed.
-                    self.buf.clear();
-                    continue;
+            // If nothing decoded, decoding has failed, and the frame is
+            // dropped.
+            if let Some(frame) = try_decode(self.buf.as_slice_mut()) {
+                self.router.route(frame).await;
+            }
+
+            // Either way, clear the buffer for the next frame
+            self.buf.clear();
+        }
+    }
+}
+
+// impl Router
+
+impl Router {
+    async fn route(&mut self, frame: Frame<'_>) {
+        let Frame {
+            port: port_id,
+            fragment,
+            chunk,
+            ..
+        } = frame;
+
+        if port_id == WellKnown::PortDirectory as u16 {
+            return self.directory(chunk).await;
+        }
+        if port_id == WellKnown::Control as u16 {
+            return self.control(chunk).await;
+        }
+
+        // Great, now we have a message! Let's see if we have someone listening to this port
+        let mut mux = self.mux.lock().await;
+        let Some(port) = mux
+            .ports
+            .as_slice_mut()
+            .iter_mut()
+            .find(|p| p.port == port_id)
+        else {
+            warn!(port_id, len = chunk.len(), "Discarded bytes, no consumer");
+            return;
+        };
+        if port.upstream.is_closed() {
+            warn!(port_id, len = chunk.len(), "Discarded bytes, port closed");
+            mux.ports.retain(|p| p.port != port_id);
+            debug!(port_id, "Closed port, its handle was dropped");
+            return;
+        }
+        if port.reset.is_pending() {
+            warn!(
+                port_id,
+                len = chunk.len(),
+                "Discarded bytes, port is resetting"
+            );
+            return;
+        }
+
+        #[cfg(feature = "secure-sermux")]
+        if let Some(ref mut secure) = port.secure {
+            if let Some(data) = self.secure.open(port_id, secure, fragment, chunk) {
+                port.deliver(data);
+            }
+            return;
+        }
+
+        // Only framed ports care where messages begin and end. Stream ports
+        // get each part of a jumbo message as it arrives.
+        if matches!(port.upstream, Upstream::Stream(_)) || fragment == Fragment::Whole {
+            return port.deliver(chunk);
+        }
+
+        match fragment {
+            Fragment::Start { total_len } => {
+                if port.jumbo.take().is_some() {
+                    warn!(port_id, "Discarded incomplete jumbo message");
                 }
-            };
+                let total_len = total_len as usize;
+                if total_len > self.max_jumbo {
+                    warn!(port_id, total_len, "Discarded jumbo message, too large");
+                    return;
+                }
+                if total_len < chunk.len() {
+                    warn!(port_id, "Discarded jumbo message, longer than announced");
+                    return;
+                }
+                port.jumbo = Some(Jumbo {
+                    buf: FixedVec::new(total_len).await,
+                });
+            }
+            Fragment::Continue if port.jumbo.is_some() => {}
+            _ => {
+                warn!(
+                    port_id,
+                    len = chunk.len(),
+                    "Discarded bytes, no jumbo message started"
+                );
+                return;
+            }
+        }
 
-            // Great, now we have a message! Let's see if we have someone listening to this port
-            let mux = self.mux.lock().await;
-            if let Some(port) = mux.ports.as_slice().iter().find(|p| p.port == port_id) {
-                if let Some(mut wgr) = port.upstream.send_grant_exact_sync(datab.len()) {
-                    wgr.copy_from_slice(datab);
-                    wgr.commit(datab.len());
-                    debug!(port_id, len = datab.len(), "Sent bytes to port");
-                } else {
-                    warn!(port_id, len = datab.len(), "Discarded bytes, full buffer");
+        let Some(mut jumbo) = port.jumbo.take() else {
+            return;
+        };
+        if jumbo.buf.try_extend_from_slice(chunk).is_err() {
+            warn!(port_id, "Discarded jumbo message, longer than announced");
+        } else if jumbo.buf.is_full() {
+            debug!(port_id, len = jumbo.buf.len(), "Reassembled jumbo message");
+            port.deliver(jumbo.buf.as_slice());
+        } else {
+            port.jumbo = Some(jumbo);
+        }
+    }
+
+    /// Handles a message from the host on the [`WellKnown::PortDirectory`]
+    /// port.
+    async fn directory(&mut self, chunk: &[u8]) {
+        #[cfg(feature = "secure-sermux")]
+        if let Ok(hello) = SecureHello::from_bytes(chunk) {
+            return self.secure_hello(hello).await;
+        }
+
+        let Ok(advert) = VersionAdvert::from_bytes(chunk) else {
+            warn!(len = chunk.len(), "Discarded bytes, not a version advert");
+            return;
+        };
+        let version = advert.negotiate(FrameVersion::LATEST);
+        debug!(?version, "Host advertised frame version");
+        self.encoding.set(version);
+        if !advert.reply {
+            send_advert(&self.out, true).await;
+        }
+    }
+
+    /// Handles a [`PortReset`] from the host on the [`WellKnown::Control`]
+    /// port.
+    async fn control(&mut self, chunk: &[u8]) {
+        let port_id = match PortReset::from_bytes(chunk) {
+            Ok(reset) if !reset.reply => reset.port,
+            _ => {
+                warn!(len = chunk.len(), "Discarded bytes, not a port reset");
+                return;
+            }
+        };
+        let requested = {
+            let mut mux = self.mux.lock().await;
+            let port = mux
+                .ports
+                .as_slice_mut()
+                .iter_mut()
+                .find(|p| p.port == port_id);
+            match port {
+                Some(port) => {
+                    if port.jumbo.take().is_some() {
+                        warn!(port_id, "Discarded incomplete jumbo message, port reset");
+                    }
+                    !port.upstream.is_closed() && port.reset.request()
                 }
-            } else {
-                warn!(port_id, len = datab.len(), "Discarded bytes, no consumer");
+                None => false,
             }
+        };
+        if requested {
+            // the port's owner replies once it has handled the reset.
+            debug!(port_id, "Host requested port reset");
+            return;
+        }
 
-            // Now we clear the buffer
-            self.buf.clear();
-            //
-            // jerb done!
-            //////////////////////////////////////////////////////////////////
+        warn!(
+            port_id,
+            "Refused port reset, port isn't open or can't be reset"
+        );
+        let reply = PortReset {
+            port: port_id,
+            reply: true,
+            ok: false,
+        }
+        .to_bytes();
+        send_frame(
+            &self.out,
+            Frame::new(self.encoding.get(), WellKnown::Control, &reply),
+        )
+        .await;
+    }
+
+    /// Answers a host's [`SecureHello`], starting a new session on a secure
+    /// port.
+    #[cfg(feature = "secure-sermux")]
+    async fn secure_hello(&mut self, hello: SecureHello) {
+        let port_id = hello.port;
+        if hello.reply {
+            // we never start handshakes, so there's nothing to reply to.
+            warn!(port_id, "Discarded secure hello, unexpected reply");
+            return;
+        }
+        let Some(random) = self.secure.random().await else {
+            warn!(port_id, "Refused secure hello, no entropy available");
+            return;
+        };
+
+        let mut mux = self.mux.lock().await;
+        let Some(psk) = mux.psk else {
+            warn!(port_id, "Refused secure hello, no pre-shared key");
+            return;
+        };
+        let Some(secure) = mux
+            .ports
+            .as_slice_mut()
+            .iter_mut()
+            .find(|p| p.port == port_id)
+            .and_then(|p| p.secure.as_mut())
+        else {
+            warn!(port_id, "Refused secure hello, not a secure port");
+            return;
+        };
+
+        let handshake = Handshake::new(Role::Responder, port_id, random);
+        let reply = handshake.hello().to_bytes();
+        let (sealer, opener) = match handshake.finish(&psk, &hello) {
+            Ok(keys) => keys,
+            Err(error) => {
+                warn!(port_id, %error, "Refused secure hello");
+                return;
+            }
+        };
+
+        // hold the sealer's lock until the reply is sent, so that the host
+        // receives the reply before any data sealed with the new keys.
+        let mut tx = secure.tx.lock().await;
+        send_frame(
+            &self.out,
+            Frame::new(FrameVersion::V1, WellKnown::PortDirectory, &reply),
+        )
+        .await;
+        tx.sealer = Some(sealer);
+        secure.opener = Some(opener);
+        debug!(port_id, "Started secure session");
+    }
+}
+
+// impl SecureRouter
+
+#[cfg(feature = "secure-sermux")]
+impl SecureRouter {
+    /// Returns 32 random bytes for an ephemeral key, or `None` if there is no
+    /// entropy service.
+    async fn random(&mut self) -> Option<[u8; 32]> {
+        if self.entropy.is_none() {
+            self.entropy = EntropyClient::from_registry_no_retry(self.kernel)
+                .await
+                .ok();
+        }
+        self.entropy.as_mut()?.random_bytes().await.ok()
+    }
+
+    /// Opens a chunk received on a secure port, returning the data it carried.
+    ///
+    /// Chunks are discarded if there is no session, or if they fail to
+    /// authenticate. Each chunk is sealed separately, so jumbo messages can't
+    /// be sent to secure ports.
+    fn open(
+        &mut self,
+        port_id: u16,
+        secure: &mut SecurePort,
+        fragment: Fragment,
+        chunk: &[u8],
+    ) -> Option<&[u8]> {
+        let Some(opener) = secure.opener.as_mut() else {
+            warn!(
+                port_id,
+                len = chunk.len(),
+                "Discarded bytes, no secure session"
+            );
+            return None;
+        };
+        if fragment != Fragment::Whole {
+            warn!(
+                port_id,
+                "Discarded jumbo message, not allowed on secure ports"
+            );
+            return None;
+        }
+        self.scratch.clear();
+        if self.scratch.try_extend_from_slice(chunk).is_err() {
+            warn!(port_id, len = chunk.len(), "Discarded bytes, too large");
+            return None;
+        }
+        match opener.open(self.scratch.as_slice_mut()) {
+            Ok(data) => Some(data),
+            Err(error) => {
+                warn!(port_id, %error, "Discarded bytes, not authentic");
+                None
+            }
+        }
+    }
+}
+
+// impl Upstream
+
+impl Upstream {
+    /// Returns `true` if the port's [`PortHandle`] has been dropped.
+    fn is_closed(&self) -> bool {
+        match self {
+            Upstream::Stream(prod) => prod.is_closed(),
+            Upstream::Framed(prod) => prod.is_closed(),
+        }
+    }
+
+    fn depth(&self) -> bbq::BufferDepth {
+        match self {
+            Upstream::Stream(prod) => prod.depth(),
+            Upstream::Framed(prod) => prod.depth(),
+        }
+    }
+}
+
+// impl PortInfo
+
+impl PortInfo {
+    /// Sends data received from the host to the port's consumer.
+    fn deliver(&self, data: &[u8]) {
+        let sent = match self.upstream {
+            Upstream::Stream(ref prod) => prod.send_grant_exact_sync(data.len()).map(|mut wgr| {
+                wgr.copy_from_slice(data);
+                wgr.commit(data.len());
+            }),
+            Upstream::Framed(ref prod) => prod.send_grant_sync(data.len()).map(|mut wgr| {
+                wgr[..data.len()].copy_from_slice(data);
+                wgr.commit(data.len());
+            }),
+        };
+        if sent.is_some() {
+            debug!(port_id = self.port, len = data.len(), "Sent bytes to port");
+        } else {
+            warn!(
+                port_id = self.port,
+                len = data.len(),
+                "Discarded bytes, full buffer"
+            );
         }
     }
 }
 
+/// Encodes a frame, and sends it to the host.
+async fn send_frame(out: &bbq::MpscProducer, frame: Frame<'_>) {
+    let mut wgr = out.send_grant_exact(frame.buffer_required()).await;
+    match frame.encode_to(&mut wgr) {
+        Ok(used) => {
+            let used = used.len();
+            wgr.commit(used);
+        }
+        Err(error) => tracing::warn!(?error, "Discarded frame, encoding failed"),
+    }
+}
+
+/// Sends a [`VersionAdvert`] to the host. Adverts are always sent as version
+/// 1 frames, so that any host can decode them.
+async fn send_advert(out: &bbq::MpscProducer, reply: bool) {
+    let advert = VersionAdvert {
+        version: FrameVersion::LATEST,
+        reply,
+    }
+    .to_bytes();
+    send_frame(
+        out,
+        Frame::new(FrameVersion::V1, WellKnown::PortDirectory, &advert),
+    )
+    .await;
+}
+
 /// Takes data from the grant
 ///
 /// Returns true if the buffer is now ready for decoding
@@ -481,37 +1605,27 @@ fn take_from_grant(buffer: &mut FixedVec<u8>, grant: GrantR) -> bool {
     try_decode
 }
 
-/// Tries to decode a port and message from the given buffer
+/// Tries to decode a frame of either version from the given buffer
 ///
 /// Either way, you should probably clear the buffer when you are done.
-fn try_decode(buffer: &mut [u8]) -> Option<(u16, &[u8])> {
-    let used = match cobs::decode_in_place(buffer) {
-        Ok(u) if u < 3 => {
-            warn!("Cobs decode too short!");
-            return None;
+fn try_decode(buffer: &mut [u8]) -> Option<Frame<'_>> {
+    match Frame::decode_from(buffer) {
+        Ok(frame) => Some(frame),
+        Err(error) => {
+            warn!(%error, "Frame decode failed!");
+            None
         }
-        Ok(u) => u,
-        Err(_) => {
-            warn!("Cobs decode failed!");
-            return None;
-        }
-    };
-
-    let total = buffer.get(..used)?;
-
-    let mut port = [0u8; 2];
-    let (portb, datab) = total.split_at(2);
-    port.copy_from_slice(portb);
-    let port_id = u16::from_le_bytes(port);
-
-    Some((port_id, datab))
+    }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::comms::bbq::{Consumer, SpscProducer};
-    use core::ops::Deref;
+    use crate::{
+        comms::bbq::{Consumer, SpscProducer},
+        test_util::{SerialHost, TestKernel},
+    };
+    use core::{ops::Deref, pin::pin};
 
     struct Stuff {
         prod: SpscProducer,
@@ -553,11 +1667,30 @@ mod test {
 
         assert!(take_from_grant(&mut ctxt.buffer, rgr));
         assert_eq!(ctxt.buffer.as_slice(), MESSAGE);
-        let (port_id, data) = try_decode(ctxt.buffer.as_slice_mut()).unwrap();
+        let Frame {
+            port: port_id,
+            chunk: data,
+            ..
+        } = try_decode(ctxt.buffer.as_slice_mut()).unwrap();
         assert_eq!(port_id, 0);
         assert_eq!(data, b"!");
     }
 
+    /// Make sure we can decode version 2 messages
+    #[test]
+    fn v2_decode() {
+        // escape, flags, port 300 as a varint, data
+        const MESSAGE: &[u8] = &[0x03, 0xFF, 0xFF, 0x04, 0xAC, 0x02, b'!', 0x00];
+        let mut ctxt = Stuff::setup();
+        ctxt.send(MESSAGE);
+
+        let rgr = ctxt.read();
+
+        assert!(take_from_grant(&mut ctxt.buffer, rgr));
+        let frame = try_decode(ctxt.buffer.as_slice_mut()).unwrap();
+        assert_eq!(frame, Frame::new(FrameVersion::V2, 300u16, b"!"));
+    }
+
     /// Make sure we successfully report empty messages as failed
     #[test]
     fn empty_message() {
@@ -602,7 +1735,11 @@ mod test {
 
         assert!(take_from_grant(&mut ctxt.buffer, rgr));
         assert_eq!(ctxt.buffer.as_slice(), MESSAGE_GOOD);
-        let (port_id, data) = try_decode(ctxt.buffer.as_slice_mut()).unwrap();
+        let Frame {
+            port: port_id,
+            chunk: data,
+            ..
+        } = try_decode(ctxt.buffer.as_slice_mut()).unwrap();
         assert_eq!(port_id, 0);
         assert_eq!(data, b"!");
     }
@@ -619,7 +1756,11 @@ mod test {
 
         assert!(take_from_grant(&mut ctxt.buffer, rgr));
         assert_eq!(ctxt.buffer.as_slice(), MESSAGE);
-        let (port_id, data) = try_decode(ctxt.buffer.as_slice_mut()).unwrap();
+        let Frame {
+            port: port_id,
+            chunk: data,
+            ..
+        } = try_decode(ctxt.buffer.as_slice_mut()).unwrap();
         assert_eq!(port_id, 0);
         assert_eq!(data, b"!");
         ctxt.clear();
@@ -629,9 +1770,230 @@ mod test {
 
         assert!(take_from_grant(&mut ctxt.buffer, rgr));
         assert_eq!(ctxt.buffer.as_slice(), MESSAGE);
-        let (port_id, data) = try_decode(ctxt.buffer.as_slice_mut()).unwrap();
+        let Frame {
+            port: port_id,
+            chunk: data,
+            ..
+        } = try_decode(ctxt.buffer.as_slice_mut()).unwrap();
         assert_eq!(port_id, 0);
         assert_eq!(data, b"!");
         ctxt.clear();
     }
+
+    /// Returns the next message the target sends on the
+    /// [`WellKnown::Control`] port.
+    async fn control(host: &mut SerialHost) -> PortReset {
+        PortReset::from_bytes(&host.recv(WellKnown::Control).await).unwrap()
+    }
+
+    #[test]
+    fn port_reset() {
+        TestKernel::run(|k| async move {
+            let mut host = SerialHost::setup(k).await;
+            let port = PortHandle::open(k, 100, 64).await.unwrap();
+            let reply = |port, ok| PortReset {
+                port,
+                reply: true,
+                ok,
+            };
+
+            // resets are refused until the port's owner waits for one...
+            host.send(WellKnown::Control, &PortReset::request(100).to_bytes())
+                .await;
+            assert_eq!(control(&mut host).await, reply(100, false));
+            // ...and for ports which aren't open.
+            host.send(WellKnown::Control, &PortReset::request(101).to_bytes())
+                .await;
+            assert_eq!(control(&mut host).await, reply(101, false));
+
+            let mut reset = pin!(port.reset());
+            assert!(futures::poll!(reset.as_mut()).is_pending());
+            host.send(100u16, b"stale").await;
+            host.send(WellKnown::Control, &PortReset::request(100).to_bytes())
+                .await;
+            reset.await;
+
+            // unread data was discarded, and the host was told.
+            assert!(port.consumer().read_grant_sync().is_none());
+            assert_eq!(control(&mut host).await, reply(100, true));
+
+            // the port works as usual after the reset.
+            host.send(100u16, b"fresh").await;
+            let rgr = port.consumer().read_grant().await;
+            assert_eq!(&rgr[..], b"fresh");
+        })
+    }
+
+    /// Returns the next [`DirectoryEntry`] the target sends on the
+    /// [`WellKnown::PortDirectory`] port, skipping other messages.
+    async fn directory(host: &mut SerialHost) -> DirectoryEntry {
+        loop {
+            let msg = host.recv(WellKnown::PortDirectory).await;
+            if let Ok(entry) = DirectoryEntry::from_bytes(&msg) {
+                return entry;
+            }
+        }
+    }
+
+    #[test]
+    fn dynamic_ports() {
+        TestKernel::run(|k| async move {
+            let mut host = SerialHost::setup(k).await;
+            let mut client = SerialMuxClient::from_registry(k).await.unwrap();
+            let entry = |port, open| DirectoryEntry { port, open };
+
+            let a = client.open_dynamic(64).await.unwrap();
+            let b = client.open_dynamic(64).await.unwrap();
+            assert!(DYNAMIC_PORTS.contains(&a.port()));
+            assert!(DYNAMIC_PORTS.contains(&b.port()));
+            assert_ne!(a.port(), b.port());
+            assert_eq!(directory(&mut host).await, entry(a.port(), true));
+            assert_eq!(directory(&mut host).await, entry(b.port(), true));
+
+            // closing a port announces it to the host...
+            let closed = a.port();
+            drop(a);
+            assert_eq!(directory(&mut host).await, entry(closed, false));
+
+            // ...and frees its number to be reused.
+            let c = client.open_dynamic(64).await.unwrap();
+            assert_eq!(c.port(), closed);
+            assert_eq!(directory(&mut host).await, entry(closed, true));
+
+            // ports in the dynamic range can't be registered explicitly.
+            let resp = client
+                .prod
+                .request_oneshot(
+                    Request::RegisterPort {
+                        port_id: DYNAMIC_PORTS.start,
+                        capacity: 64,
+                    },
+                    &client.reply,
+                )
+                .await
+                .unwrap();
+            assert_eq!(resp.body.err(), Some(SerialMuxError::ReservedPort));
+        })
+    }
+
+    #[test]
+    fn interleaved_jumbo() {
+        TestKernel::run(|k| async move {
+            let host = SerialHost::setup(k).await;
+            let a = PortHandle::open_framed(k, 100, 256).await.unwrap();
+            let b = PortHandle::open_framed(k, 101, 256).await.unwrap();
+            let frame = |port: u16, fragment, chunk| Frame {
+                version: FrameVersion::V2,
+                port,
+                fragment,
+                chunk,
+            };
+
+            host.send_frame(frame(100, Fragment::Start { total_len: 6 }, b"abc"))
+                .await;
+            host.send_frame(frame(101, Fragment::Start { total_len: 4 }, b"wx"))
+                .await;
+            host.send_frame(frame(100, Fragment::Continue, b"def"))
+                .await;
+            host.send_frame(frame(101, Fragment::Continue, b"yz")).await;
+
+            let rgr = a.consumer().read_grant().await;
+            assert_eq!(&rgr[..], b"abcdef");
+            rgr.release();
+            let rgr = b.consumer().read_grant().await;
+            assert_eq!(&rgr[..], b"wxyz");
+            rgr.release();
+
+            // starting a new jumbo message discards the incomplete one on
+            // the same port.
+            host.send_frame(frame(100, Fragment::Start { total_len: 4 }, b"ol"))
+                .await;
+            host.send_frame(frame(100, Fragment::Start { total_len: 4 }, b"ne"))
+                .await;
+            host.send_frame(frame(100, Fragment::Continue, b"wy")).await;
+            let rgr = a.consumer().read_grant().await;
+            assert_eq!(&rgr[..], b"newy");
+        })
+    }
+
+    /// A host and target exchange data on a secure port, end to end.
+    #[cfg(feature = "secure-sermux")]
+    #[test]
+    fn secure_port() {
+        use crate::services::entropy::{EntropyError, EntropyServer, EntropySource};
+
+        /// Not at all random, but this is a test.
+        struct NotRandom;
+
+        impl EntropySource for NotRandom {
+            fn fill(&mut self, buf: &mut [u8]) -> Result<(), EntropyError> {
+                buf.fill(0x42);
+                Ok(())
+            }
+        }
+
+        const PSK: [u8; 32] = [7; 32];
+
+        /// Returns the next secure hello the target sends.
+        async fn hello(host: &mut SerialHost) -> SecureHello {
+            loop {
+                let msg = host.recv(WellKnown::PortDirectory).await;
+                if let Ok(hello) = SecureHello::from_bytes(&msg) {
+                    return hello;
+                }
+            }
+        }
+
+        TestKernel::run(|k| async move {
+            EntropyServer::register(k, Default::default(), NotRandom)
+                .await
+                .unwrap();
+            let settings = SerialMuxSettings {
+                psk: Some(PSK),
+                ..Default::default()
+            };
+            let mut host = SerialHost::setup_with(k, settings).await;
+            let port = PortHandle::open_secure(k, 100, 64).await.unwrap();
+
+            // data sent before the handshake is discarded.
+            host.send(100u16, b"plaintext").await;
+
+            let handshake = Handshake::new(Role::Initiator, 100, [1; 32]);
+            host.send(WellKnown::PortDirectory, &handshake.hello().to_bytes())
+                .await;
+            let reply = hello(&mut host).await;
+            assert_eq!(reply.port, 100);
+            assert!(reply.reply);
+            let (mut sealer, mut opener) = handshake.finish(&PSK, &reply).unwrap();
+
+            let mut buf = [0u8; 64];
+            host.send(100u16, sealer.seal(b"1 2 + .", &mut buf).unwrap())
+                .await;
+            let rgr = port.consumer().read_grant().await;
+            assert_eq!(&rgr[..], b"1 2 + .");
+            let len = rgr.len();
+            rgr.release(len);
+
+            port.send(b"3 ok.").await;
+            let mut sealed = host.recv(100u16).await;
+            assert_ne!(&sealed[..], b"3 ok.");
+            assert_eq!(opener.open(&mut sealed).unwrap(), b"3 ok.");
+
+            // a replayed chunk is discarded.
+            let mut buf = [0u8; 64];
+            let replayed = sealer.seal(b"again", &mut buf).unwrap().to_vec();
+            host.send(100u16, &replayed).await;
+            host.send(100u16, &replayed).await;
+            host.send(100u16, sealer.seal(b"last", &mut buf).unwrap())
+                .await;
+            let mut received = Vec::new();
+            while received.len() < b"againlast".len() {
+                let rgr = port.consumer().read_grant().await;
+                received.extend_from_slice(&rgr);
+                let len = rgr.len();
+                rgr.release(len);
+            }
+            assert_eq!(received, b"againlast");
+        })
+    }
 }
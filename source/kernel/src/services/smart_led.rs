@@ -0,0 +1,355 @@
+//! Smart LED Service
+//!
+//! A service for driving strips (or small matrices) of addressable RGB LEDs,
+//! such as WS2812s ("NeoPixels").
+//!
+//! Like the [emb_display](crate::services::emb_display) service, this is a
+//! "frame buffer" style interface: clients allocate a [`LedFrame`] the size of
+//! the whole strip, set pixel colors in it, and send it to the service to be
+//! shown. Once shown, the frame is returned to the client for re-use.
+//!
+//! [`LedFrame`] implements [`DrawTarget`], so `embedded-graphics` may be used
+//! to draw into it, treating the LEDs as a `width` x `height` display with
+//! LEDs numbered in row-major order.
+//!
+//! Gamma correction and global brightness are applied by the service when a
+//! frame is shown (see [`correct`]), so frames should contain the colors as
+//! they are intended to be perceived.
+use embedded_graphics::{pixelcolor::Rgb888, prelude::*};
+use uuid::Uuid;
+
+use crate::{
+    comms::oneshot::Reusable,
+    mnemos_alloc::containers::HeapArray,
+    registry::{self, Envelope, KernelHandle, RegisteredDriver},
+    Kernel,
+};
+
+////////////////////////////////////////////////////////////////////////////////
+// Service Definition
+////////////////////////////////////////////////////////////////////////////////
+
+/// Registered driver type for the `SmartLed` service.
+///
+/// This module provides an implementation of the client for this service, but
+/// not the server. A server implementing this service must be provided by the
+/// hardware platform implementation.
+pub struct SmartLedService;
+
+impl RegisteredDriver for SmartLedService {
+    type Request = Request;
+    type Response = Response;
+    type Error = SmartLedError;
+    type Hello = ();
+    type ConnectError = core::convert::Infallible;
+    const UUID: Uuid = registry::known_uuids::kernel::SMART_LED;
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Message and Error Types
+////////////////////////////////////////////////////////////////////////////////
+
+pub enum Request {
+    GetMeta,
+    Show(LedFrame),
+}
+
+pub enum Response {
+    Meta(StripMetadata),
+    /// The frame was shown
+    Shown(LedFrame),
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum SmartLedError {
+    /// The frame's size does not match the size of the strip.
+    WrongSize { expected: usize, actual: usize },
+    /// Internal Error
+    InternalError,
+}
+
+/// The layout of a strip of LEDs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StripMetadata {
+    /// The number of LEDs in each row.
+    pub width: u32,
+    /// The number of rows. For a single strip, this is 1.
+    pub height: u32,
+}
+
+/// Errors returned when registering a [`SmartLedService`] server.
+#[derive(Debug)]
+pub enum RegistrationError {
+    /// An error occurred registering the [`SmartLedService`].
+    Registry(registry::RegistrationError),
+    /// The strip has no LEDs, or more LEDs than the server can drive.
+    InvalidSize(StripMetadata),
+}
+
+impl StripMetadata {
+    /// The total number of LEDs in the strip.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.width as usize * self.height as usize
+    }
+
+    /// Returns `true` if the strip has no LEDs.
+    ///
+    /// Servers must reject such strips when they are registered.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Client Definition
+////////////////////////////////////////////////////////////////////////////////
+
+/// Client interface to [`SmartLedService`].
+pub struct SmartLedClient {
+    prod: KernelHandle<SmartLedService>,
+    reply: Reusable<Envelope<Result<Response, SmartLedError>>>,
+}
+
+impl SmartLedClient {
+    /// Obtain a new client handle by querying the registry for a registered
+    /// [`SmartLedService`].
+    ///
+    /// Will retry until success
+    pub async fn from_registry(
+        kernel: &'static Kernel,
+    ) -> Result<Self, registry::ConnectError<SmartLedService>> {
+        let prod = kernel.registry().connect::<SmartLedService>(()).await?;
+
+        Ok(SmartLedClient {
+            prod,
+            reply: Reusable::new_async().await,
+        })
+    }
+
+    /// Obtain a new client handle by querying the registry for a registered
+    /// [`SmartLedService`].
+    ///
+    /// Will not retry if not immediately successful
+    pub async fn from_registry_no_retry(
+        kernel: &'static Kernel,
+    ) -> Result<Self, registry::ConnectError<SmartLedService>> {
+        let prod = kernel.registry().try_connect::<SmartLedService>(()).await?;
+
+        Ok(SmartLedClient {
+            prod,
+            reply: Reusable::new_async().await,
+        })
+    }
+
+    pub async fn get_meta(&mut self) -> Result<StripMetadata, SmartLedError> {
+        let resp = self
+            .prod
+            .request_oneshot(Request::GetMeta, &self.reply)
+            .await
+            .map_err(|_| SmartLedError::InternalError)?
+            .body?;
+
+        match resp {
+            Response::Meta(meta) => Ok(meta),
+            Response::Shown(_) => Err(SmartLedError::InternalError),
+        }
+    }
+
+    /// Allocate a new, blank [`LedFrame`] the size of the strip.
+    pub async fn new_frame(&mut self) -> Result<LedFrame, SmartLedError> {
+        let meta = self.get_meta().await?;
+        Ok(LedFrame::new(meta).await)
+    }
+
+    /// Show `frame` on the strip, returning it once it has been sent.
+    pub async fn show(&mut self, frame: LedFrame) -> Result<LedFrame, SmartLedError> {
+        let resp = self
+            .prod
+            .request_oneshot(Request::Show(frame), &self.reply)
+            .await
+            .map_err(|_| SmartLedError::InternalError)?
+            .body?;
+
+        match resp {
+            Response::Shown(frame) => Ok(frame),
+            Response::Meta(_) => Err(SmartLedError::InternalError),
+        }
+    }
+}
+
+/// A buffer of colors for every LED on a strip.
+pub struct LedFrame {
+    meta: StripMetadata,
+    pixels: HeapArray<Rgb888>,
+}
+
+impl LedFrame {
+    /// Allocate a new frame with every LED off.
+    ///
+    /// # Panics
+    ///
+    /// If `meta` describes a strip with no LEDs. Servers reject such strips
+    /// when they are registered, so this never happens for metadata returned
+    /// by [`SmartLedClient::get_meta`].
+    pub async fn new(meta: StripMetadata) -> Self {
+        let len = meta.len();
+        assert_ne!(len, 0, "a LED frame must have at least one LED");
+        Self {
+            meta,
+            pixels: HeapArray::new(len, Rgb888::BLACK).await,
+        }
+    }
+
+    #[must_use]
+    pub fn meta(&self) -> StripMetadata {
+        self.meta
+    }
+
+    /// The number of LEDs in this frame.
+    #[must_use]
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> usize {
+        self.pixels.len()
+    }
+
+    /// Sets the color of the LED at `index`, returning `false` if `index` is
+    /// out of range.
+    pub fn set(&mut self, index: usize, color: Rgb888) -> bool {
+        match self.pixels.get_mut(index) {
+            Some(pixel) => {
+                *pixel = color;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Sets every LED to `color`.
+    pub fn fill(&mut self, color: Rgb888) {
+        self.pixels.fill(color);
+    }
+
+    #[must_use]
+    pub fn pixels(&self) -> &[Rgb888] {
+        &self.pixels
+    }
+
+    pub fn pixels_mut(&mut self) -> &mut [Rgb888] {
+        &mut self.pixels
+    }
+}
+
+impl OriginDimensions for LedFrame {
+    fn size(&self) -> Size {
+        Size::new(self.meta.width, self.meta.height)
+    }
+}
+
+impl DrawTarget for LedFrame {
+    type Color = Rgb888;
+    type Error = core::convert::Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        let StripMetadata { width, height } = self.meta;
+        for Pixel(Point { x, y }, color) in pixels {
+            if x < 0 || y < 0 || x as u32 >= width || y as u32 >= height {
+                continue;
+            }
+            self.pixels[y as usize * width as usize + x as usize] = color;
+        }
+        Ok(())
+    }
+
+    fn clear(&mut self, color: Self::Color) -> Result<(), Self::Error> {
+        self.fill(color);
+        Ok(())
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Color Correction
+////////////////////////////////////////////////////////////////////////////////
+
+/// Gamma correct `color` and scale it to `brightness` (out of 255).
+///
+/// LEDs are driven with a linear PWM duty cycle, but perceived brightness is
+/// not linear, so without gamma correction, most colors appear washed out.
+/// Servers should call this on every pixel before sending it to the LEDs.
+#[must_use]
+pub fn correct(color: Rgb888, brightness: u8) -> Rgb888 {
+    let channel = |c: u8| {
+        let scaled = (u16::from(c) * (u16::from(brightness) + 1)) >> 8;
+        GAMMA8[scaled as usize]
+    };
+    Rgb888::new(channel(color.r()), channel(color.g()), channel(color.b()))
+}
+
+/// 8-bit gamma correction table, for a gamma of 2.8.
+static GAMMA8: [u8; 256] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, //
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, //
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 2, 2, 2, 2, 2, 2, 2, //
+    2, 3, 3, 3, 3, 3, 3, 3, 4, 4, 4, 4, 4, 5, 5, 5, //
+    5, 6, 6, 6, 6, 7, 7, 7, 7, 8, 8, 8, 9, 9, 9, 10, //
+    10, 10, 11, 11, 11, 12, 12, 13, 13, 13, 14, 14, 15, 15, 16, 16, //
+    17, 17, 18, 18, 19, 19, 20, 20, 21, 21, 22, 22, 23, 24, 24, 25, //
+    25, 26, 27, 27, 28, 29, 29, 30, 31, 32, 32, 33, 34, 35, 35, 36, //
+    37, 38, 39, 39, 40, 41, 42, 43, 44, 45, 46, 47, 48, 49, 50, 50, //
+    51, 52, 54, 55, 56, 57, 58, 59, 60, 61, 62, 63, 64, 66, 67, 68, //
+    69, 70, 72, 73, 74, 75, 77, 78, 79, 81, 82, 83, 85, 86, 87, 89, //
+    90, 92, 93, 95, 96, 98, 99, 101, 102, 104, 105, 107, 109, 110, 112, 114, //
+    115, 117, 119, 120, 122, 124, 126, 127, 129, 131, 133, 135, 137, 138, 140, 142, //
+    144, 146, 148, 150, 152, 154, 156, 158, 160, 162, 164, 167, 169, 171, 173, 175, //
+    177, 180, 182, 184, 186, 189, 191, 193, 196, 198, 200, 203, 205, 208, 210, 213, //
+    215, 218, 220, 223, 225, 228, 231, 233, 236, 239, 241, 244, 247, 249, 252, 255, //
+];
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use embedded_graphics::primitives::{Line, Primitive, PrimitiveStyle};
+
+    #[test]
+    fn correct_endpoints() {
+        assert_eq!(correct(Rgb888::WHITE, 255), Rgb888::WHITE);
+        assert_eq!(correct(Rgb888::BLACK, 255), Rgb888::BLACK);
+        assert_eq!(correct(Rgb888::WHITE, 0), Rgb888::BLACK);
+        // half brightness is much less than half duty cycle.
+        let half = correct(Rgb888::WHITE, 127);
+        assert!(half.r() < 64, "{half:?}");
+    }
+
+    #[test]
+    #[should_panic = "at least one LED"]
+    fn empty_frame() {
+        let meta = StripMetadata {
+            width: 8,
+            height: 0,
+        };
+        assert!(meta.is_empty());
+        futures::executor::block_on(LedFrame::new(meta));
+    }
+
+    #[test]
+    fn draw_row_major() {
+        let meta = StripMetadata {
+            width: 4,
+            height: 2,
+        };
+        let mut frame = futures::executor::block_on(LedFrame::new(meta));
+        Line::new(Point::new(0, 1), Point::new(3, 1))
+            .into_styled(PrimitiveStyle::with_stroke(Rgb888::RED, 1))
+            .draw(&mut frame)
+            .unwrap();
+
+        assert_eq!(frame.len(), meta.len());
+        assert_eq!(frame.len(), 8);
+        assert!(frame.pixels()[..4].iter().all(|&c| c == Rgb888::BLACK));
+        assert!(frame.pixels()[4..].iter().all(|&c| c == Rgb888::RED));
+    }
+}
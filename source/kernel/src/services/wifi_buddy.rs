@@ -0,0 +1,199 @@
+//! # WiFi Buddy Control Service
+//!
+//! The control path for a WiFi radio, such as the one on the ESP32-C3 WiFi
+//! Buddy: scanning for access points, and joining or leaving a network. The
+//! data path, which exchanges Ethernet frames once a network has been joined,
+//! is the separate [`NetworkService`](super::network::NetworkService).
+//!
+//! See [RFC 0196] for the overall design of the WiFi Buddy interface.
+//!
+//! This module only contains the service definition and client definition,
+//! the server must be implemented for the given target platform.
+//!
+//! [RFC 0196]: https://github.com/tosc-rs/mnemos/blob/main/rfcs/0196-wifi-buddy-interface.md
+
+use uuid::Uuid;
+
+use crate::{
+    comms::oneshot::Reusable,
+    mnemos_alloc::containers::FixedVec,
+    registry::{self, known_uuids, Envelope, KernelHandle, RegisteredDriver},
+    Kernel,
+};
+
+////////////////////////////////////////////////////////////////////////////////
+// Service Definition
+////////////////////////////////////////////////////////////////////////////////
+
+/// Service definition for the WiFi Buddy control service.
+pub struct WifiBuddyService;
+
+impl RegisteredDriver for WifiBuddyService {
+    type Request = Request;
+    type Response = Response;
+    type Error = WifiError;
+    type Hello = ();
+    type ConnectError = core::convert::Infallible;
+
+    const UUID: Uuid = known_uuids::kernel::WIFI_BUDDY;
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Message and Error Types
+////////////////////////////////////////////////////////////////////////////////
+
+pub enum Request {
+    /// Scan for access points.
+    Scan,
+    /// Join a network, leaving the current network first (if any).
+    Connect(Credentials),
+    /// Leave the current network.
+    Disconnect,
+}
+
+pub enum Response {
+    /// The access points found by a scan.
+    Scan(FixedVec<AccessPoint>),
+    /// The network was joined.
+    Connected,
+    /// The network was left.
+    Disconnected,
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum WifiError {
+    /// The SSID or password is too long.
+    InvalidCredentials,
+    /// The radio failed to scan for access points.
+    Scan,
+    /// The radio failed to join the network.
+    Connect,
+    /// The radio failed to leave the network.
+    Disconnect,
+    /// An error occurred sending the request to the service.
+    Request(registry::OneshotRequestError),
+}
+
+/// The network to join in a [`Request::Connect`].
+#[derive(Debug, Clone)]
+pub struct Credentials {
+    /// The network's SSID.
+    pub ssid: heapless::String<{ Credentials::MAX_SSID_LEN }>,
+    /// The network's passphrase, or an empty string for an open network.
+    pub password: heapless::String<{ Credentials::MAX_PASSWORD_LEN }>,
+}
+
+/// An access point found by a [`Request::Scan`].
+#[derive(Debug, Clone)]
+pub struct AccessPoint {
+    /// The access point's SSID.
+    pub ssid: heapless::String<{ Credentials::MAX_SSID_LEN }>,
+    /// The access point's MAC address.
+    pub bssid: [u8; 6],
+    /// The channel the access point is on.
+    pub channel: u8,
+    /// Received signal strength, in dBm.
+    pub rssi: i8,
+}
+
+impl Credentials {
+    /// The longest SSID allowed by IEEE 802.11.
+    pub const MAX_SSID_LEN: usize = 32;
+    /// The longest WPA2 passphrase.
+    pub const MAX_PASSWORD_LEN: usize = 64;
+
+    /// Returns credentials for the network `ssid`, using `password` as its
+    /// passphrase.
+    ///
+    /// Returns [`WifiError::InvalidCredentials`] if either is too long.
+    pub fn new(ssid: &str, password: &str) -> Result<Self, WifiError> {
+        let mut creds = Self {
+            ssid: heapless::String::new(),
+            password: heapless::String::new(),
+        };
+        creds
+            .ssid
+            .push_str(ssid)
+            .map_err(|_| WifiError::InvalidCredentials)?;
+        creds
+            .password
+            .push_str(password)
+            .map_err(|_| WifiError::InvalidCredentials)?;
+        Ok(creds)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Client Definition
+////////////////////////////////////////////////////////////////////////////////
+
+/// A client for the [`WifiBuddyService`].
+pub struct WifiBuddyClient {
+    handle: KernelHandle<WifiBuddyService>,
+    reply: Reusable<Envelope<Result<Response, WifiError>>>,
+}
+
+impl WifiBuddyClient {
+    /// Obtain a `WifiBuddyClient`
+    ///
+    /// If the [`WifiBuddyService`] hasn't been registered yet, we will retry
+    /// until it has been registered.
+    pub async fn from_registry(
+        kernel: &'static Kernel,
+    ) -> Result<Self, registry::ConnectError<WifiBuddyService>> {
+        let handle = kernel.registry().connect::<WifiBuddyService>(()).await?;
+
+        Ok(Self {
+            handle,
+            reply: Reusable::new_async().await,
+        })
+    }
+
+    /// Obtain a `WifiBuddyClient`
+    ///
+    /// Does NOT attempt to get a [`WifiBuddyService`] handle more than once.
+    pub async fn from_registry_no_retry(
+        kernel: &'static Kernel,
+    ) -> Result<Self, registry::ConnectError<WifiBuddyService>> {
+        let handle = kernel
+            .registry()
+            .try_connect::<WifiBuddyService>(())
+            .await?;
+
+        Ok(Self {
+            handle,
+            reply: Reusable::new_async().await,
+        })
+    }
+
+    /// Scan for access points.
+    pub async fn scan(&mut self) -> Result<FixedVec<AccessPoint>, WifiError> {
+        match self.request(Request::Scan).await? {
+            Response::Scan(aps) => Ok(aps),
+            _ => unreachable!("service must reply to a Scan with Scan"),
+        }
+    }
+
+    /// Join the network `ssid`, using `password` as its passphrase.
+    ///
+    /// For an open network, `password` should be empty.
+    pub async fn connect(&mut self, ssid: &str, password: &str) -> Result<(), WifiError> {
+        let creds = Credentials::new(ssid, password)?;
+        self.request(Request::Connect(creds)).await?;
+        Ok(())
+    }
+
+    /// Leave the current network.
+    pub async fn disconnect(&mut self) -> Result<(), WifiError> {
+        self.request(Request::Disconnect).await?;
+        Ok(())
+    }
+
+    async fn request(&mut self, req: Request) -> Result<Response, WifiError> {
+        self.handle
+            .request_oneshot(req, &self.reply)
+            .await
+            .map_err(WifiError::Request)?
+            .body
+    }
+}
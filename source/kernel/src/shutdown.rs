@@ -0,0 +1,344 @@
+//! # Shutdown Hooks
+//!
+//! Services with persistent state, such as a key-value store, a filesystem,
+//! or open network connections, must flush that state before the system
+//! loses power, and must do so in dependency order: a key-value store must
+//! write out its buffers *before* the filesystem beneath it is synced, and the
+//! filesystem must be synced before its block device is powered down.
+//!
+//! Such services register a *shutdown hook* using [`Kernel::on_shutdown`],
+//! with a [`ShutdownPriority`] that places it relative to other hooks. When
+//! the system is about to power off, whatever initiates the shutdown (such as
+//! a platform's power management service) calls [`Kernel::shutdown`], which
+//! runs every hook, one at a time, in priority order. Each hook is given a
+//! timeout, so that a hook which hangs can't stop the hooks after it from
+//! running: once it elapses, the hook is dropped and the next one started.
+//!
+//! Once a shutdown has started, no more hooks may be registered.
+
+use core::{fmt, future::Future, panic::Location, pin::Pin};
+
+use maitake::{sync::Mutex, time::Duration};
+use portable_atomic::{AtomicBool, Ordering};
+use tracing::{self, Instrument, Level};
+
+use crate::{mnemos_alloc::containers::Box, Kernel};
+
+/// The maximum number of shutdown hooks that may be registered.
+pub const MAX_HOOKS: usize = 32;
+
+/// Shutdown hooks registered with a [`Kernel`].
+pub struct ShutdownHooks {
+    hooks: Mutex<heapless::Vec<Hook, MAX_HOOKS>>,
+    started: AtomicBool,
+}
+
+/// When a shutdown hook runs, relative to other hooks.
+///
+/// Hooks with *lower* priorities run first. Hooks with the same priority run
+/// in the order they were registered. The associated constants describe the
+/// usual layers of the system, from the top down; a hook which must run just
+/// before or after one of them may use [`ShutdownPriority::before`] or
+/// [`ShutdownPriority::after`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ShutdownPriority(u8);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShutdownError {
+    /// [`MAX_HOOKS`] hooks have already been registered.
+    Full,
+    /// The kernel has already started shutting down.
+    ShuttingDown,
+}
+
+/// The outcome of running the kernel's shutdown hooks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ShutdownReport {
+    /// The number of hooks which completed.
+    pub completed: usize,
+    /// The number of hooks which were dropped because they did not complete
+    /// before their timeout.
+    pub timed_out: usize,
+}
+
+struct Hook {
+    priority: ShutdownPriority,
+    location: &'static Location<'static>,
+    run: Pin<alloc::boxed::Box<dyn Future<Output = ()>>>,
+}
+
+// === impl ShutdownHooks ===
+
+impl ShutdownHooks {
+    pub(crate) const fn new() -> Self {
+        Self {
+            hooks: Mutex::new(heapless::Vec::new()),
+            started: AtomicBool::new(false),
+        }
+    }
+
+    /// Returns `true` if the kernel has started shutting down.
+    #[must_use]
+    pub fn is_shutting_down(&self) -> bool {
+        self.started.load(Ordering::Acquire)
+    }
+
+    /// Returns the number of registered hooks which have not yet run.
+    pub async fn len(&self) -> usize {
+        self.hooks.lock().await.len()
+    }
+
+    /// Returns `true` if there are no registered hooks which have not yet
+    /// run.
+    pub async fn is_empty(&self) -> bool {
+        self.len().await == 0
+    }
+}
+
+impl fmt::Debug for ShutdownHooks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut s = f.debug_struct("ShutdownHooks");
+        match self.hooks.try_lock() {
+            Some(hooks) => s.field("hooks", &hooks.len()),
+            None => s.field("hooks", &format_args!("<locked>")),
+        };
+        s.field("started", &self.is_shutting_down()).finish()
+    }
+}
+
+// === impl ShutdownPriority ===
+
+impl ShutdownPriority {
+    /// Applications and shells, which should hand any unsaved data to the
+    /// services below them.
+    pub const APPLICATION: Self = Self(0x20);
+    /// Network connections, which should be closed cleanly.
+    pub const NETWORK: Self = Self(0x40);
+    /// Services which store data on a filesystem, such as a key-value store.
+    pub const STORAGE_SERVICE: Self = Self(0x60);
+    /// Filesystems, which should write out their caches and metadata.
+    pub const FILESYSTEM: Self = Self(0x80);
+    /// Device drivers, such as block devices, which should finish any
+    /// outstanding writes before they lose power.
+    pub const DRIVER: Self = Self(0xA0);
+
+    /// Returns a priority with the given value. Lower values run first.
+    #[must_use]
+    pub const fn new(priority: u8) -> Self {
+        Self(priority)
+    }
+
+    /// Returns a priority which runs just before `self`.
+    #[must_use]
+    pub const fn before(self) -> Self {
+        Self(self.0.saturating_sub(1))
+    }
+
+    /// Returns a priority which runs just after `self`.
+    #[must_use]
+    pub const fn after(self) -> Self {
+        Self(self.0.saturating_add(1))
+    }
+
+    /// Returns the priority's value.
+    #[must_use]
+    pub const fn get(self) -> u8 {
+        self.0
+    }
+}
+
+// === impl Kernel ===
+
+impl Kernel {
+    /// Returns the kernel's [`ShutdownHooks`].
+    #[inline]
+    #[must_use]
+    pub fn shutdown_hooks(&'static self) -> &'static ShutdownHooks {
+        &self.shutdown
+    }
+
+    /// Registers a hook to run when the kernel shuts down.
+    ///
+    /// `hook` is called when [`Kernel::shutdown`] reaches its `priority`, and
+    /// the future it returns is run to completion, or until the shutdown's
+    /// per-hook timeout elapses. See the [module-level
+    /// documentation](crate::shutdown) for details.
+    #[track_caller]
+    pub fn on_shutdown<F, Fut>(
+        &'static self,
+        priority: ShutdownPriority,
+        hook: F,
+    ) -> impl Future<Output = Result<(), ShutdownError>>
+    where
+        F: FnOnce() -> Fut + 'static,
+        Fut: Future<Output = ()> + 'static,
+    {
+        let location = Location::caller();
+        async move {
+            let hooks = self.shutdown_hooks();
+            if hooks.is_shutting_down() {
+                return Err(ShutdownError::ShuttingDown);
+            }
+            // futures are lazy, so `hook` isn't called until this is first
+            // polled, when its turn comes.
+            let run = async move { hook().await };
+            let run: alloc::boxed::Box<dyn Future<Output = ()>> =
+                Box::new(run).await.into_alloc_box();
+            let mut pending = hooks.hooks.lock().await;
+            // the shutdown may have started while the hook was being
+            // allocated, in which case it has already taken the hooks.
+            if hooks.is_shutting_down() {
+                return Err(ShutdownError::ShuttingDown);
+            }
+            pending
+                .push(Hook {
+                    priority,
+                    location,
+                    run: alloc::boxed::Box::into_pin(run),
+                })
+                .map_err(|_| ShutdownError::Full)?;
+            tracing::debug!(
+                priority = priority.get(),
+                hook.location = %location,
+                "registered shutdown hook"
+            );
+            Ok(())
+        }
+    }
+
+    /// Shuts the kernel down, running every registered shutdown hook in
+    /// [priority](ShutdownPriority) order.
+    ///
+    /// Each hook is given `hook_timeout` to complete, after which it is
+    /// dropped, and the next hook is run. Once this returns, the caller may
+    /// power the system off.
+    ///
+    /// Only the first call runs the hooks; later calls return a
+    /// [`ShutdownReport`] with no hooks run.
+    pub async fn shutdown(&'static self, hook_timeout: Duration) -> ShutdownReport {
+        let hooks = self.shutdown_hooks();
+        let mut report = ShutdownReport::default();
+        if hooks.started.swap(true, Ordering::AcqRel) {
+            tracing::warn!("kernel is already shutting down");
+            return report;
+        }
+
+        let mut pending = core::mem::take(&mut *hooks.hooks.lock().await);
+        // a stable sort keeps hooks with the same priority in registration
+        // order.
+        pending.sort_by_key(|hook| hook.priority);
+        tracing::info!(hooks = pending.len(), "shutting down...");
+
+        let started = self.timer().now();
+        for Hook {
+            priority,
+            location,
+            run,
+        } in pending
+        {
+            let span = tracing::span!(
+                Level::INFO,
+                "shutdown_hook",
+                priority = priority.get(),
+                hook.location = %location,
+            );
+            let hook_started = self.timer().now();
+            match self
+                .timer()
+                .timeout(run, hook_timeout)
+                .instrument(span.clone())
+                .await
+            {
+                Ok(()) => {
+                    report.completed += 1;
+                    let elapsed = self.timer().now().duration_since(hook_started);
+                    span.in_scope(|| {
+                        tracing::debug!(
+                            elapsed_ms = elapsed.as_millis() as u64,
+                            "shutdown hook complete"
+                        )
+                    });
+                }
+                Err(_) => {
+                    report.timed_out += 1;
+                    span.in_scope(
+                        || tracing::error!(timeout = ?hook_timeout, "shutdown hook timed out"),
+                    );
+                }
+            }
+        }
+
+        let elapsed = self.timer().now().duration_since(started);
+        tracing::info!(
+            completed = report.completed,
+            timed_out = report.timed_out,
+            elapsed_ms = elapsed.as_millis() as u64,
+            "shutdown complete in {elapsed:?}"
+        );
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TestKernel;
+    use std::{cell::RefCell, rc::Rc, vec, vec::Vec};
+
+    #[test]
+    fn hooks_run_in_priority_order() {
+        TestKernel::run(|k| async move {
+            let order = Rc::new(RefCell::new(Vec::new()));
+            for (priority, name) in [
+                (ShutdownPriority::DRIVER, "block device"),
+                (ShutdownPriority::STORAGE_SERVICE, "kv store"),
+                (ShutdownPriority::FILESYSTEM, "filesystem"),
+                (ShutdownPriority::FILESYSTEM.after(), "filesystem 2"),
+                (ShutdownPriority::NETWORK, "network"),
+            ] {
+                let order = order.clone();
+                k.on_shutdown(priority, move || async move {
+                    order.borrow_mut().push(name);
+                })
+                .await
+                .unwrap();
+            }
+
+            let report = k.shutdown(Duration::from_secs(1)).await;
+            assert_eq!(
+                report,
+                ShutdownReport {
+                    completed: 5,
+                    timed_out: 0
+                }
+            );
+            assert_eq!(
+                *order.borrow(),
+                vec![
+                    "network",
+                    "kv store",
+                    "filesystem",
+                    "filesystem 2",
+                    "block device"
+                ]
+            );
+            assert!(k.shutdown_hooks().is_empty().await);
+        })
+    }
+
+    #[test]
+    fn no_hooks_after_shutdown() {
+        TestKernel::run(|k| async move {
+            k.shutdown(Duration::from_secs(1)).await;
+            assert!(k.shutdown_hooks().is_shutting_down());
+            assert_eq!(
+                k.on_shutdown(ShutdownPriority::DRIVER, || async {}).await,
+                Err(ShutdownError::ShuttingDown)
+            );
+            assert_eq!(
+                k.shutdown(Duration::from_secs(1)).await,
+                ShutdownReport::default()
+            );
+        })
+    }
+}
@@ -0,0 +1,294 @@
+//! # Task-Local Storage
+//!
+//! The kernel's scheduler does not provide any per-task storage, so state
+//! which belongs to a particular task (rather than to a driver or service)
+//! would otherwise have to live in a global map keyed by task ID.
+//!
+//! Instead, a task-local *key* may be declared using the [`task_local!`]
+//! macro. A value is bound to the key for the duration of a future using
+//! [`LocalKey::scope`], and code running inside that future may then access
+//! the value with [`LocalKey::with`] and friends:
+//!
+//! ```rust,ignore
+//! kernel::task_local! {
+//!     /// The name of the current shell session.
+//!     static SESSION: &'static str;
+//! }
+//!
+//! k.spawn(SESSION.scope("tty0", async {
+//!     SESSION.with(|name| tracing::info!(session = name, "hello!"));
+//! }))
+//! .await;
+//! ```
+//!
+//! The value is stored in the [`TaskLocalFuture`] returned by `scope`, and is
+//! only bound to the key while that future is being polled. Scopes for the
+//! same key may be nested; the innermost scope's value is visible.
+//!
+//! The value is protected by a spinlock while it is accessed, so accessing a
+//! key from within a closure passed to [`LocalKey::with`] for the *same* key
+//! (or from an interrupt handler that preempts such a closure) fails, rather
+//! than deadlocking.
+//!
+//! ## Single-core only
+//!
+//! Each key has a single slot, which holds the value of whichever scope is
+//! currently being polled. This relies on the kernel's scheduler, which is a
+//! [`LocalScheduler`], polling at most one task at a time. If futures were
+//! polled concurrently on several cores (as a work-stealing scheduler on an
+//! SMP system might), one task could observe another's value.
+//!
+//! To enforce this, [`TaskLocalFuture`]s are `!Send`, so they can only be
+//! spawned on a scheduler which never moves tasks between cores. Platforms
+//! which run a scheduler per core must not bind the same key on more than
+//! one of them.
+//!
+//! [`LocalScheduler`]: maitake::scheduler::LocalScheduler
+
+use core::{
+    fmt,
+    future::Future,
+    marker::PhantomData,
+    mem,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use maitake::sync::{blocking::Mutex, spin::Spinlock};
+
+/// Declares one or more task-local [`LocalKey`]s.
+///
+/// # Examples
+///
+/// ```rust,ignore
+/// kernel::task_local! {
+///     static REQUEST_ID: u32;
+///     pub static USER: &'static str;
+/// }
+/// ```
+#[macro_export]
+macro_rules! task_local {
+    () => {};
+    ($(#[$attr:meta])* $vis:vis static $name:ident: $t:ty; $($rest:tt)*) => {
+        $(#[$attr])*
+        $vis static $name: $crate::task_local::LocalKey<$t> =
+            $crate::task_local::LocalKey::new();
+        $crate::task_local!($($rest)*);
+    };
+    ($(#[$attr:meta])* $vis:vis static $name:ident: $t:ty) => {
+        $crate::task_local!($(#[$attr])* $vis static $name: $t;);
+    };
+}
+
+/// A key for task-local data, declared using the [`task_local!`] macro.
+pub struct LocalKey<T: 'static> {
+    slot: Mutex<Option<T>, Spinlock>,
+}
+
+/// A future which binds a value to a [`LocalKey`] while it is polled.
+///
+/// This is returned by [`LocalKey::scope`].
+///
+/// A `TaskLocalFuture` is never `Send`; see [the module
+/// docs](self#single-core-only) for why.
+#[must_use = "futures do nothing unless `.await`ed or polled"]
+pub struct TaskLocalFuture<T: 'static, F> {
+    key: &'static LocalKey<T>,
+    /// The value, while it is *not* bound to the key.
+    value: Option<T>,
+    future: F,
+    /// Keys have one slot shared by every core, so this future must not be
+    /// polled on a scheduler that may move it to another core.
+    _not_send: PhantomData<*mut ()>,
+}
+
+/// Errors returned when a task-local value cannot be accessed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessError {
+    /// No value is bound to the key, because we are not inside a
+    /// [`LocalKey::scope`] for it.
+    NotSet,
+    /// The value is already being accessed, either from an enclosing call to
+    /// [`LocalKey::with`] or by an interrupt handler.
+    Busy,
+}
+
+// === impl LocalKey ===
+
+impl<T: 'static> LocalKey<T> {
+    #[doc(hidden)]
+    #[must_use]
+    #[allow(clippy::new_without_default)]
+    pub const fn new() -> Self {
+        Self {
+            slot: Mutex::new_with_raw_mutex(None, Spinlock::new()),
+        }
+    }
+
+    /// Binds `value` to this key while `future` is polled.
+    pub fn scope<F: Future>(&'static self, value: T, future: F) -> TaskLocalFuture<T, F> {
+        TaskLocalFuture {
+            key: self,
+            value: Some(value),
+            future,
+            _not_send: PhantomData,
+        }
+    }
+
+    /// Calls `f` with a reference to this key's current value.
+    ///
+    /// # Panics
+    ///
+    /// If the value cannot be accessed. See [`LocalKey::try_with`] for a
+    /// non-panicking version.
+    #[track_caller]
+    pub fn with<R>(&'static self, f: impl FnOnce(&T) -> R) -> R {
+        match self.try_with(f) {
+            Ok(r) => r,
+            Err(error) => panic!("cannot access task-local value: {error}"),
+        }
+    }
+
+    /// Calls `f` with a reference to this key's current value, or returns an
+    /// [`AccessError`] if it cannot be accessed.
+    pub fn try_with<R>(&'static self, f: impl FnOnce(&T) -> R) -> Result<R, AccessError> {
+        self.try_with_mut(|value| f(value))
+    }
+
+    /// Calls `f` with a mutable reference to this key's current value, or
+    /// returns an [`AccessError`] if it cannot be accessed.
+    ///
+    /// Changes to the value are visible for the rest of the enclosing
+    /// [`LocalKey::scope`].
+    pub fn try_with_mut<R>(&'static self, f: impl FnOnce(&mut T) -> R) -> Result<R, AccessError> {
+        let mut slot = self.slot.try_lock().ok_or(AccessError::Busy)?;
+        let value = slot.as_mut().ok_or(AccessError::NotSet)?;
+        Ok(f(value))
+    }
+
+    /// Returns a clone of this key's current value.
+    ///
+    /// # Panics
+    ///
+    /// If the value cannot be accessed.
+    #[track_caller]
+    #[must_use]
+    pub fn get(&'static self) -> T
+    where
+        T: Clone,
+    {
+        self.with(T::clone)
+    }
+
+    /// Swaps `value` with the value currently bound to this key.
+    fn swap(&'static self, value: &mut Option<T>) {
+        // This lock is only contended if the key is accessed by an ISR, which
+        // will release it shortly.
+        let mut slot = loop {
+            if let Some(slot) = self.slot.try_lock() {
+                break slot;
+            }
+            core::hint::spin_loop();
+        };
+        mem::swap(&mut *slot, value);
+    }
+}
+
+impl<T: 'static> fmt::Debug for LocalKey<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LocalKey")
+            .field("type", &core::any::type_name::<T>())
+            .finish_non_exhaustive()
+    }
+}
+
+// === impl TaskLocalFuture ===
+
+impl<T: 'static, F: Future> Future for TaskLocalFuture<T, F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        /// Puts the value back into the future once the inner future has
+        /// been polled, even if polling it panicked.
+        struct Unbind<'a, T: 'static> {
+            key: &'static LocalKey<T>,
+            value: &'a mut Option<T>,
+        }
+
+        impl<T: 'static> Drop for Unbind<'_, T> {
+            fn drop(&mut self) {
+                self.key.swap(self.value);
+            }
+        }
+
+        // Safety: `future` is structurally pinned; it is never moved out of
+        // `self`. `key` and `value` are not pinned.
+        let this = unsafe { self.get_unchecked_mut() };
+        let future = unsafe { Pin::new_unchecked(&mut this.future) };
+
+        this.key.swap(&mut this.value);
+        let _unbind = Unbind {
+            key: this.key,
+            value: &mut this.value,
+        };
+        future.poll(cx)
+    }
+}
+
+impl<T: fmt::Debug + 'static, F> fmt::Debug for TaskLocalFuture<T, F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TaskLocalFuture")
+            .field("key", self.key)
+            .field("value", &self.value)
+            .finish_non_exhaustive()
+    }
+}
+
+// === impl AccessError ===
+
+impl fmt::Display for AccessError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotSet => f.write_str("no value is set for this task-local key"),
+            Self::Busy => f.write_str("task-local value is already being accessed"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TestKernel;
+
+    crate::task_local! {
+        static NAME: &'static str;
+    }
+
+    #[test]
+    fn scopes_are_per_task() {
+        TestKernel::run(|k| async move {
+            assert_eq!(NAME.try_with(|_| ()), Err(AccessError::NotSet));
+
+            let a = k
+                .spawn(NAME.scope("a", async move {
+                    // let `b` run while `a` is still in its scope.
+                    for _ in 0..4 {
+                        k.yield_now().await;
+                    }
+                    NAME.get()
+                }))
+                .await;
+            let b = k
+                .spawn(NAME.scope("b", async move {
+                    let outer = NAME.get();
+                    let inner = NAME.scope("c", async { NAME.get() }).await;
+                    (outer, inner, NAME.get())
+                }))
+                .await;
+
+            assert_eq!(a.await.unwrap(), "a");
+            assert_eq!(b.await.unwrap(), ("b", "c", "b"));
+            assert_eq!(NAME.try_with(|_| ()), Err(AccessError::NotSet));
+        })
+    }
+}
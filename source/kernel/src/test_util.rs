@@ -1,6 +1,7 @@
 use super::*;
 
 use mnemos_alloc::heap::MnemosAlloc;
+use sermux_proto::{Frame, FrameVersion};
 use std::{
     future::Future,
     ptr::NonNull,
@@ -81,6 +82,90 @@ impl TestKernel {
     }
 }
 
+/// The host's end of a [serial mux](crate::services::serial_mux)'s serial
+/// port, for testing services which talk to the host.
+pub(crate) struct SerialHost {
+    io: comms::bbq::BidiHandle,
+    /// Data received from the target which hasn't been decoded yet.
+    carry: Vec<u8>,
+}
+
+impl SerialHost {
+    /// Registers a serial mux, and returns the host's end of its serial port.
+    pub(crate) async fn setup(k: &'static Kernel) -> Self {
+        Self::setup_with(k, Default::default()).await
+    }
+
+    /// Registers a serial mux with the given settings, and returns the host's
+    /// end of its serial port.
+    pub(crate) async fn setup_with(
+        k: &'static Kernel,
+        settings: services::serial_mux::SerialMuxSettings,
+    ) -> Self {
+        use services::{
+            serial_mux::SerialMuxServer,
+            simple_serial::{self, SimpleSerialService},
+        };
+
+        let (port, io) = comms::bbq::new_bidi_channel(1024, 1024).await;
+        let reqs = k
+            .registry()
+            .bind_konly::<SimpleSerialService>(1)
+            .await
+            .unwrap()
+            .into_request_stream(1)
+            .await;
+        k.spawn(async move {
+            let req = reqs.next_request().await;
+            let resp = req
+                .msg
+                .reply_with(Ok(simple_serial::Response::PortHandle { handle: port }));
+            req.reply.reply_konly(resp).await.map_err(drop).unwrap();
+            core::future::pending::<()>().await
+        })
+        .await;
+        SerialMuxServer::register(k, settings).await.unwrap();
+        Self {
+            io,
+            carry: Vec::new(),
+        }
+    }
+
+    /// Sends `chunk` to the target's `port`, as a single frame.
+    pub(crate) async fn send(&self, port: impl Into<u16>, chunk: &[u8]) {
+        self.send_frame(Frame::new(FrameVersion::V1, port, chunk))
+            .await;
+    }
+
+    /// Sends `frame` to the target.
+    pub(crate) async fn send_frame(&self, frame: Frame<'_>) {
+        let mut buf = vec![0; frame.buffer_required()];
+        let encoded = frame.encode_to(&mut buf).unwrap();
+        self.io.producer().write_all(encoded).await;
+    }
+
+    /// Returns the data in the next frame the target sends on `port`,
+    /// skipping frames sent on other ports.
+    pub(crate) async fn recv(&mut self, port: impl Into<u16>) -> Vec<u8> {
+        let port = port.into();
+        loop {
+            while let Some(pos) = self.carry.iter().position(|&b| b == 0) {
+                let rest = self.carry.split_off(pos + 1);
+                let mut frame = core::mem::replace(&mut self.carry, rest);
+                if let Ok(frame) = Frame::decode_from(&mut frame) {
+                    if frame.port == port {
+                        return frame.chunk.to_vec();
+                    }
+                }
+            }
+            let rgr = self.io.consumer().read_grant().await;
+            self.carry.extend_from_slice(&rgr);
+            let len = rgr.len();
+            rgr.release(len);
+        }
+    }
+}
+
 fn trace_init() {
     use tracing_subscriber::{
         filter::{EnvFilter, LevelFilter},
@@ -0,0 +1,161 @@
+//! # Timer Wheel Diagnostics
+//!
+//! Platform run loops turn the kernel's timer wheel after each scheduler
+//! tick, and then sleep until [`Turn::ticks_to_next_deadline`]. On some
+//! platforms (notably the Allwinner D1), a turn sometimes reports no next
+//! deadline even though there are pending sleeps, so the run loop has to fall
+//! back to sleeping for a fixed cap, and those sleeps wake up late.
+//!
+//! To help characterize this, run loops turn the wheel with
+//! [`Kernel::turn_timer`], and compute how long to sleep with
+//! [`Kernel::ticks_to_next_deadline`], rather than using the [`Timer`]
+//! directly. These record the counters returned by [`Kernel::timer_stats`],
+//! and emit a `DEBUG` event each time the fallback cap is used or a deadline
+//! appears to have been missed.
+//!
+//! [`Kernel::turn_timer`]: crate::Kernel::turn_timer
+//! [`Kernel::ticks_to_next_deadline`]: crate::Kernel::ticks_to_next_deadline
+//! [`Kernel::timer_stats`]: crate::Kernel::timer_stats
+//! [`Timer`]: maitake::time::Timer
+
+use core::time::Duration;
+
+use maitake::time::timer::Turn;
+use portable_atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+
+/// Counters describing how the kernel's timer wheel has been turned.
+///
+/// This is returned by [`Kernel::timer_stats`](crate::Kernel::timer_stats).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TimerStats {
+    /// The number of times the timer wheel has been turned.
+    pub turns: usize,
+    /// The total number of timers expired by those turns.
+    pub expired: usize,
+    /// The number of turns which reported no next deadline.
+    pub no_deadline: usize,
+    /// The number of turns which expired timers, although the previous turn
+    /// reported no next deadline.
+    ///
+    /// Each of these is a deadline which a run loop trusting the previous
+    /// turn would have slept through.
+    pub missed_deadlines: usize,
+    /// The number of times a run loop fell back to sleeping for its cap,
+    /// because a turn reported no next deadline.
+    pub fallback_sleeps: usize,
+    /// The most the timer has advanced between two consecutive turns.
+    pub max_advance: Duration,
+}
+
+/// The counters behind [`TimerStats`], stored in the kernel.
+pub(crate) struct TimerCounters {
+    turns: AtomicUsize,
+    expired: AtomicUsize,
+    no_deadline: AtomicUsize,
+    missed_deadlines: AtomicUsize,
+    fallback_sleeps: AtomicUsize,
+    max_advance_us: AtomicU64,
+    /// The time of the last turn, in microseconds since the kernel booted.
+    last_turn_us: AtomicU64,
+    /// Whether the last turn reported no next deadline.
+    last_had_no_deadline: AtomicBool,
+}
+
+// === impl TimerCounters ===
+
+impl TimerCounters {
+    pub(crate) const fn new() -> Self {
+        Self {
+            turns: AtomicUsize::new(0),
+            expired: AtomicUsize::new(0),
+            no_deadline: AtomicUsize::new(0),
+            missed_deadlines: AtomicUsize::new(0),
+            fallback_sleeps: AtomicUsize::new(0),
+            max_advance_us: AtomicU64::new(0),
+            last_turn_us: AtomicU64::new(0),
+            last_had_no_deadline: AtomicBool::new(false),
+        }
+    }
+
+    /// Records a `turn` of the timer wheel, which happened `now` (since the
+    /// kernel booted).
+    pub(crate) fn record_turn(&self, turn: &Turn, now: Duration) {
+        self.record(turn.expired, turn.ticks_to_next_deadline().is_some(), now);
+    }
+
+    fn record(&self, expired: usize, has_deadline: bool, now: Duration) {
+        self.turns.fetch_add(1, Ordering::Relaxed);
+        self.expired.fetch_add(expired, Ordering::Relaxed);
+
+        let now_us = now.as_micros() as u64;
+        let advance_us = now_us.saturating_sub(self.last_turn_us.swap(now_us, Ordering::Relaxed));
+        self.max_advance_us.fetch_max(advance_us, Ordering::Relaxed);
+
+        let last_had_no_deadline = self
+            .last_had_no_deadline
+            .swap(!has_deadline, Ordering::Relaxed);
+        if last_had_no_deadline && expired > 0 {
+            self.missed_deadlines.fetch_add(1, Ordering::Relaxed);
+            tracing::debug!(
+                expired,
+                advance_us,
+                "timer wheel expired timers after reporting no next deadline"
+            );
+        }
+        if !has_deadline {
+            self.no_deadline.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Records that a run loop fell back to sleeping for `fallback` ticks.
+    pub(crate) fn record_fallback(&self, fallback: u64) {
+        let fallback_sleeps = self.fallback_sleeps.fetch_add(1, Ordering::Relaxed) + 1;
+        tracing::debug!(
+            fallback,
+            fallback_sleeps,
+            "no next timer deadline, sleeping for the fallback cap"
+        );
+    }
+
+    pub(crate) fn stats(&self) -> TimerStats {
+        TimerStats {
+            turns: self.turns.load(Ordering::Relaxed),
+            expired: self.expired.load(Ordering::Relaxed),
+            no_deadline: self.no_deadline.load(Ordering::Relaxed),
+            missed_deadlines: self.missed_deadlines.load(Ordering::Relaxed),
+            fallback_sleeps: self.fallback_sleeps.load(Ordering::Relaxed),
+            max_advance: Duration::from_micros(self.max_advance_us.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_missed_deadlines() {
+        let counters = TimerCounters::new();
+        counters.record(0, true, Duration::from_millis(1));
+        counters.record(2, false, Duration::from_millis(3));
+        // expiring timers after a turn with no deadline is a miss...
+        counters.record(1, true, Duration::from_millis(10));
+        // ...but expiring them after a turn with a deadline is not.
+        counters.record(1, false, Duration::from_millis(11));
+        // and neither is a turn with no deadline that expires nothing.
+        counters.record(0, true, Duration::from_millis(12));
+        counters.record_fallback(100);
+
+        assert_eq!(
+            counters.stats(),
+            TimerStats {
+                turns: 5,
+                expired: 4,
+                no_deadline: 2,
+                missed_deadlines: 1,
+                fallback_sleeps: 1,
+                max_advance: Duration::from_millis(7),
+            }
+        );
+    }
+}
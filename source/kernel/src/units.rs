@@ -0,0 +1,224 @@
+//! # Units of Time
+//!
+//! Hardware timers count in ticks, and the length of a tick depends on the
+//! platform: the D1's `TIMER0` ticks three million times a second, the
+//! ESP32-C3's kernel clock eight million times, and Melpomene's once a
+//! microsecond. A bare `u64` tick count doesn't say which of these it is
+//! counting, so it is easy to pass one timer's ticks to another, or to
+//! confuse ticks with microseconds.
+//!
+//! This module provides types which carry their units with them:
+//!
+//! - [`Ticks<HZ>`](Ticks) counts ticks of a timer whose rate is known at
+//!   compile time, such as a platform's hardware timers.
+//! - [`KernelDuration`] counts ticks of the kernel's timer, whose rate is set
+//!   by the platform's [`Clock`] at runtime, and is returned by the kernel's
+//!   timer APIs, such as [`Kernel::ticks_to_next_deadline`].
+//!
+//! Conversions between them, and to and from [`Duration`]s, are always
+//! explicit, and return [`None`] rather than overflowing.
+//!
+//! [`Clock`]: maitake::time::Clock
+//! [`Kernel::ticks_to_next_deadline`]: crate::Kernel::ticks_to_next_deadline
+
+use core::time::Duration;
+
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+
+/// A number of ticks of a timer which ticks `HZ` times a second.
+///
+/// `HZ` must be between 1 and 1,000,000,000, so that each tick is at least a
+/// nanosecond long.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Ticks<const HZ: u64>(u64);
+
+/// A span of time, measured in ticks of the kernel's timer.
+///
+/// The length of the kernel timer's ticks is only known at runtime, so a
+/// `KernelDuration` carries it along with the number of ticks. It is returned
+/// by [`Kernel::ticks_to_next_deadline`](crate::Kernel::ticks_to_next_deadline).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KernelDuration {
+    ticks: u64,
+    tick_duration: Duration,
+}
+
+// === impl Ticks ===
+
+impl<const HZ: u64> Ticks<HZ> {
+    /// The number of ticks per second.
+    pub const HZ: u64 = {
+        assert!(HZ > 0, "a timer must tick at least once a second");
+        assert!(HZ <= NANOS_PER_SEC, "a tick must be at least 1ns long");
+        HZ
+    };
+
+    /// The length of a tick, rounded down to a whole number of nanoseconds.
+    ///
+    /// This is the tick length to construct a [`Clock`] for this timer with.
+    ///
+    /// [`Clock`]: maitake::time::Clock
+    pub const TICK_DURATION: Duration = Duration::from_nanos(NANOS_PER_SEC / Self::HZ);
+
+    /// No ticks.
+    pub const ZERO: Self = Self(0);
+
+    /// The most ticks that can be counted.
+    pub const MAX: Self = Self(u64::MAX);
+
+    /// Returns `ticks` ticks.
+    #[must_use]
+    pub const fn new(ticks: u64) -> Self {
+        Self(ticks)
+    }
+
+    /// Returns the number of ticks.
+    #[must_use]
+    pub const fn get(self) -> u64 {
+        self.0
+    }
+
+    /// Returns the number of whole ticks in `duration`, or [`None`] if there
+    /// are more than [`Ticks::MAX`].
+    #[must_use]
+    pub const fn from_duration(duration: Duration) -> Option<Self> {
+        let ticks = duration.as_nanos() * Self::HZ as u128 / NANOS_PER_SEC as u128;
+        if ticks > u64::MAX as u128 {
+            return None;
+        }
+        Some(Self(ticks as u64))
+    }
+
+    /// Returns the number of whole ticks in `duration`, or [`Ticks::MAX`] if
+    /// there are more than that.
+    #[must_use]
+    pub const fn from_duration_saturating(duration: Duration) -> Self {
+        match Self::from_duration(duration) {
+            Some(ticks) => ticks,
+            None => Self::MAX,
+        }
+    }
+
+    /// Returns how long these ticks take, rounded down to a whole number of
+    /// nanoseconds.
+    #[must_use]
+    pub const fn to_duration(self) -> Duration {
+        let nanos = self.0 as u128 * NANOS_PER_SEC as u128 / Self::HZ as u128;
+        // `HZ` is at least 1, so the number of seconds fits in a `u64`.
+        Duration::new(
+            (nanos / NANOS_PER_SEC as u128) as u64,
+            (nanos % NANOS_PER_SEC as u128) as u32,
+        )
+    }
+
+    /// Converts these ticks to the number of whole ticks of a timer which
+    /// ticks `TO` times a second, or [`None`] if there would be more than
+    /// [`Ticks::MAX`].
+    #[must_use]
+    pub const fn convert<const TO: u64>(self) -> Option<Ticks<TO>> {
+        let ticks = self.0 as u128 * Ticks::<TO>::HZ as u128 / Self::HZ as u128;
+        if ticks > u64::MAX as u128 {
+            return None;
+        }
+        Some(Ticks(ticks as u64))
+    }
+}
+
+// === impl KernelDuration ===
+
+impl KernelDuration {
+    pub(crate) const fn from_ticks(ticks: u64, tick_duration: Duration) -> Self {
+        Self {
+            ticks,
+            tick_duration,
+        }
+    }
+
+    /// Returns the number of whole kernel timer ticks in `duration`, or
+    /// `u64::MAX` ticks if there are more than that.
+    pub(crate) fn from_duration_saturating(duration: Duration, tick_duration: Duration) -> Self {
+        let ticks = duration.as_nanos() / tick_duration.as_nanos().max(1);
+        Self::from_ticks(u64::try_from(ticks).unwrap_or(u64::MAX), tick_duration)
+    }
+
+    /// Returns the number of kernel timer ticks.
+    #[must_use]
+    pub const fn ticks(self) -> u64 {
+        self.ticks
+    }
+
+    /// Returns the length of a kernel timer tick.
+    #[must_use]
+    pub const fn tick_duration(self) -> Duration {
+        self.tick_duration
+    }
+
+    /// Returns how long this is, or [`Duration::MAX`] if it is longer than
+    /// that.
+    #[must_use]
+    pub fn to_duration(self) -> Duration {
+        let nanos = self.ticks as u128 * self.tick_duration.as_nanos();
+        match u64::try_from(nanos / NANOS_PER_SEC as u128) {
+            Ok(secs) => Duration::new(secs, (nanos % NANOS_PER_SEC as u128) as u32),
+            Err(_) => Duration::MAX,
+        }
+    }
+
+    /// Converts this to the number of whole ticks of a timer which ticks `HZ`
+    /// times a second, or [`None`] if there would be more than
+    /// [`Ticks::MAX`].
+    ///
+    /// If the kernel's timer ticks at the same rate (that is, its tick length
+    /// is [`Ticks::TICK_DURATION`]), the number of ticks is returned as it
+    /// is, even if the tick length was rounded.
+    #[must_use]
+    pub fn to_ticks<const HZ: u64>(self) -> Option<Ticks<HZ>> {
+        if self.tick_duration == Ticks::<HZ>::TICK_DURATION {
+            return Some(Ticks::new(self.ticks));
+        }
+        if self.to_duration() == Duration::MAX {
+            return None;
+        }
+        Ticks::from_duration(self.to_duration())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type D1Ticks = Ticks<3_000_000>;
+
+    #[test]
+    fn ticks_and_durations() {
+        assert_eq!(D1Ticks::TICK_DURATION, Duration::from_nanos(333));
+        let ticks = D1Ticks::from_duration(Duration::from_millis(100)).unwrap();
+        assert_eq!(ticks.get(), 300_000);
+        assert_eq!(ticks.to_duration(), Duration::from_millis(100));
+        assert_eq!(ticks.convert::<16_000_000>().unwrap().get(), 1_600_000);
+        assert_eq!(D1Ticks::MAX.convert::<16_000_000>(), None);
+        assert_eq!(D1Ticks::from_duration(Duration::MAX), None);
+        assert_eq!(
+            D1Ticks::from_duration_saturating(Duration::MAX),
+            D1Ticks::MAX
+        );
+    }
+
+    #[test]
+    fn kernel_durations() {
+        // a kernel timer with the D1's rounded-down tick length.
+        let tick = D1Ticks::TICK_DURATION;
+        let sleep = KernelDuration::from_duration_saturating(Duration::from_millis(100), tick);
+        assert_eq!(sleep.ticks(), 300_300);
+        // the same timer's ticks are passed through unchanged...
+        assert_eq!(sleep.to_ticks::<3_000_000>(), Some(D1Ticks::new(300_300)));
+        // ...and other timers' are converted.
+        assert_eq!(
+            sleep.to_ticks::<1_000_000>(),
+            Some(Ticks::new(sleep.to_duration().as_micros() as u64))
+        );
+        let forever = KernelDuration::from_ticks(u64::MAX, Duration::from_secs(2));
+        assert_eq!(forever.to_duration(), Duration::MAX);
+        assert_eq!(forever.to_ticks::<1_000_000>(), None);
+    }
+}
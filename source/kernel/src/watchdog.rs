@@ -0,0 +1,470 @@
+//! # Starvation Watchdog
+//!
+//! MnemOS tasks are scheduled cooperatively: once a task is polled, nothing
+//! else runs (including the tasks handling keyboard and serial input) until
+//! it returns. A task which does a lot of work in a single poll, such as a
+//! long-running Forth builtin or a full display redraw, delays every other
+//! task, causing latency spikes that are hard to attribute.
+//!
+//! In debug builds, every task spawned with [`Kernel::spawn`] or
+//! [`Kernel::initialize`] is timed each time it is polled. Once a task has run
+//! longer than the watchdog's budget for [`WatchdogSettings::consecutive_polls`]
+//! polls in a row, its long polls are recorded, and the [`report`] daemon
+//! periodically logs a warning listing the worst offenders since its last
+//! report. Tasks are identified by their task ID, which is the number of tasks
+//! the kernel had spawned before them (see [`Kernel::task_counts`]), and their
+//! name, which is the type name of the task's future. For an `async fn`, this
+//! is the function's path.
+//!
+//! A task that needs to do a lot of work should periodically await
+//! [`Kernel::yield_now`], which lets other tasks run before continuing.
+//!
+//! Poll durations are measured with the kernel's timer, so polls shorter than
+//! the timer's tick duration may not be detected on platforms with coarse
+//! clocks.
+
+use core::{
+    any, fmt,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use maitake::{
+    sync::{blocking::Mutex, spin::Spinlock},
+    time::Duration,
+};
+use portable_atomic::{AtomicU64, AtomicUsize, Ordering};
+use serde::{Deserialize, Serialize};
+
+use crate::Kernel;
+
+/// The maximum number of offending tasks recorded between reports.
+pub const MAX_OFFENDERS: usize = 8;
+
+/// Records tasks whose polls exceed a time budget.
+pub struct Watchdog {
+    /// The poll budget, in nanoseconds. Zero if the watchdog is disabled.
+    max_poll_nanos: AtomicU64,
+    /// How many polls in a row must exceed the budget before a task is
+    /// recorded.
+    consecutive_polls: AtomicUsize,
+    offenders: Mutex<heapless::Vec<Offender, MAX_OFFENDERS>, Spinlock>,
+}
+
+/// A task which exceeded the watchdog's poll budget.
+#[derive(Debug, Clone, Copy)]
+pub struct Offender {
+    /// The task's ID. Tasks are numbered in the order they were spawned.
+    pub id: usize,
+    /// The task's name.
+    pub name: &'static str,
+    /// The task's longest poll.
+    pub longest: Duration,
+    /// How many of the task's polls were recorded.
+    pub long_polls: usize,
+    /// The most polls in a row which exceeded the budget.
+    pub streak: usize,
+}
+
+/// A future which is timed by the [`Watchdog`] each time it is polled.
+///
+/// This is returned by [`Watchdog::watch`].
+#[must_use = "futures do nothing unless `.await`ed or polled"]
+pub struct Watched<F> {
+    kernel: &'static Kernel,
+    id: usize,
+    name: &'static str,
+    /// How many polls in a row have exceeded the budget.
+    streak: usize,
+    future: F,
+}
+
+/// A future which yields to the scheduler once before completing.
+///
+/// This is returned by [`Kernel::yield_now`].
+#[must_use = "futures do nothing unless `.await`ed or polled"]
+#[derive(Debug)]
+pub struct YieldNow {
+    yielded: bool,
+}
+
+/// Settings for the starvation watchdog.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WatchdogSettings {
+    /// Should the watchdog be enabled? It only runs in debug builds.
+    #[serde(default = "WatchdogSettings::default_enabled")]
+    pub enabled: bool,
+    /// Polls longer than this are reported. Defaults to 10ms.
+    #[serde(default = "WatchdogSettings::default_max_poll")]
+    pub max_poll: Duration,
+    /// A task is only reported once this many of its polls in a row are
+    /// longer than `max_poll`. Defaults to 1, reporting every long poll.
+    /// Raise this to ignore tasks which only occasionally run long.
+    #[serde(default = "WatchdogSettings::default_consecutive_polls")]
+    pub consecutive_polls: usize,
+    /// How often to log the worst offenders. Defaults to 5 seconds.
+    #[serde(default = "WatchdogSettings::default_report_interval")]
+    pub report_interval: Duration,
+}
+
+/// Periodically logs a warning listing the tasks whose polls exceeded the
+/// budget in `settings`.
+///
+/// The watchdog is disabled until this daemon is started.
+#[tracing::instrument(skip(kernel))]
+pub async fn report(kernel: &'static Kernel, settings: WatchdogSettings) {
+    let watchdog = kernel.watchdog();
+    watchdog.set_consecutive_polls(settings.consecutive_polls);
+    watchdog.set_max_poll(Some(settings.max_poll));
+    tracing::info!(
+        max_poll = ?settings.max_poll,
+        consecutive_polls = settings.consecutive_polls,
+        "starvation watchdog running"
+    );
+
+    loop {
+        let interval = settings.report_interval;
+        kernel.sleep_with_slack(interval, interval / 2).await;
+        let mut offenders = watchdog.take_offenders();
+        if offenders.is_empty() {
+            continue;
+        }
+
+        offenders.sort_unstable_by(|a, b| b.longest.cmp(&a.longest));
+        tracing::warn!(
+            tasks = offenders.len(),
+            max_poll = ?settings.max_poll,
+            "tasks ran too long without yielding"
+        );
+        for Offender {
+            id,
+            name,
+            longest,
+            long_polls,
+            streak,
+        } in offenders
+        {
+            tracing::warn!(
+                task.id = id,
+                task.name = name,
+                ?longest,
+                long_polls,
+                streak,
+                "  task starved the scheduler"
+            );
+        }
+    }
+}
+
+// === impl Watchdog ===
+
+impl Watchdog {
+    pub(crate) const fn new() -> Self {
+        Self {
+            max_poll_nanos: AtomicU64::new(0),
+            consecutive_polls: AtomicUsize::new(1),
+            offenders: Mutex::new_with_raw_mutex(heapless::Vec::new(), Spinlock::new()),
+        }
+    }
+
+    /// Sets the poll budget, or disables the watchdog if `max_poll` is
+    /// `None`.
+    pub fn set_max_poll(&self, max_poll: Option<Duration>) {
+        let nanos = max_poll.map_or(0, |max| max.as_nanos().clamp(1, u64::MAX as u128) as u64);
+        self.max_poll_nanos.store(nanos, Ordering::Relaxed);
+    }
+
+    /// Sets how many polls in a row must exceed the poll budget before a task
+    /// is recorded. Zero is treated as one.
+    pub fn set_consecutive_polls(&self, polls: usize) {
+        self.consecutive_polls
+            .store(polls.max(1), Ordering::Relaxed);
+    }
+
+    /// Returns the tasks which have exceeded the poll budget since the last
+    /// call to `take_offenders`.
+    ///
+    /// If more than [`MAX_OFFENDERS`] tasks exceeded the budget, only those
+    /// with the longest polls are returned.
+    pub fn take_offenders(&self) -> heapless::Vec<Offender, MAX_OFFENDERS> {
+        core::mem::take(&mut *self.offenders.lock())
+    }
+
+    /// Wraps `future` so that it is timed each time it is polled.
+    ///
+    /// This is called by the kernel when spawning tasks in debug builds.
+    pub(crate) fn watch<F: Future>(
+        &self,
+        kernel: &'static Kernel,
+        id: usize,
+        future: F,
+    ) -> Watched<F> {
+        Watched {
+            kernel,
+            id,
+            name: task_name::<F>(),
+            streak: 0,
+            future,
+        }
+    }
+
+    fn max_poll(&self) -> Option<Duration> {
+        match self.max_poll_nanos.load(Ordering::Relaxed) {
+            0 => None,
+            nanos => Some(Duration::from_nanos(nanos)),
+        }
+    }
+
+    fn record(&self, id: usize, name: &'static str, elapsed: Duration, streak: usize) {
+        // If an ISR is holding the lock, just drop this sample.
+        let Some(mut offenders) = self.offenders.try_lock() else {
+            return;
+        };
+
+        if let Some(offender) = offenders.iter_mut().find(|o| o.id == id) {
+            offender.longest = offender.longest.max(elapsed);
+            offender.long_polls += 1;
+            offender.streak = offender.streak.max(streak);
+            return;
+        }
+
+        let offender = Offender {
+            id,
+            name,
+            longest: elapsed,
+            long_polls: 1,
+            streak,
+        };
+        if let Err(offender) = offenders.push(offender) {
+            // Replace the offender with the shortest longest poll, if this
+            // one is worse.
+            if let Some(least) = offenders.iter_mut().min_by_key(|o| o.longest) {
+                if least.longest < offender.longest {
+                    *least = offender;
+                }
+            }
+        }
+    }
+}
+
+/// Returns a name for a task whose future is an `F`.
+fn task_name<F>() -> &'static str {
+    let name = any::type_name::<F>();
+    // Most tasks are `#[tracing::instrument]`ed `async fn`s, so name them
+    // after the function rather than the wrapper.
+    let name = name
+        .strip_prefix("tracing::instrument::Instrumented<")
+        .and_then(|name| name.strip_suffix('>'))
+        .unwrap_or(name);
+    name.strip_suffix("::{{closure}}").unwrap_or(name)
+}
+
+impl fmt::Debug for Watchdog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Watchdog")
+            .field("max_poll", &self.max_poll())
+            .finish_non_exhaustive()
+    }
+}
+
+// === impl Watched ===
+
+impl<F: Future> Future for Watched<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // Safety: we never move `future` out of `self`.
+        let this = unsafe { self.get_unchecked_mut() };
+        let future = unsafe { Pin::new_unchecked(&mut this.future) };
+
+        let watchdog = this.kernel.watchdog();
+        let Some(max_poll) = watchdog.max_poll() else {
+            return future.poll(cx);
+        };
+
+        let timer = this.kernel.timer();
+        let started = timer.now();
+        let poll = future.poll(cx);
+        let elapsed = timer.now().duration_since(started);
+        if elapsed > max_poll {
+            this.streak += 1;
+            if this.streak >= watchdog.consecutive_polls.load(Ordering::Relaxed) {
+                watchdog.record(this.id, this.name, elapsed, this.streak);
+            }
+        } else {
+            this.streak = 0;
+        }
+        poll
+    }
+}
+
+// === impl YieldNow ===
+
+impl YieldNow {
+    pub(crate) const fn new() -> Self {
+        Self { yielded: false }
+    }
+}
+
+impl Future for YieldNow {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if self.yielded {
+            return Poll::Ready(());
+        }
+
+        // Wake ourselves, so that we're polled again once every other task
+        // that's ready has had a chance to run.
+        self.yielded = true;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
+
+// === impl WatchdogSettings ===
+
+impl WatchdogSettings {
+    pub const DEFAULT_MAX_POLL: Duration = Duration::from_millis(10);
+    pub const DEFAULT_REPORT_INTERVAL: Duration = Duration::from_secs(5);
+    pub const DEFAULT_CONSECUTIVE_POLLS: usize = 1;
+
+    const fn default_enabled() -> bool {
+        true
+    }
+
+    const fn default_max_poll() -> Duration {
+        Self::DEFAULT_MAX_POLL
+    }
+
+    const fn default_report_interval() -> Duration {
+        Self::DEFAULT_REPORT_INTERVAL
+    }
+
+    const fn default_consecutive_polls() -> usize {
+        Self::DEFAULT_CONSECUTIVE_POLLS
+    }
+}
+
+impl Default for WatchdogSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_poll: Self::DEFAULT_MAX_POLL,
+            consecutive_polls: Self::DEFAULT_CONSECUTIVE_POLLS,
+            report_interval: Self::DEFAULT_REPORT_INTERVAL,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TestKernel;
+
+    /// Blocks the scheduler for `ms` milliseconds, without yielding.
+    fn hog(ms: u64) {
+        std::thread::sleep(std::time::Duration::from_millis(ms));
+    }
+
+    #[test]
+    fn records_long_polls() {
+        TestKernel::run(|k| async move {
+            k.watchdog().set_max_poll(Some(Duration::from_millis(5)));
+
+            let id = k.task_counts().spawned;
+            let greedy = k.spawn(async { hog(20) }).await;
+            let polite = k
+                .spawn(async move {
+                    for _ in 0..4 {
+                        hog(1);
+                        k.yield_now().await;
+                    }
+                })
+                .await;
+            greedy.await.unwrap();
+            polite.await.unwrap();
+
+            let offenders = k.watchdog().take_offenders();
+            assert_eq!(offenders.len(), 1, "{offenders:?}");
+            assert_eq!(offenders[0].id, id);
+            assert!(
+                offenders[0]
+                    .name
+                    .starts_with("kernel::watchdog::tests::records_long_polls"),
+                "{offenders:?}"
+            );
+            assert_eq!(offenders[0].long_polls, 1);
+            assert_eq!(offenders[0].streak, 1);
+            assert!(offenders[0].longest >= Duration::from_millis(20));
+            assert!(k.watchdog().take_offenders().is_empty());
+        })
+    }
+
+    #[test]
+    fn consecutive_polls() {
+        TestKernel::run(|k| async move {
+            k.watchdog().set_max_poll(Some(Duration::from_millis(5)));
+            k.watchdog().set_consecutive_polls(3);
+
+            // Two long polls in a row, then one short one, twice over.
+            let bursty = k
+                .spawn(async move {
+                    for _ in 0..2 {
+                        for _ in 0..2 {
+                            hog(10);
+                            k.yield_now().await;
+                        }
+                        k.yield_now().await;
+                    }
+                })
+                .await;
+            let id = k.task_counts().spawned;
+            // Four long polls in a row.
+            let busy = k
+                .spawn(async move {
+                    for _ in 0..4 {
+                        hog(10);
+                        k.yield_now().await;
+                    }
+                })
+                .await;
+            bursty.await.unwrap();
+            busy.await.unwrap();
+
+            let offenders = k.watchdog().take_offenders();
+            assert_eq!(offenders.len(), 1, "{offenders:?}");
+            assert_eq!(offenders[0].id, id);
+            // Only the third and fourth polls are recorded.
+            assert_eq!(offenders[0].long_polls, 2);
+            assert_eq!(offenders[0].streak, 4);
+        })
+    }
+
+    #[test]
+    fn keeps_worst_offenders() {
+        TestKernel::run(|k| async move {
+            k.watchdog().set_max_poll(Some(Duration::from_millis(5)));
+
+            let first = k.task_counts().spawned;
+            for i in 0..MAX_OFFENDERS as u64 + 1 {
+                // The first task's poll is the shortest.
+                k.spawn(async move { hog(10 + i * 5) }).await.await.unwrap();
+            }
+
+            let offenders = k.watchdog().take_offenders();
+            assert_eq!(offenders.len(), MAX_OFFENDERS);
+            assert!(offenders.iter().all(|o| o.id != first), "{offenders:?}");
+        })
+    }
+
+    #[test]
+    fn disabled() {
+        TestKernel::run(|k| async move {
+            k.watchdog().set_max_poll(None);
+            k.spawn(async { hog(10) }).await.await.unwrap();
+            assert!(k.watchdog().take_offenders().is_empty());
+        })
+    }
+}
@@ -0,0 +1,77 @@
+use crate::executor::mailbox::MAILBOX;
+use abi::syscall::{
+    display::{DisplayError, DisplayRequest, DisplayResponse, FramebufferLease},
+    KernelResponseBody, UserRequestBody,
+};
+
+/// A leased display framebuffer, which is drawn to directly.
+///
+/// The kernel may revoke the lease at any time, after which damage submitted
+/// with it fails with [`DisplayError::Revoked`].
+pub struct Framebuffer {
+    lease: FramebufferLease,
+}
+
+impl Framebuffer {
+    pub async fn lease() -> Result<Self, DisplayError> {
+        let msg = UserRequestBody::Display(DisplayRequest::LeaseFramebuffer);
+        match request(msg).await? {
+            DisplayResponse::Leased(lease) => Ok(Framebuffer { lease }),
+            _ => Err(DisplayError::Unknown),
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.lease.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.lease.height
+    }
+
+    /// The framebuffer's pixels: `height` rows of `width` bytes, starting
+    /// every `stride` bytes. A pixel is lit if its byte is non-zero.
+    pub fn pixels(&mut self) -> (&mut [u8], u32) {
+        let pixels =
+            unsafe { core::slice::from_raw_parts_mut(self.lease.addr as *mut u8, self.lease.len) };
+        (pixels, self.lease.stride)
+    }
+
+    /// Show the pixels written to an area of the framebuffer.
+    pub async fn submit_damage(
+        &mut self,
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+    ) -> Result<(), DisplayError> {
+        let msg = UserRequestBody::Display(DisplayRequest::SubmitDamage {
+            lease: self.lease.lease,
+            x,
+            y,
+            width,
+            height,
+        });
+        match request(msg).await? {
+            DisplayResponse::DamageSubmitted { lease } if lease == self.lease.lease => Ok(()),
+            _ => Err(DisplayError::Unknown),
+        }
+    }
+
+    pub async fn release(self) -> Result<(), DisplayError> {
+        let msg = UserRequestBody::Display(DisplayRequest::ReleaseFramebuffer {
+            lease: self.lease.lease,
+        });
+        match request(msg).await? {
+            DisplayResponse::Released { .. } => Ok(()),
+            _ => Err(DisplayError::Unknown),
+        }
+    }
+}
+
+async fn request(msg: UserRequestBody) -> Result<DisplayResponse, DisplayError> {
+    match MAILBOX.request(msg).await {
+        Ok(KernelResponseBody::Display(resp)) => resp,
+        _ => Err(DisplayError::Unknown),
+    }
+}
@@ -6,6 +6,7 @@
 // /// Common between the Kernel and Userspace
 // pub use abi;
 
+// pub mod display;
 // pub mod executor;
 // pub mod serial;
 // pub mod utils;
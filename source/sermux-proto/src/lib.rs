@@ -3,10 +3,16 @@
 //! Wire types used by the `SerialMuxService` in the kernel. Extracted as a
 //! separate crate to allow external decoders (like `crowtty`) to share protocol
 //! definitions
+//!
+//! Two versions of the frame encoding exist; see [`FrameVersion`] for details
+//! of each, and how peers agree on which to use.
 
 #![cfg_attr(not(any(test, feature = "use-std")), no_std)]
 
-use core::{fmt::Display, mem::size_of};
+#[cfg(feature = "secure")]
+pub mod secure;
+
+use core::{cmp, fmt::Display, mem::size_of};
 
 ////////////////////////////////////////////////////////////////////////////////
 // Well Known Ports
@@ -18,7 +24,8 @@ use core::{fmt::Display, mem::size_of};
 pub enum WellKnown {
     /// A bidirectional loopback channel - echos all characters back
     Loopback = 0,
-    /// An output-only channel for sending periodic sign of life messages
+    /// An output-only channel for sending periodic sign of life messages, as
+    /// [`Ping`] frames.
     HelloWorld = 1,
     /// An input-only channel to act as a keyboard for a GUI application
     /// such as a forth console, when there is no hardware keyboard available.
@@ -29,6 +36,27 @@ pub enum WellKnown {
     PseudoKeyboard = 2,
     /// A bidirectional for binary encoded tracing messages
     BinaryTracing = 3,
+    /// A channel on which the target announces the opening and closing of
+    /// dynamically allocated ports, as [`DirectoryEntry`] frames. Peers also
+    /// exchange [`VersionAdvert`]s and [`SecureHello`]s on this port; each
+    /// message is told apart by its length.
+    PortDirectory = 4,
+    /// A bidirectional channel for the kernel's read-only debug agent, which
+    /// speaks the `mnemos-dbg-proto` protocol.
+    Debug = 5,
+    /// A bidirectional channel for the kernel's GDB remote serial protocol
+    /// stub.
+    Gdb = 6,
+    /// A bidirectional channel for setting and reading the target's shared
+    /// clipboard from the host. Messages are length-prefixed; see the
+    /// kernel's `daemons::sermux::clipboard` bridge for their format.
+    Clipboard = 7,
+    /// An output-only channel on which the target sends screenshots of its
+    /// display, each as a binary PBM image.
+    Screenshot = 8,
+    /// A bidirectional channel on which the host controls the target's ports,
+    /// for example by resetting a wedged port with a [`PortReset`].
+    Control = 9,
 
     /// A bidirectional interactive forth shell (1/4)
     ForthShell0 = 10,
@@ -46,6 +74,673 @@ impl From<WellKnown> for u16 {
     }
 }
 
+/// Ports in this range are never well known, and are reserved for dynamic
+/// allocation by the `SerialMuxService`.
+pub const DYNAMIC_PORTS: core::ops::Range<u16> = 1024..2048;
+
+/// This port number is never used, as the [version 1](FrameVersion::V1)
+/// encoding of this port number marks a [version 2](FrameVersion::V2) frame.
+pub const RESERVED_PORT: u16 = u16::MAX;
+
+////////////////////////////////////////////////////////////////////////////////
+// Port Directory
+////////////////////////////////////////////////////////////////////////////////
+
+/// An announcement sent on the [`WellKnown::PortDirectory`] port when a
+/// dynamically allocated port is opened or closed.
+///
+/// On the wire, this is the port number as a little-endian `u16`, followed by
+/// a single byte: `1` if the port was opened, or `0` if it was closed.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct DirectoryEntry {
+    pub port: u16,
+    pub open: bool,
+}
+
+impl DirectoryEntry {
+    /// The size of an encoded [DirectoryEntry]
+    pub const SIZE: usize = size_of::<u16>() + 1;
+
+    /// Encodes this entry as a [PortChunk] payload
+    #[must_use]
+    pub fn to_bytes(&self) -> [u8; Self::SIZE] {
+        let [lo, hi] = self.port.to_le_bytes();
+        [lo, hi, self.open as u8]
+    }
+
+    /// Decodes an entry from a [PortChunk] payload
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, DecodeError> {
+        match *bytes {
+            [lo, hi, open @ (0 | 1)] => Ok(Self {
+                port: u16::from_le_bytes([lo, hi]),
+                open: open == 1,
+            }),
+            _ => Err(DecodeError::MalformedFrame),
+        }
+    }
+}
+
+/// Advertises the newest [`FrameVersion`] that the sender can decode. Sent on
+/// the [`WellKnown::PortDirectory`] port.
+///
+/// Each peer sends an advert, with `reply` unset, when it starts. A peer which
+/// receives an advert with `reply` unset answers with its own advert, with
+/// `reply` set, so that a peer which restarts re-learns the other's version.
+/// Adverts are always sent as [version 1](FrameVersion::V1) frames.
+///
+/// On the wire, this is the version number as a single byte, followed by a
+/// single byte: `1` if the advert is a reply, or `0` otherwise.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct VersionAdvert {
+    pub version: FrameVersion,
+    pub reply: bool,
+}
+
+impl VersionAdvert {
+    /// The size of an encoded [VersionAdvert]
+    pub const SIZE: usize = 2;
+
+    /// Encodes this advert as a [PortChunk] payload
+    #[must_use]
+    pub fn to_bytes(&self) -> [u8; Self::SIZE] {
+        [self.version as u8, self.reply as u8]
+    }
+
+    /// Decodes an advert from a [PortChunk] payload
+    ///
+    /// Versions newer than [`FrameVersion::LATEST`] are decoded as
+    /// [`FrameVersion::LATEST`], since a peer that can decode a newer version
+    /// can also decode every older version.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let version = match bytes.first().copied() {
+            Some(1) => FrameVersion::V1,
+            Some(2..) => FrameVersion::LATEST,
+            _ => return Err(DecodeError::MalformedFrame),
+        };
+        match *bytes {
+            [_, reply @ (0 | 1)] => Ok(Self {
+                version,
+                reply: reply == 1,
+            }),
+            _ => Err(DecodeError::MalformedFrame),
+        }
+    }
+
+    /// Returns the version to encode frames sent to a peer that sent this
+    /// advert with, if this peer can decode up to `ours`.
+    #[must_use]
+    pub fn negotiate(&self, ours: FrameVersion) -> FrameVersion {
+        cmp::min(self.version, ours)
+    }
+}
+
+/// Starts an encrypted session on a secure port. Sent on the
+/// [`WellKnown::PortDirectory`] port.
+///
+/// Secure ports only accept data once both peers share a pre-shared key, and
+/// have exchanged hellos for that port. The host sends a hello, with `reply`
+/// unset, carrying a fresh ephemeral X25519 public key. The target answers
+/// with a hello of its own, with `reply` set, after which both peers derive
+/// the session's keys (see the `secure` module, with the `secure` feature).
+/// Sending a new hello starts a new session, discarding the old one.
+/// Hellos are always sent as [version 1](FrameVersion::V1) frames.
+///
+/// On the wire, this is the port number as a little-endian `u16`, followed by
+/// a single byte: `1` if the hello is a reply, or `0` otherwise, followed by
+/// the 32-byte public key.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct SecureHello {
+    pub port: u16,
+    pub reply: bool,
+    pub public: [u8; 32],
+}
+
+impl SecureHello {
+    /// The size of an encoded [SecureHello]
+    pub const SIZE: usize = size_of::<u16>() + 1 + 32;
+
+    /// Encodes this hello as a [PortChunk] payload
+    #[must_use]
+    pub fn to_bytes(&self) -> [u8; Self::SIZE] {
+        let mut bytes = [0; Self::SIZE];
+        bytes[..2].copy_from_slice(&self.port.to_le_bytes());
+        bytes[2] = self.reply as u8;
+        bytes[3..].copy_from_slice(&self.public);
+        bytes
+    }
+
+    /// Decodes a hello from a [PortChunk] payload
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, DecodeError> {
+        match bytes {
+            [lo, hi, reply @ (0 | 1), public @ ..] if public.len() == 32 => Ok(Self {
+                port: u16::from_le_bytes([*lo, *hi]),
+                reply: *reply == 1,
+                public: public.try_into().map_err(|_| DecodeError::MalformedFrame)?,
+            }),
+            _ => Err(DecodeError::MalformedFrame),
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Control
+////////////////////////////////////////////////////////////////////////////////
+
+/// Resets a port, or answers a reset. Sent on the [`WellKnown::Control`] port.
+///
+/// The host sends a reset, with `reply` unset, to recover a port that has
+/// stopped responding, such as a wedged shell, without restarting the target.
+/// The target discards any data it has received for the port but not yet
+/// read, and notifies the service which owns the port, which returns to its
+/// initial state. Once the service has been reset, the target answers with a
+/// reset of its own, with `reply` and `ok` set. If the port isn't open, or
+/// its owner doesn't handle resets, the target answers immediately, with `ok`
+/// unset.
+///
+/// On the wire, this is the [`PortReset::COMMAND`] byte, followed by the port
+/// number as a little-endian `u16`, followed by a single byte of flags: bit 0
+/// is set if the reset is a reply, and bit 1 is set if it is a reply and the
+/// port was reset.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct PortReset {
+    pub port: u16,
+    pub reply: bool,
+    pub ok: bool,
+}
+
+impl PortReset {
+    /// The first byte of an encoded [PortReset], which identifies it among
+    /// other messages on the [`WellKnown::Control`] port.
+    pub const COMMAND: u8 = 1;
+
+    /// The size of an encoded [PortReset]
+    pub const SIZE: usize = 1 + size_of::<u16>() + 1;
+
+    /// Returns a reset of `port`, to be sent by the host.
+    #[must_use]
+    pub const fn request(port: u16) -> Self {
+        Self {
+            port,
+            reply: false,
+            ok: false,
+        }
+    }
+
+    /// Encodes this reset as a [PortChunk] payload
+    #[must_use]
+    pub fn to_bytes(&self) -> [u8; Self::SIZE] {
+        let [lo, hi] = self.port.to_le_bytes();
+        let flags = self.reply as u8 | ((self.reply && self.ok) as u8) << 1;
+        [Self::COMMAND, lo, hi, flags]
+    }
+
+    /// Decodes a reset from a [PortChunk] payload
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, DecodeError> {
+        match *bytes {
+            [Self::COMMAND, lo, hi, flags @ (0 | 1 | 3)] => Ok(Self {
+                port: u16::from_le_bytes([lo, hi]),
+                reply: flags & 1 != 0,
+                ok: flags & 2 != 0,
+            }),
+            _ => Err(DecodeError::MalformedFrame),
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Frame Versions
+////////////////////////////////////////////////////////////////////////////////
+
+/// Versions of the frame encoding.
+///
+/// Decoders accept frames in either version, so a peer may switch versions at
+/// any time. However, older peers can only decode version 1 frames, so a peer
+/// only sends version 2 frames once the other peer has advertised that it can
+/// decode them, using a [`VersionAdvert`].
+///
+/// # Version 1
+///
+/// Before COBS encoding, a frame is the port number as a little-endian `u16`,
+/// followed by at least one byte of data.
+///
+/// # Version 2
+///
+/// Before COBS encoding, a frame is:
+///
+/// - The bytes `0xFF 0xFF`. In version 1, this is [`RESERVED_PORT`], so the
+///   two versions can always be told apart.
+/// - A flags byte. Bit 0 is set for the first frame of a jumbo message, and
+///   bit 1 is set for the following frames of a jumbo message. Every other
+///   bit must be zero.
+/// - The port number, as an unsigned LEB128 varint of at most 5 bytes. This
+///   leaves room to grow the port space without another version, but for
+///   now, ports above `u16::MAX` are rejected.
+/// - For the first frame of a jumbo message only, the total length of the
+///   message, as a varint.
+/// - At least one byte of data.
+///
+/// # Jumbo Messages
+///
+/// Version 2 allows sending messages larger than the receiver's maximum frame
+/// size (such as when transferring files), by splitting them into several
+/// frames (see [`Frame::fragments`]). The first frame carries the length of
+/// the whole message, so that the receiver can reserve space for it, and the
+/// remaining frames are sent on the same port until the whole message has
+/// been sent. Frames for other ports may be interleaved with them.
+///
+/// A receiver which treats a port as a stream of bytes can ignore the
+/// fragmentation entirely, and handle each frame's data as it arrives.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Copy, Clone, Hash)]
+#[repr(u8)]
+pub enum FrameVersion {
+    V1 = 1,
+    V2 = 2,
+}
+
+/// Which part of a message a [`Frame`] carries.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum Fragment {
+    /// The frame carries a whole message.
+    Whole,
+    /// The frame is the first of a jumbo message, which is `total_len` bytes
+    /// long, including this frame's data.
+    Start { total_len: u32 },
+    /// The frame continues the jumbo message most recently started on the
+    /// same port.
+    Continue,
+}
+
+/// A frame in either [version](FrameVersion) of the encoding.
+///
+/// Unlike a [PortChunk], this records which version a frame was decoded
+/// from, and which part of a jumbo message it carries.
+#[derive(Debug, PartialEq)]
+pub struct Frame<'a> {
+    pub version: FrameVersion,
+    pub port: u16,
+    pub fragment: Fragment,
+    pub chunk: &'a [u8],
+}
+
+/// An iterator over the frames needed to send a message, returned by
+/// [`Frame::fragments`].
+#[derive(Debug)]
+pub struct Fragments<'a> {
+    version: FrameVersion,
+    port: u16,
+    data: &'a [u8],
+    max_chunk: usize,
+    started: bool,
+}
+
+impl FrameVersion {
+    /// The newest version supported by this crate.
+    pub const LATEST: Self = Self::V2;
+}
+
+const V2_ESCAPE: [u8; 2] = RESERVED_PORT.to_le_bytes();
+const FLAG_JUMBO_START: u8 = 1 << 0;
+const FLAG_JUMBO_CONTINUE: u8 = 1 << 1;
+const MAX_VARINT_LEN: usize = 5;
+const MAX_HEADER_LEN: usize = V2_ESCAPE.len() + 1 + MAX_VARINT_LEN * 2;
+
+impl<'a> Frame<'a> {
+    /// Create a new Frame carrying a whole message, from the given port and
+    /// data
+    #[inline]
+    pub fn new(version: FrameVersion, port: impl Into<u16>, chunk: &'a [u8]) -> Self {
+        Self {
+            version,
+            port: port.into(),
+            fragment: Fragment::Whole,
+            chunk,
+        }
+    }
+
+    /// Splits `data` into frames carrying at most `max_chunk` bytes each.
+    ///
+    /// In [version 2](FrameVersion::V2), a message longer than `max_chunk` is
+    /// sent as a jumbo message, which the receiver may reassemble. Version 1
+    /// has no jumbo messages, so the message is sent as several unrelated
+    /// frames.
+    ///
+    /// # Panics
+    ///
+    /// If `max_chunk` is zero, or `data` is longer than `u32::MAX` bytes.
+    pub fn fragments(
+        version: FrameVersion,
+        port: impl Into<u16>,
+        data: &'a [u8],
+        max_chunk: usize,
+    ) -> Fragments<'a> {
+        assert!(max_chunk > 0, "frames must carry at least one byte");
+        assert!(
+            u32::try_from(data.len()).is_ok(),
+            "jumbo messages must be shorter than 4 GiB"
+        );
+        Fragments {
+            version,
+            port: port.into(),
+            data,
+            max_chunk,
+            started: false,
+        }
+    }
+
+    /// Calculate the size required to encode this frame
+    #[inline]
+    #[must_use]
+    pub fn buffer_required(&self) -> usize {
+        let header = match self.version {
+            FrameVersion::V1 => size_of::<u16>(),
+            FrameVersion::V2 => MAX_HEADER_LEN,
+        };
+        // Room for COBS(header + data:[u8; len]) plus a terminating zero
+        cobs::max_encoding_length(self.chunk.len() + header + 1)
+    }
+
+    /// Encodes the current [Frame] into the given buffer
+    ///
+    /// Version 1 frames cannot be part of a jumbo message, so the fragment is
+    /// ignored when encoding them. Frames for [`RESERVED_PORT`] are rejected
+    /// in either version.
+    pub fn encode_to<'b>(&self, out_buf: &'b mut [u8]) -> Result<&'b mut [u8], EncodeError> {
+        if self.port == RESERVED_PORT {
+            return Err(EncodeError::ReservedPort);
+        }
+        if out_buf.len() < self.buffer_required() {
+            return Err(EncodeError::InsufficientSize);
+        }
+
+        let mut header = [0u8; MAX_HEADER_LEN];
+        let header = self.encode_header(&mut header);
+        let mut encoder = cobs::CobsEncoder::new(out_buf);
+        encoder
+            .push(header)
+            .map_err(|_| EncodeError::UnexpectedBufferFull)?;
+        encoder
+            .push(self.chunk)
+            .map_err(|_| EncodeError::UnexpectedBufferFull)?;
+        let used = encoder
+            .finalize()
+            .map_err(|_| EncodeError::UnexpectedBufferFull)?;
+        // Get the encoded amount, with room for an extra zero terminator
+        let res = out_buf
+            .get_mut(..used + 1)
+            .ok_or(EncodeError::UnexpectedBufferFull)?;
+        res[used] = 0;
+        Ok(res)
+    }
+
+    /// Decodes a [Frame] of either version from the given buffer
+    ///
+    /// NOTE: This MAY mutate `data`, even if the decoding fails.
+    pub fn decode_from(data: &'a mut [u8]) -> Result<Self, DecodeError> {
+        let dec_len = cobs::decode_in_place(data).map_err(|_| DecodeError::CobsDecodeFailed)?;
+        let frame = data.get(..dec_len).ok_or(DecodeError::MalformedFrame)?;
+
+        let (port_bytes, rest) = match frame {
+            [0xFF, 0xFF, rest @ ..] => return Self::decode_v2(rest),
+            [lo, hi, rest @ ..] => ([*lo, *hi], rest),
+            _ => return Err(DecodeError::MalformedFrame),
+        };
+        // Messages must have a port and at least one data byte to be
+        // well formed
+        if rest.is_empty() {
+            return Err(DecodeError::MalformedFrame);
+        }
+
+        Ok(Frame::new(
+            FrameVersion::V1,
+            u16::from_le_bytes(port_bytes),
+            rest,
+        ))
+    }
+
+    fn decode_v2(frame: &'a [u8]) -> Result<Self, DecodeError> {
+        let (&flags, rest) = frame.split_first().ok_or(DecodeError::MalformedFrame)?;
+        let (port, mut rest) = decode_varint(rest)?;
+        let port = u16::try_from(port).map_err(|_| DecodeError::MalformedFrame)?;
+        let fragment = match flags {
+            0 => Fragment::Whole,
+            FLAG_JUMBO_START => {
+                let (total_len, after_len) = decode_varint(rest)?;
+                rest = after_len;
+                Fragment::Start { total_len }
+            }
+            FLAG_JUMBO_CONTINUE => Fragment::Continue,
+            _ => return Err(DecodeError::MalformedFrame),
+        };
+        if rest.is_empty() {
+            return Err(DecodeError::MalformedFrame);
+        }
+
+        Ok(Frame {
+            version: FrameVersion::V2,
+            port,
+            fragment,
+            chunk: rest,
+        })
+    }
+
+    fn encode_header<'b>(&self, buf: &'b mut [u8; MAX_HEADER_LEN]) -> &'b [u8] {
+        let used = match self.version {
+            FrameVersion::V1 => {
+                buf[..2].copy_from_slice(&self.port.to_le_bytes());
+                2
+            }
+            FrameVersion::V2 => {
+                let (flags, total_len) = match self.fragment {
+                    Fragment::Whole => (0, None),
+                    Fragment::Start { total_len } => (FLAG_JUMBO_START, Some(total_len)),
+                    Fragment::Continue => (FLAG_JUMBO_CONTINUE, None),
+                };
+                buf[..2].copy_from_slice(&V2_ESCAPE);
+                buf[2] = flags;
+                let mut used = 3;
+                used += encode_varint(self.port.into(), &mut buf[used..]);
+                if let Some(total_len) = total_len {
+                    used += encode_varint(total_len, &mut buf[used..]);
+                }
+                used
+            }
+        };
+        &buf[..used]
+    }
+}
+
+impl<'a> Iterator for Fragments<'a> {
+    type Item = Frame<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.data.is_empty() {
+            return None;
+        }
+        let total_len = self.data.len();
+        let (chunk, rest) = self.data.split_at(cmp::min(total_len, self.max_chunk));
+        let fragment = match self.version {
+            FrameVersion::V2 if self.started => Fragment::Continue,
+            FrameVersion::V2 if !rest.is_empty() => Fragment::Start {
+                // checked in `Frame::fragments`.
+                total_len: total_len as u32,
+            },
+            _ => Fragment::Whole,
+        };
+        self.started = true;
+        self.data = rest;
+        Some(Frame {
+            version: self.version,
+            port: self.port,
+            fragment,
+            chunk,
+        })
+    }
+}
+
+/// Encodes `value` as an unsigned LEB128 varint, returning the number of
+/// bytes written.
+fn encode_varint(mut value: u32, buf: &mut [u8]) -> usize {
+    let mut i = 0;
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        if value == 0 {
+            buf[i] = byte;
+            return i + 1;
+        }
+        buf[i] = byte | 0x80;
+        i += 1;
+    }
+}
+
+/// Decodes an unsigned LEB128 varint, returning the value and the remaining
+/// bytes.
+fn decode_varint(bytes: &[u8]) -> Result<(u32, &[u8]), DecodeError> {
+    let mut value = 0u32;
+    for (i, &byte) in bytes.iter().enumerate().take(MAX_VARINT_LEN) {
+        let bits = u32::from(byte & 0x7F);
+        // the last byte may only hold the top four bits of a `u32`.
+        if i == MAX_VARINT_LEN - 1 && bits > 0x0F {
+            return Err(DecodeError::MalformedFrame);
+        }
+        value |= bits << (7 * i);
+        if byte & 0x80 == 0 {
+            return Ok((value, &bytes[i + 1..]));
+        }
+    }
+    Err(DecodeError::MalformedFrame)
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Health Pings
+////////////////////////////////////////////////////////////////////////////////
+
+/// A sign of life, sent periodically on the [`WellKnown::HelloWorld`] port.
+///
+/// A host can use the sequence number to detect dropped pings, and detect a
+/// wedged target when pings stop arriving altogether.
+///
+/// On the wire, this is the sequence number as a little-endian `u32`, the
+/// uptime in milliseconds and the number of scheduler ticks as little-endian
+/// `u64`s, followed by an arbitrary payload making up the rest of the frame.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct Ping<'a> {
+    /// Incremented by one for every ping sent.
+    pub seq: u32,
+    /// Time since the kernel booted, in milliseconds.
+    pub uptime_ms: u64,
+    /// The number of times the kernel's scheduler has been ticked.
+    pub ticks: u64,
+    /// An arbitrary payload, configured on the target.
+    pub payload: &'a [u8],
+}
+
+impl<'a> Ping<'a> {
+    /// The size of an encoded [Ping], not including its payload
+    pub const HEADER_SIZE: usize = size_of::<u32>() + size_of::<u64>() * 2;
+
+    /// Encodes this ping as a [PortChunk] payload, into the given buffer
+    pub fn encode_to<'b>(&self, out_buf: &'b mut [u8]) -> Result<&'b mut [u8], EncodeError> {
+        let len = Self::HEADER_SIZE + self.payload.len();
+        let out = out_buf
+            .get_mut(..len)
+            .ok_or(EncodeError::InsufficientSize)?;
+        let (seq, rest) = out.split_at_mut(size_of::<u32>());
+        let (uptime, rest) = rest.split_at_mut(size_of::<u64>());
+        let (ticks, payload) = rest.split_at_mut(size_of::<u64>());
+        seq.copy_from_slice(&self.seq.to_le_bytes());
+        uptime.copy_from_slice(&self.uptime_ms.to_le_bytes());
+        ticks.copy_from_slice(&self.ticks.to_le_bytes());
+        payload.copy_from_slice(self.payload);
+        Ok(out)
+    }
+
+    /// Decodes a ping from a [PortChunk] payload
+    pub fn from_bytes(bytes: &'a [u8]) -> Result<Self, DecodeError> {
+        if bytes.len() < Self::HEADER_SIZE {
+            return Err(DecodeError::MalformedFrame);
+        }
+        let (seq, rest) = bytes.split_at(size_of::<u32>());
+        let (uptime, rest) = rest.split_at(size_of::<u64>());
+        let (ticks, payload) = rest.split_at(size_of::<u64>());
+        // The lengths were checked above, so these conversions can't fail.
+        let u64_from = |b: &[u8]| u64::from_le_bytes(b.try_into().unwrap());
+        Ok(Self {
+            seq: u32::from_le_bytes(seq.try_into().unwrap()),
+            uptime_ms: u64_from(uptime),
+            ticks: u64_from(ticks),
+            payload,
+        })
+    }
+
+    /// Returns the [`Notice`] carried in this ping's payload, if it carries
+    /// one.
+    pub fn notice(&self) -> Option<Notice<'a>> {
+        Notice::from_payload(self.payload)
+    }
+}
+
+/// A user-visible notification, carried as a [`Ping`]'s payload.
+///
+/// The hello daemon sends a ping as soon as a notification is posted on the
+/// target, with the notice as its payload, so that hosts watching a headless
+/// target see it.
+///
+/// On the wire, this is [`Notice::MARKER`] (an ASCII `BEL`), the severity as
+/// a byte, and then the notification's UTF-8 text, making up the rest of the
+/// payload.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct Notice<'a> {
+    /// How severe the notification is.
+    pub severity: NoticeSeverity,
+    /// The notification's text.
+    pub text: &'a str,
+}
+
+/// How severe a [`Notice`] is.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+#[repr(u8)]
+pub enum NoticeSeverity {
+    Info = 0,
+    Warn = 1,
+    Error = 2,
+}
+
+impl<'a> Notice<'a> {
+    /// The first byte of a ping payload which carries a notice.
+    pub const MARKER: u8 = 0x07;
+    /// The size of an encoded [Notice], not including its text
+    pub const HEADER_SIZE: usize = 2;
+
+    /// Encodes this notice as a [Ping] payload, into the given buffer
+    pub fn encode_to<'b>(&self, out_buf: &'b mut [u8]) -> Result<&'b mut [u8], EncodeError> {
+        let len = Self::HEADER_SIZE + self.text.len();
+        let out = out_buf
+            .get_mut(..len)
+            .ok_or(EncodeError::InsufficientSize)?;
+        out[0] = Self::MARKER;
+        out[1] = self.severity as u8;
+        out[Self::HEADER_SIZE..].copy_from_slice(self.text.as_bytes());
+        Ok(out)
+    }
+
+    /// Decodes a notice from a [Ping] payload, returning [`None`] if the
+    /// payload isn't a notice
+    pub fn from_payload(payload: &'a [u8]) -> Option<Self> {
+        let (&[Self::MARKER, severity], text) = payload.split_first_chunk::<2>()? else {
+            return None;
+        };
+        let severity = match severity {
+            0 => NoticeSeverity::Info,
+            1 => NoticeSeverity::Warn,
+            2 => NoticeSeverity::Error,
+            _ => return None,
+        };
+        let text = core::str::from_utf8(text).ok()?;
+        Some(Self { severity, text })
+    }
+}
+
 #[derive(Debug, PartialEq, Copy, Clone)]
 pub enum EncodeError {
     /// The provided buffer is not suitable in size
@@ -53,6 +748,8 @@ pub enum EncodeError {
     /// Ran out of room while filling a buffer, this is likely
     /// an error in the `sermux-proto` library.
     UnexpectedBufferFull,
+    /// The frame was addressed to [`RESERVED_PORT`], which is never used.
+    ReservedPort,
 }
 
 impl Display for EncodeError {
@@ -60,6 +757,7 @@ impl Display for EncodeError {
         let st = match self {
             EncodeError::InsufficientSize => "InsufficientSize",
             EncodeError::UnexpectedBufferFull => "UnexpectedBufferFull",
+            EncodeError::ReservedPort => "ReservedPort",
         };
         f.write_str(st)
     }
@@ -110,8 +808,14 @@ impl<'a> PortChunk<'a> {
     }
 
     /// Encodes the current [PortChunk] into the given buffer
+    ///
+    /// Chunks for [`RESERVED_PORT`] are rejected, as they would be decoded as
+    /// a [version 2](FrameVersion::V2) frame.
     pub fn encode_to<'b>(&self, out_buf: &'b mut [u8]) -> Result<&'b mut [u8], EncodeError> {
         let PortChunk { port, chunk } = self;
+        if *port == RESERVED_PORT {
+            return Err(EncodeError::ReservedPort);
+        }
         if out_buf.len() < self.buffer_required() {
             return Err(EncodeError::InsufficientSize);
         }
@@ -136,23 +840,13 @@ impl<'a> PortChunk<'a> {
 
     /// Decodes a [PortChunk] from the given buffer
     ///
+    /// Frames of either [version](FrameVersion) are accepted. Each frame of a
+    /// jumbo message is decoded as a separate [PortChunk]; use
+    /// [`Frame::decode_from`] to reassemble jumbo messages.
+    ///
     /// NOTE: This MAY mutate `data`, even if the decoding fails.
     pub fn decode_from(data: &'a mut [u8]) -> Result<Self, DecodeError> {
-        let dec_len = cobs::decode_in_place(data).map_err(|_| DecodeError::CobsDecodeFailed)?;
-
-        // Messages must have a port and at least one data byte to be
-        // well formed
-        if dec_len < (size_of::<u16>() + 1) {
-            return Err(DecodeError::MalformedFrame);
-        }
-
-        let frame = data.get(..dec_len).ok_or(DecodeError::MalformedFrame)?;
-
-        let mut port_bytes = [0u8; size_of::<u16>()];
-        let (port_data, chunk) = frame.split_at(size_of::<u16>());
-        port_bytes.copy_from_slice(port_data);
-        let port = u16::from_le_bytes(port_bytes);
-
+        let Frame { port, chunk, .. } = Frame::decode_from(data)?;
         Ok(PortChunk { port, chunk })
     }
 
@@ -298,9 +992,352 @@ mod test {
         );
     }
 
+    #[test]
+    fn directory_entry() {
+        let entry = DirectoryEntry {
+            port: 0x0401,
+            open: true,
+        };
+        let bytes = entry.to_bytes();
+        assert_eq!(bytes, [0x01, 0x04, 1]);
+        assert_eq!(DirectoryEntry::from_bytes(&bytes), Ok(entry));
+
+        // bad open flag
+        assert_eq!(
+            DirectoryEntry::from_bytes(&[0x01, 0x04, 2]),
+            Err(DecodeError::MalformedFrame)
+        );
+        // wrong length
+        assert_eq!(
+            DirectoryEntry::from_bytes(&[0x01, 0x04]),
+            Err(DecodeError::MalformedFrame)
+        );
+    }
+
+    #[test]
+    fn ping() {
+        let ping = Ping {
+            seq: 7,
+            uptime_ms: 1_500,
+            ticks: 0x0102,
+            payload: b"hi",
+        };
+        let mut buf = [0u8; 32];
+        let bytes = ping.encode_to(&mut buf).unwrap();
+        assert_eq!(bytes.len(), Ping::HEADER_SIZE + 2);
+        assert_eq!(&bytes[..4], &[7, 0, 0, 0]);
+        assert_eq!(Ping::from_bytes(bytes), Ok(ping));
+
+        // too short
+        assert_eq!(
+            Ping::from_bytes(&[0u8; Ping::HEADER_SIZE - 1]),
+            Err(DecodeError::MalformedFrame)
+        );
+        // too small a buffer
+        assert_eq!(
+            ping.encode_to(&mut [0u8; Ping::HEADER_SIZE]),
+            Err(EncodeError::InsufficientSize)
+        );
+    }
+
+    #[test]
+    fn notice() {
+        let notice = Notice {
+            severity: NoticeSeverity::Warn,
+            text: "link down",
+        };
+        let mut payload = [0u8; 16];
+        let payload = notice.encode_to(&mut payload).unwrap();
+        let ping = Ping {
+            seq: 1,
+            uptime_ms: 0,
+            ticks: 0,
+            payload,
+        };
+        assert_eq!(ping.notice(), Some(notice));
+
+        // an ordinary ping payload
+        let ping = Ping {
+            payload: b"hello",
+            ..ping
+        };
+        assert_eq!(ping.notice(), None);
+        // an unknown severity
+        assert_eq!(Notice::from_payload(&[Notice::MARKER, 3, b'!']), None);
+        // too small a buffer
+        assert_eq!(
+            notice.encode_to(&mut [0u8; Notice::HEADER_SIZE]),
+            Err(EncodeError::InsufficientSize)
+        );
+    }
+
+    #[test]
+    fn version_advert() {
+        let advert = VersionAdvert {
+            version: FrameVersion::V2,
+            reply: true,
+        };
+        let bytes = advert.to_bytes();
+        assert_eq!(bytes, [2, 1]);
+        assert_eq!(VersionAdvert::from_bytes(&bytes), Ok(advert));
+        assert_eq!(advert.negotiate(FrameVersion::V1), FrameVersion::V1);
+
+        // newer versions are treated as the latest version we know of
+        assert_eq!(
+            VersionAdvert::from_bytes(&[9, 0]),
+            Ok(VersionAdvert {
+                version: FrameVersion::LATEST,
+                reply: false,
+            })
+        );
+        // there is no version zero
+        assert_eq!(
+            VersionAdvert::from_bytes(&[0, 0]),
+            Err(DecodeError::MalformedFrame)
+        );
+        // wrong length, and not confused with a directory entry
+        assert_eq!(
+            VersionAdvert::from_bytes(&[2, 0, 1]),
+            Err(DecodeError::MalformedFrame)
+        );
+        assert!(DirectoryEntry::from_bytes(&bytes).is_err());
+    }
+
+    #[test]
+    fn secure_hello() {
+        let hello = SecureHello {
+            port: 0x0401,
+            reply: true,
+            public: [0xAA; 32],
+        };
+        let bytes = hello.to_bytes();
+        assert_eq!(bytes[..3], [0x01, 0x04, 1]);
+        assert_eq!(bytes[3..], [0xAA; 32]);
+        assert_eq!(SecureHello::from_bytes(&bytes), Ok(hello));
+
+        // bad reply flag
+        let mut bad = bytes;
+        bad[2] = 2;
+        assert_eq!(
+            SecureHello::from_bytes(&bad),
+            Err(DecodeError::MalformedFrame)
+        );
+        // truncated key
+        assert_eq!(
+            SecureHello::from_bytes(&bytes[..SecureHello::SIZE - 1]),
+            Err(DecodeError::MalformedFrame)
+        );
+        // not confused with the other directory messages
+        assert!(DirectoryEntry::from_bytes(&bytes).is_err());
+        assert!(VersionAdvert::from_bytes(&bytes).is_err());
+    }
+
+    #[test]
+    fn port_reset() {
+        let reset = PortReset::request(WellKnown::ForthShell0.into());
+        let bytes = reset.to_bytes();
+        assert_eq!(bytes, [PortReset::COMMAND, 10, 0, 0]);
+        assert_eq!(PortReset::from_bytes(&bytes), Ok(reset));
+
+        let reply = PortReset {
+            reply: true,
+            ok: true,
+            ..reset
+        };
+        assert_eq!(reply.to_bytes(), [PortReset::COMMAND, 10, 0, 3]);
+        assert_eq!(PortReset::from_bytes(&reply.to_bytes()), Ok(reply));
+
+        // requests never succeed
+        assert_eq!(
+            PortReset::from_bytes(&[PortReset::COMMAND, 10, 0, 2]),
+            Err(DecodeError::MalformedFrame)
+        );
+        // unknown command
+        assert_eq!(
+            PortReset::from_bytes(&[2, 10, 0, 0]),
+            Err(DecodeError::MalformedFrame)
+        );
+        // wrong length
+        assert_eq!(
+            PortReset::from_bytes(&bytes[..PortReset::SIZE - 1]),
+            Err(DecodeError::MalformedFrame)
+        );
+    }
+
+    #[test]
+    fn v1_frames_decode_as_v1() {
+        let data = [1, 2, 3, 4];
+        let mut buf = [0u8; 8];
+        let enc = PortChunk::new(0x4269u16, &data)
+            .encode_to(&mut buf)
+            .unwrap();
+        let frame = Frame::decode_from(enc).unwrap();
+        assert_eq!(frame, Frame::new(FrameVersion::V1, 0x4269u16, &data));
+
+        // the v1 encoder produces the same bytes as `PortChunk`
+        let pc = PortChunk::new(0x4269u16, &data);
+        let frame = Frame::new(FrameVersion::V1, 0x4269u16, &data);
+        assert_eq!(frame.buffer_required(), pc.buffer_required());
+        let mut buf2 = [0u8; 8];
+        assert_eq!(
+            frame.encode_to(&mut buf2).unwrap(),
+            pc.encode_to(&mut buf).unwrap()
+        );
+    }
+
+    #[test]
+    fn v2_frame_layout() {
+        let frame = Frame {
+            version: FrameVersion::V2,
+            port: 300,
+            fragment: Fragment::Start { total_len: 2 },
+            chunk: &[1],
+        };
+        let mut buf = [0u8; 32];
+        let enc = frame.encode_to(&mut buf).unwrap();
+        let mut decoded = enc.to_vec();
+        let len = cobs::decode_in_place(&mut decoded).unwrap();
+        // escape, flags, varint(300), varint(2), data
+        assert_eq!(&decoded[..len], &[0xFF, 0xFF, 0x01, 0xAC, 0x02, 0x02, 1]);
+        assert_eq!(Frame::decode_from(enc), Ok(frame));
+    }
+
+    #[test]
+    fn bad_v2_frames() {
+        fn decode(frame: &[u8]) -> Result<(), DecodeError> {
+            let mut buf = [0u8; 32];
+            let used = cobs::encode(frame, &mut buf);
+            Frame::decode_from(&mut buf[..=used]).map(drop)
+        }
+
+        // valid, to check the helper
+        assert_eq!(decode(&[0xFF, 0xFF, 0, 1, b'!']), Ok(()));
+        // unknown flags
+        assert_eq!(
+            decode(&[0xFF, 0xFF, 0x04, 1, b'!']),
+            Err(DecodeError::MalformedFrame)
+        );
+        // both jumbo flags
+        assert_eq!(
+            decode(&[0xFF, 0xFF, 0x03, 1, 1, b'!']),
+            Err(DecodeError::MalformedFrame)
+        );
+        // no data
+        assert_eq!(
+            decode(&[0xFF, 0xFF, 0, 1]),
+            Err(DecodeError::MalformedFrame)
+        );
+        // truncated port varint
+        assert_eq!(
+            decode(&[0xFF, 0xFF, 0, 0x80]),
+            Err(DecodeError::MalformedFrame)
+        );
+        // port too large
+        assert_eq!(
+            decode(&[0xFF, 0xFF, 0, 0x80, 0x80, 0x04, b'!']),
+            Err(DecodeError::MalformedFrame)
+        );
+        // missing length
+        assert_eq!(
+            decode(&[0xFF, 0xFF, 0x01, 1]),
+            Err(DecodeError::MalformedFrame)
+        );
+    }
+
+    #[test]
+    fn varints() {
+        for value in [0, 1, 0x7F, 0x80, 0x3FFF, 0x4000, u32::MAX] {
+            let mut buf = [0u8; MAX_VARINT_LEN + 1];
+            let len = encode_varint(value, &mut buf);
+            buf[len] = 0xAA;
+            assert_eq!(
+                decode_varint(&buf[..len + 1]),
+                Ok((value, &[0xAA][..])),
+                "value: {value:#x}"
+            );
+        }
+        assert_eq!(encode_varint(u32::MAX, &mut [0u8; MAX_VARINT_LEN]), 5);
+
+        // overflows a u32
+        assert_eq!(
+            decode_varint(&[0xFF, 0xFF, 0xFF, 0xFF, 0x1F]),
+            Err(DecodeError::MalformedFrame)
+        );
+        // too long
+        assert_eq!(
+            decode_varint(&[0x80, 0x80, 0x80, 0x80, 0x80, 0x00]),
+            Err(DecodeError::MalformedFrame)
+        );
+    }
+
+    #[test]
+    fn fragments() {
+        let data = [1, 2, 3, 4, 5];
+        let frames = Frame::fragments(FrameVersion::V2, 7u16, &data, 2).collect::<Vec<_>>();
+        let fragments = frames.iter().map(|f| f.fragment).collect::<Vec<_>>();
+        assert_eq!(
+            fragments,
+            [
+                Fragment::Start { total_len: 5 },
+                Fragment::Continue,
+                Fragment::Continue
+            ]
+        );
+        let chunks = frames.iter().map(|f| f.chunk).collect::<Vec<_>>();
+        assert_eq!(chunks, [&[1, 2][..], &[3, 4], &[5]]);
+
+        // short messages aren't jumbo messages
+        let frames = Frame::fragments(FrameVersion::V2, 7u16, &data, 5).collect::<Vec<_>>();
+        assert_eq!(frames, [Frame::new(FrameVersion::V2, 7u16, &data)]);
+
+        // v1 has no jumbo messages
+        assert!(Frame::fragments(FrameVersion::V1, 7u16, &data, 2)
+            .all(|f| f.fragment == Fragment::Whole));
+    }
+
+    #[test]
+    fn reserved_port() {
+        let mut buf = [0u8; 32];
+        assert_eq!(
+            PortChunk::new(RESERVED_PORT, &[1]).encode_to(&mut buf),
+            Err(EncodeError::ReservedPort)
+        );
+        for version in [FrameVersion::V1, FrameVersion::V2] {
+            assert_eq!(
+                Frame::new(version, RESERVED_PORT, &[1]).encode_to(&mut buf),
+                Err(EncodeError::ReservedPort)
+            );
+        }
+    }
+
     proptest! {
         #[test]
-        fn round_trip(port in any::<u16>(), ref chunk in vec(any::<u8>(), 1..256)) {
+        fn v2_round_trip(
+            port in 0..RESERVED_PORT,
+            ref chunk in vec(any::<u8>(), 1..256),
+            total_len in any::<u32>(),
+            fragment in 0..3u8,
+        ) {
+            let fragment = match fragment {
+                0 => Fragment::Whole,
+                1 => Fragment::Start { total_len },
+                _ => Fragment::Continue,
+            };
+            let frame = Frame {
+                version: FrameVersion::V2,
+                port,
+                fragment,
+                chunk,
+            };
+            let mut buf = vec![0u8; frame.buffer_required()];
+            let enc = frame.encode_to(&mut buf).unwrap();
+
+            let dec = Frame::decode_from(enc).unwrap();
+            prop_assert_eq!(dec, frame);
+        }
+
+        #[test]
+        fn round_trip(port in 0..RESERVED_PORT, ref chunk in vec(any::<u8>(), 1..256)) {
             let pc = PortChunk {
                 port,
                 chunk,
@@ -314,7 +1351,7 @@ mod test {
         }
 
         #[test]
-        fn owned_round_trip(port in any::<u16>(), ref chunk in vec(any::<u8>(), 1..256)) {
+        fn owned_round_trip(port in 0..RESERVED_PORT, ref chunk in vec(any::<u8>(), 1..256)) {
             let pc = PortChunk {
                 port,
                 chunk,
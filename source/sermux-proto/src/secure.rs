@@ -0,0 +1,394 @@
+//! Authenticated encryption for secure ports.
+//!
+//! A secure port only carries data encrypted with keys that both peers derive
+//! from a pre-shared key (PSK) and a fresh X25519 key exchange, so a peer
+//! which doesn't know the PSK can neither read nor inject data on the port.
+//! This matters when SerMux is carried over a network, rather than a cable:
+//! without it, anyone who can reach the device could use its Forth shell.
+//!
+//! # Handshake
+//!
+//! The host is always the [initiator](Role::Initiator), and the target is the
+//! [responder](Role::Responder). Each peer creates a [`Handshake`] from 32
+//! random bytes, and they exchange [`SecureHello`]s on the
+//! [`WellKnown::PortDirectory`](crate::WellKnown::PortDirectory) port. Each
+//! peer then calls [`Handshake::finish`] with the other's hello, which derives
+//! the session's keys with HKDF-SHA256, using the PSK as the salt, the X25519
+//! shared secret as the input keying material, and the following as the info:
+//!
+//! - the bytes of [`LABEL`],
+//! - the initiator's public key,
+//! - the responder's public key,
+//! - the port number, as a little-endian `u16`.
+//!
+//! The first 32 bytes of output are the key for data sent by the initiator,
+//! and the next 32 bytes are the key for data sent by the responder. A peer
+//! with the wrong PSK derives different keys, so every frame it sends fails
+//! to authenticate.
+//!
+//! # Frames
+//!
+//! Each chunk of data sent on a secure port is sealed separately, as:
+//!
+//! - a counter, as a little-endian `u64`, which starts at zero and increases
+//!   by one with each chunk sent,
+//! - the data, encrypted with ChaCha20-Poly1305,
+//! - the 16-byte Poly1305 tag.
+//!
+//! The nonce is four zero bytes followed by the counter, and the associated
+//! data is the port number, as a little-endian `u16`, so a chunk can't be
+//! replayed on another port. The receiver rejects chunks whose counter isn't
+//! greater than that of the last chunk it accepted, so chunks may be lost, but
+//! never replayed or reordered.
+
+use chacha20poly1305::{aead::AeadInPlace, ChaCha20Poly1305, Key, KeyInit, Nonce, Tag};
+use core::fmt::Display;
+use hkdf::Hkdf;
+use sha2::Sha256;
+use x25519_dalek::{PublicKey, StaticSecret};
+
+use crate::SecureHello;
+
+/// Mixed into the key derivation, so that keys derived for secure ports are
+/// never the same as keys derived for anything else.
+pub const LABEL: &[u8] = b"mnemos sermux secure port v1";
+
+/// The size of the counter at the start of a sealed chunk.
+pub const COUNTER_SIZE: usize = 8;
+
+/// The size of the tag at the end of a sealed chunk.
+pub const TAG_SIZE: usize = 16;
+
+/// How many bytes larger a sealed chunk is than the data it carries.
+pub const OVERHEAD: usize = COUNTER_SIZE + TAG_SIZE;
+
+/// Which side of the handshake a peer is on.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum Role {
+    /// The peer which sends the first hello: the host.
+    Initiator,
+    /// The peer which replies to a hello: the target.
+    Responder,
+}
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum SecureError {
+    /// The peer's hello was for a different port, or was (or wasn't) a reply
+    /// when it shouldn't (or should) have been.
+    UnexpectedHello,
+    /// The peer's public key is a low-order point, so the shared secret would
+    /// not depend on our key.
+    WeakKey,
+    /// The provided buffer is not suitable in size
+    InsufficientSize,
+    /// The chunk failed to authenticate. It was corrupted, forged, or sealed
+    /// with a different key.
+    Unauthenticated,
+    /// The chunk's counter was not greater than that of the last chunk
+    /// accepted, so it was replayed or reordered.
+    Replayed,
+    /// Every counter value has been used, so no more chunks can be sealed
+    /// with this key.
+    Exhausted,
+}
+
+/// One side of a handshake, for a single port.
+pub struct Handshake {
+    secret: StaticSecret,
+    public: PublicKey,
+    role: Role,
+    port: u16,
+}
+
+/// Seals chunks sent on a secure port.
+pub struct Sealer {
+    cipher: ChaCha20Poly1305,
+    port: u16,
+    next: u64,
+}
+
+/// Opens chunks received on a secure port.
+pub struct Opener {
+    cipher: ChaCha20Poly1305,
+    port: u16,
+    next: u64,
+}
+
+impl Display for SecureError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let st = match self {
+            SecureError::UnexpectedHello => "UnexpectedHello",
+            SecureError::WeakKey => "WeakKey",
+            SecureError::InsufficientSize => "InsufficientSize",
+            SecureError::Unauthenticated => "Unauthenticated",
+            SecureError::Replayed => "Replayed",
+            SecureError::Exhausted => "Exhausted",
+        };
+        f.write_str(st)
+    }
+}
+
+impl Handshake {
+    /// Starts a handshake for `port`.
+    ///
+    /// `random` must come from a cryptographically secure random number
+    /// generator, and must never be reused.
+    #[must_use]
+    pub fn new(role: Role, port: u16, random: [u8; 32]) -> Self {
+        let secret = StaticSecret::from(random);
+        let public = PublicKey::from(&secret);
+        Self {
+            secret,
+            public,
+            role,
+            port,
+        }
+    }
+
+    /// Returns the hello to send to the peer.
+    #[must_use]
+    pub fn hello(&self) -> SecureHello {
+        SecureHello {
+            port: self.port,
+            reply: self.role == Role::Responder,
+            public: self.public.to_bytes(),
+        }
+    }
+
+    /// Finishes the handshake with the peer's hello, returning a [`Sealer`]
+    /// for data sent to the peer, and an [`Opener`] for data received from
+    /// it.
+    pub fn finish(
+        self,
+        psk: &[u8; 32],
+        peer: &SecureHello,
+    ) -> Result<(Sealer, Opener), SecureError> {
+        // only the responder's hello is a reply.
+        if peer.port != self.port || peer.reply != (self.role == Role::Initiator) {
+            return Err(SecureError::UnexpectedHello);
+        }
+
+        let peer_public = PublicKey::from(peer.public);
+        let shared = self.secret.diffie_hellman(&peer_public);
+        if !shared.was_contributory() {
+            return Err(SecureError::WeakKey);
+        }
+
+        let (initiator, responder) = match self.role {
+            Role::Initiator => (self.public.as_bytes(), &peer.public),
+            Role::Responder => (&peer.public, self.public.as_bytes()),
+        };
+        let port = self.port.to_le_bytes();
+        let mut okm = [0u8; 64];
+        Hkdf::<Sha256>::new(Some(psk), shared.as_bytes())
+            .expand_multi_info(&[LABEL, initiator, responder, &port], &mut okm)
+            .expect("64 bytes is a valid length for HKDF-SHA256");
+        let (initiator_key, responder_key) = okm.split_at(32);
+
+        let (tx, rx) = match self.role {
+            Role::Initiator => (initiator_key, responder_key),
+            Role::Responder => (responder_key, initiator_key),
+        };
+        let cipher = |key| ChaCha20Poly1305::new(Key::from_slice(key));
+        Ok((
+            Sealer {
+                cipher: cipher(tx),
+                port: self.port,
+                next: 0,
+            },
+            Opener {
+                cipher: cipher(rx),
+                port: self.port,
+                next: 0,
+            },
+        ))
+    }
+}
+
+impl Sealer {
+    /// Seals `data` into `out`, which must be at least [`OVERHEAD`] bytes
+    /// longer than `data`. Returns the sealed chunk.
+    pub fn seal<'a>(
+        &mut self,
+        data: &[u8],
+        out: &'a mut [u8],
+    ) -> Result<&'a mut [u8], SecureError> {
+        let len = data.len() + OVERHEAD;
+        let out = out.get_mut(..len).ok_or(SecureError::InsufficientSize)?;
+        if self.next == u64::MAX {
+            return Err(SecureError::Exhausted);
+        }
+        let counter = self.next;
+        self.next += 1;
+
+        let (header, rest) = out.split_at_mut(COUNTER_SIZE);
+        let (body, tag) = rest.split_at_mut(data.len());
+        header.copy_from_slice(&counter.to_le_bytes());
+        body.copy_from_slice(data);
+        let sealed_tag = self
+            .cipher
+            .encrypt_in_place_detached(&nonce(counter), &self.port.to_le_bytes(), body)
+            .map_err(|_| SecureError::InsufficientSize)?;
+        tag.copy_from_slice(&sealed_tag);
+        Ok(out)
+    }
+}
+
+impl Opener {
+    /// Opens the sealed chunk in `chunk`, in place. Returns the data it
+    /// carried.
+    pub fn open<'a>(&mut self, chunk: &'a mut [u8]) -> Result<&'a mut [u8], SecureError> {
+        if chunk.len() < OVERHEAD {
+            return Err(SecureError::Unauthenticated);
+        }
+        let (header, rest) = chunk.split_at_mut(COUNTER_SIZE);
+        let (body, tag) = rest.split_at_mut(rest.len() - TAG_SIZE);
+        let counter = u64::from_le_bytes(header.try_into().unwrap());
+        if counter < self.next {
+            return Err(SecureError::Replayed);
+        }
+        self.cipher
+            .decrypt_in_place_detached(
+                &nonce(counter),
+                &self.port.to_le_bytes(),
+                body,
+                Tag::from_slice(tag),
+            )
+            .map_err(|_| SecureError::Unauthenticated)?;
+        // only move the window once the chunk is known to be genuine, so that
+        // a forged counter can't lock out the real peer.
+        self.next = counter + 1;
+        Ok(body)
+    }
+}
+
+fn nonce(counter: u64) -> Nonce {
+    let mut nonce = Nonce::default();
+    nonce[4..].copy_from_slice(&counter.to_le_bytes());
+    nonce
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const PSK: [u8; 32] = [7; 32];
+
+    fn session(host_psk: &[u8; 32], port: u16) -> ((Sealer, Opener), (Sealer, Opener)) {
+        let host = Handshake::new(Role::Initiator, port, [1; 32]);
+        let target = Handshake::new(Role::Responder, port, [2; 32]);
+        let host_hello = host.hello();
+        let target_hello = target.hello();
+        assert!(!host_hello.reply);
+        assert!(target_hello.reply);
+        (
+            host.finish(host_psk, &target_hello).unwrap(),
+            target.finish(&PSK, &host_hello).unwrap(),
+        )
+    }
+
+    #[test]
+    fn round_trip() {
+        let ((mut host_tx, mut host_rx), (mut target_tx, mut target_rx)) = session(&PSK, 10);
+
+        let mut buf = [0u8; 64];
+        let sealed = host_tx.seal(b"1 2 + .\n", &mut buf).unwrap();
+        assert_eq!(sealed.len(), 8 + OVERHEAD);
+        assert_eq!(sealed[..COUNTER_SIZE], 0u64.to_le_bytes());
+        assert_eq!(target_rx.open(sealed).unwrap(), b"1 2 + .\n");
+
+        let sealed = target_tx.seal(b"3 ok.\n", &mut buf).unwrap();
+        assert_eq!(host_rx.open(sealed).unwrap(), b"3 ok.\n");
+    }
+
+    #[test]
+    fn wrong_psk() {
+        let ((mut host_tx, _), (_, mut target_rx)) = session(&[8; 32], 10);
+        let mut buf = [0u8; 64];
+        let sealed = host_tx.seal(b"bye\n", &mut buf).unwrap();
+        assert_eq!(target_rx.open(sealed), Err(SecureError::Unauthenticated));
+    }
+
+    #[test]
+    fn tampered() {
+        let ((mut host_tx, _), (_, mut target_rx)) = session(&PSK, 10);
+        let mut buf = [0u8; 64];
+        let len = host_tx.seal(b"hello\n", &mut buf).unwrap().len();
+        buf[COUNTER_SIZE] ^= 1;
+        assert_eq!(
+            target_rx.open(&mut buf[..len]),
+            Err(SecureError::Unauthenticated)
+        );
+        // the forgery doesn't stop the next genuine chunk from being opened.
+        let sealed = host_tx.seal(b"again\n", &mut buf).unwrap();
+        assert_eq!(target_rx.open(sealed).unwrap(), b"again\n");
+    }
+
+    #[test]
+    fn replayed() {
+        let ((mut host_tx, _), (_, mut target_rx)) = session(&PSK, 10);
+        let mut first = [0u8; 32];
+        let mut second = [0u8; 32];
+        let first_len = host_tx.seal(b"a", &mut first).unwrap().len();
+        let second_len = host_tx.seal(b"b", &mut second).unwrap().len();
+
+        // chunks may be lost...
+        assert_eq!(target_rx.open(&mut second[..second_len]).unwrap(), b"b");
+        // ...but not reordered...
+        assert_eq!(
+            target_rx.open(&mut first[..first_len]),
+            Err(SecureError::Replayed)
+        );
+        // ...or replayed.
+        let mut again = [0u8; 32];
+        let again_len = host_tx.seal(b"c", &mut again).unwrap().len();
+        let copy = again;
+        assert_eq!(target_rx.open(&mut again[..again_len]).unwrap(), b"c");
+        let mut again = copy;
+        assert_eq!(
+            target_rx.open(&mut again[..again_len]),
+            Err(SecureError::Replayed)
+        );
+    }
+
+    #[test]
+    fn bound_to_port() {
+        let ((mut host_tx, _), _) = session(&PSK, 10);
+        let (_, (_, mut other_rx)) = session(&PSK, 11);
+        let mut buf = [0u8; 32];
+        let sealed = host_tx.seal(b"a", &mut buf).unwrap();
+        assert_eq!(other_rx.open(sealed), Err(SecureError::Unauthenticated));
+    }
+
+    #[test]
+    fn unexpected_hello() {
+        let host = Handshake::new(Role::Initiator, 10, [1; 32]);
+        let other = Handshake::new(Role::Initiator, 10, [2; 32]);
+        // two initiators
+        assert!(matches!(
+            host.finish(&PSK, &other.hello()),
+            Err(SecureError::UnexpectedHello)
+        ));
+
+        let host = Handshake::new(Role::Initiator, 10, [1; 32]);
+        let target = Handshake::new(Role::Responder, 11, [2; 32]);
+        assert!(matches!(
+            host.finish(&PSK, &target.hello()),
+            Err(SecureError::UnexpectedHello)
+        ));
+    }
+
+    #[test]
+    fn weak_key() {
+        let host = Handshake::new(Role::Initiator, 10, [1; 32]);
+        let hello = SecureHello {
+            port: 10,
+            reply: true,
+            public: [0; 32],
+        };
+        assert!(matches!(
+            host.finish(&PSK, &hello),
+            Err(SecureError::WeakKey)
+        ));
+    }
+}
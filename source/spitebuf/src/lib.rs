@@ -80,6 +80,26 @@ impl<T, STO: Storage<T>> MpScQueue<T, STO> {
         }
     }
 
+    /// Returns the number of items currently in the queue.
+    ///
+    /// If items are concurrently being enqueued or dequeued, this may be out
+    /// of date as soon as it is returned.
+    pub fn len(&self) -> usize {
+        let enqueued = self.enqueue_pos.load(Ordering::Acquire);
+        let dequeued = self.dequeue_pos.load(Ordering::Acquire);
+        enqueued.wrapping_sub(dequeued).min(self.capacity())
+    }
+
+    /// Returns `true` if the queue currently contains no items.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the total number of items the queue can hold.
+    pub fn capacity(&self) -> usize {
+        self.storage.buf().1
+    }
+
     // Mark the channel as permanently closed. Any already sent data
     // can be retrieved, but no further data will be allowed to be pushed.
     pub fn close(&self) {
@@ -88,6 +108,11 @@ impl<T, STO: Storage<T>> MpScQueue<T, STO> {
         self.prod_wait.close();
     }
 
+    /// Returns `true` if the channel has been [closed](Self::close).
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::Acquire)
+    }
+
     /// Returns the item in the front of the queue, or `None` if the queue is empty
     pub fn dequeue_sync(&self) -> Option<T> {
         // Note: DON'T check the closed flag on dequeue. We want to be able
@@ -9,7 +9,12 @@ use tracing_serde_structured::{
 pub enum TraceEvent<'a> {
     /// Sent by the target periodically when not actively tracing, to indicate
     /// liveness, or to ack a [`HostRequest::SetMaxLevel`].
-    Heartbeat(Option<SerializeLevel>),
+    ///
+    /// Includes the target's identity, if it knows it.
+    Heartbeat(
+        Option<SerializeLevel>,
+        #[serde(borrow)] Option<DeviceInfo<'a>>,
+    ),
     RegisterMeta {
         id: MetaId,
 
@@ -22,6 +27,8 @@ pub enum TraceEvent<'a> {
         #[serde(borrow)]
         fields: SerializeRecordFields<'a>,
         meta: MetaId,
+        /// When the event was recorded on the target.
+        timestamp: Timestamp,
     },
 
     NewSpan {
@@ -31,6 +38,8 @@ pub enum TraceEvent<'a> {
         is_root: bool,
         #[serde(borrow)]
         fields: SerializeSpanFields<'a>,
+        /// When the span was created on the target.
+        timestamp: Timestamp,
     },
 
     Enter(SerializeId),
@@ -46,6 +55,54 @@ pub enum TraceEvent<'a> {
         events: usize,
         metas: usize,
     },
+
+    /// Aggregated timing for a single span callsite, sent periodically by a
+    /// target in summary mode instead of [`TraceEvent::Enter`] and
+    /// [`TraceEvent::Exit`] for every span.
+    ///
+    /// Durations are the time spent *inside* spans (between entering and
+    /// exiting them) over the last summary period.
+    SpanSummary {
+        meta: MetaId,
+        /// Number of times a span from this callsite was exited.
+        count: u32,
+        /// Total time spent inside spans from this callsite, in microseconds.
+        total_us: u64,
+        /// Longest single time spent inside a span from this callsite, in
+        /// microseconds.
+        max_us: u64,
+    },
+
+    /// Sent after all [`TraceEvent::SpanSummary`]s for a summary period.
+    SummaryEnd {
+        /// Length of the summary period, in milliseconds.
+        period_ms: u32,
+        /// Number of span exits which could not be recorded, because the
+        /// target's span or callsite tables were full.
+        dropped: usize,
+    },
+}
+
+/// Identifies the target that sent a [`TraceEvent::Heartbeat`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct DeviceInfo<'a> {
+    /// The target's stable unique ID.
+    pub id: [u8; 16],
+    /// The target's hostname.
+    pub hostname: &'a str,
+}
+
+/// When a [`TraceEvent`] was recorded, according to the target's clocks.
+///
+/// Hosts should prefer this over the time at which they received the event,
+/// which may lag far behind if the target's trace buffers back up.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Timestamp {
+    /// Time since the target booted, in microseconds.
+    pub uptime_us: u64,
+    /// Wall-clock time (UTC) in microseconds since the Unix epoch, if the
+    /// target knows it.
+    pub unix_us: Option<u64>,
 }
 
 /// Requests sent from a host to a trace target.
@@ -56,6 +113,14 @@ pub enum HostRequest {
     ///
     /// This may cause the trace target to send new metadata to the host.
     SetMaxLevel(Option<SerializeLevel>), // TODO(eliza): add a keepalive?
+
+    /// Asks the target to send a [`TraceEvent::RegisterMeta`] for every
+    /// callsite it has enabled.
+    ///
+    /// Hosts send this when they receive traces whose metadata they don't
+    /// know, such as after reconnecting to a target which registered its
+    /// callsites before the host was listening.
+    ResyncMetadata,
 }
 
 #[derive(Copy, Clone, Hash, Eq, PartialEq, serde::Serialize, serde::Deserialize)]
@@ -1,6 +1,8 @@
 use clap::Parser;
 use connection::Connect;
+use libcrowtty::exec::Status;
 use miette::{Context, IntoDiagnostic};
+use std::process::ExitCode;
 use tracing::level_filters::LevelFilter;
 
 mod connection;
@@ -18,6 +20,9 @@ struct Args {
     #[clap(flatten)]
     settings: libcrowtty::Settings,
 
+    #[clap(flatten)]
+    exec: libcrowtty::Exec,
+
     /// a comma-separated list of `tracing` targets and levels to enable.
     ///
     /// for example, `info,kernel=debug,kernel::comms::bbq=trace` will enable:
@@ -42,10 +47,11 @@ struct Args {
     trace_filter: tracing_subscriber::filter::Targets,
 }
 
-fn main() -> miette::Result<()> {
+fn main() -> miette::Result<ExitCode> {
     let Args {
         connect,
         settings,
+        exec,
         verbose,
         trace_filter,
     } = Args::parse();
@@ -53,8 +59,30 @@ fn main() -> miette::Result<()> {
         .connect()
         .into_diagnostic()
         .with_context(|| format!("failed to connect to {connect}"))?;
-    libcrowtty::Crowtty::new(conn.log_tag().verbose(verbose))
+    let tag = conn.log_tag().verbose(verbose);
+
+    if exec.is_enabled() {
+        let mut status = Status::Ok;
+        for output in exec.run(tag, conn)? {
+            if !output.output.is_empty() {
+                println!("{}", output.output);
+            }
+            match output.status {
+                Status::Ok => {}
+                Status::Error => eprintln!("error: `{}` failed", output.line),
+                Status::TimedOut => eprintln!(
+                    "error: `{}` did not complete within {}ms",
+                    output.line, exec.exec_timeout_ms
+                ),
+            }
+            status = output.status;
+        }
+        return Ok(ExitCode::from(status.exit_code()));
+    }
+
+    libcrowtty::Crowtty::new(tag)
         .settings(settings)
         .trace_filter(trace_filter)
-        .run(conn)
+        .run(conn)?;
+    Ok(ExitCode::SUCCESS)
 }
@@ -13,6 +13,8 @@ fn main() {
         input_buf_elems: 1024,
         output_buf_elems: 4096,
         dict_buf_elems: 16 * 1024,
+        case_insensitive: true,
+        prelude: true,
     };
     let mut lbf = LBForth::from_params(params, (), Forth::FULL_BUILTINS);
     let forth = &mut lbf.forth;
@@ -0,0 +1,319 @@
+//! Converts a PIE ELF executable into a mnemOS relocatable flat binary.
+//!
+//! See the [`abi::flat`] module for a description of the output format.
+
+use abi::flat::Header;
+use std::{fmt, process::ExitCode};
+
+const PT_LOAD: u32 = 1;
+const SHT_RELA: u32 = 4;
+const SHT_REL: u32 = 9;
+const ET_DYN: u16 = 3;
+
+const EM_ARM: u16 = 40;
+const EM_X86_64: u16 = 62;
+const EM_AARCH64: u16 = 183;
+const EM_RISCV: u16 = 243;
+
+fn main() -> ExitCode {
+    let mut args = std::env::args().skip(1);
+    let (Some(input), Some(output), None) = (args.next(), args.next(), args.next()) else {
+        eprintln!("usage: flatpack <INPUT.elf> <OUTPUT.mnrf>");
+        return ExitCode::FAILURE;
+    };
+
+    let result = std::fs::read(&input)
+        .map_err(|e| Error(format!("failed to read {input}: {e}")))
+        .and_then(|elf| pack(&elf))
+        .and_then(|flat| {
+            std::fs::write(&output, flat)
+                .map_err(|e| Error(format!("failed to write {output}: {e}")))
+        });
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("flatpack: {error}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+struct Error(String);
+
+macro_rules! bail {
+    ($($arg:tt)*) => {
+        return Err(Error(format!($($arg)*)))
+    };
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// A little-endian ELF file, either 32- or 64-bit.
+struct Elf<'a> {
+    bytes: &'a [u8],
+    is_64: bool,
+}
+
+struct Segment {
+    offset: u64,
+    vaddr: u64,
+    filesz: u64,
+    memsz: u64,
+    align: u64,
+}
+
+struct Section {
+    kind: u32,
+    offset: u64,
+    size: u64,
+    entsize: u64,
+}
+
+fn pack(bytes: &[u8]) -> Result<Vec<u8>, Error> {
+    let elf = Elf::parse(bytes)?;
+    let machine = elf.u16(18)?;
+    if elf.u16(16)? != ET_DYN {
+        bail!("input is not a position-independent executable (ET_DYN)");
+    }
+    let relative = match machine {
+        EM_X86_64 => 8,
+        EM_RISCV => 3,
+        EM_AARCH64 => 1027,
+        EM_ARM => 23,
+        _ => bail!("unsupported ELF machine type {machine}"),
+    };
+
+    let segments = elf.segments()?;
+    if segments.is_empty() {
+        bail!("input has no loadable segments");
+    }
+    let base = segments.iter().map(|s| s.vaddr).min().unwrap();
+    let image_end = segments.iter().map(|s| s.vaddr + s.filesz).max().unwrap();
+    let mem_end = segments.iter().map(|s| s.vaddr + s.memsz).max().unwrap();
+    let align = segments.iter().map(|s| s.align).max().unwrap().max(8);
+
+    // Any gaps between segments (including the bss of segments other than
+    // the last one) are zero-filled in the image.
+    let mut image = vec![0u8; (image_end - base) as usize];
+    for seg in &segments {
+        let start = (seg.vaddr - base) as usize;
+        let data = elf.slice(seg.offset, seg.filesz)?;
+        image[start..start + data.len()].copy_from_slice(data);
+    }
+
+    let ptr_width = if elf.is_64 { 8 } else { 4 };
+    let mut relocs = Vec::new();
+    for section in elf.sections()? {
+        let is_rela = match section.kind {
+            SHT_RELA => true,
+            SHT_REL => false,
+            _ => continue,
+        };
+        if section.entsize == 0 {
+            bail!("relocation section has zero entry size");
+        }
+        let table = elf.slice(section.offset, section.size)?;
+        for entry in table.chunks_exact(section.entsize as usize) {
+            let rel = Elf {
+                bytes: entry,
+                is_64: elf.is_64,
+            };
+            let r_offset = rel.word(0)?;
+            let r_info = rel.word(ptr_width)?;
+            let kind = if elf.is_64 {
+                r_info & 0xffff_ffff
+            } else {
+                r_info & 0xff
+            };
+            match kind {
+                // R_*_NONE
+                0 => continue,
+                k if k == relative => {}
+                k => bail!("unsupported relocation type {k} at {r_offset:#x}"),
+            }
+            let Some(offset) = r_offset
+                .checked_sub(base)
+                .filter(|o| *o < image.len() as u64)
+            else {
+                bail!("relocation at {r_offset:#x} is outside the loaded image");
+            };
+            // REL relocations store the addend in place; RELA relocations
+            // carry it in the table, so write it into the image.
+            if is_rela {
+                let addend = rel.word(ptr_width * 2)?.wrapping_sub(base);
+                let at = offset as usize;
+                let Some(word) = image.get_mut(at..at + ptr_width) else {
+                    bail!("relocation at {r_offset:#x} is outside the loaded image");
+                };
+                word.copy_from_slice(&addend.to_le_bytes()[..ptr_width]);
+            }
+            relocs.push(offset as u32);
+        }
+    }
+    relocs.sort_unstable();
+    relocs.dedup();
+
+    let entry = elf.word(24)?.wrapping_sub(base);
+    let header = Header {
+        ptr_width: ptr_width as u8,
+        entry: to_u32(entry, "entry point")?,
+        image_len: to_u32(image.len() as u64, "image length")?,
+        bss_len: to_u32(mem_end - image_end, "bss length")?,
+        align: to_u32(align, "alignment")?,
+        reloc_count: to_u32(relocs.len() as u64, "relocation count")?,
+    };
+
+    let mut out = header.to_bytes().to_vec();
+    out.extend_from_slice(&image);
+    for reloc in relocs {
+        out.extend_from_slice(&reloc.to_le_bytes());
+    }
+
+    // Sanity check that the kernel will be able to parse what we wrote.
+    abi::flat::FlatBinary::parse(&out).map_err(|e| Error(format!("invalid output: {e}")))?;
+    eprintln!(
+        "flatpack: {} bytes image, {} bytes bss, {} relocations, entry at {:#x}",
+        header.image_len, header.bss_len, header.reloc_count, header.entry
+    );
+    Ok(out)
+}
+
+fn to_u32(val: u64, what: &str) -> Result<u32, Error> {
+    u32::try_from(val).map_err(|_| Error(format!("{what} {val:#x} does not fit in 32 bits")))
+}
+
+impl<'a> Elf<'a> {
+    fn parse(bytes: &'a [u8]) -> Result<Self, Error> {
+        if bytes.get(..4) != Some(b"\x7fELF") {
+            bail!("input is not an ELF file");
+        }
+        let is_64 = match bytes.get(4) {
+            Some(1) => false,
+            Some(2) => true,
+            _ => bail!("invalid ELF class"),
+        };
+        if bytes.get(5) != Some(&1) {
+            bail!("only little-endian ELF files are supported");
+        }
+        Ok(Self { bytes, is_64 })
+    }
+
+    fn slice(&self, offset: u64, len: u64) -> Result<&'a [u8], Error> {
+        usize::try_from(offset)
+            .ok()
+            .zip(usize::try_from(len).ok())
+            .and_then(|(offset, len)| self.bytes.get(offset..offset.checked_add(len)?))
+            .ok_or_else(|| Error(format!("ELF file truncated at {offset:#x}")))
+    }
+
+    fn u16(&self, at: usize) -> Result<u16, Error> {
+        let b = self.slice(at as u64, 2)?;
+        Ok(u16::from_le_bytes([b[0], b[1]]))
+    }
+
+    fn u32(&self, at: usize) -> Result<u32, Error> {
+        let b = self.slice(at as u64, 4)?;
+        Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn u64(&self, at: usize) -> Result<u64, Error> {
+        let b = self.slice(at as u64, 8)?;
+        let mut buf = [0u8; 8];
+        buf.copy_from_slice(b);
+        Ok(u64::from_le_bytes(buf))
+    }
+
+    /// Read a native-width word (`Elf32_Addr` or `Elf64_Addr`).
+    fn word(&self, at: usize) -> Result<u64, Error> {
+        if self.is_64 {
+            self.u64(at)
+        } else {
+            self.u32(at).map(u64::from)
+        }
+    }
+
+    /// Read the offset, entry size, and entry count of the program or
+    /// section header table from the ELF header.
+    fn table(&self, off_at: usize, entsize_at: usize) -> Result<(u64, usize, usize), Error> {
+        let offset = self.word(off_at)?;
+        let entsize = self.u16(entsize_at)? as usize;
+        let count = self.u16(entsize_at + 2)? as usize;
+        Ok((offset, entsize, count))
+    }
+
+    fn segments(&self) -> Result<Vec<Segment>, Error> {
+        let (phoff, entsize, count) = if self.is_64 {
+            self.table(32, 54)?
+        } else {
+            self.table(28, 42)?
+        };
+        let mut segments = Vec::new();
+        for i in 0..count {
+            let ph = Elf {
+                bytes: self.slice(phoff + (i * entsize) as u64, entsize as u64)?,
+                is_64: self.is_64,
+            };
+            if ph.u32(0)? != PT_LOAD {
+                continue;
+            }
+            let seg = if self.is_64 {
+                Segment {
+                    offset: ph.u64(8)?,
+                    vaddr: ph.u64(16)?,
+                    filesz: ph.u64(32)?,
+                    memsz: ph.u64(40)?,
+                    align: ph.u64(48)?,
+                }
+            } else {
+                Segment {
+                    offset: ph.u32(4)?.into(),
+                    vaddr: ph.u32(8)?.into(),
+                    filesz: ph.u32(16)?.into(),
+                    memsz: ph.u32(20)?.into(),
+                    align: ph.u32(28)?.into(),
+                }
+            };
+            if seg.memsz > 0 {
+                segments.push(seg);
+            }
+        }
+        Ok(segments)
+    }
+
+    fn sections(&self) -> Result<Vec<Section>, Error> {
+        let (shoff, entsize, count) = if self.is_64 {
+            self.table(40, 58)?
+        } else {
+            self.table(32, 46)?
+        };
+        let mut sections = Vec::with_capacity(count);
+        for i in 0..count {
+            let sh = Elf {
+                bytes: self.slice(shoff + (i * entsize) as u64, entsize as u64)?,
+                is_64: self.is_64,
+            };
+            let section = if self.is_64 {
+                Section {
+                    kind: sh.u32(4)?,
+                    offset: sh.u64(24)?,
+                    size: sh.u64(32)?,
+                    entsize: sh.u64(56)?,
+                }
+            } else {
+                Section {
+                    kind: sh.u32(4)?,
+                    offset: sh.u32(16)?.into(),
+                    size: sh.u32(20)?.into(),
+                    entsize: sh.u32(36)?.into(),
+                }
+            };
+            sections.push(section);
+        }
+        Ok(sections)
+    }
+}
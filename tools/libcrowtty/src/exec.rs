@@ -0,0 +1,237 @@
+//! Runs lines of Forth on a target's Forth shell, for scripted testing.
+//!
+//! Rather than bridging the target's SerMux ports to the host, [`Exec`] sends
+//! each line to a Forth shell's port, and collects the shell's output until
+//! the shell reports that the line completed (`ok.`) or failed (`ERROR.`).
+//! Lines are run one at a time, in order, stopping at the first line which
+//! fails or times out.
+//!
+//! Shells may color their output using ANSI escape sequences. These are
+//! removed from the output collected by [`Exec`], so that scripts see plain
+//! text. Other ports' output is always forwarded untouched.
+use crate::{LogTag, MAX_CHUNK};
+use clap::Parser;
+use miette::{Context, IntoDiagnostic};
+use owo_colors::{OwoColorize, Stream};
+use sermux_proto::{DecodeError, Frame, FrameVersion, WellKnown};
+use std::{
+    io::{ErrorKind, Read, Write},
+    time::{Duration, Instant},
+};
+
+/// Printed by the Forth shell after a line completes successfully.
+const OK: &str = "ok.\n";
+
+/// Printed by the Forth shell after a line fails.
+const ERROR: &str = "ERROR.\n";
+
+#[derive(Debug, Clone, Parser)]
+#[clap(next_help_heading = "Exec Options")]
+pub struct Exec {
+    /// a line of Forth to run on the target, instead of starting an
+    /// interactive session.
+    ///
+    /// may be repeated, to run several lines in order. crowtty stops at the
+    /// first line which fails or times out, and exits with a non-zero status:
+    /// 1 if the line failed, or 2 if it timed out.
+    #[arg(short = 'e', long = "exec", value_name = "FORTH", global = true)]
+    pub lines: Vec<String>,
+
+    /// SerMux port of the Forth shell to run `--exec` lines on.
+    // the id can't be `port`, as it would clash with the `tcp` subcommand's
+    // port argument.
+    #[arg(id = "shell-port", long = "port", global = true, default_value_t = WellKnown::ForthShell0 as u16)]
+    pub port: u16,
+
+    /// how long to wait for each `--exec` line to complete, in milliseconds.
+    #[arg(long, global = true, default_value_t = 5_000)]
+    pub exec_timeout_ms: u64,
+}
+
+/// The result of running a line of Forth.
+#[derive(Debug, Clone)]
+pub struct Output {
+    /// The line which was run.
+    pub line: String,
+    /// Everything the shell printed while running the line, not including the
+    /// `ok.` or `ERROR.` at the end, with any ANSI escape sequences removed.
+    pub output: String,
+    pub status: Status,
+}
+
+/// How a line of Forth finished.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Status {
+    /// The shell printed `ok.`.
+    Ok,
+    /// The shell printed `ERROR.`.
+    Error,
+    /// The shell didn't finish the line before the timeout elapsed.
+    TimedOut,
+}
+
+/// A connection to a single Forth shell on the target.
+struct Shell<P> {
+    conn: P,
+    port: u16,
+    tag: LogTag,
+    /// Bytes read from the connection which are not yet a whole frame.
+    carry: Vec<u8>,
+}
+
+impl Exec {
+    /// Returns `true` if there are any lines to run.
+    pub fn is_enabled(&self) -> bool {
+        !self.lines.is_empty()
+    }
+
+    /// Runs each line on the target's Forth shell, in order.
+    ///
+    /// Stops after the first line which does not complete successfully, so
+    /// the last [`Output`] returned is the one that failed (if any did).
+    pub fn run(&self, tag: LogTag, conn: impl Read + Write) -> miette::Result<Vec<Output>> {
+        let timeout = Duration::from_millis(self.exec_timeout_ms);
+        let mut shell = Shell {
+            conn,
+            port: self.port,
+            tag: tag.port(self.port),
+            carry: Vec::new(),
+        };
+
+        let mut outputs = Vec::with_capacity(self.lines.len());
+        for line in &self.lines {
+            let output = shell.exec(line, timeout)?;
+            let failed = output.status != Status::Ok;
+            outputs.push(output);
+            if failed {
+                break;
+            }
+        }
+        Ok(outputs)
+    }
+}
+
+impl Status {
+    /// Returns the exit code crowtty exits with when a line ends with this
+    /// status.
+    pub fn exit_code(self) -> u8 {
+        match self {
+            Self::Ok => 0,
+            Self::Error => 1,
+            Self::TimedOut => 2,
+        }
+    }
+}
+
+impl<P: Read + Write> Shell<P> {
+    fn exec(&mut self, line: &str, timeout: Duration) -> miette::Result<Output> {
+        let line = line.trim_end();
+        if line.contains('\n') {
+            miette::bail!("`--exec` lines may not contain newlines");
+        }
+
+        // the shell runs each chunk it receives as a line, so the line must be
+        // sent as a single frame.
+        let mut msg = line.as_bytes().to_vec();
+        msg.push(b'\n');
+        if msg.len() > MAX_CHUNK {
+            miette::bail!("`{line}` is longer than {MAX_CHUNK} bytes");
+        }
+        let frame = Frame::new(FrameVersion::V1, self.port, &msg);
+        let mut buf = vec![0; frame.buffer_required()];
+        let enc = frame
+            .encode_to(&mut buf)
+            .expect("sermux encoding should not fail");
+        self.tag.if_verbose(format_args!(
+            "{} {}B <- :{}",
+            "EXEC".if_supports_color(Stream::Stdout, |s| s.cyan()),
+            enc.len(),
+            self.port
+        ));
+        self.conn
+            .write_all(enc)
+            .into_diagnostic()
+            .with_context(|| format!("failed to send `{line}` to port {}", self.port))?;
+
+        let deadline = Instant::now() + timeout;
+        let mut output = String::new();
+        let (status, output) = loop {
+            // escape sequences may be split across frames, so strip them from
+            // everything received so far.
+            let mut plain = strip_ansi(&output);
+            if let Some(rest) = plain.strip_suffix(OK) {
+                plain.truncate(rest.len());
+                break (Status::Ok, plain);
+            }
+            if let Some(rest) = plain.strip_suffix(ERROR) {
+                plain.truncate(rest.len());
+                break (Status::Error, plain);
+            }
+            if Instant::now() >= deadline {
+                break (Status::TimedOut, plain);
+            }
+            self.read(&mut output)?;
+        };
+
+        Ok(Output {
+            line: line.to_string(),
+            output: output.trim_end().to_string(),
+            status,
+        })
+    }
+
+    /// Reads from the connection, appending anything sent on the shell's port
+    /// to `output`. Frames for other ports are discarded.
+    fn read(&mut self, output: &mut String) -> miette::Result<()> {
+        let mut buf = [0u8; 256];
+        let used = match self.conn.read(&mut buf) {
+            Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(()),
+            Err(e) if e.kind() == ErrorKind::TimedOut => return Ok(()),
+            Ok(0) => return Ok(()),
+            Ok(used) => used,
+            Err(e) => return Err(e).into_diagnostic().context("inbound read failed"),
+        };
+        self.carry.extend_from_slice(&buf[..used]);
+
+        while let Some(pos) = self.carry.iter().position(|b| *b == 0) {
+            let remainder = self.carry.split_off(pos + 1);
+            match Frame::decode_from(&mut self.carry) {
+                Ok(Frame { port, chunk, .. }) if port == self.port => {
+                    self.tag.if_verbose(format_args!(
+                        "{} {}B -> :{port}",
+                        "EXEC".if_supports_color(Stream::Stdout, |s| s.bright_purple()),
+                        chunk.len()
+                    ));
+                    output.push_str(&String::from_utf8_lossy(chunk));
+                }
+                // other ports (including tracing), plain text, and flushes
+                // aren't part of the shell's output.
+                Ok(_) | Err(DecodeError::CobsDecodeFailed | DecodeError::MalformedFrame) => {}
+            }
+            self.carry = remainder;
+        }
+        Ok(())
+    }
+}
+
+/// Removes ANSI escape sequences, such as color codes, from `s`.
+pub(crate) fn strip_ansi(s: &str) -> String {
+    let mut plain = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\x1b' {
+            plain.push(c);
+            continue;
+        }
+        // a control sequence is `ESC [`, followed by parameters, and ends
+        // with a byte in `@..=~`. other escapes are a single character.
+        if chars.next() == Some('[') {
+            for c in chars.by_ref() {
+                if ('@'..='~').contains(&c) {
+                    break;
+                }
+            }
+        }
+    }
+    plain
+}
@@ -0,0 +1,211 @@
+use owo_colors::{OwoColorize, Stream};
+use sermux_proto::{NoticeSeverity, Ping};
+use std::{
+    fmt,
+    sync::mpsc::{self, RecvTimeoutError},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{LogTag, WorkerHandle};
+
+/// Monitors the liveness pings sent by the target's hello daemon, and alerts
+/// when pings are dropped, stop arriving, or indicate that the target has
+/// rebooted.
+pub(crate) struct HealthWorker {
+    _tx: mpsc::Sender<Vec<u8>>,
+    rx: mpsc::Receiver<Vec<u8>>,
+    tag: LogTag,
+    timeout: Duration,
+}
+
+/// The last ping received from the target.
+struct LastPing {
+    seq: u32,
+    uptime_ms: u64,
+    at: Instant,
+}
+
+/// How a ping follows the last one.
+#[derive(Debug, PartialEq, Eq)]
+enum Sequence {
+    /// The ping is the next one.
+    Next,
+    /// This many pings were dropped before this one.
+    Missed(u32),
+    /// The target has rebooted since the last ping.
+    Rebooted,
+}
+
+/// Works out how `ping` follows `last`.
+///
+/// Sequence numbers wrap, so a ping is only treated as going backwards (and
+/// so as coming from a rebooted target) if it is less than half the sequence
+/// number space ahead of the last one. Uptime doesn't wrap.
+fn classify(last: &LastPing, ping: &Ping<'_>) -> Sequence {
+    let delta = ping.seq.wrapping_sub(last.seq);
+    if delta == 0 || delta > u32::MAX / 2 || ping.uptime_ms < last.uptime_ms {
+        Sequence::Rebooted
+    } else if delta == 1 {
+        Sequence::Next
+    } else {
+        Sequence::Missed(delta - 1)
+    }
+}
+
+impl HealthWorker {
+    pub fn spawn(tag: LogTag, timeout: Duration) -> WorkerHandle {
+        let (inp_send, inp_recv) = mpsc::channel();
+        let (out_send, out_recv) = mpsc::channel::<Vec<u8>>();
+        let worker = Self {
+            _tx: inp_send,
+            rx: out_recv,
+            tag,
+            timeout,
+        };
+        let thread_hdl = thread::spawn(move || worker.run());
+        WorkerHandle {
+            out: out_send,
+            inp: inp_recv,
+            _thread_hdl: thread_hdl,
+        }
+    }
+
+    fn run(self) {
+        let label = "PING".if_supports_color(Stream::Stdout, |x| x.bright_blue());
+        let warn = "WARN".if_supports_color(Stream::Stdout, |x| x.yellow());
+        let err = "ERR!".if_supports_color(Stream::Stdout, |x| x.red());
+        let note = "NOTE".if_supports_color(Stream::Stdout, |x| x.green());
+        let tag = self.tag;
+
+        let mut last: Option<LastPing> = None;
+        // Set once we've alerted that pings stopped, so that we only alert
+        // once per outage.
+        let mut stalled = false;
+
+        loop {
+            let chunk = match self.rx.recv_timeout(self.timeout) {
+                Ok(chunk) => chunk,
+                Err(RecvTimeoutError::Timeout) => {
+                    // Don't alert if we've never seen a ping; the target may
+                    // not have the hello daemon enabled.
+                    if let Some(ref last) = last {
+                        if !stalled {
+                            stalled = true;
+                            println!(
+                                "{tag} {label} {err} no ping from target in {:.1?} \
+                                (last seq {}, uptime {}ms); is it hung?",
+                                last.at.elapsed(),
+                                last.seq,
+                                last.uptime_ms,
+                            );
+                        }
+                    }
+                    continue;
+                }
+                Err(RecvTimeoutError::Disconnected) => return,
+            };
+
+            let ping = match Ping::from_bytes(&chunk) {
+                Ok(ping) => ping,
+                Err(e) => {
+                    println!("{tag} {label} {err} bad ping ({e}): {chunk:02x?}");
+                    continue;
+                }
+            };
+            tag.if_verbose(format_args!(
+                "{label} seq {} uptime {}ms ticks {} {:?}",
+                ping.seq,
+                ping.uptime_ms,
+                ping.ticks,
+                String::from_utf8_lossy(ping.payload),
+            ));
+
+            // notifications posted on the target are always shown, as there
+            // may be no display to show them on.
+            if let Some(notice) = ping.notice() {
+                let severity: &dyn fmt::Display = match notice.severity {
+                    NoticeSeverity::Info => &note,
+                    NoticeSeverity::Warn => &warn,
+                    NoticeSeverity::Error => &err,
+                };
+                println!("{tag} {label} {severity} {}", notice.text);
+            }
+
+            if let Some(last) = last.as_ref() {
+                if stalled {
+                    println!(
+                        "{tag} {label} pings resumed after {:.1?}",
+                        last.at.elapsed()
+                    );
+                }
+
+                match classify(last, &ping) {
+                    Sequence::Next => {}
+                    Sequence::Rebooted => println!(
+                        "{tag} {label} {warn} target rebooted (seq {} -> {}, uptime {}ms -> {}ms)",
+                        last.seq, ping.seq, last.uptime_ms, ping.uptime_ms,
+                    ),
+                    Sequence::Missed(missed) => println!(
+                        "{tag} {label} {warn} missed {missed} ping(s) (seq {} -> {})",
+                        last.seq, ping.seq,
+                    ),
+                }
+            }
+
+            stalled = false;
+            last = Some(LastPing {
+                seq: ping.seq,
+                uptime_ms: ping.uptime_ms,
+                at: Instant::now(),
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn last(seq: u32, uptime_ms: u64) -> LastPing {
+        LastPing {
+            seq,
+            uptime_ms,
+            at: Instant::now(),
+        }
+    }
+
+    fn ping(seq: u32, uptime_ms: u64) -> Ping<'static> {
+        Ping {
+            seq,
+            uptime_ms,
+            ticks: 0,
+            payload: b"hello",
+        }
+    }
+
+    #[test]
+    fn sequence() {
+        assert_eq!(classify(&last(1, 1000), &ping(2, 2000)), Sequence::Next);
+        assert_eq!(
+            classify(&last(1, 1000), &ping(4, 4000)),
+            Sequence::Missed(2)
+        );
+        assert_eq!(classify(&last(1, 1000), &ping(1, 2000)), Sequence::Rebooted);
+        assert_eq!(classify(&last(5, 5000), &ping(0, 100)), Sequence::Rebooted);
+        // uptime going backwards is a reboot, even if the sequence looks fine
+        assert_eq!(classify(&last(1, 1000), &ping(2, 10)), Sequence::Rebooted);
+    }
+
+    #[test]
+    fn sequence_wraps() {
+        assert_eq!(
+            classify(&last(u32::MAX, 1000), &ping(0, 2000)),
+            Sequence::Next
+        );
+        assert_eq!(
+            classify(&last(u32::MAX - 1, 1000), &ping(1, 4000)),
+            Sequence::Missed(2)
+        );
+    }
+}
@@ -1,7 +1,10 @@
 use clap::Parser;
 use miette::{Context, IntoDiagnostic};
 use owo_colors::{OwoColorize, Stream};
-use sermux_proto::{DecodeError, OwnedPortChunk, WellKnown};
+use sermux_proto::{
+    DecodeError, DirectoryEntry, Frame, FrameVersion, PortReset, SecureHello, VersionAdvert,
+    WellKnown,
+};
 use std::{
     collections::HashMap,
     fmt,
@@ -13,12 +16,26 @@ use std::{
 };
 use tracing::level_filters::LevelFilter;
 
+pub mod exec;
+mod health;
 mod keyboard;
+mod pty;
+pub mod script;
+mod secure;
 mod trace;
 
+pub use exec::Exec;
+pub use trace::{TraceFormat, TraceRecord, TraceSession};
+
+/// The most data sent to the target in a single frame. Longer messages are
+/// split into several frames (as a jumbo message, if the target supports
+/// them).
+const MAX_CHUNK: usize = 256;
+
 pub struct Crowtty {
     settings: Settings,
     trace_filter: tracing_subscriber::filter::Targets,
+    trace_session: TraceSession,
     tag: LogTag,
 }
 
@@ -41,6 +58,46 @@ pub struct Settings {
     /// SerMux port `n` will be mapped to TCP port `n + tcp-port-base` on localhost.
     #[arg(long, global = true, default_value_t = 10_000)]
     tcp_port_base: u16,
+
+    /// SerMux ports to expose as host PTYs (on Unix) or named pipes (on
+    /// Windows), rather than as TCP ports.
+    ///
+    /// This lets tools which expect a serial device, such as `screen` or
+    /// `minicom`, attach to the port directly. May be repeated.
+    #[arg(long = "pty", global = true, value_name = "PORT")]
+    pty_ports: Vec<u16>,
+
+    /// SerMux ports to reset when connecting to the target.
+    ///
+    /// This discards anything the target has buffered for the port, and asks
+    /// the service that owns it to start afresh, so that a wedged shell or
+    /// tracing port can be recovered without rebooting the target. May be
+    /// repeated.
+    #[arg(long = "reset", global = true, value_name = "PORT")]
+    reset_ports: Vec<u16>,
+
+    /// SerMux ports which the target opened as secure ports.
+    ///
+    /// Data on these ports is encrypted and authenticated, using keys
+    /// negotiated with the target when it is connected. This requires the
+    /// target's pre-shared key (`--psk`). May be repeated.
+    #[arg(long = "secure", global = true, value_name = "PORT")]
+    secure_ports: Vec<u16>,
+
+    /// the pre-shared key for secure ports, as 64 hex digits.
+    ///
+    /// this must match the `psk` in the target's serial mux settings.
+    #[arg(long, global = true, env = "CROWTTY_PSK", hide_env_values = true, value_parser = secure::parse_psk)]
+    psk: Option<secure::Psk>,
+
+    /// how long to wait for a liveness ping from the target before alerting
+    /// that it may be hung, in milliseconds.
+    #[arg(long, global = true, default_value_t = 3_000)]
+    ping_timeout_ms: u64,
+
+    /// how to display traces from the target.
+    #[arg(long, global = true, value_enum, default_value_t = TraceFormat::Flat)]
+    trace_format: TraceFormat,
 }
 
 impl Default for Settings {
@@ -49,6 +106,12 @@ impl Default for Settings {
             keyboard_port: WellKnown::PseudoKeyboard.into(),
             disable_stdin: false,
             tcp_port_base: 10_000,
+            pty_ports: Vec::new(),
+            reset_ports: Vec::new(),
+            secure_ports: Vec::new(),
+            psk: None,
+            ping_timeout_ms: 3_000,
+            trace_format: TraceFormat::Flat,
         }
     }
 }
@@ -67,6 +130,7 @@ impl Crowtty {
             settings: Default::default(),
             trace_filter: tracing_subscriber::filter::Targets::new()
                 .with_default(LevelFilter::INFO),
+            trace_session: Default::default(),
             tag,
         }
     }
@@ -82,6 +146,15 @@ impl Crowtty {
         }
     }
 
+    /// Keeps trace metadata in `session`, so that traces can still be decoded
+    /// when a new `Crowtty` with the same session reconnects to the target.
+    pub fn trace_session(self, trace_session: TraceSession) -> Self {
+        Self {
+            trace_session,
+            ..self
+        }
+    }
+
     pub fn run(self, mut port: impl Read + Write) -> miette::Result<()> {
         let Self {
             settings:
@@ -89,15 +162,30 @@ impl Crowtty {
                     keyboard_port,
                     disable_stdin,
                     tcp_port_base,
+                    pty_ports,
+                    reset_ports,
+                    secure_ports,
+                    psk,
+                    ping_timeout_ms,
+                    trace_format,
                 },
             trace_filter,
+            trace_session,
             tag,
         } = self;
 
+        if !secure_ports.is_empty() && psk.is_none() {
+            miette::bail!("secure ports (--secure) need the target's pre-shared key (--psk)");
+        }
+        // without any secure ports, the key is never used.
+        let mut secure = secure::SecurePorts::new(psk.unwrap_or_default().0, &secure_ports);
+
         let mut carry = Vec::new();
 
         let mut manager = TcpManager {
             workers: HashMap::new(),
+            tcp_port_base,
+            pty_ports,
         };
 
         let mut host_ports = vec![WellKnown::Loopback.into(), WellKnown::HelloWorld.into()];
@@ -128,102 +216,56 @@ impl Crowtty {
         // # connect to port N - stdio
         // stty -icanon -echo && ncat 127.0.0.1 $PORT
         // ```
-        for i in [WellKnown::Loopback.into(), WellKnown::HelloWorld.into()].into_iter() {
-            let (inp_send, inp_recv) = channel();
-            let (out_send, out_recv) = channel();
-
-            let socket =
-                std::net::TcpListener::bind(format!("127.0.0.1:{}", tcp_port_base + i)).unwrap();
-
-            let work = TcpWorker {
-                out: out_recv,
-                inp: inp_send,
-                socket,
-                port: i,
-            };
-            let tag = tag.port(i);
-            let thread_hdl = spawn(move || {
-                let mux = " MUX".if_supports_color(Stream::Stdout, |s| s.cyan());
-                let dmux = "DMUX".if_supports_color(Stream::Stdout, |s| s.bright_purple());
-                let err = "ERR!".if_supports_color(Stream::Stdout, |err| err.red());
-                for skt in work.socket.incoming() {
-                    let mut skt = match skt {
-                        Ok(skt) => skt,
-                        Err(e) => {
-                            panic!(
-                                "{tag} CONN failed to accept host connection to port {} (:{}): {e}",
-                                tcp_port_base + work.port,
-                                work.port
-                            );
-                        }
-                    };
-
-                    println!(
-                        "{tag} CONN host connected to port {} (:{})",
-                        tcp_port_base + work.port,
-                        work.port
-                    );
-
-                    skt.set_read_timeout(Some(Duration::from_millis(10))).ok();
-                    // skt.set_nonblocking(true).ok();
-                    // skt.set_nodelay(true).ok();
-
-                    // let mut last = Instant::now();
-
-                    'inner: loop {
-                        skt.flush().ok();
-                        // if last.elapsed() >= Duration::from_millis(1000) {
-                        //     last = Instant::now();
-                        //     println!("Port {} says ding", work.port);
-                        // }
-
-                        if let Ok(Some(e)) = skt.take_error() {
-                            println!("{tag} {mux} {err} {e}");
-                            break 'inner;
-                        }
-
-                        if let Ok(msg) = work.out.recv_timeout(Duration::from_millis(1)) {
-                            match skt.write_all(&msg) {
-                                Ok(_) => {}
-                                Err(e) => {
-                                    println!("{tag} {dmux} {err} write error: {e}");
-                                    break 'inner;
-                                }
-                            }
-                        }
-
-                        let mut buf = [0u8; 128];
-                        match skt.read(&mut buf) {
-                            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {}
-                            Ok(0) | Err(_) => {
-                                skt.shutdown(std::net::Shutdown::Both).ok();
-                                break 'inner;
-                            }
-                            Ok(n) => {
-                                tag.if_verbose(format_args!("{mux} {n}B <- :{}", work.port));
-                                work.inp.send(buf[..n].to_vec()).ok();
-                            }
-                        }
-                    }
-                }
-            });
-            let handle = WorkerHandle {
-                out: out_send,
-                inp: inp_recv,
-                _thread_hdl: thread_hdl,
-            };
+        for i in [
+            WellKnown::Loopback.into(),
+            WellKnown::Debug.into(),
+            // GDB can attach to this port using `target remote :$PORT`.
+            WellKnown::Gdb.into(),
+            // paste into the target's clipboard with something like
+            // `xclip -o | perl -0777 -ne 'print pack("CV", 1, length), $_' | ncat 127.0.0.1 $PORT`,
+            // and copy from it with
+            // `printf '\0' | ncat 127.0.0.1 $PORT | tail -c +5`.
+            WellKnown::Clipboard.into(),
+            // save screenshots of the target's display with something like
+            // `ncat 127.0.0.1 $PORT > screenshots.pbm`.
+            WellKnown::Screenshot.into(),
+        ]
+        .into_iter()
+        {
+            manager.spawn_host_worker(tag.port(i), i);
+        }
 
-            manager.workers.insert(i, handle);
+        for &i in &secure_ports {
+            if !manager.workers.contains_key(&i) {
+                manager.spawn_host_worker(tag.port(i), i);
+            }
         }
 
+        // spawn liveness ping monitor
+        let hello_port = WellKnown::HelloWorld.into();
+        manager.workers.insert(
+            hello_port,
+            health::HealthWorker::spawn(
+                tag.port(hello_port),
+                Duration::from_millis(ping_timeout_ms),
+            ),
+        );
+
         // spawn tracing listener
         let trace_port = WellKnown::BinaryTracing as u16;
         let trace_handle = {
             let (inp_send, inp_recv) = channel();
             let (out_send, out_recv) = channel::<Vec<u8>>();
             let thread_hdl = spawn(move || {
-                trace::TraceWorker::new(trace_filter, inp_send, out_recv, tag.port(trace_port))
-                    .run()
+                trace::TraceWorker::new(
+                    trace_filter,
+                    trace_format,
+                    inp_send,
+                    out_recv,
+                    tag.port(trace_port),
+                    trace_session,
+                )
+                .run()
             });
             WorkerHandle {
                 out: out_send,
@@ -234,6 +276,19 @@ impl Crowtty {
 
         manager.workers.insert(trace_port, trace_handle);
 
+        // dynamically allocated ports are announced on the port directory.
+        let directory_port: u16 = WellKnown::PortDirectory.into();
+        let control_port: u16 = WellKnown::Control.into();
+
+        // frames are sent as version 1 frames until the target advertises
+        // that it understands a newer version. ask it to.
+        let mut version = FrameVersion::V1;
+        send_advert(&mut port, false)?;
+
+        for &reset in &reset_ports {
+            send_reset(&mut port, reset)?;
+        }
+
         let mux = " MUX".if_supports_color(Stream::Stdout, |s| s.cyan());
         let dmux = "DMUX".if_supports_color(Stream::Stdout, |s| s.bright_purple());
         let err = "ERR!".if_supports_color(Stream::Stdout, |err| err.red());
@@ -243,11 +298,24 @@ impl Crowtty {
 
             for (port_idx, hdl) in manager.workers.iter_mut() {
                 if let Ok(msg) = hdl.inp.try_recv() {
-                    let mut nmsg = Vec::new();
-                    nmsg.extend_from_slice(&port_idx.to_le_bytes());
-                    nmsg.extend_from_slice(&msg);
-                    let mut enc_msg = cobs::encode_vec(&nmsg);
-                    enc_msg.push(0);
+                    let enc_msg = if secure.is_secure(*port_idx) {
+                        // each chunk is sealed separately, so secure ports
+                        // can't receive jumbo messages.
+                        match secure.seal(*port_idx, &msg, MAX_CHUNK) {
+                            Ok(chunks) => encode(
+                                chunks
+                                    .iter()
+                                    .map(|chunk| Frame::new(version, *port_idx, chunk)),
+                            ),
+                            Err(e) => {
+                                let tag = tag.port(*port_idx);
+                                println!("{tag} {mux} {err} discarded {}B: {e}", msg.len());
+                                continue;
+                            }
+                        }
+                    } else {
+                        encode(Frame::fragments(version, *port_idx, &msg, MAX_CHUNK))
+                    };
                     tag.port(*port_idx)
                         .if_verbose(format_args!("{mux} {}B <- :{port_idx}", enc_msg.len()));
                     port.write_all(&enc_msg)
@@ -281,13 +349,90 @@ impl Crowtty {
                 // Success means we printed something more useful than "bad decode",
                 // even if the actual decoding failed
                 let mut success = false;
-                match OwnedPortChunk::decode(&carry) {
-                    Ok(OwnedPortChunk { port, chunk }) => {
+                // frames are decoded in place, so keep the original in case
+                // this turns out to be plain text.
+                let mut frame = carry.clone();
+                match Frame::decode_from(&mut frame) {
+                    Ok(Frame {
+                        port: frame_port,
+                        chunk,
+                        ..
+                    }) if frame_port == directory_port => {
+                        success = true;
+                        let dir = " DIR".if_supports_color(Stream::Stdout, |s| s.bright_green());
+                        if let Ok(advert) = VersionAdvert::from_bytes(chunk) {
+                            version = advert.negotiate(FrameVersion::LATEST);
+                            println!("{tag} {dir} target speaks frame version {version:?}");
+                            if advert.reply {
+                                // the target is up, so secure sessions can be
+                                // started, if they haven't been already.
+                                let idle = secure.idle();
+                                start_handshakes(&mut port, &mut secure, idle)?;
+                            } else {
+                                send_advert(&mut port, true)?;
+                                // the target has just started, so it has
+                                // forgotten any secure sessions.
+                                let all = secure.ports();
+                                start_handshakes(&mut port, &mut secure, all)?;
+                            }
+                        } else if let Ok(hello) = SecureHello::from_bytes(chunk) {
+                            let tag = tag.port(hello.port);
+                            match secure.finish(&hello) {
+                                Ok(()) => {
+                                    println!("{tag} {dir} secure session on :{}", hello.port)
+                                }
+                                Err(e) => println!(
+                                    "{tag} {dir} {err} secure handshake on :{} failed: {e}",
+                                    hello.port
+                                ),
+                            }
+                        } else {
+                            manager.directory_entry(chunk, tag);
+                        }
+                    }
+                    Ok(Frame {
+                        port: frame_port,
+                        chunk,
+                        ..
+                    }) if frame_port == control_port => {
                         success = true;
-                        if let Some(hdl) = manager.workers.get_mut(&port) {
+                        let ctl = " CTL".if_supports_color(Stream::Stdout, |s| s.bright_green());
+                        match PortReset::from_bytes(chunk) {
+                            Ok(PortReset {
+                                port,
+                                reply: true,
+                                ok,
+                            }) => {
+                                let tag = tag.port(port);
+                                if ok {
+                                    println!("{tag} {ctl} port :{port} reset");
+                                } else {
+                                    println!("{tag} {ctl} {err} port :{port} can't be reset");
+                                }
+                            }
+                            _ => println!("{tag} {ctl} {err} bad control message: {chunk:#02x?}"),
+                        }
+                    }
+                    Ok(Frame { port, chunk, .. }) => {
+                        success = true;
+                        let data = if secure.is_secure(port) {
+                            secure
+                                .open(port, chunk)
+                                .map_err(|e| {
+                                    let tag = tag.port(port);
+                                    println!(
+                                        "{tag} {dmux} {err} discarded {}B on secure port :{port}: {e}",
+                                        chunk.len()
+                                    );
+                                })
+                                .ok()
+                        } else {
+                            Some(chunk.to_vec())
+                        };
+                        if let (Some(hdl), Some(data)) = (manager.workers.get_mut(&port), data) {
                             tag.port(port)
-                                .if_verbose(format_args!("{dmux} {}B -> :{port}", chunk.len()));
-                            hdl.out.send(chunk.to_vec()).ok();
+                                .if_verbose(format_args!("{dmux} {}B -> :{port}", data.len()));
+                            hdl.out.send(data).ok();
                         }
                     }
                     Err(DecodeError::CobsDecodeFailed) => {
@@ -321,6 +466,168 @@ impl Crowtty {
     }
 }
 
+/// Sends a [`VersionAdvert`] for the newest frame version we can decode to the
+/// target. Adverts are always sent as version 1 frames, so that any target
+/// can decode them.
+fn send_advert(port: &mut impl Write, reply: bool) -> miette::Result<()> {
+    let advert = VersionAdvert {
+        version: FrameVersion::LATEST,
+        reply,
+    }
+    .to_bytes();
+    let frame = Frame::new(FrameVersion::V1, WellKnown::PortDirectory, &advert);
+    let mut buf = vec![0; frame.buffer_required()];
+    let enc = frame
+        .encode_to(&mut buf)
+        .expect("sermux encoding should not fail");
+    port.write_all(enc)
+        .into_diagnostic()
+        .context("failed to send version advert")
+}
+
+/// Starts a secure session on each of `ports`, by sending a [`SecureHello`]
+/// on the port directory. Like adverts, hellos are always sent as version 1
+/// frames.
+fn start_handshakes(
+    port: &mut impl Write,
+    secure: &mut secure::SecurePorts,
+    ports: Vec<u16>,
+) -> miette::Result<()> {
+    for secure_port in ports {
+        let mut random = [0; 32];
+        getrandom::getrandom(&mut random)
+            .into_diagnostic()
+            .context("failed to generate a secure session key")?;
+        let hello = secure.start(secure_port, random).to_bytes();
+        let frame = Frame::new(FrameVersion::V1, WellKnown::PortDirectory, &hello);
+        let mut buf = vec![0; frame.buffer_required()];
+        let enc = frame
+            .encode_to(&mut buf)
+            .expect("sermux encoding should not fail");
+        port.write_all(enc)
+            .into_diagnostic()
+            .with_context(|| format!("failed to start a secure session on port {secure_port}"))?;
+    }
+    Ok(())
+}
+
+/// Encodes `frames`, one after another.
+fn encode<'a>(frames: impl IntoIterator<Item = Frame<'a>>) -> Vec<u8> {
+    let mut enc_msg = Vec::new();
+    for frame in frames {
+        let start = enc_msg.len();
+        enc_msg.resize(start + frame.buffer_required(), 0);
+        let used = frame
+            .encode_to(&mut enc_msg[start..])
+            .expect("sermux encoding should not fail")
+            .len();
+        enc_msg.truncate(start + used);
+    }
+    enc_msg
+}
+
+/// Asks the target to reset SerMux port `reset`, by sending a [`PortReset`]
+/// on the control port. Resets are sent before the target has advertised
+/// its frame version, so they're always sent as version 1 frames.
+fn send_reset(port: &mut impl Write, reset: u16) -> miette::Result<()> {
+    let msg = PortReset::request(reset).to_bytes();
+    let frame = Frame::new(FrameVersion::V1, WellKnown::Control, &msg);
+    let mut buf = vec![0; frame.buffer_required()];
+    let enc = frame
+        .encode_to(&mut buf)
+        .expect("sermux encoding should not fail");
+    port.write_all(enc)
+        .into_diagnostic()
+        .with_context(|| format!("failed to reset port {reset}"))
+}
+
+/// Spawns a worker thread which bridges SerMux port `port` to TCP port
+/// `port + tcp_port_base` on localhost.
+fn spawn_tcp_worker(tag: LogTag, port: u16, tcp_port_base: u16) -> WorkerHandle {
+    let (inp_send, inp_recv) = channel();
+    let (out_send, out_recv) = channel();
+
+    let socket =
+        std::net::TcpListener::bind(format!("127.0.0.1:{}", tcp_port_base + port)).unwrap();
+
+    let work = TcpWorker {
+        out: out_recv,
+        inp: inp_send,
+        socket,
+        port,
+    };
+    let thread_hdl = spawn(move || {
+        let mux = " MUX".if_supports_color(Stream::Stdout, |s| s.cyan());
+        let dmux = "DMUX".if_supports_color(Stream::Stdout, |s| s.bright_purple());
+        let err = "ERR!".if_supports_color(Stream::Stdout, |err| err.red());
+        for skt in work.socket.incoming() {
+            let mut skt = match skt {
+                Ok(skt) => skt,
+                Err(e) => {
+                    panic!(
+                        "{tag} CONN failed to accept host connection to port {} (:{}): {e}",
+                        tcp_port_base + work.port,
+                        work.port
+                    );
+                }
+            };
+
+            println!(
+                "{tag} CONN host connected to port {} (:{})",
+                tcp_port_base + work.port,
+                work.port
+            );
+
+            skt.set_read_timeout(Some(Duration::from_millis(10))).ok();
+            // skt.set_nonblocking(true).ok();
+            // skt.set_nodelay(true).ok();
+
+            // let mut last = Instant::now();
+
+            'inner: loop {
+                skt.flush().ok();
+                // if last.elapsed() >= Duration::from_millis(1000) {
+                //     last = Instant::now();
+                //     println!("Port {} says ding", work.port);
+                // }
+
+                if let Ok(Some(e)) = skt.take_error() {
+                    println!("{tag} {mux} {err} {e}");
+                    break 'inner;
+                }
+
+                if let Ok(msg) = work.out.recv_timeout(Duration::from_millis(1)) {
+                    match skt.write_all(&msg) {
+                        Ok(_) => {}
+                        Err(e) => {
+                            println!("{tag} {dmux} {err} write error: {e}");
+                            break 'inner;
+                        }
+                    }
+                }
+
+                let mut buf = [0u8; 128];
+                match skt.read(&mut buf) {
+                    Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {}
+                    Ok(0) | Err(_) => {
+                        skt.shutdown(std::net::Shutdown::Both).ok();
+                        break 'inner;
+                    }
+                    Ok(n) => {
+                        tag.if_verbose(format_args!("{mux} {n}B <- :{}", work.port));
+                        work.inp.send(buf[..n].to_vec()).ok();
+                    }
+                }
+            }
+        }
+    });
+    WorkerHandle {
+        out: out_send,
+        inp: inp_recv,
+        _thread_hdl: thread_hdl,
+    }
+}
+
 impl LogTag {
     pub fn serial() -> Self {
         Self::new("UART")
@@ -385,6 +692,56 @@ impl fmt::Display for LogTag {
 
 struct TcpManager {
     workers: HashMap<u16, WorkerHandle>,
+    tcp_port_base: u16,
+    /// SerMux ports bridged to host PTYs or named pipes, rather than TCP.
+    pty_ports: Vec<u16>,
+}
+
+impl TcpManager {
+    /// Spawns a worker bridging SerMux port `port` to the host, as a PTY (or
+    /// named pipe) if it was configured as one, or a TCP port otherwise.
+    fn spawn_host_worker(&mut self, tag: LogTag, port: u16) {
+        let handle = if self.pty_ports.contains(&port) {
+            pty::spawn_pty_worker(tag, port)
+        } else {
+            spawn_tcp_worker(tag, port, self.tcp_port_base)
+        };
+        self.workers.insert(port, handle);
+    }
+
+    /// Handles a [`DirectoryEntry`] announcing that a dynamic port was opened
+    /// or closed, spawning a worker for newly opened ports.
+    fn directory_entry(&mut self, chunk: &[u8], tag: LogTag) {
+        let dir = " DIR".if_supports_color(Stream::Stdout, |s| s.bright_green());
+        let DirectoryEntry { port, open } = match DirectoryEntry::from_bytes(chunk) {
+            Ok(entry) => entry,
+            Err(e) => {
+                let err = "ERR!".if_supports_color(Stream::Stdout, |err| err.red());
+                println!("{tag} {dir} {err} bad directory entry: {e}");
+                return;
+            }
+        };
+        let tag = tag.port(port);
+
+        if !open {
+            // Keep the host listener around, since the target may reuse this
+            // port number later.
+            println!("{tag} {dir} dynamic port :{port} closed");
+            return;
+        }
+
+        if self.pty_ports.contains(&port) {
+            println!("{tag} {dir} dynamic port :{port} opened");
+        } else {
+            println!(
+                "{tag} {dir} dynamic port :{port} opened on localhost:{}",
+                self.tcp_port_base + port
+            );
+        }
+        if !self.workers.contains_key(&port) {
+            self.spawn_host_worker(tag, port);
+        }
+    }
 }
 
 pub(crate) struct WorkerHandle {
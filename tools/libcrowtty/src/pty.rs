@@ -0,0 +1,223 @@
+//! Bridges SerMux ports to host serial devices, so that tools which expect a
+//! serial port (such as `screen`, `minicom`, or an editor's serial console)
+//! can attach to a virtual port directly, rather than through a TCP socket.
+//!
+//! On Unix, each port is exposed as a pseudo-terminal, whose path (such as
+//! `/dev/pts/4`) is printed when it's opened. On Windows, each port is exposed
+//! as a named pipe, `\\.\pipe\crowtty-<PORT>`.
+use owo_colors::{OwoColorize, Stream};
+use std::{
+    io::Write,
+    sync::mpsc::{channel, Receiver, Sender},
+    thread::spawn,
+};
+
+use crate::{LogTag, WorkerHandle};
+
+/// Spawns a worker which bridges SerMux port `port` to a host pseudo-terminal
+/// or named pipe.
+pub(crate) fn spawn_pty_worker(tag: LogTag, port: u16) -> WorkerHandle {
+    let (inp_send, inp_recv) = channel();
+    let (out_send, out_recv) = channel();
+    let thread_hdl = spawn(move || {
+        if let Err(e) = imp::run(tag, port, out_recv, inp_send) {
+            let err = "ERR!".if_supports_color(Stream::Stdout, |err| err.red());
+            println!("{tag}  PTY {err} failed to bridge :{port}: {e}");
+        }
+    });
+    WorkerHandle {
+        out: out_send,
+        inp: inp_recv,
+        _thread_hdl: thread_hdl,
+    }
+}
+
+/// Copies data from the target to the host side of the bridge, until
+/// writing fails.
+fn forward_writes(tag: LogTag, mut host: impl Write, out: Receiver<Vec<u8>>) {
+    while let Ok(msg) = out.recv() {
+        if let Err(e) = host.write_all(&msg).and_then(|_| host.flush()) {
+            let dmux = "DMUX".if_supports_color(Stream::Stdout, |s| s.bright_purple());
+            let err = "ERR!".if_supports_color(Stream::Stdout, |err| err.red());
+            println!("{tag} {dmux} {err} write error: {e}");
+            return;
+        }
+    }
+}
+
+#[cfg(unix)]
+mod imp {
+    use super::*;
+    use nix::{
+        pty::openpty,
+        sys::termios::{cfmakeraw, tcgetattr, tcsetattr, SetArg},
+        unistd::ttyname,
+    };
+    use std::{
+        fs::File,
+        io::{self, Read},
+    };
+
+    pub(super) fn run(
+        tag: LogTag,
+        port: u16,
+        out: Receiver<Vec<u8>>,
+        inp: Sender<Vec<u8>>,
+    ) -> io::Result<()> {
+        let pty = openpty(None, None)?;
+        let path = ttyname(&pty.slave)?;
+
+        // the target's data should be passed through untouched, rather than
+        // having the line discipline echo it back, or translate newlines.
+        let mut termios = tcgetattr(&pty.slave)?;
+        cfmakeraw(&mut termios);
+        tcsetattr(&pty.slave, SetArg::TCSANOW, &termios)?;
+
+        println!("{tag}  PTY :{port} on {}", path.display());
+
+        // keep our own handle to the subordinate side open, so that reading
+        // from the controller doesn't fail when no one has the PTY open.
+        let _slave = pty.slave;
+        let master = File::from(pty.master);
+        let reader = master.try_clone()?;
+        spawn(move || forward_reads(tag, port, reader, inp));
+        forward_writes(tag, master, out);
+        Ok(())
+    }
+
+    /// Copies data read from the host side of the bridge to the target, until
+    /// reading fails.
+    fn forward_reads(tag: LogTag, port: u16, mut host: impl Read, inp: Sender<Vec<u8>>) {
+        let mux = " MUX".if_supports_color(Stream::Stdout, |s| s.cyan());
+        let mut buf = [0u8; 128];
+        loop {
+            match host.read(&mut buf) {
+                Ok(0) => return,
+                Ok(n) => {
+                    tag.if_verbose(format_args!("{mux} {n}B <- :{port}"));
+                    if inp.send(buf[..n].to_vec()).is_err() {
+                        return;
+                    }
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(e) => {
+                    let err = "ERR!".if_supports_color(Stream::Stdout, |err| err.red());
+                    println!("{tag} {mux} {err} read error: {e}");
+                    return;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(windows)]
+mod imp {
+    use super::*;
+    use std::{
+        ffi::OsStr,
+        fs::File,
+        io::{self, Read},
+        os::windows::{ffi::OsStrExt, io::FromRawHandle},
+        ptr,
+        sync::{Arc, Mutex},
+        thread::sleep,
+        time::Duration,
+    };
+    use windows_sys::Win32::{
+        Foundation::{ERROR_NO_DATA, ERROR_PIPE_CONNECTED, INVALID_HANDLE_VALUE},
+        Storage::FileSystem::PIPE_ACCESS_DUPLEX,
+        System::Pipes::{
+            ConnectNamedPipe, CreateNamedPipeW, DisconnectNamedPipe, PIPE_NOWAIT,
+            PIPE_READMODE_BYTE, PIPE_TYPE_BYTE,
+        },
+    };
+
+    const BUF_SIZE: u32 = 4096;
+
+    pub(super) fn run(
+        tag: LogTag,
+        port: u16,
+        out: Receiver<Vec<u8>>,
+        inp: Sender<Vec<u8>>,
+    ) -> io::Result<()> {
+        let name = format!(r"\\.\pipe\crowtty-{port}");
+        let wide: Vec<u16> = OsStr::new(&name).encode_wide().chain(Some(0)).collect();
+        // a non-blocking pipe lets one thread both read and write it, as
+        // blocking reads on a synchronous handle would also block writes.
+        let handle = unsafe {
+            CreateNamedPipeW(
+                wide.as_ptr(),
+                PIPE_ACCESS_DUPLEX,
+                PIPE_TYPE_BYTE | PIPE_READMODE_BYTE | PIPE_NOWAIT,
+                1,
+                BUF_SIZE,
+                BUF_SIZE,
+                0,
+                ptr::null(),
+            )
+        };
+        if handle == INVALID_HANDLE_VALUE {
+            return Err(io::Error::last_os_error());
+        }
+        // the `File` owns the handle, and closes it when dropped.
+        let pipe = Arc::new(Mutex::new(unsafe { File::from_raw_handle(handle as _) }));
+
+        println!("{tag}  PTY :{port} on {name}");
+
+        let writer = pipe.clone();
+        spawn(move || forward_writes(tag, PipeWriter(writer), out));
+
+        let mut buf = [0u8; 128];
+        let mut connected = false;
+        loop {
+            if !connected {
+                // with `PIPE_NOWAIT`, this returns immediately, failing with
+                // `ERROR_PIPE_CONNECTED` once a client has connected.
+                unsafe { ConnectNamedPipe(handle, ptr::null_mut()) };
+                if io::Error::last_os_error().raw_os_error() == Some(ERROR_PIPE_CONNECTED as i32) {
+                    println!("{tag} CONN host connected to {name} (:{port})");
+                    connected = true;
+                } else {
+                    sleep(Duration::from_millis(10));
+                    continue;
+                }
+            }
+
+            let read = pipe.lock().unwrap().read(&mut buf);
+            match read {
+                Ok(0) => sleep(Duration::from_millis(1)),
+                Ok(n) => {
+                    let mux = " MUX".if_supports_color(Stream::Stdout, |s| s.cyan());
+                    tag.if_verbose(format_args!("{mux} {n}B <- :{port}"));
+                    if inp.send(buf[..n].to_vec()).is_err() {
+                        return Ok(());
+                    }
+                }
+                Err(e) if e.raw_os_error() == Some(ERROR_NO_DATA as i32) => {
+                    sleep(Duration::from_millis(1))
+                }
+                Err(_) => {
+                    // the client disconnected; wait for the next one.
+                    unsafe { DisconnectNamedPipe(handle) };
+                    connected = false;
+                }
+            }
+        }
+    }
+
+    /// Writes to the pipe, discarding data while no client is connected.
+    struct PipeWriter(Arc<Mutex<File>>);
+
+    impl Write for PipeWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            match self.0.lock().unwrap().write(buf) {
+                Ok(n) => Ok(n),
+                Err(_) => Ok(buf.len()),
+            }
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+}
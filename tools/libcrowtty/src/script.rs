@@ -0,0 +1,371 @@
+//! Scripted, expect-style automation of a target.
+//!
+//! [`Script`] speaks SerMux with a target directly, rather than bridging its
+//! ports to the host, so that test harnesses can drive the target from code
+//! instead of scraping crowtty's output. Output received on the ports a
+//! script [subscribes](Script::subscribe) to is buffered until it is matched
+//! by [`Script::expect`], and data is sent to any port with [`Script::send`].
+//! Trace events matching a filter are collected as [`TraceRecord`]s once
+//! [`Script::trace`] is called.
+//!
+//! As with [`Exec`](crate::Exec), ANSI escape sequences are removed from the
+//! output of subscribed ports before it is matched, and the connection
+//! should have a read timeout set, so that reads don't block forever.
+//!
+//! ```no_run
+//! # fn main() -> miette::Result<()> {
+//! use libcrowtty::{script::Script, LogTag};
+//! use regex::Regex;
+//! use sermux_proto::WellKnown;
+//! use std::{net::TcpStream, time::Duration};
+//!
+//! let conn = TcpStream::connect("127.0.0.1:9999").unwrap();
+//! conn.set_read_timeout(Some(Duration::from_millis(10))).unwrap();
+//!
+//! let shell = WellKnown::ForthShell0.into();
+//! let mut script = Script::new(LogTag::tcp(), conn)?;
+//! script.subscribe(shell);
+//! script.send(shell, b"1 2 + .\n")?;
+//! script.expect(shell, &Regex::new(r"3 ok\.").unwrap(), Duration::from_secs(5))?;
+//! # Ok(())
+//! # }
+//! ```
+use crate::{
+    exec::strip_ansi,
+    trace::{TraceRecord, TraceWorker},
+    LogTag, TraceFormat, TraceSession, MAX_CHUNK,
+};
+use miette::{Context, IntoDiagnostic};
+use owo_colors::{OwoColorize, Stream};
+use regex::Regex;
+use sermux_proto::{DecodeError, Frame, FrameVersion, VersionAdvert, WellKnown};
+use std::{
+    collections::HashMap,
+    io::{ErrorKind, Read, Write},
+    sync::mpsc::{self, Receiver, Sender},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+use tracing_subscriber::filter::Targets;
+
+/// A connection to a target, driven by code rather than by a person.
+///
+/// See the [module-level docs](self) for details.
+pub struct Script<P> {
+    conn: P,
+    tag: LogTag,
+    /// Bytes read from the connection which are not yet a whole frame.
+    carry: Vec<u8>,
+    version: FrameVersion,
+    /// Output received on each subscribed port, which has not been matched
+    /// yet.
+    ports: HashMap<u16, Output>,
+    traces: Option<Traces>,
+    /// Trace metadata, kept when the trace filter is replaced.
+    session: TraceSession,
+    /// Trace events received, which have not been matched or collected yet.
+    records: Vec<TraceRecord>,
+}
+
+/// Text matched by [`Script::expect`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Match {
+    /// The port's output which was received before the match.
+    pub before: String,
+    /// The text which matched the pattern.
+    pub matched: String,
+    /// The text matched by each of the pattern's capture groups, in order.
+    /// The whole match is not included.
+    pub captures: Vec<Option<String>>,
+}
+
+/// A port's buffered output.
+#[derive(Default)]
+struct Output {
+    text: String,
+    /// The start of an escape sequence, which was split across frames.
+    partial: String,
+}
+
+/// The trace worker decoding the target's trace events.
+struct Traces {
+    out: Sender<Vec<u8>>,
+    inp: Receiver<Vec<u8>>,
+    records: Receiver<TraceRecord>,
+    _thread_hdl: JoinHandle<()>,
+}
+
+impl<P: Read + Write> Script<P> {
+    /// Starts a script on `conn`, a connection to a target.
+    pub fn new(tag: LogTag, mut conn: P) -> miette::Result<Self> {
+        send_advert(&mut conn, false)?;
+        Ok(Self {
+            conn,
+            tag,
+            carry: Vec::new(),
+            version: FrameVersion::V1,
+            ports: HashMap::new(),
+            traces: None,
+            session: TraceSession::default(),
+            records: Vec::new(),
+        })
+    }
+
+    /// Starts buffering the output of SerMux port `port`, so that it can be
+    /// matched by [`Script::expect`].
+    ///
+    /// Output received before the port is subscribed to is discarded.
+    pub fn subscribe(&mut self, port: u16) {
+        self.ports.entry(port).or_default();
+    }
+
+    /// Stops buffering the output of `port`, discarding anything unmatched.
+    pub fn unsubscribe(&mut self, port: u16) {
+        self.ports.remove(&port);
+    }
+
+    /// Sends `data` to SerMux port `port`.
+    pub fn send(&mut self, port: u16, data: &[u8]) -> miette::Result<()> {
+        self.tag.port(port).if_verbose(format_args!(
+            "{} {}B <- :{port}",
+            "SCRP".if_supports_color(Stream::Stdout, |s| s.cyan()),
+            data.len(),
+        ));
+        write_frames(&mut self.conn, self.version, port, data)
+            .with_context(|| format!("failed to send {}B to port {port}", data.len()))
+    }
+
+    /// Waits up to `timeout` for the output of `port` to match `pattern`.
+    ///
+    /// The output up to the end of the match is consumed, so the next call
+    /// only matches output received after it. `port` must have been
+    /// [subscribed](Script::subscribe) to.
+    pub fn expect(
+        &mut self,
+        port: u16,
+        pattern: &Regex,
+        timeout: Duration,
+    ) -> miette::Result<Match> {
+        if !self.ports.contains_key(&port) {
+            miette::bail!("port {port} must be subscribed to before its output can be matched");
+        }
+
+        let deadline = Instant::now() + timeout;
+        loop {
+            let output = &mut self.ports.get_mut(&port).expect("port is subscribed").text;
+            if let Some(caps) = pattern.captures(output) {
+                let whole = caps.get(0).expect("a match always has a whole match");
+                let end = whole.end();
+                let found = Match {
+                    before: output[..whole.start()].to_string(),
+                    matched: whole.as_str().to_string(),
+                    captures: caps
+                        .iter()
+                        .skip(1)
+                        .map(|cap| cap.map(|cap| cap.as_str().to_string()))
+                        .collect(),
+                };
+                output.drain(..end);
+                return Ok(found);
+            }
+            if Instant::now() >= deadline {
+                return Err(miette::miette!(
+                    help = format!("port {port}'s unmatched output was: {output:?}"),
+                    "timed out after {timeout:?} waiting for port {port} to print /{pattern}/",
+                ));
+            }
+            self.poll()?;
+        }
+    }
+
+    /// Starts collecting the trace events enabled by `filter`.
+    ///
+    /// Unlike when crowtty is run interactively, collected events are not
+    /// printed. Calling this again replaces the filter, but keeps any events
+    /// which were already collected.
+    pub fn trace(&mut self, filter: impl Into<Targets>) {
+        let (inp_send, inp_recv) = mpsc::channel();
+        let (out_send, out_recv) = mpsc::channel();
+        let (records_send, records_recv) = mpsc::channel();
+        let worker = TraceWorker::new(
+            filter.into(),
+            TraceFormat::Flat,
+            inp_send,
+            out_recv,
+            self.tag.port(WellKnown::BinaryTracing as u16),
+            self.session.clone(),
+        )
+        .records(records_send);
+        self.traces = Some(Traces {
+            out: out_send,
+            inp: inp_recv,
+            records: records_recv,
+            _thread_hdl: thread::spawn(move || worker.run()),
+        });
+    }
+
+    /// Waits up to `timeout` for a trace event for which `matches` returns
+    /// `true`.
+    ///
+    /// Events received before the matching one are kept, and are returned by
+    /// [`Script::traces`]. [`Script::trace`] must have been called first.
+    pub fn expect_trace(
+        &mut self,
+        mut matches: impl FnMut(&TraceRecord) -> bool,
+        timeout: Duration,
+    ) -> miette::Result<TraceRecord> {
+        if self.traces.is_none() {
+            miette::bail!("tracing must be enabled before trace events can be matched");
+        }
+
+        let deadline = Instant::now() + timeout;
+        let mut checked = 0;
+        loop {
+            if let Some(idx) = self.records[checked..].iter().position(&mut matches) {
+                return Ok(self.records.remove(checked + idx));
+            }
+            checked = self.records.len();
+            if Instant::now() >= deadline {
+                miette::bail!("timed out after {timeout:?} waiting for a matching trace event");
+            }
+            self.poll()?;
+        }
+    }
+
+    /// Returns the trace events collected since the last call, after waiting
+    /// up to `wait` for more to arrive.
+    pub fn traces(&mut self, wait: Duration) -> miette::Result<Vec<TraceRecord>> {
+        let deadline = Instant::now() + wait;
+        loop {
+            self.poll()?;
+            if Instant::now() >= deadline {
+                return Ok(std::mem::take(&mut self.records));
+            }
+        }
+    }
+
+    /// Reads from the connection once, buffering the output of subscribed
+    /// ports and handing trace data to the trace worker.
+    fn poll(&mut self) -> miette::Result<()> {
+        if let Some(ref traces) = self.traces {
+            // requests from the trace worker, such as setting the max level.
+            while let Ok(msg) = traces.inp.try_recv() {
+                write_frames(
+                    &mut self.conn,
+                    self.version,
+                    WellKnown::BinaryTracing.into(),
+                    &msg,
+                )?;
+            }
+            self.records.extend(traces.records.try_iter());
+        }
+
+        let mut buf = [0u8; 256];
+        let used = match self.conn.read(&mut buf) {
+            Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(()),
+            Err(e) if e.kind() == ErrorKind::TimedOut => return Ok(()),
+            Ok(0) => return Ok(()),
+            Ok(used) => used,
+            Err(e) => return Err(e).into_diagnostic().context("inbound read failed"),
+        };
+        self.carry.extend_from_slice(&buf[..used]);
+
+        let directory_port = WellKnown::PortDirectory.into();
+        let trace_port = WellKnown::BinaryTracing.into();
+        while let Some(pos) = self.carry.iter().position(|b| *b == 0) {
+            let remainder = self.carry.split_off(pos + 1);
+            match Frame::decode_from(&mut self.carry) {
+                Ok(Frame { port, chunk, .. }) if port == directory_port => {
+                    if let Ok(advert) = VersionAdvert::from_bytes(chunk) {
+                        self.version = advert.negotiate(FrameVersion::LATEST);
+                        if !advert.reply {
+                            send_advert(&mut self.conn, true)?;
+                        }
+                    }
+                }
+                Ok(Frame { port, chunk, .. }) if port == trace_port => {
+                    if let Some(ref traces) = self.traces {
+                        traces.out.send(chunk.to_vec()).ok();
+                    }
+                }
+                Ok(Frame { port, chunk, .. }) => {
+                    if let Some(output) = self.ports.get_mut(&port) {
+                        self.tag.port(port).if_verbose(format_args!(
+                            "{} {}B -> :{port}",
+                            "SCRP".if_supports_color(Stream::Stdout, |s| s.bright_purple()),
+                            chunk.len()
+                        ));
+                        output.push(&String::from_utf8_lossy(chunk));
+                    }
+                }
+                // plain text and flushes aren't part of any port's output.
+                Err(DecodeError::CobsDecodeFailed | DecodeError::MalformedFrame) => {}
+            }
+            self.carry = remainder;
+        }
+        Ok(())
+    }
+}
+
+impl Output {
+    fn push(&mut self, chunk: &str) {
+        self.partial.push_str(chunk);
+        // hold back an escape sequence which hasn't been completely received
+        // yet, so that it can be removed once the rest of it arrives.
+        let split = self
+            .partial
+            .rfind('\x1b')
+            .filter(|&esc| !is_complete_escape(&self.partial[esc..]))
+            .unwrap_or(self.partial.len());
+        let rest = self.partial.split_off(split);
+        self.text.push_str(&strip_ansi(&self.partial));
+        self.partial = rest;
+    }
+}
+
+/// Returns `true` if `s`, which starts with `ESC`, contains a whole escape
+/// sequence.
+fn is_complete_escape(s: &str) -> bool {
+    let mut chars = s.chars().skip(1);
+    match chars.next() {
+        None => false,
+        Some('[') => chars.any(|c| ('@'..='~').contains(&c)),
+        Some(_) => true,
+    }
+}
+
+/// Writes `data` to `conn` as frames for `port`.
+fn write_frames(
+    conn: &mut impl Write,
+    version: FrameVersion,
+    port: u16,
+    data: &[u8],
+) -> miette::Result<()> {
+    let mut enc = Vec::new();
+    for frame in Frame::fragments(version, port, data, MAX_CHUNK) {
+        let start = enc.len();
+        enc.resize(start + frame.buffer_required(), 0);
+        let used = frame
+            .encode_to(&mut enc[start..])
+            .expect("sermux encoding should not fail")
+            .len();
+        enc.truncate(start + used);
+    }
+    conn.write_all(&enc).into_diagnostic()
+}
+
+/// Sends a [`VersionAdvert`] to the target, as version 1 frames.
+fn send_advert(conn: &mut impl Write, reply: bool) -> miette::Result<()> {
+    let advert = VersionAdvert {
+        version: FrameVersion::LATEST,
+        reply,
+    }
+    .to_bytes();
+    write_frames(
+        conn,
+        FrameVersion::V1,
+        WellKnown::PortDirectory.into(),
+        &advert,
+    )
+    .context("failed to send version advert")
+}
@@ -0,0 +1,255 @@
+//! The host's side of secure SerMux ports.
+//!
+//! The host always starts the handshake for a secure port, by sending a
+//! [`SecureHello`] on the port directory, and the target replies with its
+//! own. Until the reply arrives, nothing can be sent to the port. See
+//! [`sermux_proto::secure`] for the protocol.
+use sermux_proto::{
+    secure::{Handshake, Opener, Role, Sealer, SecureError, OVERHEAD},
+    SecureHello,
+};
+use std::{collections::HashMap, fmt};
+
+/// A pre-shared key, which isn't printed in debug output.
+#[derive(Clone, Copy, Default)]
+pub(crate) struct Psk(pub(crate) [u8; 32]);
+
+/// The sessions for each secure port.
+pub(crate) struct SecurePorts {
+    psk: [u8; 32],
+    sessions: HashMap<u16, Session>,
+}
+
+enum Session {
+    /// No handshake has been started.
+    Idle,
+    /// A hello has been sent, and we're waiting for the target's reply.
+    Handshaking(Handshake),
+    Established {
+        sealer: Sealer,
+        opener: Opener,
+    },
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum SessionError {
+    /// The port's handshake hasn't finished.
+    NoSession,
+    Secure(SecureError),
+}
+
+impl SecurePorts {
+    pub(crate) fn new(psk: [u8; 32], ports: &[u16]) -> Self {
+        let sessions = ports.iter().map(|&port| (port, Session::Idle)).collect();
+        Self { psk, sessions }
+    }
+
+    /// Returns every secure port.
+    pub(crate) fn ports(&self) -> Vec<u16> {
+        self.sessions.keys().copied().collect()
+    }
+
+    /// Returns the secure ports which haven't started a handshake.
+    pub(crate) fn idle(&self) -> Vec<u16> {
+        self.sessions
+            .iter()
+            .filter(|(_, session)| matches!(session, Session::Idle))
+            .map(|(&port, _)| port)
+            .collect()
+    }
+
+    pub(crate) fn is_secure(&self, port: u16) -> bool {
+        self.sessions.contains_key(&port)
+    }
+
+    /// Starts a new handshake on `port`, discarding any existing session.
+    /// Returns the hello to send to the target.
+    ///
+    /// `random` must come from a cryptographically secure random number
+    /// generator.
+    pub(crate) fn start(&mut self, port: u16, random: [u8; 32]) -> SecureHello {
+        let handshake = Handshake::new(Role::Initiator, port, random);
+        let hello = handshake.hello();
+        self.sessions.insert(port, Session::Handshaking(handshake));
+        hello
+    }
+
+    /// Finishes the handshake for the port `hello` is for, using the target's
+    /// reply.
+    pub(crate) fn finish(&mut self, hello: &SecureHello) -> Result<(), SessionError> {
+        let Some(session) = self.sessions.get_mut(&hello.port) else {
+            return Err(SessionError::Secure(SecureError::UnexpectedHello));
+        };
+        // a handshake can only be finished once, whether or not it succeeds.
+        let handshake = match std::mem::replace(session, Session::Idle) {
+            Session::Handshaking(handshake) => handshake,
+            other => {
+                *session = other;
+                return Err(SessionError::Secure(SecureError::UnexpectedHello));
+            }
+        };
+        let (sealer, opener) = handshake
+            .finish(&self.psk, hello)
+            .map_err(SessionError::Secure)?;
+        *session = Session::Established { sealer, opener };
+        Ok(())
+    }
+
+    /// Opens a sealed chunk received from the target on `port`.
+    pub(crate) fn open(&mut self, port: u16, chunk: &[u8]) -> Result<Vec<u8>, SessionError> {
+        let Some(Session::Established { opener, .. }) = self.sessions.get_mut(&port) else {
+            return Err(SessionError::NoSession);
+        };
+        let mut chunk = chunk.to_vec();
+        let data = opener.open(&mut chunk).map_err(SessionError::Secure)?;
+        Ok(data.to_vec())
+    }
+
+    /// Seals `data` to send to the target on `port`, as chunks which are at
+    /// most `max_chunk` bytes long once sealed.
+    pub(crate) fn seal(
+        &mut self,
+        port: u16,
+        data: &[u8],
+        max_chunk: usize,
+    ) -> Result<Vec<Vec<u8>>, SessionError> {
+        let Some(Session::Established { sealer, .. }) = self.sessions.get_mut(&port) else {
+            return Err(SessionError::NoSession);
+        };
+        data.chunks(max_chunk - OVERHEAD)
+            .map(|chunk| {
+                let mut sealed = vec![0; chunk.len() + OVERHEAD];
+                sealer
+                    .seal(chunk, &mut sealed)
+                    .map_err(SessionError::Secure)?;
+                Ok(sealed)
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for SessionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SessionError::NoSession => f.write_str("no secure session"),
+            SessionError::Secure(error) => write!(f, "{error}"),
+        }
+    }
+}
+
+impl fmt::Debug for Psk {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Psk(..)")
+    }
+}
+
+/// Parses a pre-shared key from 64 hex digits.
+pub(crate) fn parse_psk(s: &str) -> Result<Psk, String> {
+    let s = s.trim();
+    if s.len() != 64 || !s.is_ascii() {
+        return Err("a pre-shared key must be 64 hex digits".into());
+    }
+    let mut psk = [0; 32];
+    for (byte, digits) in psk.iter_mut().zip(s.as_bytes().chunks(2)) {
+        // checked that the string is ASCII above.
+        let digits = std::str::from_utf8(digits).unwrap();
+        *byte = u8::from_str_radix(digits, 16)
+            .map_err(|_| format!("invalid hex digits {digits:?} in pre-shared key"))?;
+    }
+    Ok(Psk(psk))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PSK: [u8; 32] = [7; 32];
+
+    /// The target's side of a session on `port`.
+    fn target(host: &SecureHello, psk: &[u8; 32]) -> (SecureHello, (Sealer, Opener)) {
+        let handshake = Handshake::new(Role::Responder, host.port, [2; 32]);
+        let reply = handshake.hello();
+        (reply, handshake.finish(psk, host).unwrap())
+    }
+
+    #[test]
+    fn session() {
+        let mut ports = SecurePorts::new(PSK, &[10]);
+        assert!(ports.is_secure(10));
+        assert!(!ports.is_secure(11));
+        assert_eq!(ports.idle(), [10]);
+        assert_eq!(ports.seal(10, b"early", 64), Err(SessionError::NoSession));
+
+        let hello = ports.start(10, [1; 32]);
+        assert_eq!(hello.port, 10);
+        assert!(!hello.reply);
+        assert!(ports.idle().is_empty());
+        let (reply, (mut target_tx, mut target_rx)) = target(&hello, &PSK);
+        ports.finish(&reply).unwrap();
+
+        // long messages are split into several chunks.
+        let data = [b'!'; 100];
+        let mut sealed = ports.seal(10, &data, 64).unwrap();
+        assert_eq!(sealed.len(), 3);
+        assert!(sealed.iter().all(|chunk| chunk.len() <= 64));
+        let opened = sealed
+            .iter_mut()
+            .flat_map(|chunk| target_rx.open(chunk).unwrap().to_vec())
+            .collect::<Vec<_>>();
+        assert_eq!(opened, data);
+
+        let mut buf = [0u8; 64];
+        let chunk = target_tx.seal(b"3 ok.", &mut buf).unwrap();
+        assert_eq!(ports.open(10, chunk).unwrap(), b"3 ok.");
+        // replayed chunks are rejected.
+        let chunk = target_tx.seal(b"once", &mut buf).unwrap().to_vec();
+        assert_eq!(ports.open(10, &chunk).unwrap(), b"once");
+        assert_eq!(
+            ports.open(10, &chunk),
+            Err(SessionError::Secure(SecureError::Replayed))
+        );
+    }
+
+    #[test]
+    fn wrong_psk() {
+        let mut ports = SecurePorts::new(PSK, &[10]);
+        let hello = ports.start(10, [1; 32]);
+        let (reply, (mut target_tx, _)) = target(&hello, &[8; 32]);
+        ports.finish(&reply).unwrap();
+
+        let mut buf = [0u8; 64];
+        let chunk = target_tx.seal(b"hi", &mut buf).unwrap();
+        assert_eq!(
+            ports.open(10, chunk),
+            Err(SessionError::Secure(SecureError::Unauthenticated))
+        );
+    }
+
+    #[test]
+    fn unexpected_hello() {
+        let mut ports = SecurePorts::new(PSK, &[10]);
+        let hello = ports.start(10, [1; 32]);
+        let (reply, _) = target(&hello, &PSK);
+        // not a secure port.
+        let other = SecureHello { port: 11, ..reply };
+        assert_eq!(
+            ports.finish(&other),
+            Err(SessionError::Secure(SecureError::UnexpectedHello))
+        );
+        ports.finish(&reply).unwrap();
+        // the handshake has already finished.
+        assert_eq!(
+            ports.finish(&reply),
+            Err(SessionError::Secure(SecureError::UnexpectedHello))
+        );
+    }
+
+    #[test]
+    fn psk() {
+        let hex = "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+        let Psk(psk) = parse_psk(hex).unwrap();
+        assert!(psk.iter().copied().eq(0..32));
+        assert!(parse_psk("00").is_err());
+        assert!(parse_psk(&hex.replace('0', "g")).is_err());
+    }
+}
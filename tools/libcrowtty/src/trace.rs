@@ -1,11 +1,11 @@
-use mnemos_trace_proto::{HostRequest, MetaId, TraceEvent};
+use mnemos_trace_proto::{HostRequest, MetaId, Timestamp, TraceEvent};
 use postcard::accumulator::{CobsAccumulator, FeedResult};
 use std::{
     collections::{BTreeMap, HashMap},
     fmt::{self, Write},
     num::NonZeroU64,
-    sync::mpsc,
-    time::Instant,
+    sync::{mpsc, Arc, Mutex},
+    time::{Duration, Instant},
 };
 use tracing::{level_filters::LevelFilter, subscriber::NoSubscriber, Level};
 use tracing_serde_structured::{
@@ -22,6 +22,8 @@ pub(crate) struct TraceWorker {
     rx: mpsc::Receiver<Vec<u8>>,
     textbuf: String,
     has_set_max_level: bool,
+    /// The ID and hostname of the target, from its last heartbeat.
+    device: Option<([u8; 16], String)>,
     /// A set of `tracing` targets and levels to enable.
     ///
     /// Currently, this is processed on the crowtty side, and we only send the
@@ -31,7 +33,66 @@ pub(crate) struct TraceWorker {
     /// fine for the time being.
     filter: Targets,
     ser_max_level: Option<SerializeLevel>,
+    format: TraceFormat,
     state: FormatState,
+    session: TraceSession,
+    /// Whether the target has been asked to send its metadata since we
+    /// connected to it.
+    synced: bool,
+    /// When we last asked the target to resend its metadata.
+    last_resync: Option<Instant>,
+    /// If set, events enabled by the filter are sent here as [`TraceRecord`]s,
+    /// rather than being printed.
+    records: Option<mpsc::Sender<TraceRecord>>,
+}
+
+/// A trace event from the target, as collected by a
+/// [`Script`](crate::script::Script).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TraceRecord {
+    pub level: Level,
+    /// The event's `tracing` target, such as `kernel::services::serial_mux`.
+    pub target: String,
+    /// The event's message, if it has one.
+    pub message: Option<String>,
+    /// The event's other fields, formatted as text.
+    pub fields: BTreeMap<String, String>,
+}
+
+/// Trace metadata received from a target, kept across connections to it.
+///
+/// Targets send the metadata for each of their callsites once, and traces
+/// refer to it by [`MetaId`]. If the connection to a target drops, traces
+/// received after reconnecting can't be decoded without the metadata received
+/// before. Passing the same session to each [`Crowtty`](crate::Crowtty) that
+/// connects to a target, with
+/// [`Crowtty::trace_session`](crate::Crowtty::trace_session), keeps that
+/// metadata around. Any metadata that is still missing is requested from the
+/// target again.
+#[derive(Clone, Default)]
+pub struct TraceSession {
+    inner: Arc<Mutex<Session>>,
+}
+
+#[derive(Default)]
+struct Session {
+    /// The ID of the target that the metadata came from, if it has one.
+    device: Option<[u8; 16]>,
+    metas: HashMap<MetaId, SerializeMetadata<'static>>,
+}
+
+/// How trace events from the target are displayed.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum TraceFormat {
+    /// one line per event, prefixed with the names of the spans it occurred in.
+    #[default]
+    Flat,
+    /// spans are drawn as an indented tree, showing when each span is
+    /// created, entered, exited and closed, with events nested under the span
+    /// they occurred in.
+    Tree,
+    /// one JSON object per trace event, for processing with other tools.
+    Json,
 }
 
 struct FormatState {
@@ -39,14 +100,32 @@ struct FormatState {
     spans: HashMap<NonZeroU64, Span>,
     metas: HashMap<MetaId, SerializeMetadata<'static>>,
     stack: Vec<NonZeroU64>,
+    /// Span timing summaries received since the last `SummaryEnd`.
+    summary: Vec<SpanSummary>,
 }
 
+struct SpanSummary {
+    meta: MetaId,
+    count: u32,
+    total_us: u64,
+    max_us: u64,
+}
+
+/// The number of callsites shown in each span summary table.
+const SUMMARY_TOP_N: usize = 10;
+
+/// How long to wait after asking the target to resend its metadata before
+/// asking again.
+const RESYNC_INTERVAL: Duration = Duration::from_secs(5);
+
 impl TraceWorker {
     pub fn new(
         filter: Targets,
+        format: TraceFormat,
         tx: mpsc::Sender<Vec<u8>>,
         rx: mpsc::Receiver<Vec<u8>>,
         tag: LogTag,
+        session: TraceSession,
     ) -> Self {
         let ser_max_level = <Targets as Layer<NoSubscriber>>::max_level_hint(&filter).and_then(
             |level| match level {
@@ -64,13 +143,29 @@ impl TraceWorker {
             state: FormatState {
                 tag,
                 spans: HashMap::new(),
-                metas: HashMap::new(),
+                metas: session.inner.lock().unwrap().metas.clone(),
                 stack: Vec::new(),
+                summary: Vec::new(),
             },
             textbuf: String::new(),
             ser_max_level,
             has_set_max_level: false,
+            device: None,
             filter,
+            format,
+            session,
+            synced: false,
+            last_resync: None,
+            records: None,
+        }
+    }
+
+    /// Sends the events enabled by the filter to `records`, instead of
+    /// printing them.
+    pub fn records(self, records: mpsc::Sender<TraceRecord>) -> Self {
+        Self {
+            records: Some(records),
+            ..self
         }
     }
 }
@@ -80,6 +175,17 @@ struct Span {
     level: DisplayLevel,
     target: String,
     start: Instant,
+    /// The span's explicit parent, if it has one.
+    ///
+    /// Services handling registry requests create spans whose parent is the
+    /// span that sent the request, rather than the current span.
+    parent: Option<NonZeroU64>,
+    /// How many ancestors of this span are being traced.
+    depth: usize,
+    /// When the span was last entered, if it is currently entered.
+    entered: Option<Instant>,
+    /// The total time spent inside the span.
+    busy: Duration,
     // TODO(eliza): reference count spans
     refs: usize,
 }
@@ -108,8 +214,43 @@ impl TraceWorker {
     }
 
     fn event(&mut self, ev: TraceEvent<'_>) {
+        // the JSON format prints the event exactly as it was received, so
+        // serialize it before it's taken apart.
+        let json = match self.format {
+            TraceFormat::Json => match serde_json::to_string(&ev) {
+                Ok(json) => Some(json),
+                Err(error) => {
+                    println!(
+                        "{} {} failed to serialize trace event as JSON: {error}",
+                        self.state.tag,
+                        "ERR!".if_supports_color(Stream::Stdout, |x| x.red()),
+                    );
+                    return;
+                }
+            },
+            TraceFormat::Flat | TraceFormat::Tree => None,
+        };
+        let tree = self.format == TraceFormat::Tree;
+
         match ev {
-            TraceEvent::Heartbeat(level) => {
+            TraceEvent::Heartbeat(level, device) => {
+                if let Some(device) = device {
+                    let known = self.device.as_ref().map(|(id, host)| (id, host.as_str()));
+                    if known != Some((&device.id, device.hostname)) {
+                        println!(
+                            "{} {} Connected to {} ({})",
+                            self.state.tag,
+                            "BEAT".if_supports_color(Stream::Stdout, |x| x.bright_red()),
+                            device
+                                .hostname
+                                .if_supports_color(Stream::Stdout, |x| x.bold()),
+                            DisplayId(&device.id),
+                        );
+                        self.device = Some((device.id, device.hostname.to_owned()));
+                        self.session_device(device.id);
+                    }
+                }
+
                 if self.state.tag.verbose {
                     println!(
                         "{} {} Found a heartbeat (level: {:?}; desired: {:?})",
@@ -121,6 +262,13 @@ impl TraceWorker {
                 }
 
                 if level == self.ser_max_level {
+                    // the target may have registered its callsites before we
+                    // connected, and it won't send their metadata again
+                    // unless we ask.
+                    if !self.synced {
+                        self.synced = true;
+                        self.request_resync();
+                    }
                     if !self.has_set_max_level || self.state.tag.verbose {
                         println!(
                             "{} {} Max level set to {:?}",
@@ -136,9 +284,10 @@ impl TraceWorker {
                     self.has_set_max_level = false;
                 }
 
-                let req = postcard::to_allocvec_cobs(&HostRequest::SetMaxLevel(self.ser_max_level))
-                    .expect("failed to serialize max level request");
-                self.tx.send(req).expect("failed to send host request");
+                // changing the max level makes the target send the metadata
+                // for every callsite it enables.
+                self.send_request(&HostRequest::SetMaxLevel(self.ser_max_level));
+                self.synced = true;
                 if self.state.tag.verbose {
                     println!(
                         "{} {} Sent request for {:?}",
@@ -176,12 +325,22 @@ impl TraceWorker {
                     println!("{}", self.textbuf);
                     self.textbuf.clear();
                 }
+                self.session
+                    .inner
+                    .lock()
+                    .unwrap()
+                    .metas
+                    .insert(id, meta.to_owned());
                 self.state.metas.insert(id, meta.to_owned());
+                if let Some(json) = json {
+                    println!("{json}");
+                }
             }
             TraceEvent::Event {
                 meta,
                 parent: _,
                 fields,
+                timestamp,
             } => {
                 let Some(meta) = self.state.metas.get(&meta) else {
                     println!(
@@ -189,6 +348,7 @@ impl TraceWorker {
                         self.state.tag,
                         "META".if_supports_color(Stream::Stdout, |x| x.bright_blue())
                     );
+                    self.request_resync();
                     return;
                 };
                 let target = meta.target.as_str();
@@ -198,11 +358,54 @@ impl TraceWorker {
                     return;
                 }
 
+                let SerializeRecordFields::De(ref fields) = fields else {
+                    unreachable!("we are deserializing!");
+                };
+
+                if let Some(ref records) = self.records {
+                    let mut record = TraceRecord {
+                        level: ser_lvl(meta.level),
+                        target: target.to_string(),
+                        message: None,
+                        fields: BTreeMap::new(),
+                    };
+                    for (key, val) in fields {
+                        let val = DisplayVal(val).to_string();
+                        match key.as_str() {
+                            "message" => record.message = Some(val),
+                            key => {
+                                record.fields.insert(key.to_string(), val);
+                            }
+                        }
+                    }
+                    // the receiver only goes away when the worker is
+                    // shutting down.
+                    let _ = records.send(record);
+                    return;
+                }
+
+                if let Some(json) = json {
+                    println!("{json}");
+                    return;
+                }
+
                 let level = DisplayLevel(meta.level);
-                write!(&mut self.textbuf, "{} {level} ", self.state.tag,).unwrap();
+                if tree {
+                    write!(&mut self.textbuf, "{} ", self.state.tag).unwrap();
+                    write_indent(&mut self.textbuf, self.state.current_depth());
+                    write!(&mut self.textbuf, "{level} ").unwrap();
+                } else {
+                    write!(
+                        &mut self.textbuf,
+                        "{} {} {level} ",
+                        self.state.tag,
+                        DisplayTimestamp(timestamp),
+                    )
+                    .unwrap();
 
-                if self.state.write_span_cx(&mut self.textbuf) {
-                    self.textbuf.push(' ');
+                    if self.state.write_span_cx(&mut self.textbuf) {
+                        self.textbuf.push(' ');
+                    }
                 }
 
                 write!(
@@ -213,10 +416,10 @@ impl TraceWorker {
                 )
                 .unwrap();
 
-                let SerializeRecordFields::De(ref fields) = fields else {
-                    unreachable!("we are deserializing!");
-                };
                 write_fields(&mut self.textbuf, fields);
+                if tree {
+                    write!(&mut self.textbuf, " {}", DisplayTimestamp(timestamp)).unwrap();
+                }
 
                 println!("{}", self.textbuf);
                 self.textbuf.clear();
@@ -225,8 +428,9 @@ impl TraceWorker {
                 id: SerializeId { id },
                 meta,
                 fields,
-                parent: _,
+                parent,
                 is_root: _,
+                timestamp,
             } => {
                 let start = Instant::now();
                 let mut repr = String::new();
@@ -236,6 +440,7 @@ impl TraceWorker {
                         self.state.tag,
                         "META".if_supports_color(Stream::Stdout, |x| x.bright_blue())
                     );
+                    self.request_resync();
                     return;
                 };
 
@@ -273,38 +478,110 @@ impl TraceWorker {
                     .unwrap();
                 }
 
+                let parent = parent.map(|SerializeId { id }| id);
+                // a span without an explicit parent is a child of the current
+                // span.
+                let depth = parent
+                    .as_ref()
+                    .or(self.state.stack.last())
+                    .and_then(|parent| self.state.spans.get(parent))
+                    .map_or(0, |parent| parent.depth + 1);
                 let tag = "SPAN".if_supports_color(Stream::Stdout, |x| x.bright_magenta());
                 let span = Span {
                     target: target.to_string(),
                     level,
                     repr,
                     start,
+                    parent,
+                    depth,
+                    entered: None,
+                    busy: Duration::ZERO,
                     refs: 1,
                 };
-                self.state
-                    .write_span_event(&tag, &span, id, &mut self.textbuf);
-
-                println!("{}", self.textbuf);
-                self.textbuf.clear();
+                if let Some(json) = json {
+                    println!("{json}");
+                } else if tree {
+                    self.state
+                        .write_tree_span(&tag, &span, id, &mut self.textbuf);
+                    write!(
+                        &mut self.textbuf,
+                        " {} {}",
+                        target.if_supports_color(Stream::Stdout, |x| x.dimmed()),
+                        DisplayTimestamp(timestamp),
+                    )
+                    .unwrap();
+                    println!("{}", self.textbuf);
+                    self.textbuf.clear();
+                } else {
+                    self.state.write_span_event(
+                        &tag,
+                        &span,
+                        id,
+                        Some(timestamp),
+                        &mut self.textbuf,
+                    );
+                    println!("{}", self.textbuf);
+                    self.textbuf.clear();
+                }
 
                 self.state.spans.insert(id, span);
             }
             TraceEvent::Enter(SerializeId { id }) => {
                 // only put a span on the stack if we enabled it when it was created.
-                if self.state.spans.contains_key(&id) {
-                    self.state.stack.push(id);
+                let Some(span) = self.state.spans.get_mut(&id) else {
+                    return;
+                };
+                span.entered = Some(Instant::now());
+                self.state.stack.push(id);
+
+                if let Some(json) = json {
+                    println!("{json}");
+                } else if tree {
+                    let enter = "ENTR".if_supports_color(Stream::Stdout, |x| x.bright_green());
+                    self.state.write_tree_span(
+                        &enter,
+                        &self.state.spans[&id],
+                        id,
+                        &mut self.textbuf,
+                    );
+                    println!("{}", self.textbuf);
+                    self.textbuf.clear();
                 }
             }
             TraceEvent::Exit(SerializeId { id }) => {
                 // only popped the span if we enabled it when it was created.
-                if self.state.spans.contains_key(&id) {
-                    let popped = self.state.stack.pop();
-                    debug_assert_eq!(popped, Some(id));
+                let Some(span) = self.state.spans.get_mut(&id) else {
+                    return;
+                };
+                let busy = span
+                    .entered
+                    .take()
+                    .map(|entered| entered.elapsed())
+                    .unwrap_or_default();
+                span.busy += busy;
+                let popped = self.state.stack.pop();
+                debug_assert_eq!(popped, Some(id));
+
+                if let Some(json) = json {
+                    println!("{json}");
+                } else if tree {
+                    let exit = "EXIT".if_supports_color(Stream::Stdout, |x| x.yellow());
+                    self.state.write_tree_span(
+                        &exit,
+                        &self.state.spans[&id],
+                        id,
+                        &mut self.textbuf,
+                    );
+                    println!("{}: {busy:?}", self.textbuf);
+                    self.textbuf.clear();
                 }
             }
             TraceEvent::CloneSpan(SerializeId { id }) => {
                 if let Some(span) = self.state.spans.get_mut(&id) {
                     span.refs += 1;
+                    if let Some(json) = json {
+                        println!("{json}");
+                    }
                 }
             }
             TraceEvent::DropSpan(SerializeId { id }) => {
@@ -316,22 +593,112 @@ impl TraceWorker {
                     return;
                 };
 
+                if let Some(json) = json {
+                    println!("{json}");
+                    if end {
+                        self.state.spans.remove(&id);
+                    }
+                    return;
+                }
+
                 if end {
                     let span = self.state.spans.remove(&id).unwrap();
 
                     let end = " END".if_supports_color(Stream::Stdout, |x| x.bright_red());
-                    self.state
-                        .write_span_event(&end, &span, id, &mut self.textbuf);
-
-                    println!("{}: {:?}", self.textbuf, span.start.elapsed());
+                    if tree {
+                        self.state
+                            .write_tree_span(&end, &span, id, &mut self.textbuf);
+                        println!(
+                            "{}: {:?} {}",
+                            self.textbuf,
+                            span.start.elapsed(),
+                            format_args!("(busy {:?})", span.busy)
+                                .if_supports_color(Stream::Stdout, |x| x.dimmed()),
+                        );
+                    } else {
+                        // the target doesn't timestamp span drops, so there's no
+                        // target-side time to show.
+                        self.state
+                            .write_span_event(&end, &span, id, None, &mut self.textbuf);
+                        println!("{}: {:?}", self.textbuf, span.start.elapsed());
+                    }
                     self.textbuf.clear();
                 }
             }
-            dropped @ TraceEvent::Discarded { .. } => {
-                println!("{} {dropped:?}", self.state.tag);
+            TraceEvent::SpanSummary {
+                meta,
+                count,
+                total_us,
+                max_us,
+            } => {
+                if let Some(json) = json {
+                    println!("{json}");
+                    return;
+                }
+                self.state.summary.push(SpanSummary {
+                    meta,
+                    count,
+                    total_us,
+                    max_us,
+                });
+            }
+            TraceEvent::SummaryEnd { period_ms, dropped } => {
+                if let Some(json) = json {
+                    println!("{json}");
+                    return;
+                }
+                self.state
+                    .write_summary(period_ms, dropped, &mut self.textbuf);
+                print!("{}", self.textbuf);
+                self.textbuf.clear();
+            }
+            dropped @ TraceEvent::Discarded { metas, .. } => {
+                match json {
+                    Some(json) => println!("{json}"),
+                    None => println!("{} {dropped:?}", self.state.tag),
+                }
+                // the target disables callsites whose metadata it couldn't
+                // send, so ask for them again.
+                if metas > 0 {
+                    self.request_resync();
+                }
             }
         }
     }
+
+    fn send_request(&self, req: &HostRequest) {
+        let req = postcard::to_allocvec_cobs(req).expect("failed to serialize host request");
+        self.tx.send(req).expect("failed to send host request");
+    }
+
+    /// Asks the target to send the metadata for all of its callsites again,
+    /// unless we already asked recently.
+    fn request_resync(&mut self) {
+        if self
+            .last_resync
+            .is_some_and(|at| at.elapsed() < RESYNC_INTERVAL)
+        {
+            return;
+        }
+        self.last_resync = Some(Instant::now());
+        self.send_request(&HostRequest::ResyncMetadata);
+        println!(
+            "{} {} Requested metadata from target",
+            self.state.tag,
+            "META".if_supports_color(Stream::Stdout, |x| x.bright_blue()),
+        );
+    }
+
+    /// Records that the session's metadata is from the target with ID `id`,
+    /// forgetting any metadata from a different target.
+    fn session_device(&mut self, id: [u8; 16]) {
+        let mut session = self.session.inner.lock().unwrap();
+        if session.device.is_some_and(|known| known != id) {
+            session.metas.clear();
+            self.state.metas.clear();
+        }
+        session.device = Some(id);
+    }
 }
 
 impl FormatState {
@@ -340,15 +707,21 @@ impl FormatState {
         tag: &impl fmt::Display,
         span: &Span,
         id: NonZeroU64,
+        timestamp: Option<Timestamp>,
         textbuf: &mut String,
     ) {
         let Span {
             target,
             level,
             repr,
+            parent,
             ..
         } = span;
-        write!(textbuf, "{} {level} ", self.tag).unwrap();
+        write!(textbuf, "{} ", self.tag).unwrap();
+        if let Some(timestamp) = timestamp {
+            write!(textbuf, "{} ", DisplayTimestamp(timestamp)).unwrap();
+        }
+        write!(textbuf, "{level} ").unwrap();
 
         self.write_span_cx(textbuf);
 
@@ -359,6 +732,92 @@ impl FormatState {
                 .if_supports_color(Stream::Stdout, |target| target.dimmed())
         )
         .unwrap();
+
+        // if the span's parent isn't the span it was created in (such as when
+        // a service handles a request from another task), say where it came
+        // from, so that the request can be followed.
+        if let Some(parent) = parent.filter(|parent| self.stack.last() != Some(parent)) {
+            write!(
+                textbuf,
+                " {}",
+                format_args!("<- ({parent:04})").if_supports_color(Stream::Stdout, |x| x.dimmed())
+            )
+            .unwrap();
+        }
+    }
+
+    /// Writes a span lifecycle line for the [`TraceFormat::Tree`] format,
+    /// indented under the span's parent.
+    fn write_tree_span(
+        &self,
+        kind: &impl fmt::Display,
+        span: &Span,
+        id: NonZeroU64,
+        textbuf: &mut String,
+    ) {
+        write!(textbuf, "{} ", self.tag).unwrap();
+        write_indent(textbuf, span.depth);
+        write!(textbuf, "{} {kind} {} ({id:04})", span.level, span.repr).unwrap();
+    }
+
+    /// Returns the indentation depth of events in the current span.
+    fn current_depth(&self) -> usize {
+        self.stack
+            .last()
+            .and_then(|id| self.spans.get(id))
+            .map_or(0, |span| span.depth + 1)
+    }
+
+    /// Writes the top [`SUMMARY_TOP_N`] span callsites from the current
+    /// summary, by total time, and clears the summary.
+    fn write_summary(&mut self, period_ms: u32, dropped: usize, textbuf: &mut String) {
+        let mut summary = std::mem::take(&mut self.summary);
+        summary.sort_unstable_by(|a, b| b.total_us.cmp(&a.total_us));
+
+        let tag = "SUMM".if_supports_color(Stream::Stdout, |x| x.bright_cyan());
+        writeln!(
+            textbuf,
+            "{} {tag} top spans over the last {period_ms}ms ({} callsites, {dropped} dropped)",
+            self.tag,
+            summary.len(),
+        )
+        .unwrap();
+        writeln!(
+            textbuf,
+            "{} {tag} {}",
+            self.tag,
+            format_args!(
+                "{:>8} {:>12} {:>12} {:>12}  span",
+                "count", "total", "mean", "max"
+            )
+            .if_supports_color(Stream::Stdout, |x| x.dimmed()),
+        )
+        .unwrap();
+
+        for entry in summary.iter().take(SUMMARY_TOP_N) {
+            let mean_us = entry.total_us / u64::from(entry.count.max(1));
+            // `fmt::Arguments` ignores width, so pad the numbers and append
+            // the units, rather than padding `format_args!("{n}us")`.
+            write!(
+                textbuf,
+                "{} {tag} {:>8} {:>10}us {:>10}us {:>10}us  ",
+                self.tag, entry.count, entry.total_us, mean_us, entry.max_us,
+            )
+            .unwrap();
+            match self.metas.get(&entry.meta) {
+                Some(meta) => writeln!(
+                    textbuf,
+                    "{}{}",
+                    format_args!("{}::", meta.target.as_str())
+                        .if_supports_color(Stream::Stdout, |x| x.dimmed()),
+                    meta.name
+                        .as_str()
+                        .if_supports_color(Stream::Stdout, |x| x.bold()),
+                ),
+                None => writeln!(textbuf, "UNKNOWN: {:?}", entry.meta),
+            }
+            .unwrap();
+        }
     }
 
     fn write_span_cx(&self, textbuf: &mut String) -> bool {
@@ -374,6 +833,14 @@ impl FormatState {
     }
 }
 
+/// Writes the tree guides for a [`TraceFormat::Tree`] line at `depth`.
+fn write_indent(textbuf: &mut String, depth: usize) {
+    let guide = "│ ".if_supports_color(Stream::Stdout, |x| x.dimmed());
+    for _ in 0..depth {
+        write!(textbuf, "{guide}").unwrap();
+    }
+}
+
 fn write_fields<'a>(to: &mut String, fields: &BTreeMap<CowString<'a>, SerializeValue<'a>>) {
     let comma = ", ".if_supports_color(Stream::Stdout, |delim| delim.dimmed());
     let mut wrote_anything = false;
@@ -431,6 +898,9 @@ impl fmt::Display for DisplayVal<'_> {
 #[derive(Copy, Clone)]
 struct DisplayLevel(SerializeLevel);
 
+/// Formats a target's device ID as hex.
+struct DisplayId<'a>(&'a [u8; 16]);
+
 impl fmt::Display for DisplayLevel {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self.0 {
@@ -469,6 +939,15 @@ impl fmt::Debug for DisplayLevel {
     }
 }
 
+impl fmt::Display for DisplayId<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for byte in self.0 {
+            write!(f, "{byte:02x}")?;
+        }
+        Ok(())
+    }
+}
+
 fn ser_lvl(lvl: SerializeLevel) -> Level {
     match lvl {
         SerializeLevel::Trace => Level::TRACE,
@@ -478,3 +957,160 @@ fn ser_lvl(lvl: SerializeLevel) -> Level {
         SerializeLevel::Error => Level::ERROR,
     }
 }
+
+/// Formats a target-side [`Timestamp`].
+///
+/// If the target knows the wall-clock time, this is formatted as an RFC 3339
+/// UTC timestamp. Otherwise, it is formatted as the time since the target
+/// booted.
+struct DisplayTimestamp(Timestamp);
+
+impl fmt::Display for DisplayTimestamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const US_PER_SEC: u64 = 1_000_000;
+        const SECS_PER_DAY: u64 = 86_400;
+
+        let text = match self.0.unix_us {
+            Some(unix_us) => {
+                let (secs, us) = (unix_us / US_PER_SEC, unix_us % US_PER_SEC);
+                let (year, month, day) = civil_from_days(secs / SECS_PER_DAY);
+                let secs = secs % SECS_PER_DAY;
+                format!(
+                    "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}.{us:06}Z",
+                    secs / 3600,
+                    secs / 60 % 60,
+                    secs % 60,
+                )
+            }
+            None => {
+                let uptime_us = self.0.uptime_us;
+                format!(
+                    "T+{:04}.{:06}s",
+                    uptime_us / US_PER_SEC,
+                    uptime_us % US_PER_SEC
+                )
+            }
+        };
+        text.if_supports_color(Stream::Stdout, |text| text.dimmed())
+            .fmt(f)
+    }
+}
+
+/// Converts a number of days since the Unix epoch to a `(year, month, day)`
+/// date in the proleptic Gregorian calendar.
+///
+/// This is Howard Hinnant's `civil_from_days` algorithm, see
+/// <https://howardhinnant.github.io/date_algorithms.html#civil_from_days>.
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    // shift the epoch to 0000-03-01, so that leap days are at the end of
+    // each year.
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn meta_id(id: u8) -> MetaId {
+        postcard::from_bytes(&[id]).unwrap()
+    }
+
+    fn state() -> FormatState {
+        FormatState {
+            tag: LogTag::new("TEST"),
+            spans: HashMap::new(),
+            metas: HashMap::new(),
+            stack: Vec::new(),
+            summary: Vec::new(),
+        }
+    }
+
+    fn entry(meta: u8, count: u32, total_us: u64, max_us: u64) -> SpanSummary {
+        SpanSummary {
+            meta: meta_id(meta),
+            count,
+            total_us,
+            max_us,
+        }
+    }
+
+    /// Returns the rows of a summary table, without the header lines.
+    fn rows(textbuf: &str) -> Vec<&str> {
+        textbuf.lines().skip(2).collect()
+    }
+
+    #[test]
+    fn summary_sorted_by_total_time() {
+        let mut state = state();
+        state.summary.push(entry(1, 10, 1_000, 300));
+        state.summary.push(entry(2, 3, 123_456_789, 100_000_000));
+        state.summary.push(entry(3, 0, 5, 5));
+
+        let mut textbuf = String::new();
+        state.write_summary(1000, 2, &mut textbuf);
+        assert!(
+            textbuf.contains("over the last 1000ms (3 callsites, 2 dropped)"),
+            "{textbuf}"
+        );
+        assert!(state.summary.is_empty(), "the summary should be cleared");
+
+        let rows = rows(&textbuf);
+        assert_eq!(rows.len(), 3, "{textbuf}");
+        assert!(rows[0].contains(&format!("{:?}", meta_id(2))), "{textbuf}");
+        assert!(rows[1].contains(&format!("{:?}", meta_id(1))), "{textbuf}");
+        assert!(rows[2].contains(&format!("{:?}", meta_id(3))), "{textbuf}");
+        // the mean is the total time over the count, and a zero count doesn't
+        // divide by zero.
+        assert!(rows[1].contains(" 100us "), "{textbuf}");
+        assert!(rows[2].contains(" 5us "), "{textbuf}");
+    }
+
+    #[test]
+    fn summary_columns_line_up() {
+        let mut state = state();
+        state.summary.push(entry(1, 1, 1, 1));
+        state
+            .summary
+            .push(entry(2, 1_000_000, 9_999_999_999, 12_345));
+
+        let mut textbuf = String::new();
+        state.write_summary(1000, 0, &mut textbuf);
+        let rows = rows(&textbuf);
+        assert_eq!(rows.len(), 2, "{textbuf}");
+        let ends = |row: &str| -> Vec<usize> { row.match_indices("us ").map(|(i, _)| i).collect() };
+        assert_eq!(ends(rows[0]).len(), 3, "{textbuf}");
+        assert_eq!(ends(rows[0]), ends(rows[1]), "{textbuf}");
+        assert_eq!(
+            rows[0].find("UNKNOWN"),
+            rows[1].find("UNKNOWN"),
+            "{textbuf}"
+        );
+    }
+
+    #[test]
+    fn summary_shows_top_callsites() {
+        let mut state = state();
+        for meta in 1..=(SUMMARY_TOP_N as u8 + 2) {
+            state.summary.push(entry(meta, 1, u64::from(meta), 1));
+        }
+
+        let mut textbuf = String::new();
+        state.write_summary(1000, 0, &mut textbuf);
+        assert!(textbuf.contains(&format!("({} callsites", SUMMARY_TOP_N + 2)));
+        let rows = rows(&textbuf);
+        assert_eq!(rows.len(), SUMMARY_TOP_N, "{textbuf}");
+        // the callsites with the least total time are left out.
+        assert!(!textbuf.contains(&format!("{:?}", meta_id(1))), "{textbuf}");
+        assert!(!textbuf.contains(&format!("{:?}", meta_id(2))), "{textbuf}");
+    }
+}
@@ -0,0 +1,151 @@
+//! Board profiles, read from `platforms/boards.toml`.
+
+use std::{
+    collections::BTreeMap,
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+
+use miette::{miette, Context, IntoDiagnostic};
+use serde::Deserialize;
+
+use crate::image;
+
+/// Every board in a profiles file.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Boards {
+    boards: BTreeMap<String, Board>,
+}
+
+/// A single board's build profile.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Board {
+    /// A human-readable description, shown by `mnemos list`.
+    #[serde(default)]
+    pub description: String,
+    /// Other names that the board can be referred to by.
+    #[serde(default)]
+    pub aliases: Vec<String>,
+    /// Which platform the board belongs to.
+    pub platform: Platform,
+    /// Overrides the Cargo package to build.
+    package: Option<String>,
+    /// The binary target to build, for platforms with more than one.
+    pub bin: Option<String>,
+    /// Extra arguments for the platform's flashing tool.
+    #[serde(default)]
+    pub flash_args: Vec<String>,
+    /// Extra arguments for the platform's runner.
+    #[serde(default)]
+    pub run_args: Vec<String>,
+    /// The first-stage bootloader written ahead of the kernel when the
+    /// kernel is written to persistent storage, relative to the workspace
+    /// root.
+    pub bt0: Option<PathBuf>,
+    /// The kernel's offset on persistent storage, in bytes.
+    #[serde(default = "Board::default_payload_offset")]
+    pub payload_offset: u64,
+    /// The sizes of the regions of the board's SPI NOR images.
+    #[serde(default)]
+    pub flash_map: image::Layout,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Platform {
+    /// Allwinner D1, loaded into DRAM over USB using `xfel`.
+    D1,
+    /// ESP32-C3 WiFi buddy, flashed using `cargo espflash`.
+    Esp32c3,
+    /// x86_64, built into a boot image and run in QEMU.
+    X86_64,
+    /// Melpomene, the desktop simulator.
+    Melpomene,
+}
+
+// === impl Boards ===
+
+impl Boards {
+    pub fn load(path: &Path) -> miette::Result<Self> {
+        let text = fs::read_to_string(path)
+            .into_diagnostic()
+            .with_context(|| format!("failed to read board profiles from {}", path.display()))?;
+        toml::from_str(&text)
+            .into_diagnostic()
+            .with_context(|| format!("failed to parse board profiles in {}", path.display()))
+    }
+
+    pub fn get(&self, name: &str) -> miette::Result<&Board> {
+        let board = self.boards.get(name).or_else(|| {
+            self.boards
+                .values()
+                .find(|board| board.aliases.iter().any(|alias| alias == name))
+        });
+        board.ok_or_else(|| {
+            let known = self.boards.keys().map(String::as_str).collect::<Vec<_>>();
+            miette!(
+                help = format!("known boards are: {}", known.join(", ")),
+                "unknown board '{name}'",
+            )
+        })
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Board)> {
+        self.boards
+            .iter()
+            .map(|(name, board)| (name.as_str(), board))
+    }
+}
+
+// === impl Board ===
+
+impl Board {
+    /// By default, the kernel is written 40 KiB into the boot medium, which
+    /// is where the first-stage bootloader expects to find it.
+    const DEFAULT_PAYLOAD_OFFSET: u64 = 40 * 1024;
+
+    const fn default_payload_offset() -> u64 {
+        Self::DEFAULT_PAYLOAD_OFFSET
+    }
+
+    /// Returns the Cargo package that the board is built from.
+    pub fn package(&self) -> &str {
+        self.package
+            .as_deref()
+            .unwrap_or_else(|| self.platform.default_package())
+    }
+
+    /// Returns the board's binary target, or an error if it doesn't have one.
+    pub fn require_bin(&self) -> miette::Result<&str> {
+        self.bin
+            .as_deref()
+            .ok_or_else(|| miette!("{} boards must set `bin` in their profile", self.platform))
+    }
+}
+
+// === impl Platform ===
+
+impl Platform {
+    fn default_package(&self) -> &'static str {
+        match self {
+            Self::D1 => "mnemos-d1",
+            Self::Esp32c3 => "mnemos-esp32c3-buddy",
+            Self::X86_64 => "mnemos-x86_64",
+            Self::Melpomene => "melpomene",
+        }
+    }
+}
+
+impl fmt::Display for Platform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // use `pad`, so that `mnemos list` can align platform names.
+        f.pad(match self {
+            Self::D1 => "Allwinner D1",
+            Self::Esp32c3 => "ESP32-C3",
+            Self::X86_64 => "x86_64",
+            Self::Melpomene => "Melpomene",
+        })
+    }
+}
@@ -0,0 +1,161 @@
+//! SPI NOR flash images for the Allwinner D1.
+//!
+//! An image holds everything the D1 needs to boot from SPI NOR, in the
+//! layout described by the [`mnemos_flash_map`] crate: the first-stage
+//! bootloader (SPL) at offset 0, where the boot ROM looks for it, then the
+//! flash map, the kernel, and the config region. The key-value region comes
+//! last, and is not part of the image, so that writing a new image doesn't
+//! erase the data the kernel has stored there.
+
+use miette::miette;
+use mnemos_flash_map::{FlashMap, Region, OFFSET, SECTOR_SIZE};
+use serde::Deserialize;
+
+/// The magic that identifies an eGON.BT0 image, at offset 4.
+const EGON_MAGIC: &[u8; 8] = b"eGON.BT0";
+
+/// The value of the checksum field while an eGON.BT0 checksum is computed.
+const EGON_STAMP: u32 = 0x5F0A_6C39;
+
+/// The sizes of the regions of a board's SPI NOR images, set by the
+/// `flash_map` table in the board's profile.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Layout {
+    /// Where the kernel region ends, and the config region starts.
+    #[serde(default = "Layout::default_kernel_end")]
+    pub kernel_end: u32,
+    /// The size of the config region.
+    #[serde(default = "Layout::default_config_size")]
+    pub config_size: u32,
+    /// The size of the key-value region, which follows the config region.
+    #[serde(default = "Layout::default_kv_size")]
+    pub kv_size: u32,
+}
+
+// === impl Layout ===
+
+impl Layout {
+    const DEFAULT_KERNEL_END: u32 = 4 * 1024 * 1024;
+    const DEFAULT_CONFIG_SIZE: u32 = 64 * 1024;
+    const DEFAULT_KV_SIZE: u32 = 1024 * 1024;
+
+    const fn default_kernel_end() -> u32 {
+        Self::DEFAULT_KERNEL_END
+    }
+
+    const fn default_config_size() -> u32 {
+        Self::DEFAULT_CONFIG_SIZE
+    }
+
+    const fn default_kv_size() -> u32 {
+        Self::DEFAULT_KV_SIZE
+    }
+
+    /// Returns the flash map for a kernel of `kernel_len` bytes, written at
+    /// `payload_offset`.
+    pub fn flash_map(&self, payload_offset: u64, kernel_len: usize) -> miette::Result<FlashMap> {
+        let kernel_offset = u32::try_from(payload_offset)
+            .map_err(|_| miette!("the payload offset {payload_offset:#x} is too large"))?;
+        let kernel_len = u32::try_from(kernel_len)
+            .map_err(|_| miette!("the kernel is {kernel_len} bytes long, which is too large"))?;
+        let map = FlashMap {
+            kernel: Region {
+                offset: kernel_offset,
+                len: self.kernel_end.saturating_sub(kernel_offset),
+            },
+            kernel_len,
+            config: Region {
+                offset: self.kernel_end,
+                len: self.config_size,
+            },
+            kv: Region {
+                offset: self.kernel_end.saturating_add(self.config_size),
+                len: self.kv_size,
+            },
+        };
+        map.validate().map_err(|error| {
+            miette!(
+                help = format!(
+                    "regions must be multiples of {SECTOR_SIZE} bytes, and start after {:#x}. \
+                    set `payload_offset` and the `flash_map` table in the board's profile",
+                    OFFSET + SECTOR_SIZE,
+                ),
+                "invalid SPI flash layout: {error}",
+            )
+        })?;
+        Ok(map)
+    }
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        Self {
+            kernel_end: Self::DEFAULT_KERNEL_END,
+            config_size: Self::DEFAULT_CONFIG_SIZE,
+            kv_size: Self::DEFAULT_KV_SIZE,
+        }
+    }
+}
+
+/// Builds a SPI NOR image from `spl`, `kernel`, and optionally `config`,
+/// using `map` as the image's flash map.
+///
+/// Space between them is filled with `0xFF`, as if it were erased.
+pub fn build(map: &FlashMap, spl: &[u8], kernel: &[u8], config: Option<&[u8]>) -> Vec<u8> {
+    let mut image = Vec::new();
+    let mut place = |offset: u32, data: &[u8]| {
+        image.resize(offset as usize, 0xFF);
+        image.extend_from_slice(data);
+    };
+    place(0, spl);
+    place(OFFSET, &map.encode());
+    place(map.kernel.offset, kernel);
+    if let Some(config) = config {
+        place(map.config.offset, config);
+    }
+    image
+}
+
+/// Checks that `spl` is an eGON.BT0 image that the D1's boot ROM will load,
+/// and that it ends before the flash map.
+pub fn check_spl(spl: &[u8]) -> miette::Result<()> {
+    const HELP: &str = "the first-stage bootloader must be an eGON.BT0 image, \
+        with a valid length and checksum";
+    if spl.get(4..12) != Some(EGON_MAGIC.as_slice()) {
+        return Err(miette!(help = HELP, "not an eGON.BT0 image"));
+    }
+    let word = |offset: usize| u32::from_le_bytes(spl[offset..offset + 4].try_into().unwrap());
+    let checksum = word(12);
+    let len = word(16) as usize;
+    if len > spl.len() || len % 4 != 0 {
+        return Err(miette!(
+            help = HELP,
+            "the eGON.BT0 header's length ({len}) is invalid for a {} byte file",
+            spl.len(),
+        ));
+    }
+    if len > OFFSET as usize {
+        return Err(miette!(
+            "the first-stage bootloader is {len} bytes long, but must fit in {OFFSET} bytes, \
+            before the flash map",
+        ));
+    }
+
+    let sum = spl[..len]
+        .chunks_exact(4)
+        .enumerate()
+        .map(|(i, word)| match i {
+            // the checksum is computed with the stamp in place of itself.
+            3 => EGON_STAMP,
+            _ => u32::from_le_bytes(word.try_into().unwrap()),
+        })
+        .fold(0u32, u32::wrapping_add);
+    if sum != checksum {
+        return Err(miette!(
+            help = HELP,
+            "the eGON.BT0 checksum is {checksum:#010x}, but should be {sum:#010x}",
+        ));
+    }
+    Ok(())
+}
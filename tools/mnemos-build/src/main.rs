@@ -0,0 +1,150 @@
+#![doc = include_str!("../README.md")]
+use std::path::PathBuf;
+
+use clap::Parser;
+
+mod board;
+mod image;
+mod platform;
+mod xfel;
+
+/// The root of the mnemOS workspace that this tool was built in.
+const WORKSPACE_ROOT: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/../..");
+
+#[derive(Parser)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    #[command(subcommand)]
+    cmd: Command,
+
+    /// the board profiles file.
+    ///
+    /// defaults to `platforms/boards.toml` in the mnemOS workspace.
+    #[arg(long, global = true, env = "MNEMOS_BOARDS")]
+    boards: Option<PathBuf>,
+
+    /// the Cargo profile to build with.
+    #[arg(long, global = true, default_value = "release")]
+    profile: String,
+
+    /// print the commands that would be run, without running them.
+    #[arg(long, short = 'n', global = true)]
+    dry_run: bool,
+
+    /// overrides the path to the `cargo` executable.
+    #[arg(long, global = true, env = "CARGO", default_value = "cargo")]
+    cargo: PathBuf,
+}
+
+#[derive(clap::Subcommand)]
+enum Command {
+    /// list the boards that can be built.
+    List,
+    /// build mnemOS for a board.
+    Build(Target),
+    /// build mnemOS for a board, and write it to the board.
+    Flash {
+        #[command(flatten)]
+        target: Target,
+        #[command(flatten)]
+        opts: platform::FlashOptions,
+    },
+    /// (D1 only) build mnemOS for a board, and lay it out in an image that
+    /// boots from SPI NOR flash.
+    ///
+    /// the image holds the first-stage bootloader, a flash map, the kernel,
+    /// and (optionally) the contents of the config region. it can be written
+    /// to the board with `mnemos flash --to spi-nor`, or with any SPI flash
+    /// programmer.
+    Image {
+        #[command(flatten)]
+        target: Target,
+        #[command(flatten)]
+        opts: platform::ImageOptions,
+        /// where to write the image.
+        ///
+        /// defaults to `mnemos-<BIN>-spi-nor.bin`, next to the kernel image.
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
+    /// build mnemOS for a board, and run it.
+    ///
+    /// hardware boards are flashed and started. emulated and simulated boards
+    /// are started in the emulator or simulator.
+    Run {
+        #[command(flatten)]
+        target: Target,
+        #[command(flatten)]
+        opts: platform::RunOptions,
+    },
+}
+
+#[derive(clap::Args)]
+struct Target {
+    /// the name (or alias) of the board, as listed by `mnemos list`.
+    board: String,
+
+    /// extra arguments to pass to `cargo build`.
+    #[arg(last = true)]
+    cargo_args: Vec<String>,
+}
+
+fn main() -> miette::Result<()> {
+    let Args {
+        cmd,
+        boards,
+        profile,
+        dry_run,
+        cargo,
+    } = Args::parse();
+    let root = PathBuf::from(WORKSPACE_ROOT);
+    let boards =
+        board::Boards::load(&boards.unwrap_or_else(|| root.join("platforms").join("boards.toml")))?;
+
+    let runner = |target: Target| -> miette::Result<_> {
+        let board = boards.get(&target.board)?;
+        let runner = platform::Runner {
+            root: root.clone(),
+            cargo: cargo.clone(),
+            profile: profile.clone(),
+            dry_run,
+            cargo_args: target.cargo_args,
+        };
+        Ok((runner, board))
+    };
+
+    match cmd {
+        Command::List => {
+            for (name, board) in boards.iter() {
+                print!("{name:<12}  {:<12}  {}", board.platform, board.description);
+                if !board.aliases.is_empty() {
+                    print!(" (aka {})", board.aliases.join(", "));
+                }
+                println!();
+            }
+            Ok(())
+        }
+        Command::Build(target) => {
+            let (runner, board) = runner(target)?;
+            runner.build(board).map(drop)
+        }
+        Command::Flash { target, opts } => {
+            let (runner, board) = runner(target)?;
+            runner.flash(board, &opts)
+        }
+        Command::Image {
+            target,
+            opts,
+            output,
+        } => {
+            let (runner, board) = runner(target)?;
+            let image = runner.image(board, &opts, output.as_deref())?;
+            eprintln!("SPI NOR image: {}", image.display());
+            Ok(())
+        }
+        Command::Run { target, opts } => {
+            let (runner, board) = runner(target)?;
+            runner.run(board, &opts)
+        }
+    }
+}
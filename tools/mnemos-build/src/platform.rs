@@ -0,0 +1,516 @@
+//! Building, flashing, and running each platform.
+//!
+//! Each of these is a thin wrapper around the commands that would otherwise
+//! be run by hand (or by the `justfile`), so that every board can be driven
+//! with the same three subcommands.
+
+use std::{
+    fs,
+    io::{Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use miette::{miette, Context, IntoDiagnostic};
+
+use crate::{
+    board::{Board, Platform},
+    image,
+    xfel::{SpiFlash, Xfel, D1_LOAD_ADDR},
+};
+
+/// Where the D1's boot ROM looks for a first-stage bootloader on an SD card.
+const D1_SD_BT0_OFFSET: u64 = 8 * 1024;
+
+/// SD cards are written in whole 1 KiB blocks, like `dd bs=1024 conv=sync`.
+const SD_BLOCK: usize = 1024;
+
+/// Shared settings for running build commands.
+#[derive(Debug)]
+pub struct Runner {
+    /// The root of the mnemOS workspace, which commands are run in.
+    pub root: PathBuf,
+    /// The `cargo` executable to run.
+    pub cargo: PathBuf,
+    /// The Cargo profile to build with.
+    pub profile: String,
+    /// If `true`, print commands without running them.
+    pub dry_run: bool,
+    /// Extra arguments passed to every `cargo build` invocation.
+    pub cargo_args: Vec<String>,
+}
+
+/// Options for `mnemos flash`.
+#[derive(Debug, clap::Args)]
+pub struct FlashOptions {
+    /// (D1 only) where to write the kernel.
+    ///
+    /// `dram` loads the kernel over FEL and starts it, without writing it to
+    /// persistent storage. `spi-nor` writes a SPI NOR image (as built by
+    /// `mnemos image`) to the board's SPI flash over FEL, and `spi-nand`
+    /// writes the first-stage bootloader and kernel to it; both then reset
+    /// the board to boot from it. `sd` writes the first-stage bootloader and
+    /// kernel to an SD card in the host machine's card reader.
+    #[arg(long, value_enum, default_value_t = Medium::Dram)]
+    pub to: Medium,
+
+    /// (D1 only) the SD card device to write to, with `--to sd`.
+    ///
+    /// everything at the start of this device will be overwritten!
+    #[arg(long, value_name = "DEVICE", required_if_eq("to", "sd"))]
+    pub sd: Option<PathBuf>,
+
+    #[command(flatten)]
+    pub image: ImageOptions,
+}
+
+/// Options for laying out a D1 kernel on persistent storage, for `mnemos
+/// image` and `mnemos flash`.
+#[derive(Debug, clap::Args)]
+pub struct ImageOptions {
+    /// (D1 only) overrides the board profile's first-stage bootloader.
+    #[arg(long)]
+    pub bt0: Option<PathBuf>,
+
+    /// (D1 only) a file to write to the config region of a SPI NOR image.
+    ///
+    /// if this is not set, the config region on the flash is left as it is.
+    #[arg(long, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+}
+
+/// Options for `mnemos run`.
+#[derive(Debug, clap::Args)]
+pub struct RunOptions {
+    #[command(flatten)]
+    pub flash: FlashOptions,
+
+    /// (D1 only) the board's serial port (not the FEL device!).
+    ///
+    /// once the board has been started, crowtty is attached to this port.
+    #[arg(long, value_name = "PORT", env = "MNEMOS_SERIAL")]
+    pub serial: Option<PathBuf>,
+}
+
+/// Where a D1 kernel is written.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, clap::ValueEnum)]
+pub enum Medium {
+    Dram,
+    Sd,
+    SpiNor,
+    SpiNand,
+}
+
+// === impl Runner ===
+
+impl Runner {
+    /// Builds `board`, returning the path to the image to flash, if the
+    /// platform produces one.
+    pub fn build(&self, board: &Board) -> miette::Result<Option<PathBuf>> {
+        match board.platform {
+            Platform::D1 => {
+                let bin = board.require_bin()?;
+                let mut cmd = self.cargo("build");
+                cmd.args(["--package", board.package(), "--bin", bin]);
+                cmd.args(&self.cargo_args);
+                self.exec(cmd)?;
+
+                // the D1 boots a raw binary, not an ELF.
+                let image = self
+                    .root
+                    .join("target/riscv64imac-unknown-none-elf")
+                    .join(format!("mnemos-{bin}.bin"));
+                let mut cmd = self.cargo("objcopy");
+                cmd.args(["--package", board.package(), "--bin", bin]);
+                cmd.args(&self.cargo_args);
+                cmd.args(["--", "-O", "binary"]).arg(&image);
+                self.exec(cmd)?;
+                Ok(Some(image))
+            }
+            Platform::Esp32c3 => {
+                let mut cmd = self.cargo("build");
+                cmd.args(["--package", board.package(), "--bin", board.require_bin()?]);
+                cmd.args(&self.cargo_args);
+                self.exec(cmd)?;
+                Ok(None)
+            }
+            Platform::X86_64 => {
+                self.exec(self.x86_bootimager(board, "build"))?;
+                Ok(None)
+            }
+            Platform::Melpomene => {
+                let mut cmd = self.cargo("build");
+                cmd.args(["--package", board.package(), "--bin", "melpomene"]);
+                cmd.args(&self.cargo_args);
+                self.exec(cmd)?;
+                Ok(None)
+            }
+        }
+    }
+
+    /// Builds `board`, and writes it to the board.
+    pub fn flash(&self, board: &Board, opts: &FlashOptions) -> miette::Result<()> {
+        if board.platform != Platform::D1 && opts.to != Medium::Dram {
+            return Err(miette!(
+                "{} boards can't be written to {:?}",
+                board.platform,
+                opts.to
+            ));
+        }
+
+        match board.platform {
+            Platform::D1 => {
+                let image = self.build(board)?.expect("D1 builds produce an image");
+                self.d1_write(board, opts, &image)
+            }
+            Platform::Esp32c3 => self.exec(self.espflash(board, false)?),
+            Platform::X86_64 | Platform::Melpomene => Err(miette!(
+                help = "use `mnemos run` instead",
+                "{} boards can't be flashed",
+                board.platform,
+            )),
+        }
+    }
+
+    /// Builds `board`, and lays it out in a SPI NOR image at `output`, or
+    /// next to the kernel image if `output` is `None`. Returns the path to
+    /// the SPI NOR image.
+    pub fn image(
+        &self,
+        board: &Board,
+        opts: &ImageOptions,
+        output: Option<&Path>,
+    ) -> miette::Result<PathBuf> {
+        if board.platform != Platform::D1 {
+            return Err(miette!(
+                "{} boards don't boot from SPI NOR images",
+                board.platform
+            ));
+        }
+        let kernel = self.build(board)?.expect("D1 builds produce an image");
+        let output = match output {
+            Some(output) => output.to_path_buf(),
+            None => spi_nor_image(board, &kernel)?,
+        };
+        self.write_image(board, opts, &kernel, &output)?;
+        Ok(output)
+    }
+
+    /// Builds `board`, and runs it.
+    ///
+    /// For hardware boards, this flashes the board and starts it. For
+    /// emulated and simulated boards, this starts the emulator or simulator.
+    pub fn run(&self, board: &Board, opts: &RunOptions) -> miette::Result<()> {
+        match board.platform {
+            // writing the D1 over FEL also starts it.
+            Platform::D1 => {
+                self.flash(board, &opts.flash)?;
+                if opts.flash.to == Medium::Sd {
+                    eprintln!("insert the SD card into the board, and reset it");
+                }
+                match opts.serial {
+                    Some(ref port) => self.exec(self.crowtty(port)),
+                    None => {
+                        eprintln!(
+                            "to attach crowtty once the board has started, pass `--serial <PORT>`"
+                        );
+                        Ok(())
+                    }
+                }
+            }
+            Platform::Esp32c3 => self.exec(self.espflash(board, true)?),
+            Platform::X86_64 => self.exec(self.x86_bootimager(board, "run")),
+            Platform::Melpomene => {
+                let mut cmd = self.cargo("run");
+                cmd.args(["--package", board.package(), "--bin", "melpomene"]);
+                cmd.args(&self.cargo_args);
+                cmd.arg("--").args(&board.run_args);
+                self.exec(cmd)
+            }
+        }
+    }
+
+    /// Writes `image` to a D1, and starts it if it was written over FEL.
+    fn d1_write(&self, board: &Board, opts: &FlashOptions, image: &Path) -> miette::Result<()> {
+        let xfel = Xfel::new(self, &board.flash_args);
+        let flash = match opts.to {
+            Medium::Dram => {
+                xfel.detect()?;
+                xfel.init_ddr()?;
+                xfel.write(D1_LOAD_ADDR, image)?;
+                return xfel.exec(D1_LOAD_ADDR);
+            }
+            Medium::Sd => {
+                let device = opts
+                    .sd
+                    .as_deref()
+                    .expect("clap requires `--sd` with `--to sd`");
+                let bt0 = self.d1_bt0(board, &opts.image)?;
+                return self.write_sd(device, &bt0, image, board.payload_offset);
+            }
+            Medium::SpiNor => {
+                // SPI NOR images include everything from offset 0, so they
+                // can be written all at once.
+                let output = spi_nor_image(board, image)?;
+                self.write_image(board, &opts.image, image, &output)?;
+                xfel.detect()?;
+                xfel.write_flash(SpiFlash::Nor, 0, &output)?;
+                return xfel.reset();
+            }
+            Medium::SpiNand => SpiFlash::Nand,
+        };
+
+        // on SPI flash, the boot ROM looks for the bootloader at offset 0.
+        let bt0 = self.d1_bt0(board, &opts.image)?;
+        if !self.dry_run {
+            check_fits(&bt0, read(&bt0)?.len() as u64, board.payload_offset)?;
+        }
+        xfel.detect()?;
+        xfel.write_flash(flash, 0, &bt0)?;
+        xfel.write_flash(flash, board.payload_offset, image)?;
+        xfel.reset()
+    }
+
+    /// Returns the first-stage bootloader to write ahead of a D1 kernel.
+    fn d1_bt0(&self, board: &Board, opts: &ImageOptions) -> miette::Result<PathBuf> {
+        if let Some(ref bt0) = opts.bt0 {
+            return Ok(bt0.clone());
+        }
+        board
+            .bt0
+            .as_ref()
+            .map(|bt0| self.root.join(bt0))
+            .ok_or_else(|| {
+                miette!(
+                    help = "set `bt0` in the board's profile, or pass `--bt0 <PATH>`. \
+                    see platforms/allwinner-d1/README.md#boot-procedure",
+                    "writing a D1 kernel to persistent storage requires a first-stage bootloader",
+                )
+            })
+    }
+
+    /// Lays out the kernel at `kernel`, its first-stage bootloader, and the
+    /// config region's contents in a SPI NOR image, and writes it to
+    /// `output`.
+    fn write_image(
+        &self,
+        board: &Board,
+        opts: &ImageOptions,
+        kernel: &Path,
+        output: &Path,
+    ) -> miette::Result<()> {
+        let bt0 = self.d1_bt0(board, opts)?;
+        eprintln!(
+            "# write a SPI NOR image of {} and {} to {}",
+            bt0.display(),
+            kernel.display(),
+            output.display()
+        );
+        if self.dry_run {
+            return Ok(());
+        }
+
+        let spl = read(&bt0)?;
+        image::check_spl(&spl)
+            .with_context(|| format!("invalid first-stage bootloader {}", bt0.display()))?;
+        let kernel = read(kernel)?;
+        let map = board
+            .flash_map
+            .flash_map(board.payload_offset, kernel.len())?;
+        let config = opts.config.as_deref().map(read).transpose()?;
+        if let Some(ref config) = config {
+            if config.len() > map.config.len as usize {
+                return Err(miette!(
+                    help = "set a larger `config_size` in the board's `flash_map`",
+                    "the config file is {} bytes long, but the config region is {} bytes",
+                    config.len(),
+                    map.config.len,
+                ));
+            }
+        }
+        for (name, region) in [
+            ("kernel", map.kernel),
+            ("config", map.config),
+            ("key-value", map.kv),
+        ] {
+            eprintln!(
+                "#   {name:<9} region: {:#010x}..{:#010x}",
+                region.offset,
+                region.end()
+            );
+        }
+
+        fs::write(output, image::build(&map, &spl, &kernel, config.as_deref()))
+            .into_diagnostic()
+            .with_context(|| format!("failed to write {}", output.display()))
+    }
+
+    /// Writes `bt0` and `image` to an SD card, in the layout the D1's boot ROM
+    /// expects.
+    fn write_sd(
+        &self,
+        device: &Path,
+        bt0: &Path,
+        image: &Path,
+        payload_offset: u64,
+    ) -> miette::Result<()> {
+        let writes = [(D1_SD_BT0_OFFSET, bt0), (payload_offset, image)];
+        for (offset, path) in writes {
+            eprintln!(
+                "# write {} to {} at {offset:#x}",
+                path.display(),
+                device.display()
+            );
+        }
+        if self.dry_run {
+            return Ok(());
+        }
+
+        let [bt0_data, image_data] = writes.map(|(_, path)| {
+            let mut data = read(path)?;
+            data.resize(data.len().next_multiple_of(SD_BLOCK), 0);
+            Ok::<_, miette::Report>(data)
+        });
+        let (bt0_data, image_data) = (bt0_data?, image_data?);
+        check_fits(
+            bt0,
+            D1_SD_BT0_OFFSET + bt0_data.len() as u64,
+            payload_offset,
+        )?;
+
+        let mut sd = fs::OpenOptions::new()
+            .write(true)
+            .open(device)
+            .into_diagnostic()
+            .with_context(|| format!("failed to open {}", device.display()))?;
+        for (offset, data) in [(D1_SD_BT0_OFFSET, bt0_data), (payload_offset, image_data)] {
+            sd.seek(SeekFrom::Start(offset))
+                .and_then(|_| sd.write_all(&data))
+                .into_diagnostic()
+                .with_context(|| format!("failed to write {}", device.display()))?;
+        }
+        sd.sync_all()
+            .into_diagnostic()
+            .with_context(|| format!("failed to sync {}", device.display()))
+    }
+
+    fn crowtty(&self, port: &Path) -> Command {
+        // crowtty runs on the host, so it doesn't get the board's
+        // `cargo_args`.
+        let mut cmd = self.cargo("run");
+        cmd.args(["--package", "crowtty", "--bin", "crowtty"])
+            .args(["--", "serial"])
+            .arg(port);
+        cmd
+    }
+
+    fn espflash(&self, board: &Board, monitor: bool) -> miette::Result<Command> {
+        let mut cmd = self.cargo("espflash");
+        cmd.args(["--package", board.package(), "--bin", board.require_bin()?]);
+        cmd.args(&self.cargo_args);
+        if monitor {
+            cmd.arg("--monitor");
+        }
+        cmd.args(&board.flash_args);
+        Ok(cmd)
+    }
+
+    fn x86_bootimager(&self, board: &Board, subcmd: &str) -> Command {
+        // the bootimager is the x86_64 kernel crate's runner, so it isn't
+        // built with `--profile`.
+        let mut cmd = Command::new(&self.cargo);
+        cmd.current_dir(&self.root)
+            .args(["run", "--package", board.package()])
+            .args(["--target", "x86_64-unknown-none"])
+            .args(["--features", "bootloader_api"])
+            .args(&self.cargo_args)
+            .args(["--", subcmd])
+            .args(&board.run_args);
+        cmd
+    }
+
+    /// Returns a `cargo <subcmd>` command, using the configured profile.
+    fn cargo(&self, subcmd: &str) -> Command {
+        let mut cmd = Command::new(&self.cargo);
+        cmd.current_dir(&self.root)
+            .arg(subcmd)
+            .args(["--profile", &self.profile]);
+        cmd
+    }
+
+    pub(crate) fn exec(&self, mut cmd: Command) -> miette::Result<()> {
+        let display = display(&cmd);
+        eprintln!("$ {display}");
+        if self.dry_run {
+            return Ok(());
+        }
+
+        let status = cmd
+            .status()
+            .into_diagnostic()
+            .with_context(|| format!("failed to run `{display}`"))?;
+        if !status.success() {
+            return Err(miette!("`{display}` failed ({status})"));
+        }
+        Ok(())
+    }
+
+    /// Runs `cmd` and returns its standard output, or `None` in dry-run mode.
+    pub(crate) fn capture(&self, mut cmd: Command) -> miette::Result<Option<String>> {
+        let display = display(&cmd);
+        eprintln!("$ {display}");
+        if self.dry_run {
+            return Ok(None);
+        }
+
+        let output = cmd
+            .output()
+            .into_diagnostic()
+            .with_context(|| format!("failed to run `{display}`"))?;
+        let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(miette!(
+                "`{display}` failed ({}): {}{}",
+                output.status,
+                stdout.trim(),
+                stderr.trim(),
+            ));
+        }
+        Ok(Some(stdout))
+    }
+}
+
+/// Returns an error if the bootloader at `bt0`, ending at `end`, would
+/// overlap a kernel written at `payload_offset`.
+fn check_fits(bt0: &Path, end: u64, payload_offset: u64) -> miette::Result<()> {
+    if end > payload_offset {
+        return Err(miette!(
+            help = "set a larger `payload_offset` in the board's profile",
+            "{} ends at {end:#x}, which overlaps the kernel at {payload_offset:#x}",
+            bt0.display(),
+        ));
+    }
+    Ok(())
+}
+
+/// Returns where the SPI NOR image for the kernel at `kernel` is written by
+/// default.
+fn spi_nor_image(board: &Board, kernel: &Path) -> miette::Result<PathBuf> {
+    Ok(kernel.with_file_name(format!("mnemos-{}-spi-nor.bin", board.require_bin()?)))
+}
+
+fn read(path: &Path) -> miette::Result<Vec<u8>> {
+    fs::read(path)
+        .into_diagnostic()
+        .with_context(|| format!("failed to read {}", path.display()))
+}
+
+fn display(cmd: &Command) -> String {
+    let mut display = cmd.get_program().to_string_lossy().into_owned();
+    for arg in cmd.get_args() {
+        display.push(' ');
+        display.push_str(&arg.to_string_lossy());
+    }
+    display
+}
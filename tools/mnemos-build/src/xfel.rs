@@ -0,0 +1,171 @@
+//! Driving an Allwinner D1 in FEL mode, using [`xfel`].
+//!
+//! In FEL mode, the D1's boot ROM presents itself as a USB device that can
+//! initialize DRAM, read and write memory, program SPI flash, and jump to
+//! code. See the [D1 README] for details on entering FEL mode.
+//!
+//! [`xfel`]: https://xboot.org/xfel/#/
+//! [D1 README]: ../../platforms/allwinner-d1/README.md
+
+use std::{fmt, path::Path, process::Command};
+
+use miette::miette;
+
+use crate::platform::Runner;
+
+/// The FEL chip ID reported by the Allwinner D1 and F133.
+pub const D1_CHIP_ID: u32 = 0x0018_5900;
+
+/// Where the D1 kernel is loaded in DRAM, and where execution starts.
+pub const D1_LOAD_ADDR: u32 = 0x4000_0000;
+
+const FEL_HELP: &str = "\
+    is the board connected and in FEL mode?\n\
+    - on the MangoPi MQ Pro, connect the USB-C port labeled \"OTG\", not \"HOST\"\n\
+    - hold the FEL button while resetting or plugging in the board\n\
+    - after a board has been loaded once, unplug and replug it to re-enter FEL mode";
+
+/// Runs `xfel` commands against a D1 in FEL mode.
+pub struct Xfel<'a> {
+    runner: &'a Runner,
+    /// Extra arguments passed to every `xfel` invocation.
+    args: &'a [String],
+}
+
+/// A chip found in FEL mode.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chip {
+    /// The chip's FEL ID.
+    pub id: u32,
+    /// The chip's name, as reported by `xfel`.
+    pub name: String,
+}
+
+/// A SPI flash chip that `xfel` can program.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SpiFlash {
+    Nor,
+    Nand,
+}
+
+// === impl Xfel ===
+
+impl<'a> Xfel<'a> {
+    pub fn new(runner: &'a Runner, args: &'a [String]) -> Self {
+        Self { runner, args }
+    }
+
+    /// Finds the D1 in FEL mode, returning an error if no chip is connected,
+    /// or if the connected chip is not a D1.
+    ///
+    /// In dry-run mode, this assumes that a D1 is connected.
+    pub fn detect(&self) -> miette::Result<Chip> {
+        let output = self
+            .runner
+            .capture(self.xfel(["version"]))
+            .map_err(|error| miette!(help = FEL_HELP, "no FEL device found: {error}"))?;
+        let Some(output) = output else {
+            return Ok(Chip {
+                id: D1_CHIP_ID,
+                name: "D1/F133".to_string(),
+            });
+        };
+
+        let chip = Chip::parse(&output).ok_or_else(|| {
+            miette!(
+                help = FEL_HELP,
+                "couldn't find a chip ID in `xfel version` output: {:?}",
+                output.trim(),
+            )
+        })?;
+        if chip.id != D1_CHIP_ID {
+            return Err(miette!(
+                "found {chip} in FEL mode, but expected an Allwinner D1 ({D1_CHIP_ID:#010x})"
+            ));
+        }
+
+        eprintln!("found {chip} in FEL mode");
+        Ok(chip)
+    }
+
+    /// Initializes the D1's DRAM controller.
+    pub fn init_ddr(&self) -> miette::Result<()> {
+        self.runner.exec(self.xfel(["ddr", "d1"]))
+    }
+
+    /// Writes the file at `path` to memory, starting at `addr`.
+    pub fn write(&self, addr: u32, path: &Path) -> miette::Result<()> {
+        let mut cmd = self.xfel(["write".to_string(), format!("{addr:#x}")]);
+        cmd.arg(path);
+        self.runner.exec(cmd)
+    }
+
+    /// Jumps to `addr`.
+    pub fn exec(&self, addr: u32) -> miette::Result<()> {
+        self.runner
+            .exec(self.xfel(["exec".to_string(), format!("{addr:#x}")]))
+    }
+
+    /// Resets the D1 using its watchdog, so that it boots from persistent
+    /// storage.
+    pub fn reset(&self) -> miette::Result<()> {
+        self.runner.exec(self.xfel(["reset"]))
+    }
+
+    /// Writes the file at `path` to SPI flash, starting at `offset`.
+    pub fn write_flash(&self, flash: SpiFlash, offset: u64, path: &Path) -> miette::Result<()> {
+        let mut cmd = self.xfel([
+            flash.command().to_string(),
+            "write".to_string(),
+            format!("{offset:#x}"),
+        ]);
+        cmd.arg(path);
+        self.runner.exec(cmd)
+    }
+
+    fn xfel<S: AsRef<std::ffi::OsStr>>(&self, args: impl IntoIterator<Item = S>) -> Command {
+        let mut cmd = Command::new("xfel");
+        cmd.args(args).args(self.args);
+        cmd
+    }
+}
+
+// === impl Chip ===
+
+impl Chip {
+    /// Parses the output of `xfel version`, which looks like:
+    ///
+    /// ```text
+    /// AWUSBFEX ID=0x00185900(D1/F133) dflag=0x44 dlength=0x08 scratchpad=0x00045000
+    /// ```
+    fn parse(output: &str) -> Option<Self> {
+        let (_, rest) = output.split_once("ID=0x")?;
+        let end = rest
+            .find(|c: char| !c.is_ascii_hexdigit())
+            .unwrap_or(rest.len());
+        let id = u32::from_str_radix(&rest[..end], 16).ok()?;
+        let name = rest[end..]
+            .strip_prefix('(')
+            .and_then(|rest| rest.split_once(')'))
+            .map(|(name, _)| name.to_string())
+            .unwrap_or_else(|| "unknown chip".to_string());
+        Some(Self { id, name })
+    }
+}
+
+impl fmt::Display for Chip {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({:#010x})", self.name, self.id)
+    }
+}
+
+// === impl SpiFlash ===
+
+impl SpiFlash {
+    fn command(&self) -> &'static str {
+        match self {
+            Self::Nor => "spinor",
+            Self::Nand => "spinand",
+        }
+    }
+}
@@ -0,0 +1,380 @@
+#![doc = include_str!("../README.md")]
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use clap::Parser;
+use miette::{miette, Context, IntoDiagnostic, Result};
+use mnemos_config::{buildtime, runtime, MnemosConfig};
+use serde::{de::DeserializeOwned, Serialize};
+use toml::Value;
+
+#[derive(Parser)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    /// the platform whose configuration type configs are checked against.
+    #[arg(long, short, value_enum)]
+    platform: Platform,
+
+    #[command(subcommand)]
+    cmd: Command,
+
+    /// override a value in each config, as `KEY=VALUE`.
+    ///
+    /// `KEY` is a dotted path, such as `services.alarm.enabled`, and `VALUE`
+    /// is a TOML value. Values which aren't valid TOML are treated as
+    /// strings. Overrides are applied before the config is checked.
+    #[arg(long = "set", global = true, value_name = "KEY=VALUE")]
+    overrides: Vec<Override>,
+}
+
+#[derive(clap::Subcommand)]
+enum Command {
+    /// check that a config is valid for the platform.
+    Validate { config: PathBuf },
+    /// print a config's effective values, with all defaults filled in.
+    Show { config: PathBuf },
+    /// convert a config between TOML and postcard.
+    ///
+    /// the formats are chosen based on the files' extensions: `.postcard`
+    /// files are postcard, and anything else is TOML.
+    Convert { input: PathBuf, output: PathBuf },
+    /// print the settings whose effective values differ between two configs.
+    Diff { old: PathBuf, new: PathBuf },
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, clap::ValueEnum)]
+enum Platform {
+    /// the Melpomene desktop simulator.
+    Melpomene,
+    /// Allwinner D1 boards.
+    D1,
+}
+
+/// A `--set KEY=VALUE` override.
+#[derive(Clone, Debug)]
+struct Override {
+    path: Vec<String>,
+    value: Value,
+}
+
+/// A setting whose effective value differs between two configs.
+#[derive(Debug, PartialEq)]
+struct Change {
+    path: String,
+    old: Option<Value>,
+    new: Option<Value>,
+}
+
+fn main() -> Result<()> {
+    let Args {
+        platform,
+        cmd,
+        overrides,
+    } = Args::parse();
+
+    // `serial-trace` adds settings to the kernel's config, so postcard
+    // configs only match if the feature is the same.
+    if platform.serial_trace() != cfg!(feature = "serial-trace") {
+        let enabled = |yes| if yes { "enabled" } else { "disabled" };
+        eprintln!(
+            "warning: the kernel's `serial-trace` feature is {} by {platform}, but {} in this \
+            tool, so postcard configs may not match the platform's",
+            enabled(platform.serial_trace()),
+            enabled(cfg!(feature = "serial-trace")),
+        );
+    }
+
+    match platform {
+        Platform::Melpomene => run::<melpo_config::PlatformConfig>(cmd, &overrides),
+        Platform::D1 => run::<d1_config::PlatformConfig>(cmd, &overrides),
+    }
+}
+
+fn run<P>(cmd: Command, overrides: &[Override]) -> Result<()>
+where
+    P: Serialize + DeserializeOwned + 'static,
+{
+    match cmd {
+        Command::Validate { config } => {
+            load::<P>(&config, overrides)?;
+            println!("{} is valid", config.display());
+        }
+        Command::Show { config } => {
+            let config = effective(&load::<P>(&config, overrides)?)?;
+            print!("{}", toml::to_string_pretty(&config).into_diagnostic()?);
+        }
+        Command::Convert { input, output } => {
+            let config = load::<P>(&input, overrides)?;
+            let bytes = if is_postcard(&output) {
+                buildtime::to_postcard(&config)?
+            } else {
+                toml::to_string_pretty(&effective(&config)?)
+                    .into_diagnostic()?
+                    .into_bytes()
+            };
+            std::fs::write(&output, bytes)
+                .into_diagnostic()
+                .wrap_err_with(|| format!("failed to write {}", output.display()))?;
+        }
+        Command::Diff { old, new } => {
+            let old_config = effective(&load::<P>(&old, overrides)?)?;
+            let new_config = effective(&load::<P>(&new, overrides)?)?;
+            let changes = diff(&old_config, &new_config);
+            if changes.is_empty() {
+                println!("{} and {} are the same", old.display(), new.display());
+            }
+            for change in changes {
+                println!("{change}");
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Loads the config at `path`, and applies `overrides` to it.
+fn load<P>(path: &Path, overrides: &[Override]) -> Result<MnemosConfig<P>>
+where
+    P: Serialize + DeserializeOwned + 'static,
+{
+    let config = if is_postcard(path) {
+        let bytes = std::fs::read(path)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("failed to read {}", path.display()))?;
+        runtime::from_postcard(&bytes).map_err(|runtime::Error::Postcard(error)| {
+            miette!("{} is not a valid postcard config: {error}", path.display())
+        })?
+    } else {
+        let text = std::fs::read_to_string(path)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("failed to read {}", path.display()))?;
+        buildtime::from_toml(&text)
+            .wrap_err_with(|| format!("{} is not a valid config", path.display()))?
+    };
+    if overrides.is_empty() {
+        return Ok(config);
+    }
+
+    let mut value = effective(&config)?;
+    for o in overrides {
+        o.apply(&mut value)?;
+    }
+    value
+        .try_into()
+        .into_diagnostic()
+        .wrap_err_with(|| format!("{} is not valid with overrides", path.display()))
+}
+
+/// Returns the effective values of `config`, as TOML.
+fn effective<P: Serialize>(config: &MnemosConfig<P>) -> Result<Value> {
+    Value::try_from(config)
+        .into_diagnostic()
+        .wrap_err("failed to convert config to TOML")
+}
+
+fn is_postcard(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "postcard")
+}
+
+/// Returns every setting whose value differs between `old` and `new`, in
+/// order.
+fn diff(old: &Value, new: &Value) -> Vec<Change> {
+    fn diff_into(path: &str, old: Option<&Value>, new: Option<&Value>, changes: &mut Vec<Change>) {
+        if let (Some(Value::Table(old)), Some(Value::Table(new))) = (old, new) {
+            let mut keys = old.keys().chain(new.keys()).collect::<Vec<_>>();
+            keys.sort();
+            keys.dedup();
+            for key in keys {
+                let path = if path.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{path}.{key}")
+                };
+                diff_into(&path, old.get(key), new.get(key), changes);
+            }
+        } else if old != new {
+            changes.push(Change {
+                path: path.to_string(),
+                old: old.cloned(),
+                new: new.cloned(),
+            });
+        }
+    }
+
+    let mut changes = Vec::new();
+    diff_into("", Some(old), Some(new), &mut changes);
+    changes
+}
+
+// === impl Platform ===
+
+impl Platform {
+    /// Returns `true` if the platform enables the kernel's `serial-trace`
+    /// feature by default.
+    fn serial_trace(self) -> bool {
+        match self {
+            Self::Melpomene => false,
+            Self::D1 => true,
+        }
+    }
+}
+
+impl fmt::Display for Platform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Melpomene => f.write_str("melpomene"),
+            Self::D1 => f.write_str("d1"),
+        }
+    }
+}
+
+// === impl Override ===
+
+impl Override {
+    fn apply(&self, root: &mut Value) -> Result<()> {
+        let (key, parents) = self.path.split_last().expect("paths are never empty");
+        let mut table = root
+            .as_table_mut()
+            .ok_or_else(|| miette!("config is not a table"))?;
+        for (i, parent) in parents.iter().enumerate() {
+            table = table
+                .entry(parent.clone())
+                .or_insert_with(|| Value::Table(Default::default()))
+                .as_table_mut()
+                .ok_or_else(|| {
+                    miette!(
+                        "can't set `{}`: `{}` is not a table",
+                        self.path.join("."),
+                        self.path[..=i].join(".")
+                    )
+                })?;
+        }
+        table.insert(key.clone(), self.value.clone());
+        Ok(())
+    }
+}
+
+impl FromStr for Override {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (key, value) = s
+            .split_once('=')
+            .ok_or_else(|| format!("expected `KEY=VALUE`, got `{s}`"))?;
+        let path = key
+            .trim()
+            .split('.')
+            .map(str::to_string)
+            .collect::<Vec<_>>();
+        if path.iter().any(String::is_empty) {
+            return Err(format!("invalid key `{key}`"));
+        }
+        let value = value.trim();
+        let value = toml::from_str::<toml::Table>(&format!("value = {value}"))
+            .ok()
+            .and_then(|mut table| table.remove("value"))
+            .unwrap_or_else(|| Value::String(value.to_string()));
+        Ok(Self { path, value })
+    }
+}
+
+// === impl Change ===
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Self { path, old, new } = self;
+        match (old, new) {
+            (Some(old), Some(new)) => write!(f, "~ {path}: {old} -> {new}"),
+            (Some(old), None) => write!(f, "- {path} = {old}"),
+            (None, Some(new)) => write!(f, "+ {path} = {new}"),
+            (None, None) => unreachable!("a change must have an old or new value"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn toml(s: &str) -> Value {
+        Value::Table(toml::from_str(s).unwrap())
+    }
+
+    #[test]
+    fn diffs_nested_tables() {
+        let old = toml(
+            r#"
+            [services.alarm]
+            enabled = true
+            [services.clipboard]
+            enabled = true
+            max_len = 256
+            "#,
+        );
+        let new = toml(
+            r#"
+            [services.alarm]
+            enabled = false
+            [services.clipboard]
+            enabled = true
+            [services.watchdog]
+            enabled = true
+            "#,
+        );
+        let changes = diff(&old, &new)
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            changes,
+            [
+                "~ services.alarm.enabled: true -> false",
+                "- services.clipboard.max_len = 256",
+                "+ services.watchdog = { enabled = true }",
+            ]
+        );
+        assert!(diff(&old, &old).is_empty());
+    }
+
+    #[test]
+    fn overrides() {
+        let mut config = toml(
+            r#"
+            [services.alarm]
+            enabled = true
+            "#,
+        );
+        for o in [
+            "services.alarm.enabled=false",
+            "services.clipboard.max_len = 512",
+            "platform.name=mq-pro",
+        ] {
+            o.parse::<Override>().unwrap().apply(&mut config).unwrap();
+        }
+        assert_eq!(
+            config,
+            toml(
+                r#"
+                platform = { name = "mq-pro" }
+                [services.alarm]
+                enabled = false
+                [services.clipboard]
+                max_len = 512
+                "#,
+            )
+        );
+
+        let error = "services.alarm.enabled.nope=1"
+            .parse::<Override>()
+            .unwrap()
+            .apply(&mut config)
+            .unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("`services.alarm.enabled` is not a table"));
+        assert!("services..alarm=1".parse::<Override>().is_err());
+        assert!("services.alarm".parse::<Override>().is_err());
+    }
+}
@@ -0,0 +1,398 @@
+use std::{
+    io::{ErrorKind, Read, Write},
+    net::{IpAddr, Ipv4Addr, SocketAddr, TcpStream},
+    time::Duration,
+};
+
+use clap::Parser;
+use miette::{Context, IntoDiagnostic};
+use mnemos_dbg_proto::{AllocTagEntry, BufferEntry, ChannelEntry, Request, Response, StepState};
+use sermux_proto::WellKnown;
+
+#[derive(Parser)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    #[command(subcommand)]
+    cmd: Command,
+
+    /// IP address that crowtty is listening on.
+    #[arg(long, global = true, default_value_t = IpAddr::V4(Ipv4Addr::LOCALHOST))]
+    ip: IpAddr,
+
+    /// host TCP port that the target's debug agent is mapped to.
+    ///
+    /// crowtty maps SerMux port `n` to TCP port `n + tcp-port-base`, so with
+    /// the default settings, this is the debug agent's well-known port plus
+    /// 10000.
+    #[arg(
+        short,
+        long,
+        global = true,
+        default_value_t = 10_000 + WellKnown::Debug as u16,
+    )]
+    port: u16,
+
+    /// how long to wait for the target to answer a request, in milliseconds.
+    #[arg(long, global = true, default_value_t = 2_000)]
+    timeout_ms: u64,
+}
+
+#[derive(Copy, Clone, clap::Subcommand)]
+enum Command {
+    /// list every service in the driver registry.
+    Registry,
+    /// show heap statistics.
+    Heap,
+    /// show scheduler task counts and boot step states.
+    Tasks,
+    /// show how full each service's connection and request queues are, and
+    /// have been.
+    Channels,
+    /// show live heap allocations, grouped by where they were allocated.
+    ///
+    /// this requires a kernel built with the `alloc-tags` feature.
+    Allocs,
+    /// show the devices found by the boot-time I²C bus scan.
+    ///
+    /// this requires a kernel configured with `i2c_scan.enabled = true`.
+    I2c,
+    /// show how the kernel is polling userspace processes' rings.
+    Rings,
+    /// show how full the SerMux's buffers are.
+    Ports,
+    /// show everything.
+    All,
+}
+
+/// A connection to a target's debug agent.
+struct Agent {
+    stream: TcpStream,
+    carry: Vec<u8>,
+}
+
+fn main() -> miette::Result<()> {
+    let Args {
+        cmd,
+        ip,
+        port,
+        timeout_ms,
+    } = Args::parse();
+    let addr = SocketAddr::new(ip, port);
+    let stream = TcpStream::connect(addr)
+        .into_diagnostic()
+        .with_context(|| format!("failed to connect to {addr}; is crowtty running?"))?;
+    stream
+        .set_read_timeout(Some(Duration::from_millis(timeout_ms)))
+        .into_diagnostic()?;
+    let mut agent = Agent {
+        stream,
+        carry: Vec::new(),
+    };
+
+    match cmd {
+        Command::Registry => registry(&mut agent),
+        Command::Heap => heap(&mut agent),
+        Command::Tasks => tasks(&mut agent),
+        Command::Channels => channels(&mut agent),
+        Command::Allocs => allocs(&mut agent),
+        Command::I2c => i2c(&mut agent),
+        Command::Rings => rings(&mut agent),
+        Command::Ports => ports(&mut agent),
+        Command::All => {
+            registry(&mut agent)?;
+            println!();
+            heap(&mut agent)?;
+            println!();
+            tasks(&mut agent)?;
+            println!();
+            channels(&mut agent)?;
+            println!();
+            allocs(&mut agent)?;
+            println!();
+            i2c(&mut agent)?;
+            println!();
+            rings(&mut agent)?;
+            println!();
+            ports(&mut agent)
+        }
+    }
+}
+
+fn registry(agent: &mut Agent) -> miette::Result<()> {
+    println!("REGISTRY");
+    println!("  {:>4}  {:<36}  USERSPACE", "ID", "UUID");
+    agent.request(Request::Registry, |rsp| {
+        if let Response::Service(svc) = rsp {
+            let user = if svc.userspace { "yes" } else { "no" };
+            println!("  {:>4}  {:<36}  {user}", svc.service_id, svc.uuid);
+        }
+    })?;
+    Ok(())
+}
+
+fn heap(agent: &mut Agent) -> miette::Result<()> {
+    println!("HEAP");
+    let answered = agent.request(Request::Heap, |rsp| {
+        if let Response::Heap(heap) = rsp {
+            println!("  total:     {:>10} B", heap.total_bytes);
+            println!("  allocated: {:>10} B", heap.allocated_bytes);
+            println!("  free:      {:>10} B", heap.free_bytes());
+            println!("  live allocations: {}", heap.live_alloc_count());
+            println!("  allocations:      {}", heap.alloc_success_count);
+            println!("  deallocations:    {}", heap.dealloc_count);
+            println!("  failed (OOM):     {}", heap.alloc_oom_count);
+        }
+    })?;
+    if !answered {
+        println!("  heap statistics are not available on this target");
+    }
+    Ok(())
+}
+
+fn tasks(agent: &mut Agent) -> miette::Result<()> {
+    println!("TASKS");
+    let mut printed_header = false;
+    agent.request(Request::Tasks, |rsp| match rsp {
+        Response::Tasks(counts) => {
+            println!("  spawned:   {}", counts.spawned);
+            println!("  completed: {}", counts.completed);
+            println!("  live:      {}", counts.live());
+        }
+        Response::BootStep { name, state } => {
+            if !printed_header {
+                println!();
+                println!("BOOT STEPS");
+                println!("  {:<24}  {:<10}  {:>10}", "STEP", "STATE", "TIME");
+                printed_header = true;
+            }
+            let (state, detail) = match state {
+                StepState::Pending => ("pending", String::new()),
+                StepState::Running { elapsed_us } => ("running", fmt_us(elapsed_us)),
+                StepState::Complete { elapsed_us } => ("complete", fmt_us(elapsed_us)),
+                StepState::Failed { elapsed_us } => ("FAILED", fmt_us(elapsed_us)),
+                StepState::Blocked { dependency } => ("BLOCKED", format!("(on {dependency})")),
+            };
+            println!("  {name:<24}  {state:<10}  {detail:>10}");
+        }
+        Response::BootDependency { dependency, .. } => {
+            println!("    after {dependency}");
+        }
+        _ => {}
+    })?;
+    Ok(())
+}
+
+fn channels(agent: &mut Agent) -> miette::Result<()> {
+    println!("CONNECTION QUEUES");
+    println!(
+        "  {:<36}  {:>5}  {:>4}  {:>9}",
+        "SERVICE", "DEPTH", "PEAK", "OVERFLOWS"
+    );
+    let mut req_queues = Vec::new();
+    agent.request(Request::Channels, |rsp| match rsp {
+        Response::Channel(chan) => print_channel(&chan),
+        Response::RequestQueue(chan) => req_queues.push(chan),
+        _ => {}
+    })?;
+
+    println!();
+    println!("REQUEST QUEUES");
+    if req_queues.is_empty() {
+        println!("  no services are serving requests from a shared queue");
+        return Ok(());
+    }
+    println!(
+        "  {:<36}  {:>5}  {:>4}  {:>9}",
+        "SERVICE", "DEPTH", "PEAK", "OVERFLOWS"
+    );
+    for chan in &req_queues {
+        print_channel(chan);
+    }
+    Ok(())
+}
+
+fn print_channel(chan: &ChannelEntry) {
+    let full = if chan.len >= chan.capacity {
+        " (FULL)"
+    } else {
+        ""
+    };
+    println!(
+        "  {:<36}  {:>2}/{:<2}  {:>4}  {:>9}{full}",
+        chan.uuid, chan.len, chan.capacity, chan.high_water, chan.overflows
+    );
+}
+
+fn allocs(agent: &mut Agent) -> miette::Result<()> {
+    println!("LIVE ALLOCATIONS");
+    let mut tags = Vec::new();
+    let mut untracked = 0;
+    let answered = agent.request(Request::Allocations, |rsp| match rsp {
+        Response::AllocTag(AllocTagEntry {
+            location,
+            scope,
+            live_count,
+            live_bytes,
+        }) => {
+            let location = location.map_or_else(
+                || "<unknown>".to_string(),
+                |loc| format!("{}:{}:{}", loc.file, loc.line, loc.column),
+            );
+            let scope = scope.unwrap_or("-").to_string();
+            tags.push((live_bytes, live_count, scope, location));
+        }
+        Response::UntrackedAllocs { count } => untracked = count,
+        _ => {}
+    })?;
+    if !answered {
+        println!("  allocation tags are not available on this target");
+        println!("  (was the kernel built with the `alloc-tags` feature?)");
+        return Ok(());
+    }
+
+    // biggest first.
+    tags.sort_unstable_by(|a, b| b.cmp(a));
+    println!(
+        "  {:>10}  {:>6}  {:<24}  LOCATION",
+        "BYTES", "COUNT", "SCOPE"
+    );
+    for (bytes, count, scope, location) in &tags {
+        println!("  {bytes:>10}  {count:>6}  {scope:<24}  {location}");
+    }
+    let total_bytes: usize = tags.iter().map(|(bytes, ..)| bytes).sum();
+    let total_count: usize = tags.iter().map(|(_, count, ..)| count).sum();
+    println!("  {total_bytes:>10}  {total_count:>6}  total");
+    if untracked > 0 {
+        println!("  {untracked} allocations could not be tracked");
+    }
+    Ok(())
+}
+
+fn i2c(agent: &mut Agent) -> miette::Result<()> {
+    println!("I2C DEVICES");
+    let mut found = 0;
+    let answered = agent.request(Request::I2cDevices, |rsp| {
+        if let Response::I2cDevice { addr } = rsp {
+            println!("  {addr:#04x}");
+            found += 1;
+        }
+    })?;
+    if !answered {
+        println!("  no I2C bus scan has completed on this target");
+        println!("  (is `i2c_scan.enabled` set in the kernel's config?)");
+    } else if found == 0 {
+        println!("  no devices found");
+    }
+    Ok(())
+}
+
+fn rings(agent: &mut Agent) -> miette::Result<()> {
+    println!("USERSPACE RINGS");
+    let mut printed_header = false;
+    let answered = agent.request(Request::Rings, |rsp| match rsp {
+        Response::Rings(stats) => {
+            println!("  budget:    {} requests/tick", stats.budget);
+            println!("  polls:     {}", stats.polls);
+            println!("  exhausted: {}", stats.exhausted);
+        }
+        Response::Process(process) => {
+            if !printed_header {
+                println!();
+                println!(
+                    "  {:>4}  {:>10}  {:>9}  {:>13}",
+                    "PID", "REQUESTS", "MALFORMED", "BACKPRESSURED"
+                );
+                printed_header = true;
+            }
+            println!(
+                "  {:>4}  {:>10}  {:>9}  {:>13}",
+                process.id, process.requests, process.malformed, process.backpressured
+            );
+        }
+        _ => {}
+    })?;
+    if !answered {
+        println!("  ring statistics are not available on this target");
+    } else if !printed_header {
+        println!("  no processes registered");
+    }
+    Ok(())
+}
+
+fn ports(agent: &mut Agent) -> miette::Result<()> {
+    println!("SERMUX BUFFERS");
+    println!("  {:<8}  {:>13}", "PORT", "BYTES");
+    let answered = agent.request(Request::Ports, |rsp| match rsp {
+        Response::SermuxOutgoing(buf) => print_buffer("outgoing", &buf),
+        Response::SermuxPort { port, incoming } => print_buffer(&port.to_string(), &incoming),
+        _ => {}
+    })?;
+    if !answered {
+        println!("  the SerMux service is not running on this target");
+    }
+    Ok(())
+}
+
+fn print_buffer(name: &str, buf: &BufferEntry) {
+    let full = if buf.committed >= buf.capacity {
+        " (FULL)"
+    } else {
+        ""
+    };
+    println!(
+        "  {name:<8}  {:>6}/{:<6}{full}",
+        buf.committed, buf.capacity
+    );
+}
+
+fn fmt_us(us: u64) -> String {
+    match us {
+        us if us >= 1_000_000 => format!("{:.2}s", us as f64 / 1_000_000.0),
+        us if us >= 1_000 => format!("{:.2}ms", us as f64 / 1_000.0),
+        us => format!("{us}us"),
+    }
+}
+
+// === impl Agent ===
+
+impl Agent {
+    /// Sends `req` to the debug agent, calling `f` with every response until
+    /// the end of the answer.
+    ///
+    /// Returns `false` if the target does not support the request.
+    fn request(&mut self, req: Request, mut f: impl FnMut(Response<'_>)) -> miette::Result<bool> {
+        let frame = postcard::to_stdvec_cobs(&req).into_diagnostic()?;
+        self.stream
+            .write_all(&frame)
+            .into_diagnostic()
+            .context("failed to send request")?;
+
+        loop {
+            while let Some(pos) = self.carry.iter().position(|&b| b == 0) {
+                let remainder = self.carry.split_off(pos + 1);
+                let mut frame = std::mem::replace(&mut self.carry, remainder);
+                let rsp = postcard::from_bytes_cobs::<Response<'_>>(&mut frame)
+                    .into_diagnostic()
+                    .context("failed to decode response")?;
+                match rsp {
+                    Response::Done => return Ok(true),
+                    Response::Unsupported => return Ok(false),
+                    rsp => f(rsp),
+                }
+            }
+
+            let mut buf = [0u8; 256];
+            let used = match self.stream.read(&mut buf) {
+                Ok(0) => miette::bail!("connection closed by crowtty"),
+                Ok(used) => used,
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                    miette::bail!(
+                        "timed out waiting for the target to answer; is the debug agent enabled?"
+                    )
+                }
+                Err(e) => return Err(e).into_diagnostic().context("read failed"),
+            };
+            self.carry.extend_from_slice(&buf[..used]);
+        }
+    }
+}
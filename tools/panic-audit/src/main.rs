@@ -0,0 +1,598 @@
+//! Checks that functions in a linked ELF image can't reach the panic handler.
+//!
+//! The call graph is recovered from the image's relocations, so the image
+//! must be linked with `--emit-relocs`. See the README for details.
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fmt,
+    process::ExitCode,
+};
+
+const SHT_SYMTAB: u32 = 2;
+const SHT_RELA: u32 = 4;
+const SHT_REL: u32 = 9;
+const SHF_EXECINSTR: u64 = 0x4;
+const STT_FUNC: u8 = 2;
+const STT_SECTION: u8 = 3;
+
+const EM_X86_64: u16 = 62;
+const R_X86_64_PC32: u64 = 2;
+const R_X86_64_PLT32: u64 = 4;
+
+/// The symbol name of the `#[panic_handler]` function.
+const PANIC_HANDLER: &str = "rust_begin_unwind";
+/// Every function in this module panics.
+const PANICKING: &str = "core::panicking";
+
+fn main() -> ExitCode {
+    let mut args = std::env::args().skip(1);
+    let (Some(image), Some(roots), None) = (args.next(), args.next(), args.next()) else {
+        eprintln!("usage: panic-audit <IMAGE.elf> <ROOTS.txt>");
+        return ExitCode::FAILURE;
+    };
+
+    let result = std::fs::read_to_string(&roots)
+        .map_err(|e| Error(format!("failed to read {roots}: {e}")))
+        .and_then(|roots| Config::parse(&roots))
+        .and_then(|config| {
+            let elf =
+                std::fs::read(&image).map_err(|e| Error(format!("failed to read {image}: {e}")))?;
+            audit(&elf, &config)
+        });
+
+    match result {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::FAILURE,
+        Err(error) => {
+            eprintln!("panic-audit: {error}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+struct Error(String);
+
+macro_rules! bail {
+    ($($arg:tt)*) => {
+        return Err(Error(format!($($arg)*)))
+    };
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// The parsed roots file.
+#[derive(Debug, Default)]
+struct Config {
+    roots: Vec<String>,
+    allow: Vec<String>,
+}
+
+/// A function in the image.
+struct Func {
+    addr: u64,
+    end: u64,
+    name: String,
+}
+
+/// The image's call graph.
+struct Graph {
+    funcs: Vec<Func>,
+    /// Callees of each function, by index into `funcs`.
+    calls: Vec<Vec<usize>>,
+}
+
+/// Returns `Ok(true)` if none of the roots can panic.
+fn audit(bytes: &[u8], config: &Config) -> Result<bool, Error> {
+    let graph = Graph::from_elf(&Elf::parse(bytes)?)?;
+    if graph.calls.iter().all(Vec::is_empty) {
+        bail!("image contains no relocations; was it linked with `--emit-relocs`?");
+    }
+
+    let mut ok = true;
+    for root in &config.roots {
+        let starts: Vec<usize> = (0..graph.funcs.len())
+            .filter(|&i| path_matches(&graph.funcs[i].name, root))
+            .collect();
+        if starts.is_empty() {
+            println!("{root}: no matching functions (was it inlined?)");
+            ok = false;
+            continue;
+        }
+
+        match graph.find_panic(&starts, config) {
+            Some(chain) => {
+                ok = false;
+                println!("{root}: can panic:");
+                for (n, &i) in chain.iter().enumerate() {
+                    let arrow = if n == 0 { "   " } else { "-> " };
+                    println!("    {arrow}{}", graph.funcs[i].name);
+                }
+            }
+            None => println!("{root}: ok ({} functions)", starts.len()),
+        }
+    }
+
+    Ok(ok)
+}
+
+// === impl Config ===
+
+impl Config {
+    fn parse(text: &str) -> Result<Self, Error> {
+        let mut config = Self::default();
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match line.split_once(char::is_whitespace) {
+                Some(("root", path)) => config.roots.push(path.trim().to_string()),
+                Some(("allow", path)) => config.allow.push(path.trim().to_string()),
+                _ => bail!("line {}: expected `root <PATH>` or `allow <PATH>`", n + 1),
+            }
+        }
+        if config.roots.is_empty() {
+            bail!("no roots to check");
+        }
+        Ok(config)
+    }
+
+    fn is_allowed(&self, name: &str) -> bool {
+        self.allow.iter().any(|path| path_matches(name, path))
+    }
+}
+
+/// Returns `true` if `name` is `path`, or an item nested inside `path`.
+fn path_matches(name: &str, path: &str) -> bool {
+    match name.strip_prefix(path) {
+        Some(rest) => rest.is_empty() || rest.starts_with("::"),
+        None => false,
+    }
+}
+
+fn is_panic(name: &str) -> bool {
+    name == PANIC_HANDLER || path_matches(name, PANICKING)
+}
+
+// === impl Graph ===
+
+impl Graph {
+    fn from_elf(elf: &Elf<'_>) -> Result<Self, Error> {
+        let sections = elf.sections()?;
+        let Some(symtab) = sections.iter().find(|s| s.kind == SHT_SYMTAB) else {
+            bail!("image has no symbol table; was it stripped?");
+        };
+        let strtab = sections
+            .get(symtab.link as usize)
+            .ok_or_else(|| Error("symbol table has no string table".to_string()))?;
+        let symbols = elf.symbols(symtab, strtab)?;
+
+        // Collect functions, ordered by address. If several symbols alias
+        // the same function, keep the first.
+        let mut by_addr = BTreeMap::new();
+        for sym in symbols
+            .iter()
+            .filter(|s| s.kind == STT_FUNC && s.shndx != 0)
+        {
+            by_addr
+                .entry(sym.value)
+                .or_insert_with(|| (sym.size, normalize(&sym.name)));
+        }
+        let mut funcs: Vec<Func> = by_addr
+            .into_iter()
+            .map(|(addr, (size, name))| Func {
+                addr,
+                end: addr + size,
+                name,
+            })
+            .collect();
+        // Functions with no size extend to the next function.
+        for i in 0..funcs.len() {
+            if funcs[i].end == funcs[i].addr {
+                funcs[i].end = funcs.get(i + 1).map_or(u64::MAX, |f| f.addr);
+            }
+        }
+
+        let machine = elf.u16(18)?;
+        let ptr_width = if elf.is_64 { 8 } else { 4 };
+        let mut calls = vec![Vec::new(); funcs.len()];
+        for section in &sections {
+            let is_rela = match section.kind {
+                SHT_RELA => true,
+                SHT_REL => false,
+                _ => continue,
+            };
+            let applies_to_code = sections
+                .get(section.info as usize)
+                .is_some_and(|s| s.flags & SHF_EXECINSTR != 0);
+            if !applies_to_code {
+                continue;
+            }
+            if section.entsize == 0 {
+                bail!("relocation section has zero entry size");
+            }
+
+            let table = elf.slice(section.offset, section.size)?;
+            for entry in table.chunks_exact(section.entsize as usize) {
+                let rel = Elf {
+                    bytes: entry,
+                    is_64: elf.is_64,
+                };
+                let r_offset = rel.word(0)?;
+                let r_info = rel.word(ptr_width)?;
+                let (sym, kind) = if elf.is_64 {
+                    (r_info >> 32, r_info & 0xffff_ffff)
+                } else {
+                    (r_info >> 8, r_info & 0xff)
+                };
+                let Some(sym) = symbols.get(sym as usize) else {
+                    continue;
+                };
+
+                let target = match sym.kind {
+                    // A reference to a function, rather than somewhere
+                    // inside it.
+                    STT_FUNC => sym.value,
+                    STT_SECTION if is_rela => {
+                        let mut addend = rel.word(ptr_width * 2)?;
+                        if !elf.is_64 {
+                            addend = addend as u32 as i32 as u64;
+                        }
+                        // x86 PC-relative relocations are relative to the end
+                        // of the instruction, so the addend is 4 bytes short.
+                        if machine == EM_X86_64 && matches!(kind, R_X86_64_PC32 | R_X86_64_PLT32) {
+                            addend = addend.wrapping_add(4);
+                        }
+                        sym.value.wrapping_add(addend)
+                    }
+                    _ => continue,
+                };
+
+                if let (Some(from), Some(to)) = (find(&funcs, r_offset), find(&funcs, target)) {
+                    if from != to && !calls[from].contains(&to) {
+                        calls[from].push(to);
+                    }
+                }
+            }
+        }
+
+        Ok(Self { funcs, calls })
+    }
+
+    /// Searches breadth-first from `starts` for a panicking function,
+    /// returning the shortest call chain that reaches one.
+    fn find_panic(&self, starts: &[usize], config: &Config) -> Option<Vec<usize>> {
+        let mut parent: Vec<Option<usize>> = vec![None; self.funcs.len()];
+        let mut seen = vec![false; self.funcs.len()];
+        let mut queue = VecDeque::new();
+        for &start in starts {
+            seen[start] = true;
+            queue.push_back(start);
+        }
+
+        while let Some(func) = queue.pop_front() {
+            let name = &self.funcs[func].name;
+            if config.is_allowed(name) {
+                continue;
+            }
+            if is_panic(name) {
+                let mut chain = vec![func];
+                while let Some(p) = parent[*chain.last().unwrap()] {
+                    chain.push(p);
+                }
+                chain.reverse();
+                return Some(chain);
+            }
+            for &callee in &self.calls[func] {
+                if !seen[callee] {
+                    seen[callee] = true;
+                    parent[callee] = Some(func);
+                    queue.push_back(callee);
+                }
+            }
+        }
+
+        None
+    }
+}
+
+/// Returns the index of the function containing `addr`.
+fn find(funcs: &[Func], addr: u64) -> Option<usize> {
+    let i = funcs.partition_point(|f| f.addr <= addr).checked_sub(1)?;
+    (addr < funcs[i].end).then_some(i)
+}
+
+// === symbol names ===
+
+/// Demangles a symbol, and strips generic parameters from it.
+fn normalize(sym: &str) -> String {
+    let demangled = demangle(sym);
+    strip_generics(demangled.as_deref().unwrap_or(sym))
+}
+
+/// Demangles a legacy Rust symbol name, without its hash.
+///
+/// Returns `None` if `sym` isn't a legacy Rust symbol.
+fn demangle(sym: &str) -> Option<String> {
+    let mut rest = sym
+        .strip_prefix("_ZN")
+        .or_else(|| sym.strip_prefix("__ZN"))?;
+    let mut parts = Vec::new();
+    while !rest.starts_with('E') {
+        let digits = rest.bytes().take_while(u8::is_ascii_digit).count();
+        let len: usize = rest[..digits].parse().ok()?;
+        let ident = rest.get(digits..digits + len)?;
+        rest = &rest[digits + len..];
+        parts.push(unescape(ident)?);
+    }
+
+    let is_hash = |s: &str| {
+        s.len() == 17 && s.starts_with('h') && s[1..].bytes().all(|b| b.is_ascii_hexdigit())
+    };
+    if parts.last().is_some_and(|s| is_hash(s)) {
+        parts.pop();
+    }
+    Some(parts.join("::"))
+}
+
+fn unescape(ident: &str) -> Option<String> {
+    // Identifiers starting with `$` are prefixed with an underscore.
+    let mut rest = ident.strip_prefix("_$").map_or(ident, |_| &ident[1..]);
+    let mut out = String::with_capacity(rest.len());
+    while let Some(c) = rest.chars().next() {
+        if let Some(escape) = rest.strip_prefix('$') {
+            let (code, tail) = escape.split_once('$')?;
+            let c = match code {
+                "SP" => '@',
+                "BP" => '*',
+                "RF" => '&',
+                "LT" => '<',
+                "GT" => '>',
+                "LP" => '(',
+                "RP" => ')',
+                "C" => ',',
+                _ => char::from_u32(u32::from_str_radix(code.strip_prefix('u')?, 16).ok()?)?,
+            };
+            out.push(c);
+            rest = tail;
+        } else if let Some(tail) = rest.strip_prefix("..") {
+            out.push_str("::");
+            rest = tail;
+        } else {
+            out.push(c);
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+    Some(out)
+}
+
+/// Removes generic parameters and qualified path brackets from a demangled
+/// path, so that `<a::B<T>>::c::{{closure}}` becomes `a::B::c::{{closure}}`.
+fn strip_generics(name: &str) -> String {
+    let mut out = String::with_capacity(name.len());
+    // How deeply nested we are inside a generic parameter list.
+    let mut generic_depth = 0usize;
+    let mut prev = '\0';
+    for c in name.chars() {
+        match c {
+            // `->` in a function pointer type isn't a bracket.
+            '>' if prev == '-' => {}
+            '<' if generic_depth > 0 => generic_depth += 1,
+            '>' if generic_depth > 0 => generic_depth -= 1,
+            // A generic parameter list follows a name.
+            '<' if prev.is_alphanumeric() || prev == '_' => generic_depth = 1,
+            // Otherwise, this is a qualified path, like `<T as Trait>`.
+            '<' | '>' => {}
+            _ if generic_depth == 0 => out.push(c),
+            _ => {}
+        }
+        prev = c;
+    }
+    out
+}
+
+// === ELF parsing ===
+
+/// A little-endian ELF file, either 32- or 64-bit.
+struct Elf<'a> {
+    bytes: &'a [u8],
+    is_64: bool,
+}
+
+struct Section {
+    kind: u32,
+    flags: u64,
+    offset: u64,
+    size: u64,
+    link: u32,
+    info: u32,
+    entsize: u64,
+}
+
+struct Symbol {
+    name: String,
+    kind: u8,
+    shndx: u16,
+    value: u64,
+    size: u64,
+}
+
+impl<'a> Elf<'a> {
+    fn parse(bytes: &'a [u8]) -> Result<Self, Error> {
+        if bytes.get(..4) != Some(b"\x7fELF") {
+            bail!("input is not an ELF file");
+        }
+        let is_64 = match bytes.get(4) {
+            Some(1) => false,
+            Some(2) => true,
+            _ => bail!("invalid ELF class"),
+        };
+        if bytes.get(5) != Some(&1) {
+            bail!("only little-endian ELF files are supported");
+        }
+        Ok(Self { bytes, is_64 })
+    }
+
+    fn slice(&self, offset: u64, len: u64) -> Result<&'a [u8], Error> {
+        usize::try_from(offset)
+            .ok()
+            .zip(usize::try_from(len).ok())
+            .and_then(|(offset, len)| self.bytes.get(offset..offset.checked_add(len)?))
+            .ok_or_else(|| Error(format!("ELF file truncated at {offset:#x}")))
+    }
+
+    fn u8(&self, at: usize) -> Result<u8, Error> {
+        Ok(self.slice(at as u64, 1)?[0])
+    }
+
+    fn u16(&self, at: usize) -> Result<u16, Error> {
+        let b = self.slice(at as u64, 2)?;
+        Ok(u16::from_le_bytes([b[0], b[1]]))
+    }
+
+    fn u32(&self, at: usize) -> Result<u32, Error> {
+        let b = self.slice(at as u64, 4)?;
+        Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn u64(&self, at: usize) -> Result<u64, Error> {
+        let b = self.slice(at as u64, 8)?;
+        let mut buf = [0u8; 8];
+        buf.copy_from_slice(b);
+        Ok(u64::from_le_bytes(buf))
+    }
+
+    /// Read a native-width word (`Elf32_Addr` or `Elf64_Addr`).
+    fn word(&self, at: usize) -> Result<u64, Error> {
+        if self.is_64 {
+            self.u64(at)
+        } else {
+            self.u32(at).map(u64::from)
+        }
+    }
+
+    fn sections(&self) -> Result<Vec<Section>, Error> {
+        let (shoff, entsize, count) = if self.is_64 {
+            (self.u64(40)?, self.u16(58)?, self.u16(60)?)
+        } else {
+            (self.u32(32)?.into(), self.u16(46)?, self.u16(48)?)
+        };
+        let mut sections = Vec::with_capacity(count as usize);
+        for i in 0..count as u64 {
+            let sh = Elf {
+                bytes: self.slice(shoff + i * entsize as u64, entsize as u64)?,
+                is_64: self.is_64,
+            };
+            let section = if self.is_64 {
+                Section {
+                    kind: sh.u32(4)?,
+                    flags: sh.u64(8)?,
+                    offset: sh.u64(24)?,
+                    size: sh.u64(32)?,
+                    link: sh.u32(40)?,
+                    info: sh.u32(44)?,
+                    entsize: sh.u64(56)?,
+                }
+            } else {
+                Section {
+                    kind: sh.u32(4)?,
+                    flags: sh.u32(8)?.into(),
+                    offset: sh.u32(16)?.into(),
+                    size: sh.u32(20)?.into(),
+                    link: sh.u32(24)?,
+                    info: sh.u32(28)?,
+                    entsize: sh.u32(36)?.into(),
+                }
+            };
+            sections.push(section);
+        }
+        Ok(sections)
+    }
+
+    fn symbols(&self, symtab: &Section, strtab: &Section) -> Result<Vec<Symbol>, Error> {
+        if symtab.entsize == 0 {
+            bail!("symbol table has zero entry size");
+        }
+        let strings = self.slice(strtab.offset, strtab.size)?;
+        let table = self.slice(symtab.offset, symtab.size)?;
+        table
+            .chunks_exact(symtab.entsize as usize)
+            .map(|entry| {
+                let sym = Elf {
+                    bytes: entry,
+                    is_64: self.is_64,
+                };
+                let (name, info, shndx, value, size) = if self.is_64 {
+                    (
+                        sym.u32(0)?,
+                        sym.u8(4)?,
+                        sym.u16(6)?,
+                        sym.u64(8)?,
+                        sym.u64(16)?,
+                    )
+                } else {
+                    let (value, size) = (sym.u32(4)?.into(), sym.u32(8)?.into());
+                    (sym.u32(0)?, sym.u8(12)?, sym.u16(14)?, value, size)
+                };
+                let name = strings
+                    .get(name as usize..)
+                    .and_then(|s| s.split(|&b| b == 0).next())
+                    .map(|s| String::from_utf8_lossy(s).into_owned())
+                    .unwrap_or_default();
+                Ok(Symbol {
+                    name,
+                    kind: info & 0xf,
+                    shndx,
+                    value,
+                    size,
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn demangles_legacy_symbols() {
+        assert_eq!(
+            demangle("_ZN4core9panicking9panic_fmt17h0123456789abcdefE").as_deref(),
+            Some("core::panicking::panic_fmt")
+        );
+        assert_eq!(
+            demangle("_ZN14mnemos_d1_core7drivers4uart6D1Uart7sending28_$u7b$$u7b$closure$u7d$$u7d$17h0123456789abcdefE").as_deref(),
+            Some("mnemos_d1_core::drivers::uart::D1Uart::sending::{{closure}}")
+        );
+        assert_eq!(demangle("memcpy"), None);
+    }
+
+    #[test]
+    fn strips_generics() {
+        assert_eq!(
+            normalize("_ZN6kernel8registry22KernelHandle$LT$RD$GT$4send28_$u7b$$u7b$closure$u7d$$u7d$17h0123456789abcdefE"),
+            "kernel::registry::KernelHandle::send::{{closure}}"
+        );
+        assert_eq!(
+            strip_generics("<kernel::comms::bbq::Consumer as core::fmt::Debug>::fmt"),
+            "kernel::comms::bbq::Consumer as core::fmt::Debug::fmt"
+        );
+        assert_eq!(
+            strip_generics("core::ptr::drop_in_place<fn() -> u8>"),
+            "core::ptr::drop_in_place"
+        );
+    }
+
+    #[test]
+    fn matches_nested_paths() {
+        assert!(path_matches("a::b", "a::b"));
+        assert!(path_matches("a::b::{{closure}}", "a::b"));
+        assert!(!path_matches("a::bc", "a::b"));
+    }
+}
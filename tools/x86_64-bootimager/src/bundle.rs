@@ -0,0 +1,105 @@
+//! Builds [boot bundles](abi::bundle) of auxiliary payloads, which are passed
+//! to the kernel as the bootloader's ramdisk.
+use abi::bundle::{self, EntryHeader, Header, Kind};
+use camino::{Utf8Path, Utf8PathBuf};
+use clap::{Args, ValueHint};
+use miette::{miette, Context, IntoDiagnostic};
+
+#[derive(Clone, Debug, Default, Args)]
+#[command(next_help_heading = "Boot Bundle Options")]
+pub struct BundleOptions {
+    /// Forth scripts to run once the kernel has booted.
+    ///
+    /// Scripts are run in the order they are given.
+    #[clap(long = "boot-script", value_hint = ValueHint::FilePath, global = true)]
+    pub boot_scripts: Vec<Utf8PathBuf>,
+
+    /// Postcard-encoded configuration blobs to bundle.
+    ///
+    /// Each blob is named after its file name, without the extension. The
+    /// kernel reads its service settings from a blob named `services`.
+    #[clap(long = "config-blob", value_hint = ValueHint::FilePath, global = true)]
+    pub config_blobs: Vec<Utf8PathBuf>,
+
+    /// A read-only filesystem image to bundle.
+    #[clap(long, value_hint = ValueHint::FilePath, global = true)]
+    pub fs_image: Option<Utf8PathBuf>,
+}
+
+impl BundleOptions {
+    /// Returns `true` if there are any payloads to bundle.
+    pub fn is_empty(&self) -> bool {
+        self.boot_scripts.is_empty() && self.config_blobs.is_empty() && self.fs_image.is_none()
+    }
+
+    /// Writes a bundle of all the payloads to `<out_dir>/mnemos-bundle.bin`,
+    /// returning its path, or `None` if there is nothing to bundle.
+    pub fn build_bundle(&self, out_dir: &Utf8Path) -> miette::Result<Option<Utf8PathBuf>> {
+        if self.is_empty() {
+            return Ok(None);
+        }
+
+        let payloads = self
+            .boot_scripts
+            .iter()
+            .map(|path| (Kind::ForthScript, path))
+            .chain(self.config_blobs.iter().map(|path| (Kind::Config, path)))
+            .chain(self.fs_image.iter().map(|path| (Kind::FsImage, path)));
+        let mut entries = Vec::new();
+        for (kind, path) in payloads {
+            let name = path
+                .file_stem()
+                .ok_or_else(|| miette!("bundle payload {path} has no file name"))?;
+            let data = std::fs::read(path)
+                .into_diagnostic()
+                .with_context(|| format!("failed to read bundle payload {path}"))?;
+            tracing::debug!(?kind, name, len = data.len(), "Bundling {path}");
+            entries.push((kind, name, data));
+        }
+
+        let bytes = encode(&entries)?;
+        let path = out_dir.join("mnemos-bundle.bin");
+        std::fs::write(&path, &bytes)
+            .into_diagnostic()
+            .with_context(|| format!("failed to write boot bundle to {path}"))?;
+        tracing::info!(
+            entries = entries.len(),
+            len = bytes.len(),
+            "Built boot bundle ({path})"
+        );
+        Ok(Some(path))
+    }
+}
+
+fn encode(entries: &[(Kind, &str, Vec<u8>)]) -> miette::Result<Vec<u8>> {
+    let entry_count = u16::try_from(entries.len())
+        .map_err(|_| miette!("too many bundle entries ({})", entries.len()))?;
+    let mut header = Header {
+        entry_count,
+        len: 0,
+    };
+
+    let mut table = Vec::with_capacity(entries.len() * EntryHeader::SIZE);
+    let mut payloads = Vec::new();
+    let base = header.payloads_offset();
+    for (kind, name, data) in entries {
+        let offset = to_u32(base + payloads.len())?;
+        let entry = EntryHeader::new(*kind, name, offset, to_u32(data.len())?)
+            .map_err(|error| miette!("can't bundle {name}: {error}"))?;
+        table.extend_from_slice(&entry.to_bytes());
+        payloads.extend_from_slice(data);
+        payloads.resize(payloads.len().next_multiple_of(bundle::ALIGN), 0);
+    }
+    header.len = to_u32(base + payloads.len())?;
+
+    let mut out = Vec::with_capacity(header.len as usize);
+    out.extend_from_slice(&header.to_bytes());
+    out.extend_from_slice(&table);
+    out.resize(base, 0);
+    out.extend_from_slice(&payloads);
+    Ok(out)
+}
+
+fn to_u32(len: usize) -> miette::Result<u32> {
+    u32::try_from(len).map_err(|_| miette!("boot bundle is too large ({len} bytes)"))
+}
@@ -3,6 +3,7 @@ use clap::{Args, Parser, ValueEnum, ValueHint};
 use miette::{miette, Context, IntoDiagnostic};
 use std::fmt;
 
+pub mod bundle;
 pub mod output;
 pub mod qemu;
 
@@ -46,6 +47,10 @@ pub struct Builder {
     /// Configures the bootloader.
     #[clap(flatten)]
     pub bootloader: BootloaderOptions,
+
+    /// Configures auxiliary payloads to bundle into the image.
+    #[clap(flatten)]
+    pub bundle: bundle::BundleOptions,
 }
 
 #[derive(Clone, Debug, Args)]
@@ -198,11 +203,15 @@ impl Builder {
             .ok_or_else(|| miette!("can't determine OUT_DIR"))?;
 
         let bootcfg = self.bootloader.boot_config();
+        let ramdisk = self.bundle.build_bundle(out_dir)?;
         let path = match self.bootloader.mode {
             BootMode::Uefi => {
                 let path = out_dir.join("mnemos-x86_64-uefi.img");
                 let mut builder = bootloader::UefiBoot::new(canonical_kernel_bin.as_ref());
                 builder.set_boot_config(&bootcfg);
+                if let Some(ramdisk) = &ramdisk {
+                    builder.set_ramdisk(ramdisk.as_ref());
+                }
                 builder
                     .create_disk_image(path.as_ref())
                     .map_err(|error| miette!("failed to build UEFI image: {error}"))?;
@@ -212,6 +221,9 @@ impl Builder {
                 let path = out_dir.join("mnemos-x86_64-bios.img");
                 let mut builder = bootloader::BiosBoot::new(canonical_kernel_bin.as_ref());
                 builder.set_boot_config(&bootcfg);
+                if let Some(ramdisk) = &ramdisk {
+                    builder.set_ramdisk(ramdisk.as_ref());
+                }
                 builder
                     .create_disk_image(path.as_ref())
                     .map_err(|error| miette!("failed to build BIOS image: {error}"))?;